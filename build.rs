@@ -0,0 +1,28 @@
+//! Records the git commit the server is built from, which it reports
+//! in its build information.
+
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-env-changed=INFLUXDB_IOX_GIT_COMMIT");
+
+    // the commit can be given explicitly for builds outside of a git
+    // checkout, such as in a docker image
+    let commit = std::env::var("INFLUXDB_IOX_GIT_COMMIT")
+        .ok()
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(&["rev-parse", "HEAD"])
+                .output()
+                .ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            String::from_utf8(output.stdout).ok()
+        })
+        .map(|commit| commit.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    println!("cargo:rustc-env=INFLUXDB_IOX_GIT_COMMIT={}", commit);
+}
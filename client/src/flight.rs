@@ -0,0 +1,268 @@
+//! A client to the Arrow Flight API of InfluxDB IOx, which streams the
+//! results of SQL queries as Arrow record batches over gRPC.
+
+use arrow_deps::{
+    arrow::{
+        datatypes::{Schema, SchemaRef},
+        error::ArrowError,
+        record_batch::RecordBatch,
+    },
+    arrow_flight::{
+        flight_descriptor::DescriptorType, flight_service_client::FlightServiceClient,
+        utils::flight_data_to_arrow_batch, Criteria, FlightData, FlightDescriptor, Ticket,
+    },
+};
+use data_types::TAG_COLUMNS_METADATA_KEY;
+use serde::Serialize;
+use snafu::{OptionExt, ResultExt, Snafu};
+use std::{convert::TryFrom, fmt, sync::Arc};
+use tonic::{metadata::MetadataValue, transport::Channel, Request, Streaming};
+
+/// Errors that occur while making Flight requests to the IOx server.
+#[derive(Debug, Snafu)]
+pub enum Error {
+    /// The URL of the server is not valid
+    #[snafu(display("Invalid server URL '{}': {}", url, source))]
+    InvalidUrl {
+        /// The invalid URL
+        url: String,
+        /// The underlying error object from `http`
+        source: tonic::codegen::http::uri::InvalidUri,
+    },
+
+    /// The client could not connect to the server
+    #[snafu(display("Error connecting to {}: {}", url, source))]
+    Connecting {
+        /// The URL of the server
+        url: String,
+        /// The underlying error object from `tonic`
+        source: tonic::transport::Error,
+    },
+
+    /// The token is not valid in gRPC metadata
+    #[snafu(display("Invalid token: {}", source))]
+    InvalidToken {
+        /// The underlying error object from `tonic`
+        source: tonic::metadata::errors::InvalidMetadataValue,
+    },
+
+    /// The server responded to the request with an error.
+    #[snafu(display("Flight request returned an error: {}", source))]
+    Grpc {
+        /// The status returned by the server
+        source: tonic::Status,
+    },
+
+    /// While serializing the ticket of a query, the underlying
+    /// `serde_json` library returned an error.
+    #[snafu(display("Error while serializing the query: {}", source))]
+    Serializing {
+        /// The underlying error object from `serde_json`.
+        source: serde_json::Error,
+    },
+
+    /// The server responded with Arrow data this client could not
+    /// read.
+    #[snafu(display("Error while decoding query results: {}", source))]
+    DecodingResults {
+        /// The underlying error object from `arrow`.
+        source: ArrowError,
+    },
+
+    /// The server listed a flight without a database and table name
+    #[snafu(display("The server listed a flight without a table name"))]
+    MissingTableName {},
+}
+
+/// A specialized `Result` for Flight client errors
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The query encoded in the ticket of a `DoGet` request
+#[derive(Debug, Serialize)]
+struct ReadInfo<'a> {
+    database_name: &'a str,
+    sql_query: &'a str,
+}
+
+/// Client to the Arrow Flight API of an InfluxDB IOx server.
+///
+/// ```no_run
+/// async fn example() -> Result<(), Box<dyn std::error::Error>> {
+///     use influxdb_iox_client::flight::FlightClient;
+///
+///     let mut client = FlightClient::connect("http://localhost:8082").await?;
+///     for table_name in client.tables("myorg_mybucket").await? {
+///         println!("{}", table_name);
+///     }
+///     let batches = client.query("myorg_mybucket", "select * from cpu").await?;
+///     println!("{} batches", batches.len());
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct FlightClient {
+    inner: FlightServiceClient<Channel>,
+    auth_header: Option<MetadataValue<tonic::metadata::Ascii>>,
+}
+
+impl FlightClient {
+    /// Connects to the gRPC server at the URL specified in
+    /// `protocol://server:port` format, sending no token.
+    pub async fn connect(url: impl Into<String>) -> Result<Self> {
+        let url = url.into();
+        let channel = Channel::from_shared(url.clone())
+            .context(InvalidUrl { url: &url })?
+            .connect()
+            .await
+            .context(Connecting { url: &url })?;
+
+        Ok(Self {
+            inner: FlightServiceClient::new(channel),
+            auth_header: None,
+        })
+    }
+
+    /// Authorize requests with the API token `token`, which is
+    /// required by servers configured with an admin token.
+    pub fn with_token(mut self, token: impl fmt::Display) -> Result<Self> {
+        let value = MetadataValue::from_str(&format!("Token {}", token)).context(InvalidToken)?;
+        self.auth_header = Some(value);
+        Ok(self)
+    }
+
+    /// Wraps `message` in a request carrying the token, if any
+    fn request<T>(&self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        if let Some(auth_header) = &self.auth_header {
+            request
+                .metadata_mut()
+                .insert("authorization", auth_header.clone());
+        }
+        request
+    }
+
+    /// Runs the SQL query `sql` against the database `db_name`,
+    /// returning the results.
+    pub async fn query(&mut self, db_name: &str, sql: &str) -> Result<Vec<RecordBatch>> {
+        let mut results = self.query_stream(db_name, sql).await?;
+
+        let mut batches = vec![];
+        while let Some(batch) = results.next_batch().await? {
+            batches.push(batch);
+        }
+        Ok(batches)
+    }
+
+    /// Runs the SQL query `sql` against the database `db_name`,
+    /// returning its results as they are received, so that they need
+    /// not all be held in memory at once.
+    pub async fn query_stream(&mut self, db_name: &str, sql: &str) -> Result<QueryResults> {
+        let ticket = serde_json::to_vec(&ReadInfo {
+            database_name: db_name,
+            sql_query: sql,
+        })
+        .context(Serializing)?;
+
+        let request = self.request(Ticket { ticket });
+        let mut stream = self.inner.do_get(request).await.context(Grpc)?.into_inner();
+
+        // The first message describes the schema of the batches that
+        // follow it
+        let schema = match stream.message().await.context(Grpc)? {
+            Some(flight_data) => Some(Arc::new(
+                Schema::try_from(&flight_data).context(DecodingResults)?,
+            )),
+            None => None,
+        };
+
+        Ok(QueryResults { schema, stream })
+    }
+
+    /// Lists the names of the tables of the database `db_name`, in
+    /// sorted order.
+    pub async fn tables(&mut self, db_name: &str) -> Result<Vec<String>> {
+        let request = self.request(Criteria {
+            expression: db_name.as_bytes().to_vec(),
+        });
+        let mut stream = self
+            .inner
+            .list_flights(request)
+            .await
+            .context(Grpc)?
+            .into_inner();
+
+        let mut table_names = vec![];
+        while let Some(flight_info) = stream.message().await.context(Grpc)? {
+            let table_name = flight_info
+                .flight_descriptor
+                .and_then(|descriptor| descriptor.path.into_iter().nth(1))
+                .context(MissingTableName)?;
+            table_names.push(table_name);
+        }
+        Ok(table_names)
+    }
+
+    /// Returns the schema of the table `table_name` of the database
+    /// `db_name`. Use `tag_columns` to find which of its columns are
+    /// tags.
+    pub async fn table_schema(&mut self, db_name: &str, table_name: &str) -> Result<SchemaRef> {
+        let request = self.request(FlightDescriptor {
+            r#type: DescriptorType::Path as i32,
+            cmd: vec![],
+            path: vec![db_name.to_string(), table_name.to_string()],
+        });
+        let schema_result = self
+            .inner
+            .get_schema(request)
+            .await
+            .context(Grpc)?
+            .into_inner();
+
+        Ok(Arc::new(
+            Schema::try_from(&schema_result).context(DecodingResults)?,
+        ))
+    }
+}
+
+/// The results of a query, which are read a record batch at a time
+/// with `next_batch`
+#[derive(Debug)]
+pub struct QueryResults {
+    /// The schema of the batches, if the server sent any
+    schema: Option<SchemaRef>,
+    stream: Streaming<FlightData>,
+}
+
+impl QueryResults {
+    /// The schema of the batches, or `None` if the query returned no
+    /// results
+    pub fn schema(&self) -> Option<SchemaRef> {
+        self.schema.clone()
+    }
+
+    /// Returns the next batch of results, or `None` once every batch
+    /// has been returned
+    pub async fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        let schema = match &self.schema {
+            Some(schema) => schema,
+            None => return Ok(None),
+        };
+
+        while let Some(flight_data) = self.stream.message().await.context(Grpc)? {
+            if let Some(batch) = flight_data_to_arrow_batch(&flight_data, Arc::clone(schema)) {
+                return Ok(Some(batch.context(DecodingResults)?));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Returns the tag columns of a schema returned by
+/// `FlightClient::table_schema`, which are listed in its metadata
+pub fn tag_columns(schema: &Schema) -> Vec<String> {
+    schema
+        .metadata()
+        .get(TAG_COLUMNS_METADATA_KEY)
+        .and_then(|tags| serde_json::from_str(tags).ok())
+        .unwrap_or_default()
+}
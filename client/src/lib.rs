@@ -0,0 +1,413 @@
+#![deny(rust_2018_idioms)]
+#![warn(
+    missing_copy_implementations,
+    missing_debug_implementations,
+    missing_docs,
+    clippy::explicit_iter_loop,
+    clippy::use_self
+)]
+
+//! # influxdb_iox_client
+//!
+//! This is a Rust client to the HTTP API of InfluxDB IOx. It writes
+//! line protocol, runs SQL queries (returning their results as Arrow
+//! record batches), and manages databases and the jobs of the server.
+//! The [`flight`] module contains a client that runs SQL queries over
+//! the Arrow Flight gRPC API instead.
+//!
+//! ## Quick start
+//!
+//! This example creates a client to an IOx server running at
+//! `http://localhost:8080`, writes two lines to the bucket `mybucket`
+//! of the org `myorg` (the database `myorg_mybucket`), and queries
+//! them with SQL.
+//!
+//! ```
+//! async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//!     use influxdb_iox_client::{Client, Line};
+//!
+//!     let client = Client::new("http://localhost:8080").with_token("some-token");
+//!
+//!     let lines = vec![
+//!         Line::builder("cpu")
+//!             .tag("host", "server01")
+//!             .field("usage", 0.5)
+//!             .build()?,
+//!         Line::builder("cpu")
+//!             .tag("host", "server02")
+//!             .field("usage", 0.87)
+//!             .build()?,
+//!     ];
+//!     client.write("myorg", "mybucket", &lines).await?;
+//!
+//!     let batches = client
+//!         .query("myorg_mybucket", "select host, usage from cpu")
+//!         .await?;
+//!     println!("{} batches", batches.len());
+//!     Ok(())
+//! }
+//! ```
+
+use arrow_deps::arrow::{error::ArrowError, ipc::reader::StreamReader, record_batch::RecordBatch};
+use data_types::{database_rules::DatabaseRules, partition_metadata::ChunkSummary};
+use reqwest::{Body, Method, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+use snafu::{ResultExt, Snafu};
+use std::fmt;
+
+pub mod flight;
+pub use flight::FlightClient;
+
+pub mod line;
+pub use line::{FieldValue, Line, LineBuilder};
+
+pub mod management;
+pub use management::{BuildInfo, DatabaseInfo, JobInfo};
+
+/// Errors that occur while making requests to the IOx server.
+#[derive(Debug, Snafu)]
+pub enum Error {
+    /// While making a request to the IOx server, the underlying
+    /// `reqwest` library returned an error.
+    #[snafu(display("Error while processing the HTTP request: {}", source))]
+    ReqwestProcessing {
+        /// The underlying error object from `reqwest`.
+        source: reqwest::Error,
+    },
+
+    /// The server responded to the request with an error.
+    #[snafu(display("HTTP request returned an error: {}, `{}`", status, message))]
+    Http {
+        /// The `StatusCode` returned from the request
+        status: StatusCode,
+        /// The error message of the server, or the body of its response
+        /// if it had none
+        message: String,
+    },
+
+    /// While serializing data as JSON to send in a request, the
+    /// underlying `serde_json` library returned an error.
+    #[snafu(display("Error while serializing to JSON: {}", source))]
+    Serializing {
+        /// The underlying error object from `serde_json`.
+        source: serde_json::Error,
+    },
+
+    /// The server responded with JSON this client could not read.
+    #[snafu(display("Error while deserializing the response: {}", source))]
+    Deserializing {
+        /// The underlying error object from `serde_json`.
+        source: serde_json::Error,
+    },
+
+    /// The server responded to a query with Arrow data this client
+    /// could not read.
+    #[snafu(display("Error while decoding query results: {}", source))]
+    DecodingResults {
+        /// The underlying error object from `arrow`.
+        source: ArrowError,
+    },
+}
+
+/// A specialized `Result` for client errors
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Client to the HTTP API of an InfluxDB IOx server.
+#[derive(Debug, Clone)]
+pub struct Client {
+    /// The base URL this client sends requests to
+    pub url: String,
+    auth_header: Option<String>,
+    reqwest: reqwest::Client,
+}
+
+impl Client {
+    /// Create a new client pointing to the URL specified in
+    /// `protocol://server:port` format, which sends no token.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let client = influxdb_iox_client::Client::new("http://localhost:8080");
+    /// ```
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into().trim_end_matches('/').to_string(),
+            auth_header: None,
+            reqwest: reqwest::Client::new(),
+        }
+    }
+
+    /// Authorize requests with the API token `token`, which is
+    /// required by servers configured with an admin token.
+    pub fn with_token(mut self, token: impl fmt::Display) -> Self {
+        self.auth_header = Some(format!("Token {}", token));
+        self
+    }
+
+    /// Consolidate common request building code
+    fn request(&self, method: Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self
+            .reqwest
+            .request(method, &format!("{}{}", self.url, path));
+        match &self.auth_header {
+            Some(auth_header) => request.header("Authorization", auth_header),
+            None => request,
+        }
+    }
+
+    /// Checks that the server is alive.
+    pub async fn ping(&self) -> Result<()> {
+        send(self.request(Method::GET, "/ping")).await?;
+        Ok(())
+    }
+
+    /// Returns the version, git commit and features of the server.
+    pub async fn build_info(&self) -> Result<BuildInfo> {
+        json(self.request(Method::GET, "/iox/api/v1/build_info")).await
+    }
+
+    /// Write line protocol data to the specified organization and
+    /// bucket.
+    pub async fn write_line_protocol(
+        &self,
+        org: &str,
+        bucket: &str,
+        body: impl Into<Body>,
+    ) -> Result<()> {
+        let request = self
+            .request(Method::POST, "/api/v2/write")
+            .query(&[("bucket", bucket), ("org", org)])
+            .body(body);
+        send(request).await?;
+        Ok(())
+    }
+
+    /// Write `lines` to the specified organization and bucket.
+    pub async fn write(&self, org: &str, bucket: &str, lines: &[Line]) -> Result<()> {
+        let mut body = String::new();
+        for line in lines {
+            body.push_str(&line.to_string());
+            body.push('\n');
+        }
+
+        self.write_line_protocol(org, bucket, body).await
+    }
+
+    /// Run the SQL query `sql` against the database `db_name`,
+    /// returning its results.
+    pub async fn query(&self, db_name: &str, sql: &str) -> Result<Vec<RecordBatch>> {
+        let request = self
+            .request(
+                Method::POST,
+                &format!("/iox/api/v1/databases/{}/query", db_name),
+            )
+            .query(&[("format", "arrow")])
+            .body(sql.to_string());
+        let body = send(request)
+            .await?
+            .bytes()
+            .await
+            .context(ReqwestProcessing)?;
+
+        let reader = StreamReader::try_new(&body[..]).context(DecodingResults)?;
+        reader
+            .collect::<Result<Vec<_>, _>>()
+            .context(DecodingResults)
+    }
+
+    /// Create the database `db_name` with the rules `rules`.
+    pub async fn create_database(
+        &self,
+        db_name: &str,
+        rules: &DatabaseRules,
+    ) -> Result<DatabaseInfo> {
+        let body = serde_json::to_string(rules).context(Serializing)?;
+        let request = self
+            .request(Method::POST, &format!("/iox/api/v1/databases/{}", db_name))
+            .body(body);
+        json(request).await
+    }
+
+    /// Replace the rules of the database `db_name` with `rules`.
+    pub async fn update_database(
+        &self,
+        db_name: &str,
+        rules: &DatabaseRules,
+    ) -> Result<DatabaseInfo> {
+        let body = serde_json::to_string(rules).context(Serializing)?;
+        let request = self
+            .request(Method::PUT, &format!("/iox/api/v1/databases/{}", db_name))
+            .body(body);
+        json(request).await
+    }
+
+    /// Returns the database `db_name` and its rules.
+    pub async fn get_database(&self, db_name: &str) -> Result<DatabaseInfo> {
+        json(self.request(Method::GET, &format!("/iox/api/v1/databases/{}", db_name))).await
+    }
+
+    /// Lists the databases the token of this client may read.
+    pub async fn list_databases(&self) -> Result<Vec<DatabaseInfo>> {
+        #[derive(Deserialize)]
+        struct DatabasesResponse {
+            databases: Vec<DatabaseInfo>,
+        }
+
+        let response: DatabasesResponse =
+            json(self.request(Method::GET, "/iox/api/v1/databases")).await?;
+        Ok(response.databases)
+    }
+
+    /// Deletes the database `db_name` and all of its data.
+    pub async fn delete_database(&self, db_name: &str) -> Result<()> {
+        send(self.request(
+            Method::DELETE,
+            &format!("/iox/api/v1/databases/{}", db_name),
+        ))
+        .await?;
+        Ok(())
+    }
+
+    /// Lists the chunks of the database `db_name`.
+    pub async fn list_chunks(&self, db_name: &str) -> Result<Vec<ChunkSummary>> {
+        #[derive(Deserialize)]
+        struct ChunksResponse {
+            chunks: Vec<ChunkSummary>,
+        }
+
+        let request = self.request(
+            Method::GET,
+            &format!("/iox/api/v1/databases/{}/chunks", db_name),
+        );
+        let response: ChunksResponse = json(request).await?;
+        Ok(response.chunks)
+    }
+
+    /// Lists the running and recently finished jobs of the server.
+    pub async fn list_jobs(&self) -> Result<Vec<JobInfo>> {
+        #[derive(Deserialize)]
+        struct JobsResponse {
+            jobs: Vec<JobInfo>,
+        }
+
+        let response: JobsResponse = json(self.request(Method::GET, "/iox/api/v1/jobs")).await?;
+        Ok(response.jobs)
+    }
+
+    /// Returns the job with id `id`.
+    pub async fn get_job(&self, id: u64) -> Result<JobInfo> {
+        json(self.request(Method::GET, &format!("/iox/api/v1/jobs/{}", id))).await
+    }
+
+    /// Cancels the running job with id `id`, returning it as it is
+    /// once cancelled.
+    pub async fn cancel_job(&self, id: u64) -> Result<JobInfo> {
+        json(self.request(Method::POST, &format!("/iox/api/v1/jobs/{}/cancel", id))).await
+    }
+}
+
+/// Sends `request`, returning its response if it succeeded
+async fn send(request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+    let response = request.send().await.context(ReqwestProcessing)?;
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    #[derive(Deserialize)]
+    struct ErrorResponse {
+        error: String,
+    }
+
+    let status = response.status();
+    let text = response.text().await.context(ReqwestProcessing)?;
+    let message = match serde_json::from_str::<ErrorResponse>(&text) {
+        Ok(response) => response.error,
+        Err(_) => text,
+    };
+    Http { status, message }.fail()
+}
+
+/// Sends `request`, returning its JSON response as a `T`
+async fn json<T: DeserializeOwned>(request: reqwest::RequestBuilder) -> Result<T> {
+    let body = send(request)
+        .await?
+        .bytes()
+        .await
+        .context(ReqwestProcessing)?;
+    serde_json::from_slice(&body).context(Deserializing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mockito::mock;
+
+    type Error = Box<dyn std::error::Error>;
+    type Result<T = (), E = Error> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn writing_lines() -> Result {
+        let token = "some-token";
+        let mock_server = mock("POST", "/api/v2/write?bucket=some-bucket&org=some-org")
+            .match_header("Authorization", format!("Token {}", token).as_str())
+            .match_body("cpu,host=server01 usage=0.5\ncpu,host=server02 usage=0.87\n")
+            .with_status(204)
+            .create();
+
+        let client = Client::new(mockito::server_url()).with_token(token);
+        let lines = vec![
+            Line::builder("cpu")
+                .tag("host", "server01")
+                .field("usage", 0.5)
+                .build()?,
+            Line::builder("cpu")
+                .tag("host", "server02")
+                .field("usage", 0.87)
+                .build()?,
+        ];
+
+        // If the request is incorrect, Mockito returns status 501 and
+        // its assertion below explains why, so don't use `?` here.
+        let _result = client.write("some-org", "some-bucket", &lines).await;
+
+        mock_server.assert();
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn server_errors() -> Result {
+        let _mock_server = mock("GET", "/iox/api/v1/databases/missing")
+            .with_status(404)
+            .with_body(r#"{"error":"Database missing not found"}"#)
+            .create();
+
+        let client = Client::new(mockito::server_url());
+        let error = client.get_database("missing").await.unwrap_err();
+        assert!(matches!(
+            error,
+            super::Error::Http { status, message }
+                if status == StatusCode::NOT_FOUND && message == "Database missing not found"
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn listing_jobs() -> Result {
+        let _mock_server = mock("GET", "/iox/api/v1/jobs")
+            .with_body(
+                r#"{"jobs":[{"id":3,"description":{"type":"replay_wal","db_name":"mydb"},"status":"completed","metrics":{"lines":10},"start_time":5,"end_time":8}]}"#,
+            )
+            .create();
+
+        let client = Client::new(mockito::server_url());
+        let jobs = client.list_jobs().await?;
+        assert_eq!(jobs.len(), 1);
+        assert_eq!(jobs[0].id, 3);
+        assert_eq!(jobs[0].status, "completed");
+        assert_eq!(jobs[0].description["db_name"], "mydb");
+        assert_eq!(jobs[0].metrics["lines"], 10);
+        assert_eq!(jobs[0].end_time, Some(8));
+        Ok(())
+    }
+}
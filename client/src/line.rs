@@ -0,0 +1,221 @@
+//! Building lines of line protocol to write
+
+use snafu::{ensure, Snafu};
+use std::{collections::BTreeMap, fmt};
+
+/// Errors that occur while building `Line`s
+#[derive(Debug, Snafu)]
+pub enum Error {
+    /// Returned when calling `build` on a `LineBuilder` with an empty
+    /// measurement name.
+    #[snafu(display("The measurement of a line must not be empty"))]
+    EmptyMeasurement {},
+
+    /// Returned when calling `build` on a `LineBuilder` that has no
+    /// fields.
+    #[snafu(display("The line of measurement '{}' has no fields", measurement))]
+    NoFields {
+        /// The measurement of the line
+        measurement: String,
+    },
+}
+
+/// The value of a field
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldValue {
+    /// A boolean value
+    Bool(bool),
+    /// A floating point value
+    F64(f64),
+    /// An integer value
+    I64(i64),
+    /// A string value
+    String(String),
+}
+
+impl From<bool> for FieldValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<f64> for FieldValue {
+    fn from(value: f64) -> Self {
+        Self::F64(value)
+    }
+}
+
+impl From<i64> for FieldValue {
+    fn from(value: i64) -> Self {
+        Self::I64(value)
+    }
+}
+
+impl From<&str> for FieldValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for FieldValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl fmt::Display for FieldValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(value) => write!(f, "{}", value),
+            Self::F64(value) => write!(f, "{}", value),
+            Self::I64(value) => write!(f, "{}i", value),
+            Self::String(value) => {
+                write!(f, "\"")?;
+                write_escaped(f, value, &['"', '\\'])?;
+                write!(f, "\"")
+            }
+        }
+    }
+}
+
+/// Incrementally constructs a `Line`.
+///
+/// Create this via `Line::builder`.
+#[derive(Debug, Clone)]
+pub struct LineBuilder {
+    measurement: String,
+    // Sorted tags are cheaper for the server to index
+    tags: BTreeMap<String, String>,
+    fields: BTreeMap<String, FieldValue>,
+    timestamp: Option<i64>,
+}
+
+impl LineBuilder {
+    /// Sets a tag, replacing any existing tag of the same name.
+    pub fn tag(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.insert(name.into(), value.into());
+        self
+    }
+
+    /// Sets a field, replacing any existing field of the same name.
+    pub fn field(mut self, name: impl Into<String>, value: impl Into<FieldValue>) -> Self {
+        self.fields.insert(name.into(), value.into());
+        self
+    }
+
+    /// Sets the timestamp, in nanoseconds since the UNIX epoch. Lines
+    /// without a timestamp are given the time the server receives them.
+    pub fn timestamp(mut self, value: i64) -> Self {
+        self.timestamp = Some(value);
+        self
+    }
+
+    /// Constructs the line
+    pub fn build(self) -> Result<Line, Error> {
+        ensure!(!self.measurement.is_empty(), EmptyMeasurement);
+        ensure!(
+            !self.fields.is_empty(),
+            NoFields {
+                measurement: self.measurement
+            }
+        );
+
+        Ok(Line(self))
+    }
+}
+
+/// A line of line protocol, which displays as the text written to the
+/// server
+#[derive(Debug, Clone)]
+pub struct Line(LineBuilder);
+
+impl Line {
+    /// Starts building a line of the measurement `measurement`
+    pub fn builder(measurement: impl Into<String>) -> LineBuilder {
+        LineBuilder {
+            measurement: measurement.into(),
+            tags: Default::default(),
+            fields: Default::default(),
+            timestamp: None,
+        }
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let line = &self.0;
+        write_escaped(f, &line.measurement, &[',', ' '])?;
+
+        for (name, value) in &line.tags {
+            write!(f, ",")?;
+            write_escaped(f, name, &[',', '=', ' '])?;
+            write!(f, "=")?;
+            write_escaped(f, value, &[',', '=', ' '])?;
+        }
+
+        for (i, (name, value)) in line.fields.iter().enumerate() {
+            write!(f, "{}", if i == 0 { ' ' } else { ',' })?;
+            write_escaped(f, name, &[',', '=', ' '])?;
+            write!(f, "={}", value)?;
+        }
+
+        if let Some(timestamp) = line.timestamp {
+            write!(f, " {}", timestamp)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes `value` to `f`, escaping `special_chars` with a backslash
+fn write_escaped(f: &mut fmt::Formatter<'_>, value: &str, special_chars: &[char]) -> fmt::Result {
+    for c in value.chars() {
+        if special_chars.contains(&c) {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type Error = Box<dyn std::error::Error>;
+    type Result<T = (), E = Error> = std::result::Result<T, E>;
+
+    #[test]
+    fn line_protocol() -> Result {
+        let line = Line::builder("cpu load")
+            .tag("region", "us west")
+            .tag("host", "a,b")
+            .field("usage", 0.5)
+            .field("count", 3i64)
+            .field("up", true)
+            .field("note", r#"said "hi" \o/"#)
+            .timestamp(10)
+            .build()?;
+
+        assert_eq!(
+            line.to_string(),
+            r#"cpu\ load,host=a\,b,region=us\ west count=3i,note="said \"hi\" \\o/",up=true,usage=0.5 10"#
+        );
+
+        let line = Line::builder("cpu").field("usage", 1.0).build()?;
+        assert_eq!(line.to_string(), "cpu usage=1");
+        Ok(())
+    }
+
+    #[test]
+    fn lines_need_a_measurement_and_a_field() {
+        assert!(matches!(
+            Line::builder("cpu").tag("host", "a").build(),
+            Err(super::Error::NoFields { .. })
+        ));
+        assert!(matches!(
+            Line::builder("").field("usage", 1.0).build(),
+            Err(super::Error::EmptyMeasurement {})
+        ));
+    }
+}
@@ -0,0 +1,63 @@
+//! The types of the responses of the management API
+
+use data_types::database_rules::DatabaseRules;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// A database and the rules currently in effect for it
+#[derive(Debug, Clone, Deserialize)]
+pub struct DatabaseInfo {
+    /// The name of the database
+    pub name: String,
+    /// The rules of the database
+    pub rules: DatabaseRules,
+}
+
+/// How many of the steps of a job are done
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub struct Progress {
+    /// The steps which are done
+    pub done: u64,
+    /// All steps of the job
+    pub total: u64,
+}
+
+/// A snapshot of the state of a job of the server
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct JobInfo {
+    /// The id of the job, by which it is looked up and cancelled
+    pub id: u64,
+    /// The work the job does, an object whose `type` names the kind of
+    /// job (such as `compact_partition`) along with its parameters
+    pub description: serde_json::Value,
+    /// `running`, `completed`, `failed` or `cancelled`
+    pub status: String,
+    /// The error the job failed with, if it failed
+    #[serde(default)]
+    pub error: Option<String>,
+    /// The progress of the job, for jobs made up of several steps
+    #[serde(default)]
+    pub progress: Option<Progress>,
+    /// Counters reported by the job, such as the number of rows or
+    /// bytes it has processed
+    #[serde(default)]
+    pub metrics: BTreeMap<String, u64>,
+    /// When the job started, in nanoseconds since the epoch
+    pub start_time: i64,
+    /// When the job finished, in nanoseconds since the epoch
+    #[serde(default)]
+    pub end_time: Option<i64>,
+}
+
+/// Describes the build of the server
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct BuildInfo {
+    /// The version of the server
+    pub version: String,
+    /// The git commit the server was built from, or `unknown`
+    pub commit: String,
+    /// The name of the build, `IOx`
+    pub build: String,
+    /// The optional features the server was built with
+    pub features: Vec<String>,
+}
@@ -0,0 +1,390 @@
+#![deny(rust_2018_idioms)]
+#![warn(
+    missing_copy_implementations,
+    missing_debug_implementations,
+    clippy::explicit_iter_loop,
+    clippy::use_self
+)]
+
+//! # data_generator
+//!
+//! This crate generates line protocol workloads for load testing,
+//! benchmarks and regression tests. A [`DataSpec`] describes the
+//! measurements, the cardinality and distribution of their tags, the
+//! patterns of their field values and the spacing of their times.
+//! The lines are generated from a seeded random number generator, so
+//! the same specification always produces the same lines.
+//!
+//! ```
+//! use data_generator::{DataSpec, Generator};
+//!
+//! let spec = DataSpec::from_toml(r#"
+//! seed = 1
+//! start_time = "2020-11-01T00:00:00Z"
+//! ticks = 10
+//!
+//! [[measurements]]
+//! name = "cpu"
+//! lines_per_tick = 4
+//!
+//! [[measurements.tags]]
+//! name = "host"
+//! cardinality = 4
+//! distribution = { type = "sequential" }
+//!
+//! [[measurements.fields]]
+//! name = "usage"
+//! value = { type = "random_float", min = 0.0, max = 100.0 }
+//! "#).unwrap();
+//!
+//! let lines: Vec<_> = Generator::new(spec).map(|line| line.to_string()).collect();
+//! assert_eq!(lines.len(), 40);
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+
+use influxdb_iox_client::{FieldValue, Line};
+use rand::{
+    distributions::{Uniform, WeightedIndex},
+    Rng, SeedableRng,
+};
+use rand_chacha::ChaCha8Rng;
+
+pub mod specification;
+
+pub use specification::{
+    DataSpec, Distribution, FieldSpec, MeasurementSpec, TagSpec, TimeSpacing, ValuePattern,
+};
+
+const NANOS_PER_MILLI: i64 = 1_000_000;
+
+/// Generates the lines described by a `DataSpec`, one tick at a time
+#[derive(Debug)]
+pub struct Generator {
+    spec: DataSpec,
+    rng: ChaCha8Rng,
+    /// The tag value samplers of each measurement
+    samplers: Vec<Vec<TagSampler>>,
+    /// The number of lines generated for each measurement, which
+    /// drives the sequential tags
+    line_counts: Vec<usize>,
+    /// The state of the random walk and counter fields of each series,
+    /// keyed by measurement, field and tag value indexes
+    field_states: HashMap<(usize, usize, Vec<usize>), FieldState>,
+    tick: usize,
+    /// The time of the most recent tick, for random spacing
+    previous_time: i64,
+    start_time: i64,
+    pending: VecDeque<Line>,
+}
+
+impl Generator {
+    /// Creates a generator of the lines of `spec`, which should have
+    /// been validated. Without a start time in `spec`, the first tick
+    /// is at the current time.
+    pub fn new(spec: DataSpec) -> Self {
+        let start_time = spec
+            .start_time_nanos()
+            .unwrap_or_else(|| chrono::Utc::now().timestamp_nanos());
+        let samplers = spec
+            .measurements
+            .iter()
+            .map(|measurement| measurement.tags.iter().map(TagSampler::new).collect())
+            .collect();
+        let line_counts = vec![0; spec.measurements.len()];
+
+        Self {
+            rng: ChaCha8Rng::seed_from_u64(spec.seed),
+            spec,
+            samplers,
+            line_counts,
+            field_states: HashMap::new(),
+            tick: 0,
+            previous_time: start_time,
+            start_time,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// The time of the next tick, in nanoseconds since the epoch
+    fn next_time(&mut self) -> i64 {
+        let tick = self.tick as i64;
+        let time = match self.spec.time_spacing {
+            TimeSpacing::Regular { interval_ms } => {
+                self.start_time + tick * interval_ms as i64 * NANOS_PER_MILLI
+            }
+            TimeSpacing::Jittered {
+                interval_ms,
+                jitter_ms,
+            } => {
+                let jitter = jitter_ms as i64 * NANOS_PER_MILLI;
+                let offset = self.rng.sample(Uniform::new_inclusive(-jitter, jitter));
+                self.start_time + tick * interval_ms as i64 * NANOS_PER_MILLI + offset
+            }
+            TimeSpacing::Random { mean_interval_ms } if self.tick > 0 => {
+                // exponentially distributed intervals make a Poisson process
+                let mean = (mean_interval_ms as i64 * NANOS_PER_MILLI) as f64;
+                let uniform: f64 = self.rng.gen();
+                self.previous_time + (-mean * (1.0 - uniform).ln()) as i64
+            }
+            TimeSpacing::Random { .. } => self.start_time,
+        };
+        self.previous_time = time;
+        time
+    }
+
+    /// Generates the lines of the next tick
+    fn generate_tick(&mut self) {
+        let time = self.next_time();
+
+        for (measurement_index, measurement) in self.spec.measurements.iter().enumerate() {
+            for _ in 0..measurement.lines_per_tick {
+                let line_count = self.line_counts[measurement_index];
+                self.line_counts[measurement_index] += 1;
+
+                // each sequential tag advances once the earlier ones wrap around
+                let mut sequence = line_count;
+                let rng = &mut self.rng;
+                let tag_indexes: Vec<_> = self.samplers[measurement_index]
+                    .iter()
+                    .map(|sampler| sampler.sample(rng, &mut sequence))
+                    .collect();
+
+                let mut builder = Line::builder(measurement.name.as_str()).timestamp(time);
+                for (tag, &index) in measurement.tags.iter().zip(&tag_indexes) {
+                    builder = builder.tag(tag.name.as_str(), format!("{}-{}", tag.name, index));
+                }
+
+                for (field_index, field) in measurement.fields.iter().enumerate() {
+                    let state = self.field_states.entry((
+                        measurement_index,
+                        field_index,
+                        tag_indexes.clone(),
+                    ));
+                    let value = next_value(&field.value, rng, state, time);
+                    builder = builder.field(field.name.as_str(), value);
+                }
+
+                let line = builder.build().expect("specification has fields");
+                self.pending.push_back(line);
+            }
+        }
+        self.tick += 1;
+    }
+}
+
+impl Iterator for Generator {
+    type Item = Line;
+
+    fn next(&mut self) -> Option<Line> {
+        while self.pending.is_empty() && self.tick < self.spec.ticks {
+            self.generate_tick();
+        }
+        self.pending.pop_front()
+    }
+}
+
+/// Chooses the values of a tag
+#[derive(Debug)]
+enum TagSampler {
+    Uniform(Uniform<usize>),
+    Zipf(WeightedIndex<f64>),
+    Sequential(usize),
+}
+
+impl TagSampler {
+    fn new(tag: &TagSpec) -> Self {
+        match tag.distribution {
+            Distribution::Uniform => Self::Uniform(Uniform::new(0, tag.cardinality)),
+            Distribution::Zipf { exponent } => {
+                let weights = (1..=tag.cardinality).map(|rank| 1.0 / (rank as f64).powf(exponent));
+                Self::Zipf(WeightedIndex::new(weights).expect("zipf weights are positive"))
+            }
+            Distribution::Sequential => Self::Sequential(tag.cardinality),
+        }
+    }
+
+    /// The index of the next value of the tag. Sequential tags take
+    /// their index from `sequence`, leaving the part of it that drives
+    /// the following sequential tags.
+    fn sample(&self, rng: &mut ChaCha8Rng, sequence: &mut usize) -> usize {
+        match self {
+            Self::Uniform(uniform) => rng.sample(uniform),
+            Self::Zipf(weights) => rng.sample(weights),
+            Self::Sequential(cardinality) => {
+                let index = *sequence % cardinality;
+                *sequence /= cardinality;
+                index
+            }
+        }
+    }
+}
+
+/// The state of a field with values that depend on the previous value
+/// of its series
+#[derive(Debug, Clone, Copy)]
+enum FieldState {
+    Float(f64),
+    Integer(i64),
+}
+
+/// The next value of a field with the pattern `pattern`, at `time`
+fn next_value(
+    pattern: &ValuePattern,
+    rng: &mut ChaCha8Rng,
+    state: std::collections::hash_map::Entry<'_, (usize, usize, Vec<usize>), FieldState>,
+    time: i64,
+) -> FieldValue {
+    match pattern {
+        ValuePattern::Constant { value } => FieldValue::from(*value),
+        ValuePattern::RandomFloat { min, max } => FieldValue::from(rng.gen_range(*min, *max)),
+        ValuePattern::RandomInteger { min, max } => {
+            FieldValue::from(rng.sample(Uniform::new_inclusive(*min, *max)))
+        }
+        ValuePattern::RandomWalk {
+            start,
+            step,
+            min,
+            max,
+        } => {
+            let state = state.or_insert(FieldState::Float(*start));
+            let value = match *state {
+                FieldState::Float(value) => value,
+                FieldState::Integer(_) => unreachable!("random walks have float state"),
+            };
+            let change = step * (rng.gen::<f64>() * 2.0 - 1.0);
+            *state = FieldState::Float((value + change).max(*min).min(*max));
+            FieldValue::from(value)
+        }
+        ValuePattern::Sine {
+            amplitude,
+            period_ms,
+            offset,
+        } => {
+            let period = *period_ms as i64 * NANOS_PER_MILLI;
+            let phase = time.rem_euclid(period) as f64 / period as f64;
+            FieldValue::from(offset + amplitude * (phase * std::f64::consts::PI * 2.0).sin())
+        }
+        ValuePattern::Counter { start, increment } => {
+            let state = state.or_insert(FieldState::Integer(*start));
+            let value = match *state {
+                FieldState::Integer(value) => value,
+                FieldState::Float(_) => unreachable!("counters have integer state"),
+            };
+            *state = FieldState::Integer(value.wrapping_add(*increment));
+            FieldValue::from(value)
+        }
+        ValuePattern::Boolean { probability } => FieldValue::from(rng.gen_bool(*probability)),
+        ValuePattern::String { values } => {
+            FieldValue::from(values[rng.gen_range(0, values.len())].as_str())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(measurements: &str) -> DataSpec {
+        DataSpec::from_toml(&format!(
+            r#"
+seed = 7
+start_time = "1000000000"
+ticks = 3
+time_spacing = {{ type = "regular", interval_ms = 1 }}
+{}
+"#,
+            measurements
+        ))
+        .unwrap()
+    }
+
+    fn lines(spec: DataSpec) -> Vec<String> {
+        Generator::new(spec).map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn same_seed_same_lines() {
+        let measurements = r#"
+[[measurements]]
+name = "cpu"
+lines_per_tick = 5
+[[measurements.tags]]
+name = "host"
+cardinality = 20
+distribution = { type = "zipf", exponent = 1.2 }
+[[measurements.fields]]
+name = "usage"
+value = { type = "random_walk", start = 50.0, step = 5.0, min = 0.0, max = 100.0 }
+[[measurements.fields]]
+name = "state"
+value = { type = "string", values = ["ok", "warn", "crit"] }
+"#;
+        let first = lines(spec(measurements));
+        assert_eq!(first.len(), 15);
+        assert_eq!(first, lines(spec(measurements)));
+
+        let mut reseeded = spec(measurements);
+        reseeded.seed = 8;
+        assert_ne!(first, lines(reseeded));
+    }
+
+    #[test]
+    fn sequential_tags_enumerate_series() {
+        let lines = lines(spec(
+            r#"
+[[measurements]]
+name = "cpu"
+lines_per_tick = 4
+[[measurements.tags]]
+name = "host"
+cardinality = 2
+distribution = { type = "sequential" }
+[[measurements.tags]]
+name = "region"
+cardinality = 2
+distribution = { type = "sequential" }
+[[measurements.fields]]
+name = "count"
+value = { type = "counter", start = 10, increment = 5 }
+"#,
+        ));
+
+        assert_eq!(
+            &lines[..6],
+            &[
+                "cpu,host=host-0,region=region-0 count=10i 1000000000",
+                "cpu,host=host-1,region=region-0 count=10i 1000000000",
+                "cpu,host=host-0,region=region-1 count=10i 1000000000",
+                "cpu,host=host-1,region=region-1 count=10i 1000000000",
+                "cpu,host=host-0,region=region-0 count=15i 1001000000",
+                "cpu,host=host-1,region=region-0 count=15i 1001000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn values_stay_in_range() {
+        let lines = lines(spec(
+            r#"
+[[measurements]]
+name = "m"
+lines_per_tick = 100
+[[measurements.fields]]
+name = "walk"
+value = { type = "random_walk", start = 0.5, step = 10.0, min = 0.0, max = 1.0 }
+"#,
+        ));
+
+        for line in lines {
+            let value: f64 = line
+                .splitn(2, "walk=")
+                .nth(1)
+                .and_then(|rest| rest.splitn(2, ' ').next())
+                .unwrap()
+                .parse()
+                .unwrap();
+            assert!((0.0..=1.0).contains(&value), "{} out of range", line);
+        }
+    }
+}
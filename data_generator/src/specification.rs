@@ -0,0 +1,398 @@
+//! The specification of a generated workload, usually read from a
+//! TOML file such as:
+//!
+//! ```toml
+//! seed = 42
+//! start_time = "2020-11-01T00:00:00Z"
+//! ticks = 360
+//!
+//! [time_spacing]
+//! type = "jittered"
+//! interval_ms = 10000
+//! jitter_ms = 500
+//!
+//! [[measurements]]
+//! name = "cpu"
+//! lines_per_tick = 10
+//!
+//! [[measurements.tags]]
+//! name = "host"
+//! cardinality = 100
+//! distribution = { type = "zipf", exponent = 1.1 }
+//!
+//! [[measurements.fields]]
+//! name = "usage_user"
+//! value = { type = "random_walk", start = 50.0, step = 2.0, min = 0.0, max = 100.0 }
+//! ```
+
+use serde::Deserialize;
+use snafu::{ensure, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error parsing the specification: {}", source))]
+    Parsing { source: toml::de::Error },
+
+    #[snafu(display("Invalid start time '{}', expected nanoseconds or RFC 3339", time))]
+    InvalidStartTime { time: String },
+
+    #[snafu(display("The specification has no measurements"))]
+    NoMeasurements {},
+
+    #[snafu(display("Measurement {} has no fields", measurement))]
+    NoFields { measurement: String },
+
+    #[snafu(display("Tag {} of measurement {} has no values", tag, measurement))]
+    NoTagValues { measurement: String, tag: String },
+
+    #[snafu(display(
+        "Tag {} of measurement {} has a zipf exponent that is negative or not finite",
+        tag,
+        measurement
+    ))]
+    InvalidExponent { measurement: String, tag: String },
+
+    #[snafu(display(
+        "Field {} of measurement {} has an empty range or no values",
+        field,
+        measurement
+    ))]
+    EmptyValueRange { measurement: String, field: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Describes the lines a `Generator` produces. The same specification
+/// and seed always produce the same lines.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DataSpec {
+    /// Seeds the random choices of tag values, field values and
+    /// times
+    #[serde(default)]
+    pub seed: u64,
+
+    /// The time of the first tick, in nanoseconds since the epoch or
+    /// in RFC 3339 format. Defaults to the time the generator is
+    /// created, so it must be set for the output to be reproducible.
+    pub start_time: Option<String>,
+
+    /// The number of ticks to generate lines for
+    pub ticks: usize,
+
+    #[serde(default)]
+    pub time_spacing: TimeSpacing,
+
+    pub measurements: Vec<MeasurementSpec>,
+}
+
+impl DataSpec {
+    /// Parses and validates a specification in TOML format
+    pub fn from_toml(spec: &str) -> Result<Self> {
+        let spec: Self = toml::from_str(spec).context(Parsing)?;
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    /// Checks the specification describes lines that can be generated
+    pub fn validate(&self) -> Result<()> {
+        ensure!(!self.measurements.is_empty(), NoMeasurements);
+        if let Some(time) = &self.start_time {
+            ensure!(parse_time(time).is_some(), InvalidStartTime { time });
+        }
+
+        for measurement in &self.measurements {
+            ensure!(
+                !measurement.fields.is_empty(),
+                NoFields {
+                    measurement: &measurement.name
+                }
+            );
+            for tag in &measurement.tags {
+                ensure!(
+                    tag.cardinality > 0,
+                    NoTagValues {
+                        measurement: &measurement.name,
+                        tag: &tag.name,
+                    }
+                );
+                if let Distribution::Zipf { exponent } = tag.distribution {
+                    ensure!(
+                        exponent >= 0.0 && exponent.is_finite(),
+                        InvalidExponent {
+                            measurement: &measurement.name,
+                            tag: &tag.name,
+                        }
+                    );
+                }
+            }
+            for field in &measurement.fields {
+                ensure!(
+                    field.value.is_valid(),
+                    EmptyValueRange {
+                        measurement: &measurement.name,
+                        field: &field.name,
+                    }
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The number of lines the specification describes
+    pub fn total_lines(&self) -> usize {
+        let lines_per_tick: usize = self.measurements.iter().map(|m| m.lines_per_tick).sum();
+        self.ticks * lines_per_tick
+    }
+
+    /// The time of the first tick, in nanoseconds since the epoch, if
+    /// one is specified
+    pub fn start_time_nanos(&self) -> Option<i64> {
+        self.start_time.as_deref().and_then(parse_time)
+    }
+}
+
+/// The spacing of the ticks at which lines are generated
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum TimeSpacing {
+    /// Ticks exactly `interval_ms` apart
+    Regular { interval_ms: u64 },
+    /// Ticks `interval_ms` apart, each moved by up to `jitter_ms`
+    /// either way
+    Jittered { interval_ms: u64, jitter_ms: u64 },
+    /// Ticks at random times, an average of `mean_interval_ms` apart,
+    /// as in a Poisson process
+    Random { mean_interval_ms: u64 },
+}
+
+impl Default for TimeSpacing {
+    fn default() -> Self {
+        Self::Regular {
+            interval_ms: 10_000,
+        }
+    }
+}
+
+/// Describes the lines of a measurement
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeasurementSpec {
+    pub name: String,
+
+    /// The number of lines generated at each tick
+    #[serde(default = "default_lines_per_tick")]
+    pub lines_per_tick: usize,
+
+    #[serde(default)]
+    pub tags: Vec<TagSpec>,
+
+    pub fields: Vec<FieldSpec>,
+}
+
+fn default_lines_per_tick() -> usize {
+    1
+}
+
+/// Describes the values of a tag
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagSpec {
+    pub name: String,
+
+    /// The number of distinct values of the tag, named
+    /// `<name>-<index>`
+    pub cardinality: usize,
+
+    #[serde(default)]
+    pub distribution: Distribution,
+}
+
+/// How often each value of a tag is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Distribution {
+    /// Every value is equally likely
+    Uniform,
+    /// The value with index `i` is chosen in proportion to
+    /// `1 / (i + 1)^exponent`, so a few values are much more common
+    /// than the rest
+    Zipf { exponent: f64 },
+    /// The values are chosen in turn. The values of all the
+    /// sequential tags of a measurement are enumerated together, so a
+    /// tick with as many lines as the product of their cardinalities
+    /// has a line for each combination.
+    Sequential,
+}
+
+impl Default for Distribution {
+    fn default() -> Self {
+        Self::Uniform
+    }
+}
+
+/// Describes the values of a field
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldSpec {
+    pub name: String,
+    pub value: ValuePattern,
+}
+
+/// The pattern of the values of a field. Patterns with state, such as
+/// a random walk, have their own state in each series.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ValuePattern {
+    /// The same float every time
+    Constant { value: f64 },
+    /// Floats uniformly distributed in `[min, max)`
+    RandomFloat { min: f64, max: f64 },
+    /// Integers uniformly distributed in `[min, max]`
+    RandomInteger { min: i64, max: i64 },
+    /// Floats that start at `start`, and change by up to `step`
+    /// either way each time, staying within `[min, max]`
+    RandomWalk {
+        start: f64,
+        step: f64,
+        min: f64,
+        max: f64,
+    },
+    /// Floats following a sine wave of the time with a period of
+    /// `period_ms`
+    Sine {
+        amplitude: f64,
+        period_ms: u64,
+        #[serde(default)]
+        offset: f64,
+    },
+    /// Integers that start at `start` and grow by `increment` each
+    /// time
+    Counter { start: i64, increment: i64 },
+    /// Booleans that are true with the given probability
+    Boolean { probability: f64 },
+    /// Strings chosen uniformly from `values`
+    String { values: Vec<String> },
+}
+
+impl ValuePattern {
+    fn is_valid(&self) -> bool {
+        match self {
+            Self::RandomFloat { min, max } => min < max,
+            Self::RandomInteger { min, max } => min <= max,
+            Self::RandomWalk {
+                min, max, start, ..
+            } => min <= start && start <= max,
+            Self::Sine { period_ms, .. } => *period_ms > 0,
+            Self::Boolean { probability } => (0.0..=1.0).contains(probability),
+            Self::String { values } => !values.is_empty(),
+            Self::Constant { .. } | Self::Counter { .. } => true,
+        }
+    }
+}
+
+/// Parses a time given as nanoseconds since the epoch or in RFC 3339
+/// format
+fn parse_time(time: &str) -> Option<i64> {
+    time.parse().ok().or_else(|| {
+        chrono::DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|time| time.timestamp_nanos())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_spec() {
+        let spec = DataSpec::from_toml(
+            r#"
+seed = 42
+start_time = "2020-11-01T00:00:00Z"
+ticks = 3
+
+[time_spacing]
+type = "jittered"
+interval_ms = 1000
+jitter_ms = 100
+
+[[measurements]]
+name = "cpu"
+lines_per_tick = 2
+
+[[measurements.tags]]
+name = "host"
+cardinality = 10
+distribution = { type = "zipf", exponent = 1.5 }
+
+[[measurements.fields]]
+name = "usage"
+value = { type = "random_walk", start = 50.0, step = 1.0, min = 0.0, max = 100.0 }
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(spec.seed, 42);
+        assert_eq!(spec.start_time_nanos(), Some(1_604_188_800_000_000_000));
+        assert_eq!(
+            spec.time_spacing,
+            TimeSpacing::Jittered {
+                interval_ms: 1000,
+                jitter_ms: 100
+            }
+        );
+        let measurement = &spec.measurements[0];
+        assert_eq!(measurement.lines_per_tick, 2);
+        assert_eq!(
+            measurement.tags[0].distribution,
+            Distribution::Zipf { exponent: 1.5 }
+        );
+        assert_eq!(
+            measurement.fields[0].value,
+            ValuePattern::RandomWalk {
+                start: 50.0,
+                step: 1.0,
+                min: 0.0,
+                max: 100.0
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_specs() {
+        let err = DataSpec::from_toml("ticks = 1\nmeasurements = []").unwrap_err();
+        assert!(matches!(err, Error::NoMeasurements {}));
+
+        let err = DataSpec::from_toml(
+            r#"
+ticks = 1
+[[measurements]]
+name = "cpu"
+[[measurements.fields]]
+name = "usage"
+value = { type = "random_float", min = 1.0, max = 1.0 }
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Field usage of measurement cpu has an empty range or no values"
+        );
+
+        let err = DataSpec::from_toml(
+            r#"
+ticks = 1
+start_time = "yesterday"
+[[measurements]]
+name = "cpu"
+[[measurements.fields]]
+name = "usage"
+value = { type = "constant", value = 1.0 }
+"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, Error::InvalidStartTime { .. }));
+    }
+}
@@ -148,12 +148,23 @@ pub fn lines_to_replicated_write(
         lines,
     );
 
+    batch_to_replicated_write(writer, sequence, &entry_bytes)
+}
+
+/// Wraps the raw bytes of a `WriteBufferBatch` (as returned by
+/// `split_lines_into_write_entry_partitions`) in a `ReplicatedWrite`
+/// from `writer` with sequence number `sequence`
+pub fn batch_to_replicated_write(
+    writer: u32,
+    sequence: u64,
+    entry_bytes: &[u8],
+) -> ReplicatedWrite {
     let mut hasher = Hasher::new();
-    hasher.update(&entry_bytes);
+    hasher.update(entry_bytes);
     let checksum = hasher.finalize();
 
     let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
-    let payload = fbb.create_vector_direct(&entry_bytes);
+    let payload = fbb.create_vector_direct(entry_bytes);
 
     let write = wb::ReplicatedWrite::create(
         &mut fbb,
@@ -212,6 +223,104 @@ pub fn split_lines_into_write_entry_partitions(
     data.split_off(idx)
 }
 
+/// A value of a column of a row written by `rows_to_write_entry_partition`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RowValue<'a> {
+    Tag(&'a str),
+    String(&'a str),
+    F64(f64),
+    I64(i64),
+    Bool(bool),
+}
+
+/// The rows written to a table by `rows_to_write_entry_partition`.
+/// Each row is the (column name, value) of each of its columns with a
+/// value, including the time column.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableRows<'a> {
+    pub name: &'a str,
+    pub rows: Vec<Vec<(&'a str, RowValue<'a>)>>,
+}
+
+/// Returns the raw bytes of a `WriteBufferBatch` with a single entry,
+/// for the partition with key `partition_key`, writing the rows of
+/// `tables`. This writes data that is not line protocol, such as data
+/// read back from another partition, without formatting it as line
+/// protocol first.
+pub fn rows_to_write_entry_partition(partition_key: &str, tables: &[TableRows<'_>]) -> Vec<u8> {
+    let mut fbb = flatbuffers::FlatBufferBuilder::new_with_capacity(1024);
+
+    let table_batches = tables
+        .iter()
+        .map(|table| {
+            let rows = table
+                .rows
+                .iter()
+                .map(|row| add_row(&mut fbb, row))
+                .collect::<Vec<_>>();
+
+            let name = fbb.create_string(table.name);
+            let rows = fbb.create_vector(&rows);
+            wb::TableWriteBatch::create(
+                &mut fbb,
+                &wb::TableWriteBatchArgs {
+                    name: Some(name),
+                    rows: Some(rows),
+                },
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let batches_vec = fbb.create_vector(&table_batches);
+    let key = fbb.create_string(partition_key);
+    let entry = wb::WriteBufferEntry::create(
+        &mut fbb,
+        &wb::WriteBufferEntryArgs {
+            partition_key: Some(key),
+            table_batches: Some(batches_vec),
+            ..Default::default()
+        },
+    );
+
+    let entries_vec = fbb.create_vector(&[entry]);
+    let batch = wb::WriteBufferBatch::create(
+        &mut fbb,
+        &wb::WriteBufferBatchArgs {
+            entries: Some(entries_vec),
+        },
+    );
+
+    fbb.finish(batch, None);
+
+    let (mut data, idx) = fbb.collapse();
+    data.split_off(idx)
+}
+
+fn add_row<'a>(
+    fbb: &mut FlatBufferBuilder<'a>,
+    row: &[(&str, RowValue<'_>)],
+) -> flatbuffers::WIPOffset<wb::Row<'a>> {
+    let row_values = row
+        .iter()
+        .map(|(column, value)| match *value {
+            RowValue::Tag(v) => add_tag_value(fbb, column, v),
+            RowValue::String(v) => add_string_value(fbb, column, v),
+            RowValue::F64(v) => add_f64_value(fbb, column, v),
+            RowValue::I64(v) => add_i64_value(fbb, column, v),
+            RowValue::Bool(v) => add_bool_value(fbb, column, v),
+        })
+        .collect::<Vec<_>>();
+
+    let row_values = fbb.create_vector(&row_values);
+
+    wb::Row::create(
+        fbb,
+        &wb::RowArgs {
+            values: Some(row_values),
+        },
+    )
+}
+
 fn add_write_entry<'a>(
     fbb: &mut FlatBufferBuilder<'a>,
     partition_key: Option<&str>,
@@ -1,8 +1,13 @@
-use influxdb_line_protocol::ParsedLine;
+use crate::RECEIVE_TIME_COLUMN_NAME;
+use influxdb_line_protocol::{EscapedStr, FieldValue, ParsedLine};
 
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, TimeZone, Utc,
+};
 use serde::{Deserialize, Serialize};
-use snafu::Snafu;
+use snafu::{ensure, Snafu};
+use std::{borrow::Cow, collections::BTreeSet};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -11,13 +16,23 @@ pub enum Error {
         source_module: &'static str,
         source: Box<dyn std::error::Error + Send + Sync + 'static>,
     },
+
+    #[snafu(display("Partition template part {} is not supported", part))]
+    UnsupportedTemplatePart { part: String },
+
+    #[snafu(display("Partition template time format {:?} is invalid", format))]
+    InvalidTimeFormat { format: String },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
 /// DatabaseRules contains the rules for replicating data, sending data to subscribers, and
 /// querying data for a single database.
-#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq)]
+///
+/// Any field left out when deserializing takes its default value, so rules can be supplied
+/// partially when creating or updating a database.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, Eq, PartialEq)]
+#[serde(default)]
 pub struct DatabaseRules {
     /// Template that generates a partition key for each row inserted into the db
     pub partition_template: PartitionTemplate,
@@ -71,6 +86,85 @@ pub struct DatabaseRules {
 
     /// When set this will buffer WAL writes in memory based on the configuration.
     pub wal_buffer_config: Option<WalBufferConfig>,
+
+    /// Rules for replacing the data in a partition with aggregates over fixed time windows
+    /// once it has aged, such as rolling raw data up into 1 minute means after 7 days and
+    /// into 1 hour means after 30 days. The rule with the largest `after` that a partition's
+    /// newest data is older than is the one applied to it.
+    #[serde(default)]
+    pub downsampling_rules: Vec<DownsamplingRule>,
+
+    /// The maximum size in bytes the mutable buffer for this database may grow to. Writes that
+    /// would take the buffer over this size are rejected. `None` means no limit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mutable_buffer_size: Option<u64>,
+
+    /// How long data is kept for. Partitions whose newest data is older than this are dropped.
+    /// `None` means data is kept forever.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retention_period_seconds: Option<u64>,
+
+    /// How long a partition may go without being written to before its chunks are closed,
+    /// persisted to parquet files and evicted from the mutable buffer, however small it is.
+    /// Evicted chunks are no longer queried. `None` means partitions are kept in memory until
+    /// they are dropped. Requires the database to have a WAL directory to persist to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub persist_idle_seconds: Option<u64>,
+
+    /// The range of timestamps lines written to this database may have, and what happens to
+    /// lines outside it.
+    #[serde(default)]
+    pub timestamp_bounds: TimestampBounds,
+
+    /// How the names of the measurements, tags and fields of lines written to this database are
+    /// normalized, and the checks they must pass for the lines to be accepted.
+    #[serde(default)]
+    pub name_rules: NameRules,
+
+    /// How the tag sets of lines written to this database are put in a canonical form, so that
+    /// lines of the same series written by different clients have the same tags in the same
+    /// order.
+    #[serde(default)]
+    pub tag_set_rules: TagSetRules,
+
+    /// What happens to lines written to this database with float field values that are NaN or
+    /// infinite.
+    #[serde(default)]
+    pub non_finite_floats: NonFiniteFloatPolicy,
+
+    /// The tables for which the count, sum, minimum and maximum of each numeric field are kept
+    /// per minute of each series as rows are written, so that window aggregates of whole
+    /// minutes are computed from them rather than from the rows. Only applies to tables created
+    /// in a partition after the table is added here.
+    #[serde(default)]
+    pub pre_aggregated_tables: Vec<String>,
+
+    /// How far behind the newest row of the open chunk of its partition a row may be before
+    /// it is written to a late chunk of the partition instead. Each late chunk holds the late
+    /// rows of one time window this long, so that the open chunk keeps a time range queries
+    /// can prune on, and late chunks are merged with the other chunks of the partition when it
+    /// is compacted. `None` means every row is written to the open chunk.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub late_write_seconds: Option<u64>,
+
+    /// The timestamp lines written to this database without one are given, and whether the
+    /// time each line is received at is recorded.
+    #[serde(default)]
+    pub receive_time: ReceiveTime,
+
+    /// The bucket of an org, in the InfluxDB 2.0 APIs, whose data this database stores. It is
+    /// recorded when the database is created or first written to as a bucket, and the
+    /// databases of different orgs are isolated from each other. `None` means the database
+    /// does not belong to an org.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<Tenant>,
+}
+
+/// A bucket within an org, in the InfluxDB 2.0 APIs
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+pub struct Tenant {
+    pub org: String,
+    pub bucket: String,
 }
 
 impl DatabaseRules {
@@ -81,12 +175,59 @@ impl DatabaseRules {
     ) -> Result<String> {
         self.partition_template.partition_key(line, default_time)
     }
+
+    /// Applies the name rules and tag set rules of the database to `lines`. Returns the lines
+    /// which pass their checks, with their names normalized and their tag sets canonicalized,
+    /// and those which do not.
+    ///
+    /// Repeated tag keys are handled by the tag set rules before names are normalized, so tag
+    /// keys which only become the same once normalized are rejected by the name rules.
+    pub fn apply_line_rules<'a, 'b>(
+        &self,
+        lines: &'a [ParsedLine<'b>],
+    ) -> (Cow<'a, [ParsedLine<'b>]>, Vec<RejectedLine>) {
+        let has_name_rules = self.name_rules != NameRules::default();
+        if !has_name_rules && !lines.iter().any(|line| self.tag_set_rules.changes(line)) {
+            return (Cow::Borrowed(lines), vec![]);
+        }
+
+        apply_to_lines(lines, |line| {
+            let line = self.tag_set_rules.deduplicate(line)?;
+            let line = if has_name_rules {
+                self.name_rules.apply_line(&line)?
+            } else {
+                line
+            };
+            Ok(self.tag_set_rules.sort(line))
+        })
+    }
+
+    /// Returns an error if these rules can not be applied, such as
+    /// when the partition template has parts that are not supported
+    pub fn validate(&self) -> Result<()> {
+        self.partition_template.validate()
+    }
+
+    /// Returns `retention_period_seconds` in nanoseconds, the unit of timestamps
+    pub fn retention_period_nanos(&self) -> Option<i64> {
+        self.retention_period_seconds.map(seconds_to_nanos)
+    }
+
+    /// Returns `persist_idle_seconds` in nanoseconds, the unit of timestamps
+    pub fn persist_idle_nanos(&self) -> Option<i64> {
+        self.persist_idle_seconds.map(seconds_to_nanos)
+    }
+
+    /// Returns `late_write_seconds` in nanoseconds, the unit of timestamps
+    pub fn late_write_nanos(&self) -> Option<i64> {
+        self.late_write_seconds.map(seconds_to_nanos)
+    }
 }
 
 /// WalBufferConfig defines the configuration for buffering data from the WAL in memory. This
 /// buffer is used for asynchronous replication and to collect segments before sending them to
 /// object storage.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct WalBufferConfig {
     /// The size the WAL buffer should be limited to. Once the buffer gets to this size it will
     /// drop old segments to remain below this size, but still try to hold as much in memory as
@@ -120,6 +261,494 @@ pub enum WalBufferRollover {
     ReturnError,
 }
 
+/// `DownsamplingRule` defines how the data in a partition is rolled up once all of it is older
+/// than `after_seconds`: the rows of each series are replaced by one row per window of
+/// `interval_seconds`, timestamped with the start of the window, holding the `aggregate` of each
+/// field over the rows in that window.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub struct DownsamplingRule {
+    /// How old, in seconds, the newest data in a partition must be for this rule to apply
+    pub after_seconds: u64,
+    /// The width, in seconds, of the windows the data is aggregated over
+    pub interval_seconds: u64,
+    /// How the values of each field within a window are combined
+    pub aggregate: DownsamplingAggregate,
+}
+
+impl DownsamplingRule {
+    /// Returns `after_seconds` in nanoseconds, the unit of timestamps
+    pub fn after_nanos(&self) -> i64 {
+        seconds_to_nanos(self.after_seconds)
+    }
+
+    /// Returns `interval_seconds` in nanoseconds, the unit of timestamps
+    pub fn interval_nanos(&self) -> i64 {
+        seconds_to_nanos(self.interval_seconds)
+    }
+}
+
+/// The default of `TimestampBounds::max_future_seconds`: one day, which allows for clients with
+/// clocks that are somewhat ahead but catches timestamps in the wrong unit or in a later year.
+pub const DEFAULT_MAX_FUTURE_SECONDS: u64 = 24 * 60 * 60;
+
+/// `TimestampBounds` is the range of timestamps lines written to a database may have. Lines with
+/// timestamps outside it are usually bogus, such as those from a client with a badly set clock,
+/// and once written they skew retention, partitioning and queries over recent data.
+///
+/// Lines without a timestamp take the time they are written at, which is always in range unless
+/// `min_nanos` is in the future. Timestamps outside the range of `i64` nanoseconds, before
+/// 1677-09-21 or after 2262-04-11, fail to parse and are always rejected.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct TimestampBounds {
+    /// The earliest timestamp accepted, in nanoseconds since the epoch. `None` means there is no
+    /// lower bound.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_nanos: Option<i64>,
+    /// How far, in seconds, timestamps may be ahead of the time they are written at. `None`
+    /// means there is no upper bound.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_future_seconds: Option<u64>,
+    /// What happens to lines with timestamps outside the bounds
+    pub policy: TimestampPolicy,
+}
+
+impl Default for TimestampBounds {
+    fn default() -> Self {
+        Self {
+            min_nanos: None,
+            max_future_seconds: Some(DEFAULT_MAX_FUTURE_SECONDS),
+            policy: TimestampPolicy::Reject,
+        }
+    }
+}
+
+impl TimestampBounds {
+    /// Returns the earliest and latest timestamps accepted at `now`, in nanoseconds since the
+    /// epoch
+    pub fn range(&self, now: i64) -> (i64, i64) {
+        let min = self.min_nanos.unwrap_or(i64::MIN);
+        let max = self
+            .max_future_seconds
+            .map(|seconds| now.saturating_add(seconds_to_nanos(seconds)))
+            .unwrap_or(i64::MAX);
+        (min, max)
+    }
+}
+
+/// `TimestampPolicy` defines what happens to lines with timestamps outside the `TimestampBounds`
+/// of a database.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub enum TimestampPolicy {
+    /// Reject the whole write with an error, so none of its lines are written
+    Reject,
+    /// Write the lines with their timestamps moved to the nearest bound
+    Clamp,
+    /// Write the lines as they are, ignoring the bounds
+    Accept,
+}
+
+impl Default for TimestampPolicy {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+/// `NonFiniteFloatPolicy` defines what happens to lines with float field values that are NaN or
+/// infinite when they are written to a database.
+///
+/// Stored values are returned as they were written by queries that read rows. The window
+/// aggregates of storage queries exclude both NaN and infinite values from every aggregate, as
+/// if the field had no value at their time, whether they are computed from the rows or from
+/// pre-aggregates. The statistics of a column, and downsampling, exclude NaN values (but not
+/// infinities, which are ordered like any other value) from its min, max, sum and mean, while
+/// still counting them and keeping them as the first or last value. SQL aggregates are computed
+/// by DataFusion, which excludes neither.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub enum NonFiniteFloatPolicy {
+    /// Reject the whole write with an error, so none of its lines are written
+    Reject,
+    /// Write the lines as they are
+    Store,
+}
+
+impl Default for NonFiniteFloatPolicy {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+/// `ReceiveTime` defines how the time lines are received at is used when they are written to a
+/// database. Lines without a timestamp are given the time they were received at, truncated to
+/// `precision`, and with `record` set every line also has the time it was received at, in
+/// nanoseconds, as the integer field `RECEIVE_TIME_COLUMN_NAME`.
+///
+/// All the lines of a write are received at the same time, so lines without a timestamp in
+/// one write all have the same timestamp.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct ReceiveTime {
+    /// The precision lines without a timestamp are given the time they were received at to
+    pub precision: TimePrecision,
+    /// Whether the time each line is received at is recorded as a field
+    pub record: bool,
+}
+
+impl ReceiveTime {
+    /// Gives the lines in `lines` without a timestamp the time `received`, in nanoseconds,
+    /// truncated to the precision of these rules, and records `received` on every line if
+    /// these rules ask for it. Lines that already have the receive time field keep their
+    /// value, so a write is only given the time it was first received at.
+    pub fn apply<'a, 'b>(
+        &self,
+        lines: &'a [ParsedLine<'b>],
+        received: i64,
+    ) -> Cow<'a, [ParsedLine<'b>]> {
+        let needs_time = |line: &ParsedLine<'_>| line.timestamp.is_none();
+        let needs_record = |line: &ParsedLine<'_>| {
+            self.record
+                && !line
+                    .field_set
+                    .iter()
+                    .any(|(key, _)| key.as_str() == RECEIVE_TIME_COLUMN_NAME)
+        };
+        if !lines
+            .iter()
+            .any(|line| needs_time(line) || needs_record(line))
+        {
+            return Cow::Borrowed(lines);
+        }
+
+        let timestamp = self.precision.truncate(received);
+        Cow::Owned(
+            lines
+                .iter()
+                .map(|line| {
+                    let mut line = line.clone();
+                    if needs_record(&line) {
+                        line.field_set.push((
+                            EscapedStr::from(RECEIVE_TIME_COLUMN_NAME),
+                            FieldValue::I64(received),
+                        ));
+                    }
+                    line.timestamp = line.timestamp.or(Some(timestamp));
+                    line
+                })
+                .collect(),
+        )
+    }
+}
+
+/// `TimePrecision` is the precision of a timestamp given to a line by the server
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub enum TimePrecision {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl Default for TimePrecision {
+    fn default() -> Self {
+        Self::Nanoseconds
+    }
+}
+
+impl TimePrecision {
+    /// Returns `nanos` truncated to this precision, in nanoseconds
+    pub fn truncate(self, nanos: i64) -> i64 {
+        let unit = match self {
+            Self::Nanoseconds => 1,
+            Self::Microseconds => 1_000,
+            Self::Milliseconds => 1_000_000,
+            Self::Seconds => 1_000_000_000,
+        };
+        nanos - nanos.rem_euclid(unit)
+    }
+}
+
+fn seconds_to_nanos(seconds: u64) -> i64 {
+    (seconds.min(i64::MAX as u64) as i64).saturating_mul(1_000_000_000)
+}
+
+/// `NameRules` are the changes made to, and the checks made of, the names of the measurements,
+/// tags and fields of lines written to a database. Names are normalized before they are checked.
+/// Lines with a name that fails the checks are rejected, and the other lines of the write are
+/// accepted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct NameRules {
+    /// The most bytes a name may have. `None` means there is no limit.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_length: Option<usize>,
+    /// The characters a name may have besides ASCII letters and digits, such as `"_-."`. `None`
+    /// means a name may have any character.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_characters: Option<String>,
+    /// Whether leading and trailing whitespace is removed from names
+    pub trim: bool,
+    /// Whether names are converted to lowercase
+    pub lowercase: bool,
+}
+
+/// A line of a write rejected by the `NameRules` of a database
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct RejectedLine {
+    /// The position of the line in the write, starting from 1
+    pub line_number: usize,
+    /// Why the line was rejected
+    pub reason: String,
+}
+
+impl NameRules {
+    /// Normalizes the names of `lines` and checks them against these rules. Returns the lines
+    /// which pass the checks, with their names normalized, and those which do not.
+    pub fn apply<'a, 'b>(
+        &self,
+        lines: &'a [ParsedLine<'b>],
+    ) -> (Cow<'a, [ParsedLine<'b>]>, Vec<RejectedLine>) {
+        if *self == Self::default() {
+            return (Cow::Borrowed(lines), vec![]);
+        }
+
+        apply_to_lines(lines, |line| self.apply_line(line))
+    }
+
+    fn apply_line<'a>(&self, line: &ParsedLine<'a>) -> Result<ParsedLine<'a>, String> {
+        let mut line = line.clone();
+
+        line.series.measurement = self.apply_name("measurement", &line.series.measurement)?;
+
+        if let Some(tag_set) = &mut line.series.tag_set {
+            let mut keys = BTreeSet::new();
+            for (key, _) in tag_set.iter_mut() {
+                *key = self.apply_name("tag", key)?;
+                if !keys.insert(key.to_string()) {
+                    return Err(format!(
+                        "tag name {:?} appears more than once",
+                        key.as_str()
+                    ));
+                }
+            }
+        }
+
+        let mut keys = BTreeSet::new();
+        for (key, _) in line.field_set.iter_mut() {
+            *key = self.apply_name("field", key)?;
+            if !keys.insert(key.to_string()) {
+                return Err(format!(
+                    "field name {:?} appears more than once",
+                    key.as_str()
+                ));
+            }
+        }
+
+        Ok(line)
+    }
+
+    /// Returns `name` normalized, or why it is not allowed
+    fn apply_name<'a>(&self, kind: &str, name: &EscapedStr<'a>) -> Result<EscapedStr<'a>, String> {
+        let name = self.normalize(name);
+
+        if name.is_empty() {
+            return Err(format!("{} name is empty", kind));
+        }
+
+        if let Some(max_length) = self.max_length {
+            if name.len() > max_length {
+                return Err(format!(
+                    "{} name {:?} is longer than {} bytes",
+                    kind,
+                    name.as_str(),
+                    max_length
+                ));
+            }
+        }
+
+        if let Some(allowed) = &self.allowed_characters {
+            if let Some(c) = name
+                .chars()
+                .find(|&c| !c.is_ascii_alphanumeric() && !allowed.contains(c))
+            {
+                return Err(format!(
+                    "{} name {:?} has the character {:?}, which is not allowed",
+                    kind,
+                    name.as_str(),
+                    c
+                ));
+            }
+        }
+
+        Ok(name)
+    }
+
+    fn normalize<'a>(&self, name: &EscapedStr<'a>) -> EscapedStr<'a> {
+        let mut normalized = Cow::Borrowed(name.as_str());
+        if self.trim {
+            normalized = match normalized {
+                Cow::Borrowed(s) => Cow::Borrowed(s.trim()),
+                Cow::Owned(s) => Cow::Owned(s.trim().to_string()),
+            };
+        }
+        if self.lowercase && normalized.chars().any(char::is_uppercase) {
+            normalized = Cow::Owned(normalized.to_lowercase());
+        }
+
+        if normalized == name.as_str() {
+            name.clone()
+        } else {
+            EscapedStr::CopiedValue(normalized.into_owned())
+        }
+    }
+}
+
+/// Applies `apply_line` to each of `lines`, returning the lines it accepts and those it rejects
+/// with the reason it gives
+fn apply_to_lines<'a, 'b>(
+    lines: &'a [ParsedLine<'b>],
+    apply_line: impl Fn(&ParsedLine<'b>) -> Result<ParsedLine<'b>, String>,
+) -> (Cow<'a, [ParsedLine<'b>]>, Vec<RejectedLine>) {
+    let mut accepted = Vec::with_capacity(lines.len());
+    let mut rejected = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        match apply_line(line) {
+            Ok(line) => accepted.push(line),
+            Err(reason) => rejected.push(RejectedLine {
+                line_number: i + 1,
+                reason,
+            }),
+        }
+    }
+    (Cow::Owned(accepted), rejected)
+}
+
+/// `TagSetRules` define the canonical form the tag sets of lines written to a database are put
+/// in. Line protocol allows the tags of a line in any order, and a tag key more than once, so
+/// without them the same series written by two clients can differ only in the order or
+/// repetition of its tags.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct TagSetRules {
+    /// Whether the tags of each line are sorted by key
+    pub sort: bool,
+    /// What happens to lines with a tag key that appears more than once
+    pub duplicates: DuplicateTagPolicy,
+}
+
+impl Default for TagSetRules {
+    fn default() -> Self {
+        Self {
+            sort: true,
+            duplicates: DuplicateTagPolicy::Reject,
+        }
+    }
+}
+
+impl TagSetRules {
+    /// Whether these rules change the tag set of `line`
+    fn changes(&self, line: &ParsedLine<'_>) -> bool {
+        let tag_set = match &line.series.tag_set {
+            Some(tag_set) => tag_set,
+            None => return false,
+        };
+
+        let sorted = tag_set
+            .iter()
+            .zip(tag_set.iter().skip(1))
+            .all(|((a, _), (b, _))| a.as_str() < b.as_str());
+        if sorted {
+            // keys in strictly increasing order are also unique
+            return false;
+        }
+
+        self.sort || has_duplicate_keys(tag_set)
+    }
+
+    /// Returns `line` with the tag keys that appear more than once handled according to the
+    /// duplicate policy, or why it is rejected
+    fn deduplicate<'a>(&self, line: &ParsedLine<'a>) -> Result<ParsedLine<'a>, String> {
+        let mut line = line.clone();
+        let tag_set = match &mut line.series.tag_set {
+            Some(tag_set) => tag_set,
+            None => return Ok(line),
+        };
+
+        let mut keys = BTreeSet::new();
+        match self.duplicates {
+            DuplicateTagPolicy::Reject => {
+                for (key, _) in tag_set.iter() {
+                    if !keys.insert(key.to_string()) {
+                        return Err(format!(
+                            "tag name {:?} appears more than once",
+                            key.as_str()
+                        ));
+                    }
+                }
+            }
+            DuplicateTagPolicy::KeepFirst => {
+                tag_set.retain(|(key, _)| keys.insert(key.to_string()));
+            }
+            DuplicateTagPolicy::KeepLast => {
+                tag_set.reverse();
+                tag_set.retain(|(key, _)| keys.insert(key.to_string()));
+                tag_set.reverse();
+            }
+        }
+
+        Ok(line)
+    }
+
+    /// Returns `line` with its tags sorted by key, if these rules sort them
+    fn sort<'a>(&self, mut line: ParsedLine<'a>) -> ParsedLine<'a> {
+        if self.sort {
+            if let Some(tag_set) = &mut line.series.tag_set {
+                tag_set.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+            }
+        }
+        line
+    }
+}
+
+/// Whether a tag key appears more than once in `tag_set`
+fn has_duplicate_keys(tag_set: &[(EscapedStr<'_>, EscapedStr<'_>)]) -> bool {
+    let mut keys = BTreeSet::new();
+    !tag_set.iter().all(|(key, _)| keys.insert(key.as_str()))
+}
+
+/// `DuplicateTagPolicy` defines what happens to lines written to a database with a tag key that
+/// appears more than once.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub enum DuplicateTagPolicy {
+    /// Reject the line, and accept the other lines of the write
+    Reject,
+    /// Write the line with the first value of the tag
+    KeepFirst,
+    /// Write the line with the last value of the tag
+    KeepLast,
+}
+
+impl Default for DuplicateTagPolicy {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+/// `DownsamplingAggregate` is how the values of a field within a downsampling window are combined
+/// into one. Integer fields remain integers (the mean is rounded down), `Count` always produces an
+/// integer, and string and boolean fields are only kept by `Count`, `First` and `Last`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
+pub enum DownsamplingAggregate {
+    Mean,
+    Sum,
+    Min,
+    Max,
+    Count,
+    /// The value with the earliest timestamp in the window
+    First,
+    /// The value with the latest timestamp in the window
+    Last,
+}
+
 /// `PartitionTemplate` is used to compute the partition key of each row that gets written. It
 /// can consist of the table name, a column name and its value, a formatted time, or a string
 /// column and regex captures of its value. For columns that do not appear in the input row,
@@ -127,43 +756,93 @@ pub enum WalBufferRollover {
 ///
 /// The key is constructed in order of the template parts; thus ordering changes what partition
 /// key is generated.
-#[derive(Debug, Serialize, Deserialize, Default, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Default, Eq, PartialEq)]
 pub struct PartitionTemplate {
     parts: Vec<TemplatePart>,
 }
 
 impl PartitionTemplate {
+    /// Creates a template that joins the given parts, in order, to
+    /// form partition keys. For example a `TimeFormat("%Y-%m-%d")`
+    /// followed by a `Column("region")` generates keys such as
+    /// `2020-10-10-region_west`.
+    pub fn new(parts: Vec<TemplatePart>) -> Self {
+        Self { parts }
+    }
+
+    /// The parts of this template, in order
+    pub fn parts(&self) -> &[TemplatePart] {
+        &self.parts
+    }
+
+    /// Returns true if this template has no parts, and so generates
+    /// an empty partition key for every line
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    /// Returns an error if any part of this template is not
+    /// supported, or has a time format that is not a valid strftime
+    /// format
+    pub fn validate(&self) -> Result<()> {
+        for part in &self.parts {
+            match part {
+                TemplatePart::RegexCapture(_) => {
+                    return UnsupportedTemplatePart {
+                        part: format!("{:?}", part),
+                    }
+                    .fail()
+                }
+                TemplatePart::TimeFormat(format) => validate_time_format(format)?,
+                TemplatePart::StrftimeColumn(strftime) => validate_time_format(&strftime.format)?,
+                TemplatePart::Table | TemplatePart::Column(_) => {}
+            }
+        }
+        Ok(())
+    }
+
     pub fn partition_key(
         &self,
         line: &ParsedLine<'_>,
         default_time: &DateTime<Utc>,
     ) -> Result<String> {
-        let parts: Vec<_> = self
+        let parts = self
             .parts
             .iter()
             .map(|p| match p {
-                TemplatePart::Table => line.series.measurement.to_string(),
-                TemplatePart::Column(column) => match line.tag_value(&column) {
+                TemplatePart::Table => Ok(line.series.measurement.to_string()),
+                TemplatePart::Column(column) => Ok(match line.tag_value(&column) {
                     Some(v) => format!("{}_{}", column, v),
                     None => match line.field_value(&column) {
                         Some(v) => format!("{}_{}", column, v),
                         None => "".to_string(),
                     },
-                },
-                TemplatePart::TimeFormat(format) => match line.timestamp {
+                }),
+                TemplatePart::TimeFormat(format) => Ok(match line.timestamp {
                     Some(t) => Utc.timestamp_nanos(t).format(&format).to_string(),
                     None => default_time.format(&format).to_string(),
-                },
-                _ => unimplemented!(),
+                }),
+                TemplatePart::StrftimeColumn(strftime) => {
+                    Ok(match line.field_value(&strftime.column) {
+                        Some(FieldValue::I64(t)) => {
+                            Utc.timestamp_nanos(*t).format(&strftime.format).to_string()
+                        }
+                        _ => "".to_string(),
+                    })
+                }
+                TemplatePart::RegexCapture(_) => UnsupportedTemplatePart {
+                    part: format!("{:?}", p),
+                }
+                .fail(),
             })
-            .collect();
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(parts.join("-"))
     }
 }
 
 /// `TemplatePart` specifies what part of a row should be used to compute this part of a partition key.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub enum TemplatePart {
     Table,
     Column(String),
@@ -172,8 +851,18 @@ pub enum TemplatePart {
     StrftimeColumn(StrftimeColumn),
 }
 
+/// Returns an error if `format` is not a valid strftime format, which
+/// would otherwise make formatting a time with it panic
+fn validate_time_format(format: &str) -> Result<()> {
+    ensure!(
+        StrftimeItems::new(format).all(|item| item != Item::Error),
+        InvalidTimeFormat { format }
+    );
+    Ok(())
+}
+
 /// `RegexCapture` is for pulling parts of a string column into the partition key.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct RegexCapture {
     column: String,
     regex: String,
@@ -181,12 +870,23 @@ pub struct RegexCapture {
 
 /// `StrftimeColumn` can be used to create a time based partition key off some column other than
 /// the builtin `time` column.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct StrftimeColumn {
     column: String,
     format: String,
 }
 
+impl StrftimeColumn {
+    /// Formats the nanosecond timestamps in the integer field `column`
+    /// with the strftime `format`
+    pub fn new(column: impl Into<String>, format: impl Into<String>) -> Self {
+        Self {
+            column: column.into(),
+            format: format.into(),
+        }
+    }
+}
+
 /// `PartitionId` is the object storage identifier for a specific partition. It should be a
 /// path that can be used against an object store to locate all the files and subdirectories
 /// for a partition. It takes the form of `/<writer ID>/<database>/<partition key>/`.
@@ -202,7 +902,7 @@ pub type WriterId = u32;
 ///
 /// For pull based subscriptions, the requester will send a matcher, which the receiver
 /// will execute against its in-memory WAL.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct Subscription {
     pub name: String,
     pub host_group_id: HostGroupId,
@@ -211,7 +911,7 @@ pub struct Subscription {
 
 /// `Matcher` specifies the rule against the table name and/or a predicate
 /// against the row to determine if it matches the write rule.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct Matcher {
     #[serde(flatten)]
     pub tables: MatchTables,
@@ -222,7 +922,7 @@ pub struct Matcher {
 
 /// `MatchTables` looks at the table name of a row to determine if it should
 /// match the rule.
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 #[serde(rename_all = "camelCase")]
 pub enum MatchTables {
     #[serde(rename = "*")]
@@ -233,7 +933,7 @@ pub enum MatchTables {
 
 pub type HostGroupId = String;
 
-#[derive(Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
 pub struct HostGroup {
     pub id: HostGroupId,
     /// `hosts` is a vector of connection strings for remote hosts.
@@ -400,6 +1100,332 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn partition_key_with_strftime_column() -> Result {
+        let template = PartitionTemplate::new(vec![TemplatePart::StrftimeColumn(
+            StrftimeColumn::new("started", "%Y-%m-%d"),
+        )]);
+
+        let line = parse_line("cpu started=1602338097000000000i 10");
+        assert_eq!(
+            "2020-10-10",
+            template.partition_key(&line, &Utc::now()).unwrap()
+        );
+
+        let line = parse_line("cpu started=1.5 10");
+        assert_eq!("", template.partition_key(&line, &Utc::now()).unwrap());
+
+        Ok(())
+    }
+
+    #[test]
+    fn regex_capture_unsupported() {
+        let rules = DatabaseRules {
+            partition_template: PartitionTemplate::new(vec![TemplatePart::RegexCapture(
+                RegexCapture {
+                    column: "host".to_string(),
+                    regex: "(.*)".to_string(),
+                },
+            )]),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            rules.validate(),
+            Err(Error::UnsupportedTemplatePart { .. })
+        ));
+
+        let line = parse_line("cpu,host=a usage=1 10");
+        assert!(rules.partition_key(&line, &Utc::now()).is_err());
+        assert!(DatabaseRules::default().validate().is_ok());
+    }
+
+    #[test]
+    fn invalid_time_formats_are_rejected() {
+        let template = |part| DatabaseRules {
+            partition_template: PartitionTemplate::new(vec![part]),
+            ..Default::default()
+        };
+
+        for format in &["%Y-%m-%d", "%Y%%%H", "no specifiers"] {
+            assert!(template(TemplatePart::TimeFormat(format.to_string()))
+                .validate()
+                .is_ok());
+        }
+
+        for format in &["%Y-%Q", "%", "%Y-%m-%"] {
+            let err = template(TemplatePart::TimeFormat(format.to_string()))
+                .validate()
+                .unwrap_err();
+            assert!(
+                matches!(err, Error::InvalidTimeFormat { .. }),
+                "{}: {}",
+                format,
+                err
+            );
+
+            let err = template(TemplatePart::StrftimeColumn(StrftimeColumn::new(
+                "other_time",
+                *format,
+            )))
+            .validate()
+            .unwrap_err();
+            assert!(matches!(err, Error::InvalidTimeFormat { .. }));
+        }
+    }
+
+    #[test]
+    fn downsampling_rule_nanos() {
+        let rule = DownsamplingRule {
+            after_seconds: 7 * 24 * 60 * 60,
+            interval_seconds: 60,
+            aggregate: DownsamplingAggregate::Mean,
+        };
+
+        assert_eq!(rule.after_nanos(), 604_800_000_000_000);
+        assert_eq!(rule.interval_nanos(), 60_000_000_000);
+    }
+
+    #[test]
+    fn rules_deserialize_with_defaults() -> Result {
+        let rules: DatabaseRules = serde_json::from_str(
+            r#"{"store_locally": true, "mutable_buffer_size": 1024, "retention_period_seconds": 60}"#,
+        )?;
+
+        assert_eq!(
+            rules,
+            DatabaseRules {
+                store_locally: true,
+                mutable_buffer_size: Some(1024),
+                retention_period_seconds: Some(60),
+                ..Default::default()
+            }
+        );
+        assert_eq!(rules.retention_period_nanos(), Some(60_000_000_000));
+
+        let json = serde_json::to_string(&DatabaseRules::default())?;
+        assert!(!json.contains("mutable_buffer_size"));
+        assert_eq!(
+            serde_json::from_str::<DatabaseRules>(&json)?,
+            DatabaseRules::default()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn timestamp_bounds_range() {
+        let now = 1_000_000_000_000_000_000;
+
+        let bounds = TimestampBounds::default();
+        assert_eq!(bounds.range(now), (i64::MIN, now + 86_400_000_000_000));
+
+        let bounds = TimestampBounds {
+            min_nanos: Some(0),
+            max_future_seconds: None,
+            policy: TimestampPolicy::Clamp,
+        };
+        assert_eq!(bounds.range(now), (0, i64::MAX));
+
+        let bounds = TimestampBounds {
+            max_future_seconds: Some(u64::MAX),
+            ..Default::default()
+        };
+        assert_eq!(bounds.range(now), (i64::MIN, i64::MAX));
+    }
+
+    #[test]
+    fn timestamp_bounds_deserialize_with_defaults() -> Result {
+        let rules: DatabaseRules =
+            serde_json::from_str(r#"{"timestamp_bounds": {"policy": "Clamp"}}"#)?;
+
+        assert_eq!(
+            rules.timestamp_bounds,
+            TimestampBounds {
+                policy: TimestampPolicy::Clamp,
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn name_rules_normalize_and_check_names() {
+        let lines = parsed_lines(
+            "CPU,Host=a usage=1 10\n\
+             cpu,host=a,HOST=b usage=1 10\n\
+             mem,host=a free-bytes=2 10\n\
+             disk_with_a_long_name,host=a used=3 10",
+        );
+
+        let rules = NameRules {
+            max_length: Some(10),
+            allowed_characters: Some("_".to_string()),
+            trim: false,
+            lowercase: true,
+        };
+        let (accepted, rejected) = rules.apply(&lines);
+
+        let accepted: Vec<_> = accepted.iter().map(ToString::to_string).collect();
+        assert_eq!(accepted, vec!["cpu,host=a usage=1 10"]);
+        assert_eq!(
+            rejected,
+            vec![
+                RejectedLine {
+                    line_number: 2,
+                    reason: r#"tag name "host" appears more than once"#.to_string(),
+                },
+                RejectedLine {
+                    line_number: 3,
+                    reason:
+                        r#"field name "free-bytes" has the character '-', which is not allowed"#
+                            .to_string(),
+                },
+                RejectedLine {
+                    line_number: 4,
+                    reason: r#"measurement name "disk_with_a_long_name" is longer than 10 bytes"#
+                        .to_string(),
+                },
+            ]
+        );
+
+        // lines are accepted as they are when there are no rules
+        let (accepted, rejected) = NameRules::default().apply(&lines);
+        assert!(matches!(accepted, Cow::Borrowed(_)));
+        assert_eq!(accepted.len(), 4);
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn name_rules_trim_names() {
+        let lines = parsed_lines(r#"cpu,\ host\ =a usage=1 10"#);
+
+        let (accepted, rejected) = NameRules::default().apply(&lines);
+        assert_eq!(accepted[0].to_string(), r#"cpu,\ host\ =a usage=1 10"#);
+        assert!(rejected.is_empty());
+
+        let rules = NameRules {
+            trim: true,
+            ..Default::default()
+        };
+        let (accepted, rejected) = rules.apply(&lines);
+        assert_eq!(accepted[0].to_string(), "cpu,host=a usage=1 10");
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn receive_time_defaults_timestamps() {
+        let lines = parsed_lines("cpu usage=1 10\ncpu usage=2\nmem free=3");
+        let received = 1_600_000_000_123_456_789;
+
+        let assigned = ReceiveTime::default().apply(&lines, received);
+        let timestamps: Vec<_> = assigned.iter().map(|line| line.timestamp).collect();
+        assert_eq!(timestamps, vec![Some(10), Some(received), Some(received)]);
+
+        let rules = ReceiveTime {
+            precision: TimePrecision::Milliseconds,
+            record: false,
+        };
+        let assigned = rules.apply(&lines, received);
+        assert_eq!(assigned[1].timestamp, Some(1_600_000_000_123_000_000));
+        assert_eq!(assigned[1].to_string(), "cpu usage=2 1600000000123000000");
+
+        // lines which all have timestamps are left as they are
+        let lines = parsed_lines("cpu usage=1 10");
+        assert!(matches!(rules.apply(&lines, received), Cow::Borrowed(_)));
+
+        assert_eq!(TimePrecision::Seconds.truncate(-1), -1_000_000_000);
+    }
+
+    #[test]
+    fn receive_time_records_the_receive_time() {
+        let lines = parsed_lines("cpu usage=1 10\ncpu usage=2");
+        let rules = ReceiveTime {
+            precision: TimePrecision::Seconds,
+            record: true,
+        };
+
+        let recorded = rules.apply(&lines, 2_000_000_123);
+        assert_eq!(
+            recorded[0].to_string(),
+            "cpu usage=1,_receive_time=2000000123i 10"
+        );
+        assert_eq!(
+            recorded[1].to_string(),
+            "cpu usage=2,_receive_time=2000000123i 2000000000"
+        );
+
+        // a write received again keeps the time it was first received at
+        let again = rules.apply(&recorded, 3_000_000_000);
+        assert!(matches!(again, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn tag_set_rules_canonicalize_tag_sets() {
+        let lines = parsed_lines(
+            "cpu,region=west,host=a usage=1 10\n\
+             cpu,host=a,region=west usage=2 10\n\
+             cpu,host=a,region=west,host=b usage=3 10\n\
+             cpu,Host=a,host=b usage=4 10",
+        );
+        let canonical = |rules: &DatabaseRules| {
+            let (accepted, rejected) = rules.apply_line_rules(&lines);
+            let accepted: Vec<_> = accepted.iter().map(ToString::to_string).collect();
+            let rejected: Vec<_> = rejected.into_iter().map(|r| r.line_number).collect();
+            (accepted, rejected)
+        };
+
+        // by default tags are sorted and lines with repeated tags rejected
+        let rules = DatabaseRules::default();
+        assert_eq!(
+            canonical(&rules),
+            (
+                vec![
+                    "cpu,host=a,region=west usage=1 10".to_string(),
+                    "cpu,host=a,region=west usage=2 10".to_string(),
+                    "cpu,Host=a,host=b usage=4 10".to_string(),
+                ],
+                vec![3]
+            )
+        );
+
+        let rules = DatabaseRules {
+            tag_set_rules: TagSetRules {
+                sort: false,
+                duplicates: DuplicateTagPolicy::KeepLast,
+            },
+            ..Default::default()
+        };
+        let (accepted, rejected) = canonical(&rules);
+        assert_eq!(accepted[0], "cpu,region=west,host=a usage=1 10");
+        assert_eq!(accepted[2], "cpu,region=west,host=b usage=3 10");
+        assert!(rejected.is_empty());
+
+        // tags which only repeat once their names are normalized are
+        // rejected by the name rules, whatever the policy
+        let rules = DatabaseRules {
+            tag_set_rules: TagSetRules {
+                sort: true,
+                duplicates: DuplicateTagPolicy::KeepFirst,
+            },
+            name_rules: NameRules {
+                lowercase: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let (accepted, rejected) = canonical(&rules);
+        assert_eq!(accepted[2], "cpu,host=a,region=west usage=3 10");
+        assert_eq!(rejected, vec![4]);
+
+        // lines already in canonical form are accepted as they are
+        let lines = parsed_lines("cpu,host=a,region=west usage=1 10\nmem free=2 10");
+        let (accepted, rejected) = DatabaseRules::default().apply_line_rules(&lines);
+        assert!(matches!(accepted, Cow::Borrowed(_)));
+        assert!(rejected.is_empty());
+    }
+
     fn parsed_lines(lp: &str) -> Vec<ParsedLine<'_>> {
         parse_lines(lp).map(|l| l.unwrap()).collect()
     }
@@ -0,0 +1,97 @@
+//! This module contains how histograms, such as those exported by
+//! Prometheus or OpenTelemetry, are stored in IOx.
+//!
+//! A histogram is stored as a measurement named after the metric, with
+//! a field per bucket and fields for the sum and count of the
+//! observations. The field of each bucket is named after its upper
+//! bound, such as `le_0.5` or `le_+Inf`, and holds the cumulative count
+//! of observations less than or equal to that bound, as the `le`
+//! buckets of Prometheus do. Its labels, other than `le`, are tags.
+
+/// The prefix of the names of the bucket columns of a histogram
+pub const BUCKET_COLUMN_PREFIX: &str = "le_";
+
+/// The column holding the sum of the observations of a histogram
+pub const SUM_COLUMN: &str = "sum";
+
+/// The column holding the number of observations of a histogram
+pub const COUNT_COLUMN: &str = "count";
+
+/// Returns the name of the column of the bucket with the upper bound
+/// `upper_bound`. Bounds are written in their shortest form, so the
+/// same bucket always has the same column, e.g. `le_1` whether the
+/// exporter wrote `1` or `1.0`.
+pub fn bucket_column(upper_bound: f64) -> String {
+    if upper_bound.is_infinite() && upper_bound > 0.0 {
+        format!("{}+Inf", BUCKET_COLUMN_PREFIX)
+    } else {
+        format!("{}{}", BUCKET_COLUMN_PREFIX, upper_bound)
+    }
+}
+
+/// Returns the upper bound of the bucket `column` counts, or `None`
+/// if it is not a bucket column
+pub fn bucket_bound(column: &str) -> Option<f64> {
+    column
+        .strip_prefix(BUCKET_COLUMN_PREFIX)
+        .and_then(parse_bound)
+}
+
+/// Parses the upper bound of a bucket as Prometheus writes it, such
+/// as `0.25` or `+Inf`. Bounds that are not a number are `None`.
+pub fn parse_bound(bound: &str) -> Option<f64> {
+    match bound {
+        "+Inf" | "Inf" | "inf" | "+inf" => Some(f64::INFINITY),
+        "-Inf" | "-inf" => Some(f64::NEG_INFINITY),
+        _ => bound.parse::<f64>().ok().filter(|bound| !bound.is_nan()),
+    }
+}
+
+/// Returns the bucket columns among `columns` with their upper
+/// bounds, in increasing order of bound
+pub fn bucket_columns<'a>(columns: impl IntoIterator<Item = &'a str>) -> Vec<(f64, &'a str)> {
+    let mut buckets: Vec<_> = columns
+        .into_iter()
+        .filter_map(|column| bucket_bound(column).map(|bound| (bound, column)))
+        .collect();
+    buckets.sort_by(|a, b| a.0.partial_cmp(&b.0).expect("bounds are never NaN"));
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bucket_column_names() {
+        assert_eq!(bucket_column(0.25), "le_0.25");
+        assert_eq!(bucket_column(1.0), "le_1");
+        assert_eq!(bucket_column(f64::INFINITY), "le_+Inf");
+
+        assert_eq!(bucket_bound("le_0.25"), Some(0.25));
+        assert_eq!(bucket_bound("le_+Inf"), Some(f64::INFINITY));
+        assert_eq!(bucket_bound("le_NaN"), None);
+        assert_eq!(bucket_bound("le_high"), None);
+        assert_eq!(bucket_bound("sum"), None);
+
+        for &bound in &["1.0", "1", "1e0"] {
+            assert_eq!(bucket_column(parse_bound(bound).unwrap()), "le_1");
+        }
+    }
+
+    #[test]
+    fn bucket_columns_are_sorted() {
+        let columns = vec![
+            "host", "le_+Inf", "le_10", "count", "le_0.5", "sum", "le_2.5",
+        ];
+        assert_eq!(
+            bucket_columns(columns),
+            vec![
+                (0.5, "le_0.5"),
+                (2.5, "le_2.5"),
+                (10.0, "le_10"),
+                (f64::INFINITY, "le_+Inf"),
+            ]
+        );
+    }
+}
@@ -11,8 +11,21 @@
 
 pub const TIME_COLUMN_NAME: &str = "time";
 
+/// The column the time each line was received at is recorded in, for
+/// databases whose rules ask for it, so the lag between when data was
+/// measured and when it was written can be queried
+pub const RECEIVE_TIME_COLUMN_NAME: &str = "_receive_time";
+
+/// The key of the schema metadata that lists the tag columns of a
+/// table, as a JSON array of column names. Tags and string fields
+/// are both `Utf8` columns in Arrow, so this is how clients tell them
+/// apart.
+pub const TAG_COLUMNS_METADATA_KEY: &str = "iox::tag_columns";
+
 pub mod data;
 pub mod database_rules;
 pub mod error;
+pub mod histogram;
 pub mod partition_metadata;
+pub mod pool;
 pub mod table_schema;
@@ -1,6 +1,7 @@
 //! This module contains structs that describe the metadata for a partition including schema,
 //! summary statistics, and file locations in storage.
 
+use std::collections::BTreeMap;
 use std::fmt::{Debug, Display};
 
 use serde::{Deserialize, Serialize};
@@ -15,6 +16,151 @@ pub struct Partition {
     pub tables: Vec<Table>,
 }
 
+/// Summarizes the data held in a partition, such as what a drop or
+/// truncate of the partition removes
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct PartitionSummary {
+    /// The partition key
+    pub key: String,
+    /// The names of the tables with data in the partition, sorted
+    pub tables: Vec<String>,
+    /// The total number of rows across all tables
+    pub row_count: u64,
+    /// The approximate number of bytes used to store the data
+    pub size: u64,
+}
+
+/// Where the data of a chunk is stored
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkStorage {
+    /// In the mutable buffer, which accepts writes
+    MutableBuffer,
+    /// In the read buffer, an immutable in-memory format for queries
+    ReadBuffer,
+    /// Only in object storage
+    ObjectStore,
+}
+
+/// Where a chunk is in its lifecycle
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChunkState {
+    /// The chunk accepts new writes
+    Open,
+    /// The chunk no longer accepts writes
+    Closed,
+    /// The chunk no longer accepts writes, and its data has been
+    /// written to parquet files
+    Persisted,
+}
+
+/// How the values of a column of a persisted chunk are encoded in its
+/// parquet file
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum ColumnEncoding {
+    /// Each value is stored in full
+    Plain,
+    /// The distinct values are stored once, and each value as its
+    /// index among them, run-length encoded
+    Dictionary,
+    /// Runs of the same value are stored as the value and its count
+    Rle,
+    /// The differences between consecutive integers, bit packed
+    DeltaBinaryPacked,
+    /// The lengths of strings, delta encoded, followed by their bytes
+    DeltaLengthByteArray,
+}
+
+/// Describes a chunk of data within a partition, where it is stored
+/// and how much memory it uses
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct ChunkSummary {
+    /// The key of the partition the chunk belongs to
+    pub partition_key: String,
+    /// The id of the chunk, unique within its partition
+    pub id: u32,
+    pub storage: ChunkStorage,
+    pub state: ChunkState,
+    /// The total number of rows across all tables
+    pub row_count: u64,
+    /// The approximate number of bytes used to store the data
+    pub size: u64,
+    /// The approximate number of bytes used to store each column,
+    /// summed across all tables, by column name
+    pub column_sizes: BTreeMap<String, u64>,
+    /// The smallest timestamp in the chunk, if it has any rows
+    pub min_time: Option<i64>,
+    /// The largest timestamp in the chunk, if it has any rows
+    pub max_time: Option<i64>,
+    /// The names of the tables with data in the chunk, sorted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub table_names: Vec<String>,
+    /// When data was last written to the chunk, in nanoseconds since
+    /// the epoch, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_write_time: Option<i64>,
+}
+
+/// Statistics of a partition, aggregated from the summaries of its
+/// chunks
+#[derive(Debug, Default, Clone, Deserialize, Serialize, PartialEq, Eq)]
+pub struct PartitionStatistics {
+    /// The partition key
+    pub key: String,
+    /// The number of chunks in the partition
+    pub chunk_count: u64,
+    /// The total number of rows across all chunks
+    pub row_count: u64,
+    /// The approximate number of bytes used to store the data
+    pub size: u64,
+    /// The approximate number of bytes stored in each storage tier
+    pub size_by_storage: BTreeMap<ChunkStorage, u64>,
+    /// The smallest timestamp in the partition, if it has any rows
+    pub min_time: Option<i64>,
+    /// The largest timestamp in the partition, if it has any rows
+    pub max_time: Option<i64>,
+    /// When data was last written to the partition, in nanoseconds
+    /// since the epoch, if known
+    pub last_write_time: Option<i64>,
+}
+
+impl PartitionStatistics {
+    /// Aggregates the summaries of chunks into the statistics of each
+    /// of their partitions, in order of partition key
+    pub fn from_chunks<'a>(chunks: impl IntoIterator<Item = &'a ChunkSummary>) -> Vec<Self> {
+        let mut partitions: BTreeMap<&str, Self> = BTreeMap::new();
+        for chunk in chunks {
+            let stats = partitions
+                .entry(&chunk.partition_key)
+                .or_insert_with(|| Self {
+                    key: chunk.partition_key.clone(),
+                    ..Default::default()
+                });
+
+            stats.chunk_count += 1;
+            stats.row_count += chunk.row_count;
+            stats.size += chunk.size;
+            *stats.size_by_storage.entry(chunk.storage).or_default() += chunk.size;
+            stats.min_time = lowest(stats.min_time, chunk.min_time);
+            stats.max_time = stats.max_time.max(chunk.max_time);
+            stats.last_write_time = stats.last_write_time.max(chunk.last_write_time);
+        }
+
+        partitions.into_iter().map(|(_, stats)| stats).collect()
+    }
+}
+
+/// The lowest of two optional values, ignoring those that are `None`
+fn lowest(a: Option<i64>, b: Option<i64>) -> Option<i64> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
 /// Metadata and statistics information for a table.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Table {
@@ -54,11 +200,19 @@ where
     }
 
     /// updates the statistics keeping the min, max and incrementing count.
+    ///
+    /// Values that are not ordered with themselves, i.e. NaN, are counted
+    /// but never become the min or max, unless the statistics started
+    /// from one; the first ordered value then replaces it.
     pub fn update(&mut self, other: T) {
         self.count += 1;
 
-        let set_min = self.min > other;
-        let set_max = self.max < other;
+        if !is_ordered(&other) {
+            return;
+        }
+
+        let set_min = !is_ordered(&self.min) || self.min > other;
+        let set_max = !is_ordered(&self.max) || self.max < other;
 
         match (set_min, set_max) {
             (true, true) => {
@@ -76,6 +230,10 @@ where
     }
 }
 
+fn is_ordered<T: PartialOrd>(value: &T) -> bool {
+    value.partial_cmp(value).is_some()
+}
+
 impl Statistics<String> {
     /// Function for string stats to avoid allocating if we're not updating min or max
     pub fn update_string(stats: &mut Self, other: &str) {
@@ -118,6 +276,20 @@ mod tests {
         assert_eq!(stat.count, 4);
     }
 
+    #[test]
+    fn statistics_update_ignores_nan() {
+        let mut stat = Statistics::new(std::f64::NAN);
+        assert_eq!(stat.count, 1);
+
+        stat.update(2.0);
+        assert_eq!((stat.min, stat.max), (2.0, 2.0));
+
+        stat.update(std::f64::NAN);
+        stat.update(std::f64::NEG_INFINITY);
+        assert_eq!((stat.min, stat.max), (std::f64::NEG_INFINITY, 2.0));
+        assert_eq!(stat.count, 4);
+    }
+
     #[test]
     fn update_string() {
         let mut stat = Statistics::new("bbb".to_string());
@@ -140,4 +312,63 @@ mod tests {
         assert_eq!(stat.max, "z".to_string());
         assert_eq!(stat.count, 4);
     }
+
+    #[test]
+    fn partition_statistics_from_chunks() {
+        let chunk = |partition_key: &str, storage, size, time: Option<i64>| ChunkSummary {
+            partition_key: partition_key.to_string(),
+            id: 0,
+            storage,
+            state: ChunkState::Closed,
+            row_count: 10,
+            size,
+            column_sizes: BTreeMap::new(),
+            min_time: time,
+            max_time: time.map(|time| time + 5),
+            table_names: vec![],
+            last_write_time: time.map(|time| time * 2),
+        };
+        let chunks = vec![
+            chunk("b", ChunkStorage::MutableBuffer, 100, Some(20)),
+            chunk("a", ChunkStorage::MutableBuffer, 100, None),
+            chunk("b", ChunkStorage::ReadBuffer, 50, Some(10)),
+            chunk("b", ChunkStorage::MutableBuffer, 25, None),
+        ];
+
+        let stats = PartitionStatistics::from_chunks(&chunks);
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            stats[0],
+            PartitionStatistics {
+                key: "a".to_string(),
+                chunk_count: 1,
+                row_count: 10,
+                size: 100,
+                size_by_storage: vec![(ChunkStorage::MutableBuffer, 100)]
+                    .into_iter()
+                    .collect(),
+                min_time: None,
+                max_time: None,
+                last_write_time: None,
+            }
+        );
+        assert_eq!(
+            stats[1],
+            PartitionStatistics {
+                key: "b".to_string(),
+                chunk_count: 3,
+                row_count: 30,
+                size: 175,
+                size_by_storage: vec![
+                    (ChunkStorage::MutableBuffer, 125),
+                    (ChunkStorage::ReadBuffer, 50)
+                ]
+                .into_iter()
+                .collect(),
+                min_time: Some(10),
+                max_time: Some(25),
+                last_write_time: Some(40),
+            }
+        );
+    }
 }
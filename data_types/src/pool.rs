@@ -0,0 +1,159 @@
+//! This module contains a pool of buffers that can be reused, so that
+//! code that repeatedly needs a scratch buffer (e.g. for the row ids
+//! that match a predicate, or the timestamps of a series) does not
+//! allocate a new one each time.
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+
+/// A buffer that can be reset to be reused, keeping its allocation
+pub trait Reusable: Default {
+    /// Empties the buffer, keeping any memory it has allocated
+    fn reset(&mut self);
+}
+
+impl<T> Reusable for Vec<T> {
+    fn reset(&mut self) {
+        self.clear()
+    }
+}
+
+/// A pool of buffers of type `T`. At most `max_buffers` buffers are
+/// kept in the pool while they are not in use; buffers returned when
+/// the pool is full are dropped.
+#[derive(Debug)]
+pub struct Pool<T> {
+    buffers: Mutex<Vec<T>>,
+    max_buffers: usize,
+}
+
+impl<T: Reusable> Pool<T> {
+    pub fn new(max_buffers: usize) -> Self {
+        Self {
+            buffers: Mutex::new(Vec::with_capacity(max_buffers)),
+            max_buffers,
+        }
+    }
+
+    /// Returns an empty buffer, reusing one from the pool if there is
+    /// one. The buffer goes back to the pool when the returned guard is
+    /// dropped.
+    pub fn get(&self) -> Pooled<'_, T> {
+        let buffer = self
+            .buffers
+            .lock()
+            .expect("mutex poisoned")
+            .pop()
+            .unwrap_or_default();
+
+        Pooled {
+            pool: self,
+            buffer: Some(buffer),
+        }
+    }
+
+    /// The number of buffers in the pool that are not in use
+    pub fn len(&self) -> usize {
+        self.buffers.lock().expect("mutex poisoned").len()
+    }
+
+    /// Returns true if none of the buffers in the pool are free
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `buffer` to the pool, e.g. one that was taken out of
+    /// its guard with `into_inner`
+    pub fn put(&self, mut buffer: T) {
+        let mut buffers = self.buffers.lock().expect("mutex poisoned");
+        if buffers.len() < self.max_buffers {
+            buffer.reset();
+            buffers.push(buffer);
+        }
+    }
+}
+
+/// A buffer taken from a `Pool`, which is returned to the pool when
+/// dropped unless it is taken out with `into_inner`
+#[derive(Debug)]
+pub struct Pooled<'a, T: Reusable> {
+    pool: &'a Pool<T>,
+    buffer: Option<T>,
+}
+
+impl<'a, T: Reusable> Pooled<'a, T> {
+    /// Takes the buffer, so it is not returned to the pool
+    pub fn into_inner(mut self) -> T {
+        self.buffer.take().expect("buffer is present until dropped")
+    }
+}
+
+impl<'a, T: Reusable> Deref for Pooled<'a, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.buffer
+            .as_ref()
+            .expect("buffer is present until dropped")
+    }
+}
+
+impl<'a, T: Reusable> DerefMut for Pooled<'a, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.buffer
+            .as_mut()
+            .expect("buffer is present until dropped")
+    }
+}
+
+impl<'a, T: Reusable> Drop for Pooled<'a, T> {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.put(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn buffers_are_reused() {
+        let pool: Pool<Vec<i64>> = Pool::new(1);
+
+        let capacity = {
+            let mut buffer = pool.get();
+            buffer.extend(0..100);
+            buffer.capacity()
+        };
+        assert_eq!(pool.len(), 1);
+
+        // the buffer is reset, but keeps its allocation
+        let buffer = pool.get();
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.capacity(), capacity);
+        assert!(pool.is_empty());
+
+        // buffers taken out of the pool are not returned to it
+        let buffer = buffer.into_inner();
+        assert_eq!(buffer.capacity(), capacity);
+        assert!(pool.is_empty());
+
+        // unless that is done explicitly
+        pool.put(buffer);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn pool_size_is_limited() {
+        let pool: Pool<Vec<f64>> = Pool::new(1);
+
+        let first = pool.get();
+        let second = pool.get();
+        drop(first);
+        drop(second);
+
+        assert_eq!(pool.len(), 1);
+    }
+}
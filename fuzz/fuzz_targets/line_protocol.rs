@@ -0,0 +1,12 @@
+//! Parses arbitrary input as line protocol, which must never panic
+//! however malformed the input is.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    for line in influxdb_line_protocol::parse_lines(data).flatten() {
+        // Parsed lines are also formatted, such as when logging them
+        let _ = line.to_string();
+    }
+});
@@ -0,0 +1,21 @@
+//! Converts arbitrary gRPC predicates into storage predicates, which
+//! must return an error rather than panic for predicates the server
+//! can't handle.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use generated_types::Predicate as RPCPredicate;
+use storage::predicate::PredicateBuilder;
+
+// The conversion lives in the server binary, which has no library
+// target to depend on, but only uses other workspace crates
+#[allow(dead_code)]
+#[path = "../../src/server/rpc/expr.rs"]
+mod expr;
+
+use expr::AddRPCNode;
+
+fuzz_target!(|predicate: RPCPredicate| {
+    let _ = PredicateBuilder::default().rpc_predicate(Some(predicate));
+});
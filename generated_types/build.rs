@@ -15,6 +15,7 @@ fn main() -> Result<()> {
     let root = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
 
     generate_grpc_types(&root)?;
+    generate_file_descriptor_set(&root)?;
     generate_wal_types(&root)?;
 
     Ok(())
@@ -22,23 +23,66 @@ fn main() -> Result<()> {
 
 /// Schema used with IOx specific gRPC requests
 ///
-/// Creates `influxdata.platform.storage.rs` and `com.github.influxdata.idpe.storage.read.rs`
+/// Creates `influxdata.platform.storage.rs`, `com.github.influxdata.idpe.storage.read.rs`,
+/// `arrow.flight.protocol.sql.rs`, `grpc.health.v1.rs` and `grpc.reflection.v1alpha.rs`
 fn generate_grpc_types(root: &Path) -> Result<()> {
-    let proto_files = vec![
+    let proto_files = grpc_proto_files(root);
+
+    // Tell cargo to recompile if any of these proto files are changed
+    for proto_file in &proto_files {
+        println!("cargo:rerun-if-changed={}", proto_file.display());
+    }
+
+    tonic_build::configure().compile(&proto_files, &[root.into()])?;
+
+    Ok(())
+}
+
+fn grpc_proto_files(root: &Path) -> Vec<PathBuf> {
+    vec![
+        root.join("flight_sql.proto"),
+        root.join("health.proto"),
         root.join("influxdb_iox.proto"),
         root.join("predicate.proto"),
+        root.join("reflection.proto"),
         root.join("storage_common.proto"),
         root.join("storage_common_idpe.proto"),
         root.join("service.proto"),
         root.join("source.proto"),
-    ];
+    ]
+}
 
-    // Tell cargo to recompile if any of these proto files are changed
-    for proto_file in &proto_files {
-        println!("cargo:rerun-if-changed={}", proto_file.display());
-    }
+/// The encoded `FileDescriptorSet` of the gRPC services, and of the Arrow
+/// Flight service whose types come from the `arrow-flight` crate, served by
+/// gRPC reflection
+///
+/// Creates `file_descriptor_set.bin`
+fn generate_file_descriptor_set(root: &Path) -> Result<()> {
+    let flight_file = root.join("flight.proto");
+    println!("cargo:rerun-if-changed={}", flight_file.display());
 
-    tonic_build::configure().compile(&proto_files, &[root.into()])?;
+    let out_dir: PathBuf = std::env::var_os("OUT_DIR")
+        .expect("Could not determine `OUT_DIR`")
+        .into();
+
+    let status = Command::new(prost_build::protoc())
+        .arg("--include_imports")
+        .arg("--include_source_info")
+        .arg(format!(
+            "--descriptor_set_out={}",
+            out_dir.join("file_descriptor_set.bin").display()
+        ))
+        .arg("-I")
+        .arg(root)
+        .arg("-I")
+        .arg(prost_build::protoc_include())
+        .args(grpc_proto_files(root))
+        .arg(flight_file)
+        .status()?;
+
+    if !status.success() {
+        panic!("`protoc` failed to write the file descriptor set");
+    }
 
     Ok(())
 }
@@ -0,0 +1,151 @@
+//! Implements `arbitrary::Arbitrary` for the predicate types, so
+//! fuzzers can build predicates from their input rather than decoding
+//! random protobuf bytes, which would almost never produce a valid
+//! `Node` tree.
+//!
+//! The generated trees are shaped like the predicates sent by clients:
+//! logical and comparison nodes with tag, field and literal leaves,
+//! including the special measurement and field tag keys and the
+//! value-less wrapper nodes InfluxQL sends. Some nodes are malformed
+//! on purpose, such as comparisons with the wrong number of children
+//! or unknown operators, as the server must reject those with an
+//! error.
+
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::{
+    node::{Comparison, Logical, Type, Value},
+    Node, Predicate,
+};
+
+/// The deepest `Node` tree generated
+const MAX_DEPTH: usize = 6;
+
+/// Tag keys which are likely to be used by other nodes in the same
+/// predicate, so tag comparisons are often on the same tag. `[0]` and
+/// `[255]` refer to the measurement and field names
+const TAG_KEYS: &[&[u8]] = &[b"\x00", b"\xff", b"host", b"region", b"state"];
+
+/// Field names which are likely to be used by other nodes in the same
+/// predicate
+const FIELD_NAMES: &[&str] = &["usage", "temp", "status"];
+
+impl Arbitrary for Node {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Result<Self> {
+        arbitrary_node(u, MAX_DEPTH)
+    }
+}
+
+impl Arbitrary for Predicate {
+    fn arbitrary(u: &mut Unstructured<'_>) -> Result<Self> {
+        Ok(Self {
+            root: u.arbitrary()?,
+        })
+    }
+}
+
+/// Builds a `Node` tree at most `depth` levels deep
+pub fn arbitrary_node(u: &mut Unstructured<'_>, depth: usize) -> Result<Node> {
+    if depth == 0 {
+        return arbitrary_leaf(u);
+    }
+
+    match u.int_in_range(0..=9)? {
+        0..=2 => arbitrary_leaf(u),
+        3..=5 => {
+            let logical = arbitrary_operator(u, &[Logical::And as i32, Logical::Or as i32])?;
+            arbitrary_expression(u, depth, Type::LogicalExpression, Value::Logical(logical))
+        }
+        6..=8 => {
+            let comparison = arbitrary_operator(
+                u,
+                &[
+                    Comparison::Equal as i32,
+                    Comparison::NotEqual as i32,
+                    Comparison::StartsWith as i32,
+                    Comparison::Regex as i32,
+                    Comparison::NotRegex as i32,
+                    Comparison::Lt as i32,
+                    Comparison::Lte as i32,
+                    Comparison::Gt as i32,
+                    Comparison::Gte as i32,
+                ],
+            )?;
+            arbitrary_expression(
+                u,
+                depth,
+                Type::ComparisonExpression,
+                Value::Comparison(comparison),
+            )
+        }
+        // A wrapper without a value, usually around a single child
+        _ => Ok(Node {
+            node_type: Type::ParenExpression as i32,
+            children: arbitrary_children(u, depth)?,
+            value: None,
+        }),
+    }
+}
+
+/// Builds a literal, tag reference or field reference
+fn arbitrary_leaf(u: &mut Unstructured<'_>) -> Result<Node> {
+    let (node_type, value) = match u.int_in_range(0..=8)? {
+        0 => (
+            Type::TagRef,
+            Value::TagRefValue(u.choose(TAG_KEYS)?.to_vec()),
+        ),
+        1 => (Type::TagRef, Value::TagRefValue(u.arbitrary()?)),
+        2 => (
+            Type::FieldRef,
+            Value::FieldRefValue(u.choose(FIELD_NAMES)?.to_string()),
+        ),
+        3 => (Type::Literal, Value::StringValue(u.arbitrary()?)),
+        4 => (Type::Literal, Value::BoolValue(u.arbitrary()?)),
+        5 => (Type::Literal, Value::IntValue(u.arbitrary()?)),
+        6 => (Type::Literal, Value::UintValue(u.arbitrary()?)),
+        7 => (Type::Literal, Value::FloatValue(u.arbitrary()?)),
+        _ => (Type::Literal, Value::RegexValue(u.arbitrary()?)),
+    };
+
+    Ok(Node {
+        node_type: node_type as i32,
+        children: vec![],
+        value: Some(value),
+    })
+}
+
+/// Builds an expression node with `value` as its operator
+fn arbitrary_expression(
+    u: &mut Unstructured<'_>,
+    depth: usize,
+    node_type: Type,
+    value: Value,
+) -> Result<Node> {
+    Ok(Node {
+        node_type: node_type as i32,
+        children: arbitrary_children(u, depth)?,
+        value: Some(value),
+    })
+}
+
+/// Builds the children of a node at `depth`, usually as many as the
+/// operators take
+fn arbitrary_children(u: &mut Unstructured<'_>, depth: usize) -> Result<Vec<Node>> {
+    let len = match u.int_in_range(0..=9)? {
+        0 => 0,
+        1 => 1,
+        2 => 3,
+        _ => 2,
+    };
+
+    (0..len).map(|_| arbitrary_node(u, depth - 1)).collect()
+}
+
+/// Chooses one of the known `operators`, or occasionally any value
+fn arbitrary_operator(u: &mut Unstructured<'_>, operators: &[i32]) -> Result<i32> {
+    if u.int_in_range(0..=19)? == 0 {
+        u.arbitrary()
+    } else {
+        Ok(*u.choose(operators)?)
+    }
+}
@@ -14,6 +14,34 @@ include!(concat!(
 ));
 include!(concat!(env!("OUT_DIR"), "/wal_generated.rs"));
 
+/// The messages of the Arrow Flight SQL protocol
+pub mod flight_sql {
+    include!(concat!(env!("OUT_DIR"), "/arrow.flight.protocol.sql.rs"));
+}
+
+/// The standard gRPC health checking and reflection services
+pub mod grpc {
+    pub mod health {
+        pub mod v1 {
+            include!(concat!(env!("OUT_DIR"), "/grpc.health.v1.rs"));
+        }
+    }
+
+    pub mod reflection {
+        pub mod v1alpha {
+            include!(concat!(env!("OUT_DIR"), "/grpc.reflection.v1alpha.rs"));
+        }
+    }
+}
+
+/// The encoded `FileDescriptorSet` of every gRPC service the server
+/// exposes, with the files they import, for gRPC reflection
+pub const FILE_DESCRIPTOR_SET: &[u8] =
+    include_bytes!(concat!(env!("OUT_DIR"), "/file_descriptor_set.bin"));
+
+#[cfg(feature = "arbitrary")]
+pub mod fuzzing;
+
 // Can't implement `Default` because `prost::Message` implements `Default`
 impl TimestampRange {
     pub fn max() -> Self {
@@ -140,7 +140,7 @@ impl nom::error::ParseError<&str> for Error {
 ///
 /// assert_eq!(timestamp, Some(1590488773254420000));
 /// ```
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ParsedLine<'a> {
     pub series: Series<'a>,
     pub field_set: FieldSet<'a>,
@@ -230,7 +230,7 @@ impl<'a> Display for ParsedLine<'a> {
 
 /// Represents the identifier of a series (measurement, tagset) for
 /// line protocol data
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Series<'a> {
     raw_input: &'a str,
     pub measurement: EscapedStr<'a>,
@@ -659,12 +659,23 @@ fn field_integer_value(i: &str) -> IResult<&str, i64> {
 }
 
 fn field_float_value(i: &str) -> IResult<&str, f64> {
-    let value = alt((field_float_value_with_decimal, field_float_value_no_decimal));
+    let value = alt((
+        field_float_value_non_finite,
+        field_float_value_with_decimal,
+        field_float_value_no_decimal,
+    ));
     map_fail(value, |value| {
         value.parse().context(FloatValueInvalid { value })
     })(i)
 }
 
+/// NaN and the infinities, as `f64` displays them, so that float values
+/// can be written back out as line protocol and parsed again. Whether
+/// they are stored is up to the database they are written to.
+fn field_float_value_non_finite(i: &str) -> IResult<&str, &str> {
+    alt((tag("NaN"), tag("inf"), tag("+inf"), tag("-inf")))(i)
+}
+
 fn field_float_value_with_decimal(i: &str) -> IResult<&str, &str> {
     recognize(separated_pair(integral_value_common, tag("."), digit1))(i)
 }
@@ -983,16 +994,16 @@ fn map_fail<'a, R1, R2>(
 // copy / pasted from influxdb2_client to avoid a dependency on that crate
 
 /// Characters to escape when writing measurement names
-const MEASUREMENT_DELIMITERS: &[char] = &[',', ' '];
+pub const MEASUREMENT_DELIMITERS: &[char] = &[',', ' '];
 
 /// Characters to escape when writing tag keys
-const TAG_KEY_DELIMITERS: &[char] = &[',', '=', ' '];
+pub const TAG_KEY_DELIMITERS: &[char] = &[',', '=', ' '];
 
 /// Characters to escape when writing tag values
-const TAG_VALUE_DELIMITERS: &[char] = TAG_KEY_DELIMITERS;
+pub const TAG_VALUE_DELIMITERS: &[char] = TAG_KEY_DELIMITERS;
 
 /// Characters to escape when writing field keys
-const FIELD_KEY_DELIMITERS: &[char] = TAG_KEY_DELIMITERS;
+pub const FIELD_KEY_DELIMITERS: &[char] = TAG_KEY_DELIMITERS;
 
 /// Characters to escape when writing string values in fields
 const FIELD_VALUE_STRING_DELIMITERS: &[char] = &['"'];
@@ -1001,8 +1012,8 @@ const FIELD_VALUE_STRING_DELIMITERS: &[char] = &['"'];
 /// escaping_escaping specificiation.
 ///
 /// Use the constants defined in this module
-fn escape_and_write_value(
-    f: &mut fmt::Formatter<'_>,
+fn escape_and_write_value<W: fmt::Write + ?Sized>(
+    f: &mut W,
     value: &str,
     escaping_specification: &[char],
 ) -> fmt::Result {
@@ -1017,6 +1028,38 @@ fn escape_and_write_value(
     f.write_str(&value[last..])
 }
 
+/// Appends `value` to `out`, escaping all characters in
+/// `escaping_specification` with a backslash, as line protocol is
+/// written.
+///
+/// Use the constants defined in this module
+pub fn write_escaped(out: &mut String, value: &str, escaping_specification: &[char]) {
+    escape_and_write_value(out, value, escaping_specification)
+        .expect("writing to a String can not fail")
+}
+
+/// The canonical key of a series: its measurement followed by its
+/// tags, sorted by key, as they would be written in line protocol.
+/// Series have the same key regardless of the order their tags were
+/// written in.
+pub fn series_key<'a>(
+    measurement: &str,
+    tags: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> String {
+    let mut tags: Vec<_> = tags.into_iter().collect();
+    tags.sort_unstable();
+
+    let mut key = String::new();
+    write_escaped(&mut key, measurement, MEASUREMENT_DELIMITERS);
+    for (tag_key, tag_value) in tags {
+        key.push(',');
+        write_escaped(&mut key, tag_key, TAG_KEY_DELIMITERS);
+        key.push('=');
+        write_escaped(&mut key, tag_value, TAG_VALUE_DELIMITERS);
+    }
+    key
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1071,6 +1114,26 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn write_escaped_values() {
+        let mut out = String::new();
+        write_escaped(&mut out, "a b,c=d", TAG_KEY_DELIMITERS);
+        assert_eq!(out, r#"a\ b\,c\=d"#);
+
+        let mut out = String::new();
+        write_escaped(&mut out, "a b,c=d", MEASUREMENT_DELIMITERS);
+        assert_eq!(out, r#"a\ b\,c=d"#);
+    }
+
+    #[test]
+    fn series_key_sorts_and_escapes_tags() {
+        assert_eq!(
+            series_key("cpu load", vec![("region", "us west"), ("host", "a,b")]),
+            r#"cpu\ load,host=a\,b,region=us\ west"#
+        );
+        assert_eq!(series_key("cpu", vec![]), "cpu");
+    }
+
     #[test]
     fn escaped_str_multi() -> Result {
         // Get an EscapedStr that has multiple parts by parsing a
@@ -1411,6 +1474,36 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn parse_non_finite_float() -> Result {
+        let input = "m0 a=NaN,b=inf,c=+inf,d=-inf 99";
+        let vals = parse(input)?;
+
+        let values: Vec<_> = vals[0]
+            .field_set
+            .iter()
+            .map(|(_, value)| value.unwrap_f64())
+            .collect();
+        assert!(values[0].is_nan());
+        assert_eq!(
+            values[1..],
+            [
+                std::f64::INFINITY,
+                std::f64::INFINITY,
+                std::f64::NEG_INFINITY
+            ]
+        );
+
+        // they are written back out as they are parsed
+        assert_eq!(vals[0].to_string(), "m0 a=NaN,b=inf,c=inf,d=-inf 99");
+
+        // other spellings are not floats
+        assert!(parse("m0 a=nan 99").is_err());
+        assert!(parse("m0 a=Infinity 99").is_err());
+
+        Ok(())
+    }
+
     #[test]
     fn parse_out_of_range_integer() -> Result {
         let input = "m0 field=99999999999999999999999999999999i 99";
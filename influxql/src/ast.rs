@@ -0,0 +1,241 @@
+//! The abstract syntax tree produced by parsing an InfluxQL `SELECT`
+//! statement.
+
+use std::collections::HashMap;
+
+use crate::{Result, UnboundParameter};
+
+/// A parsed InfluxQL `SELECT` statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelectStatement {
+    /// The expressions being selected
+    pub fields: Vec<Field>,
+
+    /// The measurements (`FROM` clause) being selected from
+    pub from: Vec<Measurement>,
+
+    /// The `WHERE` clause, if any
+    pub condition: Option<Expr>,
+
+    /// The `GROUP BY` clause, if any
+    pub dimensions: Vec<Dimension>,
+
+    /// How to fill empty `GROUP BY time()` windows
+    pub fill: Fill,
+
+    /// True if the results should be ordered by descending time
+    pub order_desc: bool,
+
+    /// The maximum number of points to return per series (`LIMIT`)
+    pub limit: Option<u64>,
+
+    /// The number of points to skip per series (`OFFSET`)
+    pub offset: Option<u64>,
+
+    /// The maximum number of series to return (`SLIMIT`)
+    pub slimit: Option<u64>,
+
+    /// The number of series to skip (`SOFFSET`)
+    pub soffset: Option<u64>,
+}
+
+impl SelectStatement {
+    /// Returns the `GROUP BY time()` dimension, if any
+    pub fn time_dimension(&self) -> Option<&TimeDimension> {
+        self.dimensions.iter().find_map(|d| match d {
+            Dimension::Time(time) => Some(time),
+            _ => None,
+        })
+    }
+
+    /// Returns the names of the tags listed in the `GROUP BY` clause
+    pub fn tag_dimensions(&self) -> impl Iterator<Item = &str> {
+        self.dimensions.iter().filter_map(|d| match d {
+            Dimension::Tag(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Replaces each `$name` placeholder in this statement with the
+    /// value of `name` in `params`, which should be a literal
+    pub fn bind(&mut self, params: &HashMap<String, Expr>) -> Result<()> {
+        for field in &mut self.fields {
+            field.expr.bind(params)?;
+        }
+        if let Some(condition) = &mut self.condition {
+            condition.bind(params)?;
+        }
+        Ok(())
+    }
+}
+
+/// A single selected expression, with an optional alias
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub expr: Expr,
+    pub alias: Option<String>,
+}
+
+/// A source of data in the `FROM` clause
+#[derive(Debug, Clone, PartialEq)]
+pub enum Measurement {
+    /// A measurement referred to by name. Any database and retention
+    /// policy qualifiers (`db.rp.name`) are discarded
+    Name(String),
+
+    /// All measurements matching a regular expression
+    Regex(String),
+}
+
+/// A dimension in the `GROUP BY` clause
+#[derive(Debug, Clone, PartialEq)]
+pub enum Dimension {
+    /// `GROUP BY time(interval[, offset])`
+    Time(TimeDimension),
+
+    /// `GROUP BY <tag>`
+    Tag(String),
+
+    /// `GROUP BY *`: group by all tags
+    Wildcard,
+
+    /// `GROUP BY /regex/`: group by all tags matching the regex
+    Regex(String),
+}
+
+/// The parameters of `GROUP BY time()`, in nanoseconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeDimension {
+    pub interval: i64,
+    pub offset: i64,
+}
+
+/// The fill policy for windows with no data, specified with `fill()`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fill {
+    /// Report the window with a null value (the default)
+    Null,
+    /// Omit the window from the results
+    None,
+    /// Report the value from the previous window
+    Previous,
+    /// Linearly interpolate between the surrounding windows
+    Linear,
+    /// Report a fixed value
+    Value(f64),
+}
+
+impl Default for Fill {
+    fn default() -> Self {
+        Self::Null
+    }
+}
+
+/// An InfluxQL expression
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    /// `*`
+    Wildcard,
+    /// A field, tag or `time` reference
+    Identifier(String),
+    /// A single quoted string
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    /// A duration such as `10m`, in nanoseconds
+    Duration(i64),
+    /// A regular expression such as `/^a.*/`
+    Regex(String),
+    /// A `$name` placeholder for a bound parameter
+    BoundParameter(String),
+    /// A function call. The function name is always lower case
+    Call {
+        name: String,
+        args: Vec<Self>,
+    },
+    Binary {
+        lhs: Box<Self>,
+        op: BinaryOp,
+        rhs: Box<Self>,
+    },
+}
+
+impl Expr {
+    /// Creates a binary expression
+    pub fn binary(lhs: Self, op: BinaryOp, rhs: Self) -> Self {
+        Self::Binary {
+            lhs: Box::new(lhs),
+            op,
+            rhs: Box::new(rhs),
+        }
+    }
+
+    fn bind(&mut self, params: &HashMap<String, Self>) -> Result<()> {
+        match self {
+            Self::BoundParameter(name) => {
+                let value = match params.get(name) {
+                    Some(value) => value.clone(),
+                    None => {
+                        return UnboundParameter {
+                            name: name.as_str(),
+                        }
+                        .fail()
+                    }
+                };
+                *self = value;
+            }
+            Self::Call { args, .. } => {
+                for arg in args {
+                    arg.bind(params)?;
+                }
+            }
+            Self::Binary { lhs, rhs, .. } => {
+                lhs.bind(params)?;
+                rhs.bind(params)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Binary operators, from lowest to highest precedence
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    RegexMatch,
+    RegexNotMatch,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+}
+
+impl BinaryOp {
+    /// The binding power of this operator; higher binds more tightly
+    pub fn precedence(self) -> u8 {
+        match self {
+            Self::Or => 1,
+            Self::And => 2,
+            Self::Eq
+            | Self::NotEq
+            | Self::Lt
+            | Self::LtEq
+            | Self::Gt
+            | Self::GtEq
+            | Self::RegexMatch
+            | Self::RegexNotMatch => 3,
+            Self::Add | Self::Sub => 4,
+            Self::Mul | Self::Div | Self::Mod => 5,
+        }
+    }
+}
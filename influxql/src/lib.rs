@@ -0,0 +1,78 @@
+//! This crate contains a parser for InfluxQL `SELECT` statements and
+//! the logic to translate them into SQL that can be planned and run
+//! by DataFusion against an InfluxDB IOx database.
+//!
+//! The goal is to allow dashboards and tools written for InfluxDB 1.x
+//! to query InfluxDB IOx directly. See
+//! https://docs.influxdata.com/influxdb/v1.8/query_language/ for the
+//! language definition.
+//!
+//! ```
+//! let statement = influxql::parse_select(
+//!     "SELECT mean(usage) FROM cpu WHERE host = 'a' GROUP BY time(1m)",
+//! )
+//! .unwrap();
+//!
+//! let sql = influxql::to_sql(&statement, 0).unwrap();
+//! assert_eq!(
+//!     sql,
+//!     r#"SELECT ("time" - ("time" % 60000000000)) AS "time", AVG(usage) AS mean FROM cpu WHERE (host = 'a') GROUP BY ("time" - ("time" % 60000000000)) ORDER BY "time""#
+//! );
+//! ```
+
+#![deny(rust_2018_idioms)]
+#![warn(
+    missing_copy_implementations,
+    missing_debug_implementations,
+    clippy::explicit_iter_loop,
+    clippy::use_self
+)]
+
+use snafu::Snafu;
+
+pub mod ast;
+mod parser;
+mod sql;
+mod token;
+
+pub use parser::{parse_query, parse_select};
+pub use sql::{to_sql, translate, FillWindows, SeriesOptions, Translation};
+
+#[derive(Debug, Snafu, Clone, PartialEq)]
+pub enum Error {
+    #[snafu(display("Unexpected character '{}' at position {}", c, pos))]
+    UnexpectedCharacter { c: char, pos: usize },
+
+    #[snafu(display("Unterminated {} starting at position {}", what, pos))]
+    Unterminated { what: &'static str, pos: usize },
+
+    #[snafu(display("Invalid number '{}' at position {}", text, pos))]
+    InvalidNumber { text: String, pos: usize },
+
+    #[snafu(display("Invalid duration '{}' at position {}", text, pos))]
+    InvalidDuration { text: String, pos: usize },
+
+    #[snafu(display("Expected {} at position {}, found {}", expected, pos, found))]
+    Expected {
+        expected: String,
+        found: String,
+        pos: usize,
+    },
+
+    #[snafu(display("Invalid time literal '{}': {}", value, source))]
+    InvalidTimeLiteral {
+        value: String,
+        source: chrono::ParseError,
+    },
+
+    #[snafu(display("Unsupported InfluxQL: {}", description))]
+    Unsupported { description: String },
+
+    #[snafu(display("Invalid InfluxQL query: {}", description))]
+    InvalidQuery { description: String },
+
+    #[snafu(display("No value provided for bound parameter ${}", name))]
+    UnboundParameter { name: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
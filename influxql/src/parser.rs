@@ -0,0 +1,662 @@
+//! A recursive descent parser for InfluxQL `SELECT` statements
+
+use crate::{
+    ast::{BinaryOp, Dimension, Expr, Field, Fill, Measurement, SelectStatement, TimeDimension},
+    token::{tokenize, Token},
+    Expected, Result,
+};
+
+/// Parses a single InfluxQL `SELECT` statement, with an optional
+/// trailing semicolon
+pub fn parse_select(input: &str) -> Result<SelectStatement> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+
+    let statement = parser.parse_select()?;
+    parser.consume(&Token::Semicolon);
+    parser.expect(&Token::Eof)?;
+
+    Ok(statement)
+}
+
+/// Parses a query made up of one or more InfluxQL `SELECT`
+/// statements separated by semicolons
+pub fn parse_query(input: &str) -> Result<Vec<SelectStatement>> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+
+    let mut statements = vec![parser.parse_select()?];
+    while parser.consume(&Token::Semicolon) && parser.peek() != &Token::Eof {
+        statements.push(parser.parse_select()?);
+    }
+    parser.expect(&Token::Eof)?;
+
+    Ok(statements)
+}
+
+#[derive(Debug)]
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    fn peek_nth(&self, n: usize) -> &Token {
+        let index = (self.pos + n).min(self.tokens.len() - 1);
+        &self.tokens[index].1
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].1.clone();
+        // never advance past Eof
+        if self.pos < self.tokens.len() - 1 {
+            self.pos += 1;
+        }
+        token
+    }
+
+    /// Returns an error describing what was expected at the current
+    /// position
+    fn expected<T>(&self, expected: impl Into<String>) -> Result<T> {
+        let (pos, found) = &self.tokens[self.pos];
+        Expected {
+            expected,
+            found: found.to_string(),
+            pos: *pos,
+        }
+        .fail()
+    }
+
+    /// Consumes the next token if it is `token`, returning true if it was
+    fn consume(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<()> {
+        if self.consume(token) {
+            Ok(())
+        } else {
+            self.expected(token.to_string())
+        }
+    }
+
+    /// Consumes the next token if it is `keyword`, returning true if it was
+    fn consume_keyword(&mut self, keyword: &str) -> bool {
+        if self.peek().is_keyword(keyword) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_keyword(&mut self, keyword: &str) -> Result<()> {
+        if self.consume_keyword(keyword) {
+            Ok(())
+        } else {
+            self.expected(keyword.to_uppercase())
+        }
+    }
+
+    fn parse_identifier(&mut self) -> Result<String> {
+        match self.peek() {
+            Token::Ident(_) | Token::QuotedIdent(_) => match self.next() {
+                Token::Ident(name) | Token::QuotedIdent(name) => Ok(name),
+                _ => unreachable!(),
+            },
+            _ => self.expected("identifier"),
+        }
+    }
+
+    fn parse_unsigned(&mut self) -> Result<u64> {
+        match *self.peek() {
+            Token::Integer(value) if value >= 0 => {
+                self.next();
+                Ok(value as u64)
+            }
+            _ => self.expected("non-negative integer"),
+        }
+    }
+
+    fn parse_duration(&mut self) -> Result<i64> {
+        match *self.peek() {
+            Token::Duration(value) => {
+                self.next();
+                Ok(value)
+            }
+            _ => self.expected("duration"),
+        }
+    }
+
+    fn parse_select(&mut self) -> Result<SelectStatement> {
+        self.expect_keyword("select")?;
+
+        let mut fields = vec![self.parse_field()?];
+        while self.consume(&Token::Comma) {
+            fields.push(self.parse_field()?);
+        }
+
+        self.expect_keyword("from")?;
+        let mut from = vec![self.parse_measurement()?];
+        while self.consume(&Token::Comma) {
+            from.push(self.parse_measurement()?);
+        }
+
+        let condition = if self.consume_keyword("where") {
+            Some(self.parse_expr()?)
+        } else {
+            None
+        };
+
+        let mut dimensions = vec![];
+        if self.consume_keyword("group") {
+            self.expect_keyword("by")?;
+            dimensions.push(self.parse_dimension()?);
+            while self.consume(&Token::Comma) {
+                dimensions.push(self.parse_dimension()?);
+            }
+        }
+
+        let fill = if self.consume_keyword("fill") {
+            self.parse_fill()?
+        } else {
+            Fill::default()
+        };
+
+        let mut order_desc = false;
+        if self.consume_keyword("order") {
+            self.expect_keyword("by")?;
+            self.expect_keyword("time")?;
+            if self.consume_keyword("desc") {
+                order_desc = true;
+            } else {
+                self.consume_keyword("asc");
+            }
+        }
+
+        let limit = self.parse_optional_limit("limit")?;
+        let offset = self.parse_optional_limit("offset")?;
+        let slimit = self.parse_optional_limit("slimit")?;
+        let soffset = self.parse_optional_limit("soffset")?;
+
+        Ok(SelectStatement {
+            fields,
+            from,
+            condition,
+            dimensions,
+            fill,
+            order_desc,
+            limit,
+            offset,
+            slimit,
+            soffset,
+        })
+    }
+
+    fn parse_optional_limit(&mut self, keyword: &str) -> Result<Option<u64>> {
+        if self.consume_keyword(keyword) {
+            Ok(Some(self.parse_unsigned()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_field(&mut self) -> Result<Field> {
+        let expr = self.parse_expr()?;
+        let alias = if self.consume_keyword("as") {
+            Some(self.parse_identifier()?)
+        } else {
+            None
+        };
+        Ok(Field { expr, alias })
+    }
+
+    /// Parses a measurement, which may be qualified with a database
+    /// and retention policy (`db.rp.name` or `db..name`)
+    fn parse_measurement(&mut self) -> Result<Measurement> {
+        if let Token::Regex(_) = self.peek() {
+            if let Token::Regex(regex) = self.next() {
+                return Ok(Measurement::Regex(regex));
+            }
+        }
+
+        let mut name = self.parse_identifier()?;
+        while self.consume(&Token::Dot) {
+            // an empty retention policy, as in db..name
+            if self.peek() == &Token::Dot {
+                continue;
+            }
+            name = self.parse_identifier()?;
+        }
+        Ok(Measurement::Name(name))
+    }
+
+    fn parse_dimension(&mut self) -> Result<Dimension> {
+        match self.peek() {
+            Token::Star => {
+                self.next();
+                Ok(Dimension::Wildcard)
+            }
+            Token::Regex(_) => match self.next() {
+                Token::Regex(regex) => Ok(Dimension::Regex(regex)),
+                _ => unreachable!(),
+            },
+            token if token.is_keyword("time") && self.peek_nth(1) == &Token::LParen => {
+                self.next();
+                self.next();
+                let interval = self.parse_duration()?;
+                let offset = if self.consume(&Token::Comma) {
+                    let negative = self.consume(&Token::Minus);
+                    let offset = self.parse_duration()?;
+                    if negative {
+                        -offset
+                    } else {
+                        offset
+                    }
+                } else {
+                    0
+                };
+                self.expect(&Token::RParen)?;
+                Ok(Dimension::Time(TimeDimension { interval, offset }))
+            }
+            _ => Ok(Dimension::Tag(self.parse_identifier()?)),
+        }
+    }
+
+    /// Parses the `(...)` following `fill`
+    fn parse_fill(&mut self) -> Result<Fill> {
+        self.expect(&Token::LParen)?;
+
+        let negative = self.consume(&Token::Minus);
+        let start = self.pos;
+        let fill = match self.next() {
+            Token::Ident(ident) if !negative && ident.eq_ignore_ascii_case("null") => Fill::Null,
+            Token::Ident(ident) if !negative && ident.eq_ignore_ascii_case("none") => Fill::None,
+            Token::Ident(ident) if !negative && ident.eq_ignore_ascii_case("previous") => {
+                Fill::Previous
+            }
+            Token::Ident(ident) if !negative && ident.eq_ignore_ascii_case("linear") => {
+                Fill::Linear
+            }
+            Token::Integer(value) => Fill::Value(value as f64),
+            Token::Float(value) => Fill::Value(value),
+            _ => {
+                self.pos = start;
+                return self.expected("null, none, previous, linear or a number");
+            }
+        };
+        let fill = match fill {
+            Fill::Value(value) if negative => Fill::Value(-value),
+            fill => fill,
+        };
+
+        self.expect(&Token::RParen)?;
+        Ok(fill)
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr> {
+        self.parse_binary(0)
+    }
+
+    /// Parses binary expressions using precedence climbing: only
+    /// operators that bind more tightly than `min_precedence` are
+    /// consumed
+    fn parse_binary(&mut self, min_precedence: u8) -> Result<Expr> {
+        let mut lhs = self.parse_unary()?;
+
+        while let Some(op) = self.peek_binary_op() {
+            let precedence = op.precedence();
+            if precedence <= min_precedence {
+                break;
+            }
+            self.next();
+            let rhs = self.parse_binary(precedence)?;
+            lhs = Expr::binary(lhs, op, rhs);
+        }
+
+        Ok(lhs)
+    }
+
+    fn peek_binary_op(&self) -> Option<BinaryOp> {
+        let op = match self.peek() {
+            Token::Plus => BinaryOp::Add,
+            Token::Minus => BinaryOp::Sub,
+            Token::Star => BinaryOp::Mul,
+            Token::Slash => BinaryOp::Div,
+            Token::Percent => BinaryOp::Mod,
+            Token::Eq => BinaryOp::Eq,
+            Token::NotEq => BinaryOp::NotEq,
+            Token::Lt => BinaryOp::Lt,
+            Token::LtEq => BinaryOp::LtEq,
+            Token::Gt => BinaryOp::Gt,
+            Token::GtEq => BinaryOp::GtEq,
+            Token::EqRegex => BinaryOp::RegexMatch,
+            Token::NotEqRegex => BinaryOp::RegexNotMatch,
+            token if token.is_keyword("and") => BinaryOp::And,
+            token if token.is_keyword("or") => BinaryOp::Or,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.consume(&Token::Minus) {
+            return match self.parse_unary()? {
+                Expr::Integer(v) => Ok(Expr::Integer(-v)),
+                Expr::Float(v) => Ok(Expr::Float(-v)),
+                Expr::Duration(v) => Ok(Expr::Duration(-v)),
+                expr => Ok(Expr::binary(Expr::Integer(0), BinaryOp::Sub, expr)),
+            };
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let expr = match self.peek().clone() {
+            Token::LParen => {
+                self.next();
+                let expr = self.parse_expr()?;
+                self.expect(&Token::RParen)?;
+                expr
+            }
+            Token::Star => {
+                self.next();
+                Expr::Wildcard
+            }
+            Token::Ident(ident) if self.peek_nth(1) == &Token::LParen => {
+                self.next();
+                self.next();
+                let mut args = vec![];
+                if !self.consume(&Token::RParen) {
+                    args.push(self.parse_expr()?);
+                    while self.consume(&Token::Comma) {
+                        args.push(self.parse_expr()?);
+                    }
+                    self.expect(&Token::RParen)?;
+                }
+                Expr::Call {
+                    name: ident.to_lowercase(),
+                    args,
+                }
+            }
+            Token::Ident(ident) if ident.eq_ignore_ascii_case("true") => {
+                self.next();
+                Expr::Boolean(true)
+            }
+            Token::Ident(ident) if ident.eq_ignore_ascii_case("false") => {
+                self.next();
+                Expr::Boolean(false)
+            }
+            Token::Ident(_) | Token::QuotedIdent(_) => Expr::Identifier(self.parse_identifier()?),
+            Token::String(value) => {
+                self.next();
+                Expr::String(value)
+            }
+            Token::Integer(value) => {
+                self.next();
+                Expr::Integer(value)
+            }
+            Token::Float(value) => {
+                self.next();
+                Expr::Float(value)
+            }
+            Token::Duration(value) => {
+                self.next();
+                Expr::Duration(value)
+            }
+            Token::Regex(value) => {
+                self.next();
+                Expr::Regex(value)
+            }
+            Token::BoundParameter(name) => {
+                self.next();
+                Expr::BoundParameter(name)
+            }
+            _ => return self.expected("expression"),
+        };
+
+        // Ignore type casts such as `usage::float`
+        if self.peek() == &Token::Colon && self.peek_nth(1) == &Token::Colon {
+            self.next();
+            self.next();
+            self.parse_identifier()?;
+        }
+
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ident(name: &str) -> Expr {
+        Expr::Identifier(name.into())
+    }
+
+    fn call(name: &str, args: Vec<Expr>) -> Expr {
+        Expr::Call {
+            name: name.into(),
+            args,
+        }
+    }
+
+    #[test]
+    fn parse_simple_select() {
+        let statement = parse_select("SELECT usage, host FROM cpu").unwrap();
+        assert_eq!(
+            statement,
+            SelectStatement {
+                fields: vec![
+                    Field {
+                        expr: ident("usage"),
+                        alias: None
+                    },
+                    Field {
+                        expr: ident("host"),
+                        alias: None
+                    },
+                ],
+                from: vec![Measurement::Name("cpu".into())],
+                condition: None,
+                dimensions: vec![],
+                fill: Fill::Null,
+                order_desc: false,
+                limit: None,
+                offset: None,
+                slimit: None,
+                soffset: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_full_select() {
+        let statement = parse_select(
+            r#"select MEAN("usage") as m, max(usage) FROM "telegraf"."autogen"."cpu"
+               WHERE host = 'a' AND time > now() - 1h
+               GROUP BY time(10m, 1m), host fill(-1) ORDER BY time DESC
+               LIMIT 10 OFFSET 2 SLIMIT 3 SOFFSET 4;"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            statement.fields,
+            vec![
+                Field {
+                    expr: call("mean", vec![ident("usage")]),
+                    alias: Some("m".into())
+                },
+                Field {
+                    expr: call("max", vec![ident("usage")]),
+                    alias: None
+                },
+            ]
+        );
+        assert_eq!(statement.from, vec![Measurement::Name("cpu".into())]);
+        assert_eq!(
+            statement.condition,
+            Some(Expr::binary(
+                Expr::binary(ident("host"), BinaryOp::Eq, Expr::String("a".into())),
+                BinaryOp::And,
+                Expr::binary(
+                    ident("time"),
+                    BinaryOp::Gt,
+                    Expr::binary(
+                        call("now", vec![]),
+                        BinaryOp::Sub,
+                        Expr::Duration(3_600_000_000_000)
+                    )
+                )
+            ))
+        );
+        assert_eq!(
+            statement.dimensions,
+            vec![
+                Dimension::Time(TimeDimension {
+                    interval: 600_000_000_000,
+                    offset: 60_000_000_000,
+                }),
+                Dimension::Tag("host".into()),
+            ]
+        );
+        assert_eq!(statement.fill, Fill::Value(-1.0));
+        assert!(statement.order_desc);
+        assert_eq!(statement.limit, Some(10));
+        assert_eq!(statement.offset, Some(2));
+        assert_eq!(statement.slimit, Some(3));
+        assert_eq!(statement.soffset, Some(4));
+    }
+
+    #[test]
+    fn parse_precedence() {
+        let statement =
+            parse_select("SELECT a + b * c FROM m WHERE x = 1 OR y = 2 AND z = 3").unwrap();
+
+        assert_eq!(
+            statement.fields[0].expr,
+            Expr::binary(
+                ident("a"),
+                BinaryOp::Add,
+                Expr::binary(ident("b"), BinaryOp::Mul, ident("c"))
+            )
+        );
+
+        assert_eq!(
+            statement.condition,
+            Some(Expr::binary(
+                Expr::binary(ident("x"), BinaryOp::Eq, Expr::Integer(1)),
+                BinaryOp::Or,
+                Expr::binary(
+                    Expr::binary(ident("y"), BinaryOp::Eq, Expr::Integer(2)),
+                    BinaryOp::And,
+                    Expr::binary(ident("z"), BinaryOp::Eq, Expr::Integer(3)),
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_regex() {
+        let statement =
+            parse_select("SELECT * FROM /cpu.*/ WHERE host =~ /^server[0-9]+$/ GROUP BY *")
+                .unwrap();
+
+        assert_eq!(statement.fields[0].expr, Expr::Wildcard);
+        assert_eq!(statement.from, vec![Measurement::Regex("cpu.*".into())]);
+        assert_eq!(
+            statement.condition,
+            Some(Expr::binary(
+                ident("host"),
+                BinaryOp::RegexMatch,
+                Expr::Regex("^server[0-9]+$".into())
+            ))
+        );
+        assert_eq!(statement.dimensions, vec![Dimension::Wildcard]);
+    }
+
+    #[test]
+    fn parse_fill_options() {
+        let fill = |s: &str| {
+            parse_select(&format!(
+                "SELECT mean(x) FROM m GROUP BY time(1m) fill({})",
+                s
+            ))
+            .unwrap()
+            .fill
+        };
+        assert_eq!(fill("null"), Fill::Null);
+        assert_eq!(fill("none"), Fill::None);
+        assert_eq!(fill("previous"), Fill::Previous);
+        assert_eq!(fill("linear"), Fill::Linear);
+        assert_eq!(fill("0"), Fill::Value(0.0));
+        assert_eq!(fill("1.5"), Fill::Value(1.5));
+    }
+
+    #[test]
+    fn parse_errors() {
+        let err = |s: &str| parse_select(s).unwrap_err().to_string();
+
+        assert_eq!(
+            err("SELECT"),
+            "Expected expression at position 6, found end of input"
+        );
+        assert_eq!(
+            err("SELECT a"),
+            "Expected FROM at position 8, found end of input"
+        );
+        assert_eq!(
+            err("SELECT a FROM m LIMIT -1"),
+            "Expected non-negative integer at position 22, found -"
+        );
+        assert_eq!(
+            err("SELECT a FROM m fill(sometimes)"),
+            "Expected null, none, previous, linear or a number at position 21, found sometimes"
+        );
+        assert_eq!(
+            err("SELECT a FROM m extra"),
+            "Expected end of input at position 16, found extra"
+        );
+    }
+
+    #[test]
+    fn parse_bound_parameters() {
+        let statement = parse_select("SELECT usage FROM cpu WHERE host = $host").unwrap();
+        assert_eq!(
+            statement.condition,
+            Some(Expr::binary(
+                Expr::Identifier("host".into()),
+                BinaryOp::Eq,
+                Expr::BoundParameter("host".into())
+            ))
+        );
+    }
+
+    #[test]
+    fn parse_multiple_statements() {
+        let statements = parse_query("SELECT a FROM m; SELECT b FROM n;").unwrap();
+        assert_eq!(statements.len(), 2);
+        assert_eq!(statements[0].from, vec![Measurement::Name("m".into())]);
+        assert_eq!(statements[1].from, vec![Measurement::Name("n".into())]);
+
+        assert_eq!(
+            parse_query("SELECT a FROM m SELECT b FROM n")
+                .unwrap_err()
+                .to_string(),
+            "Expected end of input at position 16, found SELECT"
+        );
+    }
+}
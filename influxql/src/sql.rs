@@ -0,0 +1,876 @@
+//! Translates InfluxQL `SELECT` statements into SQL that DataFusion
+//! can plan and run against an InfluxDB IOx database.
+//!
+//! Only the subset of InfluxQL that has a direct SQL equivalent, or
+//! which can be applied to the series of the results of the SQL query,
+//! is currently supported; anything else results in an
+//! `Error::Unsupported` rather than silently different results.
+//!
+//! `LIMIT` and `OFFSET`, which apply to the rows of each series in
+//! InfluxQL, `SLIMIT` and `SOFFSET`, which apply to the series, and
+//! the `fill()` of the windows of `GROUP BY time()` are not translated
+//! into SQL: they are returned as the `SeriesOptions` of the
+//! translation, to be applied to the series once the rows of each have
+//! been collected. Windows are filled from the start of the time range
+//! of the `WHERE` clause (or, if it has none, the first window with
+//! data) up to its end (or `now()`).
+//!
+//! Selected expressions may combine fields, and the results of
+//! aggregates, with arithmetic, such as `usage_user + usage_system AS
+//! total`. These are translated into SQL expressions, which DataFusion
+//! evaluates over whole Arrow arrays at a time. As in InfluxQL,
+//! division always produces a float, and an expression without an
+//! alias is named after the fields and functions in it, such as
+//! `usage_user_usage_system`.
+//!
+//! The aggregate `histogram_quantile(q)` of a histogram stored as
+//! bucket columns is passed through to the SQL, where IOx replaces it
+//! with the expression estimating the quantile from the buckets.
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime};
+use snafu::ResultExt;
+
+use crate::{
+    ast::{BinaryOp, Dimension, Expr, Field, Fill, Measurement, SelectStatement, TimeDimension},
+    InvalidQuery, InvalidTimeLiteral, Result, UnboundParameter, Unsupported,
+};
+
+/// The most windows a series is filled to, so that a `fill()` over a
+/// long time range can't make the server run out of memory
+pub const MAX_FILLED_WINDOWS: i64 = 100_000;
+
+/// The name of the timestamp column in IOx
+const TIME_COLUMN: &str = "time";
+
+/// The aggregate estimating a quantile of a histogram stored as bucket
+/// columns, which has the same name in SQL
+const HISTOGRAM_QUANTILE: &str = "histogram_quantile";
+
+/// Translates `statement` into an equivalent SQL query. `now` is the
+/// value, in nanoseconds since the epoch, used for `now()`.
+///
+/// `GROUP BY time()` is translated into a group by on the start of
+/// each time window, which is returned as the `time` column. Tags in
+/// the `GROUP BY` clause are returned as columns, and the results are
+/// ordered by tags and then time so each series is contiguous.
+pub fn to_sql(statement: &SelectStatement, now: i64) -> Result<String> {
+    translate(statement, now).map(|translation| translation.sql)
+}
+
+/// Translates `statement` into an equivalent SQL query, as `to_sql`
+/// does, and the options applied to the series of its results
+pub fn translate(statement: &SelectStatement, now: i64) -> Result<Translation> {
+    Translator { now }.select(statement)
+}
+
+/// The translation of an InfluxQL statement
+#[derive(Debug, Clone, PartialEq)]
+pub struct Translation {
+    /// The SQL query returning the rows of the series
+    pub sql: String,
+    /// What is done to the series of the results of `sql`
+    pub series: SeriesOptions,
+}
+
+/// What is done to the series of the results of a query, in order:
+/// the windows of each series are filled, the rows of each series are
+/// limited, and then the series themselves are
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SeriesOptions {
+    /// How the windows of each series are filled, if they are
+    pub fill: Option<FillWindows>,
+    /// The number of rows of each series skipped (`OFFSET`)
+    pub offset: u64,
+    /// The maximum number of rows of each series returned (`LIMIT`)
+    pub limit: Option<u64>,
+    /// The number of series skipped (`SOFFSET`)
+    pub soffset: u64,
+    /// The maximum number of series returned (`SLIMIT`)
+    pub slimit: Option<u64>,
+}
+
+/// The windows of `GROUP BY time()` a series has a row for, filled
+/// with `fill` where it has no data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FillWindows {
+    /// How the values of windows without data are filled, which is
+    /// never `Fill::None`
+    pub fill: Fill,
+    /// The windows, in nanoseconds
+    pub dimension: TimeDimension,
+    /// The first time of the time range of the query, if its `WHERE`
+    /// clause has a lower bound
+    pub start: Option<i64>,
+    /// The time the time range of the query ends before
+    pub end: i64,
+    /// True if the windows are in descending order of time
+    pub descending: bool,
+}
+
+impl FillWindows {
+    /// Returns the start of the window `time` falls into
+    pub fn window_start(&self, time: i64) -> i64 {
+        let TimeDimension { interval, offset } = self.dimension;
+        time - time.wrapping_sub(offset).rem_euclid(interval)
+    }
+
+    /// Returns the start of each window of the time range, in order,
+    /// starting from the window of `first` if the time range has no
+    /// lower bound
+    pub fn windows(&self, first: i64) -> Result<Vec<i64>> {
+        let first = self.window_start(self.start.unwrap_or(first));
+        if self.end <= first {
+            return Ok(vec![]);
+        }
+        let last = self.window_start(self.end - 1);
+
+        let interval = self.dimension.interval;
+        let count = (last - first) / interval + 1;
+        if count > MAX_FILLED_WINDOWS {
+            return InvalidQuery {
+                description: format!(
+                    "fill() of {} windows exceeds the maximum of {}: narrow the time range",
+                    count, MAX_FILLED_WINDOWS
+                ),
+            }
+            .fail();
+        }
+
+        let mut windows: Vec<_> = (0..count).map(|i| first + i * interval).collect();
+        if self.descending {
+            windows.reverse();
+        }
+        Ok(windows)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Translator {
+    now: i64,
+}
+
+impl Translator {
+    fn select(&self, statement: &SelectStatement) -> Result<Translation> {
+        let measurement = match statement.from.as_slice() {
+            [Measurement::Name(name)] => name,
+            [Measurement::Regex(_)] => return unsupported("regular expressions in FROM"),
+            _ => return unsupported("selecting from multiple measurements"),
+        };
+
+        for dimension in &statement.dimensions {
+            match dimension {
+                Dimension::Wildcard => return unsupported("GROUP BY *"),
+                Dimension::Regex(_) => return unsupported("GROUP BY /regex/"),
+                Dimension::Time(time) if time.interval <= 0 => {
+                    return InvalidQuery {
+                        description: "GROUP BY time() requires a positive interval",
+                    }
+                    .fail()
+                }
+                Dimension::Time(_) | Dimension::Tag(_) => {}
+            }
+        }
+
+        let tags = statement
+            .tag_dimensions()
+            .map(quote_ident)
+            .collect::<Vec<_>>();
+
+        let num_aggregates = statement
+            .fields
+            .iter()
+            .filter(|field| contains_aggregate(&field.expr))
+            .count();
+        let is_aggregate = num_aggregates > 0;
+
+        if is_aggregate && num_aggregates != statement.fields.len() {
+            return InvalidQuery {
+                description: "mixing aggregate and non-aggregate queries is not supported",
+            }
+            .fail();
+        }
+
+        let time_dimension = statement.time_dimension();
+        if time_dimension.is_some() && !is_aggregate {
+            return InvalidQuery {
+                description: "GROUP BY time() requires at least one aggregate function",
+            }
+            .fail();
+        }
+
+        let time = quote_ident(TIME_COLUMN);
+        let time_order = if statement.order_desc {
+            format!("{} DESC", time)
+        } else {
+            time.clone()
+        };
+
+        let mut select = tags.clone();
+        let mut group_by = vec![];
+        let mut order_by = tags.clone();
+
+        if is_aggregate {
+            group_by.extend(tags);
+
+            if let Some(time_dimension) = time_dimension {
+                let window_start = window_start(time_dimension);
+                select.push(format!("{} AS {}", window_start, time));
+                group_by.push(window_start);
+                order_by.push(time_order);
+            }
+        } else {
+            let has_wildcard = statement
+                .fields
+                .iter()
+                .any(|field| field.expr == Expr::Wildcard);
+            if !has_wildcard {
+                select.push(time);
+            }
+            order_by.push(time_order);
+        }
+
+        for (field, name) in statement.fields.iter().zip(column_names(&statement.fields)) {
+            select.push(self.field(field, name.as_deref())?);
+        }
+
+        let mut sql = format!(
+            "SELECT {} FROM {}",
+            select.join(", "),
+            quote_ident(measurement)
+        );
+
+        if let Some(condition) = &statement.condition {
+            sql.push_str(&format!(" WHERE {}", self.expr(condition)?));
+        }
+
+        if !group_by.is_empty() {
+            sql.push_str(&format!(" GROUP BY {}", group_by.join(", ")));
+        }
+
+        if !order_by.is_empty() {
+            sql.push_str(&format!(" ORDER BY {}", order_by.join(", ")));
+        }
+
+        // windows are only filled for GROUP BY time(), as in InfluxQL
+        let fill = match (time_dimension, statement.fill) {
+            (None, _) | (_, Fill::None) => None,
+            (Some(&dimension), fill) => {
+                let (start, end) = self.time_range(statement.condition.as_ref());
+                Some(FillWindows {
+                    fill,
+                    dimension,
+                    start,
+                    end: end.unwrap_or_else(|| self.now.saturating_add(1)),
+                    descending: statement.order_desc,
+                })
+            }
+        };
+
+        // LIMIT applies to each series in InfluxQL, but to the whole
+        // result in SQL, so only the rows of a single series which is
+        // not filled can be limited by the SQL query
+        let offset = statement.offset.unwrap_or(0);
+        if let (Some(limit), true, None) = (statement.limit, tags.is_empty(), fill) {
+            sql.push_str(&format!(" LIMIT {}", limit.saturating_add(offset)));
+        }
+
+        Ok(Translation {
+            sql,
+            series: SeriesOptions {
+                fill,
+                offset,
+                limit: statement.limit,
+                soffset: statement.soffset.unwrap_or(0),
+                slimit: statement.slimit,
+            },
+        })
+    }
+
+    /// Returns the time range the conditions of `condition` joined by
+    /// `AND` bound the time column to, as the first time and the time
+    /// it ends before, either of which may be unbounded
+    fn time_range(&self, condition: Option<&Expr>) -> (Option<i64>, Option<i64>) {
+        let mut range = (None, None);
+        if let Some(condition) = condition {
+            self.narrow_time_range(condition, &mut range);
+        }
+        range
+    }
+
+    fn narrow_time_range(&self, expr: &Expr, range: &mut (Option<i64>, Option<i64>)) {
+        let (lhs, op, rhs) = match expr {
+            Expr::Binary { lhs, op, rhs } => (lhs.as_ref(), *op, rhs.as_ref()),
+            _ => return,
+        };
+        if op == BinaryOp::And {
+            self.narrow_time_range(lhs, range);
+            self.narrow_time_range(rhs, range);
+            return;
+        }
+
+        let is_time = |expr: &Expr| matches!(expr, Expr::Identifier(name) if name == TIME_COLUMN);
+        let (op, value) = match (is_time(lhs), is_time(rhs)) {
+            (true, false) => (op, self.constant(rhs)),
+            (false, true) => match op {
+                BinaryOp::Lt => (BinaryOp::Gt, self.constant(lhs)),
+                BinaryOp::LtEq => (BinaryOp::GtEq, self.constant(lhs)),
+                BinaryOp::Gt => (BinaryOp::Lt, self.constant(lhs)),
+                BinaryOp::GtEq => (BinaryOp::LtEq, self.constant(lhs)),
+                op => (op, self.constant(lhs)),
+            },
+            _ => return,
+        };
+        let value = match value {
+            Some(value) => value,
+            None => return,
+        };
+
+        let (from, until) = match op {
+            BinaryOp::Gt => (Some(value.saturating_add(1)), None),
+            BinaryOp::GtEq => (Some(value), None),
+            BinaryOp::Lt => (None, Some(value)),
+            BinaryOp::LtEq => (None, Some(value.saturating_add(1))),
+            BinaryOp::Eq => (Some(value), Some(value.saturating_add(1))),
+            _ => return,
+        };
+        if let Some(from) = from {
+            range.0 = Some(range.0.map_or(from, |start| start.max(from)));
+        }
+        if let Some(until) = until {
+            range.1 = Some(range.1.map_or(until, |end| end.min(until)));
+        }
+    }
+
+    /// Returns the value of `expr`, in nanoseconds, if it is a time
+    /// or duration that does not depend on the rows, such as `now() -
+    /// 1h` or `'2020-11-01T00:00:00Z'`
+    fn constant(&self, expr: &Expr) -> Option<i64> {
+        match expr {
+            Expr::Integer(value) | Expr::Duration(value) => Some(*value),
+            Expr::String(value) => parse_time_literal(value).ok(),
+            Expr::Call { name, args } if name == "now" && args.is_empty() => Some(self.now),
+            Expr::Binary {
+                lhs,
+                op: BinaryOp::Add,
+                rhs,
+            } => self.constant(lhs)?.checked_add(self.constant(rhs)?),
+            Expr::Binary {
+                lhs,
+                op: BinaryOp::Sub,
+                rhs,
+            } => self.constant(lhs)?.checked_sub(self.constant(rhs)?),
+            _ => None,
+        }
+    }
+
+    /// Translates `field`, naming its column `name`
+    fn field(&self, field: &Field, name: Option<&str>) -> Result<String> {
+        let expr = self.expr(&field.expr)?;
+
+        Ok(match (name, &field.expr) {
+            (Some(name), Expr::Identifier(identifier)) if name == identifier => expr,
+            (Some(name), _) => format!("{} AS {}", expr, quote_ident(name)),
+            (None, _) => expr,
+        })
+    }
+
+    fn expr(&self, expr: &Expr) -> Result<String> {
+        match expr {
+            Expr::Wildcard => Ok("*".to_string()),
+            Expr::Identifier(name) => Ok(quote_ident(name)),
+            Expr::String(value) => Ok(quote_string(value)),
+            Expr::Integer(value) => Ok(value.to_string()),
+            Expr::Float(value) => Ok(format!("{:?}", value)),
+            Expr::Boolean(value) => Ok(value.to_string()),
+            Expr::Duration(value) => Ok(value.to_string()),
+            Expr::Regex(_) => unsupported("regular expressions"),
+            Expr::BoundParameter(name) => UnboundParameter { name }.fail(),
+            Expr::Call { name, args } => self.call(name, args),
+            Expr::Binary { lhs, op, rhs } => {
+                // Division in InfluxQL is never integer division
+                let is_div = matches!(op, BinaryOp::Div);
+                let op = match op {
+                    BinaryOp::Or => "OR",
+                    BinaryOp::And => "AND",
+                    BinaryOp::Eq => "=",
+                    BinaryOp::NotEq => "!=",
+                    BinaryOp::Lt => "<",
+                    BinaryOp::LtEq => "<=",
+                    BinaryOp::Gt => ">",
+                    BinaryOp::GtEq => ">=",
+                    BinaryOp::Add => "+",
+                    BinaryOp::Sub => "-",
+                    BinaryOp::Mul => "*",
+                    BinaryOp::Div => "/",
+                    BinaryOp::Mod => "%",
+                    BinaryOp::RegexMatch | BinaryOp::RegexNotMatch => {
+                        return unsupported("regular expressions")
+                    }
+                };
+
+                let lhs_sql = self.operand(lhs, rhs)?;
+                let rhs_sql = self.operand(rhs, lhs)?;
+
+                if is_div {
+                    return Ok(format!("(CAST({} AS DOUBLE) / {})", lhs_sql, rhs_sql));
+                }
+
+                Ok(format!("({} {} {})", lhs_sql, op, rhs_sql))
+            }
+        }
+    }
+
+    /// Translates one side of a binary expression. String literals
+    /// compared with `time` are timestamps, and are converted to
+    /// nanoseconds
+    fn operand(&self, expr: &Expr, other: &Expr) -> Result<String> {
+        match (expr, other) {
+            (Expr::String(value), Expr::Identifier(name)) if name == TIME_COLUMN => {
+                Ok(parse_time_literal(value)?.to_string())
+            }
+            _ => self.expr(expr),
+        }
+    }
+
+    fn call(&self, name: &str, args: &[Expr]) -> Result<String> {
+        if name == "now" && args.is_empty() {
+            return Ok(self.now.to_string());
+        }
+
+        // computed by IOx from the bucket columns of the measurement
+        if name == HISTOGRAM_QUANTILE {
+            return match args {
+                [Expr::Float(q)] if (0.0..=1.0).contains(q) => {
+                    Ok(format!("{}({:?})", HISTOGRAM_QUANTILE, q))
+                }
+                [Expr::Integer(q)] if *q == 0 || *q == 1 => {
+                    Ok(format!("{}({:?})", HISTOGRAM_QUANTILE, *q as f64))
+                }
+                _ => InvalidQuery {
+                    description: format!(
+                        "function {}() expects a quantile between 0 and 1",
+                        HISTOGRAM_QUANTILE
+                    ),
+                }
+                .fail(),
+            };
+        }
+
+        let function = match aggregate_function(name) {
+            Some(function) => function,
+            None => return unsupported(format!("function {}()", name)),
+        };
+
+        match args {
+            [arg] => Ok(format!("{}({})", function, self.expr(arg)?)),
+            _ => InvalidQuery {
+                description: format!("function {}() expects 1 argument, got {}", name, args.len()),
+            }
+            .fail(),
+        }
+    }
+}
+
+/// Returns the SQL equivalent of the InfluxQL aggregate `name`, if any
+fn aggregate_function(name: &str) -> Option<&'static str> {
+    match name {
+        "count" => Some("COUNT"),
+        "sum" => Some("SUM"),
+        "mean" => Some("AVG"),
+        "min" => Some("MIN"),
+        "max" => Some("MAX"),
+        _ => None,
+    }
+}
+
+fn contains_aggregate(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call { name, .. } => aggregate_function(name).is_some() || name == HISTOGRAM_QUANTILE,
+        Expr::Binary { lhs, rhs, .. } => contains_aggregate(lhs) || contains_aggregate(rhs),
+        _ => false,
+    }
+}
+
+/// Returns the name of the column of each of `fields`, or `None` for
+/// wildcards. A field is named by its alias or, as in InfluxQL, after
+/// the expression it selects, and names taken by an earlier field are
+/// given a suffix such as `_1`.
+fn column_names(fields: &[Field]) -> Vec<Option<String>> {
+    let mut taken = HashMap::new();
+
+    fields
+        .iter()
+        .map(|field| {
+            let name = match (&field.alias, &field.expr) {
+                (Some(alias), _) => alias.clone(),
+                (None, Expr::Wildcard) => return None,
+                (None, expr) => expr_name(expr),
+            };
+            if name.is_empty() {
+                return None;
+            }
+
+            let count = taken.entry(name.clone()).or_insert(0);
+            let name = match *count {
+                0 => name,
+                n => format!("{}_{}", name, n),
+            };
+            *count += 1;
+            Some(name)
+        })
+        .collect()
+}
+
+/// Returns the name InfluxQL gives the result of `expr`: the names of
+/// the fields and functions in it, joined with underscores
+fn expr_name(expr: &Expr) -> String {
+    match expr {
+        Expr::Identifier(name) | Expr::Call { name, .. } => name.clone(),
+        Expr::Binary { lhs, rhs, .. } => {
+            let names: Vec<_> = vec![expr_name(lhs), expr_name(rhs)]
+                .into_iter()
+                .filter(|name| !name.is_empty())
+                .collect();
+            names.join("_")
+        }
+        _ => String::new(),
+    }
+}
+
+/// Returns a SQL expression computing the start of the window each
+/// row's timestamp falls into
+fn window_start(time_dimension: &TimeDimension) -> String {
+    let TimeDimension { interval, offset } = *time_dimension;
+    let time = quote_ident(TIME_COLUMN);
+
+    if offset == 0 {
+        format!("({} - ({} % {}))", time, time, interval)
+    } else {
+        format!("({} - (({} - {}) % {}))", time, time, offset, interval)
+    }
+}
+
+/// Parses an RFC3339 timestamp, or a date and time in UTC such as
+/// `2020-11-01 10:00:00` or `2020-11-01`, into nanoseconds
+fn parse_time_literal(value: &str) -> Result<i64> {
+    let rfc3339 = DateTime::parse_from_rfc3339(value);
+    if let Ok(time) = rfc3339 {
+        return Ok(time.timestamp_nanos());
+    }
+
+    if let Ok(time) = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S%.f") {
+        return Ok(time.timestamp_nanos());
+    }
+
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        return Ok(date.and_hms(0, 0, 0).timestamp_nanos());
+    }
+
+    rfc3339
+        .map(|time| time.timestamp_nanos())
+        .context(InvalidTimeLiteral { value })
+}
+
+/// Quotes `name` if it can not be used as a bare SQL identifier
+fn quote_ident(name: &str) -> String {
+    const RESERVED: &[&str] = &[
+        "and",
+        "as",
+        "by",
+        "date",
+        "from",
+        "group",
+        "interval",
+        "limit",
+        "not",
+        "or",
+        "order",
+        "select",
+        "time",
+        "timestamp",
+        "where",
+    ];
+
+    let mut chars = name.chars();
+    let is_simple = chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !RESERVED.contains(&name.to_ascii_lowercase().as_str());
+
+    if is_simple {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+fn quote_string(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn unsupported<T>(description: impl Into<String>) -> Result<T> {
+    Unsupported { description }.fail()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse_select;
+
+    fn sql(influxql: &str) -> String {
+        let statement = parse_select(influxql).unwrap();
+        to_sql(&statement, 1_000).unwrap()
+    }
+
+    fn sql_error(influxql: &str) -> String {
+        let statement = parse_select(influxql).unwrap();
+        to_sql(&statement, 1_000).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn raw_select() {
+        assert_eq!(
+            sql("SELECT usage, \"host name\" FROM cpu"),
+            r#"SELECT "time", usage, "host name" FROM cpu ORDER BY "time""#
+        );
+        assert_eq!(
+            sql("SELECT * FROM cpu ORDER BY time DESC LIMIT 10"),
+            r#"SELECT * FROM cpu ORDER BY "time" DESC LIMIT 10"#
+        );
+    }
+
+    #[test]
+    fn time_conditions() {
+        assert_eq!(
+            sql("SELECT usage FROM cpu WHERE time > now() - 1s AND host = 'o\\'brien'"),
+            r#"SELECT "time", usage FROM cpu WHERE (("time" > (1000 - 1000000000)) AND (host = 'o''brien')) ORDER BY "time""#
+        );
+        assert_eq!(
+            sql("SELECT usage FROM cpu WHERE time >= '1970-01-01T00:00:01Z' AND time < '1970-01-02'"),
+            r#"SELECT "time", usage FROM cpu WHERE (("time" >= 1000000000) AND ("time" < 86400000000000)) ORDER BY "time""#
+        );
+        assert!(sql_error("SELECT usage FROM cpu WHERE time > 'yesterday'")
+            .starts_with("Invalid time literal 'yesterday'"));
+    }
+
+    #[test]
+    fn group_by_time_and_tags() {
+        assert_eq!(
+            sql("SELECT mean(usage), max(usage) AS peak FROM cpu GROUP BY time(10s, 1s), host, region"),
+            r#"SELECT host, region, ("time" - (("time" - 1000000000) % 10000000000)) AS "time", AVG(usage) AS mean, MAX(usage) AS peak FROM cpu GROUP BY host, region, ("time" - (("time" - 1000000000) % 10000000000)) ORDER BY host, region, "time""#
+        );
+        assert_eq!(
+            sql("SELECT count(usage) FROM cpu GROUP BY host"),
+            r#"SELECT host, COUNT(usage) AS count FROM cpu GROUP BY host ORDER BY host"#
+        );
+        assert_eq!(
+            sql("SELECT sum(usage) FROM cpu fill(none)"),
+            r#"SELECT SUM(usage) AS sum FROM cpu"#
+        );
+    }
+
+    #[test]
+    fn field_math() {
+        assert_eq!(
+            sql("SELECT usage_user + usage_system AS total, usage_user * 100 FROM cpu"),
+            r#"SELECT "time", (usage_user + usage_system) AS total, (usage_user * 100) AS usage_user FROM cpu ORDER BY "time""#
+        );
+        assert_eq!(
+            sql("SELECT usage_user + usage_system, used / total FROM cpu"),
+            r#"SELECT "time", (usage_user + usage_system) AS usage_user_usage_system, (CAST(used AS DOUBLE) / total) AS used_total FROM cpu ORDER BY "time""#
+        );
+        assert_eq!(
+            sql("SELECT mean(usage_user) + mean(usage_system), max(usage_user) - 1 FROM cpu GROUP BY host"),
+            r#"SELECT host, (AVG(usage_user) + AVG(usage_system)) AS mean_mean, (MAX(usage_user) - 1) AS max FROM cpu GROUP BY host ORDER BY host"#
+        );
+    }
+
+    #[test]
+    fn histogram_quantile() {
+        assert_eq!(
+            sql("SELECT histogram_quantile(0.9) FROM http_duration GROUP BY time(1m), path"),
+            r#"SELECT path, ("time" - ("time" % 60000000000)) AS "time", histogram_quantile(0.9) AS histogram_quantile FROM http_duration GROUP BY path, ("time" - ("time" % 60000000000)) ORDER BY path, "time""#
+        );
+        assert_eq!(
+            sql("SELECT histogram_quantile(1) * 1000 AS max_ms FROM http_duration"),
+            r#"SELECT (histogram_quantile(1.0) * 1000) AS max_ms FROM http_duration"#
+        );
+        assert_eq!(
+            sql_error("SELECT histogram_quantile(99) FROM http_duration"),
+            "Invalid InfluxQL query: function histogram_quantile() expects a quantile between 0 and 1"
+        );
+    }
+
+    #[test]
+    fn duplicate_column_names() {
+        assert_eq!(
+            sql("SELECT usage, usage * 2, usage AS peak, mean FROM cpu"),
+            r#"SELECT "time", usage, (usage * 2) AS usage_1, usage AS peak, mean FROM cpu ORDER BY "time""#
+        );
+        assert_eq!(
+            sql("SELECT mean(usage), mean(usage) * 2 FROM cpu"),
+            r#"SELECT AVG(usage) AS mean, (AVG(usage) * 2) AS mean_1 FROM cpu"#
+        );
+    }
+
+    #[test]
+    fn bound_parameters() {
+        let mut statement =
+            parse_select("SELECT usage FROM cpu WHERE host = $host AND time > $start").unwrap();
+
+        assert_eq!(
+            to_sql(&statement, 0).unwrap_err().to_string(),
+            "No value provided for bound parameter $host"
+        );
+
+        let mut params = std::collections::HashMap::new();
+        params.insert("host".to_string(), Expr::String("a'b".into()));
+        params.insert(
+            "start".to_string(),
+            Expr::String("1970-01-01T00:00:01Z".into()),
+        );
+        statement.bind(&params).unwrap();
+
+        assert_eq!(
+            to_sql(&statement, 0).unwrap(),
+            r#"SELECT "time", usage FROM cpu WHERE ((host = 'a''b') AND ("time" > 1000000000)) ORDER BY "time""#
+        );
+    }
+
+    #[test]
+    fn unsupported_features() {
+        assert_eq!(
+            sql_error("SELECT usage FROM cpu WHERE host =~ /a/"),
+            "Unsupported InfluxQL: regular expressions"
+        );
+        assert_eq!(
+            sql_error("SELECT usage FROM cpu, mem"),
+            "Unsupported InfluxQL: selecting from multiple measurements"
+        );
+        assert_eq!(
+            sql_error("SELECT percentile(usage, 95) FROM cpu"),
+            "Unsupported InfluxQL: function percentile()"
+        );
+    }
+
+    #[test]
+    fn invalid_queries() {
+        assert_eq!(
+            sql_error("SELECT usage FROM cpu GROUP BY time(1m)"),
+            "Invalid InfluxQL query: GROUP BY time() requires at least one aggregate function"
+        );
+        assert_eq!(
+            sql_error("SELECT usage, mean(usage) FROM cpu"),
+            "Invalid InfluxQL query: mixing aggregate and non-aggregate queries is not supported"
+        );
+        assert_eq!(
+            sql_error("SELECT count(usage) FROM cpu GROUP BY time(0s)"),
+            "Invalid InfluxQL query: GROUP BY time() requires a positive interval"
+        );
+    }
+
+    fn translation(influxql: &str) -> Translation {
+        let statement = parse_select(influxql).unwrap();
+        translate(&statement, 1_000).unwrap()
+    }
+
+    #[test]
+    fn series_limits() {
+        let translated =
+            translation("SELECT usage FROM cpu GROUP BY host LIMIT 2 OFFSET 1 SLIMIT 3 SOFFSET 4");
+        assert_eq!(
+            translated.sql,
+            r#"SELECT host, "time", usage FROM cpu ORDER BY host, "time""#
+        );
+        assert_eq!(
+            translated.series,
+            SeriesOptions {
+                fill: None,
+                offset: 1,
+                limit: Some(2),
+                soffset: 4,
+                slimit: Some(3),
+            }
+        );
+
+        // the rows of a single series are also limited by the SQL query
+        let translated = translation("SELECT usage FROM cpu LIMIT 2 OFFSET 1");
+        assert_eq!(
+            translated.sql,
+            r#"SELECT "time", usage FROM cpu ORDER BY "time" LIMIT 3"#
+        );
+        assert_eq!(translated.series.offset, 1);
+        assert_eq!(translated.series.limit, Some(2));
+    }
+
+    #[test]
+    fn fill_windows() {
+        let second = 1_000_000_000;
+
+        let translated = translation(
+            "SELECT mean(usage) FROM cpu WHERE time >= 10s AND time < 15s GROUP BY time(1s) \
+             fill(previous) LIMIT 5",
+        );
+        assert!(!translated.sql.contains("LIMIT"));
+        let fill = translated.series.fill.unwrap();
+        assert_eq!(
+            fill,
+            FillWindows {
+                fill: Fill::Previous,
+                dimension: TimeDimension {
+                    interval: second,
+                    offset: 0,
+                },
+                start: Some(10 * second),
+                end: 15 * second,
+                descending: false,
+            }
+        );
+        assert_eq!(
+            fill.windows(0).unwrap(),
+            (10..15).map(|s| s * second).collect::<Vec<_>>()
+        );
+
+        // without a lower bound, windows start from the first with
+        // data, and without an upper bound they end at now()
+        let fill = translation(
+            "SELECT mean(usage) FROM cpu WHERE time <= 4s GROUP BY time(2s, 1s) \
+             ORDER BY time DESC",
+        )
+        .series
+        .fill
+        .unwrap();
+        assert_eq!(fill.fill, Fill::Null);
+        assert_eq!((fill.start, fill.end), (None, 4 * second + 1));
+        assert_eq!(fill.windows(2 * second).unwrap(), vec![3 * second, second]);
+        let fill = translation("SELECT mean(usage) FROM cpu GROUP BY time(1s) fill(0)")
+            .series
+            .fill
+            .unwrap();
+        assert_eq!((fill.fill, fill.end), (Fill::Value(0.0), 1_001));
+        assert_eq!(fill.windows(0).unwrap(), vec![0]);
+
+        // windows are only filled for GROUP BY time()
+        let fill = |influxql| translation(influxql).series.fill;
+        assert_eq!(
+            fill("SELECT mean(usage) FROM cpu GROUP BY time(1s) fill(none)"),
+            None
+        );
+        assert_eq!(fill("SELECT mean(usage) FROM cpu fill(linear)"), None);
+
+        let fill = translation(
+            "SELECT count(usage) FROM cpu WHERE time >= 0 AND time < 1h GROUP BY time(1ms)",
+        )
+        .series
+        .fill
+        .unwrap();
+        assert_eq!(
+            fill.windows(0).unwrap_err().to_string(),
+            "Invalid InfluxQL query: fill() of 3600000 windows exceeds the maximum of 100000: \
+             narrow the time range"
+        );
+    }
+}
@@ -0,0 +1,391 @@
+//! Splits InfluxQL text into tokens
+
+use crate::{InvalidDuration, InvalidNumber, Result, UnexpectedCharacter, Unterminated};
+use snafu::OptionExt;
+use std::fmt;
+
+const NANOS_PER_MICRO: i64 = 1_000;
+const NANOS_PER_MILLI: i64 = 1_000_000;
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+const NANOS_PER_MINUTE: i64 = 60 * NANOS_PER_SECOND;
+const NANOS_PER_HOUR: i64 = 60 * NANOS_PER_MINUTE;
+const NANOS_PER_DAY: i64 = 24 * NANOS_PER_HOUR;
+const NANOS_PER_WEEK: i64 = 7 * NANOS_PER_DAY;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    /// A bare identifier or keyword
+    Ident(String),
+    /// A double quoted identifier
+    QuotedIdent(String),
+    /// A single quoted string
+    String(String),
+    Integer(i64),
+    Float(f64),
+    /// A duration literal, in nanoseconds
+    Duration(i64),
+    Regex(String),
+    /// A `$name` placeholder for a bound parameter
+    BoundParameter(String),
+    Comma,
+    Colon,
+    Dot,
+    Semicolon,
+    LParen,
+    RParen,
+    Star,
+    Plus,
+    Minus,
+    Slash,
+    Percent,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    EqRegex,
+    NotEqRegex,
+    Eof,
+}
+
+impl Token {
+    /// Returns true if this token is the (case insensitive) keyword
+    pub fn is_keyword(&self, keyword: &str) -> bool {
+        matches!(self, Self::Ident(ident) if ident.eq_ignore_ascii_case(keyword))
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ident(s) => write!(f, "{}", s),
+            Self::QuotedIdent(s) => write!(f, "\"{}\"", s),
+            Self::String(s) => write!(f, "'{}'", s),
+            Self::Integer(v) => write!(f, "{}", v),
+            Self::Float(v) => write!(f, "{}", v),
+            Self::Duration(v) => write!(f, "{}ns", v),
+            Self::Regex(s) => write!(f, "/{}/", s),
+            Self::BoundParameter(s) => write!(f, "${}", s),
+            Self::Comma => write!(f, ","),
+            Self::Colon => write!(f, ":"),
+            Self::Dot => write!(f, "."),
+            Self::Semicolon => write!(f, ";"),
+            Self::LParen => write!(f, "("),
+            Self::RParen => write!(f, ")"),
+            Self::Star => write!(f, "*"),
+            Self::Plus => write!(f, "+"),
+            Self::Minus => write!(f, "-"),
+            Self::Slash => write!(f, "/"),
+            Self::Percent => write!(f, "%"),
+            Self::Eq => write!(f, "="),
+            Self::NotEq => write!(f, "!="),
+            Self::Lt => write!(f, "<"),
+            Self::LtEq => write!(f, "<="),
+            Self::Gt => write!(f, ">"),
+            Self::GtEq => write!(f, ">="),
+            Self::EqRegex => write!(f, "=~"),
+            Self::NotEqRegex => write!(f, "!~"),
+            Self::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+/// Splits `input` into tokens, each paired with its starting byte
+/// offset. The last token is always `Token::Eof`.
+pub fn tokenize(input: &str) -> Result<Vec<(usize, Token)>> {
+    let mut tokens: Vec<(usize, Token)> = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some(&(pos, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        // A '/' starts a regular expression, rather than being a
+        // division, where an operand can not appear: after a regex
+        // operator, a comma, FROM or GROUP BY
+        let regex_allowed = matches!(
+            tokens.last(),
+            Some((_, Token::EqRegex)) | Some((_, Token::NotEqRegex)) | Some((_, Token::Comma))
+        ) || tokens
+            .last()
+            .map_or(false, |(_, t)| t.is_keyword("from") || t.is_keyword("by"));
+
+        let token = match c {
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let mut ident = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Ident(ident)
+            }
+            '0'..='9' => lex_number(input, pos, &mut chars)?,
+            '"' => Token::QuotedIdent(lex_quoted(&mut chars, '"', pos, "identifier")?),
+            '\'' => Token::String(lex_quoted(&mut chars, '\'', pos, "string")?),
+            '/' if regex_allowed => Token::Regex(lex_quoted(&mut chars, '/', pos, "regex")?),
+            '$' => {
+                chars.next();
+                let mut name = String::new();
+                while let Some(&(_, c)) = chars.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' {
+                        name.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if name.is_empty() {
+                    return UnexpectedCharacter { c, pos }.fail();
+                }
+                Token::BoundParameter(name)
+            }
+            _ => {
+                chars.next();
+                let next = chars.peek().map(|&(_, c)| c);
+                let (token, two_chars) = match (c, next) {
+                    ('!', Some('=')) => (Token::NotEq, true),
+                    ('!', Some('~')) => (Token::NotEqRegex, true),
+                    ('=', Some('~')) => (Token::EqRegex, true),
+                    ('<', Some('=')) => (Token::LtEq, true),
+                    ('<', Some('>')) => (Token::NotEq, true),
+                    ('>', Some('=')) => (Token::GtEq, true),
+                    ('=', _) => (Token::Eq, false),
+                    ('<', _) => (Token::Lt, false),
+                    ('>', _) => (Token::Gt, false),
+                    (',', _) => (Token::Comma, false),
+                    (':', _) => (Token::Colon, false),
+                    ('.', _) => (Token::Dot, false),
+                    (';', _) => (Token::Semicolon, false),
+                    ('(', _) => (Token::LParen, false),
+                    (')', _) => (Token::RParen, false),
+                    ('*', _) => (Token::Star, false),
+                    ('+', _) => (Token::Plus, false),
+                    ('-', _) => (Token::Minus, false),
+                    ('/', _) => (Token::Slash, false),
+                    ('%', _) => (Token::Percent, false),
+                    _ => return UnexpectedCharacter { c, pos }.fail(),
+                };
+                if two_chars {
+                    chars.next();
+                }
+                token
+            }
+        };
+
+        tokens.push((pos, token));
+    }
+
+    tokens.push((input.len(), Token::Eof));
+    Ok(tokens)
+}
+
+/// Reads the contents of a quoted string, identifier or regex,
+/// handling backslash escapes of the quote character
+fn lex_quoted(
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+    quote: char,
+    pos: usize,
+    what: &'static str,
+) -> Result<String> {
+    // skip opening quote
+    chars.next();
+
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            Some((_, '\\')) => match chars.next() {
+                Some((_, c)) if c == quote => value.push(c),
+                // Regular expressions keep their escapes
+                Some((_, c)) if quote == '/' => {
+                    value.push('\\');
+                    value.push(c);
+                }
+                Some((_, c)) => value.push(c),
+                None => return Unterminated { what, pos }.fail(),
+            },
+            Some((_, c)) if c == quote => return Ok(value),
+            Some((_, c)) => value.push(c),
+            None => return Unterminated { what, pos }.fail(),
+        }
+    }
+}
+
+/// Reads an integer, float or duration literal
+fn lex_number(
+    input: &str,
+    pos: usize,
+    chars: &mut std::iter::Peekable<std::str::CharIndices<'_>>,
+) -> Result<Token> {
+    let mut end = pos;
+    let mut is_float = false;
+    while let Some(&(i, c)) = chars.peek() {
+        if c.is_ascii_digit() || (c == '.' && !is_float) {
+            is_float |= c == '.';
+            end = i + c.len_utf8();
+            chars.next();
+        } else {
+            break;
+        }
+    }
+    let number = &input[pos..end];
+
+    // a unit suffix makes this a duration
+    let mut unit = String::new();
+    while let Some(&(_, c)) = chars.peek() {
+        if c.is_alphabetic() {
+            unit.push(c);
+            chars.next();
+        } else {
+            break;
+        }
+    }
+
+    if !unit.is_empty() {
+        let text = format!("{}{}", number, unit);
+        let multiplier = match unit.as_str() {
+            "ns" => Some(1),
+            "u" | "µ" | "us" | "µs" => Some(NANOS_PER_MICRO),
+            "ms" => Some(NANOS_PER_MILLI),
+            "s" => Some(NANOS_PER_SECOND),
+            "m" => Some(NANOS_PER_MINUTE),
+            "h" => Some(NANOS_PER_HOUR),
+            "d" => Some(NANOS_PER_DAY),
+            "w" => Some(NANOS_PER_WEEK),
+            _ => None,
+        };
+
+        return multiplier
+            .and_then(|multiplier| {
+                let value: i64 = number.parse().ok()?;
+                value.checked_mul(multiplier)
+            })
+            .map(Token::Duration)
+            .context(InvalidDuration { text, pos });
+    }
+
+    let token = if is_float {
+        number.parse().ok().map(Token::Float)
+    } else {
+        number.parse().ok().map(Token::Integer)
+    };
+    token.context(InvalidNumber { text: number, pos })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<Token> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|(_, token)| token)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_select() {
+        assert_eq!(
+            tokens(r#"SELECT "usage idle",x FROM cpu WHERE host='a'"#),
+            vec![
+                Token::Ident("SELECT".into()),
+                Token::QuotedIdent("usage idle".into()),
+                Token::Comma,
+                Token::Ident("x".into()),
+                Token::Ident("FROM".into()),
+                Token::Ident("cpu".into()),
+                Token::Ident("WHERE".into()),
+                Token::Ident("host".into()),
+                Token::Eq,
+                Token::String("a".into()),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_numbers_and_durations() {
+        assert_eq!(
+            tokens("1 2.5 10s 5m 100ms 2h 1d 1w 3u"),
+            vec![
+                Token::Integer(1),
+                Token::Float(2.5),
+                Token::Duration(10 * NANOS_PER_SECOND),
+                Token::Duration(5 * NANOS_PER_MINUTE),
+                Token::Duration(100 * NANOS_PER_MILLI),
+                Token::Duration(2 * NANOS_PER_HOUR),
+                Token::Duration(NANOS_PER_DAY),
+                Token::Duration(NANOS_PER_WEEK),
+                Token::Duration(3 * NANOS_PER_MICRO),
+                Token::Eof,
+            ]
+        );
+
+        assert_eq!(
+            tokenize("10x").unwrap_err().to_string(),
+            "Invalid duration '10x' at position 0"
+        );
+    }
+
+    #[test]
+    fn tokenize_operators() {
+        assert_eq!(
+            tokens("a != b <> c <= d >= e =~ /x\\/y/ !~ /z/ a / 2"),
+            vec![
+                Token::Ident("a".into()),
+                Token::NotEq,
+                Token::Ident("b".into()),
+                Token::NotEq,
+                Token::Ident("c".into()),
+                Token::LtEq,
+                Token::Ident("d".into()),
+                Token::GtEq,
+                Token::Ident("e".into()),
+                Token::EqRegex,
+                Token::Regex("x/y".into()),
+                Token::NotEqRegex,
+                Token::Regex("z".into()),
+                Token::Ident("a".into()),
+                Token::Slash,
+                Token::Integer(2),
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn tokenize_bound_parameters() {
+        assert_eq!(
+            tokens("host = $host_name"),
+            vec![
+                Token::Ident("host".into()),
+                Token::Eq,
+                Token::BoundParameter("host_name".into()),
+                Token::Eof,
+            ]
+        );
+        assert_eq!(
+            tokenize("host = $").unwrap_err().to_string(),
+            "Unexpected character '$' at position 7"
+        );
+    }
+
+    #[test]
+    fn tokenize_errors() {
+        assert_eq!(
+            tokenize("'abc").unwrap_err().to_string(),
+            "Unterminated string starting at position 0"
+        );
+        assert_eq!(
+            tokenize("a # b").unwrap_err().to_string(),
+            "Unexpected character '#' at position 2"
+        );
+    }
+}
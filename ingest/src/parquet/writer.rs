@@ -9,9 +9,14 @@ use arrow_deps::parquet::{
     },
     schema::types::{ColumnPath, Type},
 };
+use data_types::{
+    partition_metadata::ColumnEncoding,
+    table_schema::{ColumnDefinition, DataType},
+};
 use parquet::file::writer::ParquetWriter;
 use snafu::{OptionExt, ResultExt, Snafu};
 use std::{
+    collections::BTreeMap,
     fmt,
     io::{Seek, Write},
     rc::Rc,
@@ -139,7 +144,20 @@ where
         compression_level: CompressionLevel,
         writer: W,
     ) -> Result<Self, Error> {
-        let writer_props = create_writer_props(&schema, compression_level);
+        Self::with_encodings(schema, compression_level, &BTreeMap::new(), writer)
+    }
+
+    /// Create a new TableWriter as `new` does, which encodes each
+    /// column named in `encodings` as specified there instead of as
+    /// `compression_level` would. Encodings which can't be used for
+    /// the type of their column are ignored.
+    pub fn with_encodings(
+        schema: &data_types::table_schema::Schema,
+        compression_level: CompressionLevel,
+        encodings: &BTreeMap<String, ColumnEncoding>,
+        writer: W,
+    ) -> Result<Self, Error> {
+        let writer_props = create_writer_props(&schema, compression_level, encodings);
         let parquet_schema = convert_to_parquet_schema(&schema)?;
 
         let file_writer = SerializedFileWriter::new(writer, parquet_schema.clone(), writer_props)
@@ -375,11 +393,67 @@ fn set_integer_encoding(
     }
 }
 
+/// Returns the encoding of each column of `schema` written by an
+/// `IOxParquetTableWriter` created with `compression_level` and
+/// `encodings`
+pub fn column_encodings(
+    schema: &data_types::table_schema::Schema,
+    compression_level: CompressionLevel,
+    encodings: &BTreeMap<String, ColumnEncoding>,
+) -> BTreeMap<String, ColumnEncoding> {
+    schema
+        .get_col_defs()
+        .into_iter()
+        .map(|col_def| {
+            let encoding = match encodings.get(&col_def.name) {
+                Some(&encoding) if supports_encoding(col_def.data_type, encoding) => encoding,
+                _ => default_encoding(schema, &col_def, compression_level),
+            };
+            (col_def.name, encoding)
+        })
+        .collect()
+}
+
+/// The encoding `create_writer_props` sets up for a column, unless
+/// it is overridden
+fn default_encoding(
+    schema: &data_types::table_schema::Schema,
+    col_def: &ColumnDefinition,
+    compression_level: CompressionLevel,
+) -> ColumnEncoding {
+    match col_def.data_type {
+        DataType::Boolean => ColumnEncoding::Rle,
+        DataType::Float => ColumnEncoding::Plain,
+        DataType::String if schema.is_tag(col_def) => ColumnEncoding::Dictionary,
+        DataType::String => ColumnEncoding::DeltaLengthByteArray,
+        DataType::Integer | DataType::Timestamp => match compression_level {
+            CompressionLevel::Maximum => ColumnEncoding::DeltaBinaryPacked,
+            CompressionLevel::Compatibility => ColumnEncoding::Dictionary,
+        },
+    }
+}
+
+/// Whether the parquet writer can encode columns of `data_type` with
+/// `encoding`
+fn supports_encoding(data_type: DataType, encoding: ColumnEncoding) -> bool {
+    match encoding {
+        ColumnEncoding::Plain => true,
+        ColumnEncoding::Dictionary => data_type != DataType::Boolean,
+        ColumnEncoding::Rle => data_type == DataType::Boolean,
+        ColumnEncoding::DeltaBinaryPacked => {
+            matches!(data_type, DataType::Integer | DataType::Timestamp)
+        }
+        ColumnEncoding::DeltaLengthByteArray => data_type == DataType::String,
+    }
+}
+
 /// Create the parquet writer properties (which defines the encoding
-/// and compression for each column) for a given schema.
+/// and compression for each column) for a given schema, encoding the
+/// columns named in `encodings` as specified there.
 fn create_writer_props(
     schema: &data_types::table_schema::Schema,
     compression_level: CompressionLevel,
+    encodings: &BTreeMap<String, ColumnEncoding>,
 ) -> Rc<WriterProperties> {
     let mut builder = WriterProperties::builder();
 
@@ -446,6 +520,44 @@ fn create_writer_props(
         };
     }
 
+    // Explicit encodings replace the defaults set up above
+    for col_def in schema.get_col_defs() {
+        let encoding = match encodings.get(&col_def.name) {
+            Some(&encoding) if supports_encoding(col_def.data_type, encoding) => encoding,
+            Some(&encoding) => {
+                debug!(
+                    "Ignoring encoding {:?} of {:?} col {}",
+                    encoding, col_def.data_type, col_def.name
+                );
+                continue;
+            }
+            None => continue,
+        };
+
+        debug!(
+            "Setting encoding of {:?} col {} to {:?}",
+            col_def.data_type, col_def.name, encoding
+        );
+        let col_path = ColumnPath::from(col_def.name.clone());
+        builder = match encoding {
+            // the encoding already set up is used for the values that
+            // don't fit in the dictionary
+            ColumnEncoding::Dictionary => builder.set_column_dictionary_enabled(col_path, true),
+            ColumnEncoding::Plain => builder
+                .set_column_encoding(col_path.clone(), Encoding::PLAIN)
+                .set_column_dictionary_enabled(col_path, false),
+            ColumnEncoding::Rle => builder
+                .set_column_encoding(col_path.clone(), Encoding::RLE)
+                .set_column_dictionary_enabled(col_path, false),
+            ColumnEncoding::DeltaBinaryPacked => builder
+                .set_column_encoding(col_path.clone(), Encoding::DELTA_BINARY_PACKED)
+                .set_column_dictionary_enabled(col_path, false),
+            ColumnEncoding::DeltaLengthByteArray => builder
+                .set_column_encoding(col_path.clone(), Encoding::DELTA_LENGTH_BYTE_ARRAY)
+                .set_column_dictionary_enabled(col_path, false),
+        };
+    }
+
     // Even though the 'set_statistics_enabled()' method is called here, the resulting
     // parquet file does not appear to have statistics enabled.
     //
@@ -531,7 +643,7 @@ mod tests {
 
     fn do_test_create_writer_props(compression_level: CompressionLevel) {
         let schema = make_test_schema();
-        let writer_props = create_writer_props(&schema, compression_level);
+        let writer_props = create_writer_props(&schema, compression_level, &BTreeMap::new());
 
         let tag1_colpath = ColumnPath::from("tag1");
         assert_eq!(writer_props.encoding(&tag1_colpath), None);
@@ -636,6 +748,57 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_create_writer_props_with_encodings() {
+        let schema = make_test_schema();
+        let encodings: BTreeMap<_, _> = vec![
+            ("tag1".to_string(), ColumnEncoding::Plain),
+            ("int_field".to_string(), ColumnEncoding::DeltaBinaryPacked),
+            ("string_field".to_string(), ColumnEncoding::Dictionary),
+            // can't be used for floats
+            ("float_field".to_string(), ColumnEncoding::Rle),
+        ]
+        .into_iter()
+        .collect();
+        let writer_props =
+            create_writer_props(&schema, CompressionLevel::Compatibility, &encodings);
+
+        let tag1_colpath = ColumnPath::from("tag1");
+        assert_eq!(writer_props.encoding(&tag1_colpath), Some(Encoding::PLAIN));
+        assert_eq!(writer_props.dictionary_enabled(&tag1_colpath), false);
+
+        let int_field_colpath = ColumnPath::from("int_field");
+        assert_eq!(
+            writer_props.encoding(&int_field_colpath),
+            Some(Encoding::DELTA_BINARY_PACKED)
+        );
+        assert_eq!(writer_props.dictionary_enabled(&int_field_colpath), false);
+
+        let string_field_colpath = ColumnPath::from("string_field");
+        assert_eq!(writer_props.dictionary_enabled(&string_field_colpath), true);
+
+        let float_field_colpath = ColumnPath::from("float_field");
+        assert_eq!(
+            writer_props.encoding(&float_field_colpath),
+            Some(Encoding::PLAIN)
+        );
+
+        let expected: BTreeMap<_, _> = vec![
+            ("tag1".to_string(), ColumnEncoding::Plain),
+            ("string_field".to_string(), ColumnEncoding::Dictionary),
+            ("float_field".to_string(), ColumnEncoding::Plain),
+            ("int_field".to_string(), ColumnEncoding::DeltaBinaryPacked),
+            ("bool_field".to_string(), ColumnEncoding::Rle),
+            ("time".to_string(), ColumnEncoding::Dictionary),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(
+            column_encodings(&schema, CompressionLevel::Compatibility, &encodings),
+            expected
+        );
+    }
+
     #[test]
     fn compression_level() {
         assert_eq!(
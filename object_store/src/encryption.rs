@@ -0,0 +1,620 @@
+//! Envelope encryption of objects before they are written to an underlying
+//! object store.
+//!
+//! Each object is encrypted with a freshly generated 256 bit data key using
+//! AES-GCM. The data key is then "wrapped" (encrypted) by a [`KeyProvider`]
+//! and stored, along with the id of the key that wrapped it, in a small
+//! header in front of the ciphertext. Reading an object reverses the process.
+//!
+//! The layout of an encrypted object is:
+//!
+//! ```text
+//! | magic (4) | version (1) | key id len (2) | key id | wrapped key len (4) | wrapped key | nonce (12) | ciphertext |
+//! ```
+//!
+//! Everything before the nonce is passed to AES-GCM as associated data, so
+//! changing any field of the header makes the object fail to decrypt.
+//!
+//! Two key providers are included: [`StaticKeyProvider`], which wraps data
+//! keys with a fixed master key, and [`AwsKmsKeyProvider`], which delegates
+//! wrapping to the AWS Key Management Service.
+
+use aes_gcm::{
+    aead::{generic_array::GenericArray, Aead, NewAead, Payload},
+    Aes256Gcm,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{stream::BoxStream, FutureExt, Stream, StreamExt, TryStreamExt};
+use rand::RngCore;
+use rusoto_kms::Kms;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::{convert::TryInto, fmt, io, sync::Arc};
+
+use crate::ObjectStore;
+
+/// Marks the start of every encrypted object
+const MAGIC: &[u8; 4] = b"IOXE";
+/// Version of the envelope layout
+const VERSION: u8 = 1;
+/// Length in bytes of the AES-256 keys used to encrypt data
+const KEY_LEN: usize = 32;
+/// Length in bytes of the AES-GCM nonce
+const NONCE_LEN: usize = 12;
+
+/// Errors encrypting or decrypting objects
+#[derive(Debug, Snafu)]
+pub enum Error {
+    /// A key of the wrong length was supplied
+    #[snafu(display("Invalid key length: expected {} bytes, got {}", expected, actual))]
+    InvalidKeyLength {
+        /// The required key length
+        expected: usize,
+        /// The length of the key that was supplied
+        actual: usize,
+    },
+
+    /// The object was wrapped with a key this provider does not know about
+    #[snafu(display("Unknown encryption key id: {}", key_id))]
+    UnknownKeyId {
+        /// The id recorded in the object header
+        key_id: String,
+    },
+
+    /// The payload could not be encrypted
+    #[snafu(display("Error encrypting data"))]
+    EncryptingData,
+
+    /// The payload could not be decrypted, most likely because the wrong key
+    /// was used or the data has been tampered with
+    #[snafu(display("Error decrypting data: wrong key or corrupted data"))]
+    DecryptingData,
+
+    /// The object does not start with a valid encryption header
+    #[snafu(display("Invalid encrypted object: {}", reason))]
+    InvalidEnvelope {
+        /// What was wrong with the header
+        reason: &'static str,
+    },
+
+    /// AWS KMS failed to wrap a data key
+    #[snafu(display("Error wrapping data key with AWS KMS: {}", source))]
+    KmsEncrypt {
+        /// The underlying KMS error
+        source: rusoto_core::RusotoError<rusoto_kms::EncryptError>,
+    },
+
+    /// AWS KMS failed to unwrap a data key
+    #[snafu(display("Error unwrapping data key with AWS KMS: {}", source))]
+    KmsDecrypt {
+        /// The underlying KMS error
+        source: rusoto_core::RusotoError<rusoto_kms::DecryptError>,
+    },
+
+    /// AWS KMS returned a response without the requested key
+    #[snafu(display("AWS KMS response did not contain a key"))]
+    KmsMissingKey,
+}
+
+/// A specialized `Result` for encryption errors
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Source of the master keys used to wrap and unwrap per-object data keys.
+#[async_trait]
+pub trait KeyProvider: fmt::Debug + Send + Sync {
+    /// The id of the key used to wrap new data keys. It is stored with each
+    /// object so the matching key can be found when the object is read.
+    fn key_id(&self) -> &str;
+
+    /// Encrypt `data_key` with the current master key.
+    async fn wrap_key(&self, data_key: &[u8]) -> Result<Vec<u8>>;
+
+    /// Decrypt `wrapped_key`, which was produced by the master key `key_id`.
+    async fn unwrap_key(&self, key_id: &str, wrapped_key: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Wraps data keys with a fixed 256 bit master key, for example one supplied
+/// through configuration.
+pub struct StaticKeyProvider {
+    key_id: String,
+    cipher: Aes256Gcm,
+}
+
+impl fmt::Debug for StaticKeyProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // never print the key material
+        f.debug_struct("StaticKeyProvider")
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
+
+impl StaticKeyProvider {
+    /// Create a provider from a 32 byte master key identified by `key_id`.
+    pub fn new(key_id: impl Into<String>, master_key: &[u8]) -> Result<Self> {
+        ensure!(
+            master_key.len() == KEY_LEN,
+            InvalidKeyLength {
+                expected: KEY_LEN,
+                actual: master_key.len(),
+            }
+        );
+
+        Ok(Self {
+            key_id: key_id.into(),
+            cipher: Aes256Gcm::new(GenericArray::from_slice(master_key)),
+        })
+    }
+}
+
+#[async_trait]
+impl KeyProvider for StaticKeyProvider {
+    fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    async fn wrap_key(&self, data_key: &[u8]) -> Result<Vec<u8>> {
+        let nonce = random_bytes(NONCE_LEN);
+        let mut wrapped = nonce.clone();
+        wrapped.extend(
+            self.cipher
+                .encrypt(GenericArray::from_slice(&nonce), data_key)
+                .ok()
+                .context(EncryptingData)?,
+        );
+        Ok(wrapped)
+    }
+
+    async fn unwrap_key(&self, key_id: &str, wrapped_key: &[u8]) -> Result<Vec<u8>> {
+        ensure!(
+            key_id == self.key_id,
+            UnknownKeyId {
+                key_id: key_id.to_string(),
+            }
+        );
+        ensure!(
+            wrapped_key.len() > NONCE_LEN,
+            InvalidEnvelope {
+                reason: "wrapped key too short",
+            }
+        );
+
+        let (nonce, ciphertext) = wrapped_key.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(GenericArray::from_slice(nonce), ciphertext)
+            .ok()
+            .context(DecryptingData)
+    }
+}
+
+/// Wraps data keys using a customer master key held in [AWS
+/// KMS](https://aws.amazon.com/kms/).
+pub struct AwsKmsKeyProvider {
+    client: rusoto_kms::KmsClient,
+    key_id: String,
+}
+
+impl fmt::Debug for AwsKmsKeyProvider {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AwsKmsKeyProvider")
+            .field("client", &"rusoto_kms::KmsClient")
+            .field("key_id", &self.key_id)
+            .finish()
+    }
+}
+
+impl AwsKmsKeyProvider {
+    /// Configure a provider using the KMS key `key_id` (a key id, ARN or
+    /// alias) in the given region.
+    pub fn new(region: rusoto_core::Region, key_id: impl Into<String>) -> Self {
+        Self {
+            client: rusoto_kms::KmsClient::new(region),
+            key_id: key_id.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl KeyProvider for AwsKmsKeyProvider {
+    fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    async fn wrap_key(&self, data_key: &[u8]) -> Result<Vec<u8>> {
+        let request = rusoto_kms::EncryptRequest {
+            key_id: self.key_id.clone(),
+            plaintext: Bytes::copy_from_slice(data_key),
+            ..Default::default()
+        };
+
+        let response = self.client.encrypt(request).await.context(KmsEncrypt)?;
+        Ok(response.ciphertext_blob.context(KmsMissingKey)?.to_vec())
+    }
+
+    async fn unwrap_key(&self, key_id: &str, wrapped_key: &[u8]) -> Result<Vec<u8>> {
+        let request = rusoto_kms::DecryptRequest {
+            key_id: Some(key_id.to_string()),
+            ciphertext_blob: Bytes::copy_from_slice(wrapped_key),
+            ..Default::default()
+        };
+
+        let response = self.client.decrypt(request).await.context(KmsDecrypt)?;
+        Ok(response.plaintext.context(KmsMissingKey)?.to_vec())
+    }
+}
+
+/// Encrypts the contents of every object before handing it to an inner
+/// object store, and decrypts them when they are read back. Object names and
+/// listings are not encrypted.
+#[derive(Debug)]
+pub struct Encrypted {
+    inner: Box<ObjectStore>,
+    key_provider: Arc<dyn KeyProvider>,
+}
+
+impl Encrypted {
+    /// Wrap `inner`, encrypting all data with keys from `key_provider`.
+    pub fn new(inner: ObjectStore, key_provider: Arc<dyn KeyProvider>) -> Self {
+        Self {
+            inner: Box::new(inner),
+            key_provider,
+        }
+    }
+
+    /// Encrypt the provided bytes and save them to the specified location.
+    pub(crate) async fn put<S>(&self, location: &str, bytes: S, length: usize) -> crate::Result<()>
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
+    {
+        let content = bytes
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await
+            .context(crate::UnableToReadDataToEncrypt)?;
+
+        ensure!(
+            content.len() == length,
+            crate::DataDoesNotMatchLength {
+                actual: content.len(),
+                expected: length,
+            }
+        );
+
+        let encrypted = Bytes::from(
+            encrypt(self.key_provider.as_ref(), &content)
+                .await
+                .context(crate::UnableToEncryptData)?,
+        );
+        let length = encrypted.len();
+
+        // the inner store may itself be encrypted, so box to allow recursion
+        self.inner
+            .put(
+                location,
+                futures::stream::once(async move { Ok(encrypted) }),
+                length,
+            )
+            .boxed()
+            .await
+    }
+
+    /// Return the decrypted bytes that are stored at the specified location.
+    pub(crate) async fn get(
+        &self,
+        location: &str,
+    ) -> crate::Result<impl Stream<Item = crate::Result<Bytes>>> {
+        let content = self
+            .inner
+            .get(location)
+            .boxed()
+            .await?
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await?;
+
+        let data = Bytes::from(
+            decrypt(self.key_provider.as_ref(), &content)
+                .await
+                .context(crate::UnableToDecryptData)?,
+        );
+
+        Ok(futures::stream::once(async move { Ok(data) }))
+    }
+
+    /// Delete the object at the specified location.
+    pub(crate) async fn delete(&self, location: &str) -> crate::Result<()> {
+        self.inner.delete(location).boxed().await
+    }
+
+    /// List all the objects with the given prefix.
+    pub(crate) async fn list<'a>(
+        &'a self,
+        prefix: Option<&'a str>,
+    ) -> crate::Result<BoxStream<'a, crate::Result<Vec<String>>>> {
+        Ok(self.inner.list(prefix).boxed().await?.boxed())
+    }
+}
+
+/// Encrypt `plaintext` with a new data key, returning the complete envelope
+pub async fn encrypt(key_provider: &dyn KeyProvider, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let data_key = random_bytes(KEY_LEN);
+    let nonce = random_bytes(NONCE_LEN);
+
+    let key_id = key_provider.key_id().as_bytes();
+    let wrapped_key = key_provider.wrap_key(&data_key).await?;
+
+    let key_id_len: u16 = key_id.len().try_into().ok().context(InvalidEnvelope {
+        reason: "key id too long",
+    })?;
+    let wrapped_key_len: u32 = wrapped_key.len().try_into().ok().context(InvalidEnvelope {
+        reason: "wrapped key too long",
+    })?;
+
+    let mut envelope = Vec::with_capacity(
+        MAGIC.len() + 1 + 2 + key_id.len() + 4 + wrapped_key.len() + NONCE_LEN + plaintext.len(),
+    );
+    envelope.extend_from_slice(MAGIC);
+    envelope.push(VERSION);
+    envelope.extend_from_slice(&key_id_len.to_be_bytes());
+    envelope.extend_from_slice(key_id);
+    envelope.extend_from_slice(&wrapped_key_len.to_be_bytes());
+    envelope.extend_from_slice(&wrapped_key);
+
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(&data_key));
+    let ciphertext = cipher
+        .encrypt(
+            GenericArray::from_slice(&nonce),
+            Payload {
+                msg: plaintext,
+                aad: &envelope,
+            },
+        )
+        .ok()
+        .context(EncryptingData)?;
+
+    envelope.extend_from_slice(&nonce);
+    envelope.extend_from_slice(&ciphertext);
+
+    Ok(envelope)
+}
+
+/// Decrypt an envelope previously produced by [`encrypt`]
+pub async fn decrypt(key_provider: &dyn KeyProvider, envelope: &[u8]) -> Result<Vec<u8>> {
+    let mut reader = EnvelopeReader { data: envelope };
+
+    ensure!(
+        reader.take(MAGIC.len())? == MAGIC,
+        InvalidEnvelope {
+            reason: "missing header",
+        }
+    );
+    ensure!(
+        reader.take(1)? == [VERSION],
+        InvalidEnvelope {
+            reason: "unsupported version",
+        }
+    );
+
+    let key_id_len = u16::from_be_bytes(reader.take(2)?.try_into().unwrap()) as usize;
+    let key_id = std::str::from_utf8(reader.take(key_id_len)?)
+        .ok()
+        .context(InvalidEnvelope {
+            reason: "key id is not valid UTF-8",
+        })?;
+    let wrapped_key_len = u32::from_be_bytes(reader.take(4)?.try_into().unwrap()) as usize;
+    let wrapped_key = reader.take(wrapped_key_len)?;
+    let header = &envelope[..envelope.len() - reader.data.len()];
+    let nonce = reader.take(NONCE_LEN)?;
+    let ciphertext = reader.data;
+
+    let data_key = key_provider.unwrap_key(key_id, wrapped_key).await?;
+    ensure!(
+        data_key.len() == KEY_LEN,
+        InvalidKeyLength {
+            expected: KEY_LEN,
+            actual: data_key.len(),
+        }
+    );
+
+    let cipher = Aes256Gcm::new(GenericArray::from_slice(&data_key));
+    cipher
+        .decrypt(
+            GenericArray::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad: header,
+            },
+        )
+        .ok()
+        .context(DecryptingData)
+}
+
+fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes
+}
+
+/// Reads fixed length fields from the front of an envelope
+struct EnvelopeReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> EnvelopeReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(
+            self.data.len() >= len,
+            InvalidEnvelope {
+                reason: "truncated header",
+            }
+        );
+        let (field, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(field)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::InMemory;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type TestResult<T, E = TestError> = std::result::Result<T, E>;
+
+    fn static_provider(key_id: &str, key_byte: u8) -> Arc<dyn KeyProvider> {
+        Arc::new(StaticKeyProvider::new(key_id, &[key_byte; KEY_LEN]).unwrap())
+    }
+
+    #[tokio::test]
+    async fn roundtrip() -> TestResult<()> {
+        let provider = static_provider("key1", 42);
+        let plaintext = b"some data that should be kept secret";
+
+        let envelope = encrypt(provider.as_ref(), plaintext).await?;
+        assert!(envelope.starts_with(MAGIC));
+        assert!(!envelope
+            .windows(plaintext.len())
+            .any(|w| w == &plaintext[..]));
+
+        let decrypted = decrypt(provider.as_ref(), &envelope).await?;
+        assert_eq!(decrypted, plaintext);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn wrong_key() -> TestResult<()> {
+        let envelope = encrypt(static_provider("key1", 1).as_ref(), b"data").await?;
+
+        let res = decrypt(static_provider("key2", 1).as_ref(), &envelope).await;
+        assert!(
+            matches!(res, Err(Error::UnknownKeyId { .. })),
+            "was: {:?}",
+            res
+        );
+
+        let res = decrypt(static_provider("key1", 2).as_ref(), &envelope).await;
+        assert!(matches!(res, Err(Error::DecryptingData)), "was: {:?}", res);
+
+        Ok(())
+    }
+
+    /// Unwraps keys for any key id with the same master key, so that a
+    /// tampered key id still yields the data key.
+    #[derive(Debug)]
+    struct AnyKeyIdProvider(StaticKeyProvider);
+
+    #[async_trait]
+    impl KeyProvider for AnyKeyIdProvider {
+        fn key_id(&self) -> &str {
+            self.0.key_id()
+        }
+
+        async fn wrap_key(&self, data_key: &[u8]) -> Result<Vec<u8>> {
+            self.0.wrap_key(data_key).await
+        }
+
+        async fn unwrap_key(&self, _key_id: &str, wrapped_key: &[u8]) -> Result<Vec<u8>> {
+            self.0.unwrap_key(self.0.key_id(), wrapped_key).await
+        }
+    }
+
+    #[tokio::test]
+    async fn tampered_header() -> TestResult<()> {
+        let provider = AnyKeyIdProvider(StaticKeyProvider::new("key1", &[3; KEY_LEN])?);
+        let envelope = encrypt(&provider, b"data").await?;
+        assert_eq!(decrypt(&provider, &envelope).await?, b"data");
+
+        // the key id is still one the provider can unwrap the data key for,
+        // but the header is authenticated along with the data
+        let key_id_start = MAGIC.len() + 1 + 2;
+        let mut tampered = envelope.clone();
+        tampered[key_id_start..key_id_start + 4].copy_from_slice(b"key2");
+        let res = decrypt(&provider, &tampered).await;
+        assert!(matches!(res, Err(Error::DecryptingData)), "was: {:?}", res);
+
+        // no byte of the header can be changed without decryption failing
+        // (the ciphertext is the data followed by a 16 byte tag)
+        let header_len = envelope.len() - NONCE_LEN - (b"data".len() + 16);
+        for i in 0..header_len {
+            let mut tampered = envelope.clone();
+            tampered[i] ^= 1;
+            let res = decrypt(&provider, &tampered).await;
+            assert!(res.is_err(), "byte {} was: {:?}", i, res);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_envelope() {
+        let provider = static_provider("key1", 1);
+
+        let res = decrypt(provider.as_ref(), b"not encrypted").await;
+        assert!(
+            matches!(res, Err(Error::InvalidEnvelope { .. })),
+            "was: {:?}",
+            res
+        );
+
+        let res = decrypt(provider.as_ref(), b"IOXE").await;
+        assert!(
+            matches!(res, Err(Error::InvalidEnvelope { .. })),
+            "was: {:?}",
+            res
+        );
+    }
+
+    #[test]
+    fn static_key_length() {
+        let res = StaticKeyProvider::new("key1", &[0; 16]);
+        assert!(
+            matches!(
+                res,
+                Err(Error::InvalidKeyLength {
+                    expected: 32,
+                    actual: 16
+                })
+            ),
+            "was: {:?}",
+            res
+        );
+    }
+
+    #[tokio::test]
+    async fn encrypted_object_store() -> TestResult<()> {
+        let integration = ObjectStore::new_encrypted(
+            ObjectStore::new_in_memory(InMemory::new()),
+            static_provider("key1", 7),
+        );
+
+        crate::tests::put_get_delete_list(&integration).await?;
+
+        // data in the underlying store is encrypted
+        let data = Bytes::from("arbitrary data");
+        let stream_data = std::io::Result::Ok(data.clone());
+        integration
+            .put(
+                "secret",
+                futures::stream::once(async move { stream_data }),
+                data.len(),
+            )
+            .await?;
+
+        let inner = match &integration.0 {
+            crate::ObjectStoreIntegration::Encrypted(encrypted) => &encrypted.inner,
+            _ => panic!("wrong type"),
+        };
+        let raw = inner
+            .get("secret")
+            .await?
+            .map_ok(|b| bytes::BytesMut::from(&b[..]))
+            .try_concat()
+            .await?;
+        assert!(raw.starts_with(MAGIC));
+        assert_ne!(&*raw, data);
+
+        Ok(())
+    }
+}
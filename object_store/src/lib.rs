@@ -12,6 +12,9 @@
 //! This crate provides APIs for interacting with object storage services. It currently supports
 //! PUT, GET, DELETE, and list for Google Cloud Storage, Amazon S3, and in-memory storage.
 //!
+//! Any of these can optionally be wrapped so that object contents are encrypted at rest; see
+//! the [`encryption`] module.
+//!
 //! Future compatibility will include Azure Blob Storage, Minio, and Ceph.
 
 use bytes::Bytes;
@@ -20,10 +23,13 @@ use rusoto_core::ByteStream;
 use rusoto_credential::ChainProvider;
 use rusoto_s3::S3;
 use snafu::{ensure, futures::TryStreamExt as _, OptionExt, ResultExt, Snafu};
-use std::{collections::BTreeMap, fmt, io, path::PathBuf};
+use std::{collections::BTreeMap, fmt, io, path::PathBuf, sync::Arc};
 use tokio::{fs, sync::RwLock};
 use tokio_util::codec::{BytesCodec, FramedRead};
 
+pub mod encryption;
+use encryption::{Encrypted, KeyProvider};
+
 /// Universal interface to multiple object store services.
 #[derive(Debug)]
 pub struct ObjectStore(pub ObjectStoreIntegration);
@@ -49,7 +55,17 @@ impl ObjectStore {
         Self(ObjectStoreIntegration::File(file))
     }
 
+    /// Configure storage that encrypts all data with keys from `key_provider`
+    /// before writing it to `inner`.
+    pub fn new_encrypted(inner: Self, key_provider: Arc<dyn KeyProvider>) -> Self {
+        Self(ObjectStoreIntegration::Encrypted(Encrypted::new(
+            inner,
+            key_provider,
+        )))
+    }
+
     /// Save the provided bytes to the specified location.
+    #[tracing::instrument(level = "debug", skip(self, bytes))]
     pub async fn put<S>(&self, location: &str, bytes: S, length: usize) -> Result<()>
     where
         S: Stream<Item = io::Result<Bytes>> + Send + Sync + 'static,
@@ -60,12 +76,14 @@ impl ObjectStore {
             GoogleCloudStorage(gcs) => gcs.put(location, bytes, length).await?,
             InMemory(in_mem) => in_mem.put(location, bytes, length).await?,
             File(file) => file.put(location, bytes, length).await?,
+            Encrypted(encrypted) => encrypted.put(location, bytes, length).await?,
         }
 
         Ok(())
     }
 
     /// Return the bytes that are stored at the specified location.
+    #[tracing::instrument(level = "debug", skip(self))]
     pub async fn get(&self, location: &str) -> Result<impl Stream<Item = Result<Bytes>>> {
         use ObjectStoreIntegration::*;
         Ok(match &self.0 {
@@ -73,11 +91,13 @@ impl ObjectStore {
             GoogleCloudStorage(gcs) => gcs.get(location).await?.boxed(),
             InMemory(in_mem) => in_mem.get(location).await?.boxed(),
             File(file) => file.get(location).await?.boxed(),
+            Encrypted(encrypted) => encrypted.get(location).await?.map_err(|e| e.0).boxed(),
         }
         .err_into())
     }
 
     /// Delete the object at the specified location.
+    #[tracing::instrument(level = "debug", skip(self))]
     pub async fn delete(&self, location: &str) -> Result<()> {
         use ObjectStoreIntegration::*;
         match &self.0 {
@@ -85,6 +105,7 @@ impl ObjectStore {
             GoogleCloudStorage(gcs) => gcs.delete(location).await?,
             InMemory(in_mem) => in_mem.delete(location).await?,
             File(file) => file.delete(location).await?,
+            Encrypted(encrypted) => encrypted.delete(location).await?,
         }
 
         Ok(())
@@ -101,6 +122,7 @@ impl ObjectStore {
             GoogleCloudStorage(gcs) => gcs.list(prefix).await?.boxed(),
             InMemory(in_mem) => in_mem.list(prefix).await?.boxed(),
             File(file) => file.list(prefix).await?.boxed(),
+            Encrypted(encrypted) => encrypted.list(prefix).await?.map_err(|e| e.0).boxed(),
         }
         .err_into())
     }
@@ -117,6 +139,8 @@ pub enum ObjectStoreIntegration {
     InMemory(InMemory),
     /// Local file system storage
     File(File),
+    /// Another integration whose data is encrypted at rest
+    Encrypted(Encrypted),
 }
 
 /// Configuration for connecting to [Google Cloud Storage](https://cloud.google.com/storage/).
@@ -682,6 +706,19 @@ enum InternalError {
     },
     #[snafu(display("Unable to retrieve filename"))]
     UnableToGetFileName,
+
+    #[snafu(display("Unable to read data to encrypt: {}", source))]
+    UnableToReadDataToEncrypt {
+        source: io::Error,
+    },
+    #[snafu(display("Unable to encrypt data: {}", source))]
+    UnableToEncryptData {
+        source: encryption::Error,
+    },
+    #[snafu(display("Unable to decrypt data: {}", source))]
+    UnableToDecryptData {
+        source: encryption::Error,
+    },
 }
 
 #[cfg(test)]
@@ -714,7 +751,7 @@ mod tests {
             .await
     }
 
-    async fn put_get_delete_list(storage: &ObjectStore) -> Result<()> {
+    pub(crate) async fn put_get_delete_list(storage: &ObjectStore) -> Result<()> {
         let content_list = flatten_list_stream(storage, None).await?;
         assert!(content_list.is_empty());
 
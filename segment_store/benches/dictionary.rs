@@ -133,6 +133,37 @@ fn benchmark_select(
     group.finish();
 }
 
+// Benchmarks `=` and `!=` predicates on RLE columns with few enough distinct
+// values to be answered from the rows of each value.
+fn select_low_cardinality(c: &mut Criterion) {
+    let mut group = c.benchmark_group("encoding_rle_select_low_cardinality");
+    let cardinality = dictionary::LOW_CARDINALITY_LIMIT;
+    for &num_rows in &ROWS {
+        // short runs of each value, so there are many run-lengths to scan
+        let mut encoding = dictionary::RLE::with_dictionary(
+            (0..cardinality).map(|i| format!("value-{}", i)).collect(),
+        );
+        for i in 0..num_rows / 10 {
+            encoding.push_additional(Some(format!("value-{}", i % cardinality)), 10);
+        }
+        let encoding = dictionary::Encoding::RLE(encoding);
+
+        group.throughput(Throughput::Elements(num_rows as u64));
+        for op in &[Operator::Equal, Operator::NotEqual] {
+            group.bench_function(
+                BenchmarkId::from_parameter(format!("rows_{:?}/op_{:?}", num_rows, op)),
+                |b| {
+                    b.iter(|| {
+                        let row_ids = encoding.row_ids_filter("value-0", op, RowIDs::new_bitmap());
+                        assert!(!row_ids.is_empty());
+                    });
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
 fn generate_column(rows: usize, rows_per_value: usize, rng: &mut ThreadRng) -> Vec<String> {
     let mut col = Vec::with_capacity(rows);
     let distinct_values = rows / rows_per_value;
@@ -148,5 +179,5 @@ fn generate_column(rows: usize, rows_per_value: usize, rng: &mut ThreadRng) -> V
     col
 }
 
-criterion_group!(benches, select,);
+criterion_group!(benches, select, select_low_cardinality);
 criterion_main!(benches);
@@ -7,6 +7,7 @@ use std::collections::BTreeSet;
 use std::convert::TryFrom;
 
 use croaring::Bitmap;
+use data_types::pool::Reusable;
 
 use arrow_deps::arrow::array::{
     Float32Array, Float64Array, Int16Array, Int32Array, Int64Array, Int8Array, StringArray,
@@ -157,6 +158,30 @@ impl Column {
         }
     }
 
+    /// All values in the column, if they can be materialised without the row
+    /// ids of every row. Otherwise, the values must be read with `values`.
+    pub fn all_values(&self) -> Option<Values> {
+        match &self {
+            Column::Float(_, data) => Some(data.all_values()),
+            Column::Integer(_, data) => Some(data.all_values()),
+            Column::Unsigned(_, data) => Some(data.all_values()),
+            // dictionary encodings decode the value of each row id.
+            Column::String(_, _) => None,
+            Column::Bool => todo!(),
+            Column::ByteArray(_, _) => todo!(),
+        }
+    }
+
+    /// The values in the dictionary of a string column, which include every
+    /// non-null value in the column.
+    pub fn dictionary(&self) -> Vec<&String> {
+        match &self {
+            Column::String(_, data) => data.dictionary(),
+            Column::ByteArray(_, _) => todo!(),
+            _ => unimplemented!("dictionary is not implemented for this type"),
+        }
+    }
+
     // The distinct set of values found at the logical row ids.
     pub fn distinct_values(&self, row_ids: &[u32]) -> ValueSet<'_> {
         assert!(
@@ -203,6 +228,15 @@ impl Column {
         }
     }
 
+    /// The decoded value of an encoded id returned by `encoded_values`, or
+    /// `None` if the id encodes NULL.
+    pub fn decode_id(&self, encoded_id: u32) -> Option<String> {
+        match &self {
+            Self::String(_, data) => data.decode_id(encoded_id),
+            _ => unimplemented!("decoding ids on other column types not supported"),
+        }
+    }
+
     /// All encoded values in the column.
     pub fn all_encoded_values(&self, dst: EncodedValues) -> EncodedValues {
         match &self {
@@ -230,6 +264,33 @@ impl Column {
         value: &Value<'_>,
         dst: RowIDs,
     ) -> RowIDsOption {
+        // A predicate on NULL is an `IS NULL` (==) or `IS NOT NULL` (!=)
+        // check, which can't use the range of values in the meta-data.
+        if let Value::Null = value {
+            return self.row_ids_filter_null(op, dst);
+        }
+
+        // Only strings have a case, so other columns compare for equality.
+        let op = match (op, self) {
+            (cmp::Operator::EqualIgnoreCase, Column::String(_, _)) => op,
+            (cmp::Operator::EqualIgnoreCase, _) => &cmp::Operator::Equal,
+            _ => op,
+        };
+
+        // Numeric columns are compared with values of their own type.
+        let coerced;
+        let (op, value) = match self.coerce_scalar(op, value) {
+            ScalarCoercion::Unchanged => (op, value),
+            ScalarCoercion::Coerced(coerced_op, scalar) => {
+                coerced = (coerced_op, Value::Scalar(scalar));
+                (&coerced.0, &coerced.1)
+            }
+            ScalarCoercion::NotNull => {
+                return self.row_ids_filter_null(&cmp::Operator::NotEqual, dst)
+            }
+            ScalarCoercion::NoRows => return RowIDsOption::None(dst),
+        };
+
         // If we can get an answer using only the meta-data on the column then
         // return that answer.
         match self.evaluate_predicate_on_meta(&op, &value) {
@@ -254,6 +315,91 @@ impl Column {
         RowIDsOption::Some(row_ids)
     }
 
+    // Rewrites a comparison of a numeric column with a scalar of another
+    // numeric type into one with a scalar of the column's type, e.g., a
+    // predicate `> 2.5` on an integer column is the same as `> 2`.
+    fn coerce_scalar(&self, op: &cmp::Operator, value: &Value<'_>) -> ScalarCoercion {
+        let scalar = match value {
+            Value::Scalar(scalar) => scalar,
+            _ => return ScalarCoercion::Unchanged,
+        };
+
+        match &self {
+            Column::Float(_, _) if scalar.is_float() => ScalarCoercion::Unchanged,
+            Column::Float(_, _) => ScalarCoercion::Coerced(*op, Scalar::F64(scalar.to_f64())),
+            Column::Integer(_, _) if !scalar.is_float() && scalar.try_as_i64().is_some() => {
+                ScalarCoercion::Unchanged
+            }
+            // `i64::MIN` and `i64::MAX + 1` are exact as floats.
+            Column::Integer(_, _) => ScalarCoercion::from_float(
+                op,
+                scalar.to_f64(),
+                (i64::MIN as f64, -(i64::MIN as f64)),
+                |v| Scalar::I64(v as i64),
+            ),
+            Column::Unsigned(_, _) if !scalar.is_float() && scalar.try_as_u64().is_some() => {
+                ScalarCoercion::Unchanged
+            }
+            // `u64::MAX` rounds to `u64::MAX + 1` as a float.
+            Column::Unsigned(_, _) => {
+                ScalarCoercion::from_float(op, scalar.to_f64(), (0.0, u64::MAX as f64), |v| {
+                    Scalar::U64(v as u64)
+                })
+            }
+            _ => ScalarCoercion::Unchanged,
+        }
+    }
+
+    /// Determine the set of row ids that are NULL, for the `==` operator,
+    /// or not NULL, for the `!=` operator. Any other comparison with NULL
+    /// matches no rows.
+    fn row_ids_filter_null(&self, op: &cmp::Operator, dst: RowIDs) -> RowIDsOption {
+        let is_null = match op {
+            cmp::Operator::Equal => true,
+            cmp::Operator::NotEqual => false,
+            _ => return RowIDsOption::None(dst),
+        };
+
+        let contains_null = match &self {
+            Column::String(_, data) => data.contains_null(),
+            Column::Float(_, data) => data.contains_null(),
+            Column::Integer(_, data) => data.contains_null(),
+            Column::Unsigned(_, data) => data.contains_null(),
+            Column::ByteArray(_, data) => data.contains_null(),
+            // There is no boolean encoding yet, so nothing to be NULL.
+            Column::Bool => false,
+        };
+
+        // Without any NULL values every row is not NULL.
+        if !contains_null {
+            return if is_null {
+                RowIDsOption::None(dst)
+            } else {
+                RowIDsOption::All(dst)
+            };
+        }
+
+        // Check the column for all rows that are (not) NULL.
+        let row_ids = match &self {
+            Column::String(_, data) | Column::ByteArray(_, data) if is_null => {
+                data.row_ids_null(dst)
+            }
+            Column::String(_, data) | Column::ByteArray(_, data) => data.row_ids_not_null(dst),
+            Column::Float(_, data) if is_null => data.row_ids_null(dst),
+            Column::Float(_, data) => data.row_ids_not_null(dst),
+            Column::Integer(_, data) | Column::Unsigned(_, data) if is_null => {
+                data.row_ids_null(dst)
+            }
+            Column::Integer(_, data) | Column::Unsigned(_, data) => data.row_ids_not_null(dst),
+            Column::Bool => unreachable!("boolean columns contain no NULL values"),
+        };
+
+        if row_ids.is_empty() {
+            return RowIDsOption::None(row_ids);
+        }
+        RowIDsOption::Some(row_ids)
+    }
+
     /// Determine the set of row ids that satisfy both of the predicates.
     ///
     /// Note: this method is a special case for common range-based predicates
@@ -346,6 +492,10 @@ impl Column {
                     return PredicateMatch::All; // all rows are going to match.
                 }
             }
+
+            // The metadata range is ordered by case so can't show whether
+            // any value is equal when case is ignored.
+            cmp::Operator::EqualIgnoreCase => {}
         }
 
         if self.predicate_matches_no_values(&op, &value) {
@@ -606,6 +756,8 @@ impl<T: PartialOrd + std::fmt::Debug> MetaData<T> {
                 cmp::Operator::LT => range.1 < u,
                 // all values in column <= v
                 cmp::Operator::LTE => range.1 <= u,
+                // the range can't show all values are equal ignoring case
+                cmp::Operator::EqualIgnoreCase => false,
             },
             None => false, // only null values in column.
         }
@@ -632,6 +784,8 @@ impl<T: PartialOrd + std::fmt::Debug> MetaData<T> {
                 cmp::Operator::LT => range.0 >= u,
                 // min value in column is `> v` so no values can be `<= v`
                 cmp::Operator::LTE => range.0 > u,
+                // values outside the range may be equal ignoring case
+                cmp::Operator::EqualIgnoreCase => false,
             },
             None => true, // only null values in column so no values satisfy `v`
         }
@@ -678,6 +832,14 @@ impl StringEncoding {
         }
     }
 
+    /// Returns the values in the dictionary of the column.
+    pub fn dictionary(&self) -> Vec<&String> {
+        match &self {
+            Self::RLEDictionary(c) => c.dictionary(),
+            Self::Dictionary(c) => c.dictionary(),
+        }
+    }
+
     /// Returns the distinct set of values found at the provided row ids.
     ///
     /// TODO(edd): perf - pooling of destination sets.
@@ -696,6 +858,22 @@ impl StringEncoding {
         }
     }
 
+    /// Returns the row ids of the rows that are NULL.
+    pub fn row_ids_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::RLEDictionary(c) => c.row_ids_null(dst),
+            Self::Dictionary(c) => c.row_ids_null(dst),
+        }
+    }
+
+    /// Returns the row ids of the rows that are not NULL.
+    pub fn row_ids_not_null(&self, dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::RLEDictionary(c) => c.row_ids_not_null(dst),
+            Self::Dictionary(c) => c.row_ids_not_null(dst),
+        }
+    }
+
     /// The lexicographic minimum non-null value at the rows specified, or the
     /// NULL value if the column only contains NULL values at the provided row
     /// ids.
@@ -748,7 +926,7 @@ impl StringEncoding {
         let mut dictionary = BTreeSet::new();
 
         for i in 0..arr.len() {
-            if !arr.is_null(i) {
+            if !arr.is_null(i) && !dictionary.contains(arr.value(i)) {
                 dictionary.insert(arr.value(i).to_string());
             }
         }
@@ -760,38 +938,7 @@ impl StringEncoding {
                 dictionary::Encoding::RLE(dictionary::RLE::with_dictionary(dictionary))
             };
 
-        let mut prev = if !arr.is_null(0) {
-            Some(arr.value(0))
-        } else {
-            None
-        };
-
-        let mut count = 1;
-        for i in 1..arr.len() {
-            let next = if arr.is_null(i) {
-                None
-            } else {
-                Some(arr.value(i))
-            };
-
-            if prev == next {
-                count += 1;
-                continue;
-            }
-
-            match prev {
-                Some(x) => data.push_additional(Some(x.to_string()), count),
-                None => data.push_additional(None, count),
-            }
-            prev = next;
-            count = 1;
-        }
-
-        // Add final batch to column if any
-        match prev {
-            Some(x) => data.push_additional(Some(x.to_string()), count),
-            None => data.push_additional(None, count),
-        };
+        data.push_arrow(&arr);
 
         // TODO(edd): size of RLE column.
         let dictionary = data.dictionary();
@@ -837,6 +984,14 @@ impl StringEncoding {
         }
     }
 
+    /// The decoded value of the provided encoded id, or `None` for NULL.
+    pub fn decode_id(&self, encoded_id: u32) -> Option<String> {
+        match &self {
+            Self::RLEDictionary(c) => c.decode_id(encoded_id),
+            Self::Dictionary(c) => c.decode_id(encoded_id),
+        }
+    }
+
     fn from_opt_strs(arr: &[Option<&str>]) -> Self {
         //
         // TODO(edd): potentially switch on things like cardinality in the input
@@ -997,6 +1152,39 @@ pub enum IntegerEncoding {
 }
 
 impl IntegerEncoding {
+    /// The total number of rows in the column.
+    pub fn num_rows(&self) -> u32 {
+        match &self {
+            Self::I64I64(c) => c.num_rows(),
+            Self::I64I32(c) => c.num_rows(),
+            Self::I64U32(c) => c.num_rows(),
+            Self::I64I16(c) => c.num_rows(),
+            Self::I64U16(c) => c.num_rows(),
+            Self::I64I8(c) => c.num_rows(),
+            Self::I64U8(c) => c.num_rows(),
+            Self::I32I32(c) => c.num_rows(),
+            Self::I32I16(c) => c.num_rows(),
+            Self::I32U16(c) => c.num_rows(),
+            Self::I32I8(c) => c.num_rows(),
+            Self::I32U8(c) => c.num_rows(),
+            Self::I16I16(c) => c.num_rows(),
+            Self::I16I8(c) => c.num_rows(),
+            Self::I16U8(c) => c.num_rows(),
+            Self::I8I8(c) => c.num_rows(),
+            Self::U64U64(c) => c.num_rows(),
+            Self::U64U32(c) => c.num_rows(),
+            Self::U64U16(c) => c.num_rows(),
+            Self::U64U8(c) => c.num_rows(),
+            Self::U32U32(c) => c.num_rows(),
+            Self::U32U16(c) => c.num_rows(),
+            Self::U32U8(c) => c.num_rows(),
+            Self::U16U16(c) => c.num_rows(),
+            Self::U16U8(c) => c.num_rows(),
+            Self::U8U8(c) => c.num_rows(),
+            Self::I64I64N(c) => c.num_rows(),
+        }
+    }
+
     /// Determines if the column contains a NULL value.
     pub fn contains_null(&self) -> bool {
         if let Self::I64I64N(c) = &self {
@@ -1005,6 +1193,30 @@ impl IntegerEncoding {
         false
     }
 
+    /// Returns the row ids of the rows that are NULL.
+    pub fn row_ids_null(&self, mut dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::I64I64N(c) => c.row_ids_null(dst),
+            // Only the nullable encodings can contain NULL values.
+            _ => {
+                dst.clear();
+                dst
+            }
+        }
+    }
+
+    /// Returns the row ids of the rows that are not NULL.
+    pub fn row_ids_not_null(&self, mut dst: RowIDs) -> RowIDs {
+        match &self {
+            Self::I64I64N(c) => c.row_ids_not_null(dst),
+            _ => {
+                dst.clear();
+                dst.add_range(0, self.num_rows());
+                dst
+            }
+        }
+    }
+
     /// Returns the logical value found at the provided row id.
     pub fn value(&self, row_id: u32) -> Value<'_> {
         match &self {
@@ -1108,7 +1320,58 @@ impl IntegerEncoding {
             // unsigned 8-bit variant - logical type is u8
             Self::U8U8(c) => Values::U8(UInt8Array::from(c.values::<u8>(row_ids, vec![]))),
 
-            Self::I64I64N(c) => Values::I64(Int64Array::from(c.values(row_ids, vec![]))),
+            Self::I64I64N(c) => Values::I64(c.take(row_ids)),
+        }
+    }
+
+    /// Returns the logical values of all the rows in the column, without
+    /// needing their row ids.
+    pub fn all_values(&self) -> Values {
+        match &self {
+            // signed 64-bit variants - logical type is i64 for all these
+            Self::I64I64(c) => Values::I64(Int64Array::from(c.all_values::<i64>(vec![]))),
+            Self::I64I32(c) => Values::I64(Int64Array::from(c.all_values::<i64>(vec![]))),
+            Self::I64U32(c) => Values::I64(Int64Array::from(c.all_values::<i64>(vec![]))),
+            Self::I64I16(c) => Values::I64(Int64Array::from(c.all_values::<i64>(vec![]))),
+            Self::I64U16(c) => Values::I64(Int64Array::from(c.all_values::<i64>(vec![]))),
+            Self::I64I8(c) => Values::I64(Int64Array::from(c.all_values::<i64>(vec![]))),
+            Self::I64U8(c) => Values::I64(Int64Array::from(c.all_values::<i64>(vec![]))),
+
+            // signed 32-bit variants - logical type is i32 for all these
+            Self::I32I32(c) => Values::I32(Int32Array::from(c.all_values::<i32>(vec![]))),
+            Self::I32I16(c) => Values::I32(Int32Array::from(c.all_values::<i32>(vec![]))),
+            Self::I32U16(c) => Values::I32(Int32Array::from(c.all_values::<i32>(vec![]))),
+            Self::I32I8(c) => Values::I32(Int32Array::from(c.all_values::<i32>(vec![]))),
+            Self::I32U8(c) => Values::I32(Int32Array::from(c.all_values::<i32>(vec![]))),
+
+            // signed 16-bit variants - logical type is i16 for all these
+            Self::I16I16(c) => Values::I16(Int16Array::from(c.all_values::<i16>(vec![]))),
+            Self::I16I8(c) => Values::I16(Int16Array::from(c.all_values::<i16>(vec![]))),
+            Self::I16U8(c) => Values::I16(Int16Array::from(c.all_values::<i16>(vec![]))),
+
+            // signed 8-bit variant - logical type is i8
+            Self::I8I8(c) => Values::I8(Int8Array::from(c.all_values::<i8>(vec![]))),
+
+            // unsigned 64-bit variants - logical type is u64 for all these
+            Self::U64U64(c) => Values::U64(UInt64Array::from(c.all_values::<u64>(vec![]))),
+            Self::U64U32(c) => Values::U64(UInt64Array::from(c.all_values::<u64>(vec![]))),
+            Self::U64U16(c) => Values::U64(UInt64Array::from(c.all_values::<u64>(vec![]))),
+            Self::U64U8(c) => Values::U64(UInt64Array::from(c.all_values::<u64>(vec![]))),
+
+            // unsigned 32-bit variants - logical type is u32 for all these
+            Self::U32U32(c) => Values::U32(UInt32Array::from(c.all_values::<u32>(vec![]))),
+            Self::U32U16(c) => Values::U32(UInt32Array::from(c.all_values::<u32>(vec![]))),
+            Self::U32U8(c) => Values::U32(UInt32Array::from(c.all_values::<u32>(vec![]))),
+
+            // unsigned 16-bit variants - logical type is u16 for all these
+            Self::U16U16(c) => Values::U16(UInt16Array::from(c.all_values::<u16>(vec![]))),
+            Self::U16U8(c) => Values::U16(UInt16Array::from(c.all_values::<u16>(vec![]))),
+
+            // unsigned 8-bit variant - logical type is u8
+            Self::U8U8(c) => Values::U8(UInt8Array::from(c.all_values::<u8>(vec![]))),
+
+            // the backing Arrow array is shared rather than copied
+            Self::I64I64N(c) => Values::I64(c.all()),
         }
     }
 
@@ -1430,6 +1693,14 @@ pub enum FloatEncoding {
 }
 
 impl FloatEncoding {
+    /// The total number of rows in the column.
+    pub fn num_rows(&self) -> u32 {
+        match &self {
+            Self::Fixed64(c) => c.num_rows(),
+            Self::Fixed32(c) => c.num_rows(),
+        }
+    }
+
     /// Determines if the column contains a NULL value.
     pub fn contains_null(&self) -> bool {
         // TODO(edd): when adding the nullable columns then ask the nullable
@@ -1437,6 +1708,21 @@ impl FloatEncoding {
         false
     }
 
+    /// Returns the row ids of the rows that are NULL, of which there are none
+    /// until there is a nullable float encoding.
+    pub fn row_ids_null(&self, mut dst: RowIDs) -> RowIDs {
+        dst.clear();
+        dst
+    }
+
+    /// Returns the row ids of the rows that are not NULL, which is all of
+    /// them until there is a nullable float encoding.
+    pub fn row_ids_not_null(&self, mut dst: RowIDs) -> RowIDs {
+        dst.clear();
+        dst.add_range(0, self.num_rows());
+        dst
+    }
+
     /// Returns the logical value found at the provided row id.
     pub fn value(&self, row_id: u32) -> Value<'_> {
         match &self {
@@ -1457,6 +1743,15 @@ impl FloatEncoding {
         }
     }
 
+    /// Returns the logical values of all the rows in the column, without
+    /// needing their row ids.
+    pub fn all_values(&self) -> Values {
+        match &self {
+            Self::Fixed64(c) => Values::F64(Float64Array::from(c.all_values::<f64>(vec![]))),
+            Self::Fixed32(c) => Values::F32(Float32Array::from(c.all_values::<f32>(vec![]))),
+        }
+    }
+
     /// Returns the row ids that satisfy the provided predicate.
     ///
     /// Note: it is the caller's responsibility to ensure that the provided
@@ -2054,6 +2349,7 @@ pub enum AggregateType {
 
 /// These variants hold aggregates, which are the results of applying aggregates
 /// to column data.
+#[derive(Debug, PartialEq)]
 pub enum AggregateResult<'a> {
     // Any type of column can have rows counted. NULL values do not contribute
     // to the count. If all rows are NULL then count will be `0`.
@@ -2080,6 +2376,52 @@ pub enum AggregateResult<'a> {
     Last(Option<(i64, Value<'a>)>),
 }
 
+impl<'a> AggregateResult<'a> {
+    /// Merges the aggregate of other rows of the same column into this one,
+    /// as if it had been applied to the rows of both. Panics if `other` is a
+    /// different type of aggregate.
+    pub fn merge(&mut self, other: AggregateResult<'a>) {
+        match (self, other) {
+            (Self::Count(count), Self::Count(other)) => *count += other,
+            (Self::Sum(sum), Self::Sum(other)) => {
+                *sum = match (sum.take(), other) {
+                    (Some(sum), Some(other)) => Some(sum + other),
+                    (sum, other) => sum.or(other),
+                }
+            }
+            (Self::Min(min), Self::Min(other)) => {
+                if *min == Value::Null || (other != Value::Null && other < *min) {
+                    *min = other;
+                }
+            }
+            (Self::Max(max), Self::Max(other)) => {
+                if other > *max {
+                    *max = other;
+                }
+            }
+            // ties are won by the rows merged first
+            (Self::First(first), Self::First(Some((time, value)))) => {
+                if first
+                    .as_ref()
+                    .map_or(true, |(first_time, _)| time < *first_time)
+                {
+                    *first = Some((time, value));
+                }
+            }
+            (Self::Last(last), Self::Last(Some((time, value)))) => {
+                if last
+                    .as_ref()
+                    .map_or(true, |(last_time, _)| time > *last_time)
+                {
+                    *last = Some((time, value));
+                }
+            }
+            (Self::First(_), Self::First(None)) | (Self::Last(_), Self::Last(None)) => {}
+            (agg, other) => panic!("cannot merge {:?} into {:?}", other, agg),
+        }
+    }
+}
+
 /// A scalar is a numerical value that can be aggregated.
 #[derive(Debug, PartialEq, PartialOrd, Clone)]
 pub enum Scalar {
@@ -2168,6 +2510,28 @@ impl Scalar {
         }
     }
 
+    /// Returns true if this is a floating point scalar.
+    pub fn is_float(&self) -> bool {
+        matches!(self, Scalar::F64(_) | Scalar::F32(_))
+    }
+
+    /// Converts any scalar to an `f64`, which may lose precision for large
+    /// integers.
+    pub fn to_f64(&self) -> f64 {
+        match &self {
+            Scalar::I64(v) => *v as f64,
+            Scalar::I32(v) => f64::from(*v),
+            Scalar::I16(v) => f64::from(*v),
+            Scalar::I8(v) => f64::from(*v),
+            Scalar::U64(v) => *v as f64,
+            Scalar::U32(v) => f64::from(*v),
+            Scalar::U16(v) => f64::from(*v),
+            Scalar::U8(v) => f64::from(*v),
+            Scalar::F64(v) => *v,
+            Scalar::F32(v) => f64::from(*v),
+        }
+    }
+
     fn try_as_f64(&self) -> Option<f64> {
         match &self {
             Scalar::F64(v) => Some(*v),
@@ -2177,6 +2541,104 @@ impl Scalar {
     }
 }
 
+/// The sum of two scalars of the same type.
+impl std::ops::Add for Scalar {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        match (self, other) {
+            (Self::I64(a), Self::I64(b)) => Self::I64(a + b),
+            (Self::I32(a), Self::I32(b)) => Self::I32(a + b),
+            (Self::I16(a), Self::I16(b)) => Self::I16(a + b),
+            (Self::I8(a), Self::I8(b)) => Self::I8(a + b),
+            (Self::U64(a), Self::U64(b)) => Self::U64(a + b),
+            (Self::U32(a), Self::U32(b)) => Self::U32(a + b),
+            (Self::U16(a), Self::U16(b)) => Self::U16(a + b),
+            (Self::U8(a), Self::U8(b)) => Self::U8(a + b),
+            (Self::F64(a), Self::F64(b)) => Self::F64(a + b),
+            (Self::F32(a), Self::F32(b)) => Self::F32(a + b),
+            (a, b) => panic!("cannot add {:?} to {:?}", b, a),
+        }
+    }
+}
+
+/// The outcome of rewriting a comparison of a numeric column with a scalar of
+/// another type.
+#[derive(Debug, PartialEq)]
+enum ScalarCoercion {
+    // The scalar already has the column's type.
+    Unchanged,
+
+    // The equivalent comparison with a scalar of the column's type.
+    Coerced(cmp::Operator, Scalar),
+
+    // Every non-null value satisfies the comparison.
+    NotNull,
+
+    // No value can satisfy the comparison.
+    NoRows,
+}
+
+impl ScalarCoercion {
+    // Rewrites a comparison with the float `v` for an integer column, whose
+    // values can be any integer in `[range.0, range.1)`.
+    fn from_float(
+        op: &cmp::Operator,
+        v: f64,
+        range: (f64, f64),
+        to_scalar: impl Fn(f64) -> Scalar,
+    ) -> Self {
+        let (min, max) = range;
+        if v.is_nan() {
+            return match op {
+                cmp::Operator::NotEqual => Self::NotNull,
+                _ => Self::NoRows,
+            };
+        }
+
+        match op {
+            cmp::Operator::Equal | cmp::Operator::EqualIgnoreCase => {
+                if v.fract() == 0.0 && min <= v && v < max {
+                    Self::Coerced(cmp::Operator::Equal, to_scalar(v))
+                } else {
+                    Self::NoRows
+                }
+            }
+            cmp::Operator::NotEqual => {
+                if v.fract() == 0.0 && min <= v && v < max {
+                    Self::Coerced(cmp::Operator::NotEqual, to_scalar(v))
+                } else {
+                    Self::NotNull
+                }
+            }
+            // `x > v` is `x > floor(v)`
+            cmp::Operator::GT => match v.floor() {
+                v if v >= max => Self::NoRows,
+                v if v < min => Self::NotNull,
+                v => Self::Coerced(cmp::Operator::GT, to_scalar(v)),
+            },
+            // `x >= v` is `x >= ceil(v)`
+            cmp::Operator::GTE => match v.ceil() {
+                v if v >= max => Self::NoRows,
+                v if v <= min => Self::NotNull,
+                v => Self::Coerced(cmp::Operator::GTE, to_scalar(v)),
+            },
+            // `x < v` is `x < ceil(v)`
+            cmp::Operator::LT => match v.ceil() {
+                v if v <= min => Self::NoRows,
+                v if v >= max => Self::NotNull,
+                v => Self::Coerced(cmp::Operator::LT, to_scalar(v)),
+            },
+            // `x <= v` is `x <= floor(v)`
+            cmp::Operator::LTE => match v.floor() {
+                v if v < min => Self::NoRows,
+                v if v >= max => Self::NotNull,
+                v => Self::Coerced(cmp::Operator::LTE, to_scalar(v)),
+            },
+        }
+    }
+}
+
 /// Each variant is a possible value type that can be returned from a column.
 #[derive(Debug, PartialEq, PartialOrd, Clone)]
 pub enum Value<'a> {
@@ -2508,6 +2970,15 @@ impl RowIDs {
         }
     }
 
+    // Appends the row ids to `dst`, so a buffer can be reused for them
+    // rather than allocating a new vector as `to_vec` does.
+    pub fn append_to(&self, dst: &mut Vec<u32>) {
+        match self {
+            RowIDs::Bitmap(bm) => dst.extend(bm.iter()),
+            RowIDs::Vector(arr) => dst.extend_from_slice(arr),
+        }
+    }
+
     pub fn as_slice(&self) -> &[u32] {
         match self {
             RowIDs::Bitmap(bm) => panic!("not supported yet"),
@@ -2573,6 +3044,18 @@ impl RowIDs {
     }
 }
 
+impl Default for RowIDs {
+    fn default() -> Self {
+        Self::new_bitmap()
+    }
+}
+
+impl Reusable for RowIDs {
+    fn reset(&mut self) {
+        self.clear()
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -3025,6 +3508,42 @@ mod test {
         );
     }
 
+    #[test]
+    fn all_values() {
+        // physical type of `col` will be `u8` but logical type is `u64`
+        let col = Column::from(&[0_u64, 1, 200, 20, 100][..]);
+        assert_eq!(
+            col.all_values(),
+            Some(Values::U64(UInt64Array::from(vec![0, 1, 200, 20, 100])))
+        );
+
+        let col = Column::from(&[0.0, 1.1, 20.2][..]);
+        assert_eq!(
+            col.all_values(),
+            Some(Values::F64(Float64Array::from(vec![0.0, 1.1, 20.2])))
+        );
+
+        // nullable columns share their backing data, and select row ids with
+        // Arrow's `take` kernel.
+        let col = Column::from(Int64Array::from(vec![Some(10), None, Some(-3)]));
+        assert_eq!(
+            col.all_values(),
+            Some(Values::I64(Int64Array::from(vec![
+                Some(10),
+                None,
+                Some(-3)
+            ])))
+        );
+        assert_eq!(
+            col.values(&[1, 2]),
+            Values::I64(Int64Array::from(vec![None, Some(-3)]))
+        );
+
+        // dictionary encoded values are materialised from row ids.
+        let col = Column::from(&[Some("a"), None][..]);
+        assert!(col.all_values().is_none());
+    }
+
     #[test]
     fn distinct_values() {
         let input = &[
@@ -3257,6 +3776,75 @@ mod test {
         assert_eq!(dst_buffer.to_vec(), vec![0, 2, 6]);
     }
 
+    #[test]
+    fn row_ids_filter_str_null() {
+        let input = &[Some("Badlands"), None, Some("Racing in the Street"), None];
+
+        let dst_buffer = RowIDs::new_bitmap();
+
+        let col = Column::from(&input[..]);
+        let row_ids = col.row_ids_filter(&cmp::Operator::Equal, &Value::Null, dst_buffer);
+        let dst_buffer = match row_ids {
+            RowIDsOption::Some(dst) => {
+                assert_eq!(dst.to_vec(), vec![1, 3]);
+                dst
+            }
+            _ => panic!("expected some rows"),
+        };
+
+        let row_ids = col.row_ids_filter(&cmp::Operator::NotEqual, &Value::Null, dst_buffer);
+        let dst_buffer = match row_ids {
+            RowIDsOption::Some(dst) => {
+                assert_eq!(dst.to_vec(), vec![0, 2]);
+                dst
+            }
+            _ => panic!("expected some rows"),
+        };
+
+        // other comparisons with NULL match no rows
+        let row_ids = col.row_ids_filter(&cmp::Operator::GT, &Value::Null, dst_buffer);
+        let dst_buffer = match row_ids {
+            RowIDsOption::None(dst) => dst,
+            _ => panic!("expected no rows"),
+        };
+
+        // when the column doesn't contain any NULL values the answer comes
+        // from the meta-data.
+        let input = &[Some("Badlands"), Some("Racing in the Street")];
+
+        let col = Column::from(&input[..]);
+        let row_ids = col.row_ids_filter(&cmp::Operator::Equal, &Value::Null, dst_buffer);
+        let dst_buffer = match row_ids {
+            RowIDsOption::None(dst) => dst,
+            _ => panic!("expected no rows"),
+        };
+
+        let row_ids = col.row_ids_filter(&cmp::Operator::NotEqual, &Value::Null, dst_buffer);
+        assert!(matches!(row_ids, RowIDsOption::All(_)));
+    }
+
+    #[test]
+    fn row_ids_filter_int_null() {
+        let arr = Int64Array::from(vec![Some(1), None, None, Some(4), Some(5), None]);
+        let col = Column::from(arr);
+
+        let row_ids = col.row_ids_filter(&cmp::Operator::Equal, &Value::Null, RowIDs::new_bitmap());
+        assert_eq!(row_ids.unwrap().to_vec(), vec![1, 2, 5]);
+
+        let row_ids =
+            col.row_ids_filter(&cmp::Operator::NotEqual, &Value::Null, RowIDs::new_vector());
+        assert_eq!(row_ids.unwrap().to_vec(), vec![0, 3, 4]);
+
+        // columns without a nullable encoding have no NULL values
+        let input = &[10.2_f64, -2.43, 200.2];
+        let col = Column::from(&input[..]);
+        let row_ids = col.row_ids_filter(&cmp::Operator::Equal, &Value::Null, RowIDs::new_bitmap());
+        assert!(matches!(row_ids, RowIDsOption::None(_)));
+        let row_ids =
+            col.row_ids_filter(&cmp::Operator::NotEqual, &Value::Null, RowIDs::new_bitmap());
+        assert!(matches!(row_ids, RowIDsOption::All(_)));
+    }
+
     #[test]
     fn row_ids_filter_int() {
         let input = &[100, 200, 300, 2, 200, 22, 30];
@@ -3405,6 +3993,104 @@ mod test {
         assert!(matches!(row_ids, RowIDsOption::All(_)));
     }
 
+    #[test]
+    fn row_ids_filter_coerced_scalar() {
+        let input = &[100, 200, 300, 2, 200, 22, 30];
+
+        let col = Column::from(&input[..]);
+        let mut row_ids = col.row_ids_filter(
+            &cmp::Operator::Equal,
+            &Value::Scalar(Scalar::F64(200.0)),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![1, 4]);
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::Equal,
+            &Value::Scalar(Scalar::F64(200.5)),
+            RowIDs::new_bitmap(),
+        );
+        assert!(matches!(row_ids, RowIDsOption::None(_)));
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::GT,
+            &Value::Scalar(Scalar::F64(2.5)),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![0, 1, 2, 4, 5, 6]);
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::LTE,
+            &Value::Scalar(Scalar::F64(22.9)),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![3, 5]);
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::GT,
+            &Value::Scalar(Scalar::F64(-1e30)),
+            RowIDs::new_bitmap(),
+        );
+        assert!(matches!(row_ids, RowIDsOption::All(_)));
+
+        let input = &[100.2, 200.0, 300.1, 2.22, -200.2, 22.2, 30.2];
+        let col = Column::from(&input[..]);
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::Equal,
+            &Value::Scalar(Scalar::I64(200)),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![1]);
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::GT,
+            &Value::Scalar(Scalar::I64(-200)),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![0, 1, 2, 3, 5, 6]);
+
+        let input = &[100_u32, 200, 300, 2, 200, 22, 30];
+        let col = Column::from(&input[..]);
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::GT,
+            &Value::Scalar(Scalar::I64(-1)),
+            RowIDs::new_bitmap(),
+        );
+        assert!(matches!(row_ids, RowIDsOption::All(_)));
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::Equal,
+            &Value::Scalar(Scalar::I64(-1)),
+            RowIDs::new_bitmap(),
+        );
+        assert!(matches!(row_ids, RowIDsOption::None(_)));
+
+        // comparisons every value satisfies still exclude the NULL rows of a
+        // nullable column
+        let arr = Int64Array::from(vec![Some(2), None, Some(3), None]);
+        let col = Column::from(arr);
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::NotEqual,
+            &Value::Scalar(Scalar::F64(2.5)),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![0, 2]);
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::GT,
+            &Value::Scalar(Scalar::F64(-1e30)),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![0, 2]);
+
+        row_ids = col.row_ids_filter(
+            &cmp::Operator::NotEqual,
+            &Value::Scalar(Scalar::F64(f64::NAN)),
+            RowIDs::new_bitmap(),
+        );
+        assert_eq!(row_ids.unwrap().to_vec(), vec![0, 2]);
+    }
+
     #[test]
     fn row_ids_range() {
         let input = &[100, 200, 300, 2, 200, 22, 30];
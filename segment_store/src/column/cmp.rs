@@ -1,5 +1,5 @@
 /// Possible comparison operators
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub enum Operator {
     Equal,
     NotEqual,
@@ -7,4 +7,8 @@ pub enum Operator {
     GTE,
     LT,
     LTE,
+    /// Equality on strings ignoring differences in case. On columns of
+    /// other types it is the same as `Equal`. Segments are filtered with
+    /// it for `lower(column) = 'value'` (see `predicate_from_expr`).
+    EqualIgnoreCase,
 }
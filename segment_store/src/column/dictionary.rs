@@ -2,9 +2,12 @@ pub mod plain;
 pub mod rle;
 
 use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
 
 use croaring::Bitmap;
 
+use arrow_deps::arrow::array::{Array, StringArray};
+
 // This makes the encoding types available under the dictionary module.
 pub use self::plain::Plain;
 pub use self::rle::RLE;
@@ -14,6 +17,86 @@ use crate::column::{cmp, RowIDs};
 /// The encoded id for a NULL value.
 pub const NULL_ID: u32 = 0;
 
+/// The maximum number of distinct non-null values a dictionary encoding can
+/// hold.
+///
+/// Values are encoded as `u32` ids and `NULL_ID` is reserved for NULL, so the
+/// cardinality of a column including NULL (i.e., the number of entries in its
+/// dictionary) must also fit in a `u32`. No column in a segment can reach
+/// this, since a segment has at most `u32::MAX` rows, but dictionaries can
+/// be built from values that are not all in the column.
+///
+/// There is deliberately no encoding with wider ids: a dictionary that needs
+/// them is rejected with `CardinalityOverflow`, and the caller must split the
+/// values it was built from across more segments.
+pub const MAX_CARDINALITY: usize = u32::MAX as usize - 1;
+
+/// The most distinct non-null values a column can have for `=` and `!=`
+/// predicates on it to be answered from the rows of each value, rather than
+/// by scanning the encoded data.
+///
+/// Only the `RLE` encoding keeps the rows of each value. `Plain` is used for
+/// columns of much higher cardinality, where scanning is cheaper than
+/// keeping a bitmap per value.
+pub const LOW_CARDINALITY_LIMIT: usize = 8;
+
+/// The error returned when a dictionary encoding would need more ids than
+/// there are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CardinalityOverflow {
+    pub cardinality: usize,
+}
+
+impl fmt::Display for CardinalityOverflow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "dictionary cardinality {} exceeds the maximum of {} distinct values",
+            self.cardinality, MAX_CARDINALITY
+        )
+    }
+}
+
+impl std::error::Error for CardinalityOverflow {}
+
+/// Returns an error if a dictionary of `cardinality` distinct non-null
+/// values can not be encoded.
+fn check_cardinality(cardinality: usize) -> Result<(), CardinalityOverflow> {
+    if cardinality > MAX_CARDINALITY {
+        return Err(CardinalityOverflow { cardinality });
+    }
+    Ok(())
+}
+
+/// Returns the runs of equal adjacent values in `arr`, with the length of
+/// each, so that a run can be appended to an encoding in one go. The null
+/// bitmap is only consulted if the array has NULL values.
+fn arrow_runs(arr: &StringArray) -> impl Iterator<Item = (Option<&str>, u32)> + '_ {
+    let has_nulls = arr.null_count() > 0;
+    let value = move |i: usize| {
+        if has_nulls && arr.is_null(i) {
+            None
+        } else {
+            Some(arr.value(i))
+        }
+    };
+
+    let mut i = 0;
+    std::iter::from_fn(move || {
+        if i == arr.len() {
+            return None;
+        }
+
+        let v = value(i);
+        let start = i;
+        i += 1;
+        while i < arr.len() && value(i) == v {
+            i += 1;
+        }
+        Some((v, (i - start) as u32))
+    })
+}
+
 pub enum Encoding {
     RLE(RLE),
     Plain(Plain),
@@ -65,6 +148,40 @@ impl Encoding {
         }
     }
 
+    /// Adds additional repetitions of the provided value to the encoded data,
+    /// returning an error rather than panicking if the value is a new entry
+    /// that the dictionary has no id for.
+    pub fn try_push_additional(
+        &mut self,
+        v: Option<String>,
+        additional: u32,
+    ) -> Result<(), CardinalityOverflow> {
+        match self {
+            Encoding::RLE(ref mut env) => env.try_push_additional(v, additional),
+            Encoding::Plain(ref mut env) => env.try_push_additional(v, additional),
+        }
+    }
+
+    /// Appends every value of the Arrow array to the encoded data, a run of
+    /// equal adjacent values at a time. It is the caller's responsibility to
+    /// ensure that the dictionary encoded remains sorted.
+    pub fn push_arrow(&mut self, arr: &StringArray) {
+        match self {
+            Encoding::RLE(ref mut enc) => enc.push_arrow(arr),
+            Encoding::Plain(ref mut enc) => enc.push_arrow(arr),
+        }
+    }
+
+    /// Appends every value of the Arrow array to the encoded data, as
+    /// `push_arrow` does, returning an error rather than panicking if a value
+    /// is a new entry that the dictionary has no id for.
+    pub fn try_push_arrow(&mut self, arr: &StringArray) -> Result<(), CardinalityOverflow> {
+        match self {
+            Encoding::RLE(ref mut enc) => enc.try_push_arrow(arr),
+            Encoding::Plain(ref mut enc) => enc.try_push_arrow(arr),
+        }
+    }
+
     /// Determine if NULL is encoded in the column.
     fn contains_null(&self) -> bool {
         match self {
@@ -305,6 +422,52 @@ mod test {
 
     use super::*;
 
+    #[test]
+    fn null_id_is_first_entry() {
+        // Both encodings reserve `NULL_ID` by storing a placeholder entry at
+        // index `0` of their dictionary, so that the id of every other entry
+        // is its index.
+        assert_eq!(NULL_ID, 0);
+
+        let encodings = vec![
+            Encoding::RLE(RLE::default()),
+            Encoding::Plain(Plain::default()),
+        ];
+
+        for mut enc in encodings {
+            enc.push_additional(Some("east".to_string()), 1);
+            enc.push_none();
+            enc.push_additional(Some("north".to_string()), 1);
+
+            let name = enc.debug_name();
+            assert_eq!(
+                enc.all_encoded_values(vec![]),
+                vec![1, NULL_ID, 2],
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn cardinality_limit() {
+        assert!(check_cardinality(0).is_ok());
+        assert!(check_cardinality(MAX_CARDINALITY).is_ok());
+        assert_eq!(
+            check_cardinality(MAX_CARDINALITY + 1),
+            Err(CardinalityOverflow {
+                cardinality: MAX_CARDINALITY + 1
+            })
+        );
+        assert_eq!(
+            CardinalityOverflow {
+                cardinality: 1 << 33
+            }
+            .to_string(),
+            "dictionary cardinality 8589934592 exceeds the maximum of 4294967294 distinct values"
+        );
+    }
+
     #[test]
     fn push() {
         let encodings = vec![
@@ -317,6 +480,63 @@ mod test {
         }
     }
 
+    #[test]
+    fn push_arrow() {
+        let encodings = vec![
+            Encoding::RLE(RLE::from(vec!["east", "east"])),
+            Encoding::Plain(Plain::from(vec!["east", "east"])),
+        ];
+
+        for enc in encodings {
+            _push_arrow(enc);
+        }
+    }
+
+    fn _push_arrow(mut enc: Encoding) {
+        let arr = StringArray::from(vec![
+            Some("east"),
+            Some("north"),
+            Some("north"),
+            None,
+            None,
+            Some("south"),
+        ]);
+        enc.push_arrow(&arr);
+
+        // an array without NULL values has no null bitmap to read
+        let arr = StringArray::from(vec!["south", "west"]);
+        enc.push_arrow(&arr);
+        enc.push_arrow(&StringArray::from(Vec::<&str>::new()));
+
+        let name = enc.debug_name();
+        assert_eq!(enc.num_rows(), 10, "{}", name);
+        assert!(enc.contains_null(), "{}", name);
+        assert_eq!(
+            enc.all_values(vec![]),
+            [
+                Some(&"east".to_string()),
+                Some(&"east".to_string()),
+                Some(&"east".to_string()),
+                Some(&"north".to_string()),
+                Some(&"north".to_string()),
+                None,
+                None,
+                Some(&"south".to_string()),
+                Some(&"south".to_string()),
+                Some(&"west".to_string()),
+            ],
+            "{}",
+            name
+        );
+
+        // values must still be appended in order
+        let arr = StringArray::from(vec!["apple"]);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            enc.push_arrow(&arr);
+        }));
+        assert!(result.is_err(), "{}", name);
+    }
+
     fn _push(mut enc: Encoding) {
         enc.push_additional(Some("hello".to_string()), 1);
         enc.push_additional(None, 3);
@@ -455,6 +675,48 @@ mod test {
         assert_eq!(ids, RowIDs::Vector(vec![3, 10, 11]), "{}", name);
     }
 
+    #[test]
+    fn row_ids_filter_equal_ignore_case() {
+        let encodings = vec![
+            Encoding::RLE(RLE::default()),
+            Encoding::Plain(Plain::default()),
+        ];
+
+        for enc in encodings {
+            _row_ids_filter_equal_ignore_case(enc);
+        }
+    }
+
+    fn _row_ids_filter_equal_ignore_case(mut enc: Encoding) {
+        let name = enc.debug_name();
+        enc.push_additional(Some("Host-A".to_string()), 2); // 0, 1
+        enc.push_additional(Some("host-b".to_string()), 1); // 2
+        enc.push_none(); // 3
+        enc.push_additional(Some("host-a".to_string()), 1); // 4
+        enc.push_additional(Some("HOST-A".to_string()), 1); // 5
+
+        let ids = enc.row_ids_filter(
+            &"host-a",
+            &cmp::Operator::EqualIgnoreCase,
+            RowIDs::Vector(vec![]),
+        );
+        assert_eq!(ids, RowIDs::Vector(vec![0, 1, 4, 5]), "{}", name);
+
+        let ids = enc.row_ids_filter(
+            &"HOST-B",
+            &cmp::Operator::EqualIgnoreCase,
+            RowIDs::Vector(vec![]),
+        );
+        assert_eq!(ids, RowIDs::Vector(vec![2]), "{}", name);
+
+        let ids = enc.row_ids_filter(
+            &"host-c",
+            &cmp::Operator::EqualIgnoreCase,
+            RowIDs::Vector(vec![]),
+        );
+        assert!(ids.is_empty(), "{}", name);
+    }
+
     #[test]
     fn row_ids_filter_equal_no_null() {
         let encodings = vec![
@@ -15,7 +15,7 @@ use croaring::Bitmap;
 
 use arrow_deps::arrow::array::{Array, StringArray};
 
-use crate::column::dictionary::NULL_ID;
+use crate::column::dictionary::{arrow_runs, check_cardinality, CardinalityOverflow, NULL_ID};
 use crate::column::{cmp, RowIDs};
 
 pub struct Plain {
@@ -55,10 +55,20 @@ impl Plain {
     ///
     /// Callers are not required to provide a logical NULL value as part of the
     /// dictionary. The encoding already reserves a representation for that.
+    ///
+    /// Panics if the dictionary has more values than can be encoded.
     pub fn with_dictionary(dictionary: BTreeSet<String>) -> Self {
+        Self::try_with_dictionary(dictionary).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Initialises an Plain encoding with a set of logical values, as
+    /// `with_dictionary` does, returning an error if the dictionary has more
+    /// values than can be encoded.
+    pub fn try_with_dictionary(dictionary: BTreeSet<String>) -> Result<Self, CardinalityOverflow> {
+        check_cardinality(dictionary.len())?;
         let mut _self = Self::default();
         _self.entries.extend(dictionary.into_iter().map(Some));
-        _self
+        Ok(_self)
     }
 
     /// A reasonable estimation of the on-heap size this encoding takes up.
@@ -103,19 +113,60 @@ impl Plain {
 
     /// Adds additional repetitions of the provided value to the encoded data.
     /// It is the caller's responsibility to ensure that the dictionary remains
-    /// sorted. `push_additional` will panic if that invariant is broken.
+    /// sorted. `push_additional` will panic if that invariant is broken, or if
+    /// the value is a new entry that the dictionary has no id for.
     pub fn push_additional(&mut self, v: Option<String>, additional: u32) {
+        self.try_push_additional(v, additional)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds additional repetitions of the provided value to the encoded data,
+    /// as `push_additional` does, returning an error if the value is a new
+    /// entry that the dictionary has no id for.
+    pub fn try_push_additional(
+        &mut self,
+        v: Option<String>,
+        additional: u32,
+    ) -> Result<(), CardinalityOverflow> {
+        self.push_run(v.as_deref(), additional)
+    }
+
+    /// Appends every value of the Arrow array to the encoded data, a run of
+    /// equal adjacent values at a time. It is the caller's responsibility to
+    /// ensure that the dictionary remains sorted, and `push_arrow` will panic
+    /// if that invariant is broken, or if a value is a new entry that the
+    /// dictionary has no id for.
+    pub fn push_arrow(&mut self, arr: &StringArray) {
+        self.try_push_arrow(arr).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Appends every value of the Arrow array to the encoded data, as
+    /// `push_arrow` does, returning an error if a value is a new entry that
+    /// the dictionary has no id for.
+    pub fn try_push_arrow(&mut self, arr: &StringArray) -> Result<(), CardinalityOverflow> {
+        self.encoded_data.reserve(arr.len());
+        for (v, additional) in arrow_runs(arr) {
+            self.push_run(v, additional)?;
+        }
+        Ok(())
+    }
+
+    // Adds a run of `additional` repetitions of the value, only allocating if
+    // it is a new dictionary entry.
+    fn push_run(&mut self, v: Option<&str>, additional: u32) -> Result<(), CardinalityOverflow> {
         if v.is_none() {
             self.contains_null = true;
             self.push_encoded_values(NULL_ID, additional);
-            return;
+            return Ok(());
         }
 
-        match self.encoded_id(v.as_deref()) {
+        match self.encoded_id(v) {
             Ok(id) => self.push_encoded_values(id, additional),
             Err(idx) => {
                 // check this value can be inserted into the dictionary whilst
-                // maintaining order.
+                // maintaining order, and that there is an id for it. The
+                // entries include the one reserved for NULL.
+                check_cardinality(self.entries.len())?;
                 assert_eq!(
                     idx,
                     self.entries.len() as u32,
@@ -123,10 +174,11 @@ impl Plain {
                     v
                 );
 
-                self.entries.push(v);
+                self.entries.push(v.map(str::to_string));
                 self.push_encoded_values(idx, additional);
             }
         }
+        Ok(())
     }
 
     // Preferred method to add values to the column. `id` is the encoded
@@ -177,6 +229,7 @@ impl Plain {
             cmp::Operator::LT | cmp::Operator::LTE | cmp::Operator::GT | cmp::Operator::GTE => {
                 self.row_ids_cmp(value, op, dst)
             }
+            cmp::Operator::EqualIgnoreCase => self.row_ids_equal_ignore_case(value, dst),
         }
     }
 
@@ -237,6 +290,36 @@ impl Plain {
         dst
     }
 
+    // Finds row ids with values equal to `value` when case is ignored.
+    fn row_ids_equal_ignore_case(&self, value: &str, mut dst: RowIDs) -> RowIDs {
+        dst.clear();
+
+        // The dictionary is typically far smaller than the column, so find
+        // all entries that differ from `value` only in case before scanning
+        // the encoded data.
+        let value = value.to_lowercase();
+        let encoded_ids = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter_map(|(id, entry)| match entry {
+                Some(entry) if entry.to_lowercase() == value => Some(id as u32),
+                _ => None,
+            })
+            .collect::<BTreeSet<_>>();
+
+        if encoded_ids.is_empty() {
+            return dst;
+        }
+
+        for (i, next) in self.encoded_data.iter().enumerate() {
+            if encoded_ids.contains(next) {
+                dst.add(i as u32);
+            }
+        }
+        dst
+    }
+
     #[cfg(all(
         any(target_arch = "x86", target_arch = "x86_64"),
         target_feature = "avx2"
@@ -727,13 +810,7 @@ impl<'a> From<Vec<Option<String>>> for Plain {
 impl<'a> From<StringArray> for Plain {
     fn from(arr: StringArray) -> Self {
         let mut drle = Self::default();
-        for i in 0..arr.len() {
-            if arr.is_null(i) {
-                drle.push_none();
-            } else {
-                drle.push(arr.value(i).to_string());
-            }
-        }
+        drle.push_arrow(&arr);
         drle
     }
 }
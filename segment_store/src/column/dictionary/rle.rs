@@ -7,7 +7,9 @@ use croaring::Bitmap;
 
 use arrow_deps::arrow::array::{Array, StringArray};
 
-use crate::column::dictionary::NULL_ID;
+use crate::column::dictionary::{
+    arrow_runs, check_cardinality, CardinalityOverflow, LOW_CARDINALITY_LIMIT, NULL_ID,
+};
 use crate::column::{cmp, RowIDs};
 
 // `RLE` is a run-length encoding for dictionary columns, where all dictionary
@@ -61,7 +63,17 @@ impl RLE {
     /// Initialises an RLE encoding with a set of column values, ensuring that
     /// the rows in the column can be inserted in any order and the correct
     /// ordinal relationship will exist between the encoded values.
+    ///
+    /// Panics if the dictionary has more values than can be encoded.
     pub fn with_dictionary(dictionary: BTreeSet<String>) -> Self {
+        Self::try_with_dictionary(dictionary).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Initialises an RLE encoding with a set of column values, as
+    /// `with_dictionary` does, returning an error if the dictionary has more
+    /// values than can be encoded.
+    pub fn try_with_dictionary(dictionary: BTreeSet<String>) -> Result<Self, CardinalityOverflow> {
+        check_cardinality(dictionary.len())?;
         let mut _self = Self::default();
 
         for entry in dictionary.into_iter() {
@@ -72,7 +84,7 @@ impl RLE {
             _self.index_row_ids.insert(next_id, Bitmap::create());
         }
 
-        _self
+        Ok(_self)
     }
 
     /// A reasonable estimation of the on-heap size this encoding takes up.
@@ -122,15 +134,59 @@ impl RLE {
     /// Adds additional repetitions of the provided value to the encoded data.
     /// It is the caller's responsibility to ensure that the dictionary encoded
     /// remains sorted.
+    ///
+    /// Panics if the value is a new entry that the dictionary has no id for.
     pub fn push_additional(&mut self, v: Option<String>, additional: u32) {
+        self.try_push_additional(v, additional)
+            .unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Adds additional repetitions of the provided value to the encoded data,
+    /// as `push_additional` does, returning an error if the value is a new
+    /// entry that the dictionary has no id for.
+    pub fn try_push_additional(
+        &mut self,
+        v: Option<String>,
+        additional: u32,
+    ) -> Result<(), CardinalityOverflow> {
+        self.push_run(v.as_deref(), additional)
+    }
+
+    /// Appends every value of the Arrow array to the encoded data, a run of
+    /// equal adjacent values at a time. It is the caller's responsibility to
+    /// ensure that the dictionary encoded remains sorted.
+    ///
+    /// Panics if a value is a new entry that the dictionary has no id for.
+    pub fn push_arrow(&mut self, arr: &StringArray) {
+        self.try_push_arrow(arr).unwrap_or_else(|e| panic!("{}", e))
+    }
+
+    /// Appends every value of the Arrow array to the encoded data, as
+    /// `push_arrow` does, returning an error if a value is a new entry that
+    /// the dictionary has no id for.
+    pub fn try_push_arrow(&mut self, arr: &StringArray) -> Result<(), CardinalityOverflow> {
+        for (v, additional) in arrow_runs(arr) {
+            self.push_run(v, additional)?;
+        }
+        Ok(())
+    }
+
+    // Adds a run of `additional` repetitions of the value, only allocating if
+    // it is a new dictionary entry.
+    fn push_run(&mut self, v: Option<&str>, additional: u32) -> Result<(), CardinalityOverflow> {
         match v {
-            Some(v) => self.push_additional_some(v, additional),
+            Some(v) => self.push_additional_some(v, additional)?,
             None => self.push_additional_none(additional),
         }
+        Ok(())
     }
 
-    fn push_additional_some(&mut self, v: String, additional: u32) {
-        match self.entry_index.get(&v) {
+    fn push_additional_some(
+        &mut self,
+        v: &str,
+        additional: u32,
+    ) -> Result<(), CardinalityOverflow> {
+        match self.entry_index.get(v) {
             // existing dictionary entry for value.
             Some(id) => {
                 match self.run_lengths.last_mut() {
@@ -157,16 +213,18 @@ impl RLE {
             }
             // no dictionary entry for value.
             None => {
-                // New dictionary entry.
+                // New dictionary entry, which must have an id available.
+                check_cardinality(self.entry_index.len() + 1)?;
                 let next_id = self.next_encoded_id();
                 if next_id > 0
-                    && self.index_entries[next_id as usize - 1].cmp(&v) != std::cmp::Ordering::Less
+                    && self.index_entries[next_id as usize - 1].as_str().cmp(v)
+                        != std::cmp::Ordering::Less
                 {
                     panic!("out of order dictionary insertion");
                 }
-                self.index_entries.push(v.clone());
+                self.index_entries.push(v.to_string());
 
-                self.entry_index.insert(v, next_id);
+                self.entry_index.insert(v.to_string(), next_id);
                 self.index_row_ids.insert(next_id, Bitmap::create());
 
                 // start a new run-length
@@ -180,6 +238,7 @@ impl RLE {
             }
         }
         self.num_rows += additional;
+        Ok(())
     }
 
     fn push_additional_none(&mut self, additional: u32) {
@@ -239,10 +298,54 @@ impl RLE {
     /// the provided predicate.
     pub fn row_ids_filter(&self, value: &str, op: &cmp::Operator, dst: RowIDs) -> RowIDs {
         match op {
+            cmp::Operator::Equal | cmp::Operator::NotEqual
+                if self.entry_index.len() <= LOW_CARDINALITY_LIMIT =>
+            {
+                self.row_ids_equal_low_cardinality(value, op, dst)
+            }
             cmp::Operator::Equal | cmp::Operator::NotEqual => self.row_ids_equal(value, op, dst),
             cmp::Operator::LT | cmp::Operator::LTE | cmp::Operator::GT | cmp::Operator::GTE => {
                 self.row_ids_cmp(value, op, dst)
             }
+            cmp::Operator::EqualIgnoreCase => self.row_ids_equal_ignore_case(value, dst),
+        }
+    }
+
+    // Finds row ids based on = or != operator in a column with few distinct
+    // values, from the rows of each value: those of `value` for =, and the
+    // union of those of every other non-null value for !=. A bitmap `dst` is
+    // replaced by the result rather than having it added.
+    fn row_ids_equal_low_cardinality(
+        &self,
+        value: &str,
+        op: &cmp::Operator,
+        dst: RowIDs,
+    ) -> RowIDs {
+        let encoded_id = self.entry_index.get(value);
+        let row_ids = match op {
+            cmp::Operator::Equal => match encoded_id {
+                Some(encoded_id) => self.index_row_ids[encoded_id].clone(),
+                None => Bitmap::create(),
+            },
+            cmp::Operator::NotEqual => {
+                let mut row_ids = Bitmap::create();
+                for (other_encoded_id, other_row_ids) in &self.index_row_ids {
+                    if *other_encoded_id != NULL_ID && Some(other_encoded_id) != encoded_id {
+                        row_ids.or_inplace(other_row_ids);
+                    }
+                }
+                row_ids
+            }
+            _ => unreachable!("invalid operator"),
+        };
+
+        match dst {
+            RowIDs::Bitmap(_) => RowIDs::Bitmap(row_ids),
+            mut dst => {
+                dst.clear();
+                dst.add_from_bitmap(&row_ids);
+                dst
+            }
         }
     }
 
@@ -295,6 +398,24 @@ impl RLE {
         dst
     }
 
+    // Finds row ids with values equal to `value` when case is ignored.
+    fn row_ids_equal_ignore_case(&self, value: &str, mut dst: RowIDs) -> RowIDs {
+        dst.clear();
+
+        // Union the rows of every entry differing from `value` only in case
+        // so they are added to `dst` in order.
+        let value = value.to_lowercase();
+        let mut row_ids = Bitmap::create();
+        for (entry, encoded_id) in &self.entry_index {
+            if entry.to_lowercase() == value {
+                row_ids.or_inplace(self.index_row_ids.get(encoded_id).unwrap());
+            }
+        }
+
+        dst.add_from_bitmap(&row_ids);
+        dst
+    }
+
     // Finds row ids based on <, <=, > or >= operator.
     fn row_ids_cmp(&self, value: &str, op: &cmp::Operator, mut dst: RowIDs) -> RowIDs {
         dst.clear();
@@ -905,13 +1026,7 @@ impl<'a> From<Vec<Option<String>>> for RLE {
 impl<'a> From<StringArray> for RLE {
     fn from(arr: StringArray) -> Self {
         let mut drle = Self::default();
-        for i in 0..arr.len() {
-            if arr.is_null(i) {
-                drle.push_none();
-            } else {
-                drle.push(arr.value(i).to_string());
-            }
-        }
+        drle.push_arrow(&arr);
         drle
     }
 }
@@ -983,6 +1098,59 @@ mod test {
         assert_eq!(enc.size(), 397);
     }
 
+    #[test]
+    fn row_ids_equal_low_cardinality() {
+        let mut enc = RLE::default();
+        enc.push_additional(Some("east".to_string()), 2);
+        enc.push_none();
+        enc.push_additional(Some("north".to_string()), 2);
+        enc.push_additional(Some("east".to_string()), 1);
+        enc.push("south".to_string());
+        assert!(enc.entry_index.len() <= LOW_CARDINALITY_LIMIT);
+
+        let cases = vec![
+            ("east", cmp::Operator::Equal, vec![0, 1, 5]),
+            ("east", cmp::Operator::NotEqual, vec![3, 4, 6]),
+            ("west", cmp::Operator::Equal, vec![]),
+            ("west", cmp::Operator::NotEqual, vec![0, 1, 3, 4, 5, 6]),
+        ];
+
+        for (value, op, expected) in cases {
+            // the rows found match those found by scanning the run-lengths
+            for dst in vec![RowIDs::new_bitmap(), RowIDs::new_vector()] {
+                let row_ids = enc.row_ids_filter(value, &op, dst);
+                assert_eq!(row_ids.to_vec(), expected, "{} {:?}", value, op);
+            }
+            let row_ids = enc.row_ids_equal(value, &op, RowIDs::new_vector());
+            assert_eq!(row_ids.to_vec(), expected, "{} {:?}", value, op);
+        }
+
+        // the destination is replaced rather than added to
+        let mut dst = RowIDs::new_bitmap();
+        dst.add(2);
+        let row_ids = enc.row_ids_filter("north", &cmp::Operator::Equal, dst);
+        assert_eq!(row_ids.to_vec(), vec![3, 4]);
+    }
+
+    #[test]
+    fn push_arrow() {
+        let mut enc = RLE::default();
+        enc.push("east".to_string());
+        enc.push_arrow(&StringArray::from(vec![
+            Some("east"),
+            Some("east"),
+            None,
+            None,
+            Some("north"),
+        ]));
+        enc.push_arrow(&StringArray::from(vec!["north", "north"]));
+
+        // runs continue across pushes
+        assert_eq!(enc.run_lengths, vec![(1, 3), (NULL_ID, 2), (2, 3)]);
+        assert_eq!(enc.num_rows(), 8);
+        assert_eq!(enc.index_row_ids[&2].to_vec(), vec![5, 6, 7],);
+    }
+
     #[test]
     #[should_panic]
     fn push_wrong_order() {
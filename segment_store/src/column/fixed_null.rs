@@ -16,7 +16,7 @@ use std::cmp::Ordering;
 use std::fmt::Debug;
 
 use arrow_deps::arrow;
-use arrow_deps::arrow::array::{Array, PrimitiveArray};
+use arrow_deps::arrow::array::{make_array, Array, PrimitiveArray, UInt32Array};
 use arrow_deps::arrow::datatypes::ArrowNumericType;
 
 use crate::column::{cmp, RowIDs};
@@ -152,6 +152,22 @@ where
         dst
     }
 
+    /// Returns the logical (decoded) values for the provided row IDs as an
+    /// Arrow array, copied from the backing data with Arrow's vectorised `take`
+    /// kernel. NULL values remain NULL.
+    pub fn take(&self, row_ids: &[u32]) -> PrimitiveArray<T> {
+        let indices = UInt32Array::from(row_ids.to_vec());
+        let taken = arrow::compute::take(&make_array(self.arr.data()), &indices, None)
+            .expect("row ids must be within the column");
+        PrimitiveArray::from(taken.data())
+    }
+
+    /// Returns the logical (decoded) values for all the rows in the column as
+    /// an Arrow array, which shares the backing data rather than copying it.
+    pub fn all(&self) -> PrimitiveArray<T> {
+        PrimitiveArray::from(self.arr.data())
+    }
+
     //
     //
     // ---- Methods for aggregation.
@@ -286,6 +302,40 @@ where
         }
     }
 
+    /// Returns the set of row ids that are NULL.
+    pub fn row_ids_null(&self, dst: RowIDs) -> RowIDs {
+        self.row_ids_is_null(true, dst)
+    }
+
+    /// Returns the set of row ids that are not NULL.
+    pub fn row_ids_not_null(&self, dst: RowIDs) -> RowIDs {
+        self.row_ids_is_null(false, dst)
+    }
+
+    // Collects the ranges of rows whose nullness is `desired` and adds them
+    // in bulk to the bitmap.
+    fn row_ids_is_null(&self, desired: bool, mut dst: RowIDs) -> RowIDs {
+        dst.clear();
+
+        let mut start = None;
+        for i in 0..self.num_rows() {
+            match (self.arr.is_null(i as usize) == desired, start) {
+                (true, None) => start = Some(i),
+                (false, Some(from)) => {
+                    dst.add_range(from, i);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        // add any remaining range.
+        if let Some(from) = start {
+            dst.add_range(from, self.num_rows());
+        }
+        dst
+    }
+
     // Helper function to convert comparison operators to cmp orderings.
     fn ord_from_op(op: &cmp::Operator) -> (Ordering, Ordering) {
         match op {
@@ -631,6 +681,24 @@ mod test {
         assert_eq!(dst.capacity(), 10);
     }
 
+    #[test]
+    fn take_and_all() {
+        let data = vec![Some(0), None, Some(22), None, Some(33)];
+        let v = super::FixedNull::<Int64Type>::from(data.as_slice());
+
+        let taken = v.take(&[1, 2, 4]);
+        assert_eq!(taken.len(), 3);
+        assert!(taken.is_null(0));
+        assert_eq!((taken.value(1), taken.value(2)), (22, 33));
+
+        assert_eq!(v.take(&[]).len(), 0);
+
+        let all = v.all();
+        assert_eq!(all.len(), 5);
+        assert_eq!(all.null_count(), 2);
+        assert_eq!(all.value(4), 33);
+    }
+
     #[test]
     fn count() {
         let data = vec![Some(0), None, Some(22), None, None, Some(33), Some(44)];
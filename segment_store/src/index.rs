@@ -0,0 +1,165 @@
+use std::collections::BTreeMap;
+
+use croaring::Bitmap;
+
+use crate::column::{cmp::Operator, Value};
+use crate::segment::{Predicate, Segment};
+
+/// A TagIndex is an inverted index from the tag values in the tables of a
+/// partition to the segments of each table that contain them.
+///
+/// Segments are identified by their position within their table. The index
+/// is maintained as segments are added to a partition, and is used to find
+/// the segments that could satisfy a conjunction of tag equality predicates
+/// without scanning the dictionary columns of every segment.
+#[derive(Default)]
+pub struct TagIndex {
+    tables: BTreeMap<String, TableIndex>,
+}
+
+// The index of a single table.
+struct TableIndex {
+    // The positions of every segment indexed for the table.
+    segments: Bitmap,
+
+    // A mapping from tag key to each of its values, and the positions of the
+    // segments containing that value.
+    tags: BTreeMap<String, BTreeMap<String, Bitmap>>,
+}
+
+impl TagIndex {
+    /// Adds the tag values of `segment`, found at `position` within the table
+    /// `table_name`, to the index.
+    pub fn add_segment(&mut self, table_name: &str, position: u32, segment: &Segment<'_>) {
+        let table = self
+            .tables
+            .entry(table_name.to_owned())
+            .or_insert_with(|| TableIndex {
+                segments: Bitmap::create(),
+                tags: BTreeMap::new(),
+            });
+
+        table.segments.add(position);
+        for (tag_key, values) in segment.tag_values() {
+            let tag = table.tags.entry(tag_key.to_owned()).or_default();
+            for value in values {
+                tag.entry(value.to_owned())
+                    .or_insert_with(Bitmap::create)
+                    .add(position);
+            }
+        }
+    }
+
+    /// Removes all of the segments of the table `table_name` from the index.
+    pub fn remove_table(&mut self, table_name: &str) {
+        self.tables.remove(table_name);
+    }
+
+    /// Returns the positions of the segments of `table_name` that could
+    /// satisfy all of `predicates`.
+    ///
+    /// Only equality predicates on tag keys narrow the segments. All other
+    /// predicates must still be evaluated against the returned segments.
+    pub fn candidate_segments(&self, table_name: &str, predicates: &[Predicate<'_>]) -> Bitmap {
+        let table = match self.tables.get(table_name) {
+            Some(table) => table,
+            None => return Bitmap::create(),
+        };
+
+        let mut candidates = table.segments.clone();
+        for (column_name, (op, value)) in predicates {
+            let value = match (op, value) {
+                (Operator::Equal, Value::String(value)) => value,
+                _ => continue,
+            };
+
+            // Every segment has the same columns, so a tag key that isn't
+            // indexed may be a field, or a tag with only NULL values.
+            let tag = match table.tags.get(*column_name) {
+                Some(tag) => tag,
+                None => continue,
+            };
+
+            match tag.get(*value) {
+                Some(segments) => candidates.and_inplace(segments),
+                None => return Bitmap::create(),
+            }
+        }
+
+        candidates
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::column::Column;
+    use crate::segment::{ColumnType, TIME_COLUMN_NAME};
+
+    #[test]
+    fn candidate_segments() {
+        let tc = ColumnType::Time(Column::from(&[1_i64, 2, 3][..]));
+        let rc = ColumnType::Tag(Column::from(&["west", "west", "east"][..]));
+        let hc = ColumnType::Tag(Column::from(&["a", "b", "c"][..]));
+        let fc = ColumnType::Field(Column::from(&[100_u64, 101, 200][..]));
+        let mut columns = BTreeMap::new();
+        columns.insert(TIME_COLUMN_NAME, &tc);
+        columns.insert("region", &rc);
+        columns.insert("host", &hc);
+        columns.insert("count", &fc);
+        let segment0 = Segment::new(3, columns);
+
+        let tc = ColumnType::Time(Column::from(&[4_i64, 5][..]));
+        let rc = ColumnType::Tag(Column::from(&["south", "west"][..]));
+        let hc = ColumnType::Tag(Column::from(&[Some("a"), None][..]));
+        let fc = ColumnType::Field(Column::from(&[10_u64, 20][..]));
+        let mut columns = BTreeMap::new();
+        columns.insert(TIME_COLUMN_NAME, &tc);
+        columns.insert("region", &rc);
+        columns.insert("host", &hc);
+        columns.insert("count", &fc);
+        let segment1 = Segment::new(2, columns);
+
+        let mut index = TagIndex::default();
+        index.add_segment("cpu", 0, &segment0);
+        index.add_segment("cpu", 1, &segment1);
+
+        let cases = vec![
+            (vec![], vec![0, 1]),
+            (vec![("region", "west")], vec![0, 1]),
+            (vec![("region", "east")], vec![0]),
+            (vec![("region", "south")], vec![1]),
+            (vec![("region", "north")], vec![]),
+            (vec![("region", "west"), ("host", "b")], vec![0]),
+            (vec![("region", "south"), ("host", "b")], vec![]),
+            (vec![("region", "west"), ("host", "a")], vec![0, 1]),
+            // not a tag key
+            (vec![("count", "a")], vec![0, 1]),
+        ];
+
+        for (tags, expected) in cases {
+            let predicates = tags
+                .iter()
+                .map(|&(k, v)| (k, (Operator::Equal, Value::String(v))))
+                .collect::<Vec<_>>();
+            assert_eq!(
+                index.candidate_segments("cpu", &predicates).to_vec(),
+                expected,
+                "{:?}",
+                tags
+            );
+        }
+
+        // other predicates don't narrow the segments
+        let predicates = vec![("region", (Operator::NotEqual, Value::String("west")))];
+        assert_eq!(
+            index.candidate_segments("cpu", &predicates).to_vec(),
+            vec![0, 1]
+        );
+
+        assert!(index.candidate_segments("mem", &[]).is_empty());
+
+        index.remove_table("cpu");
+        assert!(index.candidate_segments("cpu", &[]).is_empty());
+    }
+}
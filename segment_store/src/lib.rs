@@ -3,8 +3,10 @@
 #![allow(clippy::too_many_arguments)]
 #![allow(unused_variables)]
 pub mod column;
+pub(crate) mod index;
 pub(crate) mod partition;
 pub(crate) mod segment;
+pub(crate) mod stats;
 pub(crate) mod table;
 
 use std::collections::BTreeMap;
@@ -1,12 +1,30 @@
 use std::collections::{BTreeMap, BTreeSet};
 
-use crate::column::{AggregateResult, AggregateType, Values};
-use crate::segment::{ColumnName, GroupKey};
+use crate::column::{cmp::Operator, AggregateResult, AggregateType, Value, Values};
+use crate::index::TagIndex;
+use crate::segment::{ColumnName, GroupKey, Predicate, Segment};
 use crate::table::Table;
 
 // The name of a measurement, i.e., a table name.
 type MeasurementName = String;
 
+// The estimated fraction of a table's rows matching the tag equality
+// predicates of a read above which the table's segments are scanned, rather
+// than looked up in the tag index. The index then rules out few segments, and
+// intersecting its bitmaps costs more than checking each segment's metadata.
+const MAX_INDEX_SELECTIVITY: f64 = 0.5;
+
+// How the segments of a table that could satisfy a read's predicates are
+// found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReadStrategy {
+    // Look up the segments containing the tag values in the tag index.
+    Index,
+
+    // Check the metadata of every segment of the table.
+    Scan,
+}
+
 /// A Partition comprises a collection of tables that have been organised
 /// according to a partition rule, e.g., based on time or arrival, some column
 /// value etc.
@@ -25,19 +43,97 @@ pub struct Partition<'a> {
     // The set of tables within this partition. Each table is identified by
     // a measurement name.
     tables: BTreeMap<MeasurementName, Table<'a>>,
+
+    // An index from the tag values in each table to the segments of the table
+    // containing them.
+    index: TagIndex,
 }
 
 impl<'a> Partition<'a> {
-    pub fn new(key: String, table: Table<'a>) -> Self {
+    pub fn new(key: String, mut table: Table<'a>) -> Self {
+        let table_name = table.name().to_owned();
+        let mut index = TagIndex::default();
+        for (position, segment) in table.iter().enumerate() {
+            index.add_segment(&table_name, position as u32, segment);
+        }
+
         let mut p = Self {
             key,
             meta: MetaData::new(&table),
             tables: BTreeMap::new(),
+            index,
         };
-        p.tables.insert(table.name().to_owned(), table);
+        p.tables.insert(table_name, table);
         p
     }
 
+    /// Adds a segment to the table `table_name`, creating the table if the
+    /// partition doesn't have it yet.
+    pub fn add_segment(&mut self, table_name: &str, segment: Segment<'a>) {
+        let position = self.tables.get(table_name).map_or(0, |table| table.len());
+        self.index
+            .add_segment(table_name, position as u32, &segment);
+        self.meta.add_segment(&segment);
+
+        match self.tables.get_mut(table_name) {
+            Some(table) => table.add_segment(segment),
+            None => {
+                let table = Table::new(table_name.to_owned(), segment);
+                self.tables.insert(table_name.to_owned(), table);
+            }
+        }
+    }
+
+    /// Returns vectors of columnar data for the specified column selections on
+    /// the specified table name (measurement), for each segment of the table
+    /// with rows that satisfy the conjunctive (AND) predicates.
+    ///
+    /// When the equality predicates on tag keys are estimated to be
+    /// selective, they are first resolved with the partition's tag index, so
+    /// only the segments containing every tag value are read.
+    pub fn read_filter(
+        &self,
+        table_name: &str,
+        columns: &[ColumnName<'a>],
+        predicates: &[Predicate<'_>],
+    ) -> Vec<(ColumnName<'a>, Vec<Values>)> {
+        let table = match self.tables.get(table_name) {
+            Some(table) => table,
+            None => return columns.iter().map(|&col_name| (col_name, vec![])).collect(),
+        };
+
+        match self.read_strategy(table, predicates) {
+            ReadStrategy::Index => {
+                let positions = self.index.candidate_segments(table_name, predicates);
+                table.select_segments(&positions, columns, predicates)
+            }
+            ReadStrategy::Scan => table.select(columns, predicates),
+        }
+    }
+
+    // Chooses how to find the segments of `table` that could satisfy
+    // `predicates`, from the estimated selectivity of the predicates the tag
+    // index can resolve.
+    fn read_strategy(&self, table: &Table<'_>, predicates: &[Predicate<'_>]) -> ReadStrategy {
+        let index_predicates = predicates
+            .iter()
+            .filter(|(_, (op, value))| *op == Operator::Equal && matches!(value, Value::String(_)))
+            .cloned()
+            .collect::<Vec<_>>();
+
+        let rows = table.rows();
+        if index_predicates.is_empty() || rows == 0 {
+            return ReadStrategy::Scan;
+        }
+
+        let selectivity = table.estimate_rows(&index_predicates) / rows as f64;
+        if selectivity > MAX_INDEX_SELECTIVITY {
+            ReadStrategy::Scan
+        } else {
+            ReadStrategy::Index
+        }
+    }
+
     /// Returns vectors of columnar data for the specified column
     /// selections on the specified table name (measurement).
     ///
@@ -172,6 +268,17 @@ impl MetaData {
         todo!()
     }
 
+    pub fn add_segment(&mut self, segment: &Segment<'_>) {
+        self.size += segment.size();
+        self.rows += segment.rows() as u64;
+
+        let (min, max) = segment.time_range();
+        self.time_range = match self.time_range {
+            Some((range_min, range_max)) => Some((min.min(range_min), max.max(range_max))),
+            None => Some((min, max)),
+        };
+    }
+
     // invalidate should be called when a table is removed that impacts the
     // meta data.
     pub fn invalidate(&mut self) {
@@ -179,3 +286,146 @@ impl MetaData {
         todo!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::column::{cmp::Operator, Column, Scalar, Value, ValuesIterator};
+    use crate::segment::{ColumnType, TIME_COLUMN_NAME};
+
+    // The values of the first column in each segment of the results.
+    fn segment_values(results: Vec<(ColumnName<'_>, Vec<Values>)>) -> Vec<Vec<String>> {
+        results[0]
+            .1
+            .iter()
+            .map(|values| ValuesIterator::new(values).map(|v| v.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn read_filter() {
+        let tc = ColumnType::Time(Column::from(&[1_i64, 2, 3][..]));
+        let rc = ColumnType::Tag(Column::from(&["west", "west", "east"][..]));
+        let fc = ColumnType::Field(Column::from(&[100_u64, 101, 200][..]));
+        let mut columns = BTreeMap::new();
+        columns.insert(TIME_COLUMN_NAME, &tc);
+        columns.insert("region", &rc);
+        columns.insert("count", &fc);
+        let segment = Segment::new(3, columns);
+        let mut partition = Partition::new("p1".to_owned(), Table::new("cpu".to_owned(), segment));
+
+        let tc = ColumnType::Time(Column::from(&[4_i64, 5][..]));
+        let rc = ColumnType::Tag(Column::from(&["south", "east"][..]));
+        let fc = ColumnType::Field(Column::from(&[10_u64, 20][..]));
+        let mut columns = BTreeMap::new();
+        columns.insert(TIME_COLUMN_NAME, &tc);
+        columns.insert("region", &rc);
+        columns.insert("count", &fc);
+        partition.add_segment("cpu", Segment::new(2, columns));
+
+        assert_eq!(partition.meta.rows, 5);
+        assert_eq!(partition.meta.time_range, Some((1, 5)));
+
+        let predicates = |region| {
+            vec![
+                (
+                    TIME_COLUMN_NAME,
+                    (Operator::GTE, Value::Scalar(Scalar::I64(0))),
+                ),
+                (
+                    TIME_COLUMN_NAME,
+                    (Operator::LT, Value::Scalar(Scalar::I64(10))),
+                ),
+                ("region", (Operator::Equal, Value::String(region))),
+            ]
+        };
+
+        // only the second segment has "south"
+        let results = partition.read_filter("cpu", &["count"], &predicates("south"));
+        assert_eq!(segment_values(results), vec![vec!["10"]]);
+
+        // both segments have "east"
+        let results = partition.read_filter("cpu", &["count"], &predicates("east"));
+        assert_eq!(segment_values(results), vec![vec!["200"], vec!["20"]]);
+
+        // no segment has "north"
+        let results = partition.read_filter("cpu", &["count"], &predicates("north"));
+        assert!(segment_values(results).is_empty());
+
+        let results = partition.read_filter("mem", &["count"], &predicates("east"));
+        assert!(segment_values(results).is_empty());
+    }
+
+    #[test]
+    fn read_strategy() {
+        let tc = ColumnType::Time(Column::from(&[1_i64, 2, 3][..]));
+        let rc = ColumnType::Tag(Column::from(&["west", "west", "west"][..]));
+        let fc = ColumnType::Field(Column::from(&[100_u64, 101, 200][..]));
+        let mut columns = BTreeMap::new();
+        columns.insert(TIME_COLUMN_NAME, &tc);
+        columns.insert("region", &rc);
+        columns.insert("count", &fc);
+        let segment = Segment::new(3, columns);
+        let mut partition = Partition::new("p1".to_owned(), Table::new("cpu".to_owned(), segment));
+
+        let tc = ColumnType::Time(Column::from(&[4_i64, 5][..]));
+        let rc = ColumnType::Tag(Column::from(&["west", "east"][..]));
+        let fc = ColumnType::Field(Column::from(&[10_u64, 20][..]));
+        let mut columns = BTreeMap::new();
+        columns.insert(TIME_COLUMN_NAME, &tc);
+        columns.insert("region", &rc);
+        columns.insert("count", &fc);
+        partition.add_segment("cpu", Segment::new(2, columns));
+
+        let table = &partition.tables["cpu"];
+        let region = |op, region| vec![("region", (op, Value::String(region)))];
+
+        // 4 of the 5 rows are in the west
+        let predicates = region(Operator::Equal, "west");
+        assert_eq!(
+            partition.read_strategy(table, &predicates),
+            ReadStrategy::Scan
+        );
+        let predicates = region(Operator::Equal, "east");
+        assert_eq!(
+            partition.read_strategy(table, &predicates),
+            ReadStrategy::Index
+        );
+        let predicates = region(Operator::Equal, "north");
+        assert_eq!(
+            partition.read_strategy(table, &predicates),
+            ReadStrategy::Index
+        );
+        // the index can't resolve these
+        let predicates = region(Operator::NotEqual, "west");
+        assert_eq!(
+            partition.read_strategy(table, &predicates),
+            ReadStrategy::Scan
+        );
+        assert_eq!(partition.read_strategy(table, &[]), ReadStrategy::Scan);
+
+        // both strategies read the same rows
+        let time = vec![
+            (
+                TIME_COLUMN_NAME,
+                (Operator::GTE, Value::Scalar(Scalar::I64(0))),
+            ),
+            (
+                TIME_COLUMN_NAME,
+                (Operator::LT, Value::Scalar(Scalar::I64(10))),
+            ),
+        ];
+        let mut predicates = time.clone();
+        predicates.extend(region(Operator::Equal, "west"));
+        let results = partition.read_filter("cpu", &["count"], &predicates);
+        assert_eq!(
+            segment_values(results),
+            vec![vec!["100", "101", "200"], vec!["10"]]
+        );
+
+        let mut predicates = time;
+        predicates.extend(region(Operator::Equal, "east"));
+        let results = partition.read_filter("cpu", &["count"], &predicates);
+        assert_eq!(segment_values(results), vec![vec!["20"]]);
+    }
+}
@@ -1,12 +1,34 @@
-use std::collections::BTreeMap;
-
-use arrow_deps::arrow::datatypes::SchemaRef;
-
-use crate::column::{cmp::Operator, Column, RowIDs, RowIDsOption, Scalar, Value, Values};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::sync::Arc;
+
+use arrow_deps::arrow::{
+    array::{Array, Float64Array, Int64Array, StringArray},
+    compute::{lexsort_to_indices, take, SortColumn},
+    datatypes::{DataType, SchemaRef},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use arrow_deps::datafusion::{
+    logical_plan::{Expr, Operator as ExprOperator},
+    scalar::ScalarValue,
+};
+
+use data_types::pool::Pool;
+
+use crate::column::{
+    cmp::Operator, AggregateResult, AggregateType, Column, EncodedValues, RowIDs, RowIDsOption,
+    Scalar, Value, Values,
+};
+use crate::stats::{ValueCounts, TOP_K};
 
 /// The name used for a timestamp column.
 pub const TIME_COLUMN_NAME: &str = data_types::TIME_COLUMN_NAME;
 
+/// The number of unused row id buffers each segment keeps for reuse.
+const POOLED_ROW_ID_BUFFERS: usize = 4;
+
 #[derive(Debug)]
 pub struct Schema {
     schema_ref: SchemaRef,
@@ -39,9 +61,14 @@ pub struct Segment<'a> {
 
     all_columns: BTreeMap<ColumnName<'a>, &'a Column>,
 
-    tag_columns: Vec<&'a Column>,
+    tag_columns: Vec<(ColumnName<'a>, &'a Column)>,
     field_columns: Vec<&'a Column>,
     time_column: &'a Column,
+
+    // Buffers for the row ids that satisfy predicates, and for the row
+    // ids of rows being materialised, reused across reads.
+    row_ids_pool: Pool<RowIDs>,
+    row_id_vectors_pool: Pool<Vec<u32>>,
 }
 
 impl<'a> Segment<'a> {
@@ -51,7 +78,7 @@ impl<'a> Segment<'a> {
             ..MetaData::default()
         };
 
-        let mut tag_columns: Vec<&'a Column> = vec![];
+        let mut tag_columns: Vec<(ColumnName<'a>, &'a Column)> = vec![];
         let mut field_columns: Vec<&'a Column> = vec![];
         let mut time_column: Option<&'a Column> = None;
         let mut all_columns = BTreeMap::new();
@@ -63,9 +90,10 @@ impl<'a> Segment<'a> {
                 ColumnType::Tag(c) => {
                     assert_eq!(c.num_rows(), rows);
 
-                    tag_columns.push(&c);
+                    tag_columns.push((name, &c));
+                    meta.value_counts.insert(name, ValueCounts::new(c, TOP_K));
 
-                    if let Some(range) = tag_columns.last().unwrap().column_range() {
+                    if let Some(range) = c.column_range() {
                         meta.column_ranges.insert(name, range);
                     }
                     all_columns.insert(name, c);
@@ -113,6 +141,8 @@ impl<'a> Segment<'a> {
             tag_columns,
             field_columns,
             time_column: time_column.unwrap(),
+            row_ids_pool: Pool::new(POOLED_ROW_ID_BUFFERS),
+            row_id_vectors_pool: Pool::new(POOLED_ROW_ID_BUFFERS),
         }
     }
 
@@ -137,6 +167,32 @@ impl<'a> Segment<'a> {
         self.meta.time_range
     }
 
+    /// The values of each tag column in the segment, from the column's
+    /// dictionary. These can include values that no row has if the
+    /// dictionary was built from them.
+    pub fn tag_values(&self) -> Vec<(ColumnName<'a>, Vec<&'a String>)> {
+        self.tag_columns
+            .iter()
+            .map(|&(name, column)| (name, column.dictionary()))
+            .collect()
+    }
+
+    /// Estimates the fraction of the segment's rows that satisfy the provided
+    /// predicate on the provided column, from the value counts of tag
+    /// columns. Predicates that can't be estimated are assumed to be
+    /// satisfied by every row.
+    pub fn predicate_selectivity(
+        &self,
+        column_name: ColumnName<'_>,
+        predicate: &(Operator, Value<'_>),
+    ) -> f64 {
+        self.meta
+            .value_counts
+            .get(column_name)
+            .and_then(|counts| counts.selectivity(predicate))
+            .unwrap_or(1.0)
+    }
+
     /// Efficiently determine if the provided predicate might be satisfied by
     /// the provided column.
     pub fn column_could_satisfy_predicate(
@@ -171,33 +227,152 @@ impl<'a> Segment<'a> {
         row_ids: RowIDsOption,
     ) -> Vec<(ColumnName<'a>, Values)> {
         let mut results = vec![];
-        match row_ids {
-            RowIDsOption::None(_) => results, // nothing to materialise
+        let mut row_id_vector = self.row_id_vectors_pool.get();
+        let buffer = match row_ids {
+            RowIDsOption::None(buffer) => buffer, // nothing to materialise
             RowIDsOption::Some(row_ids) => {
-                // TODO(edd): causes an allocation. Implement a way to pass a pooled
-                // buffer to the croaring Bitmap API.
-                let row_ids = row_ids.to_vec();
+                row_ids.append_to(&mut row_id_vector);
 
                 for col_name in columns {
                     let col = self.all_columns.get(*col_name).unwrap();
-                    results.push((*col_name, col.values(row_ids.as_slice())));
+                    results.push((*col_name, col.values(row_id_vector.as_slice())));
                 }
-                results
+                row_ids
             }
 
-            RowIDsOption::All(_) => {
-                // TODO(edd): Perf - add specialised method to get all
-                // materialised values from a column without having to
-                // materialise a vector of row ids.......
-                let row_ids = (0..self.rows()).collect::<Vec<_>>();
-
+            RowIDsOption::All(buffer) => {
+                // Only the columns that can't materialise all their values
+                // directly need the vector of every row id.
                 for col_name in columns {
                     let col = self.all_columns.get(*col_name).unwrap();
-                    results.push((*col_name, col.values(row_ids.as_slice())));
+                    let values = match col.all_values() {
+                        Some(values) => values,
+                        None => {
+                            if row_id_vector.is_empty() {
+                                row_id_vector.extend(0..self.rows());
+                            }
+                            col.values(row_id_vector.as_slice())
+                        }
+                    };
+                    results.push((*col_name, values));
                 }
-                results
+                buffer
+            }
+        };
+
+        self.row_ids_pool.put(buffer);
+        results
+    }
+
+    /// Returns aggregates of the rows that satisfy a set of predicates,
+    /// grouped by the values of `group_columns`, which must be string (tag)
+    /// columns.
+    ///
+    /// Rows are grouped on the encoded dictionary ids of the group columns,
+    /// and the ids of each group are only decoded when the results are
+    /// produced. NULL values are grouped as the empty string.
+    pub fn read_group(
+        &self,
+        predicates: &[Predicate<'_>],
+        group_columns: &[ColumnName<'a>],
+        aggregates: &[(ColumnName<'a>, AggregateType)],
+    ) -> BTreeMap<GroupKey, Vec<(ColumnName<'a>, AggregateResult<'a>)>> {
+        self.read_group_window(predicates, group_columns, aggregates, 0)
+            .into_iter()
+            .map(|((group_key, _), results)| (group_key, results))
+            .collect()
+    }
+
+    /// Returns aggregates of the rows that satisfy a set of predicates,
+    /// grouped as `read_group` does and windowed by time. Each group is keyed
+    /// on its group key and the start of its window.
+    ///
+    /// `window` is the interval of the windows in nanoseconds. Windows start
+    /// at multiples of the interval; if it is `0` every row is in a single
+    /// window, which starts at `0`.
+    pub fn read_group_window(
+        &self,
+        predicates: &[Predicate<'_>],
+        group_columns: &[ColumnName<'a>],
+        aggregates: &[(ColumnName<'a>, AggregateType)],
+        window: i64,
+    ) -> BTreeMap<(GroupKey, i64), Vec<(ColumnName<'a>, AggregateResult<'a>)>> {
+        let mut row_ids = self.row_id_vectors_pool.get();
+        let buffer = match self.row_ids_from_predicates(predicates) {
+            RowIDsOption::None(buffer) => buffer,
+            RowIDsOption::Some(buffer) => {
+                buffer.append_to(&mut row_ids);
+                buffer
+            }
+            RowIDsOption::All(buffer) => {
+                row_ids.extend(0..self.rows());
+                buffer
+            }
+        };
+        self.row_ids_pool.put(buffer);
+
+        // The encoded ids of each group column at each of the matching rows.
+        let group_column_ids = group_columns
+            .iter()
+            .map(|col_name| {
+                let col = self.all_columns.get(*col_name).unwrap();
+                let dst = EncodedValues::U32(Vec::with_capacity(row_ids.len()));
+                match col.encoded_values(row_ids.as_slice(), dst) {
+                    EncodedValues::U32(ids) => ids,
+                    EncodedValues::I64(_) => unreachable!("tag columns are encoded as u32 ids"),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // The row ids of each window of each group, keyed on the encoded ids
+        // of its values. The key buffer is reused, so a key is only allocated
+        // per group.
+        let mut groups: HashMap<Vec<u32>, BTreeMap<i64, Vec<u32>>> = HashMap::new();
+        let mut key = Vec::with_capacity(group_columns.len());
+        for (i, row_id) in row_ids.iter().enumerate() {
+            key.clear();
+            key.extend(group_column_ids.iter().map(|ids| ids[i]));
+
+            let window_start = match self.time_column.value(*row_id) {
+                Value::Scalar(Scalar::I64(time)) if window > 0 => time - time.rem_euclid(window),
+                _ => 0,
+            };
+
+            match groups.get_mut(&key) {
+                Some(windows) => windows.entry(window_start).or_default().push(*row_id),
+                None => {
+                    let mut windows = BTreeMap::new();
+                    windows.insert(window_start, vec![*row_id]);
+                    groups.insert(key.clone(), windows);
+                }
+            }
+        }
+
+        let mut results = BTreeMap::new();
+        for (key, windows) in groups {
+            let group_key: GroupKey = group_columns
+                .iter()
+                .zip(key)
+                .map(|(col_name, id)| {
+                    let col = self.all_columns.get(*col_name).unwrap();
+                    col.decode_id(id).unwrap_or_default()
+                })
+                .collect();
+
+            for (window_start, group_row_ids) in windows {
+                let aggs = aggregates
+                    .iter()
+                    .map(|(col_name, agg_type)| {
+                        let col = self.all_columns.get(*col_name).unwrap();
+                        let result =
+                            aggregate_rows(*col, self.time_column, agg_type, &group_row_ids);
+                        (*col_name, result)
+                    })
+                    .collect();
+                results.insert((group_key.clone(), window_start), aggs);
             }
         }
+        results
     }
 
     // Determines the set of row ids that satisfy the time range and all of the
@@ -208,11 +383,10 @@ impl<'a> Segment<'a> {
     // eventually we should be able to express the time range as just another
     // one or two predicates.
     fn row_ids_from_predicates(&self, predicates: &[Predicate<'_>]) -> RowIDsOption {
-        // TODO(edd): perf - pool the dst buffer so we can re-use it across
-        // subsequent calls to `row_ids_from_predicates`.
-        // Right now this buffer will be re-used across all columns in the
-        // segment with predicates.
-        let mut dst = RowIDs::new_bitmap();
+        // The dst buffer is re-used across all columns in the segment with
+        // predicates, and taken from the segment's pool so it is re-used
+        // across subsequent calls to `row_ids_from_predicates` too.
+        let mut dst = self.row_ids_pool.get().into_inner();
 
         // find the time range predicates and execute a specialised range based
         // row id lookup.
@@ -228,13 +402,16 @@ impl<'a> Segment<'a> {
             dst,
         );
 
-        // TODO(edd): potentially pass this in so we can re-use it once we
-        // have materialised any results.
-        let mut result_row_ids = RowIDs::new_bitmap();
+        // This buffer is handed back to the pool once any results have
+        // been materialised.
+        let mut result_row_ids = self.row_ids_pool.get().into_inner();
 
         match time_row_ids {
             // No matching rows based on time range - return buffer
-            RowIDsOption::None(_) => return time_row_ids,
+            RowIDsOption::None(_) => {
+                self.row_ids_pool.put(result_row_ids);
+                return time_row_ids;
+            }
 
             // all rows match - continue to apply predicates
             RowIDsOption::All(_dst) => {
@@ -249,13 +426,27 @@ impl<'a> Segment<'a> {
             }
         }
 
-        for (col_name, (op, value)) in predicates {
-            if col_name == &TIME_COLUMN_NAME {
-                continue; // we already processed the time column as a special case.
-            }
+        // Evaluate the most selective predicates first, so the fewest rows
+        // are carried through the intersections and a predicate no row
+        // satisfies is found as early as possible.
+        let mut predicates = predicates
+            .iter()
+            .filter(|(col_name, _)| col_name != &TIME_COLUMN_NAME)
+            .map(|(col_name, pred)| (self.predicate_selectivity(col_name, pred), col_name, pred))
+            .collect::<Vec<_>>();
+        predicates
+            .sort_by(|(a, _, _), (b, _, _)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+        for (_, col_name, (op, value)) in predicates {
             // N.B column should always exist because validation of
-            // predicates should happen at the `Table` level.
-            let col = self.all_columns.get(*col_name).unwrap();
+            // predicates should happen at the `Table` level, except for
+            // `IS NULL` predicates, which every row satisfies when the
+            // segment doesn't have the column.
+            let col = match self.all_columns.get(*col_name) {
+                Some(col) => col,
+                None if *op == Operator::Equal && *value == Value::Null => continue,
+                None => panic!("predicate on unknown column {}", col_name),
+            };
 
             // Explanation of how this buffer pattern works here. The idea is
             // that the buffer should be returned to the caller so it can be
@@ -264,7 +455,10 @@ impl<'a> Segment<'a> {
             match col.row_ids_filter(op, value, dst) {
                 // No rows will be returned for the segment because this column
                 // doe not match any rows.
-                RowIDsOption::None(_dst) => return RowIDsOption::None(_dst),
+                RowIDsOption::None(_dst) => {
+                    self.row_ids_pool.put(result_row_ids);
+                    return RowIDsOption::None(_dst);
+                }
 
                 // Intersect the row ids found at this column with all those
                 // found on other column predicates.
@@ -284,6 +478,7 @@ impl<'a> Segment<'a> {
             }
         }
 
+        self.row_ids_pool.put(dst);
         if result_row_ids.is_empty() {
             // All rows matched all predicates - return the empty buffer.
             return RowIDsOption::All(result_row_ids);
@@ -292,10 +487,403 @@ impl<'a> Segment<'a> {
     }
 }
 
+// The aggregate of the values of `column` at the provided rows, whose
+// timestamps are in `time_column`.
+fn aggregate_rows<'a>(
+    column: &'a Column,
+    time_column: &'a Column,
+    agg_type: &AggregateType,
+    row_ids: &[u32],
+) -> AggregateResult<'a> {
+    match agg_type {
+        AggregateType::Count => AggregateResult::Count(column.count(row_ids) as u64),
+        AggregateType::Min => AggregateResult::Min(column.min(row_ids)),
+        AggregateType::Max => AggregateResult::Max(column.max(row_ids)),
+        AggregateType::Sum => AggregateResult::Sum(match column.sum(row_ids) {
+            Value::Scalar(sum) => Some(sum),
+            _ => None,
+        }),
+        AggregateType::First => AggregateResult::First(
+            timestamped_rows(time_column, row_ids)
+                .min()
+                .map(|(time, row_id)| (time, column.value(row_id))),
+        ),
+        AggregateType::Last => AggregateResult::Last(
+            timestamped_rows(time_column, row_ids)
+                .max()
+                .map(|(time, row_id)| (time, column.value(row_id))),
+        ),
+    }
+}
+
+// The timestamp of each of the provided rows, paired with the row id. Rows
+// with the same timestamp are ordered by row id, so the first and last rows
+// are stable.
+fn timestamped_rows<'a>(
+    time_column: &'a Column,
+    row_ids: &'a [u32],
+) -> impl Iterator<Item = (i64, u32)> + 'a {
+    row_ids
+        .iter()
+        .filter_map(move |&row_id| match time_column.value(row_id) {
+            Value::Scalar(Scalar::I64(time)) => Some((time, row_id)),
+            _ => None,
+        })
+}
+
 pub type Predicate<'a> = (ColumnName<'a>, (Operator, Value<'a>));
 
+/// Converts a DataFusion expression into a `Predicate` on a single column,
+/// if it has one of the following forms:
+///
+///   * `column <op> literal`, for `=`, `!=`, `<`, `<=`, `>` and `>=`;
+///   * `lower(column) = 'value'`, where `value` is lower case, which is
+///     evaluated as a case-insensitive comparison against the dictionary;
+///   * `column IS NULL` and `column IS NOT NULL`.
+///
+/// Any other expression returns `None`.
+///
+/// This is only for reads of segments, which no query of the server makes
+/// yet. gRPC queries are answered from the write buffer, which looks up the
+/// values of `lower(column) = 'value'` in the dictionary of each partition.
+pub(crate) fn predicate_from_expr(expr: &Expr) -> Option<Predicate<'_>> {
+    match expr {
+        Expr::IsNull(expr) => Some((column_name(expr)?, (Operator::Equal, Value::Null))),
+        Expr::IsNotNull(expr) => Some((column_name(expr)?, (Operator::NotEqual, Value::Null))),
+        Expr::BinaryExpr { left, op, right } => {
+            let value = match right.as_ref() {
+                Expr::Literal(value) => literal_value(value)?,
+                _ => return None,
+            };
+
+            if let Some(column) = column_name(left) {
+                let op = match op {
+                    ExprOperator::Eq => Operator::Equal,
+                    ExprOperator::NotEq => Operator::NotEqual,
+                    ExprOperator::Gt => Operator::GT,
+                    ExprOperator::GtEq => Operator::GTE,
+                    ExprOperator::Lt => Operator::LT,
+                    ExprOperator::LtEq => Operator::LTE,
+                    _ => return None,
+                };
+                return Some((column, (op, value)));
+            }
+
+            // `lower(column)` can never equal a value with upper case
+            // characters, so only lower case values can ignore case.
+            match (lowered_column_name(left)?, op, value) {
+                (column, ExprOperator::Eq, Value::String(s)) if s.to_lowercase() == s => {
+                    Some((column, (Operator::EqualIgnoreCase, Value::String(s))))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+// The name of the column `expr` refers to.
+fn column_name(expr: &Expr) -> Option<ColumnName<'_>> {
+    match expr {
+        Expr::Column(name) => Some(name.as_str()),
+        _ => None,
+    }
+}
+
+// The name of the column `expr` refers to if it is `lower(column)`.
+fn lowered_column_name(expr: &Expr) -> Option<ColumnName<'_>> {
+    match expr {
+        Expr::ScalarFunction { fun, args } if fun.to_string() == "lower" => match args.as_slice() {
+            [arg] => column_name(arg),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// The value of a literal if it has a type columns can be compared with.
+fn literal_value(value: &ScalarValue) -> Option<Value<'_>> {
+    match value {
+        ScalarValue::Utf8(Some(v)) => Some(Value::String(v.as_str())),
+        ScalarValue::Boolean(Some(v)) => Some(Value::Boolean(*v)),
+        ScalarValue::Int64(Some(v)) => Some(Value::Scalar(Scalar::I64(*v))),
+        ScalarValue::UInt64(Some(v)) => Some(Value::Scalar(Scalar::U64(*v))),
+        ScalarValue::Float64(Some(v)) => Some(Value::Scalar(Scalar::F64(*v))),
+        _ => None,
+    }
+}
+
+/// The error returned when a record batch can't be converted into the
+/// columns of a segment.
+#[derive(Debug)]
+pub enum Error {
+    /// A segment must have at least one row, and at most `u32::MAX`.
+    RowCount {
+        rows: usize,
+    },
+
+    MissingTimeColumn,
+
+    InvalidTimeColumn {
+        data_type: DataType,
+    },
+
+    /// The time column has NULL values in `count` rows, the first of
+    /// which are `rows`.
+    NullTimestamps {
+        count: usize,
+        rows: Vec<usize>,
+    },
+
+    UnknownSortColumn {
+        column: String,
+    },
+
+    InvalidSortColumn {
+        column: String,
+        data_type: DataType,
+    },
+
+    UnsupportedType {
+        column: String,
+        data_type: DataType,
+    },
+
+    NullValues {
+        column: String,
+    },
+
+    Sorting {
+        source: ArrowError,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RowCount { rows } => write!(
+                f,
+                "segments must have between 1 and {} rows, got {}",
+                u32::MAX,
+                rows
+            ),
+            Self::MissingTimeColumn => {
+                write!(f, "record batch has no {} column", TIME_COLUMN_NAME)
+            }
+            Self::InvalidTimeColumn { data_type } => write!(
+                f,
+                "{} column must be of type Int64, got {:?}",
+                TIME_COLUMN_NAME, data_type
+            ),
+            Self::NullTimestamps { count, rows } => write!(
+                f,
+                "{} column has NULL values in {} rows, including rows {:?}",
+                TIME_COLUMN_NAME, count, rows
+            ),
+            Self::UnknownSortColumn { column } => {
+                write!(f, "sort key column {} is not in the record batch", column)
+            }
+            Self::InvalidSortColumn { column, data_type } => write!(
+                f,
+                "sort key column {} must be a Utf8 tag column or the {} column, got {:?}",
+                column, TIME_COLUMN_NAME, data_type
+            ),
+            Self::UnsupportedType { column, data_type } => write!(
+                f,
+                "column {} has type {:?}, which has no segment encoding",
+                column, data_type
+            ),
+            Self::NullValues { column } => write!(
+                f,
+                "column {} has NULL values, which its encoding can't hold",
+                column
+            ),
+            Self::Sorting { source } => write!(f, "error sorting record batch: {}", source),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Sorting { source } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The most rows a `NullTimestamps` error lists.
+const MAX_REPORTED_ROWS: usize = 10;
+
+/// The columns the rows of a segment are sorted by, from the first to the
+/// last. The columns of a sort key are the tag columns of the segment, and
+/// rows are finally sorted by time unless the key names the time column.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SortKey(Vec<String>);
+
+impl SortKey {
+    pub fn new<I, S>(columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Self(columns.into_iter().map(Into::into).collect())
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.0
+    }
+
+    fn contains(&self, column: &str) -> bool {
+        self.0.iter().any(|c| c == column)
+    }
+}
+
+/// The columns of a segment, converted from a record batch.
+///
+/// A `Segment` borrows its columns, so they are owned here and `segment`
+/// returns a segment over them.
+pub struct SegmentColumns {
+    rows: u32,
+    sort_key: SortKey,
+    columns: BTreeMap<String, ColumnType>,
+}
+
+impl SegmentColumns {
+    /// The sort key the rows were sorted by.
+    pub fn sort_key(&self) -> &SortKey {
+        &self.sort_key
+    }
+
+    pub fn segment(&self) -> Segment<'_> {
+        let columns = self
+            .columns
+            .iter()
+            .map(|(name, column)| (name.as_str(), column))
+            .collect();
+        Segment::new(self.rows, columns)
+    }
+}
+
+/// Converts a record batch into the columns of a segment, sorting its rows by
+/// the sort key.
+///
+/// Columns are encoded by their Arrow type:
+///
+/// * `Utf8` columns named in the sort key are dictionary encoded tag columns,
+///   and other `Utf8` columns are dictionary encoded field columns.
+/// * `Int64` columns are integer field columns, which may have NULL values.
+/// * `Float64` columns are float field columns, which may not.
+/// * The time column must be of `Int64` timestamps with no NULL values.
+///
+/// Other types, including `Boolean`, have no segment encoding yet and are
+/// rejected.
+impl TryFrom<(RecordBatch, SortKey)> for SegmentColumns {
+    type Error = Error;
+
+    fn try_from((rb, sort_key): (RecordBatch, SortKey)) -> Result<Self> {
+        let rows = rb.num_rows();
+        if rows == 0 || rows > u32::MAX as usize {
+            return Err(Error::RowCount { rows });
+        }
+
+        let schema = rb.schema();
+        let time_index = schema
+            .index_of(TIME_COLUMN_NAME)
+            .map_err(|_| Error::MissingTimeColumn)?;
+        let time = rb.column(time_index);
+        if time.data_type() != &DataType::Int64 {
+            return Err(Error::InvalidTimeColumn {
+                data_type: time.data_type().clone(),
+            });
+        }
+        if time.null_count() > 0 {
+            let null_rows = (0..rows).filter(|&i| time.is_null(i));
+            return Err(Error::NullTimestamps {
+                count: time.null_count(),
+                rows: null_rows.take(MAX_REPORTED_ROWS).collect(),
+            });
+        }
+
+        let mut sort_columns = Vec::with_capacity(sort_key.columns().len() + 1);
+        for column in sort_key.columns() {
+            let index = schema
+                .index_of(column)
+                .map_err(|_| Error::UnknownSortColumn {
+                    column: column.clone(),
+                })?;
+            let values = rb.column(index);
+            if index != time_index && values.data_type() != &DataType::Utf8 {
+                return Err(Error::InvalidSortColumn {
+                    column: column.clone(),
+                    data_type: values.data_type().clone(),
+                });
+            }
+            sort_columns.push(SortColumn {
+                values: Arc::clone(values),
+                options: None,
+            });
+        }
+        if !sort_key.contains(TIME_COLUMN_NAME) {
+            sort_columns.push(SortColumn {
+                values: Arc::clone(time),
+                options: None,
+            });
+        }
+        let indices =
+            lexsort_to_indices(&sort_columns).map_err(|source| Error::Sorting { source })?;
+
+        let mut columns = BTreeMap::new();
+        for (field, values) in schema.fields().iter().zip(rb.columns()) {
+            let name = field.name();
+            let values =
+                take(values, &indices, None).map_err(|source| Error::Sorting { source })?;
+
+            let column = match values.data_type() {
+                _ if name == TIME_COLUMN_NAME => {
+                    let values = Int64Array::from(values.data());
+                    ColumnType::Time(Column::from(values.value_slice(0, rows)))
+                }
+                DataType::Utf8 => {
+                    let values = Column::from(StringArray::from(values.data()));
+                    if sort_key.contains(name) {
+                        ColumnType::Tag(values)
+                    } else {
+                        ColumnType::Field(values)
+                    }
+                }
+                DataType::Int64 => ColumnType::Field(Column::from(Int64Array::from(values.data()))),
+                DataType::Float64 => {
+                    if values.null_count() > 0 {
+                        return Err(Error::NullValues {
+                            column: name.clone(),
+                        });
+                    }
+                    let values = Float64Array::from(values.data());
+                    ColumnType::Field(Column::from(values.value_slice(0, rows)))
+                }
+                data_type => {
+                    return Err(Error::UnsupportedType {
+                        column: name.clone(),
+                        data_type: data_type.clone(),
+                    })
+                }
+            };
+            columns.insert(name.clone(), column);
+        }
+
+        Ok(Self {
+            rows: rows as u32,
+            sort_key,
+            columns,
+        })
+    }
+}
+
 // A GroupKey is an ordered collection of row values. The order determines which
-// columns the values originated from.
+// columns the values originated from. NULL values are the empty string.
 pub type GroupKey = Vec<String>;
 
 // A representation of a column name.
@@ -356,6 +944,10 @@ struct MetaData<'a> {
     // This can be used to skip the table entirely if the time range for a query
     // falls outside of this range.
     time_range: (i64, i64),
+
+    // The counts of the most frequent values of each tag column, used to
+    // estimate the selectivity of predicates on them.
+    value_counts: BTreeMap<ColumnName<'a>, ValueCounts>,
 }
 
 impl MetaData<'_> {
@@ -368,12 +960,41 @@ impl MetaData<'_> {
         column_name: ColumnName<'_>,
         predicate: &(Operator, Value<'_>),
     ) -> bool {
+        let (op, value) = predicate;
+
+        // The column ranges don't record NULL values, so any segment could
+        // satisfy `column IS NULL` or `column IS NOT NULL`, other than one
+        // without the column, where every row is NULL.
+        if let Value::Null = value {
+            let has_column = self.column_ranges.contains_key(column_name);
+            return match op {
+                Operator::Equal => true,
+                Operator::NotEqual => has_column,
+                _ => false,
+            };
+        }
+
         let (column_min, column_max) = match self.column_ranges.get(column_name) {
             Some(range) => range,
             None => return false, // column doesn't exist.
         };
 
-        let (op, value) = predicate;
+        // Numeric values of different types are compared as floats.
+        let coerced;
+        let (column_min, column_max, value) = match (column_min, column_max, value) {
+            (Value::Scalar(min), Value::Scalar(max), Value::Scalar(v))
+                if std::mem::discriminant(min) != std::mem::discriminant(v) =>
+            {
+                coerced = (
+                    Value::Scalar(Scalar::F64(min.to_f64())),
+                    Value::Scalar(Scalar::F64(max.to_f64())),
+                    Value::Scalar(Scalar::F64(v.to_f64())),
+                );
+                (&coerced.0, &coerced.1, &coerced.2)
+            }
+            _ => (column_min, column_max, value),
+        };
+
         match op {
             // If the column range covers the value then it could contain that value.
             Operator::Equal => column_min <= value && value <= column_max,
@@ -397,6 +1018,10 @@ impl MetaData<'_> {
             // if the column min is at least as small as value then the column
             // could contain the value.
             Operator::LTE => column_min <= value,
+
+            // The column range is ordered by case, so values outside of it
+            // could still be equal when case is ignored.
+            Operator::EqualIgnoreCase => true,
         }
     }
 }
@@ -466,6 +1091,101 @@ mod test {
         arr
     }
 
+    #[test]
+    fn from_record_batch() {
+        use arrow_deps::arrow::{
+            array::BooleanArray,
+            datatypes::{Field, Schema as ArrowSchema},
+        };
+
+        let schema = Arc::new(ArrowSchema::new(vec![
+            Field::new("region", DataType::Utf8, true),
+            Field::new("host", DataType::Utf8, true),
+            Field::new("count", DataType::Int64, true),
+            Field::new("temp", DataType::Float64, false),
+            Field::new(TIME_COLUMN_NAME, DataType::Int64, false),
+        ]));
+        let rb = RecordBatch::try_new(
+            Arc::clone(&schema),
+            vec![
+                Arc::new(StringArray::from(vec!["west", "east", "west", "east"])),
+                Arc::new(StringArray::from(vec!["a", "b", "c", "d"])),
+                Arc::new(Int64Array::from(vec![Some(1), Some(2), None, Some(4)])),
+                Arc::new(Float64Array::from(vec![1.5, 2.5, 3.5, 4.5])),
+                Arc::new(Int64Array::from(vec![40, 30, 20, 10])),
+            ],
+        )
+        .unwrap();
+
+        let columns = SegmentColumns::try_from((rb.clone(), SortKey::new(vec!["region"]))).unwrap();
+        let segment = columns.segment();
+        assert_eq!(segment.rows(), 4);
+        assert_eq!(segment.time_range(), (10, 40));
+
+        // only the columns in the sort key are tags
+        let tag_values = segment.tag_values();
+        assert_eq!(tag_values.len(), 1);
+        assert_eq!(tag_values[0].0, "region");
+
+        // rows are sorted by region and then time
+        let results = segment.read_filter(
+            &["count", "host", "region", "temp", "time"],
+            &build_predicates(0, 100, vec![]),
+        );
+        let expected = "count,host,region,temp,time
+4,d,east,4.5,10
+2,b,east,2.5,30
+NULL,c,west,3.5,20
+1,a,west,1.5,40";
+        assert_eq!(stringify_read_filter_results(results), expected);
+
+        let err = SegmentColumns::try_from((rb.clone(), SortKey::new(vec!["dc"]))).unwrap_err();
+        assert!(matches!(err, Error::UnknownSortColumn { column } if column == "dc"));
+        let err = SegmentColumns::try_from((rb, SortKey::new(vec!["count"]))).unwrap_err();
+        assert!(matches!(err, Error::InvalidSortColumn { .. }));
+
+        // the time column is required
+        let rb = RecordBatch::try_new(
+            Arc::new(ArrowSchema::new(vec![schema.field(0).clone()])),
+            vec![Arc::new(StringArray::from(vec!["west"]))],
+        )
+        .unwrap();
+        let err = SegmentColumns::try_from((rb, SortKey::default())).unwrap_err();
+        assert!(matches!(err, Error::MissingTimeColumn));
+
+        let rb = RecordBatch::try_new(
+            Arc::new(ArrowSchema::new(vec![Field::new(
+                TIME_COLUMN_NAME,
+                DataType::Int64,
+                true,
+            )])),
+            vec![Arc::new(Int64Array::from(vec![
+                Some(1),
+                None,
+                Some(3),
+                None,
+            ]))],
+        )
+        .unwrap();
+        let err = SegmentColumns::try_from((rb, SortKey::default())).unwrap_err();
+        assert!(matches!(err, Error::NullTimestamps { count: 2, rows } if rows == vec![1, 3]));
+
+        // booleans have no encoding yet
+        let rb = RecordBatch::try_new(
+            Arc::new(ArrowSchema::new(vec![
+                Field::new("active", DataType::Boolean, false),
+                Field::new(TIME_COLUMN_NAME, DataType::Int64, false),
+            ])),
+            vec![
+                Arc::new(BooleanArray::from(vec![true])),
+                Arc::new(Int64Array::from(vec![1])),
+            ],
+        )
+        .unwrap();
+        let err = SegmentColumns::try_from((rb, SortKey::default())).unwrap_err();
+        assert!(matches!(err, Error::UnsupportedType { column, .. } if column == "active"));
+    }
+
     #[test]
     fn read_filter() {
         let mut columns = BTreeMap::new();
@@ -555,6 +1275,144 @@ west,4";
         assert_eq!(stringify_read_filter_results(results), expected);
     }
 
+    #[test]
+    fn read_group() {
+        let mut columns = BTreeMap::new();
+        let tc = ColumnType::Time(Column::from(&[1_i64, 2, 3, 4, 5, 6][..]));
+        columns.insert("time", &tc);
+
+        let rc = ColumnType::Tag(Column::from(
+            &[
+                Some("west"),
+                Some("west"),
+                Some("east"),
+                Some("west"),
+                None,
+                Some("east"),
+            ][..],
+        ));
+        columns.insert("region", &rc);
+
+        let mc = ColumnType::Tag(Column::from(
+            &["GET", "POST", "POST", "POST", "PUT", "GET"][..],
+        ));
+        columns.insert("method", &mc);
+
+        let fc = ColumnType::Field(Column::from(&[100_u64, 101, 200, 203, 203, 10][..]));
+        columns.insert("count", &fc);
+
+        let segment = Segment::new(6, columns);
+
+        let results = segment.read_group(
+            &build_predicates(1, 7, vec![]),
+            &["region"],
+            &[
+                ("count", AggregateType::Sum),
+                ("count", AggregateType::Count),
+            ],
+        );
+        let results = results
+            .into_iter()
+            .map(|(key, aggs)| (key.join(","), aggs))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                (
+                    "".to_string(),
+                    vec![
+                        ("count", AggregateResult::Sum(Some(Scalar::U64(203)))),
+                        ("count", AggregateResult::Count(1)),
+                    ]
+                ),
+                (
+                    "east".to_string(),
+                    vec![
+                        ("count", AggregateResult::Sum(Some(Scalar::U64(210)))),
+                        ("count", AggregateResult::Count(2)),
+                    ]
+                ),
+                (
+                    "west".to_string(),
+                    vec![
+                        ("count", AggregateResult::Sum(Some(Scalar::U64(404)))),
+                        ("count", AggregateResult::Count(3)),
+                    ]
+                ),
+            ]
+        );
+
+        let results = segment.read_group(
+            &build_predicates(
+                1,
+                7,
+                vec![("method", (Operator::Equal, Value::String("POST")))],
+            ),
+            &["region", "method"],
+            &[("count", AggregateType::Max)],
+        );
+        let results = results
+            .into_iter()
+            .map(|(key, aggs)| (key.join(","), aggs))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            vec![
+                (
+                    "east,POST".to_string(),
+                    vec![(
+                        "count",
+                        AggregateResult::Max(Value::Scalar(Scalar::U64(200)))
+                    )]
+                ),
+                (
+                    "west,POST".to_string(),
+                    vec![(
+                        "count",
+                        AggregateResult::Max(Value::Scalar(Scalar::U64(203)))
+                    )]
+                ),
+            ]
+        );
+
+        let results = segment.read_group(
+            &build_predicates(1, 7, vec![]),
+            &["region"],
+            &[
+                ("count", AggregateType::First),
+                ("count", AggregateType::Last),
+            ],
+        );
+        let first_last = |first: (i64, u64), last: (i64, u64)| {
+            vec![
+                (
+                    "count",
+                    AggregateResult::First(Some((first.0, Value::Scalar(Scalar::U64(first.1))))),
+                ),
+                (
+                    "count",
+                    AggregateResult::Last(Some((last.0, Value::Scalar(Scalar::U64(last.1))))),
+                ),
+            ]
+        };
+        assert_eq!(
+            results.into_iter().collect::<Vec<_>>(),
+            vec![
+                (vec!["".to_string()], first_last((5, 203), (5, 203))),
+                (vec!["east".to_string()], first_last((3, 200), (6, 10))),
+                (vec!["west".to_string()], first_last((1, 100), (4, 203))),
+            ]
+        );
+
+        // no rows match
+        let results = segment.read_group(
+            &build_predicates(10, 20, vec![]),
+            &["region"],
+            &[("count", AggregateType::Count)],
+        );
+        assert!(results.is_empty());
+    }
+
     #[test]
     fn segment_could_satisfy_predicate() {
         let mut columns = BTreeMap::new();
@@ -601,6 +1459,36 @@ west,4";
             ("region", &(Operator::LTE, Value::String("north")), true), // region column might contain something ≤ "north"
             ("region", &(Operator::LTE, Value::String("south")), true), // region column might contain something ≤ "south"
             ("region", &(Operator::LTE, Value::String("abc")), false), // region column can't contain something ≤ "abc"
+            ("region", &(Operator::Equal, Value::Null), true), // region column might contain NULL
+            ("region", &(Operator::NotEqual, Value::Null), true), // region column might contain a value
+            ("region", &(Operator::GT, Value::Null), false),      // nothing is > NULL
+            ("az", &(Operator::Equal, Value::Null), true), // no az column so every row is NULL
+            ("az", &(Operator::NotEqual, Value::Null), false), // no az column so no row has a value
+            (
+                "region",
+                &(Operator::EqualIgnoreCase, Value::String("WEST")),
+                true,
+            ), // region column might contain "west"
+            (
+                "az",
+                &(Operator::EqualIgnoreCase, Value::String("west")),
+                false,
+            ), // no az column
+            (
+                "time",
+                &(Operator::GT, Value::Scalar(Scalar::F64(5.5))),
+                true,
+            ), // time column might contain something > 5.5
+            (
+                "time",
+                &(Operator::GT, Value::Scalar(Scalar::F64(6.5))),
+                false,
+            ), // time column can't contain something > 6.5
+            (
+                "time",
+                &(Operator::LT, Value::Scalar(Scalar::U64(1))),
+                false,
+            ), // time column can't contain something < 1
         ];
 
         for (column_name, predicate, exp) in cases {
@@ -613,4 +1501,54 @@ west,4";
             );
         }
     }
+
+    #[test]
+    fn predicate_from_expr() {
+        use arrow_deps::datafusion::logical_plan::{col, lit};
+        use arrow_deps::datafusion::physical_plan::functions::BuiltinScalarFunction;
+        use std::str::FromStr;
+
+        let lower = |expr| Expr::ScalarFunction {
+            fun: BuiltinScalarFunction::from_str("lower").unwrap(),
+            args: vec![expr],
+        };
+
+        let cases = vec![
+            (
+                col("region").eq(lit("west")),
+                Some(("region", (Operator::Equal, Value::String("west")))),
+            ),
+            (
+                col("count").gt_eq(lit(10_i64)),
+                Some(("count", (Operator::GTE, Value::Scalar(Scalar::I64(10))))),
+            ),
+            (
+                lower(col("host")).eq(lit("server-a")),
+                Some((
+                    "host",
+                    (Operator::EqualIgnoreCase, Value::String("server-a")),
+                )),
+            ),
+            // lower(host) can't be equal to a value with upper case
+            (lower(col("host")).eq(lit("Server-A")), None),
+            (lower(col("host")).not_eq(lit("server-a")), None),
+            (
+                Expr::IsNull(Box::new(col("host"))),
+                Some(("host", (Operator::Equal, Value::Null))),
+            ),
+            (
+                Expr::IsNotNull(Box::new(col("host"))),
+                Some(("host", (Operator::NotEqual, Value::Null))),
+            ),
+            (col("host").eq(col("region")), None),
+            (
+                col("region").eq(lit("west")).and(col("host").eq(lit("a"))),
+                None,
+            ),
+        ];
+
+        for (expr, exp) in cases {
+            assert_eq!(super::predicate_from_expr(&expr), exp, "{:?}", expr);
+        }
+    }
 }
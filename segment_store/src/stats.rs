@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+
+use crate::column::{cmp::Operator, Column, EncodedValues, Value};
+
+/// The number of most frequent values whose counts are kept for each
+/// dictionary column.
+pub const TOP_K: usize = 16;
+
+/// ValueCounts summarises the distribution of the values of a dictionary
+/// (string) column in a segment: the number of rows of each of its most
+/// frequent values, and the number of rows and distinct values of the rest.
+///
+/// It is used to estimate the selectivity of predicates on the column, i.e.,
+/// the fraction of the segment's rows that satisfy them. Values outside of
+/// the most frequent ones are assumed to each appear in the same number of
+/// rows.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ValueCounts {
+    // The number of rows in the column.
+    rows: u32,
+
+    // The number of rows that are NULL.
+    null_rows: u32,
+
+    // The most frequent values and the number of rows of each, from the most
+    // to the least frequent.
+    top: Vec<(String, u32)>,
+
+    // The number of distinct non-null values not in `top`.
+    other_values: u32,
+
+    // The number of rows of the values not in `top`.
+    other_rows: u32,
+}
+
+impl ValueCounts {
+    /// Counts the values of a dictionary encoded column, keeping the counts
+    /// of at most `k` values.
+    pub fn new(column: &Column, k: usize) -> Self {
+        let ids = match column.all_encoded_values(EncodedValues::U32(vec![])) {
+            EncodedValues::U32(ids) => ids,
+            EncodedValues::I64(_) => unreachable!("dictionary columns are encoded as u32 ids"),
+        };
+
+        let mut id_counts: HashMap<u32, u32> = HashMap::new();
+        for id in &ids {
+            *id_counts.entry(*id).or_default() += 1;
+        }
+
+        let mut null_rows = 0;
+        let mut counts = Vec::with_capacity(id_counts.len());
+        for (id, count) in id_counts {
+            match column.decode_id(id) {
+                Some(value) => counts.push((value, count)),
+                None => null_rows += count,
+            }
+        }
+        // ties are broken by value so the counts kept are deterministic
+        counts.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+
+        let other = if counts.len() > k {
+            counts.split_off(k)
+        } else {
+            vec![]
+        };
+
+        Self {
+            rows: ids.len() as u32,
+            null_rows,
+            top: counts,
+            other_values: other.len() as u32,
+            other_rows: other.iter().map(|(_, count)| count).sum(),
+        }
+    }
+
+    /// The number of rows counted.
+    pub fn rows(&self) -> u32 {
+        self.rows
+    }
+
+    /// The estimated number of rows with `value`, which are exact if it is
+    /// one of the most frequent values.
+    pub fn estimate_equal(&self, value: &str) -> f64 {
+        self.estimate_matching(|v| v == value)
+    }
+
+    // The estimated number of rows of the first value `matches` accepts.
+    fn estimate_matching(&self, matches: impl Fn(&str) -> bool) -> f64 {
+        if let Some((_, count)) = self.top.iter().find(|(v, _)| matches(v)) {
+            return *count as f64;
+        }
+
+        // A value not in `top` is assumed to be as frequent as the average of
+        // the other values not in it.
+        if self.other_values == 0 {
+            return 0.0;
+        }
+        self.other_rows as f64 / self.other_values as f64
+    }
+
+    /// Estimates the fraction of rows that satisfy the predicate, or returns
+    /// `None` if the counts can't tell, such as for range predicates.
+    pub fn selectivity(&self, predicate: &(Operator, Value<'_>)) -> Option<f64> {
+        if self.rows == 0 {
+            return Some(0.0);
+        }
+        let rows = self.rows as f64;
+        let non_null_rows = (self.rows - self.null_rows) as f64;
+
+        let matching = match predicate {
+            (Operator::Equal, Value::Null) => self.null_rows as f64,
+            (Operator::NotEqual, Value::Null) => non_null_rows,
+            (Operator::Equal, Value::String(value)) => self.estimate_equal(value),
+            (Operator::NotEqual, Value::String(value)) => {
+                non_null_rows - self.estimate_equal(value)
+            }
+            (Operator::EqualIgnoreCase, Value::String(value)) => {
+                self.estimate_matching(|v| v.eq_ignore_ascii_case(value))
+            }
+            _ => return None,
+        };
+
+        Some((matching / rows).max(0.0).min(1.0))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn selectivity() {
+        let values = [
+            Some("west"),
+            Some("west"),
+            Some("west"),
+            Some("west"),
+            Some("east"),
+            Some("east"),
+            Some("north"),
+            Some("south"),
+            None,
+            None,
+        ];
+        let column = Column::from(&values[..]);
+
+        let counts = ValueCounts::new(&column, 2);
+        assert_eq!(counts.rows(), 10);
+        assert_eq!(
+            counts.top,
+            vec![("west".to_owned(), 4), ("east".to_owned(), 2)]
+        );
+        assert_eq!((counts.other_values, counts.other_rows), (2, 2));
+
+        let cases = vec![
+            ((Operator::Equal, Value::String("west")), Some(0.4)),
+            ((Operator::Equal, Value::String("east")), Some(0.2)),
+            // assumed to be as frequent as the other values not in the top
+            ((Operator::Equal, Value::String("north")), Some(0.1)),
+            ((Operator::Equal, Value::String("up")), Some(0.1)),
+            (
+                (Operator::EqualIgnoreCase, Value::String("WEST")),
+                Some(0.4),
+            ),
+            ((Operator::NotEqual, Value::String("west")), Some(0.4)),
+            ((Operator::Equal, Value::Null), Some(0.2)),
+            ((Operator::NotEqual, Value::Null), Some(0.8)),
+            ((Operator::GT, Value::String("west")), None),
+        ];
+
+        for (predicate, expected) in cases {
+            let selectivity = counts.selectivity(&predicate);
+            match (selectivity, expected) {
+                (Some(got), Some(exp)) => assert!((got - exp).abs() < 1e-9, "{:?}", predicate),
+                (got, exp) => assert_eq!(got, exp, "{:?}", predicate),
+            }
+        }
+
+        // every value is counted when there are few enough of them
+        let counts = ValueCounts::new(&column, TOP_K);
+        assert_eq!((counts.other_values, counts.other_rows), (0, 0));
+        assert_eq!(
+            counts.selectivity(&(Operator::Equal, Value::String("up"))),
+            Some(0.0)
+        );
+    }
+}
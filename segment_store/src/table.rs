@@ -2,9 +2,10 @@ use std::collections::{BTreeMap, BTreeSet};
 use std::slice::Iter;
 
 use arrow_deps::arrow::record_batch::RecordBatch;
+use croaring::Bitmap;
 
-use crate::column::{AggregateResult, AggregateType, Scalar, Value, Values};
-use crate::segment::{ColumnName, GroupKey, Predicate, Segment};
+use crate::column::{cmp::Operator, AggregateResult, AggregateType, Scalar, Value, Values};
+use crate::segment::{ColumnName, GroupKey, Predicate, Segment, TIME_COLUMN_NAME};
 
 /// A Table represents data for a single measurement.
 ///
@@ -74,17 +75,46 @@ impl<'a> Table<'a> {
 
     /// The total size of the table in bytes.
     pub fn size(&self) -> u64 {
-        todo!()
+        self.segments.iter().map(|segment| segment.size()).sum()
     }
 
     /// The number of rows in this table.
     pub fn rows(&self) -> u64 {
-        todo!()
+        self.segments
+            .iter()
+            .map(|segment| segment.rows() as u64)
+            .sum()
     }
 
     /// The time range of all segments within this table.
     pub fn time_range(&self) -> Option<(i64, i64)> {
-        todo!()
+        self.segments
+            .iter()
+            .map(|segment| segment.time_range())
+            .fold(None, |range, (min, max)| match range {
+                Some((range_min, range_max)) => Some((min.min(range_min), max.max(range_max))),
+                None => Some((min, max)),
+            })
+    }
+
+    /// Estimates the number of rows in the table that satisfy all of the
+    /// predicates, from the metadata of each segment. Predicates are assumed
+    /// to be independent of one another.
+    pub fn estimate_rows(&self, predicates: &[Predicate<'_>]) -> f64 {
+        self.segments
+            .iter()
+            .map(|segment| {
+                predicates
+                    .iter()
+                    .fold(segment.rows() as f64, |rows, (col_name, pred)| {
+                        if segment.column_could_satisfy_predicate(col_name, pred) {
+                            rows * segment.predicate_selectivity(col_name, pred)
+                        } else {
+                            0.0
+                        }
+                    })
+            })
+            .sum()
     }
 
     /// The ranges on each column in the table (across all segments).
@@ -92,11 +122,22 @@ impl<'a> Table<'a> {
         todo!()
     }
 
-    // Identify set of segments that may satisfy the predicates.
-    fn filter_segments(&self, predicates: &[Predicate<'_>]) -> Vec<&Segment<'a>> {
+    // Identify set of segments that may satisfy the predicates, out of those
+    // at `positions` if specified.
+    fn filter_segments(
+        &self,
+        positions: Option<&Bitmap>,
+        predicates: &[Predicate<'_>],
+    ) -> Vec<&Segment<'a>> {
         let mut segments = Vec::with_capacity(self.segments.len());
 
-        'seg: for segment in &self.segments {
+        'seg: for (position, segment) in self.segments.iter().enumerate() {
+            if let Some(positions) = positions {
+                if !positions.contains(position as u32) {
+                    continue;
+                }
+            }
+
             // check all provided predicates
             for (col_name, pred) in predicates {
                 if !segment.column_could_satisfy_predicate(col_name, pred) {
@@ -126,8 +167,31 @@ impl<'a> Table<'a> {
         // identify segments where time range and predicates match could match
         // using segment meta data, and then execute against those segments and
         // merge results.
-        let segments = self.filter_segments(predicates);
+        let segments = self.filter_segments(None, predicates);
+        self.read_filter_segments(segments, columns, predicates)
+    }
+
+    /// Returns vectors of columnar data for the specified column selections,
+    /// as `select` does, only reading the segments at `positions`.
+    ///
+    /// The positions are those of segments that could satisfy the
+    /// predicates, such as those a partition's tag index identifies.
+    pub fn select_segments(
+        &self,
+        positions: &Bitmap,
+        columns: &[ColumnName<'a>],
+        predicates: &[Predicate<'_>],
+    ) -> Vec<(ColumnName<'a>, Vec<Values>)> {
+        let segments = self.filter_segments(Some(positions), predicates);
+        self.read_filter_segments(segments, columns, predicates)
+    }
 
+    fn read_filter_segments(
+        &self,
+        segments: Vec<&Segment<'a>>,
+        columns: &[ColumnName<'a>],
+        predicates: &[Predicate<'_>],
+    ) -> Vec<(ColumnName<'a>, Vec<Values>)> {
         let mut results = columns.iter().map(|&col_name| (col_name, vec![])).collect();
         if segments.is_empty() {
             return results;
@@ -164,10 +228,10 @@ impl<'a> Table<'a> {
         group_columns: Vec<ColumnName<'a>>,
         aggregates: Vec<(ColumnName<'a>, AggregateType)>,
     ) -> BTreeMap<GroupKey, Vec<(ColumnName<'a>, AggregateResult<'_>)>> {
-        // identify segments where time range and predicates match could match
-        // using segment meta data, and then execute against those segments and
-        // merge results.
         self.aggregate_window(time_range, predicates, group_columns, aggregates, 0)
+            .into_iter()
+            .map(|((group_key, _), results)| (group_key, results))
+            .collect()
     }
 
     /// Returns aggregates segmented by grouping keys and windowed by time.
@@ -187,7 +251,10 @@ impl<'a> Table<'a> {
     ///
     /// Results are grouped and windowed according to the `window` parameter,
     /// which represents an interval in nanoseconds. For example, to window
-    /// results by one minute, window should be set to 600_000_000_000.
+    /// results by one minute, window should be set to 600_000_000_000. Each
+    /// result is keyed on its group key and the start of its window. If
+    /// `window` is `0` all rows of a group are in a single window, which
+    /// starts at `0`.
     pub fn aggregate_window(
         &self,
         time_range: (i64, i64),
@@ -195,11 +262,45 @@ impl<'a> Table<'a> {
         group_columns: Vec<ColumnName<'a>>,
         aggregates: Vec<(ColumnName<'a>, AggregateType)>,
         window: i64,
-    ) -> BTreeMap<GroupKey, Vec<(ColumnName<'a>, AggregateResult<'_>)>> {
+    ) -> BTreeMap<(GroupKey, i64), Vec<(ColumnName<'a>, AggregateResult<'_>)>> {
+        let mut segment_predicates = vec![
+            (
+                TIME_COLUMN_NAME,
+                (Operator::GTE, Value::Scalar(Scalar::I64(time_range.0))),
+            ),
+            (
+                TIME_COLUMN_NAME,
+                (Operator::LT, Value::Scalar(Scalar::I64(time_range.1))),
+            ),
+        ];
+        segment_predicates.extend(
+            predicates
+                .iter()
+                .map(|&(col_name, value)| (col_name, (Operator::Equal, Value::String(value)))),
+        );
+
         // identify segments where time range and predicates match could match
         // using segment meta data, and then execute against those segments and
-        // merge results.
-        todo!()
+        // merge the results of the same group and window.
+        let mut results: BTreeMap<(GroupKey, i64), Vec<(ColumnName<'a>, AggregateResult<'_>)>> =
+            BTreeMap::new();
+        for segment in self.filter_segments(None, &segment_predicates) {
+            let segment_results =
+                segment.read_group_window(&segment_predicates, &group_columns, &aggregates, window);
+            for (key, aggs) in segment_results {
+                match results.get_mut(&key) {
+                    Some(merged) => {
+                        for ((_, result), (_, agg)) in merged.iter_mut().zip(aggs) {
+                            result.merge(agg);
+                        }
+                    }
+                    None => {
+                        results.insert(key, aggs);
+                    }
+                }
+            }
+        }
+        results
     }
 
     // Perform aggregates without any grouping. Filtering on optional predicates
@@ -468,8 +569,8 @@ impl<'a> MetaData<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::column::{cmp::Operator, Column, ValuesIterator};
-    use crate::segment::{ColumnType, TIME_COLUMN_NAME};
+    use crate::column::{Column, ValuesIterator};
+    use crate::segment::ColumnType;
 
     fn stringify_select_results(table: Vec<(ColumnName<'_>, Vec<Values>)>) -> String {
         let mut out = String::new();
@@ -615,4 +716,87 @@ mod test {
             stringify_select_results(results)
         );
     }
+
+    #[test]
+    fn aggregate_window() {
+        let mut columns = BTreeMap::new();
+        let tc = ColumnType::Time(Column::from(&[1_i64, 2, 3, 4, 5, 6][..]));
+        columns.insert("time", &tc);
+        let rc = ColumnType::Tag(Column::from(
+            &["west", "west", "east", "west", "south", "north"][..],
+        ));
+        columns.insert("region", &rc);
+        let fc = ColumnType::Field(Column::from(&[100_u64, 101, 200, 203, 203, 10][..]));
+        columns.insert("count", &fc);
+        let mut table = Table::new("cpu".to_owned(), Segment::new(6, columns));
+
+        let mut columns = BTreeMap::new();
+        let tc = ColumnType::Time(Column::from(&[10_i64, 20, 30][..]));
+        columns.insert("time", &tc);
+        let rc = ColumnType::Tag(Column::from(&["south", "north", "east"][..]));
+        columns.insert("region", &rc);
+        let fc = ColumnType::Field(Column::from(&[1000_u64, 1002, 1200][..]));
+        columns.insert("count", &fc);
+        table.add_segment(Segment::new(3, columns));
+
+        // the aggregates of each group are merged across segments
+        let results = table.aggregate(
+            (1, 31),
+            &[],
+            vec!["region"],
+            vec![
+                ("count", AggregateType::Sum),
+                ("count", AggregateType::First),
+                ("count", AggregateType::Last),
+            ],
+        );
+        let u64_value = |v| Value::Scalar(Scalar::U64(v));
+        let expected = |sum, first: (i64, u64), last: (i64, u64)| {
+            vec![
+                ("count", AggregateResult::Sum(Some(Scalar::U64(sum)))),
+                (
+                    "count",
+                    AggregateResult::First(Some((first.0, u64_value(first.1)))),
+                ),
+                (
+                    "count",
+                    AggregateResult::Last(Some((last.0, u64_value(last.1)))),
+                ),
+            ]
+        };
+        assert_eq!(
+            results.into_iter().collect::<Vec<_>>(),
+            vec![
+                (
+                    vec!["east".to_string()],
+                    expected(1400, (3, 200), (30, 1200))
+                ),
+                (
+                    vec!["north".to_string()],
+                    expected(1012, (6, 10), (20, 1002))
+                ),
+                (
+                    vec!["south".to_string()],
+                    expected(1203, (5, 203), (10, 1000))
+                ),
+                (vec!["west".to_string()], expected(404, (1, 100), (4, 203))),
+            ]
+        );
+
+        // and windowed by time
+        let results = table.aggregate_window(
+            (1, 31),
+            &[("region", "west")],
+            vec![],
+            vec![("count", AggregateType::Count)],
+            3,
+        );
+        assert_eq!(
+            results.into_iter().collect::<Vec<_>>(),
+            vec![
+                ((vec![], 0), vec![("count", AggregateResult::Count(2))]),
+                ((vec![], 3), vec![("count", AggregateResult::Count(1))]),
+            ]
+        );
+    }
 }
@@ -21,6 +21,7 @@ use write_buffer::Db as WriteBufferDb;
 
 use async_trait::async_trait;
 use bytes::Bytes;
+use chrono::{DateTime, Utc};
 use futures::stream::TryStreamExt;
 use serde::{Deserialize, Serialize};
 use snafu::{OptionExt, ResultExt, Snafu};
@@ -222,6 +223,30 @@ impl<M: ConnectionManager> Server<M> {
             .context(UnknownDatabaseError {})
     }
 
+    /// Downsamples the aged partitions of each database stored locally according to the
+    /// `downsampling_rules` of the database, as of `now`. Returns the number of partitions that
+    /// were downsampled.
+    pub async fn downsample(&self, now: DateTime<Utc>) -> Result<usize> {
+        let now = now.timestamp_nanos();
+
+        let mut downsampled = 0;
+        for db in self.config.databases.values() {
+            if db.rules.downsampling_rules.is_empty() {
+                continue;
+            }
+
+            if let Some(buf) = &db.local_store {
+                downsampled += buf
+                    .downsample(&db.rules.downsampling_rules, now)
+                    .await
+                    .map_err(|e| Box::new(e) as DatabaseError)
+                    .context(UnknownDatabaseError {})?;
+            }
+        }
+
+        Ok(downsampled)
+    }
+
     pub async fn handle_replicated_write(
         &self,
         db_name: &str,
@@ -355,7 +380,10 @@ mod tests {
     use super::*;
     use arrow_deps::arrow::{csv, util::string_writer::StringWriter};
     use async_trait::async_trait;
-    use data_types::database_rules::{MatchTables, Matcher, Subscription};
+    use chrono::TimeZone;
+    use data_types::database_rules::{
+        DownsamplingAggregate, DownsamplingRule, MatchTables, Matcher, Subscription,
+    };
     use futures::TryStreamExt;
     use influxdb_line_protocol::parse_lines;
     use object_store::{InMemory, ObjectStoreIntegration};
@@ -421,6 +449,47 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn downsample_local_databases() -> Result {
+        let manager = TestConnectionManager::new();
+        let store = ObjectStore::new_in_memory(InMemory::new());
+        let mut server = Server::new(manager, store);
+        server.set_id(1);
+        let rules = DatabaseRules {
+            store_locally: true,
+            downsampling_rules: vec![DownsamplingRule {
+                after_seconds: 60,
+                interval_seconds: 60,
+                aggregate: DownsamplingAggregate::Max,
+            }],
+            ..Default::default()
+        };
+        server.create_database("foo", rules).await?;
+
+        let lines = parsed_lines("cpu bar=1 10\ncpu bar=3 20\ncpu bar=2 30");
+        server.write_lines("foo", &lines).await.unwrap();
+
+        // not old enough to be downsampled yet
+        assert_eq!(server.downsample(Utc.timestamp(30, 0)).await?, 0);
+        assert_eq!(server.downsample(Utc.timestamp(120, 0)).await?, 1);
+
+        let results = server
+            .query_local("foo", "select * from cpu")
+            .await
+            .unwrap();
+
+        let mut sw = StringWriter::new();
+        {
+            let mut writer = csv::Writer::new(&mut sw);
+            for r in results {
+                writer.write(&r).unwrap();
+            }
+        }
+        assert_eq!(&sw.to_string(), "bar,time\n3,0\n");
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn replicate_to_single_group() -> Result {
         let mut manager = TestConnectionManager::new();
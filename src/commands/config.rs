@@ -0,0 +1,9 @@
+//! This module contains code to inspect the configuration of the server
+
+use crate::server::config::Config;
+
+/// Print the effective configuration, as a TOML file which notes where
+/// each setting came from, with secrets redacted
+pub fn show(config: &Config) {
+    print!("{}", config.show());
+}
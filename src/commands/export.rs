@@ -0,0 +1,346 @@
+//! This module contains code to export the tables of a database on a
+//! running server to local Parquet or line protocol files.
+//!
+//! Each table is read with a SQL query over Arrow Flight, with a time
+//! range as a predicate on the time column, so the server only reads
+//! the chunks of the table with rows in the range. The results are
+//! written to the output as their record batches arrive, rather than
+//! once the whole table has been received.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use arrow_deps::{
+    arrow::{
+        array::{Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray},
+        datatypes::{DataType, Schema},
+        record_batch::RecordBatch,
+    },
+    parquet::{arrow::ArrowWriter, errors::ParquetError},
+};
+use data_types::TIME_COLUMN_NAME;
+use influxdb_iox_client::{
+    flight::{self, tag_columns, FlightClient},
+    FieldValue, Line,
+};
+use snafu::{ResultExt, Snafu};
+use tracing::info;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error connecting to {}: {}", host, source))]
+    Connecting { host: String, source: flight::Error },
+
+    #[snafu(display("Error reading table {}: {}", table_name, source))]
+    ReadingTable {
+        table_name: String,
+        source: flight::Error,
+    },
+
+    #[snafu(display("Error listing the tables of database {}: {}", database, source))]
+    ListingTables {
+        database: String,
+        source: flight::Error,
+    },
+
+    #[snafu(display("Error creating {}: {}", path.display(), source))]
+    CreatingOutput { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error writing {}: {}", path.display(), source))]
+    WritingOutput { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error writing parquet to {}: {}", path.display(), source))]
+    WritingParquet { path: PathBuf, source: ParquetError },
+
+    #[snafu(display(
+        "Column {} of table {} has the unsupported type {:?}",
+        column_name,
+        table_name,
+        data_type
+    ))]
+    UnsupportedDataType {
+        table_name: String,
+        column_name: String,
+        data_type: DataType,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The format of the exported files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// A directory with a Parquet file per table
+    Parquet,
+    /// A single file with the lines of every table
+    LineProtocol,
+}
+
+/// Describes what to export and where
+#[derive(Debug)]
+pub struct ExportConfig {
+    /// The URL of the gRPC API of the server
+    pub host: String,
+
+    /// The API token to authorize queries with, if any
+    pub token: Option<String>,
+
+    pub database: String,
+
+    /// The tables to export, or every table of the database if empty
+    pub tables: Vec<String>,
+
+    /// Only rows with a time at or after this, in nanoseconds since
+    /// the epoch, are exported
+    pub start: Option<i64>,
+
+    /// Only rows with a time before this, in nanoseconds since the
+    /// epoch, are exported
+    pub end: Option<i64>,
+
+    pub format: Format,
+
+    /// The directory (for Parquet) or file (for line protocol) to
+    /// write to
+    pub output: PathBuf,
+}
+
+/// Exports the tables described by `config`
+pub async fn export(config: &ExportConfig) -> Result<()> {
+    let mut client = FlightClient::connect(&config.host)
+        .await
+        .and_then(|client| match &config.token {
+            Some(token) => client.with_token(token),
+            None => Ok(client),
+        })
+        .context(Connecting { host: &config.host })?;
+
+    let tables = if config.tables.is_empty() {
+        client
+            .tables(&config.database)
+            .await
+            .context(ListingTables {
+                database: &config.database,
+            })?
+    } else {
+        config.tables.clone()
+    };
+
+    let mut lp_output = match config.format {
+        Format::Parquet => {
+            fs::create_dir_all(&config.output).context(CreatingOutput {
+                path: &config.output,
+            })?;
+            None
+        }
+        Format::LineProtocol => {
+            let file = File::create(&config.output).context(CreatingOutput {
+                path: &config.output,
+            })?;
+            Some(BufWriter::new(file))
+        }
+    };
+
+    for table_name in &tables {
+        let schema = client
+            .table_schema(&config.database, table_name)
+            .await
+            .context(ReadingTable { table_name })?;
+        let mut results = client
+            .query_stream(&config.database, &table_query(table_name, config))
+            .await
+            .context(ReadingTable { table_name })?;
+        info!("Exporting table {}", table_name);
+
+        let mut parquet_output = ParquetOutput::new(
+            config
+                .output
+                .join(format!("{}.parquet", path_safe(table_name))),
+        );
+        let mut rows = 0;
+        while let Some(batch) = results
+            .next_batch()
+            .await
+            .context(ReadingTable { table_name })?
+        {
+            rows += batch.num_rows();
+            match &mut lp_output {
+                Some(writer) => write_lines(writer, &config.output, table_name, &schema, &batch)?,
+                None => parquet_output.write(&batch)?,
+            }
+        }
+        parquet_output.close()?;
+
+        eprintln!("Exported {} rows of table {}", rows, table_name);
+    }
+
+    if let Some(mut writer) = lp_output {
+        writer.flush().context(WritingOutput {
+            path: &config.output,
+        })?;
+    }
+    Ok(())
+}
+
+/// Parses a time given as nanoseconds since the epoch or in RFC 3339
+/// format, such as `2020-11-01T00:00:00Z`
+pub fn parse_time(time: &str) -> Option<i64> {
+    time.parse().ok().or_else(|| {
+        chrono::DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|time| time.timestamp_nanos())
+    })
+}
+
+/// The query reading the rows of `table_name` in the time range of
+/// `config`
+fn table_query(table_name: &str, config: &ExportConfig) -> String {
+    let mut query = format!("select * from \"{}\"", table_name.replace('"', "\"\""));
+    let conditions: Vec<_> = config
+        .start
+        .map(|start| format!("{} >= {}", TIME_COLUMN_NAME, start))
+        .into_iter()
+        .chain(
+            config
+                .end
+                .map(|end| format!("{} < {}", TIME_COLUMN_NAME, end)),
+        )
+        .collect();
+    if !conditions.is_empty() {
+        query.push_str(" where ");
+        query.push_str(&conditions.join(" and "));
+    }
+    query
+}
+
+/// Writes the batches of a table to a Parquet file, which is created
+/// when the first batch is written, so that tables without rows in
+/// the time range have no file
+struct ParquetOutput {
+    path: PathBuf,
+    writer: Option<ArrowWriter<File>>,
+}
+
+impl ParquetOutput {
+    fn new(path: PathBuf) -> Self {
+        Self { path, writer: None }
+    }
+
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let path = &self.path;
+        if self.writer.is_none() {
+            let file = File::create(path).context(CreatingOutput { path })?;
+            let writer = ArrowWriter::try_new(file, batch.schema(), None)
+                .context(WritingParquet { path })?;
+            self.writer = Some(writer);
+        }
+
+        let writer = self.writer.as_mut().expect("writer was just created");
+        writer.write(batch).context(WritingParquet { path })
+    }
+
+    fn close(self) -> Result<()> {
+        let path = &self.path;
+        if let Some(mut writer) = self.writer {
+            writer.close().context(WritingParquet { path })?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes the rows of `batch` as lines of the measurement
+/// `table_name`. The columns listed as tags in `schema` are written as
+/// tags, and the others, except the time, as fields.
+fn write_lines(
+    writer: &mut impl Write,
+    path: &Path,
+    table_name: &str,
+    schema: &Schema,
+    batch: &RecordBatch,
+) -> Result<()> {
+    let tags = tag_columns(schema);
+    let batch_schema = batch.schema();
+    let columns: Vec<(&str, &ArrayRef)> = batch_schema
+        .fields()
+        .iter()
+        .map(|field| field.name().as_str())
+        .zip(batch.columns())
+        .collect();
+
+    for row in 0..batch.num_rows() {
+        let mut builder = Line::builder(table_name);
+        for (column_name, column) in &columns {
+            if column.is_null(row) {
+                continue;
+            }
+
+            if tags.iter().any(|tag| tag == column_name) {
+                let value = string_value(column, row);
+                if !value.is_empty() {
+                    builder = builder.tag(*column_name, value);
+                }
+            } else if *column_name == TIME_COLUMN_NAME {
+                if let Some(times) = column.as_any().downcast_ref::<Int64Array>() {
+                    builder = builder.timestamp(times.value(row));
+                }
+            } else {
+                let value = match field_value(column, row) {
+                    Some(value) => value,
+                    None => {
+                        return UnsupportedDataType {
+                            table_name,
+                            column_name: *column_name,
+                            data_type: column.data_type().clone(),
+                        }
+                        .fail()
+                    }
+                };
+                builder = builder.field(*column_name, value);
+            }
+        }
+
+        // rows whose fields are all null have nothing to write
+        if let Ok(line) = builder.build() {
+            writeln!(writer, "{}", line).context(WritingOutput { path })?;
+        }
+    }
+    Ok(())
+}
+
+fn string_value(column: &ArrayRef, row: usize) -> &str {
+    column
+        .as_any()
+        .downcast_ref::<StringArray>()
+        .map(|values| values.value(row))
+        .unwrap_or_default()
+}
+
+/// Returns the value at `row` of `column`, if it has a type that can
+/// be written as a field
+fn field_value(column: &ArrayRef, row: usize) -> Option<FieldValue> {
+    let values = column.as_any();
+    Some(match column.data_type() {
+        DataType::Float64 => values.downcast_ref::<Float64Array>()?.value(row).into(),
+        DataType::Int64 => values.downcast_ref::<Int64Array>()?.value(row).into(),
+        DataType::Boolean => values.downcast_ref::<BooleanArray>()?.value(row).into(),
+        DataType::Utf8 => values.downcast_ref::<StringArray>()?.value(row).into(),
+        _ => return None,
+    })
+}
+
+/// Returns `name` with any characters that are not safe to use in a
+/// file name replaced by underscores
+fn path_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,135 @@
+//! This module contains code to generate line protocol workloads,
+//! described by a `data_generator` specification, and write them to a
+//! running server or a file.
+
+use std::{
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use data_generator::{specification, DataSpec, Generator};
+use influxdb_iox_client::Client;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error reading {}: {}", path.display(), source))]
+    ReadingSpec { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Invalid specification {}: {}", path.display(), source))]
+    InvalidSpec {
+        path: PathBuf,
+        source: specification::Error,
+    },
+
+    #[snafu(display("Error creating {}: {}", path.display(), source))]
+    CreatingOutput { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error writing {}: {}", path.display(), source))]
+    WritingOutput { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error writing to the server: {}", source))]
+    Writing { source: influxdb_iox_client::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Where the generated lines are written
+#[derive(Debug)]
+pub enum Destination {
+    /// A line protocol file
+    File(PathBuf),
+    /// A bucket of a running server
+    Server {
+        /// The URL of the HTTP API of the server
+        host: String,
+        /// The API token to authorize writes with, if any
+        token: Option<String>,
+        org: String,
+        bucket: String,
+        /// The number of lines written in each request
+        batch_size: usize,
+    },
+}
+
+/// Describes what to generate and where
+#[derive(Debug)]
+pub struct GenerateConfig {
+    /// The TOML file with the specification of the lines
+    pub spec_path: PathBuf,
+
+    /// Replaces the seed of the specification, if set
+    pub seed: Option<u64>,
+
+    pub destination: Destination,
+}
+
+/// Generates the lines described by `config`
+pub async fn generate(config: &GenerateConfig) -> Result<()> {
+    let path = &config.spec_path;
+    let spec = fs::read_to_string(path).context(ReadingSpec { path })?;
+    let mut spec = DataSpec::from_toml(&spec).context(InvalidSpec { path })?;
+    if let Some(seed) = config.seed {
+        spec.seed = seed;
+    }
+
+    let total_lines = spec.total_lines();
+    let generator = Generator::new(spec);
+    let start = Instant::now();
+
+    match &config.destination {
+        Destination::File(path) => {
+            let file = File::create(path).context(CreatingOutput { path })?;
+            let mut writer = BufWriter::new(file);
+            for line in generator {
+                writeln!(writer, "{}", line).context(WritingOutput { path })?;
+            }
+            writer.flush().context(WritingOutput { path })?;
+        }
+        Destination::Server {
+            host,
+            token,
+            org,
+            bucket,
+            batch_size,
+        } => {
+            let mut client = Client::new(host);
+            if let Some(token) = token {
+                client = client.with_token(token);
+            }
+
+            let mut lines_written = 0;
+            let mut batch = String::new();
+            let mut batch_lines = 0;
+            for line in generator {
+                batch.push_str(&line.to_string());
+                batch.push('\n');
+                batch_lines += 1;
+
+                if batch_lines >= *batch_size || lines_written + batch_lines == total_lines {
+                    client
+                        .write_line_protocol(org, bucket, std::mem::take(&mut batch))
+                        .await
+                        .context(Writing)?;
+                    lines_written += batch_lines;
+                    batch_lines = 0;
+                    eprintln!(
+                        "Wrote {} of {} lines in {:.1}s",
+                        lines_written,
+                        total_lines,
+                        start.elapsed().as_secs_f64()
+                    );
+                }
+            }
+        }
+    }
+
+    eprintln!(
+        "Generated {} lines in {:.1}s",
+        total_lines,
+        start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
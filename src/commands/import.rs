@@ -0,0 +1,378 @@
+//! This module contains code to migrate data from InfluxDB 1.x and 2.x
+//! into a running IOx server.
+//!
+//! The data is read from 2.x TSM files, or from the line protocol
+//! written by `influx_inspect export` for 1.x (whose TSM files can't
+//! be read directly, as their series keys don't record their bucket).
+//! Each 2.x bucket, or 1.x database and retention policy, is written
+//! to its own IOx database through the write API.
+
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    io::{BufRead, BufReader},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use influxdb_iox_client::{Client, FieldValue, Line};
+use influxdb_tsm::{
+    reader::{BlockDecoder, TSMBlockReader, TSMIndexReader, ValuePair},
+    TSMError,
+};
+use snafu::{OptionExt, ResultExt, Snafu};
+use tracing::{debug, info, warn};
+
+use crate::commands::input::{FileType, InputPath, InputReader};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error opening input {}", source))]
+    OpenInput { source: super::input::Error },
+
+    #[snafu(display("Error reading {}: {}", path.display(), source))]
+    ReadingFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Error reading TSM data from {}: {}", path.display(), source))]
+    ReadingTsm { path: PathBuf, source: TSMError },
+
+    #[snafu(display("Parquet files can not be imported: {}", path.display()))]
+    UnsupportedFile { path: PathBuf },
+
+    #[snafu(display(
+        "Invalid mapping '{}', expected SOURCE=ORG/BUCKET where SOURCE is DATABASE/RP or \
+         ORG_ID/BUCKET_ID",
+        mapping
+    ))]
+    InvalidMapping { mapping: String },
+
+    #[snafu(display(
+        "Line protocol in {} has no database or retention policy context, and no --org and \
+         --bucket were given",
+        path.display()
+    ))]
+    NoDestination { path: PathBuf },
+
+    #[snafu(display("Error converting a TSM value to line protocol: {}", source))]
+    ConvertingValue {
+        source: influxdb_iox_client::line::Error,
+    },
+
+    #[snafu(display("Error writing to {}/{}: {}", org, bucket, source))]
+    Writing {
+        org: String,
+        bucket: String,
+        source: influxdb_iox_client::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The org and bucket data is written to
+type Destination = (String, String);
+
+/// Describes what to import and where
+#[derive(Debug)]
+pub struct ImportConfig {
+    /// The URL of the HTTP API of the server
+    pub host: String,
+
+    /// The API token to authorize writes with, if any
+    pub token: Option<String>,
+
+    /// The files, or directories of files, to import
+    pub paths: Vec<PathBuf>,
+
+    /// Where the data of each 1.x `database/rp` or 2.x
+    /// `org_id/bucket_id` is written, as `SOURCE=ORG/BUCKET`. Data
+    /// without a mapping is written to the org and bucket named by its
+    /// source.
+    pub mappings: Vec<String>,
+
+    /// Where line protocol without database context is written
+    pub default_destination: Option<Destination>,
+
+    /// The number of lines written in each request
+    pub batch_size: usize,
+
+    /// Limits the rate data is written at, if set
+    pub max_lines_per_second: Option<u64>,
+}
+
+/// Imports the files of `config` into the server
+pub async fn import(config: &ImportConfig) -> Result<()> {
+    let mappings = config
+        .mappings
+        .iter()
+        .map(|mapping| parse_mapping(mapping).context(InvalidMapping { mapping }))
+        .collect::<Result<BTreeMap<_, _>>>()?;
+
+    let mut client = Client::new(&config.host);
+    if let Some(token) = &config.token {
+        client = client.with_token(token);
+    }
+    let mut writer = Writer {
+        client,
+        batches: BTreeMap::new(),
+        batch_size: config.batch_size.max(1),
+        max_lines_per_second: config.max_lines_per_second,
+        lines_written: 0,
+        skipped_values: 0,
+        start: Instant::now(),
+    };
+
+    for path in &config.paths {
+        let input_path = InputPath::new(path, is_importable).context(OpenInput)?;
+        for input_reader in input_path.input_readers() {
+            let input_reader = input_reader.context(OpenInput)?;
+            let path = input_reader.path().to_path_buf();
+            info!("Importing {}", path.display());
+
+            match input_reader.file_type() {
+                FileType::TSM => import_tsm(&mut writer, &mappings, input_reader).await?,
+                FileType::LineProtocol => {
+                    import_export(&mut writer, &mappings, config, input_reader).await?
+                }
+                FileType::Parquet => return UnsupportedFile { path }.fail(),
+            }
+        }
+    }
+
+    writer.flush_all().await?;
+    if writer.skipped_values > 0 {
+        warn!(
+            "Skipped {} unsigned values too large for an integer field",
+            writer.skipped_values
+        );
+    }
+    eprintln!(
+        "Imported {} lines in {:.1}s",
+        writer.lines_written,
+        writer.start.elapsed().as_secs_f64()
+    );
+    Ok(())
+}
+
+/// Parses a mapping of the form `SOURCE=ORG/BUCKET`
+fn parse_mapping(mapping: &str) -> Option<(String, Destination)> {
+    let mut parts = mapping.splitn(2, '=');
+    let source = parts.next().filter(|source| source.contains('/'))?;
+    let destination = split_source(parts.next()?)?;
+    Some((source.to_string(), destination))
+}
+
+/// Splits a `NAMESPACE/NAME` pair into its parts
+fn split_source(source: &str) -> Option<Destination> {
+    let mut parts = source.splitn(2, '/');
+    let first = parts.next().filter(|part| !part.is_empty())?;
+    let second = parts.next().filter(|part| !part.is_empty())?;
+    Some((first.to_string(), second.to_string()))
+}
+
+/// The destination of the data of `source`
+fn destination(mappings: &BTreeMap<String, Destination>, source: &str) -> Option<Destination> {
+    mappings
+        .get(source)
+        .cloned()
+        .or_else(|| split_source(source))
+}
+
+/// True for the TSM and line protocol files of a directory
+fn is_importable(path: &Path) -> bool {
+    let name = path.to_string_lossy();
+    let name = name.trim_end_matches(".gz");
+    name.ends_with(".tsm") || name.ends_with(".lp")
+}
+
+/// Imports the data of a 2.x TSM file, written to the bucket each
+/// series belongs to
+async fn import_tsm(
+    writer: &mut Writer,
+    mappings: &BTreeMap<String, Destination>,
+    input_reader: InputReader,
+) -> Result<()> {
+    let path = input_reader.path().to_path_buf();
+    // the index and blocks are read through separate readers of the file
+    let block_reader = InputReader::new(&path.to_string_lossy()).context(OpenInput)?;
+    let len = input_reader.len() as usize;
+
+    let index_reader =
+        TSMIndexReader::try_new(input_reader, len).context(ReadingTsm { path: &path })?;
+    let mut block_reader = TSMBlockReader::new(block_reader);
+
+    for entry in index_reader {
+        let entry = entry.context(ReadingTsm { path: &path })?;
+        let source = format!("{}/{}", entry.org_id(), entry.bucket_id());
+        let destination = destination(mappings, &source).expect("TSM sources name a bucket");
+        let key = entry.parse_key().context(ReadingTsm { path: &path })?;
+
+        let mut block = block_reader
+            .decode(&entry.block)
+            .context(ReadingTsm { path: &path })?;
+
+        while let Some(pair) = block.next_pair() {
+            let timestamp = pair.timestamp();
+            let value = match pair {
+                ValuePair::F64((_, v)) => FieldValue::from(v),
+                ValuePair::I64((_, v)) => FieldValue::from(v),
+                ValuePair::Bool((_, v)) => FieldValue::from(v),
+                ValuePair::Str((_, v)) => FieldValue::from(String::from_utf8_lossy(&v).to_string()),
+                // line protocol written to IOx has no unsigned type
+                ValuePair::U64((_, v)) => match i64::try_from(v) {
+                    Ok(v) => FieldValue::from(v),
+                    Err(_) => {
+                        writer.skipped_values += 1;
+                        continue;
+                    }
+                },
+            };
+
+            let line = key
+                .tagset
+                .iter()
+                .fold(
+                    Line::builder(key.measurement.as_str()),
+                    |builder, (k, v)| builder.tag(k.as_str(), v.as_str()),
+                )
+                .field(key.field_key.as_str(), value)
+                .timestamp(timestamp)
+                .build()
+                .context(ConvertingValue)?;
+
+            writer.push(&destination, &line.to_string()).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Imports line protocol, such as that exported from 1.x by
+/// `influx_inspect export`. Its `# CONTEXT-DATABASE:` and
+/// `# CONTEXT-RETENTION-POLICY:` comments name the database and
+/// retention policy of the lines that follow them.
+async fn import_export(
+    writer: &mut Writer,
+    mappings: &BTreeMap<String, Destination>,
+    config: &ImportConfig,
+    input_reader: InputReader,
+) -> Result<()> {
+    let path = input_reader.path().to_path_buf();
+    let reader = BufReader::new(input_reader);
+
+    let mut database: Option<String> = None;
+    let mut retention_policy: Option<String> = None;
+    let mut destination = config.default_destination.clone();
+    let mut in_ddl = false;
+
+    for line in reader.lines() {
+        let line = line.context(ReadingFile { path: &path })?;
+        let line = line.trim();
+
+        if let Some(comment) = line.strip_prefix('#') {
+            let comment = comment.trim();
+            if comment == "DDL" {
+                in_ddl = true;
+            } else if comment == "DML" {
+                in_ddl = false;
+            } else if let Some(name) = comment.strip_prefix("CONTEXT-DATABASE:") {
+                database = Some(name.trim().to_string());
+            } else if let Some(name) = comment.strip_prefix("CONTEXT-RETENTION-POLICY:") {
+                retention_policy = Some(name.trim().to_string());
+            } else {
+                continue;
+            }
+
+            if let (Some(database), Some(retention_policy)) = (&database, &retention_policy) {
+                let source = format!("{}/{}", database, retention_policy);
+                debug!("Importing the lines of {}", source);
+                destination = self::destination(mappings, &source);
+            }
+            continue;
+        }
+
+        // the DDL creating the databases is replaced by the mappings
+        if line.is_empty() || in_ddl {
+            continue;
+        }
+
+        let destination = destination
+            .as_ref()
+            .context(NoDestination { path: &path })?;
+        writer.push(destination, line).await?;
+    }
+
+    Ok(())
+}
+
+/// Batches lines by destination and writes them to the server
+#[derive(Debug)]
+struct Writer {
+    client: Client,
+    batches: BTreeMap<Destination, (String, usize)>,
+    batch_size: usize,
+    max_lines_per_second: Option<u64>,
+    lines_written: u64,
+    skipped_values: u64,
+    start: Instant,
+}
+
+impl Writer {
+    /// Adds `line` to the batch of `destination`, writing the batch if
+    /// it is full
+    async fn push(&mut self, destination: &Destination, line: &str) -> Result<()> {
+        let batch = self
+            .batches
+            .entry(destination.clone())
+            .or_insert_with(|| (String::new(), 0));
+        batch.0.push_str(line);
+        batch.0.push('\n');
+        batch.1 += 1;
+
+        if batch.1 >= self.batch_size {
+            let (body, lines) = std::mem::take(batch);
+            self.write(destination, body, lines).await?;
+        }
+        Ok(())
+    }
+
+    /// Writes the remaining lines of every destination
+    async fn flush_all(&mut self) -> Result<()> {
+        let batches = std::mem::take(&mut self.batches);
+        for (destination, (body, lines)) in batches {
+            if lines > 0 {
+                self.write(&destination, body, lines).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write(&mut self, destination: &Destination, body: String, lines: usize) -> Result<()> {
+        let (org, bucket) = destination;
+        self.client
+            .write_line_protocol(org, bucket, body)
+            .await
+            .context(Writing { org, bucket })?;
+
+        self.lines_written += lines as u64;
+        eprintln!(
+            "Imported {} lines in {:.1}s",
+            self.lines_written,
+            self.start.elapsed().as_secs_f64()
+        );
+
+        // Sleep until the average rate falls to the limit
+        if let Some(max_lines_per_second) = self.max_lines_per_second {
+            let target = Duration::from_secs_f64(
+                self.lines_written as f64 / max_lines_per_second.max(1) as f64,
+            );
+            let elapsed = self.start.elapsed();
+            if target > elapsed {
+                tokio::time::delay_for(target - elapsed).await;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,422 @@
+//! This module contains code to inspect the files of a persisted
+//! chunk, without needing a running server.
+//!
+//! A chunk is persisted as a directory with a Parquet file per table.
+//! The Parquet writer doesn't record column statistics, so the
+//! minimum and maximum of each column are computed from its values.
+
+use std::{
+    borrow::Borrow,
+    collections::BTreeSet,
+    ffi::OsStr,
+    fmt::Display,
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+
+use arrow_deps::{
+    arrow::{
+        array::{
+            Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray,
+            TimestampMicrosecondArray, TimestampNanosecondArray,
+        },
+        datatypes::{DataType, Field, Schema},
+        error::ArrowError,
+        record_batch::RecordBatch,
+        util::pretty::pretty_format_batches,
+    },
+    parquet::{
+        arrow::{ArrowReader, ParquetFileArrowReader},
+        basic::LogicalType,
+        errors::ParquetError,
+        file::reader::{FileReader, SerializedFileReader},
+    },
+};
+use chrono::{TimeZone, Utc};
+use snafu::{ResultExt, Snafu};
+use tracing::debug;
+use write_buffer::PERSISTED_CHUNKS_DIR_NAME;
+
+use crate::commands::input::{InputPath, InputReader};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error opening input {}", source))]
+    OpenInput { source: super::input::Error },
+
+    #[snafu(display("No chunk files found in {}", path.display()))]
+    NoChunkFiles { path: PathBuf },
+
+    #[snafu(display("Error reading parquet metadata of {}: {}", path.display(), source))]
+    ReadingMetadata { path: PathBuf, source: ParquetError },
+
+    #[snafu(display("Error reading the rows of {}: {}", path.display(), source))]
+    ReadingRows { path: PathBuf, source: ArrowError },
+
+    #[snafu(display("Error formatting the columns of {}: {}", path.display(), source))]
+    Formatting { path: PathBuf, source: ArrowError },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The number of rows read at a time to compute column statistics
+const BATCH_SIZE: usize = 8192;
+
+/// Prints the metadata of the chunk file, or the files of the chunk
+/// directory, at `path`
+pub fn inspect(path: &Path) -> Result<()> {
+    let input_path = InputPath::new(path, is_chunk_file).context(OpenInput)?;
+    let mut input_readers = input_path
+        .input_readers()
+        .collect::<Result<Vec<_>, _>>()
+        .context(OpenInput)?;
+    if input_readers.is_empty() {
+        return NoChunkFiles { path }.fail();
+    }
+    input_readers.sort_by(|a, b| a.path().cmp(b.path()));
+
+    for input_reader in input_readers {
+        inspect_table(input_reader)?;
+    }
+    Ok(())
+}
+
+fn is_chunk_file(path: &Path) -> bool {
+    path.extension() == Some(OsStr::new("parquet"))
+}
+
+/// The summary of a column, accumulated across the row groups of a
+/// file
+#[derive(Debug)]
+struct ColumnSummary {
+    name: String,
+    data_type: String,
+    encodings: BTreeSet<String>,
+    compressions: BTreeSet<String>,
+    compressed_bytes: i64,
+    uncompressed_bytes: i64,
+    null_count: usize,
+    range: Option<ColumnRange>,
+}
+
+/// Prints the metadata of the Parquet file of a single table
+fn inspect_table(input_reader: InputReader) -> Result<()> {
+    let path = input_reader.path().to_path_buf();
+    let file_size = input_reader.len();
+    debug!("Inspecting {}", path.display());
+
+    let file_reader =
+        Rc::new(SerializedFileReader::new(input_reader).context(ReadingMetadata { path: &path })?);
+    let metadata = file_reader.metadata();
+    let file_metadata = metadata.file_metadata();
+
+    let mut columns: Vec<_> = file_metadata
+        .schema_descr()
+        .columns()
+        .iter()
+        .map(|column| {
+            let physical_type = column.physical_type().to_string();
+            let data_type = match column.logical_type() {
+                LogicalType::NONE => physical_type,
+                logical_type => format!("{} ({})", physical_type, logical_type),
+            };
+            ColumnSummary {
+                name: column.name().to_string(),
+                data_type,
+                encodings: BTreeSet::new(),
+                compressions: BTreeSet::new(),
+                compressed_bytes: 0,
+                uncompressed_bytes: 0,
+                null_count: 0,
+                range: None,
+            }
+        })
+        .collect();
+
+    for row_group in metadata.row_groups() {
+        for (summary, column) in columns.iter_mut().zip(row_group.columns()) {
+            summary
+                .encodings
+                .extend(column.encodings().iter().map(ToString::to_string));
+            summary
+                .compressions
+                .insert(column.compression().to_string());
+            summary.compressed_bytes += column.compressed_size();
+            summary.uncompressed_bytes += column.uncompressed_size();
+        }
+    }
+
+    let mut arrow_reader = ParquetFileArrowReader::new(file_reader.clone());
+    let batches = arrow_reader
+        .get_record_reader(BATCH_SIZE)
+        .context(ReadingRows { path: &path })?;
+    for batch in batches {
+        let batch = batch.context(ReadingRows { path: &path })?;
+        for (field, array) in batch.schema().fields().iter().zip(batch.columns()) {
+            if let Some(summary) = columns.iter_mut().find(|c| &c.name == field.name()) {
+                summary.null_count += array.null_count();
+                update_range(&mut summary.range, array);
+            }
+        }
+    }
+
+    let table_name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    println!("Table {}: {}", table_name, path.display());
+    if let Some((partition_key, chunk_id)) = chunk_location(&path) {
+        println!("  Partition: {}", partition_key);
+        println!("  Chunk: {}", chunk_id);
+    }
+    println!("  File size: {} bytes", file_size);
+    if let Some(created_by) = file_metadata.created_by() {
+        println!("  Created by: {}", created_by);
+    }
+    println!(
+        "  Rows: {} in {} row group{}",
+        file_metadata.num_rows(),
+        metadata.num_row_groups(),
+        if metadata.num_row_groups() == 1 {
+            ""
+        } else {
+            "s"
+        }
+    );
+    // IOx has no deletes, so a chunk never has tombstones
+    println!("  Tombstones: none");
+    println!(
+        "{}",
+        format_columns(&columns).context(Formatting { path: &path })?
+    );
+    println!();
+
+    Ok(())
+}
+
+/// The partition and id of the chunk a file was persisted to, if it
+/// is in the `chunks/<partition>/<chunk id>` directory of a database
+fn chunk_location(path: &Path) -> Option<(String, u32)> {
+    let chunk_dir = path.parent()?;
+    let partition_dir = chunk_dir.parent()?;
+    let chunks_dir = partition_dir.parent()?;
+
+    if chunks_dir.file_name()? != OsStr::new(PERSISTED_CHUNKS_DIR_NAME) {
+        return None;
+    }
+    let chunk_id = chunk_dir.file_name()?.to_str()?.parse().ok()?;
+    let partition_key = partition_dir.file_name()?.to_string_lossy().to_string();
+    Some((partition_key, chunk_id))
+}
+
+/// Formats a table with a row describing each column
+fn format_columns(columns: &[ColumnSummary]) -> Result<String, ArrowError> {
+    let join = |values: &BTreeSet<String>| values.iter().cloned().collect::<Vec<_>>().join(", ");
+    let ranges: Vec<_> = columns
+        .iter()
+        .map(|c| c.range.as_ref().and_then(ColumnRange::format))
+        .collect();
+
+    let values: Vec<Vec<String>> = vec![
+        columns.iter().map(|c| c.name.clone()).collect(),
+        columns.iter().map(|c| c.data_type.clone()).collect(),
+        columns.iter().map(|c| join(&c.encodings)).collect(),
+        columns.iter().map(|c| join(&c.compressions)).collect(),
+        columns
+            .iter()
+            .map(|c| c.compressed_bytes.to_string())
+            .collect(),
+        columns
+            .iter()
+            .map(|c| c.uncompressed_bytes.to_string())
+            .collect(),
+        columns.iter().map(|c| c.null_count.to_string()).collect(),
+        ranges
+            .iter()
+            .map(|range| {
+                range
+                    .as_ref()
+                    .map(|(min, _)| min.clone())
+                    .unwrap_or_default()
+            })
+            .collect(),
+        ranges
+            .iter()
+            .map(|range| {
+                range
+                    .as_ref()
+                    .map(|(_, max)| max.clone())
+                    .unwrap_or_default()
+            })
+            .collect(),
+    ];
+    let names = [
+        "column_name",
+        "data_type",
+        "encodings",
+        "compression",
+        "compressed_bytes",
+        "uncompressed_bytes",
+        "null_count",
+        "min",
+        "max",
+    ];
+
+    let schema = Schema::new(
+        names
+            .iter()
+            .map(|name| Field::new(name, DataType::Utf8, false))
+            .collect(),
+    );
+    let arrays: Vec<ArrayRef> = values
+        .iter()
+        .map(|values| {
+            Arc::new(StringArray::from(
+                values.iter().map(String::as_str).collect::<Vec<_>>(),
+            )) as ArrayRef
+        })
+        .collect();
+    let batch = RecordBatch::try_new(Arc::new(schema), arrays)?;
+    pretty_format_batches(&[batch])
+}
+
+/// The smallest and largest values of a column
+#[derive(Debug)]
+struct MinMax<T> {
+    min: Option<T>,
+    max: Option<T>,
+}
+
+impl<T> Default for MinMax<T> {
+    fn default() -> Self {
+        Self {
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl<T> MinMax<T> {
+    fn update<V>(&mut self, value: &V)
+    where
+        V: PartialOrd + ToOwned<Owned = T> + ?Sized,
+        T: Borrow<V>,
+    {
+        if self.min.as_ref().map_or(true, |min| value < min.borrow()) {
+            self.min = Some(value.to_owned());
+        }
+        if self.max.as_ref().map_or(true, |max| value > max.borrow()) {
+            self.max = Some(value.to_owned());
+        }
+    }
+
+    fn format(&self, f: impl Fn(&T) -> String) -> Option<(String, String)> {
+        self.min.as_ref().map(&f).zip(self.max.as_ref().map(&f))
+    }
+}
+
+/// The range of the values of a column of a supported type
+#[derive(Debug)]
+enum ColumnRange {
+    Boolean(MinMax<bool>),
+    Integer(MinMax<i64>),
+    Float(MinMax<f64>),
+    String(MinMax<String>),
+    /// Nanoseconds since the epoch
+    Timestamp(MinMax<i64>),
+}
+
+impl ColumnRange {
+    fn format(&self) -> Option<(String, String)> {
+        fn display<T: Display>(value: &T) -> String {
+            value.to_string()
+        }
+
+        match self {
+            Self::Boolean(range) => range.format(display),
+            Self::Integer(range) => range.format(display),
+            Self::Float(range) => range.format(display),
+            Self::String(range) => range.format(display),
+            Self::Timestamp(range) => {
+                range.format(|&nanos| Utc.timestamp_nanos(nanos).to_rfc3339())
+            }
+        }
+    }
+}
+
+/// Updates `range` with the non-null values of `array`. Columns of
+/// types IOx doesn't write have no range.
+fn update_range(range: &mut Option<ColumnRange>, array: &ArrayRef) {
+    let values = array.as_any();
+    let valid = |i: &usize| array.is_valid(*i);
+
+    match array.data_type() {
+        DataType::Boolean => {
+            if let Some(values) = values.downcast_ref::<BooleanArray>() {
+                if let ColumnRange::Boolean(range) =
+                    range.get_or_insert_with(|| ColumnRange::Boolean(MinMax::default()))
+                {
+                    (0..values.len())
+                        .filter(valid)
+                        .for_each(|i| range.update(&values.value(i)));
+                }
+            }
+        }
+        DataType::Int64 => {
+            if let Some(values) = values.downcast_ref::<Int64Array>() {
+                if let ColumnRange::Integer(range) =
+                    range.get_or_insert_with(|| ColumnRange::Integer(MinMax::default()))
+                {
+                    (0..values.len())
+                        .filter(valid)
+                        .for_each(|i| range.update(&values.value(i)));
+                }
+            }
+        }
+        DataType::Float64 => {
+            if let Some(values) = values.downcast_ref::<Float64Array>() {
+                if let ColumnRange::Float(range) =
+                    range.get_or_insert_with(|| ColumnRange::Float(MinMax::default()))
+                {
+                    (0..values.len())
+                        .filter(valid)
+                        .for_each(|i| range.update(&values.value(i)));
+                }
+            }
+        }
+        DataType::Utf8 => {
+            if let Some(values) = values.downcast_ref::<StringArray>() {
+                if let ColumnRange::String(range) =
+                    range.get_or_insert_with(|| ColumnRange::String(MinMax::default()))
+                {
+                    (0..values.len())
+                        .filter(valid)
+                        .for_each(|i| range.update(values.value(i)));
+                }
+            }
+        }
+        DataType::Timestamp(..) => {
+            let nanos: Vec<i64> =
+                if let Some(values) = values.downcast_ref::<TimestampMicrosecondArray>() {
+                    (0..values.len())
+                        .filter(valid)
+                        .map(|i| values.value(i) * 1000)
+                        .collect()
+                } else if let Some(values) = values.downcast_ref::<TimestampNanosecondArray>() {
+                    (0..values.len())
+                        .filter(valid)
+                        .map(|i| values.value(i))
+                        .collect()
+                } else {
+                    return;
+                };
+            if let ColumnRange::Timestamp(range) =
+                range.get_or_insert_with(|| ColumnRange::Timestamp(MinMax::default()))
+            {
+                nanos.iter().for_each(|value| range.update(value));
+            }
+        }
+        _ => {}
+    }
+}
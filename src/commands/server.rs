@@ -1,19 +1,46 @@
-use tracing::{debug, info};
+use tracing::{debug, error, info};
 
+use std::collections::BTreeMap;
 use std::fs;
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::{env::VarError, path::PathBuf};
+use std::time::Duration;
 
-use crate::server::http_routes;
-use crate::server::rpc::storage;
+use crate::server::rpc::{cache::QueryCache, server_limits::ServerLimits, storage};
+use crate::server::{
+    access::{Access, AccessMode},
+    alerts::{Alerts, ResourceUsage, SoftLimits},
+    auth::{self, Authorizer},
+    compaction::{CompactionConfig, CompactionScheduler},
+    config::{self, Config},
+    http_routes,
+    jobs::{JobDescription, Jobs},
+    log_filter::LogFilter,
+    metrics::Metrics,
+    quota::Quotas,
+    replication::{self, HttpPeerClient, Replicator},
+    router::{Mode, Router},
+    state::ServerState,
+    subscriptions::Subscriptions,
+    system_tables::SystemTables,
+    tls::{self, ReloadingCertResolver, TlsConfig},
+};
 
-use ::storage::exec::Executor as StorageExecutor;
+use ::storage::{exec::Executor as StorageExecutor, DatabaseStore};
+use hyper::server::{accept, conn::AddrIncoming};
 use hyper::service::{make_service_fn, service_fn};
 use hyper::Server;
-use write_buffer::{Db, WriteBufferDatabases};
+use object_store::{
+    encryption::{AwsKmsKeyProvider, KeyProvider, StaticKeyProvider},
+    AmazonS3, GoogleCloudStorage, ObjectStore,
+};
+use rusoto_core::Region;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use write_buffer::{Db, ReplayOptions, WriteBufferDatabases};
 
-use snafu::{ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -45,6 +72,54 @@ pub enum Error {
         source: hyper::error::Error,
     },
 
+    #[snafu(display(
+        "Unable to bind to listen for HTTPS requests on {}: {}",
+        bind_addr,
+        source
+    ))]
+    StartListeningTls {
+        bind_addr: SocketAddr,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid configuration: {}", source))]
+    InvalidConfig { source: config::Error },
+
+    #[snafu(display("Incomplete TLS configuration: {}", reason))]
+    IncompleteTlsConfig { reason: String },
+
+    #[snafu(display("tls_reload_interval must be at least one second"))]
+    ZeroTlsReloadInterval,
+
+    #[snafu(display("compaction_interval must be at least one second"))]
+    ZeroCompactionInterval,
+
+    #[snafu(display("shard_id must be set to the shard a writer owns"))]
+    MissingShardId,
+
+    #[snafu(display("Unsupported object_store {:?}: expected file, s3 or google", store))]
+    UnsupportedObjectStore { store: String },
+
+    #[snafu(display("Incomplete object store configuration: {}", reason))]
+    IncompleteObjectStoreConfig { reason: String },
+
+    #[snafu(display(
+        "object_store_encryption_key must be 64 hexadecimal digits: {}",
+        source
+    ))]
+    InvalidEncryptionKeyHex { source: hex::FromHexError },
+
+    #[snafu(display("Invalid object_store_encryption_key: {}", source))]
+    InvalidEncryptionKey {
+        source: object_store::encryption::Error,
+    },
+
+    #[snafu(display("Unable to load API tokens: {}", source))]
+    LoadingTokens { source: auth::Error },
+
+    #[snafu(display("Unable to configure TLS: {}", source))]
+    ConfiguringTls { source: tls::Error },
+
     #[snafu(display("Error serving HTTP: {}", source))]
     ServingHttp { source: hyper::error::Error },
 
@@ -56,12 +131,28 @@ pub enum Error {
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
-pub async fn main() -> Result<()> {
-    dotenv::dotenv().ok();
+/// How often the lifecycle rules of databases are applied, dropping
+/// partitions past their retention period and downsampling aged ones.
+/// They are also applied as soon as the rules of a database change.
+const LIFECYCLE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The file in the database directory the API tokens are kept in
+const TOKENS_FILE_NAME: &str = "tokens.json";
+
+/// The id objects are encrypted with `object_store_encryption_key`
+/// under, if `object_store_encryption_key_id` is not set. It is stored
+/// with each object, so the key can be told apart from those it is
+/// later rotated to.
+const DEFAULT_ENCRYPTION_KEY_ID: &str = "default";
+
+/// How often the resources of databases are checked against their
+/// soft limits
+const ALERTS_CHECK_INTERVAL: Duration = Duration::from_secs(10);
 
-    let db_dir = match std::env::var("INFLUXDB_IOX_DB_DIR") {
-        Ok(val) => val,
-        Err(_) => {
+pub async fn main(config: Config, log_filter: Arc<LogFilter>) -> Result<()> {
+    let db_dir = match config.get("db_dir") {
+        Some(val) => val.to_string(),
+        None => {
             // default database path is $HOME/.influxdb_iox
             let mut path = dirs::home_dir().unwrap();
             path.push(".influxdb_iox/");
@@ -72,67 +163,294 @@ pub async fn main() -> Result<()> {
     fs::create_dir_all(&db_dir).context(CreatingDatabaseDirectory { path: &db_dir })?;
 
     debug!("InfluxDB IOx Server using database directory: {:?}", db_dir);
+    let tokens_file = PathBuf::from(&db_dir).join(TOKENS_FILE_NAME);
 
-    let storage = Arc::new(WriteBufferDatabases::new(&db_dir));
+    // Evicted chunks are read back by queries from files which are
+    // either read into memory ("read", the default) or memory mapped
+    // ("mmap")
+    let storage = WriteBufferDatabases::new(&db_dir);
+    let storage = match config.parse("persisted_open_mode").context(InvalidConfig)? {
+        Some(mode) => storage.with_persisted_open_mode(mode),
+        None => storage,
+    };
+    // The files of persisted chunks are also copied to an object
+    // store, encrypted if a key is configured, only if one is
+    // configured
+    let storage = match object_store(&config)? {
+        Some(store) => storage.with_object_store(Arc::new(store)),
+        None => storage,
+    };
+    let storage = Arc::new(storage);
     let dirs = storage
         .wal_dirs()
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
         .context(InitializingWriteBuffer { db_dir })?;
 
-    // TODO: make recovery of multiple databases multi-threaded
+    // Background work is tracked as jobs, which are managed with the HTTP API
+    let jobs = Arc::new(Jobs::new());
+
+    // SQL queries can introspect the server through its system tables
+    let system_tables = Arc::new(SystemTables::new(Arc::clone(&jobs)));
+
+    // Databases are served as soon as they are opened, with each
+    // partition becoming visible to queries once it has been replayed
+    // from the WAL. Writes to a database are rejected until its WAL
+    // has been replayed.
+    let replay_options = ReplayOptions {
+        max_entries_per_second: config
+            .parse("replay_max_entries_per_second")
+            .context(InvalidConfig)?,
+    };
     for dir in dirs {
-        let db = Db::restore_from_wal(&dir)
+        let db = Db::open_from_wal(&dir)
             .await
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
             .context(RestoringWriteBuffer { dir })?;
-        storage.add_db(db).await;
+        let db = storage.add_db(db).await;
+        tokio::spawn(replay_wal(db, jobs.clone(), replay_options));
+    }
+
+    spawn_lifecycle(storage.clone(), jobs.clone());
+
+    // Compact fragmented partitions in the background only if an
+    // interval (in seconds) is configured
+    let compaction = Arc::new(CompactionScheduler::new(
+        compaction_config(&config)?,
+        jobs.clone(),
+    ));
+    if let Some(interval) = compaction_interval(&config)? {
+        info!("Compacting partitions every {} seconds", interval.as_secs());
+        compaction.clone().spawn(storage.clone(), interval);
     }
 
     // Fire up the query executor
-    let executor = Arc::new(StorageExecutor::default());
+    let executor = match config.parse("query_memory_limit").context(InvalidConfig)? {
+        Some(limit) => StorageExecutor::new().with_memory_limit(limit),
+        None => StorageExecutor::new(),
+    };
+    let executor = match config.get("query_spill_dir") {
+        Some(dir) => executor.with_spill_dir(dir),
+        None => executor,
+    };
+    let executor = Arc::new(executor);
 
     // Construct and start up gRPC server
 
-    let grpc_bind_addr: SocketAddr = match std::env::var("INFLUXDB_IOX_GRPC_BIND_ADDR") {
-        Ok(addr) => addr
-            .parse()
-            .expect("INFLUXDB_IOX_GRPC_BIND_ADDR environment variable not a valid SocketAddr"),
-        Err(VarError::NotPresent) => "127.0.0.1:8082".parse().unwrap(),
-        Err(VarError::NotUnicode(_)) => {
-            panic!("INFLUXDB_IOX_GRPC_BIND_ADDR environment variable not a valid unicode string")
-        }
+    let grpc_bind_addr: SocketAddr = match config.parse("grpc_bind_addr").context(InvalidConfig)? {
+        Some(addr) => addr,
+        None => "127.0.0.1:8082".parse().unwrap(),
     };
 
-    let grpc_server = storage::make_server(grpc_bind_addr, storage.clone(), executor);
+    // Bound the size of requests and how many are served at once, so a
+    // client can't make the server buffer without limit
+    let defaults = ServerLimits::default();
+    let limits = ServerLimits {
+        max_message_bytes: config
+            .parse("grpc_max_message_bytes")
+            .context(InvalidConfig)?
+            .or(defaults.max_message_bytes),
+        max_concurrent_streams: config
+            .parse("grpc_max_concurrent_streams")
+            .context(InvalidConfig)?
+            .or(defaults.max_concurrent_streams),
+        max_connections_per_peer: config
+            .parse("grpc_max_connections_per_peer")
+            .context(InvalidConfig)?
+            .or(defaults.max_connections_per_peer),
+    };
 
-    info!("gRPC server listening on http://{}", grpc_bind_addr);
+    // Cache query responses only if a cache size (in bytes) is configured
+    let max_entry_bytes = config
+        .parse("query_cache_max_entry_bytes")
+        .context(InvalidConfig)?;
+    let cache = config
+        .parse("query_cache_bytes")
+        .context(InvalidConfig)?
+        .map(|bytes| {
+            let cache = QueryCache::new(bytes);
+            match max_entry_bytes {
+                Some(max_entry_bytes) => Arc::new(cache.with_max_entry_bytes(max_entry_bytes)),
+                None => Arc::new(cache),
+            }
+        });
 
-    // Construct and start up HTTP server
+    // Require API tokens only if an admin token is configured. The
+    // tokens it creates are kept in the database directory
+    let authorizer = match config.get("admin_token") {
+        Some(token) => Authorizer::new(token)
+            .with_tokens_file(tokens_file)
+            .context(LoadingTokens)?,
+        None => Authorizer::disabled(),
+    };
+    let authorizer = Arc::new(authorizer);
+
+    // Quotas are configured per database with the HTTP API
+    let quotas = Arc::new(Quotas::new());
 
-    let bind_addr: SocketAddr = match std::env::var("INFLUXDB_IOX_BIND_ADDR") {
-        Ok(addr) => addr
-            .parse()
-            .expect("INFLUXDB_IOX_BIND_ADDR environment variable not a valid SocketAddr"),
-        Err(VarError::NotPresent) => "127.0.0.1:8080".parse().unwrap(),
-        Err(VarError::NotUnicode(_)) => {
-            panic!("INFLUXDB_IOX_BIND_ADDR environment variable not a valid unicode string")
+    // Replicate writes only if peers (as a comma separated list of the
+    // base URLs of their HTTP APIs) are configured
+    let writer_id = config
+        .parse("writer_id")
+        .context(InvalidConfig)?
+        .unwrap_or(0);
+    let replicator = match config.get("replication_peers") {
+        Some(peers) => {
+            let peers: Vec<_> = peers
+                .split(',')
+                .map(str::trim)
+                .filter(|peer| !peer.is_empty())
+                .map(ToString::to_string)
+                .collect();
+            let queue_size = config
+                .parse("replication_queue_size")
+                .context(InvalidConfig)?
+                .unwrap_or(replication::DEFAULT_QUEUE_SIZE);
+            let token = config.get("replication_token").map(ToString::to_string);
+            info!("Replicating writes as writer {} to {:?}", writer_id, peers);
+            Replicator::with_client(
+                writer_id,
+                peers,
+                queue_size,
+                Arc::new(HttpPeerClient::new(token)),
+            )
         }
+        None => Replicator::disabled(),
     };
+    let replicator = Arc::new(replicator);
 
-    let make_svc = make_service_fn(move |_conn| {
-        let storage = storage.clone();
-        async move {
-            Ok::<_, http::Error>(service_fn(move |req| {
-                let state = storage.clone();
-                http_routes::service(req, state)
-            }))
+    // Subscriptions are configured with the HTTP API
+    let subscriptions = Arc::new(Subscriptions::new());
+
+    // A router forwards writes to the writers of the shards configured
+    // with the HTTP API, instead of storing them, and a writer only
+    // stores the lines of the shard it owns
+    let mode = config
+        .parse("mode")
+        .context(InvalidConfig)?
+        .unwrap_or(Mode::Standalone);
+    let router = match mode {
+        Mode::Writer => {
+            let shard_id = config
+                .parse("shard_id")
+                .context(InvalidConfig)?
+                .context(MissingShardId)?;
+            info!("Running as the writer of shard {}", shard_id);
+            Router::writer(shard_id)
         }
+        mode => {
+            info!("Running as {}", mode);
+            Router::new(mode)
+        }
+    };
+    let router = Arc::new(router);
+
+    // Whether writes and queries are accepted, which is changed with
+    // the HTTP API while the server runs
+    let access_mode = config
+        .parse("access_mode")
+        .context(InvalidConfig)?
+        .unwrap_or(AccessMode::ReadWrite);
+    info!("Access mode is {}", access_mode);
+    let access = Access::new(access_mode);
+    // a router stores nothing to answer queries from
+    let access = if router.is_router() {
+        access.without_data()
+    } else {
+        access
+    };
+    let access = Arc::new(access);
+
+    // Warn of databases whose resources cross their soft limits, which
+    // are changed with the HTTP API while the server runs
+    let alerts = Arc::new(Alerts::new(SoftLimits {
+        memory_bytes: config
+            .parse("soft_limit_memory_bytes")
+            .context(InvalidConfig)?,
+        tag_cardinality: config
+            .parse("soft_limit_tag_cardinality")
+            .context(InvalidConfig)?,
+        wal_bytes: config
+            .parse("soft_limit_wal_bytes")
+            .context(InvalidConfig)?,
+    }));
+    spawn_alerts(storage.clone(), alerts.clone());
+
+    // The metrics of the storage RPCs, the databases and replication,
+    // exported by the HTTP API
+    let metrics = Arc::new(Metrics::default());
+    metrics.register(storage.clone());
+    metrics.register(replicator.clone());
+
+    // Serve both APIs over TLS only if a certificate is configured
+    let tls_config = tls_config(&config)?;
+    let (grpc_tls, http_tls) = match &tls_config {
+        Some(tls_config) => {
+            let resolver =
+                Arc::new(ReloadingCertResolver::new(tls_config).context(ConfiguringTls)?);
+            resolver
+                .clone()
+                .spawn_reloader(tls_reload_interval(&config)?);
+
+            let grpc_tls = tls::server_config(resolver.clone(), tls::GRPC_PROTOCOLS);
+            let http_tls = tls::server_config(resolver, tls::HTTP_PROTOCOLS);
+            (Some(grpc_tls), Some(http_tls))
+        }
+        None => (None, None),
+    };
+    let scheme = if tls_config.is_some() {
+        "https"
+    } else {
+        "http"
+    };
+
+    let state = Arc::new(ServerState {
+        storage,
+        authorizer,
+        quotas,
+        jobs,
+        replicator,
+        subscriptions,
+        router,
+        log_filter,
+        system_tables,
+        compaction,
+        access,
+        alerts,
+        metrics,
     });
 
-    let server = Server::try_bind(&bind_addr)
-        .context(StartListening { bind_addr })?
-        .serve(make_svc);
-    info!("Listening on http://{}", bind_addr);
+    let grpc_server = storage::make_server(
+        grpc_bind_addr,
+        state.clone(),
+        executor,
+        cache,
+        limits,
+        grpc_tls,
+    );
+
+    info!("gRPC server listening on {}://{}", scheme, grpc_bind_addr);
+
+    // Construct and start up HTTP server
+
+    let bind_addr: SocketAddr = match config.parse("bind_addr").context(InvalidConfig)? {
+        Some(addr) => addr,
+        None => "127.0.0.1:8080".parse().unwrap(),
+    };
+
+    let server = match http_tls {
+        Some(server_config) => {
+            let listener = TcpListener::bind(bind_addr)
+                .await
+                .context(StartListeningTls { bind_addr })?;
+            let incoming = accept::from_stream(tls::incoming(listener, server_config));
+            futures::future::Either::Left(serve_http(incoming, state))
+        }
+        None => {
+            let incoming = AddrIncoming::bind(&bind_addr).context(StartListening { bind_addr })?;
+            futures::future::Either::Right(serve_http(incoming, state))
+        }
+    };
+    info!("Listening on {}://{}", scheme, bind_addr);
 
     println!("InfluxDB IOx server ready");
 
@@ -144,3 +462,257 @@ pub async fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Serves the HTTP API on the connections accepted by `incoming`
+async fn serve_http<I>(
+    incoming: I,
+    state: Arc<ServerState<WriteBufferDatabases>>,
+) -> Result<(), hyper::Error>
+where
+    I: accept::Accept,
+    I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    let make_svc = make_service_fn(move |_conn| {
+        let state = state.clone();
+        async move {
+            let authorizer = state.authorizer.clone();
+            let service = service_fn(move |req| http_routes::service(req, state.clone()));
+            Ok::<_, http::Error>(http_routes::HttpAuthentication::new(service, authorizer))
+        }
+    });
+
+    Server::builder(incoming).serve(make_svc).await
+}
+
+/// Returns the TLS configuration, if a certificate and key are
+/// configured
+fn tls_config(config: &Config) -> Result<Option<TlsConfig>> {
+    let path = |name: &str| config.get(name).map(PathBuf::from);
+
+    let client_ca_path = path("tls_client_ca");
+    match (path("tls_cert"), path("tls_key")) {
+        (Some(cert_path), Some(key_path)) => Ok(Some(TlsConfig {
+            cert_path,
+            key_path,
+            client_ca_path,
+        })),
+        (None, None) => {
+            ensure!(
+                client_ca_path.is_none(),
+                IncompleteTlsConfig {
+                    reason: "tls_client_ca requires tls_cert and tls_key",
+                }
+            );
+            Ok(None)
+        }
+        _ => IncompleteTlsConfig {
+            reason: "tls_cert and tls_key must be set together",
+        }
+        .fail(),
+    }
+}
+
+/// Applies the lifecycle rules of all databases every
+/// `LIFECYCLE_CHECK_INTERVAL`, and whenever the rules of a database are
+/// changed, until the process exits
+fn spawn_lifecycle(storage: Arc<WriteBufferDatabases>, jobs: Arc<Jobs>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(LIFECYCLE_CHECK_INTERVAL);
+        loop {
+            let tick = interval.tick();
+            let rules_changed = storage.rules_changed();
+            futures::pin_mut!(tick, rules_changed);
+            futures::future::select(tick, rules_changed).await;
+
+            let now = chrono::Utc::now().timestamp_nanos();
+            let storage = &storage;
+            jobs.run(JobDescription::ApplyRetention, |tracker| async move {
+                let db_names = storage.db_names_sorted().await;
+                for (done, db_name) in db_names.iter().enumerate() {
+                    if let Some(db) = storage.db(db_name).await {
+                        let dropped = db.apply_retention(now).await?;
+                        tracker.add_metric("partitions_dropped", dropped as u64);
+                    }
+                    tracker.set_progress(done as u64 + 1, db_names.len() as u64);
+                }
+                Ok::<_, <Db as ::storage::Database>::Error>(())
+            })
+            .await;
+
+            jobs.run(JobDescription::Downsample, |tracker| async move {
+                let downsampled = storage.downsample(now).await?;
+                tracker.add_metric("partitions_downsampled", downsampled as u64);
+                Ok::<_, <WriteBufferDatabases as DatabaseStore>::Error>(())
+            })
+            .await;
+
+            jobs.run(JobDescription::PersistIdle, |tracker| async move {
+                let evicted = storage.persist_idle(now).await?;
+                tracker.add_metric("partitions_evicted", evicted as u64);
+                Ok::<_, <WriteBufferDatabases as DatabaseStore>::Error>(())
+            })
+            .await;
+        }
+    });
+}
+
+/// Checks the resources of all databases against the soft limits of
+/// `alerts` every `ALERTS_CHECK_INTERVAL`, until the process exits
+fn spawn_alerts(storage: Arc<WriteBufferDatabases>, alerts: Arc<Alerts>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(ALERTS_CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let mut usage = BTreeMap::new();
+            for db_name in storage.db_names_sorted().await {
+                if let Some(db) = storage.db(&db_name).await {
+                    let db_usage = ResourceUsage {
+                        memory_bytes: db.size().await as u64,
+                        tag_cardinality: db.max_tag_cardinality().await as u64,
+                        wal_bytes: db.wal_bytes(),
+                    };
+                    usage.insert(db_name, db_usage);
+                }
+            }
+            alerts.check(&usage, chrono::Utc::now().timestamp_nanos());
+        }
+    });
+}
+
+/// Replays the WAL of `db` as a job, reporting the number of WAL
+/// entries applied as its progress
+async fn replay_wal(db: Arc<Db>, jobs: Arc<Jobs>, options: ReplayOptions) {
+    let db_name = db.name.clone();
+    let description = JobDescription::ReplayWal {
+        db_name: db_name.clone(),
+    };
+    let replayed = jobs
+        .run(description, |tracker| async move {
+            db.replay_wal(options, |progress| {
+                tracker.set_progress(progress.entries_applied, progress.entries_total)
+            })
+            .await?;
+            tracker.add_metric("partitions", db.len().await as u64);
+            tracker.add_metric("bytes", db.size().await as u64);
+            Ok::<_, Box<dyn std::error::Error + Send + Sync>>(())
+        })
+        .await;
+
+    if let Some(Err(e)) = replayed {
+        error!("Error replaying the WAL of database {}: {}", db_name, e);
+    }
+}
+
+/// Returns how compactions are scheduled, defaulting any settings
+/// which are not configured
+fn compaction_config(config: &Config) -> Result<CompactionConfig> {
+    let default = CompactionConfig::default();
+    Ok(CompactionConfig {
+        max_concurrent: config
+            .parse("compaction_max_concurrent")
+            .context(InvalidConfig)?
+            .unwrap_or(default.max_concurrent),
+        max_bytes_per_second: config
+            .parse("compaction_max_bytes_per_second")
+            .context(InvalidConfig)?,
+        min_chunks: config
+            .parse("compaction_min_chunks")
+            .context(InvalidConfig)?
+            .unwrap_or(default.min_chunks),
+    })
+}
+
+/// Returns how often fragmented partitions are compacted, if they are
+fn compaction_interval(config: &Config) -> Result<Option<Duration>> {
+    let interval = config
+        .parse("compaction_interval")
+        .context(InvalidConfig)?
+        .map(Duration::from_secs);
+    ensure!(
+        interval != Some(Duration::from_secs(0)),
+        ZeroCompactionInterval
+    );
+    Ok(interval)
+}
+
+/// Returns the object store the files of persisted chunks are copied
+/// to, if one is configured, which encrypts them with the configured
+/// key or KMS key, if either is
+fn object_store(config: &Config) -> Result<Option<ObjectStore>> {
+    let bucket = || {
+        config
+            .get("object_store_bucket")
+            .context(IncompleteObjectStoreConfig {
+                reason: "object_store_bucket must be set for s3 and google",
+            })
+    };
+    let region = || -> Result<Region> {
+        Ok(config
+            .parse("object_store_region")
+            .context(InvalidConfig)?
+            .unwrap_or_default())
+    };
+
+    let key = config.get("object_store_encryption_key");
+    let kms_key_id = config.get("object_store_kms_key_id");
+
+    let store = match config.get("object_store") {
+        Some("file") => {
+            let dir = config
+                .get("object_store_dir")
+                .context(IncompleteObjectStoreConfig {
+                    reason: "object_store_dir must be set for file",
+                })?;
+            ObjectStore::new_file(object_store::File::new(dir))
+        }
+        Some("s3") => ObjectStore::new_amazon_s3(AmazonS3::new(region()?, bucket()?)),
+        Some("google") => ObjectStore::new_google_cloud_storage(GoogleCloudStorage::new(bucket()?)),
+        Some(store) => return UnsupportedObjectStore { store }.fail(),
+        None => {
+            ensure!(
+                key.is_none() && kms_key_id.is_none(),
+                IncompleteObjectStoreConfig {
+                    reason: "encryption requires object_store to be set",
+                }
+            );
+            return Ok(None);
+        }
+    };
+
+    let key_provider: Arc<dyn KeyProvider> = match (key, kms_key_id) {
+        (Some(key), None) => {
+            let key = hex::decode(key).context(InvalidEncryptionKeyHex)?;
+            let key_id = config
+                .get("object_store_encryption_key_id")
+                .unwrap_or(DEFAULT_ENCRYPTION_KEY_ID);
+            Arc::new(StaticKeyProvider::new(key_id, &key).context(InvalidEncryptionKey)?)
+        }
+        (None, Some(kms_key_id)) => Arc::new(AwsKmsKeyProvider::new(region()?, kms_key_id)),
+        (None, None) => return Ok(Some(store)),
+        (Some(_), Some(_)) => {
+            return IncompleteObjectStoreConfig {
+                reason: "object_store_encryption_key and object_store_kms_key_id can't both be set",
+            }
+            .fail()
+        }
+    };
+
+    info!(
+        "Encrypting stored objects with key {}",
+        key_provider.key_id()
+    );
+    Ok(Some(ObjectStore::new_encrypted(store, key_provider)))
+}
+
+/// Returns how often the TLS certificate files are checked for changes
+fn tls_reload_interval(config: &Config) -> Result<Duration> {
+    let interval = config
+        .parse("tls_reload_interval")
+        .context(InvalidConfig)?
+        .map(Duration::from_secs)
+        .unwrap_or(tls::DEFAULT_RELOAD_INTERVAL);
+    ensure!(interval > Duration::from_secs(0), ZeroTlsReloadInterval);
+    Ok(interval)
+}
@@ -0,0 +1,303 @@
+//! This module contains an interactive SQL shell, which runs queries
+//! against a remote IOx server over its Arrow Flight API
+
+use std::{path::PathBuf, sync::Arc, time::Instant};
+
+use arrow_deps::arrow::{
+    array::{ArrayRef, StringArray},
+    datatypes::{DataType, Field, Schema},
+    error::ArrowError,
+    record_batch::RecordBatch,
+    util::pretty::pretty_format_batches,
+};
+use influxdb_iox_client::flight::{self, FlightClient};
+use rustyline::{error::ReadlineError, Editor};
+use snafu::{ResultExt, Snafu};
+use tracing::debug;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error connecting to {}: {}", host, source))]
+    Connecting { host: String, source: flight::Error },
+
+    #[snafu(display("Error reading input: {}", source))]
+    ReadingInput { source: ReadlineError },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+const HELP: &str = r#"Statements are sent to the server once they end with a ';'. Commands:
+
+  \c NAME     use the database NAME
+  \d          list the tables of the database
+  \d TABLE    describe the columns of TABLE
+  \timing     toggle printing how long each statement took
+  \?          show this help
+  \q          quit
+"#;
+
+/// Describes how to connect to the server
+#[derive(Debug)]
+pub struct SqlConfig {
+    /// The URL of the gRPC API of the server
+    pub host: String,
+
+    /// The database to query initially, if any
+    pub database: Option<String>,
+
+    /// The API token to authorize queries with, if any
+    pub token: Option<String>,
+}
+
+/// A backslash command of the shell
+#[derive(Debug, PartialEq)]
+enum Command<'a> {
+    Help,
+    Quit,
+    UseDatabase(&'a str),
+    ListTables,
+    DescribeTable(&'a str),
+    Timing,
+    Unknown(&'a str),
+}
+
+impl<'a> Command<'a> {
+    /// Parses a line starting with a backslash
+    fn parse(line: &'a str) -> Self {
+        let mut parts = line.trim().splitn(2, char::is_whitespace);
+        let name = parts.next().unwrap_or_default();
+        let argument = parts.next().map(str::trim).filter(|arg| !arg.is_empty());
+
+        match (name, argument) {
+            ("\\?", None) | ("\\h", None) => Self::Help,
+            ("\\q", None) => Self::Quit,
+            ("\\c", Some(database)) => Self::UseDatabase(database),
+            ("\\d", None) => Self::ListTables,
+            ("\\d", Some(table_name)) => Self::DescribeTable(table_name),
+            ("\\timing", None) => Self::Timing,
+            _ => Self::Unknown(line.trim()),
+        }
+    }
+}
+
+/// The state of the shell
+#[derive(Debug)]
+struct Repl {
+    client: FlightClient,
+    database: Option<String>,
+    timing: bool,
+}
+
+impl Repl {
+    /// Runs `command`, returning false if the shell should exit
+    async fn run_command(&mut self, command: Command<'_>) -> bool {
+        match command {
+            Command::Help => print!("{}", HELP),
+            Command::Quit => return false,
+            Command::UseDatabase(database) => {
+                println!("Using database {}", database);
+                self.database = Some(database.to_string());
+            }
+            Command::ListTables => {
+                if let Some(database) = self.database() {
+                    match self.client.tables(&database).await {
+                        Ok(table_names) => print_strings("table_name", table_names),
+                        Err(e) => eprintln!("Error listing tables: {}", e),
+                    }
+                }
+            }
+            Command::DescribeTable(table_name) => {
+                if let Some(database) = self.database() {
+                    match self.client.table_schema(&database, table_name).await {
+                        Ok(schema) => print_schema(&schema),
+                        Err(e) => eprintln!("Error describing {}: {}", table_name, e),
+                    }
+                }
+            }
+            Command::Timing => {
+                self.timing = !self.timing;
+                println!("Timing is {}", if self.timing { "on" } else { "off" });
+            }
+            Command::Unknown(command) => {
+                eprintln!("Unknown command {}, type \\? for help", command)
+            }
+        }
+        true
+    }
+
+    /// Runs the SQL statement `sql` and prints its results
+    async fn run_statement(&mut self, sql: &str) {
+        let database = match self.database() {
+            Some(database) => database,
+            None => return,
+        };
+
+        let start = Instant::now();
+        match self.client.query(&database, sql).await {
+            Ok(batches) => {
+                let rows: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+                if !batches.is_empty() {
+                    print_batches(&batches);
+                }
+                print!("{} row{}", rows, if rows == 1 { "" } else { "s" });
+                if self.timing {
+                    print!(" in {:.3}s", start.elapsed().as_secs_f64());
+                }
+                println!();
+            }
+            Err(e) => eprintln!("Error running query: {}", e),
+        }
+    }
+
+    /// Returns the current database, printing a hint if there is none
+    fn database(&self) -> Option<String> {
+        if self.database.is_none() {
+            eprintln!("No database selected, use \\c NAME to choose one");
+        }
+        self.database.clone()
+    }
+
+    /// The prompt for the first line of a statement, or for the
+    /// continuation of one
+    fn prompt(&self, continuation: bool) -> String {
+        let database = self.database.as_deref().unwrap_or("");
+        format!("{}{} ", database, if continuation { "->" } else { ">" })
+    }
+}
+
+/// Runs the interactive SQL shell until the user quits it
+pub async fn repl(config: SqlConfig) -> Result<()> {
+    let client = FlightClient::connect(&config.host)
+        .await
+        .and_then(|client| match &config.token {
+            Some(token) => client.with_token(token),
+            None => Ok(client),
+        })
+        .context(Connecting { host: &config.host })?;
+
+    let mut repl = Repl {
+        client,
+        database: config.database,
+        timing: false,
+    };
+
+    let mut editor = Editor::<()>::new();
+    let history_file = history_file();
+    if let Some(history_file) = &history_file {
+        // there is no history the first time the shell runs
+        if let Err(e) = editor.load_history(history_file) {
+            debug!("Not loading history from {:?}: {}", history_file, e);
+        }
+    }
+
+    println!(
+        "Connected to {}, type \\? for help and \\q to quit",
+        config.host
+    );
+
+    let mut statement = String::new();
+    loop {
+        let line = match editor.readline(&repl.prompt(!statement.is_empty())) {
+            Ok(line) => line,
+            // Ctrl-C abandons the statement being typed
+            Err(ReadlineError::Interrupted) => {
+                statement.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e).context(ReadingInput),
+        };
+
+        if statement.is_empty() && line.trim_start().starts_with('\\') {
+            editor.add_history_entry(line.trim());
+            if !repl.run_command(Command::parse(&line)).await {
+                break;
+            }
+            continue;
+        }
+
+        if !statement.is_empty() {
+            statement.push('\n');
+        }
+        statement.push_str(&line);
+
+        let trimmed = statement.trim();
+        if trimmed.ends_with(';') {
+            editor.add_history_entry(trimmed);
+            let sql = trimmed.trim_end_matches(';').to_string();
+            statement.clear();
+            if !sql.trim().is_empty() {
+                repl.run_statement(&sql).await;
+            }
+        }
+    }
+
+    if let Some(history_file) = &history_file {
+        if let Err(e) = editor.save_history(history_file) {
+            eprintln!(
+                "WARNING: Could not save history to {:?}: {}",
+                history_file, e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The file the statements and commands typed in the shell are saved
+/// in, so they can be recalled in later sessions
+fn history_file() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".influxdb_iox_history"))
+}
+
+fn print_batches(batches: &[RecordBatch]) {
+    match pretty_format_batches(batches) {
+        Ok(table) => println!("{}", table),
+        Err(e) => eprintln!("Error formatting results: {}", e),
+    }
+}
+
+/// Prints `values` as a table of a single column `column_name`
+fn print_strings(column_name: &str, values: Vec<String>) {
+    let schema = Schema::new(vec![Field::new(column_name, DataType::Utf8, false)]);
+    let column: ArrayRef = Arc::new(StringArray::from(
+        values.iter().map(String::as_str).collect::<Vec<_>>(),
+    ));
+    print_batch(RecordBatch::try_new(Arc::new(schema), vec![column]));
+}
+
+/// Prints the name, type and nullability of each column of `schema`
+fn print_schema(schema: &Schema) {
+    let fields = schema.fields();
+    let data_types: Vec<_> = fields
+        .iter()
+        .map(|f| format!("{:?}", f.data_type()))
+        .collect();
+    let result_schema = Schema::new(vec![
+        Field::new("column_name", DataType::Utf8, false),
+        Field::new("data_type", DataType::Utf8, false),
+        Field::new("nullable", DataType::Utf8, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(
+            fields.iter().map(|f| f.name().as_str()).collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            data_types.iter().map(String::as_str).collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            fields
+                .iter()
+                .map(|f| if f.is_nullable() { "YES" } else { "NO" })
+                .collect::<Vec<_>>(),
+        )),
+    ];
+    print_batch(RecordBatch::try_new(Arc::new(result_schema), columns));
+}
+
+fn print_batch(batch: Result<RecordBatch, ArrowError>) {
+    match batch {
+        Ok(batch) => print_batches(&[batch]),
+        Err(e) => eprintln!("Error formatting results: {}", e),
+    }
+}
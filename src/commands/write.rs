@@ -0,0 +1,469 @@
+//! This module contains code to bulk import line protocol and CSV
+//! files into a running server.
+//!
+//! Files are read and uploaded in chunks of lines, so files larger
+//! than memory can be imported. After each chunk is written, the
+//! offset of the data imported so far is saved next to the file, so
+//! an import that fails part way through can continue where it
+//! stopped with `--resume`.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, BufRead, BufReader, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::Instant,
+};
+
+use influxdb_iox_client::{line, Client, FieldValue, Line};
+use snafu::{OptionExt, ResultExt, Snafu};
+use tracing::debug;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error opening {}: {}", path.display(), source))]
+    OpeningFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error reading {}: {}", path.display(), source))]
+    ReadingFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error reading CSV from {}: {}", path.display(), source))]
+    ReadingCsv { path: PathBuf, source: csv::Error },
+
+    #[snafu(display(
+        "Invalid column mapping '{}', expected NAME:tag, NAME:field[:float|int|bool|string], \
+         NAME:time[:ns|rfc3339], NAME:measurement or NAME:ignore",
+        mapping
+    ))]
+    InvalidColumnMapping { mapping: String },
+
+    #[snafu(display("Column {} in the column mapping is not in the CSV header", column))]
+    UnknownColumn { column: String },
+
+    #[snafu(display(
+        "CSV rows need a measurement, either from --measurement or a NAME:measurement column"
+    ))]
+    MissingMeasurement {},
+
+    #[snafu(display(
+        "Invalid {} value '{}' in column {} at byte {}",
+        value_type,
+        value,
+        column,
+        offset
+    ))]
+    InvalidValue {
+        value_type: String,
+        value: String,
+        column: String,
+        offset: u64,
+    },
+
+    #[snafu(display("Invalid CSV row at byte {}: {}", offset, source))]
+    InvalidRow { offset: u64, source: line::Error },
+
+    #[snafu(display(
+        "Error writing to the server: {}. Run the import again with --resume to continue from \
+         byte {}",
+        source,
+        offset
+    ))]
+    Writing {
+        offset: u64,
+        source: influxdb_iox_client::Error,
+    },
+
+    #[snafu(display("Error saving progress to {}: {}", path.display(), source))]
+    SavingProgress { path: PathBuf, source: io::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The default number of lines uploaded in each request
+pub const DEFAULT_BATCH_SIZE: usize = 5000;
+
+/// The format of the file to import
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    LineProtocol,
+    Csv,
+}
+
+impl Format {
+    /// Guesses the format of `path` from its extension
+    pub fn for_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Self::Csv,
+            _ => Self::LineProtocol,
+        }
+    }
+}
+
+/// Describes what to import and where
+#[derive(Debug)]
+pub struct WriteConfig {
+    /// The URL of the HTTP API of the server
+    pub host: String,
+
+    /// The API token to authorize writes with, if any
+    pub token: Option<String>,
+
+    pub org: String,
+    pub bucket: String,
+
+    pub path: PathBuf,
+    pub format: Format,
+
+    /// How the columns of a CSV file map to line protocol, as comma
+    /// separated `NAME:role` pairs
+    pub columns: Option<String>,
+
+    /// The measurement of the lines of a CSV file without a
+    /// measurement column
+    pub measurement: Option<String>,
+
+    /// The number of lines uploaded in each request
+    pub batch_size: usize,
+
+    /// Continue a previous import of the file from where it stopped
+    pub resume: bool,
+}
+
+/// The type of the values of a CSV field column
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FieldType {
+    Float,
+    Integer,
+    Boolean,
+    String,
+}
+
+/// The format of the values of a CSV time column
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TimeFormat {
+    Nanoseconds,
+    Rfc3339,
+}
+
+/// What a CSV column becomes in line protocol
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ColumnRole {
+    Measurement,
+    Tag,
+    Field(FieldType),
+    Time(TimeFormat),
+    Ignore,
+}
+
+impl ColumnRole {
+    fn parse(role: &str) -> Option<Self> {
+        Some(match role {
+            "measurement" => Self::Measurement,
+            "tag" => Self::Tag,
+            "field" | "field:float" => Self::Field(FieldType::Float),
+            "field:int" => Self::Field(FieldType::Integer),
+            "field:bool" => Self::Field(FieldType::Boolean),
+            "field:string" => Self::Field(FieldType::String),
+            "time" | "time:ns" => Self::Time(TimeFormat::Nanoseconds),
+            "time:rfc3339" => Self::Time(TimeFormat::Rfc3339),
+            "ignore" => Self::Ignore,
+            _ => return None,
+        })
+    }
+}
+
+/// Converts the rows of a CSV file to lines of line protocol
+#[derive(Debug)]
+struct CsvMapping {
+    header: Vec<String>,
+    roles: Vec<ColumnRole>,
+    measurement: Option<String>,
+}
+
+impl CsvMapping {
+    /// Maps the columns of `header` as described by `columns`.
+    /// Columns without a mapping are float fields, except a column
+    /// named `time`, which holds timestamps in nanoseconds.
+    fn new(
+        header: &csv::StringRecord,
+        columns: Option<&str>,
+        measurement: Option<String>,
+    ) -> Result<Self> {
+        let header: Vec<String> = header.iter().map(ToString::to_string).collect();
+
+        let mut mapped = BTreeMap::new();
+        for mapping in columns
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|mapping| !mapping.is_empty())
+        {
+            let mut parts = mapping.splitn(2, ':');
+            let column = parts.next().unwrap_or_default();
+            let role = parts
+                .next()
+                .and_then(ColumnRole::parse)
+                .context(InvalidColumnMapping { mapping })?;
+            if !header.iter().any(|name| name == column) {
+                return UnknownColumn { column }.fail();
+            }
+            mapped.insert(column, role);
+        }
+
+        let roles = header
+            .iter()
+            .map(|name| match mapped.get(name.as_str()) {
+                Some(role) => *role,
+                None if name == "time" => ColumnRole::Time(TimeFormat::Nanoseconds),
+                None => ColumnRole::Field(FieldType::Float),
+            })
+            .collect::<Vec<_>>();
+
+        if measurement.is_none() && !roles.contains(&ColumnRole::Measurement) {
+            return MissingMeasurement {}.fail();
+        }
+
+        Ok(Self {
+            header,
+            roles,
+            measurement,
+        })
+    }
+
+    /// Converts `record`, which starts at byte `offset` of the file
+    fn to_line(&self, record: &csv::StringRecord, offset: u64) -> Result<Line> {
+        let measurement = self
+            .roles
+            .iter()
+            .zip(record.iter())
+            .find(|(role, _)| **role == ColumnRole::Measurement)
+            .map(|(_, value)| value)
+            .or_else(|| self.measurement.as_deref())
+            .unwrap_or_default();
+        let mut builder = Line::builder(measurement);
+
+        for ((column, role), value) in self.header.iter().zip(&self.roles).zip(record.iter()) {
+            // empty values are missing rather than empty strings
+            if value.is_empty() {
+                continue;
+            }
+
+            let invalid = |value_type: &str| InvalidValue {
+                value_type,
+                value,
+                column,
+                offset,
+            };
+
+            builder = match role {
+                ColumnRole::Tag => builder.tag(column.as_str(), value),
+                ColumnRole::Field(field_type) => {
+                    let field_value: FieldValue = match field_type {
+                        FieldType::Float => {
+                            value.parse::<f64>().ok().context(invalid("float"))?.into()
+                        }
+                        FieldType::Integer => value
+                            .parse::<i64>()
+                            .ok()
+                            .context(invalid("integer"))?
+                            .into(),
+                        FieldType::Boolean => value
+                            .parse::<bool>()
+                            .ok()
+                            .context(invalid("boolean"))?
+                            .into(),
+                        FieldType::String => value.into(),
+                    };
+                    builder.field(column.as_str(), field_value)
+                }
+                ColumnRole::Time(TimeFormat::Nanoseconds) => {
+                    builder.timestamp(value.parse().ok().context(invalid("timestamp"))?)
+                }
+                ColumnRole::Time(TimeFormat::Rfc3339) => builder.timestamp(
+                    chrono::DateTime::parse_from_rfc3339(value)
+                        .ok()
+                        .context(invalid("RFC 3339 timestamp"))?
+                        .timestamp_nanos(),
+                ),
+                ColumnRole::Measurement | ColumnRole::Ignore => builder,
+            };
+        }
+
+        builder.build().context(InvalidRow { offset })
+    }
+}
+
+/// Tracks how much of a file has been imported, in a file next to it
+#[derive(Debug)]
+struct Progress {
+    path: PathBuf,
+    total_bytes: u64,
+    lines: usize,
+    start: Instant,
+}
+
+impl Progress {
+    fn new(input: &Path, total_bytes: u64) -> Self {
+        let mut path = input.as_os_str().to_owned();
+        path.push(".iox-progress");
+        Self {
+            path: path.into(),
+            total_bytes,
+            lines: 0,
+            start: Instant::now(),
+        }
+    }
+
+    /// The offset a previous import stopped at, if any
+    fn saved_offset(&self) -> Option<u64> {
+        fs::read_to_string(&self.path)
+            .ok()
+            .and_then(|offset| offset.trim().parse().ok())
+    }
+
+    /// Records that `lines` more lines were imported, up to `offset`
+    fn advance(&mut self, lines: usize, offset: u64) -> Result<()> {
+        fs::write(&self.path, offset.to_string()).context(SavingProgress { path: &self.path })?;
+
+        self.lines += lines;
+        let percent = if self.total_bytes == 0 {
+            100.0
+        } else {
+            offset as f64 * 100.0 / self.total_bytes as f64
+        };
+        eprintln!(
+            "Wrote {} lines ({:.1}%) in {:.1}s",
+            self.lines,
+            percent,
+            self.start.elapsed().as_secs_f64()
+        );
+        Ok(())
+    }
+
+    /// Removes the saved offset once the whole file is imported
+    fn finish(self) -> Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(e).context(SavingProgress { path: &self.path })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Imports the file described by `config` into the server
+pub async fn write(config: &WriteConfig) -> Result<()> {
+    let mut client = Client::new(&config.host);
+    if let Some(token) = &config.token {
+        client = client.with_token(token);
+    }
+
+    let path = &config.path;
+    let file = File::open(path).context(OpeningFile { path })?;
+    let total_bytes = file.metadata().context(ReadingFile { path })?.len();
+
+    let mut progress = Progress::new(path, total_bytes);
+    let start_offset = if config.resume {
+        progress.saved_offset().unwrap_or(0)
+    } else {
+        0
+    };
+    if start_offset > 0 {
+        eprintln!(
+            "Resuming the import of {} from byte {}",
+            path.display(),
+            start_offset
+        );
+    }
+
+    let batch_size = config.batch_size.max(1);
+    // the offset of the end of the data written so far, which is only
+    // saved once a chunk is written so a failed import resumes from the
+    // start of the chunk that failed
+    let mut offset = start_offset;
+    let mut body = String::new();
+    let mut lines = 0;
+
+    match config.format {
+        Format::LineProtocol => {
+            let mut reader = BufReader::new(file);
+            reader
+                .seek(SeekFrom::Start(start_offset))
+                .context(ReadingFile { path })?;
+
+            let mut chunk_end = start_offset;
+            loop {
+                let read = reader.read_line(&mut body).context(ReadingFile { path })?;
+                chunk_end += read as u64;
+                if read > 0 {
+                    if !body.ends_with('\n') {
+                        body.push('\n');
+                    }
+                    lines += 1;
+                }
+
+                if lines == batch_size || (read == 0 && lines > 0) {
+                    upload(&client, config, std::mem::take(&mut body), offset).await?;
+                    offset = chunk_end;
+                    progress.advance(lines, offset)?;
+                    lines = 0;
+                }
+                if read == 0 {
+                    break;
+                }
+            }
+        }
+        Format::Csv => {
+            let mut reader = csv::Reader::from_reader(file);
+            let header = reader.headers().context(ReadingCsv { path })?.clone();
+            let mapping = CsvMapping::new(
+                &header,
+                config.columns.as_deref(),
+                config.measurement.clone(),
+            )?;
+
+            if start_offset > 0 {
+                let mut position = csv::Position::new();
+                position.set_byte(start_offset);
+                reader.seek(position).context(ReadingCsv { path })?;
+            }
+
+            let mut record = csv::StringRecord::new();
+            loop {
+                let record_offset = reader.position().byte();
+                let more = reader
+                    .read_record(&mut record)
+                    .context(ReadingCsv { path })?;
+                if more {
+                    let line = mapping.to_line(&record, record_offset)?;
+                    body.push_str(&line.to_string());
+                    body.push('\n');
+                    lines += 1;
+                }
+
+                if lines == batch_size || (!more && lines > 0) {
+                    upload(&client, config, std::mem::take(&mut body), offset).await?;
+                    offset = reader.position().byte();
+                    progress.advance(lines, offset)?;
+                    lines = 0;
+                }
+                if !more {
+                    break;
+                }
+            }
+        }
+    }
+
+    progress.finish()
+}
+
+/// Writes the lines of `body` to the org and bucket of `config`.
+/// `offset` is where the chunk starts, from which a later import with
+/// `--resume` continues if this fails.
+async fn upload(client: &Client, config: &WriteConfig, body: String, offset: u64) -> Result<()> {
+    debug!("Writing {} bytes starting at byte {}", body.len(), offset);
+    client
+        .write_line_protocol(&config.org, &config.bucket, body)
+        .await
+        .context(Writing { offset })
+}
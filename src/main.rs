@@ -9,27 +9,44 @@
 
 use clap::{crate_authors, crate_version, value_t, App, Arg, ArgMatches, SubCommand};
 use ingest::parquet::writer::CompressionLevel;
+use std::{path::Path, sync::Arc};
 use tokio::runtime::Runtime;
 use tracing::{debug, error, info, warn};
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod panic;
 pub mod server;
 
 mod commands {
+    pub mod config;
     pub mod convert;
+    pub mod export;
     pub mod file_meta;
+    pub mod generate;
+    pub mod import;
     mod input;
+    pub mod inspect;
     pub mod server;
+    pub mod sql;
     pub mod stats;
+    pub mod write;
 }
 
 use panic::SendPanicsToTracing;
+use server::{config::Config, log_filter::LogFilter};
 
 enum ReturnCode {
     ConversionFailed = 1,
     MetadataDumpFailed = 2,
     StatsFailed = 3,
     ServerExitedAbnormally = 4,
+    InvalidConfig = 5,
+    SqlFailed = 6,
+    WriteFailed = 7,
+    ImportFailed = 8,
+    ExportFailed = 9,
+    InspectFailed = 10,
+    GenerateFailed = 11,
 }
 
 fn main() -> Result<(), std::io::Error> {
@@ -45,6 +62,33 @@ Examples:
     # Run InfluxDB IOx with full debug logging specified with RUST_LOG
     RUST_LOG=debug influxdb_iox
 
+    # Run InfluxDB IOx, exporting traces to a local Jaeger agent
+    INFLUXDB_IOX_TRACES_EXPORTER=jaeger influxdb_iox
+
+    # Run InfluxDB IOx configured by iox.toml, overriding its bind_addr
+    influxdb_iox --config iox.toml --set bind_addr=0.0.0.0:8080
+
+    # Print the configuration InfluxDB IOx would run with
+    influxdb_iox --config iox.toml config show
+
+    # Query the database mydb of a running server with SQL
+    influxdb_iox sql --database mydb
+
+    # Import the line protocol in metrics.lp into the bucket sensors of the org company
+    influxdb_iox write --org company --bucket sensors --file metrics.lp
+
+    # Migrate the TSM files of an InfluxDB 2.x engine directory
+    influxdb_iox import ~/.influxdbv2/engine/data
+
+    # Export the cpu table of the database mydb for November 2020 to cpu/cpu.parquet
+    influxdb_iox export --database mydb --table cpu --start 2020-11-01T00:00:00Z --end 2020-12-01T00:00:00Z cpu
+
+    # Print the schema, encodings and column statistics of a persisted chunk
+    influxdb_iox inspect ~/.influxdb_iox/mydb/chunks/2020-11-01T00/0
+
+    # Write the load described by workload.toml to the bucket load of the org company
+    influxdb_iox generate --org company --bucket load workload.toml
+
     # converts line protocol formatted data in temperature.lp to out.parquet
     influxdb_iox convert temperature.lp out.parquet
 
@@ -95,6 +139,17 @@ Examples:
                         .index(1),
                 ),
         )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("Print the schema, row counts, column encodings and sizes, and \
+                        minimum and maximum column values of a persisted chunk")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("The chunk directory, or a table file of a chunk, to inspect")
+                        .required(true)
+                        .index(1),
+                ),
+        )
         .subcommand(
             SubCommand::with_name("stats")
                 .about("Print out storage statistics information to stdout. \
@@ -120,6 +175,299 @@ Examples:
             SubCommand::with_name("server")
                 .about("Runs in server mode (default)")
         )
+        .subcommand(
+            SubCommand::with_name("sql")
+                .about("Runs an interactive SQL shell against a running server")
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .takes_value(true)
+                        .default_value("http://127.0.0.1:8082")
+                        .help("The URL of the gRPC API of the server"),
+                )
+                .arg(
+                    Arg::with_name("database")
+                        .short("d")
+                        .long("database")
+                        .takes_value(true)
+                        .help("The database to query, which can be changed with \\c NAME"),
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .long("token")
+                        .takes_value(true)
+                        .help("The API token to authorize queries with"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("write")
+                .about("Imports a line protocol or CSV file into a running server")
+                .arg(
+                    Arg::with_name("file")
+                        .long("file")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The file to import"),
+                )
+                .arg(
+                    Arg::with_name("org")
+                        .long("org")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The organization to write to"),
+                )
+                .arg(
+                    Arg::with_name("bucket")
+                        .long("bucket")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The bucket to write to"),
+                )
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .takes_value(true)
+                        .default_value("http://127.0.0.1:8080")
+                        .help("The URL of the HTTP API of the server"),
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .long("token")
+                        .takes_value(true)
+                        .help("The API token to authorize writes with"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["lp", "csv"])
+                        .help("The format of the file. Defaults to csv for .csv files, and line protocol otherwise"),
+                )
+                .arg(
+                    Arg::with_name("columns")
+                        .long("columns")
+                        .takes_value(true)
+                        .value_name("NAME:ROLE,...")
+                        .help("How the columns of a CSV file map to line protocol: NAME:tag, \
+                               NAME:field[:float|int|bool|string], NAME:time[:ns|rfc3339], \
+                               NAME:measurement or NAME:ignore. Other columns are float fields, \
+                               except a column named time, which holds timestamps in nanoseconds"),
+                )
+                .arg(
+                    Arg::with_name("measurement")
+                        .long("measurement")
+                        .takes_value(true)
+                        .help("The measurement of the rows of a CSV file without a measurement column"),
+                )
+                .arg(
+                    Arg::with_name("batch-size")
+                        .long("batch-size")
+                        .takes_value(true)
+                        .help("The number of lines to upload in each request [default: 5000]"),
+                )
+                .arg(
+                    Arg::with_name("resume")
+                        .long("resume")
+                        .help("Continue a previous import of the file that failed from where it stopped"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("import")
+                .about("Migrates InfluxDB 2.x TSM files, or line protocol exported from InfluxDB 1.x, into a running server")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("The files, or directories of .tsm and .lp files, to import")
+                        .required(true)
+                        .multiple(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("map")
+                        .long("map")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .value_name("SOURCE=ORG/BUCKET")
+                        .help("Writes the data of a 1.x DATABASE/RP or 2.x ORG_ID/BUCKET_ID to the bucket \
+                               BUCKET of the org ORG. Data without a mapping is written to the org and \
+                               bucket named by its source"),
+                )
+                .arg(
+                    Arg::with_name("org")
+                        .long("org")
+                        .takes_value(true)
+                        .requires("bucket")
+                        .help("The org to write line protocol without database context to"),
+                )
+                .arg(
+                    Arg::with_name("bucket")
+                        .long("bucket")
+                        .takes_value(true)
+                        .requires("org")
+                        .help("The bucket to write line protocol without database context to"),
+                )
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .takes_value(true)
+                        .default_value("http://127.0.0.1:8080")
+                        .help("The URL of the HTTP API of the server"),
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .long("token")
+                        .takes_value(true)
+                        .help("The API token to authorize writes with"),
+                )
+                .arg(
+                    Arg::with_name("batch-size")
+                        .long("batch-size")
+                        .takes_value(true)
+                        .default_value("5000")
+                        .help("The number of lines to write in each request"),
+                )
+                .arg(
+                    Arg::with_name("max-lines-per-second")
+                        .long("max-lines-per-second")
+                        .takes_value(true)
+                        .help("Limits the rate lines are written at, to reduce the load on the server"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("export")
+                .about("Exports tables of a database on a running server to Parquet or line protocol files")
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .help("The directory to write a Parquet file per table to, or the line protocol file to write")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("database")
+                        .short("d")
+                        .long("database")
+                        .takes_value(true)
+                        .required(true)
+                        .help("The database to export"),
+                )
+                .arg(
+                    Arg::with_name("table")
+                        .long("table")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("A table to export. Defaults to every table of the database"),
+                )
+                .arg(
+                    Arg::with_name("start")
+                        .long("start")
+                        .takes_value(true)
+                        .help("Only export rows at or after this time, in nanoseconds since the epoch or RFC 3339 format"),
+                )
+                .arg(
+                    Arg::with_name("end")
+                        .long("end")
+                        .takes_value(true)
+                        .help("Only export rows before this time, in nanoseconds since the epoch or RFC 3339 format"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["parquet", "lp"])
+                        .default_value("parquet")
+                        .help("The format to export to"),
+                )
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .takes_value(true)
+                        .default_value("http://127.0.0.1:8082")
+                        .help("The URL of the gRPC API of the server"),
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .long("token")
+                        .takes_value(true)
+                        .help("The API token to authorize queries with"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("generate")
+                .about("Generates the line protocol described by a workload specification, \
+                        writing it to a running server or a file")
+                .arg(
+                    Arg::with_name("SPEC")
+                        .help("The TOML file specifying the measurements, tags, field values \
+                               and times to generate")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .long("seed")
+                        .takes_value(true)
+                        .help("Replaces the seed of the specification"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .takes_value(true)
+                        .conflicts_with_all(&["org", "bucket"])
+                        .required_unless_all(&["org", "bucket"])
+                        .help("The line protocol file to write to"),
+                )
+                .arg(
+                    Arg::with_name("org")
+                        .long("org")
+                        .takes_value(true)
+                        .requires("bucket")
+                        .help("The organization to write to"),
+                )
+                .arg(
+                    Arg::with_name("bucket")
+                        .long("bucket")
+                        .takes_value(true)
+                        .requires("org")
+                        .help("The bucket to write to"),
+                )
+                .arg(
+                    Arg::with_name("host")
+                        .long("host")
+                        .takes_value(true)
+                        .default_value("http://127.0.0.1:8080")
+                        .help("The URL of the HTTP API of the server"),
+                )
+                .arg(
+                    Arg::with_name("token")
+                        .long("token")
+                        .takes_value(true)
+                        .help("The API token to authorize writes with"),
+                )
+                .arg(
+                    Arg::with_name("batch-size")
+                        .long("batch-size")
+                        .takes_value(true)
+                        .help("The number of lines to upload in each request [default: 5000]"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("Inspect the configuration of the server")
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Print the effective configuration, with secrets redacted")
+                ),
+        )
+        .arg(Arg::with_name("config").long("config").takes_value(true).value_name("FILE").help(
+            "Read the configuration from this TOML or YAML file. Environment variables and --set \
+                       flags take precedence over its settings. Defaults to the file in \
+                       INFLUXDB_IOX_CONFIG, if set",
+        ))
+        .arg(Arg::with_name("set").long("set").takes_value(true).multiple(true).number_of_values(1).value_name("NAME=VALUE").help(
+            "Set a configuration setting, such as bind_addr=0.0.0.0:8080, taking precedence over \
+                       the configuration file and environment variables",
+        ))
         .arg(Arg::with_name("verbose").short("v").long("verbose").multiple(true).help(
             "Enables verbose logging (use 'vv' for even more verbosity). You can also set log level via \
                        the environment variable RUST_LOG=<value>",
@@ -129,20 +477,39 @@ Examples:
         ))
         .get_matches();
 
-    setup_logging(matches.occurrences_of("verbose"));
+    // the .env file is read before the configuration, so it can set
+    // any of its environment variables
+    dotenv::dotenv().ok();
+
+    let set_args: Vec<&str> = matches
+        .values_of("set")
+        .map(|values| values.collect())
+        .unwrap_or_default();
+    let config = match Config::load(matches.value_of("config").map(Path::new), &set_args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(ReturnCode::InvalidConfig as _)
+        }
+    };
+
+    // the exporter of traces, if any, is shut down (flushing any spans
+    // not yet sent) when this is dropped at the end of main
+    let (log_filter, _uninstall_tracing) =
+        setup_logging(matches.occurrences_of("verbose"), &config);
 
     // Install custom panic handler (note can not use `_` otherwise
     // drop will be called immediately).
     let _f = SendPanicsToTracing::new();
 
     let mut tokio_runtime = get_runtime(matches.value_of("num-threads"))?;
-    tokio_runtime.block_on(dispatch_args(matches));
+    tokio_runtime.block_on(dispatch_args(matches, config, log_filter));
 
     info!("InfluxDB IOx server shutting down");
     Ok(())
 }
 
-async fn dispatch_args(matches: ArgMatches<'_>) {
+async fn dispatch_args(matches: ArgMatches<'_>, config: Config, log_filter: Arc<LogFilter>) {
     match matches.subcommand() {
         ("convert", Some(sub_matches)) => {
             let input_path = sub_matches.value_of("INPUT").unwrap();
@@ -167,6 +534,16 @@ async fn dispatch_args(matches: ArgMatches<'_>) {
                 }
             }
         }
+        ("inspect", Some(sub_matches)) => {
+            let input_path = sub_matches.value_of("INPUT").unwrap();
+            match commands::inspect::inspect(Path::new(input_path)) {
+                Ok(()) => debug!("Inspection completed successfully"),
+                Err(e) => {
+                    eprintln!("Inspect failed: {}", e);
+                    std::process::exit(ReturnCode::InspectFailed as _)
+                }
+            }
+        }
         ("stats", Some(sub_matches)) => {
             let config = commands::stats::StatsConfig {
                 input_path: sub_matches.value_of("INPUT").unwrap().into(),
@@ -182,9 +559,171 @@ async fn dispatch_args(matches: ArgMatches<'_>) {
                 }
             }
         }
+        ("sql", Some(sub_matches)) => {
+            let config = commands::sql::SqlConfig {
+                host: sub_matches.value_of("host").unwrap().into(),
+                database: sub_matches.value_of("database").map(Into::into),
+                token: sub_matches.value_of("token").map(Into::into),
+            };
+
+            if let Err(e) = commands::sql::repl(config).await {
+                eprintln!("SQL shell failed: {}", e);
+                std::process::exit(ReturnCode::SqlFailed as _)
+            }
+        }
+        ("write", Some(sub_matches)) => {
+            let path = std::path::PathBuf::from(sub_matches.value_of("file").unwrap());
+            let format = match sub_matches.value_of("format") {
+                Some("csv") => commands::write::Format::Csv,
+                Some(_) => commands::write::Format::LineProtocol,
+                None => commands::write::Format::for_path(&path),
+            };
+            let batch_size = match sub_matches.value_of("batch-size").map(str::parse) {
+                Some(Ok(batch_size)) => batch_size,
+                Some(Err(e)) => {
+                    eprintln!("Invalid batch-size: {}", e);
+                    std::process::exit(ReturnCode::WriteFailed as _)
+                }
+                None => commands::write::DEFAULT_BATCH_SIZE,
+            };
+            let config = commands::write::WriteConfig {
+                host: sub_matches.value_of("host").unwrap().into(),
+                token: sub_matches.value_of("token").map(Into::into),
+                org: sub_matches.value_of("org").unwrap().into(),
+                bucket: sub_matches.value_of("bucket").unwrap().into(),
+                path,
+                format,
+                columns: sub_matches.value_of("columns").map(Into::into),
+                measurement: sub_matches.value_of("measurement").map(Into::into),
+                batch_size,
+                resume: sub_matches.is_present("resume"),
+            };
+
+            match commands::write::write(&config).await {
+                Ok(()) => debug!("Import completed successfully"),
+                Err(e) => {
+                    eprintln!("Import failed: {}", e);
+                    std::process::exit(ReturnCode::WriteFailed as _)
+                }
+            }
+        }
+        ("import", Some(sub_matches)) => {
+            let config = commands::import::ImportConfig {
+                host: sub_matches.value_of("host").unwrap().into(),
+                token: sub_matches.value_of("token").map(Into::into),
+                paths: sub_matches
+                    .values_of("INPUT")
+                    .unwrap()
+                    .map(Into::into)
+                    .collect(),
+                mappings: sub_matches
+                    .values_of("map")
+                    .map(|values| values.map(Into::into).collect())
+                    .unwrap_or_default(),
+                default_destination: sub_matches
+                    .value_of("org")
+                    .zip(sub_matches.value_of("bucket"))
+                    .map(|(org, bucket)| (org.into(), bucket.into())),
+                batch_size: value_t!(sub_matches, "batch-size", usize).unwrap_or_else(|e| e.exit()),
+                max_lines_per_second: if sub_matches.is_present("max-lines-per-second") {
+                    Some(
+                        value_t!(sub_matches, "max-lines-per-second", u64)
+                            .unwrap_or_else(|e| e.exit()),
+                    )
+                } else {
+                    None
+                },
+            };
+
+            match commands::import::import(&config).await {
+                Ok(()) => debug!("Import completed successfully"),
+                Err(e) => {
+                    eprintln!("Import failed: {}", e);
+                    std::process::exit(ReturnCode::ImportFailed as _)
+                }
+            }
+        }
+        ("export", Some(sub_matches)) => {
+            let time = |name: &str| {
+                sub_matches.value_of(name).map(|time| {
+                    commands::export::parse_time(time).unwrap_or_else(|| {
+                        eprintln!("Invalid {} time '{}'", name, time);
+                        std::process::exit(ReturnCode::ExportFailed as _)
+                    })
+                })
+            };
+            let config = commands::export::ExportConfig {
+                host: sub_matches.value_of("host").unwrap().into(),
+                token: sub_matches.value_of("token").map(Into::into),
+                database: sub_matches.value_of("database").unwrap().into(),
+                tables: sub_matches
+                    .values_of("table")
+                    .map(|values| values.map(Into::into).collect())
+                    .unwrap_or_default(),
+                start: time("start"),
+                end: time("end"),
+                format: match sub_matches.value_of("format") {
+                    Some("lp") => commands::export::Format::LineProtocol,
+                    _ => commands::export::Format::Parquet,
+                },
+                output: sub_matches.value_of("OUTPUT").unwrap().into(),
+            };
+
+            match commands::export::export(&config).await {
+                Ok(()) => debug!("Export completed successfully"),
+                Err(e) => {
+                    eprintln!("Export failed: {}", e);
+                    std::process::exit(ReturnCode::ExportFailed as _)
+                }
+            }
+        }
+        ("generate", Some(sub_matches)) => {
+            let seed = match sub_matches.value_of("seed").map(str::parse) {
+                Some(Ok(seed)) => Some(seed),
+                Some(Err(e)) => {
+                    eprintln!("Invalid seed: {}", e);
+                    std::process::exit(ReturnCode::GenerateFailed as _)
+                }
+                None => None,
+            };
+            let destination = match sub_matches.value_of("output") {
+                Some(output) => commands::generate::Destination::File(output.into()),
+                None => {
+                    let batch_size = match sub_matches.value_of("batch-size").map(str::parse) {
+                        Some(Ok(batch_size)) => batch_size,
+                        Some(Err(e)) => {
+                            eprintln!("Invalid batch-size: {}", e);
+                            std::process::exit(ReturnCode::GenerateFailed as _)
+                        }
+                        None => commands::write::DEFAULT_BATCH_SIZE,
+                    };
+                    commands::generate::Destination::Server {
+                        host: sub_matches.value_of("host").unwrap().into(),
+                        token: sub_matches.value_of("token").map(Into::into),
+                        org: sub_matches.value_of("org").unwrap().into(),
+                        bucket: sub_matches.value_of("bucket").unwrap().into(),
+                        batch_size,
+                    }
+                }
+            };
+            let config = commands::generate::GenerateConfig {
+                spec_path: sub_matches.value_of("SPEC").unwrap().into(),
+                seed,
+                destination,
+            };
+
+            match commands::generate::generate(&config).await {
+                Ok(()) => debug!("Generation completed successfully"),
+                Err(e) => {
+                    eprintln!("Generate failed: {}", e);
+                    std::process::exit(ReturnCode::GenerateFailed as _)
+                }
+            }
+        }
+        ("config", Some(_)) => commands::config::show(&config),
         ("server", Some(_)) | (_, _) => {
             println!("InfluxDB IOx server starting");
-            match commands::server::main().await {
+            match commands::server::main(config, log_filter).await {
                 Ok(()) => eprintln!("Shutdown OK"),
                 Err(e) => {
                     error!("Server shutdown with error: {}", e);
@@ -207,30 +746,67 @@ const DEFAULT_LOG_LEVEL: &str = "warn";
 
 /// Configures logging in the following precedence:
 ///
-/// 1. If RUST_LOG environment variable is set, use that value
+/// 1. If the `log_filter` setting (the RUST_LOG environment variable)
+///    is set, use that value
 /// 2. if `-vv` (multiple instances of verbose), use DEFAULT_DEBUG_LOG_LEVEL
 /// 2. if `-v` (single instances of verbose), use DEFAULT_VERBOSE_LOG_LEVEL
 /// 3. Otherwise use DEFAULT_LOG_LEVEL
-fn setup_logging(num_verbose: u64) {
-    let rust_log_env = std::env::var("RUST_LOG");
-
-    match rust_log_env {
-        Ok(lvl) => {
+///
+/// The level can be changed while the server runs through the
+/// returned `LogFilter`. Spans are also exported to the tracing
+/// backend configured by the environment (see `server::telemetry`),
+/// if any.
+fn setup_logging(
+    num_verbose: u64,
+    config: &Config,
+) -> (Arc<LogFilter>, Option<server::telemetry::Uninstall>) {
+    let directives = match config.get("log_filter") {
+        Some(lvl) => {
             if num_verbose > 0 {
                 eprintln!(
-                    "WARNING: Using RUST_LOG='{}' environment, ignoring -v command line",
-                    lvl
+                    "WARNING: Using log_filter='{}' from the {}, ignoring -v command line",
+                    lvl,
+                    config.source("log_filter").expect("log_filter is set")
                 );
             }
+            lvl.to_string()
         }
-        Err(_) => match num_verbose {
-            0 => std::env::set_var("RUST_LOG", DEFAULT_LOG_LEVEL),
-            1 => std::env::set_var("RUST_LOG", DEFAULT_VERBOSE_LOG_LEVEL),
-            _ => std::env::set_var("RUST_LOG", DEFAULT_DEBUG_LOG_LEVEL),
+        None => match num_verbose {
+            0 => DEFAULT_LOG_LEVEL.to_string(),
+            1 => DEFAULT_VERBOSE_LOG_LEVEL.to_string(),
+            _ => DEFAULT_DEBUG_LOG_LEVEL.to_string(),
         },
-    }
+    };
+
+    let (log_filter, filter_layer) = match LogFilter::new(&directives) {
+        Ok(filter) => filter,
+        Err(e) => {
+            eprintln!("WARNING: {}, using '{}'", e, DEFAULT_LOG_LEVEL);
+            LogFilter::new(DEFAULT_LOG_LEVEL).expect("default log level is valid")
+        }
+    };
+
+    let tracing_config = server::telemetry::TracingConfig::from_config(config);
+    let (traces_layer, uninstall) =
+        match tracing_config.and_then(|config| server::telemetry::install(&config)) {
+            Ok(Some((tracer, uninstall))) => (
+                Some(tracing_opentelemetry::layer().with_tracer(tracer)),
+                Some(uninstall),
+            ),
+            Ok(None) => (None, None),
+            Err(e) => {
+                eprintln!("WARNING: Not exporting traces: {}", e);
+                (None, None)
+            }
+        };
+
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(tracing_subscriber::fmt::layer())
+        .with(traces_layer)
+        .init();
 
-    env_logger::init();
+    (Arc::new(log_filter), uninstall)
 }
 
 /// Creates the tokio runtime for executing IOx
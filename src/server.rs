@@ -1,2 +1,25 @@
+pub mod access;
+pub mod alerts;
+pub mod auth;
+pub mod build_info;
+pub mod compaction;
+pub mod config;
+pub mod cross_database;
 pub mod http_routes;
+pub mod jobs;
+pub mod log_filter;
+pub mod metrics;
+pub mod provision;
+pub mod query_labels;
+pub mod query_params;
+pub mod quota;
+pub mod replication;
+pub mod router;
 pub mod rpc;
+pub mod sharding;
+pub mod state;
+pub mod subscriptions;
+pub mod system_tables;
+pub mod telemetry;
+pub mod tls;
+pub mod warnings;
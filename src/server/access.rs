@@ -0,0 +1,254 @@
+//! This module contains the access modes of a server, which stop it
+//! accepting writes or queries without stopping the server, so that it
+//! can keep serving the other.
+//!
+//! A standby replica runs read-only, answering queries while the
+//! primary takes the writes. A server being backfilled or rebuilt runs
+//! ingest-only, so that queries are not answered from incomplete data,
+//! and one under maintenance accepts neither. Writes replicated from
+//! peers are accepted in every mode, since they are how a standby
+//! keeps up with the primary.
+//!
+//! The mode is configured at startup, and changed while the server
+//! runs through the HTTP management API. Those watching the mode, such
+//! as the gRPC health service, are notified of each change. A router stores no data to
+//! answer queries from, so it rejects them whatever its mode.
+
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, Snafu};
+use tokio::sync::watch;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Invalid access mode '{}': expected read_write, read_only, ingest_only or maintenance",
+        mode
+    ))]
+    InvalidMode { mode: String },
+
+    #[snafu(display("Server is {}: writes are not accepted", mode))]
+    WritesRejected { mode: AccessMode },
+
+    #[snafu(display("Server is {}: queries are not accepted", mode))]
+    QueriesRejected { mode: AccessMode },
+
+    #[snafu(display("Server stores no data: queries are not accepted"))]
+    NoData {},
+}
+
+impl Error {
+    /// Converts the error into the appropriate tonic status
+    pub fn to_status(&self) -> tonic::Status {
+        match &self {
+            Self::InvalidMode { .. } => tonic::Status::invalid_argument(self.to_string()),
+            Self::WritesRejected { .. } | Self::QueriesRejected { .. } => {
+                tonic::Status::unavailable(self.to_string())
+            }
+            Self::NoData { .. } => tonic::Status::failed_precondition(self.to_string()),
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Which requests a server accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessMode {
+    /// Accepts writes and queries
+    ReadWrite,
+    /// Accepts queries, but rejects writes
+    ReadOnly,
+    /// Accepts writes, but rejects queries
+    IngestOnly,
+    /// Rejects writes and queries
+    Maintenance,
+}
+
+impl AccessMode {
+    pub fn accepts_writes(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::IngestOnly)
+    }
+
+    pub fn accepts_queries(self) -> bool {
+        matches!(self, Self::ReadWrite | Self::ReadOnly)
+    }
+}
+
+impl Default for AccessMode {
+    fn default() -> Self {
+        Self::ReadWrite
+    }
+}
+
+impl FromStr for AccessMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "read_write" => Ok(Self::ReadWrite),
+            "read_only" => Ok(Self::ReadOnly),
+            "ingest_only" => Ok(Self::IngestOnly),
+            "maintenance" => Ok(Self::Maintenance),
+            _ => InvalidMode { mode: s }.fail(),
+        }
+    }
+}
+
+impl fmt::Display for AccessMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadWrite => write!(f, "read_write"),
+            Self::ReadOnly => write!(f, "read_only"),
+            Self::IngestOnly => write!(f, "ingest_only"),
+            Self::Maintenance => write!(f, "maintenance"),
+        }
+    }
+}
+
+/// Controls the access mode of a server
+#[derive(Debug)]
+pub struct Access {
+    /// The mode the server started in
+    initial_mode: AccessMode,
+    /// Sends the mode currently in effect to those watching it
+    mode_tx: watch::Sender<AccessMode>,
+    /// The mode currently in effect
+    mode: watch::Receiver<AccessMode>,
+    /// Whether the server stores no data, and so rejects queries in
+    /// every mode
+    no_data: bool,
+}
+
+impl Default for Access {
+    fn default() -> Self {
+        Self::new(AccessMode::default())
+    }
+}
+
+impl Access {
+    pub fn new(mode: AccessMode) -> Self {
+        let (mode_tx, mode_rx) = watch::channel(mode);
+        Self {
+            initial_mode: mode,
+            mode_tx,
+            mode: mode_rx,
+            no_data: false,
+        }
+    }
+
+    /// Rejects queries in every mode, as a server which stores no data,
+    /// such as a router, has nothing to answer them from
+    pub fn without_data(self) -> Self {
+        Self {
+            no_data: true,
+            ..self
+        }
+    }
+
+    /// The mode currently in effect
+    pub fn mode(&self) -> AccessMode {
+        *self.mode.borrow()
+    }
+
+    /// Returns a receiver of the mode currently in effect, which is
+    /// notified each time it changes
+    pub fn watch(&self) -> watch::Receiver<AccessMode> {
+        self.mode.clone()
+    }
+
+    /// The mode the server started in
+    pub fn initial_mode(&self) -> AccessMode {
+        self.initial_mode
+    }
+
+    /// Changes the mode to `mode`, which applies to every request
+    /// received after it returns
+    pub fn set(&self, mode: AccessMode) {
+        self.mode_tx
+            .broadcast(mode)
+            .expect("access holds a receiver of the mode");
+    }
+
+    /// Restores the mode the server started in
+    pub fn reset(&self) {
+        self.set(self.initial_mode)
+    }
+
+    /// Returns an error if the server does not accept writes
+    pub fn check_write(&self) -> Result<()> {
+        let mode = self.mode();
+        ensure!(mode.accepts_writes(), WritesRejected { mode });
+        Ok(())
+    }
+
+    /// Returns an error if the server does not accept queries
+    pub fn check_query(&self) -> Result<()> {
+        ensure!(!self.no_data, NoData);
+        let mode = self.mode();
+        ensure!(mode.accepts_queries(), QueriesRejected { mode });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_display_modes() {
+        for mode in &["read_write", "read_only", "ingest_only", "maintenance"] {
+            assert_eq!(mode.parse::<AccessMode>().unwrap().to_string(), *mode);
+        }
+
+        assert_eq!(
+            "readonly".parse::<AccessMode>().unwrap_err().to_string(),
+            "Invalid access mode 'readonly': expected read_write, read_only, \
+             ingest_only or maintenance"
+        );
+    }
+
+    #[test]
+    fn modes_reject_requests() {
+        let access = Access::default();
+        assert!(access.check_write().is_ok());
+        assert!(access.check_query().is_ok());
+
+        access.set(AccessMode::ReadOnly);
+        assert_eq!(
+            access.check_write().unwrap_err().to_string(),
+            "Server is read_only: writes are not accepted"
+        );
+        assert!(access.check_query().is_ok());
+
+        access.set(AccessMode::IngestOnly);
+        assert!(access.check_write().is_ok());
+        assert_eq!(
+            access.check_query().unwrap_err().to_string(),
+            "Server is ingest_only: queries are not accepted"
+        );
+
+        access.set(AccessMode::Maintenance);
+        assert!(access.check_write().is_err());
+        assert!(access.check_query().is_err());
+
+        access.reset();
+        assert_eq!(access.mode(), AccessMode::ReadWrite);
+        assert!(access.check_write().is_ok());
+    }
+
+    #[test]
+    fn servers_without_data_reject_queries() {
+        let access = Access::default().without_data();
+        assert!(access.check_write().is_ok());
+        assert_eq!(
+            access.check_query().unwrap_err().to_string(),
+            "Server stores no data: queries are not accepted"
+        );
+
+        access.set(AccessMode::ReadOnly);
+        assert!(matches!(access.check_query(), Err(Error::NoData {})));
+    }
+}
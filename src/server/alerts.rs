@@ -0,0 +1,323 @@
+//! This module contains the soft limits on the resources of each
+//! database, which give operators early warning that a database is
+//! growing towards its hard limits (such as its quota or
+//! `mutable_buffer_size`) without rejecting anything:
+//!
+//! * the bytes of data in its mutable buffer
+//! * the number of distinct values of its tag with the most values
+//! * the bytes of data in its WAL, all of which is read to restore the
+//!   database when the server restarts
+//!
+//! The resources of each database are checked periodically. When one
+//! crosses its limit an alert fires, and when it falls back within the
+//! limit the alert is resolved. Each of these is logged and recorded
+//! as an event, and the `MAX_EVENTS` most recent events are kept in
+//! memory for the management API.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    fmt,
+    sync::{Mutex, RwLock},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+/// The number of events which are kept
+pub const MAX_EVENTS: usize = 1000;
+
+/// A resource of a database with a soft limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Resource {
+    MemoryBytes,
+    TagCardinality,
+    WalBytes,
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MemoryBytes => write!(f, "memory_bytes"),
+            Self::TagCardinality => write!(f, "tag_cardinality"),
+            Self::WalBytes => write!(f, "wal_bytes"),
+        }
+    }
+}
+
+const RESOURCES: [Resource; 3] = [
+    Resource::MemoryBytes,
+    Resource::TagCardinality,
+    Resource::WalBytes,
+];
+
+/// The soft limits on the resources of each database. Resources
+/// without a limit are never alerted on.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SoftLimits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memory_bytes: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_cardinality: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub wal_bytes: Option<u64>,
+}
+
+impl SoftLimits {
+    fn limit(&self, resource: Resource) -> Option<u64> {
+        match resource {
+            Resource::MemoryBytes => self.memory_bytes,
+            Resource::TagCardinality => self.tag_cardinality,
+            Resource::WalBytes => self.wal_bytes,
+        }
+    }
+}
+
+/// The resources a database uses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ResourceUsage {
+    pub memory_bytes: u64,
+    pub tag_cardinality: u64,
+    pub wal_bytes: u64,
+}
+
+impl ResourceUsage {
+    fn value(&self, resource: Resource) -> u64 {
+        match resource {
+            Resource::MemoryBytes => self.memory_bytes,
+            Resource::TagCardinality => self.tag_cardinality,
+            Resource::WalBytes => self.wal_bytes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlertState {
+    /// The resource crossed its limit
+    Firing,
+    /// The resource fell back within its limit
+    Resolved,
+}
+
+/// A change in the state of the alert on a resource of a database
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AlertEvent {
+    pub id: u64,
+    /// When the event happened, in nanoseconds since the epoch
+    pub time: i64,
+    pub db_name: String,
+    pub resource: Resource,
+    pub state: AlertState,
+    /// The usage of the resource when it was checked
+    pub value: u64,
+    /// The limit crossed, which is `None` for an alert resolved by its
+    /// limit being removed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct AlertsState {
+    next_id: u64,
+    events: VecDeque<AlertEvent>,
+    /// The resources of each database whose alerts are firing
+    firing: BTreeSet<(String, Resource)>,
+}
+
+/// The soft limits of the server and the alerts on them
+#[derive(Debug, Default)]
+pub struct Alerts {
+    limits: RwLock<SoftLimits>,
+    state: Mutex<AlertsState>,
+}
+
+impl Alerts {
+    pub fn new(limits: SoftLimits) -> Self {
+        Self {
+            limits: RwLock::new(limits),
+            state: Default::default(),
+        }
+    }
+
+    /// The soft limits in effect
+    pub fn limits(&self) -> SoftLimits {
+        *self.limits.read().expect("alerts lock poisoned")
+    }
+
+    /// Replaces the soft limits, which apply from the next check
+    pub fn set_limits(&self, limits: SoftLimits) {
+        *self.limits.write().expect("alerts lock poisoned") = limits;
+    }
+
+    /// Checks the usage of each database against the soft limits,
+    /// recording and logging an event for each alert which fires or is
+    /// resolved. Alerts of databases missing from `usage`, which have
+    /// been deleted, are forgotten. Returns the new events.
+    pub fn check(&self, usage: &BTreeMap<String, ResourceUsage>, now: i64) -> Vec<AlertEvent> {
+        let limits = self.limits();
+        let mut state = self.state.lock().expect("alerts lock poisoned");
+        state
+            .firing
+            .retain(|(db_name, _)| usage.contains_key(db_name));
+
+        let mut events = vec![];
+        for (db_name, usage) in usage {
+            for &resource in &RESOURCES {
+                let value = usage.value(resource);
+                let key = (db_name.clone(), resource);
+                let was_firing = state.firing.contains(&key);
+
+                let limit = limits.limit(resource);
+                let event_state = match limit {
+                    Some(limit) if value > limit && !was_firing => {
+                        warn!(
+                            "Database {} is over its soft limit of {} {}: {}",
+                            db_name, limit, resource, value
+                        );
+                        state.firing.insert(key);
+                        AlertState::Firing
+                    }
+                    Some(limit) if value <= limit && was_firing => {
+                        info!(
+                            "Database {} is back within its soft limit of {} {}: {}",
+                            db_name, limit, resource, value
+                        );
+                        state.firing.remove(&key);
+                        AlertState::Resolved
+                    }
+                    None if was_firing => {
+                        info!(
+                            "Soft limit of {} removed while database {} was over it: {}",
+                            resource, db_name, value
+                        );
+                        state.firing.remove(&key);
+                        AlertState::Resolved
+                    }
+                    _ => continue,
+                };
+
+                let event = AlertEvent {
+                    id: state.next_id,
+                    time: now,
+                    db_name: db_name.clone(),
+                    resource,
+                    state: event_state,
+                    value,
+                    limit,
+                };
+                state.next_id += 1;
+                state.events.push_back(event.clone());
+                events.push(event);
+            }
+        }
+
+        while state.events.len() > MAX_EVENTS {
+            state.events.pop_front();
+        }
+        events
+    }
+
+    /// Returns the events kept, oldest first, after the event with id
+    /// `after` if given
+    pub fn events(&self, after: Option<u64>) -> Vec<AlertEvent> {
+        let state = self.state.lock().expect("alerts lock poisoned");
+        state
+            .events
+            .iter()
+            .filter(|event| after.map_or(true, |after| event.id > after))
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the database and resource of each alert which is firing
+    pub fn firing(&self) -> Vec<(String, Resource)> {
+        let state = self.state.lock().expect("alerts lock poisoned");
+        state.firing.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usage(db_name: &str, memory_bytes: u64) -> BTreeMap<String, ResourceUsage> {
+        let mut usage = BTreeMap::new();
+        usage.insert(
+            db_name.to_string(),
+            ResourceUsage {
+                memory_bytes,
+                tag_cardinality: 10,
+                wal_bytes: 0,
+            },
+        );
+        usage
+    }
+
+    #[test]
+    fn alerts_fire_and_resolve() {
+        let alerts = Alerts::new(SoftLimits {
+            memory_bytes: Some(1000),
+            tag_cardinality: Some(100),
+            ..Default::default()
+        });
+
+        assert!(alerts.check(&usage("mydb", 500), 1).is_empty());
+
+        let events = alerts.check(&usage("mydb", 1500), 2);
+        assert_eq!(
+            events,
+            vec![AlertEvent {
+                id: 0,
+                time: 2,
+                db_name: "mydb".to_string(),
+                resource: Resource::MemoryBytes,
+                state: AlertState::Firing,
+                value: 1500,
+                limit: Some(1000),
+            }]
+        );
+        assert_eq!(
+            alerts.firing(),
+            vec![("mydb".to_string(), Resource::MemoryBytes)]
+        );
+
+        // an alert fires once, however long the resource is over
+        assert!(alerts.check(&usage("mydb", 2000), 3).is_empty());
+
+        let events = alerts.check(&usage("mydb", 1000), 4);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].id, 1);
+        assert_eq!(events[0].state, AlertState::Resolved);
+        assert!(alerts.firing().is_empty());
+
+        assert_eq!(alerts.events(None).len(), 2);
+        assert_eq!(alerts.events(Some(0)), events);
+        assert!(alerts.events(Some(1)).is_empty());
+    }
+
+    #[test]
+    fn changed_limits_and_deleted_databases() {
+        let alerts = Alerts::new(SoftLimits::default());
+        assert!(alerts.check(&usage("mydb", 1500), 1).is_empty());
+
+        alerts.set_limits(SoftLimits {
+            memory_bytes: Some(1000),
+            ..Default::default()
+        });
+        assert_eq!(alerts.check(&usage("mydb", 1500), 2).len(), 1);
+
+        alerts.set_limits(SoftLimits::default());
+        let events = alerts.check(&usage("mydb", 1500), 3);
+        assert_eq!(events[0].state, AlertState::Resolved);
+        assert_eq!(events[0].limit, None);
+
+        alerts.set_limits(SoftLimits {
+            memory_bytes: Some(1000),
+            ..Default::default()
+        });
+        assert_eq!(alerts.check(&usage("mydb", 1500), 4).len(), 1);
+        assert!(alerts.check(&BTreeMap::new(), 5).is_empty());
+        assert!(alerts.firing().is_empty());
+    }
+}
@@ -0,0 +1,844 @@
+//! This module contains the authentication and authorization of
+//! requests to the HTTP and gRPC APIs.
+//!
+//! Requests present an API token in the `Authorization` header (or
+//! gRPC metadata), as `Token <token>` or `Bearer <token>`. Each token
+//! has a list of scopes granting read or write access to a database,
+//! or to all databases with `*`.
+//!
+//! Authentication is enabled by configuring an admin token, which
+//! may access every database and manage the other tokens. When no
+//! admin token is configured every request is allowed, as before
+//! authentication existed.
+//!
+//! A scope can also be limited to some of the rows of its database
+//! with a filter, to the measurements it names and the rows with the
+//! tag values it names (e.g. `tenant_id='acme'`). The filter is
+//! AND-ed into every storage gRPC query made with the token, and
+//! writes of lines outside the filter are rejected. Requests which can
+//! not be limited to the rows of a filter, such as SQL queries, are
+//! rejected for tokens with a filter on their database.
+//!
+//! Requests are authenticated by a layer wrapping each API, before
+//! they are routed or their messages decoded: `GrpcAuthentication`
+//! for the gRPC services, and `HttpAuthentication` in `http_routes`
+//! for the HTTP API, which also rejects requests to the routes
+//! reserved for the admin. The permissions a request needs on a
+//! database depend on the database it names, so are checked once the
+//! request is decoded.
+//!
+//! Tokens other than the admin token are persisted to a file, if one
+//! is configured, so they are kept when the server restarts. Only the
+//! SHA-256 digests of their secret values are kept, by which tokens
+//! are looked up, and the admin token is compared in constant time, so
+//! the time taken to authenticate a request does not tell how close
+//! its token is to a valid one.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    task::{Context, Poll},
+};
+
+use arrow_deps::datafusion::logical_plan::{col, lit};
+use futures::future::{self, Either, Ready};
+use hyper::Body;
+use influxdb_line_protocol::ParsedLine;
+use rand::Rng;
+use ring::{constant_time, digest};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use storage::predicate::Predicate;
+use tonic::{body::BoxBody, codegen::Service, transport::NamedService, Code};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Missing or unknown API token"))]
+    Unauthenticated,
+
+    #[snafu(display("Token is not authorized to {} database '{}'", permission, db_name))]
+    NotAuthorized {
+        db_name: String,
+        permission: Permission,
+    },
+
+    #[snafu(display(
+        "Token may only access some rows of database '{}', which this request can not be limited to",
+        db_name
+    ))]
+    RowsRestricted { db_name: String },
+
+    #[snafu(display(
+        "Token is not authorized to write line '{}' to database '{}'",
+        series,
+        db_name
+    ))]
+    LineOutOfScope { db_name: String, series: String },
+
+    #[snafu(display(
+        "Scopes of database '{}' overlap, which is not allowed when one has a filter",
+        database
+    ))]
+    ConflictingScopes { database: String },
+
+    #[snafu(display("Only the admin token may manage tokens"))]
+    AdminRequired,
+
+    #[snafu(display("Token '{}' not found", id))]
+    TokenNotFound { id: String },
+
+    #[snafu(display("Error reading tokens from {:?}: {}", path, source))]
+    ReadingTokens {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid tokens file {:?}: {}", path, source))]
+    InvalidTokensFile {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Error writing tokens to {:?}: {}", path, source))]
+    WritingTokens {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Converts the error into the appropriate tonic status
+    pub fn to_status(&self) -> tonic::Status {
+        match &self {
+            Self::Unauthenticated => tonic::Status::unauthenticated(self.to_string()),
+            Self::NotAuthorized { .. }
+            | Self::RowsRestricted { .. }
+            | Self::LineOutOfScope { .. } => tonic::Status::permission_denied(self.to_string()),
+            Self::ConflictingScopes { .. } => tonic::Status::invalid_argument(self.to_string()),
+            Self::AdminRequired => tonic::Status::permission_denied(self.to_string()),
+            Self::TokenNotFound { .. } => tonic::Status::not_found(self.to_string()),
+            Self::ReadingTokens { .. }
+            | Self::InvalidTokensFile { .. }
+            | Self::WritingTokens { .. } => tonic::Status::internal(self.to_string()),
+        }
+    }
+}
+
+/// The name of the header (and gRPC metadata key) carrying the token
+pub const AUTHORIZATION: &str = "authorization";
+
+/// Scopes for this database name apply to all databases
+const ALL_DATABASES: &str = "*";
+
+/// An operation on the data of a database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    Read,
+    Write,
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Write => write!(f, "write"),
+        }
+    }
+}
+
+/// Grants `permission` on the database `database`, or on all
+/// databases if `database` is `*`. If the scope has a filter, only the
+/// rows it matches may be read or written.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Scope {
+    pub database: String,
+    pub permission: Permission,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter: Option<RowFilter>,
+}
+
+impl Scope {
+    /// Returns true if the scope is on the database `db_name`, either
+    /// by name or as a scope on all databases
+    pub fn covers(&self, db_name: &str) -> bool {
+        self.database == ALL_DATABASES || self.database == db_name
+    }
+
+    fn allows(&self, db_name: &str, permission: Permission) -> bool {
+        self.permission == permission && self.covers(db_name)
+    }
+
+    /// Returns true if both scopes grant the same permission on some
+    /// database
+    fn overlaps(&self, other: &Self) -> bool {
+        self.permission == other.permission
+            && (self.database == ALL_DATABASES
+                || other.database == ALL_DATABASES
+                || self.database == other.database)
+    }
+}
+
+/// The rows of a database a scope is limited to: those of the
+/// measurements in `measurements`, if given, with every tag value in
+/// `tags`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize, Serialize)]
+pub struct RowFilter {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub measurements: Option<BTreeSet<String>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl RowFilter {
+    /// Returns true if the line is within the filter
+    pub fn allows_line(&self, line: &ParsedLine<'_>) -> bool {
+        let measurement = line.series.measurement.as_str();
+        if let Some(measurements) = &self.measurements {
+            if !measurements.contains(measurement) {
+                return false;
+            }
+        }
+
+        self.tags.iter().all(|(key, value)| {
+            line.series.tag_set.as_ref().map_or(false, |tag_set| {
+                tag_set
+                    .iter()
+                    .any(|(k, v)| k.as_str() == key && v.as_str() == value)
+            })
+        })
+    }
+
+    /// Returns an error for the first of `lines` outside the filter
+    pub fn check_lines(&self, db_name: &str, lines: &[ParsedLine<'_>]) -> Result<()> {
+        match lines.iter().find(|line| !self.allows_line(line)) {
+            Some(line) => LineOutOfScope {
+                db_name,
+                series: line.series.to_string(),
+            }
+            .fail(),
+            None => Ok(()),
+        }
+    }
+
+    /// Limits `predicate` to the rows within the filter
+    pub fn restrict(&self, mut predicate: Predicate) -> Predicate {
+        if let Some(measurements) = &self.measurements {
+            predicate.table_names = Some(match predicate.table_names {
+                Some(table_names) => table_names.intersection(measurements).cloned().collect(),
+                None => measurements.clone(),
+            });
+        }
+
+        for (key, value) in &self.tags {
+            predicate.exprs.push(col(key).eq(lit(value.as_str())));
+        }
+        predicate
+    }
+}
+
+/// An API token, without its secret value
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Token {
+    pub id: String,
+    pub description: String,
+    pub scopes: Vec<Scope>,
+}
+
+/// The identity a request was authenticated as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Principal {
+    /// Authentication is disabled, so everything is allowed
+    Anonymous,
+    /// The admin token, which is allowed everything
+    Admin,
+    /// A token, which is allowed what its scopes grant
+    Token(Token),
+}
+
+impl Principal {
+    /// Returns true if the principal may perform `permission` on the
+    /// database `db_name`
+    pub fn is_authorized(&self, db_name: &str, permission: Permission) -> bool {
+        match self {
+            Self::Anonymous | Self::Admin => true,
+            Self::Token(token) => token
+                .scopes
+                .iter()
+                .any(|scope| scope.allows(db_name, permission)),
+        }
+    }
+
+    /// Returns an error unless the principal may perform `permission`
+    /// on all the rows of the database `db_name`
+    pub fn authorize(&self, db_name: &str, permission: Permission) -> Result<()> {
+        ensure!(
+            self.row_filter(db_name, permission)?.is_none(),
+            RowsRestricted { db_name }
+        );
+        Ok(())
+    }
+
+    /// Returns an error unless the principal may perform `permission`
+    /// on the database `db_name`, otherwise the filter of the rows it
+    /// is limited to, if any
+    pub fn row_filter(&self, db_name: &str, permission: Permission) -> Result<Option<RowFilter>> {
+        match self {
+            Self::Anonymous | Self::Admin => Ok(None),
+            Self::Token(token) => token
+                .scopes
+                .iter()
+                .find(|scope| scope.allows(db_name, permission))
+                .map(|scope| scope.filter.clone())
+                .context(NotAuthorized {
+                    db_name,
+                    permission,
+                }),
+        }
+    }
+
+    /// Returns an error unless the principal may manage tokens
+    pub fn authorize_admin(&self) -> Result<()> {
+        match self {
+            Self::Anonymous | Self::Admin => Ok(()),
+            Self::Token(_) => AdminRequired.fail(),
+        }
+    }
+}
+
+/// Authenticates requests and manages API tokens
+#[derive(Debug, Default)]
+pub struct Authorizer {
+    /// The admin token, if authentication is enabled
+    admin_token: Option<String>,
+    /// Tokens, keyed on the digest of their secret value
+    tokens: RwLock<BTreeMap<String, Token>>,
+    /// The file the tokens are persisted to, if any
+    tokens_file: Option<PathBuf>,
+}
+
+impl Authorizer {
+    /// Creates an authorizer that allows every request
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    /// Creates an authorizer that requires each request to present
+    /// `admin_token` or a token created by the admin
+    pub fn new(admin_token: impl Into<String>) -> Self {
+        Self {
+            admin_token: Some(admin_token.into()),
+            ..Default::default()
+        }
+    }
+
+    /// Persists the tokens to the file at `path`, restoring the tokens
+    /// already persisted to it
+    pub fn with_tokens_file(self, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let tokens = match std::fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data).context(InvalidTokensFile { path: &path })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).context(ReadingTokens { path: &path }),
+        };
+
+        Ok(Self {
+            tokens: RwLock::new(tokens),
+            tokens_file: Some(path),
+            ..self
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.admin_token.is_some()
+    }
+
+    /// Authenticates a request with the value of its `Authorization`
+    /// header, if any
+    pub fn authenticate(&self, authorization: Option<&str>) -> Result<Principal> {
+        let admin_token = match &self.admin_token {
+            Some(admin_token) => admin_token,
+            None => return Ok(Principal::Anonymous),
+        };
+
+        let secret = authorization
+            .and_then(parse_authorization)
+            .context(Unauthenticated)?;
+
+        if constant_time::verify_slices_are_equal(secret.as_bytes(), admin_token.as_bytes()).is_ok()
+        {
+            return Ok(Principal::Admin);
+        }
+
+        self.tokens
+            .read()
+            .expect("token lock poisoned")
+            .get(&secret_digest(secret))
+            .cloned()
+            .map(Principal::Token)
+            .context(Unauthenticated)
+    }
+
+    /// Creates a token with `scopes`, returning it and its secret value.
+    ///
+    /// A scope with a filter may not overlap another scope, as the
+    /// rows the token could access would then be ambiguous.
+    pub fn create_token(&self, description: String, scopes: Vec<Scope>) -> Result<(Token, String)> {
+        for (index, scope) in scopes.iter().enumerate() {
+            let conflict = scopes[index + 1..].iter().find(|other| {
+                (scope.filter.is_some() || other.filter.is_some()) && scope.overlaps(other)
+            });
+            if let Some(other) = conflict {
+                let database = if scope.database == ALL_DATABASES {
+                    &other.database
+                } else {
+                    &scope.database
+                };
+                return ConflictingScopes { database }.fail();
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+        let token = Token {
+            id: format!("{:016x}", rng.gen::<u64>()),
+            description,
+            scopes,
+        };
+        let secret = format!("{:032x}", rng.gen::<u128>());
+        let digest = secret_digest(&secret);
+
+        let mut tokens = self.tokens.write().expect("token lock poisoned");
+        tokens.insert(digest.clone(), token.clone());
+        if let Err(e) = self.persist(&tokens) {
+            tokens.remove(&digest);
+            return Err(e);
+        }
+
+        Ok((token, secret))
+    }
+
+    /// Returns the tokens, ordered by id
+    pub fn tokens(&self) -> Vec<Token> {
+        let mut tokens: Vec<_> = self
+            .tokens
+            .read()
+            .expect("token lock poisoned")
+            .values()
+            .cloned()
+            .collect();
+        tokens.sort_by(|a, b| a.id.cmp(&b.id));
+        tokens
+    }
+
+    /// Deletes the token with the id `id`
+    pub fn delete_token(&self, id: &str) -> Result<()> {
+        let mut tokens = self.tokens.write().expect("token lock poisoned");
+
+        let digest = tokens
+            .iter()
+            .find(|(_, token)| token.id == id)
+            .map(|(digest, _)| digest.clone())
+            .context(TokenNotFound { id })?;
+
+        let token = tokens.remove(&digest).expect("token was just found");
+        if let Err(e) = self.persist(&tokens) {
+            tokens.insert(digest, token);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Writes `tokens` to the tokens file, if there is one. They are
+    /// written to a temporary file which then replaces it, so a crash
+    /// while writing leaves either the old or the new tokens.
+    fn persist(&self, tokens: &BTreeMap<String, Token>) -> Result<()> {
+        let path = match &self.tokens_file {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let data = serde_json::to_vec_pretty(tokens).context(InvalidTokensFile { path })?;
+
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let write = |tmp_path: &Path| -> std::io::Result<()> {
+            let mut file = std::fs::File::create(tmp_path)?;
+            file.write_all(&data)?;
+            file.sync_all()?;
+            std::fs::rename(tmp_path, path)
+        };
+        write(&tmp_path).context(WritingTokens { path })
+    }
+}
+
+/// Returns the hex encoded SHA-256 digest of the secret value of a
+/// token, by which the token is looked up
+fn secret_digest(secret: &str) -> String {
+    digest::digest(&digest::SHA256, secret.as_bytes())
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Wraps a gRPC service so that the requests it receives are rejected
+/// with an `UNAUTHENTICATED` status unless they present a valid token
+/// in their `authorization` metadata. Services then authorize the
+/// principal of each request for the database it names.
+#[derive(Debug, Clone)]
+pub struct GrpcAuthentication<S> {
+    inner: S,
+    authorizer: Arc<Authorizer>,
+}
+
+impl<S> GrpcAuthentication<S> {
+    pub fn new(inner: S, authorizer: Arc<Authorizer>) -> Self {
+        Self { inner, authorizer }
+    }
+}
+
+impl<S: NamedService> NamedService for GrpcAuthentication<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> Service<http::Request<Body>> for GrpcAuthentication<S>
+where
+    S: Service<http::Request<Body>, Response = http::Response<BoxBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Either<Ready<Result<Self::Response, Self::Error>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+        let authorization = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+
+        match self.authorizer.authenticate(authorization) {
+            Ok(_) => Either::Right(self.inner.call(req)),
+            Err(e) => {
+                let response = http::Response::builder()
+                    .status(http::StatusCode::OK)
+                    .header(http::header::CONTENT_TYPE, "application/grpc")
+                    .header("grpc-status", (Code::Unauthenticated as i32).to_string())
+                    .header("grpc-message", e.to_string())
+                    .body(BoxBody::empty())
+                    .expect("Should have been able to construct a response");
+                Either::Left(future::ok(response))
+            }
+        }
+    }
+}
+
+/// Returns the token of an `Authorization` header value of the form
+/// `Token <token>` or `Bearer <token>`
+fn parse_authorization(authorization: &str) -> Option<&str> {
+    let mut parts = authorization.trim().splitn(2, ' ');
+    match (parts.next()?, parts.next()) {
+        ("Token", Some(token)) | ("Bearer", Some(token)) => Some(token.trim()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scope(database: &str, permission: Permission) -> Scope {
+        Scope {
+            database: database.into(),
+            permission,
+            filter: None,
+        }
+    }
+
+    fn filtered_scope(database: &str, permission: Permission, filter: RowFilter) -> Scope {
+        Scope {
+            filter: Some(filter),
+            ..scope(database, permission)
+        }
+    }
+
+    fn tenant_filter() -> RowFilter {
+        let mut tags = BTreeMap::new();
+        tags.insert("tenant_id".to_string(), "acme".to_string());
+        RowFilter {
+            measurements: Some(
+                vec!["cpu".to_string(), "mem".to_string()]
+                    .into_iter()
+                    .collect(),
+            ),
+            tags,
+        }
+    }
+
+    #[test]
+    fn test_disabled() {
+        let authorizer = Authorizer::disabled();
+        assert!(!authorizer.is_enabled());
+
+        let principal = authorizer.authenticate(None).unwrap();
+        assert_eq!(principal, Principal::Anonymous);
+        principal.authorize("mydb", Permission::Write).unwrap();
+        principal.authorize_admin().unwrap();
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let authorizer = Authorizer::new("secret");
+        assert!(authorizer.is_enabled());
+
+        assert_eq!(
+            authorizer.authenticate(Some("Token secret")).unwrap(),
+            Principal::Admin
+        );
+        assert_eq!(
+            authorizer.authenticate(Some("Bearer secret")).unwrap(),
+            Principal::Admin
+        );
+
+        for authorization in &[
+            None,
+            Some("secret"),
+            Some("Token other"),
+            Some("Basic secret"),
+        ] {
+            let err = authorizer.authenticate(*authorization).unwrap_err();
+            assert_eq!(err.to_string(), "Missing or unknown API token");
+        }
+    }
+
+    #[test]
+    fn test_token_scopes() {
+        let authorizer = Authorizer::new("secret");
+        let (token, token_secret) = authorizer
+            .create_token(
+                "telegraf".into(),
+                vec![
+                    scope("metrics", Permission::Write),
+                    scope("*", Permission::Read),
+                ],
+            )
+            .unwrap();
+
+        let principal = authorizer
+            .authenticate(Some(&format!("Token {}", token_secret)))
+            .unwrap();
+        assert_eq!(principal, Principal::Token(token));
+
+        principal.authorize("metrics", Permission::Write).unwrap();
+        principal.authorize("metrics", Permission::Read).unwrap();
+        principal.authorize("other", Permission::Read).unwrap();
+
+        let err = principal.authorize("other", Permission::Write).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Token is not authorized to write database 'other'"
+        );
+        assert!(principal.authorize_admin().is_err());
+    }
+
+    #[test]
+    fn test_token_management() {
+        let authorizer = Authorizer::new("secret");
+        let (a, a_secret) = authorizer.create_token("a".into(), vec![]).unwrap();
+        let (b, _) = authorizer.create_token("b".into(), vec![]).unwrap();
+        assert_ne!(a.id, b.id);
+
+        let mut expected = vec![a.clone(), b.clone()];
+        expected.sort_by(|a, b| a.id.cmp(&b.id));
+        assert_eq!(authorizer.tokens(), expected);
+
+        authorizer.delete_token(&a.id).unwrap();
+        assert_eq!(authorizer.tokens(), vec![b]);
+        assert!(authorizer
+            .authenticate(Some(&format!("Token {}", a_secret)))
+            .is_err());
+
+        let err = authorizer.delete_token(&a.id).unwrap_err();
+        assert_eq!(err.to_string(), format!("Token '{}' not found", a.id));
+    }
+
+    #[test]
+    fn test_persisted_tokens() {
+        let dir = test_helpers::tmp_dir().unwrap();
+        let path = dir.path().join("tokens.json");
+
+        let authorizer = Authorizer::new("secret").with_tokens_file(&path).unwrap();
+        let (a, a_secret) = authorizer.create_token("a".into(), vec![]).unwrap();
+        let (b, _) = authorizer.create_token("b".into(), vec![]).unwrap();
+        authorizer.delete_token(&b.id).unwrap();
+
+        // the tokens are restored, without their secrets being stored
+        let data = std::fs::read_to_string(&path).unwrap();
+        assert!(!data.contains(&a_secret), "{}", data);
+        let authorizer = Authorizer::new("secret").with_tokens_file(&path).unwrap();
+        assert_eq!(authorizer.tokens(), vec![a.clone()]);
+        assert_eq!(
+            authorizer
+                .authenticate(Some(&format!("Token {}", a_secret)))
+                .unwrap(),
+            Principal::Token(a)
+        );
+
+        std::fs::write(&path, "not json").unwrap();
+        let err = Authorizer::new("secret")
+            .with_tokens_file(&path)
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidTokensFile { .. }));
+    }
+
+    #[tokio::test]
+    async fn test_grpc_authentication() {
+        let inner = hyper::service::service_fn(|_req: http::Request<Body>| async {
+            Ok::<_, std::convert::Infallible>(http::Response::new(BoxBody::empty()))
+        });
+        let mut service = GrpcAuthentication::new(inner, Arc::new(Authorizer::new("secret")));
+        let request = |authorization: &str| {
+            http::Request::builder()
+                .header(AUTHORIZATION, authorization)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = service.call(request("Token secret")).await.unwrap();
+        assert!(response.headers().get("grpc-status").is_none());
+
+        let response = service.call(request("Token other")).await.unwrap();
+        assert_eq!(response.headers()["grpc-status"], "16");
+        assert_eq!(
+            response.headers()["grpc-message"],
+            "Missing or unknown API token"
+        );
+    }
+
+    #[test]
+    fn test_row_filter_scopes() {
+        let authorizer = Authorizer::new("secret");
+        let (token, _) = authorizer
+            .create_token(
+                "acme".into(),
+                vec![
+                    filtered_scope("metrics", Permission::Read, tenant_filter()),
+                    scope("metrics", Permission::Write),
+                ],
+            )
+            .unwrap();
+        let principal = Principal::Token(token);
+
+        assert_eq!(
+            principal.row_filter("metrics", Permission::Read).unwrap(),
+            Some(tenant_filter())
+        );
+        assert_eq!(
+            principal.row_filter("metrics", Permission::Write).unwrap(),
+            None
+        );
+        assert!(principal.row_filter("other", Permission::Read).is_err());
+        assert!(principal.is_authorized("metrics", Permission::Read));
+
+        // requests that can not be filtered are rejected
+        let err = principal
+            .authorize("metrics", Permission::Read)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Token may only access some rows of database 'metrics', which this request can not \
+             be limited to"
+        );
+        assert_eq!(err.to_status().code(), tonic::Code::PermissionDenied);
+        principal.authorize("metrics", Permission::Write).unwrap();
+
+        let err = authorizer
+            .create_token(
+                "ambiguous".into(),
+                vec![
+                    filtered_scope("metrics", Permission::Read, tenant_filter()),
+                    scope("*", Permission::Read),
+                ],
+            )
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Scopes of database 'metrics' overlap, which is not allowed when one has a filter"
+        );
+    }
+
+    #[test]
+    fn test_row_filter_lines() {
+        let filter = tenant_filter();
+        let lines: Vec<_> = influxdb_line_protocol::parse_lines(
+            "cpu,tenant_id=acme,host=a usage=1 10\n\
+             mem,host=a,tenant_id=acme free=2 10",
+        )
+        .map(|line| line.unwrap())
+        .collect();
+        filter.check_lines("metrics", &lines).unwrap();
+
+        for lp in &[
+            "cpu,tenant_id=other usage=1 10",
+            "cpu,host=a usage=1 10",
+            "disk,tenant_id=acme used=1 10",
+        ] {
+            let lines: Vec<_> = influxdb_line_protocol::parse_lines(lp)
+                .map(|line| line.unwrap())
+                .collect();
+            assert!(!filter.allows_line(&lines[0]), "{}", lp);
+        }
+
+        let lines: Vec<_> = influxdb_line_protocol::parse_lines("cpu,tenant_id=other usage=1 10")
+            .map(|line| line.unwrap())
+            .collect();
+        assert_eq!(
+            filter
+                .check_lines("metrics", &lines)
+                .unwrap_err()
+                .to_string(),
+            "Token is not authorized to write line 'cpu,tenant_id=other' to database 'metrics'"
+        );
+    }
+
+    #[test]
+    fn test_row_filter_predicate() {
+        let filter = tenant_filter();
+
+        let predicate = filter.restrict(Predicate::default());
+        assert_eq!(
+            predicate.table_names,
+            Some(
+                vec!["cpu".to_string(), "mem".to_string()]
+                    .into_iter()
+                    .collect()
+            )
+        );
+        assert_eq!(
+            format!("{:?}", predicate.exprs),
+            format!("{:?}", vec![col("tenant_id").eq(lit("acme"))])
+        );
+
+        // the measurements of the request are limited to the filter
+        let predicate = filter.restrict(Predicate {
+            table_names: Some(
+                vec!["cpu".to_string(), "disk".to_string()]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        });
+        assert_eq!(
+            predicate.table_names,
+            Some(vec!["cpu".to_string()].into_iter().collect())
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! This module describes the build of the server: its version, the git
+//! commit it was built from, and the optional features it was built
+//! with.
+//!
+//! The version is also reported, as by InfluxDB, in the
+//! `X-Influxdb-Version` and `X-Influxdb-Build` headers of every HTTP
+//! response, which tools such as the `influx` CLI and Grafana health
+//! checks use to recognize the server.
+
+use serde::Serialize;
+
+/// The name of the build reported in the `X-Influxdb-Build` header,
+/// which InfluxDB sets to `OSS` or `ENT`
+pub const BUILD_NAME: &str = "IOx";
+
+/// The version of the server
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The git commit the server was built from, or `unknown`
+pub const COMMIT: &str = env!("INFLUXDB_IOX_GIT_COMMIT");
+
+/// Describes the build of the server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BuildInfo {
+    pub version: &'static str,
+    pub commit: &'static str,
+    pub build: &'static str,
+    /// The optional cargo features the server was built with
+    pub features: Vec<&'static str>,
+}
+
+/// Returns the build information of this server
+pub fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        commit: COMMIT,
+        build: BUILD_NAME,
+        features: features(),
+    }
+}
+
+fn features() -> Vec<&'static str> {
+    let mut features = vec![];
+    if cfg!(feature = "kafka") {
+        features.push("kafka");
+    }
+    if cfg!(feature = "otlp") {
+        features.push("otlp");
+    }
+    features
+}
@@ -0,0 +1,415 @@
+//! This module schedules the compaction of fragmented partitions in
+//! the background, so that queries read fewer, larger chunks.
+//!
+//! Every interval, the partitions of all databases with at least
+//! `min_chunks` chunks, none of which are open, are compacted, those
+//! with the most chunks first. So that compactions do not starve
+//! queries:
+//!
+//! * at most `max_concurrent` compactions run at once, each of which
+//!   keeps a blocking thread busy until it is done. The chunks are
+//!   rewritten without holding the lock on their database, so writes
+//!   and queries can go on meanwhile.
+//! * if `max_bytes_per_second` is set, compactions are paused before
+//!   each chunk they rewrite, so that the bytes of the chunks they read
+//!   do not exceed that rate
+//!
+//! Each compaction is tracked as a job, and the backlog of partitions
+//! waiting to be compacted is reported by `CompactionScheduler::metrics`.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use data_types::partition_metadata::ChunkState;
+use futures::StreamExt;
+use serde::Serialize;
+use storage::{Database, DatabaseStore, Pacer};
+use tracing::{info, warn};
+
+use super::jobs::{JobDescription, Jobs};
+
+/// How compactions are scheduled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompactionConfig {
+    /// The most compactions which run at once
+    pub max_concurrent: usize,
+    /// The most bytes of chunks compacted per second, across all
+    /// compactions, if limited
+    pub max_bytes_per_second: Option<u64>,
+    /// The fewest chunks a partition must have to be compacted
+    pub min_chunks: usize,
+}
+
+impl Default for CompactionConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: 1,
+            max_bytes_per_second: None,
+            min_chunks: 2,
+        }
+    }
+}
+
+/// A partition which is waiting to be compacted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompactionCandidate {
+    pub db_name: String,
+    pub partition_key: String,
+    /// The number of chunks of the partition
+    pub chunks: u64,
+    /// The approximate number of bytes used by the chunks
+    pub bytes: u64,
+}
+
+/// Counters describing the compactions of the scheduler
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct CompactionMetrics {
+    /// The number of partitions waiting to be compacted
+    pub backlog_partitions: u64,
+    /// The number of chunks of the partitions waiting to be compacted
+    pub backlog_chunks: u64,
+    /// The bytes of the partitions waiting to be compacted
+    pub backlog_bytes: u64,
+    /// The number of compactions running
+    pub running: u64,
+    /// The number of compactions which completed
+    pub completed: u64,
+    /// The number of compactions which failed, were cancelled or whose
+    /// database was deleted before they started
+    pub failed: u64,
+    /// The bytes of the chunks compacted by completed compactions
+    pub bytes_compacted: u64,
+    /// The total time compactions were paused by the limit on bytes
+    /// per second, in nanoseconds
+    pub throttled_nanos: u64,
+}
+
+/// Compacts the fragmented partitions of all databases, limiting the
+/// resources the compactions use
+#[derive(Debug)]
+pub struct CompactionScheduler {
+    config: CompactionConfig,
+    jobs: Arc<Jobs>,
+    throttle: Option<Throttle>,
+    metrics: Mutex<CompactionMetrics>,
+}
+
+impl CompactionScheduler {
+    pub fn new(config: CompactionConfig, jobs: Arc<Jobs>) -> Self {
+        let throttle = config
+            .max_bytes_per_second
+            .filter(|&bytes_per_second| bytes_per_second > 0)
+            .map(Throttle::new);
+
+        Self {
+            config,
+            jobs,
+            throttle,
+            metrics: Default::default(),
+        }
+    }
+
+    /// Returns the counters describing the compactions so far
+    pub fn metrics(&self) -> CompactionMetrics {
+        self.metrics.lock().expect("mutex poisoned").clone()
+    }
+
+    /// Returns the partitions of the databases in `storage` which
+    /// should be compacted, in the order they will be: most chunks
+    /// first, then most bytes
+    pub async fn candidates<T: DatabaseStore>(&self, storage: &T) -> Vec<CompactionCandidate> {
+        let mut candidates = vec![];
+        for db_name in storage.db_names_sorted().await {
+            let db = match storage.db(&db_name).await {
+                Some(db) => db,
+                None => continue,
+            };
+
+            let mut partitions: BTreeMap<String, CompactionCandidate> = BTreeMap::new();
+            let mut open = BTreeSet::new();
+            for chunk in db.chunks().await {
+                if chunk.state == ChunkState::Open {
+                    open.insert(chunk.partition_key.clone());
+                }
+
+                let partition = partitions
+                    .entry(chunk.partition_key.clone())
+                    .or_insert_with(|| CompactionCandidate {
+                        db_name: db_name.clone(),
+                        partition_key: chunk.partition_key,
+                        chunks: 0,
+                        bytes: 0,
+                    });
+                partition.chunks += 1;
+                partition.bytes += chunk.size;
+            }
+
+            candidates.extend(partitions.into_iter().map(|(_, p)| p).filter(|p| {
+                p.chunks >= self.config.min_chunks as u64 && !open.contains(&p.partition_key)
+            }));
+        }
+
+        candidates.sort_by(|a, b| (b.chunks, b.bytes).cmp(&(a.chunks, a.bytes)));
+        candidates
+    }
+
+    /// Compacts the partitions of the databases in `storage` which
+    /// should be compacted, returning once all compactions are done
+    pub async fn run_once<T>(self: &Arc<Self>, storage: &Arc<T>)
+    where
+        T: DatabaseStore + 'static,
+    {
+        let candidates = self.candidates(storage.as_ref()).await;
+        self.update(|metrics| {
+            metrics.backlog_partitions = candidates.len() as u64;
+            metrics.backlog_chunks = candidates.iter().map(|c| c.chunks).sum();
+            metrics.backlog_bytes = candidates.iter().map(|c| c.bytes).sum();
+        });
+
+        // each compaction runs in its own task so that they can run on
+        // different threads, and the next is only started once there
+        // are fewer than `max_concurrent` running
+        futures::stream::iter(candidates)
+            .map(|candidate| {
+                let scheduler = Arc::clone(self);
+                let storage = Arc::clone(storage);
+                tokio::spawn(async move { scheduler.compact(storage.as_ref(), candidate).await })
+            })
+            .buffer_unordered(self.config.max_concurrent.max(1))
+            .for_each(|result| async move {
+                if let Err(e) = result {
+                    warn!("Compaction task failed: {}", e);
+                }
+            })
+            .await;
+    }
+
+    /// Runs `run_once` every `interval`, until the process exits
+    pub fn spawn<T>(self: Arc<Self>, storage: Arc<T>, interval: Duration)
+    where
+        T: DatabaseStore + 'static,
+    {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                self.run_once(&storage).await;
+            }
+        });
+    }
+
+    async fn compact<T: DatabaseStore>(
+        self: Arc<Self>,
+        storage: &T,
+        candidate: CompactionCandidate,
+    ) {
+        self.update(|metrics| {
+            metrics.backlog_partitions = metrics.backlog_partitions.saturating_sub(1);
+            metrics.backlog_chunks = metrics.backlog_chunks.saturating_sub(candidate.chunks);
+            metrics.backlog_bytes = metrics.backlog_bytes.saturating_sub(candidate.bytes);
+            metrics.running += 1;
+        });
+
+        let result = match storage.db(&candidate.db_name).await {
+            Some(db) => {
+                let description = JobDescription::CompactPartition {
+                    db_name: candidate.db_name.clone(),
+                    partition_key: candidate.partition_key.clone(),
+                };
+                let (db, key, pace) = (&db, &candidate.partition_key, self.pacer());
+                self.jobs
+                    .run(description, |tracker| async move {
+                        let chunk = db.compact_partition(key, pace).await?;
+                        tracker.add_metric("rows", chunk.row_count);
+                        tracker.add_metric("bytes", chunk.size);
+                        Ok::<_, <T::Database as Database>::Error>(chunk)
+                    })
+                    .await
+            }
+            None => None,
+        };
+
+        match &result {
+            Some(Ok(chunk)) => info!(
+                "Compacted {} chunks of partition {} of database {} into chunk {}",
+                candidate.chunks, candidate.partition_key, candidate.db_name, chunk.id
+            ),
+            Some(Err(e)) => warn!(
+                "Unable to compact partition {} of database {}: {}",
+                candidate.partition_key, candidate.db_name, e
+            ),
+            None => warn!(
+                "Compaction of partition {} of database {} did not run to completion",
+                candidate.partition_key, candidate.db_name
+            ),
+        }
+
+        self.update(|metrics| {
+            metrics.running -= 1;
+            match result {
+                Some(Ok(_)) => {
+                    metrics.completed += 1;
+                    metrics.bytes_compacted += candidate.bytes;
+                }
+                _ => metrics.failed += 1,
+            }
+        });
+    }
+
+    /// Returns the pacer which holds compactions to the limit on bytes
+    /// per second, if any, by blocking the thread rewriting the chunks
+    fn pacer(self: &Arc<Self>) -> Option<Pacer> {
+        if self.throttle.is_none() {
+            return None;
+        }
+
+        let scheduler = Arc::clone(self);
+        Some(Arc::new(move |bytes| {
+            if let Some(throttle) = &scheduler.throttle {
+                let delay = throttle.reserve(bytes);
+                if delay > Duration::from_secs(0) {
+                    scheduler.update(|metrics| metrics.throttled_nanos += delay.as_nanos() as u64);
+                    std::thread::sleep(delay);
+                }
+            }
+        }))
+    }
+
+    fn update(&self, f: impl FnOnce(&mut CompactionMetrics)) {
+        f(&mut self.metrics.lock().expect("mutex poisoned"))
+    }
+}
+
+/// Paces compactions so that the bytes they read do not exceed a rate
+#[derive(Debug)]
+struct Throttle {
+    bytes_per_second: u64,
+    /// When the bytes reserved so far will have been read at the rate
+    next_start: Mutex<Instant>,
+}
+
+impl Throttle {
+    fn new(bytes_per_second: u64) -> Self {
+        Self {
+            bytes_per_second,
+            next_start: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Reserves the time to read `bytes` at the rate, returning how
+    /// long the compaction reading them must wait before it reads them
+    fn reserve(&self, bytes: u64) -> Duration {
+        let now = Instant::now();
+        let mut next_start = self.next_start.lock().expect("mutex poisoned");
+        let start = (*next_start).max(now);
+        *next_start = start + Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        start - now
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::partition_metadata::{ChunkStorage, ChunkSummary};
+    use storage::test::TestDatabaseStore;
+
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = Error> = std::result::Result<T, E>;
+
+    fn chunk(partition_key: &str, id: u32, state: ChunkState, size: u64) -> ChunkSummary {
+        ChunkSummary {
+            partition_key: partition_key.to_string(),
+            id,
+            storage: ChunkStorage::MutableBuffer,
+            state,
+            row_count: 1,
+            size,
+            column_sizes: Default::default(),
+            min_time: None,
+            max_time: None,
+            table_names: vec![],
+            last_write_time: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn compacts_most_fragmented_partitions_first() -> Result {
+        let storage = Arc::new(TestDatabaseStore::new());
+        let db = storage.db_or_create("mydb").await?;
+        db.set_chunks(vec![
+            chunk("a", 0, ChunkState::Closed, 10),
+            chunk("a", 1, ChunkState::Closed, 10),
+            chunk("b", 0, ChunkState::Closed, 10),
+            chunk("b", 1, ChunkState::Persisted, 10),
+            chunk("b", 2, ChunkState::Closed, 10),
+            // partitions with an open chunk are still being written to
+            chunk("c", 0, ChunkState::Closed, 10),
+            chunk("c", 1, ChunkState::Open, 10),
+            // and partitions with a single chunk are not fragmented
+            chunk("d", 0, ChunkState::Closed, 100),
+        ])
+        .await;
+
+        let jobs = Arc::new(Jobs::new());
+        let scheduler = Arc::new(CompactionScheduler::new(
+            CompactionConfig::default(),
+            Arc::clone(&jobs),
+        ));
+
+        let candidates = scheduler.candidates(storage.as_ref()).await;
+        let keys: Vec<_> = candidates
+            .iter()
+            .map(|c| (c.partition_key.as_str(), c.chunks, c.bytes))
+            .collect();
+        assert_eq!(keys, vec![("b", 3, 30), ("a", 2, 20)]);
+
+        scheduler.run_once(&storage).await;
+
+        let compacted: Vec<_> = jobs.list().into_iter().map(|job| job.description).collect();
+        assert_eq!(
+            compacted,
+            vec![
+                JobDescription::CompactPartition {
+                    db_name: "mydb".to_string(),
+                    partition_key: "b".to_string(),
+                },
+                JobDescription::CompactPartition {
+                    db_name: "mydb".to_string(),
+                    partition_key: "a".to_string(),
+                },
+            ]
+        );
+        assert!(scheduler.candidates(storage.as_ref()).await.is_empty());
+
+        assert_eq!(
+            scheduler.metrics(),
+            CompactionMetrics {
+                completed: 2,
+                bytes_compacted: 50,
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn throttle_paces_bytes() {
+        let throttle = Throttle::new(1000);
+
+        assert_eq!(throttle.reserve(500), Duration::from_secs(0));
+
+        // the next compaction waits for the first 500 bytes to be read
+        let delay = throttle.reserve(1000);
+        assert!(delay > Duration::from_millis(400), "{:?}", delay);
+        assert!(delay <= Duration::from_millis(500), "{:?}", delay);
+
+        // and the one after for those 1500 bytes
+        let delay = throttle.reserve(0);
+        assert!(delay > Duration::from_millis(1400), "{:?}", delay);
+        assert!(delay <= Duration::from_millis(1500), "{:?}", delay);
+    }
+}
@@ -0,0 +1,556 @@
+//! This module merges the configuration of the server from, in
+//! increasing order of precedence:
+//!
+//! 1. a configuration file, in TOML or YAML (chosen by its extension),
+//!    named by the `--config` flag or `INFLUXDB_IOX_CONFIG` environment
+//!    variable
+//! 2. environment variables, such as `INFLUXDB_IOX_BIND_ADDR`
+//! 3. `--set name=value` command line flags
+//!
+//! Each setting has a name, such as `bind_addr`, by which it is set in
+//! the file and with `--set`, and an environment variable. Tables of
+//! the file are flattened by joining their keys with `_`, so these are
+//! the same setting:
+//!
+//! ```toml
+//! tls_cert = "/etc/iox/cert.pem"
+//!
+//! [tls]
+//! cert = "/etc/iox/cert.pem"
+//! ```
+//!
+//! Arrays are joined with commas, as the environment variables of
+//! list settings (such as `INFLUXDB_IOX_REPLICATION_PEERS`) expect.
+//! Unknown settings are rejected, so a misspelled setting is not
+//! silently ignored.
+
+use std::{
+    collections::BTreeMap,
+    env::VarError,
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use serde_json::Value;
+use snafu::{OptionExt, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error reading config file {:?}: {}", path, source))]
+    ReadingFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Unsupported config file {:?}: expected a .toml, .yaml or .yml file",
+        path
+    ))]
+    UnsupportedFormat { path: PathBuf },
+
+    #[snafu(display("Error parsing config file {:?}: {}", path, source))]
+    ParsingToml {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+
+    #[snafu(display("Error parsing config file {:?}: {}", path, source))]
+    ParsingYaml {
+        path: PathBuf,
+        source: serde_yaml::Error,
+    },
+
+    #[snafu(display("Config file {:?} is not a table of settings", path))]
+    NotATable { path: PathBuf },
+
+    #[snafu(display("Unknown setting '{}'", name))]
+    UnknownSetting { name: String },
+
+    #[snafu(display("Invalid value for setting '{}': {}", name, reason))]
+    InvalidValue { name: String, reason: String },
+
+    #[snafu(display("{} environment variable not a valid unicode string", env_var))]
+    NotUnicode { env_var: String },
+
+    #[snafu(display("Invalid setting '{}': expected name=value", arg))]
+    InvalidSetArg { arg: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The environment variable naming the configuration file, if the
+/// `--config` flag is not given
+pub const CONFIG_FILE_ENV_VAR: &str = "INFLUXDB_IOX_CONFIG";
+
+/// A setting of the server
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Setting {
+    /// The name of the setting in the configuration file and `--set`
+    pub name: &'static str,
+    /// The environment variable the setting is read from
+    pub env_var: &'static str,
+    /// Whether the value is redacted when the configuration is shown
+    pub secret: bool,
+}
+
+const fn setting(name: &'static str, env_var: &'static str) -> Setting {
+    Setting {
+        name,
+        env_var,
+        secret: false,
+    }
+}
+
+/// All settings of the server, in the order they are shown
+pub const SETTINGS: &[Setting] = &[
+    setting("db_dir", "INFLUXDB_IOX_DB_DIR"),
+    setting("bind_addr", "INFLUXDB_IOX_BIND_ADDR"),
+    setting("grpc_bind_addr", "INFLUXDB_IOX_GRPC_BIND_ADDR"),
+    setting(
+        "grpc_max_message_bytes",
+        "INFLUXDB_IOX_GRPC_MAX_MESSAGE_BYTES",
+    ),
+    setting(
+        "grpc_max_concurrent_streams",
+        "INFLUXDB_IOX_GRPC_MAX_CONCURRENT_STREAMS",
+    ),
+    setting(
+        "grpc_max_connections_per_peer",
+        "INFLUXDB_IOX_GRPC_MAX_CONNECTIONS_PER_PEER",
+    ),
+    setting("log_filter", "RUST_LOG"),
+    setting("query_memory_limit", "INFLUXDB_IOX_QUERY_MEMORY_LIMIT"),
+    setting("query_spill_dir", "INFLUXDB_IOX_QUERY_SPILL_DIR"),
+    setting("query_cache_bytes", "INFLUXDB_IOX_QUERY_CACHE_BYTES"),
+    setting(
+        "query_cache_max_entry_bytes",
+        "INFLUXDB_IOX_QUERY_CACHE_MAX_ENTRY_BYTES",
+    ),
+    setting("compaction_interval", "INFLUXDB_IOX_COMPACTION_INTERVAL"),
+    setting(
+        "compaction_max_concurrent",
+        "INFLUXDB_IOX_COMPACTION_MAX_CONCURRENT",
+    ),
+    setting(
+        "compaction_max_bytes_per_second",
+        "INFLUXDB_IOX_COMPACTION_MAX_BYTES_PER_SECOND",
+    ),
+    setting(
+        "compaction_min_chunks",
+        "INFLUXDB_IOX_COMPACTION_MIN_CHUNKS",
+    ),
+    setting(
+        "replay_max_entries_per_second",
+        "INFLUXDB_IOX_REPLAY_MAX_ENTRIES_PER_SECOND",
+    ),
+    setting("persisted_open_mode", "INFLUXDB_IOX_PERSISTED_OPEN_MODE"),
+    setting("object_store", "INFLUXDB_IOX_OBJECT_STORE"),
+    setting("object_store_dir", "INFLUXDB_IOX_OBJECT_STORE_DIR"),
+    setting("object_store_bucket", "INFLUXDB_IOX_OBJECT_STORE_BUCKET"),
+    setting("object_store_region", "INFLUXDB_IOX_OBJECT_STORE_REGION"),
+    Setting {
+        name: "object_store_encryption_key",
+        env_var: "INFLUXDB_IOX_OBJECT_STORE_ENCRYPTION_KEY",
+        secret: true,
+    },
+    setting(
+        "object_store_encryption_key_id",
+        "INFLUXDB_IOX_OBJECT_STORE_ENCRYPTION_KEY_ID",
+    ),
+    setting(
+        "object_store_kms_key_id",
+        "INFLUXDB_IOX_OBJECT_STORE_KMS_KEY_ID",
+    ),
+    setting(
+        "soft_limit_memory_bytes",
+        "INFLUXDB_IOX_SOFT_LIMIT_MEMORY_BYTES",
+    ),
+    setting(
+        "soft_limit_tag_cardinality",
+        "INFLUXDB_IOX_SOFT_LIMIT_TAG_CARDINALITY",
+    ),
+    setting("soft_limit_wal_bytes", "INFLUXDB_IOX_SOFT_LIMIT_WAL_BYTES"),
+    Setting {
+        name: "admin_token",
+        env_var: "INFLUXDB_IOX_ADMIN_TOKEN",
+        secret: true,
+    },
+    setting("writer_id", "INFLUXDB_IOX_WRITER_ID"),
+    setting("replication_peers", "INFLUXDB_IOX_REPLICATION_PEERS"),
+    Setting {
+        name: "replication_token",
+        env_var: "INFLUXDB_IOX_REPLICATION_TOKEN",
+        secret: true,
+    },
+    setting(
+        "replication_queue_size",
+        "INFLUXDB_IOX_REPLICATION_QUEUE_SIZE",
+    ),
+    setting("mode", "INFLUXDB_IOX_MODE"),
+    setting("shard_id", "INFLUXDB_IOX_SHARD_ID"),
+    setting("access_mode", "INFLUXDB_IOX_ACCESS_MODE"),
+    setting("tls_cert", "INFLUXDB_IOX_TLS_CERT"),
+    setting("tls_key", "INFLUXDB_IOX_TLS_KEY"),
+    setting("tls_client_ca", "INFLUXDB_IOX_TLS_CLIENT_CA"),
+    setting("tls_reload_interval", "INFLUXDB_IOX_TLS_RELOAD_INTERVAL"),
+    setting("traces_exporter", "INFLUXDB_IOX_TRACES_EXPORTER"),
+    setting("traces_service_name", "INFLUXDB_IOX_TRACES_SERVICE_NAME"),
+    setting("traces_otlp_endpoint", "INFLUXDB_IOX_TRACES_OTLP_ENDPOINT"),
+];
+
+fn find_setting(name: &str) -> Result<&'static Setting> {
+    SETTINGS
+        .iter()
+        .find(|setting| setting.name == name)
+        .context(UnknownSetting { name })
+}
+
+/// Where the value of a setting came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    File,
+    Env,
+    CommandLine,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::File => write!(f, "config file"),
+            Self::Env => write!(f, "environment"),
+            Self::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+/// The effective configuration of the server
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// The configuration file read, if any
+    file: Option<PathBuf>,
+    /// The value of each setting which is set, and where it came from
+    values: BTreeMap<&'static str, (String, Source)>,
+}
+
+impl Config {
+    /// Merges the configuration from the file `file` (or the one named
+    /// by `INFLUXDB_IOX_CONFIG`, if `file` is `None`), the environment
+    /// of the process, and the `name=value` settings in `set_args`
+    pub fn load(file: Option<&Path>, set_args: &[&str]) -> Result<Self> {
+        let file = match file {
+            Some(file) => Some(file.to_path_buf()),
+            None => env_var(CONFIG_FILE_ENV_VAR)?.map(PathBuf::from),
+        };
+
+        let contents = match &file {
+            Some(path) => Some(std::fs::read_to_string(path).context(ReadingFile { path })?),
+            None => None,
+        };
+
+        Self::from_layers(file.as_deref().zip(contents.as_deref()), env_var, set_args)
+    }
+
+    /// Merges the configuration from `file`, a path and its contents,
+    /// the environment variables looked up with `env`, and the
+    /// `name=value` settings in `set_args`
+    fn from_layers<F>(file: Option<(&Path, &str)>, env: F, set_args: &[&str]) -> Result<Self>
+    where
+        F: Fn(&str) -> Result<Option<String>>,
+    {
+        let mut config = Self::default();
+
+        if let Some((path, contents)) = file {
+            config.file = Some(path.to_path_buf());
+            for (name, value) in parse_file(path, contents)? {
+                config.set(&name, value, Source::File)?;
+            }
+        }
+
+        for setting in SETTINGS {
+            if let Some(value) = env(setting.env_var)? {
+                config.set(setting.name, value, Source::Env)?;
+            }
+        }
+
+        for arg in set_args {
+            let mut parts = arg.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) if !name.is_empty() => {
+                    config.set(name.trim(), value.to_string(), Source::CommandLine)?
+                }
+                _ => return InvalidSetArg { arg: *arg }.fail(),
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn set(&mut self, name: &str, value: String, source: Source) -> Result<()> {
+        let setting = find_setting(name)?;
+        self.values.insert(setting.name, (value, source));
+        Ok(())
+    }
+
+    /// The configuration file read, if any
+    pub fn file(&self) -> Option<&Path> {
+        self.file.as_deref()
+    }
+
+    /// Returns the value of the setting `name`, if it is set
+    pub fn get(&self, name: &str) -> Option<&str> {
+        debug_assert!(find_setting(name).is_ok(), "unknown setting {}", name);
+        self.values.get(name).map(|(value, _)| value.as_str())
+    }
+
+    /// Returns where the value of the setting `name` came from, if it
+    /// is set
+    pub fn source(&self, name: &str) -> Option<Source> {
+        self.values.get(name).map(|(_, source)| *source)
+    }
+
+    /// Parses the value of the setting `name`, if it is set
+    pub fn parse<T>(&self, name: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: fmt::Display,
+    {
+        self.get(name)
+            .map(|value| {
+                value.parse().map_err(|e: T::Err| Error::InvalidValue {
+                    name: name.to_string(),
+                    reason: format!("'{}': {}", value, e),
+                })
+            })
+            .transpose()
+    }
+
+    /// Renders the configuration as a TOML file, noting where each
+    /// value came from, with the values of secret settings redacted
+    pub fn show(&self) -> String {
+        let mut out = match &self.file {
+            Some(path) => format!("# config file: {}\n", path.display()),
+            None => "# no config file\n".to_string(),
+        };
+
+        for setting in SETTINGS {
+            match self.values.get(setting.name) {
+                Some((value, source)) => {
+                    let value = if setting.secret {
+                        "<redacted>"
+                    } else {
+                        value.as_str()
+                    };
+                    let value = toml::Value::String(value.to_string());
+                    out.push_str(&format!("{} = {} # {}", setting.name, value, source));
+                    if *source == Source::Env {
+                        out.push_str(&format!(" ({})", setting.env_var));
+                    }
+                    out.push('\n');
+                }
+                None => out.push_str(&format!(
+                    "# {} is not set ({})\n",
+                    setting.name, setting.env_var
+                )),
+            }
+        }
+
+        out
+    }
+}
+
+/// Reads the environment variable `name`, if it is set
+fn env_var(name: &str) -> Result<Option<String>> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => NotUnicode { env_var: name }.fail(),
+    }
+}
+
+/// Parses the configuration file at `path`, returning its settings
+/// as names and values
+fn parse_file(path: &Path, contents: &str) -> Result<Vec<(String, String)>> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let value: Value = match extension {
+        Some("toml") => toml::from_str(contents).context(ParsingToml { path })?,
+        Some("yaml") | Some("yml") => {
+            serde_yaml::from_str(contents).context(ParsingYaml { path })?
+        }
+        _ => return UnsupportedFormat { path }.fail(),
+    };
+
+    let mut settings = vec![];
+    match value {
+        Value::Object(table) => flatten("", table, &mut settings)?,
+        // an empty YAML file
+        Value::Null => {}
+        _ => return NotATable { path }.fail(),
+    }
+    Ok(settings)
+}
+
+/// Appends the settings in `table`, whose names are prefixed with
+/// `prefix`, to `settings`
+fn flatten(
+    prefix: &str,
+    table: serde_json::Map<String, Value>,
+    settings: &mut Vec<(String, String)>,
+) -> Result<()> {
+    for (key, value) in table {
+        let name = format!("{}{}", prefix, key);
+        match value {
+            Value::Object(table) => flatten(&format!("{}_", name), table, settings)?,
+            Value::Array(values) => {
+                let values = values
+                    .into_iter()
+                    .map(|value| scalar(&name, value))
+                    .collect::<Result<Vec<_>>>()?;
+                settings.push((name, values.join(",")));
+            }
+            Value::Null => {}
+            value => {
+                let value = scalar(&name, value)?;
+                settings.push((name, value));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns `value` as the string an environment variable would hold
+fn scalar(name: &str, value: Value) -> Result<String> {
+    match value {
+        Value::String(value) => Ok(value),
+        Value::Number(value) => Ok(value.to_string()),
+        Value::Bool(value) => Ok(value.to_string()),
+        _ => InvalidValue {
+            name,
+            reason: "expected a string, number or boolean",
+        }
+        .fail(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(
+        vars: &'static [(&'static str, &'static str)],
+    ) -> impl Fn(&str) -> Result<Option<String>> {
+        move |name| {
+            Ok(vars
+                .iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.to_string()))
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let file = r#"
+bind_addr = "0.0.0.0:8080"
+grpc_bind_addr = "0.0.0.0:8082"
+mode = "writer"
+"#;
+        let config = Config::from_layers(
+            Some((Path::new("iox.toml"), file)),
+            env(&[
+                ("INFLUXDB_IOX_GRPC_BIND_ADDR", "0.0.0.0:9082"),
+                ("INFLUXDB_IOX_MODE", "router"),
+            ]),
+            &["mode=standalone"],
+        )
+        .unwrap();
+
+        assert_eq!(config.get("bind_addr"), Some("0.0.0.0:8080"));
+        assert_eq!(config.source("bind_addr"), Some(Source::File));
+        assert_eq!(config.get("grpc_bind_addr"), Some("0.0.0.0:9082"));
+        assert_eq!(config.source("grpc_bind_addr"), Some(Source::Env));
+        assert_eq!(config.get("mode"), Some("standalone"));
+        assert_eq!(config.source("mode"), Some(Source::CommandLine));
+        assert_eq!(config.get("db_dir"), None);
+        assert_eq!(config.file(), Some(Path::new("iox.toml")));
+    }
+
+    #[test]
+    fn file_formats() {
+        let toml = r#"
+writer_id = 3
+replication_peers = ["http://a:8080", "http://b:8080"]
+
+[tls]
+cert = "/etc/iox/cert.pem"
+reload_interval = 30
+"#;
+        let yaml = r#"
+writer_id: 3
+replication_peers:
+  - http://a:8080
+  - http://b:8080
+tls:
+  cert: /etc/iox/cert.pem
+  reload_interval: 30
+"#;
+
+        let from_toml =
+            Config::from_layers(Some((Path::new("iox.toml"), toml)), env(&[]), &[]).unwrap();
+        let from_yaml =
+            Config::from_layers(Some((Path::new("iox.yml"), yaml)), env(&[]), &[]).unwrap();
+        assert_eq!(from_toml.values, from_yaml.values);
+
+        assert_eq!(from_toml.parse::<u32>("writer_id").unwrap(), Some(3));
+        assert_eq!(
+            from_toml.get("replication_peers"),
+            Some("http://a:8080,http://b:8080")
+        );
+        assert_eq!(from_toml.get("tls_cert"), Some("/etc/iox/cert.pem"));
+        assert_eq!(from_toml.get("tls_reload_interval"), Some("30"));
+
+        assert!(matches!(
+            Config::from_layers(Some((Path::new("iox.ini"), "")), env(&[]), &[]),
+            Err(Error::UnsupportedFormat { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_settings() {
+        assert!(matches!(
+            Config::from_layers(Some((Path::new("iox.toml"), "bind_adr = \"a\"")), env(&[]), &[]),
+            Err(Error::UnknownSetting { name }) if name == "bind_adr"
+        ));
+        assert!(matches!(
+            Config::from_layers(None, env(&[]), &["mode"]),
+            Err(Error::InvalidSetArg { .. })
+        ));
+
+        let config = Config::from_layers(None, env(&[]), &["writer_id=first"]).unwrap();
+        assert_eq!(
+            config.parse::<u32>("writer_id").unwrap_err().to_string(),
+            "Invalid value for setting 'writer_id': 'first': invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn show_redacts_secrets() {
+        let config = Config::from_layers(
+            None,
+            env(&[("INFLUXDB_IOX_ADMIN_TOKEN", "hunter2")]),
+            &[
+                "bind_addr=0.0.0.0:8080",
+                "object_store_encryption_key=0badc0de",
+            ],
+        )
+        .unwrap();
+
+        let shown = config.show();
+        assert!(!shown.contains("hunter2"));
+        assert!(shown
+            .contains("admin_token = \"<redacted>\" # environment (INFLUXDB_IOX_ADMIN_TOKEN)\n"));
+        assert!(!shown.contains("0badc0de"));
+        assert!(shown.contains("bind_addr = \"0.0.0.0:8080\" # command line\n"));
+        assert!(shown.contains("# db_dir is not set (INFLUXDB_IOX_DB_DIR)\n"));
+        assert!(shown.starts_with("# no config file\n"));
+    }
+}
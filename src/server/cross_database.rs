@@ -0,0 +1,492 @@
+//! This module contains support for SQL queries that read tables
+//! from more than one database on this server.
+//!
+//! Tables in other databases are referred to by qualifying them
+//! with the name of the database, such as `db1.cpu`, and can be
+//! joined with each other and with (unqualified) tables from the
+//! database the query is run against:
+//!
+//! ```sql
+//! SELECT region, version FROM cpu JOIN deploys.services ON region = service_region
+//! ```
+//!
+//! Tables qualified with `system` are not read from a database named
+//! `system`, but are the system tables (see the `system_tables`
+//! module) of the database the query is run against.
+//!
+//! The other tables are read as queries of a single database read
+//! them, from the chunks with rows in the range of times the select
+//! reading them bounds the time column to (if any), and with the rows
+//! of overlapping chunks deduplicated.
+
+use std::collections::BTreeMap;
+
+use arrow_deps::{
+    arrow::record_batch::RecordBatch,
+    datafusion::{
+        datasource::MemTable, error::DataFusionError, execution::context::ExecutionContext,
+        prelude::ExecutionConfig,
+    },
+};
+use snafu::{OptionExt, ResultExt, Snafu};
+use sqlparser::{
+    ast::{ObjectName, Query, SetExpr, Statement, TableFactor, TableWithJoins},
+    dialect::GenericDialect,
+    parser::{Parser, ParserError},
+};
+use storage::{predicate::TimestampRange, Database, DatabaseStore};
+use write_buffer::select_time_range;
+
+use super::system_tables::{self, SystemTables, SYSTEM_SCHEMA};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid SQL query '{}': {}", query, source))]
+    InvalidSql { query: String, source: ParserError },
+
+    #[snafu(display("Unsupported SQL statement in query '{}'", query))]
+    UnsupportedStatement { query: String },
+
+    #[snafu(display("Invalid table name '{}': expected 'table' or 'database.table'", name))]
+    InvalidTableName { name: String },
+
+    #[snafu(display("Not authorized to query database '{}'", db_name))]
+    NotAuthorized { db_name: String },
+
+    #[snafu(display("Database '{}' not found", db_name))]
+    DatabaseNotFound { db_name: String },
+
+    #[snafu(display(
+        "Error reading table '{}' from database '{}': {}",
+        table_name,
+        db_name,
+        source
+    ))]
+    ReadingTable {
+        db_name: String,
+        table_name: String,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
+    #[snafu(display("Error reading system table: {}", source))]
+    ReadingSystemTable { source: system_tables::Error },
+
+    #[snafu(display("Error executing query '{}': {}", query, source))]
+    Executing {
+        query: String,
+        source: DataFusionError,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns true if `sql` refers to any table qualified with a
+/// database name. Queries that can not be parsed return false, so
+/// that they fail with the error of the database they are run against.
+pub fn references_other_databases(sql: &str) -> bool {
+    match table_names(sql) {
+        Ok(names) => names.iter().any(|(name, _)| name.0.len() > 1),
+        Err(_) => false,
+    }
+}
+
+/// Runs the SQL query `sql` against the databases in `store`.
+/// Unqualified table names are read from `default_db`, and qualified
+/// ones (`database.table`) from the named database, except for those
+/// qualified with `system`, which are read from `system_tables`.
+///
+/// `authorize` is called with the name of each database the query
+/// reads from, other than `default_db`'s system tables, and the query
+/// fails if it returns false for any of them.
+pub async fn query<T, F>(
+    store: &T,
+    default_db: &str,
+    sql: &str,
+    system_tables: &SystemTables,
+    authorize: F,
+) -> Result<Vec<RecordBatch>>
+where
+    T: DatabaseStore,
+    F: Fn(&str) -> bool,
+{
+    // The range of times each table is read in, which is unbounded
+    // unless every select reading it bounds it to the same range
+    let mut ranges: BTreeMap<String, Option<TimestampRange>> = BTreeMap::new();
+    let names = table_names(sql)?;
+    for (name, range) in &names {
+        ranges
+            .entry(name.to_string())
+            .and_modify(|existing| {
+                if *existing != *range {
+                    *existing = None
+                }
+            })
+            .or_insert(*range);
+    }
+
+    // Load each referenced table once, using the name DataFusion will
+    // look it up by
+    let mut tables = BTreeMap::new();
+    for (name, _) in names {
+        let (db_name, table_name) = match name.0.as_slice() {
+            [table_name] => (default_db.to_string(), table_name.value.clone()),
+            [db_name, table_name] => (db_name.value.clone(), table_name.value.clone()),
+            _ => {
+                return InvalidTableName {
+                    name: name.to_string(),
+                }
+                .fail()
+            }
+        };
+
+        let registered_name = name.to_string();
+        if db_name == SYSTEM_SCHEMA {
+            if tables.contains_key(&registered_name) {
+                continue;
+            }
+
+            let db = store.db(default_db).await.context(DatabaseNotFound {
+                db_name: default_db,
+            })?;
+            let batch = system_tables
+                .table(default_db, db.as_ref(), &table_name)
+                .await
+                .context(ReadingSystemTable)?;
+            tables.insert(registered_name, (batch.schema(), vec![batch]));
+            continue;
+        }
+
+        if !authorize(&db_name) {
+            return NotAuthorized { db_name }.fail();
+        }
+
+        if tables.contains_key(&registered_name) {
+            continue;
+        }
+
+        let db = store
+            .db(&db_name)
+            .await
+            .context(DatabaseNotFound { db_name: &db_name })?;
+
+        let range = ranges.get(&registered_name).copied().flatten();
+        let (schema, data) = db
+            .query_table(&table_name, range)
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(ReadingTable {
+                db_name: &db_name,
+                table_name: &table_name,
+            })?;
+
+        tables.insert(registered_name, (schema, data));
+    }
+
+    let config = ExecutionConfig::new().with_batch_size(1024 * 1024);
+    let mut ctx = ExecutionContext::with_config(config);
+
+    for (name, (schema, data)) in tables {
+        let provider = MemTable::new(schema, vec![data]).context(Executing { query: sql })?;
+        ctx.register_table(&name, Box::new(provider));
+    }
+
+    let plan = ctx
+        .create_logical_plan(sql)
+        .context(Executing { query: sql })?;
+    let plan = ctx.optimize(&plan).context(Executing { query: sql })?;
+    let plan = ctx
+        .create_physical_plan(&plan)
+        .context(Executing { query: sql })?;
+
+    ctx.collect(plan).await.context(Executing { query: sql })
+}
+
+/// Returns the names of all the tables `sql` reads from, each with the
+/// range of times the select reading it bounds its time column to
+fn table_names(sql: &str) -> Result<Vec<(ObjectName, Option<TimestampRange>)>> {
+    let dialect = GenericDialect {};
+    let statements = Parser::parse_sql(&dialect, sql).context(InvalidSql { query: sql })?;
+
+    let mut names = vec![];
+    for statement in statements {
+        match statement {
+            Statement::Query(query) => query_table_names(&query, &mut names),
+            _ => return UnsupportedStatement { query: sql }.fail(),
+        }
+    }
+
+    Ok(names)
+}
+
+type TableNames = Vec<(ObjectName, Option<TimestampRange>)>;
+
+fn query_table_names(query: &Query, names: &mut TableNames) {
+    set_expr_table_names(&query.body, names)
+}
+
+fn set_expr_table_names(expr: &SetExpr, names: &mut TableNames) {
+    match expr {
+        SetExpr::Select(select) => {
+            // only the selects of a single table have a range
+            let range = select_time_range(select);
+            for table in &select.from {
+                table_with_joins_names(table, range, names)
+            }
+        }
+        SetExpr::Query(query) => query_table_names(query, names),
+        SetExpr::SetOperation { left, right, .. } => {
+            set_expr_table_names(left, names);
+            set_expr_table_names(right, names);
+        }
+        _ => {}
+    }
+}
+
+fn table_with_joins_names(
+    table: &TableWithJoins,
+    range: Option<TimestampRange>,
+    names: &mut TableNames,
+) {
+    table_factor_names(&table.relation, range, names);
+    for join in &table.joins {
+        table_factor_names(&join.relation, None, names);
+    }
+}
+
+fn table_factor_names(factor: &TableFactor, range: Option<TimestampRange>, names: &mut TableNames) {
+    match factor {
+        TableFactor::Table { name, .. } => names.push((name.clone(), range)),
+        TableFactor::Derived { subquery, .. } => query_table_names(subquery, names),
+        // the time column of the joined tables is ambiguous
+        TableFactor::NestedJoin(table) => table_with_joins_names(table, None, names),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::jobs::Jobs;
+    use arrow_deps::arrow::util::pretty::pretty_format_batches;
+    use influxdb_line_protocol::parse_lines;
+    use std::sync::Arc;
+    use write_buffer::{Db, WriteBufferDatabases};
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    async fn make_store() -> Result<WriteBufferDatabases, TestError> {
+        let store = WriteBufferDatabases::new(test_helpers::tmp_dir()?.into_path());
+
+        for (name, lp) in &[
+            (
+                "metrics",
+                "cpu,region=west user=23.2 10\ncpu,region=east user=5.5 20",
+            ),
+            ("deploys", "services,service_region=west version=\"1.2\" 5"),
+        ] {
+            let db = Db::new(*name);
+            let lines = parse_lines(lp).collect::<Result<Vec<_>, _>>()?;
+            db.write_lines(&lines).await?;
+            store.add_db(db).await;
+        }
+
+        Ok(store)
+    }
+
+    #[test]
+    fn test_references_other_databases() {
+        assert!(!references_other_databases("select * from cpu"));
+        assert!(references_other_databases("select * from db1.cpu"));
+        assert!(references_other_databases(
+            "select * from cpu join db2.services on a = b"
+        ));
+        assert!(references_other_databases(
+            "select * from (select * from db2.services) as s"
+        ));
+        assert!(references_other_databases(
+            "select a from cpu union all select a from db2.services"
+        ));
+        assert!(!references_other_databases("not sql"));
+    }
+
+    #[tokio::test]
+    async fn test_cross_database_query() -> Result<(), TestError> {
+        let store = make_store().await?;
+        let system_tables = SystemTables::new(Arc::new(Jobs::new()));
+
+        let results = query(
+            &store,
+            "metrics",
+            "select region, version from cpu join deploys.services on region = service_region",
+            &system_tables,
+            |_| true,
+        )
+        .await?;
+
+        let expected = r#"+--------+---------+
+| region | version |
++--------+---------+
+| west   | 1.2     |
++--------+---------+
+"#;
+        assert_eq!(pretty_format_batches(&results)?, expected);
+
+        let results = query(
+            &store,
+            "deploys",
+            "select region from metrics.cpu order by region",
+            &system_tables,
+            |_| true,
+        )
+        .await?;
+        let expected = r#"+--------+
+| region |
++--------+
+| east   |
+| west   |
++--------+
+"#;
+        assert_eq!(pretty_format_batches(&results)?, expected);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cross_database_query_reads_chunks_as_queries_do() -> Result<(), TestError> {
+        let store = make_store().await?;
+        let system_tables = SystemTables::new(Arc::new(Jobs::new()));
+
+        let db = Db::new("overlapping");
+        let write = |lp: &'static str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let db = &db;
+            async move { db.write_lines(&lines).await }
+        };
+        write("cpu,host=A user=1 10\ncpu,host=B user=2 10").await?;
+        db.close_chunk("1970-01-01T00", 0).await?;
+        write("cpu,host=A user=3 10\ncpu,host=A user=4 20").await?;
+        db.close_chunk("1970-01-01T00", 1).await?;
+        write("cpu,host=A user=5 1000").await?;
+        let metrics = db.chunk_pruning_metrics();
+        store.add_db(db).await;
+
+        // the rows of the overlapping chunks are deduplicated, and the
+        // chunk without rows in the range is not read
+        let results = query(
+            &store,
+            "metrics",
+            "select host, time, user from overlapping.cpu where time < 100 order by host, time",
+            &system_tables,
+            |_| true,
+        )
+        .await?;
+        let expected = r#"+------+------+------+
+| host | time | user |
++------+------+------+
+| A    | 10   | 3    |
+| A    | 20   | 4    |
+| B    | 10   | 2    |
++------+------+------+
+"#;
+        assert_eq!(pretty_format_batches(&results)?, expected);
+        assert_eq!((metrics.chunks_scanned(), metrics.chunks_pruned()), (2, 1));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cross_database_query_errors() -> Result<(), TestError> {
+        let store = make_store().await?;
+        let system_tables = SystemTables::new(Arc::new(Jobs::new()));
+
+        let error = query(
+            &store,
+            "metrics",
+            "select * from deploys.services",
+            &system_tables,
+            |db| db == "metrics",
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Not authorized to query database 'deploys'"
+        );
+
+        let error = query(
+            &store,
+            "metrics",
+            "select * from missing.cpu",
+            &system_tables,
+            |_| true,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(error.to_string(), "Database 'missing' not found");
+
+        let error = query(
+            &store,
+            "metrics",
+            "select * from a.b.c",
+            &system_tables,
+            |_| true,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid table name 'a.b.c': expected 'table' or 'database.table'"
+        );
+
+        let error = query(&store, "metrics", "drop table cpu", &system_tables, |_| {
+            true
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Unsupported SQL statement in query 'drop table cpu'"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_system_tables_query() -> Result<(), TestError> {
+        let store = make_store().await?;
+        let system_tables = SystemTables::new(Arc::new(Jobs::new()));
+
+        // system tables describe the database the query is run
+        // against, and need no further authorization
+        let results = query(
+            &store,
+            "metrics",
+            "select partition_key, storage, state, row_count from system.chunks",
+            &system_tables,
+            |_| false,
+        )
+        .await?;
+        let expected = r#"+---------------+----------------+-------+-----------+
+| partition_key | storage        | state | row_count |
++---------------+----------------+-------+-----------+
+| 1970-01-01T00 | mutable_buffer | open  | 2         |
++---------------+----------------+-------+-----------+
+"#;
+        assert_eq!(pretty_format_batches(&results)?, expected);
+
+        let error = query(
+            &store,
+            "metrics",
+            "select * from system.tables",
+            &system_tables,
+            |_| true,
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Error reading system table: System table 'tables' not found: expected chunks, columns, queries or operations"
+        );
+
+        Ok(())
+    }
+}
@@ -11,46 +11,246 @@
 //!
 //! Long term, we expect to create IOx specific api in terms of
 //! database names and may remove this quasi /v2 API from the Deloren.
+//!
+//! The first such IOx specific route is
+//! `POST /iox/api/v1/databases/{name}/query`, which runs the SQL
+//! query in the request body against the named database and returns
+//! the results in the Arrow IPC streaming format, CSV, or JSON lines,
+//! selected by the `format` query parameter or the `Accept` header.
+//!
+//! For compatibility with tools written for InfluxDB 1.x, such as
+//! Grafana's InfluxQL datasource, `GET /query` and `POST /query` run
+//! InfluxQL queries and return results in the 1.x JSON format.
+//!
+//! Both query routes return warnings about parts of the request that
+//! were not used, such as a bound parameter the query doesn't refer
+//! to, in `X-IOx-Warning` headers. InfluxQL queries also return them in
+//! the `messages` of the first statement's results, as InfluxDB 1.x
+//! does.
+//!
+//! As InfluxDB does, every response has `X-Influxdb-Version` and
+//! `X-Influxdb-Build` headers, and `/ping` responds with no content
+//! (or with the version, if `verbose=true`) so health checks recognize
+//! the server. `GET /health` reports its health in the format of
+//! InfluxDB 2.x, and `GET /iox/api/v1/build_info` returns its version,
+//! git commit and optional features. None of these require a token.
+//!
+//! Databases are created with their rules by `POST
+//! /iox/api/v1/databases/{name}`, and their rules are read, replaced
+//! or the database deleted with `GET`, `PUT` and `DELETE` requests to
+//! the same path. `GET /iox/api/v1/databases` lists all databases with
+//! the rules currently in effect for each. Replaced rules take effect
+//! immediately: later writes are checked against them, and the
+//! retention period and downsampling rules are applied right away
+//! rather than at their next scheduled check.
+//!
+//! `GET /iox/api/v1/databases/{name}/partitions` lists the keys of the
+//! partitions of a database, and `POST` requests to
+//! `/iox/api/v1/databases/{name}/partitions/drop?key={key}` (or
+//! `.../truncate?key={key}`) drop a partition or remove all of its data.
+//! With `dry_run=true` they only report what would be removed. `GET
+//! /iox/api/v1/databases/{name}/partitions/stats` returns the row
+//! count, size by storage tier, time range and last write time of each
+//! partition, aggregated from its chunks.
+//!
+//! `GET /iox/api/v1/databases/{name}/chunks` lists the chunks of a
+//! database, with their storage, lifecycle state, size by column and
+//! time range, and `GET
+//! /iox/api/v1/databases/{name}/chunk?partition_key={key}&id={id}`
+//! returns a single chunk.
+//!
+//! For operational remediation, `POST` requests to
+//! `/iox/api/v1/databases/{name}/chunk/close?partition_key={key}&id={id}`
+//! force an open chunk closed, so later writes go to a new chunk,
+//! `.../chunk/persist?partition_key={key}&id={id}` writes a closed chunk
+//! to parquet files, and
+//! `/iox/api/v1/databases/{name}/partitions/compact?key={key}` merges
+//! all chunks of a partition into one.
+//!
+//! These lists, and those of jobs and tokens, return every item unless
+//! a request pages through them with `limit={n}`, passing the `next`
+//! cursor of each response back as `after={cursor}`. They can also be
+//! filtered: chunks by `table`, `state` and time range (`start` and
+//! `end`, in nanoseconds since the epoch), partitions by `table`, jobs
+//! by `status` and start time, and tokens by `database`.
+//!
+//! Long running background work, such as compactions, is tracked as
+//! jobs. `GET /iox/api/v1/jobs` lists them with their status, progress
+//! and metrics, `GET /iox/api/v1/jobs/{id}` returns one, and `POST
+//! /iox/api/v1/jobs/{id}/cancel` cancels one which is still running.
+//!
+//! Fragmented partitions may also be compacted in the background, and
+//! `GET /iox/api/v1/compaction` reports the backlog of partitions
+//! waiting to be compacted along with counters of past compactions.
+//!
+//! With `format=prometheus`, `/api/v2/write` accepts metrics in the
+//! Prometheus text exposition format. Histograms are stored with a
+//! column per bucket and columns for their sum and count, and queries
+//! estimate their quantiles with `histogram_quantile(q)`.
+//!
+//! Writes accepted by `/api/v2/write` are replicated asynchronously to
+//! any peer servers configured, which store them with `POST
+//! /iox/api/v1/databases/{name}/replicated_write`. `GET
+//! /iox/api/v1/replication` reports the state of replication to each
+//! peer, such as the writes it has not yet acknowledged and its lag.
+//!
+//! Subscriptions stream the writes to a database to subscribers, such
+//! as readers of the `Subscribe` gRPC or Kafka topics. `GET
+//! /iox/api/v1/subscriptions` lists them, and they are read, created
+//! or replaced, and deleted with `GET`, `PUT` and `DELETE` requests to
+//! `/iox/api/v1/subscriptions/{name}`.
+//!
+//! A server started as a router forwards the writes it accepts to the
+//! writers of their shards instead of storing them, and rejects
+//! queries. A server started as the writer of a shard rejects writes
+//! of lines which belong to another shard. `GET /iox/api/v1/shard_map`
+//! returns the mode of the server and its shards, which are replaced
+//! by a `PUT` request to the same path, on routers and writers alike.
+//! After the shards change, `POST /iox/api/v1/reshard` to each writer
+//! moves the series it stores which belong to another shard under the
+//! new shard map to the writers of that shard, deleting them from the
+//! writer once they are written there.
+//!
+//! `POST /iox/api/v1/apply` reconciles the server to a declarative
+//! spec of its databases, with their rules, and optionally its shards:
+//! databases missing from the server are created, and those whose
+//! rules differ are updated. Applying the same spec again changes
+//! nothing, so specs can be kept in version control and applied on
+//! every change. Databases the spec doesn't name are only deleted if
+//! it sets `prune`, and with `dry_run=true` the response lists the
+//! changes without making them.
+//!
+//! The quota of a database is managed with `GET`, `PUT` and `DELETE`
+//! requests to `/iox/api/v1/databases/{name}/quota`, and the quotas
+//! of all databases are listed by `GET /iox/api/v1/quotas`.
+//!
+//! What the server logs is changed while it runs with a `PUT` request
+//! to `/iox/api/v1/log_filter`, whose body holds `RUST_LOG` style
+//! directives such as `info,segment_store=debug`. A `GET` request to
+//! the same path returns the directives in effect, and a `DELETE`
+//! request restores those the server started with.
+//!
+//! A server can be made to reject writes (`read_only`), queries
+//! (`ingest_only`) or both (`maintenance`) with a `PUT` request to
+//! `/iox/api/v1/access_mode`, such as `{"mode": "read_only"}`. Rejected
+//! requests fail with `503 Service Unavailable`, so clients can retry
+//! them against another server. `GET` returns the mode in effect and
+//! `DELETE` restores the mode the server started in.
+//!
+//! `GET /iox/api/v1/alerts` reports the soft limits on the memory, tag
+//! cardinality and WAL size of each database, the alerts firing on
+//! databases over them, and the recent events of alerts firing and
+//! being resolved (those after an event id with `after={id}`). A `PUT`
+//! request to the same path replaces the limits.
+//!
+//! Clients may attach labels to SQL queries, such as the dashboard or
+//! user they were run for, in an `X-IOx-Query-Labels` header (see the
+//! `query_labels` module). They are recorded in `system.queries` and
+//! on the spans of the query, so expensive queries can be attributed
+//! to their source.
+//!
+//! `GET /metrics` returns the metrics of the server, such as the
+//! latency, response size and errors of each storage RPC by database,
+//! in the Prometheus text format (see the `metrics` module).
+//!
+//! SQL queries may also read the system tables of the database they
+//! are run against, such as `system.chunks` and `system.queries`,
+//! which describe its chunks, its recent queries and its jobs.
+
+mod format;
+mod pagination;
+mod prometheus;
+mod v1;
 
-use http::header::CONTENT_ENCODING;
-use tracing::{debug, error, info};
+use http::header::{ACCEPT, CONTENT_ENCODING, CONTENT_TYPE};
+use tracing::{debug, error, info, info_span};
+use tracing_futures::Instrument;
 
-use arrow_deps::arrow;
+use arrow_deps::{arrow, datafusion::physical_plan::SendableRecordBatchStream};
+use data_types::{
+    data::ReplicatedWrite,
+    database_rules::{self, DatabaseRules, RejectedLine},
+    partition_metadata::{ChunkState, ChunkSummary, PartitionStatistics, PartitionSummary},
+};
 use influxdb_line_protocol::parse_lines;
-use storage::{org_and_bucket_to_database, Database, DatabaseStore};
+use influxql::ast::{Measurement, SelectStatement};
+use storage::{org_and_bucket_to_database, tenant, Database, DatabaseStore};
+
+use super::{
+    access::{self, Access, AccessMode},
+    alerts::{AlertEvent, Alerts, Resource, SoftLimits},
+    auth::{self, Authorizer, Permission, Principal, Scope},
+    build_info,
+    compaction::CompactionScheduler,
+    cross_database,
+    jobs::{self, JobDescription, JobInfo, JobStatus, Jobs},
+    log_filter::{self, LogFilter},
+    metrics::Metrics,
+    provision::{self, Spec},
+    query_labels::{self, QueryLabels},
+    query_params,
+    quota::{self, Quota, Quotas, Usage},
+    replication::{PeerStatus, Replicator, REPLICATED_WRITE_PATH},
+    router::{self, Mode, Router, Shard, ShardMap},
+    sharding::series_key,
+    state::ServerState,
+    subscriptions::{self, SubscriptionConfig, SubscriptionInfo, Subscriptions},
+    system_tables::{QueryRecord, SystemTables},
+    telemetry,
+    warnings::{WarningCode, Warnings},
+};
 
 use bytes::{Bytes, BytesMut};
-use futures::{self, StreamExt};
-use hyper::{Body, Method, StatusCode};
-use serde::Deserialize;
-use snafu::{OptionExt, ResultExt, Snafu};
+use chrono::Utc;
+use futures::{
+    self,
+    future::{self, Either, Ready},
+    StreamExt,
+};
+use hyper::{service::Service, Body, Method, StatusCode};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
 use std::str;
 use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use pagination::{paginate, PageParams, TimeRangeParams};
 
 #[derive(Debug, Snafu)]
 pub enum ApplicationError {
     // Internal (unexpected) errors
     #[snafu(display(
-        "Internal error accessing org {}, bucket {}:  {}",
+        "Internal error writing points into org {}, bucket {}:  {}",
         org,
         bucket_name,
         source
     ))]
-    BucketByName {
+    WritingPoints {
         org: String,
         bucket_name: String,
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
     #[snafu(display(
-        "Internal error writing points into org {}, bucket {}:  {}",
-        org,
-        bucket_name,
+        "Partial write: {} of {} lines rejected: {}",
+        rejected.len(),
+        total,
+        describe_rejected_lines(rejected)
+    ))]
+    PartialWrite {
+        total: usize,
+        rejected: Vec<RejectedLine>,
+    },
+
+    #[snafu(display(
+        "Internal error storing replicated write in database {}:  {}",
+        name,
         source
     ))]
-    WritingPoints {
-        org: String,
-        bucket_name: String,
+    StoringReplicatedWrite {
+        name: String,
         source: Box<dyn std::error::Error + Send + Sync>,
     },
 
@@ -68,12 +268,91 @@ pub enum ApplicationError {
     #[snafu(display("Bucket {} not found in org {}", bucket, org))]
     BucketNotFound { org: String, bucket: String },
 
+    #[snafu(display("Database {} not found", name))]
+    DatabaseNotFound { name: String },
+
+    #[snafu(display("Database {} already exists", name))]
+    DatabaseAlreadyExists { name: String },
+
+    #[snafu(display("Partition {} not found in database {}", key, name))]
+    PartitionNotFound { name: String, key: String },
+
+    #[snafu(display(
+        "Chunk {} of partition {} not found in database {}",
+        id,
+        partition_key,
+        name
+    ))]
+    ChunkNotFound {
+        name: String,
+        partition_key: String,
+        id: u32,
+    },
+
+    #[snafu(display(
+        "Chunk {} of partition {} in database {} is {:?}, expected {:?}",
+        id,
+        partition_key,
+        name,
+        state,
+        expected
+    ))]
+    UnexpectedChunkState {
+        name: String,
+        partition_key: String,
+        id: u32,
+        state: ChunkState,
+        expected: ChunkState,
+    },
+
+    #[snafu(display("Invalid database rules: {}", source))]
+    InvalidDatabaseRules { source: database_rules::Error },
+
+    #[snafu(display("Internal error configuring database {}: {}", name, source))]
+    ConfiguringDatabase {
+        name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Internal error reading database {}: {}", name, source))]
+    ReadingDatabase {
+        name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Internal error deleting series from database {}: {}", name, source))]
+    DeletingSeries {
+        name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
     #[snafu(display("Body exceeds limit of {} bytes", max_body_size))]
     RequestSizeExceeded { max_body_size: usize },
 
     #[snafu(display("Expected query string in request, but none was provided"))]
     ExpectedQueryString {},
 
+    #[snafu(display("Unknown result format '{}'", format))]
+    UnknownResultFormat { format: String },
+
+    #[snafu(display("None of the result formats in '{}' are supported", accept))]
+    NotAcceptable { accept: String },
+
+    #[snafu(display("Missing required parameter '{}'", name))]
+    MissingQueryParameter { name: &'static str },
+
+    #[snafu(display("Error binding query parameters: {}", source))]
+    BindingParams { source: query_params::Error },
+
+    #[snafu(display("Error in cross-database query: {}", source))]
+    CrossDatabaseQuery { source: cross_database::Error },
+
+    #[snafu(display("Error in InfluxQL query: {}", source))]
+    InfluxQL { source: influxql::Error },
+
+    #[snafu(display("Error in InfluxDB 1.x query: {}", source))]
+    V1Query { source: v1::Error },
+
     #[snafu(display("Invalid query string '{}': {}", query_string, source))]
     InvalidQueryString {
         query_string: String,
@@ -106,14 +385,70 @@ pub enum ApplicationError {
     #[snafu(display("Error reading request body as utf8: {}", source))]
     ReadingBodyAsUtf8 { source: std::str::Utf8Error },
 
+    #[snafu(display(
+        "Error reading line {} of the request body as utf8: {}. \
+         Set invalid_utf8=replace to write it with the invalid bytes replaced",
+        line,
+        source
+    ))]
+    ReadingLineAsUtf8 {
+        line: usize,
+        source: std::str::Utf8Error,
+    },
+
     #[snafu(display("Error parsing line protocol: {}", source))]
     ParsingLineProtocol {
         source: influxdb_line_protocol::Error,
     },
 
+    #[snafu(display("{}", source))]
+    ParsingPrometheus { source: prometheus::Error },
+
     #[snafu(display("Error decompressing body as gzip: {}", source))]
     ReadingBodyAsGzip { source: std::io::Error },
 
+    #[snafu(display("{}", source))]
+    Authorization { source: auth::Error },
+
+    #[snafu(display("{}", source))]
+    QuotaError { source: quota::Error },
+
+    #[snafu(display("{}", source))]
+    JobError { source: jobs::Error },
+
+    #[snafu(display("{}", source))]
+    Pagination { source: pagination::Error },
+
+    #[snafu(display("{}", source))]
+    SubscriptionError { source: subscriptions::Error },
+
+    #[snafu(display("{}", source))]
+    TenantError { source: tenant::Error },
+
+    #[snafu(display("{}", source))]
+    RoutingError { source: router::Error },
+
+    #[snafu(display("{}", source))]
+    LogFilterError { source: log_filter::Error },
+
+    #[snafu(display("{}", source))]
+    AccessError { source: access::Error },
+
+    #[snafu(display("{}", source))]
+    ProvisionError { source: provision::Error },
+
+    #[snafu(display("{}", source))]
+    InvalidQueryLabels { source: query_labels::Error },
+
+    #[snafu(display("Invalid job id '{}': {}", id, source))]
+    InvalidJobId {
+        id: String,
+        source: std::num::ParseIntError,
+    },
+
+    #[snafu(display("Operation on database {} was cancelled", name))]
+    OperationCancelled { name: String },
+
     #[snafu(display("No handler for {:?} {}", method, path))]
     RouteNotFound { method: Method, path: String },
 
@@ -124,21 +459,99 @@ pub enum ApplicationError {
 impl ApplicationError {
     pub fn status_code(&self) -> StatusCode {
         match self {
-            Self::BucketByName { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Self::WritingPoints { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::PartialWrite { .. } => StatusCode::BAD_REQUEST,
+            Self::StoringReplicatedWrite { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Self::Query { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Self::QueryError { .. } => StatusCode::BAD_REQUEST,
             Self::BucketNotFound { .. } => StatusCode::NOT_FOUND,
+            Self::DatabaseNotFound { .. } => StatusCode::NOT_FOUND,
+            Self::DatabaseAlreadyExists { .. } => StatusCode::CONFLICT,
+            Self::PartitionNotFound { .. } => StatusCode::NOT_FOUND,
+            Self::ChunkNotFound { .. } => StatusCode::NOT_FOUND,
+            Self::UnexpectedChunkState { .. } => StatusCode::CONFLICT,
+            Self::InvalidDatabaseRules { .. } => StatusCode::BAD_REQUEST,
+            Self::ConfiguringDatabase { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::ReadingDatabase { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::DeletingSeries { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             Self::RequestSizeExceeded { .. } => StatusCode::BAD_REQUEST,
             Self::ExpectedQueryString { .. } => StatusCode::BAD_REQUEST,
+            Self::UnknownResultFormat { .. } => StatusCode::BAD_REQUEST,
+            Self::NotAcceptable { .. } => StatusCode::NOT_ACCEPTABLE,
+            Self::MissingQueryParameter { .. } => StatusCode::BAD_REQUEST,
+            Self::BindingParams { .. } => StatusCode::BAD_REQUEST,
+            Self::CrossDatabaseQuery { source } => match source {
+                cross_database::Error::NotAuthorized { .. } => StatusCode::FORBIDDEN,
+                cross_database::Error::DatabaseNotFound { .. } => StatusCode::NOT_FOUND,
+                _ => StatusCode::BAD_REQUEST,
+            },
+            Self::InfluxQL { .. } => StatusCode::BAD_REQUEST,
+            Self::V1Query { .. } => StatusCode::BAD_REQUEST,
             Self::InvalidQueryString { .. } => StatusCode::BAD_REQUEST,
             Self::InvalidRequestBody { .. } => StatusCode::BAD_REQUEST,
             Self::InvalidContentEncoding { .. } => StatusCode::BAD_REQUEST,
             Self::ReadingHeaderAsUtf8 { .. } => StatusCode::BAD_REQUEST,
             Self::ReadingBody { .. } => StatusCode::BAD_REQUEST,
             Self::ReadingBodyAsUtf8 { .. } => StatusCode::BAD_REQUEST,
+            Self::ReadingLineAsUtf8 { .. } => StatusCode::BAD_REQUEST,
             Self::ParsingLineProtocol { .. } => StatusCode::BAD_REQUEST,
+            Self::ParsingPrometheus { .. } => StatusCode::BAD_REQUEST,
             Self::ReadingBodyAsGzip { .. } => StatusCode::BAD_REQUEST,
+            Self::Authorization { source } => match source {
+                auth::Error::Unauthenticated => StatusCode::UNAUTHORIZED,
+                auth::Error::TokenNotFound { .. } => StatusCode::NOT_FOUND,
+                auth::Error::ConflictingScopes { .. } => StatusCode::BAD_REQUEST,
+                auth::Error::ReadingTokens { .. }
+                | auth::Error::InvalidTokensFile { .. }
+                | auth::Error::WritingTokens { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+                _ => StatusCode::FORBIDDEN,
+            },
+            Self::QuotaError { source } => match source {
+                quota::Error::QuotaNotFound { .. } => StatusCode::NOT_FOUND,
+                _ => StatusCode::TOO_MANY_REQUESTS,
+            },
+            Self::JobError { source } => match source {
+                jobs::Error::JobNotFound { .. } => StatusCode::NOT_FOUND,
+                jobs::Error::JobFinished { .. } => StatusCode::CONFLICT,
+            },
+            Self::Pagination { .. } => StatusCode::BAD_REQUEST,
+            Self::TenantError { source } => match source {
+                tenant::Error::OtherTenant { .. } => StatusCode::CONFLICT,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Self::SubscriptionError { source } => match source {
+                subscriptions::Error::SubscriptionNotFound { .. } => StatusCode::NOT_FOUND,
+                subscriptions::Error::CreatingKafkaProducer { .. } => {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+                _ => StatusCode::BAD_REQUEST,
+            },
+            Self::RoutingError { source } => match source {
+                router::Error::NoShards { .. } => StatusCode::SERVICE_UNAVAILABLE,
+                router::Error::ShardMapVersionMismatch { .. } => StatusCode::CONFLICT,
+                router::Error::ForwardingWrite { .. } => StatusCode::BAD_GATEWAY,
+                router::Error::NotOwned { .. } => StatusCode::MISDIRECTED_REQUEST,
+                router::Error::MissingShardId { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+                _ => StatusCode::BAD_REQUEST,
+            },
+            Self::LogFilterError { source } => match source {
+                log_filter::Error::InvalidDirectives { .. } => StatusCode::BAD_REQUEST,
+                log_filter::Error::ReplacingFilter { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Self::AccessError { source } => match source {
+                access::Error::InvalidMode { .. } => StatusCode::BAD_REQUEST,
+                access::Error::NoData { .. } => StatusCode::MISDIRECTED_REQUEST,
+                _ => StatusCode::SERVICE_UNAVAILABLE,
+            },
+            Self::ProvisionError { source } => match source {
+                provision::Error::InvalidRules { .. } | provision::Error::InvalidShards { .. } => {
+                    StatusCode::BAD_REQUEST
+                }
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            },
+            Self::InvalidQueryLabels { .. } => StatusCode::BAD_REQUEST,
+            Self::InvalidJobId { .. } => StatusCode::BAD_REQUEST,
+            Self::OperationCancelled { .. } => StatusCode::CONFLICT,
             Self::RouteNotFound { .. } => StatusCode::NOT_FOUND,
             Self::CreatingGzipDecoder { .. } => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -147,11 +560,147 @@ impl ApplicationError {
 
 const MAX_SIZE: usize = 10_485_760; // max write request size of 10MB
 
+/// The number of rejected lines described in the error of a partial
+/// write
+const MAX_DESCRIBED_REJECTED_LINES: usize = 10;
+
+/// Prefix of the IOx specific routes that operate on a named database
+const DATABASES_PATH: &str = "/iox/api/v1/databases/";
+
+/// The IOx specific route that lists all databases and their rules
+const LIST_DATABASES_PATH: &str = "/iox/api/v1/databases";
+
+/// The routes under `/iox/api/v1/databases/{name}/`
+const DATABASE_ROUTES: &[&str] = &[
+    "query",
+    "quota",
+    "partitions",
+    "partitions/stats",
+    "partitions/drop",
+    "partitions/truncate",
+    "partitions/compact",
+    "chunks",
+    "chunk",
+    "chunk/close",
+    "chunk/persist",
+    REPLICATED_WRITE_PATH,
+];
+
+/// The IOx specific route that manages API tokens
+const TOKENS_PATH: &str = "/iox/api/v1/tokens";
+
+/// The IOx specific route that lists the quotas of all databases
+const QUOTAS_PATH: &str = "/iox/api/v1/quotas";
+
+/// The IOx specific route that manages background jobs
+const JOBS_PATH: &str = "/iox/api/v1/jobs";
+
+/// The IOx specific route that reports the state of replication
+const REPLICATION_PATH: &str = "/iox/api/v1/replication";
+
+/// The IOx specific route that reports the backlog and counters of
+/// background compactions
+const COMPACTION_PATH: &str = "/iox/api/v1/compaction";
+
+/// The IOx specific route that manages subscriptions
+const SUBSCRIPTIONS_PATH: &str = "/iox/api/v1/subscriptions";
+
+/// The IOx specific route that manages the shards a router forwards
+/// writes to, or a writer checks the lines it accepts against
+const SHARD_MAP_PATH: &str = "/iox/api/v1/shard_map";
+
+/// The IOx specific route that changes what the server logs
+const LOG_FILTER_PATH: &str = "/iox/api/v1/log_filter";
+
+/// The IOx specific route that changes which requests the server
+/// accepts
+const ACCESS_MODE_PATH: &str = "/iox/api/v1/access_mode";
+
+/// The IOx specific route that reports and configures the soft limits
+/// on the resources of databases
+const ALERTS_PATH: &str = "/iox/api/v1/alerts";
+
+/// The IOx specific route that describes the build of the server
+const BUILD_INFO_PATH: &str = "/iox/api/v1/build_info";
+
+/// The IOx specific route that moves the series of a writer which
+/// belong to other shards to the writers of those shards
+const RESHARD_PATH: &str = "/iox/api/v1/reshard";
+
+/// The route that returns the metrics of the server in the Prometheus
+/// text format
+const METRICS_PATH: &str = "/metrics";
+
+/// The IOx specific route that reconciles the databases and shards of
+/// the server to a declarative spec
+const APPLY_PATH: &str = "/iox/api/v1/apply";
+
+/// The number of lines at a time a writer sends to the writers of
+/// other shards when resharding
+const RESHARD_BATCH_LINES: usize = 10_000;
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+
 #[derive(Debug, Deserialize)]
 /// Body of the request to the /write endpoint
 struct WriteInfo {
     org: String,
     bucket: String,
+    #[serde(default)]
+    invalid_utf8: InvalidUtf8,
+    #[serde(default)]
+    format: WriteFormat,
+}
+
+/// The format of the body of a write
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum WriteFormat {
+    LineProtocol,
+    /// The Prometheus text exposition format, which is converted into
+    /// line protocol with histograms stored as bucket columns
+    Prometheus,
+}
+
+impl Default for WriteFormat {
+    fn default() -> Self {
+        Self::LineProtocol
+    }
+}
+
+/// What a write does with line protocol that isn't valid UTF-8, such as
+/// tag values from a misbehaving agent. Everything stored is valid UTF-8,
+/// so reads are never affected.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum InvalidUtf8 {
+    /// Reject the write, reporting the first line that isn't valid
+    Reject,
+    /// Replace each invalid sequence with U+FFFD and write every line
+    Replace,
+}
+
+impl Default for InvalidUtf8 {
+    fn default() -> Self {
+        Self::Reject
+    }
+}
+
+/// Decodes the line protocol in the body of a write, handling invalid
+/// UTF-8 as requested
+fn decode_write_body(
+    body: &[u8],
+    invalid_utf8: InvalidUtf8,
+) -> Result<Cow<'_, str>, ApplicationError> {
+    match str::from_utf8(body) {
+        Ok(body) => Ok(Cow::Borrowed(body)),
+        Err(_) if invalid_utf8 == InvalidUtf8::Replace => Ok(String::from_utf8_lossy(body)),
+        Err(source) => {
+            let valid = &body[..source.valid_up_to()];
+            let line = valid.iter().filter(|&&b| b == b'\n').count() + 1;
+            Err(ApplicationError::ReadingLineAsUtf8 { line, source })
+        }
+    }
 }
 
 /// Parse the request's body into raw bytes, applying size limits and
@@ -210,32 +759,86 @@ async fn parse_body(req: hyper::Request<Body>) -> Result<Bytes, ApplicationError
 async fn write<T: DatabaseStore>(
     req: hyper::Request<Body>,
     storage: Arc<T>,
+    principal: &Principal,
+    quotas: &Quotas,
+    replicator: &Replicator,
+    subscriptions: &Subscriptions,
+    router: &Router,
 ) -> Result<Option<Body>, ApplicationError> {
-    let query = req.uri().query().context(ExpectedQueryString)?;
+    let received = Utc::now().timestamp_nanos();
+    let query = req.uri().query().context(ExpectedQueryString)?.to_string();
 
-    let write_info: WriteInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
-        query_string: String::from(query),
+    let write_info: WriteInfo = serde_urlencoded::from_str(&query).context(InvalidQueryString {
+        query_string: query.clone(),
     })?;
 
     let db_name = org_and_bucket_to_database(&write_info.org, &write_info.bucket);
 
-    let db = storage
-        .db_or_create(&db_name)
-        .await
-        .map_err(|e| Box::new(e) as _)
-        .context(BucketByName {
-            org: write_info.org.clone(),
-            bucket_name: write_info.bucket.clone(),
-        })?;
+    let row_filter = principal
+        .row_filter(&db_name, Permission::Write)
+        .context(Authorization)?;
 
+    let authorization = req.headers().get(auth::AUTHORIZATION).cloned();
     let body = parse_body(req).await?;
 
-    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+    let body = decode_write_body(&body, write_info.invalid_utf8)?;
+    let body = match write_info.format {
+        WriteFormat::LineProtocol => body,
+        WriteFormat::Prometheus => {
+            Cow::Owned(prometheus::to_line_protocol(&body).context(ParsingPrometheus)?)
+        }
+    };
 
-    let lines = parse_lines(body)
+    let lines = parse_lines(&body)
         .collect::<Result<Vec<_>, influxdb_line_protocol::Error>>()
         .context(ParsingLineProtocol)?;
 
+    // a token limited to some rows may only write lines within them
+    if let Some(row_filter) = row_filter {
+        row_filter
+            .check_lines(&db_name, &lines)
+            .context(Authorization)?;
+    }
+
+    quotas
+        .check_write(&db_name, body.len(), &lines)
+        .context(QuotaError)?;
+
+    // a router stores nothing itself, leaving the writers of the
+    // shards of the lines to store, replicate and publish them
+    if router.is_router() {
+        debug!(
+            "Forwarding {} lines for database {} to writers",
+            lines.len(),
+            db_name
+        );
+        // the lines are always forwarded as line protocol, whatever
+        // format they were written in
+        let query = query_without_param(&query, "format");
+        router
+            .forward(&query, authorization, &lines)
+            .await
+            .context(RoutingError)?;
+        return Ok(None);
+    }
+
+    // a writer only stores the lines of its own shard
+    router.check_owned(&lines).context(RoutingError)?;
+
+    let db = tenant::bucket_db_or_create(storage.as_ref(), &write_info.org, &write_info.bucket)
+        .await
+        .context(TenantError)?;
+
+    // lines with names or tags the rules of the database do not allow
+    // are rejected, and the rest of the write is accepted
+    let rules = db.rules().await;
+    let total = lines.len();
+    let (lines, rejected) = rules.apply_line_rules(&lines);
+
+    // the lines are given the time they were received at before they are
+    // written, so that replicas and subscribers see the same timestamps
+    let lines = rules.receive_time.apply(&lines, received);
+
     debug!(
         "Inserting {} lines into database {} (org {} bucket {})",
         lines.len(),
@@ -244,15 +847,211 @@ async fn write<T: DatabaseStore>(
         write_info.bucket
     );
 
-    db.write_lines(&lines)
+    if !lines.is_empty() {
+        db.write_lines(&lines)
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(WritingPoints {
+                org: write_info.org.clone(),
+                bucket_name: write_info.bucket.clone(),
+            })?;
+
+        replicator.replicate(&db_name, &rules, &lines);
+        subscriptions.publish(&db_name, &lines);
+    }
+
+    ensure!(rejected.is_empty(), PartialWrite { total, rejected });
+
+    Ok(None)
+}
+
+/// Returns the query string `query` without the parameter `name`
+fn query_without_param(query: &str, name: &str) -> String {
+    query
+        .split('&')
+        .filter(|param| param.split('=').next() != Some(name))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Describes the first `MAX_DESCRIBED_REJECTED_LINES` of the lines
+/// rejected from a write
+fn describe_rejected_lines(rejected: &[RejectedLine]) -> String {
+    let mut description = rejected
+        .iter()
+        .take(MAX_DESCRIBED_REJECTED_LINES)
+        .map(|line| format!("line {}: {}", line.line_number, line.reason))
+        .collect::<Vec<_>>()
+        .join("; ");
+    if rejected.len() > MAX_DESCRIBED_REJECTED_LINES {
+        description.push_str(&format!(
+            "; and {} more",
+            rejected.len() - MAX_DESCRIBED_REJECTED_LINES
+        ));
+    }
+    description
+}
+
+/// Stores a write replicated from another server in the database
+/// `db_name`, creating it if needed
+#[tracing::instrument(level = "debug")]
+async fn replicated_write<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    db_name: &str,
+    storage: Arc<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    principal
+        .authorize(db_name, Permission::Write)
+        .context(Authorization)?;
+
+    let db = storage
+        .db_or_create(db_name)
         .await
         .map_err(|e| Box::new(e) as _)
-        .context(WritingPoints {
-            org: write_info.org.clone(),
-            bucket_name: write_info.bucket.clone(),
-        })?;
+        .context(StoringReplicatedWrite { name: db_name })?;
 
-    Ok(None)
+    let body = parse_body(req).await?;
+    let write = ReplicatedWrite {
+        data: body.to_vec(),
+    };
+
+    debug!("Storing replicated write {} in database {}", write, db_name);
+
+    db.store_replicated_write(&write)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(StoringReplicatedWrite { name: db_name })?;
+
+    Ok(body_response(None))
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response of the replication endpoint
+struct ReplicationResponse {
+    writer_id: u32,
+    peers: Vec<PeerStatus>,
+}
+
+/// Returns the state of replication to each peer of this server
+fn replication_status(replicator: &Replicator) -> Result<hyper::Response<Body>, ApplicationError> {
+    json_response(
+        StatusCode::OK,
+        &ReplicationResponse {
+            writer_id: replicator.writer_id(),
+            peers: replicator.status(),
+        },
+    )
+}
+
+/// Returns the backlog of partitions waiting to be compacted, and the
+/// counters of the compactions so far
+fn compaction_status(
+    compaction: &CompactionScheduler,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    json_response(StatusCode::OK, &compaction.metrics())
+}
+
+/// Returns the metrics of the server in the Prometheus text format
+fn metrics_route(metrics: &Metrics) -> Result<hyper::Response<Body>, ApplicationError> {
+    Ok(hyper::Response::builder()
+        .header(CONTENT_TYPE, "text/plain; version=0.0.4")
+        .body(metrics.render().into())
+        .expect("Should have been able to construct a response"))
+}
+
+#[derive(Debug, Deserialize)]
+/// Query string of the request to list the buckets of an org
+struct ListBucketsInfo {
+    org: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+/// A bucket, as sent to and returned from the /buckets endpoint
+struct BucketInfo {
+    #[serde(rename = "orgID")]
+    org: String,
+    name: String,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response of the /buckets endpoint listing buckets
+struct BucketsResponse {
+    buckets: Vec<BucketInfo>,
+}
+
+/// Lists the buckets of the org specified by the `org` query
+/// parameter that may be read by `principal`
+#[tracing::instrument(level = "debug")]
+async fn list_buckets<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    storage: Arc<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString)?;
+
+    let info: ListBucketsInfo = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    let buckets = tenant::org_buckets(storage.as_ref(), &info.org)
+        .await
+        .into_iter()
+        .filter(|name| {
+            let db_name = org_and_bucket_to_database(&info.org, name);
+            principal.is_authorized(&db_name, Permission::Read)
+        })
+        .map(|name| BucketInfo {
+            org: info.org.clone(),
+            name,
+        })
+        .collect();
+
+    let body = serde_json::to_string(&BucketsResponse { buckets })
+        .expect("Should have been able to serialize buckets");
+
+    Ok(hyper::Response::builder()
+        .header(CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .body(body.into())
+        .expect("Should have been able to construct a response"))
+}
+
+/// Creates the bucket described by the JSON body of the request, if
+/// it does not already exist
+#[tracing::instrument(level = "debug")]
+async fn create_bucket<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    storage: Arc<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let body = parse_body(req).await?;
+    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+
+    let bucket: BucketInfo =
+        serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+    let db_name = org_and_bucket_to_database(&bucket.org, &bucket.name);
+
+    principal
+        .authorize(&db_name, Permission::Write)
+        .context(Authorization)?;
+
+    tenant::bucket_db_or_create(storage.as_ref(), &bucket.org, &bucket.name)
+        .await
+        .context(TenantError)?;
+
+    info!(
+        "Created database {} for bucket {} in org {}",
+        db_name, bucket.name, bucket.org
+    );
+
+    let body = serde_json::to_string(&bucket).expect("Should have been able to serialize bucket");
+
+    Ok(hyper::Response::builder()
+        .status(StatusCode::CREATED)
+        .header(CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .body(body.into())
+        .expect("Should have been able to construct a response"))
 }
 
 #[derive(Deserialize, Debug)]
@@ -270,6 +1069,8 @@ struct ReadInfo {
 async fn read<T: DatabaseStore>(
     req: hyper::Request<Body>,
     storage: Arc<T>,
+    principal: &Principal,
+    quotas: &Quotas,
 ) -> Result<Option<Body>, ApplicationError> {
     let query = req.uri().query().context(ExpectedQueryString {})?;
 
@@ -279,6 +1080,11 @@ async fn read<T: DatabaseStore>(
 
     let db_name = org_and_bucket_to_database(&read_info.org, &read_info.bucket);
 
+    principal
+        .authorize(&db_name, Permission::Read)
+        .context(Authorization)?;
+    quotas.check_query(&db_name).context(QuotaError)?;
+
     let db = storage.db(&db_name).await.context(BucketNotFound {
         org: read_info.org.clone(),
         bucket: read_info.bucket.clone(),
@@ -294,117 +1100,4625 @@ async fn read<T: DatabaseStore>(
     Ok(Some(results.into_bytes().into()))
 }
 
-// Route to test that the server is alive
-#[tracing::instrument(level = "debug")]
-async fn ping(req: hyper::Request<Body>) -> Result<Option<Body>, ApplicationError> {
-    let response_body = "PONG";
-    Ok(Some(response_body.into()))
-}
-
-fn no_op(name: &str) -> Result<Option<Body>, ApplicationError> {
-    info!("NOOP: {}", name);
-    Ok(None)
+#[derive(Debug, Default, Deserialize)]
+/// Query string of the request to the /query endpoint
+struct QueryInfo {
+    format: Option<String>,
+    /// JSON object with the values of `$name` placeholders in the query
+    params: Option<String>,
 }
 
-pub async fn service<T: DatabaseStore>(
+/// Runs the SQL query in the body of the request against `db_name`,
+/// returning the results in the format requested by the `format`
+/// query parameter or `Accept` header. Results are streamed to the
+/// client as each batch is encoded.
+#[tracing::instrument(level = "debug")]
+async fn query<T: DatabaseStore>(
     req: hyper::Request<Body>,
+    db_name: &str,
     storage: Arc<T>,
-) -> http::Result<hyper::Response<Body>> {
-    let method = req.method().clone();
-    let uri = req.uri().clone();
+    principal: &Principal,
+    quotas: &Quotas,
+    system_tables: Arc<SystemTables>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    principal
+        .authorize(db_name, Permission::Read)
+        .context(Authorization)?;
+    quotas.check_query(db_name).context(QuotaError)?;
+    let labels = QueryLabels::from_headers(req.headers()).context(InvalidQueryLabels)?;
 
-    let response = match (req.method(), req.uri().path()) {
-        (&Method::POST, "/api/v2/write") => write(req, storage).await,
-        (&Method::POST, "/api/v2/buckets") => no_op("create bucket"),
-        (&Method::GET, "/ping") => ping(req).await,
-        (&Method::GET, "/api/v2/read") => read(req, storage).await,
-        _ => Err(ApplicationError::RouteNotFound {
-            method: method.clone(),
-            path: uri.to_string(),
-        }),
+    let query_info: QueryInfo = match req.uri().query() {
+        Some(query) => serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?,
+        None => QueryInfo::default(),
     };
 
-    let result = match response {
-        Ok(Some(body)) => hyper::Response::builder()
-            .body(body)
-            .expect("Should have been able to construct a response"),
-        Ok(None) => hyper::Response::builder()
-            .status(StatusCode::NO_CONTENT)
-            .body(Body::empty())
-            .expect("Should have been able to construct a response"),
-        Err(e) => {
-            error!(error = ?e, method = ?method, uri = ?uri, "Error while handing request");
-            let json = serde_json::json!({"error": e.to_string()}).to_string();
-            hyper::Response::builder()
-                .status(e.status_code())
-                .body(json.into())
-                .expect("Should have been able to construct a response")
-        }
-    };
-    info!(method = ?method, uri = ?uri, status = ?result.status(), "Handled request");
-    Ok(result)
-}
+    let output_format = result_format(&req, query_info.format)?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    let db = storage
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { name: db_name })?;
 
-    use http::header;
-    use reqwest::{Client, Response};
+    let body = parse_body(req).await?;
+    let sql = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
 
-    use hyper::service::{make_service_fn, service_fn};
-    use hyper::Server;
+    let mut warnings = Warnings::new();
+    let sql = match query_info.params {
+        Some(params) => {
+            let params = query_params::parse_params(&params).context(BindingParams)?;
+            add_unused_param_warnings(&mut warnings, sql, &params);
+            query_params::bind_sql(sql, &params).context(BindingParams)?
+        }
+        None => sql.to_string(),
+    };
 
-    use storage::{test::TestDatabaseStore, DatabaseStore};
+    debug!(labels = %labels, "Running SQL query on database {}: {}", db_name, sql);
 
-    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
-    type Result<T, E = Error> = std::result::Result<T, E>;
+    let start_time = Utc::now();
+    // the labels are recorded on the span of the query, so they are
+    // exported with its trace
+    let span = info_span!("sql_query", db_name, labels = %labels);
+    let results = async {
+        if cross_database::references_other_databases(&sql) {
+            // The databases of different orgs are isolated from each
+            // other, even when the token may read both
+            let same_tenant = tenant::same_tenant_databases(storage.as_ref(), db_name).await;
+            let batches =
+                cross_database::query(storage.as_ref(), db_name, &sql, &system_tables, |other| {
+                    same_tenant.contains(other)
+                        && principal.authorize(other, Permission::Read).is_ok()
+                })
+                .await
+                .context(CrossDatabaseQuery)?;
+            let schema = batches
+                .first()
+                .map(|batch| batch.schema())
+                .unwrap_or_else(|| Arc::new(arrow::datatypes::Schema::empty()));
+            Ok(storage::exec::batches_stream(schema, batches))
+        } else {
+            db.query_stream(&sql)
+                .await
+                .map_err(|e| Box::new(e) as _)
+                .context(QueryError {})
+        }
+    }
+    .instrument(span)
+    .await;
+
+    // the query is recorded once its results have all been produced,
+    // or the client has gone away
+    let mut recorder = QueryRecorder::new(
+        system_tables,
+        QueryRecord {
+            db_name: db_name.to_string(),
+            query_text: sql.clone(),
+            start_time: start_time.timestamp_nanos(),
+            duration_nanos: 0,
+            rows: Some(0),
+            error: None,
+            labels,
+        },
+    );
+    let results = match results {
+        Ok(results) => results,
+        Err(e) => {
+            recorder.error(e.to_string());
+            return Err(e);
+        }
+    };
+
+    let schema = results.schema();
+    let results = RecordedResults { results, recorder };
+    let encoded = format::encode_stream(output_format, schema, results);
+
+    let mut response = hyper::Response::builder()
+        .header(CONTENT_TYPE, output_format.content_type())
+        .body(Body::wrap_stream(encoded))
+        .expect("Should have been able to construct a response");
+    warnings.add_to_headers(response.headers_mut());
+    Ok(response)
+}
+
+/// Records a query in the system tables when it is finished or
+/// dropped, with the rows of its results it has seen and how long it
+/// ran for
+#[derive(Debug)]
+struct QueryRecorder {
+    system_tables: Arc<SystemTables>,
+    record: Option<QueryRecord>,
+}
+
+impl QueryRecorder {
+    fn new(system_tables: Arc<SystemTables>, record: QueryRecord) -> Self {
+        Self {
+            system_tables,
+            record: Some(record),
+        }
+    }
+
+    /// Counts the rows of `batch`, or records the error producing it
+    fn batch(&mut self, batch: &arrow::error::Result<arrow::record_batch::RecordBatch>) {
+        match batch {
+            Ok(batch) => {
+                if let Some(rows) = self.record.as_mut().and_then(|record| record.rows.as_mut()) {
+                    *rows += batch.num_rows() as u64;
+                }
+            }
+            Err(e) => self.error(e.to_string()),
+        }
+    }
+
+    fn error(&mut self, error: String) {
+        if let Some(record) = self.record.as_mut() {
+            record.rows = None;
+            record.error = Some(error);
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(mut record) = self.record.take() {
+            record.duration_nanos = Utc::now().timestamp_nanos() - record.start_time;
+            self.system_tables.record_query(record);
+        }
+    }
+}
+
+impl Drop for QueryRecorder {
+    fn drop(&mut self) {
+        self.finish()
+    }
+}
+
+/// The results of a query, which are recorded by `recorder` as they
+/// are produced
+struct RecordedResults {
+    results: SendableRecordBatchStream,
+    recorder: QueryRecorder,
+}
+
+impl futures::Stream for RecordedResults {
+    type Item = arrow::error::Result<arrow::record_batch::RecordBatch>;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let batch = futures::ready!(this.results.poll_next_unpin(cx));
+        match &batch {
+            Some(batch) => this.recorder.batch(batch),
+            None => this.recorder.finish(),
+        }
+        Poll::Ready(batch)
+    }
+}
+
+/// Adds a warning to `warnings` for each of `params` that `query`
+/// doesn't refer to
+fn add_unused_param_warnings(warnings: &mut Warnings, query: &str, params: &query_params::Params) {
+    for name in query_params::unused_params(query, params) {
+        warnings.add(
+            WarningCode::UnusedParameter,
+            format!("parameter ${} is not used by the query", name),
+        );
+    }
+}
+
+/// Returns the format in which to return query results: the `format`
+/// query parameter if present, otherwise the first supported type in
+/// the `Accept` header, defaulting to the Arrow IPC streaming format
+fn result_format(
+    req: &hyper::Request<Body>,
+    format_param: Option<String>,
+) -> Result<format::ResultFormat, ApplicationError> {
+    if let Some(name) = format_param {
+        return format::ResultFormat::from_name(&name)
+            .context(UnknownResultFormat { format: name });
+    }
+
+    // clippy says the const needs to be assigned to a local variable:
+    // error: a `const` item with interior mutability should not be borrowed
+    let header_name = ACCEPT;
+    match req.headers().get(&header_name) {
+        None => Ok(format::ResultFormat::default()),
+        Some(accept) => {
+            let accept = accept.to_str().context(ReadingHeaderAsUtf8 {
+                header_name: header_name.as_str(),
+            })?;
+            format::ResultFormat::from_accept(accept).context(NotAcceptable { accept })
+        }
+    }
+}
+
+/// Runs the InfluxQL statements in the `q` parameter against the `db`
+/// database, returning the results in the JSON format of the
+/// InfluxDB 1.x `/query` API.
+///
+/// As in InfluxDB 1.x, errors running individual statements are
+/// reported in the results of that statement rather than failing the
+/// whole request.
+#[tracing::instrument(level = "debug")]
+async fn v1_query<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    storage: Arc<T>,
+    principal: &Principal,
+    quotas: &Quotas,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query_string = req.uri().query().unwrap_or_default().to_string();
+    let mut params: v1::QueryParams =
+        serde_urlencoded::from_str(&query_string).context(InvalidQueryString {
+            query_string: &query_string,
+        })?;
+
+    // POST requests may also send parameters as a form encoded body
+    if req.method() == Method::POST {
+        let body = parse_body(req).await?;
+        let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+        let form: v1::QueryParams =
+            serde_urlencoded::from_str(body).context(InvalidQueryString { query_string: body })?;
+        params = params.or(form);
+    }
+
+    let epoch = v1::Epoch::parse(params.epoch.as_deref()).context(V1Query)?;
+    let db_name = params.db.context(MissingQueryParameter { name: "db" })?;
+    principal
+        .authorize(&db_name, Permission::Read)
+        .context(Authorization)?;
+    quotas.check_query(&db_name).context(QuotaError)?;
+    let q = params.q.context(MissingQueryParameter { name: "q" })?;
+
+    let mut statements = influxql::parse_query(&q).context(InfluxQL)?;
+
+    let params = match params.params {
+        Some(params) => query_params::parse_params(&params).context(BindingParams)?,
+        None => query_params::Params::new(),
+    };
+    let mut warnings = Warnings::new();
+    add_unused_param_warnings(&mut warnings, &q, &params);
+    let params = query_params::influxql_params(&params).context(BindingParams)?;
+    for statement in &mut statements {
+        statement.bind(&params).context(InfluxQL)?;
+    }
+
+    let db = storage.db(&db_name).await;
+    let now = Utc::now().timestamp_nanos();
+
+    let mut results = Vec::with_capacity(statements.len());
+    for (statement_id, statement) in statements.iter().enumerate() {
+        let result = match &db {
+            Some(db) => match run_influxql_statement(db.as_ref(), statement, now, epoch).await {
+                Ok(series) => v1::StatementResult::success(statement_id, series),
+                Err(e) => v1::StatementResult::error(statement_id, e),
+            },
+            None => v1::StatementResult::error(
+                statement_id,
+                ApplicationError::DatabaseNotFound {
+                    name: db_name.clone(),
+                },
+            ),
+        };
+        let result = if statement_id == 0 {
+            result.with_warnings(&warnings)
+        } else {
+            result
+        };
+        results.push(result);
+    }
+
+    let body = serde_json::to_string(&v1::QueryResponse { results })
+        .expect("Should have been able to serialize query results");
+
+    let mut response = hyper::Response::builder()
+        .header(CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .body(body.into())
+        .expect("Should have been able to construct a response");
+    warnings.add_to_headers(response.headers_mut());
+    Ok(response)
+}
+
+/// Translates `statement` to SQL, runs it against `db`, and converts
+/// the results to 1.x series, filled and limited as the statement says
+async fn run_influxql_statement<D: Database>(
+    db: &D,
+    statement: &SelectStatement,
+    now: i64,
+    epoch: v1::Epoch,
+) -> Result<Vec<v1::Series>, ApplicationError> {
+    let translation = influxql::translate(statement, now).context(InfluxQL)?;
+
+    debug!(
+        "Running SQL translation of InfluxQL statement: {}",
+        translation.sql
+    );
+
+    let results = db
+        .query(&translation.sql)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(QueryError {})?;
+
+    // `to_sql` only accepts statements with a single named measurement
+    let measurement = match statement.from.first() {
+        Some(Measurement::Name(name)) => name.as_str(),
+        _ => "",
+    };
+    let tags = statement.tag_dimensions().collect::<Vec<_>>();
+
+    // the series are filled in nanoseconds, and their times formatted
+    // as `epoch` once they have been
+    let mut series = v1::batches_to_series(measurement, &tags, &results, v1::Epoch::Integer(1))
+        .context(V1Query)?;
+    v1::apply_series_options(&mut series, &translation.series).context(V1Query)?;
+    v1::format_times(&mut series, epoch);
+    Ok(series)
+}
+
+/// Dispatches requests of the form `/iox/api/v1/databases/{name}/{route}`
+async fn database_route<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    path: &str,
+    state: &ServerState<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let storage = Arc::clone(&state.storage);
+    let ServerState {
+        quotas,
+        jobs,
+        system_tables,
+        access,
+        ..
+    } = state;
+    let rest = path.strip_prefix(DATABASES_PATH).unwrap_or_default();
+    if !rest.is_empty() && !rest.contains('/') {
+        let db_name = rest.to_string();
+        return database_config_route(req, &db_name, storage, principal).await;
+    }
+
+    let route = DATABASE_ROUTES.iter().find_map(|route| {
+        let db_name = rest.strip_suffix(route)?.strip_suffix('/')?;
+        Some((db_name, *route))
+    });
+
+    match (req.method(), route) {
+        (&Method::POST, Some((db_name, "query"))) if !db_name.is_empty() => {
+            access.check_query().context(AccessError)?;
+            let db_name = db_name.to_string();
+            let system_tables = Arc::clone(system_tables);
+            query(req, &db_name, storage, principal, quotas, system_tables).await
+        }
+        (_, Some((db_name, "quota"))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            quota_route(req, &db_name, principal, quotas).await
+        }
+        (&Method::GET, Some((db_name, "partitions"))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            list_partitions(req, &db_name, storage, principal).await
+        }
+        (&Method::GET, Some((db_name, "partitions/stats"))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            list_partition_statistics(req, &db_name, storage, principal).await
+        }
+        (&Method::GET, Some((db_name, "chunks"))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            list_chunks(req, &db_name, storage, principal).await
+        }
+        (&Method::GET, Some((db_name, "chunk"))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            get_chunk(req, &db_name, storage, principal).await
+        }
+        (&Method::POST, Some((db_name, "partitions/drop"))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            remove_partition(req, &db_name, storage, principal, false).await
+        }
+        (&Method::POST, Some((db_name, "partitions/truncate"))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            remove_partition(req, &db_name, storage, principal, true).await
+        }
+        (&Method::POST, Some((db_name, "partitions/compact"))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            compact_partition(req, &db_name, storage, principal, jobs).await
+        }
+        (&Method::POST, Some((db_name, "chunk/close"))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            chunk_operation(
+                req,
+                &db_name,
+                storage,
+                principal,
+                jobs,
+                ChunkOperation::Close,
+            )
+            .await
+        }
+        (&Method::POST, Some((db_name, "chunk/persist"))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            chunk_operation(
+                req,
+                &db_name,
+                storage,
+                principal,
+                jobs,
+                ChunkOperation::Persist,
+            )
+            .await
+        }
+        (&Method::POST, Some((db_name, REPLICATED_WRITE_PATH))) if !db_name.is_empty() => {
+            let db_name = db_name.to_string();
+            replicated_write(req, &db_name, storage, principal).await
+        }
+        _ => RouteNotFound {
+            method: req.method().clone(),
+            path,
+        }
+        .fail(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// A database and the rules currently in effect for it, as returned
+/// from the database endpoints
+struct DatabaseInfo {
+    name: String,
+    rules: DatabaseRules,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response listing databases
+struct DatabasesResponse {
+    databases: Vec<DatabaseInfo>,
+}
+
+/// Dispatches requests of the form `/iox/api/v1/databases/{name}`.
+/// The rules of a database may be read with read permission on it,
+/// but databases may only be created, updated or deleted with the
+/// admin token.
+async fn database_config_route<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    db_name: &str,
+    storage: Arc<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    match req.method() {
+        &Method::GET => {
+            principal
+                .authorize(db_name, Permission::Read)
+                .context(Authorization)?;
+
+            let db = storage
+                .db(db_name)
+                .await
+                .context(DatabaseNotFound { name: db_name })?;
+
+            json_response(
+                StatusCode::OK,
+                &DatabaseInfo {
+                    name: db_name.to_string(),
+                    rules: db.rules().await,
+                },
+            )
+        }
+        &Method::POST => {
+            principal.authorize_admin().context(Authorization)?;
+            let rules = parse_rules(req).await?;
+
+            if storage.db(db_name).await.is_some() {
+                return DatabaseAlreadyExists { name: db_name }.fail();
+            }
+
+            storage
+                .create_db(db_name, rules.clone())
+                .await
+                .map_err(|e| Box::new(e) as _)
+                .context(ConfiguringDatabase { name: db_name })?;
+            info!("Created database {} with rules {:?}", db_name, rules);
+
+            json_response(
+                StatusCode::CREATED,
+                &DatabaseInfo {
+                    name: db_name.to_string(),
+                    rules,
+                },
+            )
+        }
+        &Method::PUT => {
+            principal.authorize_admin().context(Authorization)?;
+            let rules = parse_rules(req).await?;
+
+            let db = storage
+                .db(db_name)
+                .await
+                .context(DatabaseNotFound { name: db_name })?;
+
+            db.set_rules(rules.clone())
+                .await
+                .map_err(|e| Box::new(e) as _)
+                .context(ConfiguringDatabase { name: db_name })?;
+            info!("Updated rules of database {} to {:?}", db_name, rules);
+
+            json_response(
+                StatusCode::OK,
+                &DatabaseInfo {
+                    name: db_name.to_string(),
+                    rules,
+                },
+            )
+        }
+        &Method::DELETE => {
+            principal.authorize_admin().context(Authorization)?;
+
+            if storage.db(db_name).await.is_none() {
+                return DatabaseNotFound { name: db_name }.fail();
+            }
+
+            storage
+                .delete_db(db_name)
+                .await
+                .map_err(|e| Box::new(e) as _)
+                .context(ConfiguringDatabase { name: db_name })?;
+            info!("Deleted database {}", db_name);
+
+            Ok(body_response(None))
+        }
+        method => RouteNotFound {
+            method: method.clone(),
+            path: req.uri().path(),
+        }
+        .fail(),
+    }
+}
+
+/// Parses the JSON body of the request as database rules
+async fn parse_rules(req: hyper::Request<Body>) -> Result<DatabaseRules, ApplicationError> {
+    let body = parse_body(req).await?;
+    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+
+    let rules: DatabaseRules =
+        serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+    rules.validate().context(InvalidDatabaseRules)?;
+
+    Ok(rules)
+}
+
+/// Lists the databases that may be read by `principal`, along with
+/// the rules currently in effect for each
+async fn list_databases<T: DatabaseStore>(
+    storage: Arc<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let mut databases = vec![];
+    for name in storage.db_names_sorted().await {
+        if !principal.is_authorized(&name, Permission::Read) {
+            continue;
+        }
+
+        if let Some(db) = storage.db(&name).await {
+            databases.push(DatabaseInfo {
+                rules: db.rules().await,
+                name,
+            });
+        }
+    }
+
+    json_response(StatusCode::OK, &DatabasesResponse { databases })
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response listing the partitions of a database
+struct PartitionsResponse {
+    partitions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+/// Query parameters of the request listing partitions
+struct ListPartitionsParams {
+    /// Only list the partitions with data in this table
+    table: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response with the statistics of the partitions of a
+/// database
+struct PartitionStatisticsResponse {
+    partitions: Vec<PartitionStatistics>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+/// Query parameters of the requests to drop or truncate a partition
+struct RemovePartitionParams {
+    key: String,
+    #[serde(default)]
+    dry_run: bool,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response to dropping or truncating a partition,
+/// describing the data that was (or for a dry run, would be) removed
+struct RemovedPartition {
+    dry_run: bool,
+    removed: PartitionSummary,
+}
+
+/// Lists the keys of the partitions of a database, optionally only
+/// those with data in the `table` query parameter, a page at a time.
+/// Requires read permission on the database.
+async fn list_partitions<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    db_name: &str,
+    storage: Arc<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    principal
+        .authorize(db_name, Permission::Read)
+        .context(Authorization)?;
+
+    let params: ListPartitionsParams = optional_query_params(&req)?;
+    let page_params: PageParams = optional_query_params(&req)?;
+
+    let db = storage
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { name: db_name })?;
+
+    let mut partitions = db.partition_keys().await;
+    if let Some(table) = &params.table {
+        let with_table: BTreeSet<_> = db
+            .chunks()
+            .await
+            .into_iter()
+            .filter(|chunk| chunk.table_names.contains(table))
+            .map(|chunk| chunk.partition_key)
+            .collect();
+        partitions.retain(|key| with_table.contains(key));
+    }
+
+    let page = paginate(partitions, &page_params, String::clone).context(Pagination)?;
+
+    json_response(
+        StatusCode::OK,
+        &PartitionsResponse {
+            partitions: page.items,
+            next: page.next,
+        },
+    )
+}
+
+/// Returns the statistics of the partitions of a database, aggregated
+/// from its chunks, a page at a time. Requires read permission on the
+/// database.
+async fn list_partition_statistics<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    db_name: &str,
+    storage: Arc<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    principal
+        .authorize(db_name, Permission::Read)
+        .context(Authorization)?;
+
+    let page_params: PageParams = optional_query_params(&req)?;
+
+    let db = storage
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { name: db_name })?;
+
+    let partitions = PartitionStatistics::from_chunks(&db.chunks().await);
+    let page = paginate(partitions, &page_params, |stats| stats.key.clone()).context(Pagination)?;
+
+    json_response(
+        StatusCode::OK,
+        &PartitionStatisticsResponse {
+            partitions: page.items,
+            next: page.next,
+        },
+    )
+}
+
+/// Drops, or if `truncate` is true truncates, the partition of a
+/// database named by the `key` query parameter, which requires the
+/// admin token. With `dry_run=true` nothing is removed, and the
+/// response describes what would have been.
+async fn remove_partition<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    db_name: &str,
+    storage: Arc<T>,
+    principal: &Principal,
+    truncate: bool,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    principal.authorize_admin().context(Authorization)?;
+
+    let query = req.uri().query().context(ExpectedQueryString)?;
+    let params: RemovePartitionParams =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?;
+
+    let db = storage
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { name: db_name })?;
+
+    if !db.partition_keys().await.contains(&params.key) {
+        return PartitionNotFound {
+            name: db_name,
+            key: params.key,
+        }
+        .fail();
+    }
+
+    let removed = if truncate {
+        db.truncate_partition(&params.key, params.dry_run).await
+    } else {
+        db.drop_partition(&params.key, params.dry_run).await
+    }
+    .map_err(|e| Box::new(e) as _)
+    .context(ConfiguringDatabase { name: db_name })?;
+
+    if !params.dry_run {
+        info!(
+            "{} partition {} of database {}",
+            if truncate { "Truncated" } else { "Dropped" },
+            params.key,
+            db_name
+        );
+    }
+
+    json_response(
+        StatusCode::OK,
+        &RemovedPartition {
+            dry_run: params.dry_run,
+            removed,
+        },
+    )
+}
+
+#[derive(Debug, Default, Deserialize)]
+/// Query parameters of the request listing chunks
+struct ListChunksParams {
+    partition_key: Option<String>,
+    /// Only list the chunks with data in this table
+    table: Option<String>,
+    /// Only list the chunks in this lifecycle state
+    state: Option<ChunkState>,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response listing the chunks of a database
+struct ChunksResponse {
+    chunks: Vec<ChunkSummary>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+/// Query parameters of the request for a single chunk
+struct GetChunkParams {
+    partition_key: String,
+    id: u32,
+}
+
+/// Lists the chunks of a database in order of partition key and id, a
+/// page at a time, which requires read permission on the database.
+///
+/// The chunks listed can be restricted to those of the partition named
+/// by the `partition_key` query parameter, with data in the `table`,
+/// in the lifecycle `state`, or with rows between the times `start`
+/// and `end`.
+async fn list_chunks<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    db_name: &str,
+    storage: Arc<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    principal
+        .authorize(db_name, Permission::Read)
+        .context(Authorization)?;
+
+    let params: ListChunksParams = optional_query_params(&req)?;
+    let time_range: TimeRangeParams = optional_query_params(&req)?;
+    time_range.validate().context(Pagination)?;
+    let page_params: PageParams = optional_query_params(&req)?;
+
+    let db = storage
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { name: db_name })?;
+
+    let chunks = db
+        .chunks()
+        .await
+        .into_iter()
+        .filter(|chunk| {
+            params
+                .partition_key
+                .as_ref()
+                .map_or(true, |key| &chunk.partition_key == key)
+                && params
+                    .table
+                    .as_ref()
+                    .map_or(true, |table| chunk.table_names.contains(table))
+                && params.state.map_or(true, |state| chunk.state == state)
+                && (time_range.is_unbounded()
+                    || match (chunk.min_time, chunk.max_time) {
+                        (Some(min), Some(max)) => time_range.overlaps(min, max),
+                        _ => false,
+                    })
+        })
+        .collect();
+
+    let page = paginate(chunks, &page_params, |chunk| {
+        (chunk.partition_key.clone(), chunk.id)
+    })
+    .context(Pagination)?;
+
+    json_response(
+        StatusCode::OK,
+        &ChunksResponse {
+            chunks: page.items,
+            next: page.next,
+        },
+    )
+}
+
+/// Returns the chunk of a database identified by the `partition_key`
+/// and `id` query parameters, which requires read permission on the
+/// database
+async fn get_chunk<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    db_name: &str,
+    storage: Arc<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    principal
+        .authorize(db_name, Permission::Read)
+        .context(Authorization)?;
+
+    let query = req.uri().query().context(ExpectedQueryString)?;
+    let params: GetChunkParams = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    let db = storage
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { name: db_name })?;
+
+    let chunk = db
+        .chunks()
+        .await
+        .into_iter()
+        .find(|chunk| chunk.partition_key == params.partition_key && chunk.id == params.id)
+        .context(ChunkNotFound {
+            name: db_name,
+            partition_key: &params.partition_key,
+            id: params.id,
+        })?;
+
+    json_response(StatusCode::OK, &chunk)
+}
+
+#[derive(Debug, Deserialize)]
+/// Query parameters of the request compacting a partition
+struct CompactPartitionParams {
+    key: String,
+}
+
+/// Merges all chunks of the partition of a database named by the
+/// `key` query parameter into a single closed chunk, which requires
+/// the admin token. The compaction is tracked as a job, and the
+/// response is the new chunk.
+async fn compact_partition<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    db_name: &str,
+    storage: Arc<T>,
+    principal: &Principal,
+    jobs: &Arc<Jobs>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    principal.authorize_admin().context(Authorization)?;
+
+    let query = req.uri().query().context(ExpectedQueryString)?;
+    let params: CompactPartitionParams =
+        serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?;
+
+    let db = storage
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { name: db_name })?;
+
+    if !db
+        .chunks()
+        .await
+        .iter()
+        .any(|c| c.partition_key == params.key)
+    {
+        return PartitionNotFound {
+            name: db_name,
+            key: params.key,
+        }
+        .fail();
+    }
+
+    let description = JobDescription::CompactPartition {
+        db_name: db_name.to_string(),
+        partition_key: params.key.clone(),
+    };
+    let (db, key) = (&db, &params.key);
+    let chunk = jobs
+        .run(description, |tracker| async move {
+            let chunk = db.compact_partition(key, None).await?;
+            tracker.add_metric("rows", chunk.row_count);
+            tracker.add_metric("bytes", chunk.size);
+            Ok::<_, <T::Database as Database>::Error>(chunk)
+        })
+        .await
+        .context(OperationCancelled { name: db_name })?
+        .map_err(|e| Box::new(e) as _)
+        .context(ConfiguringDatabase { name: db_name })?;
+
+    info!(
+        "Compacted partition {} of database {} into chunk {}",
+        params.key, db_name, chunk.id
+    );
+
+    json_response(StatusCode::OK, &chunk)
+}
+
+#[derive(Debug, Clone, Copy)]
+/// An operation which moves a chunk on to the next stage of its
+/// lifecycle
+enum ChunkOperation {
+    /// Close an open chunk, so it no longer accepts writes
+    Close,
+    /// Write a closed chunk to parquet files
+    Persist,
+}
+
+impl ChunkOperation {
+    /// The state a chunk must be in for the operation to apply
+    fn expected_state(self) -> ChunkState {
+        match self {
+            Self::Close => ChunkState::Open,
+            Self::Persist => ChunkState::Closed,
+        }
+    }
+}
+
+/// Applies `operation` to the chunk of a database identified by the
+/// `partition_key` and `id` query parameters, which requires the admin
+/// token. Persisting is tracked as a job. Responds with the chunk as it
+/// is after the operation.
+async fn chunk_operation<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    db_name: &str,
+    storage: Arc<T>,
+    principal: &Principal,
+    jobs: &Arc<Jobs>,
+    operation: ChunkOperation,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    principal.authorize_admin().context(Authorization)?;
+
+    let query = req.uri().query().context(ExpectedQueryString)?;
+    let params: GetChunkParams = serde_urlencoded::from_str(query).context(InvalidQueryString {
+        query_string: query,
+    })?;
+
+    let db = storage
+        .db(db_name)
+        .await
+        .context(DatabaseNotFound { name: db_name })?;
+
+    let chunk = db
+        .chunks()
+        .await
+        .into_iter()
+        .find(|chunk| chunk.partition_key == params.partition_key && chunk.id == params.id)
+        .context(ChunkNotFound {
+            name: db_name,
+            partition_key: &params.partition_key,
+            id: params.id,
+        })?;
+
+    let expected = operation.expected_state();
+    if chunk.state != expected {
+        return UnexpectedChunkState {
+            name: db_name,
+            partition_key: params.partition_key,
+            id: params.id,
+            state: chunk.state,
+            expected,
+        }
+        .fail();
+    }
+
+    let chunk = match operation {
+        ChunkOperation::Close => db.close_chunk(&params.partition_key, params.id).await,
+        ChunkOperation::Persist => {
+            let description = JobDescription::PersistChunk {
+                db_name: db_name.to_string(),
+                partition_key: params.partition_key.clone(),
+                chunk_id: params.id,
+            };
+            let (db, params) = (&db, &params);
+            jobs.run(description, |tracker| async move {
+                let chunk = db.persist_chunk(&params.partition_key, params.id).await?;
+                tracker.add_metric("rows", chunk.row_count);
+                tracker.add_metric("bytes", chunk.size);
+                Ok::<_, <T::Database as Database>::Error>(chunk)
+            })
+            .await
+            .context(OperationCancelled { name: db_name })?
+        }
+    }
+    .map_err(|e| Box::new(e) as _)
+    .context(ConfiguringDatabase { name: db_name })?;
+
+    info!(
+        "{:?} chunk {} of partition {} of database {}",
+        operation, params.id, params.partition_key, db_name
+    );
+
+    json_response(StatusCode::OK, &chunk)
+}
+
+#[derive(Debug, Serialize)]
+/// The quota of a database and its usage, as returned from the quota
+/// endpoints
+struct QuotaInfo {
+    database: String,
+    quota: Quota,
+    usage: Usage,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response listing quotas
+struct QuotasResponse {
+    quotas: Vec<QuotaInfo>,
+}
+
+/// Dispatches requests of the form `/iox/api/v1/databases/{name}/quota`.
+/// The quota may be read with read permission on the database, but
+/// only changed with the admin token.
+async fn quota_route(
+    req: hyper::Request<Body>,
+    db_name: &str,
+    principal: &Principal,
+    quotas: &Quotas,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    match req.method() {
+        &Method::GET => {
+            principal
+                .authorize(db_name, Permission::Read)
+                .context(Authorization)?;
+
+            let (quota, usage) = quotas.get(db_name).context(QuotaError)?;
+            json_response(
+                StatusCode::OK,
+                &QuotaInfo {
+                    database: db_name.to_string(),
+                    quota,
+                    usage,
+                },
+            )
+        }
+        &Method::PUT => {
+            principal.authorize_admin().context(Authorization)?;
+
+            let body = parse_body(req).await?;
+            let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+            let quota: Quota =
+                serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+            quotas.set(db_name, quota);
+            info!("Set quota of database {} to {:?}", db_name, quota);
+
+            json_response(
+                StatusCode::OK,
+                &QuotaInfo {
+                    database: db_name.to_string(),
+                    quota,
+                    usage: Usage::default(),
+                },
+            )
+        }
+        &Method::DELETE => {
+            principal.authorize_admin().context(Authorization)?;
+
+            quotas.remove(db_name).context(QuotaError)?;
+            info!("Removed quota of database {}", db_name);
+
+            Ok(body_response(None))
+        }
+        method => RouteNotFound {
+            method: method.clone(),
+            path: req.uri().path(),
+        }
+        .fail(),
+    }
+}
+
+/// Lists the quotas of all databases, which requires the admin token
+fn list_quotas(quotas: &Quotas) -> Result<hyper::Response<Body>, ApplicationError> {
+    let quotas = quotas
+        .all()
+        .into_iter()
+        .map(|(database, quota, usage)| QuotaInfo {
+            database,
+            quota,
+            usage,
+        })
+        .collect();
+
+    json_response(StatusCode::OK, &QuotasResponse { quotas })
+}
+
+#[derive(Debug, Deserialize)]
+/// Body of the request to create a token
+struct CreateTokenInfo {
+    #[serde(default)]
+    description: String,
+    scopes: Vec<Scope>,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response to creating a token, the only time the value
+/// of the token is returned
+struct CreatedToken {
+    #[serde(flatten)]
+    token: auth::Token,
+    token_value: String,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response listing tokens
+struct TokensResponse {
+    tokens: Vec<auth::Token>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+/// Query parameters of the request listing tokens
+struct ListTokensParams {
+    /// Only list the tokens with a scope on this database, including
+    /// those with a scope on all databases
+    database: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response listing jobs
+struct JobsResponse {
+    jobs: Vec<JobInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+/// Query parameters of the request listing jobs, which can be
+/// restricted to those with a status and to those started between the
+/// times `start` and `end`
+struct ListJobsParams {
+    status: Option<JobStatus>,
+}
+
+/// Dispatches requests to list jobs (`GET /iox/api/v1/jobs`), get one
+/// (`GET /iox/api/v1/jobs/{id}`) and cancel one (`POST
+/// /iox/api/v1/jobs/{id}/cancel`), which may only be made with the
+/// admin token
+fn jobs_route(
+    req: hyper::Request<Body>,
+    path: &str,
+    jobs: &Jobs,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let rest = path
+        .strip_prefix(JOBS_PATH)
+        .and_then(|rest| rest.strip_prefix('/'));
+    let (id, cancel) = match rest {
+        Some(rest) => match rest.strip_suffix("/cancel") {
+            Some(id) => (Some(id), true),
+            None => (Some(rest), false),
+        },
+        None => (None, false),
+    };
+    let id = match id {
+        Some(id) => Some(id.parse().context(InvalidJobId { id })?),
+        None => None,
+    };
+
+    match (req.method(), id, cancel) {
+        (&Method::GET, None, false) if path == JOBS_PATH => {
+            let params: ListJobsParams = optional_query_params(&req)?;
+            let time_range: TimeRangeParams = optional_query_params(&req)?;
+            time_range.validate().context(Pagination)?;
+            let page_params: PageParams = optional_query_params(&req)?;
+
+            let jobs = jobs
+                .list()
+                .into_iter()
+                .filter(|job| {
+                    params.status.map_or(true, |status| job.status == status)
+                        && time_range.contains(job.start_time)
+                })
+                .collect();
+            let page = paginate(jobs, &page_params, |job| job.id).context(Pagination)?;
+
+            json_response(
+                StatusCode::OK,
+                &JobsResponse {
+                    jobs: page.items,
+                    next: page.next,
+                },
+            )
+        }
+        (&Method::GET, Some(id), false) => {
+            json_response(StatusCode::OK, &jobs.get(id).context(JobError)?)
+        }
+        (&Method::POST, Some(id), true) => {
+            let job = jobs.cancel(id).context(JobError)?;
+            info!("Cancelled job {}", id);
+
+            json_response(StatusCode::OK, &job)
+        }
+        _ => RouteNotFound {
+            method: req.method().clone(),
+            path,
+        }
+        .fail(),
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response of the shard map endpoint
+struct ShardMapResponse {
+    mode: Mode,
+    /// The shard this server owns, if it is a writer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    shard_id: Option<u32>,
+    #[serde(flatten)]
+    shard_map: ShardMap,
+}
+
+#[derive(Debug, Deserialize)]
+/// Body of the request to replace the shards of a router or writer
+struct SetShardsRequest {
+    shards: Vec<Shard>,
+    /// If given, the shards are only replaced if the shard map is at
+    /// this version
+    expected_version: Option<u64>,
+}
+
+/// Dispatches requests to read (`GET`) or replace (`PUT`) the shards
+/// a router forwards writes to, or a writer checks the lines it
+/// accepts against, which may only be made with the admin token
+async fn shard_map_route(
+    req: hyper::Request<Body>,
+    router: &Router,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    match req.method() {
+        &Method::GET => json_response(
+            StatusCode::OK,
+            &ShardMapResponse {
+                mode: router.mode(),
+                shard_id: router.shard_id(),
+                shard_map: router.shard_map(),
+            },
+        ),
+        &Method::PUT => {
+            let body = parse_body(req).await?;
+            let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+            let request: SetShardsRequest =
+                serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+            let shard_map = router
+                .set_shards(request.shards, request.expected_version)
+                .context(RoutingError)?;
+            info!("Set shard map to version {}", shard_map.version);
+
+            json_response(
+                StatusCode::OK,
+                &ShardMapResponse {
+                    mode: router.mode(),
+                    shard_id: router.shard_id(),
+                    shard_map,
+                },
+            )
+        }
+        method => RouteNotFound {
+            method: method.clone(),
+            path: req.uri().path(),
+        }
+        .fail(),
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+/// Query parameters of the request to apply a spec
+struct ApplySpecParams {
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Reconciles the databases and shards of the server to the spec in
+/// the body of the request, which requires the admin token. With
+/// `dry_run=true` nothing is changed, and the response describes the
+/// changes that would have been made.
+async fn apply_spec<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    storage: Arc<T>,
+    router: &Router,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let params: ApplySpecParams = optional_query_params(&req)?;
+    let body = parse_body(req).await?;
+    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+    let spec: Spec =
+        serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+    let changes = provision::apply(&*storage, router, spec, params.dry_run)
+        .await
+        .context(ProvisionError)?;
+
+    json_response(StatusCode::OK, &changes)
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response of the log filter endpoint
+struct LogFilterResponse {
+    /// The directives in effect
+    directives: String,
+    /// The directives the server started with
+    initial_directives: String,
+}
+
+#[derive(Debug, Deserialize)]
+/// Body of the request to change what the server logs
+struct SetLogFilterRequest {
+    directives: String,
+}
+
+/// Dispatches requests to read (`GET`), replace (`PUT`) or restore
+/// (`DELETE`) the filter of what the server logs, which may only be
+/// made with the admin token
+async fn log_filter_route(
+    req: hyper::Request<Body>,
+    log_filter: &LogFilter,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    match req.method() {
+        &Method::GET => {}
+        &Method::PUT => {
+            let body = parse_body(req).await?;
+            let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+            let request: SetLogFilterRequest =
+                serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+            log_filter
+                .set(&request.directives)
+                .context(LogFilterError)?;
+            info!("Set log filter to '{}'", request.directives);
+        }
+        &Method::DELETE => {
+            log_filter.reset().context(LogFilterError)?;
+            info!(
+                "Restored log filter to '{}'",
+                log_filter.initial_directives()
+            );
+        }
+        method => {
+            return RouteNotFound {
+                method: method.clone(),
+                path: req.uri().path(),
+            }
+            .fail()
+        }
+    }
+
+    json_response(
+        StatusCode::OK,
+        &LogFilterResponse {
+            directives: log_filter.directives(),
+            initial_directives: log_filter.initial_directives().to_string(),
+        },
+    )
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response of the access mode endpoint
+struct AccessModeResponse {
+    /// The mode in effect
+    mode: AccessMode,
+    /// The mode the server started in
+    initial_mode: AccessMode,
+}
+
+#[derive(Debug, Deserialize)]
+/// Body of the request to change which requests the server accepts
+struct SetAccessModeRequest {
+    mode: AccessMode,
+}
+
+/// Dispatches requests to read (`GET`), change (`PUT`) or restore
+/// (`DELETE`) the access mode of the server, which may only be made
+/// with the admin token
+async fn access_mode_route(
+    req: hyper::Request<Body>,
+    access: &Access,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    match req.method() {
+        &Method::GET => {}
+        &Method::PUT => {
+            let body = parse_body(req).await?;
+            let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+            let request: SetAccessModeRequest =
+                serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+            access.set(request.mode);
+            info!("Set access mode to {}", request.mode);
+        }
+        &Method::DELETE => {
+            access.reset();
+            info!("Restored access mode to {}", access.initial_mode());
+        }
+        method => {
+            return RouteNotFound {
+                method: method.clone(),
+                path: req.uri().path(),
+            }
+            .fail()
+        }
+    }
+
+    json_response(
+        StatusCode::OK,
+        &AccessModeResponse {
+            mode: access.mode(),
+            initial_mode: access.initial_mode(),
+        },
+    )
+}
+
+#[derive(Debug, Deserialize, Default)]
+/// Query parameters of the alerts endpoint
+struct AlertsInfo {
+    /// Only return the events after the event with this id
+    after: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+/// An alert which is firing
+struct FiringAlert {
+    db_name: String,
+    resource: Resource,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response of the alerts endpoint
+struct AlertsResponse {
+    /// The soft limits in effect
+    limits: SoftLimits,
+    firing: Vec<FiringAlert>,
+    /// The recent events, oldest first
+    events: Vec<AlertEvent>,
+}
+
+/// Dispatches requests to read the alerts on the soft limits of the
+/// resources of databases (`GET`) or to replace the limits (`PUT`),
+/// which may only be made with the admin token
+async fn alerts_route(
+    req: hyper::Request<Body>,
+    alerts: &Alerts,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let info: AlertsInfo = match req.uri().query() {
+        Some(query) => serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?,
+        None => AlertsInfo::default(),
+    };
+
+    match req.method() {
+        &Method::GET => {}
+        &Method::PUT => {
+            let body = parse_body(req).await?;
+            let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+            let limits: SoftLimits =
+                serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+            alerts.set_limits(limits);
+            info!("Set soft limits to {:?}", limits);
+        }
+        method => {
+            return RouteNotFound {
+                method: method.clone(),
+                path: req.uri().path(),
+            }
+            .fail()
+        }
+    }
+
+    let firing = alerts
+        .firing()
+        .into_iter()
+        .map(|(db_name, resource)| FiringAlert { db_name, resource })
+        .collect();
+    json_response(
+        StatusCode::OK,
+        &AlertsResponse {
+            limits: alerts.limits(),
+            firing,
+            events: alerts.events(info.after),
+        },
+    )
+}
+
+#[derive(Debug, Deserialize)]
+/// Body of the request to reshard the database of a writer
+struct ReshardRequest {
+    /// The shard the writer stores the series of
+    shard_id: u32,
+    /// The shards of the new shard map
+    shards: Vec<Shard>,
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response to a request to reshard a database
+struct ReshardResponse {
+    lines_moved: u64,
+}
+
+/// Moves the series of the database named by the `org` and `bucket`
+/// of the query string which do not belong to the shard `shard_id` of
+/// the new shard map in the body to the writers of their shards, as a
+/// job. Only the admin token may reshard a database.
+///
+/// The database is read a partition at a time. The lines of the moved
+/// series of each partition are sent as writes with the authorization
+/// of this request, in batches of `RESHARD_BATCH_LINES`, and once all
+/// of them have been written the series are deleted from the
+/// partition on this writer. As a series written to this writer after
+/// its lines were read would be deleted without being moved, routers
+/// should use the new shard map before resharding.
+async fn reshard<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    storage: Arc<T>,
+    jobs: &Arc<Jobs>,
+    router: &Router,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let query = req.uri().query().context(ExpectedQueryString)?.to_string();
+    let write_info: WriteInfo = serde_urlencoded::from_str(&query).context(InvalidQueryString {
+        query_string: query.clone(),
+    })?;
+    let db_name = org_and_bucket_to_database(&write_info.org, &write_info.bucket);
+
+    let authorization = req.headers().get(auth::AUTHORIZATION).cloned();
+    let body = parse_body(req).await?;
+    let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+    let request: ReshardRequest =
+        serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+    let shard_map = ShardMap::new(0, request.shards).context(RoutingError)?;
+
+    let db = storage
+        .db(&db_name)
+        .await
+        .context(DatabaseNotFound { name: &db_name })?;
+
+    let description = JobDescription::Reshard {
+        db_name: db_name.clone(),
+        shard_id: request.shard_id,
+    };
+    let shard_id = request.shard_id;
+    let (db, db_name, query, shard_map) = (&db, &db_name, &query, &shard_map);
+    let authorization = authorization.as_ref();
+    let lines_moved = jobs
+        .run(description, |tracker| async move {
+            let partition_keys = db.partition_keys().await;
+            let total = partition_keys.len() as u64;
+            let mut lines_moved = 0;
+            tracker.set_progress(0, total);
+
+            for (done, partition_key) in partition_keys.iter().enumerate() {
+                let lp = db
+                    .partition_line_protocol(partition_key)
+                    .await
+                    .map_err(|e| Box::new(e) as _)
+                    .context(ReadingDatabase { name: db_name })?;
+                let lines = parse_lines(&lp)
+                    .collect::<Result<Vec<_>, influxdb_line_protocol::Error>>()
+                    .context(ParsingLineProtocol)?;
+
+                let moving: Vec<_> = lines
+                    .iter()
+                    .filter(|line| {
+                        shard_map.shard_for(line).map(|shard| shard.id) != Some(shard_id)
+                    })
+                    .collect();
+
+                for batch in moving.chunks(RESHARD_BATCH_LINES) {
+                    router
+                        .forward_to(shard_map, query, authorization, batch)
+                        .await
+                        .context(RoutingError)?;
+
+                    lines_moved += batch.len() as u64;
+                    tracker.add_metric("lines_moved", batch.len() as u64);
+                }
+
+                // the series are only deleted once all their lines have
+                // been written to the writers of their new shard
+                let series_keys: BTreeSet<_> = moving.iter().map(|line| series_key(line)).collect();
+                if !series_keys.is_empty() {
+                    db.delete_series(partition_key, &series_keys)
+                        .await
+                        .map_err(|e| Box::new(e) as _)
+                        .context(DeletingSeries { name: db_name })?;
+                }
+
+                tracker.set_progress(done as u64 + 1, total);
+            }
+
+            Ok::<_, ApplicationError>(lines_moved)
+        })
+        .await
+        .context(OperationCancelled { name: db_name })??;
+
+    info!(
+        "Moved {} lines of database {} from shard {}",
+        lines_moved, db_name, shard_id
+    );
+
+    json_response(StatusCode::OK, &ReshardResponse { lines_moved })
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response listing subscriptions
+struct SubscriptionsResponse {
+    subscriptions: Vec<SubscriptionInfo>,
+}
+
+/// Dispatches requests to list subscriptions (`GET
+/// /iox/api/v1/subscriptions`), and to get, create or replace, and
+/// delete one (`GET`, `PUT` and `DELETE`
+/// `/iox/api/v1/subscriptions/{name}`), which may only be made with
+/// the admin token
+async fn subscriptions_route(
+    req: hyper::Request<Body>,
+    path: &str,
+    subscriptions: &Arc<Subscriptions>,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let name = path
+        .strip_prefix(SUBSCRIPTIONS_PATH)
+        .and_then(|rest| rest.strip_prefix('/'));
+
+    match (req.method(), name) {
+        (&Method::GET, None) if path == SUBSCRIPTIONS_PATH => json_response(
+            StatusCode::OK,
+            &SubscriptionsResponse {
+                subscriptions: subscriptions.list(),
+            },
+        ),
+        (&Method::GET, Some(name)) => json_response(
+            StatusCode::OK,
+            &subscriptions.get(name).context(SubscriptionError)?,
+        ),
+        (&Method::PUT, Some(name)) => {
+            let body = parse_body(req).await?;
+            let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+            let config: SubscriptionConfig =
+                serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+            subscriptions
+                .create(name, config)
+                .context(SubscriptionError)?;
+
+            json_response(
+                StatusCode::OK,
+                &subscriptions.get(name).context(SubscriptionError)?,
+            )
+        }
+        (&Method::DELETE, Some(name)) => {
+            subscriptions.delete(name).context(SubscriptionError)?;
+            info!("Deleted subscription {}", name);
+
+            Ok(body_response(None))
+        }
+        _ => RouteNotFound {
+            method: req.method().clone(),
+            path,
+        }
+        .fail(),
+    }
+}
+
+/// Dispatches requests of the form `/iox/api/v1/tokens[/{id}]`, which
+/// may only be made with the admin token
+async fn tokens_route(
+    req: hyper::Request<Body>,
+    path: &str,
+    authorizer: &Authorizer,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let id = path
+        .strip_prefix(TOKENS_PATH)
+        .and_then(|rest| rest.strip_prefix('/'));
+
+    match (req.method(), id) {
+        (&Method::GET, None) => {
+            let params: ListTokensParams = optional_query_params(&req)?;
+            let page_params: PageParams = optional_query_params(&req)?;
+
+            let tokens = authorizer
+                .tokens()
+                .into_iter()
+                .filter(|token| {
+                    params.database.as_ref().map_or(true, |database| {
+                        token.scopes.iter().any(|scope| scope.covers(database))
+                    })
+                })
+                .collect();
+            let page =
+                paginate(tokens, &page_params, |token| token.id.clone()).context(Pagination)?;
+
+            json_response(
+                StatusCode::OK,
+                &TokensResponse {
+                    tokens: page.items,
+                    next: page.next,
+                },
+            )
+        }
+        (&Method::POST, None) => {
+            let body = parse_body(req).await?;
+            let body = str::from_utf8(&body).context(ReadingBodyAsUtf8)?;
+            let info: CreateTokenInfo =
+                serde_json::from_str(body).context(InvalidRequestBody { request_body: body })?;
+
+            let (token, token_value) = authorizer
+                .create_token(info.description, info.scopes)
+                .context(Authorization)?;
+            info!("Created token {}", token.id);
+
+            json_response(StatusCode::CREATED, &CreatedToken { token, token_value })
+        }
+        (&Method::DELETE, Some(id)) if !id.is_empty() => {
+            authorizer.delete_token(id).context(Authorization)?;
+            info!("Deleted token {}", id);
+
+            Ok(body_response(None))
+        }
+        _ => RouteNotFound {
+            method: req.method().clone(),
+            path,
+        }
+        .fail(),
+    }
+}
+
+/// Builds a JSON response with `status` and the serialized `value`
+/// Parses the query parameters `P` of `req`, all of which are optional
+fn optional_query_params<P: serde::de::DeserializeOwned + Default>(
+    req: &hyper::Request<Body>,
+) -> Result<P, ApplicationError> {
+    match req.uri().query() {
+        Some(query) => serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        }),
+        None => Ok(P::default()),
+    }
+}
+
+fn json_response(
+    status: StatusCode,
+    value: &impl Serialize,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let body = serde_json::to_string(value).expect("Should have been able to serialize response");
+
+    Ok(hyper::Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, JSON_CONTENT_TYPE)
+        .body(body.into())
+        .expect("Should have been able to construct a response"))
+}
+
+#[derive(Debug, Deserialize, Default)]
+/// Query parameters of the /ping endpoint
+struct PingInfo {
+    /// Whether to respond with the version of the server, rather than
+    /// no content
+    #[serde(default)]
+    verbose: bool,
+}
+
+/// Route to test that the server is alive, which responds as InfluxDB
+/// does: with no content (the version is in the headers of every
+/// response), or with the version in JSON if `verbose=true`
+#[tracing::instrument(level = "debug")]
+async fn ping(req: hyper::Request<Body>) -> Result<hyper::Response<Body>, ApplicationError> {
+    let info: PingInfo = match req.uri().query() {
+        Some(query) => serde_urlencoded::from_str(query).context(InvalidQueryString {
+            query_string: query,
+        })?,
+        None => PingInfo::default(),
+    };
+
+    if info.verbose && req.method() == Method::GET {
+        json_response(
+            StatusCode::OK,
+            &serde_json::json!({ "version": build_info::VERSION }),
+        )
+    } else {
+        Ok(body_response(None))
+    }
+}
+
+/// Reports the health of the server in the format of the InfluxDB 2.x
+/// `/health` endpoint
+#[tracing::instrument(level = "debug")]
+fn health() -> Result<hyper::Response<Body>, ApplicationError> {
+    json_response(
+        StatusCode::OK,
+        &serde_json::json!({
+            "name": "influxdb_iox",
+            "message": "ready for queries and writes",
+            "status": "pass",
+            "checks": [],
+            "version": build_info::VERSION,
+            "commit": build_info::COMMIT,
+        }),
+    )
+}
+
+/// Adds the headers by which InfluxDB identifies itself to `response`
+fn add_version_headers(response: &mut hyper::Response<Body>) {
+    let headers = response.headers_mut();
+    headers.insert(
+        "X-Influxdb-Version",
+        http::HeaderValue::from_static(build_info::VERSION),
+    );
+    headers.insert(
+        "X-Influxdb-Build",
+        http::HeaderValue::from_static(build_info::BUILD_NAME),
+    );
+}
+
+pub async fn service<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    state: Arc<ServerState<T>>,
+) -> http::Result<hyper::Response<Body>> {
+    let method = req.method().clone();
+    let uri = req.uri().clone();
+
+    // the spans of the request continue the trace of the caller, if
+    // it sent one
+    let span = info_span!("http_request", method = %method, path = uri.path());
+    telemetry::set_remote_parent(&span, req.headers());
+
+    let response = async {
+        match (&method, uri.path()) {
+            (&Method::GET, "/ping") | (&Method::HEAD, "/ping") => ping(req).await,
+            (&Method::GET, "/health") => health(),
+            (&Method::GET, BUILD_INFO_PATH) => {
+                json_response(StatusCode::OK, &build_info::build_info())
+            }
+            // the principal is added by `HttpAuthentication`, without
+            // which nothing but the public routes is served
+            _ => match req.extensions().get::<Principal>().cloned() {
+                Some(principal) => route(req, &state, &principal).await,
+                None => Err(ApplicationError::Authorization {
+                    source: auth::Error::Unauthenticated,
+                }),
+            },
+        }
+    }
+    .instrument(span)
+    .await;
+
+    let result = match response {
+        Ok(response) => response,
+        Err(e) => error_response(&method, &uri, e),
+    };
+    Ok(handled(&method, &uri, result))
+}
+
+/// Builds the response to a request that failed with `e`
+fn error_response(method: &Method, uri: &http::Uri, e: ApplicationError) -> hyper::Response<Body> {
+    error!(error = ?e, method = ?method, uri = ?uri, "Error while handing request");
+    let json = serde_json::json!({"error": e.to_string()}).to_string();
+    hyper::Response::builder()
+        .status(e.status_code())
+        .body(json.into())
+        .expect("Should have been able to construct a response")
+}
+
+/// Finishes the response to a request
+fn handled(
+    method: &Method,
+    uri: &http::Uri,
+    mut response: hyper::Response<Body>,
+) -> hyper::Response<Body> {
+    add_version_headers(&mut response);
+    info!(method = ?method, uri = ?uri, status = ?response.status(), "Handled request");
+    response
+}
+
+/// Wraps the HTTP API so that each request, other than those of the
+/// public routes, is authenticated with its `Authorization` header
+/// before it is routed. Requests without a valid token are rejected,
+/// as are requests to the routes reserved for the admin made with
+/// other tokens. The principal of the other requests is added to
+/// their extensions, for the routes to authorize it for the databases
+/// they name.
+#[derive(Debug, Clone)]
+pub struct HttpAuthentication<S> {
+    inner: S,
+    authorizer: Arc<Authorizer>,
+}
+
+impl<S> HttpAuthentication<S> {
+    pub fn new(inner: S, authorizer: Arc<Authorizer>) -> Self {
+        Self { inner, authorizer }
+    }
+}
+
+impl<S> Service<hyper::Request<Body>> for HttpAuthentication<S>
+where
+    S: Service<hyper::Request<Body>, Response = hyper::Response<Body>, Error = http::Error>,
+{
+    type Response = hyper::Response<Body>;
+    type Error = http::Error;
+    type Future = Either<Ready<http::Result<hyper::Response<Body>>>, S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: hyper::Request<Body>) -> Self::Future {
+        let path = req.uri().path();
+        if is_public(req.method(), path) {
+            return Either::Right(self.inner.call(req));
+        }
+
+        let principal = authenticate(&req, &self.authorizer).and_then(|principal| {
+            if is_admin_only(path) {
+                principal.authorize_admin().context(Authorization)?;
+            }
+            Ok(principal)
+        });
+
+        match principal {
+            Ok(principal) => {
+                req.extensions_mut().insert(principal);
+                Either::Right(self.inner.call(req))
+            }
+            Err(e) => {
+                let response = error_response(req.method(), req.uri(), e);
+                Either::Left(future::ok(handled(req.method(), req.uri(), response)))
+            }
+        }
+    }
+}
+
+/// Returns true for the routes which do not require a token
+fn is_public(method: &Method, path: &str) -> bool {
+    matches!(
+        (method, path),
+        (&Method::GET, "/ping")
+            | (&Method::HEAD, "/ping")
+            | (&Method::GET, "/health")
+            | (&Method::GET, BUILD_INFO_PATH)
+    )
+}
+
+/// Returns true for the routes which only the admin may use
+fn is_admin_only(path: &str) -> bool {
+    let exact = [
+        QUOTAS_PATH,
+        REPLICATION_PATH,
+        COMPACTION_PATH,
+        METRICS_PATH,
+        SHARD_MAP_PATH,
+        LOG_FILTER_PATH,
+        ACCESS_MODE_PATH,
+        ALERTS_PATH,
+        RESHARD_PATH,
+        APPLY_PATH,
+    ];
+    let prefixes = [TOKENS_PATH, JOBS_PATH, SUBSCRIPTIONS_PATH];
+
+    exact.contains(&path) || prefixes.iter().any(|prefix| path.starts_with(prefix))
+}
+
+/// Authenticates the request with its `Authorization` header
+fn authenticate(
+    req: &hyper::Request<Body>,
+    authorizer: &Authorizer,
+) -> Result<Principal, ApplicationError> {
+    let authorization = match req.headers().get(auth::AUTHORIZATION) {
+        Some(value) => Some(value.to_str().context(ReadingHeaderAsUtf8 {
+            header_name: auth::AUTHORIZATION,
+        })?),
+        None => None,
+    };
+
+    authorizer
+        .authenticate(authorization)
+        .context(Authorization)
+}
+
+/// Dispatches an authenticated request to the handler of its route
+async fn route<T: DatabaseStore>(
+    req: hyper::Request<Body>,
+    state: &ServerState<T>,
+    principal: &Principal,
+) -> Result<hyper::Response<Body>, ApplicationError> {
+    let storage = Arc::clone(&state.storage);
+    let ServerState {
+        authorizer,
+        quotas,
+        jobs,
+        replicator,
+        subscriptions,
+        router,
+        log_filter,
+        compaction,
+        access,
+        alerts,
+        metrics,
+        ..
+    } = state;
+    let path = req.uri().path().to_string();
+
+    match (req.method(), path.as_str()) {
+        (&Method::POST, "/api/v2/write") => {
+            access.check_write().context(AccessError)?;
+            write(
+                req,
+                storage,
+                principal,
+                quotas,
+                replicator,
+                subscriptions,
+                router,
+            )
+            .await
+            .map(body_response)
+        }
+        (&Method::POST, "/api/v2/buckets") => create_bucket(req, storage, principal).await,
+        (&Method::GET, "/api/v2/buckets") => list_buckets(req, storage, principal).await,
+        (&Method::GET, "/api/v2/read") => {
+            access.check_query().context(AccessError)?;
+            read(req, storage, principal, quotas)
+                .await
+                .map(body_response)
+        }
+        (&Method::GET, "/query") | (&Method::POST, "/query") => {
+            access.check_query().context(AccessError)?;
+            v1_query(req, storage, principal, quotas).await
+        }
+        (&Method::GET, QUOTAS_PATH) => list_quotas(quotas),
+        (&Method::GET, REPLICATION_PATH) => replication_status(replicator),
+        (&Method::GET, COMPACTION_PATH) => compaction_status(compaction),
+        (&Method::GET, METRICS_PATH) => metrics_route(metrics),
+        (_, SHARD_MAP_PATH) => shard_map_route(req, router).await,
+        (_, LOG_FILTER_PATH) => log_filter_route(req, log_filter).await,
+        (_, ACCESS_MODE_PATH) => access_mode_route(req, access).await,
+        (_, ALERTS_PATH) => alerts_route(req, alerts).await,
+        (&Method::POST, RESHARD_PATH) => reshard(req, storage, jobs, router).await,
+        (&Method::POST, APPLY_PATH) => apply_spec(req, storage, router).await,
+        (&Method::GET, LIST_DATABASES_PATH) => list_databases(storage, principal).await,
+        (_, path) if path.starts_with(DATABASES_PATH) => {
+            database_route(req, path, state, principal).await
+        }
+        (_, path) if path.starts_with(TOKENS_PATH) => tokens_route(req, path, authorizer).await,
+        (_, path) if path.starts_with(JOBS_PATH) => jobs_route(req, path, jobs),
+        (_, path) if path.starts_with(SUBSCRIPTIONS_PATH) => {
+            subscriptions_route(req, path, subscriptions).await
+        }
+        _ => RouteNotFound {
+            method: req.method().clone(),
+            path: req.uri().to_string(),
+        }
+        .fail(),
+    }
+}
+
+/// Builds the response for a route that returns an optional body
+fn body_response(body: Option<Body>) -> hyper::Response<Body> {
+    match body {
+        Some(body) => hyper::Response::builder()
+            .body(body)
+            .expect("Should have been able to construct a response"),
+        None => hyper::Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .expect("Should have been able to construct a response"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use http::header;
+    use reqwest::{Client, Response};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::Server;
+
+    use arrow::{datatypes::Schema, record_batch::RecordBatch};
+    use data_types::RECEIVE_TIME_COLUMN_NAME;
+    use influxdb_line_protocol::FieldValue;
+    use storage::{test::TestDatabaseStore, DatabaseStore};
+
+    use crate::server::warnings::WARNING_HEADER;
+
+    type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T, E = Error> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn test_ping() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let response = client.get(&format!("{}/ping", server_url)).send().await?;
+        assert_eq!(
+            response.headers().get("X-Influxdb-Version").unwrap(),
+            build_info::VERSION
+        );
+        assert_eq!(response.headers().get("X-Influxdb-Build").unwrap(), "IOx");
+
+        // Print the response so if the test fails, we have a log of what went wrong
+        check_response("ping", Ok(response), StatusCode::NO_CONTENT, "").await;
+
+        let response = client.head(&format!("{}/ping", server_url)).send().await;
+        check_response("ping head", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client
+            .get(&format!("{}/ping?verbose=true", server_url))
+            .send()
+            .await;
+        check_response(
+            "ping verbose",
+            response,
+            StatusCode::OK,
+            &format!(r#"{{"version":"{}"}}"#, build_info::VERSION),
+        )
+        .await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_build_info() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        // neither route requires a token
+        let server_url = start_server(
+            ServerState::for_test(test_storage).with_authorizer(Authorizer::new("admin")),
+        );
+        let client = Client::new();
+
+        let response = client
+            .get(&format!("{}/iox/api/v1/build_info", server_url))
+            .send()
+            .await;
+        check_response(
+            "build info",
+            response,
+            StatusCode::OK,
+            &serde_json::to_string(&build_info::build_info())?,
+        )
+        .await;
+
+        let response = client.get(&format!("{}/health", server_url)).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let health: serde_json::Value = response.json().await?;
+        assert_eq!(health["status"], "pass");
+        assert_eq!(health["version"], build_info::VERSION);
+
+        // errors are reported with the version headers too
+        let response = client
+            .get(&format!("{}/api/v2/buckets", server_url))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get("X-Influxdb-Version").unwrap(),
+            build_info::VERSION
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+
+        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+
+        // send write data
+        let bucket_name = "MyBucket";
+        let org_name = "MyOrg";
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket={}&org={}",
+                server_url, bucket_name, org_name
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // Check that the data got into the right bucket
+        let test_db = test_storage
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+
+        // Ensure the same line protocol data gets through
+        assert_eq!(test_db.get_lines().await, vec![lp_data]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partial_write() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let db = test_storage.db_or_create("MyOrg_MyBucket").await?;
+        db.set_rules(DatabaseRules {
+            name_rules: database_rules::NameRules {
+                allowed_characters: Some("_".to_string()),
+                lowercase: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+        .await?;
+
+        let response = Client::new()
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body("CPU,Host=a usage=1 10\nmem,host=a free-bytes=2 10")
+            .send()
+            .await;
+        check_response(
+            "partial write",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Partial write: 1 of 2 lines rejected: line 2: field name \"free-bytes\" has the character '-', which is not allowed"}"#,
+        )
+        .await;
+
+        // the accepted line is written with its names normalized
+        assert_eq!(db.get_lines().await, vec!["cpu,host=a usage=1 10"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_receive_time() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let db = test_storage.db_or_create("MyOrg_MyBucket").await?;
+        db.set_rules(DatabaseRules {
+            receive_time: database_rules::ReceiveTime {
+                precision: database_rules::TimePrecision::Seconds,
+                record: true,
+            },
+            ..Default::default()
+        })
+        .await?;
+
+        let response = Client::new()
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body("cpu usage=1\ncpu usage=2 10")
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // both lines record the same receive time, and the line without a
+        // timestamp is given it to the second
+        let lines = db.get_lines().await;
+        let lines: Vec<_> = parse_lines(&lines.join("\n"))
+            .map(|line| line.unwrap())
+            .collect();
+        let received: Vec<_> = lines
+            .iter()
+            .map(|line| match line.field_value(RECEIVE_TIME_COLUMN_NAME) {
+                Some(FieldValue::I64(received)) => *received,
+                other => panic!("unexpected receive time {:?}", other),
+            })
+            .collect();
+        assert_eq!(received[0], received[1]);
+        assert_eq!(
+            lines[0].timestamp,
+            Some(received[0] - received[0] % 1_000_000_000)
+        );
+        assert_eq!(lines[1].timestamp, Some(10));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_write_invalid_utf8() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+
+        let lp_data = b"cpu,host=a usage=1 1\ncpu,host=\xff usage=2 2".to_vec();
+
+        // by default the write is rejected
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                server_url
+            ))
+            .body(lp_data.clone())
+            .send()
+            .await;
+
+        check_response(
+            "write",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Error reading line 2 of the request body as utf8: invalid utf-8 sequence of 1 bytes from index 30. Set invalid_utf8=replace to write it with the invalid bytes replaced"}"#,
+        )
+        .await;
+
+        // unless the invalid bytes are replaced
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg&invalid_utf8=replace",
+                server_url
+            ))
+            .body(lp_data)
+            .send()
+            .await;
+
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let test_db = test_storage
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+
+        assert_eq!(
+            test_db.get_lines().await,
+            vec!["cpu,host=a usage=1 1", "cpu,host=\u{fffd} usage=2 2"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_prometheus_write() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let metrics = r#"# TYPE request_duration_seconds histogram
+request_duration_seconds_bucket{path="/a",le="0.05"} 24 1000
+request_duration_seconds_bucket{path="/a",le="+Inf"} 30 1000
+request_duration_seconds_sum{path="/a"} 5.2 1000
+request_duration_seconds_count{path="/a"} 30 1000
+up{job="iox"} 1 1000
+"#;
+
+        let response = Client::new()
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg&format=prometheus",
+                server_url
+            ))
+            .body(metrics)
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let test_db = test_storage
+            .db("MyOrg_MyBucket")
+            .await
+            .expect("Database exists");
+        assert_eq!(
+            test_db.get_lines().await,
+            vec![
+                "up,job=iox value=1 1000000000",
+                "request_duration_seconds,path=/a le_0.05=24,le_+Inf=30,sum=5.2,count=30 1000000000",
+            ]
+        );
+
+        let response = Client::new()
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg&format=prometheus",
+                server_url
+            ))
+            .body("up{job=iox} 1")
+            .send()
+            .await;
+        check_response(
+            "write",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Error parsing Prometheus metrics at line 1: expected a quoted value for label 'job'"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_without_param() {
+        assert_eq!(
+            query_without_param("org=MyOrg&format=prometheus&bucket=MyBucket", "format"),
+            "org=MyOrg&bucket=MyBucket"
+        );
+        assert_eq!(query_without_param("org=MyOrg", "format"), "org=MyOrg");
+    }
+
+    #[tokio::test]
+    async fn test_buckets() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+
+        for (org, bucket) in &[("MyOrg", "b"), ("MyOrg", "a"), ("My_Org", "c")] {
+            let response = client
+                .post(&format!("{}/api/v2/buckets", server_url))
+                .body(format!(r#"{{"orgID":"{}","name":"{}"}}"#, org, bucket))
+                .send()
+                .await;
+            check_response(
+                "create bucket",
+                response,
+                StatusCode::CREATED,
+                &format!(r#"{{"orgID":"{}","name":"{}"}}"#, org, bucket),
+            )
+            .await;
+        }
+
+        // the database keeps the name it always had, and the bucket it
+        // stores is recorded, so the underscore in the org doesn't make
+        // it a bucket of an org "My"
+        assert!(test_storage.db("My_Org_c").await.is_some());
+        let response = client
+            .post(&format!("{}/api/v2/buckets", server_url))
+            .body(r#"{"orgID":"My","name":"Org_c"}"#)
+            .send()
+            .await;
+        check_response(
+            "create bucket",
+            response,
+            StatusCode::CONFLICT,
+            r#"{"error":"Database My_Org_c stores bucket c of org My_Org, not bucket Org_c of org My"}"#,
+        )
+        .await;
+
+        let response = client
+            .get(&format!("{}/api/v2/buckets?org=MyOrg", server_url))
+            .send()
+            .await;
+        check_response(
+            "list buckets",
+            response,
+            StatusCode::OK,
+            r#"{"buckets":[{"orgID":"MyOrg","name":"a"},{"orgID":"MyOrg","name":"b"}]}"#,
+        )
+        .await;
+
+        let response = client
+            .get(&format!("{}/api/v2/buckets?org=My", server_url))
+            .send()
+            .await;
+        check_response(
+            "list buckets",
+            response,
+            StatusCode::OK,
+            r#"{"buckets":[]}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_auth() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = start_server(
+            ServerState::for_test(test_storage.clone()).with_authorizer(Authorizer::new("admin")),
+        );
+
+        let client = Client::new();
+        let lp_data = "cpu,host=a usage=1 10";
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+
+        // the server is alive to anyone
+        let response = client.get(&format!("{}/ping", server_url)).send().await;
+        check_response("ping", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client.post(&write_url).body(lp_data).send().await;
+        check_response(
+            "write without token",
+            response,
+            StatusCode::UNAUTHORIZED,
+            r#"{"error":"Missing or unknown API token"}"#,
+        )
+        .await;
+
+        // create a token that may only read the bucket
+        let response = client
+            .post(&format!("{}/iox/api/v1/tokens", server_url))
+            .header(header::AUTHORIZATION, "Token admin")
+            .body(r#"{"description":"reader","scopes":[{"database":"MyOrg_MyBucket","permission":"read"}]}"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+        let token_id = created["id"].as_str().unwrap().to_string();
+        let reader = format!("Token {}", created["token_value"].as_str().unwrap());
+
+        let response = client
+            .post(&write_url)
+            .header(header::AUTHORIZATION, &reader)
+            .body(lp_data)
+            .send()
+            .await;
+        check_response(
+            "write with read token",
+            response,
+            StatusCode::FORBIDDEN,
+            r#"{"error":"Token is not authorized to write database 'MyOrg_MyBucket'"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&write_url)
+            .header(header::AUTHORIZATION, "Bearer admin")
+            .body(lp_data)
+            .send()
+            .await;
+        check_response(
+            "write with admin token",
+            response,
+            StatusCode::NO_CONTENT,
+            "",
+        )
+        .await;
+
+        let test_db = test_storage.db("MyOrg_MyBucket").await.unwrap();
+        test_db.set_query_values(vec![]).await;
+        let response = client
+            .post(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query?format=json",
+                server_url
+            ))
+            .header(header::AUTHORIZATION, &reader)
+            .body("select * from cpu")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // only the admin token may manage tokens
+        let response = client
+            .get(&format!("{}/iox/api/v1/tokens", server_url))
+            .header(header::AUTHORIZATION, &reader)
+            .send()
+            .await;
+        check_response(
+            "list tokens with read token",
+            response,
+            StatusCode::FORBIDDEN,
+            r#"{"error":"Only the admin token may manage tokens"}"#,
+        )
+        .await;
+
+        let response = client
+            .get(&format!("{}/iox/api/v1/tokens", server_url))
+            .header(header::AUTHORIZATION, "Token admin")
+            .send()
+            .await;
+        check_response(
+            "list tokens",
+            response,
+            StatusCode::OK,
+            &format!(
+                r#"{{"tokens":[{{"id":"{}","description":"reader","scopes":[{{"database":"MyOrg_MyBucket","permission":"read"}}]}}]}}"#,
+                token_id
+            ),
+        )
+        .await;
+
+        let response = client
+            .delete(&format!("{}/iox/api/v1/tokens/{}", server_url, token_id))
+            .header(header::AUTHORIZATION, "Token admin")
+            .send()
+            .await;
+        check_response("delete token", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client
+            .get(&format!("{}/api/v2/buckets?org=MyOrg", server_url))
+            .header(header::AUTHORIZATION, &reader)
+            .send()
+            .await;
+        check_response(
+            "list buckets with deleted token",
+            response,
+            StatusCode::UNAUTHORIZED,
+            r#"{"error":"Missing or unknown API token"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_row_filtered_token() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = start_server(
+            ServerState::for_test(test_storage.clone()).with_authorizer(Authorizer::new("admin")),
+        );
+
+        let client = Client::new();
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+        let tokens_url = format!("{}/iox/api/v1/tokens", server_url);
+
+        let response = client
+            .post(&tokens_url)
+            .header(header::AUTHORIZATION, "Token admin")
+            .body(r#"{"description":"acme","scopes":[{"database":"MyOrg_MyBucket","permission":"write","filter":{"tags":{"tenant_id":"acme"}}}]}"#)
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+        let tenant = format!("Token {}", created["token_value"].as_str().unwrap());
+
+        let response = client
+            .post(&write_url)
+            .header(header::AUTHORIZATION, &tenant)
+            .body("cpu,tenant_id=acme usage=1 10")
+            .send()
+            .await;
+        check_response("write within filter", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client
+            .post(&write_url)
+            .header(header::AUTHORIZATION, &tenant)
+            .body("cpu,tenant_id=acme usage=1 10\ncpu,tenant_id=other usage=1 10")
+            .send()
+            .await;
+        check_response(
+            "write outside filter",
+            response,
+            StatusCode::FORBIDDEN,
+            r#"{"error":"Token is not authorized to write line 'cpu,tenant_id=other' to database 'MyOrg_MyBucket'"}"#,
+        )
+        .await;
+
+        // a filtered scope can not overlap another scope
+        let response = client
+            .post(&tokens_url)
+            .header(header::AUTHORIZATION, "Token admin")
+            .body(r#"{"description":"ambiguous","scopes":[{"database":"*","permission":"read"},{"database":"MyOrg_MyBucket","permission":"read","filter":{"measurements":["cpu"]}}]}"#)
+            .send()
+            .await;
+        check_response(
+            "create conflicting token",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Scopes of database 'MyOrg_MyBucket' overlap, which is not allowed when one has a filter"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_database_config() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let db_url = format!("{}/iox/api/v1/databases/mydb", server_url);
+
+        let rules = DatabaseRules {
+            mutable_buffer_size: Some(1024),
+            retention_period_seconds: Some(3600),
+            ..Default::default()
+        };
+        let expected = |rules: &DatabaseRules| {
+            serde_json::to_string(&DatabaseInfo {
+                name: "mydb".to_string(),
+                rules: rules.clone(),
+            })
+            .unwrap()
+        };
+
+        let response = client.get(&db_url).send().await;
+        check_response(
+            "get missing database",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Database mydb not found"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&db_url)
+            .body(r#"{"mutable_buffer_size":1024,"retention_period_seconds":3600}"#)
+            .send()
+            .await;
+        check_response(
+            "create database",
+            response,
+            StatusCode::CREATED,
+            &expected(&rules),
+        )
+        .await;
+
+        let db = test_storage.db("mydb").await.expect("database was created");
+        assert_eq!(db.rules().await, rules);
+
+        let response = client.post(&db_url).body("{}").send().await;
+        check_response(
+            "create existing database",
+            response,
+            StatusCode::CONFLICT,
+            r#"{"error":"Database mydb already exists"}"#,
+        )
+        .await;
+
+        let response = client
+            .put(&db_url)
+            .body(r#"{"store_locally":true}"#)
+            .send()
+            .await;
+        let updated = DatabaseRules {
+            store_locally: true,
+            ..Default::default()
+        };
+        check_response(
+            "update database",
+            response,
+            StatusCode::OK,
+            &expected(&updated),
+        )
+        .await;
+        assert_eq!(db.rules().await, updated);
+
+        let response = client.get(&db_url).send().await;
+        check_response(
+            "get database",
+            response,
+            StatusCode::OK,
+            &expected(&updated),
+        )
+        .await;
+
+        let response = client
+            .get(&format!("{}/iox/api/v1/databases", server_url))
+            .send()
+            .await;
+        check_response(
+            "list databases",
+            response,
+            StatusCode::OK,
+            &format!(r#"{{"databases":[{}]}}"#, expected(&updated)),
+        )
+        .await;
+
+        let response = client.put(&db_url).body("not json").send().await;
+        assert_eq!(response?.status(), StatusCode::BAD_REQUEST);
+
+        let response = client
+            .put(&db_url)
+            .body(
+                r#"{"partition_template":{"parts":[{"RegexCapture":{"column":"a","regex":"b"}}]}}"#,
+            )
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(db.rules().await, updated);
+
+        let response = client.delete(&db_url).send().await;
+        check_response("delete database", response, StatusCode::NO_CONTENT, "").await;
+        assert!(test_storage.db("mydb").await.is_none());
+
+        let response = client.delete(&db_url).send().await;
+        check_response(
+            "delete missing database",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Database mydb not found"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_metrics() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let response = Client::new()
+            .get(&format!("{}/metrics", server_url))
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "text/plain; version=0.0.4"
+        );
+        let body = response.text().await?;
+        assert!(body.contains("# TYPE iox_storage_rpc_duration_seconds histogram"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_spec() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let apply_url = format!("{}/iox/api/v1/apply", server_url);
+        let spec = r#"{"databases":{"mydb":{"retention_period_seconds":3600}}}"#;
+
+        let response = client
+            .post(&format!("{}?dry_run=true", apply_url))
+            .body(spec)
+            .send()
+            .await;
+        check_response(
+            "dry run",
+            response,
+            StatusCode::OK,
+            r#"{"dry_run":true,"created":["mydb"],"updated":[],"unchanged":[],"deleted":[],"shards_changed":false}"#,
+        )
+        .await;
+        assert!(test_storage.db("mydb").await.is_none());
+
+        let response = client.post(&apply_url).body(spec).send().await;
+        check_response(
+            "apply spec",
+            response,
+            StatusCode::OK,
+            r#"{"dry_run":false,"created":["mydb"],"updated":[],"unchanged":[],"deleted":[],"shards_changed":false}"#,
+        )
+        .await;
+        let db = test_storage.db("mydb").await.expect("database was created");
+        assert_eq!(db.rules().await.retention_period_seconds, Some(3600));
+
+        let response = client.post(&apply_url).body(spec).send().await;
+        check_response(
+            "apply spec again",
+            response,
+            StatusCode::OK,
+            r#"{"dry_run":false,"created":[],"updated":[],"unchanged":["mydb"],"deleted":[],"shards_changed":false}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&apply_url)
+            .body(r#"{"shards":[{"id":1,"writers":[]}]}"#)
+            .send()
+            .await;
+        check_response(
+            "apply invalid spec",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Invalid shards: Invalid shard map: shard 1 has no writers"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partitions() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let db = test_storage.db_or_create("mydb").await?;
+        let summary = PartitionSummary {
+            key: "2020-10-10T13".to_string(),
+            tables: vec!["cpu".to_string()],
+            row_count: 10,
+            size: 100,
+        };
+        db.set_partitions(vec![
+            summary.clone(),
+            PartitionSummary {
+                key: "2020-10-10T14".to_string(),
+                ..Default::default()
+            },
+        ])
+        .await;
+
+        let client = Client::new();
+        let partitions_url = format!("{}/iox/api/v1/databases/mydb/partitions", server_url);
+
+        let response = client.get(&partitions_url).send().await;
+        check_response(
+            "list partitions",
+            response,
+            StatusCode::OK,
+            r#"{"partitions":["2020-10-10T13","2020-10-10T14"]}"#,
+        )
+        .await;
+
+        let removed = r#"{"key":"2020-10-10T13","tables":["cpu"],"row_count":10,"size":100}"#;
+
+        let response = client
+            .post(&format!(
+                "{}/drop?key=2020-10-10T13&dry_run=true",
+                partitions_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "drop partition dry run",
+            response,
+            StatusCode::OK,
+            &format!(r#"{{"dry_run":true,"removed":{}}}"#, removed),
+        )
+        .await;
+        assert_eq!(db.partition_keys().await.len(), 2);
+
+        let response = client
+            .post(&format!("{}/truncate?key=2020-10-10T13", partitions_url))
+            .send()
+            .await;
+        check_response(
+            "truncate partition",
+            response,
+            StatusCode::OK,
+            &format!(r#"{{"dry_run":false,"removed":{}}}"#, removed),
+        )
+        .await;
+        assert_eq!(db.partition_keys().await.len(), 2);
+
+        let response = client
+            .post(&format!("{}/drop?key=2020-10-10T13", partitions_url))
+            .send()
+            .await;
+        check_response(
+            "drop partition",
+            response,
+            StatusCode::OK,
+            r#"{"dry_run":false,"removed":{"key":"2020-10-10T13","tables":[],"row_count":0,"size":0}}"#,
+        )
+        .await;
+        assert_eq!(db.partition_keys().await, vec!["2020-10-10T14"]);
+
+        let response = client
+            .post(&format!("{}/drop?key=2020-10-10T13", partitions_url))
+            .send()
+            .await;
+        check_response(
+            "drop missing partition",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Partition 2020-10-10T13 not found in database mydb"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&format!("{}/drop", partitions_url))
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chunks() -> Result<()> {
+        use data_types::partition_metadata::{ChunkState, ChunkStorage};
+
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let chunk = |partition_key: &str| ChunkSummary {
+            partition_key: partition_key.to_string(),
+            id: 0,
+            storage: ChunkStorage::MutableBuffer,
+            state: ChunkState::Open,
+            row_count: 2,
+            size: 80,
+            column_sizes: vec![("time".to_string(), 32), ("usage".to_string(), 32)]
+                .into_iter()
+                .collect(),
+            min_time: Some(10),
+            max_time: Some(20),
+            table_names: vec![],
+            last_write_time: None,
+        };
+        let db = test_storage.db_or_create("mydb").await?;
+        db.set_chunks(vec![chunk("2020-10-10T13"), chunk("2020-10-10T14")])
+            .await;
+
+        let client = Client::new();
+        let db_url = format!("{}/iox/api/v1/databases/mydb", server_url);
+        let expected_chunk = |partition_key: &str| {
+            format!(
+                r#"{{"partition_key":"{}","id":0,"storage":"mutable_buffer","state":"open","row_count":2,"size":80,"column_sizes":{{"time":32,"usage":32}},"min_time":10,"max_time":20}}"#,
+                partition_key
+            )
+        };
+
+        let response = client.get(&format!("{}/chunks", db_url)).send().await;
+        check_response(
+            "list chunks",
+            response,
+            StatusCode::OK,
+            &format!(
+                r#"{{"chunks":[{},{}]}}"#,
+                expected_chunk("2020-10-10T13"),
+                expected_chunk("2020-10-10T14")
+            ),
+        )
+        .await;
+
+        let response = client
+            .get(&format!("{}/chunks?partition_key=2020-10-10T14", db_url))
+            .send()
+            .await;
+        check_response(
+            "list chunks of partition",
+            response,
+            StatusCode::OK,
+            &format!(r#"{{"chunks":[{}]}}"#, expected_chunk("2020-10-10T14")),
+        )
+        .await;
+
+        let response = client
+            .get(&format!(
+                "{}/chunk?partition_key=2020-10-10T13&id=0",
+                db_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "get chunk",
+            response,
+            StatusCode::OK,
+            &expected_chunk("2020-10-10T13"),
+        )
+        .await;
+
+        let response = client
+            .get(&format!(
+                "{}/chunk?partition_key=2020-10-10T13&id=1",
+                db_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "get missing chunk",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Chunk 1 of partition 2020-10-10T13 not found in database mydb"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_list_pagination() -> Result<()> {
+        use data_types::partition_metadata::ChunkStorage;
+
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let chunk = |partition_key: &str, id: u32, table: &str, time: i64| ChunkSummary {
+            partition_key: partition_key.to_string(),
+            id,
+            storage: ChunkStorage::MutableBuffer,
+            state: if id == 0 {
+                ChunkState::Closed
+            } else {
+                ChunkState::Open
+            },
+            row_count: 1,
+            size: 40,
+            column_sizes: Default::default(),
+            min_time: Some(time),
+            max_time: Some(time),
+            table_names: vec![table.to_string()],
+            last_write_time: None,
+        };
+        let db = test_storage.db_or_create("mydb").await?;
+        db.set_chunks(vec![
+            chunk("2020-10-10T14", 0, "mem", 300),
+            chunk("2020-10-10T13", 1, "cpu", 200),
+            chunk("2020-10-10T13", 0, "cpu", 100),
+        ])
+        .await;
+        db.set_partitions(vec![
+            PartitionSummary {
+                key: "2020-10-10T13".to_string(),
+                ..Default::default()
+            },
+            PartitionSummary {
+                key: "2020-10-10T14".to_string(),
+                ..Default::default()
+            },
+        ])
+        .await;
+
+        let client = Client::new();
+        let db_url = format!("{}/iox/api/v1/databases/mydb", server_url);
+        let list = |query: &str| {
+            let request = client.get(&format!("{}/{}", db_url, query)).send();
+            let query = query.to_string();
+            async move {
+                let response = request.await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK, "{}", query);
+                serde_json::from_str::<serde_json::Value>(&response.text().await.unwrap()).unwrap()
+            }
+        };
+        let chunk_ids = |listed: &serde_json::Value| -> Vec<String> {
+            listed["chunks"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|chunk| {
+                    format!(
+                        "{}:{}",
+                        chunk["partition_key"].as_str().unwrap(),
+                        chunk["id"]
+                    )
+                })
+                .collect()
+        };
+
+        let first = list("chunks?limit=2").await;
+        assert_eq!(
+            chunk_ids(&first),
+            vec!["2020-10-10T13:0", "2020-10-10T13:1"]
+        );
+        assert_eq!(first["next"], "2020-10-10T13:1");
+
+        let last = list("chunks?limit=2&after=2020-10-10T13:1").await;
+        assert_eq!(chunk_ids(&last), vec!["2020-10-10T14:0"]);
+        assert!(last.get("next").is_none());
+
+        let filtered = list("chunks?table=cpu&state=closed").await;
+        assert_eq!(chunk_ids(&filtered), vec!["2020-10-10T13:0"]);
+
+        let filtered = list("chunks?start=150&end=300").await;
+        assert_eq!(chunk_ids(&filtered), vec!["2020-10-10T13:1"]);
+
+        let partitions = list("partitions?table=mem").await;
+        assert_eq!(
+            partitions,
+            serde_json::json!({ "partitions": ["2020-10-10T14"] })
+        );
+
+        let partitions = list("partitions?limit=1").await;
+        assert_eq!(
+            partitions,
+            serde_json::json!({ "partitions": ["2020-10-10T13"], "next": "2020-10-10T13" })
+        );
+
+        let response = client
+            .get(&format!("{}/chunks?after=2020-10-10T13", db_url))
+            .send()
+            .await;
+        check_response(
+            "list chunks after invalid cursor",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Invalid cursor '2020-10-10T13'"}"#,
+        )
+        .await;
+
+        let response = client
+            .get(&format!("{}/chunks?limit=0", db_url))
+            .send()
+            .await;
+        check_response(
+            "list chunks with invalid limit",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Invalid limit 0: must be at least 1"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_partition_statistics() -> Result<()> {
+        use data_types::partition_metadata::ChunkStorage;
+
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let chunk = |id: u32, storage: ChunkStorage, time: i64| ChunkSummary {
+            partition_key: "2020-10-10T13".to_string(),
+            id,
+            storage,
+            state: ChunkState::Closed,
+            row_count: 2,
+            size: 80,
+            column_sizes: Default::default(),
+            min_time: Some(time),
+            max_time: Some(time + 10),
+            table_names: vec![],
+            last_write_time: Some(time * 1000),
+        };
+        let db = test_storage.db_or_create("mydb").await?;
+        db.set_chunks(vec![
+            chunk(0, ChunkStorage::ReadBuffer, 100),
+            chunk(1, ChunkStorage::MutableBuffer, 200),
+        ])
+        .await;
+
+        let client = Client::new();
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/mydb/partitions/stats",
+                server_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "partition statistics",
+            response,
+            StatusCode::OK,
+            r#"{"partitions":[{"key":"2020-10-10T13","chunk_count":2,"row_count":4,"size":160,"size_by_storage":{"mutable_buffer":80,"read_buffer":80},"min_time":100,"max_time":210,"last_write_time":200000}]}"#,
+        )
+        .await;
+
+        let response = client
+            .get(&format!(
+                "{}/iox/api/v1/databases/missing/partitions/stats",
+                server_url
+            ))
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::NOT_FOUND);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_chunk_operations() -> Result<()> {
+        use data_types::partition_metadata::ChunkStorage;
+
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let chunk = |id: u32, state: ChunkState| ChunkSummary {
+            partition_key: "2020-10-10T13".to_string(),
+            id,
+            storage: ChunkStorage::MutableBuffer,
+            state,
+            row_count: 2,
+            size: 80,
+            column_sizes: vec![("time".to_string(), 32)].into_iter().collect(),
+            min_time: Some(10),
+            max_time: Some(20),
+            table_names: vec![],
+            last_write_time: None,
+        };
+        let db = test_storage.db_or_create("mydb").await?;
+        db.set_chunks(vec![chunk(0, ChunkState::Open), chunk(1, ChunkState::Open)])
+            .await;
+
+        let client = Client::new();
+        let db_url = format!("{}/iox/api/v1/databases/mydb", server_url);
+        let expected_chunk = |id: u32, state: &str, row_count: u64, size: u64, time: u64| {
+            format!(
+                r#"{{"partition_key":"2020-10-10T13","id":{},"storage":"mutable_buffer","state":"{}","row_count":{},"size":{},"column_sizes":{{"time":{}}},"min_time":10,"max_time":20}}"#,
+                id, state, row_count, size, time
+            )
+        };
+
+        let response = client
+            .post(&format!(
+                "{}/chunk/persist?partition_key=2020-10-10T13&id=0",
+                db_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "persist open chunk",
+            response,
+            StatusCode::CONFLICT,
+            r#"{"error":"Chunk 0 of partition 2020-10-10T13 in database mydb is Open, expected Closed"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&format!(
+                "{}/chunk/close?partition_key=2020-10-10T13&id=0",
+                db_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "close chunk",
+            response,
+            StatusCode::OK,
+            &expected_chunk(0, "closed", 2, 80, 32),
+        )
+        .await;
+
+        let response = client
+            .post(&format!(
+                "{}/chunk/persist?partition_key=2020-10-10T13&id=0",
+                db_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "persist chunk",
+            response,
+            StatusCode::OK,
+            &expected_chunk(0, "persisted", 2, 80, 32),
+        )
+        .await;
+
+        let response = client
+            .post(&format!(
+                "{}/chunk/close?partition_key=2020-10-10T13&id=5",
+                db_url
+            ))
+            .send()
+            .await;
+        check_response(
+            "close missing chunk",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Chunk 5 of partition 2020-10-10T13 not found in database mydb"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&format!("{}/partitions/compact?key=2020-10-10T13", db_url))
+            .send()
+            .await;
+        check_response(
+            "compact partition",
+            response,
+            StatusCode::OK,
+            &expected_chunk(2, "closed", 4, 160, 64),
+        )
+        .await;
+        assert_eq!(db.chunks().await.len(), 1);
+
+        let response = client
+            .post(&format!("{}/partitions/compact?key=2020-10-10T14", db_url))
+            .send()
+            .await;
+        check_response(
+            "compact missing partition",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Partition 2020-10-10T14 not found in database mydb"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_jobs() -> Result<()> {
+        use data_types::partition_metadata::ChunkStorage;
+
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let jobs = Arc::new(Jobs::new());
+        let server_url =
+            start_server(ServerState::for_test(test_storage.clone()).with_jobs(jobs.clone()));
+
+        let db = test_storage.db_or_create("mydb").await?;
+        db.set_chunks(vec![ChunkSummary {
+            partition_key: "2020-10-10T13".to_string(),
+            id: 0,
+            storage: ChunkStorage::MutableBuffer,
+            state: ChunkState::Closed,
+            row_count: 2,
+            size: 80,
+            column_sizes: Default::default(),
+            min_time: None,
+            max_time: None,
+            table_names: vec![],
+            last_write_time: None,
+        }])
+        .await;
+
+        let client = Client::new();
+        let jobs_url = format!("{}/iox/api/v1/jobs", server_url);
+
+        // compacting a partition is tracked as a job
+        let response = client
+            .post(&format!(
+                "{}/iox/api/v1/databases/mydb/partitions/compact?key=2020-10-10T13",
+                server_url
+            ))
+            .send()
+            .await;
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+
+        let job = jobs.get(0)?;
+        let expected_job = format!(
+            r#"{{"id":0,"description":{{"type":"compact_partition","db_name":"mydb","partition_key":"2020-10-10T13"}},"status":"completed","metrics":{{"bytes":80,"rows":2}},"start_time":{},"end_time":{}}}"#,
+            job.start_time,
+            job.end_time.unwrap()
+        );
+
+        let response = client.get(&jobs_url).send().await;
+        check_response(
+            "list jobs",
+            response,
+            StatusCode::OK,
+            &format!(r#"{{"jobs":[{}]}}"#, expected_job),
+        )
+        .await;
+
+        let response = client.get(&format!("{}/0", jobs_url)).send().await;
+        check_response("get job", response, StatusCode::OK, &expected_job).await;
+
+        let response = client.post(&format!("{}/0/cancel", jobs_url)).send().await;
+        check_response(
+            "cancel finished job",
+            response,
+            StatusCode::CONFLICT,
+            r#"{"error":"Job 0 has already finished"}"#,
+        )
+        .await;
+
+        // a running job can be cancelled
+        let (started_tx, started_rx) = futures::channel::oneshot::channel();
+        let running = {
+            let jobs = jobs.clone();
+            tokio::spawn(async move {
+                jobs.run(jobs::JobDescription::ApplyRetention, |tracker| async move {
+                    tracker.set_progress(1, 3);
+                    started_tx.send(()).unwrap();
+                    futures::future::pending::<Result<(), String>>().await
+                })
+                .await
+            })
+        };
+        started_rx.await.unwrap();
+
+        let response = client.get(&format!("{}/1", jobs_url)).send().await;
+        check_response(
+            "get running job",
+            response,
+            StatusCode::OK,
+            &format!(
+                r#"{{"id":1,"description":{{"type":"apply_retention"}},"status":"running","progress":{{"done":1,"total":3}},"metrics":{{}},"start_time":{}}}"#,
+                jobs.get(1)?.start_time
+            ),
+        )
+        .await;
+
+        let response = client.post(&format!("{}/1/cancel", jobs_url)).send().await;
+        assert_eq!(response.unwrap().status(), StatusCode::OK);
+        assert_eq!(running.await?, None);
+        assert_eq!(jobs.get(1)?.status, jobs::JobStatus::Cancelled);
+
+        let response = client
+            .get(&format!("{}?status=cancelled", jobs_url))
+            .send()
+            .await?;
+        let listed: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+        assert_eq!(listed["jobs"][0]["id"], 1);
+        assert_eq!(listed["jobs"].as_array().map(Vec::len), Some(1));
+
+        let response = client.get(&format!("{}?limit=1", jobs_url)).send().await?;
+        let listed: serde_json::Value = serde_json::from_str(&response.text().await?)?;
+        assert_eq!(listed["jobs"][0]["id"], 0);
+        assert_eq!(listed["next"], "0");
+
+        let response = client.get(&format!("{}/7", jobs_url)).send().await;
+        check_response(
+            "get missing job",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Job 7 not found"}"#,
+        )
+        .await;
+
+        let response = client.get(&format!("{}/seven", jobs_url)).send().await;
+        check_response(
+            "get job with invalid id",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Invalid job id 'seven': invalid digit found in string"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscriptions() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let subscriptions = Arc::new(Subscriptions::new());
+        let server_url = start_server(
+            ServerState::for_test(test_storage).with_subscriptions(subscriptions.clone()),
+        );
+
+        let client = Client::new();
+        let subscriptions_url = format!("{}/iox/api/v1/subscriptions", server_url);
+
+        let response = client
+            .put(&format!("{}/cpu_changes", subscriptions_url))
+            .body(r#"{"db_name":"MyOrg_MyBucket","measurements":["cpu"],"sink":{"type":"grpc"}}"#)
+            .send()
+            .await;
+        let expected = r#"{"name":"cpu_changes","config":{"db_name":"MyOrg_MyBucket","measurements":["cpu"],"format":"line_protocol","sink":{"type":"grpc"}},"pending":0,"last_sequence":0,"acknowledged_sequence":0,"dropped":0}"#;
+        check_response("create subscription", response, StatusCode::OK, expected).await;
+
+        // accepted writes are published to the subscriptions matching them
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+        let response = client
+            .post(&write_url)
+            .body("cpu,host=a usage=1 10\nmem free=2i 10")
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let messages = subscriptions.messages_after("cpu_changes", 0)?;
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].data, b"cpu,host=a usage=1 10\n".to_vec());
+
+        let response = client.get(&subscriptions_url).send().await;
+        let expected = r#"{"subscriptions":[{"name":"cpu_changes","config":{"db_name":"MyOrg_MyBucket","measurements":["cpu"],"format":"line_protocol","sink":{"type":"grpc"}},"pending":1,"last_sequence":1,"acknowledged_sequence":0,"dropped":0}]}"#;
+        check_response("list subscriptions", response, StatusCode::OK, expected).await;
+
+        let response = client
+            .delete(&format!("{}/cpu_changes", subscriptions_url))
+            .send()
+            .await;
+        check_response("delete subscription", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client
+            .get(&format!("{}/cpu_changes", subscriptions_url))
+            .send()
+            .await;
+        check_response(
+            "get deleted subscription",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Subscription cpu_changes not found"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_router() -> Result<()> {
+        let writer_storages = vec![
+            Arc::new(TestDatabaseStore::new()),
+            Arc::new(TestDatabaseStore::new()),
+        ];
+        let writers = vec![Arc::new(Router::writer(1)), Arc::new(Router::writer(2))];
+        let writer_urls: Vec<_> = writer_storages
+            .iter()
+            .zip(&writers)
+            .map(|(storage, writer)| {
+                start_server(ServerState::for_test(storage.clone()).with_router(writer.clone()))
+            })
+            .collect();
+
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let router = Arc::new(Router::new(Mode::Router));
+        let server_url = start_server(
+            ServerState::for_test(test_storage.clone())
+                .with_router(router.clone())
+                .with_access(Arc::new(Access::default().without_data())),
+        );
+
+        let client = Client::new();
+        let lp_data = "cpu,host=a usage=1 10\nmem free=2i 10\ndisk used=3i 10\nnet rx=4i 10";
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+
+        let response = client.post(&write_url).body(lp_data).send().await;
+        check_response(
+            "write without shards",
+            response,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"error":"No shards are configured to route writes to"}"#,
+        )
+        .await;
+
+        let shard_map_url = format!("{}/iox/api/v1/shard_map", server_url);
+        let body = format!(
+            r#"{{"shards":[{{"id":1,"writers":["{}"]}},{{"id":2,"writers":["{}"]}}]}}"#,
+            writer_urls[0], writer_urls[1]
+        );
+        let response = client.put(&shard_map_url).body(body).send().await;
+        let expected = format!(
+            r#"{{"mode":"router","version":1,"shards":[{{"id":1,"writers":["{}"]}},{{"id":2,"writers":["{}"]}}]}}"#,
+            writer_urls[0], writer_urls[1]
+        );
+        check_response("set shards", response, StatusCode::OK, &expected).await;
+
+        // the writers are given the same shards, to check the lines
+        // they are sent against
+        let shards = router.shard_map().shards;
+        for writer in &writers {
+            writer.set_shards(shards.clone(), None).unwrap();
+        }
+
+        let response = client.post(&write_url).body(lp_data).send().await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // the router stores nothing, and each line is stored by the
+        // writer of its shard
+        assert!(test_storage.db("MyOrg_MyBucket").await.is_none());
+
+        let shard_map = router.shard_map();
+        let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+        for (storage, shard) in writer_storages.iter().zip(&shard_map.shards) {
+            let expected: Vec<_> = lines
+                .iter()
+                .filter(|line| shard_map.shard_for(line) == Some(shard))
+                .map(ToString::to_string)
+                .collect();
+            let stored = match storage.db("MyOrg_MyBucket").await {
+                Some(db) => db.get_lines().await,
+                None => vec![],
+            };
+            assert_eq!(stored, expected, "lines of shard {}", shard.id);
+        }
+
+        // a writer rejects the lines of the other shard
+        let other_shard_lp = (0..)
+            .map(|host| format!("cpu,host={} usage=1 10", host))
+            .find(|lp| {
+                let line = parse_lines(lp).next().unwrap().unwrap();
+                shard_map.shard_for(&line).unwrap().id != 1
+            })
+            .unwrap();
+        let writer_write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", writer_urls[0]);
+        let response = client
+            .post(&writer_write_url)
+            .body(other_shard_lp)
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::MISDIRECTED_REQUEST);
+
+        // and the router answers no queries
+        let query_url = format!(
+            "{}/api/v2/read?bucket=MyBucket&org=MyOrg&sql_query=select%20*%20from%20cpu",
+            server_url
+        );
+        let response = client.get(&query_url).send().await;
+        check_response(
+            "query router",
+            response,
+            StatusCode::MISDIRECTED_REQUEST,
+            r#"{"error":"Server stores no data: queries are not accepted"}"#,
+        )
+        .await;
+
+        let body = format!(
+            r#"{{"shards":[{{"id":1,"writers":["{}"]}}],"expected_version":0}}"#,
+            writer_urls[0]
+        );
+        let response = client.put(&shard_map_url).body(body).send().await;
+        check_response(
+            "set shards at old version",
+            response,
+            StatusCode::CONFLICT,
+            r#"{"error":"Shard map is at version 1, not the expected version 0"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_log_filter() -> Result<()> {
+        let (log_filter, _layer) = LogFilter::new("warn").unwrap();
+        let log_filter = Arc::new(log_filter);
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url =
+            start_server(ServerState::for_test(test_storage).with_log_filter(log_filter.clone()));
+
+        let client = Client::new();
+        let log_filter_url = format!("{}/iox/api/v1/log_filter", server_url);
+
+        let response = client.get(&log_filter_url).send().await;
+        check_response(
+            "get log filter",
+            response,
+            StatusCode::OK,
+            r#"{"directives":"warn","initial_directives":"warn"}"#,
+        )
+        .await;
+
+        let response = client
+            .put(&log_filter_url)
+            .body(r#"{"directives":"info,segment_store=debug"}"#)
+            .send()
+            .await;
+        check_response(
+            "set log filter",
+            response,
+            StatusCode::OK,
+            r#"{"directives":"info,segment_store=debug","initial_directives":"warn"}"#,
+        )
+        .await;
+        assert_eq!(log_filter.directives(), "info,segment_store=debug");
+
+        let response = client
+            .put(&log_filter_url)
+            .body(r#"{"directives":"segment_store=loud"}"#)
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(log_filter.directives(), "info,segment_store=debug");
+
+        let response = client.delete(&log_filter_url).send().await;
+        check_response(
+            "restore log filter",
+            response,
+            StatusCode::OK,
+            r#"{"directives":"warn","initial_directives":"warn"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_alerts() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage);
+
+        let client = Client::new();
+        let alerts_url = format!("{}/iox/api/v1/alerts", server_url);
+
+        let response = client.get(&alerts_url).send().await;
+        check_response(
+            "get alerts",
+            response,
+            StatusCode::OK,
+            r#"{"limits":{},"firing":[],"events":[]}"#,
+        )
+        .await;
+
+        let response = client
+            .put(&alerts_url)
+            .body(r#"{"memory_bytes":1048576,"wal_bytes":10485760}"#)
+            .send()
+            .await;
+        check_response(
+            "set soft limits",
+            response,
+            StatusCode::OK,
+            r#"{"limits":{"memory_bytes":1048576,"wal_bytes":10485760},"firing":[],"events":[]}"#,
+        )
+        .await;
+
+        let response = client.get(&format!("{}?after=10", alerts_url)).send().await;
+        check_response(
+            "get alerts after an event",
+            response,
+            StatusCode::OK,
+            r#"{"limits":{"memory_bytes":1048576,"wal_bytes":10485760},"firing":[],"events":[]}"#,
+        )
+        .await;
+
+        let response = client.put(&alerts_url).body("not json").send().await;
+        assert_eq!(response?.status(), StatusCode::BAD_REQUEST);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_access_mode() -> Result<()> {
+        let access = Arc::new(Access::new(AccessMode::ReadOnly));
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url =
+            start_server(ServerState::for_test(test_storage.clone()).with_access(access.clone()));
+
+        let client = Client::new();
+        let access_mode_url = format!("{}/iox/api/v1/access_mode", server_url);
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+        let query_url = format!(
+            "{}/api/v2/read?bucket=MyBucket&org=MyOrg&sql_query=select%20*%20from%20h2o_temperature",
+            server_url
+        );
+        let lp_data = "h2o_temperature,location=santa_monica surface_degrees=65.2 1568756160";
+
+        // a read-only server rejects writes
+        let response = client.post(&write_url).body(lp_data).send().await;
+        check_response(
+            "write to read-only server",
+            response,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"error":"Server is read_only: writes are not accepted"}"#,
+        )
+        .await;
+        assert!(test_storage.db("MyOrg_MyBucket").await.is_none());
+
+        let response = client
+            .put(&access_mode_url)
+            .body(r#"{"mode":"ingest_only"}"#)
+            .send()
+            .await;
+        check_response(
+            "set access mode",
+            response,
+            StatusCode::OK,
+            r#"{"mode":"ingest_only","initial_mode":"read_only"}"#,
+        )
+        .await;
+
+        // an ingest-only server accepts writes, but rejects queries
+        let response = client.post(&write_url).body(lp_data).send().await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client.get(&query_url).send().await;
+        check_response(
+            "query ingest-only server",
+            response,
+            StatusCode::SERVICE_UNAVAILABLE,
+            r#"{"error":"Server is ingest_only: queries are not accepted"}"#,
+        )
+        .await;
+
+        let response = client
+            .put(&access_mode_url)
+            .body(r#"{"mode":"read_mostly"}"#)
+            .send()
+            .await;
+        assert_eq!(response?.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(access.mode(), AccessMode::IngestOnly);
+
+        let response = client.delete(&access_mode_url).send().await;
+        check_response(
+            "restore access mode",
+            response,
+            StatusCode::OK,
+            r#"{"mode":"read_only","initial_mode":"read_only"}"#,
+        )
+        .await;
+
+        // and read-only servers answer queries again
+        test_storage
+            .db("MyOrg_MyBucket")
+            .await
+            .unwrap()
+            .set_query_values(vec![])
+            .await;
+        let response = client.get(&query_url).send().await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Ok(())
+    }
 
     #[tokio::test]
-    async fn test_ping() -> Result<()> {
+    async fn test_reshard() -> Result<()> {
+        let source_storage = Arc::new(TestDatabaseStore::new());
+        let source_url = test_server(source_storage.clone());
+        let target_storage = Arc::new(TestDatabaseStore::new());
+        let target_url = test_server(target_storage.clone());
+
+        let client = Client::new();
+        let lp_data: String = (0..20)
+            .map(|i| format!("cpu,host=host{} usage={} 10\n", i, i))
+            .collect();
+        let response = client
+            .post(&format!(
+                "{}/api/v2/write?bucket=MyBucket&org=MyOrg",
+                source_url
+            ))
+            .body(lp_data.clone())
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // the test database has all its lines in every partition
+        let source_db = source_storage.db("MyOrg_MyBucket").await.unwrap();
+        source_db
+            .set_partitions(vec![PartitionSummary {
+                key: "1970-01-01T00".into(),
+                ..Default::default()
+            }])
+            .await;
+
+        // the source stores shard 1, and shard 2 is added
+        let shards = vec![
+            Shard {
+                id: 1,
+                writers: vec![source_url.clone()],
+            },
+            Shard {
+                id: 2,
+                writers: vec![target_url.clone()],
+            },
+        ];
+        let shard_map = ShardMap::new(1, shards).unwrap();
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        let (expected, kept): (Vec<_>, Vec<_>) = lines
+            .iter()
+            .map(|line| (shard_map.shard_for(line).unwrap().id, line.to_string()))
+            .partition(|(id, _)| *id == 2);
+        let expected: Vec<_> = expected.into_iter().map(|(_, line)| line).collect();
+        let kept: Vec<_> = kept.into_iter().map(|(_, line)| line).collect();
+        assert!(!expected.is_empty());
+        assert!(!kept.is_empty());
+
+        let reshard_url = format!(
+            "{}/iox/api/v1/reshard?bucket=MyBucket&org=MyOrg",
+            source_url
+        );
+        let body = format!(
+            r#"{{"shard_id":1,"shards":[{{"id":1,"writers":["{}"]}},{{"id":2,"writers":["{}"]}}]}}"#,
+            source_url, target_url
+        );
+        let response = client.post(&reshard_url).body(body).send().await;
+        check_response(
+            "reshard",
+            response,
+            StatusCode::OK,
+            &format!(r#"{{"lines_moved":{}}}"#, expected.len()),
+        )
+        .await;
+
+        let target_db = target_storage.db("MyOrg_MyBucket").await.unwrap();
+        assert_eq!(target_db.get_lines().await, expected);
+
+        // the moved series are deleted from the source
+        assert_eq!(source_db.get_lines().await, kept);
+
+        let response = client
+            .post(&format!(
+                "{}/iox/api/v1/reshard?bucket=Missing&org=MyOrg",
+                source_url
+            ))
+            .body(r#"{"shard_id":1,"shards":[]}"#)
+            .send()
+            .await;
+        check_response(
+            "reshard missing database",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Database MyOrg_Missing not found"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_replication() -> Result<()> {
+        // the peer requires API tokens, so the writes are replicated
+        // with the configured token
+        let peer_storage = Arc::new(TestDatabaseStore::new());
+        let peer_url = start_server(
+            ServerState::for_test(peer_storage.clone()).with_authorizer(Authorizer::new("secret")),
+        );
+
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let replicator = Arc::new(Replicator::new(
+            7,
+            vec![peer_url.clone()],
+            Some("secret".to_string()),
+        ));
+        let server_url = start_server(
+            ServerState::for_test(test_storage.clone()).with_replicator(replicator.clone()),
+        );
+
+        let client = Client::new();
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+        let response = client
+            .post(&write_url)
+            .body("cpu,host=a usage=1 10")
+            .send()
+            .await;
+        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+
+        // the write is stored locally and shipped to the peer
+        let db = test_storage.db("MyOrg_MyBucket").await.unwrap();
+        assert_eq!(db.get_lines().await, vec!["cpu,host=a usage=1 10"]);
+
+        let mut writes = vec![];
+        for _ in 0..100 {
+            if let Some(peer_db) = peer_storage.db("MyOrg_MyBucket").await {
+                writes = peer_db.get_writes().await;
+                if !writes.is_empty() {
+                    break;
+                }
+            }
+            tokio::time::delay_for(std::time::Duration::from_millis(20)).await;
+        }
+        assert_eq!(writes.len(), 1);
+        assert_eq!(writes[0].writer_and_sequence(), (7, 1));
+        assert!(writes[0].write_buffer_batch().is_some());
+
+        let response = client
+            .get(&format!("{}/iox/api/v1/replication", server_url))
+            .send()
+            .await;
+        let expected = format!(
+            r#"{{"writer_id":7,"peers":[{{"url":"{}","pending":0,"acknowledged":1,"dropped":0,"last_queued_sequence":1,"last_acknowledged_sequence":1,"failed_attempts":0,"lag_nanos":0}}]}}"#,
+            peer_url
+        );
+        check_response("replication status", response, StatusCode::OK, &expected).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_compaction_status() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage);
+
+        let response = Client::new()
+            .get(&format!("{}/iox/api/v1/compaction", server_url))
+            .send()
+            .await;
+        let expected = r#"{"backlog_partitions":0,"backlog_chunks":0,"backlog_bytes":0,"running":0,"completed":0,"failed":0,"bytes_compacted":0,"throttled_nanos":0}"#;
+        check_response("compaction status", response, StatusCode::OK, expected).await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_quotas() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let quotas = Arc::new(Quotas::new());
+        let server_url =
+            start_server(ServerState::for_test(test_storage.clone()).with_quotas(quotas.clone()));
+
+        let client = Client::new();
+        let quota_url = format!("{}/iox/api/v1/databases/MyOrg_MyBucket/quota", server_url);
+        let write_url = format!("{}/api/v2/write?bucket=MyBucket&org=MyOrg", server_url);
+
+        let response = client.get(&quota_url).send().await;
+        check_response(
+            "get missing quota",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"No quota configured for database 'MyOrg_MyBucket'"}"#,
+        )
+        .await;
+
+        let response = client
+            .put(&quota_url)
+            .body(r#"{"max_series":1}"#)
+            .send()
+            .await;
+        check_response(
+            "set quota",
+            response,
+            StatusCode::OK,
+            r#"{"database":"MyOrg_MyBucket","quota":{"max_series":1},"usage":{"write_bytes":0,"queries":0,"series":0,"rejected_writes":0,"rejected_queries":0}}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&write_url)
+            .body("cpu,host=a usage=1 10\ncpu,host=a usage=2 20")
+            .send()
+            .await;
+        check_response("write within quota", response, StatusCode::NO_CONTENT, "").await;
+
+        let response = client
+            .post(&write_url)
+            .body("cpu,host=b usage=1 10")
+            .send()
+            .await;
+        check_response(
+            "write exceeding quota",
+            response,
+            StatusCode::TOO_MANY_REQUESTS,
+            r#"{"error":"Database 'MyOrg_MyBucket' exceeded its quota of 1 series"}"#,
+        )
+        .await;
+
+        let response = client
+            .get(&format!("{}/iox/api/v1/quotas", server_url))
+            .send()
+            .await;
+        check_response(
+            "list quotas",
+            response,
+            StatusCode::OK,
+            r#"{"quotas":[{"database":"MyOrg_MyBucket","quota":{"max_series":1},"usage":{"write_bytes":43,"queries":0,"series":1,"rejected_writes":1,"rejected_queries":0}}]}"#,
+        )
+        .await;
+
+        let response = client.delete(&quota_url).send().await;
+        check_response("delete quota", response, StatusCode::NO_CONTENT, "").await;
+        assert!(quotas.all().is_empty());
+
+        let response = client
+            .post(&write_url)
+            .body("cpu,host=b usage=1 10")
+            .send()
+            .await;
+        check_response("write without quota", response, StatusCode::NO_CONTENT, "").await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query() -> Result<()> {
+        use arrow::{
+            array::{ArrayRef, Int64Array, StringArray},
+            datatypes::{DataType, Field},
+            ipc::reader::StreamReader,
+        };
+
         let test_storage = Arc::new(TestDatabaseStore::new());
         let server_url = test_server(test_storage.clone());
 
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, false),
+            Field::new("value", DataType::Int64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![
+                Arc::new(StringArray::from(vec!["a", "b"])) as ArrayRef,
+                Arc::new(Int64Array::from(vec![1, 2])) as ArrayRef,
+            ],
+        )?;
+
+        let test_db = test_storage.db_or_create("MyOrg_MyBucket").await?;
+        test_db.set_query_values(vec![batch]).await;
+
         let client = Client::new();
-        let response = client.get(&format!("{}/ping", server_url)).send().await;
+        let sql = "select host, value from cpu";
+        let response = client
+            .post(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query",
+                server_url
+            ))
+            .body(sql)
+            .send()
+            .await?;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            format::ResultFormat::Arrow.content_type()
+        );
+        let body = response.bytes().await?;
+
+        let reader = StreamReader::try_new(&body[..])?;
+        assert_eq!(reader.schema(), schema);
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 2);
+
+        let expected_request = storage::test::QueryRequest {
+            query: sql.to_string(),
+        };
+        assert_eq!(test_db.get_query_request().await, Some(expected_request));
 
-        // Print the response so if the test fails, we have a log of what went wrong
-        check_response("ping", response, StatusCode::OK, "PONG").await;
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_write() -> Result<()> {
+    async fn test_query_formats() -> Result<()> {
+        use arrow::{
+            array::{ArrayRef, Int64Array, StringArray},
+            datatypes::{DataType, Field},
+        };
+
         let test_storage = Arc::new(TestDatabaseStore::new());
         let server_url = test_server(test_storage.clone());
 
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, false),
+            Field::new("value", DataType::Int64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec!["a", "b"])) as ArrayRef,
+                Arc::new(Int64Array::from(vec![1, 2])) as ArrayRef,
+            ],
+        )?;
+
+        let test_db = test_storage.db_or_create("MyOrg_MyBucket").await?;
+        let url = format!("{}/iox/api/v1/databases/MyOrg_MyBucket/query", server_url);
         let client = Client::new();
 
-        let lp_data = "h2o_temperature,location=santa_monica,state=CA surface_degrees=65.2,bottom_degrees=50.4 1568756160";
+        // the format parameter takes precedence over the Accept header
+        test_db.set_query_values(vec![batch.clone()]).await;
+        let response = client
+            .post(&format!("{}?format=csv", url))
+            .header(header::ACCEPT, "application/x-ndjson")
+            .body("select host, value from cpu")
+            .send()
+            .await;
+        check_response(
+            "query csv",
+            response,
+            StatusCode::OK,
+            "#group,false,false,false,false\n\
+             #datatype,string,long,string,long\n\
+             #default,_result,,,\n\
+             ,result,table,host,value\n\
+             ,,0,a,1\n\
+             ,,0,b,2\n",
+        )
+        .await;
 
-        // send write data
-        let bucket_name = "MyBucket";
-        let org_name = "MyOrg";
+        test_db.set_query_values(vec![batch]).await;
+        let response = client
+            .post(&url)
+            .header(header::ACCEPT, "application/x-ndjson")
+            .body("select host, value from cpu")
+            .send()
+            .await?;
+        assert_eq!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            "application/x-ndjson"
+        );
+        assert_eq!(
+            response.text().await?,
+            "{\"host\":\"a\",\"value\":1}\n{\"host\":\"b\",\"value\":2}\n"
+        );
+
+        let response = client
+            .post(&url)
+            .header(header::ACCEPT, "text/html")
+            .body("select host, value from cpu")
+            .send()
+            .await;
+        check_response(
+            "query not acceptable",
+            response,
+            StatusCode::NOT_ACCEPTABLE,
+            r#"{"error":"None of the result formats in 'text/html' are supported"}"#,
+        )
+        .await;
+
+        let response = client
+            .post(&format!("{}?format=xml", url))
+            .body("select host, value from cpu")
+            .send()
+            .await;
+        check_response(
+            "query unknown format",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Unknown result format 'xml'"}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_system_tables() -> Result<()> {
+        use data_types::partition_metadata::ChunkStorage;
+
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let db = test_storage.db_or_create("MyOrg_MyBucket").await?;
+        db.set_chunks(vec![ChunkSummary {
+            partition_key: "2020-10-10T13".to_string(),
+            id: 0,
+            storage: ChunkStorage::MutableBuffer,
+            state: ChunkState::Closed,
+            row_count: 2,
+            size: 80,
+            column_sizes: Default::default(),
+            min_time: None,
+            max_time: None,
+            table_names: vec![],
+            last_write_time: None,
+        }])
+        .await;
+
+        let url = format!(
+            "{}/iox/api/v1/databases/MyOrg_MyBucket/query?format=ndjson",
+            server_url
+        );
+        let client = Client::new();
+
+        let response = client
+            .post(&url)
+            .header(
+                query_labels::QUERY_LABELS_HEADER,
+                "user=alice,dashboard=chunks",
+            )
+            .body("select partition_key, state, row_count from system.chunks")
+            .send()
+            .await;
+        check_response(
+            "query system.chunks",
+            response,
+            StatusCode::OK,
+            "{\"partition_key\":\"2020-10-10T13\",\"state\":\"closed\",\"row_count\":2}\n",
+        )
+        .await;
+
+        let response = client
+            .post(&url)
+            .header(query_labels::QUERY_LABELS_HEADER, "user")
+            .body("select partition_key from system.chunks")
+            .send()
+            .await;
+        check_response(
+            "query with invalid labels",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Invalid query label 'user': expected key=value"}"#,
+        )
+        .await;
+
+        // the query above is recorded in system.queries, with its labels
+        let response = client
+            .post(&url)
+            .body("select query_text, rows, labels from system.queries")
+            .send()
+            .await;
+        check_response(
+            "query system.queries",
+            response,
+            StatusCode::OK,
+            "{\"query_text\":\"select partition_key, state, row_count from system.chunks\",\"rows\":1,\"labels\":\"dashboard=chunks,user=alice\"}\n",
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_params() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+        let test_db = test_storage.db_or_create("MyOrg_MyBucket").await?;
+        let client = Client::new();
+
+        // SQL
+        test_db.set_query_values(vec![]).await;
         let response = client
             .post(&format!(
-                "{}/api/v2/write?bucket={}&org={}",
-                server_url, bucket_name, org_name
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query",
+                server_url
             ))
-            .body(lp_data)
+            .query(&[("params", r#"{"host": "o'brien", "min": 2}"#)])
+            .body("select * from cpu where host = $host and value > $min")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(WARNING_HEADER).is_none());
+
+        let expected_request = storage::test::QueryRequest {
+            query: "select * from cpu where host = 'o''brien' and value > 2".to_string(),
+        };
+        assert_eq!(test_db.get_query_request().await, Some(expected_request));
+
+        // unused parameters are returned as warnings
+        test_db.set_query_values(vec![]).await;
+        let response = client
+            .post(&format!(
+                "{}/iox/api/v1/databases/MyOrg_MyBucket/query",
+                server_url
+            ))
+            .query(&[("params", r#"{"host": "a", "min": 2}"#)])
+            .body("select * from cpu where host = $host")
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get(WARNING_HEADER).unwrap(),
+            "unused_parameter: parameter $min is not used by the query"
+        );
+
+        test_db.set_query_values(vec![]).await;
+        let response = client
+            .get(&format!("{}/query", server_url))
+            .query(&[
+                ("db", "MyOrg_MyBucket"),
+                ("q", "SELECT value FROM cpu"),
+                ("params", r#"{"host": "a"}"#),
+            ])
+            .send()
+            .await?;
+        assert_eq!(
+            response.headers().get(WARNING_HEADER).unwrap(),
+            "unused_parameter: parameter $host is not used by the query"
+        );
+        assert_eq!(
+            response.text().await?,
+            r#"{"results":[{"statement_id":0,"messages":[{"level":"warning","text":"unused_parameter: parameter $host is not used by the query"}]}]}"#
+        );
+
+        // InfluxQL
+        test_db.set_query_values(vec![]).await;
+        let response = client
+            .get(&format!("{}/query", server_url))
+            .query(&[
+                ("db", "MyOrg_MyBucket"),
+                ("q", "SELECT value FROM cpu WHERE host = $host"),
+                ("params", r#"{"host": "a"}"#),
+            ])
+            .send()
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let expected_request = storage::test::QueryRequest {
+            query: r#"SELECT "time", value FROM cpu WHERE (host = 'a') ORDER BY "time""#
+                .to_string(),
+        };
+        assert_eq!(test_db.get_query_request().await, Some(expected_request));
+
+        // missing parameters are an error
+        let response = client
+            .get(&format!("{}/query", server_url))
+            .query(&[
+                ("db", "MyOrg_MyBucket"),
+                ("q", "SELECT value FROM cpu WHERE host = $host"),
+            ])
             .send()
             .await;
+        check_response(
+            "missing param",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Error in InfluxQL query: No value provided for bound parameter $host"}"#,
+        )
+        .await;
 
-        check_response("write", response, StatusCode::NO_CONTENT, "").await;
+        Ok(())
+    }
 
-        // Check that the data got into the right bucket
-        let test_db = test_storage
-            .db("MyOrg_MyBucket")
-            .await
-            .expect("Database exists");
+    #[tokio::test]
+    async fn test_query_database_not_found() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let response = client
+            .post(&format!(
+                "{}/iox/api/v1/databases/missing/query",
+                server_url
+            ))
+            .body("select * from cpu")
+            .send()
+            .await;
+
+        check_response(
+            "query",
+            response,
+            StatusCode::NOT_FOUND,
+            r#"{"error":"Database missing not found"}"#,
+        )
+        .await;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v1_query() -> Result<()> {
+        use arrow::{
+            array::{ArrayRef, Float64Array, Int64Array},
+            datatypes::{DataType, Field},
+        };
+
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("time", DataType::Int64, false),
+            Field::new("usage", DataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Int64Array::from(vec![1_000_000_000, 2_000_000_000])) as ArrayRef,
+                Arc::new(Float64Array::from(vec![Some(0.5), None])) as ArrayRef,
+            ],
+        )?;
+
+        let test_db = test_storage.db_or_create("telegraf").await?;
+        test_db.set_query_values(vec![batch]).await;
+
+        let client = Client::new();
+        let response = client
+            .get(&format!("{}/query", server_url))
+            .query(&[
+                ("db", "telegraf"),
+                ("q", "SELECT usage FROM cpu"),
+                ("epoch", "s"),
+            ])
+            .send()
+            .await;
+
+        check_response(
+            "v1 query",
+            response,
+            StatusCode::OK,
+            r#"{"results":[{"statement_id":0,"series":[{"name":"cpu","columns":["time","usage"],"values":[[1,0.5],[2,null]]}]}]}"#,
+        )
+        .await;
+
+        let expected_request = storage::test::QueryRequest {
+            query: r#"SELECT "time", usage FROM cpu ORDER BY "time""#.to_string(),
+        };
+        assert_eq!(test_db.get_query_request().await, Some(expected_request));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v1_query_post_form() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        // queries against missing databases fail per statement
+        let client = Client::new();
+        let response = client
+            .post(&format!("{}/query?db=missing", server_url))
+            .form(&[("q", "SELECT usage FROM cpu")])
+            .send()
+            .await;
+
+        check_response(
+            "v1 query",
+            response,
+            StatusCode::OK,
+            r#"{"results":[{"statement_id":0,"error":"Database missing not found"}]}"#,
+        )
+        .await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_v1_query_errors() -> Result<()> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let server_url = test_server(test_storage.clone());
+
+        let client = Client::new();
+        let response = client
+            .get(&format!("{}/query?db=telegraf", server_url))
+            .send()
+            .await;
+        check_response(
+            "v1 query",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Missing required parameter 'q'"}"#,
+        )
+        .await;
+
+        let response = client
+            .get(&format!("{}/query", server_url))
+            .query(&[("db", "telegraf"), ("q", "SELECT usage")])
+            .send()
+            .await;
+        check_response(
+            "v1 query",
+            response,
+            StatusCode::BAD_REQUEST,
+            r#"{"error":"Error in InfluxQL query: Expected FROM at position 12, found end of input"}"#,
+        )
+        .await;
 
-        // Ensure the same line protocol data gets through
-        assert_eq!(test_db.get_lines().await, vec![lp_data]);
         Ok(())
     }
 
@@ -482,13 +5796,19 @@ mod tests {
     /// creates an instance of the http service backed by a in-memory
     /// testable database.  Returns the url of the server
     fn test_server(storage: Arc<TestDatabaseStore>) -> String {
+        start_server(ServerState::for_test(storage))
+    }
+
+    /// Starts an instance of the http service serving `state`,
+    /// returning the url of the server
+    fn start_server(state: ServerState<TestDatabaseStore>) -> String {
+        let state = Arc::new(state);
         let make_svc = make_service_fn(move |_conn| {
-            let storage = storage.clone();
+            let state = Arc::clone(&state);
             async move {
-                Ok::<_, http::Error>(service_fn(move |req| {
-                    let state = storage.clone();
-                    super::service(req, state)
-                }))
+                let authorizer = Arc::clone(&state.authorizer);
+                let service = service_fn(move |req| super::service(req, Arc::clone(&state)));
+                Ok::<_, http::Error>(HttpAuthentication::new(service, authorizer))
             }
         });
 
@@ -0,0 +1,488 @@
+//! This module contains the serializers for query results returned
+//! by the HTTP API. Results are encoded one record batch at a time so
+//! they can be streamed to the client as they are serialized.
+
+use std::{
+    fmt,
+    io::Write,
+    sync::{Arc, Mutex},
+};
+
+use arrow_deps::arrow::{
+    self,
+    array::{Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray, UInt64Array},
+    datatypes::{DataType as ArrowDataType, SchemaRef},
+    error::Result as ArrowResult,
+    ipc::writer::StreamWriter,
+    record_batch::RecordBatch,
+};
+use bytes::Bytes;
+use futures::{stream, Stream, StreamExt};
+use serde_json::Value;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unsupported data type {:?} in column '{}'", data_type, column_name))]
+    UnsupportedDataType {
+        data_type: ArrowDataType,
+        column_name: String,
+    },
+
+    #[snafu(display("Error encoding results as Arrow: {}", source))]
+    EncodingArrow { source: arrow::error::ArrowError },
+
+    #[snafu(display("Error encoding results as CSV: {}", source))]
+    EncodingCsv { source: csv::Error },
+
+    #[snafu(display("Error encoding results as JSON: {}", source))]
+    EncodingJson { source: serde_json::Error },
+
+    #[snafu(display("Error executing query: {}", source))]
+    ExecutingQuery { source: arrow::error::ArrowError },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The formats in which query results can be returned
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ResultFormat {
+    /// The Arrow IPC streaming format (the default)
+    Arrow,
+    /// Annotated CSV, with `#group`, `#datatype` and `#default`
+    /// annotation rows, as returned by the InfluxDB 2.0 query API
+    Csv,
+    /// One JSON object per row, separated by newlines
+    JsonLines,
+}
+
+impl Default for ResultFormat {
+    fn default() -> Self {
+        Self::Arrow
+    }
+}
+
+impl ResultFormat {
+    /// Returns the format with the given name, as specified in the
+    /// `format` query parameter
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "arrow" => Some(Self::Arrow),
+            "csv" => Some(Self::Csv),
+            "jsonl" | "ndjson" => Some(Self::JsonLines),
+            _ => None,
+        }
+    }
+
+    /// Returns the first supported format listed in the value of an
+    /// `Accept` header, ignoring any quality values
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        accept.split(',').find_map(|media_range| {
+            let media_type = media_range.split(';').next().unwrap_or_default().trim();
+            match media_type {
+                "application/vnd.apache.arrow.stream" | "application/*" | "*/*" => {
+                    Some(Self::Arrow)
+                }
+                "text/csv" | "text/*" => Some(Self::Csv),
+                "application/x-ndjson" | "application/jsonl" => Some(Self::JsonLines),
+                _ => None,
+            }
+        })
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Self::Arrow => "application/vnd.apache.arrow.stream",
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::JsonLines => "application/x-ndjson",
+        }
+    }
+}
+
+/// Serializes record batches in a `ResultFormat`, producing one chunk
+/// of output for the start of the results, each batch, and the end of
+/// the results
+pub struct BatchEncoder {
+    format: ResultFormat,
+    schema: SchemaRef,
+    arrow_writer: Option<StreamWriter<SharedBuffer>>,
+    buffer: SharedBuffer,
+}
+
+impl fmt::Debug for BatchEncoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BatchEncoder")
+            .field("format", &self.format)
+            .field("schema", &self.schema)
+            .finish()
+    }
+}
+
+impl BatchEncoder {
+    /// Returns an encoder of batches with the schema `schema`
+    pub fn new(format: ResultFormat, schema: SchemaRef) -> Self {
+        Self {
+            format,
+            schema,
+            arrow_writer: None,
+            buffer: SharedBuffer::default(),
+        }
+    }
+
+    /// Returns the start of the results: the schema of an Arrow stream,
+    /// or the annotations and header of CSV
+    pub fn start(&mut self) -> Result<Bytes> {
+        match self.format {
+            ResultFormat::Arrow => {
+                let writer = StreamWriter::try_new(self.buffer.clone(), &self.schema)
+                    .context(EncodingArrow)?;
+                self.arrow_writer = Some(writer);
+            }
+            ResultFormat::Csv => {
+                // the results are a single table, so no column is part
+                // of its group key
+                let fields = self.schema.fields();
+                let rows: [(&[&str], Vec<&str>); 4] = [
+                    (
+                        &["#group", "false", "false"],
+                        fields.iter().map(|_| "false").collect(),
+                    ),
+                    (
+                        &["#datatype", "string", "long"],
+                        fields
+                            .iter()
+                            .map(|field| csv_datatype(field.data_type()))
+                            .collect(),
+                    ),
+                    (
+                        &["#default", "_result", ""],
+                        fields.iter().map(|_| "").collect(),
+                    ),
+                    (
+                        &["", "result", "table"],
+                        fields.iter().map(|field| field.name().as_str()).collect(),
+                    ),
+                ];
+
+                let mut writer = csv::Writer::from_writer(self.buffer.clone());
+                for (annotation, columns) in &rows {
+                    writer
+                        .write_record(annotation.iter().chain(columns))
+                        .context(EncodingCsv)?;
+                }
+                writer
+                    .flush()
+                    .map_err(csv::Error::from)
+                    .context(EncodingCsv)?;
+            }
+            ResultFormat::JsonLines => {}
+        }
+        Ok(self.buffer.take())
+    }
+
+    /// Returns the rows of `batch`
+    pub fn write(&mut self, batch: &RecordBatch) -> Result<Bytes> {
+        match self.format {
+            ResultFormat::Arrow => {
+                if let Some(writer) = self.arrow_writer.as_mut() {
+                    writer.write(batch).context(EncodingArrow)?;
+                }
+            }
+            ResultFormat::Csv => {
+                let schema = batch.schema();
+                let mut writer = csv::Writer::from_writer(self.buffer.clone());
+                for row in 0..batch.num_rows() {
+                    // the rows are all of the first table of the result
+                    let mut record = vec![String::new(), String::new(), "0".to_string()];
+                    for (column, field) in batch.columns().iter().zip(schema.fields()) {
+                        record.push(match json_value(column, row, field.name())? {
+                            Value::Null => String::new(),
+                            Value::String(value) => value,
+                            value => value.to_string(),
+                        });
+                    }
+                    writer.write_record(&record).context(EncodingCsv)?;
+                }
+                writer
+                    .flush()
+                    .map_err(csv::Error::from)
+                    .context(EncodingCsv)?;
+            }
+            ResultFormat::JsonLines => {
+                let schema = batch.schema();
+                let mut buffer = self.buffer.clone();
+                for row in 0..batch.num_rows() {
+                    let mut object = serde_json::Map::new();
+                    for (column, field) in batch.columns().iter().zip(schema.fields()) {
+                        object.insert(field.name().clone(), json_value(column, row, field.name())?);
+                    }
+                    serde_json::to_writer(&mut buffer, &object).context(EncodingJson)?;
+                    buffer.push(b"\n");
+                }
+            }
+        }
+        Ok(self.buffer.take())
+    }
+
+    /// Returns the end of the results
+    pub fn finish(&mut self) -> Result<Bytes> {
+        if let Some(writer) = self.arrow_writer.as_mut() {
+            writer.finish().context(EncodingArrow)?;
+        }
+        Ok(self.buffer.take())
+    }
+}
+
+/// Encodes the batches of `batches`, which have the schema `schema`, in
+/// `format` as they are produced. The returned stream produces a chunk
+/// of output for the start of the results, each batch, and the end of
+/// the results, and ends after the first error.
+pub fn encode_stream<S>(
+    format: ResultFormat,
+    schema: SchemaRef,
+    batches: S,
+) -> impl Stream<Item = Result<Bytes>> + Send
+where
+    S: Stream<Item = ArrowResult<RecordBatch>> + Send + Unpin,
+{
+    let encoder = BatchEncoder::new(format, schema);
+    stream::unfold(Some((encoder, batches, false)), |state| async move {
+        let (mut encoder, mut batches, started) = state?;
+
+        let result = if !started {
+            encoder.start()
+        } else {
+            match batches.next().await {
+                Some(Ok(batch)) => encoder.write(&batch),
+                Some(Err(e)) => Err(e).context(ExecutingQuery),
+                None => return Some((encoder.finish(), None)),
+            }
+        };
+
+        // don't try to continue encoding after an error
+        let next = match result {
+            Ok(_) => Some((encoder, batches, true)),
+            Err(_) => None,
+        };
+        Some((result, next))
+    })
+}
+
+/// A `Write` implementation that can be shared with a format writer
+/// while the bytes written so far are taken out of it
+#[derive(Debug, Default, Clone)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn push(&self, bytes: &[u8]) {
+        self.0
+            .lock()
+            .expect("mutex poisoned")
+            .extend_from_slice(bytes);
+    }
+
+    fn take(&self) -> Bytes {
+        std::mem::take(&mut *self.0.lock().expect("mutex poisoned")).into()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.push(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns the annotated CSV datatype for an Arrow type
+fn csv_datatype(data_type: &ArrowDataType) -> &'static str {
+    match data_type {
+        ArrowDataType::Int64 => "long",
+        ArrowDataType::UInt64 => "unsignedLong",
+        ArrowDataType::Float64 => "double",
+        ArrowDataType::Boolean => "boolean",
+        _ => "string",
+    }
+}
+
+/// Returns the value at `row` of `column` as JSON
+pub fn json_value(column: &ArrayRef, row: usize, column_name: &str) -> Result<Value> {
+    if column.is_null(row) {
+        return Ok(Value::Null);
+    }
+
+    Ok(match column.data_type() {
+        ArrowDataType::Utf8 => column
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap()
+            .value(row)
+            .into(),
+        ArrowDataType::Float64 => column
+            .as_any()
+            .downcast_ref::<Float64Array>()
+            .unwrap()
+            .value(row)
+            .into(),
+        ArrowDataType::Int64 => column
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap()
+            .value(row)
+            .into(),
+        ArrowDataType::UInt64 => column
+            .as_any()
+            .downcast_ref::<UInt64Array>()
+            .unwrap()
+            .value(row)
+            .into(),
+        ArrowDataType::Boolean => column
+            .as_any()
+            .downcast_ref::<BooleanArray>()
+            .unwrap()
+            .value(row)
+            .into(),
+        data_type => {
+            return UnsupportedDataType {
+                data_type: data_type.clone(),
+                column_name,
+            }
+            .fail()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{
+        datatypes::{Field, Schema},
+        error::ArrowError,
+    };
+
+    fn schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("host", ArrowDataType::Utf8, true),
+            Field::new("time", ArrowDataType::Int64, false),
+            Field::new("usage", ArrowDataType::Float64, true),
+        ]))
+    }
+
+    fn batches() -> Vec<RecordBatch> {
+        let batch = |hosts: Vec<Option<&str>>, times: Vec<i64>, usages: Vec<Option<f64>>| {
+            RecordBatch::try_new(
+                schema(),
+                vec![
+                    Arc::new(StringArray::from(hosts)) as ArrayRef,
+                    Arc::new(Int64Array::from(times)),
+                    Arc::new(Float64Array::from(usages)),
+                ],
+            )
+            .unwrap()
+        };
+
+        vec![
+            batch(vec![Some("a, b")], vec![1], vec![Some(0.5)]),
+            batch(vec![None], vec![2], vec![None]),
+        ]
+    }
+
+    /// Returns the chunks of output of encoding `batches` as they are
+    /// produced
+    async fn encode_results(
+        format: ResultFormat,
+        batches: Vec<ArrowResult<RecordBatch>>,
+    ) -> Vec<Result<Bytes>> {
+        encode_stream(format, schema(), stream::iter(batches))
+            .collect()
+            .await
+    }
+
+    async fn encode(format: ResultFormat, batches: Vec<RecordBatch>) -> Vec<Bytes> {
+        let batches = batches.into_iter().map(Ok).collect();
+        encode_results(format, batches)
+            .await
+            .into_iter()
+            .collect::<Result<_>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_from_accept() {
+        assert_eq!(ResultFormat::from_accept("*/*"), Some(ResultFormat::Arrow));
+        assert_eq!(
+            ResultFormat::from_accept("text/html, text/csv;q=0.9"),
+            Some(ResultFormat::Csv)
+        );
+        assert_eq!(
+            ResultFormat::from_accept("application/x-ndjson"),
+            Some(ResultFormat::JsonLines)
+        );
+        assert_eq!(ResultFormat::from_accept("text/html"), None);
+    }
+
+    #[tokio::test]
+    async fn test_encode_csv() {
+        let chunks = encode(ResultFormat::Csv, batches()).await;
+
+        // one chunk for the header, each batch and the end
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(
+            String::from_utf8(chunks.concat()).unwrap(),
+            "#group,false,false,false,false,false\n\
+             #datatype,string,long,string,long,double\n\
+             #default,_result,,,,\n\
+             ,result,table,host,time,usage\n\
+             ,,0,\"a, b\",1,0.5\n\
+             ,,0,,2,\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encode_json_lines() {
+        let chunks = encode(ResultFormat::JsonLines, batches()).await;
+
+        assert_eq!(
+            String::from_utf8(chunks.concat()).unwrap(),
+            "{\"host\":\"a, b\",\"time\":1,\"usage\":0.5}\n{\"host\":null,\"time\":2,\"usage\":null}\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_encode_arrow() {
+        use arrow::ipc::reader::StreamReader;
+
+        let chunks = encode(ResultFormat::Arrow, batches()).await;
+        let body = chunks.concat();
+
+        let reader = StreamReader::try_new(&body[..]).unwrap();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_encode_no_rows() {
+        let chunks = encode(ResultFormat::Csv, vec![]).await;
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[0].ends_with(b",result,table,host,time,usage\n"));
+        assert!(chunks[1].is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_encode_error() {
+        let mut batches: Vec<_> = batches().into_iter().map(Ok).collect();
+        batches.insert(1, Err(ArrowError::ComputeError("oops".into())));
+
+        // encoding stops at the error
+        let results = encode_results(ResultFormat::JsonLines, batches).await;
+        assert_eq!(results.len(), 3);
+        assert!(results[1].is_ok());
+        assert_eq!(
+            results[2].as_ref().unwrap_err().to_string(),
+            "Error executing query: Compute error: oops"
+        );
+    }
+}
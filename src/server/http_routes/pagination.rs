@@ -0,0 +1,246 @@
+//! This module contains the paging and filtering of the lists returned
+//! by the management API, such as the chunks of a database, which can
+//! be far too long to return in one response.
+//!
+//! Lists are sorted by a key unique to each item. A request with
+//! `limit={n}` receives at most `n` items and, if there are more, a
+//! `next` cursor in the response. Passing it back with `after={cursor}`
+//! returns the items after the last one received. Items added or
+//! removed between requests do not cause others to be skipped or
+//! repeated, as the cursor names the last item rather than a position.
+
+use serde::Deserialize;
+use snafu::{OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid cursor '{}'", cursor))]
+    InvalidCursor { cursor: String },
+
+    #[snafu(display("Invalid limit 0: must be at least 1"))]
+    InvalidLimit,
+
+    #[snafu(display("Invalid time range: start {} is not before end {}", start, end))]
+    InvalidTimeRange { start: i64, end: i64 },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The query parameters selecting a page of a list
+#[derive(Debug, Default, Deserialize)]
+pub struct PageParams {
+    /// The most items to return, or all of them if `None`
+    pub limit: Option<usize>,
+    /// The cursor of the last item of the previous page
+    pub after: Option<String>,
+}
+
+/// The query parameters restricting a list to the items with times
+/// between `start` (inclusive) and `end` (exclusive), in nanoseconds
+/// since the epoch
+#[derive(Debug, Default, Deserialize)]
+pub struct TimeRangeParams {
+    pub start: Option<i64>,
+    pub end: Option<i64>,
+}
+
+impl TimeRangeParams {
+    /// Checks that the range is not empty
+    pub fn validate(&self) -> Result<()> {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) if start >= end => InvalidTimeRange { start, end }.fail(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns true if no range was given
+    pub fn is_unbounded(&self) -> bool {
+        self.start.is_none() && self.end.is_none()
+    }
+
+    /// Returns true if the times from `min` to `max` (both inclusive)
+    /// overlap the range
+    pub fn overlaps(&self, min: i64, max: i64) -> bool {
+        self.start.map_or(true, |start| max >= start) && self.end.map_or(true, |end| min < end)
+    }
+
+    /// Returns true if `time` is within the range
+    pub fn contains(&self, time: i64) -> bool {
+        self.overlaps(time, time)
+    }
+}
+
+/// The key lists are sorted by, which is written in responses as an
+/// opaque cursor
+pub trait Cursor: Ord + Sized {
+    fn encode(&self) -> String;
+
+    fn decode(cursor: &str) -> Option<Self>;
+}
+
+impl Cursor for String {
+    fn encode(&self) -> String {
+        self.clone()
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        Some(cursor.to_string())
+    }
+}
+
+impl Cursor for u64 {
+    fn encode(&self) -> String {
+        self.to_string()
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        cursor.parse().ok()
+    }
+}
+
+/// The key of a chunk: its partition key and id, written as
+/// `{partition_key}:{id}`. Partition keys may themselves contain `:`,
+/// so the id is whatever follows the last one.
+impl Cursor for (String, u32) {
+    fn encode(&self) -> String {
+        format!("{}:{}", self.0, self.1)
+    }
+
+    fn decode(cursor: &str) -> Option<Self> {
+        let mut parts = cursor.rsplitn(2, ':');
+        let id = parts.next()?.parse().ok()?;
+        let partition_key = parts.next()?;
+        Some((partition_key.to_string(), id))
+    }
+}
+
+/// A page of a list
+#[derive(Debug, PartialEq)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    /// The cursor to request the next page with, if there are more
+    /// items
+    pub next: Option<String>,
+}
+
+/// Sorts `items` by the key `key` returns for each, and returns the
+/// page of them `params` selects
+pub fn paginate<T, K: Cursor>(
+    mut items: Vec<T>,
+    params: &PageParams,
+    key: impl Fn(&T) -> K,
+) -> Result<Page<T>> {
+    if params.limit == Some(0) {
+        return InvalidLimit.fail();
+    }
+
+    items.sort_by_key(|item| key(item));
+
+    if let Some(cursor) = &params.after {
+        let after = K::decode(cursor).context(InvalidCursor { cursor })?;
+        items.retain(|item| key(item) > after);
+    }
+
+    let next = match params.limit {
+        Some(limit) if items.len() > limit => {
+            items.truncate(limit);
+            items.last().map(|item| key(item).encode())
+        }
+        _ => None,
+    };
+
+    Ok(Page { items, next })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn page(items: &[u64], limit: Option<usize>, after: Option<&str>) -> Result<Page<u64>> {
+        let params = PageParams {
+            limit,
+            after: after.map(ToString::to_string),
+        };
+        paginate(items.to_vec(), &params, |item| *item)
+    }
+
+    #[test]
+    fn pages() {
+        let items = [5, 1, 4, 2, 3];
+        assert_eq!(
+            page(&items, None, None).unwrap(),
+            Page {
+                items: vec![1, 2, 3, 4, 5],
+                next: None,
+            }
+        );
+
+        let first = page(&items, Some(2), None).unwrap();
+        assert_eq!(first.items, vec![1, 2]);
+        assert_eq!(first.next.as_deref(), Some("2"));
+
+        let second = page(&items, Some(2), first.next.as_deref()).unwrap();
+        assert_eq!(second.items, vec![3, 4]);
+
+        let last = page(&items, Some(2), second.next.as_deref()).unwrap();
+        assert_eq!(
+            last,
+            Page {
+                items: vec![5],
+                next: None,
+            }
+        );
+
+        // a page that ends with the last item has no next page
+        assert_eq!(page(&items, Some(5), None).unwrap().next, None);
+
+        // items after a cursor are found even if its item is gone
+        assert_eq!(page(&[1, 3], None, Some("2")).unwrap().items, vec![3]);
+
+        assert_eq!(
+            page(&items, None, Some("two")).unwrap_err().to_string(),
+            "Invalid cursor 'two'"
+        );
+        assert_eq!(
+            page(&items, Some(0), None).unwrap_err().to_string(),
+            "Invalid limit 0: must be at least 1"
+        );
+    }
+
+    #[test]
+    fn chunk_cursors() {
+        let key = ("2020-10-10T13:00".to_string(), 3);
+        assert_eq!(key.encode(), "2020-10-10T13:00:3");
+        assert_eq!(<(String, u32)>::decode(&key.encode()), Some(key));
+
+        assert_eq!(<(String, u32)>::decode("2020-10-10T13"), None);
+        assert_eq!(<(String, u32)>::decode("2020-10-10T13:x"), None);
+    }
+
+    #[test]
+    fn time_ranges() {
+        let range = TimeRangeParams {
+            start: Some(10),
+            end: Some(20),
+        };
+        range.validate().unwrap();
+        assert!(range.overlaps(0, 10));
+        assert!(range.overlaps(19, 30));
+        assert!(!range.overlaps(0, 9));
+        assert!(!range.overlaps(20, 30));
+        assert!(range.contains(10));
+        assert!(!range.contains(20));
+
+        assert!(TimeRangeParams::default().overlaps(0, 0));
+        assert_eq!(
+            TimeRangeParams {
+                start: Some(20),
+                end: Some(10),
+            }
+            .validate()
+            .unwrap_err()
+            .to_string(),
+            "Invalid time range: start 20 is not before end 10"
+        );
+    }
+}
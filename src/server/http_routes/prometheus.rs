@@ -0,0 +1,393 @@
+//! This module contains the conversion of metrics in the Prometheus
+//! text exposition format into line protocol, so that they are written
+//! like any other line protocol.
+//!
+//! Each sample is a line of the measurement named after its metric,
+//! with its labels as tags and its value in the `value` field. The
+//! samples of a histogram are instead combined into one line per
+//! series, with a field for each bucket and for the sum and count, as
+//! the `histogram` module of `data_types` describes. OpenTelemetry
+//! histograms are written the same way once exported in this format,
+//! such as by the Prometheus exporter of the OpenTelemetry collector.
+//!
+//! See https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use std::collections::{BTreeMap, HashMap};
+
+use data_types::histogram::{self, COUNT_COLUMN, SUM_COLUMN};
+use influxdb_line_protocol::{
+    write_escaped, FIELD_KEY_DELIMITERS, MEASUREMENT_DELIMITERS, TAG_KEY_DELIMITERS,
+    TAG_VALUE_DELIMITERS,
+};
+use snafu::{OptionExt, Snafu};
+
+/// The field of the line of a sample that is not part of a histogram
+const VALUE_FIELD: &str = "value";
+
+/// The label of the upper bound of a histogram bucket
+const BUCKET_LABEL: &str = "le";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error parsing Prometheus metrics at line {}: {}", line, description))]
+    InvalidSample { line: usize, description: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Converts the metrics in `text` into line protocol. Samples without
+/// a timestamp are written without one, so they are stored at the time
+/// they are written.
+pub fn to_line_protocol(text: &str) -> Result<String> {
+    let mut types = HashMap::new();
+    let mut histograms: Vec<HistogramLine> = vec![];
+    let mut histogram_index = HashMap::new();
+    let mut lp = String::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(comment) = line.strip_prefix('#') {
+            let mut words = comment.split_whitespace();
+            if let (Some("TYPE"), Some(name), Some(metric_type)) =
+                (words.next(), words.next(), words.next())
+            {
+                types.insert(name.to_string(), metric_type.to_string());
+            }
+            continue;
+        }
+
+        let mut sample = parse_sample(line).map_err(|description| Error::InvalidSample {
+            line: line_number,
+            description,
+        })?;
+
+        let component = histogram_component(&sample.name, &types);
+        let (family, component) = match component {
+            Some(component) => component,
+            None => {
+                write_line(
+                    &mut lp,
+                    &sample.name,
+                    &sample.labels,
+                    &[(VALUE_FIELD.to_string(), sample.value)],
+                    sample.timestamp,
+                );
+                continue;
+            }
+        };
+
+        let field = match component {
+            Component::Bucket => {
+                let bound = sample
+                    .labels
+                    .remove(BUCKET_LABEL)
+                    .and_then(|bound| histogram::parse_bound(&bound))
+                    .context(InvalidSample {
+                        line: line_number,
+                        description: "histogram bucket without a numeric le label",
+                    })?;
+                histogram::bucket_column(bound)
+            }
+            Component::Sum => SUM_COLUMN.to_string(),
+            Component::Count => COUNT_COLUMN.to_string(),
+        };
+
+        let key = (family.to_string(), sample.labels, sample.timestamp);
+        let index = *histogram_index.entry(key.clone()).or_insert_with(|| {
+            histograms.push(HistogramLine {
+                measurement: key.0,
+                labels: key.1,
+                timestamp: key.2,
+                fields: vec![],
+            });
+            histograms.len() - 1
+        });
+        histograms[index].fields.push((field, sample.value));
+    }
+
+    for histogram in histograms {
+        write_line(
+            &mut lp,
+            &histogram.measurement,
+            &histogram.labels,
+            &histogram.fields,
+            histogram.timestamp,
+        );
+    }
+
+    Ok(lp)
+}
+
+/// A sample of a metric
+#[derive(Debug)]
+struct Sample {
+    name: String,
+    labels: BTreeMap<String, String>,
+    value: f64,
+    /// Nanoseconds since the epoch
+    timestamp: Option<i64>,
+}
+
+/// The samples of a series of a histogram, which are written as one
+/// line
+#[derive(Debug)]
+struct HistogramLine {
+    measurement: String,
+    labels: BTreeMap<String, String>,
+    timestamp: Option<i64>,
+    fields: Vec<(String, f64)>,
+}
+
+/// Which part of a histogram a sample is
+#[derive(Debug, Clone, Copy)]
+enum Component {
+    Bucket,
+    Sum,
+    Count,
+}
+
+/// Returns the histogram the metric `name` is part of, and which part
+/// it is, if its family was declared a histogram
+fn histogram_component<'a>(
+    name: &'a str,
+    types: &HashMap<String, String>,
+) -> Option<(&'a str, Component)> {
+    let components = [
+        ("_bucket", Component::Bucket),
+        ("_sum", Component::Sum),
+        ("_count", Component::Count),
+    ];
+
+    components.iter().find_map(|&(suffix, component)| {
+        let family = name.strip_suffix(suffix)?;
+        match types.get(family).map(String::as_str) {
+            Some("histogram") => Some((family, component)),
+            _ => None,
+        }
+    })
+}
+
+/// Parses a sample line, such as `http_requests_total{method="post"}
+/// 1027 1395066363000`, returning a description of what is wrong if
+/// it is not valid
+fn parse_sample(line: &str) -> Result<Sample, String> {
+    let name_end = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or_else(|| line.len());
+    let name = &line[..name_end];
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err("expected a metric name".to_string());
+    }
+
+    let mut rest = line[name_end..].trim_start();
+    let mut labels = BTreeMap::new();
+    if let Some(label_text) = rest.strip_prefix('{') {
+        rest = parse_labels(label_text, &mut labels)?;
+    }
+
+    let mut words = rest.split_whitespace();
+    let value = words.next().ok_or_else(|| "expected a value".to_string())?;
+    let value = parse_value(value).ok_or_else(|| format!("invalid value '{}'", value))?;
+
+    let timestamp = match words.next() {
+        Some(timestamp) => Some(
+            timestamp
+                .parse::<i64>()
+                .ok()
+                .and_then(|millis| millis.checked_mul(1_000_000))
+                .ok_or_else(|| format!("invalid timestamp '{}'", timestamp))?,
+        ),
+        None => None,
+    };
+
+    if words.next().is_some() {
+        return Err("unexpected text after the timestamp".to_string());
+    }
+
+    Ok(Sample {
+        name: name.to_string(),
+        labels,
+        value,
+        timestamp,
+    })
+}
+
+/// Parses the labels following the `{` of a sample into `labels`,
+/// returning the text after the closing `}`. Labels with empty values
+/// are the same as no label to Prometheus, and are left out.
+fn parse_labels<'a>(
+    mut rest: &'a str,
+    labels: &mut BTreeMap<String, String>,
+) -> Result<&'a str, String> {
+    loop {
+        rest = rest.trim_start();
+        if let Some(rest) = rest.strip_prefix('}') {
+            return Ok(rest);
+        }
+
+        let equals = rest
+            .find('=')
+            .ok_or_else(|| "expected a label name".to_string())?;
+        let name = rest[..equals].trim();
+        rest = rest[equals + 1..].trim_start();
+        let quoted = rest
+            .strip_prefix('"')
+            .ok_or_else(|| format!("expected a quoted value for label '{}'", name))?;
+
+        let unterminated = || format!("unterminated value of label '{}'", name);
+        let mut value = String::new();
+        let mut chars = quoted.char_indices();
+        let end = loop {
+            match chars.next() {
+                Some((index, '"')) => break index + 1,
+                Some((_, '\\')) => match chars.next() {
+                    Some((_, 'n')) => value.push('\n'),
+                    Some((_, c)) => value.push(c),
+                    None => return Err(unterminated()),
+                },
+                Some((_, c)) => value.push(c),
+                None => return Err(unterminated()),
+            }
+        };
+        rest = quoted[end..].trim_start();
+
+        if value.contains('\n') {
+            return Err(format!(
+                "the value of label '{}' contains a newline, which tags cannot",
+                name
+            ));
+        }
+        if !value.is_empty() {
+            labels.insert(name.to_string(), value);
+        }
+
+        if let Some(next) = rest.strip_prefix(',') {
+            rest = next;
+        } else if !rest.starts_with('}') {
+            return Err("expected ',' or '}' after a label".to_string());
+        }
+    }
+}
+
+fn parse_value(value: &str) -> Option<f64> {
+    match value {
+        "NaN" => Some(f64::NAN),
+        "+Inf" => Some(f64::INFINITY),
+        "-Inf" => Some(f64::NEG_INFINITY),
+        _ => value.parse().ok(),
+    }
+}
+
+/// Appends a line of line protocol to `lp`. Floats are displayed
+/// without an exponent, as line protocol requires.
+fn write_line(
+    lp: &mut String,
+    measurement: &str,
+    tags: &BTreeMap<String, String>,
+    fields: &[(String, f64)],
+    timestamp: Option<i64>,
+) {
+    write_escaped(lp, measurement, MEASUREMENT_DELIMITERS);
+    for (key, value) in tags {
+        lp.push(',');
+        write_escaped(lp, key, TAG_KEY_DELIMITERS);
+        lp.push('=');
+        write_escaped(lp, value, TAG_VALUE_DELIMITERS);
+    }
+
+    for (index, (key, value)) in fields.iter().enumerate() {
+        lp.push(if index == 0 { ' ' } else { ',' });
+        write_escaped(lp, key, FIELD_KEY_DELIMITERS);
+        lp.push_str(&format!("={}", value));
+    }
+
+    if let Some(timestamp) = timestamp {
+        lp.push_str(&format!(" {}", timestamp));
+    }
+    lp.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples_are_lines() {
+        let text = r#"
+# HELP http_requests_total The total number of HTTP requests.
+# TYPE http_requests_total counter
+http_requests_total{method="post",code="200"} 1027 1395066363000
+http_requests_total{method="post",code="400", } 3 1395066363000
+# a comment
+process_start_time_seconds 1.5e9
+temperature{room="living room",sensor=""} -Inf
+"#;
+
+        assert_eq!(
+            to_line_protocol(text).unwrap(),
+            "http_requests_total,code=200,method=post value=1027 1395066363000000000\n\
+             http_requests_total,code=400,method=post value=3 1395066363000000000\n\
+             process_start_time_seconds value=1500000000\n\
+             temperature,room=living\\ room value=-inf\n"
+        );
+    }
+
+    #[test]
+    fn histograms_are_one_line_per_series() {
+        let text = r#"
+# TYPE request_duration_seconds histogram
+request_duration_seconds_bucket{path="/a",le="0.05"} 24
+request_duration_seconds_bucket{path="/a",le="0.5"} 129
+request_duration_seconds_bucket{path="/a",le="1.0"} 133
+request_duration_seconds_bucket{path="/a",le="+Inf"} 144
+request_duration_seconds_sum{path="/a"} 53.4
+request_duration_seconds_count{path="/a"} 144
+request_duration_seconds_bucket{path="/b",le="1.0"} 2
+request_duration_seconds_bucket{path="/b",le="+Inf"} 2
+request_duration_seconds_sum{path="/b"} 0.7
+request_duration_seconds_count{path="/b"} 2
+# TYPE queue_length_bucket gauge
+queue_length_bucket 4
+"#;
+
+        assert_eq!(
+            to_line_protocol(text).unwrap(),
+            "queue_length_bucket value=4\n\
+             request_duration_seconds,path=/a le_0.05=24,le_0.5=129,le_1=133,le_+Inf=144,sum=53.4,count=144\n\
+             request_duration_seconds,path=/b le_1=2,le_+Inf=2,sum=0.7,count=2\n"
+        );
+    }
+
+    #[test]
+    fn invalid_samples() {
+        let cases = vec![
+            ("{a=\"b\"} 1", "line 1: expected a metric name"),
+            ("m{a=\"b} 1", "line 1: unterminated value of label 'a'"),
+            ("m{a=b} 1", "line 1: expected a quoted value for label 'a'"),
+            (
+                "m{a=\"b\" c=\"d\"} 1",
+                "line 1: expected ',' or '}' after a label",
+            ),
+            ("\nm one", "line 2: invalid value 'one'"),
+            ("m 1 yesterday", "line 1: invalid timestamp 'yesterday'"),
+            (
+                "# TYPE m histogram\nm_bucket 1",
+                "line 2: histogram bucket without a numeric le label",
+            ),
+        ];
+
+        for (text, expected) in cases {
+            assert_eq!(
+                to_line_protocol(text).unwrap_err().to_string(),
+                format!("Error parsing Prometheus metrics at {}", expected),
+                "converting {:?}",
+                text
+            );
+        }
+    }
+}
@@ -0,0 +1,663 @@
+//! This module contains the parts of the InfluxDB 1.x `/query` API
+//! that are independent of HTTP: the request parameters, the JSON
+//! response shape, and the conversion of query results into series.
+//!
+//! See https://docs.influxdata.com/influxdb/v1.8/tools/api/#query-http-endpoint
+
+use std::collections::BTreeMap;
+
+use arrow_deps::arrow::{
+    array::{Array, ArrayRef, Int64Array},
+    record_batch::RecordBatch,
+};
+use chrono::{SecondsFormat, TimeZone, Utc};
+use influxql::{ast::Fill, FillWindows, SeriesOptions};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use super::format::json_value;
+use crate::server::warnings::Warnings;
+
+/// The name of the timestamp column in IOx
+const TIME_COLUMN: &str = "time";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid epoch '{}': must be one of ns, u, µ, ms, s, m or h", epoch))]
+    InvalidEpoch { epoch: String },
+
+    #[snafu(display("Tag column '{}' not found in query results", column_name))]
+    TagColumnNotFound { column_name: String },
+
+    #[snafu(display("Expected integer timestamps in column '{}'", column_name))]
+    InvalidTimeColumn { column_name: String },
+
+    #[snafu(display("{}", source))]
+    ConvertingValue { source: super::format::Error },
+
+    #[snafu(display("{}", source))]
+    FillingWindows { source: influxql::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, Default, Deserialize)]
+/// Parameters of a request to the /query endpoint, which may be sent
+/// in the query string or, for POST requests, as a form encoded body
+pub struct QueryParams {
+    pub db: Option<String>,
+    pub q: Option<String>,
+    pub epoch: Option<String>,
+    /// JSON object with the values of `$name` placeholders in `q`
+    pub params: Option<String>,
+}
+
+impl QueryParams {
+    /// Returns these parameters, using values from `other` for any
+    /// that were not set
+    pub fn or(self, other: Self) -> Self {
+        Self {
+            db: self.db.or(other.db),
+            q: self.q.or(other.q),
+            epoch: self.epoch.or(other.epoch),
+            params: self.params.or(other.params),
+        }
+    }
+}
+
+/// How timestamps are returned, controlled by the `epoch` parameter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Epoch {
+    /// An RFC3339 string with nanosecond precision (the default)
+    Rfc3339,
+    /// An integer count of units since the epoch, where the value is
+    /// the number of nanoseconds in each unit
+    Integer(i64),
+}
+
+impl Epoch {
+    pub fn parse(epoch: Option<&str>) -> Result<Self> {
+        let nanos_per_unit = match epoch {
+            None => return Ok(Self::Rfc3339),
+            Some("ns") => 1,
+            Some("u") | Some("µ") => 1_000,
+            Some("ms") => 1_000_000,
+            Some("s") => 1_000_000_000,
+            Some("m") => 60_000_000_000,
+            Some("h") => 3_600_000_000_000,
+            Some(epoch) => return InvalidEpoch { epoch }.fail(),
+        };
+        Ok(Self::Integer(nanos_per_unit))
+    }
+
+    fn format(self, nanos: i64) -> Value {
+        match self {
+            Self::Rfc3339 => Utc
+                .timestamp_nanos(nanos)
+                .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+                .into(),
+            Self::Integer(nanos_per_unit) => (nanos / nanos_per_unit).into(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// Body of the response to the /query endpoint
+pub struct QueryResponse {
+    pub results: Vec<StatementResult>,
+}
+
+#[derive(Debug, Serialize)]
+/// The results of a single statement in the query
+pub struct StatementResult {
+    pub statement_id: usize,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub series: Vec<Series>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl StatementResult {
+    pub fn success(statement_id: usize, series: Vec<Series>) -> Self {
+        Self {
+            statement_id,
+            series,
+            messages: vec![],
+            error: None,
+        }
+    }
+
+    pub fn error(statement_id: usize, error: impl std::fmt::Display) -> Self {
+        Self {
+            statement_id,
+            series: vec![],
+            messages: vec![],
+            error: Some(error.to_string()),
+        }
+    }
+
+    /// Adds a warning message for each of `warnings`
+    pub fn with_warnings(mut self, warnings: &Warnings) -> Self {
+        self.messages.extend(warnings.iter().map(|warning| Message {
+            level: "warning",
+            text: warning.to_string(),
+        }));
+        self
+    }
+}
+
+#[derive(Debug, Serialize)]
+/// A message about the execution of a statement
+pub struct Message {
+    pub level: &'static str,
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+/// The rows of a single measurement and set of `GROUP BY` tag values
+pub struct Series {
+    pub name: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    pub columns: Vec<String>,
+    pub values: Vec<Vec<Value>>,
+}
+
+/// Converts the results of a query against `measurement` into
+/// series. `tags` are the names of the `GROUP BY` tag columns, and
+/// rows with the same tag values must be contiguous.
+///
+/// The `time` column, if any, is always returned first, and the tag
+/// columns are returned in `Series::tags` rather than as columns.
+pub fn batches_to_series(
+    measurement: &str,
+    tags: &[&str],
+    batches: &[RecordBatch],
+    epoch: Epoch,
+) -> Result<Vec<Series>> {
+    let mut series: Vec<Series> = Vec::new();
+
+    for batch in batches {
+        let schema = batch.schema();
+
+        let tag_indexes = tags
+            .iter()
+            .map(|&tag| {
+                schema
+                    .fields()
+                    .iter()
+                    .position(|field| field.name() == tag)
+                    .context(TagColumnNotFound { column_name: tag })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut value_indexes = (0..schema.fields().len())
+            .filter(|index| !tag_indexes.contains(index))
+            .collect::<Vec<_>>();
+        value_indexes.sort_by_key(|&index| schema.field(index).name() != TIME_COLUMN);
+
+        let columns = value_indexes
+            .iter()
+            .map(|&index| schema.field(index).name().to_string())
+            .collect::<Vec<_>>();
+
+        for row in 0..batch.num_rows() {
+            let mut row_tags = BTreeMap::new();
+            for (&index, &tag) in tag_indexes.iter().zip(tags) {
+                let value =
+                    match json_value(batch.column(index), row, tag).context(ConvertingValue)? {
+                        Value::Null => String::new(),
+                        Value::String(value) => value,
+                        value => value.to_string(),
+                    };
+                row_tags.insert(tag.to_string(), value);
+            }
+
+            let values = value_indexes
+                .iter()
+                .map(|&index| {
+                    let column = batch.column(index);
+                    let column_name = schema.field(index).name();
+                    if column_name == TIME_COLUMN {
+                        time_value(column, row, column_name, epoch)
+                    } else {
+                        json_value(column, row, column_name).context(ConvertingValue)
+                    }
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            match series.last_mut() {
+                Some(last) if last.tags == row_tags && last.columns == columns => {
+                    last.values.push(values)
+                }
+                _ => series.push(Series {
+                    name: measurement.to_string(),
+                    tags: row_tags,
+                    columns: columns.clone(),
+                    values: vec![values],
+                }),
+            }
+        }
+    }
+
+    Ok(series)
+}
+
+/// Applies `options` to `series`, which must have been converted with
+/// times in nanoseconds (`Epoch::Integer(1)`): fills their windows,
+/// limits their rows and then limits the series. Series left without
+/// rows are not returned.
+pub fn apply_series_options(series: &mut Vec<Series>, options: &SeriesOptions) -> Result<()> {
+    if let Some(fill) = &options.fill {
+        let first = series
+            .iter()
+            .flat_map(|series| {
+                series
+                    .values
+                    .iter()
+                    .filter_map(move |row| row_time(series, row))
+            })
+            .min();
+        if let Some(first) = first {
+            for series in series.iter_mut() {
+                fill_windows(series, fill, first)?;
+            }
+        }
+    }
+
+    for series in series.iter_mut() {
+        let offset = (options.offset as usize).min(series.values.len());
+        series.values.drain(..offset);
+        if let Some(limit) = options.limit {
+            series.values.truncate(limit as usize);
+        }
+    }
+    series.retain(|series| !series.values.is_empty());
+
+    let soffset = (options.soffset as usize).min(series.len());
+    series.drain(..soffset);
+    if let Some(slimit) = options.slimit {
+        series.truncate(slimit as usize);
+    }
+    Ok(())
+}
+
+/// Formats the times of `series`, which are in nanoseconds, as `epoch`
+pub fn format_times(series: &mut [Series], epoch: Epoch) {
+    for series in series {
+        if series.columns.first().map(String::as_str) != Some(TIME_COLUMN) {
+            continue;
+        }
+        for row in &mut series.values {
+            if let Some(nanos) = row[0].as_i64() {
+                row[0] = epoch.format(nanos);
+            }
+        }
+    }
+}
+
+/// Returns the time of `row` of `series`, in nanoseconds
+fn row_time(series: &Series, row: &[Value]) -> Option<i64> {
+    if series.columns.first().map(String::as_str) != Some(TIME_COLUMN) {
+        return None;
+    }
+    row.first()?.as_i64()
+}
+
+/// Adds a row to `series` for each window of `fill` it has no row
+/// for, and fills the values of the windows without data as `fill`
+/// says. `first` is the first time of any series.
+fn fill_windows(series: &mut Series, fill: &FillWindows, first: i64) -> Result<()> {
+    // without times the series has no windows to fill
+    let times: Option<Vec<i64>> = series
+        .values
+        .iter()
+        .map(|row| row_time(series, row))
+        .collect();
+    let times = match times {
+        Some(times) => times,
+        None => return Ok(()),
+    };
+    let mut rows: BTreeMap<_, _> = times.into_iter().zip(series.values.drain(..)).collect();
+
+    let width = series.columns.len();
+    for window in fill.windows(first).context(FillingWindows)? {
+        rows.entry(window).or_insert_with(|| {
+            let mut row = vec![Value::Null; width];
+            row[0] = Value::from(window);
+            row
+        });
+    }
+
+    let times: Vec<i64> = rows.keys().copied().collect();
+    let mut values: Vec<Vec<Value>> = rows.into_iter().map(|(_, row)| row).collect();
+    for column in 1..width {
+        match fill.fill {
+            Fill::Null | Fill::None => {}
+            Fill::Value(value) => {
+                for row in &mut values {
+                    if row[column].is_null() {
+                        row[column] = Value::from(value);
+                    }
+                }
+            }
+            Fill::Previous => {
+                let mut previous = Value::Null;
+                for row in &mut values {
+                    if row[column].is_null() {
+                        row[column] = previous.clone();
+                    } else {
+                        previous = row[column].clone();
+                    }
+                }
+            }
+            Fill::Linear => fill_linear(&times, &mut values, column),
+        }
+    }
+
+    if fill.descending {
+        values.reverse();
+    }
+    series.values = values;
+    Ok(())
+}
+
+/// Fills the null values of `column` of `rows`, at `times`, by
+/// interpolating between the numbers before and after them. Values
+/// without a number both before and after them are left null.
+fn fill_linear(times: &[i64], rows: &mut [Vec<Value>], column: usize) {
+    let mut previous: Option<(i64, f64)> = None;
+    for index in 0..rows.len() {
+        if let Some(value) = rows[index][column].as_f64() {
+            previous = Some((times[index], value));
+            continue;
+        }
+        if !rows[index][column].is_null() {
+            continue;
+        }
+
+        let next = (index + 1..rows.len())
+            .find_map(|next| Some((times[next], rows[next][column].as_f64()?)));
+        if let (Some((t0, v0)), Some((t1, v1))) = (previous, next) {
+            let t = times[index];
+            let value = v0 + (v1 - v0) * (t - t0) as f64 / (t1 - t0) as f64;
+            rows[index][column] = Value::from(value);
+        }
+    }
+}
+
+fn time_value(column: &ArrayRef, row: usize, column_name: &str, epoch: Epoch) -> Result<Value> {
+    if column.is_null(row) {
+        return Ok(Value::Null);
+    }
+
+    let timestamps = column
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .context(InvalidTimeColumn { column_name })?;
+
+    Ok(epoch.format(timestamps.value(row)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::warnings::WarningCode;
+    use arrow_deps::arrow::{
+        array::{Float64Array, StringArray},
+        datatypes::{DataType as ArrowDataType, Field, Schema},
+    };
+    use std::sync::Arc;
+
+    #[test]
+    fn test_epoch() {
+        assert_eq!(Epoch::parse(None).unwrap(), Epoch::Rfc3339);
+        assert_eq!(Epoch::parse(Some("ms")).unwrap(), Epoch::Integer(1_000_000));
+        assert_eq!(
+            Epoch::parse(Some("y")).unwrap_err().to_string(),
+            "Invalid epoch 'y': must be one of ns, u, µ, ms, s, m or h"
+        );
+
+        let nanos = 1_600_000_000_123_456_789;
+        assert_eq!(
+            Epoch::Rfc3339.format(nanos),
+            Value::from("2020-09-13T12:26:40.123456789Z")
+        );
+        assert_eq!(
+            Epoch::Integer(1_000_000_000).format(nanos),
+            Value::from(1_600_000_000)
+        );
+    }
+
+    #[test]
+    fn test_batches_to_series() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", ArrowDataType::Utf8, true),
+            Field::new("time", ArrowDataType::Int64, false),
+            Field::new("mean", ArrowDataType::Float64, true),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec![Some("a"), Some("a"), None])) as ArrayRef,
+                Arc::new(Int64Array::from(vec![0, 60_000_000_000, 0])),
+                Arc::new(Float64Array::from(vec![Some(1.5), None, Some(3.0)])),
+            ],
+        )
+        .unwrap();
+
+        let series =
+            batches_to_series("cpu", &["host"], &[batch], Epoch::Integer(1_000_000_000)).unwrap();
+
+        let tags = |host: &str| {
+            let mut tags = BTreeMap::new();
+            tags.insert("host".to_string(), host.to_string());
+            tags
+        };
+        let columns = vec!["time".to_string(), "mean".to_string()];
+        assert_eq!(
+            series,
+            vec![
+                Series {
+                    name: "cpu".into(),
+                    tags: tags("a"),
+                    columns: columns.clone(),
+                    values: vec![
+                        vec![Value::from(0), Value::from(1.5)],
+                        vec![Value::from(60), Value::Null],
+                    ],
+                },
+                Series {
+                    name: "cpu".into(),
+                    tags: tags(""),
+                    columns,
+                    values: vec![vec![Value::from(0), Value::from(3.0)]],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_response_json() {
+        let mut warnings = Warnings::new();
+        warnings.add(WarningCode::UnusedParameter, "$host is not used");
+
+        let response = QueryResponse {
+            results: vec![
+                StatementResult::success(
+                    0,
+                    vec![Series {
+                        name: "cpu".into(),
+                        tags: BTreeMap::new(),
+                        columns: vec!["time".into(), "usage".into()],
+                        values: vec![vec![Value::from(10), Value::from(99)]],
+                    }],
+                ),
+                StatementResult::error(1, "oops"),
+                StatementResult::success(2, vec![]).with_warnings(&warnings),
+            ],
+        };
+
+        assert_eq!(
+            serde_json::to_string(&response).unwrap(),
+            r#"{"results":[{"statement_id":0,"series":[{"name":"cpu","columns":["time","usage"],"values":[[10,99]]}]},{"statement_id":1,"error":"oops"},{"statement_id":2,"messages":[{"level":"warning","text":"unused_parameter: $host is not used"}]}]}"#
+        );
+    }
+
+    fn series(host: &str, values: Vec<(i64, Option<f64>)>) -> Series {
+        Series {
+            name: "cpu".into(),
+            tags: vec![("host".to_string(), host.to_string())]
+                .into_iter()
+                .collect(),
+            columns: vec!["time".into(), "usage".into()],
+            values: values
+                .into_iter()
+                .map(|(time, usage)| {
+                    vec![Value::from(time), usage.map_or(Value::Null, Value::from)]
+                })
+                .collect(),
+        }
+    }
+
+    fn filled(fill: Fill, start: Option<i64>, end: i64) -> SeriesOptions {
+        SeriesOptions {
+            fill: Some(FillWindows {
+                fill,
+                dimension: influxql::ast::TimeDimension {
+                    interval: 10,
+                    offset: 0,
+                },
+                start,
+                end,
+                descending: false,
+            }),
+            ..Default::default()
+        }
+    }
+
+    fn usages(series: &Series) -> Vec<(i64, Option<f64>)> {
+        series
+            .values
+            .iter()
+            .map(|row| (row[0].as_i64().unwrap(), row[1].as_f64()))
+            .collect()
+    }
+
+    #[test]
+    fn test_fill_windows() {
+        let data = || {
+            vec![
+                series("a", vec![(10, Some(1.0)), (40, Some(4.0))]),
+                series("b", vec![(30, Some(9.0))]),
+            ]
+        };
+
+        let mut nulls = data();
+        apply_series_options(&mut nulls, &filled(Fill::Null, None, 50)).unwrap();
+        assert_eq!(
+            usages(&nulls[0]),
+            vec![(10, Some(1.0)), (20, None), (30, None), (40, Some(4.0))]
+        );
+        // windows start at the first time of any series
+        assert_eq!(
+            usages(&nulls[1]),
+            vec![(10, None), (20, None), (30, Some(9.0)), (40, None)]
+        );
+
+        let mut previous = data();
+        apply_series_options(&mut previous, &filled(Fill::Previous, Some(0), 60)).unwrap();
+        assert_eq!(
+            usages(&previous[0]),
+            vec![
+                (0, None),
+                (10, Some(1.0)),
+                (20, Some(1.0)),
+                (30, Some(1.0)),
+                (40, Some(4.0)),
+                (50, Some(4.0))
+            ]
+        );
+
+        let mut linear = data();
+        apply_series_options(&mut linear, &filled(Fill::Linear, None, 50)).unwrap();
+        assert_eq!(
+            usages(&linear[0]),
+            vec![
+                (10, Some(1.0)),
+                (20, Some(2.0)),
+                (30, Some(3.0)),
+                (40, Some(4.0))
+            ]
+        );
+        assert_eq!(
+            usages(&linear[1]),
+            vec![(10, None), (20, None), (30, Some(9.0)), (40, None)]
+        );
+
+        let mut value = data();
+        let mut options = filled(Fill::Value(-1.0), None, 50);
+        options.fill.as_mut().unwrap().descending = true;
+        apply_series_options(&mut value, &options).unwrap();
+        assert_eq!(
+            usages(&value[1]),
+            vec![
+                (40, Some(-1.0)),
+                (30, Some(9.0)),
+                (20, Some(-1.0)),
+                (10, Some(-1.0))
+            ]
+        );
+
+        let mut too_many = data();
+        let err =
+            apply_series_options(&mut too_many, &filled(Fill::Null, None, i64::MAX)).unwrap_err();
+        assert!(matches!(err, Error::FillingWindows { .. }), "{}", err);
+    }
+
+    #[test]
+    fn test_series_limits() {
+        let data = || {
+            vec![
+                series("a", vec![(10, Some(1.0)), (20, Some(2.0)), (30, Some(3.0))]),
+                series("b", vec![(10, Some(4.0))]),
+                series("c", vec![(10, Some(5.0)), (20, Some(6.0))]),
+            ]
+        };
+
+        let mut limited = data();
+        let options = SeriesOptions {
+            offset: 1,
+            limit: Some(1),
+            ..Default::default()
+        };
+        apply_series_options(&mut limited, &options).unwrap();
+        // series b has no rows left
+        assert_eq!(limited.len(), 2);
+        assert_eq!(usages(&limited[0]), vec![(20, Some(2.0))]);
+        assert_eq!(usages(&limited[1]), vec![(20, Some(6.0))]);
+
+        let mut slimited = data();
+        let options = SeriesOptions {
+            soffset: 1,
+            slimit: Some(1),
+            ..Default::default()
+        };
+        apply_series_options(&mut slimited, &options).unwrap();
+        assert_eq!(slimited.len(), 1);
+        assert_eq!(slimited[0].tags["host"], "b");
+    }
+
+    #[test]
+    fn test_format_times() {
+        let mut formatted = vec![series("a", vec![(1_600_000_000_123_456_789, Some(1.0))])];
+        format_times(&mut formatted, Epoch::Rfc3339);
+        assert_eq!(
+            formatted[0].values[0][0],
+            Value::from("2020-09-13T12:26:40.123456789Z")
+        );
+    }
+}
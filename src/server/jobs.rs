@@ -0,0 +1,416 @@
+//! This module tracks long running background work as jobs, such as
+//! compacting partitions, persisting chunks, replaying the WAL of a
+//! database at startup, resharding a database, dropping data past
+//! its retention period and downsampling aged data.
+//!
+//! Each job is given an id, by which its status, progress and metrics
+//! can be looked up, and by which it can be cancelled. Cancelling a
+//! job aborts its work at the next point it yields, so work which
+//! does not yield (such as a compaction, which holds the lock on its
+//! database throughout) runs to completion regardless. A job runs as
+//! part of the future which started it, so if that future is dropped,
+//! as when the client of the request which started it disconnects,
+//! its work stops and it is recorded as cancelled.
+//!
+//! Jobs are kept in memory: all running jobs, along with the
+//! `MAX_FINISHED_JOBS` which finished most recently.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use futures::future::{AbortHandle, Abortable};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Job {} not found", id))]
+    JobNotFound { id: JobId },
+
+    #[snafu(display("Job {} has already finished", id))]
+    JobFinished { id: JobId },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+pub type JobId = u64;
+
+/// The number of finished jobs which are kept, so their outcome can
+/// be looked up
+pub const MAX_FINISHED_JOBS: usize = 100;
+
+/// The work a job does
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobDescription {
+    /// Merging the chunks of a partition into one
+    CompactPartition {
+        db_name: String,
+        partition_key: String,
+    },
+    /// Writing a chunk to parquet files
+    PersistChunk {
+        db_name: String,
+        partition_key: String,
+        chunk_id: u32,
+    },
+    /// Restoring a database from its WAL
+    ReplayWal { db_name: String },
+    /// Moving the series of a database which do not belong to the
+    /// shard `shard_id` under a new shard map to their shards
+    Reshard { db_name: String, shard_id: u32 },
+    /// Dropping the partitions of all databases which are past their
+    /// retention period
+    ApplyRetention,
+    /// Downsampling the partitions of all databases according to their
+    /// downsampling rules
+    Downsample,
+    /// Persisting and evicting the partitions of all databases which
+    /// have not been written to for their idle period
+    PersistIdle,
+}
+
+impl JobDescription {
+    /// The name of the database the job works on, if it works on one
+    pub fn db_name(&self) -> Option<&str> {
+        match self {
+            Self::CompactPartition { db_name, .. }
+            | Self::PersistChunk { db_name, .. }
+            | Self::ReplayWal { db_name }
+            | Self::Reshard { db_name, .. } => Some(db_name),
+            Self::ApplyRetention | Self::Downsample | Self::PersistIdle => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobStatus {
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+/// How many of the steps of a job are done
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Progress {
+    pub done: u64,
+    pub total: u64,
+}
+
+/// A snapshot of the state of a job
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct JobInfo {
+    pub id: JobId,
+    pub description: JobDescription,
+    pub status: JobStatus,
+    /// The error the job failed with, if it failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The progress of the job, for jobs made up of several steps
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<Progress>,
+    /// Counters reported by the job, such as the number of rows or
+    /// bytes it has processed
+    pub metrics: BTreeMap<String, u64>,
+    /// When the job started, in nanoseconds since the epoch
+    pub start_time: i64,
+    /// When the job finished, in nanoseconds since the epoch
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end_time: Option<i64>,
+}
+
+#[derive(Debug)]
+struct Job {
+    info: JobInfo,
+    abort_handle: AbortHandle,
+}
+
+#[derive(Debug, Default)]
+struct JobsState {
+    next_id: JobId,
+    jobs: BTreeMap<JobId, Job>,
+}
+
+/// The jobs of the server
+#[derive(Debug, Default)]
+pub struct Jobs {
+    state: Mutex<JobsState>,
+}
+
+impl Jobs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs `work` as a new job described by `description`, passing
+    /// it a tracker with which to report its progress. Returns the
+    /// result of `work`, or `None` if the job was cancelled.
+    pub async fn run<F, Fut, T, E>(
+        self: &Arc<Self>,
+        description: JobDescription,
+        work: F,
+    ) -> Option<Result<T, E>>
+    where
+        F: FnOnce(JobTracker) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Display,
+    {
+        let (abort_handle, abort_registration) = AbortHandle::new_pair();
+        let id = {
+            let mut state = self.state.lock().expect("mutex poisoned");
+            let id = state.next_id;
+            state.next_id += 1;
+            state.jobs.insert(
+                id,
+                Job {
+                    info: JobInfo {
+                        id,
+                        description,
+                        status: JobStatus::Running,
+                        error: None,
+                        progress: None,
+                        metrics: BTreeMap::new(),
+                        start_time: now(),
+                        end_time: None,
+                    },
+                    abort_handle,
+                },
+            );
+            id
+        };
+
+        let tracker = JobTracker {
+            jobs: Arc::clone(self),
+            id,
+        };
+        let _cancel_on_drop = CancelOnDrop { jobs: self, id };
+        let result = Abortable::new(work(tracker), abort_registration).await;
+
+        match &result {
+            Ok(Ok(_)) => self.finish(id, JobStatus::Completed, None),
+            Ok(Err(e)) => self.finish(id, JobStatus::Failed, Some(e.to_string())),
+            Err(_) => self.finish(id, JobStatus::Cancelled, None),
+        }
+
+        result.ok()
+    }
+
+    /// Returns all jobs, in the order they were started
+    pub fn list(&self) -> Vec<JobInfo> {
+        let state = self.state.lock().expect("mutex poisoned");
+        state.jobs.values().map(|job| job.info.clone()).collect()
+    }
+
+    /// Returns the job with id `id`
+    pub fn get(&self, id: JobId) -> Result<JobInfo> {
+        let state = self.state.lock().expect("mutex poisoned");
+        let job = state.jobs.get(&id).context(JobNotFound { id })?;
+        Ok(job.info.clone())
+    }
+
+    /// Cancels the running job with id `id`, returning it as it is
+    /// once cancelled
+    pub fn cancel(&self, id: JobId) -> Result<JobInfo> {
+        {
+            let state = self.state.lock().expect("mutex poisoned");
+            let job = state.jobs.get(&id).context(JobNotFound { id })?;
+            ensure!(job.info.status == JobStatus::Running, JobFinished { id });
+            job.abort_handle.abort();
+        }
+
+        self.finish(id, JobStatus::Cancelled, None);
+        self.get(id)
+    }
+
+    /// Applies `f` to the job with id `id`, if it is still known
+    fn update(&self, id: JobId, f: impl FnOnce(&mut JobInfo)) {
+        let mut state = self.state.lock().expect("mutex poisoned");
+        if let Some(job) = state.jobs.get_mut(&id) {
+            f(&mut job.info)
+        }
+    }
+
+    /// Records that the job with id `id` finished with `status`, if it
+    /// has not finished already, and forgets the oldest finished jobs
+    /// beyond `MAX_FINISHED_JOBS`
+    fn finish(&self, id: JobId, status: JobStatus, error: Option<String>) {
+        let mut state = self.state.lock().expect("mutex poisoned");
+        if let Some(job) = state.jobs.get_mut(&id) {
+            if job.info.status == JobStatus::Running {
+                job.info.status = status;
+                job.info.error = error;
+                job.info.end_time = Some(now());
+            }
+        }
+
+        let finished: Vec<_> = state
+            .jobs
+            .values()
+            .filter(|job| job.info.status != JobStatus::Running)
+            .map(|job| job.info.id)
+            .collect();
+        for id in finished
+            .iter()
+            .take(finished.len().saturating_sub(MAX_FINISHED_JOBS))
+        {
+            state.jobs.remove(id);
+        }
+    }
+}
+
+/// Records the job with id `id` as cancelled when dropped, unless it
+/// has finished already, so a job whose future is dropped before it
+/// finishes is not left running
+struct CancelOnDrop<'a> {
+    jobs: &'a Jobs,
+    id: JobId,
+}
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        self.jobs.finish(self.id, JobStatus::Cancelled, None)
+    }
+}
+
+/// Used by the work of a job to report its progress
+#[derive(Debug, Clone)]
+pub struct JobTracker {
+    jobs: Arc<Jobs>,
+    id: JobId,
+}
+
+impl JobTracker {
+    /// The id of the job
+    pub fn id(&self) -> JobId {
+        self.id
+    }
+
+    /// Records that `done` of the `total` steps of the job are done
+    pub fn set_progress(&self, done: u64, total: u64) {
+        self.jobs.update(self.id, |info| {
+            info.progress = Some(Progress { done, total })
+        })
+    }
+
+    /// Adds `value` to the metric of the job named `name`
+    pub fn add_metric(&self, name: &str, value: u64) {
+        self.jobs.update(self.id, |info| {
+            *info.metrics.entry(name.to_string()).or_default() += value
+        })
+    }
+}
+
+/// The current time, in nanoseconds since the epoch
+fn now() -> i64 {
+    chrono::Utc::now().timestamp_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::channel::oneshot;
+
+    fn description(db_name: &str) -> JobDescription {
+        JobDescription::ReplayWal {
+            db_name: db_name.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn completed_and_failed_jobs() {
+        let jobs = Arc::new(Jobs::new());
+
+        let result = jobs
+            .run(description("a"), |tracker| async move {
+                tracker.set_progress(1, 2);
+                tracker.add_metric("rows", 3);
+                tracker.add_metric("rows", 4);
+                tracker.set_progress(2, 2);
+                Ok::<_, String>(tracker.id())
+            })
+            .await;
+        assert_eq!(result, Some(Ok(0)));
+
+        let result = jobs
+            .run(description("b"), |_| async { Err::<(), _>("no WAL") })
+            .await;
+        assert_eq!(result, Some(Err("no WAL")));
+
+        let completed = jobs.get(0).unwrap();
+        assert_eq!(completed.status, JobStatus::Completed);
+        assert_eq!(completed.progress, Some(Progress { done: 2, total: 2 }));
+        assert_eq!(completed.metrics["rows"], 7);
+        assert!(completed.end_time.unwrap() >= completed.start_time);
+
+        let failed = jobs.get(1).unwrap();
+        assert_eq!(failed.status, JobStatus::Failed);
+        assert_eq!(failed.error.as_deref(), Some("no WAL"));
+
+        assert_eq!(jobs.list(), vec![completed, failed]);
+        assert!(matches!(jobs.get(2), Err(Error::JobNotFound { id: 2 })));
+        assert!(matches!(jobs.cancel(0), Err(Error::JobFinished { id: 0 })));
+    }
+
+    #[tokio::test]
+    async fn cancel_job() {
+        let jobs = Arc::new(Jobs::new());
+        let (started_tx, started_rx) = oneshot::channel();
+
+        let running = {
+            let jobs = Arc::clone(&jobs);
+            tokio::spawn(async move {
+                jobs.run(description("a"), |_| async move {
+                    started_tx.send(()).unwrap();
+                    futures::future::pending::<Result<(), String>>().await
+                })
+                .await
+            })
+        };
+        started_rx.await.unwrap();
+
+        assert_eq!(jobs.get(0).unwrap().status, JobStatus::Running);
+        let cancelled = jobs.cancel(0).unwrap();
+        assert_eq!(cancelled.status, JobStatus::Cancelled);
+        assert!(cancelled.end_time.is_some());
+
+        assert_eq!(running.await.unwrap(), None);
+        assert_eq!(jobs.get(0).unwrap(), cancelled);
+    }
+
+    #[tokio::test]
+    async fn dropped_job_is_cancelled() {
+        let jobs = Arc::new(Jobs::new());
+        let (started_tx, started_rx) = oneshot::channel();
+
+        let running = Box::pin(jobs.run(description("a"), |_| async move {
+            started_tx.send(()).unwrap();
+            futures::future::pending::<Result<(), String>>().await
+        }));
+        let running = futures::future::select(running, started_rx).await;
+        assert_eq!(jobs.get(0).unwrap().status, JobStatus::Running);
+
+        drop(running);
+        let cancelled = jobs.get(0).unwrap();
+        assert_eq!(cancelled.status, JobStatus::Cancelled);
+        assert!(cancelled.end_time.is_some());
+    }
+
+    #[tokio::test]
+    async fn finished_jobs_are_limited() {
+        let jobs = Arc::new(Jobs::new());
+        for _ in 0..MAX_FINISHED_JOBS + 5 {
+            jobs.run(description("a"), |_| async { Ok::<_, String>(()) })
+                .await;
+        }
+
+        let ids: Vec<_> = jobs.list().into_iter().map(|job| job.id).collect();
+        assert_eq!(ids.len(), MAX_FINISHED_JOBS);
+        assert_eq!(ids[0], 5);
+    }
+}
@@ -0,0 +1,122 @@
+//! This module changes which spans and events the server logs while
+//! it runs, so that, for example, an operator can turn on debug
+//! logging of one crate during an incident without restarting the
+//! server and losing its state.
+//!
+//! The filter is made up of directives in the syntax of `RUST_LOG`,
+//! such as `info,segment_store=debug`. It is installed as a layer of
+//! the global subscriber at startup, and replaced through the HTTP
+//! management API.
+
+use std::sync::Mutex;
+
+use snafu::{ResultExt, Snafu};
+use tracing_subscriber::{filter::ParseError, reload, EnvFilter, Registry};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid log filter '{}': {}", directives, source))]
+    InvalidDirectives {
+        directives: String,
+        source: ParseError,
+    },
+
+    #[snafu(display("Error replacing log filter: {}", source))]
+    ReplacingFilter { source: reload::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The layer of the subscriber which filters what is logged
+pub type FilterLayer = reload::Layer<EnvFilter, Registry>;
+
+/// Controls the filter of what is logged
+#[derive(Debug)]
+pub struct LogFilter {
+    handle: reload::Handle<EnvFilter, Registry>,
+    /// The directives the server started with
+    initial_directives: String,
+    /// The directives currently in effect
+    directives: Mutex<String>,
+}
+
+impl LogFilter {
+    /// Creates a filter of `directives`, returning it along with the
+    /// layer it controls, which must be installed in the subscriber
+    pub fn new(directives: &str) -> Result<(Self, FilterLayer)> {
+        let filter = parse(directives)?;
+        let (layer, handle) = reload::Layer::new(filter);
+
+        let log_filter = Self {
+            handle,
+            initial_directives: directives.to_string(),
+            directives: Mutex::new(directives.to_string()),
+        };
+        Ok((log_filter, layer))
+    }
+
+    /// The directives currently in effect
+    pub fn directives(&self) -> String {
+        self.directives.lock().expect("mutex poisoned").clone()
+    }
+
+    /// The directives the server started with
+    pub fn initial_directives(&self) -> &str {
+        &self.initial_directives
+    }
+
+    /// Replaces the filter with one of `directives`
+    pub fn set(&self, directives: &str) -> Result<()> {
+        let filter = parse(directives)?;
+
+        let mut current = self.directives.lock().expect("mutex poisoned");
+        self.handle.reload(filter).context(ReplacingFilter)?;
+        *current = directives.to_string();
+        Ok(())
+    }
+
+    /// Restores the filter the server started with
+    pub fn reset(&self) -> Result<()> {
+        self.set(&self.initial_directives)
+    }
+}
+
+fn parse(directives: &str) -> Result<EnvFilter> {
+    EnvFilter::try_new(directives).context(InvalidDirectives { directives })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_and_reset() {
+        let (log_filter, _layer) = LogFilter::new("warn").unwrap();
+        assert_eq!(log_filter.directives(), "warn");
+
+        log_filter.set("info,segment_store=debug").unwrap();
+        assert_eq!(log_filter.directives(), "info,segment_store=debug");
+        assert_eq!(log_filter.initial_directives(), "warn");
+
+        assert!(matches!(
+            log_filter.set("segment_store=loud"),
+            Err(Error::InvalidDirectives { .. })
+        ));
+        assert_eq!(log_filter.directives(), "info,segment_store=debug");
+
+        log_filter.reset().unwrap();
+        assert_eq!(log_filter.directives(), "warn");
+    }
+
+    #[test]
+    fn set_without_subscriber() {
+        let (log_filter, layer) = LogFilter::new("warn").unwrap();
+        drop(layer);
+
+        assert!(matches!(
+            log_filter.set("info"),
+            Err(Error::ReplacingFilter { .. })
+        ));
+        assert_eq!(log_filter.directives(), "warn");
+    }
+}
@@ -0,0 +1,557 @@
+//! This module contains the metrics registry of the server, which is
+//! exported in the Prometheus text format by `GET /metrics` for
+//! monitoring and alerting on service level objectives.
+//!
+//! Each storage RPC (`read_filter`, `read_group`, `tag_values` and so
+//! on) is recorded, labeled by the database it read, the RPC and its
+//! outcome:
+//!
+//! * `iox_storage_rpc_duration_seconds`, a histogram of the time from
+//!   the start of each RPC until the last message of its response was
+//!   sent (or it failed)
+//! * `iox_storage_rpc_response_bytes`, a histogram of the encoded size
+//!   of the messages of each response
+//! * `iox_storage_rpc_errors_total`, the number of RPCs which did not
+//!   succeed, by outcome
+//!
+//! The outcome is `ok`, `client_error` for requests the server
+//! rejected (such as an invalid predicate or an exhausted quota),
+//! `server_error` for requests that failed otherwise, or `cancelled`
+//! if the client went away before the whole response was sent.
+//!
+//! Components of the server whose metrics describe their current
+//! state, rather than events, register a `Collector`, which is asked
+//! for its metrics each time they are rendered. The databases export
+//! the number of chunks their queries have scanned and pruned, by
+//! database, as `iox_chunks_scanned_total` and `iox_chunks_pruned_total`.
+
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use futures::Stream;
+use tokio::sync::mpsc;
+use tonic::{Code, Status};
+use write_buffer::WriteBufferDatabases;
+
+/// The upper bounds of the buckets of the RPC duration histograms, in
+/// seconds
+pub const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The upper bounds of the buckets of the RPC response size histograms,
+/// in bytes
+pub const RESPONSE_BYTES_BUCKETS: &[f64] = &[
+    1024.0,
+    4096.0,
+    16_384.0,
+    65_536.0,
+    262_144.0,
+    1_048_576.0,
+    4_194_304.0,
+    16_777_216.0,
+    67_108_864.0,
+];
+
+/// How an RPC ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Outcome {
+    Ok,
+    ClientError,
+    ServerError,
+    Cancelled,
+}
+
+impl Outcome {
+    /// The outcome of an RPC which failed with `code`
+    pub fn from_code(code: Code) -> Self {
+        match code {
+            Code::Ok => Self::Ok,
+            Code::Cancelled => Self::Cancelled,
+            Code::InvalidArgument
+            | Code::NotFound
+            | Code::AlreadyExists
+            | Code::PermissionDenied
+            | Code::Unauthenticated
+            | Code::ResourceExhausted
+            | Code::FailedPrecondition
+            | Code::OutOfRange => Self::ClientError,
+            _ => Self::ServerError,
+        }
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ok => write!(f, "ok"),
+            Self::ClientError => write!(f, "client_error"),
+            Self::ServerError => write!(f, "server_error"),
+            Self::Cancelled => write!(f, "cancelled"),
+        }
+    }
+}
+
+/// A histogram of observed values, counted in buckets by their upper
+/// bounds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// The number of values in each bucket (not including those of
+    /// smaller buckets), and last those above every bound
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|&bound| value <= bound)
+            .unwrap_or_else(|| self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+    }
+
+    /// The number of values observed
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// Writes the histogram as the Prometheus metric `name`, with the
+    /// labels `labels` (already formatted as `name="value",...`)
+    fn render(&self, out: &mut String, name: &str, labels: &str) -> fmt::Result {
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                name, labels, bound, cumulative
+            )?;
+        }
+        writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            name,
+            labels,
+            self.count()
+        )?;
+        writeln!(out, "{}_sum{{{}}} {}", name, labels, self.sum)?;
+        writeln!(out, "{}_count{{{}}} {}", name, labels, self.count())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct RpcLabels {
+    db_name: String,
+    rpc: &'static str,
+    outcome: Outcome,
+}
+
+impl fmt::Display for RpcLabels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "db_name=\"{}\",rpc=\"{}\",outcome=\"{}\"",
+            escape_label_value(&self.db_name),
+            self.rpc,
+            self.outcome
+        )
+    }
+}
+
+#[derive(Debug)]
+struct RpcMetrics {
+    duration: Histogram,
+    response_bytes: Histogram,
+}
+
+/// A source of metrics which are read when the metrics are rendered
+pub trait Collector: fmt::Debug + Send + Sync + 'static {
+    /// Writes the current metrics of the collector to `out` in the
+    /// Prometheus text format
+    fn collect(&self, out: &mut String) -> fmt::Result;
+}
+
+/// The metrics of the server
+#[derive(Debug, Default)]
+pub struct Metrics {
+    storage_rpcs: Mutex<BTreeMap<RpcLabels, RpcMetrics>>,
+    collectors: Mutex<Vec<Arc<dyn Collector>>>,
+}
+
+impl Metrics {
+    /// Starts recording the storage RPC `rpc` of the database
+    /// `db_name`, which is recorded when the returned recorder finishes
+    /// or is dropped
+    pub fn storage_rpc(self: &Arc<Self>, rpc: &'static str, db_name: String) -> RpcRecorder {
+        RpcRecorder {
+            metrics: Arc::clone(self),
+            rpc,
+            db_name,
+            start: Instant::now(),
+            response_bytes: 0,
+            error: None,
+            finished: false,
+        }
+    }
+
+    /// Records that the storage RPC `rpc` of the database `db_name`
+    /// ended with `outcome` after `duration`, having sent
+    /// `response_bytes` bytes of messages
+    pub fn record_storage_rpc(
+        &self,
+        rpc: &'static str,
+        db_name: &str,
+        outcome: Outcome,
+        duration: Duration,
+        response_bytes: u64,
+    ) {
+        let labels = RpcLabels {
+            db_name: db_name.to_string(),
+            rpc,
+            outcome,
+        };
+        let mut storage_rpcs = self.storage_rpcs.lock().expect("mutex poisoned");
+        let metrics = storage_rpcs.entry(labels).or_insert_with(|| RpcMetrics {
+            duration: Histogram::new(DURATION_BUCKETS),
+            response_bytes: Histogram::new(RESPONSE_BYTES_BUCKETS),
+        });
+        metrics.duration.observe(duration.as_secs_f64());
+        metrics.response_bytes.observe(response_bytes as f64);
+    }
+
+    /// Registers `collector`, whose metrics are included each time the
+    /// metrics are rendered
+    pub fn register(&self, collector: Arc<dyn Collector>) {
+        self.collectors
+            .lock()
+            .expect("mutex poisoned")
+            .push(collector);
+    }
+
+    /// Returns the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        {
+            let storage_rpcs = self.storage_rpcs.lock().expect("mutex poisoned");
+            Self::render_storage_rpcs(&mut out, &storage_rpcs).expect("writing to a string");
+        }
+
+        let collectors = self.collectors.lock().expect("mutex poisoned").clone();
+        for collector in collectors {
+            collector.collect(&mut out).expect("writing to a string");
+        }
+        out
+    }
+
+    fn render_storage_rpcs(
+        out: &mut String,
+        storage_rpcs: &BTreeMap<RpcLabels, RpcMetrics>,
+    ) -> fmt::Result {
+        let name = "iox_storage_rpc_duration_seconds";
+        writeln!(
+            out,
+            "# HELP {} The time from the start of each storage RPC until its response was sent",
+            name
+        )?;
+        writeln!(out, "# TYPE {} histogram", name)?;
+        for (labels, metrics) in storage_rpcs {
+            metrics.duration.render(out, name, &labels.to_string())?;
+        }
+
+        let name = "iox_storage_rpc_response_bytes";
+        writeln!(
+            out,
+            "# HELP {} The encoded size of the messages of each storage RPC response",
+            name
+        )?;
+        writeln!(out, "# TYPE {} histogram", name)?;
+        for (labels, metrics) in storage_rpcs {
+            metrics
+                .response_bytes
+                .render(out, name, &labels.to_string())?;
+        }
+
+        let name = "iox_storage_rpc_errors_total";
+        writeln!(
+            out,
+            "# HELP {} The number of storage RPCs which did not succeed",
+            name
+        )?;
+        writeln!(out, "# TYPE {} counter", name)?;
+        for (labels, metrics) in storage_rpcs {
+            if labels.outcome != Outcome::Ok {
+                writeln!(out, "{}{{{}}} {}", name, labels, metrics.duration.count())?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Collector for WriteBufferDatabases {
+    fn collect(&self, out: &mut String) -> fmt::Result {
+        let chunk_pruning = self.chunk_pruning();
+
+        let name = "iox_chunks_scanned_total";
+        writeln!(
+            out,
+            "# HELP {} The number of chunks the queries of each database have scanned",
+            name
+        )?;
+        writeln!(out, "# TYPE {} counter", name)?;
+        for (db_name, scanned, _) in &chunk_pruning {
+            writeln!(
+                out,
+                "{}{{db_name=\"{}\"}} {}",
+                name,
+                escape_label_value(db_name),
+                scanned
+            )?;
+        }
+
+        let name = "iox_chunks_pruned_total";
+        writeln!(
+            out,
+            "# HELP {} The number of chunks the queries of each database have pruned",
+            name
+        )?;
+        writeln!(out, "# TYPE {} counter", name)?;
+        for (db_name, _, pruned) in &chunk_pruning {
+            writeln!(
+                out,
+                "{}{{db_name=\"{}\"}} {}",
+                name,
+                escape_label_value(db_name),
+                pruned
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Escapes a label value of the Prometheus text format
+pub fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+/// Records a storage RPC when it finishes: when the last message of its
+/// response has been sent, when it fails, or when it is dropped before
+/// either (as the client went away)
+#[derive(Debug)]
+pub struct RpcRecorder {
+    metrics: Arc<Metrics>,
+    rpc: &'static str,
+    db_name: String,
+    start: Instant,
+    response_bytes: u64,
+    /// The code of the first error sent in the response, if any
+    error: Option<Code>,
+    finished: bool,
+}
+
+impl RpcRecorder {
+    /// Returns the response of the RPC, the messages of `result` if it
+    /// succeeded, recording the RPC once the messages have been sent
+    pub fn respond<M>(
+        mut self,
+        result: Result<mpsc::Receiver<Result<M, Status>>, Status>,
+    ) -> Result<tonic::Response<MeteredStream<M>>, Status> {
+        match result {
+            Ok(rx) => Ok(tonic::Response::new(MeteredStream { rx, recorder: self })),
+            Err(status) => {
+                self.error = Some(status.code());
+                self.finish();
+                Err(status)
+            }
+        }
+    }
+
+    fn finish(&mut self) {
+        if self.finished {
+            return;
+        }
+        self.finished = true;
+
+        let outcome = self.error.map_or(Outcome::Ok, Outcome::from_code);
+        self.metrics.record_storage_rpc(
+            self.rpc,
+            &self.db_name,
+            outcome,
+            self.start.elapsed(),
+            self.response_bytes,
+        );
+    }
+}
+
+impl Drop for RpcRecorder {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.error = Some(Code::Cancelled);
+            self.finish();
+        }
+    }
+}
+
+/// The messages of the response to a storage RPC, counting their size
+/// and recording the RPC after the last one
+#[derive(Debug)]
+pub struct MeteredStream<M> {
+    rx: mpsc::Receiver<Result<M, Status>>,
+    recorder: RpcRecorder,
+}
+
+// the messages are never pinned, only the stream
+impl<M> Unpin for MeteredStream<M> {}
+
+impl<M: prost::Message> Stream for MeteredStream<M> {
+    type Item = Result<M, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let item = match Pin::new(&mut self.rx).poll_next(cx) {
+            Poll::Ready(item) => item,
+            Poll::Pending => return Poll::Pending,
+        };
+
+        match &item {
+            Some(Ok(message)) => self.recorder.response_bytes += message.encoded_len() as u64,
+            Some(Err(status)) => {
+                if self.recorder.error.is_none() {
+                    self.recorder.error = Some(status.code());
+                }
+            }
+            None => self.recorder.finish(),
+        }
+
+        Poll::Ready(item)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use generated_types::StringValuesResponse;
+
+    fn values(values: &[&str]) -> StringValuesResponse {
+        StringValuesResponse {
+            values: values.iter().map(|v| v.as_bytes().to_vec()).collect(),
+        }
+    }
+
+    #[test]
+    fn histogram() {
+        let mut histogram = Histogram::new(&[1.0, 10.0]);
+        for value in &[0.5, 1.0, 5.0, 20.0] {
+            histogram.observe(*value);
+        }
+        assert_eq!(histogram.count(), 4);
+
+        let mut out = String::new();
+        histogram.render(&mut out, "latency", r#"rpc="a""#).unwrap();
+        let expected = r#"latency_bucket{rpc="a",le="1"} 2
+latency_bucket{rpc="a",le="10"} 3
+latency_bucket{rpc="a",le="+Inf"} 4
+latency_sum{rpc="a"} 26.5
+latency_count{rpc="a"} 4
+"#;
+        assert_eq!(out, expected);
+    }
+
+    #[tokio::test]
+    async fn storage_rpcs() {
+        let metrics = Arc::new(Metrics::default());
+
+        // the RPC is recorded once its whole response is sent
+        let (mut tx, rx) = mpsc::channel(4);
+        let recorder = metrics.storage_rpc("tag_values", "my\"db".to_string());
+        let mut stream = recorder.respond(Ok(rx)).unwrap().into_inner();
+        let message = values(&["a", "b"]);
+        let size = prost::Message::encoded_len(&message);
+        tx.send(Ok(message)).await.unwrap();
+        drop(tx);
+        assert!(stream.next().await.unwrap().is_ok());
+        assert!(!metrics
+            .render()
+            .contains("iox_storage_rpc_duration_seconds_count"));
+        assert!(stream.next().await.is_none());
+
+        // as is one which fails, or which is dropped before the end
+        // of its response
+        let recorder = metrics.storage_rpc("read_filter", "mydb".to_string());
+        let status = recorder
+            .respond::<StringValuesResponse>(Err(Status::invalid_argument("bad predicate")))
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        let (_tx, rx) = mpsc::channel::<Result<StringValuesResponse, Status>>(4);
+        let recorder = metrics.storage_rpc("read_filter", "mydb".to_string());
+        drop(recorder.respond(Ok(rx)));
+
+        let rendered = metrics.render();
+        let lines: Vec<_> = rendered
+            .lines()
+            .filter(|line| line.contains("_count") || line.starts_with("iox_storage_rpc_errors"))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"iox_storage_rpc_duration_seconds_count{db_name="my\"db",rpc="tag_values",outcome="ok"} 1"#,
+                r#"iox_storage_rpc_duration_seconds_count{db_name="mydb",rpc="read_filter",outcome="client_error"} 1"#,
+                r#"iox_storage_rpc_duration_seconds_count{db_name="mydb",rpc="read_filter",outcome="cancelled"} 1"#,
+                r#"iox_storage_rpc_response_bytes_count{db_name="my\"db",rpc="tag_values",outcome="ok"} 1"#,
+                r#"iox_storage_rpc_response_bytes_count{db_name="mydb",rpc="read_filter",outcome="client_error"} 1"#,
+                r#"iox_storage_rpc_response_bytes_count{db_name="mydb",rpc="read_filter",outcome="cancelled"} 1"#,
+                r#"iox_storage_rpc_errors_total{db_name="mydb",rpc="read_filter",outcome="client_error"} 1"#,
+                r#"iox_storage_rpc_errors_total{db_name="mydb",rpc="read_filter",outcome="cancelled"} 1"#,
+            ]
+        );
+        assert!(rendered.contains(&format!(
+            r#"iox_storage_rpc_response_bytes_sum{{db_name="my\"db",rpc="tag_values",outcome="ok"}} {}"#,
+            size
+        )));
+    }
+
+    #[tokio::test]
+    async fn chunk_pruning() {
+        use storage::{Database, DatabaseStore};
+
+        let metrics = Metrics::default();
+        let store = Arc::new(WriteBufferDatabases::new(
+            test_helpers::tmp_dir().unwrap().into_path(),
+        ));
+        metrics.register(store.clone());
+
+        let db = store.db_or_create("mydb").await.unwrap();
+        let lines: Vec<_> = influxdb_line_protocol::parse_lines("cpu usage=1 10")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await.unwrap();
+        db.query("select * from cpu").await.unwrap();
+
+        let rendered = metrics.render();
+        assert!(rendered.contains(r#"iox_chunks_scanned_total{db_name="mydb"} 1"#));
+        assert!(rendered.contains(r#"iox_chunks_pruned_total{db_name="mydb"} 0"#));
+    }
+}
@@ -0,0 +1,263 @@
+//! This module reconciles a server to a declarative spec of the
+//! databases it should have, each with its rules (which include its
+//! retention period), and of the shards a router forwards writes to.
+//!
+//! Applying a spec is idempotent: databases missing from the server
+//! are created, those whose rules differ from the spec are updated, and
+//! applying the same spec again changes nothing. Databases the spec
+//! doesn't name are left alone unless it sets `prune`, in which case
+//! they are deleted along with their data. The whole spec is validated
+//! before anything is changed, so an invalid spec changes nothing.
+
+use std::collections::BTreeMap;
+
+use data_types::database_rules::{self, DatabaseRules};
+use serde::{Deserialize, Serialize};
+use snafu::{ResultExt, Snafu};
+use storage::{Database, DatabaseStore};
+use tracing::info;
+
+use super::router::{self, Router, Shard, ShardMap};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid rules for database {}: {}", name, source))]
+    InvalidRules {
+        name: String,
+        source: database_rules::Error,
+    },
+
+    #[snafu(display("Invalid shards: {}", source))]
+    InvalidShards { source: router::Error },
+
+    #[snafu(display("Error applying the spec of database {}: {}", name, source))]
+    ApplyingDatabase {
+        name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Error applying shards: {}", source))]
+    ApplyingShards { source: router::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The databases and shards a server should have
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Spec {
+    /// The rules of each database, by name
+    #[serde(default)]
+    pub databases: BTreeMap<String, DatabaseRules>,
+
+    /// The shards writes are forwarded to. `None` leaves the shards
+    /// unchanged.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shards: Option<Vec<Shard>>,
+
+    /// Whether databases not in `databases` are deleted
+    #[serde(default)]
+    pub prune: bool,
+}
+
+/// The changes made (or for a dry run, that would be made) to
+/// reconcile a server to a spec
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Changes {
+    pub dry_run: bool,
+    pub created: Vec<String>,
+    pub updated: Vec<String>,
+    pub unchanged: Vec<String>,
+    pub deleted: Vec<String>,
+    pub shards_changed: bool,
+}
+
+impl Changes {
+    /// Whether the server already matched the spec
+    pub fn is_empty(&self) -> bool {
+        self.created.is_empty()
+            && self.updated.is_empty()
+            && self.deleted.is_empty()
+            && !self.shards_changed
+    }
+}
+
+/// Reconciles the databases of `storage` and the shards of `router` to
+/// `spec`, returning the changes made. With `dry_run` nothing is
+/// changed, and the changes that would have been made are returned.
+pub async fn apply<T: DatabaseStore>(
+    storage: &T,
+    router: &Router,
+    spec: Spec,
+    dry_run: bool,
+) -> Result<Changes> {
+    for (name, rules) in &spec.databases {
+        rules.validate().context(InvalidRules { name })?;
+    }
+    if let Some(shards) = &spec.shards {
+        ShardMap::new(0, shards.clone()).context(InvalidShards)?;
+    }
+
+    let mut changes = Changes {
+        dry_run,
+        ..Default::default()
+    };
+
+    let existing = storage.db_names_sorted().await;
+    if spec.prune {
+        changes.deleted = existing
+            .iter()
+            .filter(|name| !spec.databases.contains_key(*name))
+            .cloned()
+            .collect();
+    }
+
+    let mut updates = vec![];
+    for (name, rules) in spec.databases {
+        match storage.db(&name).await {
+            Some(db) => {
+                if db.rules().await == rules {
+                    changes.unchanged.push(name);
+                } else {
+                    changes.updated.push(name.clone());
+                    updates.push((name, Some(db), rules));
+                }
+            }
+            None => {
+                changes.created.push(name.clone());
+                updates.push((name, None, rules));
+            }
+        }
+    }
+
+    let shards = spec
+        .shards
+        .filter(|shards| *shards != router.shard_map().shards);
+    changes.shards_changed = shards.is_some();
+
+    if dry_run {
+        return Ok(changes);
+    }
+
+    for name in &changes.deleted {
+        storage
+            .delete_db(name)
+            .await
+            .map_err(|e| Box::new(e) as _)
+            .context(ApplyingDatabase { name })?;
+        info!("Deleted database {} not in the applied spec", name);
+    }
+
+    for (name, db, rules) in updates {
+        match db {
+            Some(db) => {
+                db.set_rules(rules.clone())
+                    .await
+                    .map_err(|e| Box::new(e) as _)
+                    .context(ApplyingDatabase { name: &name })?;
+                info!("Updated rules of database {} to {:?}", name, rules);
+            }
+            None => {
+                storage
+                    .create_db(&name, rules.clone())
+                    .await
+                    .map_err(|e| Box::new(e) as _)
+                    .context(ApplyingDatabase { name: &name })?;
+                info!("Created database {} with rules {:?}", name, rules);
+            }
+        }
+    }
+
+    if let Some(shards) = shards {
+        let shard_map = router.set_shards(shards, None).context(ApplyingShards)?;
+        info!("Set shard map to version {}", shard_map.version);
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage::test::TestDatabaseStore;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+    #[tokio::test]
+    async fn apply_is_idempotent() -> Result<(), TestError> {
+        let storage = TestDatabaseStore::new();
+        let router = Router::default();
+        storage.create_db("old", DatabaseRules::default()).await?;
+        storage.create_db("kept", DatabaseRules::default()).await?;
+
+        let spec: Spec = serde_json::from_str(
+            r#"{
+                "databases": {
+                    "kept": {"retention_period_seconds": 3600},
+                    "new": {}
+                },
+                "shards": [{"id": 1, "writers": ["http://writer1:8080"]}]
+            }"#,
+        )?;
+
+        // a dry run reports the changes without making them
+        let changes = apply(&storage, &router, spec.clone(), true).await?;
+        assert_eq!(
+            changes,
+            Changes {
+                dry_run: true,
+                created: vec!["new".to_string()],
+                updated: vec!["kept".to_string()],
+                unchanged: vec![],
+                deleted: vec![],
+                shards_changed: true,
+            }
+        );
+        assert_eq!(storage.db_names_sorted().await, vec!["kept", "old"]);
+        assert!(router.shard_map().shards.is_empty());
+
+        let changes = apply(&storage, &router, spec.clone(), false).await?;
+        assert!(!changes.dry_run);
+        assert_eq!(changes.created, vec!["new"]);
+        assert_eq!(changes.updated, vec!["kept"]);
+        assert!(changes.shards_changed);
+        assert_eq!(storage.db_names_sorted().await, vec!["kept", "new", "old"]);
+        let rules = storage.db("kept").await.unwrap().rules().await;
+        assert_eq!(rules.retention_period_seconds, Some(3600));
+        assert_eq!(router.shard_map().version, 1);
+
+        // applying the same spec again changes nothing
+        let changes = apply(&storage, &router, spec.clone(), false).await?;
+        assert!(changes.is_empty());
+        assert_eq!(changes.unchanged, vec!["kept", "new"]);
+        assert_eq!(router.shard_map().version, 1);
+
+        // databases not in the spec are only deleted when pruning
+        let spec = Spec {
+            prune: true,
+            ..spec
+        };
+        let changes = apply(&storage, &router, spec, false).await?;
+        assert_eq!(changes.deleted, vec!["old"]);
+        assert_eq!(storage.db_names_sorted().await, vec!["kept", "new"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn invalid_spec_changes_nothing() -> Result<(), TestError> {
+        let storage = TestDatabaseStore::new();
+        let router = Router::default();
+
+        let spec: Spec = serde_json::from_str(
+            r#"{
+                "databases": {"new": {}},
+                "shards": [{"id": 1, "writers": []}]
+            }"#,
+        )?;
+        let err = apply(&storage, &router, spec, false).await.unwrap_err();
+        assert!(matches!(err, Error::InvalidShards { .. }));
+        assert!(storage.db_names_sorted().await.is_empty());
+
+        Ok(())
+    }
+}
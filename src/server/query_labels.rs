@@ -0,0 +1,165 @@
+//! This module contains the labels clients attach to their queries to
+//! say where they came from, such as the dashboard or user a query
+//! was run for, so that expensive queries can be attributed to their
+//! source.
+//!
+//! Labels are sent as comma separated `key=value` pairs in the
+//! `X-IOx-Query-Labels` header, which may be repeated:
+//!
+//! ```text
+//! X-IOx-Query-Labels: dashboard=cpu-overview, user=alice
+//! ```
+//!
+//! They are recorded with the query in `system.queries`, written to
+//! the log line of the query, and recorded on the span the query runs
+//! in, so they are exported with its trace.
+
+use std::{collections::BTreeMap, fmt};
+
+use http::{header::ToStrError, HeaderMap};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Error reading the {} header as UTF-8: {}",
+        QUERY_LABELS_HEADER,
+        source
+    ))]
+    ReadingHeader { source: ToStrError },
+
+    #[snafu(display("Invalid query label '{}': expected key=value", label))]
+    InvalidLabel { label: String },
+
+    #[snafu(display("Too many query labels: {}, expected at most {}", count, MAX_LABELS))]
+    TooManyLabels { count: usize },
+
+    #[snafu(display(
+        "Query label '{}' is too long: expected at most {} bytes",
+        key,
+        MAX_LABEL_LENGTH
+    ))]
+    LabelTooLong { key: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The header the labels of a query are sent in
+pub const QUERY_LABELS_HEADER: &str = "x-iox-query-labels";
+
+/// The most labels a query may have
+pub const MAX_LABELS: usize = 16;
+
+/// The longest, in bytes, the key and value of a label may be together
+pub const MAX_LABEL_LENGTH: usize = 256;
+
+/// The labels of a query, by key
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QueryLabels(BTreeMap<String, String>);
+
+impl QueryLabels {
+    /// Reads the labels from every `X-IOx-Query-Labels` header in
+    /// `headers`. A key repeated is given its last value.
+    pub fn from_headers(headers: &HeaderMap) -> Result<Self> {
+        let mut labels = BTreeMap::new();
+        for value in headers.get_all(QUERY_LABELS_HEADER) {
+            let value = value.to_str().context(ReadingHeader)?;
+            for label in value.split(',').map(str::trim).filter(|l| !l.is_empty()) {
+                let mut parts = label.splitn(2, '=');
+                let key = parts.next().unwrap_or_default().trim();
+                let value = parts.next().context(InvalidLabel { label })?.trim();
+                ensure!(!key.is_empty(), InvalidLabel { label });
+                ensure!(
+                    key.len() + value.len() <= MAX_LABEL_LENGTH,
+                    LabelTooLong { key }
+                );
+
+                labels.insert(key.to_string(), value.to_string());
+            }
+        }
+
+        ensure!(
+            labels.len() <= MAX_LABELS,
+            TooManyLabels {
+                count: labels.len()
+            }
+        );
+
+        Ok(Self(labels))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Returns the value of the label `key`, if the query has it
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(String::as_str)
+    }
+}
+
+/// Formats the labels as they are sent, sorted by key
+impl fmt::Display for QueryLabels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (key, value)) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ",")?;
+            }
+            write!(f, "{}={}", key, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn headers(values: &[&'static str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(QUERY_LABELS_HEADER, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn from_headers() {
+        let labels = QueryLabels::from_headers(&headers(&[])).unwrap();
+        assert!(labels.is_empty());
+        assert_eq!(labels.to_string(), "");
+
+        let labels = QueryLabels::from_headers(&headers(&[
+            "user=alice, dashboard = cpu-overview",
+            "panel=3,user=bob,",
+        ]))
+        .unwrap();
+        assert_eq!(labels.get("dashboard"), Some("cpu-overview"));
+        assert_eq!(labels.get("user"), Some("bob"));
+        assert_eq!(labels.get("org"), None);
+        assert_eq!(
+            labels.to_string(),
+            "dashboard=cpu-overview,panel=3,user=bob"
+        );
+
+        let err = QueryLabels::from_headers(&headers(&["user"])).unwrap_err();
+        assert!(matches!(err, Error::InvalidLabel { .. }));
+        let err = QueryLabels::from_headers(&headers(&["=alice"])).unwrap_err();
+        assert!(matches!(err, Error::InvalidLabel { .. }));
+
+        let many: String = (0..=MAX_LABELS)
+            .map(|i| format!("key{}=value", i))
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut headers = HeaderMap::new();
+        headers.insert(QUERY_LABELS_HEADER, HeaderValue::from_str(&many).unwrap());
+        let err = QueryLabels::from_headers(&headers).unwrap_err();
+        assert!(matches!(err, Error::TooManyLabels { count } if count == MAX_LABELS + 1));
+
+        let long = format!("user={}", "a".repeat(MAX_LABEL_LENGTH));
+        headers.insert(QUERY_LABELS_HEADER, HeaderValue::from_str(&long).unwrap());
+        let err = QueryLabels::from_headers(&headers).unwrap_err();
+        assert!(matches!(err, Error::LabelTooLong { .. }));
+    }
+}
@@ -0,0 +1,250 @@
+//! This module contains support for bound parameters in queries.
+//!
+//! Parameters are referred to in query text as `$name` and their
+//! values are provided separately as a JSON object, such as
+//! `{"host": "server01", "limit": 10}`. Keeping values out of the
+//! query text avoids clients having to escape them, and means the
+//! same query text can be reused with different values.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use serde_json::Value;
+use snafu::{OptionExt, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid query parameters '{}': {}", params, source))]
+    InvalidParams {
+        params: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Unsupported value for parameter ${}: expected a string, number, boolean or null",
+        name
+    ))]
+    UnsupportedValue { name: String },
+
+    #[snafu(display("No value provided for bound parameter ${}", name))]
+    MissingParam { name: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The values of bound parameters, by name
+pub type Params = BTreeMap<String, Value>;
+
+/// Parses the JSON object of parameter values
+pub fn parse_params(params: &str) -> Result<Params> {
+    serde_json::from_str(params).context(InvalidParams { params })
+}
+
+/// Replaces each `$name` placeholder in `sql` with the SQL literal for
+/// the value of `name` in `params`. Placeholders inside string
+/// literals, quoted identifiers and comments are left unchanged.
+pub fn bind_sql(sql: &str, params: &Params) -> Result<String> {
+    let mut bound = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' | '"' => {
+                // A doubled quote is an escaped quote, which is
+                // handled by leaving and then re-entering the literal
+                bound.push(c);
+                for next in &mut chars {
+                    bound.push(next);
+                    if next == c {
+                        break;
+                    }
+                }
+            }
+            '-' if matches!(chars.peek(), Some('-')) => {
+                bound.push(c);
+                for next in &mut chars {
+                    bound.push(next);
+                    if next == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if matches!(chars.peek(), Some('*')) => {
+                chars.next();
+                bound.push_str("/*");
+                let mut previous = None;
+                for next in &mut chars {
+                    bound.push(next);
+                    if previous == Some('*') && next == '/' {
+                        break;
+                    }
+                    previous = Some(next);
+                }
+            }
+            '$' => {
+                let mut name = String::new();
+                while let Some(&next) = chars.peek() {
+                    if next.is_ascii_alphanumeric() || next == '_' {
+                        name.push(next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+
+                if name.is_empty() {
+                    bound.push(c);
+                } else {
+                    let value = params.get(&name).context(MissingParam { name: &name })?;
+                    bound.push_str(&sql_literal(&name, value)?);
+                }
+            }
+            _ => bound.push(c),
+        }
+    }
+
+    Ok(bound)
+}
+
+/// Returns the names of the parameters in `params` that no `$name`
+/// placeholder in `query` refers to, which are likely mistakes
+pub fn unused_params(query: &str, params: &Params) -> Vec<String> {
+    let mut referred = BTreeSet::new();
+    let mut rest = query;
+    while let Some(index) = rest.find('$') {
+        rest = &rest[index + 1..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or_else(|| rest.len());
+        referred.insert(&rest[..len]);
+    }
+
+    params
+        .keys()
+        .filter(|name| !referred.contains(name.as_str()))
+        .cloned()
+        .collect()
+}
+
+/// Converts `params` into InfluxQL literals for use with
+/// `SelectStatement::bind`
+pub fn influxql_params(params: &Params) -> Result<HashMap<String, influxql::ast::Expr>> {
+    use influxql::ast::Expr;
+
+    params
+        .iter()
+        .map(|(name, value)| {
+            let expr = match value {
+                Value::String(value) => Expr::String(value.clone()),
+                Value::Bool(value) => Expr::Boolean(*value),
+                Value::Number(number) => match number.as_i64() {
+                    Some(value) => Expr::Integer(value),
+                    None => Expr::Float(number.as_f64().unwrap_or(f64::NAN)),
+                },
+                _ => return UnsupportedValue { name }.fail(),
+            };
+            Ok((name.clone(), expr))
+        })
+        .collect()
+}
+
+fn sql_literal(name: &str, value: &Value) -> Result<String> {
+    match value {
+        Value::Null => Ok("NULL".to_string()),
+        Value::Bool(value) => Ok(value.to_string()),
+        Value::Number(value) => Ok(value.to_string()),
+        Value::String(value) => Ok(format!("'{}'", value.replace('\'', "''"))),
+        Value::Array(_) | Value::Object(_) => UnsupportedValue { name }.fail(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(json: &str) -> Params {
+        parse_params(json).unwrap()
+    }
+
+    #[test]
+    fn test_bind_sql() {
+        let params = params(r#"{"host": "o'brien", "min": 1.5, "limit": 10, "on": true}"#);
+
+        assert_eq!(
+            bind_sql(
+                "select * from cpu where host = $host and usage > $min and up = $on limit $limit",
+                &params
+            )
+            .unwrap(),
+            "select * from cpu where host = 'o''brien' and usage > 1.5 and up = true limit 10"
+        );
+    }
+
+    #[test]
+    fn test_bind_sql_ignores_quoted_text() {
+        let params = params(r#"{"host": "a"}"#);
+
+        assert_eq!(
+            bind_sql(
+                r#"select '$host', 'it''s $host', "$host" from cpu -- $host
+where host = $host /* $host */ and cost = $"#,
+                &params
+            )
+            .unwrap(),
+            r#"select '$host', 'it''s $host', "$host" from cpu -- $host
+where host = 'a' /* $host */ and cost = $"#
+        );
+    }
+
+    #[test]
+    fn test_unused_params() {
+        let params = params(r#"{"host": "a", "host_id": 1, "region": "b"}"#);
+
+        assert_eq!(
+            unused_params(
+                "select * from cpu where host = $host and id = $host_id",
+                &params
+            ),
+            vec!["region"]
+        );
+        assert_eq!(
+            unused_params("SELECT usage FROM cpu WHERE region = $region", &params),
+            vec!["host", "host_id"]
+        );
+        assert!(unused_params("select $", &Params::new()).is_empty());
+    }
+
+    #[test]
+    fn test_bind_sql_errors() {
+        assert_eq!(
+            bind_sql("select * from cpu where host = $host", &Params::new())
+                .unwrap_err()
+                .to_string(),
+            "No value provided for bound parameter $host"
+        );
+
+        assert_eq!(
+            bind_sql(
+                "select * from cpu where host = $host",
+                &params(r#"{"host": ["a"]}"#)
+            )
+            .unwrap_err()
+            .to_string(),
+            "Unsupported value for parameter $host: expected a string, number, boolean or null"
+        );
+
+        assert!(parse_params("[1, 2]")
+            .unwrap_err()
+            .to_string()
+            .starts_with("Invalid query parameters '[1, 2]'"));
+    }
+
+    #[test]
+    fn test_influxql_params() {
+        use influxql::ast::Expr;
+
+        let params = influxql_params(&params(r#"{"host": "a", "n": 3, "f": 0.5}"#)).unwrap();
+        assert_eq!(params["host"], Expr::String("a".into()));
+        assert_eq!(params["n"], Expr::Integer(3));
+        assert_eq!(params["f"], Expr::Float(0.5));
+    }
+}
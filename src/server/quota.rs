@@ -0,0 +1,487 @@
+//! This module contains the quotas that limit the resources each
+//! database may use, so that one tenant can not starve the others:
+//!
+//! * the rate at which data may be written, in bytes per second
+//! * the rate at which queries may be run, per second
+//! * the number of distinct series that may be written
+//! * the memory each query of the gRPC storage API may use to buffer
+//!   results
+//! * the number of series, and bytes of frames, each `read_filter` or
+//!   `read_group` request may return
+//!
+//! Rates are enforced with a token bucket allowing bursts of up to
+//! one second's worth of requests. A single request larger than that
+//! is still accepted when the bucket is not empty, after which
+//! requests are rejected until the bucket has refilled.
+//!
+//! Quotas and usage are kept in memory, so series are counted from
+//! when the quota was set (or the server started).
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeMap, HashSet},
+    hash::{Hash, Hasher},
+    sync::RwLock,
+    time::Instant,
+};
+
+use influxdb_line_protocol::ParsedLine;
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Database '{}' exceeded its write quota of {} bytes per second",
+        db_name,
+        limit
+    ))]
+    WriteRateExceeded { db_name: String, limit: u64 },
+
+    #[snafu(display(
+        "Database '{}' exceeded its query quota of {} queries per second",
+        db_name,
+        limit
+    ))]
+    QueryRateExceeded { db_name: String, limit: u64 },
+
+    #[snafu(display("Database '{}' exceeded its quota of {} series", db_name, limit))]
+    SeriesLimitExceeded { db_name: String, limit: usize },
+
+    #[snafu(display("No quota configured for database '{}'", db_name))]
+    QuotaNotFound { db_name: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Converts the error into the appropriate tonic status
+    pub fn to_status(&self) -> tonic::Status {
+        match &self {
+            Self::WriteRateExceeded { .. }
+            | Self::QueryRateExceeded { .. }
+            | Self::SeriesLimitExceeded { .. } => {
+                tonic::Status::resource_exhausted(self.to_string())
+            }
+            Self::QuotaNotFound { .. } => tonic::Status::not_found(self.to_string()),
+        }
+    }
+}
+
+/// The limits on the resources of a database. Resources without a
+/// limit are unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct Quota {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write_bytes_per_sec: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queries_per_sec: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_series: Option<usize>,
+    /// The maximum number of bytes of results each query may buffer
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_query_memory: Option<usize>,
+    /// The maximum number of series each read request may return
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_read_series: Option<usize>,
+    /// The maximum number of bytes of frames each read request may
+    /// return
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_read_frame_bytes: Option<usize>,
+}
+
+/// The resources a database used since its quota was set
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub write_bytes: u64,
+    pub queries: u64,
+    /// The number of distinct series written, if `max_series` is set
+    pub series: usize,
+    pub rejected_writes: u64,
+    pub rejected_queries: u64,
+}
+
+/// Limits the rate of some quantity with a token bucket holding up to
+/// one second's worth of tokens
+#[derive(Debug)]
+struct RateLimiter {
+    per_sec: u64,
+    /// May be negative after a request larger than the bucket
+    available: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    fn new(per_sec: u64, now: Instant) -> Self {
+        Self {
+            per_sec,
+            available: per_sec as f64,
+            last_refill: now,
+        }
+    }
+
+    /// Takes `amount` tokens, returning false if the bucket is empty
+    fn try_acquire(&mut self, amount: u64, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        self.available =
+            (self.available + elapsed.as_secs_f64() * self.per_sec as f64).min(self.per_sec as f64);
+        self.last_refill = now;
+
+        if self.available <= 0.0 {
+            return false;
+        }
+        self.available -= amount as f64;
+        true
+    }
+}
+
+#[derive(Debug)]
+struct DatabaseQuota {
+    quota: Quota,
+    usage: Usage,
+    write_limiter: Option<RateLimiter>,
+    query_limiter: Option<RateLimiter>,
+    /// Hashes of the series keys written, only tracked if
+    /// `max_series` is set
+    series: HashSet<u64>,
+}
+
+impl DatabaseQuota {
+    fn new(quota: Quota, now: Instant) -> Self {
+        Self {
+            quota,
+            usage: Usage::default(),
+            write_limiter: quota
+                .write_bytes_per_sec
+                .map(|limit| RateLimiter::new(limit, now)),
+            query_limiter: quota
+                .queries_per_sec
+                .map(|limit| RateLimiter::new(limit, now)),
+            series: HashSet::new(),
+        }
+    }
+}
+
+/// The quotas of each database
+#[derive(Debug, Default)]
+pub struct Quotas {
+    databases: RwLock<BTreeMap<String, DatabaseQuota>>,
+}
+
+impl Quotas {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the quota of the database `db_name`, resetting its usage
+    pub fn set(&self, db_name: impl Into<String>, quota: Quota) {
+        self.databases
+            .write()
+            .expect("quota lock poisoned")
+            .insert(db_name.into(), DatabaseQuota::new(quota, Instant::now()));
+    }
+
+    /// Removes the quota of the database `db_name`
+    pub fn remove(&self, db_name: &str) -> Result<()> {
+        self.databases
+            .write()
+            .expect("quota lock poisoned")
+            .remove(db_name)
+            .map(|_| ())
+            .context(QuotaNotFound { db_name })
+    }
+
+    /// Returns the quota of the database `db_name` and its usage
+    pub fn get(&self, db_name: &str) -> Result<(Quota, Usage)> {
+        self.databases
+            .read()
+            .expect("quota lock poisoned")
+            .get(db_name)
+            .map(|database| (database.quota, database.usage))
+            .context(QuotaNotFound { db_name })
+    }
+
+    /// Returns the quotas and usage of all databases with a quota, in
+    /// order of database name
+    pub fn all(&self) -> Vec<(String, Quota, Usage)> {
+        self.databases
+            .read()
+            .expect("quota lock poisoned")
+            .iter()
+            .map(|(db_name, database)| (db_name.clone(), database.quota, database.usage))
+            .collect()
+    }
+
+    /// Returns the maximum number of bytes of results a query of the
+    /// database `db_name` may buffer, if limited
+    pub fn max_query_memory(&self, db_name: &str) -> Option<usize> {
+        self.databases
+            .read()
+            .expect("quota lock poisoned")
+            .get(db_name)
+            .and_then(|database| database.quota.max_query_memory)
+    }
+
+    /// Returns the maximum number of series, and of bytes of frames, a
+    /// read request of the database `db_name` may return, if limited
+    pub fn max_read_results(&self, db_name: &str) -> (Option<usize>, Option<usize>) {
+        self.databases
+            .read()
+            .expect("quota lock poisoned")
+            .get(db_name)
+            .map(|database| {
+                (
+                    database.quota.max_read_series,
+                    database.quota.max_read_frame_bytes,
+                )
+            })
+            .unwrap_or_default()
+    }
+
+    /// Returns an error if writing `lines`, `bytes` long, to the
+    /// database `db_name` would exceed its quota. Otherwise the write
+    /// is counted as usage.
+    pub fn check_write(&self, db_name: &str, bytes: usize, lines: &[ParsedLine<'_>]) -> Result<()> {
+        self.check_write_at(db_name, bytes, lines, Instant::now())
+    }
+
+    fn check_write_at(
+        &self,
+        db_name: &str,
+        bytes: usize,
+        lines: &[ParsedLine<'_>],
+        now: Instant,
+    ) -> Result<()> {
+        let mut databases = self.databases.write().expect("quota lock poisoned");
+        let database = match databases.get_mut(db_name) {
+            Some(database) => database,
+            None => return Ok(()),
+        };
+
+        // check the series limit first, so that rejected writes don't
+        // use up the write rate
+        let mut new_series = HashSet::new();
+        if let Some(limit) = database.quota.max_series {
+            new_series.extend(
+                lines
+                    .iter()
+                    .map(series_hash)
+                    .filter(|hash| !database.series.contains(hash)),
+            );
+
+            if database.series.len() + new_series.len() > limit {
+                database.usage.rejected_writes += 1;
+                return SeriesLimitExceeded { db_name, limit }.fail();
+            }
+        }
+
+        if let Some(limiter) = &mut database.write_limiter {
+            if !limiter.try_acquire(bytes as u64, now) {
+                database.usage.rejected_writes += 1;
+                let limit = limiter.per_sec;
+                return WriteRateExceeded { db_name, limit }.fail();
+            }
+        }
+
+        database.series.extend(new_series);
+        database.usage.series = database.series.len();
+        database.usage.write_bytes += bytes as u64;
+        Ok(())
+    }
+
+    /// Returns an error if running a query of the database `db_name`
+    /// would exceed its quota. Otherwise the query is counted as
+    /// usage.
+    pub fn check_query(&self, db_name: &str) -> Result<()> {
+        self.check_query_at(db_name, Instant::now())
+    }
+
+    fn check_query_at(&self, db_name: &str, now: Instant) -> Result<()> {
+        let mut databases = self.databases.write().expect("quota lock poisoned");
+        let database = match databases.get_mut(db_name) {
+            Some(database) => database,
+            None => return Ok(()),
+        };
+
+        if let Some(limiter) = &mut database.query_limiter {
+            if !limiter.try_acquire(1, now) {
+                database.usage.rejected_queries += 1;
+                let limit = limiter.per_sec;
+                return QueryRateExceeded { db_name, limit }.fail();
+            }
+        }
+
+        database.usage.queries += 1;
+        Ok(())
+    }
+}
+
+/// Returns a hash of the measurement and (sorted) tags of `line`,
+/// identifying its series. Only the hashes are kept, to bound the
+/// memory used to count series.
+fn series_hash(line: &ParsedLine<'_>) -> u64 {
+    let mut tags = line
+        .series
+        .tag_set
+        .iter()
+        .flatten()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect::<Vec<_>>();
+    tags.sort_unstable();
+
+    let mut hasher = DefaultHasher::new();
+    line.series.measurement.as_str().hash(&mut hasher);
+    tags.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb_line_protocol::parse_lines;
+    use std::time::Duration;
+
+    fn lines(lp: &str) -> Vec<ParsedLine<'_>> {
+        parse_lines(lp).map(|line| line.unwrap()).collect()
+    }
+
+    #[test]
+    fn test_no_quota() {
+        let quotas = Quotas::new();
+        let lp = "cpu,host=a usage=1 10";
+        quotas.check_write("mydb", lp.len(), &lines(lp)).unwrap();
+        quotas.check_query("mydb").unwrap();
+
+        assert!(quotas.get("mydb").is_err());
+        assert!(quotas.max_query_memory("mydb").is_none());
+        assert!(quotas.remove("mydb").is_err());
+    }
+
+    #[test]
+    fn test_write_rate() {
+        let quotas = Quotas::new();
+        quotas.set(
+            "mydb",
+            Quota {
+                write_bytes_per_sec: Some(100),
+                ..Default::default()
+            },
+        );
+        let now = Instant::now();
+
+        quotas.check_write_at("mydb", 60, &[], now).unwrap();
+        // a write larger than the remaining quota is accepted...
+        quotas.check_write_at("mydb", 60, &[], now).unwrap();
+        // ...but none after it, until the quota has refilled
+        let err = quotas.check_write_at("mydb", 1, &[], now).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Database 'mydb' exceeded its write quota of 100 bytes per second"
+        );
+        quotas
+            .check_write_at("mydb", 1, &[], now + Duration::from_millis(500))
+            .unwrap();
+
+        // other databases are not limited
+        quotas.check_write_at("otherdb", 1000, &[], now).unwrap();
+
+        let (_, usage) = quotas.get("mydb").unwrap();
+        assert_eq!(usage.write_bytes, 121);
+        assert_eq!(usage.rejected_writes, 1);
+    }
+
+    #[test]
+    fn test_query_rate() {
+        let quotas = Quotas::new();
+        quotas.set(
+            "mydb",
+            Quota {
+                queries_per_sec: Some(2),
+                ..Default::default()
+            },
+        );
+        let now = Instant::now();
+
+        quotas.check_query_at("mydb", now).unwrap();
+        quotas.check_query_at("mydb", now).unwrap();
+        let err = quotas.check_query_at("mydb", now).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Database 'mydb' exceeded its query quota of 2 queries per second"
+        );
+        quotas
+            .check_query_at("mydb", now + Duration::from_secs(1))
+            .unwrap();
+
+        let (_, usage) = quotas.get("mydb").unwrap();
+        assert_eq!(usage.queries, 3);
+        assert_eq!(usage.rejected_queries, 1);
+    }
+
+    #[test]
+    fn test_max_series() {
+        let quotas = Quotas::new();
+        quotas.set(
+            "mydb",
+            Quota {
+                max_series: Some(2),
+                ..Default::default()
+            },
+        );
+
+        let lp = "cpu,host=a,region=west usage=1 10\n\
+                  cpu,region=west,host=a usage=2 20\n\
+                  cpu,host=b usage=3 10";
+        quotas.check_write("mydb", lp.len(), &lines(lp)).unwrap();
+
+        // existing series may still be written
+        let lp = "cpu,host=b usage=4 30";
+        quotas.check_write("mydb", lp.len(), &lines(lp)).unwrap();
+
+        let lp = "cpu,host=b usage=4 40\nmem,host=b free=1 40";
+        let err = quotas
+            .check_write("mydb", lp.len(), &lines(lp))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Database 'mydb' exceeded its quota of 2 series"
+        );
+
+        let (_, usage) = quotas.get("mydb").unwrap();
+        assert_eq!(usage.series, 2);
+        assert_eq!(usage.rejected_writes, 1);
+    }
+
+    #[test]
+    fn test_set_and_remove() {
+        let quotas = Quotas::new();
+        let quota = Quota {
+            max_query_memory: Some(1000),
+            max_read_series: Some(10),
+            ..Default::default()
+        };
+        quotas.set("b", quota);
+        quotas.set("a", Quota::default());
+
+        assert_eq!(quotas.max_query_memory("b"), Some(1000));
+        assert_eq!(quotas.max_read_results("b"), (Some(10), None));
+        assert_eq!(quotas.max_read_results("c"), (None, None));
+        assert_eq!(
+            quotas
+                .all()
+                .into_iter()
+                .map(|(db_name, quota, _)| (db_name, quota))
+                .collect::<Vec<_>>(),
+            vec![
+                ("a".to_string(), Quota::default()),
+                ("b".to_string(), quota)
+            ]
+        );
+
+        quotas.remove("b").unwrap();
+        assert!(quotas.get("b").is_err());
+        assert_eq!(
+            quotas.remove("b").unwrap_err().to_string(),
+            "No quota configured for database 'b'"
+        );
+    }
+}
@@ -0,0 +1,589 @@
+//! This module replicates the writes accepted by this server to its
+//! peers, so that a warm standby can be kept up to date.
+//!
+//! Each accepted write is wrapped in a `ReplicatedWrite`, identified
+//! by the writer id of this server and the next of its sequence
+//! numbers, and queued for each peer. A task per peer sends the writes
+//! queued for it, in order, to the replicated write endpoint of the
+//! peer's HTTP API, retrying each with exponential backoff until the
+//! peer acknowledges it. Writes are therefore delivered at least once.
+//! If the peers require API tokens, the writes are sent with the
+//! configured replication token, which must be authorized to write
+//! the replicated databases.
+//!
+//! The queues are only kept in memory, so writes not yet acknowledged
+//! by a peer when this server stops are not replicated to it. Each
+//! queue holds at most a configured number of writes: once a peer
+//! falls that far behind, further writes are dropped for it (and
+//! counted) rather than holding up the writes to this server. The
+//! state of each queue is exported as metrics (`iox_replication_*`,
+//! labeled by peer).
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use data_types::{
+    data::{batch_to_replicated_write, split_lines_into_write_entry_partitions, ReplicatedWrite},
+    database_rules::DatabaseRules,
+};
+use hyper::{client::HttpConnector, Body, Method};
+use influxdb_line_protocol::ParsedLine;
+use serde::Serialize;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{info, warn};
+
+use super::{
+    auth,
+    metrics::{escape_label_value, Collector},
+    telemetry,
+};
+
+/// The path, relative to a database in the HTTP API, that replicated
+/// writes are sent to
+pub const REPLICATED_WRITE_PATH: &str = "replicated_write";
+
+/// How long to wait before retrying a write a peer did not
+/// acknowledge for the first time
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest to wait before retrying a write a peer did not
+/// acknowledge
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// The number of writes queued for a peer, if no other is configured,
+/// beyond which further writes are dropped for it
+pub const DEFAULT_QUEUE_SIZE: usize = 10_000;
+
+pub type PeerError = Box<dyn std::error::Error + Send + Sync + 'static>;
+
+/// Sends replicated writes to peers. This is a trait so that peers
+/// can be replaced in tests.
+#[tonic::async_trait]
+pub trait PeerClient: std::fmt::Debug + Send + Sync + 'static {
+    /// Sends `write` to the database `db_name` of the peer at `url`,
+    /// returning once the peer has acknowledged it
+    async fn send(
+        &self,
+        url: &str,
+        db_name: &str,
+        write: &ReplicatedWrite,
+    ) -> Result<(), PeerError>;
+}
+
+/// Sends replicated writes to the HTTP API of peers, over plain HTTP
+#[derive(Debug, Default)]
+pub struct HttpPeerClient {
+    client: hyper::Client<HttpConnector>,
+    /// The API token the writes are sent with, if any
+    token: Option<String>,
+}
+
+impl HttpPeerClient {
+    /// Creates a client which sends writes with the API token `token`,
+    /// if any
+    pub fn new(token: Option<String>) -> Self {
+        Self {
+            client: Default::default(),
+            token,
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl PeerClient for HttpPeerClient {
+    async fn send(
+        &self,
+        url: &str,
+        db_name: &str,
+        write: &ReplicatedWrite,
+    ) -> Result<(), PeerError> {
+        let uri = format!(
+            "{}/iox/api/v1/databases/{}/{}",
+            url.trim_end_matches('/'),
+            db_name,
+            REPLICATED_WRITE_PATH
+        );
+        let mut request = hyper::Request::builder()
+            .method(Method::POST)
+            .uri(uri)
+            .header(http::header::CONTENT_TYPE, "application/octet-stream");
+        if let Some(token) = &self.token {
+            request = request.header(auth::AUTHORIZATION, format!("Token {}", token));
+        }
+        let mut request = request.body(Body::from(write.data.clone()))?;
+        telemetry::inject_context(request.headers_mut());
+
+        let response = self.client.request(request).await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            return Err(format!(
+                "peer responded with {}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+/// The state of replication to a peer
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct PeerStatus {
+    pub url: String,
+    /// The number of writes queued for the peer which it has not yet
+    /// acknowledged
+    pub pending: u64,
+    /// The number of writes the peer has acknowledged
+    pub acknowledged: u64,
+    /// The number of writes dropped for the peer, as its queue was full
+    pub dropped: u64,
+    /// The sequence number of the last write queued for the peer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_queued_sequence: Option<u64>,
+    /// The sequence number of the last write the peer acknowledged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_acknowledged_sequence: Option<u64>,
+    /// The number of attempts to send a write to the peer which failed
+    pub failed_attempts: u64,
+    /// The error of the last failed attempt, cleared once a write is
+    /// acknowledged
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    /// How far behind this server the peer is: the number of
+    /// nanoseconds since the oldest write it has not acknowledged was
+    /// queued
+    pub lag_nanos: i64,
+}
+
+#[derive(Debug)]
+struct QueuedWrite {
+    db_name: String,
+    write: Arc<ReplicatedWrite>,
+    sequence: u64,
+}
+
+#[derive(Debug, Default)]
+struct PeerState {
+    status: PeerStatus,
+    /// When each pending write was queued, oldest first
+    queued_times: VecDeque<i64>,
+}
+
+#[derive(Debug)]
+struct Peer {
+    sender: Mutex<mpsc::Sender<QueuedWrite>>,
+    state: Arc<Mutex<PeerState>>,
+}
+
+/// Replicates the writes accepted by this server to its peers
+#[derive(Debug)]
+pub struct Replicator {
+    writer_id: u32,
+    next_sequence: AtomicU64,
+    peers: Vec<Peer>,
+}
+
+impl Replicator {
+    /// Creates a replicator which does not replicate writes anywhere
+    pub fn disabled() -> Self {
+        Self {
+            writer_id: 0,
+            next_sequence: AtomicU64::new(1),
+            peers: vec![],
+        }
+    }
+
+    /// Creates a replicator which replicates writes to the HTTP API of
+    /// the peers at `urls`, identifying them as written by `writer_id`
+    /// and sending them with the API token `token`, if any
+    pub fn new(writer_id: u32, urls: Vec<String>, token: Option<String>) -> Self {
+        Self::with_client(
+            writer_id,
+            urls,
+            DEFAULT_QUEUE_SIZE,
+            Arc::new(HttpPeerClient::new(token)),
+        )
+    }
+
+    /// Creates a replicator which replicates writes to the peers at
+    /// `urls` with `client`, queueing at most `queue_size` writes for
+    /// each. Must be called within a tokio runtime, as a task is
+    /// spawned for each peer.
+    pub fn with_client(
+        writer_id: u32,
+        urls: Vec<String>,
+        queue_size: usize,
+        client: Arc<dyn PeerClient>,
+    ) -> Self {
+        let peers = urls
+            .into_iter()
+            .map(|url| {
+                let (sender, receiver) = mpsc::channel(queue_size.max(1));
+                let state = Arc::new(Mutex::new(PeerState {
+                    status: PeerStatus {
+                        url: url.clone(),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                }));
+
+                tokio::spawn(send_to_peer(
+                    url,
+                    Arc::clone(&client),
+                    receiver,
+                    Arc::clone(&state),
+                ));
+
+                Peer {
+                    sender: Mutex::new(sender),
+                    state,
+                }
+            })
+            .collect();
+
+        Self {
+            writer_id,
+            next_sequence: AtomicU64::new(1),
+            peers,
+        }
+    }
+
+    /// The writer id this server's writes are replicated as
+    pub fn writer_id(&self) -> u32 {
+        self.writer_id
+    }
+
+    /// Queues `lines`, written to the database `db_name` with rules
+    /// `rules`, to be replicated to every peer
+    pub fn replicate(&self, db_name: &str, rules: &DatabaseRules, lines: &[ParsedLine<'_>]) {
+        if self.peers.is_empty() {
+            return;
+        }
+
+        let default_time = chrono::Utc::now();
+        let batch = split_lines_into_write_entry_partitions(
+            |line| write_buffer::partition_key(line, rules, &default_time),
+            lines,
+        );
+        let sequence = self.next_sequence.fetch_add(1, Ordering::SeqCst);
+        let write = Arc::new(batch_to_replicated_write(self.writer_id, sequence, &batch));
+
+        let now = now();
+        for peer in &self.peers {
+            let queued = QueuedWrite {
+                db_name: db_name.to_string(),
+                write: Arc::clone(&write),
+                sequence,
+            };
+
+            // the state is updated while queueing, so that the task
+            // sending the write can not record it as acknowledged first
+            let mut state = peer.state.lock().expect("mutex poisoned");
+            match peer.sender.lock().expect("mutex poisoned").try_send(queued) {
+                Ok(()) => {
+                    state.status.pending += 1;
+                    state.status.last_queued_sequence = Some(sequence);
+                    state.queued_times.push_back(now);
+                }
+                Err(TrySendError::Full(_)) => {
+                    state.status.dropped += 1;
+                    warn!(
+                        "replication queue of {} is full, write {} dropped",
+                        state.status.url, sequence
+                    );
+                }
+                Err(TrySendError::Closed(_)) => {
+                    warn!(
+                        "replication task has stopped, write {} not queued",
+                        sequence
+                    );
+                }
+            }
+        }
+    }
+
+    /// Returns the state of replication to each peer
+    pub fn status(&self) -> Vec<PeerStatus> {
+        let now = now();
+        self.peers
+            .iter()
+            .map(|peer| {
+                let state = peer.state.lock().expect("mutex poisoned");
+                PeerStatus {
+                    lag_nanos: state
+                        .queued_times
+                        .front()
+                        .map_or(0, |queued| now.saturating_sub(*queued)),
+                    ..state.status.clone()
+                }
+            })
+            .collect()
+    }
+}
+
+impl Collector for Replicator {
+    fn collect(&self, out: &mut String) -> fmt::Result {
+        let status = self.status();
+        let metrics: [(&str, &str, &str, fn(&PeerStatus) -> f64); 5] = [
+            (
+                "iox_replication_pending_writes",
+                "gauge",
+                "The number of writes queued for each peer which it has not acknowledged",
+                |peer| peer.pending as f64,
+            ),
+            (
+                "iox_replication_acknowledged_writes_total",
+                "counter",
+                "The number of writes each peer has acknowledged",
+                |peer| peer.acknowledged as f64,
+            ),
+            (
+                "iox_replication_dropped_writes_total",
+                "counter",
+                "The number of writes dropped for each peer as its queue was full",
+                |peer| peer.dropped as f64,
+            ),
+            (
+                "iox_replication_failed_attempts_total",
+                "counter",
+                "The number of attempts to send a write to each peer which failed",
+                |peer| peer.failed_attempts as f64,
+            ),
+            (
+                "iox_replication_lag_seconds",
+                "gauge",
+                "The time since the oldest write each peer has not acknowledged was queued",
+                |peer| peer.lag_nanos as f64 / 1e9,
+            ),
+        ];
+
+        for (name, kind, help, value) in metrics.iter() {
+            writeln!(out, "# HELP {} {}", name, help)?;
+            writeln!(out, "# TYPE {} {}", name, kind)?;
+            for peer in &status {
+                writeln!(
+                    out,
+                    "{}{{peer=\"{}\"}} {}",
+                    name,
+                    escape_label_value(&peer.url),
+                    value(peer)
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends each write received on `receiver` to the peer at `url` in
+/// turn, retrying until it is acknowledged, and records the progress
+/// in `state`
+async fn send_to_peer(
+    url: String,
+    client: Arc<dyn PeerClient>,
+    mut receiver: mpsc::Receiver<QueuedWrite>,
+    state: Arc<Mutex<PeerState>>,
+) {
+    while let Some(queued) = receiver.recv().await {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            match client.send(&url, &queued.db_name, &queued.write).await {
+                Ok(()) => {
+                    let mut state = state.lock().expect("mutex poisoned");
+                    state.status.pending -= 1;
+                    state.status.acknowledged += 1;
+                    state.status.last_acknowledged_sequence = Some(queued.sequence);
+                    state.status.last_error = None;
+                    state.queued_times.pop_front();
+                    break;
+                }
+                Err(e) => {
+                    warn!(
+                        "error replicating write {} to {}, retrying in {:?}: {}",
+                        queued.sequence, url, backoff, e
+                    );
+                    {
+                        let mut state = state.lock().expect("mutex poisoned");
+                        state.status.failed_attempts += 1;
+                        state.status.last_error = Some(e.to_string());
+                    }
+
+                    tokio::time::delay_for(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    info!("replication to {} stopped", url);
+}
+
+/// The current time, in nanoseconds since the epoch
+fn now() -> i64 {
+    chrono::Utc::now().timestamp_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb_line_protocol::parse_lines;
+
+    /// A peer which fails the first `failures` attempts to send it a
+    /// write, then records the writes it is sent
+    #[derive(Debug, Default)]
+    struct TestPeerClient {
+        failures: Mutex<usize>,
+        received: Mutex<Vec<(String, String, u32, u64)>>,
+    }
+
+    #[tonic::async_trait]
+    impl PeerClient for TestPeerClient {
+        async fn send(
+            &self,
+            url: &str,
+            db_name: &str,
+            write: &ReplicatedWrite,
+        ) -> Result<(), PeerError> {
+            {
+                let mut failures = self.failures.lock().unwrap();
+                if *failures > 0 {
+                    *failures -= 1;
+                    return Err("peer unavailable".into());
+                }
+            }
+
+            let (writer, sequence) = write.writer_and_sequence();
+            self.received.lock().unwrap().push((
+                url.to_string(),
+                db_name.to_string(),
+                writer,
+                sequence,
+            ));
+            Ok(())
+        }
+    }
+
+    async fn wait_for_acknowledged(replicator: &Replicator, acknowledged: u64) {
+        for _ in 0..100 {
+            if replicator
+                .status()
+                .iter()
+                .all(|peer| peer.acknowledged == acknowledged)
+            {
+                return;
+            }
+            tokio::time::delay_for(Duration::from_millis(20)).await;
+        }
+        panic!("writes not acknowledged: {:?}", replicator.status());
+    }
+
+    #[tokio::test]
+    async fn replicates_to_all_peers() {
+        let client = Arc::new(TestPeerClient::default());
+        let replicator = Replicator::with_client(
+            7,
+            vec!["http://a".to_string(), "http://b".to_string()],
+            DEFAULT_QUEUE_SIZE,
+            client.clone(),
+        );
+
+        let lines: Vec<_> = parse_lines("cpu usage=1 10").map(|l| l.unwrap()).collect();
+        replicator.replicate("mydb", &DatabaseRules::default(), &lines);
+        replicator.replicate("otherdb", &DatabaseRules::default(), &lines);
+        wait_for_acknowledged(&replicator, 2).await;
+
+        let mut received = client.received.lock().unwrap().clone();
+        received.sort();
+        assert_eq!(
+            received,
+            vec![
+                ("http://a".to_string(), "mydb".to_string(), 7, 1),
+                ("http://a".to_string(), "otherdb".to_string(), 7, 2),
+                ("http://b".to_string(), "mydb".to_string(), 7, 1),
+                ("http://b".to_string(), "otherdb".to_string(), 7, 2),
+            ]
+        );
+
+        let status = replicator.status();
+        assert_eq!(status[0].url, "http://a");
+        assert_eq!(status[0].pending, 0);
+        assert_eq!(status[0].last_queued_sequence, Some(2));
+        assert_eq!(status[0].last_acknowledged_sequence, Some(2));
+        assert_eq!(status[0].lag_nanos, 0);
+    }
+
+    #[tokio::test]
+    async fn retries_until_acknowledged() {
+        let client = Arc::new(TestPeerClient {
+            failures: Mutex::new(2),
+            ..Default::default()
+        });
+        let replicator = Replicator::with_client(
+            1,
+            vec!["http://a".to_string()],
+            DEFAULT_QUEUE_SIZE,
+            client.clone(),
+        );
+
+        let lines: Vec<_> = parse_lines("cpu usage=1 10").map(|l| l.unwrap()).collect();
+        replicator.replicate("mydb", &DatabaseRules::default(), &lines);
+        wait_for_acknowledged(&replicator, 1).await;
+
+        let status = &replicator.status()[0];
+        assert_eq!(status.failed_attempts, 2);
+        assert_eq!(status.last_error, None);
+        assert_eq!(client.received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn drops_writes_when_queue_full() {
+        let client = Arc::new(TestPeerClient::default());
+        let replicator =
+            Replicator::with_client(1, vec!["http://a".to_string()], 2, client.clone());
+
+        // the task sending the writes does not run until this test
+        // yields, so only the first two writes fit in the queue
+        let lines: Vec<_> = parse_lines("cpu usage=1 10").map(|l| l.unwrap()).collect();
+        for _ in 0..4 {
+            replicator.replicate("mydb", &DatabaseRules::default(), &lines);
+        }
+        let status = &replicator.status()[0];
+        assert_eq!(status.pending, 2);
+        assert_eq!(status.dropped, 2);
+        assert_eq!(status.last_queued_sequence, Some(2));
+
+        wait_for_acknowledged(&replicator, 2).await;
+        let received: Vec<_> = client
+            .received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, _, _, sequence)| *sequence)
+            .collect();
+        assert_eq!(received, vec![1, 2]);
+
+        let mut out = String::new();
+        replicator.collect(&mut out).unwrap();
+        assert!(out.contains(r#"iox_replication_acknowledged_writes_total{peer="http://a"} 2"#));
+        assert!(out.contains(r#"iox_replication_dropped_writes_total{peer="http://a"} 2"#));
+        assert!(out.contains(r#"iox_replication_pending_writes{peer="http://a"} 0"#));
+    }
+
+    #[test]
+    fn disabled_replicator_has_no_peers() {
+        let replicator = Replicator::disabled();
+        let lines: Vec<_> = parse_lines("cpu usage=1 10").map(|l| l.unwrap()).collect();
+        replicator.replicate("mydb", &DatabaseRules::default(), &lines);
+        assert!(replicator.status().is_empty());
+    }
+}
@@ -0,0 +1,501 @@
+//! This module contains the roles a server can take in a cluster, as
+//! groundwork for scaling writes horizontally.
+//!
+//! A standalone server (the default) stores the writes it accepts. In
+//! a cluster, a router validates the writes it accepts, splits their
+//! lines between shards, and forwards the lines of each shard to every
+//! writer of the shard, storing nothing itself, so it rejects queries.
+//! Each writer owns the data of one shard, configured when it starts,
+//! and rejects writes of lines which belong to another shard under its
+//! shard map. It otherwise behaves like a standalone server.
+//!
+//! Lines are assigned to shards by consistent hashing of their series
+//! keys (see the `sharding` module). The shards of a router, and their
+//! writers, are set with the management API. Each change to them
+//! increments the version of the shard map, and a change may be made
+//! conditional on the version it was based on, so that concurrent
+//! changes are not lost.
+//!
+//! When shards are added or removed, the series which now belong to
+//! another shard are moved there by resharding each writer: streaming
+//! the lines of its series which belong to another shard under the new
+//! shard map to the writers of that shard, a partition at a time, and
+//! then deleting them from the writer. The writers are given the new
+//! shard map first, so that they accept the lines of the series moved
+//! to their shards.
+
+use std::{fmt, str::FromStr, sync::RwLock};
+
+use hyper::{client::HttpConnector, header::HeaderValue, Body, Method};
+use influxdb_line_protocol::ParsedLine;
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tracing::debug;
+
+use super::{sharding::HashRing, telemetry};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Invalid server mode '{}': expected standalone, router or writer",
+        mode
+    ))]
+    InvalidMode { mode: String },
+
+    #[snafu(display("Invalid shard map: {}", reason))]
+    InvalidShardMap { reason: String },
+
+    #[snafu(display(
+        "Shard map is at version {}, not the expected version {}",
+        actual,
+        expected
+    ))]
+    ShardMapVersionMismatch { expected: u64, actual: u64 },
+
+    #[snafu(display("No shards are configured to route writes to"))]
+    NoShards {},
+
+    #[snafu(display("A writer must be configured with the shard it owns"))]
+    MissingShardId {},
+
+    #[snafu(display(
+        "Line '{}' belongs to shard {}, not shard {} of this writer",
+        line,
+        line_shard,
+        shard_id
+    ))]
+    NotOwned {
+        line: String,
+        line_shard: u32,
+        shard_id: u32,
+    },
+
+    #[snafu(display(
+        "Error forwarding write to shard {} at {}: {}",
+        shard_id,
+        writer,
+        source
+    ))]
+    ForwardingWrite {
+        shard_id: u32,
+        writer: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The role of a server in a cluster
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// Stores the writes it accepts
+    Standalone,
+    /// Forwards the writes it accepts to the writers of their shards
+    Router,
+    /// Stores the writes of the shard it owns
+    Writer,
+}
+
+impl Default for Mode {
+    fn default() -> Self {
+        Self::Standalone
+    }
+}
+
+impl FromStr for Mode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "standalone" => Ok(Self::Standalone),
+            "router" => Ok(Self::Router),
+            "writer" => Ok(Self::Writer),
+            _ => InvalidMode { mode: s }.fail(),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Standalone => write!(f, "standalone"),
+            Self::Router => write!(f, "router"),
+            Self::Writer => write!(f, "writer"),
+        }
+    }
+}
+
+/// A shard, and the base URLs of the HTTP APIs of its writers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Shard {
+    pub id: u32,
+    pub writers: Vec<String>,
+}
+
+/// The shards writes are routed to
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ShardMap {
+    /// Incremented on each change to the shards
+    pub version: u64,
+    pub shards: Vec<Shard>,
+    #[serde(skip)]
+    ring: HashRing,
+}
+
+impl ShardMap {
+    /// Creates a shard map of `shards` at version `version`, checking
+    /// that every shard has a writer and that no shard id is repeated
+    pub fn new(version: u64, shards: Vec<Shard>) -> Result<Self> {
+        for (i, shard) in shards.iter().enumerate() {
+            ensure!(
+                !shard.writers.is_empty(),
+                InvalidShardMap {
+                    reason: format!("shard {} has no writers", shard.id),
+                }
+            );
+            ensure!(
+                !shards[..i].iter().any(|other| other.id == shard.id),
+                InvalidShardMap {
+                    reason: format!("shard {} is listed more than once", shard.id),
+                }
+            );
+        }
+
+        let ids: Vec<_> = shards.iter().map(|shard| shard.id).collect();
+        Ok(Self {
+            version,
+            shards,
+            ring: HashRing::new(&ids),
+        })
+    }
+
+    /// Returns the shard the series of the line belongs to, or `None`
+    /// if there are no shards
+    pub fn shard_for(&self, line: &ParsedLine<'_>) -> Option<&Shard> {
+        self.ring
+            .shard_index_for(line)
+            .map(|index| &self.shards[index])
+    }
+}
+
+/// The role of this server, and the shards it routes writes to when
+/// it is a router, or the shards of the lines it accepts when it is a
+/// writer
+#[derive(Debug)]
+pub struct Router {
+    mode: Mode,
+    /// The shard a writer owns
+    shard_id: Option<u32>,
+    shard_map: RwLock<ShardMap>,
+    client: hyper::Client<HttpConnector>,
+}
+
+impl Default for Router {
+    fn default() -> Self {
+        Self::new(Mode::Standalone)
+    }
+}
+
+impl Router {
+    pub fn new(mode: Mode) -> Self {
+        Self {
+            mode,
+            shard_id: None,
+            shard_map: Default::default(),
+            client: Default::default(),
+        }
+    }
+
+    /// Creates the router of a writer which owns the shard `shard_id`
+    pub fn writer(shard_id: u32) -> Self {
+        Self {
+            shard_id: Some(shard_id),
+            ..Self::new(Mode::Writer)
+        }
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// The shard this server owns, if it is a writer
+    pub fn shard_id(&self) -> Option<u32> {
+        self.shard_id
+    }
+
+    /// Whether writes are forwarded to writers rather than stored
+    pub fn is_router(&self) -> bool {
+        self.mode == Mode::Router
+    }
+
+    /// Returns an error if this server is a writer and any of `lines`
+    /// belongs to a shard it does not own under its shard map, which
+    /// must have been set
+    pub fn check_owned(&self, lines: &[ParsedLine<'_>]) -> Result<()> {
+        if self.mode != Mode::Writer {
+            return Ok(());
+        }
+        let shard_id = self.shard_id.context(MissingShardId)?;
+
+        let shard_map = self.shard_map.read().expect("lock poisoned");
+        ensure!(!shard_map.shards.is_empty(), NoShards);
+        for line in lines {
+            let line_shard = shard_map.shard_for(line).context(NoShards)?.id;
+            ensure!(
+                line_shard == shard_id,
+                NotOwned {
+                    line: line.to_string(),
+                    line_shard,
+                    shard_id,
+                }
+            );
+        }
+
+        Ok(())
+    }
+
+    pub fn shard_map(&self) -> ShardMap {
+        self.shard_map.read().expect("lock poisoned").clone()
+    }
+
+    /// Replaces the shards writes are routed to, returning the new
+    /// shard map. If `expected_version` is given, the shards are only
+    /// replaced if the shard map is still at that version.
+    pub fn set_shards(
+        &self,
+        shards: Vec<Shard>,
+        expected_version: Option<u64>,
+    ) -> Result<ShardMap> {
+        let mut shard_map = self.shard_map.write().expect("lock poisoned");
+        if let Some(expected) = expected_version {
+            ensure!(
+                shard_map.version == expected,
+                ShardMapVersionMismatch {
+                    expected,
+                    actual: shard_map.version,
+                }
+            );
+        }
+
+        *shard_map = ShardMap::new(shard_map.version + 1, shards)?;
+        Ok(shard_map.clone())
+    }
+
+    /// Forwards each of `lines` to every writer of its shard, as a
+    /// write with the query string `query` (which names the org and
+    /// bucket) and `authorization` header of the original write
+    pub async fn forward(
+        &self,
+        query: &str,
+        authorization: Option<HeaderValue>,
+        lines: &[ParsedLine<'_>],
+    ) -> Result<()> {
+        let shard_map = self.shard_map();
+        ensure!(!shard_map.shards.is_empty(), NoShards);
+
+        let lines: Vec<_> = lines.iter().collect();
+        self.forward_to(&shard_map, query, authorization.as_ref(), &lines)
+            .await
+    }
+
+    /// Forwards each of `lines` to every writer of its shard under
+    /// `shard_map`, which need not be the shard map of this server
+    pub async fn forward_to(
+        &self,
+        shard_map: &ShardMap,
+        query: &str,
+        authorization: Option<&HeaderValue>,
+        lines: &[&ParsedLine<'_>],
+    ) -> Result<()> {
+        let mut shard_lines: Vec<String> = vec![String::new(); shard_map.shards.len()];
+        for line in lines {
+            let index = shard_map.ring.shard_index_for(line).context(NoShards)?;
+            shard_lines[index].push_str(&line.to_string());
+            shard_lines[index].push('\n');
+        }
+
+        for (shard, body) in shard_map.shards.iter().zip(shard_lines) {
+            if body.is_empty() {
+                continue;
+            }
+
+            for writer in &shard.writers {
+                debug!("Forwarding write to shard {} at {}", shard.id, writer);
+                self.send(writer, query, authorization, body.clone())
+                    .await
+                    .context(ForwardingWrite {
+                        shard_id: shard.id,
+                        writer,
+                    })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends `body` as a write to the writer at `writer`
+    async fn send(
+        &self,
+        writer: &str,
+        query: &str,
+        authorization: Option<&HeaderValue>,
+        body: String,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let uri = format!("{}/api/v2/write?{}", writer.trim_end_matches('/'), query);
+        let mut request = hyper::Request::builder().method(Method::POST).uri(uri);
+        if let Some(authorization) = authorization {
+            request = request.header(http::header::AUTHORIZATION, authorization);
+        }
+        let mut request = request.body(Body::from(body))?;
+        telemetry::inject_context(request.headers_mut());
+
+        let response = self.client.request(request).await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            return Err(format!(
+                "writer responded with {}: {}",
+                status,
+                String::from_utf8_lossy(&body)
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb_line_protocol::parse_lines;
+
+    fn shard(id: u32, writers: &[&str]) -> Shard {
+        Shard {
+            id,
+            writers: writers.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn parse_mode() {
+        assert_eq!("router".parse::<Mode>().unwrap(), Mode::Router);
+        assert_eq!("writer".parse::<Mode>().unwrap(), Mode::Writer);
+        assert_eq!(Mode::Standalone.to_string(), "standalone");
+        assert!(matches!(
+            "leader".parse::<Mode>(),
+            Err(Error::InvalidMode { .. })
+        ));
+    }
+
+    #[test]
+    fn set_shards() {
+        let router = Router::new(Mode::Router);
+        assert_eq!(router.shard_map(), ShardMap::default());
+
+        let shard_map = router
+            .set_shards(vec![shard(1, &["http://a"]), shard(2, &["http://b"])], None)
+            .unwrap();
+        assert_eq!(shard_map.version, 1);
+        assert_eq!(shard_map.shards.len(), 2);
+
+        assert!(matches!(
+            router.set_shards(vec![shard(1, &[])], None),
+            Err(Error::InvalidShardMap { .. })
+        ));
+        assert!(matches!(
+            router.set_shards(vec![shard(1, &["http://a"]), shard(1, &["http://b"])], None),
+            Err(Error::InvalidShardMap { .. })
+        ));
+        assert_eq!(router.shard_map(), shard_map);
+    }
+
+    #[test]
+    fn set_shards_at_version() {
+        let router = Router::new(Mode::Router);
+        router
+            .set_shards(vec![shard(1, &["http://a"])], Some(0))
+            .unwrap();
+
+        assert!(matches!(
+            router.set_shards(vec![shard(2, &["http://b"])], Some(0)),
+            Err(Error::ShardMapVersionMismatch {
+                expected: 0,
+                actual: 1
+            })
+        ));
+
+        let shard_map = router
+            .set_shards(vec![shard(2, &["http://b"])], Some(1))
+            .unwrap();
+        assert_eq!(shard_map.version, 2);
+        assert_eq!(shard_map.shards, vec![shard(2, &["http://b"])]);
+    }
+
+    #[test]
+    fn lines_of_a_series_go_to_one_shard() {
+        let shard_map =
+            ShardMap::new(1, vec![shard(1, &["http://a"]), shard(2, &["http://b"])]).unwrap();
+        let lines: Vec<_> =
+            parse_lines("cpu,host=a,region=west usage=1 10\ncpu,region=west,host=a usage=2 20")
+                .map(|l| l.unwrap())
+                .collect();
+
+        let first = shard_map.shard_for(&lines[0]).unwrap();
+        let second = shard_map.shard_for(&lines[1]).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(ShardMap::default().shard_for(&lines[0]), None);
+    }
+
+    #[test]
+    fn writers_own_their_shard() {
+        let lp: Vec<_> = (0..20)
+            .map(|host| format!("cpu,host={} usage=1 10", host))
+            .collect();
+        let lp = lp.join("\n");
+        let lines: Vec<_> = parse_lines(&lp).map(|l| l.unwrap()).collect();
+
+        // standalone servers and routers accept every line
+        assert!(Router::default().check_owned(&lines).is_ok());
+        assert!(Router::new(Mode::Router).check_owned(&lines).is_ok());
+
+        let writer = Router::writer(1);
+        assert!(matches!(
+            writer.check_owned(&lines),
+            Err(Error::NoShards {})
+        ));
+        assert!(matches!(
+            Router::new(Mode::Writer).check_owned(&lines),
+            Err(Error::MissingShardId {})
+        ));
+
+        let shard_map = writer
+            .set_shards(vec![shard(1, &["http://a"]), shard(2, &["http://b"])], None)
+            .unwrap();
+        let (owned, other): (Vec<_>, Vec<_>) = lines
+            .into_iter()
+            .partition(|line| shard_map.shard_for(line).unwrap().id == 1);
+        assert!(!owned.is_empty() && !other.is_empty());
+
+        assert!(writer.check_owned(&owned).is_ok());
+        assert!(matches!(
+            writer.check_owned(&other),
+            Err(Error::NotOwned {
+                line_shard: 2,
+                shard_id: 1,
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn forward_without_shards() {
+        let router = Router::new(Mode::Router);
+        let lines: Vec<_> = parse_lines("cpu usage=1 10").map(|l| l.unwrap()).collect();
+        assert!(matches!(
+            router.forward("org=a&bucket=b", None, &lines).await,
+            Err(Error::NoShards {})
+        ));
+    }
+}
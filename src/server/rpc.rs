@@ -1,6 +1,13 @@
 //! This module contains gRPC service implementatations
 
+pub mod cache;
 pub mod data;
 pub mod expr;
+pub mod flight;
+pub mod flight_sql;
+pub mod health;
 pub mod input;
+pub mod limits;
+pub mod reflection;
+pub mod server_limits;
 pub mod storage;
@@ -0,0 +1,412 @@
+//! This module contains a cache of the responses to storage gRPC
+//! queries, so that dashboards which frequently refresh the same
+//! queries do not run them again while the data is unchanged.
+//!
+//! Responses are keyed by the database, its generation, the name of
+//! the RPC, the protobuf encoding of the request and the rows the
+//! principal making the request may read. The generation of a database
+//! changes whenever data is written to it, which invalidates any
+//! responses cached for the previous generation.
+//!
+//! Cached responses are looked up with the current generation of the
+//! database, but are cached under the generation their plan was made
+//! from, which the database reads under the same lock as the data the
+//! plan reads. So the responses to a query planned just before a write
+//! are never returned as those of the data after it.
+//!
+//! The cache is bounded by the number of bytes of the responses it
+//! holds, as they would be encoded to be sent, along with their keys.
+//! The responses to a query larger than the per entry limit are not
+//! cached at all, so one large query can't evict the responses to all
+//! the others.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use generated_types::{MeasurementFieldsResponse, ReadResponse, StringValuesResponse};
+use prost::Message;
+
+use crate::server::auth::RowFilter;
+
+/// Identifies the responses to a query
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    db_name: String,
+    generation: u64,
+    rpc: &'static str,
+    /// The protobuf encoding of the request. prost encodes the fields
+    /// of a message in order, so equal requests are encoded the same.
+    request: Vec<u8>,
+    /// The rows the principal making the request may read, if
+    /// restricted
+    row_filter: Option<RowFilter>,
+}
+
+impl CacheKey {
+    /// Returns the key of the responses to `request`, a request to
+    /// `rpc` for the database `db_name` by a principal that may only
+    /// read the rows of `row_filter`, as of the first generation of
+    /// the database. See `with_generation`.
+    pub fn for_request(
+        db_name: impl Into<String>,
+        rpc: &'static str,
+        request: &impl Message,
+        row_filter: Option<&RowFilter>,
+    ) -> Self {
+        let mut encoded = Vec::with_capacity(request.encoded_len());
+        request
+            .encode(&mut encoded)
+            .expect("a vector has the capacity to encode a message");
+
+        Self {
+            db_name: db_name.into(),
+            generation: 0,
+            rpc,
+            request: encoded,
+            row_filter: row_filter.cloned(),
+        }
+    }
+
+    /// Returns the key of the same responses as of `generation` of
+    /// the database
+    pub fn with_generation(self, generation: u64) -> Self {
+        Self { generation, ..self }
+    }
+
+    /// Returns the number of bytes of the key
+    fn size(&self) -> usize {
+        self.db_name.len() + self.request.len()
+    }
+}
+
+/// The responses to a query, of the type its RPC returns
+#[derive(Debug, Clone, PartialEq)]
+pub enum CachedResponses {
+    /// The responses to `read_filter`, `read_group` and
+    /// `read_window_aggregate`
+    Read(Vec<ReadResponse>),
+    /// The response listing measurements, tag keys or tag values
+    StringValues(StringValuesResponse),
+    /// The response to `measurement_fields`
+    MeasurementFields(MeasurementFieldsResponse),
+}
+
+impl CachedResponses {
+    /// Returns the number of bytes of the responses, as they are
+    /// encoded when sent
+    pub fn size(&self) -> usize {
+        match self {
+            Self::Read(responses) => responses.iter().map(Message::encoded_len).sum(),
+            Self::StringValues(response) => response.encoded_len(),
+            Self::MeasurementFields(response) => response.encoded_len(),
+        }
+    }
+}
+
+/// The default fraction of the capacity of a cache the responses to
+/// a single query may take up
+pub const DEFAULT_MAX_ENTRY_FRACTION: usize = 8;
+
+/// A cache of the responses of queries, holding up to `capacity`
+/// bytes of them, evicting the least recently used when full
+#[derive(Debug)]
+pub struct QueryCache {
+    capacity: usize,
+    /// The maximum number of bytes of the responses to a single query,
+    /// larger responses are not cached
+    max_entry_bytes: usize,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    /// The responses of each query, and their size including the key
+    entries: HashMap<CacheKey, (Arc<CachedResponses>, usize)>,
+    /// Keys of `entries`, least recently used first
+    usage: VecDeque<CacheKey>,
+    /// The total size of `entries`
+    bytes: usize,
+}
+
+impl CacheState {
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some((_, size)) = self.entries.remove(key) {
+            self.bytes -= size;
+        }
+        self.usage.retain(|k| k != key);
+    }
+}
+
+impl QueryCache {
+    /// Creates a cache of up to `capacity` bytes of responses, the
+    /// responses of each query taking up to
+    /// 1/`DEFAULT_MAX_ENTRY_FRACTION` of it
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            max_entry_bytes: capacity / DEFAULT_MAX_ENTRY_FRACTION,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    /// Only caches the responses of queries of up to `max_entry_bytes`
+    pub fn with_max_entry_bytes(self, max_entry_bytes: usize) -> Self {
+        Self {
+            max_entry_bytes,
+            ..self
+        }
+    }
+
+    /// Returns the cached responses for `key`, if any
+    pub fn get(&self, key: &CacheKey) -> Option<Arc<CachedResponses>> {
+        let mut state = self.state.lock().expect("mutex poisoned");
+
+        let (responses, _) = state.entries.get(key).cloned()?;
+        state.usage.retain(|k| k != key);
+        state.usage.push_back(key.clone());
+
+        Some(responses)
+    }
+
+    /// Caches `responses` for `key`, discarding any responses cached
+    /// for earlier generations of the same database. Responses larger
+    /// than the per entry limit, or of an earlier generation than
+    /// those already cached, are not cached.
+    pub fn insert(&self, key: CacheKey, responses: CachedResponses) {
+        let size = key.size() + responses.size();
+        if size > self.max_entry_bytes || size > self.capacity {
+            return;
+        }
+
+        let mut state = self.state.lock().expect("mutex poisoned");
+
+        let same_db = |k: &&CacheKey| k.db_name == key.db_name;
+        if state
+            .entries
+            .keys()
+            .filter(same_db)
+            .any(|k| k.generation > key.generation)
+        {
+            return;
+        }
+
+        let stale = state
+            .entries
+            .keys()
+            .filter(same_db)
+            .filter(|k| k.generation < key.generation)
+            .cloned()
+            .collect::<Vec<_>>();
+        for k in &stale {
+            state.remove(k);
+        }
+
+        state.remove(&key);
+        while state.bytes + size > self.capacity {
+            match state.usage.pop_front() {
+                Some(oldest) => state.remove(&oldest),
+                None => break,
+            }
+        }
+
+        state
+            .entries
+            .insert(key.clone(), (Arc::new(responses), size));
+        state.usage.push_back(key);
+        state.bytes += size;
+    }
+
+    /// Returns the number of queries with cached responses
+    pub fn len(&self) -> usize {
+        self.state.lock().expect("mutex poisoned").entries.len()
+    }
+
+    /// Returns true if no responses are cached
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of bytes of the cached responses and their keys
+    pub fn bytes(&self) -> usize {
+        self.state.lock().expect("mutex poisoned").bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use generated_types::TagKeysRequest;
+
+    fn key(db_name: &str, generation: u64, request: &str) -> CacheKey {
+        CacheKey {
+            db_name: db_name.into(),
+            generation,
+            rpc: "read_group",
+            request: request.as_bytes().to_vec(),
+            row_filter: None,
+        }
+    }
+
+    fn responses(n: usize) -> CachedResponses {
+        CachedResponses::Read(vec![ReadResponse { frames: vec![] }; n])
+    }
+
+    fn len(responses: Option<Arc<CachedResponses>>) -> usize {
+        match responses.as_deref() {
+            Some(CachedResponses::Read(responses)) => responses.len(),
+            other => panic!("unexpected cached responses {:?}", other),
+        }
+    }
+
+    /// Returns a string values response of `size` bytes, when encoded
+    fn sized(size: usize) -> CachedResponses {
+        // each value is encoded after its tag and length
+        CachedResponses::StringValues(StringValuesResponse {
+            values: vec![vec![b'x'; size - 2]],
+        })
+    }
+
+    #[test]
+    fn get_and_insert() {
+        let cache = QueryCache::new(1000);
+        let a = key("db", 1, "read_group a");
+
+        assert!(cache.get(&a).is_none());
+
+        cache.insert(a.clone(), responses(2));
+        assert_eq!(len(cache.get(&a)), 2);
+
+        // different requests are cached separately
+        let other = key("db", 1, "read_group b");
+        assert!(cache.get(&other).is_none());
+        cache.insert(other.clone(), responses(1));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(len(cache.get(&a)), 2);
+        assert_eq!(len(cache.get(&other)), 1);
+    }
+
+    #[test]
+    fn new_generation_invalidates() {
+        let cache = QueryCache::new(1000);
+        let old = key("db", 1, "read_group a");
+        let other_db = key("other", 1, "read_group a");
+        cache.insert(old.clone(), responses(1));
+        cache.insert(other_db.clone(), responses(1));
+
+        let new = key("db", 2, "read_group a");
+        assert!(cache.get(&new).is_none());
+        cache.insert(new.clone(), responses(3));
+
+        assert!(cache.get(&old).is_none());
+        assert_eq!(len(cache.get(&new)), 3);
+        assert_eq!(len(cache.get(&other_db)), 1);
+
+        // the responses of a query planned before the last write are
+        // not cached, nor do they evict those planned after it
+        let planned_before = key("db", 1, "read_group b");
+        cache.insert(planned_before.clone(), responses(1));
+        assert!(cache.get(&planned_before).is_none());
+        assert_eq!(len(cache.get(&new)), 3);
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        // each entry is 3 bytes of key and 7 of responses
+        let cache = QueryCache::new(25).with_max_entry_bytes(25);
+        let a = key("db", 1, "a");
+        let b = key("db", 1, "b");
+        let c = key("db", 1, "c");
+
+        cache.insert(a.clone(), sized(7));
+        cache.insert(b.clone(), sized(7));
+        assert_eq!(cache.bytes(), 20);
+        // use a, so b is evicted rather than a
+        assert!(cache.get(&a).is_some());
+        cache.insert(c.clone(), sized(7));
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.bytes(), 20);
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&c).is_some());
+
+        // a larger entry evicts as many as it needs the room of
+        cache.insert(b.clone(), sized(17));
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.bytes(), 20);
+        assert!(cache.get(&b).is_some());
+
+        let disabled = QueryCache::new(0);
+        disabled.insert(a, sized(7));
+        assert!(disabled.is_empty());
+    }
+
+    #[test]
+    fn does_not_cache_large_entries() {
+        let cache = QueryCache::new(200);
+        assert_eq!(cache.max_entry_bytes, 200 / DEFAULT_MAX_ENTRY_FRACTION);
+
+        let small = key("db", 1, "small");
+        cache.insert(small.clone(), sized(7));
+        assert_eq!(cache.bytes(), 14);
+
+        // neither cached nor evicting the responses already cached
+        let large = key("db", 1, "large");
+        cache.insert(large.clone(), sized(20));
+        assert!(cache.get(&large).is_none());
+        assert!(cache.get(&small).is_some());
+        assert_eq!(cache.bytes(), 14);
+
+        let cache = cache.with_max_entry_bytes(50);
+        cache.insert(large.clone(), sized(20));
+        assert!(cache.get(&large).is_some());
+        assert_eq!(cache.bytes(), 41);
+    }
+
+    #[test]
+    fn keys_of_requests() {
+        use generated_types::TimestampRange;
+
+        let request = |start| TagKeysRequest {
+            tags_source: None,
+            range: Some(TimestampRange { start, end: 200 }),
+            predicate: None,
+        };
+        let row_filter = RowFilter {
+            measurements: Some(vec!["cpu".to_string()].into_iter().collect()),
+            ..RowFilter::default()
+        };
+
+        let tag_keys = CacheKey::for_request("db", "tag_keys", &request(100), None);
+        assert_eq!(
+            tag_keys,
+            CacheKey::for_request("db", "tag_keys", &request(100), None)
+        );
+        assert_ne!(
+            tag_keys,
+            CacheKey::for_request("db", "tag_keys", &request(101), None)
+        );
+        assert_ne!(
+            tag_keys,
+            CacheKey::for_request("db", "measurement_tag_keys", &request(100), None)
+        );
+        assert_ne!(
+            tag_keys,
+            CacheKey::for_request("other", "tag_keys", &request(100), None)
+        );
+        // principals that may read different rows get different responses
+        assert_ne!(
+            tag_keys,
+            CacheKey::for_request("db", "tag_keys", &request(100), Some(&row_filter))
+        );
+
+        assert_eq!(tag_keys.generation, 0);
+        let later = tag_keys.clone().with_generation(3);
+        assert_eq!(later.generation, 3);
+        assert_ne!(later, tag_keys);
+        assert_eq!(later.request, tag_keys.request);
+    }
+}
@@ -1,20 +1,29 @@
 //! This module contains code to translate from InfluxDB IOx data
 //! formats into the formats needed by gRPC
 
-use std::{collections::BTreeSet, sync::Arc};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    mem,
+    sync::Arc,
+};
 
 use arrow_deps::arrow::{
     array::{ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray},
     datatypes::DataType as ArrowDataType,
 };
 
+use data_types::pool::Pool;
 use storage::exec::{
     fieldlist::FieldList,
+    gaps::SeriesGaps,
     seriesset::{GroupDescription, GroupedSeriesSetItem, SeriesSet},
+    window_aggregate::{WindowedSeriesSet, WindowedValues},
 };
 
 use generated_types::{
     measurement_fields_response::{FieldType, MessageField},
+    read_gaps_response::{Gap as RpcGap, SeriesGaps as RpcSeriesGaps},
     read_response::{
         frame::Data, BooleanPointsFrame, DataType, FloatPointsFrame, Frame, GroupFrame,
         IntegerPointsFrame, SeriesFrame, StringPointsFrame,
@@ -47,6 +56,12 @@ pub fn tag_keys_to_byte_vecs(tag_keys: Arc<BTreeSet<String>>) -> Vec<Vec<u8>> {
     specials_iter.chain(tag_keys_iter).collect()
 }
 
+thread_local! {
+    /// Buffers for the timestamps of the series sets being converted
+    /// to frames, reused from one series set to the next
+    static TIMESTAMP_BUFFERS: Pool<Vec<i64>> = Pool::new(1);
+}
+
 /// Convert `SeriesSet` into a form suitable for gRPC transport
 ///
 /// Each `SeriesSet` gets converted into this pattern:
@@ -66,10 +81,25 @@ pub fn series_set_to_read_response(series_set: SeriesSet) -> Result<ReadResponse
 }
 
 fn series_set_to_frames(series_set: SeriesSet) -> Result<Vec<Frame>> {
-    let mut data_records = Vec::new();
-    for field_index in series_set.field_indices.iter() {
-        field_to_data(&mut data_records, &series_set, *field_index)?
-    }
+    let data_records = TIMESTAMP_BUFFERS.with(|buffers| {
+        // The timestamps are the same for every field, so they are
+        // extracted once into a reused buffer and copied into each frame
+        let mut timestamps = buffers.get();
+        let timestamp_array = series_set
+            .batch
+            .column(series_set.timestamp_index)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .unwrap();
+        let end_row = series_set.start_row + series_set.num_rows;
+        timestamps.extend((series_set.start_row..end_row).map(|row| timestamp_array.value(row)));
+
+        let mut data_records = Vec::with_capacity(series_set.field_indices.len() * 2);
+        for field_index in series_set.field_indices.iter() {
+            field_to_data(&mut data_records, &series_set, *field_index, &timestamps)?
+        }
+        Ok::<_, Error>(data_records)
+    })?;
 
     let frames = data_records
         .into_iter()
@@ -83,6 +113,280 @@ fn series_set_to_frames(series_set: SeriesSet) -> Result<Vec<Frame>> {
     Ok(frames)
 }
 
+/// The tags of a series, without its measurement and field
+type SeriesTags = Vec<(Arc<String>, Arc<String>)>;
+
+/// Merges the frames of the series that are sent in more than one
+/// `SeriesSet`, as happens when a series has rows in several chunks,
+/// so that each series is converted to one `SeriesFrame` per field,
+/// followed by the points of all of its rows in time order.
+///
+/// The series sets of each table must be pushed one after the other,
+/// as the series set plans produce them. The series of a table are
+/// held until the series sets of the next table (or `finish`) show
+/// that there are no more of them, and are then returned in the order
+/// of their tag values, as the plan of each chunk orders them.
+#[derive(Debug, Default)]
+pub struct SeriesMerger {
+    table_name: Option<Arc<String>>,
+    series: Vec<MergedSeries>,
+    /// The position of each series in `series`
+    positions: HashMap<SeriesTags, usize>,
+}
+
+#[derive(Debug)]
+struct MergedSeries {
+    tags: SeriesTags,
+    /// The frames of each field of the series
+    fields: Vec<(SeriesFrame, Data)>,
+    /// Whether the points of the series came from more than one series
+    /// set, and so may not be in time order
+    merged: bool,
+}
+
+impl SeriesMerger {
+    /// Adds the frames of `series_set`, returning the responses of the
+    /// series of the previous table if it is of another table
+    pub fn push(&mut self, series_set: SeriesSet) -> Result<Vec<ReadResponse>> {
+        let mut responses = vec![];
+        if self.table_name.as_ref() != Some(&series_set.table_name) {
+            responses = self.finish();
+            self.table_name = Some(Arc::clone(&series_set.table_name));
+        }
+
+        let tags = series_set.tags.clone();
+        let mut frames = series_set_to_read_response(series_set)?
+            .frames
+            .into_iter()
+            .filter_map(|frame| frame.data);
+        let mut fields = vec![];
+        while let (Some(Data::Series(series_frame)), Some(points)) = (frames.next(), frames.next())
+        {
+            fields.push((series_frame, points));
+        }
+
+        match self.positions.get(&tags) {
+            Some(&position) => self.series[position].merge(fields),
+            None => {
+                self.positions.insert(tags.clone(), self.series.len());
+                self.series.push(MergedSeries {
+                    tags,
+                    fields,
+                    merged: false,
+                });
+            }
+        }
+
+        Ok(responses)
+    }
+
+    /// Returns the responses of the series pushed since the last
+    /// table's were returned
+    pub fn finish(&mut self) -> Vec<ReadResponse> {
+        self.positions.clear();
+        let mut series = mem::take(&mut self.series);
+        series.sort_by(|a, b| compare_series_tags(&a.tags, &b.tags));
+
+        series
+            .into_iter()
+            .map(MergedSeries::into_read_response)
+            .collect()
+    }
+}
+
+impl MergedSeries {
+    /// Appends the points of `fields` to those of the same fields
+    fn merge(&mut self, fields: Vec<(SeriesFrame, Data)>) {
+        self.merged = true;
+        for (series_frame, points) in fields {
+            match self.fields.iter_mut().find(|(f, _)| *f == series_frame) {
+                Some((_, existing)) => append_points(existing, points),
+                None => self.fields.push((series_frame, points)),
+            }
+        }
+    }
+
+    fn into_read_response(self) -> ReadResponse {
+        let mut frames = Vec::with_capacity(self.fields.len() * 2);
+        for (series_frame, mut points) in self.fields {
+            if self.merged {
+                sort_points(&mut points);
+            }
+            frames.push(Frame {
+                data: Some(Data::Series(series_frame)),
+            });
+            frames.push(Frame { data: Some(points) });
+        }
+        ReadResponse { frames }
+    }
+}
+
+/// Orders the tags of series as the series set plans do: by the value
+/// of each tag key in turn, with series without the key first
+fn compare_series_tags(
+    a: &[(Arc<String>, Arc<String>)],
+    b: &[(Arc<String>, Arc<String>)],
+) -> Ordering {
+    let (mut a, mut b) = (a.iter().peekable(), b.iter().peekable());
+    loop {
+        let ordering = match (a.peek(), b.peek()) {
+            (None, None) => return Ordering::Equal,
+            (Some(_), None) => return Ordering::Greater,
+            (None, Some(_)) => return Ordering::Less,
+            (Some((a_key, a_value)), Some((b_key, b_value))) => match a_key.cmp(b_key) {
+                // b has no value for a's key
+                Ordering::Less => return Ordering::Greater,
+                Ordering::Greater => return Ordering::Less,
+                Ordering::Equal => a_value.cmp(b_value),
+            },
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+        a.next();
+        b.next();
+    }
+}
+
+/// Appends the points of `from` to `into`, which must be points of
+/// the same type
+fn append_points(into: &mut Data, from: Data) {
+    match (into, from) {
+        (Data::FloatPoints(into), Data::FloatPoints(from)) => {
+            into.timestamps.extend(from.timestamps);
+            into.values.extend(from.values);
+        }
+        (Data::IntegerPoints(into), Data::IntegerPoints(from)) => {
+            into.timestamps.extend(from.timestamps);
+            into.values.extend(from.values);
+        }
+        (Data::BooleanPoints(into), Data::BooleanPoints(from)) => {
+            into.timestamps.extend(from.timestamps);
+            into.values.extend(from.values);
+        }
+        (Data::StringPoints(into), Data::StringPoints(from)) => {
+            into.timestamps.extend(from.timestamps);
+            into.values.extend(from.values);
+        }
+        _ => unreachable!("the points of fields of the same type are of the same type"),
+    }
+}
+
+/// Sorts the points of `points` by their timestamps, keeping the
+/// order of points with the same timestamp
+fn sort_points(points: &mut Data) {
+    match points {
+        Data::FloatPoints(p) => sort_by_timestamp(&mut p.timestamps, &mut p.values),
+        Data::IntegerPoints(p) => sort_by_timestamp(&mut p.timestamps, &mut p.values),
+        Data::BooleanPoints(p) => sort_by_timestamp(&mut p.timestamps, &mut p.values),
+        Data::StringPoints(p) => sort_by_timestamp(&mut p.timestamps, &mut p.values),
+        _ => {}
+    }
+}
+
+fn sort_by_timestamp<T>(timestamps: &mut Vec<i64>, values: &mut Vec<T>) {
+    if timestamps.windows(2).all(|pair| pair[0] <= pair[1]) {
+        return;
+    }
+
+    let mut points = timestamps
+        .drain(..)
+        .zip(values.drain(..))
+        .collect::<Vec<_>>();
+    points.sort_by_key(|(timestamp, _)| *timestamp);
+    for (timestamp, value) in points {
+        timestamps.push(timestamp);
+        values.push(value);
+    }
+}
+
+/// Convert `WindowedSeriesSet` into a form suitable for gRPC transport
+///
+/// Each field gets converted into a SeriesFrame followed by a points
+/// frame with the aggregate of each window, in the same pattern as
+/// `series_set_to_read_response`.
+///
+/// Points frames can not represent null values, so windows without a
+/// value are left out of the frames (which is why `fill(null)` is
+/// rejected by the storage service).
+pub fn windowed_series_set_to_read_response(
+    windowed_series_set: WindowedSeriesSet,
+) -> Result<ReadResponse> {
+    let WindowedSeriesSet {
+        table_name,
+        tags,
+        timestamps,
+        fields,
+    } = windowed_series_set;
+
+    let mut frames = Vec::with_capacity(fields.len() * 2);
+    for field in fields {
+        let (data_type, data) = match field.values {
+            WindowedValues::F64(values) => {
+                let (timestamps, values) = present_values(&timestamps, values);
+                let data = Data::FloatPoints(FloatPointsFrame { timestamps, values });
+                (DataType::Float, data)
+            }
+            WindowedValues::I64(values) => {
+                let (timestamps, values) = present_values(&timestamps, values);
+                let data = Data::IntegerPoints(IntegerPointsFrame { timestamps, values });
+                (DataType::Integer, data)
+            }
+            WindowedValues::Bool(values) => {
+                let (timestamps, values) = present_values(&timestamps, values);
+                let data = Data::BooleanPoints(BooleanPointsFrame { timestamps, values });
+                (DataType::Boolean, data)
+            }
+            WindowedValues::String(values) => {
+                let (timestamps, values) = present_values(&timestamps, values);
+                let data = Data::StringPoints(StringPointsFrame { timestamps, values });
+                (DataType::String, data)
+            }
+        };
+
+        let series_frame = SeriesFrame {
+            tags: convert_tags(table_name.as_ref(), &field.name, &tags),
+            data_type: data_type as i32,
+        };
+        frames.push(Frame {
+            data: Some(Data::Series(series_frame)),
+        });
+        frames.push(Frame { data: Some(data) });
+    }
+
+    Ok(ReadResponse { frames })
+}
+
+/// Convert `SeriesGaps` into a form suitable for gRPC transport. The
+/// series is identified by its `_measurement` and tags.
+pub fn series_gaps_to_rpc(series_gaps: SeriesGaps) -> RpcSeriesGaps {
+    let SeriesGaps {
+        table_name,
+        tags,
+        gaps,
+    } = series_gaps;
+
+    RpcSeriesGaps {
+        tags: convert_series_tags(table_name.as_ref(), &tags),
+        gaps: gaps
+            .into_iter()
+            .map(|gap| RpcGap {
+                start: gap.start,
+                end: gap.end,
+            })
+            .collect(),
+    }
+}
+
+/// Returns the timestamps and values of the windows that have a value
+fn present_values<T>(timestamps: &[i64], values: Vec<Option<T>>) -> (Vec<i64>, Vec<T>) {
+    timestamps
+        .iter()
+        .zip(values)
+        .filter_map(|(&timestamp, value)| value.map(|value| (timestamp, value)))
+        .unzip()
+}
+
 /// Convert `GroupedSeriesSetIem` into a form suitable for gRPC transport
 ///
 /// Each `GroupedSeriesSetItem` gets converted into this pattern:
@@ -147,7 +451,12 @@ fn data_type(array: &ArrayRef) -> Result<DataType> {
 }
 
 // Convert and append a single field to a sequence of frames
-fn field_to_data(frames: &mut Vec<Data>, series_set: &SeriesSet, field_index: usize) -> Result<()> {
+fn field_to_data(
+    frames: &mut Vec<Data>,
+    series_set: &SeriesSet,
+    field_index: usize,
+    timestamps: &[i64],
+) -> Result<()> {
     let batch = &series_set.batch;
     let schema = batch.schema();
 
@@ -166,12 +475,7 @@ fn field_to_data(frames: &mut Vec<Data>, series_set: &SeriesSet, field_index: us
     };
     frames.push(Data::Series(series_frame));
 
-    let timestamps = batch
-        .column(series_set.timestamp_index)
-        .as_any()
-        .downcast_ref::<Int64Array>()
-        .unwrap()
-        .extract_values(start_row, num_rows);
+    let timestamps = timestamps.to_vec();
 
     frames.push(match array.data_type() {
         ArrowDataType::Utf8 => {
@@ -223,14 +527,22 @@ fn convert_tags(
     field_name: &str,
     tags: &[(Arc<String>, Arc<String>)],
 ) -> Vec<Tag> {
+    // Special case "field" which is modeled as a tag of "_field"
+    let mut converted_tags = vec![Tag {
+        key: b"_field".to_vec(),
+        value: field_name.bytes().collect(),
+    }];
+
+    converted_tags.extend(convert_series_tags(table_name, tags));
+    converted_tags
+}
+
+/// Converts the tags of a series (without a field)
+fn convert_series_tags(table_name: &str, tags: &[(Arc<String>, Arc<String>)]) -> Vec<Tag> {
     let mut converted_tags = Vec::new();
 
     // Special case "measurement" name which is modeled as a tag of
-    // "_measurement" and "field" which is modeled as a tag of "_field"
-    converted_tags.push(Tag {
-        key: b"_field".to_vec(),
-        value: field_name.bytes().collect(),
-    });
+    // "_measurement"
     converted_tags.push(Tag {
         key: b"_measurement".to_vec(),
         value: table_name.bytes().collect(),
@@ -367,6 +679,140 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_series_merger() {
+        let series_set = |table_name: &str, tag_value: Option<&str>, start_row, num_rows| {
+            let tags = tag_value
+                .map(|value| vec![(Arc::new("tag1".to_string()), Arc::new(value.to_string()))])
+                .unwrap_or_default();
+            SeriesSet {
+                table_name: Arc::new(table_name.into()),
+                tags,
+                timestamp_index: 4,
+                field_indices: Arc::new(vec![1]),
+                start_row,
+                num_rows,
+                batch: make_record_batch(),
+            }
+        };
+        let dump = |responses: Vec<ReadResponse>| {
+            responses
+                .iter()
+                .flat_map(|response| response.frames.iter().map(dump_frame))
+                .collect::<Vec<_>>()
+        };
+
+        let mut merger = SeriesMerger::default();
+
+        // the series of one chunk of the first table
+        assert!(merger.push(series_set("a", None, 0, 1)).unwrap().is_empty());
+        assert!(merger
+            .push(series_set("a", Some("val1"), 2, 2))
+            .unwrap()
+            .is_empty());
+        // and of another chunk
+        assert!(merger
+            .push(series_set("a", Some("val0"), 3, 1))
+            .unwrap()
+            .is_empty());
+        assert!(merger
+            .push(series_set("a", Some("val1"), 1, 1))
+            .unwrap()
+            .is_empty());
+
+        let responses = merger.push(series_set("b", Some("val1"), 0, 1)).unwrap();
+        let expected_frames = vec![
+            "SeriesFrame, tags: _field=int_field,_measurement=a, type: 1",
+            "IntegerPointsFrame, timestamps: [1000], values: \"1\"",
+            "SeriesFrame, tags: _field=int_field,_measurement=a,tag1=val0, type: 1",
+            "IntegerPointsFrame, timestamps: [4000], values: \"4\"",
+            "SeriesFrame, tags: _field=int_field,_measurement=a,tag1=val1, type: 1",
+            "IntegerPointsFrame, timestamps: [2000, 3000, 4000], values: \"2,3,4\"",
+        ];
+        assert_eq!(responses.len(), 3);
+        assert_eq!(dump(responses), expected_frames);
+
+        let expected_frames = vec![
+            "SeriesFrame, tags: _field=int_field,_measurement=b,tag1=val1, type: 1",
+            "IntegerPointsFrame, timestamps: [1000], values: \"1\"",
+        ];
+        assert_eq!(dump(merger.finish()), expected_frames);
+        assert!(merger.finish().is_empty());
+    }
+
+    #[test]
+    fn test_windowed_series_set_conversion() {
+        use storage::exec::window_aggregate::WindowedField;
+
+        let windowed_series_set = WindowedSeriesSet {
+            table_name: Arc::new("the_table".into()),
+            tags: vec![(Arc::new("tag1".into()), Arc::new("val1".into()))],
+            timestamps: vec![1000, 2000, 3000],
+            fields: vec![
+                WindowedField {
+                    name: "float_field".into(),
+                    values: WindowedValues::F64(vec![Some(1.5), None, Some(3.5)]),
+                },
+                WindowedField {
+                    name: "count".into(),
+                    values: WindowedValues::I64(vec![Some(2), Some(0), Some(1)]),
+                },
+            ],
+        };
+
+        let response = windowed_series_set_to_read_response(windowed_series_set)
+            .expect("Correctly converted windowed series set");
+
+        let dumped_frames = response
+            .frames
+            .iter()
+            .map(|f| dump_frame(f))
+            .collect::<Vec<_>>();
+
+        let expected_frames = vec![
+            "SeriesFrame, tags: _field=float_field,_measurement=the_table,tag1=val1, type: 0",
+            "FloatPointsFrame, timestamps: [1000, 3000], values: \"1.5,3.5\"",
+            "SeriesFrame, tags: _field=count,_measurement=the_table,tag1=val1, type: 1",
+            "IntegerPointsFrame, timestamps: [1000, 2000, 3000], values: \"2,0,1\"",
+        ];
+
+        assert_eq!(
+            dumped_frames, expected_frames,
+            "Expected:\n{:#?}\nActual:\n{:#?}",
+            expected_frames, dumped_frames
+        );
+    }
+
+    #[test]
+    fn test_series_gaps_conversion() {
+        use storage::exec::gaps::Gap;
+
+        let series_gaps = SeriesGaps {
+            table_name: Arc::new("the_table".into()),
+            tags: vec![(Arc::new("tag1".into()), Arc::new("val1".into()))],
+            gaps: vec![
+                Gap { start: 10, end: 50 },
+                Gap {
+                    start: 60,
+                    end: 100,
+                },
+            ],
+        };
+
+        let converted = series_gaps_to_rpc(series_gaps);
+
+        assert_eq!(
+            dump_tags(&converted.tags),
+            "_measurement=the_table,tag1=val1"
+        );
+        let gaps = converted
+            .gaps
+            .iter()
+            .map(|gap| (gap.start, gap.end))
+            .collect::<Vec<_>>();
+        assert_eq!(gaps, vec![(10, 50), (60, 100)]);
+    }
+
     #[test]
     fn test_group_group_conversion() {
         let group_description = GroupDescription {
@@ -5,13 +5,14 @@ use std::convert::TryFrom;
 
 use arrow_deps::datafusion::{
     logical_plan::{Expr, Operator},
+    physical_plan::functions::BuiltinScalarFunction,
     scalar::ScalarValue,
 };
 use generated_types::{
     node::Comparison as RPCComparison, node::Logical as RPCLogical, node::Value as RPCValue,
     Node as RPCNode, Predicate as RPCPredicate,
 };
-use snafu::{ResultExt, Snafu};
+use snafu::{OptionExt, ResultExt, Snafu};
 use storage::predicate::PredicateBuilder;
 
 #[derive(Debug, Snafu)]
@@ -67,9 +68,6 @@ pub enum Error {
     ))]
     UnsupportedNumberOfChildren { op: Operator, num_children: usize },
 
-    #[snafu(display("Error converting tag_name to utf8: {}", source))]
-    ConvertingTagName { source: std::string::FromUtf8Error },
-
     #[snafu(display("Error converting field_name to utf8: {}", source))]
     ConvertingFieldName { source: std::string::FromUtf8Error },
 }
@@ -112,7 +110,11 @@ impl AddRPCNode for PredicateBuilder {
 
                         // step one is to flatten any AND tree into a vector of conjucts
                         let conjuncts = flatten_ands(node, Vec::new())?;
-                        conjuncts.into_iter().try_fold(self, convert_simple_node)
+                        let builder = conjuncts.into_iter().try_fold(self, convert_simple_node)?;
+
+                        // clean up whatever the client generated so it
+                        // doesn't get in the way of pruning
+                        Ok(builder.simplify())
                     }
                 }
             }
@@ -254,20 +256,18 @@ impl InListBuilder {
     fn append(self, node: &RPCNode) -> Result<Self, &'static str> {
         // lhs = rhs
         if Some(RPCValue::Comparison(RPCComparison::Equal as i32)) == node.value {
-            assert_eq!(node.children.len(), 2);
-            let lhs = &node.children[0];
-            let rhs = &node.children[1];
-            self.append_equal(lhs, rhs)
+            match node.children.as_slice() {
+                [lhs, rhs] => self.append_equal(lhs, rhs),
+                _ => Err("Equal did not have exactly two children"),
+            }
         }
         // lhs OR rhs
         else if Some(RPCValue::Logical(RPCLogical::Or as i32)) == node.value {
-            assert_eq!(node.children.len(), 2);
-
-            let lhs = &node.children[0];
-            let rhs = &node.children[1];
-
-            // recurse down both sides
-            self.append(lhs).and_then(|s| s.append(rhs))
+            match node.children.as_slice() {
+                // recurse down both sides
+                [lhs, rhs] => self.append(lhs).and_then(|s| s.append(rhs)),
+                _ => Err("OR did not have exactly two children"),
+            }
         } else {
             Err("Found something other than equal or OR")
         }
@@ -338,6 +338,13 @@ impl SpecialTagKeys for String {
 
 // converts a Node from the RPC layer into a datafusion logical expr
 fn convert_node_to_expr(node: RPCNode) -> Result<Expr> {
+    if let Some(expr) = convert_tag_null_comparison(&node)? {
+        return Ok(expr);
+    }
+    if let Some(expr) = convert_tag_regex_ignoring_case(&node)? {
+        return Ok(expr);
+    }
+
     let RPCNode {
         children,
         node_type: _,
@@ -348,10 +355,105 @@ fn convert_node_to_expr(node: RPCNode) -> Result<Expr> {
         .map(convert_node_to_expr)
         .collect::<Result<Vec<_>>>()?;
 
-    let value = value.expect("Normalization removed all None values");
+    // Normalization removes nodes without values, but don't rely on
+    // it as the input comes from clients
+    let value = value.context(EmptyPredicateValue)?;
     build_node(value, inputs)
 }
 
+/// Converts a comparison of a tag with the empty string, which is how
+/// the storage gRPC API expresses that a tag is missing (`tag = ''`)
+/// or present (`tag != ''`, as Flux sends for `exists r.tag`), into
+/// an `IS NULL` or `IS NOT NULL` check on the tag column. Returns
+/// `None` for any other node.
+fn convert_tag_null_comparison(node: &RPCNode) -> Result<Option<Expr>> {
+    let is_null = match node.value {
+        Some(RPCValue::Comparison(comparison)) if comparison == RPCComparison::Equal as i32 => true,
+        Some(RPCValue::Comparison(comparison)) if comparison == RPCComparison::NotEqual as i32 => {
+            false
+        }
+        _ => return Ok(None),
+    };
+
+    let tag_name = match node.children.as_slice() {
+        [RPCNode {
+            value: Some(RPCValue::TagRefValue(tag_name)),
+            ..
+        }, RPCNode {
+            value: Some(RPCValue::StringValue(value)),
+            ..
+        }] if value.is_empty() => tag_name,
+        _ => return Ok(None),
+    };
+
+    let column = Box::new(Expr::Column(make_tag_name(tag_name.clone())?));
+    Ok(Some(if is_null {
+        Expr::IsNull(column)
+    } else {
+        Expr::IsNotNull(column)
+    }))
+}
+
+/// Converts a match of a tag against a case-insensitive regular
+/// expression for a single string, such as `host =~ /(?i)^server-a$/`,
+/// into `lower(host) = 'server-a'`, which the write buffer can evaluate
+/// against its dictionary. Returns `None` for any other node, including
+/// other regular expressions, which are not supported.
+fn convert_tag_regex_ignoring_case(node: &RPCNode) -> Result<Option<Expr>> {
+    match node.value {
+        Some(RPCValue::Comparison(comparison)) if comparison == RPCComparison::Regex as i32 => {}
+        _ => return Ok(None),
+    }
+
+    let (tag_name, value) = match node.children.as_slice() {
+        [RPCNode {
+            value: Some(RPCValue::TagRefValue(tag_name)),
+            ..
+        }, RPCNode {
+            value: Some(RPCValue::RegexValue(regexp)),
+            ..
+        }] if !tag_name.is_measurement() && !tag_name.is_field() => {
+            match ignoring_case_literal(regexp) {
+                Some(value) => (tag_name, value),
+                None => return Ok(None),
+            }
+        }
+        _ => return Ok(None),
+    };
+
+    let column = Expr::Column(make_tag_name(tag_name.clone())?);
+    Ok(Some(Expr::BinaryExpr {
+        left: Box::new(Expr::ScalarFunction {
+            fun: BuiltinScalarFunction::Lower,
+            args: vec![column],
+        }),
+        op: Operator::Eq,
+        right: Box::new(Expr::Literal(ScalarValue::Utf8(Some(value.to_lowercase())))),
+    }))
+}
+
+/// Returns the string `regexp` matches if it is `(?i)^string$`, where
+/// characters of the string with a special meaning are escaped with `\`
+fn ignoring_case_literal(regexp: &str) -> Option<String> {
+    let escaped = regexp.strip_prefix("(?i)^")?.strip_suffix('$')?;
+
+    let mut value = String::with_capacity(escaped.len());
+    let mut chars = escaped.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next()? {
+                c if c.is_ascii_punctuation() => value.push(c),
+                _ => return None,
+            },
+            '.' | '+' | '*' | '?' | '(' | ')' | '|' | '[' | ']' | '{' | '}' | '^' | '$' => {
+                return None
+            }
+            c => value.push(c),
+        }
+    }
+    Some(value)
+}
+
 fn make_tag_name(tag_name: Vec<u8>) -> Result<String> {
     // These should have been handled at a higher level -- if we get
     // here it is too late
@@ -360,14 +462,17 @@ fn make_tag_name(tag_name: Vec<u8>) -> Result<String> {
     } else if tag_name.is_field() {
         InternalInvalidFieldReference.fail()
     } else {
-        String::from_utf8(tag_name).context(ConvertingTagName)
+        // Writes replace invalid UTF-8 in tag keys with U+FFFD, so
+        // do the same here to refer to the same column
+        Ok(String::from_utf8(tag_name)
+            .unwrap_or_else(|e| String::from_utf8_lossy(e.as_bytes()).into_owned()))
     }
 }
 
 // Builds an Expr given the Value and the converted children
 fn build_node(value: RPCValue, inputs: Vec<Expr>) -> Result<Expr> {
     // Only logical / comparison ops can have inputs.
-    let can_have_children = matches!(&value,  RPCValue::Logical(_) | RPCValue::Comparison(_));
+    let can_have_children = matches!(&value, RPCValue::Logical(_) | RPCValue::Comparison(_));
 
     if !can_have_children && !inputs.is_empty() {
         return UnexpectedChildren { value }.fail();
@@ -506,6 +611,72 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_convert_predicate_field_comparison() {
+        // a comparison between two fields: usage_user > usage_system
+        let field_ref = |field_name: &str| RPCNode {
+            node_type: RPCNodeType::FieldRef as i32,
+            children: vec![],
+            value: Some(RPCValue::FieldRefValue(field_name.into())),
+        };
+
+        let comparison = RPCNode {
+            node_type: RPCNodeType::ComparisonExpression as i32,
+            children: vec![field_ref("usage_user"), field_ref("usage_system")],
+            value: Some(RPCValue::Comparison(RPCComparison::Gt as i32)),
+        };
+
+        let rpc_predicate = RPCPredicate {
+            root: Some(comparison),
+        };
+
+        let predicate = PredicateBuilder::default()
+            .rpc_predicate(Some(rpc_predicate))
+            .expect("successfully converting predicate")
+            .build();
+
+        assert_eq!(
+            format!("{:?}", predicate.exprs),
+            "[#usage_user Gt #usage_system]"
+        );
+    }
+
+    #[test]
+    fn test_convert_predicate_invalid_utf8_tag() {
+        // tag keys are written with invalid utf8 replaced, so refer to
+        // them the same way
+        let comparison = RPCNode {
+            node_type: RPCNodeType::ComparisonExpression as i32,
+            children: vec![
+                RPCNode {
+                    node_type: RPCNodeType::TagRef as i32,
+                    children: vec![],
+                    value: Some(RPCValue::TagRefValue(b"ho\xffst".to_vec())),
+                },
+                RPCNode {
+                    node_type: RPCNodeType::Literal as i32,
+                    children: vec![],
+                    value: Some(RPCValue::StringValue("h1".into())),
+                },
+            ],
+            value: Some(RPCValue::Comparison(RPCComparison::Equal as i32)),
+        };
+
+        let rpc_predicate = RPCPredicate {
+            root: Some(comparison),
+        };
+
+        let predicate = PredicateBuilder::default()
+            .rpc_predicate(Some(rpc_predicate))
+            .expect("successfully converting predicate")
+            .build();
+
+        assert_eq!(
+            format!("{:?}", predicate.exprs),
+            "[#ho\u{fffd}st Eq Utf8(\"h1\")]"
+        );
+    }
+
     #[test]
     fn test_convert_predicate_no_children() {
         let comparison = RPCNode {
@@ -530,6 +701,136 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_convert_predicate_tag_null_comparisons() {
+        let cases = vec![
+            (RPCComparison::Equal, "#state IS NULL"),
+            (RPCComparison::NotEqual, "#state IS NOT NULL"),
+        ];
+
+        for (comparison, expected_expr) in cases {
+            let mut node = make_tag_ref_node(b"state", "");
+            node.value = Some(RPCValue::Comparison(comparison as i32));
+            let rpc_predicate = RPCPredicate { root: Some(node) };
+
+            let predicate = PredicateBuilder::default()
+                .rpc_predicate(Some(rpc_predicate))
+                .expect("successfully converting predicate")
+                .build();
+
+            assert_eq!(predicate.exprs.len(), 1);
+            assert_eq!(format!("{:?}", predicate.exprs[0]), expected_expr);
+        }
+
+        // comparisons with other values are not null checks
+        let rpc_predicate = RPCPredicate {
+            root: Some(make_tag_ref_node(b"state", "MA")),
+        };
+        let predicate = PredicateBuilder::default()
+            .rpc_predicate(Some(rpc_predicate))
+            .expect("successfully converting predicate")
+            .build();
+        assert_eq!(
+            format!("{:?}", predicate.exprs[0]),
+            "#state Eq Utf8(\"MA\")"
+        );
+    }
+
+    #[test]
+    fn test_convert_predicate_tag_regex_ignoring_case() {
+        let regex_node = |tag_name: &[u8], regexp: &str| RPCNode {
+            node_type: RPCNodeType::ComparisonExpression as i32,
+            children: vec![
+                RPCNode {
+                    node_type: RPCNodeType::TagRef as i32,
+                    children: vec![],
+                    value: Some(RPCValue::TagRefValue(tag_name.to_vec())),
+                },
+                RPCNode {
+                    node_type: RPCNodeType::Literal as i32,
+                    children: vec![],
+                    value: Some(RPCValue::RegexValue(regexp.into())),
+                },
+            ],
+            value: Some(RPCValue::Comparison(RPCComparison::Regex as i32)),
+        };
+
+        let cases = vec![
+            (r"(?i)^server-a$", r#"lower(#host) Eq Utf8("server-a")"#),
+            (r"(?i)^Server-A$", r#"lower(#host) Eq Utf8("server-a")"#),
+            (
+                r"(?i)^a\.example\.com$",
+                r#"lower(#host) Eq Utf8("a.example.com")"#,
+            ),
+        ];
+        for (regexp, expected_expr) in cases {
+            let rpc_predicate = RPCPredicate {
+                root: Some(regex_node(b"host", regexp)),
+            };
+            let predicate = PredicateBuilder::default()
+                .rpc_predicate(Some(rpc_predicate))
+                .expect("successfully converting predicate")
+                .build();
+
+            assert_eq!(predicate.exprs.len(), 1);
+            assert_eq!(format!("{:?}", predicate.exprs[0]), expected_expr);
+        }
+
+        // other regular expressions are still not supported
+        let cases = vec![
+            r"^server-a$",
+            r"(?i)server-a",
+            r"(?i)^server-.$",
+            r"(?i)^server\d$",
+        ];
+        for regexp in cases {
+            let rpc_predicate = RPCPredicate {
+                root: Some(regex_node(b"host", regexp)),
+            };
+            let res = PredicateBuilder::default().rpc_predicate(Some(rpc_predicate));
+
+            let actual_error = error_result_to_string(res);
+            assert!(
+                actual_error.contains("Regular expression predicates are not supported"),
+                "{}: {}",
+                regexp,
+                actual_error
+            );
+        }
+    }
+
+    #[test]
+    fn test_convert_predicate_wrong_number_of_children() {
+        // Found by fuzzing: looking for an IN list used to panic when
+        // an equal or OR node did not have two children
+        let (comparison, _) = make_host_comparison();
+
+        let equal = RPCNode {
+            node_type: RPCNodeType::ComparisonExpression as i32,
+            children: vec![make_tag_ref_node(b"host", "h1")],
+            value: Some(RPCValue::Comparison(RPCComparison::Equal as i32)),
+        };
+        let or = RPCNode {
+            node_type: RPCNodeType::LogicalExpression as i32,
+            children: vec![comparison.clone(), comparison.clone(), comparison],
+            value: Some(RPCValue::Logical(RPCLogical::Or as i32)),
+        };
+
+        for root in vec![equal, or] {
+            let rpc_predicate = RPCPredicate { root: Some(root) };
+            let res = PredicateBuilder::default().rpc_predicate(Some(rpc_predicate));
+
+            let expected_error = "Error creating predicate: Unsupported number of children";
+            let actual_error = error_result_to_string(res);
+            assert!(
+                actual_error.contains(expected_error),
+                "expected '{}' not found in '{}'",
+                expected_error,
+                actual_error
+            );
+        }
+    }
+
     #[test]
     fn test_convert_predicate_comparison_bad_values() {
         // Send in invalid input to simulate a bad actor
@@ -0,0 +1,951 @@
+//! This module contains an implementation of the Arrow Flight
+//! service, which lets Arrow native clients run SQL queries against a
+//! `storage::Database` and receive the results as a stream of
+//! `RecordBatch`es.
+//!
+//! The ticket passed to `DoGet` is a JSON encoded [`ReadInfo`], for
+//! example:
+//!
+//! ```json
+//! {"database_name": "MyOrg_MyBucket", "sql_query": "select * from cpu"}
+//! ```
+//!
+//! Instead of naming a database, the ticket may omit `database_name`
+//! and the request address a bucket of an org with `org` and `bucket`
+//! gRPC metadata, as with the InfluxDB 2.0 APIs.
+//!
+//! `ListFlights` lists the tables of the database named by the
+//! criteria expression (or addressed by `org` and `bucket` metadata),
+//! as flights whose descriptor path is the database and table name.
+//! `GetSchema` returns the schema of the table such a descriptor names,
+//! listing its tag columns in the `iox::tag_columns` schema metadata.
+//!
+//! The query may contain `$name` placeholders, whose values are given
+//! in an optional `params` object:
+//!
+//! ```json
+//! {
+//!   "database_name": "MyOrg_MyBucket",
+//!   "sql_query": "select * from cpu where host = $host",
+//!   "params": {"host": "server01"}
+//! }
+//! ```
+//!
+//! The service also implements the Arrow Flight SQL protocol, as
+//! described in the `flight_sql` module. The database of a Flight SQL
+//! request is named by its `iox-database` metadata.
+
+use std::{collections::HashMap, pin::Pin, sync::Arc};
+
+use arrow_deps::{
+    arrow::{
+        datatypes::{Schema, SchemaRef},
+        ipc::writer::IpcWriteOptions,
+        record_batch::RecordBatch,
+    },
+    arrow_flight::{
+        flight_descriptor::DescriptorType,
+        flight_service_server::FlightService as Flight,
+        utils::{
+            flight_data_from_arrow_batch, flight_data_from_arrow_schema,
+            flight_schema_from_arrow_schema,
+        },
+        Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint,
+        FlightInfo, HandshakeRequest, HandshakeResponse, PutResult, SchemaResult, Ticket,
+    },
+};
+use data_types::{error::ErrorLogger, TAG_COLUMNS_METADATA_KEY};
+use futures::Stream;
+use generated_types::flight_sql::{
+    ActionCreatePreparedStatementResult, CommandGetTables, TicketStatementQuery,
+};
+use serde::{Deserialize, Serialize};
+use snafu::{OptionExt, ResultExt, Snafu};
+use storage::{
+    exec::Executor as StorageExecutor,
+    org_and_bucket_to_database,
+    predicate::{Predicate, PredicateBuilder},
+    Database, DatabaseStore,
+};
+use tokio::sync::mpsc;
+use tonic::{metadata::MetadataMap, Request, Response, Status, Streaming};
+use tracing::info;
+
+use crate::server::{
+    access::Access,
+    auth::{self, Authorizer, Permission, Principal},
+    query_params::{self, Params},
+    quota::Quotas,
+    rpc::flight_sql::{self, Command, TableInfo},
+};
+
+/// The metadata naming the database of a Flight SQL request
+pub const DATABASE_METADATA: &str = "iox-database";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid ticket. Error: {} Ticket: {:?}", source, ticket))]
+    InvalidTicket {
+        source: std::string::FromUtf8Error,
+        ticket: Vec<u8>,
+    },
+
+    #[snafu(display("Invalid query, could not parse '{}': {}", query, source))]
+    InvalidQuery {
+        query: String,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Ticket does not specify a database_name, and request has no org and bucket metadata"
+    ))]
+    MissingDatabase,
+
+    #[snafu(display("Database {} not found", database_name))]
+    DatabaseNotFound { database_name: String },
+
+    #[snafu(display("Error binding query parameters: {}", source))]
+    BindingParams { source: query_params::Error },
+
+    #[snafu(display("Error running query in database {}: {}", database_name, source))]
+    Query {
+        database_name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Invalid database name in criteria: {}", source))]
+    InvalidCriteria { source: std::string::FromUtf8Error },
+
+    #[snafu(display(
+        "Invalid flight descriptor, expected a path of a database and table name: {:?}",
+        descriptor
+    ))]
+    InvalidDescriptor { descriptor: FlightDescriptor },
+
+    #[snafu(display("Error listing tables of database {}: {}", database_name, source))]
+    ListingTables {
+        database_name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display(
+        "Error listing tag columns of table {} in database {}: {}",
+        table_name,
+        database_name,
+        source
+    ))]
+    ListingTagColumns {
+        database_name: String,
+        table_name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("Table {} not found in database {}", table_name, database_name))]
+    TableNotFound {
+        database_name: String,
+        table_name: String,
+    },
+
+    #[snafu(display("Error sending results via channel:  {}", source))]
+    SendingResults {
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+
+    #[snafu(display("{}", source))]
+    FlightSql { source: flight_sql::Error },
+
+    #[snafu(display(
+        "Flight SQL request has no {} metadata, nor org and bucket metadata",
+        DATABASE_METADATA
+    ))]
+    MissingDatabaseMetadata,
+
+    #[snafu(display(
+        "Invalid flight descriptor, expected a Flight SQL command: {:?}",
+        descriptor
+    ))]
+    ExpectedCommand { descriptor: FlightDescriptor },
+
+    #[snafu(display("Invalid statement handle: {}", source))]
+    InvalidHandle { source: serde_json::Error },
+
+    #[snafu(display("Unexpected Flight SQL message {:?} for {}", command, request))]
+    UnexpectedCommand {
+        command: Box<Command>,
+        request: &'static str,
+    },
+
+    #[snafu(display("Unsupported action {}", action_type))]
+    UnsupportedAction { action_type: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Converts a result from the business logic into the appropriate tonic status
+    fn to_status(&self) -> tonic::Status {
+        match &self {
+            Self::InvalidTicket { .. } => Status::invalid_argument(self.to_string()),
+            Self::InvalidQuery { .. } => Status::invalid_argument(self.to_string()),
+            Self::MissingDatabase => Status::invalid_argument(self.to_string()),
+            Self::DatabaseNotFound { .. } => Status::not_found(self.to_string()),
+            Self::BindingParams { .. } => Status::invalid_argument(self.to_string()),
+            Self::Query { .. } => Status::invalid_argument(self.to_string()),
+            Self::InvalidCriteria { .. } => Status::invalid_argument(self.to_string()),
+            Self::InvalidDescriptor { .. } => Status::invalid_argument(self.to_string()),
+            Self::ListingTables { .. } => Status::internal(self.to_string()),
+            Self::ListingTagColumns { .. } => Status::internal(self.to_string()),
+            Self::TableNotFound { .. } => Status::not_found(self.to_string()),
+            Self::SendingResults { .. } => Status::internal(self.to_string()),
+            Self::FlightSql { source } => source.to_status(),
+            Self::MissingDatabaseMetadata => Status::invalid_argument(self.to_string()),
+            Self::ExpectedCommand { .. } => Status::invalid_argument(self.to_string()),
+            Self::InvalidHandle { .. } => Status::invalid_argument(self.to_string()),
+            Self::UnexpectedCommand { .. } => Status::invalid_argument(self.to_string()),
+            Self::UnsupportedAction { .. } => Status::unimplemented(self.to_string()),
+        }
+    }
+}
+
+/// The query encoded in a Flight `Ticket`, or in the handle of a
+/// Flight SQL statement
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ReadInfo {
+    /// The database to query, if not addressed by request metadata
+    #[serde(default)]
+    pub database_name: Option<String>,
+    pub sql_query: String,
+    /// Values for the `$name` placeholders in `sql_query`, if any
+    #[serde(default)]
+    pub params: Option<Params>,
+}
+
+type TonicStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send + Sync + 'static>>;
+
+#[derive(Debug)]
+pub struct FlightService<T: DatabaseStore> {
+    db_store: Arc<T>,
+    /// Runs the plans listing the tables of a database
+    executor: Arc<StorageExecutor>,
+    /// Authorizes the API token of each request
+    authorizer: Arc<Authorizer>,
+    /// Limits the queries of each database
+    quotas: Arc<Quotas>,
+    /// Whether the server accepts queries
+    access: Arc<Access>,
+}
+
+impl<T> FlightService<T>
+where
+    T: DatabaseStore + 'static,
+{
+    /// Create a new FlightService connected to `db_store`
+    pub fn new(db_store: Arc<T>, executor: Arc<StorageExecutor>) -> Self {
+        Self {
+            db_store,
+            executor,
+            authorizer: Arc::new(Authorizer::disabled()),
+            quotas: Arc::new(Quotas::new()),
+            access: Arc::new(Access::default()),
+        }
+    }
+
+    /// Authorize the API token of each request with `authorizer`
+    pub fn with_authorizer(self, authorizer: Arc<Authorizer>) -> Self {
+        Self { authorizer, ..self }
+    }
+
+    /// Limit the queries of each database to its quota in `quotas`
+    pub fn with_quotas(self, quotas: Arc<Quotas>) -> Self {
+        Self { quotas, ..self }
+    }
+
+    /// Reject queries while the access mode of `access` does not
+    /// accept them
+    pub fn with_access(self, access: Arc<Access>) -> Self {
+        Self { access, ..self }
+    }
+
+    /// Returns the principal the API token in `metadata` authenticates
+    /// as, looking it up again after `GrpcAuthentication`
+    fn authenticate(&self, metadata: &MetadataMap) -> Result<Principal, Status> {
+        let authorization = metadata
+            .get(auth::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        self.authorizer
+            .authenticate(authorization)
+            .map_err(|e| e.to_status())
+    }
+
+    /// Authenticates the API token in `metadata` and checks that it
+    /// may read the database `database_name`
+    fn authorize_read(&self, metadata: &MetadataMap, database_name: &str) -> Result<(), Status> {
+        self.access.check_query().map_err(|e| e.to_status())?;
+
+        self.authenticate(metadata)?
+            .authorize(database_name, Permission::Read)
+            .map_err(|e| e.to_status())
+    }
+
+    /// Returns the schema of the results of the Flight SQL statement
+    /// `read_info`, if `principal` may query its database. The
+    /// statement is run to find the schema of its results.
+    async fn statement_schema(
+        &self,
+        principal: &Principal,
+        read_info: &ReadInfo,
+    ) -> Result<SchemaRef, Status> {
+        let database_name = read_info
+            .database_name
+            .clone()
+            .context(MissingDatabaseMetadata)
+            .map_err(|e| e.to_status())?;
+
+        principal
+            .authorize(&database_name, Permission::Read)
+            .map_err(|e| e.to_status())?;
+        self.quotas
+            .check_query(&database_name)
+            .map_err(|e| e.to_status())?;
+
+        let results = run_query(self.db_store.as_ref(), &database_name, read_info.clone())
+            .await
+            .map_err(|e| e.to_status())?;
+        Ok(results_schema(&results))
+    }
+
+    /// Returns the results of a Flight SQL command listing the catalog,
+    /// limited to the databases `principal` may read
+    async fn catalog_batch(&self, principal: &Principal, command: &Command) -> Result<RecordBatch> {
+        match command {
+            Command::GetCatalogs(_) => flight_sql::catalogs_batch(command).context(FlightSql),
+            Command::GetDbSchemas(get_db_schemas) => {
+                let database_names: Vec<_> = if flight_sql::matches_catalog(&get_db_schemas.catalog)
+                {
+                    self.db_store
+                        .db_names_sorted()
+                        .await
+                        .into_iter()
+                        .filter(|name| {
+                            flight_sql::matches_pattern(
+                                &get_db_schemas.db_schema_filter_pattern,
+                                name,
+                            ) && principal.is_authorized(name, Permission::Read)
+                        })
+                        .collect()
+                } else {
+                    vec![]
+                };
+                flight_sql::db_schemas_batch(command, &database_names).context(FlightSql)
+            }
+            Command::GetTables(get_tables) => {
+                let tables = self.list_tables(principal, get_tables).await?;
+                flight_sql::tables_batch(command, &tables).context(FlightSql)
+            }
+            Command::GetTableTypes(_) => flight_sql::table_types_batch(command).context(FlightSql),
+            command => UnexpectedCommand {
+                command: Box::new(command.clone()),
+                request: "DoGet",
+            }
+            .fail(),
+        }
+    }
+
+    /// Returns the tables matching the filters of `get_tables`, of the
+    /// databases `principal` may query
+    async fn list_tables(
+        &self,
+        principal: &Principal,
+        get_tables: &CommandGetTables,
+    ) -> Result<Vec<TableInfo>> {
+        let mut tables = vec![];
+        let lists_tables = get_tables.table_types.is_empty()
+            || get_tables
+                .table_types
+                .iter()
+                .any(|table_type| table_type == flight_sql::TABLE_TYPE);
+        if !lists_tables || !flight_sql::matches_catalog(&get_tables.catalog) {
+            return Ok(tables);
+        }
+
+        let options = IpcWriteOptions::default();
+        for database_name in self.db_store.db_names_sorted().await {
+            if !flight_sql::matches_pattern(&get_tables.db_schema_filter_pattern, &database_name)
+                || principal
+                    .authorize(&database_name, Permission::Read)
+                    .is_err()
+            {
+                continue;
+            }
+            // the database may have been deleted since it was listed
+            let db = match self.db_store.db(&database_name).await {
+                Some(db) => db,
+                None => continue,
+            };
+
+            for table_name in table_names(&*db, &self.executor, &database_name).await? {
+                if !flight_sql::matches_pattern(&get_tables.table_name_filter_pattern, &table_name)
+                {
+                    continue;
+                }
+
+                let schema = if get_tables.include_schema {
+                    let schema =
+                        table_schema(&*db, &self.executor, &database_name, &table_name).await?;
+                    Some(flight_schema_from_arrow_schema(&schema, &options).schema)
+                } else {
+                    None
+                };
+                tables.push(TableInfo {
+                    database_name: database_name.clone(),
+                    table_name,
+                    schema,
+                });
+            }
+        }
+        Ok(tables)
+    }
+
+    /// Returns the database named `database_name`
+    async fn db(&self, database_name: &str) -> Result<Arc<T::Database>> {
+        self.db_store
+            .db(database_name)
+            .await
+            .context(DatabaseNotFound { database_name })
+    }
+}
+
+/// Implements the Arrow Flight service, and the Flight SQL protocol on
+/// top of it, for a DatabaseStore
+#[tonic::async_trait]
+impl<T> Flight for FlightService<T>
+where
+    T: DatabaseStore + 'static,
+{
+    type HandshakeStream = TonicStream<HandshakeResponse>;
+    type ListFlightsStream = TonicStream<FlightInfo>;
+    type DoGetStream = mpsc::Receiver<Result<FlightData, Status>>;
+    type DoPutStream = TonicStream<PutResult>;
+    type DoActionStream = TonicStream<arrow_deps::arrow_flight::Result>;
+    type ListActionsStream = TonicStream<ActionType>;
+    type DoExchangeStream = TonicStream<FlightData>;
+
+    async fn get_schema(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<SchemaResult>, Status> {
+        let (metadata, descriptor) = (request.metadata().clone(), request.into_inner());
+        let (database_name, table_name) = match descriptor.path.as_slice() {
+            [database_name, table_name] if descriptor.r#type == DescriptorType::Path as i32 => {
+                (database_name.clone(), table_name.clone())
+            }
+            _ => {
+                return InvalidDescriptor { descriptor }
+                    .fail()
+                    .map_err(|e| e.to_status())
+            }
+        };
+        self.authorize_read(&metadata, &database_name)?;
+
+        let db = self.db(&database_name).await.map_err(|e| e.to_status())?;
+        let schema = table_schema(&*db, &self.executor, &database_name, &table_name)
+            .await
+            .map_err(|e| e.to_status())?;
+
+        let options = IpcWriteOptions::default();
+        Ok(Response::new(flight_schema_from_arrow_schema(
+            &schema, &options,
+        )))
+    }
+
+    async fn do_get(
+        &self,
+        request: Request<Ticket>,
+    ) -> Result<Response<Self::DoGetStream>, Status> {
+        let (tx, rx) = mpsc::channel(4);
+
+        let metadata_database_name = metadata_database_name(request.metadata());
+        let principal = self.authenticate(request.metadata())?;
+
+        let ticket = request.into_inner().ticket;
+        let read_info = if flight_sql::is_flight_sql_ticket(&ticket) {
+            match Command::decode(&ticket)
+                .context(FlightSql)
+                .map_err(|e| e.to_status())?
+            {
+                Command::TicketStatementQuery(ticket) => {
+                    parse_handle(&ticket.statement_handle).map_err(|e| e.to_status())?
+                }
+                command => {
+                    self.access.check_query().map_err(|e| e.to_status())?;
+                    let batch = self
+                        .catalog_batch(&principal, &command)
+                        .await
+                        .map_err(|e| e.to_status())?;
+
+                    tokio::spawn(async move {
+                        send_flight_data(tx, vec![batch])
+                            .await
+                            .log_if_error("Sending Flight SQL catalog")
+                    });
+                    return Ok(Response::new(rx));
+                }
+            }
+        } else {
+            parse_ticket(ticket).map_err(|e| e.to_status())?
+        };
+
+        let database_name = read_info
+            .database_name
+            .clone()
+            .or(metadata_database_name)
+            .context(MissingDatabase)
+            .map_err(|e| e.to_status())?;
+
+        principal
+            .authorize(&database_name, Permission::Read)
+            .map_err(|e| e.to_status())?;
+        self.access.check_query().map_err(|e| e.to_status())?;
+        self.quotas
+            .check_query(&database_name)
+            .map_err(|e| e.to_status())?;
+
+        info!(
+            "do_get for database {}, query: {}",
+            database_name, read_info.sql_query
+        );
+
+        do_get_impl(tx, self.db_store.clone(), database_name, read_info)
+            .await
+            .map_err(|e| e.to_status())?;
+
+        Ok(Response::new(rx))
+    }
+
+    async fn handshake(
+        &self,
+        _request: Request<Streaming<HandshakeRequest>>,
+    ) -> Result<Response<Self::HandshakeStream>, Status> {
+        Err(Status::unimplemented("handshake"))
+    }
+
+    async fn list_flights(
+        &self,
+        request: Request<Criteria>,
+    ) -> Result<Response<Self::ListFlightsStream>, Status> {
+        let metadata_database_name = metadata_database_name(request.metadata());
+        let metadata = request.metadata().clone();
+
+        let expression = request.into_inner().expression;
+        let database_name = if expression.is_empty() {
+            metadata_database_name
+        } else {
+            Some(
+                String::from_utf8(expression)
+                    .context(InvalidCriteria)
+                    .map_err(|e| e.to_status())?,
+            )
+        }
+        .context(MissingDatabase)
+        .map_err(|e| e.to_status())?;
+        self.authorize_read(&metadata, &database_name)?;
+
+        let db = self.db(&database_name).await.map_err(|e| e.to_status())?;
+        let table_names = table_names(&*db, &self.executor, &database_name)
+            .await
+            .map_err(|e| e.to_status())?;
+
+        let flights: Vec<_> = table_names
+            .iter()
+            .map(|table_name| {
+                Ok::<_, Status>(FlightInfo {
+                    schema: vec![],
+                    flight_descriptor: Some(FlightDescriptor {
+                        r#type: DescriptorType::Path as i32,
+                        cmd: vec![],
+                        path: vec![database_name.clone(), table_name.clone()],
+                    }),
+                    endpoint: vec![],
+                    total_records: -1,
+                    total_bytes: -1,
+                })
+            })
+            .collect();
+
+        Ok(Response::new(Box::pin(futures::stream::iter(flights))))
+    }
+
+    async fn get_flight_info(
+        &self,
+        request: Request<FlightDescriptor>,
+    ) -> Result<Response<FlightInfo>, Status> {
+        let (metadata, descriptor) = (request.metadata().clone(), request.into_inner());
+        if descriptor.r#type != DescriptorType::Cmd as i32 {
+            return ExpectedCommand { descriptor }
+                .fail()
+                .map_err(|e| e.to_status());
+        }
+
+        let principal = self.authenticate(&metadata)?;
+        self.access.check_query().map_err(|e| e.to_status())?;
+
+        let command = Command::decode(&descriptor.cmd)
+            .context(FlightSql)
+            .map_err(|e| e.to_status())?;
+        let read_info = match command {
+            Command::StatementQuery(statement) => {
+                statement_read_info(&metadata, statement.query).map_err(|e| e.to_status())?
+            }
+            Command::PreparedStatementQuery(statement) => {
+                parse_handle(&statement.prepared_statement_handle).map_err(|e| e.to_status())?
+            }
+            command => {
+                // the catalog is listed with the command as the ticket
+                let schema = command
+                    .metadata_schema()
+                    .context(UnexpectedCommand {
+                        command: Box::new(command.clone()),
+                        request: "GetFlightInfo",
+                    })
+                    .map_err(|e| e.to_status())?;
+                return Ok(Response::new(flight_info(
+                    &schema,
+                    descriptor,
+                    command.encode(),
+                )));
+            }
+        };
+
+        info!(
+            "get_flight_info for database {:?}, query: {}",
+            read_info.database_name, read_info.sql_query
+        );
+
+        let schema = self.statement_schema(&principal, &read_info).await?;
+        let ticket = Command::TicketStatementQuery(TicketStatementQuery {
+            statement_handle: encode_handle(&read_info),
+        });
+        Ok(Response::new(flight_info(
+            &schema,
+            descriptor,
+            ticket.encode(),
+        )))
+    }
+
+    async fn do_put(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoPutStream>, Status> {
+        Err(Status::unimplemented("do_put"))
+    }
+
+    async fn do_action(
+        &self,
+        request: Request<Action>,
+    ) -> Result<Response<Self::DoActionStream>, Status> {
+        let (metadata, action) = (request.metadata().clone(), request.into_inner());
+        let principal = self.authenticate(&metadata)?;
+        self.access.check_query().map_err(|e| e.to_status())?;
+
+        let command = match action.r#type.as_str() {
+            flight_sql::CREATE_PREPARED_STATEMENT | flight_sql::CLOSE_PREPARED_STATEMENT => {
+                Command::decode(&action.body)
+                    .context(FlightSql)
+                    .map_err(|e| e.to_status())?
+            }
+            _ => {
+                return UnsupportedAction {
+                    action_type: action.r#type,
+                }
+                .fail()
+                .map_err(|e| e.to_status())
+            }
+        };
+
+        let results = match command {
+            Command::CreatePreparedStatement(statement) => {
+                let read_info =
+                    statement_read_info(&metadata, statement.query).map_err(|e| e.to_status())?;
+                let schema = self.statement_schema(&principal, &read_info).await?;
+
+                let options = IpcWriteOptions::default();
+                let result = ActionCreatePreparedStatementResult {
+                    prepared_statement_handle: encode_handle(&read_info),
+                    dataset_schema: flight_schema_from_arrow_schema(&schema, &options).schema,
+                    parameter_schema: vec![],
+                };
+                vec![Ok(arrow_deps::arrow_flight::Result {
+                    body: flight_sql::encode_prepared_statement(&result),
+                })]
+            }
+            // handles hold their statement, so there is nothing to close
+            Command::ClosePreparedStatement(_) => vec![],
+            command => {
+                return UnexpectedCommand {
+                    command: Box::new(command),
+                    request: "DoAction",
+                }
+                .fail()
+                .map_err(|e| e.to_status())
+            }
+        };
+
+        Ok(Response::new(Box::pin(futures::stream::iter(results))))
+    }
+
+    async fn list_actions(
+        &self,
+        _request: Request<Empty>,
+    ) -> Result<Response<Self::ListActionsStream>, Status> {
+        let actions = vec![
+            ActionType {
+                r#type: flight_sql::CREATE_PREPARED_STATEMENT.to_string(),
+                description: "Creates a Flight SQL prepared statement".to_string(),
+            },
+            ActionType {
+                r#type: flight_sql::CLOSE_PREPARED_STATEMENT.to_string(),
+                description: "Closes a Flight SQL prepared statement".to_string(),
+            },
+        ];
+
+        Ok(Response::new(Box::pin(futures::stream::iter(
+            actions.into_iter().map(Ok),
+        ))))
+    }
+
+    async fn do_exchange(
+        &self,
+        _request: Request<Streaming<FlightData>>,
+    ) -> Result<Response<Self::DoExchangeStream>, Status> {
+        Err(Status::unimplemented("do_exchange"))
+    }
+}
+
+/// Returns the name of the database named by the `iox-database`
+/// metadata of a request, or of the bucket addressed by its `org` and
+/// `bucket` metadata, if any
+fn metadata_database_name(metadata: &MetadataMap) -> Option<String> {
+    if let Some(database_name) = metadata
+        .get(DATABASE_METADATA)
+        .and_then(|value| value.to_str().ok())
+    {
+        return Some(database_name.to_string());
+    }
+
+    let org = metadata.get("org")?.to_str().ok()?;
+    let bucket = metadata.get("bucket")?.to_str().ok()?;
+    Some(org_and_bucket_to_database(org, bucket))
+}
+
+/// Returns the names of the tables of `db`
+async fn table_names<D: Database>(
+    db: &D,
+    executor: &StorageExecutor,
+    database_name: &str,
+) -> Result<Vec<String>> {
+    let plan = db
+        .table_names(Predicate::default())
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(ListingTables { database_name })?
+        .plan;
+    let table_names = executor
+        .to_string_set(plan)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(ListingTables { database_name })?;
+
+    Ok(table_names.iter().cloned().collect())
+}
+
+/// Returns the schema of the table `table_name` of `db`. As tables
+/// only exist once rows have been written to them, this is the schema
+/// of the first row of the table. Its tag columns are listed in the
+/// `TAG_COLUMNS_METADATA_KEY` metadata.
+async fn table_schema<D: Database>(
+    db: &D,
+    executor: &StorageExecutor,
+    database_name: &str,
+    table_name: &str,
+) -> Result<Arc<Schema>> {
+    let sql_query = format!(
+        "select * from \"{}\" limit 1",
+        table_name.replace('"', "\"\"")
+    );
+    let results = db
+        .query(&sql_query)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(Query { database_name })?;
+
+    let schema = results
+        .first()
+        .map(|batch| batch.schema())
+        .context(TableNotFound {
+            database_name,
+            table_name,
+        })?;
+
+    let predicate = PredicateBuilder::default().table(table_name).build();
+    let plan = db
+        .tag_column_names(predicate)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(ListingTagColumns {
+            database_name,
+            table_name,
+        })?
+        .plan;
+    let tag_columns = executor
+        .to_string_set(plan)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(ListingTagColumns {
+            database_name,
+            table_name,
+        })?;
+
+    let mut metadata = HashMap::new();
+    metadata.insert(
+        TAG_COLUMNS_METADATA_KEY.to_string(),
+        serde_json::to_string(&*tag_columns).expect("string sets serialize to JSON"),
+    );
+    Ok(Arc::new(Schema::new_with_metadata(
+        schema.fields().clone(),
+        metadata,
+    )))
+}
+
+/// Returns the Flight SQL statement `sql_query`, of the database named
+/// by the metadata of the request
+fn statement_read_info(metadata: &MetadataMap, sql_query: String) -> Result<ReadInfo> {
+    let database_name = metadata_database_name(metadata).context(MissingDatabaseMetadata)?;
+    Ok(ReadInfo {
+        database_name: Some(database_name),
+        sql_query,
+        params: None,
+    })
+}
+
+/// Encodes a Flight SQL statement as the handle of a statement or
+/// prepared statement
+fn encode_handle(read_info: &ReadInfo) -> Vec<u8> {
+    serde_json::to_vec(read_info).expect("statements serialize to JSON")
+}
+
+/// Decodes the handle of a Flight SQL statement or prepared statement
+fn parse_handle(handle: &[u8]) -> Result<ReadInfo> {
+    serde_json::from_slice(handle).context(InvalidHandle)
+}
+
+/// Returns the information of the flight of the results with `schema`
+/// of `descriptor`, which are fetched with `ticket`
+fn flight_info(schema: &Schema, descriptor: FlightDescriptor, ticket: Vec<u8>) -> FlightInfo {
+    let options = IpcWriteOptions::default();
+    FlightInfo {
+        schema: flight_schema_from_arrow_schema(schema, &options).schema,
+        flight_descriptor: Some(descriptor),
+        endpoint: vec![FlightEndpoint {
+            ticket: Some(Ticket { ticket }),
+            location: vec![],
+        }],
+        total_records: -1,
+        total_bytes: -1,
+    }
+}
+
+/// Decodes the JSON `ReadInfo` from a Flight ticket
+fn parse_ticket(ticket: Vec<u8>) -> Result<ReadInfo> {
+    let json_str = String::from_utf8(ticket.clone()).context(InvalidTicket { ticket })?;
+
+    serde_json::from_str(&json_str).context(InvalidQuery { query: &json_str })
+}
+
+/// Runs the query described by `read_info` against `database_name`
+/// and spawns a task that sends the schema followed by each of the
+/// resulting record batches to `tx`
+async fn do_get_impl<T>(
+    tx: mpsc::Sender<Result<FlightData, Status>>,
+    db_store: Arc<T>,
+    database_name: String,
+    read_info: ReadInfo,
+) -> Result<()>
+where
+    T: DatabaseStore,
+{
+    let results = run_query(db_store.as_ref(), &database_name, read_info).await?;
+
+    // Send the results from a separate task so we can return the
+    // stream to the client before the channel fills up
+    tokio::spawn(async move {
+        send_flight_data(tx, results)
+            .await
+            .log_if_error("Sending flight data")
+    });
+
+    Ok(())
+}
+
+/// Runs the query described by `read_info` against `database_name`
+async fn run_query<T>(
+    db_store: &T,
+    database_name: &str,
+    read_info: ReadInfo,
+) -> Result<Vec<RecordBatch>>
+where
+    T: DatabaseStore,
+{
+    let ReadInfo {
+        database_name: _,
+        sql_query,
+        params,
+    } = read_info;
+
+    let sql_query = match params {
+        Some(params) => query_params::bind_sql(&sql_query, &params).context(BindingParams)?,
+        None => sql_query,
+    };
+
+    let db = db_store
+        .db(database_name)
+        .await
+        .context(DatabaseNotFound { database_name })?;
+
+    db.query(&sql_query)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(Query { database_name })
+}
+
+/// Returns the schema of `results`, which is empty if there are none
+fn results_schema(results: &[RecordBatch]) -> SchemaRef {
+    results
+        .first()
+        .map(|batch| batch.schema())
+        .unwrap_or_else(|| Arc::new(Schema::empty()))
+}
+
+/// Encodes `results` as Flight data and sends them to `tx`
+async fn send_flight_data(
+    mut tx: mpsc::Sender<Result<FlightData, Status>>,
+    results: Vec<RecordBatch>,
+) -> Result<()> {
+    let options = IpcWriteOptions::default();
+
+    let schema = results_schema(&results);
+
+    let flights = std::iter::once(flight_data_from_arrow_schema(&schema, &options)).chain(
+        results
+            .iter()
+            .map(|batch| flight_data_from_arrow_batch(batch, &options)),
+    );
+
+    for flight in flights {
+        tx.send(Ok(flight))
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+            .context(SendingResults)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,384 @@
+//! This module contains the messages of the Arrow Flight SQL protocol,
+//! which lets off-the-shelf Flight SQL drivers (such as the JDBC, ODBC
+//! and ADBC drivers) query IOx through its Arrow Flight service.
+//!
+//! Flight SQL requests are `google.protobuf.Any` encoded messages in
+//! the command of a `FlightDescriptor`, the body of an `Action` or a
+//! `Ticket`. The server supports:
+//!
+//! * `CommandStatementQuery`, to run a SQL query
+//! * the `CreatePreparedStatement` and `ClosePreparedStatement`
+//!   actions, and `CommandPreparedStatementQuery` to run a prepared
+//!   statement. Binding parameters to prepared statements is not
+//!   supported.
+//! * `CommandGetCatalogs`, `CommandGetDbSchemas`, `CommandGetTables`
+//!   and `CommandGetTableTypes`, to list the catalog
+//!
+//! Each IOx database is a database schema of the single catalog `iox`.
+//! As Flight SQL statements do not name the database they query, it is
+//! named by the `iox-database` metadata of the request (or addressed
+//! by its `org` and `bucket` metadata), which drivers send as a
+//! connection property.
+//!
+//! The handle of a statement or prepared statement holds the statement
+//! itself, so the server keeps no state for them between requests.
+
+use std::sync::Arc;
+
+use arrow_deps::arrow::{
+    array::{ArrayRef, BinaryArray, StringArray},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use generated_types::flight_sql::{
+    ActionClosePreparedStatementRequest, ActionCreatePreparedStatementRequest,
+    ActionCreatePreparedStatementResult, CommandGetCatalogs, CommandGetDbSchemas,
+    CommandGetTableTypes, CommandGetTables, CommandPreparedStatementQuery, CommandStatementQuery,
+    TicketStatementQuery,
+};
+use prost::Message;
+use snafu::{ResultExt, Snafu};
+
+/// The name of the catalog holding every database
+pub const CATALOG: &str = "iox";
+
+/// The type of every table
+pub const TABLE_TYPE: &str = "TABLE";
+
+/// The action creating a prepared statement
+pub const CREATE_PREPARED_STATEMENT: &str = "CreatePreparedStatement";
+
+/// The action closing a prepared statement
+pub const CLOSE_PREPARED_STATEMENT: &str = "ClosePreparedStatement";
+
+/// The prefix of the type URL of each Flight SQL message
+const TYPE_URL_PREFIX: &str = "type.googleapis.com/arrow.flight.protocol.sql.";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid Flight SQL message: {}", source))]
+    DecodingAny { source: prost::DecodeError },
+
+    #[snafu(display("Invalid Flight SQL message {}: {}", type_url, source))]
+    DecodingCommand {
+        type_url: String,
+        source: prost::DecodeError,
+    },
+
+    #[snafu(display("Unsupported Flight SQL message {}", type_url))]
+    UnsupportedCommand { type_url: String },
+
+    #[snafu(display("Error creating Flight SQL results: {}", source))]
+    CreatingBatch { source: ArrowError },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Converts the error into the appropriate tonic status
+    pub fn to_status(&self) -> tonic::Status {
+        match &self {
+            Self::DecodingAny { .. } | Self::DecodingCommand { .. } => {
+                tonic::Status::invalid_argument(self.to_string())
+            }
+            Self::UnsupportedCommand { .. } => tonic::Status::unimplemented(self.to_string()),
+            Self::CreatingBatch { .. } => tonic::Status::internal(self.to_string()),
+        }
+    }
+}
+
+/// A Flight SQL message supported by the server
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    StatementQuery(CommandStatementQuery),
+    TicketStatementQuery(TicketStatementQuery),
+    PreparedStatementQuery(CommandPreparedStatementQuery),
+    CreatePreparedStatement(ActionCreatePreparedStatementRequest),
+    ClosePreparedStatement(ActionClosePreparedStatementRequest),
+    GetCatalogs(CommandGetCatalogs),
+    GetDbSchemas(CommandGetDbSchemas),
+    GetTables(CommandGetTables),
+    GetTableTypes(CommandGetTableTypes),
+}
+
+impl Command {
+    /// Decodes the `Any` encoded message `bytes`
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let any = prost_types::Any::decode(bytes).context(DecodingAny)?;
+        let type_url = any.type_url.as_str();
+        let value = any.value.as_slice();
+
+        Ok(match type_url.strip_prefix(TYPE_URL_PREFIX) {
+            Some("CommandStatementQuery") => Self::StatementQuery(decode(type_url, value)?),
+            Some("TicketStatementQuery") => Self::TicketStatementQuery(decode(type_url, value)?),
+            Some("CommandPreparedStatementQuery") => {
+                Self::PreparedStatementQuery(decode(type_url, value)?)
+            }
+            Some("ActionCreatePreparedStatementRequest") => {
+                Self::CreatePreparedStatement(decode(type_url, value)?)
+            }
+            Some("ActionClosePreparedStatementRequest") => {
+                Self::ClosePreparedStatement(decode(type_url, value)?)
+            }
+            Some("CommandGetCatalogs") => Self::GetCatalogs(decode(type_url, value)?),
+            Some("CommandGetDbSchemas") => Self::GetDbSchemas(decode(type_url, value)?),
+            Some("CommandGetTables") => Self::GetTables(decode(type_url, value)?),
+            Some("CommandGetTableTypes") => Self::GetTableTypes(decode(type_url, value)?),
+            _ => return UnsupportedCommand { type_url }.fail(),
+        })
+    }
+
+    /// Encodes the message as an `Any`
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            Self::StatementQuery(m) => encode_any("CommandStatementQuery", m),
+            Self::TicketStatementQuery(m) => encode_any("TicketStatementQuery", m),
+            Self::PreparedStatementQuery(m) => encode_any("CommandPreparedStatementQuery", m),
+            Self::CreatePreparedStatement(m) => {
+                encode_any("ActionCreatePreparedStatementRequest", m)
+            }
+            Self::ClosePreparedStatement(m) => encode_any("ActionClosePreparedStatementRequest", m),
+            Self::GetCatalogs(m) => encode_any("CommandGetCatalogs", m),
+            Self::GetDbSchemas(m) => encode_any("CommandGetDbSchemas", m),
+            Self::GetTables(m) => encode_any("CommandGetTables", m),
+            Self::GetTableTypes(m) => encode_any("CommandGetTableTypes", m),
+        }
+    }
+
+    /// Returns the schema of the results of a command listing the
+    /// catalog, or `None` for other messages
+    pub fn metadata_schema(&self) -> Option<SchemaRef> {
+        let utf8 = |name: &str, nullable: bool| Field::new(name, DataType::Utf8, nullable);
+
+        let fields = match self {
+            Self::GetCatalogs(_) => vec![utf8("catalog_name", false)],
+            Self::GetDbSchemas(_) => {
+                vec![utf8("catalog_name", true), utf8("db_schema_name", false)]
+            }
+            Self::GetTables(command) => {
+                let mut fields = vec![
+                    utf8("catalog_name", true),
+                    utf8("db_schema_name", true),
+                    utf8("table_name", false),
+                    utf8("table_type", false),
+                ];
+                if command.include_schema {
+                    fields.push(Field::new("table_schema", DataType::Binary, false));
+                }
+                fields
+            }
+            Self::GetTableTypes(_) => vec![utf8("table_type", false)],
+            _ => return None,
+        };
+        Some(Arc::new(Schema::new(fields)))
+    }
+}
+
+fn decode<M: Message + Default>(type_url: &str, value: &[u8]) -> Result<M> {
+    M::decode(value).context(DecodingCommand { type_url })
+}
+
+fn encode_any(name: &str, message: &impl Message) -> Vec<u8> {
+    let mut value = Vec::with_capacity(message.encoded_len());
+    message
+        .encode(&mut value)
+        .expect("encoding into a Vec can not fail");
+
+    let any = prost_types::Any {
+        type_url: format!("{}{}", TYPE_URL_PREFIX, name),
+        value,
+    };
+    let mut bytes = Vec::with_capacity(any.encoded_len());
+    any.encode(&mut bytes)
+        .expect("encoding into a Vec can not fail");
+    bytes
+}
+
+/// Encodes the result of the `CreatePreparedStatement` action
+pub fn encode_prepared_statement(result: &ActionCreatePreparedStatementResult) -> Vec<u8> {
+    encode_any("ActionCreatePreparedStatementResult", result)
+}
+
+/// Returns true if `ticket` is a Flight SQL message, rather than the
+/// JSON encoded `ReadInfo` of IOx clients
+pub fn is_flight_sql_ticket(ticket: &[u8]) -> bool {
+    ticket.iter().find(|b| !b.is_ascii_whitespace()) != Some(&b'{')
+}
+
+/// Returns true if the catalog filter of a command matches the catalog
+pub fn matches_catalog(catalog: &str) -> bool {
+    catalog.is_empty() || catalog == CATALOG
+}
+
+/// Returns true if `value` matches the SQL `LIKE` pattern `pattern`,
+/// in which `%` matches any characters and `_` matches one. An empty
+/// pattern matches everything, as the pattern is unset.
+pub fn matches_pattern(pattern: &str, value: &str) -> bool {
+    fn matches(pattern: &[char], value: &[char]) -> bool {
+        match pattern.split_first() {
+            None => value.is_empty(),
+            Some(('%', rest)) => (0..=value.len()).any(|skip| matches(rest, &value[skip..])),
+            Some(('_', rest)) => !value.is_empty() && matches(rest, &value[1..]),
+            Some((c, rest)) => value.first() == Some(c) && matches(rest, &value[1..]),
+        }
+    }
+
+    if pattern.is_empty() {
+        return true;
+    }
+    let pattern: Vec<_> = pattern.chars().collect();
+    let value: Vec<_> = value.chars().collect();
+    matches(&pattern, &value)
+}
+
+/// Returns the results of `CommandGetCatalogs`
+pub fn catalogs_batch(command: &Command) -> Result<RecordBatch> {
+    batch(command, vec![Arc::new(StringArray::from(vec![CATALOG]))])
+}
+
+/// Returns the results of `CommandGetDbSchemas` listing the databases
+/// `database_names`
+pub fn db_schemas_batch(command: &Command, database_names: &[String]) -> Result<RecordBatch> {
+    let names: Vec<_> = database_names.iter().map(|name| name.as_str()).collect();
+    batch(
+        command,
+        vec![
+            Arc::new(StringArray::from(vec![CATALOG; names.len()])),
+            Arc::new(StringArray::from(names)),
+        ],
+    )
+}
+
+/// A table listed by `CommandGetTables`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TableInfo {
+    pub database_name: String,
+    pub table_name: String,
+    /// The IPC encoded schema of the table, if requested
+    pub schema: Option<Vec<u8>>,
+}
+
+/// Returns the results of `CommandGetTables` listing `tables`
+pub fn tables_batch(command: &Command, tables: &[TableInfo]) -> Result<RecordBatch> {
+    let mut columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(vec![CATALOG; tables.len()])),
+        Arc::new(StringArray::from(
+            tables
+                .iter()
+                .map(|table| table.database_name.as_str())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            tables
+                .iter()
+                .map(|table| table.table_name.as_str())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(vec![TABLE_TYPE; tables.len()])),
+    ];
+
+    if matches!(command, Command::GetTables(command) if command.include_schema) {
+        columns.push(Arc::new(BinaryArray::from(
+            tables
+                .iter()
+                .map(|table| table.schema.as_deref().unwrap_or_default())
+                .collect::<Vec<_>>(),
+        )));
+    }
+
+    batch(command, columns)
+}
+
+/// Returns the results of `CommandGetTableTypes`
+pub fn table_types_batch(command: &Command) -> Result<RecordBatch> {
+    batch(command, vec![Arc::new(StringArray::from(vec![TABLE_TYPE]))])
+}
+
+fn batch(command: &Command, columns: Vec<ArrayRef>) -> Result<RecordBatch> {
+    let schema = command
+        .metadata_schema()
+        .expect("only commands listing the catalog have results");
+    RecordBatch::try_new(schema, columns).context(CreatingBatch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn commands_round_trip() {
+        let commands = vec![
+            Command::StatementQuery(CommandStatementQuery {
+                query: "select * from cpu".into(),
+                transaction_id: vec![],
+            }),
+            Command::GetTables(CommandGetTables {
+                db_schema_filter_pattern: "MyOrg_%".into(),
+                include_schema: true,
+                ..Default::default()
+            }),
+            Command::GetCatalogs(CommandGetCatalogs {}),
+        ];
+
+        for command in commands {
+            let bytes = command.encode();
+            assert!(is_flight_sql_ticket(&bytes));
+            assert_eq!(Command::decode(&bytes).unwrap(), command);
+        }
+
+        assert!(!is_flight_sql_ticket(br#"{"sql_query":"select 1"}"#));
+
+        let unsupported = prost_types::Any {
+            type_url: format!("{}CommandStatementUpdate", TYPE_URL_PREFIX),
+            value: vec![],
+        };
+        let mut bytes = vec![];
+        unsupported.encode(&mut bytes).unwrap();
+        let err = Command::decode(&bytes).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Unsupported Flight SQL message \
+             type.googleapis.com/arrow.flight.protocol.sql.CommandStatementUpdate"
+        );
+        assert_eq!(err.to_status().code(), tonic::Code::Unimplemented);
+    }
+
+    #[test]
+    fn like_patterns() {
+        assert!(matches_pattern("", "anything"));
+        assert!(matches_pattern("cpu", "cpu"));
+        assert!(!matches_pattern("cpu", "cpu2"));
+        assert!(matches_pattern("cpu%", "cpu2"));
+        assert!(matches_pattern("%_load", "system_load"));
+        assert!(matches_pattern("c_u", "cpu"));
+        assert!(!matches_pattern("c_u", "cu"));
+        assert!(matches_pattern("%", ""));
+
+        assert!(matches_catalog(""));
+        assert!(matches_catalog(CATALOG));
+        assert!(!matches_catalog("other"));
+    }
+
+    #[test]
+    fn metadata_batches() {
+        let command = Command::GetTables(CommandGetTables {
+            include_schema: true,
+            ..Default::default()
+        });
+        let tables = vec![TableInfo {
+            database_name: "mydb".into(),
+            table_name: "cpu".into(),
+            schema: Some(vec![1, 2, 3]),
+        }];
+        let batch = tables_batch(&command, &tables).unwrap();
+        assert_eq!(batch.num_rows(), 1);
+        assert_eq!(batch.num_columns(), 5);
+        assert_eq!(batch.schema().field(4).name(), "table_schema");
+
+        let command = Command::GetDbSchemas(CommandGetDbSchemas::default());
+        let batch = db_schemas_batch(&command, &["a".to_string(), "b".to_string()]).unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.schema().field(1).name(), "db_schema_name");
+    }
+}
@@ -0,0 +1,246 @@
+//! This module contains the standard gRPC health checking service,
+//! `grpc.health.v1.Health`, which load balancers and Kubernetes gRPC
+//! probes use to check the server without a token.
+//!
+//! The health of each service follows the state of the server: the
+//! Storage and Flight services, which answer queries, are
+//! `NOT_SERVING` while its access mode rejects queries, and the IOx
+//! service is `NOT_SERVING` while it is in maintenance. The server as a
+//! whole, checked with an empty service name, is `SERVING` unless it
+//! is in maintenance.
+//!
+//! `Watch` sends the health of the service, and then sends it again
+//! whenever a change of the access mode changes it. Nothing outlives
+//! the response stream, so a watch ends as soon as its client goes
+//! away, whether or not the health ever changes.
+
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use generated_types::grpc::health::v1::{
+    health_check_response::ServingStatus, health_server::Health, HealthCheckRequest,
+    HealthCheckResponse,
+};
+use tokio::{stream::Stream, sync::watch};
+use tonic::{Request, Response, Status};
+
+use crate::server::access::{Access, AccessMode};
+
+/// The full name of the IOx gRPC service
+pub const IOX_SERVICE: &str = "influxdata.platform.storage.IOx";
+
+/// The full name of the InfluxDB storage gRPC service
+pub const STORAGE_SERVICE: &str = "influxdata.platform.storage.Storage";
+
+/// The full name of the Arrow Flight service
+pub const FLIGHT_SERVICE: &str = "arrow.flight.protocol.FlightService";
+
+/// The full name of the health checking service
+pub const HEALTH_SERVICE: &str = "grpc.health.v1.Health";
+
+/// The full name of the reflection service
+pub const REFLECTION_SERVICE: &str = "grpc.reflection.v1alpha.ServerReflection";
+
+/// The full names of every service the gRPC server exposes
+pub const SERVICES: &[&str] = &[
+    IOX_SERVICE,
+    STORAGE_SERVICE,
+    FLIGHT_SERVICE,
+    HEALTH_SERVICE,
+    REFLECTION_SERVICE,
+];
+
+#[derive(Debug)]
+pub struct HealthService {
+    access: Arc<Access>,
+}
+
+impl HealthService {
+    pub fn new(access: Arc<Access>) -> Self {
+        Self { access }
+    }
+
+    /// Returns the health of the service named `service` (or of the
+    /// server as a whole if it is empty), or `None` if there is no such
+    /// service
+    pub fn status(&self, service: &str) -> Option<ServingStatus> {
+        service_status(self.access.mode(), service)
+    }
+}
+
+fn service_status(mode: AccessMode, service: &str) -> Option<ServingStatus> {
+    let serving = match service {
+        STORAGE_SERVICE | FLIGHT_SERVICE => mode.accepts_queries(),
+        "" | IOX_SERVICE => mode != AccessMode::Maintenance,
+        HEALTH_SERVICE | REFLECTION_SERVICE => true,
+        _ => return None,
+    };
+
+    Some(if serving {
+        ServingStatus::Serving
+    } else {
+        ServingStatus::NotServing
+    })
+}
+
+fn response(status: ServingStatus) -> HealthCheckResponse {
+    HealthCheckResponse {
+        status: status as i32,
+    }
+}
+
+#[tonic::async_trait]
+impl Health for HealthService {
+    async fn check(
+        &self,
+        req: Request<HealthCheckRequest>,
+    ) -> Result<Response<HealthCheckResponse>, Status> {
+        let service = req.into_inner().service;
+        let status = self
+            .status(&service)
+            .ok_or_else(|| Status::not_found(format!("Unknown service '{}'", service)))?;
+
+        Ok(Response::new(response(status)))
+    }
+
+    type WatchStream = HealthWatch;
+
+    async fn watch(
+        &self,
+        req: Request<HealthCheckRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        Ok(Response::new(HealthWatch {
+            modes: self.access.watch(),
+            service: req.into_inner().service,
+            last: None,
+        }))
+    }
+}
+
+/// The responses of a `Watch` of the health of `service`: its health,
+/// and then its health each time a change of the access mode changes it
+#[derive(Debug)]
+pub struct HealthWatch {
+    modes: watch::Receiver<AccessMode>,
+    service: String,
+    /// The health last sent
+    last: Option<ServingStatus>,
+}
+
+impl HealthWatch {
+    fn status(&self, mode: AccessMode) -> ServingStatus {
+        service_status(mode, &self.service).unwrap_or(ServingStatus::ServiceUnknown)
+    }
+}
+
+impl Stream for HealthWatch {
+    type Item = Result<HealthCheckResponse, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if self.last.is_none() {
+            let status = self.status(*self.modes.borrow());
+            self.last = Some(status);
+            return Poll::Ready(Some(Ok(response(status))));
+        }
+
+        loop {
+            let mode = match Pin::new(&mut self.modes).poll_next(cx) {
+                Poll::Ready(Some(mode)) => mode,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            let status = self.status(mode);
+            if self.last != Some(status) {
+                self.last = Some(status);
+                return Poll::Ready(Some(Ok(response(status))));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn statuses_follow_access_mode() {
+        let status = |mode, service| service_status(mode, service).unwrap();
+
+        for &service in SERVICES.iter().chain(&[""]) {
+            assert_eq!(
+                status(AccessMode::ReadWrite, service),
+                ServingStatus::Serving
+            );
+        }
+
+        assert_eq!(
+            status(AccessMode::IngestOnly, STORAGE_SERVICE),
+            ServingStatus::NotServing
+        );
+        assert_eq!(
+            status(AccessMode::IngestOnly, FLIGHT_SERVICE),
+            ServingStatus::NotServing
+        );
+        assert_eq!(
+            status(AccessMode::IngestOnly, IOX_SERVICE),
+            ServingStatus::Serving
+        );
+        assert_eq!(
+            status(AccessMode::ReadOnly, STORAGE_SERVICE),
+            ServingStatus::Serving
+        );
+        assert_eq!(
+            status(AccessMode::Maintenance, ""),
+            ServingStatus::NotServing
+        );
+        assert_eq!(
+            status(AccessMode::Maintenance, HEALTH_SERVICE),
+            ServingStatus::Serving
+        );
+
+        assert_eq!(service_status(AccessMode::ReadWrite, "foo.Bar"), None);
+    }
+
+    #[tokio::test]
+    async fn watch_follows_access_mode() {
+        use tokio::{stream::StreamExt, time::timeout};
+
+        let access = Arc::new(Access::default());
+        let service = HealthService::new(Arc::clone(&access));
+        let watch = |service_name: &str| {
+            service.watch(Request::new(HealthCheckRequest {
+                service: service_name.to_string(),
+            }))
+        };
+        let wait = std::time::Duration::from_secs(5);
+        let status = |response: Option<Result<HealthCheckResponse, Status>>| {
+            response.unwrap().unwrap().status
+        };
+
+        let mut statuses = watch(STORAGE_SERVICE).await.unwrap().into_inner();
+        assert_eq!(status(statuses.next().await), ServingStatus::Serving as i32);
+
+        // changes of the mode which leave the health as it was are not
+        // sent
+        access.set(AccessMode::ReadOnly);
+        access.set(AccessMode::IngestOnly);
+        assert_eq!(
+            status(timeout(wait, statuses.next()).await.unwrap()),
+            ServingStatus::NotServing as i32
+        );
+        access.reset();
+        assert_eq!(
+            status(timeout(wait, statuses.next()).await.unwrap()),
+            ServingStatus::Serving as i32
+        );
+
+        let mut statuses = watch("foo.Bar").await.unwrap().into_inner();
+        assert_eq!(
+            status(statuses.next().await),
+            ServingStatus::ServiceUnknown as i32
+        );
+    }
+}
@@ -0,0 +1,369 @@
+//! This module contains the limits on the size of the results of the
+//! `read_filter` and `read_group` storage RPCs, so that a query
+//! matching far more series than expected can not tie up the server
+//! and the client sending them.
+//!
+//! The number of series and the total encoded size of the frames
+//! returned can be limited by the quota of each database, and further
+//! lowered by each request with the `iox-max-series` and
+//! `iox-max-frame-bytes` metadata. By default a request whose results
+//! exceed a limit fails with a `RESOURCE_EXHAUSTED` status. A request
+//! with the `iox-on-limit: truncate` metadata instead receives the
+//! series that fit within the limits, and the `iox-partial-result`
+//! trailer to tell it the results are incomplete.
+//!
+//! Results are only truncated between series (or groups), so every
+//! series returned has all of its points.
+
+use generated_types::{read_response::frame::Data, ReadResponse};
+use prost::Message;
+use snafu::{ResultExt, Snafu};
+use tonic::metadata::MetadataMap;
+
+/// The metadata of a request lowering the number of series returned
+pub const MAX_SERIES: &str = "iox-max-series";
+
+/// The metadata of a request lowering the total size of the frames
+/// returned, in bytes
+pub const MAX_FRAME_BYTES: &str = "iox-max-frame-bytes";
+
+/// The metadata of a request choosing what happens when a limit is
+/// hit: `fail` (the default) or `truncate`
+pub const ON_LIMIT: &str = "iox-on-limit";
+
+/// The trailer returned, with the value `true`, when the results were
+/// truncated to fit within the limits
+pub const PARTIAL_RESULT: &str = "iox-partial-result";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid {} metadata '{}': {}", name, value, source))]
+    InvalidLimit {
+        name: &'static str,
+        value: String,
+        source: std::num::ParseIntError,
+    },
+
+    #[snafu(display("Invalid {} metadata '{}': expected fail or truncate", ON_LIMIT, value))]
+    InvalidOnLimit { value: String },
+
+    #[snafu(display("Query results exceeded the limit of {} series", limit))]
+    SeriesLimitExceeded { limit: usize },
+
+    #[snafu(display("Query results exceeded the limit of {} bytes of frames", limit))]
+    FrameBytesLimitExceeded { limit: usize },
+}
+
+impl Error {
+    /// Converts the error into the appropriate tonic status
+    pub fn to_status(&self) -> tonic::Status {
+        match &self {
+            Self::InvalidLimit { .. } | Self::InvalidOnLimit { .. } => {
+                tonic::Status::invalid_argument(self.to_string())
+            }
+            Self::SeriesLimitExceeded { .. } | Self::FrameBytesLimitExceeded { .. } => {
+                tonic::Status::resource_exhausted(self.to_string())
+            }
+        }
+    }
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The limits on the results of a single request. Results without a
+/// limit are unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReadLimits {
+    pub max_series: Option<usize>,
+    pub max_frame_bytes: Option<usize>,
+    /// Whether results exceeding a limit are truncated, rather than
+    /// failing the request
+    pub truncate: bool,
+}
+
+impl ReadLimits {
+    /// Applies the limits requested in `metadata`, which can lower but
+    /// not raise the limits of the database
+    pub fn with_request(self, metadata: &MetadataMap) -> Result<Self> {
+        let truncate = match metadata_str(metadata, ON_LIMIT) {
+            None | Some("fail") => false,
+            Some("truncate") => true,
+            Some(value) => return InvalidOnLimit { value }.fail(),
+        };
+
+        Ok(Self {
+            max_series: lowest(self.max_series, metadata_limit(metadata, MAX_SERIES)?),
+            max_frame_bytes: lowest(
+                self.max_frame_bytes,
+                metadata_limit(metadata, MAX_FRAME_BYTES)?,
+            ),
+            truncate,
+        })
+    }
+
+    /// Returns a limiter of the responses of a request to these limits
+    pub fn limiter(self) -> ResultLimiter {
+        ResultLimiter {
+            limits: self,
+            series: 0,
+            frame_bytes: 0,
+            truncated: false,
+        }
+    }
+}
+
+fn metadata_str<'a>(metadata: &'a MetadataMap, name: &str) -> Option<&'a str> {
+    // values that are not valid strings are treated as invalid limits
+    metadata
+        .get(name)
+        .map(|value| value.to_str().unwrap_or("<invalid>"))
+}
+
+fn metadata_limit(metadata: &MetadataMap, name: &'static str) -> Result<Option<usize>> {
+    metadata_str(metadata, name)
+        .map(|value| value.parse().context(InvalidLimit { name, value }))
+        .transpose()
+}
+
+fn lowest(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+/// Keeps track of the results sent for a request, to stop them at its
+/// limits
+#[derive(Debug)]
+pub struct ResultLimiter {
+    limits: ReadLimits,
+    series: usize,
+    frame_bytes: usize,
+    truncated: bool,
+}
+
+impl ResultLimiter {
+    /// Returns the frames of `response` that fit within the limits,
+    /// with what was sent before.
+    ///
+    /// If the frames don't all fit and the limits truncate results,
+    /// the series that fit are returned and the results are marked as
+    /// truncated, after which no more frames are returned. Otherwise
+    /// an error is returned. `None` is returned if no frames fit.
+    pub fn admit(&mut self, response: ReadResponse) -> Result<Option<ReadResponse>> {
+        if self.truncated {
+            return Ok(None);
+        }
+
+        let mut admitted = 0;
+        let mut series = self.series;
+        let mut frame_bytes = self.frame_bytes;
+        let mut exceeded = None;
+        for (index, frame) in response.frames.iter().enumerate() {
+            // the frames of a series (or group) are only admitted once
+            // all of them are known to fit
+            let starts_series = matches!(frame.data, Some(Data::Series(_)));
+            if starts_series || matches!(frame.data, Some(Data::Group(_))) {
+                self.series = series;
+                self.frame_bytes = frame_bytes;
+                admitted = index;
+            }
+
+            if starts_series {
+                series += 1;
+            }
+            frame_bytes += frame.encoded_len();
+
+            exceeded = match (self.limits.max_series, self.limits.max_frame_bytes) {
+                (Some(limit), _) if series > limit => Some(Error::SeriesLimitExceeded { limit }),
+                (_, Some(limit)) if frame_bytes > limit => {
+                    Some(Error::FrameBytesLimitExceeded { limit })
+                }
+                _ => None,
+            };
+            if exceeded.is_some() {
+                break;
+            }
+        }
+
+        match exceeded {
+            None => {
+                self.series = series;
+                self.frame_bytes = frame_bytes;
+                Ok(Some(response))
+            }
+            Some(exceeded) => self.limit_reached(response, admitted, exceeded),
+        }
+    }
+
+    /// Whether the results were truncated to fit within the limits
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Returns the first `admitted` frames of `response` if results are
+    /// truncated, otherwise the error `exceeded`
+    fn limit_reached(
+        &mut self,
+        mut response: ReadResponse,
+        admitted: usize,
+        exceeded: Error,
+    ) -> Result<Option<ReadResponse>> {
+        if !self.limits.truncate {
+            return Err(exceeded);
+        }
+
+        self.truncated = true;
+        response.frames.truncate(admitted);
+        Ok(Some(response).filter(|response| !response.frames.is_empty()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use generated_types::read_response::{FloatPointsFrame, Frame, SeriesFrame};
+    use tonic::metadata::MetadataValue;
+
+    fn series_frame() -> Frame {
+        Frame {
+            data: Some(Data::Series(SeriesFrame::default())),
+        }
+    }
+
+    fn points_frame(points: usize) -> Frame {
+        Frame {
+            data: Some(Data::FloatPoints(FloatPointsFrame {
+                timestamps: vec![1; points],
+                values: vec![1.0; points],
+            })),
+        }
+    }
+
+    /// A response of `series` series, each with one frame of points
+    fn response(series: usize) -> ReadResponse {
+        ReadResponse {
+            frames: (0..series)
+                .flat_map(|_| vec![series_frame(), points_frame(10)])
+                .collect(),
+        }
+    }
+
+    fn frame_count(response: Option<ReadResponse>) -> usize {
+        response.map(|response| response.frames.len()).unwrap_or(0)
+    }
+
+    #[test]
+    fn request_limits() {
+        let database = ReadLimits {
+            max_series: Some(100),
+            max_frame_bytes: None,
+            truncate: false,
+        };
+        assert_eq!(
+            database.with_request(&MetadataMap::new()).unwrap(),
+            database
+        );
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert(MAX_SERIES, MetadataValue::from_static("1000"));
+        metadata.insert(MAX_FRAME_BYTES, MetadataValue::from_static("4096"));
+        metadata.insert(ON_LIMIT, MetadataValue::from_static("truncate"));
+        assert_eq!(
+            database.with_request(&metadata).unwrap(),
+            ReadLimits {
+                max_series: Some(100),
+                max_frame_bytes: Some(4096),
+                truncate: true,
+            }
+        );
+
+        metadata.insert(MAX_SERIES, MetadataValue::from_static("ten"));
+        assert_eq!(
+            database.with_request(&metadata).unwrap_err().to_string(),
+            "Invalid iox-max-series metadata 'ten': invalid digit found in string"
+        );
+
+        let mut metadata = MetadataMap::new();
+        metadata.insert(ON_LIMIT, MetadataValue::from_static("ignore"));
+        let err = database.with_request(&metadata).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid iox-on-limit metadata 'ignore': expected fail or truncate"
+        );
+        assert_eq!(err.to_status().code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn unlimited_results() {
+        let mut limiter = ReadLimits::default().limiter();
+        assert_eq!(frame_count(limiter.admit(response(100)).unwrap()), 200);
+        assert!(!limiter.is_truncated());
+    }
+
+    #[test]
+    fn series_limit() {
+        let limits = ReadLimits {
+            max_series: Some(3),
+            ..Default::default()
+        };
+
+        let mut limiter = limits.limiter();
+        assert_eq!(frame_count(limiter.admit(response(2)).unwrap()), 4);
+        let err = limiter.admit(response(2)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Query results exceeded the limit of 3 series"
+        );
+        assert_eq!(err.to_status().code(), tonic::Code::ResourceExhausted);
+
+        let mut limiter = ReadLimits {
+            truncate: true,
+            ..limits
+        }
+        .limiter();
+        assert_eq!(frame_count(limiter.admit(response(2)).unwrap()), 4);
+        assert!(!limiter.is_truncated());
+        assert_eq!(frame_count(limiter.admit(response(2)).unwrap()), 2);
+        assert!(limiter.is_truncated());
+        assert!(limiter.admit(response(1)).unwrap().is_none());
+    }
+
+    #[test]
+    fn frame_bytes_limit() {
+        let series_bytes = response(1)
+            .frames
+            .iter()
+            .map(|frame| frame.encoded_len())
+            .sum::<usize>();
+        let limits = ReadLimits {
+            max_frame_bytes: Some(series_bytes * 2 + 1),
+            truncate: true,
+            ..Default::default()
+        };
+
+        // the points of a series are not split from it
+        let mut limiter = limits.limiter();
+        assert_eq!(frame_count(limiter.admit(response(3)).unwrap()), 4);
+        assert!(limiter.is_truncated());
+
+        let mut limiter = limits.limiter();
+        assert_eq!(frame_count(limiter.admit(response(2)).unwrap()), 4);
+        assert!(limiter.admit(response(1)).unwrap().is_none());
+        assert!(limiter.is_truncated());
+
+        let mut limiter = ReadLimits {
+            truncate: false,
+            ..limits
+        }
+        .limiter();
+        assert_eq!(
+            limiter.admit(response(3)).unwrap_err().to_string(),
+            format!(
+                "Query results exceeded the limit of {} bytes of frames",
+                series_bytes * 2 + 1
+            )
+        );
+    }
+}
@@ -0,0 +1,317 @@
+//! This module contains the gRPC reflection service,
+//! `grpc.reflection.v1alpha.ServerReflection`, which describes the
+//! services the server exposes to tools such as `grpcurl`, so they can
+//! be called without their `.proto` files.
+//!
+//! The descriptions are the `FileDescriptorSet` compiled into
+//! `generated_types`. A file is returned with every file it imports,
+//! directly or not, so clients need no further requests to decode it.
+//! Extensions are not used by any of the services, so requests for
+//! them are answered with errors.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+};
+
+use generated_types::{
+    grpc::reflection::v1alpha::{
+        server_reflection_request::MessageRequest, server_reflection_response::MessageResponse,
+        server_reflection_server::ServerReflection, ErrorResponse, FileDescriptorResponse,
+        ListServiceResponse, ServerReflectionRequest, ServerReflectionResponse, ServiceResponse,
+    },
+    FILE_DESCRIPTOR_SET,
+};
+use prost::Message;
+use prost_types::{DescriptorProto, FileDescriptorProto, FileDescriptorSet};
+use tokio::sync::mpsc;
+use tonic::{Code, Request, Response, Status, Streaming};
+use tracing::warn;
+
+use super::health::SERVICES;
+
+/// The files describing the services, indexed by name and by the
+/// symbols they define
+#[derive(Debug, Default)]
+struct Descriptors {
+    files: BTreeMap<String, FileDescriptorProto>,
+    /// The file defining each fully qualified message, enum, service and
+    /// method
+    symbols: BTreeMap<String, String>,
+}
+
+impl Descriptors {
+    fn new(set: FileDescriptorSet) -> Self {
+        let mut descriptors = Self::default();
+
+        for file in set.file {
+            let file_name = file.name().to_string();
+            let package = file.package();
+
+            for message in &file.message_type {
+                descriptors.add_message(package, message, &file_name);
+            }
+            for enum_type in &file.enum_type {
+                descriptors.add_symbol(package, enum_type.name(), &file_name);
+            }
+            for service in &file.service {
+                let service_name = descriptors.add_symbol(package, service.name(), &file_name);
+                for method in &service.method {
+                    descriptors.add_symbol(&service_name, method.name(), &file_name);
+                }
+            }
+
+            descriptors.files.insert(file_name, file);
+        }
+
+        descriptors
+    }
+
+    /// Adds `message` and the messages and enums nested in it
+    fn add_message(&mut self, scope: &str, message: &DescriptorProto, file_name: &str) {
+        let message_name = self.add_symbol(scope, message.name(), file_name);
+        for nested in &message.nested_type {
+            self.add_message(&message_name, nested, file_name);
+        }
+        for enum_type in &message.enum_type {
+            self.add_symbol(&message_name, enum_type.name(), file_name);
+        }
+    }
+
+    /// Adds the symbol `name` in `scope`, returning its full name
+    fn add_symbol(&mut self, scope: &str, name: &str, file_name: &str) -> String {
+        let full_name = if scope.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", scope, name)
+        };
+        self.symbols
+            .insert(full_name.clone(), file_name.to_string());
+        full_name
+    }
+
+    /// Returns the encoded file `file_name` followed by the files it
+    /// imports, or `None` if there is no such file
+    fn file_with_imports(&self, file_name: &str) -> Option<Vec<Vec<u8>>> {
+        self.files.get(file_name)?;
+
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![file_name];
+        let mut encoded = vec![];
+        while let Some(name) = pending.pop() {
+            if !seen.insert(name) {
+                continue;
+            }
+            // the set includes every import, but skip any that is missing
+            // rather than failing the request
+            if let Some(file) = self.files.get(name) {
+                let mut buf = Vec::with_capacity(file.encoded_len());
+                file.encode(&mut buf).expect("buffer has capacity");
+                encoded.push(buf);
+                pending.extend(file.dependency.iter().map(String::as_str));
+            }
+        }
+
+        Some(encoded)
+    }
+
+    /// Answers a single reflection request
+    fn respond(&self, request: &MessageRequest) -> MessageResponse {
+        let files = match request {
+            MessageRequest::FileByFilename(file_name) => self
+                .file_with_imports(file_name)
+                .ok_or_else(|| (Code::NotFound, format!("Unknown file '{}'", file_name))),
+            MessageRequest::FileContainingSymbol(symbol) => self
+                .symbols
+                .get(symbol.trim_start_matches('.'))
+                .and_then(|file_name| self.file_with_imports(file_name))
+                .ok_or_else(|| (Code::NotFound, format!("Unknown symbol '{}'", symbol))),
+            MessageRequest::FileContainingExtension(_)
+            | MessageRequest::AllExtensionNumbersOfType(_) => Err((
+                Code::Unimplemented,
+                "Extensions are not supported".to_string(),
+            )),
+            MessageRequest::ListServices(_) => {
+                return MessageResponse::ListServicesResponse(ListServiceResponse {
+                    service: SERVICES
+                        .iter()
+                        .map(|name| ServiceResponse {
+                            name: name.to_string(),
+                        })
+                        .collect(),
+                })
+            }
+        };
+
+        match files {
+            Ok(file_descriptor_proto) => {
+                MessageResponse::FileDescriptorResponse(FileDescriptorResponse {
+                    file_descriptor_proto,
+                })
+            }
+            Err((code, error_message)) => MessageResponse::ErrorResponse(ErrorResponse {
+                error_code: code as i32,
+                error_message,
+            }),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ReflectionService {
+    descriptors: Arc<Descriptors>,
+}
+
+impl ReflectionService {
+    pub fn new() -> Self {
+        let set = FileDescriptorSet::decode(FILE_DESCRIPTOR_SET)
+            .expect("file descriptor set generated by the build is valid");
+
+        Self {
+            descriptors: Arc::new(Descriptors::new(set)),
+        }
+    }
+}
+
+impl Default for ReflectionService {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[tonic::async_trait]
+impl ServerReflection for ReflectionService {
+    type ServerReflectionInfoStream = mpsc::Receiver<Result<ServerReflectionResponse, Status>>;
+
+    async fn server_reflection_info(
+        &self,
+        req: Request<Streaming<ServerReflectionRequest>>,
+    ) -> Result<Response<Self::ServerReflectionInfoStream>, Status> {
+        let mut requests = req.into_inner();
+        let descriptors = Arc::clone(&self.descriptors);
+        let (mut tx, rx) = mpsc::channel(4);
+
+        tokio::spawn(async move {
+            loop {
+                let request = match requests.message().await {
+                    Ok(Some(request)) => request,
+                    Ok(None) => return,
+                    Err(e) => {
+                        warn!("Error receiving reflection request: {}", e);
+                        return;
+                    }
+                };
+
+                let response = match &request.message_request {
+                    Some(message_request) => Ok(ServerReflectionResponse {
+                        valid_host: request.host.clone(),
+                        message_response: Some(descriptors.respond(message_request)),
+                        original_request: Some(request),
+                    }),
+                    None => Err(Status::invalid_argument("Empty reflection request")),
+                };
+
+                if tx.send(response).await.is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Response::new(rx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn files(response: MessageResponse) -> Vec<FileDescriptorProto> {
+        match response {
+            MessageResponse::FileDescriptorResponse(response) => response
+                .file_descriptor_proto
+                .iter()
+                .map(|buf| FileDescriptorProto::decode(buf.as_slice()).unwrap())
+                .collect(),
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    fn error_code(response: MessageResponse) -> i32 {
+        match response {
+            MessageResponse::ErrorResponse(response) => response.error_code,
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn lists_services() {
+        let service = ReflectionService::new();
+        let response = service
+            .descriptors
+            .respond(&MessageRequest::ListServices(String::new()));
+
+        match response {
+            MessageResponse::ListServicesResponse(response) => {
+                let names: Vec<_> = response.service.into_iter().map(|s| s.name).collect();
+                assert_eq!(names, SERVICES);
+            }
+            other => panic!("unexpected response: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn every_service_is_described() {
+        let descriptors = ReflectionService::new().descriptors;
+
+        for &name in SERVICES {
+            let files =
+                files(descriptors.respond(&MessageRequest::FileContainingSymbol(name.to_string())));
+            assert!(
+                files[0]
+                    .service
+                    .iter()
+                    .any(|service| name.ends_with(service.name())),
+                "{} not described",
+                name
+            );
+
+            // imports are included
+            let included: Vec<_> = files.iter().map(|file| file.name()).collect();
+            for dependency in &files[0].dependency {
+                assert!(included.contains(&dependency.as_str()));
+            }
+        }
+    }
+
+    #[test]
+    fn symbols_and_files() {
+        let descriptors = ReflectionService::new().descriptors;
+
+        let by_method = files(descriptors.respond(&MessageRequest::FileContainingSymbol(
+            "grpc.health.v1.Health.Check".to_string(),
+        )));
+        let by_enum = files(descriptors.respond(&MessageRequest::FileContainingSymbol(
+            ".grpc.health.v1.HealthCheckResponse.ServingStatus".to_string(),
+        )));
+        let by_name = files(descriptors.respond(&MessageRequest::FileByFilename(
+            by_method[0].name().to_string(),
+        )));
+        assert_eq!(by_method, by_enum);
+        assert_eq!(by_method, by_name);
+
+        assert_eq!(
+            error_code(
+                descriptors.respond(&MessageRequest::FileContainingSymbol("foo.Bar".to_string()))
+            ),
+            Code::NotFound as i32
+        );
+        assert_eq!(
+            error_code(
+                descriptors.respond(&MessageRequest::AllExtensionNumbersOfType(
+                    "grpc.health.v1.HealthCheckRequest".to_string()
+                ))
+            ),
+            Code::Unimplemented as i32
+        );
+    }
+}
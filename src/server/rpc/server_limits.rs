@@ -0,0 +1,419 @@
+//! This module contains the limits on the requests and connections the
+//! gRPC server accepts, so that clients can not make it buffer without
+//! bound:
+//!
+//! * The size of each message a request sends. A message declaring a
+//!   larger size fails the request with a `RESOURCE_EXHAUSTED` status
+//!   before any of it is buffered.
+//! * The number of requests (HTTP/2 streams) open at once on each
+//!   connection, which is advertised to clients in the HTTP/2 settings.
+//!   Streams opened beyond it are refused.
+//! * The number of connections open at once from each client address.
+//!   Connections beyond it are closed as soon as they are accepted.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+use bytes::{Buf, Bytes};
+use futures::{Stream, StreamExt};
+use hyper::{body::HttpBody, Body, HeaderMap};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tonic::{
+    codegen::Service,
+    transport::{server::Connected, NamedService},
+    Status,
+};
+use tracing::warn;
+
+/// The largest message a request may send by default, in bytes
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 16 * 1024 * 1024;
+
+/// The most requests each connection may have open at once by default
+pub const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/// The length of the prefix of each gRPC message: a compression flag
+/// followed by the length of the message as a big endian `u32`
+const MESSAGE_PREFIX_BYTES: usize = 5;
+
+/// The limits of the gRPC server. Limits that are `None` are unlimited.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ServerLimits {
+    pub max_message_bytes: Option<usize>,
+    pub max_concurrent_streams: Option<u32>,
+    /// Connections from clients behind the same proxy or NAT share an
+    /// address, so this is unlimited by default
+    pub max_connections_per_peer: Option<usize>,
+}
+
+impl Default for ServerLimits {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: Some(DEFAULT_MAX_MESSAGE_BYTES),
+            max_concurrent_streams: Some(DEFAULT_MAX_CONCURRENT_STREAMS),
+            max_connections_per_peer: None,
+        }
+    }
+}
+
+/// Wraps a gRPC service so the requests it receives fail if they send a
+/// message larger than the limit
+#[derive(Debug, Clone)]
+pub struct MessageSizeLimit<S> {
+    inner: S,
+    max_message_bytes: Option<usize>,
+}
+
+impl<S> MessageSizeLimit<S> {
+    pub fn new(inner: S, max_message_bytes: Option<usize>) -> Self {
+        Self {
+            inner,
+            max_message_bytes,
+        }
+    }
+}
+
+impl<S: NamedService> NamedService for MessageSizeLimit<S> {
+    const NAME: &'static str = S::NAME;
+}
+
+impl<S> Service<http::Request<Body>> for MessageSizeLimit<S>
+where
+    S: Service<http::Request<LimitedBody>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: http::Request<Body>) -> Self::Future {
+        let max_message_bytes = self.max_message_bytes;
+        self.inner
+            .call(req.map(|body| LimitedBody::new(body, max_message_bytes)))
+    }
+}
+
+/// A request body that fails with a `RESOURCE_EXHAUSTED` status when the
+/// prefix of a message declares it larger than the limit. The data of
+/// the body is passed on as it arrives, only reading the prefixes.
+#[derive(Debug)]
+pub struct LimitedBody {
+    inner: Body,
+    max_message_bytes: Option<usize>,
+    /// The bytes of the prefix of the next message read so far
+    prefix: [u8; MESSAGE_PREFIX_BYTES],
+    prefix_len: usize,
+    /// The bytes of the current message yet to be read
+    remaining: usize,
+}
+
+impl LimitedBody {
+    fn new(inner: Body, max_message_bytes: Option<usize>) -> Self {
+        Self {
+            inner,
+            max_message_bytes,
+            prefix: [0; MESSAGE_PREFIX_BYTES],
+            prefix_len: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Reads the prefixes of the messages in `data`, returning an error
+    /// for the first message larger than the limit
+    fn check(&mut self, mut data: &[u8], max_message_bytes: usize) -> Result<(), Status> {
+        while !data.is_empty() {
+            if self.remaining > 0 {
+                let skipped = self.remaining.min(data.len());
+                self.remaining -= skipped;
+                data = &data[skipped..];
+                continue;
+            }
+
+            let read = (MESSAGE_PREFIX_BYTES - self.prefix_len).min(data.len());
+            self.prefix[self.prefix_len..self.prefix_len + read].copy_from_slice(&data[..read]);
+            self.prefix_len += read;
+            data = &data[read..];
+
+            if self.prefix_len == MESSAGE_PREFIX_BYTES {
+                let mut len = &self.prefix[1..];
+                let message_bytes = len.get_u32() as usize;
+                if message_bytes > max_message_bytes {
+                    return Err(Status::resource_exhausted(format!(
+                        "Request message of {} bytes exceeds the limit of {} bytes",
+                        message_bytes, max_message_bytes
+                    )));
+                }
+                self.prefix_len = 0;
+                self.remaining = message_bytes;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl HttpBody for LimitedBody {
+    type Data = Bytes;
+    type Error = Status;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = match futures::ready!(Pin::new(&mut self.inner).poll_data(cx)) {
+            Some(Ok(data)) => data,
+            Some(Err(e)) => return Poll::Ready(Some(Err(Status::from_error(&e)))),
+            None => return Poll::Ready(None),
+        };
+
+        if let Some(max_message_bytes) = self.max_message_bytes {
+            if let Err(status) = self.check(&data, max_message_bytes) {
+                return Poll::Ready(Some(Err(status)));
+            }
+        }
+
+        Poll::Ready(Some(Ok(data)))
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        Pin::new(&mut self.inner)
+            .poll_trailers(cx)
+            .map_err(|e| Status::from_error(&e))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+}
+
+/// The number of connections open from each client address
+#[derive(Debug, Default)]
+pub struct PeerConnections {
+    max_per_peer: Option<usize>,
+    open: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl PeerConnections {
+    pub fn new(max_per_peer: Option<usize>) -> Self {
+        Self {
+            max_per_peer,
+            open: Default::default(),
+        }
+    }
+
+    /// Records a connection from `peer`, returning a guard which
+    /// records it closed when dropped, or `None` if the peer already
+    /// has as many connections open as it may
+    pub fn try_open(self: &Arc<Self>, peer: IpAddr) -> Option<PeerConnection> {
+        let mut open = self.open.lock().expect("mutex poisoned");
+        let count = open.entry(peer).or_default();
+        if self.max_per_peer.map_or(false, |max| *count >= max) {
+            return None;
+        }
+        *count += 1;
+
+        Some(PeerConnection {
+            connections: Arc::clone(self),
+            peer,
+        })
+    }
+
+    /// The number of connections open from `peer`
+    pub fn open(&self, peer: IpAddr) -> usize {
+        let open = self.open.lock().expect("mutex poisoned");
+        open.get(&peer).copied().unwrap_or_default()
+    }
+
+    fn close(&self, peer: IpAddr) {
+        let mut open = self.open.lock().expect("mutex poisoned");
+        if let Some(count) = open.get_mut(&peer) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(&peer);
+            }
+        }
+    }
+}
+
+/// An open connection counted by `PeerConnections`
+#[derive(Debug)]
+pub struct PeerConnection {
+    connections: Arc<PeerConnections>,
+    peer: IpAddr,
+}
+
+impl Drop for PeerConnection {
+    fn drop(&mut self) {
+        self.connections.close(self.peer)
+    }
+}
+
+/// A connection counted against the limit of its client address, for
+/// as long as it is open
+#[derive(Debug)]
+pub struct LimitedConnection<IO> {
+    io: IO,
+    _connection: Option<PeerConnection>,
+}
+
+impl<IO: Connected> Connected for LimitedConnection<IO> {
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.io.remote_addr()
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for LimitedConnection<IO> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_read(cx, buf)
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for LimitedConnection<IO> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+/// Counts the connections of `incoming` by client address, closing
+/// those from clients that already have as many open as they may
+pub fn limit_connections<I, IO>(
+    incoming: I,
+    connections: Arc<PeerConnections>,
+) -> impl Stream<Item = io::Result<LimitedConnection<IO>>>
+where
+    I: Stream<Item = io::Result<IO>>,
+    IO: Connected,
+{
+    incoming.filter_map(move |io| {
+        let connections = Arc::clone(&connections);
+        async move {
+            let io = match io {
+                Ok(io) => io,
+                Err(e) => return Some(Err(e)),
+            };
+
+            let connection = match io.remote_addr() {
+                Some(addr) => match connections.try_open(addr.ip()) {
+                    Some(connection) => Some(connection),
+                    None => {
+                        warn!(
+                            "Closing gRPC connection from {}: it has {} connections open",
+                            addr,
+                            connections.open(addr.ip())
+                        );
+                        return None;
+                    }
+                },
+                None => None,
+            };
+
+            Some(Ok(LimitedConnection {
+                io,
+                _connection: connection,
+            }))
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::BufMut;
+
+    fn message(len: usize) -> Vec<u8> {
+        let mut message = vec![0];
+        message.put_u32(len as u32);
+        message.resize(MESSAGE_PREFIX_BYTES + len, 7);
+        message
+    }
+
+    async fn read_body(chunks: Vec<Vec<u8>>, max_message_bytes: usize) -> Result<usize, Status> {
+        let chunks = futures::stream::iter(chunks.into_iter().map(Ok::<_, io::Error>));
+        let mut body = LimitedBody::new(Body::wrap_stream(chunks), Some(max_message_bytes));
+
+        let mut read = 0;
+        while let Some(data) = body.data().await {
+            read += data?.len();
+        }
+        Ok(read)
+    }
+
+    #[tokio::test]
+    async fn message_size_limit() {
+        // messages within the limit are passed on, with prefixes split
+        // across chunks
+        let mut data = message(10);
+        data.extend(message(3));
+        let chunks = vec![
+            data[..2].to_vec(),
+            data[2..12].to_vec(),
+            data[12..].to_vec(),
+        ];
+        assert_eq!(read_body(chunks, 10).await.unwrap(), 23);
+
+        // a larger message fails once its prefix is read
+        let mut data = message(10);
+        data.extend(message(11));
+        let chunks = vec![data[..17].to_vec(), data[17..].to_vec()];
+        let status = read_body(chunks, 10).await.unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(
+            status.message(),
+            "Request message of 11 bytes exceeds the limit of 10 bytes"
+        );
+    }
+
+    #[test]
+    fn peer_connections() {
+        let connections = Arc::new(PeerConnections::new(Some(2)));
+        let peer: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let first = connections.try_open(peer).unwrap();
+        let second = connections.try_open(peer).unwrap();
+        assert!(connections.try_open(peer).is_none());
+        assert_eq!(connections.open(peer), 2);
+
+        // the limit is per address
+        let _other = connections.try_open(other).unwrap();
+
+        // closing a connection allows another
+        drop(first);
+        assert_eq!(connections.open(peer), 1);
+        let _third = connections.try_open(peer).unwrap();
+
+        drop(second);
+        assert_eq!(connections.open(peer), 1);
+
+        let unlimited = Arc::new(PeerConnections::new(None));
+        let open: Vec<_> = (0..10).map(|_| unlimited.try_open(peer).unwrap()).collect();
+        assert_eq!(unlimited.open(peer), open.len());
+    }
+}
@@ -2,48 +2,100 @@
 //! implemented in terms of the `storage::Database` and
 //! `storage::DatabaseStore`
 
-use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    convert::TryFrom,
+    net::SocketAddr,
+    str::FromStr,
+    sync::Arc,
+};
 
+use arrow_deps::arrow_flight::flight_service_server::FlightServiceServer;
 use generated_types::{
+    aggregate::AggregateType,
+    fill::FillType,
+    grpc::{
+        health::v1::health_server::HealthServer,
+        reflection::v1alpha::server_reflection_server::ServerReflectionServer,
+    },
     i_ox_server::{IOx, IOxServer},
     storage_server::{Storage, StorageServer},
-    CapabilitiesResponse, CreateBucketRequest, CreateBucketResponse, DeleteBucketRequest,
-    DeleteBucketResponse, GetBucketsResponse, Int64ValuesResponse, MeasurementFieldsRequest,
-    MeasurementFieldsResponse, MeasurementNamesRequest, MeasurementTagKeysRequest,
-    MeasurementTagValuesRequest, Organization, Predicate, ReadFilterRequest, ReadGroupRequest,
-    ReadResponse, ReadSeriesCardinalityRequest, ReadWindowAggregateRequest, StringValuesResponse,
-    TagKeysRequest, TagValuesRequest, TestErrorRequest, TestErrorResponse, TimestampRange,
+    Aggregate as RpcAggregate, Bucket, CapabilitiesResponse, CreateBucketRequest,
+    CreateBucketResponse, CreateDatabaseRequest, CreateDatabaseResponse, DatabaseConfig,
+    DeleteBucketRequest, DeleteBucketResponse, DeleteDatabaseRequest, DeleteDatabaseResponse,
+    Duration as RpcDuration, Fill as RpcFill, GetBucketsResponse, GetDatabaseRequest,
+    GetDatabaseResponse, Int64ValuesResponse, ListDatabasesRequest, ListDatabasesResponse,
+    MeasurementFieldsRequest, MeasurementFieldsResponse, MeasurementNamesRequest,
+    MeasurementTagKeysRequest, MeasurementTagValuesRequest, Organization, Predicate,
+    ReadFilterRequest, ReadGapsRequest, ReadGapsResponse, ReadGroupRequest, ReadResponse,
+    ReadSeriesCardinalityRequest, ReadWindowAggregateRequest, StringValuesResponse,
+    SubscribeRequest, SubscribeResponse, SubscriptionFormat, TagKeysRequest, TagValuesRequest,
+    TestErrorRequest, TestErrorResponse, TimestampRange, UpdateDatabaseRequest,
+    UpdateDatabaseResponse, Window as RpcWindow,
 };
 
-use data_types::error::ErrorLogger;
+use data_types::{database_rules::DatabaseRules, error::ErrorLogger};
+use prost::Message;
 
 #[allow(unused_imports)]
 // For some reason rust thinks these imports are unused, but then
 // complains of unresolved imports if they are not imported.
 use generated_types::{node, Node};
 
+use crate::server::access::Access;
+use crate::server::auth::{self, Authorizer, GrpcAuthentication, Permission, Principal, RowFilter};
+use crate::server::metrics::{MeteredStream, Metrics, RpcRecorder};
+use crate::server::quota::Quotas;
+use crate::server::rpc::cache::{CacheKey, CachedResponses, QueryCache};
 use crate::server::rpc::expr::{AddRPCNode, SpecialTagKeys};
+use crate::server::rpc::flight::FlightService;
+use crate::server::rpc::health::HealthService;
 use crate::server::rpc::input::GrpcInputs;
+use crate::server::rpc::limits::{self, ReadLimits, ResultLimiter};
+use crate::server::rpc::reflection::ReflectionService;
+use crate::server::rpc::server_limits::{
+    limit_connections, MessageSizeLimit, PeerConnections, ServerLimits,
+};
+use crate::server::state::ServerState;
+use crate::server::subscriptions::{self, Subscriptions};
+use crate::server::telemetry;
+use crate::server::tls;
+use crate::server::warnings::{WarningCode, Warnings};
 
 use storage::{
     exec::{
+        gaps::{find_gaps, Error as GapsError},
         seriesset::{Error as SeriesSetError, GroupedSeriesSetItem, SeriesSet},
+        stats::QueryStats,
+        top_n::{Order, SelectedSeries, TopN},
+        window_aggregate::{
+            Aggregate, Error as WindowAggregateError, Fill, PreAggregatedSeries, Transform,
+            WindowAggregate,
+        },
         Executor as StorageExecutor,
     },
+    id::Id,
     org_and_bucket_to_database,
-    predicate::PredicateBuilder,
+    predicate::{PredicateBuilder, TimestampRange as StorageTimestampRange},
+    tenant,
+    window::{
+        location::Error as LocationError, Duration as WindowDuration, Location as WindowLocation,
+    },
     Database, DatabaseStore,
 };
 
-use snafu::{OptionExt, ResultExt, Snafu};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 
-use tokio::sync::mpsc;
-use tonic::Status;
-use tracing::{info, warn};
+use tokio::{net::TcpListener, sync::mpsc};
+use tonic::{
+    metadata::{MetadataMap, MetadataValue},
+    Code, Status,
+};
+use tracing::{debug, info, info_span, warn};
 
 use super::data::{
     fieldlist_to_measurement_fields_response, grouped_series_set_item_to_read_response,
-    series_set_to_read_response, tag_keys_to_byte_vecs,
+    series_gaps_to_rpc, tag_keys_to_byte_vecs, windowed_series_set_to_read_response, SeriesMerger,
 };
 
 #[derive(Debug, Snafu)]
@@ -51,6 +103,16 @@ pub enum Error {
     #[snafu(display("gRPC server error:  {}", source))]
     ServerError { source: tonic::transport::Error },
 
+    #[snafu(display(
+        "Unable to bind to listen for gRPC requests on {}: {}",
+        bind_addr,
+        source
+    ))]
+    StartListening {
+        bind_addr: SocketAddr,
+        source: std::io::Error,
+    },
+
     #[snafu(display("Database not found: {}", db_name))]
     DatabaseNotFound { db_name: String },
 
@@ -120,6 +182,21 @@ pub enum Error {
     #[snafu(display("Computing groups series: {}", source))]
     ComputingGroupedSeriesSet { source: SeriesSetError },
 
+    #[snafu(display("Invalid window aggregate request: {}", description))]
+    InvalidWindowAggregate { description: String },
+
+    #[snafu(display("Computing window aggregate: {}", source))]
+    ComputingWindowAggregate { source: WindowAggregateError },
+
+    #[snafu(display("Invalid window location: {}", source))]
+    InvalidWindowLocation { source: LocationError },
+
+    #[snafu(display("Invalid gap threshold {}: must not be negative", threshold))]
+    InvalidGapThreshold { threshold: i64 },
+
+    #[snafu(display("Computing gaps: {}", source))]
+    ComputingGaps { source: GapsError },
+
     #[snafu(display("Converting time series into gRPC response:  {}", source))]
     ConvertingSeriesSet {
         source: crate::server::rpc::data::Error,
@@ -137,6 +214,9 @@ pub enum Error {
 
     #[snafu(display("Operation not yet implemented:  {}", operation))]
     NotYetImplemented { operation: String },
+
+    #[snafu(display("{}", source))]
+    Authorization { source: auth::Error },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -146,6 +226,7 @@ impl Error {
     fn to_status(&self) -> tonic::Status {
         match &self {
             Self::ServerError { .. } => Status::internal(self.to_string()),
+            Self::StartListening { .. } => Status::internal(self.to_string()),
             Self::DatabaseNotFound { .. } => Status::not_found(self.to_string()),
             Self::ListingTables { .. } => Status::internal(self.to_string()),
             Self::ListingColumns { .. } => {
@@ -164,10 +245,16 @@ impl Error {
             Self::ConvertingPredicate { .. } => Status::invalid_argument(self.to_string()),
             Self::ComputingSeriesSet { .. } => Status::invalid_argument(self.to_string()),
             Self::ComputingGroupedSeriesSet { .. } => Status::invalid_argument(self.to_string()),
+            Self::InvalidWindowAggregate { .. } => Status::invalid_argument(self.to_string()),
+            Self::ComputingWindowAggregate { .. } => Status::invalid_argument(self.to_string()),
+            Self::InvalidWindowLocation { .. } => Status::invalid_argument(self.to_string()),
+            Self::InvalidGapThreshold { .. } => Status::invalid_argument(self.to_string()),
+            Self::ComputingGaps { .. } => Status::invalid_argument(self.to_string()),
             Self::ConvertingSeriesSet { .. } => Status::invalid_argument(self.to_string()),
             Self::ConvertingFieldList { .. } => Status::invalid_argument(self.to_string()),
             Self::SendingResults { .. } => Status::internal(self.to_string()),
             Self::NotYetImplemented { .. } => Status::internal(self.to_string()),
+            Self::Authorization { source } => source.to_status(),
         }
     }
 }
@@ -176,6 +263,19 @@ impl Error {
 pub struct GrpcService<T: DatabaseStore> {
     db_store: Arc<T>,
     executor: Arc<StorageExecutor>,
+    /// Cache of query responses, if enabled
+    cache: Option<Arc<QueryCache>>,
+    /// Authorizes the API token of each request
+    authorizer: Arc<Authorizer>,
+    /// Limits the queries of each database
+    quotas: Arc<Quotas>,
+    /// The subscriptions read with the `Subscribe` RPC
+    subscriptions: Arc<Subscriptions>,
+    /// Whether the server accepts queries
+    access: Arc<Access>,
+    /// Where the latency, response size and outcome of storage RPCs
+    /// are recorded
+    metrics: Arc<Metrics>,
 }
 
 impl<T> GrpcService<T>
@@ -184,7 +284,150 @@ where
 {
     /// Create a new GrpcService connected to `db_store`
     pub fn new(db_store: Arc<T>, executor: Arc<StorageExecutor>) -> Self {
-        Self { db_store, executor }
+        Self {
+            db_store,
+            executor,
+            cache: None,
+            authorizer: Arc::new(Authorizer::disabled()),
+            quotas: Arc::new(Quotas::new()),
+            subscriptions: Arc::new(Subscriptions::new()),
+            access: Arc::new(Access::default()),
+            metrics: Arc::new(Metrics::default()),
+        }
+    }
+
+    /// Cache the responses to read requests in `cache`
+    pub fn with_cache(self, cache: Option<Arc<QueryCache>>) -> Self {
+        Self { cache, ..self }
+    }
+
+    /// Authorize the API token of each request with `authorizer`
+    pub fn with_authorizer(self, authorizer: Arc<Authorizer>) -> Self {
+        Self { authorizer, ..self }
+    }
+
+    /// Limit the queries of each database to its quota in `quotas`
+    pub fn with_quotas(self, quotas: Arc<Quotas>) -> Self {
+        Self { quotas, ..self }
+    }
+
+    /// Stream the messages of the subscriptions in `subscriptions`
+    pub fn with_subscriptions(self, subscriptions: Arc<Subscriptions>) -> Self {
+        Self {
+            subscriptions,
+            ..self
+        }
+    }
+
+    /// Reject queries while the access mode of `access` does not
+    /// accept them
+    pub fn with_access(self, access: Arc<Access>) -> Self {
+        Self { access, ..self }
+    }
+
+    /// Record the storage RPCs in `metrics`
+    pub fn with_metrics(self, metrics: Arc<Metrics>) -> Self {
+        Self { metrics, ..self }
+    }
+
+    /// Starts recording the storage RPC `rpc` of `req`, labeled with
+    /// the database it names (or none, if it names no valid database)
+    fn storage_rpc<R: GrpcInputs>(
+        &self,
+        rpc: &'static str,
+        req: &tonic::Request<R>,
+    ) -> RpcRecorder {
+        let db_name = get_database_name(req.get_ref()).unwrap_or_default();
+        self.metrics.storage_rpc(rpc, db_name)
+    }
+
+    /// Returns the executor for queries of the database `db_name`,
+    /// limited to the query memory of its quota
+    fn executor_for(&self, db_name: &str) -> Arc<StorageExecutor> {
+        match self.quotas.max_query_memory(db_name) {
+            Some(limit) => Arc::new(self.executor.limited_to(limit)),
+            None => Arc::clone(&self.executor),
+        }
+    }
+
+    /// Returns where the responses to `request`, a request to `rpc` for
+    /// the rows of the database `db_name` within `row_filter`, are
+    /// cached, if responses are cached
+    fn cache_entry(
+        &self,
+        db_name: &str,
+        rpc: &'static str,
+        request: &impl Message,
+        row_filter: Option<&RowFilter>,
+    ) -> Option<(Arc<QueryCache>, CacheKey)> {
+        let cache = self.cache.clone()?;
+        Some((
+            cache,
+            CacheKey::for_request(db_name, rpc, request, row_filter),
+        ))
+    }
+
+    /// Returns the limits on the results of a read request of the
+    /// database `db_name`: those of its quota, lowered by any limits
+    /// in the metadata of `req`
+    fn read_limits_for<R>(
+        &self,
+        req: &tonic::Request<R>,
+        db_name: &str,
+    ) -> Result<ReadLimits, Status> {
+        let (max_series, max_frame_bytes) = self.quotas.max_read_results(db_name);
+        ReadLimits {
+            max_series,
+            max_frame_bytes,
+            truncate: false,
+        }
+        .with_request(req.metadata())
+        .map_err(|e| e.to_status())
+    }
+
+    /// Returns the principal the request authenticates as with its
+    /// `authorization` metadata. Requests without a valid token are
+    /// rejected by `GrpcAuthentication` before they are decoded, but
+    /// tonic passes nothing it finds on to the service, so the token is
+    /// looked up again here.
+    fn authenticate<R>(&self, req: &tonic::Request<R>) -> Result<Principal, Status> {
+        let authorization = match req.metadata().get(auth::AUTHORIZATION) {
+            Some(value) => Some(value.to_str().map_err(|_| {
+                Status::unauthenticated("authorization metadata is not a valid string")
+            })?),
+            None => None,
+        };
+
+        self.authorizer
+            .authenticate(authorization)
+            .map_err(|e| e.to_status())
+    }
+
+    /// Returns the name of the database a storage request reads from,
+    /// and the filter of the rows the request is limited to, if the
+    /// request may perform `permission` on it. Reads are also rejected
+    /// if the server does not accept queries, and counted against the
+    /// query quota of the database.
+    fn authorize<R: GrpcInputs>(
+        &self,
+        req: &tonic::Request<R>,
+        permission: Permission,
+    ) -> Result<(String, Option<RowFilter>), Status> {
+        let principal = self.authenticate(req)?;
+        let db_name = get_database_name(req.get_ref())?;
+
+        let row_filter = principal
+            .row_filter(&db_name, permission)
+            .map_err(|e| e.to_status())?;
+
+        if permission == Permission::Read {
+            self.access.check_query().map_err(|e| e.to_status())?;
+            self.quotas
+                .check_query(&db_name)
+                .map_err(|e| e.to_status())?;
+        }
+
+        Ok((db_name, row_filter))
     }
 }
 
@@ -194,12 +437,31 @@ impl<T> IOx for GrpcService<T>
 where
     T: DatabaseStore + 'static,
 {
-    // TODO: Do we want to keep this gRPC request?
     async fn create_bucket(
         &self,
-        _req: tonic::Request<CreateBucketRequest>,
+        req: tonic::Request<CreateBucketRequest>,
     ) -> Result<tonic::Response<CreateBucketResponse>, Status> {
-        Err(Status::unimplemented("create_bucket"))
+        let principal = self.authenticate(&req)?;
+
+        let CreateBucketRequest { org_id, bucket } = req.into_inner();
+        let bucket = bucket.ok_or_else(|| Status::invalid_argument("missing bucket"))?;
+
+        let (org, bucket) = bucket_names(org_id, bucket.id)?;
+        let db_name = org_and_bucket_to_database(&org, &bucket);
+        principal
+            .authorize(&db_name, Permission::Write)
+            .map_err(|e| e.to_status())?;
+
+        info!("create_bucket for database {}", db_name);
+
+        tenant::bucket_db_or_create(self.db_store.as_ref(), &org, &bucket)
+            .await
+            .map_err(|e| match e {
+                tenant::Error::OtherTenant { .. } => Status::already_exists(e.to_string()),
+                _ => Status::internal(e.to_string()),
+            })?;
+
+        Ok(tonic::Response::new(CreateBucketResponse {}))
     }
 
     async fn delete_bucket(
@@ -209,11 +471,39 @@ where
         Err(Status::unimplemented("delete_bucket"))
     }
 
+    /// Lists the buckets of an org that the request may read. Only
+    /// buckets named by an id, and so addressable by the `ReadSource`
+    /// of storage requests, are listed.
     async fn get_buckets(
         &self,
-        _req: tonic::Request<Organization>,
+        req: tonic::Request<Organization>,
     ) -> Result<tonic::Response<GetBucketsResponse>, Status> {
-        Err(Status::unimplemented("get_buckets"))
+        let principal = self.authenticate(&req)?;
+
+        let org_id = req.into_inner().id;
+        let org = Id::try_from(org_id)
+            .map_err(|e| Status::invalid_argument(format!("invalid org id: {}", e)))?
+            .to_string();
+
+        let buckets = tenant::org_buckets(self.db_store.as_ref(), &org)
+            .await
+            .into_iter()
+            .filter(|name| {
+                let db_name = org_and_bucket_to_database(&org, name);
+                principal.is_authorized(&db_name, Permission::Read)
+            })
+            .filter_map(|name| {
+                let id = Id::from_str(&name).ok()?;
+                Some(Bucket {
+                    org_id,
+                    id: id.into(),
+                    name,
+                    ..Default::default()
+                })
+            })
+            .collect();
+
+        Ok(tonic::Response::new(GetBucketsResponse { buckets }))
     }
 
     async fn test_error(
@@ -223,41 +513,40 @@ where
         warn!("Got a test_error request. About to panic");
         panic!("This is a test panic");
     }
-}
 
-/// Implementes the protobuf defined Storage service for a DatabaseStore
-#[tonic::async_trait]
-impl<T> Storage for GrpcService<T>
-where
-    T: DatabaseStore + 'static,
-{
-    type ReadFilterStream = mpsc::Receiver<Result<ReadResponse, Status>>;
+    type ReadGapsStream = mpsc::Receiver<Result<ReadGapsResponse, Status>>;
 
-    async fn read_filter(
+    async fn read_gaps(
         &self,
-        req: tonic::Request<ReadFilterRequest>,
-    ) -> Result<tonic::Response<Self::ReadFilterStream>, Status> {
+        req: tonic::Request<ReadGapsRequest>,
+    ) -> Result<tonic::Response<Self::ReadGapsStream>, Status> {
         let (tx, rx) = mpsc::channel(4);
 
-        let read_filter_request = req.into_inner();
+        let (db_name, row_filter) = self.authorize(&req, Permission::Read)?;
 
-        let db_name = get_database_name(&read_filter_request)?;
+        let read_gaps_request = req.into_inner();
 
-        let ReadFilterRequest {
+        let ReadGapsRequest {
             read_source: _read_source,
             range,
             predicate,
-        } = read_filter_request;
+            threshold,
+        } = read_gaps_request;
 
-        info!("read_filter for database {}, range: {:?}", db_name, range);
+        info!(
+            "read_gaps for database {}, range: {:?}, threshold: {}",
+            db_name, range, threshold
+        );
 
-        read_filter_impl(
-            tx.clone(),
+        read_gaps_impl(
+            tx,
             self.db_store.clone(),
-            self.executor.clone(),
+            self.executor_for(&db_name),
             db_name,
             range,
             predicate,
+            row_filter,
+            threshold,
         )
         .await
         .map_err(|e| e.to_status())?;
@@ -265,363 +554,812 @@ where
         Ok(tonic::Response::new(rx))
     }
 
-    type ReadGroupStream = mpsc::Receiver<Result<ReadResponse, Status>>;
+    type SubscribeStream = mpsc::Receiver<Result<SubscribeResponse, Status>>;
 
-    async fn read_group(
+    async fn subscribe(
         &self,
-        req: tonic::Request<ReadGroupRequest>,
-    ) -> Result<tonic::Response<Self::ReadGroupStream>, Status> {
-        let (tx, rx) = mpsc::channel(4);
+        req: tonic::Request<SubscribeRequest>,
+    ) -> Result<tonic::Response<Self::SubscribeStream>, Status> {
+        let principal = self.authenticate(&req)?;
 
-        let read_group_request = req.into_inner();
+        let SubscribeRequest {
+            name,
+            after_sequence,
+        } = req.into_inner();
 
-        let db_name = get_database_name(&read_group_request)?;
-
-        let ReadGroupRequest {
-            read_source: _read_source,
-            range,
-            predicate,
-            group_keys,
-            // TODO: handle Group::None
-            group: _group,
-            // TODO: handle aggregate values, especially whether None is the same as
-            // Some(AggregateType::None) or not
-            aggregate: _aggregate,
-            hints: _,
-        } = read_group_request;
+        let subscription = self.subscriptions.get(&name).map_err(|e| e.to_status())?;
+        principal
+            .authorize(&subscription.config.db_name, Permission::Read)
+            .map_err(|e| e.to_status())?;
 
         info!(
-            "read_group for database {}, range: {:?}, group_keys: {:?}",
-            db_name, range, group_keys
+            "subscribe to subscription {} after sequence {}",
+            name, after_sequence
         );
 
-        read_group_impl(
-            tx.clone(),
-            self.db_store.clone(),
-            self.executor.clone(),
-            db_name,
-            range,
-            predicate,
-            group_keys,
-        )
-        .await
-        .map_err(|e| e.to_status())?;
+        let updates = self
+            .subscriptions
+            .subscribe(&name, after_sequence)
+            .map_err(|e| e.to_status())?;
+
+        let (tx, rx) = mpsc::channel(4);
+        tokio::spawn(stream_subscription(
+            tx,
+            Arc::clone(&self.subscriptions),
+            name,
+            updates,
+            after_sequence,
+        ));
 
         Ok(tonic::Response::new(rx))
     }
 
-    type ReadWindowAggregateStream = mpsc::Receiver<Result<ReadResponse, Status>>;
+    async fn list_databases(
+        &self,
+        req: tonic::Request<ListDatabasesRequest>,
+    ) -> Result<tonic::Response<ListDatabasesResponse>, Status> {
+        let principal = self.authenticate(&req)?;
+
+        let mut databases = vec![];
+        for name in self.db_store.db_names_sorted().await {
+            if !principal.is_authorized(&name, Permission::Read) {
+                continue;
+            }
 
-    async fn read_window_aggregate(
+            if let Some(db) = self.db_store.db(&name).await {
+                databases.push(database_config(name, &db.rules().await)?);
+            }
+        }
+
+        Ok(tonic::Response::new(ListDatabasesResponse { databases }))
+    }
+
+    async fn get_database(
         &self,
-        _req: tonic::Request<ReadWindowAggregateRequest>,
-    ) -> Result<tonic::Response<Self::ReadGroupStream>, Status> {
-        unimplemented!("read_window_aggregate not yet implemented");
+        req: tonic::Request<GetDatabaseRequest>,
+    ) -> Result<tonic::Response<GetDatabaseResponse>, Status> {
+        let principal = self.authenticate(&req)?;
+
+        let GetDatabaseRequest { name } = req.into_inner();
+        principal
+            .authorize(&name, Permission::Read)
+            .map_err(|e| e.to_status())?;
+
+        let db = self
+            .db_store
+            .db(&name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("database {} not found", name)))?;
+        let database = database_config(name, &db.rules().await)?;
+
+        Ok(tonic::Response::new(GetDatabaseResponse {
+            database: Some(database),
+        }))
     }
 
-    type TagKeysStream = mpsc::Receiver<Result<StringValuesResponse, Status>>;
+    async fn create_database(
+        &self,
+        req: tonic::Request<CreateDatabaseRequest>,
+    ) -> Result<tonic::Response<CreateDatabaseResponse>, Status> {
+        let principal = self.authenticate(&req)?;
+        principal.authorize_admin().map_err(|e| e.to_status())?;
+
+        let (name, rules) = parse_database_config(req.into_inner().database)?;
+
+        if self.db_store.db(&name).await.is_some() {
+            return Err(Status::already_exists(format!(
+                "database {} already exists",
+                name
+            )));
+        }
+
+        self.db_store
+            .create_db(&name, rules.clone())
+            .await
+            .map_err(|e| Status::invalid_argument(e.to_string()))?;
+        info!("Created database {} with rules {:?}", name, rules);
 
-    async fn tag_keys(
+        Ok(tonic::Response::new(CreateDatabaseResponse {}))
+    }
+
+    async fn update_database(
         &self,
-        req: tonic::Request<TagKeysRequest>,
-    ) -> Result<tonic::Response<Self::TagKeysStream>, Status> {
-        let (mut tx, rx) = mpsc::channel(4);
+        req: tonic::Request<UpdateDatabaseRequest>,
+    ) -> Result<tonic::Response<UpdateDatabaseResponse>, Status> {
+        let principal = self.authenticate(&req)?;
+        principal.authorize_admin().map_err(|e| e.to_status())?;
 
-        let tag_keys_request = req.into_inner();
+        let (name, rules) = parse_database_config(req.into_inner().database)?;
 
-        let db_name = get_database_name(&tag_keys_request)?;
+        let db = self
+            .db_store
+            .db(&name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("database {} not found", name)))?;
 
-        let TagKeysRequest {
-            tags_source: _tag_source,
-            range,
-            predicate,
-        } = tag_keys_request;
+        db.set_rules(rules.clone())
+            .await
+            .map_err(|e| Status::internal(e.to_string()))?;
+        info!("Updated rules of database {} to {:?}", name, rules);
 
-        info!("tag_keys for database {}, range: {:?}", db_name, range);
+        Ok(tonic::Response::new(UpdateDatabaseResponse {}))
+    }
 
-        let measurement = None;
+    async fn delete_database(
+        &self,
+        req: tonic::Request<DeleteDatabaseRequest>,
+    ) -> Result<tonic::Response<DeleteDatabaseResponse>, Status> {
+        let principal = self.authenticate(&req)?;
+        principal.authorize_admin().map_err(|e| e.to_status())?;
 
-        let response = tag_keys_impl(
-            self.db_store.clone(),
-            self.executor.clone(),
-            db_name,
-            measurement,
-            range,
-            predicate,
-        )
-        .await
-        .map_err(|e| e.to_status());
+        let DeleteDatabaseRequest { name } = req.into_inner();
 
-        tx.send(response)
+        if self.db_store.db(&name).await.is_none() {
+            return Err(Status::not_found(format!("database {} not found", name)));
+        }
+
+        self.db_store
+            .delete_db(&name)
             .await
-            .expect("sending tag_keys response to server");
+            .map_err(|e| Status::internal(e.to_string()))?;
+        info!("Deleted database {}", name);
 
-        Ok(tonic::Response::new(rx))
+        Ok(tonic::Response::new(DeleteDatabaseResponse {}))
     }
+}
 
-    type TagValuesStream = mpsc::Receiver<Result<StringValuesResponse, Status>>;
+/// Returns the gRPC representation of database `name` with `rules`
+fn database_config(name: String, rules: &DatabaseRules) -> Result<DatabaseConfig, Status> {
+    let rules_json = serde_json::to_string(rules)
+        .map_err(|e| Status::internal(format!("serializing database rules: {}", e)))?;
 
-    async fn tag_values(
-        &self,
-        req: tonic::Request<TagValuesRequest>,
-    ) -> Result<tonic::Response<Self::TagValuesStream>, Status> {
-        let (mut tx, rx) = mpsc::channel(4);
+    Ok(DatabaseConfig { name, rules_json })
+}
 
-        let tag_values_request = req.into_inner();
+/// Returns the name and the validated rules of the database described
+/// by `config`
+fn parse_database_config(
+    config: Option<DatabaseConfig>,
+) -> Result<(String, DatabaseRules), Status> {
+    let DatabaseConfig { name, rules_json } =
+        config.ok_or_else(|| Status::invalid_argument("missing database"))?;
+
+    let rules: DatabaseRules = serde_json::from_str(&rules_json)
+        .map_err(|e| Status::invalid_argument(format!("invalid database rules: {}", e)))?;
+    rules
+        .validate()
+        .map_err(|e| Status::invalid_argument(format!("invalid database rules: {}", e)))?;
+
+    Ok((name, rules))
+}
 
-        let db_name = get_database_name(&tag_values_request)?;
+/// Sends the messages of subscription `name` after `sequence` to `tx`
+/// as they are published, until the subscriber disconnects or the
+/// subscription is deleted or replaced
+async fn stream_subscription(
+    mut tx: mpsc::Sender<Result<SubscribeResponse, Status>>,
+    subscriptions: Arc<Subscriptions>,
+    name: String,
+    mut updates: tokio::sync::watch::Receiver<u64>,
+    mut sequence: u64,
+) {
+    while updates.recv().await.is_some() {
+        let messages = match subscriptions.messages_after(&name, sequence) {
+            Ok(messages) => messages,
+            Err(_) => break,
+        };
 
-        let TagValuesRequest {
-            tags_source: _tag_source,
-            range,
-            predicate,
-            tag_key,
-        } = tag_values_request;
+        for message in messages {
+            let format = match message.format {
+                subscriptions::Format::LineProtocol => SubscriptionFormat::LineProtocol,
+                subscriptions::Format::Arrow => SubscriptionFormat::Arrow,
+            };
+            let response = SubscribeResponse {
+                sequence: message.sequence,
+                db_name: message.db_name.clone(),
+                measurement: message.measurement.clone(),
+                format: format as i32,
+                data: message.data.clone(),
+            };
 
-        let measurement = None;
+            if tx.send(Ok(response)).await.is_err() {
+                info!("subscriber of subscription {} disconnected", name);
+                return;
+            }
+            sequence = message.sequence;
+        }
+    }
 
-        // Special case a request for 'tag_key=_measurement" means to list all measurements
-        let response = if tag_key.is_measurement() {
-            info!(
-                "tag_values with tag_key=[x00] for database {}, range: {:?} --> returning measurement_names",
-                db_name, range
-            );
+    // the subscriber reconnects to continue with the subscription
+    // that replaced this one, if any
+    let _ = tx
+        .send(Err(Status::unavailable(format!(
+            "subscription {} was deleted or replaced",
+            name
+        ))))
+        .await;
+}
 
-            if predicate.is_some() {
-                unimplemented!("tag_value for a measurement, with general predicate");
-            }
+/// Implementes the protobuf defined Storage service for a DatabaseStore
+#[tonic::async_trait]
+impl<T> Storage for GrpcService<T>
+where
+    T: DatabaseStore + 'static,
+{
+    type ReadFilterStream = MeteredStream<ReadResponse>;
 
-            measurement_name_impl(self.db_store.clone(), self.executor.clone(), db_name, range)
-                .await
-        } else {
-            info!(
-                "tag_values for database {}, range: {:?}, tag_key: {}",
-                db_name, range, tag_key
-            );
+    async fn read_filter(
+        &self,
+        req: tonic::Request<ReadFilterRequest>,
+    ) -> Result<tonic::Response<Self::ReadFilterStream>, Status> {
+        let recorder = self.storage_rpc("read_filter", &req);
+        let rx = async {
+            let (tx, rx) = mpsc::channel(4);
+
+            let (db_name, row_filter) = self.authorize(&req, Permission::Read)?;
+            let cache =
+                self.cache_entry(&db_name, "read_filter", req.get_ref(), row_filter.as_ref());
+            let limits = self.read_limits_for(&req, &db_name)?;
+
+            let read_filter_request = req.into_inner();
 
-            tag_values_impl(
+            let ReadFilterRequest {
+                read_source: _read_source,
+                range,
+                predicate,
+            } = read_filter_request;
+
+            info!("read_filter for database {}, range: {:?}", db_name, range);
+
+            read_filter_impl(
+                tx.clone(),
                 self.db_store.clone(),
-                self.executor.clone(),
+                self.executor_for(&db_name),
+                cache,
                 db_name,
-                tag_key,
-                measurement,
                 range,
                 predicate,
+                row_filter,
+                limits,
             )
             .await
-        };
-
-        let response = response.map_err(|e| e.to_status());
-
-        tx.send(response)
-            .await
-            .expect("sending tag_values response to server");
+            .map_err(|e| e.to_status())?;
 
-        Ok(tonic::Response::new(rx))
+            Ok::<_, Status>(rx)
+        }
+        .await;
+        recorder.respond(rx)
     }
 
-    type ReadSeriesCardinalityStream = mpsc::Receiver<Result<Int64ValuesResponse, Status>>;
-
-    async fn read_series_cardinality(
-        &self,
-        _req: tonic::Request<ReadSeriesCardinalityRequest>,
-    ) -> Result<tonic::Response<Self::ReadSeriesCardinalityStream>, Status> {
-        unimplemented!("read_series_cardinality not yet implemented");
-    }
+    type ReadGroupStream = MeteredStream<ReadResponse>;
 
-    async fn capabilities(
+    async fn read_group(
         &self,
-        _req: tonic::Request<()>,
-    ) -> Result<tonic::Response<CapabilitiesResponse>, Status> {
-        // Full list of go capabilities in
-        // idpe/storage/read/capabilities.go (aka window aggregate /
-        // pushdown)
-        //
-        // For now, do not claim to support any capabilities
-        let caps = CapabilitiesResponse {
-            caps: HashMap::new(),
-        };
-        Ok(tonic::Response::new(caps))
-    }
-
-    type MeasurementNamesStream = mpsc::Receiver<Result<StringValuesResponse, Status>>;
+        req: tonic::Request<ReadGroupRequest>,
+    ) -> Result<tonic::Response<Self::ReadGroupStream>, Status> {
+        let recorder = self.storage_rpc("read_group", &req);
+        let rx = async {
+            let (tx, rx) = mpsc::channel(4);
 
-    async fn measurement_names(
-        &self,
-        req: tonic::Request<MeasurementNamesRequest>,
-    ) -> Result<tonic::Response<Self::MeasurementNamesStream>, Status> {
-        let (mut tx, rx) = mpsc::channel(4);
+            let (db_name, row_filter) = self.authorize(&req, Permission::Read)?;
+            let cache =
+                self.cache_entry(&db_name, "read_group", req.get_ref(), row_filter.as_ref());
+            let limits = self.read_limits_for(&req, &db_name)?;
 
-        let measurement_names_request = req.into_inner();
+            let read_group_request = req.into_inner();
 
-        let db_name = get_database_name(&measurement_names_request)?;
+            let ReadGroupRequest {
+                read_source: _read_source,
+                range,
+                predicate,
+                group_keys,
+                // TODO: handle Group::None
+                group: _group,
+                aggregate,
+                hints,
+                window,
+                fill,
+            } = read_group_request;
 
-        let MeasurementNamesRequest {
-            source: _source,
-            range,
-            predicate,
-        } = measurement_names_request;
+            info!(
+                "read_group for database {}, range: {:?}, group_keys: {:?}, window: {:?}",
+                db_name, range, group_keys, window
+            );
 
-        if let Some(predicate) = predicate {
-            return NotYetImplemented {
-                operation: format!(
-                    "measurement_names request with a predicate: {:?}",
-                    predicate
-                ),
+            let mut warnings = Warnings::new();
+            if hints != 0 {
+                warnings.add(WarningCode::IgnoredField, "hints are not supported");
             }
-            .fail()
-            .map_err(|e| e.to_status());
-        }
 
-        info!(
-            "measurement_names for database {}, range: {:?}",
-            db_name, range
-        );
-
-        let response =
-            measurement_name_impl(self.db_store.clone(), self.executor.clone(), db_name, range)
-                .await
-                .map_err(|e| e.to_status());
+            let group_aggregate = convert_group_aggregate(
+                aggregate.clone(),
+                window.clone(),
+                fill.clone(),
+                range.as_ref(),
+            )
+            .map_err(|e| e.to_status())?;
 
-        tx.send(response)
+            read_group_impl(
+                tx.clone(),
+                self.db_store.clone(),
+                self.executor_for(&db_name),
+                cache,
+                db_name,
+                range,
+                predicate,
+                row_filter,
+                group_keys,
+                group_aggregate,
+                limits,
+                warnings,
+            )
             .await
-            .expect("sending measurement names response to server");
+            .map_err(|e| e.to_status())?;
 
-        Ok(tonic::Response::new(rx))
+            Ok::<_, Status>(rx)
+        }
+        .await;
+        recorder.respond(rx)
     }
 
-    type MeasurementTagKeysStream = mpsc::Receiver<Result<StringValuesResponse, Status>>;
+    type ReadWindowAggregateStream = MeteredStream<ReadResponse>;
 
-    async fn measurement_tag_keys(
+    async fn read_window_aggregate(
         &self,
-        req: tonic::Request<MeasurementTagKeysRequest>,
-    ) -> Result<tonic::Response<Self::MeasurementTagKeysStream>, Status> {
-        let (mut tx, rx) = mpsc::channel(4);
+        req: tonic::Request<ReadWindowAggregateRequest>,
+    ) -> Result<tonic::Response<Self::ReadWindowAggregateStream>, Status> {
+        let recorder = self.storage_rpc("read_window_aggregate", &req);
+        let rx = async {
+            let (tx, rx) = mpsc::channel(4);
+
+            let (db_name, row_filter) = self.authorize(&req, Permission::Read)?;
+            let cache = self.cache_entry(
+                &db_name,
+                "read_window_aggregate",
+                req.get_ref(),
+                row_filter.as_ref(),
+            );
 
-        let measurement_tag_keys_request = req.into_inner();
+            let read_window_aggregate_request = req.into_inner();
 
-        let db_name = get_database_name(&measurement_tag_keys_request)?;
+            let ReadWindowAggregateRequest {
+                read_source: _read_source,
+                range,
+                predicate,
+                window_every,
+                offset,
+                aggregate,
+                window,
+                fill,
+            } = read_window_aggregate_request;
 
-        let MeasurementTagKeysRequest {
-            source: _source,
-            measurement,
-            range,
-            predicate,
-        } = measurement_tag_keys_request;
+            info!(
+                "read_window_aggregate for database {}, range: {:?}, window_every: {:?}, offset: {:?}, aggregate: {:?}, window: {:?}, fill: {:?}",
+                db_name, range, window_every, offset, aggregate, window, fill
+            );
 
-        info!(
-            "measurement_tag_keys for database {}, range: {:?}, measurement: {}",
-            db_name, range, measurement
-        );
+            let mut warnings = Warnings::new();
+            if window.is_some() && (window_every != 0 || offset != 0) {
+                warnings.add(
+                    WarningCode::IgnoredField,
+                    "window_every and offset are ignored because window is set",
+                );
+            }
 
-        let measurement = Some(measurement);
+            let window_aggregate = convert_window_aggregate(
+                window_every,
+                offset,
+                window,
+                &aggregate,
+                fill,
+                range.as_ref(),
+            )
+            .map_err(|e| e.to_status())?;
 
-        let response = tag_keys_impl(
-            self.db_store.clone(),
-            self.executor.clone(),
-            db_name,
-            measurement,
-            range,
-            predicate,
-        )
-        .await
-        .map_err(|e| e.to_status());
+            read_window_aggregate_impl(
+                tx.clone(),
+                self.db_store.clone(),
+                self.executor_for(&db_name),
+                cache,
+                db_name,
+                range,
+                predicate,
+                row_filter,
+                window_aggregate,
+                warnings,
+            )
+            .await
+            .map_err(|e| e.to_status())?;
 
-        tx.send(response)
+            Ok::<_, Status>(rx)
+        }
+        .await;
+        recorder.respond(rx)
+    }
+
+    type TagKeysStream = MeteredStream<StringValuesResponse>;
+
+    async fn tag_keys(
+        &self,
+        req: tonic::Request<TagKeysRequest>,
+    ) -> Result<tonic::Response<Self::TagKeysStream>, Status> {
+        let recorder = self.storage_rpc("tag_keys", &req);
+        let rx = async {
+            let (mut tx, rx) = mpsc::channel(4);
+
+            let (db_name, row_filter) = self.authorize(&req, Permission::Read)?;
+            let cache = self.cache_entry(&db_name, "tag_keys", req.get_ref(), row_filter.as_ref());
+
+            let tag_keys_request = req.into_inner();
+
+            let TagKeysRequest {
+                tags_source: _tag_source,
+                range,
+                predicate,
+            } = tag_keys_request;
+
+            info!("tag_keys for database {}, range: {:?}", db_name, range);
+
+            let measurement = None;
+
+            let response = tag_keys_impl(
+                self.db_store.clone(),
+                self.executor_for(&db_name),
+                cache,
+                db_name,
+                measurement,
+                range,
+                predicate,
+                row_filter,
+            )
             .await
-            .expect("sending measurement_tag_keys response to server");
+            .map_err(|e| e.to_status());
 
-        Ok(tonic::Response::new(rx))
+            tx.send(response)
+                .await
+                .expect("sending tag_keys response to server");
+
+            Ok::<_, Status>(rx)
+        }
+        .await;
+        recorder.respond(rx)
+    }
+
+    type TagValuesStream = MeteredStream<StringValuesResponse>;
+
+    async fn tag_values(
+        &self,
+        req: tonic::Request<TagValuesRequest>,
+    ) -> Result<tonic::Response<Self::TagValuesStream>, Status> {
+        let recorder = self.storage_rpc("tag_values", &req);
+        let rx = async {
+            let (mut tx, rx) = mpsc::channel(4);
+
+            let (db_name, row_filter) = self.authorize(&req, Permission::Read)?;
+            let cache =
+                self.cache_entry(&db_name, "tag_values", req.get_ref(), row_filter.as_ref());
+
+            let tag_values_request = req.into_inner();
+
+            let TagValuesRequest {
+                tags_source: _tag_source,
+                range,
+                predicate,
+                tag_key,
+            } = tag_values_request;
+
+            let measurement = None;
+
+            // Special case a request for 'tag_key=_measurement" means to list all measurements
+            let response = if tag_key.is_measurement() {
+                info!(
+                    "tag_values with tag_key=[x00] for database {}, range: {:?} --> returning measurement_names",
+                    db_name, range
+                );
+
+                if predicate.is_some() {
+                    unimplemented!("tag_value for a measurement, with general predicate");
+                }
+
+                measurement_name_impl(
+                    self.db_store.clone(),
+                    self.executor_for(&db_name),
+                    cache,
+                    db_name,
+                    range,
+                    row_filter,
+                )
+                .await
+            } else {
+                info!(
+                    "tag_values for database {}, range: {:?}, tag_key: {}",
+                    db_name, range, tag_key
+                );
+
+                tag_values_impl(
+                    self.db_store.clone(),
+                    self.executor_for(&db_name),
+                    cache,
+                    db_name,
+                    tag_key,
+                    measurement,
+                    range,
+                    predicate,
+                    row_filter,
+                )
+                .await
+            };
+
+            let response = response.map_err(|e| e.to_status());
+
+            tx.send(response)
+                .await
+                .expect("sending tag_values response to server");
+
+            Ok::<_, Status>(rx)
+        }
+        .await;
+        recorder.respond(rx)
+    }
+
+    type ReadSeriesCardinalityStream = mpsc::Receiver<Result<Int64ValuesResponse, Status>>;
+
+    async fn read_series_cardinality(
+        &self,
+        _req: tonic::Request<ReadSeriesCardinalityRequest>,
+    ) -> Result<tonic::Response<Self::ReadSeriesCardinalityStream>, Status> {
+        unimplemented!("read_series_cardinality not yet implemented");
+    }
+
+    async fn capabilities(
+        &self,
+        _req: tonic::Request<()>,
+    ) -> Result<tonic::Response<CapabilitiesResponse>, Status> {
+        // Full list of go capabilities in
+        // idpe/storage/read/capabilities.go (aka window aggregate /
+        // pushdown)
+        //
+        // For now, do not claim to support any capabilities
+        let caps = CapabilitiesResponse {
+            caps: HashMap::new(),
+        };
+        Ok(tonic::Response::new(caps))
+    }
+
+    type MeasurementNamesStream = MeteredStream<StringValuesResponse>;
+
+    async fn measurement_names(
+        &self,
+        req: tonic::Request<MeasurementNamesRequest>,
+    ) -> Result<tonic::Response<Self::MeasurementNamesStream>, Status> {
+        let recorder = self.storage_rpc("measurement_names", &req);
+        let rx = async {
+            let (mut tx, rx) = mpsc::channel(4);
+
+            let (db_name, row_filter) = self.authorize(&req, Permission::Read)?;
+            let cache = self.cache_entry(
+                &db_name,
+                "measurement_names",
+                req.get_ref(),
+                row_filter.as_ref(),
+            );
+
+            let measurement_names_request = req.into_inner();
+
+            let MeasurementNamesRequest {
+                source: _source,
+                range,
+                predicate,
+            } = measurement_names_request;
+
+            if let Some(predicate) = predicate {
+                return NotYetImplemented {
+                    operation: format!(
+                        "measurement_names request with a predicate: {:?}",
+                        predicate
+                    ),
+                }
+                .fail()
+                .map_err(|e| e.to_status());
+            }
+
+            info!(
+                "measurement_names for database {}, range: {:?}",
+                db_name, range
+            );
+
+            let response = measurement_name_impl(
+                self.db_store.clone(),
+                self.executor_for(&db_name),
+                cache,
+                db_name,
+                range,
+                row_filter,
+            )
+            .await
+            .map_err(|e| e.to_status());
+
+            tx.send(response)
+                .await
+                .expect("sending measurement names response to server");
+
+            Ok::<_, Status>(rx)
+        }
+        .await;
+        recorder.respond(rx)
+    }
+
+    type MeasurementTagKeysStream = MeteredStream<StringValuesResponse>;
+
+    async fn measurement_tag_keys(
+        &self,
+        req: tonic::Request<MeasurementTagKeysRequest>,
+    ) -> Result<tonic::Response<Self::MeasurementTagKeysStream>, Status> {
+        let recorder = self.storage_rpc("measurement_tag_keys", &req);
+        let rx = async {
+            let (mut tx, rx) = mpsc::channel(4);
+
+            let (db_name, row_filter) = self.authorize(&req, Permission::Read)?;
+            let cache = self.cache_entry(
+                &db_name,
+                "measurement_tag_keys",
+                req.get_ref(),
+                row_filter.as_ref(),
+            );
+
+            let measurement_tag_keys_request = req.into_inner();
+
+            let MeasurementTagKeysRequest {
+                source: _source,
+                measurement,
+                range,
+                predicate,
+            } = measurement_tag_keys_request;
+
+            info!(
+                "measurement_tag_keys for database {}, range: {:?}, measurement: {}",
+                db_name, range, measurement
+            );
+
+            let measurement = Some(measurement);
+
+            let response = tag_keys_impl(
+                self.db_store.clone(),
+                self.executor_for(&db_name),
+                cache,
+                db_name,
+                measurement,
+                range,
+                predicate,
+                row_filter,
+            )
+            .await
+            .map_err(|e| e.to_status());
+
+            tx.send(response)
+                .await
+                .expect("sending measurement_tag_keys response to server");
+
+            Ok::<_, Status>(rx)
+        }
+        .await;
+        recorder.respond(rx)
     }
 
-    type MeasurementTagValuesStream = mpsc::Receiver<Result<StringValuesResponse, Status>>;
+    type MeasurementTagValuesStream = MeteredStream<StringValuesResponse>;
 
     async fn measurement_tag_values(
         &self,
         req: tonic::Request<MeasurementTagValuesRequest>,
     ) -> Result<tonic::Response<Self::MeasurementTagValuesStream>, Status> {
-        let (mut tx, rx) = mpsc::channel(4);
-
-        let measurement_tag_values_request = req.into_inner();
-
-        let db_name = get_database_name(&measurement_tag_values_request)?;
+        let recorder = self.storage_rpc("measurement_tag_values", &req);
+        let rx = async {
+            let (mut tx, rx) = mpsc::channel(4);
+
+            let (db_name, row_filter) = self.authorize(&req, Permission::Read)?;
+            let cache = self.cache_entry(
+                &db_name,
+                "measurement_tag_values",
+                req.get_ref(),
+                row_filter.as_ref(),
+            );
 
-        let MeasurementTagValuesRequest {
-            source: _source,
-            measurement,
-            range,
-            predicate,
-            tag_key,
-        } = measurement_tag_values_request;
+            let measurement_tag_values_request = req.into_inner();
 
-        info!(
-            "measurement_tag_values for database {}, range: {:?}, measurement: {}, tag_key: {}",
-            db_name, range, measurement, tag_key
-        );
+            let MeasurementTagValuesRequest {
+                source: _source,
+                measurement,
+                range,
+                predicate,
+                tag_key,
+            } = measurement_tag_values_request;
 
-        let measurement = Some(measurement);
+            info!(
+                "measurement_tag_values for database {}, range: {:?}, measurement: {}, tag_key: {}",
+                db_name, range, measurement, tag_key
+            );
 
-        let response = tag_values_impl(
-            self.db_store.clone(),
-            self.executor.clone(),
-            db_name,
-            tag_key,
-            measurement,
-            range,
-            predicate,
-        )
-        .await
-        .map_err(|e| e.to_status());
+            let measurement = Some(measurement);
 
-        tx.send(response)
+            let response = tag_values_impl(
+                self.db_store.clone(),
+                self.executor_for(&db_name),
+                cache,
+                db_name,
+                tag_key,
+                measurement,
+                range,
+                predicate,
+                row_filter,
+            )
             .await
-            .expect("sending measurement_tag_values response to server");
+            .map_err(|e| e.to_status());
 
-        Ok(tonic::Response::new(rx))
+            tx.send(response)
+                .await
+                .expect("sending measurement_tag_values response to server");
+
+            Ok::<_, Status>(rx)
+        }
+        .await;
+        recorder.respond(rx)
     }
 
-    type MeasurementFieldsStream = mpsc::Receiver<Result<MeasurementFieldsResponse, Status>>;
+    type MeasurementFieldsStream = MeteredStream<MeasurementFieldsResponse>;
 
     async fn measurement_fields(
         &self,
         req: tonic::Request<MeasurementFieldsRequest>,
     ) -> Result<tonic::Response<Self::MeasurementFieldsStream>, Status> {
-        let (mut tx, rx) = mpsc::channel(4);
-
-        let measurement_fields_request = req.into_inner();
-
-        let db_name = get_database_name(&measurement_fields_request)?;
+        let recorder = self.storage_rpc("measurement_fields", &req);
+        let rx = async {
+            let (mut tx, rx) = mpsc::channel(4);
+
+            let (db_name, row_filter) = self.authorize(&req, Permission::Read)?;
+            let cache = self.cache_entry(
+                &db_name,
+                "measurement_fields",
+                req.get_ref(),
+                row_filter.as_ref(),
+            );
 
-        let MeasurementFieldsRequest {
-            source: _source,
-            measurement,
-            range,
-            predicate,
-        } = measurement_fields_request;
+            let measurement_fields_request = req.into_inner();
 
-        info!(
-            "measurement_fields for database {}, range: {:?}",
-            db_name, range
-        );
+            let MeasurementFieldsRequest {
+                source: _source,
+                measurement,
+                range,
+                predicate,
+            } = measurement_fields_request;
 
-        let measurement = measurement;
+            info!(
+                "measurement_fields for database {}, range: {:?}",
+                db_name, range
+            );
 
-        let response = measurement_fields_impl(
-            self.db_store.clone(),
-            self.executor.clone(),
-            db_name,
-            measurement,
-            range,
-            predicate,
-        )
-        .await
-        .map_err(|e| e.to_status());
+            let measurement = measurement;
 
-        tx.send(response)
+            let response = measurement_fields_impl(
+                self.db_store.clone(),
+                self.executor_for(&db_name),
+                cache,
+                db_name,
+                measurement,
+                range,
+                predicate,
+                row_filter,
+            )
             .await
-            .expect("sending measurement_fields response to server");
+            .map_err(|e| e.to_status());
 
-        Ok(tonic::Response::new(rx))
+            tx.send(response)
+                .await
+                .expect("sending measurement_fields response to server");
+
+            Ok::<_, Status>(rx)
+        }
+        .await;
+        recorder.respond(rx)
     }
 }
 
@@ -639,6 +1377,28 @@ impl SetRange for PredicateBuilder {
     }
 }
 
+/// Returns the org and bucket names of the bucket `bucket_id` of the
+/// org `org_id`, as addressed by the `ReadSource` of storage requests
+fn bucket_names(org_id: u64, bucket_id: u64) -> Result<(String, String), Status> {
+    let org_id = Id::try_from(org_id)
+        .map_err(|e| Status::invalid_argument(format!("invalid org id: {}", e)))?;
+    let bucket_id = Id::try_from(bucket_id)
+        .map_err(|e| Status::invalid_argument(format!("invalid bucket id: {}", e)))?;
+
+    Ok((org_id.to_string(), bucket_id.to_string()))
+}
+
+/// Limits `predicate` to the rows within `row_filter`, if any
+fn restrict_rows(
+    predicate: storage::predicate::Predicate,
+    row_filter: Option<RowFilter>,
+) -> storage::predicate::Predicate {
+    match row_filter {
+        Some(row_filter) => row_filter.restrict(predicate),
+        None => predicate,
+    }
+}
+
 fn get_database_name(input: &impl GrpcInputs) -> Result<String, Status> {
     Ok(org_and_bucket_to_database(
         input.org_id()?,
@@ -646,6 +1406,36 @@ fn get_database_name(input: &impl GrpcInputs) -> Result<String, Status> {
     ))
 }
 
+/// Returns where the responses cached in `cache` are, as of the
+/// `generation` of the database.
+///
+/// Responses are looked up as of the generation of the database when
+/// the request is received, and cached as of the generation of the
+/// data the plan of the query read, so the responses to a query
+/// planned before data is written are not cached as those of the data
+/// written.
+fn at_generation(
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
+    generation: u64,
+) -> Option<(Arc<QueryCache>, CacheKey)> {
+    cache.map(|(cache, key)| (cache, key.with_generation(generation)))
+}
+
+/// Returns the responses cached in `cache`, if any
+fn cached_responses(cache: &Option<(Arc<QueryCache>, CacheKey)>) -> Option<Arc<CachedResponses>> {
+    let (cache, key) = cache.as_ref()?;
+    let responses = cache.get(key)?;
+    debug!("Returning cached results for {:?}", key);
+    Some(responses)
+}
+
+/// Adds `responses` to `cache`, if any
+fn cache_responses(cache: Option<(Arc<QueryCache>, CacheKey)>, responses: CachedResponses) {
+    if let Some((cache, key)) = cache {
+        cache.insert(key, responses);
+    }
+}
+
 // The following code implements the business logic of the requests as
 // methods that return Results with module specific Errors (and thus
 // can use ?, etc). The trait implemententations then handle mapping
@@ -656,32 +1446,55 @@ fn get_database_name(input: &impl GrpcInputs) -> Result<String, Status> {
 async fn measurement_name_impl<T>(
     db_store: Arc<T>,
     executor: Arc<StorageExecutor>,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
     db_name: String,
     range: Option<TimestampRange>,
+    row_filter: Option<RowFilter>,
 ) -> Result<StringValuesResponse>
 where
     T: DatabaseStore,
 {
+    // tables are listed without evaluating expressions, so only
+    // filters of measurements can be applied
+    if row_filter
+        .iter()
+        .any(|row_filter| !row_filter.tags.is_empty())
+    {
+        return Err(Error::Authorization {
+            source: auth::Error::RowsRestricted { db_name },
+        });
+    }
+
     let predicate = PredicateBuilder::default().set_range(range).build();
+    let predicate = restrict_rows(predicate, row_filter);
 
-    let plan = db_store
+    let db = db_store
         .db(&db_name)
         .await
-        .context(DatabaseNotFound { db_name: &db_name })?
+        .context(DatabaseNotFound { db_name: &db_name })?;
+
+    let cache = at_generation(cache, db.generation());
+    if let Some(CachedResponses::StringValues(response)) = cached_responses(&cache).as_deref() {
+        return Ok(response.clone());
+    }
+
+    let planned = db
         .table_names(predicate)
         .await
         .map_err(|e| Error::ListingTables {
             db_name: db_name.clone(),
             source: Box::new(e),
         })?;
+    let cache = at_generation(cache, planned.generation);
 
-    let table_names = executor
-        .to_string_set(plan)
-        .await
-        .map_err(|e| Error::ListingTables {
-            db_name: db_name.clone(),
-            source: Box::new(e),
-        })?;
+    let table_names =
+        executor
+            .to_string_set(planned.plan)
+            .await
+            .map_err(|e| Error::ListingTables {
+                db_name: db_name.clone(),
+                source: Box::new(e),
+            })?;
 
     // Map the resulting collection of Strings into a Vec<Vec<u8>>for return
     let values = table_names
@@ -689,17 +1502,21 @@ where
         .map(|name| name.bytes().collect())
         .collect::<Vec<_>>();
 
-    Ok(StringValuesResponse { values })
+    let response = StringValuesResponse { values };
+    cache_responses(cache, CachedResponses::StringValues(response.clone()));
+    Ok(response)
 }
 
 /// Return tag keys with optional measurement, timestamp and arbitratry predicates
 async fn tag_keys_impl<T>(
     db_store: Arc<T>,
     executor: Arc<StorageExecutor>,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
     db_name: String,
     measurement: Option<String>,
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
+    row_filter: Option<RowFilter>,
 ) -> Result<StringValuesResponse>
 where
     T: DatabaseStore,
@@ -715,11 +1532,18 @@ where
         })?
         .build();
 
+    let predicate = restrict_rows(predicate, row_filter);
+
     let db = db_store
         .db(&db_name)
         .await
         .context(DatabaseNotFound { db_name: &db_name })?;
 
+    let cache = at_generation(cache, db.generation());
+    if let Some(CachedResponses::StringValues(response)) = cached_responses(&cache).as_deref() {
+        return Ok(response.clone());
+    }
+
     let tag_key_plan = db
         .tag_column_names(predicate)
         .await
@@ -727,31 +1551,35 @@ where
             db_name: db_name.clone(),
             source: Box::new(e),
         })?;
+    let cache = at_generation(cache, tag_key_plan.generation);
 
-    let tag_keys =
-        executor
-            .to_string_set(tag_key_plan)
-            .await
-            .map_err(|e| Error::ListingColumns {
-                db_name: db_name.clone(),
-                source: Box::new(e),
-            })?;
+    let tag_keys = executor
+        .to_string_set(tag_key_plan.plan)
+        .await
+        .map_err(|e| Error::ListingColumns {
+            db_name: db_name.clone(),
+            source: Box::new(e),
+        })?;
 
     // Map the resulting collection of Strings into a Vec<Vec<u8>>for return
     let values = tag_keys_to_byte_vecs(tag_keys);
 
-    Ok(StringValuesResponse { values })
+    let response = StringValuesResponse { values };
+    cache_responses(cache, CachedResponses::StringValues(response.clone()));
+    Ok(response)
 }
 
 /// Return tag values for tag_name, with optional measurement, timestamp and arbitratry predicates
 async fn tag_values_impl<T>(
     db_store: Arc<T>,
     executor: Arc<StorageExecutor>,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
     db_name: String,
     tag_name: String,
     measurement: Option<String>,
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
+    row_filter: Option<RowFilter>,
 ) -> Result<StringValuesResponse>
 where
     T: DatabaseStore,
@@ -767,11 +1595,18 @@ where
         })?
         .build();
 
+    let predicate = restrict_rows(predicate, row_filter);
+
     let db = db_store
         .db(&db_name)
         .await
         .context(DatabaseNotFound { db_name: &db_name })?;
 
+    let cache = at_generation(cache, db.generation());
+    if let Some(CachedResponses::StringValues(response)) = cached_responses(&cache).as_deref() {
+        return Ok(response.clone());
+    }
+
     let tag_value_plan =
         db.column_values(&tag_name, predicate)
             .await
@@ -780,16 +1615,16 @@ where
                 tag_name: tag_name.clone(),
                 source: Box::new(e),
             })?;
+    let cache = at_generation(cache, tag_value_plan.generation);
 
-    let tag_values =
-        executor
-            .to_string_set(tag_value_plan)
-            .await
-            .map_err(|e| Error::ListingTagValues {
-                db_name: db_name.clone(),
-                tag_name: tag_name.clone(),
-                source: Box::new(e),
-            })?;
+    let tag_values = executor
+        .to_string_set(tag_value_plan.plan)
+        .await
+        .map_err(|e| Error::ListingTagValues {
+            db_name: db_name.clone(),
+            tag_name: tag_name.clone(),
+            source: Box::new(e),
+        })?;
 
     // Map the resulting collection of Strings into a Vec<Vec<u8>>for return
     let values = tag_values
@@ -797,17 +1632,25 @@ where
         .map(|name| name.bytes().collect())
         .collect::<Vec<_>>();
 
-    Ok(StringValuesResponse { values })
+    let response = StringValuesResponse { values };
+    cache_responses(cache, CachedResponses::StringValues(response.clone()));
+    Ok(response)
 }
 
-/// Launch async tasks that send the result of executing read_filter to `tx`
+/// Launch async tasks that send the result of executing read_filter to
+/// `tx`, within `limits`. If `cache` is provided, the responses are
+/// cached, keyed by the predicate.
+#[allow(clippy::too_many_arguments)]
 async fn read_filter_impl<T>(
     tx: mpsc::Sender<Result<ReadResponse, Status>>,
     db_store: Arc<T>,
     executor: Arc<StorageExecutor>,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
     db_name: String,
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
+    row_filter: Option<RowFilter>,
+    limits: ReadLimits,
 ) -> Result<()>
 where
     T: DatabaseStore,
@@ -822,11 +1665,23 @@ where
         })?
         .build();
 
+    let predicate = restrict_rows(predicate, row_filter);
+    debug!(
+        "read_filter predicate for database {}: {}",
+        db_name, predicate
+    );
+
     let db = db_store
         .db(&db_name)
         .await
         .context(DatabaseNotFound { db_name: &db_name })?;
 
+    let cache = at_generation(cache, db.generation());
+    if let Some(CachedResponses::Read(responses)) = cached_responses(&cache).as_deref() {
+        send_cached_responses(tx, responses.clone(), limits, Warnings::new());
+        return Ok(());
+    }
+
     let series_plan =
         db.query_series(predicate)
             .await
@@ -834,13 +1689,16 @@ where
                 db_name: db_name.clone(),
                 source: Box::new(e),
             })?;
+    let cache = at_generation(cache, series_plan.generation);
 
     // Spawn task to convert between series sets and the gRPC results
     // and to run the actual plans (so we can return a result to the
     // client before we start sending result)
+    let stats = Arc::new(QueryStats::default());
     let (tx_series, rx_series) = mpsc::channel(4);
+    let convert_stats = Arc::clone(&stats);
     tokio::spawn(async move {
-        convert_series_set(rx_series, tx)
+        convert_series_set(rx_series, tx, cache, convert_stats, limits.limiter())
             .await
             .log_if_error("Converting series set")
     });
@@ -848,7 +1706,7 @@ where
     // fire up the plans and start the pipeline flowing
     tokio::spawn(async move {
         executor
-            .to_series_set(series_plan, tx_series)
+            .to_series_set(series_plan.plan, tx_series, &stats)
             .await
             .map_err(|e| Error::FilteringSeries {
                 db_name: db_name.clone(),
@@ -860,19 +1718,97 @@ where
     Ok(())
 }
 
-/// Receives SeriesSets from rx, converts them to ReadResponse and
-/// and sends them to tx
-async fn convert_series_set(
+/// Launch async tasks that send the gaps longer than `threshold` in
+/// each series to `tx`
+async fn read_gaps_impl<T>(
+    tx: mpsc::Sender<Result<ReadGapsResponse, Status>>,
+    db_store: Arc<T>,
+    executor: Arc<StorageExecutor>,
+    db_name: String,
+    range: Option<TimestampRange>,
+    rpc_predicate: Option<Predicate>,
+    row_filter: Option<RowFilter>,
+    threshold: i64,
+) -> Result<()>
+where
+    T: DatabaseStore,
+{
+    ensure!(threshold >= 0, InvalidGapThreshold { threshold });
+
+    let rpc_predicate_string = format!("{:?}", rpc_predicate);
+
+    let mut predicate = PredicateBuilder::default()
+        .set_range(range)
+        .rpc_predicate(rpc_predicate)
+        .context(ConvertingPredicate {
+            rpc_predicate_string,
+        })?
+        .build();
+
+    let mut predicate = restrict_rows(predicate, row_filter);
+
+    // Gaps are found from the timestamps alone, so unless the request
+    // restricts the fields, don't read any field values
+    if predicate.field_columns.is_none() {
+        predicate.field_columns = Some(BTreeSet::new());
+    }
+    let range = predicate.range;
+
+    let db = db_store
+        .db(&db_name)
+        .await
+        .context(DatabaseNotFound { db_name: &db_name })?;
+
+    let series_plan =
+        db.query_series(predicate)
+            .await
+            .map_err(|e| Error::PlanningFilteringSeries {
+                db_name: db_name.clone(),
+                source: Box::new(e),
+            })?;
+
+    let (tx_series, rx_series) = mpsc::channel(4);
+    tokio::spawn(async move {
+        convert_series_gaps(rx_series, tx, threshold, range)
+            .await
+            .log_if_error("Converting series gaps")
+    });
+
+    // The statistics of gaps queries are not returned to clients
+    tokio::spawn(async move {
+        executor
+            .to_series_set(series_plan.plan, tx_series, &QueryStats::default())
+            .await
+            .map_err(|e| Error::FilteringSeries {
+                db_name: db_name.clone(),
+                source: Box::new(e),
+            })
+            .log_if_error("Running series set plan")
+    });
+
+    Ok(())
+}
+
+/// Receives SeriesSets from rx, finds their gaps and sends those of
+/// the series with any gaps to tx
+async fn convert_series_gaps(
     mut rx: mpsc::Receiver<Result<SeriesSet, SeriesSetError>>,
-    mut tx: mpsc::Sender<Result<ReadResponse, Status>>,
+    mut tx: mpsc::Sender<Result<ReadGapsResponse, Status>>,
+    threshold: i64,
+    range: Option<StorageTimestampRange>,
 ) -> Result<()> {
     while let Some(series_set) = rx.recv().await {
-        let response = series_set
+        let series_gaps = series_set
             .context(ComputingSeriesSet)
-            .and_then(|series_set| {
-                series_set_to_read_response(series_set).context(ConvertingSeriesSet)
-            })
-            .map_err(|e| Status::internal(e.to_string()));
+            .and_then(|series_set| find_gaps(&series_set, threshold, range).context(ComputingGaps));
+
+        let response = match series_gaps {
+            Ok(series_gaps) if series_gaps.gaps.is_empty() => continue,
+            Ok(series_gaps) => Ok(ReadGapsResponse {
+                series: vec![series_gaps_to_rpc(series_gaps)],
+            }),
+            Err(e) => Err(Status::internal(e.to_string())),
+        };
 
         tx.send(response)
             .await
@@ -882,15 +1818,164 @@ async fn convert_series_set(
     Ok(())
 }
 
+/// Receives SeriesSets from rx, converts them to ReadResponse and
+/// and sends them to tx within the limits of `limiter`, followed by
+/// the statistics of the query. If all the responses are sent
+/// successfully they are also added to `cache`.
+///
+/// The frames of series with rows in more than one chunk are merged,
+/// so each series is sent once, with its points in time order.
+async fn convert_series_set(
+    mut rx: mpsc::Receiver<Result<SeriesSet, SeriesSetError>>,
+    mut tx: mpsc::Sender<Result<ReadResponse, Status>>,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
+    stats: Arc<QueryStats>,
+    mut limiter: ResultLimiter,
+) -> Result<()> {
+    let mut cached_responses = cache.as_ref().map(|_| Vec::new());
+    let mut merger = SeriesMerger::default();
+    let mut done = false;
+
+    while !done {
+        let responses = match rx.recv().await {
+            Some(series_set) => match series_set
+                .context(ComputingSeriesSet)
+                .and_then(|series_set| merger.push(series_set).context(ConvertingSeriesSet))
+            {
+                Ok(responses) => responses.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            },
+            None => {
+                done = true;
+                merger.finish().into_iter().map(Ok).collect::<Vec<_>>()
+            }
+        };
+
+        for response in responses {
+            let sent = send_cacheable_response(
+                &mut tx,
+                response,
+                &mut cached_responses,
+                &stats,
+                &mut limiter,
+            )
+            .await?;
+            if !sent {
+                return Ok(());
+            }
+        }
+        // the remaining series sets are not needed, and dropping rx
+        // stops the plans producing them
+        if limiter.is_truncated() {
+            break;
+        }
+    }
+
+    if let Some(responses) = cached_responses {
+        cache_responses(cache, CachedResponses::Read(responses));
+    }
+    send_query_stats(&mut tx, &stats, limiter.is_truncated(), &Warnings::new()).await
+}
+
+/// Sends `response` to tx, counting the frames it emits in `stats`
+async fn send_read_response(
+    tx: &mut mpsc::Sender<Result<ReadResponse, Status>>,
+    response: Result<ReadResponse, Status>,
+    stats: &QueryStats,
+) -> Result<()> {
+    if let Ok(response) = &response {
+        stats.add_frames_emitted(response.frames.len() as u64);
+    }
+
+    tx.send(response)
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+        .context(SendingResults)
+}
+
+/// Sends the frames of `response` that fit within the limits of
+/// `limiter` to tx. If the limits are exceeded without truncating
+/// the results, the request fails instead and false is returned, after
+/// which nothing more is to be sent.
+async fn send_limited_response(
+    tx: &mut mpsc::Sender<Result<ReadResponse, Status>>,
+    response: Result<ReadResponse, Status>,
+    stats: &QueryStats,
+    limiter: &mut ResultLimiter,
+) -> Result<bool> {
+    let response = match response.map(|response| limiter.admit(response)) {
+        Ok(Ok(Some(response))) => Ok(response),
+        Ok(Ok(None)) => return Ok(true),
+        Ok(Err(e)) => {
+            send_read_response(tx, Err(e.to_status()), stats).await?;
+            return Ok(false);
+        }
+        Err(status) => Err(status),
+    };
+
+    send_read_response(tx, response, stats).await?;
+    Ok(true)
+}
+
+/// Ends the responses sent to tx with the statistics of the query,
+/// whether its results were truncated to fit within its limits, and
+/// the warnings of the request.
+///
+/// The statistics are sent as the metadata of an `Ok` status, which
+/// ends the stream successfully, so that they are returned to the
+/// client in the trailers of the response.
+async fn send_query_stats(
+    tx: &mut mpsc::Sender<Result<ReadResponse, Status>>,
+    stats: &QueryStats,
+    truncated: bool,
+    warnings: &Warnings,
+) -> Result<()> {
+    tx.send(Err(query_stats_status(stats, truncated, warnings)))
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+        .context(SendingResults)
+}
+
+/// Returns an `Ok` status whose metadata holds the statistics of a
+/// query and its `warnings`, and marks its results as partial if they
+/// were `truncated`
+fn query_stats_status(stats: &QueryStats, truncated: bool, warnings: &Warnings) -> Status {
+    let mut metadata = MetadataMap::new();
+    for (name, value) in stats.named_values() {
+        metadata.insert(name, value.into());
+    }
+    if truncated {
+        metadata.insert(limits::PARTIAL_RESULT, MetadataValue::from_static("true"));
+    }
+    warnings.add_to_metadata(&mut metadata);
+    Status::with_metadata(Code::Ok, "", metadata)
+}
+
 /// Launch async tasks that send the result of executing read_group to `tx`
+///
+/// If `cache` is provided, the responses are cached, keyed by the
+/// predicate, group keys and the other parameters of the request
+/// provided with the cache.
+///
+/// If `group_aggregate` is provided, the series of each group are
+/// aggregated as it describes.
+///
+/// The results are sent within `limits`, whether or not they are
+/// returned from the cache, followed by `warnings`.
+#[allow(clippy::too_many_arguments)]
 async fn read_group_impl<T>(
     tx: mpsc::Sender<Result<ReadResponse, Status>>,
     db_store: Arc<T>,
     executor: Arc<StorageExecutor>,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
     db_name: String,
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
+    row_filter: Option<RowFilter>,
     group_keys: Vec<String>,
+    group_aggregate: Option<GroupAggregate>,
+    limits: ReadLimits,
+    warnings: Warnings,
 ) -> Result<()>
 where
     T: DatabaseStore,
@@ -905,32 +1990,55 @@ where
         })?
         .build();
 
+    let predicate = restrict_rows(predicate, row_filter);
+    debug!(
+        "read_group predicate for database {}: {}",
+        db_name, predicate
+    );
+
     let db = db_store
         .db(&db_name)
         .await
         .context(DatabaseNotFound { db_name: &db_name })?;
 
+    let cache = at_generation(cache, db.generation());
+    if let Some(CachedResponses::Read(responses)) = cached_responses(&cache).as_deref() {
+        send_cached_responses(tx, responses.clone(), limits, warnings);
+        return Ok(());
+    }
+
     let grouped_series_set_plan = db.query_groups(predicate, group_keys).await.map_err(|e| {
         Error::PlanningFilteringSeries {
             db_name: db_name.clone(),
             source: Box::new(e),
         }
     })?;
+    let cache = at_generation(cache, grouped_series_set_plan.generation);
 
     // Spawn task to convert between series sets and the gRPC results
     // and to run the actual plans (so we can return a result to the
     // client before we start sending result)
+    let stats = Arc::new(QueryStats::default());
     let (tx_series, rx_series) = mpsc::channel(4);
+    let convert_stats = Arc::clone(&stats);
     tokio::spawn(async move {
-        convert_grouped_series_set(rx_series, tx)
-            .await
-            .log_if_error("Converting grouped series set")
+        convert_grouped_series_set(
+            rx_series,
+            tx,
+            cache,
+            group_aggregate,
+            convert_stats,
+            limits.limiter(),
+            warnings,
+        )
+        .await
+        .log_if_error("Converting grouped series set")
     });
 
     // fire up the plans and start the pipeline flowing
     tokio::spawn(async move {
         executor
-            .to_grouped_series_set(grouped_series_set_plan, tx_series)
+            .to_grouped_series_set(grouped_series_set_plan.plan, tx_series, &stats)
             .await
             .map_err(|e| Error::GroupingSeries {
                 db_name: db_name.clone(),
@@ -942,38 +2050,505 @@ where
     Ok(())
 }
 
+/// How the series of each group of a `read_group` request are aggregated
+#[derive(Debug, Clone, Copy)]
+struct GroupAggregate {
+    /// Aggregates the values of the series of each group together in
+    /// windows of time, or of each series when selecting series
+    window_aggregate: WindowAggregate,
+    /// Selects the top (or bottom) series of each group, ranked by
+    /// their aggregated values
+    top_n: Option<TopN>,
+}
+
 /// Receives SeriesSets from rx, converts them to ReadResponse and
-/// and sends them to tx
+/// and sends them to tx within the limits of `limiter`, followed by
+/// the statistics of the query and `warnings`. If all the responses
+/// are sent successfully they are also added to `cache`.
+///
+/// If `group_aggregate` is provided, the series of each group are
+/// aggregated together, as one series per table, and sent once all
+/// the series of the group have been received. When selecting the top
+/// (or bottom) series, each series is aggregated on its own instead,
+/// and the selected series of each group are sent once all the series
+/// of the group have been received.
 async fn convert_grouped_series_set(
     mut rx: mpsc::Receiver<Result<GroupedSeriesSetItem, SeriesSetError>>,
     mut tx: mpsc::Sender<Result<ReadResponse, Status>>,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
+    group_aggregate: Option<GroupAggregate>,
+    stats: Arc<QueryStats>,
+    mut limiter: ResultLimiter,
+    warnings: Warnings,
 ) -> Result<()> {
+    let mut cached_responses = cache.as_ref().map(|_| Vec::new());
+
+    // The series selected so far from the current group
+    let mut selected: Option<SelectedSeries> = None;
+    // The series of the current group to be aggregated together
+    let mut group_series: Vec<SeriesSet> = vec![];
+
     while let Some(grouped_series_set_item) = rx.recv().await {
-        let response = grouped_series_set_item
-            .context(ComputingGroupedSeriesSet)
-            .and_then(|grouped_series_set_item| {
-                grouped_series_set_item_to_read_response(grouped_series_set_item)
-                    .context(ConvertingSeriesSet)
-            })
-            .map_err(|e| Status::internal(e.to_string()));
+        let mut responses = vec![];
+
+        match grouped_series_set_item.context(ComputingGroupedSeriesSet) {
+            Ok(GroupedSeriesSetItem::GroupStart(group_description)) => {
+                responses.extend(selected_to_read_responses(selected.take()));
+                responses.extend(group_to_read_responses(
+                    group_aggregate,
+                    std::mem::take(&mut group_series),
+                ));
+                selected = group_aggregate
+                    .and_then(|group_aggregate| group_aggregate.top_n)
+                    .map(|top_n| top_n.select());
+
+                let item = GroupedSeriesSetItem::GroupStart(group_description);
+                responses.push(
+                    grouped_series_set_item_to_read_response(item).context(ConvertingSeriesSet),
+                );
+            }
+            Ok(GroupedSeriesSetItem::GroupData(series_set)) => {
+                match (group_aggregate, selected.as_mut()) {
+                    (Some(group_aggregate), Some(selected)) => {
+                        match group_aggregate
+                            .window_aggregate
+                            .apply(&series_set)
+                            .context(ComputingWindowAggregate)
+                        {
+                            Ok(windowed_series_set) => selected.push(windowed_series_set),
+                            Err(e) => responses.push(Err(e)),
+                        }
+                    }
+                    (Some(_), None) => group_series.push(series_set),
+                    (None, _) => {
+                        let item = GroupedSeriesSetItem::GroupData(series_set);
+                        responses.push(
+                            grouped_series_set_item_to_read_response(item)
+                                .context(ConvertingSeriesSet),
+                        )
+                    }
+                }
+            }
+            Err(e) => responses.push(Err(e)),
+        }
 
-        tx.send(response)
+        for response in responses {
+            let sent = send_cacheable_response(
+                &mut tx,
+                response,
+                &mut cached_responses,
+                &stats,
+                &mut limiter,
+            )
+            .await?;
+            if !sent {
+                return Ok(());
+            }
+        }
+        if limiter.is_truncated() {
+            break;
+        }
+    }
+
+    let responses = selected_to_read_responses(selected)
+        .into_iter()
+        .chain(group_to_read_responses(group_aggregate, group_series));
+    for response in responses {
+        let sent = send_cacheable_response(
+            &mut tx,
+            response,
+            &mut cached_responses,
+            &stats,
+            &mut limiter,
+        )
+        .await?;
+        if !sent {
+            return Ok(());
+        }
+    }
+
+    if let Some(responses) = cached_responses {
+        cache_responses(cache, CachedResponses::Read(responses));
+    }
+    send_query_stats(&mut tx, &stats, limiter.is_truncated(), &warnings).await
+}
+
+/// Converts the series selected from a group, if any, to ReadResponses
+fn selected_to_read_responses(selected: Option<SelectedSeries>) -> Vec<Result<ReadResponse>> {
+    selected
+        .map(|selected| selected.finish())
+        .unwrap_or_default()
+        .into_iter()
+        .map(|windowed_series_set| {
+            windowed_series_set_to_read_response(windowed_series_set).context(ConvertingSeriesSet)
+        })
+        .collect()
+}
+
+/// Aggregates the series of a group together, as one series per
+/// table, and converts the results to ReadResponses
+fn group_to_read_responses(
+    group_aggregate: Option<GroupAggregate>,
+    group_series: Vec<SeriesSet>,
+) -> Vec<Result<ReadResponse>> {
+    let window_aggregate = match group_aggregate {
+        Some(group_aggregate) => group_aggregate.window_aggregate,
+        None => return vec![],
+    };
+
+    let mut tables: BTreeMap<Arc<String>, Vec<SeriesSet>> = BTreeMap::new();
+    for series_set in group_series {
+        tables
+            .entry(Arc::clone(&series_set.table_name))
+            .or_default()
+            .push(series_set);
+    }
+
+    tables
+        .into_iter()
+        .map(|(_, series_sets)| {
+            let windowed_series_set = window_aggregate
+                .apply_to_group(series_sets)
+                .context(ComputingWindowAggregate)?;
+            windowed_series_set_to_read_response(windowed_series_set).context(ConvertingSeriesSet)
+        })
+        .collect()
+}
+
+/// Sends `response` to tx within the limits of `limiter`, also adding
+/// it to `cached_responses` unless the responses are incomplete.
+/// Returns false if nothing more is to be sent, as for
+/// `send_limited_response`.
+async fn send_cacheable_response(
+    tx: &mut mpsc::Sender<Result<ReadResponse, Status>>,
+    response: Result<ReadResponse>,
+    cached_responses: &mut Option<Vec<ReadResponse>>,
+    stats: &QueryStats,
+    limiter: &mut ResultLimiter,
+) -> Result<bool> {
+    let response = response.map_err(|e| Status::internal(e.to_string()));
+    add_cached_response(&response, cached_responses);
+
+    let sent = send_limited_response(tx, response, stats, limiter).await?;
+    // nor results that exceeded the limits of this request, which
+    // may not be those of the next
+    if !sent || limiter.is_truncated() {
+        *cached_responses = None;
+    }
+    Ok(sent)
+}
+
+/// Adds `response` to `cached_responses`, unless it is an error, which
+/// leaves the responses incomplete and not to be cached
+fn add_cached_response(
+    response: &Result<ReadResponse, Status>,
+    cached_responses: &mut Option<Vec<ReadResponse>>,
+) {
+    match (response, cached_responses.as_mut()) {
+        (Ok(response), Some(responses)) => responses.push(response.clone()),
+        // don't cache incomplete results
+        (Err(_), _) => *cached_responses = None,
+        (Ok(_), None) => {}
+    }
+}
+
+/// Spawns a task sending the cached `responses` of a read request to
+/// tx within `limits`, followed by the statistics of the query and
+/// `warnings`
+fn send_cached_responses(
+    mut tx: mpsc::Sender<Result<ReadResponse, Status>>,
+    responses: Vec<ReadResponse>,
+    limits: ReadLimits,
+    warnings: Warnings,
+) {
+    // No chunks are scanned to answer from the cache
+    let stats = QueryStats::default();
+    let mut limiter = limits.limiter();
+    tokio::spawn(async move {
+        for response in responses {
+            match send_limited_response(&mut tx, Ok(response), &stats, &mut limiter).await {
+                Ok(true) if !limiter.is_truncated() => {}
+                Ok(true) => break,
+                Ok(false) | Err(_) => return,
+            }
+        }
+        send_query_stats(&mut tx, &stats, limiter.is_truncated(), &warnings)
             .await
-            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
-            .context(SendingResults)?
+            .ok();
+    });
+}
+
+/// Converts the window, aggregate and fill of a
+/// `read_window_aggregate` request into a `WindowAggregate`. The
+/// `window` is used if specified, otherwise the (nanosecond)
+/// `window_every` and `offset`.
+///
+/// The aggregate computed in each window can be followed by a
+/// derivative or rate, computed from the aggregated values.
+fn convert_window_aggregate(
+    window_every: i64,
+    offset: i64,
+    window: Option<RpcWindow>,
+    aggregate: &[RpcAggregate],
+    fill: Option<RpcFill>,
+    range: Option<&TimestampRange>,
+) -> Result<WindowAggregate> {
+    let (aggregate, transform) = match aggregate {
+        [aggregate] => (convert_aggregate(aggregate)?, None),
+        [aggregate, transform] => (
+            convert_aggregate(aggregate)?,
+            Some(convert_transform(transform)?),
+        ),
+        _ => {
+            return InvalidWindowAggregate {
+                description: format!(
+                    "expected an aggregate, optionally followed by a derivative or rate, got {} aggregates",
+                    aggregate.len()
+                ),
+            }
+            .fail()
+        }
+    };
+
+    let window_aggregate =
+        make_window_aggregate(window_every, offset, window, aggregate, fill, range)?;
+
+    Ok(match transform {
+        Some(transform) => window_aggregate.with_transform(transform),
+        None => window_aggregate,
+    })
+}
+
+/// Creates a `WindowAggregate` computing `aggregate` in each window
+/// of `window` or, if not specified, of the (nanosecond)
+/// `window_every` and `offset`. The windows of `window` are aligned to
+/// its location, if it has one.
+fn make_window_aggregate(
+    window_every: i64,
+    offset: i64,
+    window: Option<RpcWindow>,
+    aggregate: Aggregate,
+    fill: Option<RpcFill>,
+    range: Option<&TimestampRange>,
+) -> Result<WindowAggregate> {
+    let (every, offset, location) = match window {
+        Some(window) => (
+            convert_duration(window.every.as_ref()),
+            convert_duration(window.offset.as_ref()),
+            WindowLocation::load(&window.location).context(InvalidWindowLocation)?,
+        ),
+        None => (
+            WindowDuration::from_nsecs(window_every),
+            WindowDuration::from_nsecs(offset),
+            WindowLocation::Utc,
+        ),
+    };
+
+    let fill = match fill {
+        Some(fill) => convert_fill(&fill)?,
+        None => Fill::None,
+    };
+
+    let range = range.map(|range| storage::predicate::TimestampRange::new(range.start, range.end));
+
+    let window_aggregate = WindowAggregate::try_new(every, offset, aggregate, fill, range)
+        .context(ComputingWindowAggregate)?;
+    Ok(window_aggregate.with_location(location))
+}
+
+/// Converts the aggregate, window and fill of a `read_group` request
+/// into the `GroupAggregate` applied to the series of each group.
+///
+/// Without an aggregate (either unset or `NONE`) the series are
+/// returned as they are, and `None` is returned. Otherwise the
+/// aggregate is computed in each window, or over the whole `range` if
+/// no window is specified. `TOP` and `BOTTOM` select the `k` series
+/// with the highest maximum (or lowest minimum) aggregated values.
+fn convert_group_aggregate(
+    aggregate: Option<RpcAggregate>,
+    window: Option<RpcWindow>,
+    fill: Option<RpcFill>,
+    range: Option<&TimestampRange>,
+) -> Result<Option<GroupAggregate>> {
+    let aggregate = aggregate.filter(|aggregate| aggregate.r#type != AggregateType::None as i32);
+
+    let (aggregate, window) = match (aggregate, window) {
+        (None, None) => return Ok(None),
+        (None, Some(_)) => {
+            return InvalidWindowAggregate {
+                description: "a window requires an aggregate",
+            }
+            .fail()
+        }
+        (Some(aggregate), Some(window)) => (aggregate, window),
+        (Some(aggregate), None) => (aggregate, range_window(range)?),
+    };
+
+    let order = match AggregateType::from_i32(aggregate.r#type) {
+        Some(AggregateType::Top) => Some(Order::Top),
+        Some(AggregateType::Bottom) => Some(Order::Bottom),
+        _ => None,
+    };
+
+    let (window_aggregate, top_n) = match order {
+        Some(order) => {
+            ensure!(
+                aggregate.k > 0,
+                InvalidWindowAggregate {
+                    description: "TOP and BOTTOM require a positive k",
+                }
+            );
+            let top_n = TopN::new(order, aggregate.k as usize);
+            let window_aggregate =
+                make_window_aggregate(0, 0, Some(window), top_n.aggregate(), fill, range)?;
+            (window_aggregate, Some(top_n))
+        }
+        None => {
+            let window_aggregate =
+                convert_window_aggregate(0, 0, Some(window), &[aggregate], fill, range)?;
+            (window_aggregate, None)
+        }
+    };
+
+    Ok(Some(GroupAggregate {
+        window_aggregate,
+        top_n,
+    }))
+}
+
+/// Returns a window that covers all of `range`
+fn range_window(range: Option<&TimestampRange>) -> Result<RpcWindow> {
+    let every = range
+        .and_then(|range| range.end.checked_sub(range.start))
+        .filter(|&every| every > 0)
+        .context(InvalidWindowAggregate {
+            description: "an aggregate without a window requires a bounded range",
+        })?;
+    // align the window with the start of the range
+    let offset = range
+        .map(|range| range.start.rem_euclid(every))
+        .unwrap_or_default();
+
+    let nsecs = |nsecs| RpcDuration {
+        nsecs,
+        months: 0,
+        negative: false,
+    };
+
+    Ok(RpcWindow {
+        every: Some(nsecs(every)),
+        offset: Some(nsecs(offset)),
+        location: String::new(),
+    })
+}
+
+fn convert_duration(duration: Option<&RpcDuration>) -> WindowDuration {
+    match duration {
+        Some(duration) => {
+            let sign = if duration.negative { -1 } else { 1 };
+            if duration.months != 0 {
+                WindowDuration::from_months(sign * duration.months)
+            } else {
+                WindowDuration::from_nsecs(sign * duration.nsecs)
+            }
+        }
+        None => WindowDuration::from_nsecs(0),
     }
-    Ok(())
 }
 
-/// Return fields with optional measurement, timestamp and arbitratry predicates
-async fn measurement_fields_impl<T>(
+fn convert_aggregate(aggregate: &RpcAggregate) -> Result<Aggregate> {
+    match AggregateType::from_i32(aggregate.r#type) {
+        Some(AggregateType::Sum) => Ok(Aggregate::Sum),
+        Some(AggregateType::Count) => Ok(Aggregate::Count),
+        Some(AggregateType::Min) => Ok(Aggregate::Min),
+        Some(AggregateType::Max) => Ok(Aggregate::Max),
+        Some(AggregateType::First) => Ok(Aggregate::First),
+        Some(AggregateType::Last) => Ok(Aggregate::Last),
+        Some(AggregateType::Mean) => Ok(Aggregate::Mean),
+        Some(AggregateType::Top) | Some(AggregateType::Bottom) => InvalidWindowAggregate {
+            description: "TOP and BOTTOM are only supported by read_group",
+        }
+        .fail(),
+        Some(AggregateType::Derivative)
+        | Some(AggregateType::NonNegativeDerivative)
+        | Some(AggregateType::Rate) => InvalidWindowAggregate {
+            description: "derivatives and rates must follow an aggregate",
+        }
+        .fail(),
+        Some(AggregateType::None) | None => InvalidWindowAggregate {
+            description: format!("unsupported aggregate type {}", aggregate.r#type),
+        }
+        .fail(),
+    }
+}
+
+fn convert_transform(transform: &RpcAggregate) -> Result<Transform> {
+    const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+    let unit = match transform.unit {
+        0 => NANOS_PER_SECOND,
+        unit => unit,
+    };
+    ensure!(
+        unit > 0,
+        InvalidWindowAggregate {
+            description: format!("invalid derivative unit {}", unit),
+        }
+    );
+
+    match AggregateType::from_i32(transform.r#type) {
+        Some(AggregateType::Derivative) => Ok(Transform::Derivative { unit }),
+        Some(AggregateType::NonNegativeDerivative) => Ok(Transform::NonNegativeDerivative { unit }),
+        Some(AggregateType::Rate) => Ok(Transform::Rate),
+        _ => InvalidWindowAggregate {
+            description: format!(
+                "expected a derivative or rate to follow the aggregate, got aggregate type {}",
+                transform.r#type
+            ),
+        }
+        .fail(),
+    }
+}
+
+/// Converts the fill of a request. Points frames can not represent
+/// null values, so `fill(null)` is rejected rather than reported as if
+/// the windows had been omitted.
+fn convert_fill(fill: &RpcFill) -> Result<Fill> {
+    match FillType::from_i32(fill.r#type) {
+        Some(FillType::None) => Ok(Fill::None),
+        Some(FillType::Null) => InvalidWindowAggregate {
+            description: "fill(null) is not supported, as points frames can not hold null values",
+        }
+        .fail(),
+        Some(FillType::Value) => Ok(Fill::Value(fill.value)),
+        Some(FillType::Previous) => Ok(Fill::Previous),
+        Some(FillType::Linear) => Ok(Fill::Linear),
+        None => InvalidWindowAggregate {
+            description: format!("unsupported fill type {}", fill.r#type),
+        }
+        .fail(),
+    }
+}
+
+/// Launch async tasks that send the result of executing
+/// read_window_aggregate to `tx`
+///
+/// If `cache` is provided, the responses are cached, keyed by the
+/// predicate and the other parameters of the request provided with
+/// the cache.
+#[allow(clippy::too_many_arguments)]
+async fn read_window_aggregate_impl<T>(
+    tx: mpsc::Sender<Result<ReadResponse, Status>>,
     db_store: Arc<T>,
     executor: Arc<StorageExecutor>,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
     db_name: String,
-    measurement: String,
     range: Option<TimestampRange>,
     rpc_predicate: Option<Predicate>,
-) -> Result<MeasurementFieldsResponse>
+    row_filter: Option<RowFilter>,
+    window_aggregate: WindowAggregate,
+    warnings: Warnings,
+) -> Result<()>
 where
     T: DatabaseStore,
 {
@@ -981,70 +2556,366 @@ where
 
     let predicate = PredicateBuilder::default()
         .set_range(range)
-        .table_option(Some(measurement))
         .rpc_predicate(rpc_predicate)
         .context(ConvertingPredicate {
             rpc_predicate_string,
         })?
         .build();
 
+    let predicate = restrict_rows(predicate, row_filter);
+    debug!(
+        "read_window_aggregate predicate for database {}: {}",
+        db_name, predicate
+    );
+
     let db = db_store
         .db(&db_name)
         .await
         .context(DatabaseNotFound { db_name: &db_name })?;
 
-    let fieldlist_plan = db
-        .field_columns(predicate)
+    let cache = at_generation(cache, db.generation());
+    if let Some(CachedResponses::Read(responses)) = cached_responses(&cache).as_deref() {
+        // the results of window aggregates are not limited
+        send_cached_responses(tx, responses.clone(), ReadLimits::default(), warnings);
+        return Ok(());
+    }
+
+    let pre_aggregated = db
+        .query_pre_aggregated_series(predicate.clone(), &window_aggregate)
         .await
-        .map_err(|e| Error::ListingFields {
+        .map_err(|e| Error::PlanningFilteringSeries {
             db_name: db_name.clone(),
             source: Box::new(e),
         })?;
 
-    let fieldlist =
-        executor
-            .to_fieldlist(fieldlist_plan)
+    if let Some(pre_aggregated) = pre_aggregated {
+        debug!(
+            "read_window_aggregate for database {} answered from pre-aggregates",
+            db_name
+        );
+        let cache = at_generation(cache, pre_aggregated.generation);
+        tokio::spawn(async move {
+            convert_pre_aggregated_series(
+                pre_aggregated.plan,
+                tx,
+                window_aggregate,
+                cache,
+                warnings,
+            )
+            .await
+            .log_if_error("Converting pre-aggregated series")
+        });
+        return Ok(());
+    }
+
+    let series_plan =
+        db.query_series(predicate)
             .await
-            .map_err(|e| Error::ListingFields {
+            .map_err(|e| Error::PlanningFilteringSeries {
                 db_name: db_name.clone(),
                 source: Box::new(e),
             })?;
+    let cache = at_generation(cache, series_plan.generation);
+
+    // Spawn task to aggregate the series sets in windows and convert
+    // them to gRPC results
+    let stats = Arc::new(QueryStats::default());
+    let (tx_series, rx_series) = mpsc::channel(4);
+    let convert_stats = Arc::clone(&stats);
+    tokio::spawn(async move {
+        convert_windowed_series_set(
+            rx_series,
+            tx,
+            window_aggregate,
+            cache,
+            convert_stats,
+            warnings,
+        )
+        .await
+        .log_if_error("Converting windowed series set")
+    });
+
+    // fire up the plans and start the pipeline flowing
+    tokio::spawn(async move {
+        executor
+            .to_series_set(series_plan.plan, tx_series, &stats)
+            .await
+            .map_err(|e| Error::FilteringSeries {
+                db_name: db_name.clone(),
+                source: Box::new(e),
+            })
+            .log_if_error("Running series set plan")
+    });
+
+    Ok(())
+}
+
+/// Receives SeriesSets from rx, aggregates them in the windows of
+/// `window_aggregate`, converts them to ReadResponse and sends them to
+/// tx, followed by the statistics of the query and `warnings`. If all
+/// the responses are sent successfully they are also added to `cache`.
+async fn convert_windowed_series_set(
+    mut rx: mpsc::Receiver<Result<SeriesSet, SeriesSetError>>,
+    mut tx: mpsc::Sender<Result<ReadResponse, Status>>,
+    window_aggregate: WindowAggregate,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
+    stats: Arc<QueryStats>,
+    warnings: Warnings,
+) -> Result<()> {
+    let mut cached_responses = cache.as_ref().map(|_| Vec::new());
+
+    while let Some(series_set) = rx.recv().await {
+        let response = series_set
+            .context(ComputingSeriesSet)
+            .and_then(|series_set| {
+                window_aggregate_to_read_response(&window_aggregate, &series_set)
+            })
+            .map_err(|e| Status::internal(e.to_string()));
+
+        add_cached_response(&response, &mut cached_responses);
+        send_read_response(&mut tx, response, &stats).await?
+    }
+
+    if let Some(responses) = cached_responses {
+        cache_responses(cache, CachedResponses::Read(responses));
+    }
+    send_query_stats(&mut tx, &stats, false, &warnings).await
+}
+
+/// Aggregates the buckets of each of `pre_aggregated` in the windows
+/// of `window_aggregate`, converts them to ReadResponse and sends them
+/// to tx, followed by the statistics of the query and `warnings`. If
+/// all the responses are sent successfully they are also added to
+/// `cache`.
+async fn convert_pre_aggregated_series(
+    pre_aggregated: PreAggregatedSeries,
+    mut tx: mpsc::Sender<Result<ReadResponse, Status>>,
+    window_aggregate: WindowAggregate,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
+    warnings: Warnings,
+) -> Result<()> {
+    let mut cached_responses = cache.as_ref().map(|_| Vec::new());
+    let stats = QueryStats::default();
+    stats.record_chunks(pre_aggregated.chunks_scanned, pre_aggregated.chunks_pruned);
+
+    for series in &pre_aggregated.series {
+        let response = window_aggregate
+            .apply_to_buckets(series)
+            .context(ComputingWindowAggregate)
+            .and_then(|windowed_series_set| {
+                windowed_series_set_to_read_response(windowed_series_set)
+                    .context(ConvertingSeriesSet)
+            })
+            .map_err(|e| Status::internal(e.to_string()));
+
+        add_cached_response(&response, &mut cached_responses);
+        send_read_response(&mut tx, response, &stats).await?
+    }
+
+    if let Some(responses) = cached_responses {
+        cache_responses(cache, CachedResponses::Read(responses));
+    }
+    send_query_stats(&mut tx, &stats, false, &warnings).await
+}
+
+/// Aggregates `series_set` in the windows of `window_aggregate` and
+/// converts the result to a ReadResponse
+fn window_aggregate_to_read_response(
+    window_aggregate: &WindowAggregate,
+    series_set: &SeriesSet,
+) -> Result<ReadResponse> {
+    let windowed_series_set = window_aggregate
+        .apply(series_set)
+        .context(ComputingWindowAggregate)?;
+
+    windowed_series_set_to_read_response(windowed_series_set).context(ConvertingSeriesSet)
+}
+
+/// Return fields with optional measurement, timestamp and arbitratry predicates
+async fn measurement_fields_impl<T>(
+    db_store: Arc<T>,
+    executor: Arc<StorageExecutor>,
+    cache: Option<(Arc<QueryCache>, CacheKey)>,
+    db_name: String,
+    measurement: String,
+    range: Option<TimestampRange>,
+    rpc_predicate: Option<Predicate>,
+    row_filter: Option<RowFilter>,
+) -> Result<MeasurementFieldsResponse>
+where
+    T: DatabaseStore,
+{
+    let rpc_predicate_string = format!("{:?}", rpc_predicate);
+
+    let predicate = PredicateBuilder::default()
+        .set_range(range)
+        .table_option(Some(measurement))
+        .rpc_predicate(rpc_predicate)
+        .context(ConvertingPredicate {
+            rpc_predicate_string,
+        })?
+        .build();
+
+    let predicate = restrict_rows(predicate, row_filter);
+
+    let db = db_store
+        .db(&db_name)
+        .await
+        .context(DatabaseNotFound { db_name: &db_name })?;
+
+    let cache = at_generation(cache, db.generation());
+    if let Some(CachedResponses::MeasurementFields(response)) = cached_responses(&cache).as_deref()
+    {
+        return Ok(response.clone());
+    }
+
+    let fieldlist_plan = db
+        .field_columns(predicate)
+        .await
+        .map_err(|e| Error::ListingFields {
+            db_name: db_name.clone(),
+            source: Box::new(e),
+        })?;
+    let cache = at_generation(cache, fieldlist_plan.generation);
+
+    let fieldlist = executor
+        .to_fieldlist(fieldlist_plan.plan)
+        .await
+        .map_err(|e| Error::ListingFields {
+            db_name: db_name.clone(),
+            source: Box::new(e),
+        })?;
+
+    // And convert the result
+    let response =
+        fieldlist_to_measurement_fields_response(fieldlist).context(ConvertingFieldList)?;
+    cache_responses(cache, CachedResponses::MeasurementFields(response.clone()));
+    Ok(response)
+}
 
-    // And convert the result
-    fieldlist_to_measurement_fields_response(fieldlist).context(ConvertingFieldList)
-}
-
 /// Instantiate a server listening on the specified address
 /// implementing the IOx and Storage gRPC interfaces, the
 /// underlying hyper server instance. Resolves when the server has
-/// shutdown.
+/// shutdown. If `tls` is specified, connections are served over TLS.
+///
+/// The standard health checking and reflection services are served
+/// alongside, without authentication.
+///
+/// The size of request messages, and the number of requests and
+/// connections open at once, are bounded by `limits`.
 pub async fn make_server<T>(
     bind_addr: SocketAddr,
-    storage: Arc<T>,
+    state: Arc<ServerState<T>>,
     executor: Arc<StorageExecutor>,
+    cache: Option<Arc<QueryCache>>,
+    limits: ServerLimits,
+    tls: Option<Arc<tls::ReloadingServerConfig>>,
 ) -> Result<()>
 where
     T: DatabaseStore + 'static,
 {
-    tonic::transport::Server::builder()
-        .add_service(IOxServer::new(GrpcService::new(
-            storage.clone(),
-            executor.clone(),
-        )))
-        .add_service(StorageServer::new(GrpcService::new(
-            storage.clone(),
-            executor.clone(),
-        )))
-        .serve(bind_addr)
+    let ServerState {
+        storage,
+        authorizer,
+        quotas,
+        subscriptions,
+        access,
+        metrics,
+        ..
+    } = state.as_ref();
+    let max_message_bytes = limits.max_message_bytes;
+    let router = tonic::transport::Server::builder()
+        .max_concurrent_streams(limits.max_concurrent_streams)
+        // the spans of each request continue the trace of the caller,
+        // if it sent one
+        .trace_fn(|headers| {
+            let span = info_span!("grpc_request");
+            telemetry::set_remote_parent(&span, headers);
+            span
+        })
+        // requests are authenticated before their messages are read
+        .add_service(GrpcAuthentication::new(
+            MessageSizeLimit::new(
+                IOxServer::new(
+                    GrpcService::new(storage.clone(), executor.clone())
+                        .with_authorizer(authorizer.clone())
+                        .with_quotas(quotas.clone())
+                        .with_subscriptions(subscriptions.clone())
+                        .with_access(access.clone()),
+                ),
+                max_message_bytes,
+            ),
+            authorizer.clone(),
+        ))
+        .add_service(GrpcAuthentication::new(
+            MessageSizeLimit::new(
+                StorageServer::new(
+                    GrpcService::new(storage.clone(), executor.clone())
+                        .with_cache(cache)
+                        .with_authorizer(authorizer.clone())
+                        .with_quotas(quotas.clone())
+                        .with_access(access.clone())
+                        .with_metrics(metrics.clone()),
+                ),
+                max_message_bytes,
+            ),
+            authorizer.clone(),
+        ))
+        .add_service(GrpcAuthentication::new(
+            MessageSizeLimit::new(
+                FlightServiceServer::new(
+                    FlightService::new(storage.clone(), executor)
+                        .with_authorizer(authorizer.clone())
+                        .with_quotas(quotas.clone())
+                        .with_access(access.clone()),
+                ),
+                max_message_bytes,
+            ),
+            authorizer.clone(),
+        ))
+        .add_service(MessageSizeLimit::new(
+            HealthServer::new(HealthService::new(access.clone())),
+            max_message_bytes,
+        ))
+        .add_service(MessageSizeLimit::new(
+            ServerReflectionServer::new(ReflectionService::new()),
+            max_message_bytes,
+        ));
+
+    // connections are counted by client address, so the server accepts
+    // them itself rather than with tonic
+    let listener = TcpListener::bind(bind_addr)
         .await
-        .context(ServerError {})
-        .log_if_error("Running Tonic Server")
+        .context(StartListening { bind_addr })?;
+    let connections = Arc::new(PeerConnections::new(limits.max_connections_per_peer));
+    match tls {
+        Some(server_config) => {
+            let incoming = tls::incoming(listener, server_config);
+            router
+                .serve_with_incoming(limit_connections(incoming, connections))
+                .await
+        }
+        None => {
+            let incoming = futures::stream::unfold(listener, |mut listener| async move {
+                let stream = listener.accept().await.map(|(stream, _)| stream);
+                Some((stream, listener))
+            });
+            router
+                .serve_with_incoming(limit_connections(incoming, connections))
+                .await
+        }
+    }
+    .context(ServerError {})
+    .log_if_error("Running Tonic Server")
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::panic::SendPanicsToTracing;
+    use crate::server::access::AccessMode;
+    use crate::server::quota::Quota;
+    use crate::server::warnings::WARNING_TRAILER;
     use arrow_deps::arrow::datatypes::DataType;
     use std::{
         convert::TryFrom,
@@ -1053,6 +2924,8 @@ mod tests {
     };
     use storage::{
         exec::fieldlist::{Field, FieldList},
+        exec::seriesset::GroupDescription,
+        exec::stats::{CHUNKS_SCANNED, FRAMES_EMITTED},
         exec::FieldListPlan,
         exec::GroupedSeriesSetPlans,
         exec::SeriesSetPlans,
@@ -1060,6 +2933,7 @@ mod tests {
         test::ColumnNamesRequest,
         test::FieldColumnsRequest,
         test::QueryGroupsRequest,
+        test::QueryRequest,
         test::TestDatabaseStore,
         test::{ColumnValuesRequest, QuerySeriesRequest},
     };
@@ -1068,14 +2942,16 @@ mod tests {
 
     use futures::prelude::*;
 
+    use arrow_deps::arrow_flight::{flight_service_client, Ticket};
     use generated_types::{i_ox_client, read_response::frame, storage_client, ReadSource};
     use prost::Message;
 
     type IOxClient = i_ox_client::IOxClient<tonic::transport::Channel>;
     type StorageClient = storage_client::StorageClient<tonic::transport::Channel>;
+    type FlightClient = flight_service_client::FlightServiceClient<tonic::transport::Channel>;
 
     #[tokio::test]
-    async fn test_influxdb_iox_rpc() -> Result<()> {
+    async fn test_influxdb_iox_rpc() -> Result<(), tonic::Status> {
         let mut fixture = Fixture::new(11807)
             .await
             .expect("Connecting to test server");
@@ -1087,18 +2963,291 @@ mod tests {
         };
 
         // Test response from influxdb_iox server
-        let res = fixture.iox_client.get_buckets(org).await;
+        let res = fixture.iox_client.get_buckets(org.clone()).await;
 
         match res {
             Err(e) => {
-                assert_eq!(e.code(), Code::Unimplemented);
-                assert_eq!(e.message(), "get_buckets");
+                assert!(false, "Unexpected iox_client error: {:?}", e);
             }
             Ok(buckets) => {
-                assert!(false, "Unexpected iox_client success: {:?}", buckets);
+                assert!(buckets.into_inner().buckets.is_empty());
             }
         };
 
+        // create a bucket in the org, and one in another org
+        for (org_id, bucket_id) in &[(1337, 42), (7, 43)] {
+            let request = CreateBucketRequest {
+                org_id: *org_id,
+                bucket: Some(Bucket {
+                    id: *bucket_id,
+                    ..Default::default()
+                }),
+            };
+            fixture.iox_client.create_bucket(request).await?;
+        }
+
+        // the bucket is addressed by the ids of a ReadSource
+        let db_name = org_and_bucket_to_database("0000000000000539", "000000000000002a");
+        assert!(fixture.test_storage.db(&db_name).await.is_some());
+
+        let buckets = fixture
+            .iox_client
+            .get_buckets(org)
+            .await?
+            .into_inner()
+            .buckets;
+        assert_eq!(buckets.len(), 1);
+        assert_eq!(buckets[0].org_id, 1337);
+        assert_eq!(buckets[0].id, 42);
+        assert_eq!(buckets[0].name, "000000000000002a");
+
+        let request = CreateBucketRequest {
+            org_id: 0,
+            bucket: Some(Bucket::default()),
+        };
+        let status = fixture.iox_client.create_bucket(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(status.message(), "invalid org id: ID cannot be zero");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_database_management_rpc() -> Result<(), tonic::Status> {
+        let mut fixture = Fixture::new(11907)
+            .await
+            .expect("Connecting to test server");
+
+        let rules = DatabaseRules {
+            retention_period_seconds: Some(3600),
+            ..Default::default()
+        };
+        let database = DatabaseConfig {
+            name: "mydb".into(),
+            rules_json: serde_json::to_string(&rules).unwrap(),
+        };
+
+        fixture
+            .iox_client
+            .create_database(CreateDatabaseRequest {
+                database: Some(database.clone()),
+            })
+            .await?;
+
+        let status = fixture
+            .iox_client
+            .create_database(CreateDatabaseRequest {
+                database: Some(database.clone()),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::AlreadyExists);
+
+        let databases = fixture
+            .iox_client
+            .list_databases(ListDatabasesRequest {})
+            .await?
+            .into_inner()
+            .databases;
+        assert_eq!(databases, vec![database]);
+
+        let rules = DatabaseRules {
+            retention_period_seconds: Some(7200),
+            ..Default::default()
+        };
+        fixture
+            .iox_client
+            .update_database(UpdateDatabaseRequest {
+                database: Some(DatabaseConfig {
+                    name: "mydb".into(),
+                    rules_json: serde_json::to_string(&rules).unwrap(),
+                }),
+            })
+            .await?;
+
+        let database = fixture
+            .iox_client
+            .get_database(GetDatabaseRequest {
+                name: "mydb".into(),
+            })
+            .await?
+            .into_inner()
+            .database
+            .unwrap();
+        let got: DatabaseRules = serde_json::from_str(&database.rules_json).unwrap();
+        assert_eq!(got, rules);
+
+        let status = fixture
+            .iox_client
+            .update_database(UpdateDatabaseRequest {
+                database: Some(DatabaseConfig {
+                    name: "mydb".into(),
+                    rules_json: "not json".into(),
+                }),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        fixture
+            .iox_client
+            .delete_database(DeleteDatabaseRequest {
+                name: "mydb".into(),
+            })
+            .await?;
+        assert!(fixture.test_storage.db("mydb").await.is_none());
+
+        let status = fixture
+            .iox_client
+            .get_database(GetDatabaseRequest {
+                name: "mydb".into(),
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_flight_do_get() -> Result<(), tonic::Status> {
+        use arrow_deps::arrow::{
+            array::{ArrayRef, Int64Array},
+            datatypes::{Field, Schema},
+            record_batch::RecordBatch,
+        };
+
+        // Note we use a unique port. TODO: let the OS pick the port
+        let mut fixture = Fixture::new(11904)
+            .await
+            .expect("Connecting to test server");
+
+        let db_name = "MyOrg_MyBucket";
+
+        let schema = Arc::new(Schema::new(vec![Field::new(
+            "value",
+            DataType::Int64,
+            false,
+        )]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from(vec![1, 2, 3])) as ArrayRef],
+        )
+        .unwrap();
+
+        let test_db = fixture
+            .test_storage
+            .db_or_create(db_name)
+            .await
+            .expect("creating test database");
+        test_db.set_query_values(vec![batch.clone()]).await;
+
+        let sql_query = "select value from cpu";
+        let ticket = Ticket {
+            ticket: serde_json::json!({
+                "database_name": db_name,
+                "sql_query": sql_query,
+            })
+            .to_string()
+            .into_bytes(),
+        };
+
+        let mut response = fixture.flight_client.do_get(ticket).await?.into_inner();
+        let mut flight_data = vec![];
+        while let Some(data) = response.message().await? {
+            flight_data.push(data);
+        }
+
+        // schema followed by one record batch
+        assert_eq!(flight_data.len(), 2);
+        let actual_schema = Schema::try_from(&flight_data[0]).expect("decoding schema");
+        assert_eq!(&actual_schema, schema.as_ref());
+
+        let expected_request = QueryRequest {
+            query: sql_query.to_string(),
+        };
+        assert_eq!(test_db.get_query_request().await, Some(expected_request));
+
+        // unknown databases are reported as not found
+        let ticket = Ticket {
+            ticket: br#"{"database_name": "missing", "sql_query": "select 1"}"#.to_vec(),
+        };
+        let status = fixture.flight_client.do_get(ticket).await.unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        // so are invalid tickets
+        let ticket = Ticket {
+            ticket: b"not json".to_vec(),
+        };
+        let status = fixture.flight_client.do_get(ticket).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        // parameters are bound into the query
+        test_db.set_query_values(vec![batch.clone()]).await;
+        let ticket = Ticket {
+            ticket: serde_json::json!({
+                "database_name": db_name,
+                "sql_query": "select value from cpu where host = $host",
+                "params": {"host": "a"},
+            })
+            .to_string()
+            .into_bytes(),
+        };
+        let mut response = fixture.flight_client.do_get(ticket).await?.into_inner();
+        while response.message().await?.is_some() {}
+
+        let expected_request = QueryRequest {
+            query: "select value from cpu where host = 'a'".to_string(),
+        };
+        assert_eq!(test_db.get_query_request().await, Some(expected_request));
+
+        // the database may instead be addressed by org and bucket metadata
+        test_db.set_query_values(vec![batch]).await;
+        let ticket = Ticket {
+            ticket: br#"{"sql_query": "select 1"}"#.to_vec(),
+        };
+        let mut request = tonic::Request::new(ticket.clone());
+        request
+            .metadata_mut()
+            .insert("org", "MyOrg".parse().unwrap());
+        request
+            .metadata_mut()
+            .insert("bucket", "MyBucket".parse().unwrap());
+        let mut response = fixture.flight_client.do_get(request).await?.into_inner();
+        while response.message().await?.is_some() {}
+
+        let expected_request = QueryRequest {
+            query: "select 1".to_string(),
+        };
+        assert_eq!(test_db.get_query_request().await, Some(expected_request));
+
+        let status = fixture.flight_client.do_get(ticket).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_message_size_limit() -> Result<(), tonic::Status> {
+        // Note we use a unique port. TODO: let the OS pick the port
+        let limits = ServerLimits {
+            max_message_bytes: Some(100),
+            ..Default::default()
+        };
+        let mut fixture = Fixture::with_limits(11906, limits)
+            .await
+            .expect("Connecting to test server");
+
+        // requests with larger messages fail before they are read
+        let ticket = Ticket {
+            ticket: vec![b' '; 1000],
+        };
+        let status = fixture.flight_client.do_get(ticket).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted, "{}", status);
+
+        // and smaller messages are served as usual
+        assert_eq!(HashMap::new(), fixture.storage_client.capabilities().await?);
+
         Ok(())
     }
 
@@ -1597,183 +3746,1275 @@ mod tests {
         for _ in 0usize..100 {
             let request = TestErrorRequest {};
 
-            // Test response from storage server
-            let response = fixture.iox_client.test_error(request).await;
-            assert!(response.is_err(), "Got an error response: {:?}", response);
+            // Test response from storage server
+            let response = fixture.iox_client.test_error(request).await;
+            assert!(response.is_err(), "Got an error response: {:?}", response);
+        }
+
+        // Ensure there are still threads to answer actual client queries
+        assert_eq!(HashMap::new(), fixture.storage_client.capabilities().await?);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_filter() -> Result<(), tonic::Status> {
+        // Note we use a unique port. TODO: let the OS pick the port
+        let mut fixture = Fixture::new(11901)
+            .await
+            .expect("Connecting to test server");
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let partition_id = 1;
+
+        let test_db = fixture
+            .test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+
+        let source = Some(StorageClientWrapper::read_source(
+            db_info.org_id,
+            db_info.bucket_id,
+            partition_id,
+        ));
+
+        let request = ReadFilterRequest {
+            read_source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+        };
+
+        let expected_request = QuerySeriesRequest {
+            predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into()
+        };
+
+        let dummy_series_set_plan = SeriesSetPlans::from(vec![]);
+        test_db.set_query_series_values(dummy_series_set_plan).await;
+
+        let actual_frames = fixture.storage_client.read_filter(request).await?;
+
+        // TODO: encode this in the test case or something
+        let expected_frames: Vec<String> = vec!["0 frames".into()];
+
+        assert_eq!(
+            actual_frames, expected_frames,
+            "unexpected frames returned by query_series",
+        );
+        assert_eq!(
+            test_db.get_query_series_request().await,
+            Some(expected_request),
+            "unexpected request to query_series",
+        );
+
+        // the statistics of the query are returned in the trailers
+        let request = ReadFilterRequest {
+            read_source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+        };
+        test_db
+            .set_query_series_values(SeriesSetPlans::from(vec![]))
+            .await;
+
+        let mut stream = fixture
+            .storage_client
+            .inner
+            .read_filter(request)
+            .await?
+            .into_inner();
+        while stream.message().await?.is_some() {}
+        let trailers = stream.trailers().await?.expect("query statistics");
+        for (name, value) in &[(CHUNKS_SCANNED, "0"), (FRAMES_EMITTED, "0")] {
+            let actual = trailers.get(*name).expect("query statistic").to_str();
+            assert_eq!(actual.unwrap(), *value, "unexpected value of {}", name);
+        }
+
+        // ---
+        // test error
+        // ---
+        let request = ReadFilterRequest {
+            read_source: source.clone(),
+            range: None,
+            predicate: None,
+        };
+
+        // Note we don't set the response on the test database, so we expect an error
+        let response = fixture.storage_client.read_filter(request).await;
+        assert!(response.is_err());
+        let response_string = format!("{:?}", response);
+        let expected_error = "No saved query_series in TestDatabase";
+        assert!(
+            response_string.contains(expected_error),
+            "'{}' did not contain expected content '{}'",
+            response_string,
+            expected_error
+        );
+
+        let expected_request = Some(QuerySeriesRequest {
+            predicate: "Predicate {}".into(),
+        });
+        assert_eq!(test_db.get_query_series_request().await, expected_request);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_group() -> Result<(), tonic::Status> {
+        // Note we use a unique port. TODO: let the OS pick the port
+        let mut fixture = Fixture::new(11902)
+            .await
+            .expect("Connecting to test server");
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let partition_id = 1;
+
+        let test_db = fixture
+            .test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+
+        let source = Some(StorageClientWrapper::read_source(
+            db_info.org_id,
+            db_info.bucket_id,
+            partition_id,
+        ));
+
+        let group = generated_types::read_group_request::Group::None as i32;
+
+        let request = ReadGroupRequest {
+            read_source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+            group_keys: vec![String::from("tag1")],
+            group,
+            aggregate: None,
+            hints: 0,
+            window: None,
+            fill: None,
+        };
+
+        let expected_request = QueryGroupsRequest {
+            predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into(),
+            group_columns: vec![String::from("tag1")],
+        };
+
+        // TODO setup any expected results
+        let dummy_groups_set_plan = GroupedSeriesSetPlans::from(vec![]);
+        test_db.set_query_groups_values(dummy_groups_set_plan).await;
+
+        let actual_frames = fixture.storage_client.read_group(request).await?;
+        let expected_frames: Vec<String> = vec!["0 group frames".into()];
+
+        assert_eq!(
+            actual_frames, expected_frames,
+            "unexpected frames returned by query_groups"
+        );
+        assert_eq!(
+            test_db.get_query_groups_request().await,
+            Some(expected_request),
+            "unexpected request to query_groups"
+        );
+
+        // ---
+        // test error
+        // ---
+        let request = ReadGroupRequest {
+            read_source: source.clone(),
+            range: None,
+            predicate: None,
+            group_keys: vec![],
+            group,
+            aggregate: None,
+            hints: 0,
+            window: None,
+            fill: None,
+        };
+
+        // Note we don't set the response on the test database, so we expect an error
+        let response = fixture.storage_client.read_group(request).await;
+        assert!(response.is_err());
+        let response_string = format!("{:?}", response);
+        let expected_error = "No saved query_groups in TestDatabase";
+        assert!(
+            response_string.contains(expected_error),
+            "'{}' did not contain expected content '{}'",
+            response_string,
+            expected_error
+        );
+
+        let expected_request = Some(QueryGroupsRequest {
+            predicate: "Predicate {}".into(),
+            group_columns: vec![],
+        });
+        assert_eq!(test_db.get_query_groups_request().await, expected_request);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_read_window_aggregate() -> Result<(), tonic::Status> {
+        // Note we use a unique port. TODO: let the OS pick the port
+        let mut fixture = Fixture::new(11905)
+            .await
+            .expect("Connecting to test server");
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let partition_id = 1;
+
+        let test_db = fixture
+            .test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+
+        let source = Some(StorageClientWrapper::read_source(
+            db_info.org_id,
+            db_info.bucket_id,
+            partition_id,
+        ));
+
+        let request = ReadWindowAggregateRequest {
+            read_source: source.clone(),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+            window_every: 10,
+            offset: 0,
+            aggregate: vec![RpcAggregate {
+                r#type: AggregateType::Sum as i32,
+                k: 0,
+                unit: 0,
+            }],
+            window: None,
+            fill: Some(RpcFill {
+                r#type: FillType::Value as i32,
+                value: 0.0,
+            }),
+        };
+
+        let expected_request = QuerySeriesRequest {
+            predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into()
+        };
+
+        let dummy_series_set_plan = SeriesSetPlans::from(vec![]);
+        test_db.set_query_series_values(dummy_series_set_plan).await;
+
+        let actual_frames = fixture
+            .storage_client
+            .read_window_aggregate(request.clone())
+            .await?;
+        let expected_frames: Vec<String> = vec!["0 aggregate frames".into()];
+
+        assert_eq!(
+            actual_frames, expected_frames,
+            "unexpected frames returned by query_series",
+        );
+        assert_eq!(
+            test_db.get_query_series_request().await,
+            Some(expected_request),
+            "unexpected request to query_series",
+        );
+
+        // the window_every of a request with a window is ignored, which
+        // is returned as a warning in the trailers
+        let window_request = ReadWindowAggregateRequest {
+            window: Some(RpcWindow {
+                every: Some(RpcDuration {
+                    nsecs: 10,
+                    months: 0,
+                    negative: false,
+                }),
+                offset: None,
+                location: String::new(),
+            }),
+            ..request.clone()
+        };
+        test_db
+            .set_query_series_values(SeriesSetPlans::from(vec![]))
+            .await;
+
+        let mut stream = fixture
+            .storage_client
+            .inner
+            .read_window_aggregate(window_request)
+            .await?
+            .into_inner();
+        while stream.message().await?.is_some() {}
+        let trailers = stream.trailers().await?.expect("query statistics");
+        let warnings: Vec<_> = trailers
+            .get_all(WARNING_TRAILER)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            warnings,
+            vec!["ignored_field: window_every and offset are ignored because window is set"]
+        );
+
+        // ---
+        // test error
+        // ---
+        let request = ReadWindowAggregateRequest {
+            aggregate: vec![],
+            ..request
+        };
+
+        let response = fixture.storage_client.read_window_aggregate(request).await;
+        let status = response.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "Invalid window aggregate request: expected an aggregate, optionally followed by a derivative or rate, got 0 aggregates"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_convert_window_aggregate() {
+        let sum = vec![RpcAggregate {
+            r#type: AggregateType::Sum as i32,
+            k: 0,
+            unit: 0,
+        }];
+
+        let window_aggregate = convert_window_aggregate(10, 0, None, &sum, None, None).unwrap();
+        assert_eq!(window_aggregate.aggregate(), Aggregate::Sum);
+        assert_eq!(window_aggregate.fill(), Fill::None);
+
+        let window = RpcWindow {
+            every: Some(RpcDuration {
+                nsecs: 0,
+                months: 1,
+                negative: false,
+            }),
+            offset: None,
+            location: String::new(),
+        };
+        let fill = RpcFill {
+            r#type: FillType::Linear as i32,
+            value: 0.0,
+        };
+        let window_aggregate =
+            convert_window_aggregate(0, 0, Some(window), &sum, Some(fill), None).unwrap();
+        assert_eq!(window_aggregate.fill(), Fill::Linear);
+
+        let fill = RpcFill {
+            r#type: FillType::Null as i32,
+            value: 0.0,
+        };
+        let error = convert_window_aggregate(10, 0, None, &sum, Some(fill), None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid window aggregate request: fill(null) is not supported, as points frames can not hold null values"
+        );
+
+        let none = vec![RpcAggregate {
+            r#type: AggregateType::None as i32,
+            k: 0,
+            unit: 0,
+        }];
+        let error = convert_window_aggregate(10, 0, None, &none, None, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid window aggregate request: unsupported aggregate type 0"
+        );
+
+        let error = convert_window_aggregate(0, 0, None, &sum, None, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Computing window aggregate: Invalid window: every must be a positive number of either months or nanoseconds"
+        );
+
+        let window = RpcWindow {
+            every: Some(RpcDuration {
+                nsecs: 0,
+                months: 1,
+                negative: false,
+            }),
+            offset: None,
+            location: "../passwd".into(),
+        };
+        let error = convert_window_aggregate(0, 0, Some(window), &sum, None, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid window location: Invalid location name '../passwd'"
+        );
+
+        // an aggregate can be followed by a derivative or rate
+        let derivative = vec![
+            sum[0].clone(),
+            RpcAggregate {
+                r#type: AggregateType::Derivative as i32,
+                k: 0,
+                unit: 0,
+            },
+        ];
+        let window_aggregate =
+            convert_window_aggregate(10, 0, None, &derivative, None, None).unwrap();
+        assert_eq!(
+            window_aggregate.transform(),
+            Some(Transform::Derivative {
+                unit: 1_000_000_000
+            })
+        );
+
+        let error =
+            convert_window_aggregate(10, 0, None, &derivative[1..], None, None).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid window aggregate request: derivatives and rates must follow an aggregate"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_group_window_aggregate() {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let service = GrpcService::new(test_storage.clone(), Arc::new(StorageExecutor::default()));
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let test_db = test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+
+        let window = RpcWindow {
+            every: Some(RpcDuration {
+                nsecs: 10,
+                months: 0,
+                negative: false,
+            }),
+            offset: None,
+            location: String::new(),
+        };
+        let request = ReadGroupRequest {
+            read_source: Some(StorageClientWrapper::read_source(
+                db_info.org_id,
+                db_info.bucket_id,
+                1,
+            )),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+            group_keys: vec![String::from("tag1")],
+            group: generated_types::read_group_request::Group::By as i32,
+            aggregate: Some(RpcAggregate {
+                r#type: AggregateType::Mean as i32,
+                k: 0,
+                unit: 0,
+            }),
+            hints: 0,
+            window: Some(window),
+            fill: None,
+        };
+
+        test_db
+            .set_query_groups_values(GroupedSeriesSetPlans::from(vec![]))
+            .await;
+        let response = service
+            .read_group(tonic::Request::new(request.clone()))
+            .await;
+        assert!(
+            response.is_ok(),
+            "unexpected response: {:?}",
+            response.err()
+        );
+
+        let expected_request = Some(QueryGroupsRequest {
+            predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into(),
+            group_columns: vec![String::from("tag1")],
+        });
+        assert_eq!(test_db.get_query_groups_request().await, expected_request);
+
+        // a window requires an aggregate
+        let request = ReadGroupRequest {
+            aggregate: None,
+            ..request
+        };
+        let status = service
+            .read_group(tonic::Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "Invalid window aggregate request: a window requires an aggregate"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_read_group_aggregates_series_of_group() {
+        use arrow_deps::arrow::{
+            array::{Float64Array, Int64Array, StringArray},
+            datatypes::{Field, Schema},
+            record_batch::RecordBatch,
+        };
+
+        // two series of one group, with points in the same windows
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("state", DataType::Utf8, true),
+            Field::new("usage", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec!["a", "a", "b", "b"])),
+                Arc::new(StringArray::from(vec!["MA", "MA", "MA", "MA"])),
+                Arc::new(Float64Array::from(vec![1.0, 2.0, 10.0, 20.0])),
+                Arc::new(Int64Array::from(vec![1, 15, 2, 16])),
+            ],
+        )
+        .unwrap();
+        let series_set = |host: &str, start_row| SeriesSet {
+            table_name: Arc::new("cpu".into()),
+            tags: vec![
+                (Arc::new("host".into()), Arc::new(host.into())),
+                (Arc::new("state".into()), Arc::new("MA".into())),
+            ],
+            timestamp_index: 3,
+            field_indices: Arc::new(vec![2]),
+            start_row,
+            num_rows: 2,
+            batch: batch.clone(),
+        };
+
+        let (mut tx_series, rx_series) = mpsc::channel(4);
+        let items = vec![
+            GroupedSeriesSetItem::GroupStart(GroupDescription {
+                tags: vec![(Arc::new("state".into()), Arc::new("MA".into()))],
+            }),
+            GroupedSeriesSetItem::GroupData(series_set("a", 0)),
+            GroupedSeriesSetItem::GroupData(series_set("b", 2)),
+        ];
+        for item in items {
+            tx_series.send(Ok(item)).await.unwrap();
+        }
+        drop(tx_series);
+
+        let window_aggregate = WindowAggregate::try_new(
+            WindowDuration::from_nsecs(10),
+            WindowDuration::from_nsecs(0),
+            Aggregate::Sum,
+            Fill::None,
+            None,
+        )
+        .unwrap();
+        let group_aggregate = GroupAggregate {
+            window_aggregate,
+            top_n: None,
+        };
+        let (tx, mut rx) = mpsc::channel(8);
+        convert_grouped_series_set(
+            rx_series,
+            tx,
+            None,
+            Some(group_aggregate),
+            Arc::new(QueryStats::default()),
+            ReadLimits::default().limiter(),
+            Warnings::new(),
+        )
+        .await
+        .unwrap();
+
+        let mut frames = vec![];
+        while let Some(Ok(response)) = rx.recv().await {
+            frames.extend(response.frames);
+        }
+
+        // the group frame, then a single series summing both series
+        assert_eq!(frames.len(), 3, "unexpected frames: {:?}", frames);
+        match &frames[1].data {
+            Some(frame::Data::Series(series)) => {
+                let keys: Vec<_> = series.tags.iter().map(|tag| tag.key.clone()).collect();
+                assert!(keys.contains(&b"state".to_vec()), "{:?}", keys);
+                assert!(!keys.contains(&b"host".to_vec()), "{:?}", keys);
+            }
+            other => panic!("unexpected frame: {:?}", other),
+        }
+        match &frames[2].data {
+            Some(frame::Data::FloatPoints(points)) => {
+                assert_eq!(points.timestamps, vec![10, 20]);
+                assert_eq!(points.values, vec![11.0, 22.0]);
+            }
+            other => panic!("unexpected frame: {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_auth() {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let authorizer = Arc::new(Authorizer::new("admin"));
+        let service = GrpcService::new(test_storage.clone(), Arc::new(StorageExecutor::default()))
+            .with_authorizer(authorizer.clone());
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let test_db = test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+
+        let (_, reader) = authorizer
+            .create_token(
+                "reader".into(),
+                vec![auth::Scope {
+                    database: db_info.db_name.clone(),
+                    permission: Permission::Read,
+                    filter: None,
+                }],
+            )
+            .unwrap();
+        let reader = format!("Token {}", reader);
+
+        let request = |bucket_id: u64, authorization: Option<&str>| {
+            let mut request = tonic::Request::new(ReadFilterRequest {
+                read_source: Some(StorageClientWrapper::read_source(
+                    db_info.org_id,
+                    bucket_id,
+                    1,
+                )),
+                range: None,
+                predicate: None,
+            });
+            if let Some(authorization) = authorization {
+                request
+                    .metadata_mut()
+                    .insert("authorization", authorization.parse().unwrap());
+            }
+            request
+        };
+
+        let status = service
+            .read_filter(request(db_info.bucket_id, None))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+
+        test_db
+            .set_query_series_values(SeriesSetPlans::from(vec![]))
+            .await;
+        let response = service
+            .read_filter(request(db_info.bucket_id, Some(&reader)))
+            .await;
+        assert!(response.is_ok(), "unexpected error: {:?}", response.err());
+
+        // the token may not read other buckets
+        let status = service
+            .read_filter(request(789, Some(&reader)))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        // nor create them
+        let mut create_request = tonic::Request::new(CreateBucketRequest {
+            org_id: db_info.org_id,
+            bucket: Some(Bucket {
+                id: 789,
+                ..Default::default()
+            }),
+        });
+        create_request
+            .metadata_mut()
+            .insert("authorization", reader.parse().unwrap());
+        let status = service.create_bucket(create_request).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+
+        // the queries of a token limited to some rows are restricted to them
+        let mut tags = BTreeMap::new();
+        tags.insert("tenant_id".to_string(), "acme".to_string());
+        let (_, tenant) = authorizer
+            .create_token(
+                "tenant".into(),
+                vec![auth::Scope {
+                    database: db_info.db_name.clone(),
+                    permission: Permission::Read,
+                    filter: Some(RowFilter {
+                        measurements: Some(vec!["cpu".to_string()].into_iter().collect()),
+                        tags,
+                    }),
+                }],
+            )
+            .unwrap();
+        let tenant = format!("Token {}", tenant);
+
+        test_db
+            .set_query_series_values(SeriesSetPlans::from(vec![]))
+            .await;
+        let response = service
+            .read_filter(request(db_info.bucket_id, Some(&tenant)))
+            .await;
+        assert!(response.is_ok(), "unexpected error: {:?}", response.err());
+        let expected_request = Some(QuerySeriesRequest {
+            predicate: "Predicate { table_names: cpu exprs: [#tenant_id Eq Utf8(\"acme\")]}".into(),
+        });
+        assert_eq!(test_db.get_query_series_request().await, expected_request);
+
+        let mut names_request = tonic::Request::new(MeasurementNamesRequest {
+            source: Some(StorageClientWrapper::read_source(
+                db_info.org_id,
+                db_info.bucket_id,
+                1,
+            )),
+            range: None,
+            predicate: None,
+        });
+        names_request
+            .metadata_mut()
+            .insert("authorization", tenant.parse().unwrap());
+        let status = service.measurement_names(names_request).await.unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_grpc_quota() {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let quotas = Arc::new(Quotas::new());
+        let service = GrpcService::new(test_storage.clone(), Arc::new(StorageExecutor::default()))
+            .with_quotas(quotas.clone());
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let test_db = test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+        test_db
+            .set_query_series_values(SeriesSetPlans::from(vec![]))
+            .await;
+
+        quotas.set(
+            db_info.db_name.clone(),
+            Quota {
+                queries_per_sec: Some(1),
+                ..Default::default()
+            },
+        );
+
+        let request = || {
+            tonic::Request::new(ReadFilterRequest {
+                read_source: Some(StorageClientWrapper::read_source(
+                    db_info.org_id,
+                    db_info.bucket_id,
+                    1,
+                )),
+                range: None,
+                predicate: None,
+            })
+        };
+
+        let response = service.read_filter(request()).await;
+        assert!(response.is_ok(), "unexpected error: {:?}", response.err());
+
+        let status = service.read_filter(request()).await.unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+
+        let (_, usage) = quotas.get(&db_info.db_name).unwrap();
+        assert_eq!(usage.queries, 1);
+        assert_eq!(usage.rejected_queries, 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_limits() {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let service = GrpcService::new(test_storage.clone(), Arc::new(StorageExecutor::default()));
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let test_db = test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+        test_db
+            .set_query_series_values(SeriesSetPlans::from(vec![]))
+            .await;
+
+        let mut request = tonic::Request::new(ReadFilterRequest {
+            read_source: Some(StorageClientWrapper::read_source(
+                db_info.org_id,
+                db_info.bucket_id,
+                1,
+            )),
+            range: None,
+            predicate: None,
+        });
+        request
+            .metadata_mut()
+            .insert(limits::ON_LIMIT, MetadataValue::from_static("drop"));
+        let status = service.read_filter(request).await.unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "Invalid iox-on-limit metadata 'drop': expected fail or truncate"
+        );
+
+        // sends the start of three groups, and returns what is received
+        async fn read_groups(limits: ReadLimits) -> Vec<Result<ReadResponse, Status>> {
+            let (mut tx_series, rx_series) = mpsc::channel(4);
+            let (tx, mut rx) = mpsc::channel(4);
+            for group in &["a", "b", "c"] {
+                let group = GroupDescription {
+                    tags: vec![(Arc::new("state".into()), Arc::new(group.to_string()))],
+                };
+                tx_series
+                    .send(Ok(GroupedSeriesSetItem::GroupStart(group)))
+                    .await
+                    .unwrap();
+            }
+            drop(tx_series);
+
+            let stats = Arc::new(QueryStats::default());
+            convert_grouped_series_set(
+                rx_series,
+                tx,
+                None,
+                None,
+                stats,
+                limits.limiter(),
+                Warnings::new(),
+            )
+            .await
+            .unwrap();
+
+            let mut responses = vec![];
+            while let Some(response) = rx.recv().await {
+                responses.push(response);
+            }
+            responses
         }
 
-        // Ensure there are still threads to answer actual client queries
-        assert_eq!(HashMap::new(), fixture.storage_client.capabilities().await?);
+        let unlimited = read_groups(ReadLimits::default()).await;
+        assert_eq!(unlimited.len(), 4);
+        let trailers = unlimited[3].as_ref().unwrap_err().metadata();
+        assert!(trailers.get(limits::PARTIAL_RESULT).is_none());
 
-        Ok(())
+        let group_bytes = unlimited[0].as_ref().unwrap().frames[0].encoded_len();
+        let byte_limits = ReadLimits {
+            max_frame_bytes: Some(group_bytes * 2),
+            ..Default::default()
+        };
+
+        // the request fails once the results exceed the limit
+        let failed = read_groups(byte_limits).await;
+        assert_eq!(failed.len(), 3);
+        let status = failed[2].as_ref().unwrap_err();
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        assert_eq!(
+            status.message(),
+            format!(
+                "Query results exceeded the limit of {} bytes of frames",
+                group_bytes * 2
+            )
+        );
+
+        // or returns the results within the limit, marked as partial
+        let truncated = read_groups(ReadLimits {
+            truncate: true,
+            ..byte_limits
+        })
+        .await;
+        assert_eq!(truncated.len(), 3);
+        let status = truncated[2].as_ref().unwrap_err();
+        assert_eq!(status.code(), Code::Ok);
+        let trailers = status.metadata();
+        for (name, value) in &[(limits::PARTIAL_RESULT, "true"), (FRAMES_EMITTED, "2")] {
+            let actual = trailers.get(*name).expect("trailer").to_str();
+            assert_eq!(actual.unwrap(), *value, "unexpected value of {}", name);
+        }
     }
 
     #[tokio::test]
-    async fn test_read_filter() -> Result<(), tonic::Status> {
-        // Note we use a unique port. TODO: let the OS pick the port
-        let mut fixture = Fixture::new(11901)
-            .await
-            .expect("Connecting to test server");
+    async fn test_grpc_access_mode() {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let access = Arc::new(Access::new(AccessMode::IngestOnly));
+        let service = GrpcService::new(test_storage.clone(), Arc::new(StorageExecutor::default()))
+            .with_access(access.clone());
 
         let db_info = OrgAndBucket::new(123, 456);
-        let partition_id = 1;
-
-        let test_db = fixture
-            .test_storage
+        let test_db = test_storage
             .db_or_create(&db_info.db_name)
             .await
             .expect("creating test database");
+        test_db
+            .set_query_series_values(SeriesSetPlans::from(vec![]))
+            .await;
 
-        let source = Some(StorageClientWrapper::read_source(
-            db_info.org_id,
-            db_info.bucket_id,
-            partition_id,
-        ));
+        let request = || {
+            tonic::Request::new(ReadFilterRequest {
+                read_source: Some(StorageClientWrapper::read_source(
+                    db_info.org_id,
+                    db_info.bucket_id,
+                    1,
+                )),
+                range: None,
+                predicate: None,
+            })
+        };
 
-        let request = ReadFilterRequest {
-            read_source: source.clone(),
+        let status = service.read_filter(request()).await.unwrap_err();
+        assert_eq!(status.code(), Code::Unavailable);
+        assert_eq!(
+            status.message(),
+            "Server is ingest_only: queries are not accepted"
+        );
+
+        access.set(AccessMode::ReadOnly);
+        let response = service.read_filter(request()).await;
+        assert!(response.is_ok(), "unexpected error: {:?}", response.err());
+    }
+
+    #[tokio::test]
+    async fn test_read_gaps() {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let service = GrpcService::new(test_storage.clone(), Arc::new(StorageExecutor::default()));
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let test_db = test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+
+        let request = ReadGapsRequest {
+            read_source: Some(StorageClientWrapper::read_source(
+                db_info.org_id,
+                db_info.bucket_id,
+                1,
+            )),
             range: make_timestamp_range(150, 200),
             predicate: make_state_ma_predicate(),
+            threshold: 10,
         };
 
-        let expected_request = QuerySeriesRequest {
-            predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into()
+        test_db
+            .set_query_series_values(SeriesSetPlans::from(vec![]))
+            .await;
+        let mut rx = service
+            .read_gaps(tonic::Request::new(request.clone()))
+            .await
+            .expect("read_gaps succeeds")
+            .into_inner();
+        assert!(rx.recv().await.is_none(), "no series, so no gaps");
+
+        // no field values are read
+        let expected_request = Some(QuerySeriesRequest {
+            predicate: "Predicate { field_columns:  exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into(),
+        });
+        assert_eq!(test_db.get_query_series_request().await, expected_request);
+
+        let request = ReadGapsRequest {
+            threshold: -1,
+            ..request
         };
+        let status = service
+            .read_gaps(tonic::Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "Invalid gap threshold -1: must not be negative"
+        );
+    }
 
-        let dummy_series_set_plan = SeriesSetPlans::from(vec![]);
-        test_db.set_query_series_values(dummy_series_set_plan).await;
+    #[tokio::test]
+    async fn test_subscribe() {
+        use crate::server::subscriptions::{Format, Sink, SubscriptionConfig};
+
+        let subscriptions = Arc::new(Subscriptions::new());
+        subscriptions
+            .create(
+                "cpu_changes",
+                SubscriptionConfig {
+                    db_name: "mydb".to_string(),
+                    measurements: vec!["cpu".to_string()],
+                    format: Format::LineProtocol,
+                    sink: Sink::Grpc,
+                },
+            )
+            .unwrap();
+        let service = GrpcService::new(
+            Arc::new(TestDatabaseStore::new()),
+            Arc::new(StorageExecutor::default()),
+        )
+        .with_subscriptions(subscriptions.clone());
 
-        let actual_frames = fixture.storage_client.read_filter(request).await?;
+        let publish = |lp: &str| {
+            let lines: Vec<_> = influxdb_line_protocol::parse_lines(lp)
+                .map(|l| l.unwrap())
+                .collect();
+            subscriptions.publish("mydb", &lines);
+        };
+        publish("cpu,host=a usage=1 10\nmem free=2i 10");
 
-        // TODO: encode this in the test case or something
-        let expected_frames: Vec<String> = vec!["0 frames".into()];
+        let request = SubscribeRequest {
+            name: "cpu_changes".to_string(),
+            after_sequence: 0,
+        };
+        let mut rx = service
+            .subscribe(tonic::Request::new(request.clone()))
+            .await
+            .expect("subscribe succeeds")
+            .into_inner();
+
+        let response = rx.recv().await.unwrap().unwrap();
+        assert_eq!(response.sequence, 1);
+        assert_eq!(response.db_name, "mydb");
+        assert_eq!(response.measurement, "cpu");
+        assert_eq!(response.format, SubscriptionFormat::LineProtocol as i32);
+        assert_eq!(response.data, b"cpu,host=a usage=1 10\n".to_vec());
+
+        // messages published while subscribed are streamed
+        publish("cpu,host=b usage=3 20");
+        let response = rx.recv().await.unwrap().unwrap();
+        assert_eq!(response.sequence, 2);
+
+        // resubscribing acknowledges the messages processed
+        let request = SubscribeRequest {
+            after_sequence: 1,
+            ..request
+        };
+        let mut rx = service
+            .subscribe(tonic::Request::new(request))
+            .await
+            .expect("subscribe succeeds")
+            .into_inner();
+        assert_eq!(rx.recv().await.unwrap().unwrap().sequence, 2);
+        assert_eq!(subscriptions.get("cpu_changes").unwrap().pending, 1);
+
+        let request = SubscribeRequest {
+            name: "missing".to_string(),
+            after_sequence: 0,
+        };
+        let status = service
+            .subscribe(tonic::Request::new(request))
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "Subscription missing not found");
+    }
+
+    #[test]
+    fn test_convert_group_aggregate() {
+        let range = TimestampRange {
+            start: 150,
+            end: 200,
+        };
+        let aggregate = |aggregate_type: AggregateType| {
+            Some(RpcAggregate {
+                r#type: aggregate_type as i32,
+                k: 0,
+                unit: 0,
+            })
+        };
 
+        // without an aggregate, the grouped series are passed through
+        let window_aggregate = convert_group_aggregate(None, None, None, Some(&range)).unwrap();
+        assert!(window_aggregate.is_none());
+        let window_aggregate =
+            convert_group_aggregate(aggregate(AggregateType::None), None, None, Some(&range))
+                .unwrap();
+        assert!(window_aggregate.is_none());
+
+        // an aggregate without a window is computed over the whole range
+        let group_aggregate =
+            convert_group_aggregate(aggregate(AggregateType::Max), None, None, Some(&range))
+                .unwrap()
+                .unwrap();
+        assert_eq!(group_aggregate.window_aggregate.aggregate(), Aggregate::Max);
+        assert!(group_aggregate.top_n.is_none());
+
+        // the bottom series are ranked by their minimum values
+        let bottom = Some(RpcAggregate {
+            r#type: AggregateType::Bottom as i32,
+            k: 3,
+            unit: 0,
+        });
+        let group_aggregate = convert_group_aggregate(bottom, None, None, Some(&range))
+            .unwrap()
+            .unwrap();
+        assert_eq!(group_aggregate.window_aggregate.aggregate(), Aggregate::Min);
+        assert_eq!(group_aggregate.top_n, Some(TopN::new(Order::Bottom, 3)));
+
+        let error =
+            convert_group_aggregate(aggregate(AggregateType::Top), None, None, Some(&range))
+                .unwrap_err();
         assert_eq!(
-            actual_frames, expected_frames,
-            "unexpected frames returned by query_series",
+            error.to_string(),
+            "Invalid window aggregate request: TOP and BOTTOM require a positive k"
         );
+
+        let error =
+            convert_group_aggregate(aggregate(AggregateType::Max), None, None, None).unwrap_err();
         assert_eq!(
-            test_db.get_query_series_request().await,
-            Some(expected_request),
-            "unexpected request to query_series",
+            error.to_string(),
+            "Invalid window aggregate request: an aggregate without a window requires a bounded range"
         );
+    }
 
-        // ---
-        // test error
-        // ---
-        let request = ReadFilterRequest {
-            read_source: source.clone(),
-            range: None,
-            predicate: None,
+    #[tokio::test]
+    async fn test_read_group_cache() -> Result<(), tonic::Status> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let cache = Arc::new(QueryCache::new(1_000_000));
+        let service = GrpcService::new(test_storage.clone(), Arc::new(StorageExecutor::default()))
+            .with_cache(Some(cache.clone()));
+
+        let db_info = OrgAndBucket::new(123, 456);
+        let test_db = test_storage
+            .db_or_create(&db_info.db_name)
+            .await
+            .expect("creating test database");
+
+        let request = ReadGroupRequest {
+            read_source: Some(StorageClientWrapper::read_source(
+                db_info.org_id,
+                db_info.bucket_id,
+                1,
+            )),
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
+            group_keys: vec![String::from("tag1")],
+            group: generated_types::read_group_request::Group::By as i32,
+            aggregate: None,
+            hints: 0,
+            window: None,
+            fill: None,
         };
 
-        // Note we don't set the response on the test database, so we expect an error
-        let response = fixture.storage_client.read_filter(request).await;
-        assert!(response.is_err());
-        let response_string = format!("{:?}", response);
-        let expected_error = "No saved query_series in TestDatabase";
+        // reads all the responses to a read_group request
+        async fn read_group(
+            service: &GrpcService<TestDatabaseStore>,
+            request: ReadGroupRequest,
+        ) -> Result<Vec<ReadResponse>, tonic::Status> {
+            let mut rx = service
+                .read_group(tonic::Request::new(request))
+                .await?
+                .into_inner();
+
+            // the responses end with an `Ok` status holding the
+            // statistics of the query
+            let mut responses = vec![];
+            while let Some(response) = rx.next().await {
+                match response {
+                    Ok(response) => responses.push(response),
+                    Err(status) if status.code() == Code::Ok => break,
+                    Err(status) => return Err(status),
+                }
+            }
+            Ok(responses)
+        }
+
+        test_db
+            .set_query_groups_values(GroupedSeriesSetPlans::from(vec![]))
+            .await;
+        assert!(read_group(&service, request.clone()).await?.is_empty());
+        assert_eq!(cache.len(), 1);
+
+        // The test database returns an error if asked to plan the
+        // query again, so this response must come from the cache
+        assert!(read_group(&service, request.clone()).await?.is_empty());
+
+        // a different request is not answered from the cache
+        let other_request = ReadGroupRequest {
+            group_keys: vec![String::from("tag2")],
+            ..request.clone()
+        };
+        let response = read_group(&service, other_request).await;
         assert!(
-            response_string.contains(expected_error),
-            "'{}' did not contain expected content '{}'",
-            response_string,
-            expected_error
+            format!("{:?}", response).contains("No saved query_groups in TestDatabase"),
+            "unexpected response: {:?}",
+            response
         );
 
-        let expected_request = Some(QuerySeriesRequest {
-            predicate: "Predicate {}".into(),
-        });
-        assert_eq!(test_db.get_query_series_request().await, expected_request);
+        // writing to the database invalidates the cached responses
+        test_db.add_lp_string("h2o,state=MA temp=70.4 150").await;
+        let response = read_group(&service, request).await;
+        assert!(
+            format!("{:?}", response).contains("No saved query_groups in TestDatabase"),
+            "unexpected response: {:?}",
+            response
+        );
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_read_group() -> Result<(), tonic::Status> {
-        // Note we use a unique port. TODO: let the OS pick the port
-        let mut fixture = Fixture::new(11902)
-            .await
-            .expect("Connecting to test server");
+    async fn test_read_cache() -> Result<(), tonic::Status> {
+        let test_storage = Arc::new(TestDatabaseStore::new());
+        let cache = Arc::new(QueryCache::new(1_000_000));
+        let service = GrpcService::new(test_storage.clone(), Arc::new(StorageExecutor::default()))
+            .with_cache(Some(cache.clone()));
 
         let db_info = OrgAndBucket::new(123, 456);
-        let partition_id = 1;
-
-        let test_db = fixture
-            .test_storage
+        let test_db = test_storage
             .db_or_create(&db_info.db_name)
             .await
             .expect("creating test database");
-
         let source = Some(StorageClientWrapper::read_source(
             db_info.org_id,
             db_info.bucket_id,
-            partition_id,
+            1,
         ));
 
-        let group = generated_types::read_group_request::Group::None as i32;
-
-        let request = ReadGroupRequest {
-            read_source: source.clone(),
+        // The test database only returns the saved results once, so the
+        // second response to each request must come from the cache
+        let request = TagKeysRequest {
+            tags_source: source.clone(),
             range: make_timestamp_range(150, 200),
             predicate: make_state_ma_predicate(),
-            group_keys: vec![String::from("tag1")],
-            group,
-            aggregate: None,
-            hints: 0,
-        };
-
-        let expected_request = QueryGroupsRequest {
-            predicate: "Predicate { exprs: [#state Eq Utf8(\"MA\")] range: TimestampRange { start: 150, end: 200 }}".into(),
-            group_columns: vec![String::from("tag1")],
         };
+        test_db.set_column_names(to_string_vec(&["tag1"])).await;
+        for _ in 0..2 {
+            let response = service
+                .tag_keys(tonic::Request::new(request.clone()))
+                .await?
+                .into_inner()
+                .next()
+                .await
+                .expect("tag_keys response")?;
+            assert_eq!(
+                response.values,
+                vec![
+                    b"_field".to_vec(),
+                    b"_measurement".to_vec(),
+                    b"tag1".to_vec()
+                ]
+            );
+        }
+        assert_eq!(cache.len(), 1);
 
-        // TODO setup any expected results
-        let dummy_groups_set_plan = GroupedSeriesSetPlans::from(vec![]);
-        test_db.set_query_groups_values(dummy_groups_set_plan).await;
-
-        let actual_frames = fixture.storage_client.read_group(request).await?;
-        let expected_frames: Vec<String> = vec!["0 group frames".into()];
-
-        assert_eq!(
-            actual_frames, expected_frames,
-            "unexpected frames returned by query_groups"
-        );
-        assert_eq!(
-            test_db.get_query_groups_request().await,
-            Some(expected_request),
-            "unexpected request to query_groups"
-        );
-
-        // ---
-        // test error
-        // ---
-        let request = ReadGroupRequest {
-            read_source: source.clone(),
-            range: None,
-            predicate: None,
-            group_keys: vec![],
-            group,
-            aggregate: None,
-            hints: 0,
+        let request = ReadFilterRequest {
+            read_source: source,
+            range: make_timestamp_range(150, 200),
+            predicate: make_state_ma_predicate(),
         };
+        test_db
+            .set_query_series_values(SeriesSetPlans::from(vec![]))
+            .await;
+        for _ in 0..2 {
+            // there are no series, so the only response is the `Ok`
+            // status holding the statistics of the query
+            let status = service
+                .read_filter(tonic::Request::new(request.clone()))
+                .await?
+                .into_inner()
+                .next()
+                .await
+                .expect("read_filter statistics")
+                .unwrap_err();
+            assert_eq!(status.code(), Code::Ok, "{}", status);
+        }
+        assert_eq!(cache.len(), 2);
 
-        // Note we don't set the response on the test database, so we expect an error
-        let response = fixture.storage_client.read_group(request).await;
-        assert!(response.is_err());
-        let response_string = format!("{:?}", response);
-        let expected_error = "No saved query_groups in TestDatabase";
+        // writing to the database invalidates the cached responses
+        test_db.add_lp_string("h2o,state=MA temp=70.4 150").await;
+        let response = service
+            .read_filter(tonic::Request::new(request))
+            .await
+            .map(|_| ());
         assert!(
-            response_string.contains(expected_error),
-            "'{}' did not contain expected content '{}'",
-            response_string,
-            expected_error
+            format!("{:?}", response).contains("No saved query_series in TestDatabase"),
+            "unexpected response: {:?}",
+            response
         );
 
-        let expected_request = Some(QueryGroupsRequest {
-            predicate: "Predicate {}".into(),
-            group_columns: vec![],
-        });
-        assert_eq!(test_db.get_query_groups_request().await, expected_request);
-
         Ok(())
     }
 
@@ -2104,6 +5345,31 @@ mod tests {
             Ok(vec![s])
         }
 
+        /// Make a request to Storage::read_window_aggregate and do the
+        /// required async dance to flatten the resulting stream
+        async fn read_window_aggregate(
+            &mut self,
+            request: ReadWindowAggregateRequest,
+        ) -> Result<Vec<String>, tonic::Status> {
+            let responses: Vec<_> = self
+                .inner
+                .read_window_aggregate(request)
+                .await?
+                .into_inner()
+                .try_collect()
+                .await?;
+
+            let data_frames: Vec<frame::Data> = responses
+                .into_iter()
+                .flat_map(|r| r.frames)
+                .flat_map(|f| f.data)
+                .collect();
+
+            let s = format!("{} aggregate frames", data_frames.len());
+
+            Ok(vec![s])
+        }
+
         /// Make a request to Storage::measurement_fields and do the
         /// required async dance to flatten the resulting stream to Strings
         async fn measurement_fields(
@@ -2183,6 +5449,7 @@ mod tests {
     struct Fixture {
         iox_client: IOxClient,
         storage_client: StorageClientWrapper,
+        flight_client: FlightClient,
         test_storage: Arc<TestDatabaseStore>,
         _test_executor: Arc<StorageExecutor>,
     }
@@ -2191,6 +5458,16 @@ mod tests {
         /// Start up a test rpc server listening on `port`, returning
         /// a fixture with the test server and clients
         async fn new(port: u16) -> Result<Self, tonic::transport::Error> {
+            Self::with_limits(port, ServerLimits::default()).await
+        }
+
+        /// Start up a test rpc server listening on `port` with
+        /// `limits`, returning a fixture with the test server and
+        /// clients
+        async fn with_limits(
+            port: u16,
+            limits: ServerLimits,
+        ) -> Result<Self, tonic::transport::Error> {
             let test_storage = Arc::new(TestDatabaseStore::new());
             let test_executor = Arc::new(StorageExecutor::default());
 
@@ -2200,16 +5477,25 @@ mod tests {
 
             println!("Starting InfluxDB IOx rpc test server on {:?}", bind_addr);
 
-            let server = make_server(bind_addr, test_storage.clone(), test_executor.clone());
+            let server = make_server(
+                bind_addr,
+                Arc::new(ServerState::for_test(test_storage.clone())),
+                test_executor.clone(),
+                None,
+                limits,
+                None,
+            );
             tokio::task::spawn(server);
 
             let iox_client = connect_to_server::<IOxClient>(bind_addr).await?;
             let storage_client =
                 StorageClientWrapper::new(connect_to_server::<StorageClient>(bind_addr).await?);
+            let flight_client = connect_to_server::<FlightClient>(bind_addr).await?;
 
             Ok(Self {
                 iox_client,
                 storage_client,
+                flight_client,
                 test_storage,
                 _test_executor: test_executor,
             })
@@ -2235,4 +5521,11 @@ mod tests {
             Self::connect(addr).await
         }
     }
+
+    #[tonic::async_trait]
+    impl NewClient for FlightClient {
+        async fn connect(addr: String) -> Result<Self, tonic::transport::Error> {
+            Self::connect(addr).await
+        }
+    }
 }
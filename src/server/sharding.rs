@@ -0,0 +1,184 @@
+//! This module assigns series to shards by consistent hashing, so that
+//! changing the shards of a cluster moves as few series as possible.
+//!
+//! A series is identified by its series key: its measurement and its
+//! tags, sorted by key. Each shard is placed on a ring of 64 bit hashes
+//! at `VIRTUAL_NODES_PER_SHARD` points, derived from its id, and a
+//! series belongs to the shard of the first point at or after the hash
+//! of its series key, wrapping around at the end of the ring.
+//!
+//! Adding a shard to `n` others therefore only moves the series which
+//! land on the points of the new shard, about `1 / (n + 1)` of them,
+//! and removing a shard only moves its own series.
+
+use influxdb_line_protocol::ParsedLine;
+
+/// The number of points each shard is placed at on the ring, which
+/// evens out the share of series each shard gets
+pub const VIRTUAL_NODES_PER_SHARD: u32 = 128;
+
+/// The canonical key of the series of `line` (see
+/// `influxdb_line_protocol::series_key`). Lines of the same series
+/// have the same key regardless of the order their tags were written
+/// in.
+pub fn series_key(line: &ParsedLine<'_>) -> String {
+    let tags = line
+        .series
+        .tag_set
+        .iter()
+        .flatten()
+        .map(|(key, value)| (key.as_str(), value.as_str()));
+    influxdb_line_protocol::series_key(line.series.measurement.as_str(), tags)
+}
+
+/// The hash of `bytes` used to place shards and series on the ring:
+/// the 64 bit FNV-1a hash, which unlike the hasher of the standard
+/// library is the same on every server, with its bits mixed by the
+/// MurmurHash3 finalizer so that similar inputs (such as the names of
+/// the points of a shard) spread over the whole ring
+pub fn hash(bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    let mut hash = bytes.iter().fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+    });
+
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// A consistent hash ring of shards
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashRing {
+    /// The points of the ring, sorted by hash, each with the index of
+    /// its shard in the ids the ring was created from
+    points: Vec<(u64, usize)>,
+}
+
+impl HashRing {
+    /// Creates a ring of the shards with ids `shard_ids`. Where a
+    /// series lands depends only on the ids, not on their order.
+    pub fn new(shard_ids: &[u32]) -> Self {
+        let mut points: Vec<_> = shard_ids
+            .iter()
+            .enumerate()
+            .flat_map(|(index, id)| {
+                (0..VIRTUAL_NODES_PER_SHARD)
+                    .map(move |point| (hash(format!("{}-{}", id, point).as_bytes()), index))
+            })
+            .collect();
+        // ties, however unlikely, are broken by shard id so they do
+        // not depend on the order of the ids either
+        points.sort_unstable_by_key(|&(hash, index)| (hash, shard_ids[index]));
+
+        Self { points }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Returns the index, in the ids the ring was created from, of the
+    /// shard the series with key `series_key` belongs to, or `None` if
+    /// the ring has no shards
+    pub fn shard_index(&self, series_key: &str) -> Option<usize> {
+        if self.points.is_empty() {
+            return None;
+        }
+
+        let hash = hash(series_key.as_bytes());
+        let point = self
+            .points
+            .binary_search_by_key(&hash, |&(point, _)| point)
+            .unwrap_or_else(|point| point);
+        let (_, index) = self.points[point % self.points.len()];
+        Some(index)
+    }
+
+    /// Returns the index of the shard the series of `line` belongs to
+    pub fn shard_index_for(&self, line: &ParsedLine<'_>) -> Option<usize> {
+        self.shard_index(&series_key(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb_line_protocol::parse_lines;
+
+    fn keys(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|i| format!("cpu,host=host{},region=r{}", i, i % 7))
+            .collect()
+    }
+
+    #[test]
+    fn series_key_sorts_tags() {
+        let lines: Vec<_> = parse_lines(
+            "cpu,region=west,host=a usage=1 10\ncpu,host=a,region=west usage=2 20\ncpu usage=3 30",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+
+        assert_eq!(series_key(&lines[0]), "cpu,host=a,region=west");
+        assert_eq!(series_key(&lines[1]), "cpu,host=a,region=west");
+        assert_eq!(series_key(&lines[2]), "cpu");
+    }
+
+    #[test]
+    fn placement_depends_only_on_shard_ids() {
+        let ids = [3, 1, 2];
+        let ring = HashRing::new(&ids);
+        let reversed = HashRing::new(&[2, 1, 3]);
+
+        for key in keys(1000) {
+            let id = ids[ring.shard_index(&key).unwrap()];
+            assert_eq!(id, [2, 1, 3][reversed.shard_index(&key).unwrap()]);
+        }
+        assert_eq!(HashRing::new(&[]).shard_index("cpu"), None);
+    }
+
+    #[test]
+    fn series_are_spread_over_shards() {
+        let ring = HashRing::new(&[1, 2, 3, 4]);
+        let mut counts = [0; 4];
+        for key in keys(10_000) {
+            counts[ring.shard_index(&key).unwrap()] += 1;
+        }
+
+        for count in &counts {
+            assert!(
+                (1_500..3_500).contains(count),
+                "uneven shards: {:?}",
+                counts
+            );
+        }
+    }
+
+    #[test]
+    fn adding_a_shard_moves_only_its_series() {
+        let before_ids = [1, 2, 3, 4];
+        let after_ids = [1, 2, 3, 4, 5];
+        let before = HashRing::new(&before_ids);
+        let after = HashRing::new(&after_ids);
+
+        let keys = keys(10_000);
+        let mut moved = 0;
+        for key in &keys {
+            let from = before_ids[before.shard_index(key).unwrap()];
+            let to = after_ids[after.shard_index(key).unwrap()];
+            if from != to {
+                // a series only ever moves to the new shard
+                assert_eq!(to, 5);
+                moved += 1;
+            }
+        }
+
+        // about a fifth of the series move
+        assert!((1_000..3_000).contains(&moved), "moved {}", moved);
+    }
+}
@@ -0,0 +1,117 @@
+//! This module contains the state the HTTP and gRPC APIs of a server
+//! are served with: its databases and the services shared by the
+//! requests to them.
+
+use std::sync::Arc;
+
+use super::{
+    access::Access, alerts::Alerts, auth::Authorizer, compaction::CompactionScheduler, jobs::Jobs,
+    log_filter::LogFilter, metrics::Metrics, quota::Quotas, replication::Replicator,
+    router::Router, subscriptions::Subscriptions, system_tables::SystemTables,
+};
+
+/// The databases of a server, stored in `storage`, and its services
+pub struct ServerState<T> {
+    pub storage: Arc<T>,
+    /// Authenticates requests and manages API tokens
+    pub authorizer: Arc<Authorizer>,
+    /// The quotas of each database
+    pub quotas: Arc<Quotas>,
+    /// The background jobs of the server
+    pub jobs: Arc<Jobs>,
+    /// Replicates writes to the peers of the server
+    pub replicator: Arc<Replicator>,
+    /// Delivers writes to subscribers
+    pub subscriptions: Arc<Subscriptions>,
+    /// Routes writes to the writers of their shards, when the server
+    /// is a router
+    pub router: Arc<Router>,
+    /// The filter of what the server logs
+    pub log_filter: Arc<LogFilter>,
+    /// The tables describing the server, which queries may read
+    pub system_tables: Arc<SystemTables>,
+    /// Schedules the compaction of fragmented partitions
+    pub compaction: Arc<CompactionScheduler>,
+    /// Whether writes and queries are accepted
+    pub access: Arc<Access>,
+    /// Alerts on the resources of databases crossing their soft limits
+    pub alerts: Arc<Alerts>,
+    /// The metrics the server exports
+    pub metrics: Arc<Metrics>,
+}
+
+#[cfg(test)]
+impl<T> ServerState<T> {
+    /// Returns the state of a server storing its databases in
+    /// `storage`, with the default services other than those set with
+    /// the `with_` methods
+    pub fn for_test(storage: Arc<T>) -> Self {
+        let jobs = Arc::new(Jobs::new());
+        // a log filter which controls no subscriber, for servers whose
+        // tests do not change it
+        let (log_filter, _layer) = LogFilter::new("info").unwrap();
+
+        Self {
+            storage,
+            authorizer: Arc::new(Authorizer::disabled()),
+            quotas: Arc::new(Quotas::new()),
+            jobs: Arc::clone(&jobs),
+            replicator: Arc::new(Replicator::disabled()),
+            subscriptions: Arc::new(Subscriptions::new()),
+            router: Arc::new(Router::default()),
+            log_filter: Arc::new(log_filter),
+            system_tables: Arc::new(SystemTables::new(Arc::clone(&jobs))),
+            compaction: Arc::new(CompactionScheduler::new(Default::default(), jobs)),
+            access: Arc::new(Access::default()),
+            alerts: Arc::new(Alerts::default()),
+            metrics: Arc::new(Metrics::default()),
+        }
+    }
+
+    pub fn with_authorizer(mut self, authorizer: Authorizer) -> Self {
+        self.authorizer = Arc::new(authorizer);
+        self
+    }
+
+    pub fn with_quotas(mut self, quotas: Arc<Quotas>) -> Self {
+        self.quotas = quotas;
+        self
+    }
+
+    /// Tracks jobs in `jobs`, which the system tables and the
+    /// compaction scheduler then report
+    pub fn with_jobs(mut self, jobs: Arc<Jobs>) -> Self {
+        self.system_tables = Arc::new(SystemTables::new(Arc::clone(&jobs)));
+        self.compaction = Arc::new(CompactionScheduler::new(
+            Default::default(),
+            Arc::clone(&jobs),
+        ));
+        self.jobs = jobs;
+        self
+    }
+
+    pub fn with_replicator(mut self, replicator: Arc<Replicator>) -> Self {
+        self.replicator = replicator;
+        self
+    }
+
+    pub fn with_subscriptions(mut self, subscriptions: Arc<Subscriptions>) -> Self {
+        self.subscriptions = subscriptions;
+        self
+    }
+
+    pub fn with_router(mut self, router: Arc<Router>) -> Self {
+        self.router = router;
+        self
+    }
+
+    pub fn with_log_filter(mut self, log_filter: Arc<LogFilter>) -> Self {
+        self.log_filter = log_filter;
+        self
+    }
+
+    pub fn with_access(mut self, access: Arc<Access>) -> Self {
+        self.access = access;
+        self
+    }
+}
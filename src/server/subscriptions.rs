@@ -0,0 +1,713 @@
+//! This module streams the writes accepted by this server to
+//! subscribers, for change data capture.
+//!
+//! A subscription matches the writes to one database, optionally only
+//! those to some of its measurements, and encodes the lines written to
+//! each measurement as a message, in line protocol or as an Arrow IPC
+//! stream. Messages are numbered in order and kept until they are
+//! acknowledged, so they are delivered at least once:
+//!
+//! * Subscriptions with a gRPC sink are read with the `Subscribe`
+//!   RPC, which acknowledges every message up to the sequence number
+//!   it is given and then streams the messages after it. A subscriber
+//!   resumes after a disconnection by subscribing again with the
+//!   sequence number of the last message it processed.
+//! * Subscriptions with a Kafka sink (only available with the `kafka`
+//!   feature) are delivered by a task which produces each message to
+//!   the topic, retrying with backoff, and acknowledges it once the
+//!   broker has.
+//!
+//! Messages are only kept in memory, and at most
+//! `MAX_PENDING_MESSAGES` of them per subscription: beyond that the
+//! oldest are dropped, and counted as such.
+
+#[cfg(feature = "kafka")]
+mod kafka;
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use arrow_deps::arrow::{ipc::writer::StreamWriter, record_batch::RecordBatch};
+use influxdb_line_protocol::{parse_lines, ParsedLine};
+use serde::{Deserialize, Serialize};
+use snafu::{ensure, OptionExt, Snafu};
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Subscription {} not found", name))]
+    SubscriptionNotFound { name: String },
+
+    #[snafu(display(
+        "Invalid subscription name '{}': must not be empty or contain '/'",
+        name
+    ))]
+    InvalidSubscriptionName { name: String },
+
+    #[snafu(display(
+        "Subscription {} has a {} sink, which is not read with the Subscribe RPC",
+        name,
+        sink
+    ))]
+    NotAGrpcSubscription { name: String, sink: &'static str },
+
+    #[snafu(display("Kafka sinks are not supported: the server was built without Kafka support"))]
+    KafkaNotSupported {},
+
+    #[snafu(display("Error creating Kafka producer for subscription {}: {}", name, source))]
+    CreatingKafkaProducer {
+        name: String,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Converts the error into the appropriate tonic status
+    pub fn to_status(&self) -> tonic::Status {
+        match &self {
+            Self::SubscriptionNotFound { .. } => tonic::Status::not_found(self.to_string()),
+            Self::InvalidSubscriptionName { .. }
+            | Self::NotAGrpcSubscription { .. }
+            | Self::KafkaNotSupported { .. } => {
+                tonic::Status::failed_precondition(self.to_string())
+            }
+            Self::CreatingKafkaProducer { .. } => tonic::Status::internal(self.to_string()),
+        }
+    }
+}
+
+/// The most messages kept for a subscription before the oldest are
+/// dropped
+pub const MAX_PENDING_MESSAGES: usize = 10_000;
+
+/// How long a push sink waits before retrying a message for the
+/// first time
+pub const INITIAL_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The longest a push sink waits before retrying a message
+pub const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// How the lines of a message are encoded
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+    /// Line protocol, one line per point
+    LineProtocol,
+    /// An Arrow IPC stream, with a single record batch
+    Arrow,
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Self::LineProtocol
+    }
+}
+
+/// Where the messages of a subscription are delivered
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Sink {
+    /// Read by subscribers with the `Subscribe` RPC
+    Grpc,
+    /// Produced to `topic` on the Kafka cluster at `brokers`, a comma
+    /// separated list of host:port pairs
+    Kafka { brokers: String, topic: String },
+}
+
+impl Sink {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Grpc => "grpc",
+            Self::Kafka { .. } => "kafka",
+        }
+    }
+}
+
+/// The configuration of a subscription
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubscriptionConfig {
+    /// The database whose writes are matched
+    pub db_name: String,
+    /// The measurements whose writes are matched. All are if empty.
+    #[serde(default)]
+    pub measurements: Vec<String>,
+    #[serde(default)]
+    pub format: Format,
+    pub sink: Sink,
+}
+
+impl SubscriptionConfig {
+    fn matches(&self, db_name: &str, measurement: &str) -> bool {
+        self.db_name == db_name
+            && (self.measurements.is_empty() || self.measurements.iter().any(|m| m == measurement))
+    }
+}
+
+/// The lines of one write to one measurement, as delivered to a
+/// subscriber
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub sequence: u64,
+    pub db_name: String,
+    pub measurement: String,
+    pub format: Format,
+    pub data: Vec<u8>,
+}
+
+/// A snapshot of the state of a subscription
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubscriptionInfo {
+    pub name: String,
+    pub config: SubscriptionConfig,
+    /// The number of messages not yet acknowledged
+    pub pending: u64,
+    /// The sequence number of the last message
+    pub last_sequence: u64,
+    /// The sequence number of the last message acknowledged
+    pub acknowledged_sequence: u64,
+    /// The number of messages dropped, unacknowledged, because too
+    /// many were pending
+    pub dropped: u64,
+}
+
+#[derive(Debug)]
+struct Subscription {
+    config: SubscriptionConfig,
+    /// The messages not yet acknowledged, in sequence order
+    messages: VecDeque<Arc<Message>>,
+    last_sequence: u64,
+    acknowledged_sequence: u64,
+    dropped: u64,
+    /// Notifies the readers of the subscription of new messages, and
+    /// that the subscription was deleted or replaced when dropped
+    notify: watch::Sender<u64>,
+    updates: watch::Receiver<u64>,
+}
+
+impl Subscription {
+    fn info(&self, name: &str) -> SubscriptionInfo {
+        SubscriptionInfo {
+            name: name.to_string(),
+            config: self.config.clone(),
+            pending: self.messages.len() as u64,
+            last_sequence: self.last_sequence,
+            acknowledged_sequence: self.acknowledged_sequence,
+            dropped: self.dropped,
+        }
+    }
+
+    fn acknowledge(&mut self, sequence: u64) {
+        let sequence = sequence.min(self.last_sequence);
+        while matches!(self.messages.front(), Some(m) if m.sequence <= sequence) {
+            self.messages.pop_front();
+        }
+        self.acknowledged_sequence = self.acknowledged_sequence.max(sequence);
+    }
+
+    fn messages_after(&self, sequence: u64) -> Vec<Arc<Message>> {
+        self.messages
+            .iter()
+            .filter(|m| m.sequence > sequence)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Delivers messages to an external system, such as a Kafka topic
+#[tonic::async_trait]
+pub trait PushSink: std::fmt::Debug + Send + Sync + 'static {
+    /// Delivers `message`, returning once it has been acknowledged
+    async fn send(&self, message: &Message)
+        -> Result<(), Box<dyn std::error::Error + Send + Sync>>;
+}
+
+/// The subscriptions of the server
+#[derive(Debug, Default)]
+pub struct Subscriptions {
+    subscriptions: Mutex<BTreeMap<String, Subscription>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the subscription `name` with `config`, replacing any
+    /// subscription with that name. The messages pending for the
+    /// subscription replaced are kept, and delivered to the new sink.
+    pub fn create(self: &Arc<Self>, name: &str, config: SubscriptionConfig) -> Result<()> {
+        let push_sink = match &config.sink {
+            Sink::Grpc => None,
+            Sink::Kafka { brokers, topic } => Some(self.kafka_sink(name, brokers, topic)?),
+        };
+        self.create_with_sink(name, config, push_sink)
+    }
+
+    #[cfg(feature = "kafka")]
+    fn kafka_sink(&self, name: &str, brokers: &str, topic: &str) -> Result<Arc<dyn PushSink>> {
+        use snafu::ResultExt;
+
+        let sink = kafka::KafkaSink::new(brokers, topic)
+            .map_err(|e| Box::new(e) as _)
+            .context(CreatingKafkaProducer { name })?;
+        Ok(Arc::new(sink))
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    fn kafka_sink(&self, _name: &str, _brokers: &str, _topic: &str) -> Result<Arc<dyn PushSink>> {
+        KafkaNotSupported {}.fail()
+    }
+
+    /// Creates the subscription `name` with `config`, delivering its
+    /// messages to `push_sink` if there is one
+    fn create_with_sink(
+        self: &Arc<Self>,
+        name: &str,
+        config: SubscriptionConfig,
+        push_sink: Option<Arc<dyn PushSink>>,
+    ) -> Result<()> {
+        ensure!(
+            !name.is_empty() && !name.contains('/'),
+            InvalidSubscriptionName { name }
+        );
+
+        let mut subscriptions = self.subscriptions.lock().expect("mutex poisoned");
+        let (messages, last_sequence, acknowledged_sequence, dropped) =
+            match subscriptions.remove(name) {
+                Some(old) => (
+                    old.messages,
+                    old.last_sequence,
+                    old.acknowledged_sequence,
+                    old.dropped,
+                ),
+                None => (VecDeque::new(), 0, 0, 0),
+            };
+        let (notify, updates) = watch::channel(last_sequence);
+        let subscription = Subscription {
+            config,
+            messages,
+            last_sequence,
+            acknowledged_sequence,
+            dropped,
+            notify,
+            updates,
+        };
+
+        if let Some(sink) = push_sink {
+            tokio::spawn(deliver(
+                Arc::clone(self),
+                name.to_string(),
+                sink,
+                subscription.updates.clone(),
+                acknowledged_sequence,
+            ));
+        }
+
+        info!("Created subscription {}: {:?}", name, subscription.config);
+        subscriptions.insert(name.to_string(), subscription);
+        Ok(())
+    }
+
+    /// Deletes the subscription `name`, and its pending messages
+    pub fn delete(&self, name: &str) -> Result<()> {
+        let mut subscriptions = self.subscriptions.lock().expect("mutex poisoned");
+        subscriptions
+            .remove(name)
+            .context(SubscriptionNotFound { name })?;
+        Ok(())
+    }
+
+    /// Returns the subscription `name`
+    pub fn get(&self, name: &str) -> Result<SubscriptionInfo> {
+        let subscriptions = self.subscriptions.lock().expect("mutex poisoned");
+        let subscription = subscriptions
+            .get(name)
+            .context(SubscriptionNotFound { name })?;
+        Ok(subscription.info(name))
+    }
+
+    /// Returns all subscriptions, in name order
+    pub fn list(&self) -> Vec<SubscriptionInfo> {
+        let subscriptions = self.subscriptions.lock().expect("mutex poisoned");
+        subscriptions
+            .iter()
+            .map(|(name, subscription)| subscription.info(name))
+            .collect()
+    }
+
+    /// Adds a message for the lines written to each measurement of
+    /// `lines` to the subscriptions matching it
+    pub fn publish(&self, db_name: &str, lines: &[ParsedLine<'_>]) {
+        let mut subscriptions = self.subscriptions.lock().expect("mutex poisoned");
+        if !subscriptions
+            .values()
+            .any(|subscription| subscription.config.db_name == db_name)
+        {
+            return;
+        }
+
+        let mut by_measurement: BTreeMap<&str, Vec<&ParsedLine<'_>>> = BTreeMap::new();
+        for line in lines {
+            by_measurement
+                .entry(line.series.measurement.as_str())
+                .or_default()
+                .push(line);
+        }
+
+        // the lines of each measurement are only encoded once in each
+        // format, however many subscriptions match them
+        let mut encoded: HashMap<(&str, Format), Option<Vec<u8>>> = HashMap::new();
+
+        for (name, subscription) in subscriptions.iter_mut() {
+            for (measurement, lines) in &by_measurement {
+                if !subscription.config.matches(db_name, measurement) {
+                    continue;
+                }
+
+                let format = subscription.config.format;
+                let data = encoded.entry((*measurement, format)).or_insert_with(|| {
+                    match encode(format, lines) {
+                        Ok(data) => Some(data),
+                        Err(e) => {
+                            warn!(
+                                "Error encoding write to {} for subscriptions: {}",
+                                measurement, e
+                            );
+                            None
+                        }
+                    }
+                });
+                let data = match data {
+                    Some(data) => data.clone(),
+                    None => continue,
+                };
+
+                subscription.last_sequence += 1;
+                subscription.messages.push_back(Arc::new(Message {
+                    sequence: subscription.last_sequence,
+                    db_name: db_name.to_string(),
+                    measurement: measurement.to_string(),
+                    format,
+                    data,
+                }));
+
+                if subscription.messages.len() > MAX_PENDING_MESSAGES {
+                    subscription.messages.pop_front();
+                    subscription.dropped += 1;
+                    warn!(
+                        "Dropped message of subscription {}: more than {} pending",
+                        name, MAX_PENDING_MESSAGES
+                    );
+                }
+            }
+
+            // there are no readers if the subscription is not being
+            // read, which is fine
+            let _ = subscription.notify.broadcast(subscription.last_sequence);
+        }
+    }
+
+    /// Acknowledges the messages of the gRPC subscription `name` up to
+    /// and including `sequence`, and returns a receiver notified of
+    /// each new message, which closes when the subscription is deleted
+    /// or replaced
+    pub fn subscribe(&self, name: &str, sequence: u64) -> Result<watch::Receiver<u64>> {
+        let mut subscriptions = self.subscriptions.lock().expect("mutex poisoned");
+        let subscription = subscriptions
+            .get_mut(name)
+            .context(SubscriptionNotFound { name })?;
+        ensure!(
+            subscription.config.sink == Sink::Grpc,
+            NotAGrpcSubscription {
+                name,
+                sink: subscription.config.sink.name(),
+            }
+        );
+
+        subscription.acknowledge(sequence);
+        Ok(subscription.updates.clone())
+    }
+
+    /// Acknowledges the messages of subscription `name` up to and
+    /// including `sequence`
+    pub fn acknowledge(&self, name: &str, sequence: u64) -> Result<()> {
+        let mut subscriptions = self.subscriptions.lock().expect("mutex poisoned");
+        let subscription = subscriptions
+            .get_mut(name)
+            .context(SubscriptionNotFound { name })?;
+        subscription.acknowledge(sequence);
+        Ok(())
+    }
+
+    /// Returns the pending messages of subscription `name` after
+    /// `sequence`
+    pub fn messages_after(&self, name: &str, sequence: u64) -> Result<Vec<Arc<Message>>> {
+        let subscriptions = self.subscriptions.lock().expect("mutex poisoned");
+        let subscription = subscriptions
+            .get(name)
+            .context(SubscriptionNotFound { name })?;
+        Ok(subscription.messages_after(sequence))
+    }
+}
+
+/// Delivers the messages of subscription `name` after `sequence` to
+/// `sink` in order, retrying each until it is acknowledged, until the
+/// subscription is deleted or replaced
+async fn deliver(
+    subscriptions: Arc<Subscriptions>,
+    name: String,
+    sink: Arc<dyn PushSink>,
+    mut updates: watch::Receiver<u64>,
+    mut sequence: u64,
+) {
+    while updates.recv().await.is_some() {
+        let messages = match subscriptions.messages_after(&name, sequence) {
+            Ok(messages) => messages,
+            Err(_) => break,
+        };
+
+        for message in messages {
+            let mut backoff = INITIAL_BACKOFF;
+            while let Err(e) = sink.send(&message).await {
+                warn!(
+                    "Error delivering message {} of subscription {}, retrying in {:?}: {}",
+                    message.sequence, name, backoff, e
+                );
+                tokio::time::delay_for(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+
+            sequence = message.sequence;
+            if subscriptions.acknowledge(&name, sequence).is_err() {
+                break;
+            }
+        }
+    }
+
+    info!("Delivery of subscription {} stopped", name);
+}
+
+type EncodeError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Encodes `lines`, which are all to the same measurement, in `format`
+fn encode(format: Format, lines: &[&ParsedLine<'_>]) -> Result<Vec<u8>, EncodeError> {
+    let mut lp = String::new();
+    for line in lines {
+        lp.push_str(&line.to_string());
+        lp.push('\n');
+    }
+
+    match format {
+        Format::LineProtocol => Ok(lp.into_bytes()),
+        Format::Arrow => {
+            let lines = parse_lines(&lp).collect::<Result<Vec<_>, _>>()?;
+            let batches = write_buffer::lines_to_arrow(&lines)?;
+            match batches.into_iter().next() {
+                Some((_, batch)) => encode_arrow(&batch),
+                None => Ok(vec![]),
+            }
+        }
+    }
+}
+
+/// Encodes `batch` as an Arrow IPC stream
+fn encode_arrow(batch: &RecordBatch) -> Result<Vec<u8>, EncodeError> {
+    let mut data = vec![];
+    {
+        let mut writer = StreamWriter::try_new(&mut data, &batch.schema())?;
+        writer.write(batch)?;
+        writer.finish()?;
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb_line_protocol::parse_lines;
+
+    fn config(db_name: &str, measurements: &[&str], format: Format) -> SubscriptionConfig {
+        SubscriptionConfig {
+            db_name: db_name.to_string(),
+            measurements: measurements.iter().map(ToString::to_string).collect(),
+            format,
+            sink: Sink::Grpc,
+        }
+    }
+
+    fn publish(subscriptions: &Subscriptions, db_name: &str, lp: &str) {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        subscriptions.publish(db_name, &lines);
+    }
+
+    fn data(messages: &[Arc<Message>]) -> Vec<(u64, String)> {
+        messages
+            .iter()
+            .map(|m| (m.sequence, String::from_utf8(m.data.clone()).unwrap()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn filters_and_acknowledges_messages() {
+        let subscriptions = Arc::new(Subscriptions::new());
+        subscriptions
+            .create("all", config("mydb", &[], Format::LineProtocol))
+            .unwrap();
+        subscriptions
+            .create("cpu", config("mydb", &["cpu"], Format::LineProtocol))
+            .unwrap();
+
+        publish(
+            &subscriptions,
+            "mydb",
+            "cpu,host=a usage=1 10\nmem free=2i 10\ncpu,host=b usage=3 20",
+        );
+        publish(&subscriptions, "otherdb", "cpu,host=a usage=4 30");
+        publish(&subscriptions, "mydb", "mem free=5i 40");
+
+        assert_eq!(
+            data(&subscriptions.messages_after("all", 0).unwrap()),
+            vec![
+                (
+                    1,
+                    "cpu,host=a usage=1 10\ncpu,host=b usage=3 20\n".to_string()
+                ),
+                (2, "mem free=2i 10\n".to_string()),
+                (3, "mem free=5i 40\n".to_string()),
+            ]
+        );
+        assert_eq!(
+            data(&subscriptions.messages_after("cpu", 0).unwrap()),
+            vec![(
+                1,
+                "cpu,host=a usage=1 10\ncpu,host=b usage=3 20\n".to_string()
+            )]
+        );
+
+        // subscribing again acknowledges the messages processed, and
+        // the rest are delivered again
+        subscriptions.subscribe("all", 2).unwrap();
+        assert_eq!(
+            data(&subscriptions.messages_after("all", 0).unwrap()),
+            vec![(3, "mem free=5i 40\n".to_string())]
+        );
+
+        let info = subscriptions.get("all").unwrap();
+        assert_eq!(info.pending, 1);
+        assert_eq!(info.last_sequence, 3);
+        assert_eq!(info.acknowledged_sequence, 2);
+        assert_eq!(info.dropped, 0);
+
+        subscriptions.delete("cpu").unwrap();
+        let names: Vec<_> = subscriptions.list().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["all"]);
+        assert!(matches!(
+            subscriptions.subscribe("cpu", 0),
+            Err(Error::SubscriptionNotFound { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn arrow_messages() {
+        let subscriptions = Arc::new(Subscriptions::new());
+        subscriptions
+            .create("arrow", config("mydb", &[], Format::Arrow))
+            .unwrap();
+
+        publish(
+            &subscriptions,
+            "mydb",
+            "cpu,host=a usage=1 10\ncpu usage=2 20",
+        );
+
+        let messages = subscriptions.messages_after("arrow", 0).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].measurement, "cpu");
+
+        let reader =
+            arrow_deps::arrow::ipc::reader::StreamReader::try_new(&messages[0].data[..]).unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].num_rows(), 2);
+    }
+
+    #[derive(Debug, Default)]
+    struct TestSink {
+        failures: Mutex<usize>,
+        received: Mutex<Vec<u64>>,
+    }
+
+    #[tonic::async_trait]
+    impl PushSink for TestSink {
+        async fn send(
+            &self,
+            message: &Message,
+        ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err("broker unavailable".into());
+            }
+            self.received.lock().unwrap().push(message.sequence);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn push_sink_retries_until_acknowledged() {
+        let subscriptions = Arc::new(Subscriptions::new());
+        let sink = Arc::new(TestSink {
+            failures: Mutex::new(2),
+            ..Default::default()
+        });
+        let config = SubscriptionConfig {
+            sink: Sink::Kafka {
+                brokers: "localhost:9092".to_string(),
+                topic: "writes".to_string(),
+            },
+            ..config("mydb", &[], Format::LineProtocol)
+        };
+        subscriptions
+            .create_with_sink("push", config, Some(sink.clone()))
+            .unwrap();
+
+        publish(&subscriptions, "mydb", "cpu usage=1 10\nmem free=2i 10");
+
+        for _ in 0..100 {
+            if subscriptions.get("push").unwrap().acknowledged_sequence == 2 {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(20)).await;
+        }
+
+        assert_eq!(*sink.received.lock().unwrap(), vec![1, 2]);
+        assert_eq!(subscriptions.get("push").unwrap().pending, 0);
+        assert!(matches!(
+            subscriptions.subscribe("push", 0),
+            Err(Error::NotAGrpcSubscription { sink: "kafka", .. })
+        ));
+    }
+
+    #[cfg(not(feature = "kafka"))]
+    #[test]
+    fn kafka_requires_feature() {
+        let subscriptions = Arc::new(Subscriptions::new());
+        let config = SubscriptionConfig {
+            sink: Sink::Kafka {
+                brokers: "localhost:9092".to_string(),
+                topic: "writes".to_string(),
+            },
+            ..config("mydb", &[], Format::LineProtocol)
+        };
+        assert!(matches!(
+            subscriptions.create("kafka", config),
+            Err(Error::KafkaNotSupported {})
+        ));
+    }
+}
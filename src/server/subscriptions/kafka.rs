@@ -0,0 +1,76 @@
+//! This module contains the sink which produces the messages of a
+//! subscription to a Kafka topic. Each message is keyed by its
+//! measurement, so the messages for a measurement stay in order within
+//! a partition of the topic, and carries its database, format and
+//! sequence number as headers.
+
+use std::{fmt, time::Duration};
+
+use rdkafka::{
+    config::ClientConfig,
+    error::KafkaError,
+    message::OwnedHeaders,
+    producer::{FutureProducer, FutureRecord},
+};
+
+use super::{Format, Message, PushSink};
+
+/// How long the producer tries to deliver a message before failing it,
+/// after which the subscription retries it
+const MESSAGE_TIMEOUT_MS: &str = "30000";
+
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl fmt::Debug for KafkaSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KafkaSink")
+            .field("topic", &self.topic)
+            .finish()
+    }
+}
+
+impl KafkaSink {
+    /// Creates a sink producing to `topic` on the Kafka cluster at
+    /// `brokers`
+    pub fn new(brokers: &str, topic: &str) -> Result<Self, KafkaError> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", MESSAGE_TIMEOUT_MS)
+            .create()?;
+
+        Ok(Self {
+            producer,
+            topic: topic.to_string(),
+        })
+    }
+}
+
+#[tonic::async_trait]
+impl PushSink for KafkaSink {
+    async fn send(
+        &self,
+        message: &Message,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let format = match message.format {
+            Format::LineProtocol => "line_protocol",
+            Format::Arrow => "arrow",
+        };
+        let headers = OwnedHeaders::new()
+            .add("db_name", &message.db_name)
+            .add("format", format)
+            .add("sequence", &message.sequence.to_string());
+        let record = FutureRecord::to(&self.topic)
+            .key(&message.measurement)
+            .payload(&message.data)
+            .headers(headers);
+
+        self.producer
+            .send(record, Duration::from_secs(0))
+            .await
+            .map_err(|(e, _)| Box::new(e) as _)
+            .map(|_| ())
+    }
+}
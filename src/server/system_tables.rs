@@ -0,0 +1,455 @@
+//! This module describes the state of the server as tables, which SQL
+//! queries can read, join and filter like any other. The tables are in
+//! the `system` schema:
+//!
+//! * `system.chunks` has a row for each chunk of the database
+//! * `system.columns` has the approximate size of each column of each
+//!   chunk of the database
+//! * `system.queries` has the most recent SQL queries run against the
+//!   database, the newest `MAX_QUERIES` across all databases
+//! * `system.operations` has the jobs (see the `jobs` module) working
+//!   on the database
+//!
+//! The tables only describe the database a query is run against, so a
+//! query may only read what a token with read permission on that
+//! database could already see. A database named `system` can not be
+//! referred to by other databases, as its name is taken by the schema.
+//!
+//! ```sql
+//! SELECT partition_key, sum(size) FROM system.chunks GROUP BY partition_key
+//! ```
+
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use arrow_deps::arrow::{
+    array::{ArrayRef, Int64Array, StringArray, UInt32Array, UInt64Array},
+    datatypes::{DataType, Field, Schema},
+    error::ArrowError,
+    record_batch::RecordBatch,
+};
+use serde::Serialize;
+use snafu::{ResultExt, Snafu};
+use storage::Database;
+
+use super::jobs::Jobs;
+use super::query_labels::QueryLabels;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "System table '{}' not found: expected chunks, columns, queries or operations",
+        table_name
+    ))]
+    TableNotFound { table_name: String },
+
+    #[snafu(display("Error building system table '{}': {}", table_name, source))]
+    BuildingTable {
+        table_name: String,
+        source: ArrowError,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The schema qualifying the names of the system tables
+pub const SYSTEM_SCHEMA: &str = "system";
+
+/// The number of queries, across all databases, kept for
+/// `system.queries`
+pub const MAX_QUERIES: usize = 100;
+
+/// A SQL query run against a database
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryRecord {
+    pub db_name: String,
+    pub query_text: String,
+    /// When the query started, in nanoseconds since the epoch
+    pub start_time: i64,
+    /// How long the query took to run, in nanoseconds
+    pub duration_nanos: i64,
+    /// The number of rows the query returned, if it succeeded
+    pub rows: Option<u64>,
+    /// The error the query failed with, if it failed
+    pub error: Option<String>,
+    /// The labels the client attached to the query
+    pub labels: QueryLabels,
+}
+
+/// The source of the system tables: the server's recent queries and
+/// its jobs
+#[derive(Debug)]
+pub struct SystemTables {
+    jobs: Arc<Jobs>,
+    queries: Mutex<VecDeque<QueryRecord>>,
+}
+
+impl SystemTables {
+    pub fn new(jobs: Arc<Jobs>) -> Self {
+        Self {
+            jobs,
+            queries: Default::default(),
+        }
+    }
+
+    /// Records that `query` was run, forgetting the oldest query
+    /// beyond `MAX_QUERIES`
+    pub fn record_query(&self, query: QueryRecord) {
+        let mut queries = self.queries.lock().expect("mutex poisoned");
+        queries.push_back(query);
+        while queries.len() > MAX_QUERIES {
+            queries.pop_front();
+        }
+    }
+
+    /// Returns the recorded queries run against the database `db_name`,
+    /// oldest first
+    pub fn queries(&self, db_name: &str) -> Vec<QueryRecord> {
+        let queries = self.queries.lock().expect("mutex poisoned");
+        queries
+            .iter()
+            .filter(|query| query.db_name == db_name)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the contents of the system table `table_name`, which
+    /// describes the database `db_name`
+    pub async fn table<D: Database>(
+        &self,
+        db_name: &str,
+        db: &D,
+        table_name: &str,
+    ) -> Result<RecordBatch> {
+        let batch = match table_name {
+            "chunks" => chunks_table(db).await,
+            "columns" => columns_table(db).await,
+            "queries" => self.queries_table(db_name),
+            "operations" => self.operations_table(db_name),
+            _ => return TableNotFound { table_name }.fail(),
+        };
+
+        batch.context(BuildingTable { table_name })
+    }
+
+    fn queries_table(&self, db_name: &str) -> Result<RecordBatch, ArrowError> {
+        let queries = self.queries(db_name);
+
+        let schema = Schema::new(vec![
+            Field::new("query_text", DataType::Utf8, false),
+            Field::new("start_time", DataType::Int64, false),
+            Field::new("duration_nanos", DataType::Int64, false),
+            Field::new("rows", DataType::UInt64, true),
+            Field::new("error", DataType::Utf8, true),
+            Field::new("labels", DataType::Utf8, true),
+        ]);
+        let labels: Vec<_> = queries
+            .iter()
+            .map(|q| {
+                if q.labels.is_empty() {
+                    None
+                } else {
+                    Some(q.labels.to_string())
+                }
+            })
+            .collect();
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from(
+                queries
+                    .iter()
+                    .map(|q| q.query_text.as_str())
+                    .collect::<Vec<_>>(),
+            )),
+            Arc::new(Int64Array::from(
+                queries.iter().map(|q| q.start_time).collect::<Vec<_>>(),
+            )),
+            Arc::new(Int64Array::from(
+                queries.iter().map(|q| q.duration_nanos).collect::<Vec<_>>(),
+            )),
+            Arc::new(UInt64Array::from(
+                queries.iter().map(|q| q.rows).collect::<Vec<_>>(),
+            )),
+            Arc::new(StringArray::from(
+                queries
+                    .iter()
+                    .map(|q| q.error.as_deref())
+                    .collect::<Vec<_>>(),
+            )),
+            Arc::new(StringArray::from(
+                labels.iter().map(Option::as_deref).collect::<Vec<_>>(),
+            )),
+        ];
+
+        RecordBatch::try_new(Arc::new(schema), columns)
+    }
+
+    fn operations_table(&self, db_name: &str) -> Result<RecordBatch, ArrowError> {
+        let jobs: Vec<_> = self
+            .jobs
+            .list()
+            .into_iter()
+            .filter(|job| job.description.db_name() == Some(db_name))
+            .collect();
+
+        let schema = Schema::new(vec![
+            Field::new("id", DataType::UInt64, false),
+            Field::new("operation", DataType::Utf8, false),
+            Field::new("description", DataType::Utf8, false),
+            Field::new("status", DataType::Utf8, false),
+            Field::new("error", DataType::Utf8, true),
+            Field::new("progress_done", DataType::UInt64, true),
+            Field::new("progress_total", DataType::UInt64, true),
+            Field::new("start_time", DataType::Int64, false),
+            Field::new("end_time", DataType::Int64, true),
+        ]);
+
+        let descriptions: Vec<_> = jobs
+            .iter()
+            .map(|job| serde_json::to_value(&job.description).unwrap_or_default())
+            .collect();
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(UInt64Array::from(
+                jobs.iter().map(|job| job.id).collect::<Vec<_>>(),
+            )),
+            Arc::new(StringArray::from(
+                descriptions
+                    .iter()
+                    .map(|description| description["type"].as_str().unwrap_or_default())
+                    .collect::<Vec<_>>(),
+            )),
+            Arc::new(StringArray::from(
+                descriptions
+                    .iter()
+                    .map(|description| description.to_string())
+                    .collect::<Vec<_>>(),
+            )),
+            Arc::new(StringArray::from(
+                jobs.iter()
+                    .map(|job| variant_name(&job.status))
+                    .collect::<Vec<_>>(),
+            )),
+            Arc::new(StringArray::from(
+                jobs.iter()
+                    .map(|job| job.error.as_deref())
+                    .collect::<Vec<_>>(),
+            )),
+            Arc::new(UInt64Array::from(
+                jobs.iter()
+                    .map(|job| job.progress.map(|p| p.done))
+                    .collect::<Vec<_>>(),
+            )),
+            Arc::new(UInt64Array::from(
+                jobs.iter()
+                    .map(|job| job.progress.map(|p| p.total))
+                    .collect::<Vec<_>>(),
+            )),
+            Arc::new(Int64Array::from(
+                jobs.iter().map(|job| job.start_time).collect::<Vec<_>>(),
+            )),
+            Arc::new(Int64Array::from(
+                jobs.iter().map(|job| job.end_time).collect::<Vec<_>>(),
+            )),
+        ];
+
+        RecordBatch::try_new(Arc::new(schema), columns)
+    }
+}
+
+async fn chunks_table<D: Database>(db: &D) -> Result<RecordBatch, ArrowError> {
+    let chunks = db.chunks().await;
+
+    let schema = Schema::new(vec![
+        Field::new("partition_key", DataType::Utf8, false),
+        Field::new("id", DataType::UInt32, false),
+        Field::new("storage", DataType::Utf8, false),
+        Field::new("state", DataType::Utf8, false),
+        Field::new("row_count", DataType::UInt64, false),
+        Field::new("size", DataType::UInt64, false),
+        Field::new("min_time", DataType::Int64, true),
+        Field::new("max_time", DataType::Int64, true),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(
+            chunks
+                .iter()
+                .map(|c| c.partition_key.as_str())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt32Array::from(
+            chunks.iter().map(|c| c.id).collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            chunks
+                .iter()
+                .map(|c| variant_name(&c.storage))
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            chunks
+                .iter()
+                .map(|c| variant_name(&c.state))
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt64Array::from(
+            chunks.iter().map(|c| c.row_count).collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt64Array::from(
+            chunks.iter().map(|c| c.size).collect::<Vec<_>>(),
+        )),
+        Arc::new(Int64Array::from(
+            chunks.iter().map(|c| c.min_time).collect::<Vec<_>>(),
+        )),
+        Arc::new(Int64Array::from(
+            chunks.iter().map(|c| c.max_time).collect::<Vec<_>>(),
+        )),
+    ];
+
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+async fn columns_table<D: Database>(db: &D) -> Result<RecordBatch, ArrowError> {
+    let chunks = db.chunks().await;
+    let rows: Vec<_> = chunks
+        .iter()
+        .flat_map(|chunk| {
+            chunk
+                .column_sizes
+                .iter()
+                .map(move |(column_name, size)| (chunk, column_name.as_str(), *size))
+        })
+        .collect();
+
+    let schema = Schema::new(vec![
+        Field::new("partition_key", DataType::Utf8, false),
+        Field::new("chunk_id", DataType::UInt32, false),
+        Field::new("column_name", DataType::Utf8, false),
+        Field::new("size", DataType::UInt64, false),
+    ]);
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from(
+            rows.iter()
+                .map(|(chunk, _, _)| chunk.partition_key.as_str())
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt32Array::from(
+            rows.iter()
+                .map(|(chunk, _, _)| chunk.id)
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(StringArray::from(
+            rows.iter()
+                .map(|(_, column_name, _)| *column_name)
+                .collect::<Vec<_>>(),
+        )),
+        Arc::new(UInt64Array::from(
+            rows.iter().map(|(_, _, size)| *size).collect::<Vec<_>>(),
+        )),
+    ];
+
+    RecordBatch::try_new(Arc::new(schema), columns)
+}
+
+/// The name `value`, a unit enum variant, is serialized as, such as
+/// `mutable_buffer` for `ChunkStorage::MutableBuffer`
+fn variant_name(value: &impl Serialize) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|value| value.as_str().map(ToString::to_string))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::jobs::JobDescription;
+    use arrow_deps::arrow::util::pretty::pretty_format_batches;
+    use influxdb_line_protocol::parse_lines;
+    use write_buffer::Db;
+
+    fn query(db_name: &str, query_text: &str) -> QueryRecord {
+        QueryRecord {
+            db_name: db_name.to_string(),
+            query_text: query_text.to_string(),
+            start_time: 10,
+            duration_nanos: 5,
+            rows: Some(1),
+            error: None,
+            labels: QueryLabels::default(),
+        }
+    }
+
+    #[test]
+    fn queries_are_limited() {
+        let system_tables = SystemTables::new(Arc::new(Jobs::new()));
+        for i in 0..MAX_QUERIES + 5 {
+            system_tables.record_query(query("mydb", &format!("select {}", i)));
+        }
+        system_tables.record_query(query("otherdb", "select 1"));
+
+        let queries = system_tables.queries("mydb");
+        assert_eq!(queries.len(), MAX_QUERIES - 1);
+        assert_eq!(queries[0].query_text, "select 6");
+        assert_eq!(system_tables.queries("otherdb").len(), 1);
+    }
+
+    #[tokio::test]
+    async fn system_tables() {
+        let jobs = Arc::new(Jobs::new());
+        let system_tables = SystemTables::new(Arc::clone(&jobs));
+        let db = Db::new("mydb");
+        let lines: Vec<_> = parse_lines("cpu usage=1 10").map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await.unwrap();
+
+        let mut headers = http::HeaderMap::new();
+        headers.insert(
+            crate::server::query_labels::QUERY_LABELS_HEADER,
+            http::HeaderValue::from_static("user=alice,dashboard=cpu"),
+        );
+        system_tables.record_query(query("mydb", "select * from cpu"));
+        system_tables.record_query(QueryRecord {
+            labels: QueryLabels::from_headers(&headers).unwrap(),
+            ..query("mydb", "select * from mem")
+        });
+        system_tables.record_query(query("otherdb", "select * from mem"));
+        let batch = system_tables.table("mydb", &db, "queries").await.unwrap();
+        let expected = r#"+-------------------+------------+----------------+------+-------+--------------------------+
+| query_text        | start_time | duration_nanos | rows | error | labels                   |
++-------------------+------------+----------------+------+-------+--------------------------+
+| select * from cpu | 10         | 5              | 1    |       |                          |
+| select * from mem | 10         | 5              | 1    |       | dashboard=cpu,user=alice |
++-------------------+------------+----------------+------+-------+--------------------------+
+"#;
+        assert_eq!(pretty_format_batches(&[batch]).unwrap(), expected);
+
+        for db_name in &["mydb", "otherdb"] {
+            let description = JobDescription::ReplayWal {
+                db_name: db_name.to_string(),
+            };
+            jobs.run(description, |_| async { Ok::<_, String>(()) })
+                .await;
+        }
+        let batch = system_tables
+            .table("mydb", &db, "operations")
+            .await
+            .unwrap();
+        assert_eq!(batch.num_rows(), 1);
+        let operation = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(operation.value(0), "replay_wal");
+
+        let batch = system_tables.table("mydb", &db, "chunks").await.unwrap();
+        assert_eq!(batch.num_rows(), 1);
+        let batch = system_tables.table("mydb", &db, "columns").await.unwrap();
+        assert_eq!(batch.num_rows(), db.chunks().await[0].column_sizes.len());
+
+        assert!(matches!(
+            system_tables.table("mydb", &db, "tables").await,
+            Err(Error::TableNotFound { .. })
+        ));
+    }
+}
@@ -0,0 +1,321 @@
+//! This module contains the distributed tracing of the server: the
+//! export of its spans to a tracing backend, and the propagation of
+//! trace context to and from the other services a request passes
+//! through.
+//!
+//! Spans are exported with OpenTelemetry, to the exporter named by the
+//! `traces_exporter` setting (see the `config` module), or the
+//! `INFLUXDB_IOX_TRACES_EXPORTER` environment variable:
+//!
+//! * `none` (the default) exports nothing
+//! * `jaeger` sends spans to a Jaeger agent, configured with the
+//!   `OTEL_EXPORTER_JAEGER_AGENT_HOST` and
+//!   `OTEL_EXPORTER_JAEGER_AGENT_PORT` environment variables
+//! * `otlp` sends spans to an OpenTelemetry collector at the endpoint
+//!   in the `traces_otlp_endpoint` setting, `localhost:4317` by
+//!   default. It is only available if the server is built with the
+//!   `otlp` feature, as its exporter links gRPC's C core
+//!
+//! Trace context is read from and written to the W3C `traceparent`
+//! and `tracestate` headers of HTTP requests and gRPC metadata, so a
+//! request traced by a client, or forwarded from another server,
+//! continues the same trace.
+
+use std::{fmt, str::FromStr};
+
+use http::{header::HeaderName, HeaderMap, HeaderValue};
+use opentelemetry::{
+    global,
+    propagation::{Extractor, Injector},
+    sdk::{propagation::TraceContextPropagator, trace::Tracer},
+    Context,
+};
+use snafu::{ResultExt, Snafu};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+use super::config::{self, Config};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Invalid traces exporter '{}': expected none, jaeger or otlp",
+        exporter
+    ))]
+    InvalidExporter { exporter: String },
+
+    #[snafu(display(
+        "The otlp traces exporter is not supported: the server was built without the otlp feature"
+    ))]
+    OtlpNotSupported {},
+
+    #[snafu(display("Invalid tracing configuration: {}", source))]
+    InvalidConfig { source: config::Error },
+
+    #[snafu(display("Error installing {} exporter: {}", exporter, source))]
+    InstallingExporter {
+        exporter: Exporter,
+        source: Box<dyn std::error::Error + Send + Sync>,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The name the spans of this server are exported under, unless
+/// `traces_service_name` is set
+pub const DEFAULT_SERVICE_NAME: &str = "iox";
+
+/// The OpenTelemetry collector spans are sent to by the `otlp`
+/// exporter, unless `traces_otlp_endpoint` is set
+pub const DEFAULT_OTLP_ENDPOINT: &str = "localhost:4317";
+
+/// Where spans are exported to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exporter {
+    None,
+    Jaeger,
+    Otlp,
+}
+
+impl Default for Exporter {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+impl FromStr for Exporter {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Self::None),
+            "jaeger" => Ok(Self::Jaeger),
+            "otlp" => Ok(Self::Otlp),
+            _ => InvalidExporter { exporter: s }.fail(),
+        }
+    }
+}
+
+impl fmt::Display for Exporter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "none"),
+            Self::Jaeger => write!(f, "jaeger"),
+            Self::Otlp => write!(f, "otlp"),
+        }
+    }
+}
+
+/// How spans are exported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracingConfig {
+    pub exporter: Exporter,
+    pub service_name: String,
+    pub otlp_endpoint: String,
+}
+
+impl TracingConfig {
+    /// Reads the tracing settings described in the module
+    /// documentation from `config`
+    pub fn from_config(config: &Config) -> Result<Self> {
+        let exporter = config
+            .parse("traces_exporter")
+            .context(InvalidConfig)?
+            .unwrap_or_default();
+
+        Ok(Self {
+            exporter,
+            service_name: config
+                .get("traces_service_name")
+                .unwrap_or(DEFAULT_SERVICE_NAME)
+                .to_string(),
+            otlp_endpoint: config
+                .get("traces_otlp_endpoint")
+                .unwrap_or(DEFAULT_OTLP_ENDPOINT)
+                .to_string(),
+        })
+    }
+}
+
+/// Shuts down the exporter when dropped, flushing any spans it has
+/// not yet sent
+pub enum Uninstall {
+    Jaeger(opentelemetry_jaeger::Uninstall),
+    #[cfg(feature = "otlp")]
+    Otlp(opentelemetry_otlp::Uninstall),
+}
+
+impl fmt::Debug for Uninstall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Jaeger(_) => write!(f, "Uninstall(jaeger)"),
+            #[cfg(feature = "otlp")]
+            Self::Otlp(_) => write!(f, "Uninstall(otlp)"),
+        }
+    }
+}
+
+/// Installs the exporter of `config`, returning the tracer spans
+/// should be recorded with, or `None` if spans are not exported. The
+/// exporter runs until the returned `Uninstall` is dropped.
+pub fn install(config: &TracingConfig) -> Result<Option<(Tracer, Uninstall)>> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    match config.exporter {
+        Exporter::None => Ok(None),
+        Exporter::Jaeger => {
+            let (tracer, uninstall) = opentelemetry_jaeger::new_pipeline()
+                .from_env()
+                .with_service_name(&config.service_name)
+                .install()
+                .map_err(|e| Box::new(e) as _)
+                .context(InstallingExporter {
+                    exporter: config.exporter,
+                })?;
+            Ok(Some((tracer, Uninstall::Jaeger(uninstall))))
+        }
+        Exporter::Otlp => install_otlp(config).map(Some),
+    }
+}
+
+#[cfg(feature = "otlp")]
+fn install_otlp(config: &TracingConfig) -> Result<(Tracer, Uninstall)> {
+    let (tracer, uninstall) = opentelemetry_otlp::new_pipeline()
+        .with_endpoint(&config.otlp_endpoint)
+        .with_trace_config(opentelemetry::sdk::trace::config().with_resource(
+            opentelemetry::sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+                "service.name",
+                config.service_name.clone(),
+            )]),
+        ))
+        .install()
+        .map_err(|e| Box::new(e) as _)
+        .context(InstallingExporter {
+            exporter: config.exporter,
+        })?;
+    Ok((tracer, Uninstall::Otlp(uninstall)))
+}
+
+#[cfg(not(feature = "otlp"))]
+fn install_otlp(_config: &TracingConfig) -> Result<(Tracer, Uninstall)> {
+    OtlpNotSupported {}.fail()
+}
+
+/// Reads trace context from HTTP headers or gRPC metadata
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(HeaderName::as_str).collect()
+    }
+}
+
+/// Writes trace context to HTTP headers or gRPC metadata
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl<'a> Injector for HeaderInjector<'a> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(key.as_bytes()),
+            HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+/// Makes `span` a child of the trace context in `headers`, if they
+/// have any, so the span continues the trace of the caller
+pub fn set_remote_parent(span: &Span, headers: &HeaderMap) {
+    let context =
+        global::get_text_map_propagator(|propagator| propagator.extract(&HeaderExtractor(headers)));
+    span.set_parent(&context);
+}
+
+/// Writes the trace context of the current span to `headers`, so the
+/// service they are sent to continues the trace of this server
+pub fn inject_context(headers: &mut HeaderMap) {
+    let context: Context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut HeaderInjector(headers))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_exporter() {
+        assert_eq!("jaeger".parse::<Exporter>().unwrap(), Exporter::Jaeger);
+        assert_eq!("otlp".parse::<Exporter>().unwrap(), Exporter::Otlp);
+        assert_eq!(Exporter::default().to_string(), "none");
+        assert!(matches!(
+            "zipkin".parse::<Exporter>(),
+            Err(Error::InvalidExporter { .. })
+        ));
+    }
+
+    #[test]
+    fn otlp_config() {
+        let config = Config::load(
+            None,
+            &[
+                "traces_exporter=otlp",
+                "traces_service_name=iox-router",
+                "traces_otlp_endpoint=collector:4317",
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            TracingConfig::from_config(&config).unwrap(),
+            TracingConfig {
+                exporter: Exporter::Otlp,
+                service_name: "iox-router".to_string(),
+                otlp_endpoint: "collector:4317".to_string(),
+            }
+        );
+
+        let config = Config::load(None, &["traces_exporter=otlp"]).unwrap();
+        assert_eq!(
+            TracingConfig::from_config(&config).unwrap().otlp_endpoint,
+            DEFAULT_OTLP_ENDPOINT
+        );
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    #[test]
+    fn otlp_requires_feature() {
+        let config = TracingConfig {
+            exporter: Exporter::Otlp,
+            service_name: DEFAULT_SERVICE_NAME.to_string(),
+            otlp_endpoint: DEFAULT_OTLP_ENDPOINT.to_string(),
+        };
+        assert!(matches!(install(&config), Err(Error::OtlpNotSupported {})));
+    }
+
+    #[test]
+    fn extract_trace_context() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            HeaderValue::from_static("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"),
+        );
+
+        let extractor = HeaderExtractor(&headers);
+        assert_eq!(
+            extractor.get("traceparent"),
+            Some("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01")
+        );
+        assert_eq!(extractor.get("tracestate"), None);
+        assert_eq!(extractor.keys(), vec!["traceparent"]);
+
+        let mut injected = HeaderMap::new();
+        HeaderInjector(&mut injected).set("tracestate", "congo=t61rcWkgMzE".to_string());
+        assert_eq!(injected["tracestate"], "congo=t61rcWkgMzE");
+    }
+}
@@ -0,0 +1,519 @@
+//! This module contains the TLS termination of the HTTP and gRPC
+//! servers, so IOx can be exposed without a proxy in front of it.
+//!
+//! The certificate chain and private key are read from PEM files.
+//! When a client CA is configured, clients must present a certificate
+//! signed by it (mutual TLS).
+//!
+//! The certificate files, including the client CA, are checked for
+//! changes periodically, and new connections use the new certificates
+//! once they have been loaded, so certificates can be rotated without
+//! restarting the server.
+
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock,
+    },
+    time::{Duration, SystemTime},
+};
+
+use futures::{Stream, StreamExt};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use tokio::{
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tokio_rustls::{
+    rustls::{
+        internal::pemfile,
+        sign::{self, CertifiedKey},
+        AllowAnyAuthenticatedClient, ClientHello, NoClientAuth, ResolvesServerCert, RootCertStore,
+        ServerConfig,
+    },
+    server::TlsStream,
+    TlsAcceptor,
+};
+use tracing::{debug, info, warn};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unable to read {:?}: {}", path, source))]
+    ReadingFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("No PEM encoded certificates found in {:?}", path))]
+    InvalidCertificate { path: PathBuf },
+
+    #[snafu(display("No PEM encoded PKCS8 or RSA private key found in {:?}", path))]
+    InvalidPrivateKey { path: PathBuf },
+
+    #[snafu(display("Unsupported private key type in {:?}", path))]
+    UnsupportedPrivateKey { path: PathBuf },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// How often the certificate files are checked for changes by default
+pub const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long accepting connections pauses after an error, such as
+/// running out of file descriptors, which would otherwise recur
+/// immediately
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// The ALPN protocols of the HTTP server
+pub const HTTP_PROTOCOLS: &[&[u8]] = &[b"h2", b"http/1.1"];
+
+/// The ALPN protocols of the gRPC server, which requires HTTP/2
+pub const GRPC_PROTOCOLS: &[&[u8]] = &[b"h2"];
+
+/// The files TLS is configured with
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain of the server
+    pub cert_path: PathBuf,
+    /// PEM file with the private key of the server
+    pub key_path: PathBuf,
+    /// PEM file with the CA certificates client certificates must be
+    /// signed by. Client certificates are not requested if `None`.
+    pub client_ca_path: Option<PathBuf>,
+}
+
+/// Resolves the certificate of each TLS handshake to the most
+/// recently loaded certificate, and keeps the most recently loaded
+/// client CA certificates for the server configurations
+pub struct ReloadingCertResolver {
+    config: TlsConfig,
+    state: RwLock<CertState>,
+}
+
+type FilesModified = (Option<SystemTime>, Option<SystemTime>, Option<SystemTime>);
+
+struct CertState {
+    /// The modification times of the certificate, key and client CA
+    /// files when they were loaded
+    modified: FilesModified,
+    certified_key: CertifiedKey,
+    client_roots: Option<RootCertStore>,
+    /// Incremented whenever the client CA certificates are reloaded,
+    /// so server configurations know to verify clients against them
+    client_roots_generation: u64,
+}
+
+impl ReloadingCertResolver {
+    /// Loads the certificate, key and client CA certificates of
+    /// `config`, failing if they are invalid
+    pub fn new(config: &TlsConfig) -> Result<Self> {
+        let config = config.clone();
+
+        let modified = files_modified(&config);
+        let certified_key = load_certified_key(&config.cert_path, &config.key_path)?;
+        let client_roots = config
+            .client_ca_path
+            .as_deref()
+            .map(load_client_roots)
+            .transpose()?;
+
+        Ok(Self {
+            config,
+            state: RwLock::new(CertState {
+                modified,
+                certified_key,
+                client_roots,
+                client_roots_generation: 0,
+            }),
+        })
+    }
+
+    /// Loads the certificate files again if any of them changed since
+    /// they were last loaded, returning true if they were.
+    ///
+    /// If the new files are invalid (for example because only one of
+    /// them was replaced yet) the previous certificates remain in use,
+    /// and loading is retried on the next call.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let modified = files_modified(&self.config);
+        let previous = self.state.read().expect("cert lock poisoned").modified;
+        if modified == previous {
+            return Ok(false);
+        }
+
+        let certified_key = load_certified_key(&self.config.cert_path, &self.config.key_path)?;
+        let client_roots = self
+            .config
+            .client_ca_path
+            .as_deref()
+            .map(load_client_roots)
+            .transpose()?;
+
+        let mut state = self.state.write().expect("cert lock poisoned");
+        if modified.2 != previous.2 {
+            state.client_roots_generation += 1;
+        }
+        state.modified = modified;
+        state.certified_key = certified_key;
+        state.client_roots = client_roots;
+        Ok(true)
+    }
+
+    /// Checks the certificate files for changes every `interval`,
+    /// until the process exits
+    pub fn spawn_reloader(self: Arc<Self>, interval: Duration) {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                ticks.tick().await;
+                match self.reload_if_changed() {
+                    Ok(true) => info!("Reloaded TLS certificate from {:?}", self.config.cert_path),
+                    Ok(false) => {}
+                    Err(e) => warn!("Unable to reload TLS certificate: {}", e),
+                }
+            }
+        });
+    }
+}
+
+impl std::fmt::Debug for ReloadingCertResolver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReloadingCertResolver")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<CertifiedKey> {
+        Some(
+            self.state
+                .read()
+                .expect("cert lock poisoned")
+                .certified_key
+                .clone(),
+        )
+    }
+}
+
+/// Returns the modification times of the certificate, key and client
+/// CA files of `config`
+fn files_modified(config: &TlsConfig) -> FilesModified {
+    (
+        modified(&config.cert_path),
+        modified(&config.key_path),
+        config.client_ca_path.as_deref().and_then(modified),
+    )
+}
+
+/// Returns the modification time of `path`, or `None` if it can not be
+/// read (in which case loading the file reports the error)
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn open(path: &Path) -> Result<BufReader<File>> {
+    let file = File::open(path).context(ReadingFile { path })?;
+    Ok(BufReader::new(file))
+}
+
+/// Loads the certificate chain in `cert_path` and the private key in
+/// `key_path`
+pub fn load_certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey> {
+    let certs = pemfile::certs(&mut open(cert_path)?).unwrap_or_default();
+    ensure!(!certs.is_empty(), InvalidCertificate { path: cert_path });
+
+    // prefer PKCS8 keys, as written by most tools, over RSA keys
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(key_path)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(key_path)?).unwrap_or_default();
+    }
+    let key = match keys.into_iter().next() {
+        Some(key) => key,
+        None => return InvalidPrivateKey { path: key_path }.fail(),
+    };
+
+    let signing_key = sign::any_supported_type(&key)
+        .ok()
+        .context(UnsupportedPrivateKey { path: key_path })?;
+
+    Ok(CertifiedKey::new(certs, Arc::new(signing_key)))
+}
+
+/// Loads the CA certificates in `path`
+fn load_client_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    let (valid, _invalid) = roots.add_pem_file(&mut open(path)?).unwrap_or_default();
+    ensure!(valid > 0, InvalidCertificate { path });
+    Ok(roots)
+}
+
+/// The rustls configuration of a server negotiating a set of
+/// protocols, which is rebuilt when the client CA certificates of its
+/// resolver are reloaded
+pub struct ReloadingServerConfig {
+    resolver: Arc<ReloadingCertResolver>,
+    protocols: Vec<Vec<u8>>,
+    /// The most recent configuration and the generation of the client
+    /// CA certificates it was built with
+    current: Mutex<(u64, Arc<ServerConfig>)>,
+}
+
+impl ReloadingServerConfig {
+    /// Returns the configuration new connections should use
+    pub fn current(&self) -> Arc<ServerConfig> {
+        let mut current = self.current.lock().expect("server config lock poisoned");
+        let state = self.resolver.state.read().expect("cert lock poisoned");
+        if current.0 != state.client_roots_generation {
+            *current = (
+                state.client_roots_generation,
+                build_server_config(&self.resolver, &state, &self.protocols),
+            );
+        }
+        Arc::clone(&current.1)
+    }
+}
+
+impl std::fmt::Debug for ReloadingServerConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ReloadingServerConfig")
+            .field("resolver", &self.resolver)
+            .field("protocols", &self.protocols)
+            .finish()
+    }
+}
+
+/// Creates the rustls configuration of a server negotiating
+/// `protocols`, which presents the certificates of `resolver` and
+/// verifies clients against its client CA certificates
+pub fn server_config(
+    resolver: Arc<ReloadingCertResolver>,
+    protocols: &[&[u8]],
+) -> Arc<ReloadingServerConfig> {
+    let protocols: Vec<_> = protocols.iter().map(|protocol| protocol.to_vec()).collect();
+
+    let current = {
+        let state = resolver.state.read().expect("cert lock poisoned");
+        (
+            state.client_roots_generation,
+            build_server_config(&resolver, &state, &protocols),
+        )
+    };
+
+    Arc::new(ReloadingServerConfig {
+        resolver,
+        protocols,
+        current: Mutex::new(current),
+    })
+}
+
+fn build_server_config(
+    resolver: &Arc<ReloadingCertResolver>,
+    state: &CertState,
+    protocols: &[Vec<u8>],
+) -> Arc<ServerConfig> {
+    let client_verifier = match &state.client_roots {
+        Some(roots) => AllowAnyAuthenticatedClient::new(roots.clone()),
+        None => NoClientAuth::new(),
+    };
+
+    let mut server_config = ServerConfig::new(client_verifier);
+    server_config.cert_resolver = Arc::clone(resolver);
+    server_config.set_protocols(protocols);
+
+    Arc::new(server_config)
+}
+
+/// Accepts connections on `listener`, returning a stream of the
+/// connections that completed a TLS handshake.
+///
+/// Handshakes happen concurrently, so a slow client doesn't delay
+/// the others. Connections which can not be accepted or whose
+/// handshake fails are logged and dropped, so the stream never yields
+/// an error, which would stop the server.
+pub fn incoming(
+    mut listener: TcpListener,
+    server_config: Arc<ReloadingServerConfig>,
+) -> impl Stream<Item = io::Result<TlsStream<TcpStream>>> {
+    let (tx, rx) = mpsc::channel(32);
+    // set once a connection can not be passed on, as the server has
+    // shut down
+    let closed = Arc::new(AtomicBool::new(false));
+
+    tokio::spawn(async move {
+        while !closed.load(Ordering::Relaxed) {
+            let (stream, peer_addr) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Unable to accept TLS connection: {}", e);
+                    tokio::time::delay_for(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+
+            let acceptor = TlsAcceptor::from(server_config.current());
+            let mut tx = tx.clone();
+            let closed = Arc::clone(&closed);
+            tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => {
+                        if tx.send(stream).await.is_err() {
+                            closed.store(true, Ordering::Relaxed);
+                        }
+                    }
+                    Err(e) => debug!("TLS handshake with {} failed: {}", peer_addr, e),
+                }
+            });
+        }
+    });
+
+    rx.map(Ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type TestResult<T = (), E = TestError> = std::result::Result<T, E>;
+
+    /// Writes a new self signed certificate and its key to `cert_path`
+    /// and `key_path`, returning the DER encoded certificate
+    fn write_self_signed(cert_path: &Path, key_path: &Path) -> TestResult<Vec<u8>> {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()])?;
+        std::fs::write(cert_path, cert.serialize_pem()?)?;
+        std::fs::write(key_path, cert.serialize_private_key_pem())?;
+        Ok(cert.serialize_der()?)
+    }
+
+    fn resolved_cert(resolver: &ReloadingCertResolver) -> Vec<u8> {
+        let state = resolver.state.read().unwrap();
+        state.certified_key.cert[0].0.clone()
+    }
+
+    #[test]
+    fn test_load_certified_key() -> TestResult {
+        let dir = test_helpers::tmp_dir()?;
+        let cert_path = dir.path().join("cert.pem");
+        let key_path = dir.path().join("key.pem");
+        let der = write_self_signed(&cert_path, &key_path)?;
+
+        let certified_key = load_certified_key(&cert_path, &key_path)?;
+        assert_eq!(certified_key.cert.len(), 1);
+        assert_eq!(certified_key.cert[0].0, der);
+
+        let err = load_certified_key(&key_path, &key_path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("No PEM encoded certificates found in {:?}", key_path)
+        );
+
+        let err = load_certified_key(&cert_path, &cert_path).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "No PEM encoded PKCS8 or RSA private key found in {:?}",
+                cert_path
+            )
+        );
+
+        let missing_path = dir.path().join("missing.pem");
+        let err = load_certified_key(&missing_path, &key_path).unwrap_err();
+        assert!(matches!(err, Error::ReadingFile { .. }));
+
+        Ok(())
+    }
+
+    fn tls_config(dir: &Path) -> TlsConfig {
+        TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+            client_ca_path: None,
+        }
+    }
+
+    #[test]
+    fn test_reload_if_changed() -> TestResult {
+        let dir = test_helpers::tmp_dir()?;
+        let config = tls_config(dir.path());
+        let first = write_self_signed(&config.cert_path, &config.key_path)?;
+
+        let resolver = ReloadingCertResolver::new(&config)?;
+        assert_eq!(resolved_cert(&resolver), first);
+        assert!(!resolver.reload_if_changed()?);
+
+        // make sure the modification times differ even on file
+        // systems with coarse timestamps
+        std::thread::sleep(Duration::from_millis(1100));
+
+        // an invalid certificate is reported, and the previous one
+        // remains in use
+        std::fs::write(&config.cert_path, "not a certificate")?;
+        assert!(resolver.reload_if_changed().is_err());
+        assert_eq!(resolved_cert(&resolver), first);
+
+        let second = write_self_signed(&config.cert_path, &config.key_path)?;
+        assert_ne!(first, second);
+        assert!(resolver.reload_if_changed()?);
+        assert_eq!(resolved_cert(&resolver), second);
+        assert!(!resolver.reload_if_changed()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_config() -> TestResult {
+        let dir = test_helpers::tmp_dir()?;
+        let mut config = tls_config(dir.path());
+        write_self_signed(&config.cert_path, &config.key_path)?;
+
+        let resolver = Arc::new(ReloadingCertResolver::new(&config)?);
+        let server_config = server_config(resolver, GRPC_PROTOCOLS);
+        assert_eq!(server_config.current().alpn_protocols, vec![b"h2".to_vec()]);
+
+        // the server's own certificate doubles as a client CA
+        config.client_ca_path = Some(config.cert_path.clone());
+        let resolver = Arc::new(ReloadingCertResolver::new(&config)?);
+        server_config(resolver, HTTP_PROTOCOLS);
+
+        config.client_ca_path = Some(config.key_path.clone());
+        assert!(ReloadingCertResolver::new(&config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reload_client_ca() -> TestResult {
+        let dir = test_helpers::tmp_dir()?;
+        let mut config = tls_config(dir.path());
+        write_self_signed(&config.cert_path, &config.key_path)?;
+        let ca_path = dir.path().join("ca.pem");
+        write_self_signed(&ca_path, &dir.path().join("ca-key.pem"))?;
+        config.client_ca_path = Some(ca_path.clone());
+
+        let resolver = Arc::new(ReloadingCertResolver::new(&config)?);
+        let server_config = server_config(Arc::clone(&resolver), HTTP_PROTOCOLS);
+        let first = server_config.current();
+        assert!(Arc::ptr_eq(&first, &server_config.current()));
+
+        std::thread::sleep(Duration::from_millis(1100));
+
+        // replacing only the server certificate keeps the configuration
+        write_self_signed(&config.cert_path, &config.key_path)?;
+        assert!(resolver.reload_if_changed()?);
+        assert!(Arc::ptr_eq(&first, &server_config.current()));
+
+        std::thread::sleep(Duration::from_millis(1100));
+
+        // new connections verify clients against the new client CA
+        write_self_signed(&ca_path, &dir.path().join("ca-key.pem"))?;
+        assert!(resolver.reload_if_changed()?);
+        let second = server_config.current();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert!(Arc::ptr_eq(&second, &server_config.current()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+//! This module contains the warnings returned with the results of a
+//! query, for parts of a request that were accepted but not acted on,
+//! such as a parameter that is ignored. Unlike errors they don't fail
+//! the request, but clients should see them rather than only the
+//! server's log.
+//!
+//! Warnings are returned in the `iox-warning` trailers of gRPC
+//! responses and the `X-IOx-Warning` headers of HTTP responses, one
+//! for each warning, formatted as `{code}: {message}`.
+
+use std::fmt;
+
+use http::header::{HeaderMap, HeaderValue};
+use serde::Serialize;
+use tonic::metadata::{MetadataMap, MetadataValue};
+
+/// The trailer of a gRPC response holding a warning
+pub const WARNING_TRAILER: &str = "iox-warning";
+
+/// The header of an HTTP response holding a warning
+pub const WARNING_HEADER: &str = "x-iox-warning";
+
+/// What a warning is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningCode {
+    /// A field of the request was ignored
+    IgnoredField,
+    /// A bound parameter was not referred to by the query
+    UnusedParameter,
+}
+
+impl fmt::Display for WarningCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IgnoredField => write!(f, "ignored_field"),
+            Self::UnusedParameter => write!(f, "unused_parameter"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Warning {
+    pub code: WarningCode,
+    pub message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+/// The warnings of a single request
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Warnings {
+    warnings: Vec<Warning>,
+}
+
+impl Warnings {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, code: WarningCode, message: impl Into<String>) {
+        self.warnings.push(Warning {
+            code,
+            message: message.into(),
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Warning> {
+        self.warnings.iter()
+    }
+
+    /// Adds a `WARNING_TRAILER` entry to `metadata` for each warning.
+    /// Warnings that are not valid metadata values are left out.
+    pub fn add_to_metadata(&self, metadata: &mut MetadataMap) {
+        for warning in self.iter() {
+            if let Ok(value) = MetadataValue::from_str(&warning.to_string()) {
+                metadata.append(WARNING_TRAILER, value);
+            }
+        }
+    }
+
+    /// Adds a `WARNING_HEADER` header to `headers` for each warning.
+    /// Warnings that are not valid header values are left out.
+    pub fn add_to_headers(&self, headers: &mut HeaderMap) {
+        for warning in self.iter() {
+            if let Ok(value) = HeaderValue::from_str(&warning.to_string()) {
+                headers.append(WARNING_HEADER, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warnings_in_metadata_and_headers() {
+        let mut warnings = Warnings::new();
+        assert!(warnings.is_empty());
+
+        warnings.add(WarningCode::IgnoredField, "hints are not supported");
+        warnings.add(WarningCode::UnusedParameter, "parameter $host is not used");
+        warnings.add(WarningCode::IgnoredField, "two\nlines");
+
+        let mut metadata = MetadataMap::new();
+        warnings.add_to_metadata(&mut metadata);
+        let values: Vec<_> = metadata
+            .get_all(WARNING_TRAILER)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            values,
+            vec![
+                "ignored_field: hints are not supported",
+                "unused_parameter: parameter $host is not used",
+            ]
+        );
+
+        let mut headers = HeaderMap::new();
+        warnings.add_to_headers(&mut headers);
+        assert_eq!(headers.get_all(WARNING_HEADER).iter().count(), 2);
+    }
+}
@@ -3,21 +3,34 @@
 //! interface abstracts away many of the details
 mod counters;
 pub mod fieldlist;
+pub mod gaps;
+mod memory;
 mod planning;
 mod schema_pivot;
 pub mod seriesset;
+mod spill;
+pub mod stats;
 pub mod stringset;
+pub mod top_n;
+pub mod window_aggregate;
 
-use std::sync::Arc;
+use std::{path::PathBuf, sync::Arc};
 
 use arrow_deps::{
     arrow::record_batch::RecordBatch,
-    datafusion::{self, logical_plan::LogicalPlan},
+    datafusion::{
+        self,
+        logical_plan::{LogicalPlan, LogicalPlanBuilder},
+    },
 };
 use counters::ExecutionCounters;
+use data_types::TIME_COLUMN_NAME;
+use memory::MemoryBudget;
 
 use planning::IOxExecutionContext;
+pub use planning::{batches_stream, execute_stream};
 use schema_pivot::SchemaPivotNode;
+use stats::QueryStats;
 
 use fieldlist::{FieldList, IntoFieldList};
 use seriesset::{
@@ -26,6 +39,7 @@ use seriesset::{
 };
 use stringset::{IntoStringSet, StringSet, StringSetRef};
 use tokio::sync::mpsc::{self, error::SendError};
+use tracing::debug;
 
 use snafu::{ResultExt, Snafu};
 
@@ -99,6 +113,31 @@ pub enum StringSetPlan {
     // A datafusion plan(s) to execute. Each plan must produce
     // RecordBatches with exactly one String column
     Plan(Vec<LogicalPlan>),
+    // The plans of each chunk, which are run one at a time so that
+    // chunks that can add no new values to those found so far are
+    // skipped
+    Chunks(Vec<ChunkStringSetPlan>),
+}
+
+/// The plan producing the strings (e.g. tag values) of a single chunk,
+/// along with every string the plan could produce
+#[derive(Debug)]
+pub struct ChunkStringSetPlan {
+    /// Must produce RecordBatches with exactly one String column
+    pub plan: LogicalPlan,
+
+    /// The strings the chunk has, whether or not they pass the
+    /// predicate of `plan`, such as all the values of a tag in the
+    /// dictionary of the chunk
+    pub possible_values: StringSet,
+}
+
+impl ChunkStringSetPlan {
+    /// Returns true if the chunk has strings other than `values`, so
+    /// running its plan might add to them
+    pub fn contains_other_values(&self, values: &StringSet) -> bool {
+        !self.possible_values.is_subset(values)
+    }
 }
 
 impl From<StringSetRef> for StringSetPlan {
@@ -132,6 +171,14 @@ where
     }
 }
 
+impl From<Vec<ChunkStringSetPlan>> for StringSetPlan {
+    /// Create a plan which runs the plan of each chunk, skipping those
+    /// whose strings have all been produced by earlier chunks
+    fn from(chunks: Vec<ChunkStringSetPlan>) -> Self {
+        Self::Chunks(chunks)
+    }
+}
+
 impl From<Vec<LogicalPlan>> for StringSetPlan {
     /// Create a DataFusion LogicalPlan node, each if which must
     /// produce a single output Utf8 column. The output of each plan
@@ -185,12 +232,35 @@ pub struct GroupedSeriesSetPlan {
     pub num_prefix_tag_group_columns: usize,
 }
 
+/// A plan along with the generation of the database it was made from
+/// (see `Database::generation`). The generation is read while the data
+/// the plan reads is locked, so the results of the plan are those of
+/// that generation even if data is written while the plan runs.
+#[derive(Debug)]
+pub struct Planned<P> {
+    pub plan: P,
+    pub generation: u64,
+}
+
+impl<P> Planned<P> {
+    pub fn new(plan: P, generation: u64) -> Self {
+        Self { plan, generation }
+    }
+}
+
 /// A container for plans which each produces a logical stream of
 /// timeseries (from across many potential tables). A `SeriesSetPlans`
 /// can be executed to produce streams of `SeriesSet`s.
 #[derive(Debug, Default)]
 pub struct SeriesSetPlans {
     pub plans: Vec<SeriesSetPlan>,
+
+    /// The number of chunks the plans read data from
+    pub chunks_scanned: u64,
+
+    /// The number of chunks skipped while planning, because they
+    /// could not contain data matching the query
+    pub chunks_pruned: u64,
 }
 
 /// A container for plans which each produces a logical stream of
@@ -200,17 +270,30 @@ pub struct SeriesSetPlans {
 #[derive(Debug, Default)]
 pub struct GroupedSeriesSetPlans {
     pub grouped_plans: Vec<GroupedSeriesSetPlan>,
+
+    /// The number of chunks the plans read data from
+    pub chunks_scanned: u64,
+
+    /// The number of chunks skipped while planning, because they
+    /// could not contain data matching the query
+    pub chunks_pruned: u64,
 }
 
 impl From<Vec<SeriesSetPlan>> for SeriesSetPlans {
     fn from(plans: Vec<SeriesSetPlan>) -> Self {
-        Self { plans }
+        Self {
+            plans,
+            ..Self::default()
+        }
     }
 }
 
 impl From<Vec<GroupedSeriesSetPlan>> for GroupedSeriesSetPlans {
     fn from(grouped_plans: Vec<GroupedSeriesSetPlan>) -> Self {
-        Self { grouped_plans }
+        Self {
+            grouped_plans,
+            ..Self::default()
+        }
     }
 }
 
@@ -227,6 +310,14 @@ pub enum FieldListPlan {
 #[derive(Debug, Default)]
 pub struct Executor {
     counters: Arc<ExecutionCounters>,
+
+    /// The maximum number of bytes of results each query may buffer,
+    /// if any
+    memory_limit: Option<usize>,
+
+    /// The directory the rows of series set queries that exceed the
+    /// memory limit are spilled to, if any
+    spill_dir: Option<PathBuf>,
 }
 
 impl Executor {
@@ -234,14 +325,88 @@ impl Executor {
         Self::default()
     }
 
+    /// Limits each query run by this executor to buffering at most
+    /// `memory_limit` bytes of results and operator state (such as the
+    /// rows being sorted). Queries that exceed the limit fail with a
+    /// "query exceeded memory limit" error.
+    pub fn with_memory_limit(self, memory_limit: usize) -> Self {
+        Self {
+            memory_limit: Some(memory_limit),
+            ..self
+        }
+    }
+
+    /// Groups the rows of series set and grouped series set queries
+    /// that would exceed the memory limit by spilling them to temporary
+    /// files in `spill_dir`, rather than failing the queries. Has no
+    /// effect without a memory limit.
+    pub fn with_spill_dir(self, spill_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            spill_dir: Some(spill_dir.into()),
+            ..self
+        }
+    }
+
+    /// Returns an executor sharing the counters of this one, whose
+    /// queries may buffer at most `memory_limit` bytes (or the limit of
+    /// this executor, if lower)
+    pub fn limited_to(&self, memory_limit: usize) -> Self {
+        let memory_limit = match self.memory_limit {
+            Some(limit) => limit.min(memory_limit),
+            None => memory_limit,
+        };
+
+        Self {
+            counters: Arc::clone(&self.counters),
+            memory_limit: Some(memory_limit),
+            spill_dir: self.spill_dir.clone(),
+        }
+    }
+
+    /// Returns a new memory budget for a single query
+    fn new_budget(&self) -> Arc<MemoryBudget> {
+        Arc::new(MemoryBudget::new(self.memory_limit))
+    }
+
     /// Executes this plan and returns the resulting set of strings
     pub async fn to_string_set(&self, plan: StringSetPlan) -> Result<StringSetRef> {
         match plan {
             StringSetPlan::Known(res) => res,
-            StringSetPlan::Plan(plans) => run_logical_plans(self.counters.clone(), plans)
-                .await?
-                .into_stringset()
-                .context(StringSetConversion),
+            StringSetPlan::Plan(plans) => {
+                run_logical_plans(self.counters.clone(), self.new_budget(), plans)
+                    .await?
+                    .into_stringset()
+                    .context(StringSetConversion)
+            }
+            StringSetPlan::Chunks(chunks) => {
+                let budget = self.new_budget();
+                let num_chunks = chunks.len();
+                let mut skipped = 0;
+
+                let mut strings = StringSet::new();
+                for chunk in chunks {
+                    if !chunk.contains_other_values(&strings) {
+                        skipped += 1;
+                        continue;
+                    }
+
+                    let chunk_strings = run_logical_plans(
+                        self.counters.clone(),
+                        Arc::clone(&budget),
+                        vec![chunk.plan],
+                    )
+                    .await?
+                    .into_stringset()
+                    .context(StringSetConversion)?;
+                    strings.extend(chunk_strings.iter().cloned());
+                }
+
+                debug!(
+                    "Skipped {} of {} chunks that could add no new strings",
+                    skipped, num_chunks
+                );
+                Ok(StringSetRef::new(strings))
+            }
         }
     }
 
@@ -250,6 +415,18 @@ impl Executor {
     ///
     /// The SeriesSets are guaranteed to come back ordered by table_name
     ///
+    /// The SeriesSets of each plan are produced as its record batches
+    /// are, and producing them waits while `tx` is full, so a slow
+    /// receiver limits how much of the results are held in memory.
+    ///
+    /// If the executor has a memory limit and a spill directory, the
+    /// rows of each plan are grouped into series by a `SpillingGroupBy`
+    /// rather than sorted in memory, as for grouped plans.
+    ///
+    /// The chunks the plans scanned, and the rows and memory they
+    /// used, are recorded in `stats` before the returned future
+    /// resolves, and so before `tx` is closed.
+    ///
     /// Note that the returned future resolves (e.g. "returns") once
     /// all plans have been sent to `tx`. This means that the future
     /// will not resolve if there is nothing hooked up receiving
@@ -259,8 +436,14 @@ impl Executor {
         &self,
         series_set_plans: SeriesSetPlans,
         mut tx: mpsc::Sender<Result<SeriesSet, SeriesSetError>>,
+        stats: &QueryStats,
     ) -> Result<()> {
-        let SeriesSetPlans { mut plans } = series_set_plans;
+        let SeriesSetPlans {
+            mut plans,
+            chunks_scanned,
+            chunks_pruned,
+        } = series_set_plans;
+        stats.record_chunks(chunks_scanned, chunks_pruned);
 
         if plans.is_empty() {
             return Ok(());
@@ -270,6 +453,8 @@ impl Executor {
         // channels
         plans.sort_by(|a, b| a.table_name.cmp(&b.table_name));
         let mut rx_channels = Vec::new(); // sorted by table names
+        let budget = self.new_budget();
+        let spill_dir = self.memory_limit.and(self.spill_dir.clone());
 
         // Run the plans in parallel
         let handles = plans
@@ -277,6 +462,8 @@ impl Executor {
             .map(|plan| {
                 // Clone Arc's for transmission to threads
                 let counters = self.counters.clone();
+                let budget = budget.clone();
+                let spill_dir = spill_dir.clone();
                 let (plan_tx, plan_rx) = mpsc::channel(1);
                 rx_channels.push(plan_rx);
 
@@ -292,16 +479,39 @@ impl Executor {
                     let field_columns = Arc::new(field_columns);
 
                     // TODO run these on some executor other than the main tokio pool (maybe?)
-                    let ctx = IOxExecutionContext::new(counters);
-                    let physical_plan = ctx
-                        .make_plan(&plan)
-                        .await
-                        .context(DataFusionPhysicalPlanning)?;
+                    let ctx = IOxExecutionContext::new(counters, budget);
+                    let unsorted_plan = spill_dir.as_ref().and_then(|_| without_sort(&plan));
 
-                    let it = ctx
-                        .execute(physical_plan)
-                        .await
-                        .context(SeriesSetExecution)?;
+                    let it = match (spill_dir, unsorted_plan) {
+                        (Some(spill_dir), Some(unsorted_plan)) => {
+                            let physical_plan = ctx
+                                .make_plan(&unsorted_plan)
+                                .await
+                                .context(DataFusionPhysicalPlanning)?;
+
+                            // each series is a group of all the tag columns
+                            let mut sort_columns = tag_columns.as_ref().clone();
+                            sort_columns.push(Arc::new(TIME_COLUMN_NAME.to_string()));
+                            ctx.execute_grouped(
+                                physical_plan,
+                                &sort_columns,
+                                tag_columns.len(),
+                                &spill_dir,
+                            )
+                            .await
+                            .context(SeriesSetExecution)?
+                        }
+                        _ => {
+                            let physical_plan = ctx
+                                .make_plan(&plan)
+                                .await
+                                .context(DataFusionPhysicalPlanning)?;
+
+                            ctx.execute(physical_plan)
+                                .await
+                                .context(SeriesSetExecution)?
+                        }
+                    };
 
                     SeriesSetConverter::new(plan_tx)
                         .convert(table_name, tag_columns, field_columns, it)
@@ -329,12 +539,22 @@ impl Executor {
         for join_handle in handles {
             join_handle.await.context(JoinError)??;
         }
+        record_budget(stats, &budget);
         Ok(())
     }
 
     /// Executes the the Grouped plans, sending the
     /// results one by one to the `tx` chanel.
     ///
+    /// If the executor has a memory limit and a spill directory, the
+    /// rows of each plan are grouped by a `SpillingGroupBy` rather than
+    /// sorted in memory, so that queries whose rows exceed the limit
+    /// complete, with their groups in the same order.
+    ///
+    /// The chunks the plans scanned, and the rows and memory they
+    /// used, are recorded in `stats` before the returned future
+    /// resolves, and so before `tx` is closed.
+    ///
     /// Note that the returned future resolves (e.g. "returns") once
     /// all plans have been sent to `tx`. This means that the future
     /// will not resolve if there is nothing hooked up receiving
@@ -344,8 +564,16 @@ impl Executor {
         &self,
         grouped_series_set_plans: GroupedSeriesSetPlans,
         tx: mpsc::Sender<Result<GroupedSeriesSetItem, SeriesSetError>>,
+        stats: &QueryStats,
     ) -> Result<()> {
-        let GroupedSeriesSetPlans { grouped_plans } = grouped_series_set_plans;
+        let GroupedSeriesSetPlans {
+            grouped_plans,
+            chunks_scanned,
+            chunks_pruned,
+        } = grouped_series_set_plans;
+        stats.record_chunks(chunks_scanned, chunks_pruned);
+        let budget = self.new_budget();
+        let spill_dir = self.memory_limit.and(self.spill_dir.clone());
 
         // Run the plans in parallel
         let handles = grouped_plans
@@ -353,6 +581,8 @@ impl Executor {
             .map(|plan| {
                 // Clone Arc's for transmission to threads
                 let counters = self.counters.clone();
+                let budget = budget.clone();
+                let spill_dir = spill_dir.clone();
                 let tx = tx.clone();
                 tokio::task::spawn(async move {
                     let GroupedSeriesSetPlan {
@@ -371,16 +601,38 @@ impl Executor {
                     let field_columns = Arc::new(field_columns);
 
                     // TODO run these on some executor other than the main tokio pool (maybe?)
-                    let ctx = IOxExecutionContext::new(counters);
-                    let physical_plan = ctx
-                        .make_plan(&plan)
-                        .await
-                        .context(DataFusionPhysicalPlanning)?;
+                    let ctx = IOxExecutionContext::new(counters, budget);
+                    let unsorted_plan = spill_dir.as_ref().and_then(|_| without_sort(&plan));
 
-                    let it = ctx
-                        .execute(physical_plan)
-                        .await
-                        .context(GroupedSeriesSetExecution)?;
+                    let it = match (spill_dir, unsorted_plan) {
+                        (Some(spill_dir), Some(unsorted_plan)) => {
+                            let physical_plan = ctx
+                                .make_plan(&unsorted_plan)
+                                .await
+                                .context(DataFusionPhysicalPlanning)?;
+
+                            let mut sort_columns = tag_columns.as_ref().clone();
+                            sort_columns.push(Arc::new(TIME_COLUMN_NAME.to_string()));
+                            ctx.execute_grouped(
+                                physical_plan,
+                                &sort_columns,
+                                num_prefix_tag_group_columns,
+                                &spill_dir,
+                            )
+                            .await
+                            .context(GroupedSeriesSetExecution)?
+                        }
+                        _ => {
+                            let physical_plan = ctx
+                                .make_plan(&plan)
+                                .await
+                                .context(DataFusionPhysicalPlanning)?;
+
+                            ctx.execute(physical_plan)
+                                .await
+                                .context(GroupedSeriesSetExecution)?
+                        }
+                    };
 
                     GroupedSeriesSetConverter::new(tx)
                         .convert(
@@ -402,6 +654,7 @@ impl Executor {
         for join_handle in handles {
             join_handle.await.context(JoinError)??;
         }
+        record_budget(stats, &budget);
         Ok(())
     }
 
@@ -410,14 +663,17 @@ impl Executor {
         match plan {
             FieldListPlan::Known(res) => res,
             FieldListPlan::Plans(plans) => {
+                let budget = self.new_budget();
+
                 // Run the plans in parallel
                 let handles = plans
                     .into_iter()
                     .map(|plan| {
                         let counters = self.counters.clone();
+                        let budget = budget.clone();
 
                         tokio::task::spawn(async move {
-                            let ctx = IOxExecutionContext::new(counters);
+                            let ctx = IOxExecutionContext::new(counters, budget);
                             let physical_plan = ctx
                                 .make_plan(&plan)
                                 .await
@@ -452,7 +708,7 @@ impl Executor {
     /// Run the plan and return a record batch reader for reading the results
     pub async fn run_logical_plan(&self, plan: LogicalPlan) -> Result<Vec<RecordBatch>> {
         let counters = self.counters.clone();
-        run_logical_plans(counters, vec![plan]).await
+        run_logical_plans(counters, self.new_budget(), vec![plan]).await
     }
 }
 /// Create a SchemaPivot node which  an arbitrary input like
@@ -475,19 +731,37 @@ pub fn make_schema_pivot(input: LogicalPlan) -> LogicalPlan {
     LogicalPlan::Extension { node }
 }
 
+/// Returns `plan` without the sort beneath its projection, as grouped
+/// plans are built, or `None` if `plan` is not of that form
+fn without_sort(plan: &LogicalPlan) -> Option<LogicalPlan> {
+    match plan {
+        LogicalPlan::Projection { expr, input, .. } => match input.as_ref() {
+            LogicalPlan::Sort { input, .. } => LogicalPlanBuilder::from(input.as_ref())
+                .project(expr.clone())
+                .ok()?
+                .build()
+                .ok(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 /// plans and runs the plans in parallel and collects the results
 /// run each plan in parallel and collect the results
 async fn run_logical_plans(
     counters: Arc<ExecutionCounters>,
+    budget: Arc<MemoryBudget>,
     plans: Vec<LogicalPlan>,
 ) -> Result<Vec<RecordBatch>> {
     let value_futures = plans
         .into_iter()
         .map(|plan| {
             let counters = counters.clone();
+            let budget = budget.clone();
             // TODO run these on some executor other than the main tokio pool
             tokio::task::spawn(async move {
-                let ctx = IOxExecutionContext::new(counters);
+                let ctx = IOxExecutionContext::new(counters, budget);
                 let physical_plan = ctx.make_plan(&plan).await.expect("making logical plan");
 
                 // TODO: avoid this buffering
@@ -507,6 +781,14 @@ async fn run_logical_plans(
     Ok(results)
 }
 
+/// Records the rows produced by the plans of a query, and the memory
+/// they used, in `stats`. As memory charged to a budget is not given
+/// back until the query completes, the memory used is also the peak.
+fn record_budget(stats: &QueryStats, budget: &MemoryBudget) {
+    stats.add_rows_scanned(budget.rows() as u64);
+    stats.record_memory(budget.used() as u64);
+}
+
 #[cfg(test)]
 mod tests {
     use arrow_deps::arrow::{
@@ -516,6 +798,7 @@ mod tests {
         datatypes::DataType,
         datatypes::{Field, Schema, SchemaRef},
     };
+    use arrow_deps::datafusion::logical_plan::{col, Expr};
     use stringset::StringSet;
 
     use super::*;
@@ -631,6 +914,38 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn executor_chunk_string_set_skips_chunks() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Utf8, true)]));
+        let chunk = |strings: &[&str], possible_values: &[&str]| {
+            let data = to_string_array(strings);
+            let batch =
+                RecordBatch::try_new(schema.clone(), vec![data]).expect("created new record batch");
+            ChunkStringSetPlan {
+                plan: make_plan(schema.clone(), vec![batch]),
+                possible_values: to_set(possible_values).as_ref().clone(),
+            }
+        };
+
+        let first = chunk(&["foo", "bar"], &["foo", "bar", "baz"]);
+        // only has values the first chunk produced, so it is skipped
+        // (its plan would add "qux" if it were run)
+        let skipped = chunk(&["qux"], &["bar", "foo"]);
+        let last = chunk(&["baz"], &["baz", "zed"]);
+
+        assert!(!skipped.contains_other_values(&to_set(&["foo", "bar"])));
+        assert!(last.contains_other_values(&to_set(&["foo", "bar"])));
+
+        let plan: StringSetPlan = vec![first, skipped, last].into();
+
+        let executor = Executor::new();
+        let results = executor.to_string_set(plan).await?;
+
+        assert_eq!(results, to_set(&["foo", "bar", "baz"]));
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn executor_datafusion_string_set_nulls() -> Result<()> {
         // Ensure that nulls in the output set are handled reasonably
@@ -692,6 +1007,138 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn executor_datafusion_string_set_memory_limit() -> Result<()> {
+        // Ensure that a query buffering more than the memory limit fails
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Utf8, true)]));
+        let data = to_string_array(&["foo", "bar", "baz", "foo"]);
+        let batch =
+            RecordBatch::try_new(schema.clone(), vec![data]).expect("created new record batch");
+        let scan = make_plan(schema, vec![batch]);
+
+        let executor = Executor::new().with_memory_limit(10);
+        let results = executor.to_string_set(vec![scan.clone()].into()).await;
+
+        let actual_error = match results {
+            Ok(_) => "Unexpected Ok".into(),
+            Err(e) => format!("{}", e),
+        };
+        let expected_error = "query exceeded memory limit";
+        assert!(
+            actual_error.contains(expected_error),
+            "expected error '{}' not found in '{:?}'",
+            expected_error,
+            actual_error
+        );
+
+        // each query has its own budget, so a generous limit is not
+        // used up by earlier queries
+        let executor = Executor::new().with_memory_limit(1_000_000);
+        for _ in 0..3 {
+            let results = executor.to_string_set(vec![scan.clone()].into()).await?;
+            assert_eq!(results, to_set(&["foo", "bar", "baz"]));
+        }
+
+        // a lower limit applies to the queries of the limited executor
+        let results = executor
+            .limited_to(10)
+            .to_string_set(vec![scan.clone()].into())
+            .await;
+        assert!(results.is_err());
+        executor
+            .limited_to(10_000_000)
+            .to_string_set(vec![scan].into())
+            .await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn executor_series_set_spills_rows() -> Result<()> {
+        // the rows of 1000 series, 100 rows each, in a single batch
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, false),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        let hosts: Vec<_> = (0..100_000).map(|i| format!("host{}", i % 1000)).collect();
+        let hosts: Vec<_> = hosts.iter().map(String::as_str).collect();
+        let times = (0..100_000).rev().collect::<Vec<i64>>();
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![to_string_array(&hosts), Arc::new(Int64Array::from(times))],
+        )
+        .expect("created new record batch");
+        let input_size = memory::batch_memory_size(&batch);
+
+        let scan = make_plan(schema, vec![batch]);
+        let sort_expr = |name: &str| Expr::Sort {
+            expr: Box::new(col(name)),
+            asc: true,
+            nulls_first: true,
+        };
+        let plan = LogicalPlanBuilder::from(&scan)
+            .sort(vec![sort_expr("host"), sort_expr("time")])
+            .expect("sorting")
+            .project(vec![col("host"), col("time")])
+            .expect("projecting")
+            .build()
+            .expect("building plan");
+
+        // returns the tags and number of rows of each series, or the
+        // first error
+        let run = |executor: Executor| {
+            let plan = plan.clone();
+            async move {
+                let plans = SeriesSetPlans {
+                    plans: vec![SeriesSetPlan {
+                        table_name: Arc::new("cpu".into()),
+                        plan,
+                        tag_columns: vec![Arc::new("host".into())],
+                        field_columns: vec![],
+                    }],
+                    ..Default::default()
+                };
+                let (tx, mut rx) = mpsc::channel(2000);
+                executor
+                    .to_series_set(plans, tx, &QueryStats::default())
+                    .await
+                    .expect("running plans");
+
+                let mut series = vec![];
+                while let Some(series_set) = rx.recv().await {
+                    let series_set = series_set.map_err(|e| e.to_string())?;
+                    series.push((series_set.tags, series_set.num_rows));
+                }
+                Ok::<_, String>(series)
+            }
+        };
+
+        // the sort buffers all the rows, and its results take as much
+        // memory again
+        let limit = input_size * 3 / 2;
+        let error = run(Executor::new().with_memory_limit(limit))
+            .await
+            .unwrap_err();
+        assert!(
+            error.contains("query exceeded memory limit"),
+            "unexpected error: {}",
+            error
+        );
+
+        // grouping the rows spills them instead, producing the same
+        // series as the sort
+        let spill_dir = tempfile::tempdir().unwrap();
+        let executor = Executor::new()
+            .with_memory_limit(limit)
+            .with_spill_dir(spill_dir.path());
+        let spilled = run(executor).await.unwrap();
+        let sorted = run(Executor::new()).await.unwrap();
+        assert_eq!(spilled.len(), 1000);
+        assert_eq!(spilled, sorted);
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn make_schema_pivot_is_planned() -> Result<()> {
         // Test that all the planning logic is wired up and that we
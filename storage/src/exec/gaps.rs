@@ -0,0 +1,211 @@
+//! This module contains the detection of gaps in the data of each
+//! series: the periods, longer than some threshold, in which no
+//! points were written. This is useful for availability reporting,
+//! such as finding the periods a host stopped reporting metrics.
+//!
+//! Gaps are found using only the timestamps of each series (and the
+//! validity of its fields), so field values are never materialized.
+
+use std::sync::Arc;
+
+use arrow_deps::arrow::array::{Array, Int64Array};
+use snafu::{OptionExt, Snafu};
+
+use super::seriesset::SeriesSet;
+use crate::predicate::TimestampRange;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Timestamp column of series set is not an Int64 column"))]
+    InvalidTimestampColumn,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// A period without any points
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    /// The timestamp of the last point before the gap, or the start
+    /// of the queried range
+    pub start: i64,
+    /// The timestamp of the first point after the gap, or the end of
+    /// the queried range
+    pub end: i64,
+}
+
+/// The gaps of a single series
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SeriesGaps {
+    /// The table name this series came from
+    pub table_name: Arc<String>,
+
+    /// key = value pairs that define this series
+    pub tags: Vec<(Arc<String>, Arc<String>)>,
+
+    /// The gaps of the series, in time order
+    pub gaps: Vec<Gap>,
+}
+
+/// Finds the gaps in `series_set` longer than `threshold`
+/// nanoseconds.
+///
+/// If `range` is specified, the periods between the start of the range
+/// and the first point, and between the last point and the end of the
+/// range, are also reported when they are longer than `threshold`.
+///
+/// If `series_set` has any fields, only the rows where at least one of
+/// them is non null count as points.
+pub fn find_gaps(
+    series_set: &SeriesSet,
+    threshold: i64,
+    range: Option<TimestampRange>,
+) -> Result<SeriesGaps> {
+    let batch = &series_set.batch;
+
+    let times = batch
+        .column(series_set.timestamp_index)
+        .as_any()
+        .downcast_ref::<Int64Array>()
+        .context(InvalidTimestampColumn)?;
+
+    let fields = series_set
+        .field_indices
+        .iter()
+        .map(|&index| batch.column(index))
+        .collect::<Vec<_>>();
+
+    let end_row = series_set.start_row + series_set.num_rows;
+    let mut timestamps = (series_set.start_row..end_row)
+        .filter(|&row| !times.is_null(row))
+        .filter(|&row| fields.is_empty() || fields.iter().any(|field| field.is_valid(row)))
+        .map(|row| times.value(row))
+        .collect::<Vec<_>>();
+    timestamps.sort_unstable();
+
+    let mut boundaries = Vec::with_capacity(timestamps.len() + 2);
+    if let Some(range) = &range {
+        boundaries.push(range.start);
+    }
+    boundaries.extend(timestamps);
+    if let Some(range) = &range {
+        boundaries.push(range.end);
+    }
+
+    let gaps = boundaries
+        .windows(2)
+        .map(|pair| Gap {
+            start: pair[0],
+            end: pair[1],
+        })
+        .filter(|gap| i128::from(gap.end) - i128::from(gap.start) > i128::from(threshold))
+        .collect();
+
+    Ok(SeriesGaps {
+        table_name: Arc::clone(&series_set.table_name),
+        tags: series_set.tags.clone(),
+        gaps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{
+        array::Float64Array,
+        datatypes::{DataType, Field, Schema},
+        record_batch::RecordBatch,
+    };
+
+    /// Returns a series set with points at 10, 20, 50, 55 and 100,
+    /// where the value of the field at 50 is null
+    fn make_series_set(with_field: bool) -> SeriesSet {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Float64Array::from(vec![
+                    Some(1.0),
+                    Some(2.0),
+                    None,
+                    Some(4.0),
+                    Some(5.0),
+                ])),
+                // not in time order
+                Arc::new(Int64Array::from(vec![20, 10, 50, 55, 100])),
+            ],
+        )
+        .unwrap();
+
+        let field_indices = if with_field { vec![0] } else { vec![] };
+
+        SeriesSet {
+            table_name: Arc::new("cpu".into()),
+            tags: vec![(Arc::new("host".into()), Arc::new("h1".into()))],
+            timestamp_index: 1,
+            field_indices: Arc::new(field_indices),
+            start_row: 0,
+            num_rows: 5,
+            batch,
+        }
+    }
+
+    fn gaps(series_gaps: SeriesGaps) -> Vec<(i64, i64)> {
+        series_gaps
+            .gaps
+            .into_iter()
+            .map(|gap| (gap.start, gap.end))
+            .collect()
+    }
+
+    #[test]
+    fn test_find_gaps() {
+        let series_set = make_series_set(false);
+
+        let series_gaps = find_gaps(&series_set, 10, None).unwrap();
+        assert_eq!(*series_gaps.table_name, "cpu");
+        assert_eq!(series_gaps.tags, series_set.tags);
+        assert_eq!(gaps(series_gaps), vec![(20, 50), (55, 100)]);
+
+        let series_gaps = find_gaps(&series_set, 30, None).unwrap();
+        assert_eq!(gaps(series_gaps), vec![(55, 100)]);
+
+        let series_gaps = find_gaps(&series_set, 100, None).unwrap();
+        assert!(gaps(series_gaps).is_empty());
+    }
+
+    #[test]
+    fn test_find_gaps_range() {
+        let series_set = make_series_set(false);
+
+        let range = TimestampRange { start: 0, end: 200 };
+        let series_gaps = find_gaps(&series_set, 10, Some(range)).unwrap();
+        assert_eq!(
+            gaps(series_gaps),
+            vec![(20, 50), (55, 100), (100, 200)],
+            "gap from the start of the range is not longer than the threshold"
+        );
+
+        let range = TimestampRange {
+            start: i64::MIN,
+            end: i64::MAX,
+        };
+        let series_gaps = find_gaps(&series_set, 40, Some(range)).unwrap();
+        assert_eq!(
+            gaps(series_gaps),
+            vec![(i64::MIN, 10), (55, 100), (100, i64::MAX)]
+        );
+    }
+
+    #[test]
+    fn test_find_gaps_null_fields() {
+        let series_set = make_series_set(true);
+
+        // the row at 50 has no field values, so is not a point
+        let series_gaps = find_gaps(&series_set, 10, None).unwrap();
+        assert_eq!(gaps(series_gaps), vec![(20, 55), (55, 100)]);
+    }
+}
@@ -0,0 +1,387 @@
+//! This module contains the per-query memory budget enforced by the
+//! executor.
+//!
+//! Every `RecordBatch` produced while executing a query is charged
+//! against the budget of that query, as is the state of the operators
+//! of its plan which buffer rows: the rows each sort buffers before
+//! producing any, and the groups of each hash aggregate (as it
+//! produces them, since DataFusion does not report the size of its
+//! hash tables). Since the executor buffers the results (to build
+//! string sets, field lists and series sets) the memory is not
+//! returned until the query completes, and a query whose results
+//! would exceed its budget fails rather than consuming all the memory
+//! of the server. The rows of series set plans are instead grouped by
+//! a `SpillingGroupBy` when the executor has a spill directory, which
+//! spills them to disk rather than failing.
+
+use std::{
+    any::Any,
+    fmt,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use arrow_deps::{
+    arrow::{
+        array::Array, datatypes::SchemaRef, error::ArrowError, error::Result as ArrowResult,
+        record_batch::RecordBatch,
+    },
+    datafusion::{
+        error::{DataFusionError, Result as DataFusionResult},
+        physical_plan::{
+            hash_aggregate::HashAggregateExec, sort::SortExec, Distribution, ExecutionPlan,
+            Partitioning, RecordBatchStream, SendableRecordBatchStream,
+        },
+    },
+};
+use async_trait::async_trait;
+use snafu::Snafu;
+use tokio::stream::Stream;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "query exceeded memory limit: {} bytes required, limit is {} bytes",
+        used,
+        limit
+    ))]
+    MemoryLimitExceeded { used: usize, limit: usize },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Tracks the memory used by a single query, and ensures it remains
+/// within `limit` bytes (if any). Also counts the rows charged to it.
+#[derive(Debug, Default)]
+pub struct MemoryBudget {
+    limit: Option<usize>,
+    used: AtomicUsize,
+    rows: AtomicUsize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            used: AtomicUsize::new(0),
+            rows: AtomicUsize::new(0),
+        }
+    }
+
+    /// Records that `bytes` more memory are in use, returning an
+    /// error if that takes the query over its limit
+    pub fn reserve(&self, bytes: usize) -> Result<()> {
+        let used = self.used.fetch_add(bytes, Ordering::Relaxed) + bytes;
+
+        match self.limit {
+            Some(limit) if used > limit => MemoryLimitExceeded { used, limit }.fail(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Records that `bytes` previously reserved are no longer in use
+    pub fn release(&self, bytes: usize) {
+        self.used.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// The limit of this budget, if any
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Returns the number of bytes reserved so far
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    /// Records that `rows` more rows have been produced
+    pub fn add_rows(&self, rows: usize) {
+        self.rows.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    /// Returns the number of rows produced so far
+    pub fn rows(&self) -> usize {
+        self.rows.load(Ordering::Relaxed)
+    }
+}
+
+/// Returns the number of bytes of memory used by the arrays of `batch`
+pub fn batch_memory_size(batch: &RecordBatch) -> usize {
+    batch
+        .columns()
+        .iter()
+        .map(|column| column.get_array_memory_size())
+        .sum()
+}
+
+/// Wraps `input` so that each `RecordBatch` it produces is charged to
+/// `budget`. Once the budget is exhausted the stream produces an
+/// error instead.
+pub fn track_memory(
+    input: SendableRecordBatchStream,
+    budget: Arc<MemoryBudget>,
+) -> SendableRecordBatchStream {
+    Box::pin(MemoryTrackingStream {
+        input,
+        budget,
+        count_rows: true,
+    })
+}
+
+/// Returns `plan` charging the state of its operators to `budget`: the
+/// inputs of its sorts, which they buffer, and the outputs of its hash
+/// aggregates, which are their groups
+pub fn track_operator_state(
+    plan: Arc<dyn ExecutionPlan>,
+    budget: &Arc<MemoryBudget>,
+) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+    let children = plan.children();
+    let plan = if children.is_empty() {
+        plan
+    } else {
+        let is_sort = plan.as_any().is::<SortExec>();
+        let children = children
+            .into_iter()
+            .map(|child| {
+                let child = track_operator_state(child, budget)?;
+                Ok(if is_sort {
+                    Arc::new(MemoryTrackingExec::new(child, Arc::clone(budget))) as _
+                } else {
+                    child
+                })
+            })
+            .collect::<DataFusionResult<Vec<_>>>()?;
+        plan.with_new_children(children)?
+    };
+
+    if plan.as_any().is::<HashAggregateExec>() {
+        Ok(Arc::new(MemoryTrackingExec::new(plan, Arc::clone(budget))))
+    } else {
+        Ok(plan)
+    }
+}
+
+/// Physical operator that charges each `RecordBatch` its input
+/// produces to a memory budget, as the state of the operator it is the
+/// input (or output) of. Its rows are not counted as rows of the query.
+pub struct MemoryTrackingExec {
+    input: Arc<dyn ExecutionPlan>,
+    budget: Arc<MemoryBudget>,
+}
+
+impl MemoryTrackingExec {
+    pub fn new(input: Arc<dyn ExecutionPlan>, budget: Arc<MemoryBudget>) -> Self {
+        Self { input, budget }
+    }
+}
+
+impl fmt::Debug for MemoryTrackingExec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryTrackingExec")
+            .field("input", &self.input)
+            .finish()
+    }
+}
+
+#[async_trait]
+impl ExecutionPlan for MemoryTrackingExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.input.output_partitioning()
+    }
+
+    fn required_child_distribution(&self) -> Distribution {
+        Distribution::UnspecifiedDistribution
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![Arc::clone(&self.input)]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        match children.len() {
+            1 => Ok(Arc::new(Self::new(
+                Arc::clone(&children[0]),
+                Arc::clone(&self.budget),
+            ))),
+            _ => Err(DataFusionError::Internal(
+                "MemoryTrackingExec wrong number of children".to_string(),
+            )),
+        }
+    }
+
+    async fn execute(&self, partition: usize) -> DataFusionResult<SendableRecordBatchStream> {
+        let input = self.input.execute(partition).await?;
+        Ok(Box::pin(MemoryTrackingStream {
+            input,
+            budget: Arc::clone(&self.budget),
+            count_rows: false,
+        }))
+    }
+}
+
+struct MemoryTrackingStream {
+    input: SendableRecordBatchStream,
+    budget: Arc<MemoryBudget>,
+    /// Whether the rows are counted as rows produced by the query
+    count_rows: bool,
+}
+
+impl Stream for MemoryTrackingStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.input.as_mut().poll_next(cx) {
+            Poll::Ready(Some(Ok(batch))) => {
+                if self.count_rows {
+                    self.budget.add_rows(batch.num_rows());
+                }
+                let result = self
+                    .budget
+                    .reserve(batch_memory_size(&batch))
+                    .map(|()| batch)
+                    .map_err(|e| ArrowError::MemoryError(e.to_string()));
+                Poll::Ready(Some(result))
+            }
+            other => other,
+        }
+    }
+}
+
+impl RecordBatchStream for MemoryTrackingStream {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::exec::{counters::ExecutionCounters, planning::IOxExecutionContext};
+    use arrow_deps::{
+        arrow::{
+            array::Int64Array,
+            datatypes::{DataType, Field, Schema},
+        },
+        datafusion::{
+            logical_plan::{col, Expr, LogicalPlan, LogicalPlanBuilder},
+            physical_plan::common::SizedRecordBatchStream,
+        },
+    };
+    use tokio::stream::StreamExt;
+
+    #[test]
+    fn test_reserve() {
+        let budget = MemoryBudget::new(Some(100));
+        budget.reserve(60).unwrap();
+        budget.reserve(40).unwrap();
+        assert_eq!(budget.used(), 100);
+
+        assert_eq!(
+            budget.reserve(1).unwrap_err().to_string(),
+            "query exceeded memory limit: 101 bytes required, limit is 100 bytes"
+        );
+
+        budget.release(101);
+        budget.reserve(100).unwrap();
+
+        // no limit
+        let budget = MemoryBudget::new(None);
+        budget.reserve(usize::MAX / 2).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_track_memory() {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = Arc::new(
+            RecordBatch::try_new(
+                schema.clone(),
+                vec![Arc::new(Int64Array::from(vec![1, 2, 3]))],
+            )
+            .unwrap(),
+        );
+        let size = batch_memory_size(&batch);
+        assert!(size > 0);
+
+        // Enough for one batch but not two
+        let budget = Arc::new(MemoryBudget::new(Some(size + size / 2)));
+        let input = Box::pin(SizedRecordBatchStream::new(
+            schema,
+            vec![batch.clone(), batch],
+        ));
+        let results = track_memory(input, budget.clone())
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok());
+        let error = results[1].as_ref().unwrap_err().to_string();
+        assert!(
+            error.contains("query exceeded memory limit"),
+            "unexpected error: {}",
+            error
+        );
+        assert_eq!(budget.used(), 2 * size);
+        assert_eq!(budget.rows(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_track_operator_state() {
+        let schema = Arc::new(Schema::new(vec![Field::new("a", DataType::Int64, false)]));
+        let batch = RecordBatch::try_new(
+            schema.clone(),
+            vec![Arc::new(Int64Array::from(vec![3, 1, 2]))],
+        )
+        .unwrap();
+        let size = batch_memory_size(&batch);
+
+        let scan = LogicalPlan::InMemoryScan {
+            data: vec![vec![batch]],
+            schema: schema.clone(),
+            projection: None,
+            projected_schema: schema,
+        };
+        let sort = Expr::Sort {
+            expr: Box::new(col("a")),
+            asc: true,
+            nulls_first: true,
+        };
+        let plan = LogicalPlanBuilder::from(&scan)
+            .sort(vec![sort])
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let budget = Arc::new(MemoryBudget::new(None));
+        let ctx =
+            IOxExecutionContext::new(Arc::new(ExecutionCounters::default()), Arc::clone(&budget));
+        let physical_plan = ctx.make_plan(&plan).await.unwrap();
+        assert!(
+            format!("{:?}", physical_plan).contains("MemoryTrackingExec"),
+            "sort input not tracked: {:?}",
+            physical_plan
+        );
+
+        let results = ctx.collect(physical_plan).await.unwrap();
+        assert_eq!(results.len(), 1);
+
+        // the rows the sort buffered are charged as well as its
+        // results, which are the only rows counted
+        assert!(budget.used() >= size + batch_memory_size(&results[0]));
+        assert_eq!(budget.rows(), 3);
+    }
+}
@@ -1,9 +1,10 @@
 //! This module contains plumbing to connect InfluxDB IOx extensions to DataFusion
 
-use std::sync::Arc;
+use std::{path::Path, sync::Arc};
 
 use arrow_deps::{
-    arrow::record_batch::RecordBatch,
+    arrow::{datatypes::SchemaRef, record_batch::RecordBatch},
+    datafusion::physical_plan::common::SizedRecordBatchStream,
     datafusion::physical_plan::merge::MergeExec,
     datafusion::physical_plan::SendableRecordBatchStream,
     datafusion::{
@@ -21,12 +22,17 @@ use arrow_deps::{
 
 use crate::exec::schema_pivot::{SchemaPivotExec, SchemaPivotNode};
 
+use tokio::stream::StreamExt;
 use tracing::debug;
 
 // Reuse DataFusion error and Result types for this module
 pub use arrow_deps::datafusion::error::{DataFusionError as Error, Result};
 
-use super::counters::ExecutionCounters;
+use super::{
+    counters::ExecutionCounters,
+    memory::{track_memory, track_operator_state, MemoryBudget},
+    spill::{self, SpillingGroupBy},
+};
 
 struct IOxQueryPlanner {}
 
@@ -80,12 +86,14 @@ impl ExtensionPlanner for IOxExtensionPlanner {
 
 pub struct IOxExecutionContext {
     counters: Arc<ExecutionCounters>,
+    budget: Arc<MemoryBudget>,
     inner: ExecutionContext,
 }
 
 impl IOxExecutionContext {
-    /// Create an ExecutionContext suitable for executing DataFusion plans
-    pub fn new(counters: Arc<ExecutionCounters>) -> Self {
+    /// Create an ExecutionContext suitable for executing DataFusion
+    /// plans, charging the results to the memory `budget` of the query
+    pub fn new(counters: Arc<ExecutionCounters>, budget: Arc<MemoryBudget>) -> Self {
         const BATCH_SIZE: usize = 1000;
 
         // TBD: Should we be reusing an execution context across all executions?
@@ -94,7 +102,11 @@ impl IOxExecutionContext {
         let config = config.with_query_planner(Arc::new(IOxQueryPlanner {}));
         let inner = ExecutionContext::with_config(config);
 
-        Self { counters, inner }
+        Self {
+            counters,
+            budget,
+            inner,
+        }
     }
 
     pub async fn make_plan(&self, plan: &LogicalPlan) -> Result<Arc<dyn ExecutionPlan>> {
@@ -112,7 +124,10 @@ impl IOxExecutionContext {
             plan.display_graphviz(),
         );
 
-        self.inner.create_physical_plan(&plan)
+        // the rows the operators of the plan buffer are charged to the
+        // budget of the query, as well as its results
+        let physical_plan = self.inner.create_physical_plan(&plan)?;
+        track_operator_state(physical_plan, &self.budget)
     }
 
     /// Executes the logical plan using DataFusion and produces RecordBatches
@@ -121,18 +136,81 @@ impl IOxExecutionContext {
 
         debug!("Running plan, physical:\n{:?}", physical_plan);
 
-        self.inner.collect(physical_plan).await
+        if physical_plan.output_partitioning().partition_count() == 0 {
+            return Ok(vec![]);
+        }
+
+        // stop at the first error, which may be the query exceeding
+        // its memory budget
+        let mut stream = self.execute(physical_plan).await?;
+        let mut batches = Vec::new();
+        while let Some(batch) = stream.next().await {
+            batches.push(batch?);
+        }
+        Ok(batches)
     }
 
     /// Executes the physical plan and produces a RecordBatchStream to stream over the result
     /// that iterates over the results.
+    ///
+    /// Each RecordBatch is charged to the memory budget of the query,
+    /// and the stream errors if the budget is exceeded.
     pub async fn execute(
         &self,
         physical_plan: Arc<dyn ExecutionPlan>,
     ) -> Result<SendableRecordBatchStream> {
-        if physical_plan.output_partitioning().partition_count() <= 1 {
-            physical_plan.execute(0).await
-        } else {
+        let stream = self.execute_untracked(physical_plan).await?;
+        Ok(track_memory(stream, self.budget.clone()))
+    }
+
+    /// Executes the physical plan, grouping its rows by sorting them on
+    /// `sort_columns`, the first `num_group_columns` of which are the
+    /// group columns. Rows that would exceed the memory budget of the
+    /// query are spilled to temporary files in `spill_dir`, rather than
+    /// failing the query.
+    pub async fn execute_grouped(
+        &self,
+        physical_plan: Arc<dyn ExecutionPlan>,
+        sort_columns: &[Arc<String>],
+        num_group_columns: usize,
+        spill_dir: &Path,
+    ) -> Result<SendableRecordBatchStream> {
+        let spill_error = |e: spill::Error| Error::Execution(e.to_string());
+
+        let mut group_by = SpillingGroupBy::try_new(
+            physical_plan.schema(),
+            sort_columns,
+            num_group_columns,
+            self.budget.clone(),
+            spill_dir,
+        )
+        .map_err(spill_error)?;
+
+        // the rows are charged to the budget as they are grouped
+        let mut stream = self.execute_untracked(physical_plan).await?;
+        while let Some(batch) = stream.next().await {
+            group_by.push(batch?).map_err(spill_error)?;
+        }
+        group_by.finish().map_err(spill_error)
+    }
+
+    async fn execute_untracked(
+        &self,
+        physical_plan: Arc<dyn ExecutionPlan>,
+    ) -> Result<SendableRecordBatchStream> {
+        execute_stream(physical_plan).await
+    }
+}
+
+/// Executes `physical_plan`, merging its partitions into one, and
+/// returns a stream of the batches it produces as they are produced
+pub async fn execute_stream(
+    physical_plan: Arc<dyn ExecutionPlan>,
+) -> Result<SendableRecordBatchStream> {
+    match physical_plan.output_partitioning().partition_count() {
+        0 => Ok(batches_stream(physical_plan.schema(), vec![])),
+        1 => physical_plan.execute(0).await,
+        _ => {
             // merge into a single partition
             let plan = MergeExec::new(physical_plan);
             // MergeExec must produce a single partition
@@ -141,3 +219,9 @@ impl IOxExecutionContext {
         }
     }
 }
+
+/// Returns a stream of `batches`, which have the schema `schema`
+pub fn batches_stream(schema: SchemaRef, batches: Vec<RecordBatch>) -> SendableRecordBatchStream {
+    let batches = batches.into_iter().map(Arc::new).collect();
+    Box::pin(SizedRecordBatchStream::new(schema, batches))
+}
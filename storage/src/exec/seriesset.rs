@@ -20,7 +20,8 @@
 use std::sync::Arc;
 
 use arrow::{
-    array::StringArray, datatypes::DataType, datatypes::SchemaRef, record_batch::RecordBatch,
+    array::StringArray, compute::concat, datatypes::DataType, datatypes::SchemaRef,
+    record_batch::RecordBatch,
 };
 use arrow_deps::{
     arrow::{self},
@@ -52,6 +53,9 @@ pub enum Error {
         source: arrow::error::ArrowError,
     },
 
+    #[snafu(display("Error concatenating the rows of a series: {}", source))]
+    ConcatenatingSeries { source: arrow::error::ArrowError },
+
     #[snafu(display("Sending series set results during conversion: {:?}", source))]
     SendingDuringConversion {
         source: Box<SendError<Result<SeriesSet>>>,
@@ -99,6 +103,52 @@ pub struct SeriesSet {
     pub batch: RecordBatch,
 }
 
+impl SeriesSet {
+    /// Returns a SeriesSet with the rows of each of `series_sets` in
+    /// turn, which must not be empty and must all have the same
+    /// schema. It has the table name and tags of the first. The rows
+    /// of all of them are copied into a new batch at once, unless
+    /// there is only one.
+    pub fn concat(mut series_sets: Vec<Self>) -> Result<Self> {
+        assert!(!series_sets.is_empty(), "no series sets to concatenate");
+        if series_sets.len() == 1 {
+            return Ok(series_sets.remove(0));
+        }
+
+        let first = &series_sets[0];
+        let columns = (0..first.batch.num_columns())
+            .map(|index| {
+                let slices = series_sets
+                    .iter()
+                    .map(|series_set| {
+                        series_set
+                            .batch
+                            .column(index)
+                            .slice(series_set.start_row, series_set.num_rows)
+                    })
+                    .collect::<Vec<_>>();
+                concat(&slices)
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .context(ConcatenatingSeries)?;
+        let batch =
+            RecordBatch::try_new(first.batch.schema(), columns).context(ConcatenatingSeries)?;
+
+        Ok(Self {
+            table_name: first.table_name.clone(),
+            tags: first.tags.clone(),
+            timestamp_index: first.timestamp_index,
+            field_indices: first.field_indices.clone(),
+            start_row: 0,
+            num_rows: series_sets
+                .iter()
+                .map(|series_set| series_set.num_rows)
+                .sum(),
+            batch,
+        })
+    }
+}
+
 /// Describes a group of series "group of series" series. Namely,
 /// several logical timeseries that share the same timestamps and
 /// name=value tag keys, grouped by some subset of the tag keys
@@ -166,6 +216,14 @@ impl SeriesSetConverter {
     }
 
     /// Does the actual conversion logic, but returns any error in processing
+    ///
+    /// The SeriesSets are sent as the record batches they come from
+    /// arrive, so only the batches of the series that have not been
+    /// sent yet are held in memory, and sending waits for the receiver
+    /// to take the previous results (e.g. as gRPC flow control allows).
+    ///
+    /// Series whose rows span more than one record batch are sent as a
+    /// single SeriesSet, whose batch holds just the rows of that series
     pub async fn convert_impl(
         &mut self,
         table_name: Arc<String>,
@@ -173,94 +231,131 @@ impl SeriesSetConverter {
         field_columns: Arc<Vec<Arc<String>>>,
         mut it: SendableRecordBatchStream,
     ) -> Result<()> {
-        // for now, only handle a single record batch
-        if let Some(batch) = it.next().await {
-            let batch = batch.context(ReadingRecordBatch)?;
+        // the parts of the last series of the previous batches, which
+        // may continue in the next one. They are concatenated once the
+        // series ends, so that its rows are only copied once.
+        let mut pending: Vec<SeriesSet> = vec![];
 
-            if it.next().await.is_some() {
-                // but not yet
-                unimplemented!("Computing series across multiple record batches not yet supported");
+        while let Some(batch) = it.next().await {
+            let batch = batch.context(ReadingRecordBatch)?;
+            if batch.num_rows() == 0 {
+                continue;
             }
 
-            let schema = batch.schema();
-            // TODO: check that the tag columns are sorted by tag name...
-
-            let timestamp_index =
-                schema
-                    .index_of(TIME_COLUMN_NAME)
-                    .context(ColumnNotFoundForSeriesSet {
-                        column_name: TIME_COLUMN_NAME,
-                    })?;
-            let tag_indicies = Self::names_to_indices(&schema, &tag_columns)?;
-            let field_indicies = Arc::new(Self::names_to_indices(&schema, &field_columns)?);
+            let mut series_sets =
+                Self::batch_to_series_sets(&table_name, &tag_columns, &field_columns, batch)?;
 
-            // Algorithm: compute, via bitsets, the rows at which each
-            // tag column changes and thereby where the tagset
-            // changes. Emit a new SeriesSet at each such transition
-            let mut tag_transitions = tag_indicies
-                .iter()
-                .map(|&col| Self::compute_transitions(&batch, col))
-                .collect::<Result<Vec<_>>>()?;
-
-            // no tag columns, emit a single tagset
-            let intersections = if tag_transitions.is_empty() {
-                let mut b = Bitmap::create_with_capacity(1);
-                let end_row = batch.num_rows();
-                b.add(end_row as u32);
-                b
-            } else {
-                // OR bitsets together to to find all rows where the
-                // keyset (values of the tag keys) changes
-                let remaining = tag_transitions.split_off(1);
-
-                remaining
-                    .into_iter()
-                    .for_each(|b| tag_transitions[0].or_inplace(&b));
-                // take the first item
-                tag_transitions.into_iter().next().unwrap()
-            };
-
-            let mut start_row: u32 = 0;
-
-            // create each series (since bitmap are not Send, we can't
-            // call await during the loop)
-
-            // emit each series
-            let series_sets = intersections
-                .iter()
-                .map(|end_row| {
-                    let series_set = SeriesSet {
-                        table_name: table_name.clone(),
-                        tags: Self::get_tag_keys(
-                            &batch,
-                            start_row as usize,
-                            &tag_columns,
-                            &tag_indicies,
-                        ),
-                        timestamp_index,
-                        field_indices: field_indicies.clone(),
-                        start_row: start_row as usize,
-                        num_rows: (end_row - start_row) as usize,
-                        batch: batch.clone(),
-                    };
-
-                    start_row = end_row;
-                    series_set
-                })
-                .collect::<Vec<_>>();
+            let continued = pending
+                .last()
+                .map_or(false, |previous| previous.tags == series_sets[0].tags);
+            if continued {
+                pending.push(series_sets.remove(0));
+                if series_sets.is_empty() {
+                    continue;
+                }
+            }
+            if !pending.is_empty() {
+                let series_set = SeriesSet::concat(std::mem::take(&mut pending))?;
+                self.send(series_set).await?;
+            }
 
+            pending.extend(series_sets.pop());
             for series_set in series_sets {
-                self.tx
-                    .send(Ok(series_set))
-                    .await
-                    .map_err(|e| Error::SendingDuringConversion {
-                        source: Box::new(e),
-                    })?;
+                self.send(series_set).await?;
             }
         }
+
+        if !pending.is_empty() {
+            self.send(SeriesSet::concat(pending)?).await?;
+        }
         Ok(())
     }
 
+    async fn send(&mut self, series_set: SeriesSet) -> Result<()> {
+        self.tx
+            .send(Ok(series_set))
+            .await
+            .map_err(|e| Error::SendingDuringConversion {
+                source: Box::new(e),
+            })
+    }
+
+    /// Divides the rows of `batch`, which must have at least one row,
+    /// into the SeriesSets of each tagset
+    fn batch_to_series_sets(
+        table_name: &Arc<String>,
+        tag_columns: &Arc<Vec<Arc<String>>>,
+        field_columns: &Arc<Vec<Arc<String>>>,
+        batch: RecordBatch,
+    ) -> Result<Vec<SeriesSet>> {
+        let schema = batch.schema();
+        // TODO: check that the tag columns are sorted by tag name...
+
+        let timestamp_index =
+            schema
+                .index_of(TIME_COLUMN_NAME)
+                .context(ColumnNotFoundForSeriesSet {
+                    column_name: TIME_COLUMN_NAME,
+                })?;
+        let tag_indicies = Self::names_to_indices(&schema, tag_columns)?;
+        let field_indicies = Arc::new(Self::names_to_indices(&schema, field_columns)?);
+
+        // Algorithm: compute, via bitsets, the rows at which each
+        // tag column changes and thereby where the tagset
+        // changes. Emit a new SeriesSet at each such transition
+        let mut tag_transitions = tag_indicies
+            .iter()
+            .map(|&col| Self::compute_transitions(&batch, col))
+            .collect::<Result<Vec<_>>>()?;
+
+        // no tag columns, emit a single tagset
+        let intersections = if tag_transitions.is_empty() {
+            let mut b = Bitmap::create_with_capacity(1);
+            let end_row = batch.num_rows();
+            b.add(end_row as u32);
+            b
+        } else {
+            // OR bitsets together to to find all rows where the
+            // keyset (values of the tag keys) changes
+            let remaining = tag_transitions.split_off(1);
+
+            remaining
+                .into_iter()
+                .for_each(|b| tag_transitions[0].or_inplace(&b));
+            // take the first item
+            tag_transitions.into_iter().next().unwrap()
+        };
+
+        let mut start_row: u32 = 0;
+
+        // create each series (since bitmap are not Send, they can't be
+        // held across the awaits of sending the series)
+        let series_sets = intersections
+            .iter()
+            .map(|end_row| {
+                let series_set = SeriesSet {
+                    table_name: table_name.clone(),
+                    tags: Self::get_tag_keys(
+                        &batch,
+                        start_row as usize,
+                        tag_columns,
+                        &tag_indicies,
+                    ),
+                    timestamp_index,
+                    field_indices: field_indicies.clone(),
+                    start_row: start_row as usize,
+                    num_rows: (end_row - start_row) as usize,
+                    batch: batch.clone(),
+                };
+
+                start_row = end_row;
+                series_set
+            })
+            .collect::<Vec<_>>();
+
+        Ok(series_sets)
+    }
+
     // look up which column index correponds to each column name
     fn names_to_indices(schema: &SchemaRef, column_names: &[Arc<String>]) -> Result<Vec<usize>> {
         column_names
@@ -639,6 +734,74 @@ mod tests {
         Ok(())
     }
 
+    // series that span record batches
+    #[tokio::test]
+    async fn test_convert_multiple_batches() -> Result<()> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("tag_a", DataType::Utf8, true),
+            Field::new("float_field", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+
+        let input = parse_batches_to_iterator(
+            schema,
+            &[
+                "one,10.0,1000\n",
+                "one,10.1,2000\n",
+                "one,10.2,3000\n\
+                 two,20.0,4000\n",
+                "two,20.1,5000\n",
+                "three,30.0,6000\n",
+            ],
+        );
+
+        let table_name = "foo";
+        let tag_columns = ["tag_a"];
+        let field_columns = ["float_field"];
+        let results = convert(table_name, &tag_columns, &field_columns, input).await;
+
+        assert_eq!(results.len(), 3);
+        let series_set1 = results[0].as_ref().expect("Correctly converted");
+        assert_eq!(series_set1.tags, str_pair_vec_to_vec(&[("tag_a", "one")]));
+        assert_eq!(series_set1.start_row, 0);
+        assert_eq!(series_set1.num_rows, 3);
+
+        // the rows of the series, from three batches, are combined
+        // into one record batch
+        let expected_data = vec![
+            "+-------+-------------+------+",
+            "| tag_a | float_field | time |",
+            "+-------+-------------+------+",
+            "| one   | 10          | 1000 |",
+            "| one   | 10.1        | 2000 |",
+            "| one   | 10.2        | 3000 |",
+            "+-------+-------------+------+",
+            "",
+        ];
+
+        let actual_data = pretty_format_batches(&[series_set1.batch.clone()])
+            .expect("formatting batch")
+            .split('\n')
+            .map(|s| s.to_string())
+            .collect::<Vec<String>>();
+
+        assert_eq!(expected_data, actual_data);
+
+        // the second series is made up of rows of two batches too
+        let series_set2 = results[1].as_ref().expect("Correctly converted");
+        assert_eq!(series_set2.tags, str_pair_vec_to_vec(&[("tag_a", "two")]));
+        assert_eq!(series_set2.start_row, 0);
+        assert_eq!(series_set2.num_rows, 2);
+        assert_eq!(series_set2.batch.num_rows(), 2);
+
+        let series_set3 = results[2].as_ref().expect("Correctly converted");
+        assert_eq!(series_set3.tags, str_pair_vec_to_vec(&[("tag_a", "three")]));
+        assert_eq!(series_set3.start_row, 0);
+        assert_eq!(series_set3.num_rows, 1);
+
+        Ok(())
+    }
+
     // two tag columns, three series
     #[tokio::test]
     async fn test_convert_two_tag_multi_series() -> Result<()> {
@@ -936,7 +1099,16 @@ mod tests {
     }
 
     fn parse_to_iterator(schema: SchemaRef, data: &str) -> SendableRecordBatchStream {
-        let batch = parse_to_record_batch(schema.clone(), data);
-        Box::pin(SizedRecordBatchStream::new(schema, vec![Arc::new(batch)]))
+        parse_batches_to_iterator(schema, &[data])
+    }
+
+    /// Test helper: parses each of the csv contents in `data` into a
+    /// record batch of the returned stream
+    fn parse_batches_to_iterator(schema: SchemaRef, data: &[&str]) -> SendableRecordBatchStream {
+        let batches = data
+            .iter()
+            .map(|data| Arc::new(parse_to_record_batch(schema.clone(), data)))
+            .collect();
+        Box::pin(SizedRecordBatchStream::new(schema, batches))
     }
 }
@@ -0,0 +1,911 @@
+//! This module contains the spilling group by, which groups the rows
+//! of a grouped series set plan whose rows would not fit in the memory
+//! budget of the query.
+//!
+//! Rather than sorting all the rows at once, the rows are hash
+//! partitioned on their group columns, and buffered in memory. When
+//! the buffered rows would take more than half the budget, the
+//! partitions are appended to temporary files (they are "spilled").
+//!
+//! Once all the rows have been partitioned, each partition is read
+//! back and sorted into a run of rows, which is written to a file of
+//! its own. A partition whose rows would take more than half the
+//! budget is instead divided again, hashing with another seed, and
+//! each of its own partitions is sorted in turn, up to `MAX_DEPTH`
+//! times. Every group is in a single partition, and so in a single
+//! run, so the runs are merged (the merge phase) by repeatedly taking
+//! the rows of the run with the first group, reading a batch of each
+//! run at a time. The groups are produced in order, as when the rows
+//! are sorted in memory, which is what is done if no rows are spilled.
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs::File,
+    hash::{Hash, Hasher},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+
+use arrow_deps::{
+    arrow::{
+        array::{Array, ArrayRef, StringArray, UInt32Array},
+        compute::{concat, lexsort_to_indices, take, SortColumn},
+        datatypes::SchemaRef,
+        error::{ArrowError, Result as ArrowResult},
+        ipc::{reader::StreamReader, writer::StreamWriter},
+        record_batch::RecordBatch,
+    },
+    datafusion::physical_plan::{RecordBatchStream, SendableRecordBatchStream},
+};
+use snafu::{OptionExt, ResultExt, Snafu};
+use tempfile::TempDir;
+use tokio::stream::Stream;
+use tracing::debug;
+
+use super::memory::{self, batch_memory_size, MemoryBudget};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Column '{}' to group by not found: {}", column_name, source))]
+    ColumnNotFound {
+        column_name: String,
+        source: ArrowError,
+    },
+
+    #[snafu(display("Group column '{}' is not a string column", column_name))]
+    InvalidGroupColumn { column_name: String },
+
+    #[snafu(display("Partitioning rows to group: {}", source))]
+    Partitioning { source: ArrowError },
+
+    #[snafu(display("Sorting rows of a partition: {}", source))]
+    Sorting { source: ArrowError },
+
+    #[snafu(display("Merging sorted runs of rows: {}", source))]
+    Merging { source: ArrowError },
+
+    #[snafu(display("Creating spill directory in {:?}: {}", dir, source))]
+    CreatingSpillDir {
+        dir: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Opening spill file {:?}: {}", path, source))]
+    OpeningSpillFile {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Writing spill file: {}", source))]
+    WritingSpillFile { source: ArrowError },
+
+    #[snafu(display("Reading spill file: {}", source))]
+    ReadingSpillFile { source: ArrowError },
+
+    #[snafu(display("Grouping rows: {}", source))]
+    Memory { source: memory::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The number of partitions the rows are divided into, and that each
+/// partition too large to sort in memory is divided into
+const NUM_PARTITIONS: usize = 16;
+
+/// The number of times a partition is divided again before its rows
+/// are sorted in memory regardless of their size, which is only
+/// reached when a few groups have most of the rows
+const MAX_DEPTH: usize = 3;
+
+/// The number of rows in each batch of a run, and in each batch
+/// produced by the merge
+const BATCH_SIZE: usize = 1024;
+
+/// The rows of one partition, in memory and spilled
+#[derive(Default)]
+struct Partition {
+    batches: Vec<RecordBatch>,
+    /// The file the partition is spilled to, once it has been
+    spill_file: Option<(PathBuf, StreamWriter<BufWriter<File>>)>,
+}
+
+/// Groups rows by sorting them on the `sort_columns`, the first
+/// `num_group_columns` of which are the group columns, spilling them
+/// to temporary files in `spill_dir` if they would exceed half of
+/// `budget`
+pub struct SpillingGroupBy {
+    schema: SchemaRef,
+    /// The indices of the columns to sort on
+    sort_indices: Vec<usize>,
+    num_group_columns: usize,
+    budget: Arc<MemoryBudget>,
+    spill_dir: PathBuf,
+    partitions: Vec<Partition>,
+    /// The bytes of the partitions in memory, which are reserved from
+    /// `budget`
+    buffered: usize,
+    /// The directory of the spill files, created when rows are first
+    /// spilled and removed once it is no longer used
+    temp_dir: Option<Arc<TempDir>>,
+    /// How many times the rows have been divided before, as the
+    /// partitions of partitions. The rows are hashed with the depth, so
+    /// that the rows of a partition are divided among all the
+    /// partitions of the next depth.
+    depth: usize,
+    /// Distinguishes the spill files of this group by from those of the
+    /// other partitions divided at the same depth
+    name: String,
+}
+
+impl SpillingGroupBy {
+    pub fn try_new(
+        schema: SchemaRef,
+        sort_columns: &[Arc<String>],
+        num_group_columns: usize,
+        budget: Arc<MemoryBudget>,
+        spill_dir: impl Into<PathBuf>,
+    ) -> Result<Self> {
+        let sort_indices = sort_columns
+            .iter()
+            .map(|column_name| {
+                schema.index_of(column_name).context(ColumnNotFound {
+                    column_name: column_name.as_str(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        // without group columns all the rows are in the same group
+        let num_partitions = if num_group_columns == 0 {
+            1
+        } else {
+            NUM_PARTITIONS
+        };
+
+        Ok(Self {
+            schema,
+            sort_indices,
+            num_group_columns,
+            budget,
+            spill_dir: spill_dir.into(),
+            partitions: (0..num_partitions).map(|_| Partition::default()).collect(),
+            buffered: 0,
+            temp_dir: None,
+            depth: 0,
+            name: String::new(),
+        })
+    }
+
+    /// Returns a group by that divides the rows of partition `index`
+    /// of this one among partitions of its own
+    fn repartition(&self, index: usize) -> Self {
+        Self {
+            schema: Arc::clone(&self.schema),
+            sort_indices: self.sort_indices.clone(),
+            num_group_columns: self.num_group_columns,
+            budget: Arc::clone(&self.budget),
+            spill_dir: self.spill_dir.clone(),
+            partitions: (0..NUM_PARTITIONS).map(|_| Partition::default()).collect(),
+            buffered: 0,
+            temp_dir: self.temp_dir.clone(),
+            depth: self.depth + 1,
+            name: format!("{}{}-", self.name, index),
+        }
+    }
+
+    /// Adds the rows of `batch`, spilling the rows added so far first
+    /// if there is not enough of the budget left for them
+    pub fn push(&mut self, batch: RecordBatch) -> Result<()> {
+        for (index, batch) in self.partition(batch)? {
+            let size = batch_memory_size(&batch);
+            if let Some(limit) = self.budget.limit() {
+                if self.buffered > 0 && self.buffered + size > limit / 2 {
+                    self.spill()?;
+                }
+            }
+
+            self.budget.reserve(size).context(Memory)?;
+            self.buffered += size;
+            self.partitions[index].batches.push(batch);
+        }
+        Ok(())
+    }
+
+    /// Returns the partition the rows of the group `key` are in
+    fn partition_of(&self, key: &[Option<&str>]) -> usize {
+        let mut hasher = DefaultHasher::new();
+        self.depth.hash(&mut hasher);
+        key.hash(&mut hasher);
+        hasher.finish() as usize % self.partitions.len()
+    }
+
+    /// Returns the partition of each row of `batch`, as a batch of
+    /// the rows of each partition
+    fn partition(&self, batch: RecordBatch) -> Result<Vec<(usize, RecordBatch)>> {
+        let num_partitions = self.partitions.len();
+        if num_partitions == 1 {
+            return Ok(vec![(0, batch)]);
+        }
+
+        let group_indices = &self.sort_indices[..self.num_group_columns];
+        let group_columns = group_columns(&self.schema, &batch, group_indices)?;
+
+        let mut rows = vec![vec![]; num_partitions];
+        for row in 0..batch.num_rows() {
+            let key = group_key(&group_columns, row);
+            rows[self.partition_of(&key)].push(row as u32);
+        }
+
+        rows.into_iter()
+            .enumerate()
+            .filter(|(_, rows)| !rows.is_empty())
+            .map(|(index, rows)| Ok((index, take_rows(&batch, rows)?)))
+            .collect::<ArrowResult<Vec<_>>>()
+            .context(Partitioning)
+    }
+
+    /// Appends the partitions in memory to their spill files,
+    /// releasing their memory
+    fn spill(&mut self) -> Result<()> {
+        if self.temp_dir.is_none() {
+            let temp_dir = tempfile::Builder::new()
+                .prefix("iox-spill")
+                .tempdir_in(&self.spill_dir)
+                .context(CreatingSpillDir {
+                    dir: &self.spill_dir,
+                })?;
+            self.temp_dir = Some(Arc::new(temp_dir));
+        }
+        let dir = self.temp_dir.as_ref().expect("created above").path();
+
+        for (index, partition) in self.partitions.iter_mut().enumerate() {
+            if partition.batches.is_empty() {
+                continue;
+            }
+
+            if partition.spill_file.is_none() {
+                let path = dir.join(format!("partition-{}{}.arrow", self.name, index));
+                let file = File::create(&path).context(OpeningSpillFile { path: &path })?;
+                let writer = StreamWriter::try_new(BufWriter::new(file), &self.schema)
+                    .context(WritingSpillFile)?;
+                partition.spill_file = Some((path, writer));
+            }
+            let (_, writer) = partition.spill_file.as_mut().expect("created above");
+
+            for batch in partition.batches.drain(..) {
+                writer.write(&batch).context(WritingSpillFile)?;
+            }
+        }
+
+        debug!(
+            "Spilled {} bytes of rows to group to {:?}",
+            self.buffered, dir
+        );
+        self.budget.release(self.buffered);
+        self.buffered = 0;
+        Ok(())
+    }
+
+    /// Returns the rows added, in the order of the sort columns
+    pub fn finish(self) -> Result<SendableRecordBatchStream> {
+        Ok(Box::pin(self.into_merge()?))
+    }
+
+    /// Sorts the rows added into runs, which are merged by the stream
+    /// returned
+    fn into_merge(mut self) -> Result<MergeStream> {
+        let schema = Arc::clone(&self.schema);
+        let group_indices = self.sort_indices[..self.num_group_columns].to_vec();
+        let budget = Arc::clone(&self.budget);
+        let temp_dir = self.temp_dir.clone();
+
+        let mut runs = vec![];
+        if self.temp_dir.is_none() {
+            // without spilling, sort all the rows at once, whose memory
+            // is then charged to the run
+            let batches: Vec<_> = self
+                .partitions
+                .drain(..)
+                .flat_map(|partition| partition.batches)
+                .collect();
+            if !batches.is_empty() {
+                let sorted =
+                    sort_batches(&self.schema, &self.sort_indices, &batches).context(Sorting)?;
+                runs.push(Run {
+                    current: Some((sorted, 0)),
+                    rest: None,
+                    reserved: self.buffered,
+                });
+            }
+        } else {
+            self.write_runs(&mut runs)?;
+        }
+
+        Ok(MergeStream {
+            schema,
+            group_indices,
+            budget,
+            runs,
+            produced: 0,
+            _temp_dir: temp_dir,
+        })
+    }
+
+    /// Sorts the rows of each partition into a run in a spill file of
+    /// its own, adding them to `runs`. The rows of partitions that
+    /// would take more than half the budget are divided again, and the
+    /// runs of their partitions added instead.
+    fn write_runs(mut self, runs: &mut Vec<Run>) -> Result<()> {
+        let can_repartition = self.num_group_columns > 0 && self.depth < MAX_DEPTH;
+        let half_budget = self.budget.limit().map(|limit| limit / 2);
+        let oversized =
+            |size: usize| can_repartition && half_budget.map_or(false, |half| size > half);
+
+        let partitions: Vec<_> = self.partitions.drain(..).collect();
+        for (index, partition) in partitions.into_iter().enumerate() {
+            let mut batches = partition.batches;
+            let mut size = batches.iter().map(batch_memory_size).sum::<usize>();
+            let mut spilled = match partition.spill_file {
+                Some((path, mut writer)) => {
+                    writer.finish().context(WritingSpillFile)?;
+                    Some(open_spill_file(&path)?)
+                }
+                None => None,
+            };
+
+            // read back the spilled rows, unless there are too many
+            // to sort in memory
+            while !oversized(size) {
+                let batch = match spilled.as_mut().and_then(|reader| reader.next()) {
+                    Some(batch) => batch.context(ReadingSpillFile)?,
+                    None => break,
+                };
+                let batch_size = batch_memory_size(&batch);
+                self.budget.reserve(batch_size).context(Memory)?;
+                self.buffered += batch_size;
+                size += batch_size;
+                batches.push(batch);
+            }
+
+            if oversized(size) {
+                debug!(
+                    "Dividing the rows of spilled partition {}{} again",
+                    self.name, index
+                );
+                // the rows are handed over to the partitions of the
+                // next depth, which reserve them again
+                self.budget.release(size);
+                self.buffered -= size;
+
+                // the batches read back are as small as the rows of the
+                // partition in each batch pushed, so they are combined
+                // into batches of `BATCH_SIZE` rows before being divided
+                let mut group_by = self.repartition(index);
+                let read_back = spilled
+                    .into_iter()
+                    .flatten()
+                    .map(|batch| batch.context(ReadingSpillFile));
+                let mut pending = vec![];
+                let mut pending_rows = 0;
+                for batch in batches.into_iter().map(Ok).chain(read_back) {
+                    let batch = batch?;
+                    pending_rows += batch.num_rows();
+                    pending.push(batch);
+                    if pending_rows >= BATCH_SIZE {
+                        let batch = concat_batches(&self.schema, &pending).context(Partitioning)?;
+                        group_by.push(batch)?;
+                        pending.clear();
+                        pending_rows = 0;
+                    }
+                }
+                if !pending.is_empty() {
+                    let batch = concat_batches(&self.schema, &pending).context(Partitioning)?;
+                    group_by.push(batch)?;
+                }
+                group_by.write_runs(runs)?;
+            } else if !batches.is_empty() {
+                let sorted =
+                    sort_batches(&self.schema, &self.sort_indices, &batches).context(Sorting)?;
+                runs.push(self.write_run(index, &sorted)?);
+                self.budget.release(size);
+                self.buffered -= size;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the rows of `sorted`, of partition `index`, to a spill
+    /// file as a run
+    fn write_run(&self, index: usize, sorted: &RecordBatch) -> Result<Run> {
+        let dir = self.temp_dir.as_ref().expect("rows were spilled").path();
+        let path = dir.join(format!("run-{}{}.arrow", self.name, index));
+        let file = File::create(&path).context(OpeningSpillFile { path: &path })?;
+        let mut writer =
+            StreamWriter::try_new(BufWriter::new(file), &self.schema).context(WritingSpillFile)?;
+
+        let num_rows = sorted.num_rows() as u32;
+        for start in (0..num_rows).step_by(BATCH_SIZE) {
+            let end = num_rows.min(start + BATCH_SIZE as u32);
+            let batch = take_rows(sorted, (start..end).collect()).context(WritingSpillFile)?;
+            writer.write(&batch).context(WritingSpillFile)?;
+        }
+        writer.finish().context(WritingSpillFile)?;
+
+        Ok(Run {
+            current: None,
+            rest: Some(open_spill_file(&path)?),
+            reserved: 0,
+        })
+    }
+}
+
+/// Rows sorted on the sort columns, none of whose groups have rows in
+/// any other run
+struct Run {
+    /// The batch rows are being taken from, and the next row to take
+    current: Option<(RecordBatch, usize)>,
+    /// The file of the batches after `current`
+    rest: Option<StreamReader<BufReader<File>>>,
+    /// The bytes of `current` reserved from the budget
+    reserved: usize,
+}
+
+impl Run {
+    /// Reads the next batch of the run once all the rows of the current
+    /// one have been taken, leaving `current` empty if there are none
+    fn fill(&mut self, budget: &MemoryBudget) -> Result<()> {
+        loop {
+            match &self.current {
+                Some((batch, row)) if *row < batch.num_rows() => return Ok(()),
+                Some(_) => {
+                    self.current = None;
+                    budget.release(self.reserved);
+                    self.reserved = 0;
+                }
+                None => {}
+            }
+
+            match self.rest.as_mut().and_then(|reader| reader.next()) {
+                Some(batch) => {
+                    let batch = batch.context(ReadingSpillFile)?;
+                    let size = batch_memory_size(&batch);
+                    budget.reserve(size).context(Memory)?;
+                    self.reserved += size;
+                    self.current = Some((batch, 0));
+                }
+                None => {
+                    self.rest = None;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Takes the rows of the current batch up to `end`, releasing the
+    /// batch once all its rows are taken
+    fn take(&mut self, end: usize, budget: &MemoryBudget) -> ArrowResult<RecordBatch> {
+        let (batch, start) = self.current.as_mut().expect("run is filled");
+        let rows = if *start == 0 && end == batch.num_rows() {
+            batch.clone()
+        } else {
+            take_rows(batch, (*start as u32..end as u32).collect())?
+        };
+        *start = end;
+
+        if end == batch.num_rows() {
+            self.current = None;
+            budget.release(self.reserved);
+            self.reserved = 0;
+        }
+        Ok(rows)
+    }
+}
+
+/// Produces the rows of the runs in the order of the sort columns, by
+/// taking the rows of the run whose next row has the first group up
+/// to the first group of the next row of any other run
+struct MergeStream {
+    schema: SchemaRef,
+    /// The indices of the group columns
+    group_indices: Vec<usize>,
+    budget: Arc<MemoryBudget>,
+    runs: Vec<Run>,
+    /// The bytes reserved for the last batch produced
+    produced: usize,
+    _temp_dir: Option<Arc<TempDir>>,
+}
+
+impl MergeStream {
+    /// Merges the next rows of the runs into a batch of (at least)
+    /// `BATCH_SIZE` rows, unless fewer remain
+    fn next_batch(&mut self) -> Result<Option<RecordBatch>> {
+        // the previous batch is no longer charged to the query
+        self.budget.release(self.produced);
+        self.produced = 0;
+
+        let mut batches = vec![];
+        let mut num_rows = 0;
+        while num_rows < BATCH_SIZE {
+            for run in &mut self.runs {
+                run.fill(&self.budget)?;
+            }
+            self.runs.retain(|run| run.current.is_some());
+
+            let (index, end) = match self.next_rows()? {
+                Some(next) => next,
+                None => break,
+            };
+            let rows = self.runs[index].take(end, &self.budget).context(Merging)?;
+            num_rows += rows.num_rows();
+            batches.push(rows);
+        }
+
+        let batch = match batches.len() {
+            0 => return Ok(None),
+            1 => batches.pop().expect("one batch"),
+            _ => concat_batches(&self.schema, &batches).context(Merging)?,
+        };
+        let size = batch_memory_size(&batch);
+        self.budget.reserve(size).context(Memory)?;
+        self.produced = size;
+        Ok(Some(batch))
+    }
+
+    /// Returns the index of the run whose next row has the first group,
+    /// and the end of the rows of its current batch before the first
+    /// group of the next row of any other run
+    fn next_rows(&self) -> Result<Option<(usize, usize)>> {
+        let mut heads = Vec::with_capacity(self.runs.len());
+        for run in &self.runs {
+            let (batch, row) = run.current.as_ref().expect("run is filled");
+            let columns = group_columns(&self.schema, batch, &self.group_indices)?;
+            heads.push((columns, *row, batch.num_rows()));
+        }
+
+        let first = match (0..heads.len()).min_by_key(|&i| group_key(&heads[i].0, heads[i].1)) {
+            Some(first) => first,
+            None => return Ok(None),
+        };
+        let bound = heads
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != first)
+            .map(|(_, (columns, row, _))| group_key(columns, *row))
+            .min();
+
+        let (columns, start, num_rows) = &heads[first];
+        let end = match bound {
+            Some(bound) => (start + 1..*num_rows)
+                .find(|&row| group_key(columns, row) >= bound)
+                .unwrap_or(*num_rows),
+            None => *num_rows,
+        };
+        Ok(Some((first, end)))
+    }
+}
+
+impl Stream for MergeStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let result = self.next_batch().map_err(|e| match e {
+            Error::Memory { source } => ArrowError::MemoryError(source.to_string()),
+            e => ArrowError::IoError(e.to_string()),
+        });
+        Poll::Ready(result.transpose())
+    }
+}
+
+impl RecordBatchStream for MergeStream {
+    fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+}
+
+impl Drop for MergeStream {
+    fn drop(&mut self) {
+        let reserved = self.runs.iter().map(|run| run.reserved).sum::<usize>();
+        self.budget.release(self.produced + reserved);
+    }
+}
+
+/// Returns the group columns of `batch`, at `group_indices`
+fn group_columns<'a>(
+    schema: &SchemaRef,
+    batch: &'a RecordBatch,
+    group_indices: &[usize],
+) -> Result<Vec<&'a StringArray>> {
+    group_indices
+        .iter()
+        .map(|&index| {
+            batch
+                .column(index)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .context(InvalidGroupColumn {
+                    column_name: schema.field(index).name(),
+                })
+        })
+        .collect()
+}
+
+/// Returns the group of `row`, as the values of the group `columns`.
+/// Groups are ordered as the rows are sorted, with nulls first.
+fn group_key<'a>(columns: &[&'a StringArray], row: usize) -> Vec<Option<&'a str>> {
+    columns
+        .iter()
+        .map(|column| {
+            if column.is_null(row) {
+                None
+            } else {
+                Some(column.value(row))
+            }
+        })
+        .collect()
+}
+
+fn open_spill_file(path: &Path) -> Result<StreamReader<BufReader<File>>> {
+    let file = File::open(path).context(OpeningSpillFile { path })?;
+    StreamReader::try_new(BufReader::new(file)).context(ReadingSpillFile)
+}
+
+/// Returns the `rows` of `batch`, in that order
+fn take_rows(batch: &RecordBatch, rows: Vec<u32>) -> ArrowResult<RecordBatch> {
+    let rows = UInt32Array::from(rows);
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column, &rows, None))
+        .collect::<ArrowResult<Vec<_>>>()?;
+    RecordBatch::try_new(batch.schema(), columns)
+}
+
+/// Combines the rows of `batches` into a single batch
+fn concat_batches(schema: &SchemaRef, batches: &[RecordBatch]) -> ArrowResult<RecordBatch> {
+    let columns = (0..schema.fields().len())
+        .map(|index| {
+            let arrays = batches
+                .iter()
+                .map(|batch| Arc::clone(batch.column(index)))
+                .collect::<Vec<ArrayRef>>();
+            concat(&arrays)
+        })
+        .collect::<ArrowResult<Vec<_>>>()?;
+    RecordBatch::try_new(Arc::clone(schema), columns)
+}
+
+/// Combines `batches`, sorting their rows on the columns at
+/// `sort_indices`
+fn sort_batches(
+    schema: &SchemaRef,
+    sort_indices: &[usize],
+    batches: &[RecordBatch],
+) -> ArrowResult<RecordBatch> {
+    let batch = concat_batches(schema, batches)?;
+
+    // ascending with nulls first, as the sort of the plan
+    let sort_columns = sort_indices
+        .iter()
+        .map(|&index| SortColumn {
+            values: Arc::clone(batch.column(index)),
+            options: None,
+        })
+        .collect::<Vec<_>>();
+    let indices = lexsort_to_indices(&sort_columns)?;
+
+    let columns = batch
+        .columns()
+        .iter()
+        .map(|column| take(column, &indices, None))
+        .collect::<ArrowResult<Vec<_>>>()?;
+    RecordBatch::try_new(Arc::clone(schema), columns)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{
+        array::Int64Array,
+        datatypes::{DataType, Field, Schema},
+    };
+    use tokio::stream::StreamExt;
+
+    fn make_schema() -> SchemaRef {
+        Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("time", DataType::Int64, false),
+        ]))
+    }
+
+    fn make_batch(schema: &SchemaRef, hosts: Vec<&str>, times: Vec<i64>) -> RecordBatch {
+        RecordBatch::try_new(
+            Arc::clone(schema),
+            vec![
+                Arc::new(StringArray::from(hosts)),
+                Arc::new(Int64Array::from(times)),
+            ],
+        )
+        .unwrap()
+    }
+
+    fn make_group_by(budget: &Arc<MemoryBudget>, spill_dir: &std::path::Path) -> SpillingGroupBy {
+        let sort_columns = vec![Arc::new("host".to_string()), Arc::new("time".to_string())];
+        let mut group_by = SpillingGroupBy::try_new(
+            make_schema(),
+            &sort_columns,
+            1,
+            Arc::clone(budget),
+            spill_dir,
+        )
+        .unwrap();
+
+        let schema = make_schema();
+        for time in (0..8).rev() {
+            group_by
+                .push(make_batch(
+                    &schema,
+                    vec!["b", "a", "c"],
+                    vec![time, time, time],
+                ))
+                .unwrap();
+        }
+        group_by
+    }
+
+    /// Returns the (host, time) of each row produced by `stream`
+    async fn collect_rows(
+        stream: impl Stream<Item = ArrowResult<RecordBatch>> + Unpin,
+    ) -> Vec<(String, i64)> {
+        let results = stream
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<ArrowResult<Vec<_>>>()
+            .unwrap();
+
+        let mut rows = vec![];
+        for batch in &results {
+            let hosts = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .unwrap();
+            let times = batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<Int64Array>()
+                .unwrap();
+            for row in 0..batch.num_rows() {
+                rows.push((hosts.value(row).to_string(), times.value(row)));
+            }
+        }
+        rows
+    }
+
+    #[tokio::test]
+    async fn test_group_by_without_spilling() {
+        let budget = Arc::new(MemoryBudget::new(None));
+        let group_by = make_group_by(&budget, std::path::Path::new("/nonexistent"));
+        assert!(group_by.temp_dir.is_none());
+
+        // without spilling, the groups are in order
+        let mut expected = vec![];
+        for host in &["a", "b", "c"] {
+            expected.extend((0..8).map(|time| (host.to_string(), time)));
+        }
+        assert_eq!(collect_rows(group_by.finish().unwrap()).await, expected);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_group_by_with_spilling() {
+        // Measure the memory of all the rows, and allow a bit more than
+        // that, so the rows are spilled once more than three quarters of
+        // them are buffered
+        let budget = Arc::new(MemoryBudget::new(None));
+        let size = make_group_by(&budget, std::path::Path::new("/nonexistent"))
+            .budget
+            .used();
+        let budget = Arc::new(MemoryBudget::new(Some(size + size / 2)));
+
+        let spill_dir = tempfile::tempdir().unwrap();
+        let group_by = make_group_by(&budget, spill_dir.path());
+        assert!(group_by.temp_dir.is_some());
+        let rows = collect_rows(group_by.finish().unwrap()).await;
+        assert_eq!(rows.len(), 24);
+
+        // the runs of the partitions are merged, so the groups are in
+        // order just the same
+        let mut expected = vec![];
+        for host in &["a", "b", "c"] {
+            expected.extend((0..8).map(|time| (host.to_string(), time)));
+        }
+        assert_eq!(rows, expected);
+
+        // the spill files are removed, and their memory released, once
+        // the rows have been produced
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_group_by_divides_large_partitions_again() {
+        let schema = make_schema();
+        let sort_columns = vec![Arc::new("host".to_string()), Arc::new("time".to_string())];
+        let new_group_by = |budget: &Arc<MemoryBudget>, spill_dir: &std::path::Path| {
+            SpillingGroupBy::try_new(
+                make_schema(),
+                &sort_columns,
+                1,
+                Arc::clone(budget),
+                spill_dir,
+            )
+            .unwrap()
+        };
+
+        // most of the rows are of hosts in the first partition, which
+        // takes more than half the budget
+        let budget = Arc::new(MemoryBudget::new(None));
+        let group_by = new_group_by(&budget, std::path::Path::new("/nonexistent"));
+        let hosts: Vec<_> = (0..)
+            .map(|i| format!("host{:03}", i))
+            .filter(|host| group_by.partition_of(&[Some(host.as_str())]) == 0)
+            .take(32)
+            .collect();
+        let others: Vec<_> = (0..)
+            .map(|i| format!("other{:03}", i))
+            .filter(|host| group_by.partition_of(&[Some(host.as_str())]) != 0)
+            .take(3)
+            .collect();
+
+        let push_rows = |group_by: &mut SpillingGroupBy| {
+            for times in (0..2048).collect::<Vec<i64>>().rchunks(32) {
+                let mut batch_hosts = vec![];
+                let mut batch_times = vec![];
+                for host in &hosts {
+                    batch_hosts.extend(times.iter().map(|_| host.as_str()));
+                    batch_times.extend_from_slice(times);
+                }
+                group_by
+                    .push(make_batch(&schema, batch_hosts, batch_times))
+                    .unwrap();
+            }
+            let other_hosts = others.iter().map(|host| host.as_str()).collect();
+            group_by
+                .push(make_batch(&schema, other_hosts, vec![0, 0, 0]))
+                .unwrap();
+        };
+
+        let mut group_by = new_group_by(&budget, std::path::Path::new("/nonexistent"));
+        push_rows(&mut group_by);
+        let size = budget.used();
+
+        let budget = Arc::new(MemoryBudget::new(Some(size / 2)));
+        let spill_dir = tempfile::tempdir().unwrap();
+        let mut group_by = new_group_by(&budget, spill_dir.path());
+        push_rows(&mut group_by);
+        assert!(group_by.temp_dir.is_some());
+
+        // the first partition is sorted as several runs
+        let merge = group_by.into_merge().unwrap();
+        assert!(merge.runs.len() > 4, "{} runs", merge.runs.len());
+
+        let rows = collect_rows(merge).await;
+
+        let mut expected = vec![];
+        for host in &hosts {
+            expected.extend((0..2048).map(|time| (host.clone(), time)));
+        }
+        for host in &others {
+            expected.push((host.clone(), 0));
+        }
+        expected.sort();
+        assert_eq!(rows.len(), expected.len());
+        assert!(rows == expected);
+
+        assert_eq!(std::fs::read_dir(spill_dir.path()).unwrap().count(), 0);
+        assert_eq!(budget.used(), 0);
+    }
+}
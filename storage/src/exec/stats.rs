@@ -0,0 +1,110 @@
+//! This module contains the statistics collected about the cost of
+//! running a single query, which are returned to clients alongside the
+//! results so they can track the server side cost of each query.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The name under which each statistic is returned to clients, e.g.
+/// in the trailers of a gRPC response
+pub const CHUNKS_SCANNED: &str = "iox-chunks-scanned";
+pub const CHUNKS_PRUNED: &str = "iox-chunks-pruned";
+pub const ROWS_SCANNED: &str = "iox-rows-scanned";
+pub const FRAMES_EMITTED: &str = "iox-frames-emitted";
+pub const PEAK_MEMORY_BYTES: &str = "iox-peak-memory-bytes";
+
+/// Statistics about the cost of running a single query. They are
+/// updated by the tasks that plan and execute the query, and are
+/// complete once all of its results have been produced.
+#[derive(Debug, Default)]
+pub struct QueryStats {
+    chunks_scanned: AtomicU64,
+    chunks_pruned: AtomicU64,
+    rows_scanned: AtomicU64,
+    frames_emitted: AtomicU64,
+    peak_memory_bytes: AtomicU64,
+}
+
+impl QueryStats {
+    /// Records the number of chunks the query read data from, and the
+    /// number it skipped because they could not contain matching data
+    pub fn record_chunks(&self, scanned: u64, pruned: u64) {
+        self.chunks_scanned.fetch_add(scanned, Ordering::Relaxed);
+        self.chunks_pruned.fetch_add(pruned, Ordering::Relaxed);
+    }
+
+    /// Records that the plans of the query produced `rows` more rows
+    pub fn add_rows_scanned(&self, rows: u64) {
+        self.rows_scanned.fetch_add(rows, Ordering::Relaxed);
+    }
+
+    /// Records that `frames` more frames were sent to the client
+    pub fn add_frames_emitted(&self, frames: u64) {
+        self.frames_emitted.fetch_add(frames, Ordering::Relaxed);
+    }
+
+    /// Records that the query held `bytes` of results in memory at
+    /// once, which becomes the peak if it is more than any before
+    pub fn record_memory(&self, bytes: u64) {
+        self.peak_memory_bytes.fetch_max(bytes, Ordering::Relaxed);
+    }
+
+    pub fn chunks_scanned(&self) -> u64 {
+        self.chunks_scanned.load(Ordering::Relaxed)
+    }
+
+    pub fn chunks_pruned(&self) -> u64 {
+        self.chunks_pruned.load(Ordering::Relaxed)
+    }
+
+    pub fn rows_scanned(&self) -> u64 {
+        self.rows_scanned.load(Ordering::Relaxed)
+    }
+
+    pub fn frames_emitted(&self) -> u64 {
+        self.frames_emitted.load(Ordering::Relaxed)
+    }
+
+    pub fn peak_memory_bytes(&self) -> u64 {
+        self.peak_memory_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Returns each statistic along with the name it is returned to
+    /// clients under
+    pub fn named_values(&self) -> Vec<(&'static str, u64)> {
+        vec![
+            (CHUNKS_SCANNED, self.chunks_scanned()),
+            (CHUNKS_PRUNED, self.chunks_pruned()),
+            (ROWS_SCANNED, self.rows_scanned()),
+            (FRAMES_EMITTED, self.frames_emitted()),
+            (PEAK_MEMORY_BYTES, self.peak_memory_bytes()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_stats() {
+        let stats = QueryStats::default();
+        stats.record_chunks(3, 1);
+        stats.record_chunks(1, 1);
+        stats.add_rows_scanned(100);
+        stats.add_frames_emitted(4);
+        stats.add_frames_emitted(2);
+        stats.record_memory(1024);
+        stats.record_memory(512);
+
+        assert_eq!(
+            stats.named_values(),
+            vec![
+                (CHUNKS_SCANNED, 4),
+                (CHUNKS_PRUNED, 2),
+                (ROWS_SCANNED, 100),
+                (FRAMES_EMITTED, 6),
+                (PEAK_MEMORY_BYTES, 1024),
+            ]
+        );
+    }
+}
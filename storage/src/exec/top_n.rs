@@ -0,0 +1,255 @@
+//! This module contains the selection of the top (or bottom) `k`
+//! series of a group, ranked by their aggregated values, such as "the
+//! 10 hosts with the highest maximum cpu usage".
+//!
+//! Series are ranked as they arrive using a heap bounded to `k`
+//! entries, so only the selected series are kept in memory rather
+//! than every series of the group.
+
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+    sync::Arc,
+};
+
+use super::window_aggregate::{Aggregate, WindowedField, WindowedSeriesSet, WindowedValues};
+
+/// Whether the series with the highest or lowest values are selected
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    Top,
+    Bottom,
+}
+
+/// Selects the `k` series with the highest (or lowest) values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TopN {
+    order: Order,
+    k: usize,
+}
+
+impl TopN {
+    pub fn new(order: Order, k: usize) -> Self {
+        Self { order, k }
+    }
+
+    pub fn order(&self) -> Order {
+        self.order
+    }
+
+    pub fn k(&self) -> usize {
+        self.k
+    }
+
+    /// The aggregate the series are ranked by: the maximum value of a
+    /// series when selecting the top series, the minimum otherwise
+    pub fn aggregate(&self) -> Aggregate {
+        match self.order {
+            Order::Top => Aggregate::Max,
+            Order::Bottom => Aggregate::Min,
+        }
+    }
+
+    /// Starts selecting the series of a new group
+    pub fn select(&self) -> SelectedSeries {
+        SelectedSeries {
+            top_n: *self,
+            heap: BinaryHeap::new(),
+            next_sequence: 0,
+        }
+    }
+}
+
+/// The series selected so far from the series of a group
+#[derive(Debug)]
+pub struct SelectedSeries {
+    top_n: TopN,
+    /// The selected series, with the lowest ranked at the top of the heap
+    heap: BinaryHeap<Reverse<RankedSeries>>,
+    next_sequence: usize,
+}
+
+impl SelectedSeries {
+    /// Considers each field of `series_set` for selection. Fields
+    /// without numeric values are never selected.
+    pub fn push(&mut self, series_set: WindowedSeriesSet) {
+        let WindowedSeriesSet {
+            table_name,
+            tags,
+            timestamps,
+            fields,
+        } = series_set;
+
+        for field in fields {
+            let score = match self.score(&field.values) {
+                Some(score) => score,
+                None => continue,
+            };
+
+            let ranked = RankedSeries {
+                score,
+                sequence: self.next_sequence,
+                field,
+                table_name: Arc::clone(&table_name),
+                tags: tags.clone(),
+                timestamps: timestamps.clone(),
+            };
+            self.next_sequence += 1;
+
+            self.heap.push(Reverse(ranked));
+            if self.heap.len() > self.top_n.k {
+                self.heap.pop();
+            }
+        }
+    }
+
+    /// Returns the selected series, each with a single field, best
+    /// ranked first
+    pub fn finish(self) -> Vec<WindowedSeriesSet> {
+        self.heap
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(ranked)| WindowedSeriesSet {
+                table_name: ranked.table_name,
+                tags: ranked.tags,
+                timestamps: ranked.timestamps,
+                fields: vec![ranked.field],
+            })
+            .collect()
+    }
+
+    /// Returns the score of `values`, where higher scores are ranked
+    /// first, or `None` if there are no (non NaN) numeric values
+    fn score(&self, values: &WindowedValues) -> Option<f64> {
+        let values: Vec<f64> = match values {
+            WindowedValues::F64(values) => values.iter().flatten().cloned().collect(),
+            WindowedValues::I64(values) => values.iter().flatten().map(|&v| v as f64).collect(),
+            WindowedValues::Bool(_) | WindowedValues::String(_) => return None,
+        };
+
+        let values = values.into_iter().filter(|v| !v.is_nan());
+        match self.top_n.order {
+            Order::Top => values.fold(None, |max, v| Some(max.map_or(v, |max: f64| max.max(v)))),
+            Order::Bottom => values
+                .fold(None, |min, v| Some(min.map_or(v, |min: f64| min.min(v))))
+                .map(|min| -min),
+        }
+    }
+}
+
+/// A field of a series with the score it is ranked by
+#[derive(Debug)]
+struct RankedSeries {
+    score: f64,
+    /// The order the series was pushed in, so that earlier series are
+    /// ranked first when they have the same score
+    sequence: usize,
+    field: WindowedField,
+    table_name: Arc<String>,
+    tags: Vec<(Arc<String>, Arc<String>)>,
+    timestamps: Vec<i64>,
+}
+
+impl Ord for RankedSeries {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // scores are never NaN
+        self.score
+            .partial_cmp(&other.score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for RankedSeries {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for RankedSeries {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for RankedSeries {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn make_series(host: &str, values: WindowedValues) -> WindowedSeriesSet {
+        WindowedSeriesSet {
+            table_name: Arc::new("cpu".into()),
+            tags: vec![(Arc::new("host".into()), Arc::new(host.into()))],
+            timestamps: vec![10, 20],
+            fields: vec![WindowedField {
+                name: "usage".into(),
+                values,
+            }],
+        }
+    }
+
+    fn selected_hosts(selected: SelectedSeries) -> Vec<String> {
+        selected
+            .finish()
+            .into_iter()
+            .map(|series| series.tags[0].1.to_string())
+            .collect()
+    }
+
+    fn push_all(selected: &mut SelectedSeries) {
+        selected.push(make_series(
+            "a",
+            WindowedValues::F64(vec![Some(1.0), Some(5.0)]),
+        ));
+        selected.push(make_series("b", WindowedValues::I64(vec![None, Some(9)])));
+        selected.push(make_series("c", WindowedValues::F64(vec![None, None])));
+        selected.push(make_series(
+            "d",
+            WindowedValues::F64(vec![Some(-2.0), Some(f64::NAN)]),
+        ));
+        selected.push(make_series(
+            "e",
+            WindowedValues::F64(vec![Some(5.0), Some(3.0)]),
+        ));
+        selected.push(make_series(
+            "f",
+            WindowedValues::String(vec![Some("x".into()), None]),
+        ));
+    }
+
+    #[test]
+    fn test_top() {
+        let top_n = TopN::new(Order::Top, 3);
+        assert_eq!(top_n.aggregate(), Aggregate::Max);
+
+        let mut selected = top_n.select();
+        push_all(&mut selected);
+
+        // a and e have the same score, and a was pushed first
+        assert_eq!(selected_hosts(selected), vec!["b", "a", "e"]);
+    }
+
+    #[test]
+    fn test_bottom() {
+        let top_n = TopN::new(Order::Bottom, 2);
+        assert_eq!(top_n.aggregate(), Aggregate::Min);
+
+        let mut selected = top_n.select();
+        push_all(&mut selected);
+
+        assert_eq!(selected_hosts(selected), vec!["d", "a"]);
+    }
+
+    #[test]
+    fn test_fewer_series_than_k() {
+        let mut selected = TopN::new(Order::Top, 10).select();
+        push_all(&mut selected);
+        assert_eq!(selected_hosts(selected), vec!["b", "a", "e", "d"]);
+
+        let mut selected = TopN::new(Order::Top, 0).select();
+        push_all(&mut selected);
+        assert!(selected.finish().is_empty());
+    }
+}
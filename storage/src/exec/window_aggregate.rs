@@ -0,0 +1,1429 @@
+//! This module contains the window executor, which computes an
+//! aggregate of the points of each series within each window of
+//! time, as requested by `read_window_aggregate`.
+//!
+//! By default only windows that contain points are reported. A `Fill`
+//! policy can instead report every window of the queried range, so
+//! that clients receive a value for each window bucket even when no
+//! points were written in it.
+//!
+//! Windows of months follow the calendar, so vary in length. They, and
+//! windows of days, can be aligned to a time zone, so that they start
+//! at local midnight, and are shorter or longer when the clocks change.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use arrow_deps::arrow::{
+    array::{Array, ArrayRef, BooleanArray, Float64Array, Int64Array, StringArray},
+    datatypes::DataType,
+};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+
+use super::seriesset::SeriesSet;
+use crate::{
+    predicate::TimestampRange,
+    window::{Duration, Location, Window},
+};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Invalid window: every must be a positive number of either months or nanoseconds"
+    ))]
+    InvalidWindow,
+
+    #[snafu(display(
+        "Aggregate {:?} is not supported for {} field '{}'",
+        aggregate,
+        data_type,
+        field_name
+    ))]
+    UnsupportedAggregate {
+        aggregate: Aggregate,
+        data_type: &'static str,
+        field_name: String,
+    },
+
+    #[snafu(display(
+        "{:?} is not supported for {} field '{}'",
+        transform,
+        data_type,
+        field_name
+    ))]
+    UnsupportedTransform {
+        transform: Transform,
+        data_type: &'static str,
+        field_name: String,
+    },
+
+    #[snafu(display("Unsupported data type {:?} of field '{}'", data_type, field_name))]
+    UnsupportedDataType {
+        data_type: DataType,
+        field_name: String,
+    },
+
+    #[snafu(display("Timestamp column of series set is not an Int64 column"))]
+    InvalidTimestampColumn,
+
+    #[snafu(display("Filling windows would produce more than {} windows per series", limit))]
+    TooManyWindows { limit: usize },
+
+    #[snafu(display("Error combining the series of a group: {}", source))]
+    CombiningSeries { source: super::seriesset::Error },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The maximum number of windows reported for each series when
+/// filling windows without points
+const MAX_FILLED_WINDOWS: usize = 1_000_000;
+
+/// The aggregate computed for the points in each window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregate {
+    Sum,
+    Count,
+    Min,
+    Max,
+    First,
+    Last,
+    Mean,
+}
+
+/// How windows that contain no points are reported
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fill {
+    /// Omit the window (the default)
+    None,
+    /// Report the window with a null value
+    Null,
+    /// Report the window with a fixed value. Only applies to numeric
+    /// values, and integer values are truncated
+    Value(f64),
+    /// Report the value of the previous window that has one
+    Previous,
+    /// Report a value linearly interpolated between the surrounding
+    /// windows that have values. Only applies to numeric values
+    Linear,
+}
+
+impl Default for Fill {
+    fn default() -> Self {
+        Self::None
+    }
+}
+
+/// Computes the rate of change of the aggregated values between
+/// consecutive windows that have values. The rates are reported as
+/// floats at the timestamp of the later window, and windows without a
+/// rate (such as the first window) have no value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transform {
+    /// The change in value per `unit` nanoseconds, which must be positive
+    Derivative { unit: i64 },
+    /// As `Derivative`, but leaving out decreases in value
+    NonNegativeDerivative { unit: i64 },
+    /// The increase in value per second of a counter. A decrease in
+    /// value is treated as the counter having been reset to zero.
+    Rate,
+}
+
+/// The aggregated values of a field in each window, `None` for
+/// windows without a value
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowedValues {
+    F64(Vec<Option<f64>>),
+    I64(Vec<Option<i64>>),
+    Bool(Vec<Option<bool>>),
+    String(Vec<Option<String>>),
+}
+
+/// A field of a `WindowedSeriesSet`
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowedField {
+    pub name: String,
+    pub values: WindowedValues,
+}
+
+/// The result of aggregating the points of a `SeriesSet` in each window
+#[derive(Debug)]
+pub struct WindowedSeriesSet {
+    /// The table name this series came from
+    pub table_name: Arc<String>,
+
+    /// key = value pairs that define this series
+    pub tags: Vec<(Arc<String>, Arc<String>)>,
+
+    /// The (exclusive) end of each window, which is the timestamp the
+    /// aggregates of the window are reported at
+    pub timestamps: Vec<i64>,
+
+    /// The values of each field, one per window in `timestamps`
+    pub fields: Vec<WindowedField>,
+}
+
+/// The number, sum, minimum and maximum of the values of a field in a
+/// bucket of time
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bucket<T> {
+    pub count: i64,
+    pub sum: T,
+    pub min: T,
+    pub max: T,
+}
+
+/// The values of a numeric field aggregated in buckets of time, keyed
+/// on the start of each bucket that has values
+#[derive(Debug, Clone, PartialEq)]
+pub enum BucketedValues {
+    F64(BTreeMap<i64, Bucket<f64>>),
+    I64(BTreeMap<i64, Bucket<i64>>),
+}
+
+/// A field of a `BucketedSeries`
+#[derive(Debug, Clone, PartialEq)]
+pub struct BucketedField {
+    pub name: String,
+    pub values: BucketedValues,
+}
+
+/// The points of a series aggregated in buckets of time as they were
+/// written, from which the aggregates of windows made up of whole
+/// buckets are computed without reading the points
+#[derive(Debug, Clone, PartialEq)]
+pub struct BucketedSeries {
+    /// The table name this series came from
+    pub table_name: Arc<String>,
+
+    /// key = value pairs that define this series
+    pub tags: Vec<(Arc<String>, Arc<String>)>,
+
+    /// The start of each bucket the series has points in, in order
+    pub buckets: Vec<i64>,
+
+    /// The values of each field, which need not have values in every
+    /// bucket of `buckets`
+    pub fields: Vec<BucketedField>,
+}
+
+/// The series that pass a predicate, aggregated in buckets of time
+#[derive(Debug, Default)]
+pub struct PreAggregatedSeries {
+    pub series: Vec<BucketedSeries>,
+
+    /// The number of chunks the series were read from
+    pub chunks_scanned: u64,
+
+    /// The number of chunks skipped because they could not contain
+    /// matching data
+    pub chunks_pruned: u64,
+}
+
+/// Describes how to aggregate the points of series in windows of time
+#[derive(Debug, Clone, Copy)]
+pub struct WindowAggregate {
+    window: Window,
+    aggregate: Aggregate,
+    fill: Fill,
+    range: Option<TimestampRange>,
+    transform: Option<Transform>,
+}
+
+impl WindowAggregate {
+    /// Creates a description of windows of `every` (starting at
+    /// `offset`) in which `aggregate` is computed. Windows without
+    /// points are reported according to `fill`, extending to cover
+    /// `range`, if specified.
+    pub fn try_new(
+        every: Duration,
+        offset: Duration,
+        aggregate: Aggregate,
+        fill: Fill,
+        range: Option<TimestampRange>,
+    ) -> Result<Self> {
+        let valid = !every.is_negative() && ((every.months() > 0) != (every.nanoseconds() > 0));
+        ensure!(valid, InvalidWindow);
+
+        Ok(Self {
+            window: Window::new(every, every, offset),
+            aggregate,
+            fill,
+            range,
+            transform: None,
+        })
+    }
+
+    /// Aligns the windows to the local time of `location`, so that
+    /// windows of days and months follow its calendar, including
+    /// changes for daylight saving time
+    pub fn with_location(self, location: Location) -> Self {
+        Self {
+            window: self.window.with_location(location),
+            ..self
+        }
+    }
+
+    /// Applies `transform` to the aggregated (and filled) values of
+    /// each window
+    pub fn with_transform(self, transform: Transform) -> Self {
+        Self {
+            transform: Some(transform),
+            ..self
+        }
+    }
+
+    pub fn aggregate(&self) -> Aggregate {
+        self.aggregate
+    }
+
+    pub fn fill(&self) -> Fill {
+        self.fill
+    }
+
+    pub fn transform(&self) -> Option<Transform> {
+        self.transform
+    }
+
+    /// Computes the aggregate of each field of `series_set` in each window
+    pub fn apply(&self, series_set: &SeriesSet) -> Result<WindowedSeriesSet> {
+        let batch = &series_set.batch;
+        let schema = batch.schema();
+
+        let times = batch
+            .column(series_set.timestamp_index)
+            .as_any()
+            .downcast_ref::<Int64Array>()
+            .context(InvalidTimestampColumn)?;
+
+        // the rows in each window that has points, keyed on the
+        // start of the window, and in time order
+        let mut windows: BTreeMap<i64, (i64, Vec<usize>)> = BTreeMap::new();
+        let end_row = series_set.start_row + series_set.num_rows;
+        for row in series_set.start_row..end_row {
+            if times.is_null(row) {
+                continue;
+            }
+
+            let bounds = self.window.get_earliest_bounds(times.value(row));
+            windows
+                .entry(bounds.start())
+                .or_insert_with(|| (bounds.stop(), vec![]))
+                .1
+                .push(row);
+        }
+        for (_, rows) in windows.values_mut() {
+            rows.sort_by_key(|&row| times.value(row));
+        }
+
+        let bounds = self.window_bounds(&windows)?;
+        let timestamps = bounds.iter().map(|&(_, stop)| stop).collect::<Vec<_>>();
+
+        let no_rows = vec![];
+        let rows = bounds
+            .iter()
+            .map(|(start, _)| windows.get(start).map_or(&no_rows, |(_, rows)| rows))
+            .collect::<Vec<_>>();
+
+        let fields = series_set
+            .field_indices
+            .iter()
+            .map(|&index| {
+                let name = schema.field(index).name().to_string();
+                let values = aggregate_column(batch.column(index), &rows, self.aggregate, &name)?;
+                self.windowed_field(name, values, &timestamps)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(WindowedSeriesSet {
+            table_name: Arc::clone(&series_set.table_name),
+            tags: series_set.tags.clone(),
+            timestamps,
+            fields,
+        })
+    }
+
+    /// Computes the aggregate of each field in each window across the
+    /// points of all of `series_sets`, as if they were a single series.
+    /// They must not be empty, and must all be of the same table, with
+    /// the same schema. The result has the tags all of them have in
+    /// common, such as the tags they are grouped by.
+    pub fn apply_to_group(&self, series_sets: Vec<SeriesSet>) -> Result<WindowedSeriesSet> {
+        let mut tags = series_sets[0].tags.clone();
+        for series_set in &series_sets[1..] {
+            tags.retain(|tag| series_set.tags.contains(tag));
+        }
+
+        let combined = SeriesSet::concat(series_sets).context(CombiningSeries)?;
+        let mut windowed_series_set = self.apply(&combined)?;
+        windowed_series_set.tags = tags;
+        Ok(windowed_series_set)
+    }
+
+    /// Returns true if the aggregate of each window can be computed
+    /// from the aggregates of buckets of `width` nanoseconds, as every
+    /// window is made up of whole buckets
+    pub fn can_apply_to_buckets(&self, width: i64) -> bool {
+        use Aggregate::*;
+
+        matches!(self.aggregate, Sum | Count | Min | Max | Mean) && self.window.is_aligned_to(width)
+    }
+
+    /// Computes the aggregate of each field of `series` in each window
+    /// from the aggregates of its buckets, which gives the same windows
+    /// as `apply` would for the points of the series if
+    /// `can_apply_to_buckets` is true for the width of the buckets
+    pub fn apply_to_buckets(&self, series: &BucketedSeries) -> Result<WindowedSeriesSet> {
+        // the buckets in each window that has points, keyed on the
+        // start of the window
+        let mut windows: BTreeMap<i64, (i64, Vec<i64>)> = BTreeMap::new();
+        for &bucket in &series.buckets {
+            let bounds = self.window.get_earliest_bounds(bucket);
+            windows
+                .entry(bounds.start())
+                .or_insert_with(|| (bounds.stop(), vec![]))
+                .1
+                .push(bucket);
+        }
+
+        let bounds = self.window_bounds(&windows)?;
+        let timestamps = bounds.iter().map(|&(_, stop)| stop).collect::<Vec<_>>();
+
+        let no_buckets = vec![];
+        let buckets = bounds
+            .iter()
+            .map(|(start, _)| {
+                windows
+                    .get(start)
+                    .map_or(&no_buckets, |(_, buckets)| buckets)
+            })
+            .collect::<Vec<_>>();
+
+        let fields = series
+            .fields
+            .iter()
+            .map(|field| {
+                let values =
+                    aggregate_buckets(&field.values, &buckets, self.aggregate, &field.name)?;
+                self.windowed_field(field.name.clone(), values, &timestamps)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(WindowedSeriesSet {
+            table_name: Arc::clone(&series.table_name),
+            tags: series.tags.clone(),
+            timestamps,
+            fields,
+        })
+    }
+
+    /// Fills and transforms the aggregated `values` of the field `name`,
+    /// where `timestamps` are the timestamps of each window
+    fn windowed_field(
+        &self,
+        name: String,
+        mut values: WindowedValues,
+        timestamps: &[i64],
+    ) -> Result<WindowedField> {
+        values.fill(self.fill, timestamps);
+        if let Some(transform) = self.transform {
+            values = transform.apply(values, timestamps, &name)?;
+        }
+        Ok(WindowedField { name, values })
+    }
+
+    /// Returns the (start, stop) of each window to report, given the
+    /// windows that have points
+    fn window_bounds<T>(&self, windows: &BTreeMap<i64, (i64, T)>) -> Result<Vec<(i64, i64)>> {
+        if self.fill == Fill::None {
+            return Ok(windows
+                .iter()
+                .map(|(&start, &(stop, _))| (start, stop))
+                .collect());
+        }
+
+        let (mut first, mut last) = match (windows.keys().next(), windows.keys().next_back()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return Ok(vec![]),
+        };
+
+        if let Some(range) = self.range {
+            if range.start < range.end {
+                ensure!(
+                    self.estimated_windows(range) <= MAX_FILLED_WINDOWS as i128,
+                    TooManyWindows {
+                        limit: MAX_FILLED_WINDOWS
+                    }
+                );
+
+                first = first.min(self.window.get_earliest_bounds(range.start).start());
+                last = last.max(self.window.get_earliest_bounds(range.end - 1).start());
+            }
+        }
+
+        let mut bounds = vec![];
+        let mut window = self.window.get_earliest_bounds(first);
+        while window.start() <= last {
+            ensure!(
+                bounds.len() < MAX_FILLED_WINDOWS,
+                TooManyWindows {
+                    limit: MAX_FILLED_WINDOWS
+                }
+            );
+            bounds.push((window.start(), window.stop()));
+            window = self.window.get_earliest_bounds(window.stop());
+        }
+
+        Ok(bounds)
+    }
+
+    /// Returns (an upper bound of) the number of windows in `range`
+    fn estimated_windows(&self, range: TimestampRange) -> i128 {
+        const SHORTEST_MONTH_NANOS: i64 = 28 * 24 * 60 * 60 * 1_000_000_000;
+
+        let every = self.window.every();
+        let window_nanos = every.nanoseconds() + every.months() * SHORTEST_MONTH_NANOS;
+        (i128::from(range.end) - i128::from(range.start)) / i128::from(window_nanos) + 1
+    }
+}
+
+/// Computes `aggregate` of the non null values of `array` in each
+/// window, where `rows` are the rows in each window. Float values that
+/// are NaN or infinite are excluded from every aggregate, as if they
+/// were null, as they are from pre-aggregates.
+fn aggregate_column(
+    array: &ArrayRef,
+    rows: &[&Vec<usize>],
+    aggregate: Aggregate,
+    field_name: &str,
+) -> Result<WindowedValues> {
+    use Aggregate::*;
+
+    let unsupported = |data_type: &'static str| -> Result<WindowedValues> {
+        UnsupportedAggregate {
+            aggregate,
+            data_type,
+            field_name,
+        }
+        .fail()
+    };
+
+    Ok(match array.data_type() {
+        DataType::Float64 => {
+            let array = array.as_any().downcast_ref::<Float64Array>().unwrap();
+            let mut windows = window_values(array, rows, |row| array.value(row));
+            for values in &mut windows {
+                values.retain(|value| value.is_finite());
+            }
+            match aggregate {
+                Count => WindowedValues::I64(count(&windows)),
+                Sum => WindowedValues::F64(reduce(windows, |values| values.iter().sum())),
+                Min => WindowedValues::F64(reduce(windows, |values| {
+                    values.iter().cloned().fold(f64::INFINITY, f64::min)
+                })),
+                Max => WindowedValues::F64(reduce(windows, |values| {
+                    values.iter().cloned().fold(f64::NEG_INFINITY, f64::max)
+                })),
+                First => WindowedValues::F64(reduce(windows, |values| values[0])),
+                Last => WindowedValues::F64(reduce(windows, |values| values[values.len() - 1])),
+                Mean => WindowedValues::F64(reduce(windows, |values| {
+                    values.iter().sum::<f64>() / values.len() as f64
+                })),
+            }
+        }
+        DataType::Int64 => {
+            let array = array.as_any().downcast_ref::<Int64Array>().unwrap();
+            let windows = window_values(array, rows, |row| array.value(row));
+            match aggregate {
+                Count => WindowedValues::I64(count(&windows)),
+                Sum => WindowedValues::I64(reduce(windows, |values| {
+                    values.iter().fold(0i64, |sum, &v| sum.saturating_add(v))
+                })),
+                Min => WindowedValues::I64(reduce(windows, |values| {
+                    values.iter().cloned().min().unwrap()
+                })),
+                Max => WindowedValues::I64(reduce(windows, |values| {
+                    values.iter().cloned().max().unwrap()
+                })),
+                First => WindowedValues::I64(reduce(windows, |values| values[0])),
+                Last => WindowedValues::I64(reduce(windows, |values| values[values.len() - 1])),
+                Mean => WindowedValues::F64(reduce(windows, |values| {
+                    values.iter().map(|&v| v as f64).sum::<f64>() / values.len() as f64
+                })),
+            }
+        }
+        DataType::Boolean => {
+            let array = array.as_any().downcast_ref::<BooleanArray>().unwrap();
+            let windows = window_values(array, rows, |row| array.value(row));
+            match aggregate {
+                Count => WindowedValues::I64(count(&windows)),
+                First => WindowedValues::Bool(reduce(windows, |values| values[0])),
+                Last => WindowedValues::Bool(reduce(windows, |values| values[values.len() - 1])),
+                _ => return unsupported("boolean"),
+            }
+        }
+        DataType::Utf8 => {
+            let array = array.as_any().downcast_ref::<StringArray>().unwrap();
+            let windows = window_values(array, rows, |row| array.value(row).to_string());
+            match aggregate {
+                Count => WindowedValues::I64(count(&windows)),
+                First => WindowedValues::String(reduce(windows, |mut values| values.remove(0))),
+                Last => WindowedValues::String(reduce(windows, |mut values| values.pop().unwrap())),
+                _ => return unsupported("string"),
+            }
+        }
+        data_type => {
+            return UnsupportedDataType {
+                data_type: data_type.clone(),
+                field_name,
+            }
+            .fail()
+        }
+    })
+}
+
+/// Computes `aggregate` of the values of the buckets in each window,
+/// where `windows` are the starts of the buckets in each window
+fn aggregate_buckets(
+    values: &BucketedValues,
+    windows: &[&Vec<i64>],
+    aggregate: Aggregate,
+    field_name: &str,
+) -> Result<WindowedValues> {
+    use Aggregate::*;
+
+    let unsupported = || -> Result<WindowedValues> {
+        UnsupportedAggregate {
+            aggregate,
+            data_type: "pre-aggregated",
+            field_name,
+        }
+        .fail()
+    };
+
+    Ok(match values {
+        BucketedValues::F64(buckets) => {
+            let windows = window_buckets(buckets, windows);
+            match aggregate {
+                Count => WindowedValues::I64(count_buckets(&windows)),
+                Sum => WindowedValues::F64(reduce(windows, |buckets| {
+                    buckets.iter().map(|b| b.sum).sum()
+                })),
+                Min => WindowedValues::F64(reduce(windows, |buckets| {
+                    buckets.iter().map(|b| b.min).fold(f64::INFINITY, f64::min)
+                })),
+                Max => WindowedValues::F64(reduce(windows, |buckets| {
+                    buckets
+                        .iter()
+                        .map(|b| b.max)
+                        .fold(f64::NEG_INFINITY, f64::max)
+                })),
+                Mean => WindowedValues::F64(reduce(windows, |buckets| {
+                    let sum = buckets.iter().map(|b| b.sum).sum::<f64>();
+                    sum / buckets.iter().map(|b| b.count).sum::<i64>() as f64
+                })),
+                First | Last => return unsupported(),
+            }
+        }
+        BucketedValues::I64(buckets) => {
+            let windows = window_buckets(buckets, windows);
+            match aggregate {
+                Count => WindowedValues::I64(count_buckets(&windows)),
+                Sum => WindowedValues::I64(reduce(windows, |buckets| {
+                    buckets
+                        .iter()
+                        .fold(0i64, |sum, b| sum.saturating_add(b.sum))
+                })),
+                Min => WindowedValues::I64(reduce(windows, |buckets| {
+                    buckets.iter().map(|b| b.min).min().unwrap()
+                })),
+                Max => WindowedValues::I64(reduce(windows, |buckets| {
+                    buckets.iter().map(|b| b.max).max().unwrap()
+                })),
+                Mean => WindowedValues::F64(reduce(windows, |buckets| {
+                    let sum = buckets.iter().map(|b| b.sum as f64).sum::<f64>();
+                    sum / buckets.iter().map(|b| b.count).sum::<i64>() as f64
+                })),
+                First | Last => return unsupported(),
+            }
+        }
+    })
+}
+
+/// Returns the buckets of `buckets` in each window, where `windows`
+/// are the starts of the buckets in each window
+fn window_buckets<'a, T>(
+    buckets: &'a BTreeMap<i64, Bucket<T>>,
+    windows: &[&Vec<i64>],
+) -> Vec<Vec<&'a Bucket<T>>> {
+    windows
+        .iter()
+        .map(|starts| {
+            starts
+                .iter()
+                .filter_map(|start| buckets.get(start))
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the number of values in the buckets of each window, `None`
+/// for windows without values
+fn count_buckets<T>(windows: &[Vec<&Bucket<T>>]) -> Vec<Option<i64>> {
+    windows
+        .iter()
+        .map(|buckets| match buckets.iter().map(|b| b.count).sum() {
+            0 => None,
+            n => Some(n),
+        })
+        .collect()
+}
+
+/// Returns the non null values of `array` in each window
+fn window_values<T>(
+    array: &dyn Array,
+    rows: &[&Vec<usize>],
+    value: impl Fn(usize) -> T,
+) -> Vec<Vec<T>> {
+    rows.iter()
+        .map(|rows| {
+            rows.iter()
+                .filter(|&&row| !array.is_null(row))
+                .map(|&row| value(row))
+                .collect()
+        })
+        .collect()
+}
+
+/// Returns the number of values in each window, `None` for windows
+/// without values
+fn count<T>(windows: &[Vec<T>]) -> Vec<Option<i64>> {
+    windows
+        .iter()
+        .map(|values| match values.len() {
+            0 => None,
+            n => Some(n as i64),
+        })
+        .collect()
+}
+
+/// Reduces the (non empty) values of each window with `f`, `None`
+/// for windows without values
+fn reduce<T, U>(windows: Vec<Vec<T>>, f: impl Fn(Vec<T>) -> U) -> Vec<Option<U>> {
+    windows
+        .into_iter()
+        .map(|values| {
+            if values.is_empty() {
+                None
+            } else {
+                Some(f(values))
+            }
+        })
+        .collect()
+}
+
+impl Transform {
+    /// Computes the rates of change of `values`, where `timestamps`
+    /// are the timestamps of each window
+    fn apply(
+        &self,
+        values: WindowedValues,
+        timestamps: &[i64],
+        field_name: &str,
+    ) -> Result<WindowedValues> {
+        let values = match values {
+            WindowedValues::F64(values) => values,
+            WindowedValues::I64(values) => values
+                .into_iter()
+                .map(|value| value.map(|v| v as f64))
+                .collect(),
+            WindowedValues::Bool(_) => return self.unsupported("boolean", field_name),
+            WindowedValues::String(_) => return self.unsupported("string", field_name),
+        };
+
+        let mut rates = Vec::with_capacity(values.len());
+        let mut previous = None;
+        for (value, &timestamp) in values.into_iter().zip(timestamps) {
+            let rate = match (previous, value) {
+                (Some((previous_timestamp, previous_value)), Some(value)) => {
+                    self.rate(previous_value, value, timestamp - previous_timestamp)
+                }
+                _ => None,
+            };
+            rates.push(rate);
+
+            if let Some(value) = value {
+                previous = Some((timestamp, value));
+            }
+        }
+
+        Ok(WindowedValues::F64(rates))
+    }
+
+    /// Returns the rate of change from `previous` to `value`, which
+    /// are `elapsed` (> 0) nanoseconds apart
+    fn rate(&self, previous: f64, value: f64, elapsed: i64) -> Option<f64> {
+        const NANOS_PER_SECOND: f64 = 1_000_000_000.0;
+
+        let elapsed = elapsed as f64;
+        match *self {
+            Self::Derivative { unit } => Some((value - previous) * unit as f64 / elapsed),
+            Self::NonNegativeDerivative { unit } => {
+                Some((value - previous) * unit as f64 / elapsed).filter(|&rate| rate >= 0.0)
+            }
+            Self::Rate => {
+                let increase = if value < previous {
+                    value
+                } else {
+                    value - previous
+                };
+                Some(increase * NANOS_PER_SECOND / elapsed)
+            }
+        }
+    }
+
+    fn unsupported(&self, data_type: &'static str, field_name: &str) -> Result<WindowedValues> {
+        UnsupportedTransform {
+            transform: *self,
+            data_type,
+            field_name,
+        }
+        .fail()
+    }
+}
+
+impl WindowedValues {
+    /// Fills the windows without values according to `fill`, where
+    /// `timestamps` are the timestamps of each window
+    fn fill(&mut self, fill: Fill, timestamps: &[i64]) {
+        match (self, fill) {
+            (_, Fill::None) | (_, Fill::Null) => {}
+            (Self::F64(values), Fill::Value(value)) => fill_value(values, value),
+            (Self::I64(values), Fill::Value(value)) => fill_value(values, value as i64),
+            (Self::F64(values), Fill::Previous) => fill_previous(values),
+            (Self::I64(values), Fill::Previous) => fill_previous(values),
+            (Self::Bool(values), Fill::Previous) => fill_previous(values),
+            (Self::String(values), Fill::Previous) => fill_previous(values),
+            (Self::F64(values), Fill::Linear) => {
+                fill_linear(values, timestamps, |previous, next, elapsed, total| {
+                    previous + (next - previous) * (elapsed as f64 / total as f64)
+                })
+            }
+            (Self::I64(values), Fill::Linear) => {
+                fill_linear(values, timestamps, |previous, next, elapsed, total| {
+                    let delta = (i128::from(next) - i128::from(previous)) * i128::from(elapsed)
+                        / i128::from(total);
+                    (i128::from(previous) + delta) as i64
+                })
+            }
+            // fixed values and interpolation only apply to numbers
+            (Self::Bool(_), _) | (Self::String(_), _) => {}
+        }
+    }
+}
+
+fn fill_value<T: Copy>(values: &mut [Option<T>], value: T) {
+    for v in values.iter_mut() {
+        v.get_or_insert(value);
+    }
+}
+
+fn fill_previous<T: Clone>(values: &mut [Option<T>]) {
+    let mut previous = None;
+    for value in values.iter_mut() {
+        if value.is_some() {
+            previous = value.clone();
+        } else {
+            *value = previous.clone();
+        }
+    }
+}
+
+/// Fills the windows between each pair of windows with values using
+/// `interpolate(previous, next, elapsed, total)`, where `elapsed` is
+/// the time since the previous window and `total` the time between
+/// the previous and next windows
+fn fill_linear<T: Copy>(
+    values: &mut [Option<T>],
+    timestamps: &[i64],
+    interpolate: impl Fn(T, T, i64, i64) -> T,
+) {
+    let known = values
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| value.is_some())
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    for pair in known.windows(2) {
+        let (previous, next) = (pair[0], pair[1]);
+        let (previous_value, next_value) = match (values[previous], values[next]) {
+            (Some(previous_value), Some(next_value)) => (previous_value, next_value),
+            _ => continue,
+        };
+        let total = timestamps[next] - timestamps[previous];
+
+        let gap = values[previous + 1..next]
+            .iter_mut()
+            .zip(&timestamps[previous + 1..next]);
+        for (value, &timestamp) in gap {
+            let elapsed = timestamp - timestamps[previous];
+            *value = Some(interpolate(previous_value, next_value, elapsed, total));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::{
+        datatypes::{Field, Schema},
+        record_batch::RecordBatch,
+    };
+
+    const MINUTE: i64 = 60_000_000_000;
+
+    /// Returns a series set with `a` (float), `b` (integer) and `c`
+    /// (string) fields and points at 0, 30s, 2m and 4m30s
+    fn make_series_set() -> SeriesSet {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("host", DataType::Utf8, true),
+            Field::new("a", DataType::Float64, true),
+            Field::new("b", DataType::Int64, true),
+            Field::new("c", DataType::Utf8, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(vec!["h1", "h1", "h1", "h1"])),
+                Arc::new(Float64Array::from(vec![
+                    Some(1.0),
+                    Some(2.0),
+                    None,
+                    Some(7.0),
+                ])),
+                Arc::new(Int64Array::from(vec![
+                    Some(10),
+                    Some(20),
+                    Some(30),
+                    Some(60),
+                ])),
+                Arc::new(StringArray::from(vec!["x", "y", "z", "w"])),
+                Arc::new(Int64Array::from(vec![
+                    0,
+                    MINUTE / 2,
+                    2 * MINUTE,
+                    4 * MINUTE + MINUTE / 2,
+                ])),
+            ],
+        )
+        .unwrap();
+
+        SeriesSet {
+            table_name: Arc::new("cpu".into()),
+            tags: vec![(Arc::new("host".into()), Arc::new("h1".into()))],
+            timestamp_index: 4,
+            field_indices: Arc::new(vec![1, 2, 3]),
+            start_row: 0,
+            num_rows: 4,
+            batch,
+        }
+    }
+
+    fn window_aggregate(
+        aggregate: Aggregate,
+        fill: Fill,
+        range: Option<TimestampRange>,
+    ) -> WindowAggregate {
+        WindowAggregate::try_new(
+            Duration::from_nsecs(MINUTE),
+            Duration::from_nsecs(0),
+            aggregate,
+            fill,
+            range,
+        )
+        .unwrap()
+    }
+
+    fn field_values(windowed: &WindowedSeriesSet, name: &str) -> WindowedValues {
+        windowed
+            .fields
+            .iter()
+            .find(|field| field.name == name)
+            .unwrap()
+            .values
+            .clone()
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let series_set = make_series_set();
+
+        let windowed = window_aggregate(Aggregate::Sum, Fill::None, None)
+            .apply(&series_set)
+            .unwrap();
+        assert_eq!(*windowed.table_name, "cpu");
+        assert_eq!(windowed.tags, series_set.tags);
+        assert_eq!(windowed.timestamps, vec![MINUTE, 3 * MINUTE, 5 * MINUTE]);
+        assert_eq!(
+            field_values(&windowed, "a"),
+            WindowedValues::F64(vec![Some(3.0), None, Some(7.0)])
+        );
+        assert_eq!(
+            field_values(&windowed, "b"),
+            WindowedValues::I64(vec![Some(30), Some(30), Some(60)])
+        );
+
+        let windowed = window_aggregate(Aggregate::Mean, Fill::None, None)
+            .apply(&series_set)
+            .unwrap();
+        assert_eq!(
+            field_values(&windowed, "b"),
+            WindowedValues::F64(vec![Some(15.0), Some(30.0), Some(60.0)])
+        );
+
+        let windowed = window_aggregate(Aggregate::Count, Fill::None, None)
+            .apply(&series_set)
+            .unwrap();
+        assert_eq!(
+            field_values(&windowed, "a"),
+            WindowedValues::I64(vec![Some(2), None, Some(1)])
+        );
+        assert_eq!(
+            field_values(&windowed, "c"),
+            WindowedValues::I64(vec![Some(2), Some(1), Some(1)])
+        );
+
+        let windowed = window_aggregate(Aggregate::Last, Fill::None, None)
+            .apply(&series_set)
+            .unwrap();
+        assert_eq!(
+            field_values(&windowed, "c"),
+            WindowedValues::String(vec![Some("y".into()), Some("z".into()), Some("w".into())])
+        );
+
+        let error = window_aggregate(Aggregate::Max, Fill::None, None)
+            .apply(&series_set)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Aggregate Max is not supported for string field 'c'"
+        );
+    }
+
+    #[test]
+    fn test_apply_to_group() {
+        let series_set = |host: &str| SeriesSet {
+            tags: vec![
+                (Arc::new("host".into()), Arc::new(host.into())),
+                (Arc::new("region".into()), Arc::new("west".into())),
+            ],
+            ..make_series_set()
+        };
+
+        let windowed = window_aggregate(Aggregate::Sum, Fill::None, None)
+            .apply_to_group(vec![series_set("h1"), series_set("h2")])
+            .unwrap();
+        assert_eq!(*windowed.table_name, "cpu");
+        assert_eq!(
+            windowed.tags,
+            vec![(Arc::new("region".into()), Arc::new("west".into()))]
+        );
+        assert_eq!(windowed.timestamps, vec![MINUTE, 3 * MINUTE, 5 * MINUTE]);
+        assert_eq!(
+            field_values(&windowed, "b"),
+            WindowedValues::I64(vec![Some(60), Some(60), Some(120)])
+        );
+    }
+
+    #[test]
+    fn test_fill() {
+        let series_set = make_series_set();
+        let range = Some(TimestampRange::new(-MINUTE, 6 * MINUTE));
+
+        let expected_timestamps = (0..7).map(|i| i * MINUTE).collect::<Vec<_>>();
+        let cases = vec![
+            (
+                Fill::Null,
+                vec![None, Some(15.0), None, Some(30.0), None, Some(60.0), None],
+            ),
+            (
+                Fill::Value(0.0),
+                vec![
+                    Some(0.0),
+                    Some(15.0),
+                    Some(0.0),
+                    Some(30.0),
+                    Some(0.0),
+                    Some(60.0),
+                    Some(0.0),
+                ],
+            ),
+            (
+                Fill::Previous,
+                vec![
+                    None,
+                    Some(15.0),
+                    Some(15.0),
+                    Some(30.0),
+                    Some(30.0),
+                    Some(60.0),
+                    Some(60.0),
+                ],
+            ),
+            (
+                Fill::Linear,
+                vec![
+                    None,
+                    Some(15.0),
+                    Some(22.5),
+                    Some(30.0),
+                    Some(45.0),
+                    Some(60.0),
+                    None,
+                ],
+            ),
+        ];
+
+        for (fill, expected) in cases {
+            let windowed = window_aggregate(Aggregate::Mean, fill, range)
+                .apply(&series_set)
+                .unwrap();
+            assert_eq!(windowed.timestamps, expected_timestamps, "fill: {:?}", fill);
+            assert_eq!(
+                field_values(&windowed, "b"),
+                WindowedValues::F64(expected),
+                "fill: {:?}",
+                fill
+            );
+        }
+
+        // without a range, only the windows between the first and
+        // last points are filled
+        let windowed = window_aggregate(Aggregate::Sum, Fill::Linear, None)
+            .apply(&series_set)
+            .unwrap();
+        assert_eq!(
+            windowed.timestamps,
+            vec![MINUTE, 2 * MINUTE, 3 * MINUTE, 4 * MINUTE, 5 * MINUTE]
+        );
+        assert_eq!(
+            field_values(&windowed, "b"),
+            WindowedValues::I64(vec![Some(30), Some(30), Some(30), Some(45), Some(60)])
+        );
+        // strings can not be interpolated
+        let windowed = window_aggregate(Aggregate::First, Fill::Linear, None)
+            .apply(&series_set)
+            .unwrap();
+        assert_eq!(
+            field_values(&windowed, "c"),
+            WindowedValues::String(vec![
+                Some("x".into()),
+                None,
+                Some("z".into()),
+                None,
+                Some("w".into())
+            ])
+        );
+    }
+
+    #[test]
+    fn test_transforms() {
+        const SECOND: i64 = 1_000_000_000;
+
+        let timestamps = vec![SECOND, 2 * SECOND, 3 * SECOND, 4 * SECOND];
+        let values = || WindowedValues::F64(vec![Some(10.0), Some(4.0), None, Some(8.0)]);
+
+        let cases = vec![
+            (
+                Transform::Derivative { unit: SECOND },
+                vec![None, Some(-6.0), None, Some(2.0)],
+            ),
+            (
+                Transform::Derivative { unit: MINUTE },
+                vec![None, Some(-360.0), None, Some(120.0)],
+            ),
+            (
+                Transform::NonNegativeDerivative { unit: SECOND },
+                vec![None, None, None, Some(2.0)],
+            ),
+            // the decrease from 10 to 4 is a counter reset
+            (Transform::Rate, vec![None, Some(4.0), None, Some(2.0)]),
+        ];
+
+        for (transform, expected) in cases {
+            let actual = transform.apply(values(), &timestamps, "f").unwrap();
+            assert_eq!(actual, WindowedValues::F64(expected), "{:?}", transform);
+        }
+
+        let error = Transform::Rate
+            .apply(WindowedValues::Bool(vec![Some(true)]), &[SECOND], "f")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Rate is not supported for boolean field 'f'"
+        );
+
+        // transforms apply to the aggregated values of each window
+        let windowed = window_aggregate(Aggregate::Count, Fill::None, None)
+            .with_transform(Transform::Derivative { unit: MINUTE })
+            .apply(&make_series_set())
+            .unwrap();
+        assert_eq!(
+            field_values(&windowed, "b"),
+            WindowedValues::F64(vec![None, Some(-0.5), Some(0.0)])
+        );
+    }
+
+    #[test]
+    fn test_calendar_windows() {
+        let time = |s: &str| {
+            chrono::DateTime::parse_from_rfc3339(s)
+                .unwrap()
+                .timestamp_nanos()
+        };
+        let stops = |window_aggregate: WindowAggregate, start: &str| {
+            let mut windows = BTreeMap::new();
+            windows.insert(time(start), (0, vec![]));
+            window_aggregate
+                .window_bounds(&windows)
+                .unwrap()
+                .into_iter()
+                .map(|(_, stop)| stop)
+                .collect::<Vec<_>>()
+        };
+
+        // each window is a calendar month
+        let range = TimestampRange::new(time("2020-01-01T00:00:00Z"), time("2020-04-01T00:00:00Z"));
+        let months = WindowAggregate::try_new(
+            Duration::from_months(1),
+            Duration::from_nsecs(0),
+            Aggregate::Count,
+            Fill::Null,
+            Some(range),
+        )
+        .unwrap();
+        assert_eq!(
+            stops(months, "2020-01-01T00:00:00Z"),
+            vec![
+                time("2020-02-01T00:00:00Z"),
+                time("2020-03-01T00:00:00Z"),
+                time("2020-04-01T00:00:00Z"),
+            ]
+        );
+
+        // days in New York, the second of which is 23 hours long
+        let location = Location::load("America/New_York").unwrap();
+        let range = TimestampRange::new(time("2021-03-13T05:00:00Z"), time("2021-03-16T04:00:00Z"));
+        let days = WindowAggregate::try_new(
+            Duration::from_nsecs(24 * 60 * MINUTE),
+            Duration::from_nsecs(0),
+            Aggregate::Count,
+            Fill::Null,
+            Some(range),
+        )
+        .unwrap()
+        .with_location(location);
+        assert_eq!(
+            stops(days, "2021-03-13T05:00:00Z"),
+            vec![
+                time("2021-03-14T05:00:00Z"),
+                time("2021-03-15T04:00:00Z"),
+                time("2021-03-16T04:00:00Z"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_windows() {
+        let error = WindowAggregate::try_new(
+            Duration::from_nsecs(0),
+            Duration::from_nsecs(0),
+            Aggregate::Sum,
+            Fill::None,
+            None,
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid window: every must be a positive number of either months or nanoseconds"
+        );
+
+        let error = window_aggregate(
+            Aggregate::Sum,
+            Fill::Null,
+            Some(TimestampRange::new(std::i64::MIN, std::i64::MAX)),
+        )
+        .apply(&make_series_set())
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Filling windows would produce more than 1000000 windows per series"
+        );
+    }
+
+    #[test]
+    fn test_buckets() {
+        let bucket = |count, sum, min, max| Bucket {
+            count,
+            sum,
+            min,
+            max,
+        };
+
+        // the points of `make_series_set` in buckets of a minute
+        let series = BucketedSeries {
+            table_name: Arc::new("cpu".into()),
+            tags: vec![(Arc::new("host".into()), Arc::new("h1".into()))],
+            buckets: vec![0, 2 * MINUTE, 4 * MINUTE],
+            fields: vec![
+                BucketedField {
+                    name: "a".into(),
+                    values: BucketedValues::F64(
+                        vec![
+                            (0, bucket(2, 3.0, 1.0, 2.0)),
+                            (4 * MINUTE, bucket(1, 7.0, 7.0, 7.0)),
+                        ]
+                        .into_iter()
+                        .collect(),
+                    ),
+                },
+                BucketedField {
+                    name: "b".into(),
+                    values: BucketedValues::I64(
+                        vec![
+                            (0, bucket(2, 30, 10, 20)),
+                            (2 * MINUTE, bucket(1, 30, 30, 30)),
+                            (4 * MINUTE, bucket(1, 60, 60, 60)),
+                        ]
+                        .into_iter()
+                        .collect(),
+                    ),
+                },
+            ],
+        };
+
+        let aggregates = vec![
+            Aggregate::Sum,
+            Aggregate::Count,
+            Aggregate::Min,
+            Aggregate::Max,
+            Aggregate::Mean,
+        ];
+        for aggregate in aggregates {
+            for every in &[MINUTE, 2 * MINUTE] {
+                let window_aggregate = WindowAggregate::try_new(
+                    Duration::from_nsecs(*every),
+                    Duration::from_nsecs(0),
+                    aggregate,
+                    Fill::Null,
+                    None,
+                )
+                .unwrap();
+                assert!(window_aggregate.can_apply_to_buckets(MINUTE));
+
+                let expected = window_aggregate.apply(&make_series_set()).unwrap();
+                let windowed = window_aggregate.apply_to_buckets(&series).unwrap();
+                assert_eq!(windowed.timestamps, expected.timestamps, "{:?}", aggregate);
+                for name in &["a", "b"] {
+                    assert_eq!(
+                        field_values(&windowed, name),
+                        field_values(&expected, name),
+                        "{:?} of {}",
+                        aggregate,
+                        name
+                    );
+                }
+            }
+        }
+
+        // the first and last points are not kept, and windows must be
+        // made of whole buckets
+        assert!(!window_aggregate(Aggregate::First, Fill::None, None).can_apply_to_buckets(MINUTE));
+        assert!(
+            !window_aggregate(Aggregate::Sum, Fill::None, None).can_apply_to_buckets(2 * MINUTE)
+        );
+    }
+
+    #[test]
+    fn test_non_finite_floats() {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("a", DataType::Float64, true),
+            Field::new("time", DataType::Int64, false),
+        ]));
+        let values = vec![
+            1.0,
+            f64::NAN,
+            f64::INFINITY,
+            2.0,
+            f64::NEG_INFINITY,
+            f64::NAN,
+            f64::INFINITY,
+        ];
+        let times = vec![0, 1, 2, 3, 4, MINUTE, MINUTE + 1];
+        let batch = RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(Float64Array::from(values)),
+                Arc::new(Int64Array::from(times)),
+            ],
+        )
+        .unwrap();
+        let series_set = SeriesSet {
+            table_name: Arc::new("cpu".into()),
+            tags: vec![],
+            timestamp_index: 1,
+            field_indices: Arc::new(vec![0]),
+            start_row: 0,
+            num_rows: 7,
+            batch,
+        };
+
+        // the pre-aggregates of the same points, which only have the
+        // finite values of the first minute
+        let series = BucketedSeries {
+            table_name: Arc::new("cpu".into()),
+            tags: vec![],
+            buckets: vec![0, MINUTE],
+            fields: vec![BucketedField {
+                name: "a".into(),
+                values: BucketedValues::F64(
+                    vec![(
+                        0,
+                        Bucket {
+                            count: 2,
+                            sum: 3.0,
+                            min: 1.0,
+                            max: 2.0,
+                        },
+                    )]
+                    .into_iter()
+                    .collect(),
+                ),
+            }],
+        };
+
+        // non-finite values are excluded from every aggregate, so the
+        // second window has no values
+        let expected = vec![
+            (Aggregate::Count, WindowedValues::I64(vec![Some(2), None])),
+            (Aggregate::Sum, WindowedValues::F64(vec![Some(3.0), None])),
+            (Aggregate::Min, WindowedValues::F64(vec![Some(1.0), None])),
+            (Aggregate::Max, WindowedValues::F64(vec![Some(2.0), None])),
+            (Aggregate::Mean, WindowedValues::F64(vec![Some(1.5), None])),
+            (Aggregate::First, WindowedValues::F64(vec![Some(1.0), None])),
+            (Aggregate::Last, WindowedValues::F64(vec![Some(2.0), None])),
+        ];
+        for (aggregate, expected) in expected {
+            let window_aggregate = window_aggregate(aggregate, Fill::Null, None);
+            let windowed = window_aggregate.apply(&series_set).unwrap();
+            assert_eq!(field_values(&windowed, "a"), expected, "{:?}", aggregate);
+
+            if window_aggregate.can_apply_to_buckets(MINUTE) {
+                let windowed = window_aggregate.apply_to_buckets(&series).unwrap();
+                assert_eq!(field_values(&windowed, "a"), expected, "{:?}", aggregate);
+            }
+        }
+    }
+}
@@ -6,22 +6,39 @@
     clippy::use_self
 )]
 
-use arrow_deps::arrow::record_batch::RecordBatch;
+use arrow_deps::{
+    arrow::{datatypes::SchemaRef, record_batch::RecordBatch},
+    datafusion::physical_plan::SendableRecordBatchStream,
+};
 use async_trait::async_trait;
-use data_types::data::ReplicatedWrite;
-use exec::{FieldListPlan, GroupedSeriesSetPlans, SeriesSetPlans, StringSetPlan};
+use data_types::{
+    data::ReplicatedWrite,
+    database_rules::DatabaseRules,
+    partition_metadata::{ChunkSummary, PartitionSummary},
+};
+use exec::{
+    window_aggregate::{PreAggregatedSeries, WindowAggregate},
+    FieldListPlan, GroupedSeriesSetPlans, Planned, SeriesSetPlans, StringSetPlan,
+};
 use influxdb_line_protocol::ParsedLine;
 
-use std::{fmt::Debug, sync::Arc};
+use std::{collections::BTreeSet, fmt::Debug, sync::Arc};
 
 pub mod exec;
 pub mod id;
 pub mod predicate;
+pub mod tenant;
 pub mod util;
 pub mod window;
 
 use self::predicate::{Predicate, TimestampRange};
 
+/// Called by a database with the number of bytes of data a long
+/// running operation, such as a compaction, is about to rewrite, so
+/// that the rate it rewrites data at can be limited. It is called on a
+/// thread which may block until the bytes may be rewritten.
+pub type Pacer = Arc<dyn Fn(u64) + Send + Sync + 'static>;
+
 #[async_trait]
 
 /// A `Database` describes something that stores InfluxDB Timeseries
@@ -53,25 +70,123 @@ pub trait Database: Debug + Send + Sync {
     /// Stores the replicated write in the write buffer and, if enabled, the write ahead log.
     async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error>;
 
+    /// Returns a number that changes whenever data is written to
+    /// this database, so that cached query results can be discarded
+    /// when they may no longer be valid. The plans of queries are
+    /// returned with the generation of the data they read.
+    fn generation(&self) -> u64;
+
+    /// Returns the rules currently in effect for this database
+    async fn rules(&self) -> DatabaseRules;
+
+    /// Replaces the rules for this database. The new rules apply to
+    /// all subsequent writes and queries.
+    async fn set_rules(&self, rules: DatabaseRules) -> Result<(), Self::Error>;
+
+    /// Returns the keys of the partitions in this database
+    async fn partition_keys(&self) -> Vec<String>;
+
+    /// Returns summaries of the chunks of data in this database,
+    /// ordered by partition key and then chunk id
+    async fn chunks(&self) -> Vec<ChunkSummary>;
+
+    /// Removes the partition with key `partition_key` along with all
+    /// of its data, returning a summary of what was removed. If
+    /// `dry_run` is true nothing is removed, and the summary
+    /// describes what would have been.
+    async fn drop_partition(
+        &self,
+        partition_key: &str,
+        dry_run: bool,
+    ) -> Result<PartitionSummary, Self::Error>;
+
+    /// Removes all data from the partition with key `partition_key`,
+    /// leaving it empty, and returns a summary of what was removed. If
+    /// `dry_run` is true nothing is removed, and the summary
+    /// describes what would have been.
+    async fn truncate_partition(
+        &self,
+        partition_key: &str,
+        dry_run: bool,
+    ) -> Result<PartitionSummary, Self::Error>;
+
+    /// Closes the open chunk with id `chunk_id` of the partition with
+    /// key `partition_key`, so that subsequent writes to the
+    /// partition go to a new chunk, and returns a summary of it
+    async fn close_chunk(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+    ) -> Result<ChunkSummary, Self::Error>;
+
+    /// Merges all chunks of the partition with key `partition_key`
+    /// into a single new, closed, chunk and returns a summary of it.
+    /// If `pace` is given, it is called with the bytes of each chunk
+    /// before the chunk is rewritten.
+    async fn compact_partition(
+        &self,
+        partition_key: &str,
+        pace: Option<Pacer>,
+    ) -> Result<ChunkSummary, Self::Error>;
+
+    /// Writes the data of the closed chunk with id `chunk_id` of the
+    /// partition with key `partition_key` to parquet files, and
+    /// returns a summary of it
+    async fn persist_chunk(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+    ) -> Result<ChunkSummary, Self::Error>;
+
+    /// Returns the data of the partition with key `partition_key` as
+    /// line protocol, one line per row, so the data of a database can
+    /// be read a partition at a time
+    async fn partition_line_protocol(&self, partition_key: &str) -> Result<String, Self::Error>;
+
+    /// Deletes the rows of the series with keys `series_keys` (as
+    /// returned by `influxdb_line_protocol::series_key`) from the
+    /// partition with key `partition_key`, returning the number of rows
+    /// deleted. The deletion is durable: the rows written so far are
+    /// not restored if the database restarts, while rows of the series
+    /// written later are kept.
+    async fn delete_series(
+        &self,
+        partition_key: &str,
+        series_keys: &BTreeSet<String>,
+    ) -> Result<u64, Self::Error>;
+
     /// Execute the specified query and return arrow record batches with the result
     async fn query(&self, query: &str) -> Result<Vec<RecordBatch>, Self::Error>;
 
+    /// Execute the specified query and return a stream of the arrow
+    /// record batches of the result, produced as the query runs
+    async fn query_stream(&self, query: &str) -> Result<SendableRecordBatchStream, Self::Error>;
+
     /// Returns a plan that lists the names of tables in this
     /// database that have at least one row that matches the
     /// conditions listed on `predicate`
-    async fn table_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error>;
+    async fn table_names(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<StringSetPlan>, Self::Error>;
 
     /// Returns a plan that produces the names of "tag" columns (as
     /// defined in the data written via `write_lines`)) names in this
     /// database, and have more than zero rows which pass the
     /// conditions specified by `predicate`.
-    async fn tag_column_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error>;
+    async fn tag_column_names(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<StringSetPlan>, Self::Error>;
 
     /// Returns a plan that produces a list of column names in this
     /// database which store fields (as defined in the data written
     /// via `write_lines`), and which have at least one row which
     /// matches the conditions listed on `predicate`.
-    async fn field_columns(&self, predicate: Predicate) -> Result<FieldListPlan, Self::Error>;
+    async fn field_columns(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<FieldListPlan>, Self::Error>;
 
     /// Returns a plan which finds the distinct values in the
     /// `column_name` column of this database which pass the
@@ -80,7 +195,7 @@ pub trait Database: Debug + Send + Sync {
         &self,
         column_name: &str,
         predicate: Predicate,
-    ) -> Result<StringSetPlan, Self::Error>;
+    ) -> Result<Planned<StringSetPlan>, Self::Error>;
 
     /// Returns a plan that finds all rows rows which pass the
     /// conditions specified by `predicate` in the form of logical
@@ -89,7 +204,24 @@ pub trait Database: Debug + Send + Sync {
     /// A time series is defined by the unique values in a set of
     /// "tag_columns" for each field in the "field_columns", orderd by
     /// the time column.
-    async fn query_series(&self, predicate: Predicate) -> Result<SeriesSetPlans, Self::Error>;
+    async fn query_series(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<SeriesSetPlans>, Self::Error>;
+
+    /// Returns the time series which pass the conditions specified by
+    /// `predicate`, as the aggregates of their points in buckets of
+    /// time maintained as they were written, if `window_aggregate` can
+    /// be computed from them.
+    ///
+    /// Returns `None` if it can not, such as when the tables of the
+    /// series do not have pre-aggregates, in which case the series are
+    /// aggregated from the plans of `query_series` instead.
+    async fn query_pre_aggregated_series(
+        &self,
+        predicate: Predicate,
+        window_aggregate: &WindowAggregate,
+    ) -> Result<Option<Planned<PreAggregatedSeries>>, Self::Error>;
 
     /// Returns a plan that finds sets of rows which pass the
     /// conditions specified by `predicate` and which form groups of
@@ -103,7 +235,7 @@ pub trait Database: Debug + Send + Sync {
         &self,
         predicate: Predicate,
         group_columns: Vec<String>,
-    ) -> Result<GroupedSeriesSetPlans, Self::Error>;
+    ) -> Result<Planned<GroupedSeriesSetPlans>, Self::Error>;
 
     /// Fetch the specified table names and columns as Arrow
     /// RecordBatches. Columns are returned in the order specified.
@@ -112,6 +244,17 @@ pub trait Database: Debug + Send + Sync {
         table_name: &str,
         columns: &[&str],
     ) -> Result<Vec<RecordBatch>, Self::Error>;
+
+    /// Fetch the rows of the table `table_name` as SQL queries read
+    /// them: from the chunks with rows in `range` (all chunks if not
+    /// given), with the rows of chunks with overlapping time ranges
+    /// deduplicated. Returns the schema of the table, which is known
+    /// even if no chunk has rows in `range`, and the batches.
+    async fn query_table(
+        &self,
+        table_name: &str,
+        range: Option<TimestampRange>,
+    ) -> Result<(SchemaRef, Vec<RecordBatch>), Self::Error>;
 }
 
 #[async_trait]
@@ -123,6 +266,9 @@ pub trait DatabaseStore: Debug + Send + Sync {
     /// The type of error this DataBase store generates
     type Error: std::error::Error + Send + Sync + 'static;
 
+    /// List the database names, in sorted order
+    async fn db_names_sorted(&self) -> Vec<String>;
+
     /// Retrieve the database specified by `name` returning None if no
     /// such database exists
     async fn db(&self, name: &str) -> Option<Arc<Self::Database>>;
@@ -130,12 +276,24 @@ pub trait DatabaseStore: Debug + Send + Sync {
     /// Retrieve the database specified by `name`, creating it if it
     /// doesn't exist.
     async fn db_or_create(&self, name: &str) -> Result<Arc<Self::Database>, Self::Error>;
+
+    /// Create the database specified by `name` with the given rules,
+    /// returning an error if it already exists.
+    async fn create_db(
+        &self,
+        name: &str,
+        rules: DatabaseRules,
+    ) -> Result<Arc<Self::Database>, Self::Error>;
+
+    /// Remove the database specified by `name` along with all of its
+    /// data, returning an error if it doesn't exist.
+    async fn delete_db(&self, name: &str) -> Result<(), Self::Error>;
 }
 
 /// Compatibility: return the database name to use for the specified
-/// org and bucket name.
+/// org and bucket name. See the `tenant` module for details.
 ///
-/// TODO move to somewhere else / change the traits to take the database name directly
+/// TODO change the traits to take the database name directly
 pub fn org_and_bucket_to_database(org: impl Into<String>, bucket: &str) -> String {
     org.into() + "_" + bucket
 }
@@ -1,11 +1,18 @@
-use std::collections::BTreeSet;
+use std::{cmp::Ordering, collections::BTreeSet, fmt};
 
-use arrow_deps::datafusion::logical_plan::Expr;
+use arrow_deps::datafusion::{
+    logical_plan::{Expr, Operator},
+    scalar::ScalarValue,
+};
+use data_types::TIME_COLUMN_NAME;
+use serde::{Deserialize, Serialize};
+
+mod serialize;
 
 /// Specifies a continuous range of nanosecond timestamps. Timestamp
 /// predicates are so common and critical to performance of timeseries
 /// databases in general, and IOx in particular, that they are handled specially
-#[derive(Clone, PartialEq, Copy, Debug)]
+#[derive(Clone, PartialEq, Copy, Debug, Serialize, Deserialize)]
 pub struct TimestampRange {
     /// Start defines the inclusive lower bound.
     pub start: i64,
@@ -24,6 +31,14 @@ impl TimestampRange {
         self.start <= v && v < self.end
     }
 
+    /// Returns the range of values contained in both this range and
+    /// `other`, which is empty if they don't overlap
+    pub fn intersect(&self, other: &Self) -> Self {
+        let start = self.start.max(other.start);
+        let end = self.end.min(other.end).max(start);
+        Self { start, end }
+    }
+
     #[inline]
     /// Returns true if this range contains the value v
     pub fn contains_opt(&self, v: Option<i64>) -> bool {
@@ -38,7 +53,13 @@ impl TimestampRange {
 /// between some types of columns (tags and fields), and likewise
 /// this structure has some types of restrictions that only apply to
 /// certain types of columns.
-#[derive(Clone, Debug, Default)]
+///
+/// Predicates can be serialized, for example to log or cache them, as
+/// long as their expressions are built from columns, literals,
+/// comparisons and boolean logic, as those of the storage gRPC API
+/// are. Their `Display` form is a stable, SQL-like rendering, such as
+/// `{table_names: [cpu], exprs: ["host" = 'a'], range: [100, 200)}`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct Predicate {
     /// Optional filter. If present, restrict the results to only
     /// those tables whose names are in `table_names`
@@ -53,6 +74,7 @@ pub struct Predicate {
     /// are 'AND'ed together). Only rows that evaluate to TRUE for all
     /// these expressions should be returned. Other rows are excluded
     /// from the results.
+    #[serde(default, with = "serialize")]
     pub exprs: Vec<Expr>,
 
     /// Optional timestamp range: only rows within this range are included in
@@ -65,6 +87,209 @@ impl Predicate {
     pub fn has_exprs(&self) -> bool {
         !self.exprs.is_empty()
     }
+
+    /// Rewrites this predicate into an equivalent one that is easier
+    /// to prune chunks with. Machine generated predicates often contain
+    /// constant expressions and repeated restrictions, so this:
+    ///
+    /// 1. Folds comparisons between literals and boolean logic on literals
+    /// (e.g. `1 = 1 AND x` becomes `x`)
+    ///
+    /// 2. Removes conjuncts that are always true and duplicate conjuncts
+    ///
+    /// 3. Merges comparisons of the time column with integer literals into
+    /// `range`
+    ///
+    /// If any conjunct is always false, the only expression left is `false`.
+    pub fn simplify(mut self) -> Self {
+        let mut conjuncts = vec![];
+        for expr in std::mem::take(&mut self.exprs) {
+            split_conjuncts(simplify_expr(expr), &mut conjuncts);
+        }
+
+        let mut seen = BTreeSet::new();
+        for expr in conjuncts {
+            match literal_bool(&expr) {
+                Some(true) => continue,
+                Some(false) => {
+                    self.exprs = vec![expr];
+                    return self;
+                }
+                None => {}
+            }
+
+            if let Some(range) = time_range(&expr) {
+                self.range = Some(match self.range {
+                    Some(existing) => existing.intersect(&range),
+                    None => range,
+                });
+            } else if seen.insert(format!("{:?}", expr)) {
+                self.exprs.push(expr);
+            }
+        }
+        self
+    }
+}
+
+/// Folds constant sub-expressions of `expr`. Only rewrites that also hold
+/// when some of the inputs are NULL are made.
+fn simplify_expr(expr: Expr) -> Expr {
+    match expr {
+        Expr::BinaryExpr { left, op, right } => {
+            let left = simplify_expr(*left);
+            let right = simplify_expr(*right);
+
+            match (&op, literal_bool(&left), literal_bool(&right)) {
+                (Operator::And, Some(false), _) | (Operator::And, _, Some(false)) => {
+                    return bool_expr(false)
+                }
+                (Operator::And, Some(true), _) => return right,
+                (Operator::And, _, Some(true)) => return left,
+                (Operator::Or, Some(true), _) | (Operator::Or, _, Some(true)) => {
+                    return bool_expr(true)
+                }
+                (Operator::Or, Some(false), _) => return right,
+                (Operator::Or, _, Some(false)) => return left,
+                _ => {}
+            }
+
+            if let (Expr::Literal(l), Expr::Literal(r)) = (&left, &right) {
+                if let Some(value) = compare_literals(l, &op, r) {
+                    return bool_expr(value);
+                }
+            }
+
+            Expr::BinaryExpr {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            }
+        }
+        Expr::Not(expr) => match simplify_expr(*expr) {
+            Expr::Literal(ScalarValue::Boolean(Some(value))) => bool_expr(!value),
+            expr => Expr::Not(Box::new(expr)),
+        },
+        Expr::Nested(expr) => match simplify_expr(*expr) {
+            expr @ Expr::Literal(_) => expr,
+            expr => Expr::Nested(Box::new(expr)),
+        },
+        expr => expr,
+    }
+}
+
+/// Appends the conjuncts of `expr` (the expressions `AND`ed together) to
+/// `dst`
+fn split_conjuncts(expr: Expr, dst: &mut Vec<Expr>) {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::And,
+            right,
+        } => {
+            split_conjuncts(*left, dst);
+            split_conjuncts(*right, dst);
+        }
+        Expr::Nested(expr) => split_conjuncts(*expr, dst),
+        expr => dst.push(expr),
+    }
+}
+
+fn bool_expr(value: bool) -> Expr {
+    Expr::Literal(ScalarValue::Boolean(Some(value)))
+}
+
+/// Returns the value of `expr` if it is a boolean literal
+fn literal_bool(expr: &Expr) -> Option<bool> {
+    match expr {
+        Expr::Literal(ScalarValue::Boolean(Some(value))) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Evaluates `left op right` if both literals have the same type and `op`
+/// is a comparison
+fn compare_literals(left: &ScalarValue, op: &Operator, right: &ScalarValue) -> Option<bool> {
+    let ordering = match (left, right) {
+        (ScalarValue::Int64(Some(l)), ScalarValue::Int64(Some(r))) => l.cmp(r),
+        (ScalarValue::UInt64(Some(l)), ScalarValue::UInt64(Some(r))) => l.cmp(r),
+        (ScalarValue::Float64(Some(l)), ScalarValue::Float64(Some(r))) => l.partial_cmp(r)?,
+        (ScalarValue::Utf8(Some(l)), ScalarValue::Utf8(Some(r))) => l.cmp(r),
+        (ScalarValue::Boolean(Some(l)), ScalarValue::Boolean(Some(r))) => l.cmp(r),
+        _ => return None,
+    };
+
+    match op {
+        Operator::Eq => Some(ordering == Ordering::Equal),
+        Operator::NotEq => Some(ordering != Ordering::Equal),
+        Operator::Lt => Some(ordering == Ordering::Less),
+        Operator::LtEq => Some(ordering != Ordering::Greater),
+        Operator::Gt => Some(ordering == Ordering::Greater),
+        Operator::GtEq => Some(ordering != Ordering::Less),
+        _ => None,
+    }
+}
+
+/// Returns the range of timestamps `expr` restricts the time column to,
+/// if it compares the time column with an integer literal
+fn time_range(expr: &Expr) -> Option<TimestampRange> {
+    let (op, value) = match expr {
+        Expr::BinaryExpr { left, op, right } => match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(name), Expr::Literal(ScalarValue::Int64(Some(value))))
+                if name == TIME_COLUMN_NAME =>
+            {
+                (op, *value)
+            }
+            _ => return None,
+        },
+        _ => return None,
+    };
+
+    let (start, end) = match op {
+        Operator::Eq => (value, value.saturating_add(1)),
+        Operator::Lt => (i64::MIN, value),
+        Operator::LtEq => (i64::MIN, value.saturating_add(1)),
+        Operator::Gt => (value.saturating_add(1), i64::MAX),
+        Operator::GtEq => (value, i64::MAX),
+        _ => return None,
+    };
+    Some(TimestampRange::new(start, end))
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let write_names = |f: &mut fmt::Formatter<'_>, names: &BTreeSet<String>| {
+            let names = names.iter().map(String::as_str).collect::<Vec<_>>();
+            write!(f, "[{}]", names.join(", "))
+        };
+
+        let mut separator = "";
+        write!(f, "{{")?;
+        if let Some(table_names) = &self.table_names {
+            write!(f, "table_names: ")?;
+            write_names(f, table_names)?;
+            separator = ", ";
+        }
+        if let Some(field_columns) = &self.field_columns {
+            write!(f, "{}field_columns: ", separator)?;
+            write_names(f, field_columns)?;
+            separator = ", ";
+        }
+        if !self.exprs.is_empty() {
+            write!(f, "{}exprs: [", separator)?;
+            for (i, expr) in self.exprs.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                serialize::write_expr(f, expr)?;
+            }
+            write!(f, "]")?;
+            separator = ", ";
+        }
+        if let Some(range) = &self.range {
+            write!(f, "{}range: [{}, {})", separator, range.start, range.end)?;
+        }
+        write!(f, "}}")
+    }
 }
 
 #[derive(Debug, Default)]
@@ -140,8 +365,135 @@ impl PredicateBuilder {
         self
     }
 
+    /// Simplifies the predicate built so far, see `Predicate::simplify`
+    pub fn simplify(self) -> Self {
+        Self {
+            inner: self.inner.simplify(),
+        }
+    }
+
     /// Create a predicate, consuming this builder
     pub fn build(self) -> Predicate {
         self.inner
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::datafusion::logical_plan::{col, lit};
+
+    fn simplify(exprs: Vec<Expr>) -> Predicate {
+        exprs
+            .into_iter()
+            .fold(PredicateBuilder::default(), |builder, expr| {
+                builder.add_expr(expr)
+            })
+            .timestamp_range(0, 1000)
+            .build()
+            .simplify()
+    }
+
+    #[test]
+    fn simplify_folds_constants() {
+        let predicate = simplify(vec![
+            lit(1_i64).eq(lit(1_i64)),
+            col("state").eq(lit("MA")).and(lit("a").lt(lit("b"))),
+            col("city")
+                .eq(lit("Boston"))
+                .or(lit(2.0_f64).gt(lit(3.0_f64))),
+        ]);
+
+        assert_eq!(
+            format!("{:?}", predicate.exprs),
+            r#"[#state Eq Utf8("MA"), #city Eq Utf8("Boston")]"#
+        );
+        assert_eq!(predicate.range, Some(TimestampRange::new(0, 1000)));
+    }
+
+    #[test]
+    fn simplify_always_false() {
+        let predicate = simplify(vec![
+            col("state").eq(lit("MA")),
+            col("city").eq(lit("Boston")).and(lit(1_i64).eq(lit(2_i64))),
+        ]);
+
+        assert_eq!(format!("{:?}", predicate.exprs), "[Boolean(false)]");
+    }
+
+    #[test]
+    fn simplify_deduplicates_conjuncts() {
+        let predicate = simplify(vec![
+            col("state").eq(lit("MA")),
+            col("state")
+                .eq(lit("MA"))
+                .and(col("city").eq(lit("Boston"))),
+            col("state").eq(lit("MA")),
+        ]);
+
+        assert_eq!(
+            format!("{:?}", predicate.exprs),
+            r#"[#state Eq Utf8("MA"), #city Eq Utf8("Boston")]"#
+        );
+    }
+
+    #[test]
+    fn serialize_predicates() {
+        let predicate = PredicateBuilder::default()
+            .table("cpu")
+            .field_columns(vec!["usage".into(), "system".into()])
+            .add_expr(
+                col("state")
+                    .eq(lit("O'Neil"))
+                    .or(col("host").is_null().and(col("usage").gt(lit(1.5_f64)))),
+            )
+            .add_expr(Expr::Not(Box::new(col("up").eq(lit(true)))))
+            .add_expr(col("count").lt_eq(lit(10_u64)))
+            .timestamp_range(100, 200)
+            .build();
+
+        assert_eq!(
+            predicate.to_string(),
+            r#"{table_names: [cpu], field_columns: [system, usage], exprs: [("state" = 'O''Neil') OR (("host" IS NULL) AND ("usage" > 1.5)), NOT ("up" = true), "count" <= 10], range: [100, 200)}"#
+        );
+
+        let json = serde_json::to_string(&predicate).unwrap();
+        let round_tripped: Predicate = serde_json::from_str(&json).unwrap();
+        assert_eq!(round_tripped.to_string(), predicate.to_string());
+        assert_eq!(
+            format!("{:?}", round_tripped.exprs),
+            format!("{:?}", predicate.exprs)
+        );
+        assert_eq!(round_tripped.range, predicate.range);
+
+        let empty: Predicate = serde_json::from_str("{}").unwrap();
+        assert_eq!(empty.to_string(), "{}");
+
+        let unsupported = PredicateBuilder::default().add_expr(Expr::Wildcard).build();
+        assert!(serde_json::to_string(&unsupported)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Unsupported expression in predicate"));
+    }
+
+    #[test]
+    fn simplify_merges_time_ranges() {
+        let predicate = simplify(vec![
+            col("time").gt_eq(lit(100_i64)),
+            col("time").lt(lit(2000_i64)),
+            col("time").lt_eq(lit(500_i64)),
+            col("state").eq(lit("MA")),
+        ]);
+
+        assert_eq!(
+            format!("{:?}", predicate.exprs),
+            r#"[#state Eq Utf8("MA")]"#
+        );
+        assert_eq!(predicate.range, Some(TimestampRange::new(100, 501)));
+
+        // ranges that don't overlap can't contain any timestamps
+        let predicate = simplify(vec![col("time").gt(lit(5000_i64))]);
+        assert_eq!(predicate.range, Some(TimestampRange::new(5001, 5001)));
+        assert!(!predicate.range.unwrap().contains(5001));
+    }
+}
@@ -0,0 +1,297 @@
+//! This module contains the serialized and textual forms of the
+//! expressions of a `Predicate`.
+//!
+//! DataFusion expressions are not serializable, so the expressions
+//! predicates are built from (comparisons and boolean logic on columns
+//! and literals) are converted to and from `SerializedExpr`. Predicates
+//! with any other expression can not be serialized.
+//!
+//! The textual form writes expressions as SQL, and does not depend on
+//! the `Debug` output of DataFusion.
+
+use std::{convert::TryFrom, fmt};
+
+use arrow_deps::datafusion::{
+    logical_plan::{Expr, Operator},
+    scalar::ScalarValue,
+};
+use serde::{ser, Deserialize, Deserializer, Serialize, Serializer};
+
+/// An expression of a predicate, in a form serde can serialize
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SerializedExpr {
+    Column(String),
+    Literal(SerializedScalar),
+    Binary {
+        left: Box<SerializedExpr>,
+        op: SerializedOperator,
+        right: Box<SerializedExpr>,
+    },
+    Not(Box<SerializedExpr>),
+    IsNull(Box<SerializedExpr>),
+    IsNotNull(Box<SerializedExpr>),
+    Nested(Box<SerializedExpr>),
+}
+
+/// A literal value, which is `None` for a typed `NULL`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SerializedScalar {
+    Boolean(Option<bool>),
+    Int64(Option<i64>),
+    UInt64(Option<u64>),
+    Float64(Option<f64>),
+    Utf8(Option<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SerializedOperator {
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Plus,
+    Minus,
+    Multiply,
+    Divide,
+    Modulus,
+    And,
+    Or,
+    Like,
+    NotLike,
+}
+
+/// The error converting an expression a predicate can't serialize
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnsupportedExpr(String);
+
+impl fmt::Display for UnsupportedExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unsupported expression in predicate: {}", self.0)
+    }
+}
+
+impl std::error::Error for UnsupportedExpr {}
+
+fn unsupported<T>(what: impl fmt::Debug) -> Result<T, UnsupportedExpr> {
+    Err(UnsupportedExpr(format!("{:?}", what)))
+}
+
+impl TryFrom<&Expr> for SerializedExpr {
+    type Error = UnsupportedExpr;
+
+    fn try_from(expr: &Expr) -> Result<Self, Self::Error> {
+        let boxed = |expr: &Expr| Self::try_from(expr).map(Box::new);
+
+        Ok(match expr {
+            Expr::Column(name) => Self::Column(name.clone()),
+            Expr::Literal(value) => Self::Literal(SerializedScalar::try_from(value)?),
+            Expr::BinaryExpr { left, op, right } => Self::Binary {
+                left: boxed(left)?,
+                op: op.into(),
+                right: boxed(right)?,
+            },
+            Expr::Not(expr) => Self::Not(boxed(expr)?),
+            Expr::IsNull(expr) => Self::IsNull(boxed(expr)?),
+            Expr::IsNotNull(expr) => Self::IsNotNull(boxed(expr)?),
+            Expr::Nested(expr) => Self::Nested(boxed(expr)?),
+            expr => return unsupported(expr),
+        })
+    }
+}
+
+impl From<SerializedExpr> for Expr {
+    fn from(expr: SerializedExpr) -> Self {
+        let boxed = |expr: Box<SerializedExpr>| Box::new(Self::from(*expr));
+
+        match expr {
+            SerializedExpr::Column(name) => Self::Column(name),
+            SerializedExpr::Literal(value) => Self::Literal(value.into()),
+            SerializedExpr::Binary { left, op, right } => Self::BinaryExpr {
+                left: boxed(left),
+                op: op.into(),
+                right: boxed(right),
+            },
+            SerializedExpr::Not(expr) => Self::Not(boxed(expr)),
+            SerializedExpr::IsNull(expr) => Self::IsNull(boxed(expr)),
+            SerializedExpr::IsNotNull(expr) => Self::IsNotNull(boxed(expr)),
+            SerializedExpr::Nested(expr) => Self::Nested(boxed(expr)),
+        }
+    }
+}
+
+impl TryFrom<&ScalarValue> for SerializedScalar {
+    type Error = UnsupportedExpr;
+
+    fn try_from(value: &ScalarValue) -> Result<Self, Self::Error> {
+        Ok(match value {
+            ScalarValue::Boolean(value) => Self::Boolean(*value),
+            ScalarValue::Int64(value) => Self::Int64(*value),
+            ScalarValue::UInt64(value) => Self::UInt64(*value),
+            ScalarValue::Float64(value) => Self::Float64(*value),
+            ScalarValue::Utf8(value) => Self::Utf8(value.clone()),
+            value => return unsupported(value),
+        })
+    }
+}
+
+impl From<SerializedScalar> for ScalarValue {
+    fn from(value: SerializedScalar) -> Self {
+        match value {
+            SerializedScalar::Boolean(value) => Self::Boolean(value),
+            SerializedScalar::Int64(value) => Self::Int64(value),
+            SerializedScalar::UInt64(value) => Self::UInt64(value),
+            SerializedScalar::Float64(value) => Self::Float64(value),
+            SerializedScalar::Utf8(value) => Self::Utf8(value),
+        }
+    }
+}
+
+impl From<&Operator> for SerializedOperator {
+    fn from(op: &Operator) -> Self {
+        match op {
+            Operator::Eq => Self::Eq,
+            Operator::NotEq => Self::NotEq,
+            Operator::Lt => Self::Lt,
+            Operator::LtEq => Self::LtEq,
+            Operator::Gt => Self::Gt,
+            Operator::GtEq => Self::GtEq,
+            Operator::Plus => Self::Plus,
+            Operator::Minus => Self::Minus,
+            Operator::Multiply => Self::Multiply,
+            Operator::Divide => Self::Divide,
+            Operator::Modulus => Self::Modulus,
+            Operator::And => Self::And,
+            Operator::Or => Self::Or,
+            Operator::Like => Self::Like,
+            Operator::NotLike => Self::NotLike,
+        }
+    }
+}
+
+impl From<SerializedOperator> for Operator {
+    fn from(op: SerializedOperator) -> Self {
+        match op {
+            SerializedOperator::Eq => Self::Eq,
+            SerializedOperator::NotEq => Self::NotEq,
+            SerializedOperator::Lt => Self::Lt,
+            SerializedOperator::LtEq => Self::LtEq,
+            SerializedOperator::Gt => Self::Gt,
+            SerializedOperator::GtEq => Self::GtEq,
+            SerializedOperator::Plus => Self::Plus,
+            SerializedOperator::Minus => Self::Minus,
+            SerializedOperator::Multiply => Self::Multiply,
+            SerializedOperator::Divide => Self::Divide,
+            SerializedOperator::Modulus => Self::Modulus,
+            SerializedOperator::And => Self::And,
+            SerializedOperator::Or => Self::Or,
+            SerializedOperator::Like => Self::Like,
+            SerializedOperator::NotLike => Self::NotLike,
+        }
+    }
+}
+
+/// Serializes the expressions of a predicate, for use with
+/// `#[serde(with)]`
+pub fn serialize<S: Serializer>(exprs: &[Expr], serializer: S) -> Result<S::Ok, S::Error> {
+    let exprs = exprs
+        .iter()
+        .map(SerializedExpr::try_from)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(<S::Error as ser::Error>::custom)?;
+    exprs.serialize(serializer)
+}
+
+/// Deserializes the expressions of a predicate, for use with
+/// `#[serde(with)]`
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Expr>, D::Error> {
+    let exprs = Vec::<SerializedExpr>::deserialize(deserializer)?;
+    Ok(exprs.into_iter().map(Expr::from).collect())
+}
+
+/// Writes `expr` as SQL. Expressions that can't be serialized are
+/// written in their `Debug` form.
+pub fn write_expr(f: &mut fmt::Formatter<'_>, expr: &Expr) -> fmt::Result {
+    match expr {
+        Expr::Column(name) => write!(f, "\"{}\"", name.replace('"', "\"\"")),
+        Expr::Literal(value) => write_literal(f, value),
+        Expr::BinaryExpr { left, op, right } => {
+            write_operand(f, left)?;
+            write!(f, " {} ", SerializedOperator::from(op).sql())?;
+            write_operand(f, right)
+        }
+        Expr::Not(expr) => {
+            write!(f, "NOT ")?;
+            write_operand(f, expr)
+        }
+        Expr::IsNull(expr) => {
+            write_operand(f, expr)?;
+            write!(f, " IS NULL")
+        }
+        Expr::IsNotNull(expr) => {
+            write_operand(f, expr)?;
+            write!(f, " IS NOT NULL")
+        }
+        Expr::Nested(expr) => {
+            write!(f, "(")?;
+            write_expr(f, expr)?;
+            write!(f, ")")
+        }
+        expr => write!(f, "{:?}", expr),
+    }
+}
+
+/// Writes `expr`, in parentheses if it is made of other expressions
+fn write_operand(f: &mut fmt::Formatter<'_>, expr: &Expr) -> fmt::Result {
+    match expr {
+        Expr::BinaryExpr { .. } | Expr::Not(_) | Expr::IsNull(_) | Expr::IsNotNull(_) => {
+            write!(f, "(")?;
+            write_expr(f, expr)?;
+            write!(f, ")")
+        }
+        expr => write_expr(f, expr),
+    }
+}
+
+fn write_literal(f: &mut fmt::Formatter<'_>, value: &ScalarValue) -> fmt::Result {
+    match value {
+        ScalarValue::Boolean(Some(value)) => write!(f, "{}", value),
+        ScalarValue::Int64(Some(value)) => write!(f, "{}", value),
+        ScalarValue::UInt64(Some(value)) => write!(f, "{}", value),
+        // the `Debug` form always has a decimal point, e.g. `1.0`
+        ScalarValue::Float64(Some(value)) => write!(f, "{:?}", value),
+        ScalarValue::Utf8(Some(value)) => write!(f, "'{}'", value.replace('\'', "''")),
+        ScalarValue::Boolean(None)
+        | ScalarValue::Int64(None)
+        | ScalarValue::UInt64(None)
+        | ScalarValue::Float64(None)
+        | ScalarValue::Utf8(None) => write!(f, "NULL"),
+        value => write!(f, "{:?}", value),
+    }
+}
+
+impl SerializedOperator {
+    fn sql(self) -> &'static str {
+        match self {
+            Self::Eq => "=",
+            Self::NotEq => "!=",
+            Self::Lt => "<",
+            Self::LtEq => "<=",
+            Self::Gt => ">",
+            Self::GtEq => ">=",
+            Self::Plus => "+",
+            Self::Minus => "-",
+            Self::Multiply => "*",
+            Self::Divide => "/",
+            Self::Modulus => "%",
+            Self::And => "AND",
+            Self::Or => "OR",
+            Self::Like => "LIKE",
+            Self::NotLike => "NOT LIKE",
+        }
+    }
+}
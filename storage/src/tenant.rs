@@ -0,0 +1,226 @@
+//! This module maps the tenants of the InfluxDB 2.0 APIs, buckets
+//! within organizations ("orgs"), onto the databases of an IOx server.
+//!
+//! The data of each bucket is stored in its own database, named
+//! `{org}_{bucket}` (see `org_and_bucket_to_database`). The name only
+//! addresses the database: the org and bucket a database stores are
+//! recorded explicitly, as the `tenant` of its rules, when it is
+//! created or first written to as a bucket. Which org a database
+//! belongs to is therefore never guessed from its name, so the
+//! databases of an org `My_Org` can not be confused with those of an
+//! org `My`, and a bucket can not be written to as the bucket of
+//! another org whose database has the same name. Databases not
+//! created for a bucket, and those created before tenants were
+//! recorded and not written to as a bucket since, belong to no org.
+
+use std::{collections::BTreeSet, sync::Arc};
+
+use data_types::database_rules::Tenant;
+use snafu::{ResultExt, Snafu};
+
+use crate::{org_and_bucket_to_database, Database, DatabaseStore};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error creating database {} for bucket: {}", db_name, source))]
+    CreatingDatabase {
+        db_name: String,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
+    #[snafu(display("Error recording the bucket of database {}: {}", db_name, source))]
+    RecordingTenant {
+        db_name: String,
+        source: Box<dyn std::error::Error + Send + Sync + 'static>,
+    },
+
+    #[snafu(display(
+        "Database {} stores bucket {} of org {}, not bucket {} of org {}",
+        db_name,
+        existing.bucket,
+        existing.org,
+        requested.bucket,
+        requested.org
+    ))]
+    OtherTenant {
+        db_name: String,
+        existing: Tenant,
+        requested: Tenant,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns the database that stores the data of `bucket` in `org`,
+/// creating it if it does not exist and recording the bucket it
+/// stores if it has not been already. Returns an error if the database
+/// stores another bucket.
+pub async fn bucket_db_or_create<T: DatabaseStore>(
+    db_store: &T,
+    org: &str,
+    bucket: &str,
+) -> Result<Arc<T::Database>> {
+    let db_name = org_and_bucket_to_database(org, bucket);
+    let db = db_store
+        .db_or_create(&db_name)
+        .await
+        .map_err(|e| Box::new(e) as _)
+        .context(CreatingDatabase { db_name: &db_name })?;
+
+    let requested = Tenant {
+        org: org.to_string(),
+        bucket: bucket.to_string(),
+    };
+    let mut rules = db.rules().await;
+    match &rules.tenant {
+        Some(existing) if *existing == requested => {}
+        Some(existing) => {
+            return OtherTenant {
+                db_name,
+                existing: existing.clone(),
+                requested,
+            }
+            .fail()
+        }
+        None => {
+            rules.tenant = Some(requested);
+            db.set_rules(rules)
+                .await
+                .map_err(|e| Box::new(e) as _)
+                .context(RecordingTenant { db_name: &db_name })?;
+        }
+    }
+
+    Ok(db)
+}
+
+/// Returns the org whose bucket the database `db_name` stores, or
+/// `None` if it does not exist or does not belong to an org
+pub async fn db_org<T: DatabaseStore>(db_store: &T, db_name: &str) -> Option<String> {
+    let db = db_store.db(db_name).await?;
+    db.rules().await.tenant.map(|tenant| tenant.org)
+}
+
+/// Returns the names of the databases a query of the database
+/// `db_name` may read from: the databases of the buckets of the same
+/// org, or, if it does not belong to an org, the other databases that
+/// do not either.
+pub async fn same_tenant_databases<T: DatabaseStore>(
+    db_store: &T,
+    db_name: &str,
+) -> BTreeSet<String> {
+    let org = db_org(db_store, db_name).await;
+
+    let mut names = BTreeSet::new();
+    for other in db_store.db_names_sorted().await {
+        if db_org(db_store, &other).await == org {
+            names.insert(other);
+        }
+    }
+    names
+}
+
+/// Returns the names of the buckets of `org` stored in `db_store`, in
+/// sorted order
+pub async fn org_buckets<T: DatabaseStore>(db_store: &T, org: &str) -> Vec<String> {
+    let mut buckets = vec![];
+    for db_name in db_store.db_names_sorted().await {
+        if let Some(db) = db_store.db(&db_name).await {
+            match db.rules().await.tenant {
+                Some(tenant) if tenant.org == org => buckets.push(tenant.bucket),
+                _ => {}
+            }
+        }
+    }
+    buckets.sort();
+    buckets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::TestDatabaseStore;
+
+    #[tokio::test]
+    async fn test_bucket_db_or_create() {
+        let db_store = TestDatabaseStore::new();
+
+        // databases keep the names they always had
+        bucket_db_or_create(&db_store, "My_Org", "MyBucket")
+            .await
+            .unwrap();
+        let db = db_store.db("My_Org_MyBucket").await.unwrap();
+        assert_eq!(
+            db.rules().await.tenant,
+            Some(Tenant {
+                org: "My_Org".to_string(),
+                bucket: "MyBucket".to_string(),
+            })
+        );
+
+        // and can not be written to as the bucket of another org
+        let err = bucket_db_or_create(&db_store, "My", "Org_MyBucket")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Database My_Org_MyBucket stores bucket MyBucket of org My_Org, not bucket \
+             Org_MyBucket of org My"
+        );
+
+        // databases which existed before tenants were recorded are
+        // claimed by the first bucket written to them
+        db_store.db_or_create("MyOrg_old").await.unwrap();
+        bucket_db_or_create(&db_store, "MyOrg", "old")
+            .await
+            .unwrap();
+        assert_eq!(db_org(&db_store, "MyOrg_old").await.unwrap(), "MyOrg");
+    }
+
+    #[tokio::test]
+    async fn test_same_tenant_databases() {
+        let db_store = TestDatabaseStore::new();
+        for (org, bucket) in &[("MyOrg", "a"), ("MyOrg", "b"), ("My", "Org_c")] {
+            bucket_db_or_create(&db_store, org, bucket).await.unwrap();
+        }
+        for db_name in &["mydb", "otherdb"] {
+            db_store.db_or_create(db_name).await.unwrap();
+        }
+
+        // the name of the database of bucket Org_c of org My doesn't
+        // make it a database of MyOrg
+        let names = same_tenant_databases(&db_store, "MyOrg_a").await;
+        assert_eq!(
+            names.into_iter().collect::<Vec<_>>(),
+            vec!["MyOrg_a", "MyOrg_b"]
+        );
+
+        let names = same_tenant_databases(&db_store, "My_Org_c").await;
+        assert_eq!(names.into_iter().collect::<Vec<_>>(), vec!["My_Org_c"]);
+
+        let names = same_tenant_databases(&db_store, "mydb").await;
+        assert_eq!(
+            names.into_iter().collect::<Vec<_>>(),
+            vec!["mydb", "otherdb"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_org_buckets() {
+        let db_store = TestDatabaseStore::new();
+        for (org, bucket) in &[
+            ("MyOrg", "b"),
+            ("MyOrg", "a"),
+            ("My_Org", "c"),
+            ("MyOrgX", "d"),
+        ] {
+            bucket_db_or_create(&db_store, org, bucket).await.unwrap();
+        }
+        db_store.db_or_create("MyOrg_e").await.unwrap();
+
+        assert_eq!(org_buckets(&db_store, "MyOrg").await, vec!["a", "b"]);
+        assert_eq!(org_buckets(&db_store, "My_Org").await, vec!["c"]);
+        assert!(org_buckets(&db_store, "My").await.is_empty());
+        assert!(org_buckets(&db_store, "NoOrg").await.is_empty());
+    }
+}
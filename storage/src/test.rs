@@ -1,23 +1,42 @@
 //! This module provides a reference implementaton of `storage::DatabaseSource` and
 //! `storage::Database` for use in testing.
 
-use arrow_deps::arrow::record_batch::RecordBatch;
+use arrow_deps::{
+    arrow::{
+        datatypes::{Schema, SchemaRef},
+        record_batch::RecordBatch,
+    },
+    datafusion::physical_plan::SendableRecordBatchStream,
+};
 
 use crate::{
     exec::FieldListPlan,
     exec::{
+        batches_stream,
         stringset::{StringSet, StringSetRef},
-        GroupedSeriesSetPlans, SeriesSetPlans, StringSetPlan,
+        window_aggregate::{PreAggregatedSeries, WindowAggregate},
+        GroupedSeriesSetPlans, Planned, SeriesSetPlans, StringSetPlan,
     },
-    Database, DatabaseStore, Predicate, TimestampRange,
+    Database, DatabaseStore, Pacer, Predicate, TimestampRange,
 };
 
-use data_types::data::ReplicatedWrite;
-use influxdb_line_protocol::{parse_lines, ParsedLine};
+use data_types::{
+    data::ReplicatedWrite,
+    database_rules::DatabaseRules,
+    partition_metadata::{ChunkState, ChunkStorage, ChunkSummary, PartitionSummary},
+};
+use influxdb_line_protocol::{parse_lines, series_key, ParsedLine};
 
 use async_trait::async_trait;
-use snafu::{OptionExt, Snafu};
-use std::{collections::BTreeMap, collections::BTreeSet, sync::Arc};
+use snafu::{ensure, OptionExt, Snafu};
+use std::{
+    collections::BTreeMap,
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use std::fmt::Write;
 
@@ -31,6 +50,9 @@ pub struct TestDatabase {
     /// Replicated writes which have been written to this database, in order
     replicated_writes: Mutex<Vec<ReplicatedWrite>>,
 
+    /// Incremented on each write to this database
+    generation: AtomicU64,
+
     /// `column_names` to return upon next request
     column_names: Arc<Mutex<Option<StringSetRef>>>,
 
@@ -60,6 +82,21 @@ pub struct TestDatabase {
 
     /// The last request for `query_series`
     field_columns_request: Arc<Mutex<Option<FieldColumnsRequest>>>,
+
+    /// Responses to return on the next SQL `query`
+    query_values: Arc<Mutex<Option<Vec<RecordBatch>>>>,
+
+    /// The last SQL `query` request
+    query_request: Arc<Mutex<Option<QueryRequest>>>,
+
+    /// The rules currently set on this database
+    rules: Mutex<DatabaseRules>,
+
+    /// The partitions of this database, by key
+    partitions: Mutex<BTreeMap<String, PartitionSummary>>,
+
+    /// The chunks of this database
+    chunks: Mutex<Vec<ChunkSummary>>,
 }
 
 /// Records the parameters passed to a column name request
@@ -94,6 +131,13 @@ pub struct QueryGroupsRequest {
     pub group_columns: Vec<String>,
 }
 
+/// Records the parameters passed to a SQL `query` request
+#[derive(Debug, PartialEq, Clone)]
+pub struct QueryRequest {
+    /// The SQL text of the query
+    pub query: String,
+}
+
 /// Records the parameters passed to a `field_columns` request
 #[derive(Debug, PartialEq, Clone)]
 pub struct FieldColumnsRequest {
@@ -108,6 +152,25 @@ pub enum TestError {
 
     #[snafu(display("Test database execution:  {:?}", source))]
     Execution { source: crate::exec::Error },
+
+    #[snafu(display("Test database {} already exists", name))]
+    DatabaseExists { name: String },
+
+    #[snafu(display("Test database {} not found", name))]
+    DatabaseNotFound { name: String },
+
+    #[snafu(display("Test partition {} not found", key))]
+    PartitionNotFound { key: String },
+
+    #[snafu(display("Test chunk {} of partition {} not found", id, key))]
+    ChunkNotFound { key: String, id: u32 },
+
+    #[snafu(display("Test chunk {} of partition {} is {:?}", id, key, state))]
+    UnexpectedChunkState {
+        key: String,
+        id: u32,
+        state: ChunkState,
+    },
 }
 
 impl TestDatabase {
@@ -192,6 +255,54 @@ impl TestDatabase {
     pub async fn get_field_columns_request(&self) -> Option<FieldColumnsRequest> {
         self.field_columns_request.clone().lock().await.take()
     }
+
+    /// Set the record batches that will be returned on a call to query
+    pub async fn set_query_values(&self, batches: Vec<RecordBatch>) {
+        *(self.query_values.clone().lock().await) = Some(batches);
+    }
+
+    /// Get the parameters from the last SQL query request
+    pub async fn get_query_request(&self) -> Option<QueryRequest> {
+        self.query_request.clone().lock().await.take()
+    }
+
+    /// Sets the partitions this database reports having
+    pub async fn set_partitions(&self, partitions: Vec<PartitionSummary>) {
+        *self.partitions.lock().await =
+            partitions.into_iter().map(|p| (p.key.clone(), p)).collect();
+    }
+
+    /// Sets the chunks this database reports having
+    pub async fn set_chunks(&self, chunks: Vec<ChunkSummary>) {
+        *self.chunks.lock().await = chunks;
+    }
+
+    /// Moves the chunk with `id` of partition `key` from state `from`
+    /// to state `to`
+    async fn update_chunk(
+        &self,
+        key: &str,
+        id: u32,
+        from: ChunkState,
+        to: ChunkState,
+    ) -> Result<ChunkSummary, TestError> {
+        let mut chunks = self.chunks.lock().await;
+        let chunk = chunks
+            .iter_mut()
+            .find(|c| c.partition_key == key && c.id == id)
+            .context(ChunkNotFound { key, id })?;
+        ensure!(
+            chunk.state == from,
+            UnexpectedChunkState {
+                key,
+                id,
+                state: chunk.state
+            }
+        );
+
+        chunk.state = to;
+        Ok(chunk.clone())
+    }
 }
 
 /// returns true if this line is within the range of the timestamp
@@ -252,23 +363,220 @@ impl Database for TestDatabase {
         for line in lines {
             saved_lines.push(line.to_string())
         }
+        self.generation.fetch_add(1, Ordering::SeqCst);
         Ok(())
     }
 
     /// Adds the replicated write to this database
     async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
         self.replicated_writes.lock().await.push(write.clone());
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    async fn rules(&self) -> DatabaseRules {
+        self.rules.lock().await.clone()
+    }
+
+    async fn set_rules(&self, rules: DatabaseRules) -> Result<(), Self::Error> {
+        *self.rules.lock().await = rules;
         Ok(())
     }
 
+    async fn partition_keys(&self) -> Vec<String> {
+        self.partitions.lock().await.keys().cloned().collect()
+    }
+
+    async fn chunks(&self) -> Vec<ChunkSummary> {
+        self.chunks.lock().await.clone()
+    }
+
+    async fn drop_partition(
+        &self,
+        partition_key: &str,
+        dry_run: bool,
+    ) -> Result<PartitionSummary, Self::Error> {
+        let mut partitions = self.partitions.lock().await;
+        let summary = partitions
+            .get(partition_key)
+            .cloned()
+            .context(PartitionNotFound { key: partition_key })?;
+
+        if !dry_run {
+            partitions.remove(partition_key);
+        }
+        Ok(summary)
+    }
+
+    async fn truncate_partition(
+        &self,
+        partition_key: &str,
+        dry_run: bool,
+    ) -> Result<PartitionSummary, Self::Error> {
+        let mut partitions = self.partitions.lock().await;
+        let partition = partitions
+            .get_mut(partition_key)
+            .context(PartitionNotFound { key: partition_key })?;
+        let summary = partition.clone();
+
+        if !dry_run {
+            *partition = PartitionSummary {
+                key: partition_key.to_string(),
+                ..Default::default()
+            };
+        }
+        Ok(summary)
+    }
+
+    async fn close_chunk(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+    ) -> Result<ChunkSummary, Self::Error> {
+        self.update_chunk(
+            partition_key,
+            chunk_id,
+            ChunkState::Open,
+            ChunkState::Closed,
+        )
+        .await
+    }
+
+    async fn compact_partition(
+        &self,
+        partition_key: &str,
+        _pace: Option<Pacer>,
+    ) -> Result<ChunkSummary, Self::Error> {
+        let mut chunks = self.chunks.lock().await;
+        let (compacted, mut remaining): (Vec<_>, Vec<_>) = chunks
+            .drain(..)
+            .partition(|c| c.partition_key == partition_key);
+        ensure!(
+            !compacted.is_empty(),
+            PartitionNotFound { key: partition_key }
+        );
+
+        let mut summary = ChunkSummary {
+            partition_key: partition_key.to_string(),
+            id: compacted.iter().map(|c| c.id).max().unwrap_or_default() + 1,
+            storage: ChunkStorage::MutableBuffer,
+            state: ChunkState::Closed,
+            row_count: 0,
+            size: 0,
+            column_sizes: BTreeMap::new(),
+            min_time: compacted.iter().filter_map(|c| c.min_time).min(),
+            max_time: compacted.iter().filter_map(|c| c.max_time).max(),
+            table_names: vec![],
+            last_write_time: compacted.iter().filter_map(|c| c.last_write_time).max(),
+        };
+        for chunk in compacted {
+            summary.row_count += chunk.row_count;
+            summary.size += chunk.size;
+            for (column, size) in chunk.column_sizes {
+                *summary.column_sizes.entry(column).or_default() += size;
+            }
+            summary.table_names.extend(chunk.table_names);
+        }
+        summary.table_names.sort();
+        summary.table_names.dedup();
+
+        remaining.push(summary.clone());
+        *chunks = remaining;
+        Ok(summary)
+    }
+
+    async fn persist_chunk(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+    ) -> Result<ChunkSummary, Self::Error> {
+        self.update_chunk(
+            partition_key,
+            chunk_id,
+            ChunkState::Closed,
+            ChunkState::Persisted,
+        )
+        .await
+    }
+
+    /// The test database does not partition the lines written to it,
+    /// so every partition has all of them
+    async fn partition_line_protocol(&self, _partition_key: &str) -> Result<String, Self::Error> {
+        let saved_lines = self.saved_lines.lock().await;
+        Ok(saved_lines
+            .iter()
+            .map(|line| format!("{}\n", line))
+            .collect())
+    }
+
+    async fn delete_series(
+        &self,
+        _partition_key: &str,
+        series_keys: &BTreeSet<String>,
+    ) -> Result<u64, Self::Error> {
+        let mut saved_lines = self.saved_lines.lock().await;
+        let before = saved_lines.len();
+        saved_lines.retain(|line| {
+            let line = parse_lines(line)
+                .next()
+                .expect("one saved line")
+                .expect("Correctly parsed saved line");
+            let tags = line
+                .series
+                .tag_set
+                .iter()
+                .flatten()
+                .map(|(key, value)| (key.as_str(), value.as_str()));
+            !series_keys.contains(&series_key(line.series.measurement.as_str(), tags))
+        });
+
+        let deleted = before - saved_lines.len();
+        if deleted > 0 {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+        Ok(deleted as u64)
+    }
+
     /// Execute the specified query and return arrow record batches with the result
-    async fn query(&self, _query: &str) -> Result<Vec<RecordBatch>, Self::Error> {
-        unimplemented!("query Not yet implemented");
+    async fn query(&self, query: &str) -> Result<Vec<RecordBatch>, Self::Error> {
+        let new_query_request = Some(QueryRequest {
+            query: query.to_string(),
+        });
+
+        *self.query_request.clone().lock().await = new_query_request;
+
+        self.query_values
+            .clone()
+            .lock()
+            .await
+            .take()
+            // Turn None into an error
+            .context(General {
+                message: "No saved query values in TestDatabase",
+            })
+    }
+
+    /// Streams the saved query values, as `query` returns them
+    async fn query_stream(&self, query: &str) -> Result<SendableRecordBatchStream, Self::Error> {
+        let batches = self.query(query).await?;
+        let schema = batches
+            .first()
+            .map(|batch| batch.schema())
+            .unwrap_or_else(|| Arc::new(Schema::empty()));
+        Ok(batches_stream(schema, batches))
     }
 
     /// Return all table names that are saved in this database
-    async fn table_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
+    async fn table_names(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<StringSetPlan>, Self::Error> {
         let saved_lines = self.saved_lines.lock().await;
+        let generation = self.generation();
 
         let names = parse_lines(&saved_lines.join("\n"))
             .filter_map(|line| {
@@ -281,11 +589,14 @@ impl Database for TestDatabase {
             })
             .collect::<StringSet>();
 
-        Ok(names.into())
+        Ok(Planned::new(names.into(), generation))
     }
 
     /// Return the mocked out column names, recording the request
-    async fn tag_column_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
+    async fn tag_column_names(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<StringSetPlan>, Self::Error> {
         // save the request
         let predicate = predicate_to_test_string(&predicate);
 
@@ -305,10 +616,13 @@ impl Database for TestDatabase {
                 message: "No saved column_names in TestDatabase",
             });
 
-        Ok(column_names.into())
+        Ok(Planned::new(column_names.into(), self.generation()))
     }
 
-    async fn field_columns(&self, predicate: Predicate) -> Result<FieldListPlan, Self::Error> {
+    async fn field_columns(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<FieldListPlan>, Self::Error> {
         // save the request
         let predicate = predicate_to_test_string(&predicate);
 
@@ -326,6 +640,7 @@ impl Database for TestDatabase {
             .context(General {
                 message: "No saved field_column_name in TestDatabase",
             })
+            .map(|plan| Planned::new(plan, self.generation()))
     }
 
     /// Return the mocked out column values, recording the request
@@ -333,7 +648,7 @@ impl Database for TestDatabase {
         &self,
         column_name: &str,
         predicate: Predicate,
-    ) -> Result<StringSetPlan, Self::Error> {
+    ) -> Result<Planned<StringSetPlan>, Self::Error> {
         // save the request
         let predicate = predicate_to_test_string(&predicate);
 
@@ -356,10 +671,13 @@ impl Database for TestDatabase {
                 message: "No saved column_values in TestDatabase",
             });
 
-        Ok(column_values.into())
+        Ok(Planned::new(column_values.into(), self.generation()))
     }
 
-    async fn query_series(&self, predicate: Predicate) -> Result<SeriesSetPlans, Self::Error> {
+    async fn query_series(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<SeriesSetPlans>, Self::Error> {
         let predicate = predicate_to_test_string(&predicate);
 
         let new_queries_series_request = Some(QuerySeriesRequest { predicate });
@@ -375,13 +693,24 @@ impl Database for TestDatabase {
             .context(General {
                 message: "No saved query_series in TestDatabase",
             })
+            .map(|plans| Planned::new(plans, self.generation()))
+    }
+
+    /// The test database has no pre-aggregates, so the series of
+    /// window aggregates are always read with `query_series`
+    async fn query_pre_aggregated_series(
+        &self,
+        _predicate: Predicate,
+        _window_aggregate: &WindowAggregate,
+    ) -> Result<Option<Planned<PreAggregatedSeries>>, Self::Error> {
+        Ok(None)
     }
 
     async fn query_groups(
         &self,
         predicate: Predicate,
         group_columns: Vec<String>,
-    ) -> Result<GroupedSeriesSetPlans, Self::Error> {
+    ) -> Result<Planned<GroupedSeriesSetPlans>, Self::Error> {
         let predicate = predicate_to_test_string(&predicate);
 
         let new_queries_groups_request = Some(QueryGroupsRequest {
@@ -400,6 +729,7 @@ impl Database for TestDatabase {
             .context(General {
                 message: "No saved query_groups in TestDatabase",
             })
+            .map(|plans| Planned::new(plans, self.generation()))
     }
 
     /// Fetch the specified table names and columns as Arrow RecordBatches
@@ -410,6 +740,14 @@ impl Database for TestDatabase {
     ) -> Result<Vec<RecordBatch>, Self::Error> {
         unimplemented!("table_to_arrow Not yet implemented for test database");
     }
+
+    async fn query_table(
+        &self,
+        _table_name: &str,
+        _range: Option<TimestampRange>,
+    ) -> Result<(SchemaRef, Vec<RecordBatch>), Self::Error> {
+        unimplemented!("query_table Not yet implemented for test database");
+    }
 }
 
 #[derive(Debug)]
@@ -444,6 +782,14 @@ impl Default for TestDatabaseStore {
 impl DatabaseStore for TestDatabaseStore {
     type Database = TestDatabase;
     type Error = TestError;
+
+    /// List the database names.
+    async fn db_names_sorted(&self) -> Vec<String> {
+        let databases = self.databases.lock().await;
+
+        databases.keys().cloned().collect()
+    }
+
     /// Retrieve the database specified name
     async fn db(&self, name: &str) -> Option<Arc<Self::Database>> {
         let databases = self.databases.lock().await;
@@ -464,4 +810,32 @@ impl DatabaseStore for TestDatabaseStore {
             Ok(new_db)
         }
     }
+
+    /// Create the database specified by name with the given rules
+    async fn create_db(
+        &self,
+        name: &str,
+        rules: DatabaseRules,
+    ) -> Result<Arc<Self::Database>, Self::Error> {
+        let mut databases = self.databases.lock().await;
+
+        if databases.contains_key(name) {
+            return DatabaseExists { name }.fail();
+        }
+
+        let new_db = Arc::new(TestDatabase::new());
+        new_db.set_rules(rules).await?;
+        databases.insert(name.to_string(), new_db.clone());
+        Ok(new_db)
+    }
+
+    /// Remove the database specified by name
+    async fn delete_db(&self, name: &str) -> Result<(), Self::Error> {
+        let mut databases = self.databases.lock().await;
+
+        databases
+            .remove(name)
+            .map(|_| ())
+            .context(DatabaseNotFound { name })
+    }
 }
@@ -1,5 +1,9 @@
 //! This module contains DataFusion utility functions and helpers
-use arrow_deps::datafusion::{logical_plan::Expr, logical_plan::Operator};
+use arrow_deps::{
+    arrow::datatypes::{DataType, Schema},
+    datafusion::{logical_plan::Expr, logical_plan::Operator, scalar::ScalarValue},
+};
+use std::convert::TryFrom;
 
 /// Encode the traversal of an expression tree. When passed to
 /// `visit_expression`, `ExpressionVisitor::visit` is invoked
@@ -172,3 +176,173 @@ impl AndExprBuilder {
         self.cur_expr
     }
 }
+
+/// Rewrites comparisons in `expr` between a numeric column in `schema` and
+/// a numeric literal of another type so both sides have the same type,
+/// rather than failing when the plan is executed. For example, `x > 2.5`
+/// where `x` is an Int64 column becomes `CAST(x AS Float64) > 2.5`, and
+/// `y = 1` where `y` is a Float64 column becomes `y = 1.0`.
+pub fn coerce_numeric_comparisons(expr: Expr, schema: &Schema) -> Expr {
+    match expr {
+        Expr::BinaryExpr { left, op, right } => {
+            let left = coerce_numeric_comparisons(*left, schema);
+            let right = coerce_numeric_comparisons(*right, schema);
+
+            let is_comparison = matches!(
+                op,
+                Operator::Eq
+                    | Operator::NotEq
+                    | Operator::Lt
+                    | Operator::LtEq
+                    | Operator::Gt
+                    | Operator::GtEq
+            );
+
+            let (left, right) = match (&left, &right) {
+                (Expr::Column(name), Expr::Literal(value)) if is_comparison => {
+                    coerce_comparison(name, value, schema).unwrap_or((left, right))
+                }
+                (Expr::Literal(value), Expr::Column(name)) if is_comparison => {
+                    match coerce_comparison(name, value, schema) {
+                        Some((column, literal)) => (literal, column),
+                        None => (left, right),
+                    }
+                }
+                (Expr::Column(left_name), Expr::Column(right_name)) if is_comparison => {
+                    coerce_column_comparison(left_name, right_name, schema).unwrap_or((left, right))
+                }
+                _ => (left, right),
+            };
+
+            Expr::BinaryExpr {
+                left: Box::new(left),
+                op,
+                right: Box::new(right),
+            }
+        }
+        Expr::Not(expr) => Expr::Not(Box::new(coerce_numeric_comparisons(*expr, schema))),
+        Expr::Nested(expr) => Expr::Nested(Box::new(coerce_numeric_comparisons(*expr, schema))),
+        expr => expr,
+    }
+}
+
+/// Returns the column and literal expressions to compare the column `name`
+/// with `value`, if their types differ but can be coerced to match
+fn coerce_comparison(name: &str, value: &ScalarValue, schema: &Schema) -> Option<(Expr, Expr)> {
+    let column = Expr::Column(name.to_string());
+    let column_type = schema.field_with_name(name).ok()?.data_type();
+
+    let float_column = || Expr::Cast {
+        expr: Box::new(Expr::Column(name.to_string())),
+        data_type: DataType::Float64,
+    };
+
+    match (column_type, value) {
+        (DataType::Float64, ScalarValue::Int64(Some(v))) => {
+            Some((column, Expr::Literal(ScalarValue::Float64(Some(*v as f64)))))
+        }
+        (DataType::Float64, ScalarValue::UInt64(Some(v))) => {
+            Some((column, Expr::Literal(ScalarValue::Float64(Some(*v as f64)))))
+        }
+        (DataType::Int64, ScalarValue::UInt64(Some(v))) => Some(match i64::try_from(*v) {
+            Ok(v) => (column, Expr::Literal(ScalarValue::Int64(Some(v)))),
+            Err(_) => (
+                float_column(),
+                Expr::Literal(ScalarValue::Float64(Some(*v as f64))),
+            ),
+        }),
+        (DataType::UInt64, ScalarValue::Int64(Some(v))) => Some(match u64::try_from(*v) {
+            Ok(v) => (column, Expr::Literal(ScalarValue::UInt64(Some(v)))),
+            Err(_) => (
+                float_column(),
+                Expr::Literal(ScalarValue::Float64(Some(*v as f64))),
+            ),
+        }),
+        (DataType::Int64, ScalarValue::Float64(Some(v)))
+        | (DataType::UInt64, ScalarValue::Float64(Some(v))) => Some((
+            float_column(),
+            Expr::Literal(ScalarValue::Float64(Some(*v))),
+        )),
+        _ => None,
+    }
+}
+
+/// Returns the expressions to compare the columns `left` and `right` with,
+/// if they are numeric columns of different types, which are both compared
+/// as Float64 values
+fn coerce_column_comparison(left: &str, right: &str, schema: &Schema) -> Option<(Expr, Expr)> {
+    let left_type = schema.field_with_name(left).ok()?.data_type();
+    let right_type = schema.field_with_name(right).ok()?.data_type();
+
+    let is_numeric = |data_type: &DataType| {
+        matches!(
+            data_type,
+            DataType::Int64 | DataType::UInt64 | DataType::Float64
+        )
+    };
+    if left_type == right_type || !is_numeric(left_type) || !is_numeric(right_type) {
+        return None;
+    }
+
+    let as_float = |name: &str, data_type: &DataType| match data_type {
+        DataType::Float64 => Expr::Column(name.to_string()),
+        _ => Expr::Cast {
+            expr: Box::new(Expr::Column(name.to_string())),
+            data_type: DataType::Float64,
+        },
+    };
+    Some((as_float(left, left_type), as_float(right, right_type)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::{
+        arrow::datatypes::Field,
+        datafusion::logical_plan::{col, lit},
+    };
+
+    #[test]
+    fn coerce_numeric_comparisons_to_column_type() {
+        let schema = Schema::new(vec![
+            Field::new("i", DataType::Int64, true),
+            Field::new("u", DataType::UInt64, true),
+            Field::new("f", DataType::Float64, true),
+            Field::new("s", DataType::Utf8, true),
+        ]);
+
+        let cases = vec![
+            (col("f").eq(lit(1_i64)), "#f Eq Float64(1)"),
+            (lit(1_u64).lt(col("f")), "Float64(1) Lt #f"),
+            (
+                col("i").gt(lit(2.5_f64)),
+                "CAST(#i AS Float64) Gt Float64(2.5)",
+            ),
+            (col("i").eq(lit(3_u64)), "#i Eq Int64(3)"),
+            (
+                col("u").gt_eq(lit(-1_i64)),
+                "CAST(#u AS Float64) GtEq Float64(-1)",
+            ),
+            (col("u").lt(lit(7_i64)), "#u Lt UInt64(7)"),
+            // already the same type, or not numeric
+            (col("i").eq(lit(3_i64)), "#i Eq Int64(3)"),
+            (col("s").eq(lit("x")), "#s Eq Utf8(\"x\")"),
+            (col("missing").eq(lit(2.5_f64)), "#missing Eq Float64(2.5)"),
+            (col("f").gt(col("i")), "#f Gt CAST(#i AS Float64)"),
+            (
+                col("i").lt(col("u")),
+                "CAST(#i AS Float64) Lt CAST(#u AS Float64)",
+            ),
+            (col("i").eq(col("s")), "#i Eq #s"),
+            (
+                col("s").eq(lit("x")).and(col("f").lt_eq(lit(10_i64))),
+                "#s Eq Utf8(\"x\") And #f LtEq Float64(10)",
+            ),
+        ];
+
+        for (expr, expected) in cases {
+            let coerced = coerce_numeric_comparisons(expr, &schema);
+            assert_eq!(format!("{:?}", coerced), expected);
+        }
+    }
+}
@@ -5,9 +5,16 @@
 //! transliteration of the original Go code into Rust as possible. It
 //! does not forcing idomatic Rust when that might obscure the mapping
 //! between the original code and this port.
+//!
+//! Windows are computed in UTC unless a `Location` is given, in which
+//! case they follow the local clock and calendar of the location.
 use chrono::{prelude::*, Month::February};
 use std::ops::{Add, Mul};
 
+pub mod location;
+
+pub use location::Location;
+
 /// Duration is a vector representing the duration unit components.
 ///
 /// Original: https://github.com/influxdata/flux/blob/1e9bfd49f21c0e679b42acf6fc515ce05c6dec2b/values/time.go#L18
@@ -65,6 +72,11 @@ impl Duration {
     pub fn months(&self) -> i64 {
         self.months
     }
+
+    pub fn is_negative(&self) -> bool {
+        self.negative
+    }
+
     pub fn nanoseconds(&self) -> i64 {
         self.nsecs
     }
@@ -260,6 +272,18 @@ pub struct Bounds {
     stop: i64,
 }
 
+impl Bounds {
+    /// The inclusive start of the window
+    pub fn start(&self) -> i64 {
+        self.start
+    }
+
+    /// The exclusive end of the window
+    pub fn stop(&self) -> i64 {
+        self.stop
+    }
+}
+
 /// Represents a window in time
 ///
 /// Original: https://github.com/influxdata/flux/blob/1e9bfd49f21c0e679b42acf6fc515ce05c6dec2b/execute/window.go#L11
@@ -269,6 +293,7 @@ pub struct Window {
     // The period of the window.
     period: Duration,
     offset: Duration,
+    location: Location,
 }
 
 impl Window {
@@ -277,16 +302,62 @@ impl Window {
             every,
             period,
             offset,
+            location: Location::Utc,
         }
     }
 
+    /// Aligns the windows to the local time of `location` rather than
+    /// UTC, so that windows of days start at local midnight and windows
+    /// of months at the first of the month
+    pub fn with_location(self, location: Location) -> Self {
+        Self { location, ..self }
+    }
+
+    /// The distance between the start of consecutive windows
+    pub fn every(&self) -> Duration {
+        self.every
+    }
+
+    /// Returns true if every window starts and stops on a multiple of
+    /// `nanos` nanoseconds since the epoch, so the window of a time is
+    /// determined by the multiple of `nanos` it follows
+    pub fn is_aligned_to(&self, nanos: i64) -> bool {
+        let aligned = |d: Duration| d.months == 0 && d.nsecs % nanos == 0;
+        aligned(self.every)
+            && aligned(self.period)
+            && aligned(self.offset)
+            && matches!(self.location, Location::Utc)
+    }
+
     /// returns the bounds for the earliest window bounds
     /// that contains the given time t.  For underlapping windows that
     /// do not contain time t, the window directly after time t will be
     /// returned.
     ///
-    /// Original: https://github.com/influxdata/flux/blob/1e9bfd49f21c0e679b42acf6fc515ce05c6dec2b/execute/window.go#L70
+    /// Porting note: the bounds are computed in the local time of the
+    /// location, and converted back. Where the clocks are turned back
+    /// the windows of the repeated local times are those of their first
+    /// occurrence, and times of the second occurrence are in the
+    /// window of the last repeated time.
     pub fn get_earliest_bounds(&self, t: i64) -> Bounds {
+        let location = self.location;
+        let local = match location.end_of_repeated(t) {
+            Some(end) => end - 1,
+            None => location.to_local(t),
+        };
+
+        let bounds = self.get_earliest_local_bounds(local);
+        Bounds {
+            start: location.to_utc(bounds.start),
+            stop: location.to_utc(bounds.stop),
+        }
+    }
+
+    /// The bounds (in local time) of the earliest window that contains
+    /// the local time t
+    ///
+    /// Original: https://github.com/influxdata/flux/blob/1e9bfd49f21c0e679b42acf6fc515ce05c6dec2b/execute/window.go#L70
+    fn get_earliest_local_bounds(&self, t: i64) -> Bounds {
         // translate to not-offset coordinate
         // t = t.Add(w.Offset.Mul(-1))
         let t = t + self.offset.mul(-1);
@@ -564,6 +635,101 @@ mod tests {
         }
     }
 
+    #[test]
+    fn get_earliest_bounds_in_location() {
+        let location = Location::load("America/New_York").unwrap();
+        const NS_HOUR: i64 = 60 * 60 * 1_000_000_000;
+
+        let window =
+            |every| Window::new(every, every, Duration::from_nsecs(0)).with_location(location);
+        let day = window(Duration::from_nsecs(24 * NS_HOUR));
+        let hour = window(Duration::from_nsecs(NS_HOUR));
+        let month = window(Duration::from_months(1));
+
+        let testcases = vec![
+            (
+                "day",
+                day,
+                "2021-01-10T12:00:00Z",
+                ("2021-01-10T05:00:00Z", "2021-01-11T05:00:00Z"),
+            ),
+            (
+                "day when the clocks go forward",
+                day,
+                "2021-03-14T12:00:00Z",
+                ("2021-03-14T05:00:00Z", "2021-03-15T04:00:00Z"),
+            ),
+            (
+                "day when the clocks go back",
+                day,
+                "2021-11-07T12:00:00Z",
+                ("2021-11-07T04:00:00Z", "2021-11-08T05:00:00Z"),
+            ),
+            (
+                "month",
+                month,
+                "2021-11-15T00:00:00Z",
+                ("2021-11-01T04:00:00Z", "2021-12-01T05:00:00Z"),
+            ),
+            (
+                "month in local time",
+                month,
+                "2021-12-01T02:00:00Z",
+                ("2021-11-01T04:00:00Z", "2021-12-01T05:00:00Z"),
+            ),
+            (
+                "hour before the clocks go forward",
+                hour,
+                "2021-03-14T06:30:00Z",
+                ("2021-03-14T06:00:00Z", "2021-03-14T07:00:00Z"),
+            ),
+            (
+                "hour after the clocks go forward",
+                hour,
+                "2021-03-14T07:30:00Z",
+                ("2021-03-14T07:00:00Z", "2021-03-14T08:00:00Z"),
+            ),
+            (
+                "repeated hour, first time",
+                hour,
+                "2021-11-07T05:30:00Z",
+                ("2021-11-07T05:00:00Z", "2021-11-07T07:00:00Z"),
+            ),
+            (
+                "repeated hour, second time",
+                hour,
+                "2021-11-07T06:30:00Z",
+                ("2021-11-07T05:00:00Z", "2021-11-07T07:00:00Z"),
+            ),
+        ];
+
+        for (name, w, t, (start, stop)) in testcases {
+            let want = Bounds {
+                start: must_parse_time(start),
+                stop: must_parse_time(stop),
+            };
+            let got = w.get_earliest_bounds(must_parse_time(t));
+            assert_eq!(want, got, "'{}' did not get expected bounds", name);
+        }
+    }
+
+    #[test]
+    fn window_is_aligned_to() {
+        const MINUTE: i64 = 60_000_000_000;
+        let window = |every: Duration, offset: Duration| Window::new(every, every, offset);
+
+        let hours = Duration::from_nsecs(60 * MINUTE);
+        assert!(window(hours, Duration::from_nsecs(0)).is_aligned_to(MINUTE));
+        assert!(window(hours, Duration::from_nsecs(-5 * MINUTE)).is_aligned_to(MINUTE));
+        assert!(!window(hours, Duration::from_nsecs(30_000_000_000)).is_aligned_to(MINUTE));
+        assert!(!window(
+            Duration::from_nsecs(90_000_000_000),
+            Duration::from_nsecs(0)
+        )
+        .is_aligned_to(MINUTE));
+        assert!(!window(Duration::from_months(1), Duration::from_nsecs(0)).is_aligned_to(MINUTE));
+    }
+
     #[test]
     fn test_timestamp_to_datetime() {
         assert_eq!(
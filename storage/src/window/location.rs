@@ -0,0 +1,241 @@
+//! This module contains the locations (time zones) windows can be
+//! aligned to, so that windows of days and months start at local
+//! midnight, and are shorter or longer when the clocks change for
+//! daylight saving time.
+//!
+//! Time zones are those of the IANA time zone database, which
+//! `chrono-tz` compiles into the server, so windows are aligned the
+//! same whatever the time zone database of the host (if it has one).
+//! Leap seconds are ignored.
+
+use chrono::{LocalResult, NaiveDateTime, Offset, TimeZone};
+use chrono_tz::Tz;
+use snafu::{OptionExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Unknown location '{}'", name))]
+    UnknownLocation { name: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+const NANOS_PER_SEC: i64 = 1_000_000_000;
+
+/// Seconds per day. UTC offsets are less than a day.
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// The location whose clock and calendar windows follow
+#[derive(Debug, Clone, Copy)]
+pub enum Location {
+    Utc,
+    Zone(Tz),
+}
+
+impl Location {
+    /// Returns the location named `name`, such as
+    /// `America/New_York`. An empty name is UTC.
+    pub fn load(name: &str) -> Result<Self> {
+        if name.is_empty() || name == "UTC" {
+            return Ok(Self::Utc);
+        }
+
+        let zone: Tz = name.parse().ok().context(UnknownLocation { name })?;
+        Ok(Self::Zone(zone))
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Utc => "UTC",
+            Self::Zone(zone) => zone.name(),
+        }
+    }
+
+    /// Converts the nanosecond timestamp `t` into the local time of
+    /// this location, as nanoseconds since midnight of 1970-01-01
+    /// (local time)
+    pub fn to_local(&self, t: i64) -> i64 {
+        match self {
+            Self::Utc => t,
+            Self::Zone(zone) => {
+                let offset = offset_at(*zone, t.div_euclid(NANOS_PER_SEC));
+                t.saturating_add(offset * NANOS_PER_SEC)
+            }
+        }
+    }
+
+    /// Converts the local time `local` back into a nanosecond
+    /// timestamp. A local time that occurs twice, because the clocks
+    /// were turned back, is the first of the two. A local time that is
+    /// skipped, because the clocks were turned forward, is the time
+    /// the clocks changed.
+    pub fn to_utc(&self, local: i64) -> i64 {
+        let zone = match self {
+            Self::Utc => return local,
+            Self::Zone(zone) => *zone,
+        };
+        let secs = local.div_euclid(NANOS_PER_SEC);
+        let nanos = local.rem_euclid(NANOS_PER_SEC);
+
+        match first_utc(zone, secs) {
+            Some(utc) => utc.saturating_mul(NANOS_PER_SEC).saturating_add(nanos),
+            None => {
+                // skipped: the clocks changed at the first time whose
+                // local time is after it
+                let change = first_time(secs - SECS_PER_DAY, secs + SECS_PER_DAY, |t| {
+                    t + offset_at(zone, t) > secs
+                });
+                change.saturating_mul(NANOS_PER_SEC)
+            }
+        }
+    }
+
+    /// If the local time of `t` also occurred earlier, because the
+    /// clocks were turned back (within a day) before `t`, returns the
+    /// local time at which the repeated times end
+    pub fn end_of_repeated(&self, t: i64) -> Option<i64> {
+        let zone = match self {
+            Self::Utc => return None,
+            Self::Zone(zone) => *zone,
+        };
+        let secs = t.div_euclid(NANOS_PER_SEC);
+        let offset = offset_at(zone, secs);
+
+        // the local time first occurred before the clocks were turned
+        // back from the previous offset
+        let local = secs + offset;
+        let first = first_utc(zone, local).filter(|&first| first < secs)?;
+        let previous_offset = offset_at(zone, first);
+
+        // the local times from the change up to the previous local time
+        // of the change are repeated
+        let change = first_time(first, secs, |t| offset_at(zone, t) == offset);
+        Some((change + previous_offset).saturating_mul(NANOS_PER_SEC))
+    }
+}
+
+/// Returns the UTC offset of `zone` at `secs`, in seconds
+fn offset_at(zone: Tz, secs: i64) -> i64 {
+    let offset = zone.offset_from_utc_datetime(&NaiveDateTime::from_timestamp(secs, 0));
+    i64::from(offset.fix().local_minus_utc())
+}
+
+/// Returns the first time whose local time in `zone` is `local` (in
+/// seconds), or `None` if the local time is skipped
+fn first_utc(zone: Tz, local: i64) -> Option<i64> {
+    let offsets = zone.offset_from_local_datetime(&NaiveDateTime::from_timestamp(local, 0));
+    match offsets.map(|offset| local - i64::from(offset.fix().local_minus_utc())) {
+        LocalResult::Single(utc) => Some(utc),
+        LocalResult::Ambiguous(a, b) => Some(a.min(b)),
+        LocalResult::None => None,
+    }
+}
+
+/// Returns the first time after `from` up to `to` for which `is_after`
+/// is true, which it is for every time after that up to `to`
+fn first_time(mut from: i64, mut to: i64, is_after: impl Fn(i64) -> bool) -> i64 {
+    while to - from > 1 {
+        let middle = from + (to - from) / 2;
+        if is_after(middle) {
+            to = middle;
+        } else {
+            from = middle;
+        }
+    }
+    to
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(s: &str) -> i64 {
+        chrono::DateTime::parse_from_rfc3339(s)
+            .unwrap()
+            .timestamp_nanos()
+    }
+
+    #[test]
+    fn load_locations() {
+        assert!(matches!(Location::load("").unwrap(), Location::Utc));
+        assert!(matches!(
+            Location::load("../etc/passwd"),
+            Err(Error::UnknownLocation { .. })
+        ));
+        assert_eq!(
+            Location::load("Not/A_Zone").unwrap_err().to_string(),
+            "Unknown location 'Not/A_Zone'"
+        );
+        assert_eq!(
+            Location::load("Australia/Lord_Howe").unwrap().name(),
+            "Australia/Lord_Howe"
+        );
+    }
+
+    #[test]
+    fn local_times() {
+        let location = Location::load("America/New_York").unwrap();
+        assert_eq!(location.name(), "America/New_York");
+
+        let utc_midnight = |s: &str| time(&format!("{}T00:00:00Z", s));
+
+        // standard and daylight saving time
+        assert_eq!(
+            location.to_local(time("2021-01-01T05:00:00Z")),
+            utc_midnight("2021-01-01")
+        );
+        assert_eq!(
+            location.to_utc(utc_midnight("2021-07-01")),
+            time("2021-07-01T04:00:00Z")
+        );
+
+        // 02:30 is skipped when the clocks go forward at 02:00
+        assert_eq!(
+            location.to_utc(time("2021-03-14T02:30:00Z")),
+            time("2021-03-14T07:00:00Z")
+        );
+
+        // 01:30 is repeated when the clocks go back at 02:00
+        assert_eq!(
+            location.to_utc(time("2021-11-07T01:30:00Z")),
+            time("2021-11-07T05:30:00Z")
+        );
+        assert_eq!(location.end_of_repeated(time("2021-11-07T05:30:00Z")), None);
+        assert_eq!(
+            location.end_of_repeated(time("2021-11-07T06:30:00Z")),
+            Some(time("2021-11-07T02:00:00Z"))
+        );
+        assert_eq!(location.end_of_repeated(time("2021-11-07T07:00:00Z")), None);
+
+        // far in the future, by the current rules
+        assert_eq!(
+            location.to_utc(utc_midnight("2040-07-01")),
+            time("2040-07-01T04:00:00Z")
+        );
+    }
+
+    #[test]
+    fn local_times_in_the_southern_hemisphere() {
+        // Lord Howe Island turns its clocks back by half an hour, at
+        // 02:00 local time, in April
+        let location = Location::load("Australia/Lord_Howe").unwrap();
+        assert_eq!(
+            location.to_local(time("2021-01-01T00:00:00Z")),
+            time("2021-01-01T11:00:00Z")
+        );
+        assert_eq!(
+            location.to_local(time("2021-07-01T00:00:00Z")),
+            time("2021-07-01T10:30:00Z")
+        );
+
+        // 01:45 is repeated, first at +11 and then at +10:30
+        assert_eq!(
+            location.to_utc(time("2021-04-04T01:45:00Z")),
+            time("2021-04-03T14:45:00Z")
+        );
+        assert_eq!(
+            location.end_of_repeated(time("2021-04-03T15:15:00Z")),
+            Some(time("2021-04-04T02:00:00Z"))
+        );
+    }
+}
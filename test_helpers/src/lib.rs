@@ -9,6 +9,10 @@
 use std::{env, f64, sync::Arc};
 pub use tempfile;
 
+#[cfg(feature = "server")]
+pub mod rpc;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod tracing;
 
 pub type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
@@ -0,0 +1,150 @@
+//! Utilities for making requests to the storage gRPC API and checking
+//! the frames it returns. Frames are compared as strings, so expected
+//! results are easy to write and mismatches easy to read.
+
+use futures::TryStreamExt;
+use generated_types::{
+    read_response::{frame::Data, *},
+    ReadResponse, ReadSource, Tag,
+};
+use prost::Message;
+
+use crate::Result;
+
+/// The source of a request reading the bucket `bucket_id` of the org
+/// `org_id`
+pub fn read_source(org_id: u64, bucket_id: u64) -> Option<prost_types::Any> {
+    let read_source = ReadSource {
+        org_id,
+        bucket_id,
+        partition_id: u64::from(u32::MAX),
+    };
+    let mut value = Vec::new();
+    read_source
+        .encode(&mut value)
+        .expect("encoding to a Vec can't fail");
+    Some(prost_types::Any {
+        type_url: "/TODO".to_string(),
+        value,
+    })
+}
+
+/// Collects the data of all the frames of the responses of a
+/// `read_filter` or `read_group` request
+pub async fn collect_frames(responses: tonic::Streaming<ReadResponse>) -> Result<Vec<Data>> {
+    let responses: Vec<_> = responses.try_collect().await?;
+    Ok(responses
+        .into_iter()
+        .flat_map(|r| r.frames)
+        .flat_map(|f| f.data)
+        .collect())
+}
+
+/// Asserts `actual` frames dump as the `expected` strings, printing
+/// both if they don't
+#[track_caller]
+pub fn assert_frames<S: AsRef<str>>(expected: &[S], actual: &[Data]) {
+    let expected: Vec<&str> = expected.iter().map(AsRef::as_ref).collect();
+    let actual = dump_data_frames(actual);
+    assert_eq!(
+        expected,
+        actual,
+        "Expected:\n{}\nActual:\n{}",
+        expected.join("\n"),
+        actual.join("\n")
+    );
+}
+
+/// substitutes "ns0" --> ns_since_epoch, ns1-->ns_since_epoch+1, etc,
+/// up to ns9
+pub fn substitute_nanos(ns_since_epoch: i64, lines: &[&str]) -> Vec<String> {
+    let substitutions: Vec<_> = (0..10)
+        .map(|i| (format!("ns{}", i), format!("{}", ns_since_epoch + i)))
+        .collect();
+
+    lines
+        .iter()
+        .map(|line| {
+            let mut line = line.to_string();
+            for (from, to) in &substitutions {
+                line = line.replace(from, to);
+            }
+            line
+        })
+        .collect()
+}
+
+pub fn dump_data_frames(frames: &[Data]) -> Vec<String> {
+    frames.iter().map(|f| dump_data(f)).collect()
+}
+
+pub fn dump_data(data: &Data) -> String {
+    match Some(data) {
+        Some(Data::Series(SeriesFrame { tags, data_type })) => format!(
+            "SeriesFrame, tags: {}, type: {:?}",
+            dump_tags(tags),
+            data_type
+        ),
+        Some(Data::FloatPoints(FloatPointsFrame { timestamps, values })) => format!(
+            "FloatPointsFrame, timestamps: {:?}, values: {:?}",
+            timestamps,
+            dump_values(values)
+        ),
+        Some(Data::IntegerPoints(IntegerPointsFrame { timestamps, values })) => format!(
+            "IntegerPointsFrame, timestamps: {:?}, values: {:?}",
+            timestamps,
+            dump_values(values)
+        ),
+        Some(Data::BooleanPoints(BooleanPointsFrame { timestamps, values })) => format!(
+            "BooleanPointsFrame, timestamps: {:?}, values: {}",
+            timestamps,
+            dump_values(values)
+        ),
+        Some(Data::StringPoints(StringPointsFrame { timestamps, values })) => format!(
+            "StringPointsFrame, timestamps: {:?}, values: {}",
+            timestamps,
+            dump_values(values)
+        ),
+        Some(Data::Group(GroupFrame {
+            tag_keys,
+            partition_key_vals,
+        })) => format!(
+            "GroupFrame, tag_keys: {}, partition_key_vals: {}",
+            dump_u8_vec(tag_keys),
+            dump_u8_vec(partition_key_vals),
+        ),
+        None => "<NO data field>".into(),
+        _ => ":thinking_face: unknown frame type".into(),
+    }
+}
+
+fn dump_values<T>(v: &[T]) -> String
+where
+    T: std::fmt::Display,
+{
+    v.iter()
+        .map(|item| format!("{}", item))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn dump_u8_vec(encoded_strings: &[Vec<u8>]) -> String {
+    encoded_strings
+        .iter()
+        .map(|b| String::from_utf8_lossy(b))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+fn dump_tags(tags: &[Tag]) -> String {
+    tags.iter()
+        .map(|tag| {
+            format!(
+                "{}={}",
+                String::from_utf8_lossy(&tag.key),
+                String::from_utf8_lossy(&tag.value),
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
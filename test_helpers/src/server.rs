@@ -0,0 +1,211 @@
+//! Runs an `influxdb_iox` server for integration tests.
+//!
+//! Each `TestServer` listens on its own ports and stores its data in
+//! its own temporary directory, so tests using separate servers can
+//! run in parallel:
+//!
+//! ```no_run
+//! # async fn example() -> test_helpers::Result {
+//! use test_helpers::server::TestServer;
+//!
+//! let server = TestServer::new()?;
+//! server.wait_until_ready().await;
+//!
+//! server
+//!     .write_line_protocol("company", "sensors", "cpu,host=a usage=0.5 100")
+//!     .await?;
+//! let batches = server
+//!     .iox_client()
+//!     .query("company_sensors", "select * from cpu")
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    process::{Child, Command},
+    time::Duration,
+};
+
+use futures::future;
+use generated_types::storage_client::StorageClient;
+use tempfile::TempDir;
+use tonic::transport::Channel;
+
+use crate::Result;
+
+/// The name of the server binary, as built by cargo
+const SERVER_BINARY: &str = "influxdb_iox";
+
+/// How long `wait_until_ready` waits for the server to start
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An `influxdb_iox` server process, which is killed when this is
+/// dropped
+#[derive(Debug)]
+pub struct TestServer {
+    server_process: Child,
+    binary: PathBuf,
+    http_addr: SocketAddr,
+    grpc_addr: SocketAddr,
+
+    // The temporary directory **must** be last so that it is
+    // dropped after the database closes.
+    dir: TempDir,
+}
+
+impl TestServer {
+    /// Starts the `influxdb_iox` binary built by cargo in the target
+    /// directory of the running test
+    pub fn new() -> Result<Self> {
+        Self::with_binary(assert_cmd::cargo::cargo_bin(SERVER_BINARY))
+    }
+
+    /// Starts the server binary at `binary`
+    pub fn with_binary(binary: impl Into<PathBuf>) -> Result<Self> {
+        let _ = dotenv::dotenv(); // load .env file if present
+
+        let dir = crate::tmp_dir()?;
+        let binary = binary.into();
+        let http_addr = unused_addr()?;
+        let grpc_addr = unused_addr()?;
+        let server_process = spawn(&binary, dir.path(), http_addr, grpc_addr)?;
+
+        Ok(Self {
+            server_process,
+            binary,
+            http_addr,
+            grpc_addr,
+            dir,
+        })
+    }
+
+    /// Kills the server and starts it again with the same ports and
+    /// data directory, to test the data is restored
+    pub fn restart(&mut self) -> Result<()> {
+        self.server_process.kill()?;
+        self.server_process.wait()?;
+        self.server_process = spawn(
+            &self.binary,
+            self.dir.path(),
+            self.http_addr,
+            self.grpc_addr,
+        )?;
+        Ok(())
+    }
+
+    /// The directory the server stores its data in
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// The base URL of the HTTP API, such as `http://127.0.0.1:8080`
+    pub fn http_base(&self) -> String {
+        format!("http://{}", self.http_addr)
+    }
+
+    /// The base URL of the gRPC API, such as `http://127.0.0.1:8082`
+    pub fn grpc_base(&self) -> String {
+        format!("http://{}", self.grpc_addr)
+    }
+
+    /// A client of the HTTP API of the server
+    pub fn iox_client(&self) -> influxdb_iox_client::Client {
+        influxdb_iox_client::Client::new(self.http_base())
+    }
+
+    /// Connects a client to the Arrow Flight API of the server
+    pub async fn flight_client(&self) -> Result<influxdb_iox_client::FlightClient> {
+        Ok(influxdb_iox_client::FlightClient::connect(self.grpc_base()).await?)
+    }
+
+    /// Connects a client to the storage gRPC API of the server
+    pub async fn storage_client(&self) -> Result<StorageClient<Channel>> {
+        Ok(StorageClient::connect(self.grpc_base()).await?)
+    }
+
+    /// Writes `lp` to the bucket `bucket` of the org `org`
+    pub async fn write_line_protocol(&self, org: &str, bucket: &str, lp: &str) -> Result<()> {
+        self.iox_client()
+            .write_line_protocol(org, bucket, lp.to_string())
+            .await?;
+        Ok(())
+    }
+
+    /// Waits until both the HTTP and gRPC servers accept requests
+    pub async fn wait_until_ready(&self) {
+        // Poll the RPC and HTTP servers separately as they listen on
+        // different ports but both need to be up for the test to run
+        let try_grpc_connect = async {
+            let mut interval = tokio::time::interval(Duration::from_millis(500));
+            loop {
+                match self.storage_client().await {
+                    Ok(storage_client) => {
+                        println!(
+                            "Successfully connected storage_client: {:?}",
+                            storage_client
+                        );
+                        return;
+                    }
+                    Err(e) => {
+                        println!("Waiting for gRPC server to be up: {}", e);
+                    }
+                }
+                interval.tick().await;
+            }
+        };
+
+        let try_http_connect = async {
+            let client = self.iox_client();
+            let mut interval = tokio::time::interval(Duration::from_millis(500));
+            loop {
+                match client.ping().await {
+                    Ok(()) => {
+                        println!("Successfully pinged HTTP server");
+                        return;
+                    }
+                    Err(e) => {
+                        println!("Waiting for HTTP server to be up: {}", e);
+                    }
+                }
+                interval.tick().await;
+            }
+        };
+
+        let pair = future::join(try_http_connect, try_grpc_connect);
+
+        let capped_check = tokio::time::timeout(STARTUP_TIMEOUT, pair);
+
+        match capped_check.await {
+            Ok(_) => println!("Server is up correctly"),
+            Err(e) => println!("WARNING: server was not ready: {}", e),
+        }
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.server_process
+            .kill()
+            .expect("Should have been able to kill the test server");
+    }
+}
+
+fn spawn(binary: &Path, dir: &Path, http_addr: SocketAddr, grpc_addr: SocketAddr) -> Result<Child> {
+    Ok(Command::new(binary)
+        // Can enable for debbugging
+        //.arg("-vv")
+        .env("INFLUXDB_IOX_DB_DIR", dir)
+        .env("INFLUXDB_IOX_BIND_ADDR", http_addr.to_string())
+        .env("INFLUXDB_IOX_GRPC_BIND_ADDR", grpc_addr.to_string())
+        .spawn()?)
+}
+
+/// An address on localhost with a port nothing is listening on
+fn unused_addr() -> Result<SocketAddr> {
+    // the listener is closed before the server binds the port, so
+    // another process could take it in between, but that is unlikely
+    Ok(TcpListener::bind("127.0.0.1:0")?.local_addr()?)
+}
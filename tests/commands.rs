@@ -394,3 +394,136 @@ fn stats_temperature_parquet() {
             "temperature.parquet: total columns (  5), rows: 6.00      (       6), size: 1.13 k    (    1128), bits per row: 1504.0000"
         ));
 }
+
+#[test]
+fn write_non_existent_input_filename() {
+    let mut cmd = Command::cargo_bin("influxdb_iox").unwrap();
+    let assert = cmd
+        .arg("write")
+        .arg("--org")
+        .arg("company")
+        .arg("--bucket")
+        .arg("sensors")
+        .arg("--file")
+        .arg("non_existent_input.lp")
+        .assert();
+
+    assert.failure().code(7).stderr(predicate::str::contains(
+        "Import failed: Error opening non_existent_input.lp",
+    ));
+}
+
+#[test]
+fn write_csv_without_measurement() {
+    let csv_file = test_helpers::tempfile::Builder::new()
+        .suffix(".csv")
+        .tempfile()
+        .expect("creating temp file");
+    fs::write(csv_file.path(), "host,usage,time\nserver01,0.5,100\n").expect("writing CSV");
+
+    let mut cmd = Command::cargo_bin("influxdb_iox").unwrap();
+    let assert = cmd
+        .arg("write")
+        .arg("--org")
+        .arg("company")
+        .arg("--bucket")
+        .arg("sensors")
+        .arg("--columns")
+        .arg("host:tag")
+        .arg("--file")
+        .arg(csv_file.path())
+        .assert();
+
+    assert.failure().code(7).stderr(predicate::str::contains(
+        "CSV rows need a measurement, either from --measurement or a NAME:measurement column",
+    ));
+}
+
+#[test]
+fn import_bad_mapping() {
+    let mut cmd = Command::cargo_bin("influxdb_iox").unwrap();
+    let assert = cmd
+        .arg("import")
+        .arg("--map")
+        .arg("telegraf=company/sensors")
+        .arg("tests/fixtures/lineproto")
+        .assert();
+
+    assert.failure().code(8).stderr(predicate::str::contains(
+        "Import failed: Invalid mapping 'telegraf=company/sensors'",
+    ));
+}
+
+#[test]
+fn inspect_temperature_parquet() {
+    let mut cmd = Command::cargo_bin("influxdb_iox").unwrap();
+    let assert = cmd
+        .arg("inspect")
+        .arg("tests/fixtures/parquet/temperature.parquet")
+        .assert();
+
+    assert
+        .success()
+        .stdout(predicate::str::contains(
+            "Table temperature: tests/fixtures/parquet/temperature.parquet",
+        ))
+        .stdout(predicate::str::contains("Rows: 6 in 1 row group"))
+        .stdout(predicate::str::contains("Tombstones: none"))
+        .stdout(predicate::str::contains("RLE_DICTIONARY"))
+        .stdout(predicate::str::contains("state"));
+}
+
+#[test]
+fn inspect_directory_without_chunk_files() {
+    let mut cmd = Command::cargo_bin("influxdb_iox").unwrap();
+    let assert = cmd.arg("inspect").arg("tests/fixtures/lineproto").assert();
+
+    assert.failure().code(10).stderr(predicate::str::contains(
+        "Inspect failed: No chunk files found in tests/fixtures/lineproto",
+    ));
+}
+
+#[test]
+fn generate_line_protocol_file() {
+    let dir = test_helpers::tmp_dir().unwrap();
+    let spec_path = dir.path().join("workload.toml");
+    fs::write(
+        &spec_path,
+        r#"
+seed = 3
+start_time = "2020-11-01T00:00:00Z"
+ticks = 5
+time_spacing = { type = "regular", interval_ms = 1000 }
+
+[[measurements]]
+name = "cpu"
+lines_per_tick = 2
+
+[[measurements.tags]]
+name = "host"
+cardinality = 2
+distribution = { type = "sequential" }
+
+[[measurements.fields]]
+name = "requests"
+value = { type = "counter", start = 0, increment = 1 }
+"#,
+    )
+    .expect("writing spec");
+    let output_path = dir.path().join("workload.lp");
+
+    let mut cmd = Command::cargo_bin("influxdb_iox").unwrap();
+    cmd.arg("generate")
+        .arg("--output")
+        .arg(&output_path)
+        .arg(&spec_path)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Generated 10 lines"));
+
+    let lines = fs::read_to_string(&output_path).expect("reading output");
+    let lines: Vec<_> = lines.lines().collect();
+    assert_eq!(lines.len(), 10);
+    assert_eq!(lines[0], "cpu,host=host-0 requests=0i 1604188800000000000");
+    assert_eq!(lines[9], "cpu,host=host-1 requests=4i 1604188804000000000");
+}
@@ -1,57 +1,42 @@
-// The test in this file runs the server in a separate thread and makes HTTP requests as a smoke
-// test for the integration of the whole system.
+// The test in this file runs the server in a separate process and makes HTTP and gRPC requests
+// as a smoke test for the integration of the whole system.
 //
-// As written, only one test of this style can run at a time. Add more data to the existing test to
-// test more scenarios rather than adding more tests in the same style.
-//
-// Or, change the way this test behaves to create isolated instances by:
-//
-// - Finding an unused port for the server to run on and using that port in the URL
-// - Creating a temporary directory for an isolated database path
-//
-// Or, change the tests to use one server and isolate through `org_id` by:
-//
-// - Starting one server before all the relevant tests are run
-// - Creating a unique org_id per test
-// - Stopping the server after all relevant tests are run
+// The server is run with `test_helpers::server::TestServer`, which gives each server its own
+// ports and database directory, so tests in this style can run in parallel.
 
-use assert_cmd::prelude::*;
 use futures::prelude::*;
 use generated_types::{
+    aggregate::AggregateType,
     node::{Comparison, Type as NodeType, Value},
     read_group_request::Group,
-    read_response::{frame::Data, *},
-    storage_client::StorageClient,
-    MeasurementFieldsRequest, MeasurementNamesRequest, MeasurementTagKeysRequest,
-    MeasurementTagValuesRequest, Node, Predicate, ReadFilterRequest, ReadGroupRequest, ReadSource,
-    Tag, TagKeysRequest, TagValuesRequest, TimestampRange,
+    read_response::*,
+    Aggregate, MeasurementFieldsRequest, MeasurementNamesRequest, MeasurementTagKeysRequest,
+    MeasurementTagValuesRequest, Node, Predicate, ReadFilterRequest, ReadGroupRequest,
+    TagKeysRequest, TagValuesRequest, TimestampRange, Window,
 };
-use prost::Message;
 use std::convert::TryInto;
 use std::fs;
-use std::process::{Child, Command};
 use std::str;
-use std::time::{Duration, SystemTime};
-use std::u32;
-use tempfile::TempDir;
-use test_helpers::*;
-
-const HTTP_BASE: &str = "http://localhost:8080";
-const API_BASE: &str = "http://localhost:8080/api/v2";
-const GRPC_URL_BASE: &str = "http://localhost:8082/";
+use std::time::SystemTime;
+use test_helpers::{
+    rpc::{assert_frames, collect_frames, read_source, substitute_nanos},
+    server::TestServer,
+};
+
 const TOKEN: &str = "InfluxDB IOx doesn't have authentication yet";
 
 type Error = Box<dyn std::error::Error + Send + Sync + 'static>;
 type Result<T, E = Error> = std::result::Result<T, E>;
 
 async fn read_data_as_sql(
+    server: &TestServer,
     client: &reqwest::Client,
     path: &str,
     org_id: &str,
     bucket_id: &str,
     sql_query: &str,
 ) -> Result<Vec<String>> {
-    let url = format!("{}{}", API_BASE, path);
+    let url = format!("{}/api/v2{}", server.http_base(), path);
     let lines = client
         .get(&url)
         .query(&[
@@ -94,7 +79,7 @@ async fn read_and_write_data() -> Result<()> {
     let bucket_id = u64::from_str_radix(bucket_id_str, 16).unwrap();
 
     let client = reqwest::Client::new();
-    let client2 = influxdb2_client::Client::new(HTTP_BASE, TOKEN);
+    let client2 = influxdb2_client::Client::new(server.http_base(), TOKEN);
 
     let start_time = SystemTime::now();
     let ns_since_epoch: i64 = start_time
@@ -175,6 +160,7 @@ async fn read_and_write_data() -> Result<()> {
     );
 
     let text = read_data_as_sql(
+        &server,
         &client,
         "/read",
         org_id_str,
@@ -189,7 +175,7 @@ async fn read_and_write_data() -> Result<()> {
     );
 
     // Make an invalid organization WAL dir to test that the server ignores it instead of crashing
-    let invalid_org_dir = server.dir.path().join("not-an-org-id");
+    let invalid_org_dir = server.dir().join("not-an-org-id");
     fs::create_dir(invalid_org_dir)?;
 
     // Test the WAL by restarting the server
@@ -199,6 +185,7 @@ async fn read_and_write_data() -> Result<()> {
     // Then check the entries are restored from the WAL
 
     let text = read_data_as_sql(
+        &server,
         &client,
         "/read",
         org_id_str,
@@ -208,26 +195,16 @@ async fn read_and_write_data() -> Result<()> {
     .await?;
     assert_eq!(text, expected_read_data);
 
-    let mut storage_client = StorageClient::connect(GRPC_URL_BASE).await?;
+    test_iox_client(&server, org_id_str, bucket_id_str).await?;
+
+    let mut storage_client = server.storage_client().await?;
 
     // Validate that capabilities rpc endpoint is hooked up
     let capabilities_response = storage_client.capabilities(()).await?;
     let capabilities_response = capabilities_response.into_inner();
     assert_eq!(capabilities_response.caps, std::collections::HashMap::new());
 
-    let partition_id = u64::from(u32::MAX);
-    let read_source = ReadSource {
-        org_id,
-        bucket_id,
-        partition_id,
-    };
-    let mut d = Vec::new();
-    read_source.encode(&mut d)?;
-    let read_source = prost_types::Any {
-        type_url: "/TODO".to_string(),
-        value: d,
-    };
-    let read_source = Some(read_source);
+    let read_source = read_source(org_id, bucket_id);
 
     let range = TimestampRange {
         start: ns_since_epoch,
@@ -261,13 +238,7 @@ async fn read_and_write_data() -> Result<()> {
         predicate: predicate.clone(),
     });
     let read_response = storage_client.read_filter(read_filter_request).await?;
-
-    let responses: Vec<_> = read_response.into_inner().try_collect().await?;
-    let frames: Vec<Data> = responses
-        .into_iter()
-        .flat_map(|r| r.frames)
-        .flat_map(|f| f.data)
-        .collect();
+    let frames = collect_frames(read_response.into_inner()).await?;
 
     assert_eq!(frames.len(), 10);
 
@@ -284,15 +255,7 @@ async fn read_and_write_data() -> Result<()> {
         "IntegerPointsFrame, timestamps: [ns6], values: \"4\""
     ]);
 
-    let actual_frames = dump_data_frames(&frames);
-
-    assert_eq!(
-        expected_frames,
-        actual_frames,
-        "Expected:\n{}\nActual:\n{}",
-        expected_frames.join("\n"),
-        actual_frames.join("\n")
-    );
+    assert_frames(&expected_frames, &frames);
 
     let tag_keys_request = tonic::Request::new(TagKeysRequest {
         tags_source: read_source.clone(),
@@ -334,16 +297,11 @@ async fn read_and_write_data() -> Result<()> {
         group: Group::By as _,
         aggregate: None,
         hints: 0,
+        window: None,
+        fill: None,
     });
     let read_group_response = storage_client.read_group(read_group_request).await?;
-
-    let responses: Vec<_> = read_group_response.into_inner().try_collect().await?;
-
-    let frames: Vec<_> = responses
-        .into_iter()
-        .flat_map(|r| r.frames)
-        .flat_map(|f| f.data)
-        .collect();
+    let frames = collect_frames(read_group_response.into_inner()).await?;
 
     let expected_group_frames = substitute_nanos(ns_since_epoch, &[
         "GroupFrame, tag_keys: region, partition_key_vals: ",
@@ -357,15 +315,53 @@ async fn read_and_write_data() -> Result<()> {
         "FloatPointsFrame, timestamps: [ns0, ns4], values: \"0.64,0.000003\""
     ]);
 
-    let actual_group_frames = dump_data_frames(&frames);
+    assert_frames(&expected_group_frames, &frames);
 
-    assert_eq!(
-        expected_group_frames,
-        actual_group_frames,
-        "Expected:\n{}\nActual:\n{}",
-        expected_group_frames.join("\n"),
-        actual_group_frames.join("\n")
-    );
+    // group by region and sum the values in windows of 2ns, aligned
+    // so that the first window starts at ns0
+    let read_group_request = tonic::Request::new(ReadGroupRequest {
+        read_source: read_source.clone(),
+        range: range.clone(),
+        predicate: predicate.clone(),
+        group_keys: vec![String::from("region")],
+        group: Group::By as _,
+        aggregate: Some(Aggregate {
+            r#type: AggregateType::Sum as _,
+            k: 0,
+            unit: 0,
+        }),
+        hints: 0,
+        window: Some(Window {
+            every: Some(generated_types::Duration {
+                nsecs: 2,
+                months: 0,
+                negative: false,
+            }),
+            offset: Some(generated_types::Duration {
+                nsecs: ns_since_epoch % 2,
+                months: 0,
+                negative: false,
+            }),
+            location: String::new(),
+        }),
+        fill: None,
+    });
+    let read_group_response = storage_client.read_group(read_group_request).await?;
+    let frames = collect_frames(read_group_response.into_inner()).await?;
+
+    let expected_group_frames = substitute_nanos(ns_since_epoch, &[
+        "GroupFrame, tag_keys: region, partition_key_vals: ",
+        "SeriesFrame, tags: _field=value,_measurement=cpu_load_short,region=,host=server01, type: 0",
+        "FloatPointsFrame, timestamps: [ns2], values: \"27.99\"",
+        "GroupFrame, tag_keys: region, partition_key_vals: us-east",
+        "SeriesFrame, tags: _field=value,_measurement=cpu_load_short,region=us-east,host=server01, type: 0",
+        "FloatPointsFrame, timestamps: [ns4], values: \"1234567.891011\"",
+        "GroupFrame, tag_keys: region, partition_key_vals: us-west",
+        "SeriesFrame, tags: _field=value,_measurement=cpu_load_short,region=us-west,host=server01, type: 0",
+        "FloatPointsFrame, timestamps: [ns2, ns6], values: \"0.64,0.000003\""
+    ]);
+
+    assert_frames(&expected_group_frames, &frames);
 
     let measurement_names_request = tonic::Request::new(MeasurementNamesRequest {
         source: read_source.clone(),
@@ -458,213 +454,106 @@ async fn read_and_write_data() -> Result<()> {
     Ok(())
 }
 
-// Don't make a separate #test function so that we can reuse the same
-// server process
-async fn test_http_error_messages(client: &influxdb2_client::Client) -> Result<()> {
-    // send malformed request (bucket id is invalid)
-    let result = client
-        .write_line_protocol("Bar", "Foo", "arbitrary")
-        .await
-        .expect_err("Should have errored");
+#[tokio::test]
+async fn read_filter_from_test_server() -> Result<()> {
+    let server = TestServer::new()?;
+    server.wait_until_ready().await;
 
-    let expected_error = "HTTP request returned an error: 400 Bad Request, `{\"error\":\"Error parsing line protocol: A generic parsing error occurred: TakeWhile1\"}`";
-    assert_eq!(result.to_string(), expected_error);
+    let org_id = "0000222200002222";
+    let bucket_id = "2222000022220000";
+    server
+        .write_line_protocol(
+            org_id,
+            bucket_id,
+            "cpu,host=server01 usage=0.5 100\ncpu,host=server02 usage=0.75 200",
+        )
+        .await?;
+
+    let mut storage_client = server.storage_client().await?;
+    let read_filter_request = tonic::Request::new(ReadFilterRequest {
+        read_source: read_source(
+            u64::from_str_radix(org_id, 16)?,
+            u64::from_str_radix(bucket_id, 16)?,
+        ),
+        range: Some(TimestampRange {
+            start: 0,
+            end: 1000,
+        }),
+        predicate: None,
+    });
+    let read_response = storage_client.read_filter(read_filter_request).await?;
+    let frames = collect_frames(read_response.into_inner()).await?;
+
+    assert_frames(
+        &[
+            "SeriesFrame, tags: _field=usage,_measurement=cpu,host=server01, type: 0",
+            "FloatPointsFrame, timestamps: [100], values: \"0.5\"",
+            "SeriesFrame, tags: _field=usage,_measurement=cpu,host=server02, type: 0",
+            "FloatPointsFrame, timestamps: [200], values: \"0.75\"",
+        ],
+        &frames,
+    );
 
     Ok(())
 }
 
-/// substitutes "ns" --> ns_since_epoch, ns1-->ns_since_epoch+1, etc
-fn substitute_nanos(ns_since_epoch: i64, lines: &[&str]) -> Vec<String> {
-    let substitutions = vec![
-        ("ns0", format!("{}", ns_since_epoch)),
-        ("ns1", format!("{}", ns_since_epoch + 1)),
-        ("ns2", format!("{}", ns_since_epoch + 2)),
-        ("ns3", format!("{}", ns_since_epoch + 3)),
-        ("ns4", format!("{}", ns_since_epoch + 4)),
-        ("ns5", format!("{}", ns_since_epoch + 5)),
-        ("ns6", format!("{}", ns_since_epoch + 6)),
-    ];
+// Don't make a separate #test function so that we can reuse the same
+// server process
+/// Checks the data written to the bucket `bucket_id` of the org
+/// `org_id` with the IOx client
+async fn test_iox_client(server: &TestServer, org_id: &str, bucket_id: &str) -> Result<()> {
+    let client = server.iox_client();
+    let db_name = format!("{}_{}", org_id, bucket_id);
 
-    lines
-        .iter()
-        .map(|line| {
-            let mut line = line.to_string();
-            for (from, to) in &substitutions {
-                line = line.replace(from, to);
-            }
-            line
-        })
-        .collect()
-}
+    let build_info = client.build_info().await?;
+    assert_eq!(build_info.version, env!("CARGO_PKG_VERSION"));
 
-struct TestServer {
-    server_process: Child,
+    let databases = client.list_databases().await?;
+    assert!(databases.iter().any(|db| db.name == db_name));
 
-    // The temporary directory **must** be last so that it is
-    // dropped after the database closes.
-    #[allow(dead_code)]
-    dir: TempDir,
-}
+    let batches = client
+        .query(&db_name, "select count(*) from cpu_load_short")
+        .await?;
+    let rows: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+    assert_eq!(rows, 1);
 
-impl TestServer {
-    fn new() -> Result<Self> {
-        let _ = dotenv::dotenv(); // load .env file if present
-
-        let dir = test_helpers::tmp_dir()?;
-
-        let server_process = Command::cargo_bin("influxdb_iox")?
-            // Can enable for debbugging
-            //.arg("-vv")
-            .env("INFLUXDB_IOX_DB_DIR", dir.path())
-            .spawn()?;
-
-        Ok(Self {
-            dir,
-            server_process,
-        })
-    }
-
-    fn restart(&mut self) -> Result<()> {
-        self.server_process.kill()?;
-        self.server_process.wait()?;
-        self.server_process = Command::cargo_bin("influxdb_iox")?
-            // Can enable for debbugging
-            //.arg("-vv")
-            .env("INFLUXDB_IOX_DB_DIR", self.dir.path())
-            .spawn()?;
-        Ok(())
-    }
-
-    async fn wait_until_ready(&self) {
-        // Poll the RPC and HTTP servers separately as they listen on
-        // different ports but both need to be up for the test to run
-        let try_grpc_connect = async {
-            let mut interval = tokio::time::interval(Duration::from_millis(500));
-            loop {
-                match StorageClient::connect(GRPC_URL_BASE).await {
-                    Ok(storage_client) => {
-                        println!(
-                            "Successfully connected storage_client: {:?}",
-                            storage_client
-                        );
-                        return;
-                    }
-                    Err(e) => {
-                        println!("Waiting for gRPC server to be up: {}", e);
-                    }
-                }
-                interval.tick().await;
-            }
-        };
-
-        let try_http_connect = async {
-            let client = reqwest::Client::new();
-            let url = format!("{}/ping", HTTP_BASE);
-            let mut interval = tokio::time::interval(Duration::from_millis(500));
-            loop {
-                match client.get(&url).send().await {
-                    Ok(resp) => {
-                        println!("Successfully got a response from HTTP: {:?}", resp);
-                        return;
-                    }
-                    Err(e) => {
-                        println!("Waiting for HTTP server to be up: {}", e);
-                    }
-                }
-                interval.tick().await;
-            }
-        };
-
-        let pair = future::join(try_http_connect, try_grpc_connect);
-
-        let capped_check = tokio::time::timeout(Duration::from_secs(3), pair);
-
-        match capped_check.await {
-            Ok(_) => println!("Server is up correctly"),
-            Err(e) => println!("WARNING: server was not ready: {}", e),
-        }
-    }
-}
+    let chunks = client.list_chunks(&db_name).await?;
+    assert!(!chunks.is_empty());
 
-impl Drop for TestServer {
-    fn drop(&mut self) {
-        self.server_process
-            .kill()
-            .expect("Should have been able to kill the test server");
-    }
-}
+    let mut flight_client = server.flight_client().await?;
+    let tables = flight_client.tables(&db_name).await?;
+    assert_eq!(
+        tables,
+        vec!["attributes", "cpu_load_short", "status", "swap", "system"]
+    );
 
-fn dump_data_frames(frames: &[Data]) -> Vec<String> {
-    frames.iter().map(|f| dump_data(f)).collect()
-}
+    let schema = flight_client
+        .table_schema(&db_name, "cpu_load_short")
+        .await?;
+    assert!(schema.field_with_name("host").is_ok());
+    assert_eq!(
+        influxdb_iox_client::flight::tag_columns(&schema),
+        vec!["host", "region"]
+    );
 
-fn dump_data(data: &Data) -> String {
-    match Some(data) {
-        Some(Data::Series(SeriesFrame { tags, data_type })) => format!(
-            "SeriesFrame, tags: {}, type: {:?}",
-            dump_tags(tags),
-            data_type
-        ),
-        Some(Data::FloatPoints(FloatPointsFrame { timestamps, values })) => format!(
-            "FloatPointsFrame, timestamps: {:?}, values: {:?}",
-            timestamps,
-            dump_values(values)
-        ),
-        Some(Data::IntegerPoints(IntegerPointsFrame { timestamps, values })) => format!(
-            "IntegerPointsFrame, timestamps: {:?}, values: {:?}",
-            timestamps,
-            dump_values(values)
-        ),
-        Some(Data::BooleanPoints(BooleanPointsFrame { timestamps, values })) => format!(
-            "BooleanPointsFrame, timestamps: {:?}, values: {}",
-            timestamps,
-            dump_values(values)
-        ),
-        Some(Data::StringPoints(StringPointsFrame { timestamps, values })) => format!(
-            "StringPointsFrame, timestamps: {:?}, values: {}",
-            timestamps,
-            dump_values(values)
-        ),
-        Some(Data::Group(GroupFrame {
-            tag_keys,
-            partition_key_vals,
-        })) => format!(
-            "GroupFrame, tag_keys: {}, partition_key_vals: {}",
-            dump_u8_vec(tag_keys),
-            dump_u8_vec(partition_key_vals),
-        ),
-        None => "<NO data field>".into(),
-        _ => ":thinking_face: unknown frame type".into(),
-    }
-}
+    let batches = flight_client
+        .query(&db_name, "select * from cpu_load_short")
+        .await?;
+    let rows: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+    assert_eq!(rows, 5);
 
-fn dump_values<T>(v: &[T]) -> String
-where
-    T: std::fmt::Display,
-{
-    v.iter()
-        .map(|item| format!("{}", item))
-        .collect::<Vec<_>>()
-        .join(",")
+    Ok(())
 }
 
-fn dump_u8_vec(encoded_strings: &[Vec<u8>]) -> String {
-    encoded_strings
-        .iter()
-        .map(|b| String::from_utf8_lossy(b))
-        .collect::<Vec<_>>()
-        .join(",")
-}
+async fn test_http_error_messages(client: &influxdb2_client::Client) -> Result<()> {
+    // send malformed request (bucket id is invalid)
+    let result = client
+        .write_line_protocol("Bar", "Foo", "arbitrary")
+        .await
+        .expect_err("Should have errored");
+
+    let expected_error = "HTTP request returned an error: 400 Bad Request, `{\"error\":\"Error parsing line protocol: A generic parsing error occurred: TakeWhile1\"}`";
+    assert_eq!(result.to_string(), expected_error);
 
-fn dump_tags(tags: &[Tag]) -> String {
-    tags.iter()
-        .map(|tag| {
-            format!(
-                "{}={}",
-                String::from_utf8_lossy(&tag.key),
-                String::from_utf8_lossy(&tag.value),
-            )
-        })
-        .collect::<Vec<_>>()
-        .join(",")
+    Ok(())
 }
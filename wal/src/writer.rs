@@ -60,7 +60,9 @@ impl WalDetails {
         })?)
     }
 
-    pub async fn write_and_sync(&self, data: Vec<u8>) -> Result<()> {
+    /// Appends `data` to the WAL, returning the sequence number it was
+    /// written with once it has been synced to disk
+    pub async fn write_and_sync(&self, data: Vec<u8>) -> Result<SequenceNumber> {
         let payload = WritePayload::new(data).context(UnderlyingWalError {})?;
 
         let (notify_tx, mut notify_rx) = mpsc::channel(1);
@@ -72,13 +74,13 @@ impl WalDetails {
             .await
             .expect("The WAL thread should always be running to receive a write");
 
-        let _ = notify_rx
+        let sequence_number = notify_rx
             .next()
             .await
             .expect("The WAL thread should always be running to send a response.")
             .context(UnderlyingWalError {})?;
 
-        Ok(())
+        Ok(sequence_number)
     }
 }
 
@@ -0,0 +1,162 @@
+//! This module copies the files of persisted chunks to an object
+//! store, so that they are also kept off the server, and encrypted at
+//! rest if the store encrypts what it stores. The files of a chunk are
+//! copied under a prefix of their own (see `Db::chunk_object_prefix`),
+//! which is removed from the store along with the chunk's directory.
+
+use std::path::{Path, PathBuf};
+
+use bytes::Bytes;
+use futures::{stream, TryStreamExt};
+use object_store::ObjectStore;
+use snafu::{ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error reading persisted chunk files in {:?}: {}", dir, source))]
+    ReadingChunkFiles {
+        dir: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Error copying {} to the object store: {}", location, source))]
+    CopyingObject {
+        location: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error listing {} in the object store: {}", prefix, source))]
+    ListingObjects {
+        prefix: String,
+        source: object_store::Error,
+    },
+
+    #[snafu(display("Error removing {} from the object store: {}", location, source))]
+    RemovingObject {
+        location: String,
+        source: object_store::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Copies the files of the chunk persisted to `chunk_dir` to `store`,
+/// each named `prefix` followed by its file name, returning the
+/// number of files copied
+pub async fn copy_chunk(store: &ObjectStore, chunk_dir: &Path, prefix: &str) -> Result<usize> {
+    let mut entries = tokio::fs::read_dir(chunk_dir)
+        .await
+        .context(ReadingChunkFiles { dir: chunk_dir })?;
+
+    let mut copied = 0;
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .context(ReadingChunkFiles { dir: chunk_dir })?
+    {
+        let path = entry.path();
+        let file_name = match path.file_name().and_then(|name| name.to_str()) {
+            Some(file_name) => file_name,
+            None => continue,
+        };
+        let location = format!("{}{}", prefix, file_name);
+
+        let contents = tokio::fs::read(&path)
+            .await
+            .context(ReadingChunkFiles { dir: chunk_dir })?;
+        let length = contents.len();
+        let contents = stream::iter(vec![Ok::<_, std::io::Error>(Bytes::from(contents))]);
+        store
+            .put(&location, contents, length)
+            .await
+            .context(CopyingObject {
+                location: &location,
+            })?;
+        copied += 1;
+    }
+
+    Ok(copied)
+}
+
+/// Removes the objects named with `prefix` from `store`, returning the
+/// number removed
+pub async fn remove_chunk(store: &ObjectStore, prefix: &str) -> Result<usize> {
+    let locations: Vec<String> = store
+        .list(Some(prefix))
+        .await
+        .context(ListingObjects { prefix })?
+        .try_concat()
+        .await
+        .context(ListingObjects { prefix })?;
+
+    for location in &locations {
+        store
+            .delete(location)
+            .await
+            .context(RemovingObject { location })?;
+    }
+
+    Ok(locations.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use object_store::{encryption::StaticKeyProvider, InMemory};
+    use std::sync::Arc;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    async fn get(store: &ObjectStore, location: &str) -> Result<Vec<u8>> {
+        let bytes: Vec<Bytes> = store.get(location).await?.try_collect().await?;
+        Ok(bytes.concat())
+    }
+
+    async fn list(store: &ObjectStore) -> Result<Vec<String>> {
+        let mut locations: Vec<String> = store.list(None).await?.try_concat().await?;
+        locations.sort();
+        Ok(locations)
+    }
+
+    #[tokio::test]
+    async fn copies_and_removes_chunk_files() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        std::fs::write(dir.path().join("cpu.parquet"), b"cpu data")?;
+        std::fs::write(dir.path().join("cpu.encodings.json"), b"{}")?;
+
+        let key = StaticKeyProvider::new("test", &[7; 32])?;
+        let store =
+            ObjectStore::new_encrypted(ObjectStore::new_in_memory(InMemory::new()), Arc::new(key));
+
+        let copied = copy_chunk(&store, dir.path(), "mydb/chunks/p/0/").await?;
+        assert_eq!(copied, 2);
+        let other = copy_chunk(&store, dir.path(), "mydb/chunks/p/1/").await?;
+        assert_eq!(other, 2);
+        assert_eq!(
+            list(&store).await?,
+            vec![
+                "mydb/chunks/p/0/cpu.encodings.json",
+                "mydb/chunks/p/0/cpu.parquet",
+                "mydb/chunks/p/1/cpu.encodings.json",
+                "mydb/chunks/p/1/cpu.parquet",
+            ]
+        );
+
+        // the copies are decrypted as they are read back
+        assert_eq!(
+            get(&store, "mydb/chunks/p/0/cpu.parquet").await?,
+            b"cpu data"
+        );
+
+        assert_eq!(remove_chunk(&store, "mydb/chunks/p/0/").await?, 2);
+        assert_eq!(
+            list(&store).await?,
+            vec![
+                "mydb/chunks/p/1/cpu.encodings.json",
+                "mydb/chunks/p/1/cpu.parquet",
+            ]
+        );
+        Ok(())
+    }
+}
@@ -4,6 +4,9 @@ use snafu::Snafu;
 use crate::dictionary::Dictionary;
 use data_types::{data::type_description, partition_metadata::Statistics};
 
+use std::collections::BTreeSet;
+use std::mem;
+
 #[derive(Debug, Snafu)]
 pub enum Error {
     #[snafu(display("Don't know how to insert a column of type {}", inserted_value_type))]
@@ -114,6 +117,22 @@ impl Column {
         self.len() == 0
     }
 
+    /// The approximate number of bytes used to store the values of
+    /// this column. Tag values are stored in the partition dictionary
+    /// and so are not included.
+    pub fn size(&self) -> usize {
+        match self {
+            Self::F64(v, _) => mem::size_of::<Option<f64>>() * v.len(),
+            Self::I64(v, _) => mem::size_of::<Option<i64>>() * v.len(),
+            Self::String(v, _) => v
+                .iter()
+                .map(|s| mem::size_of::<Option<String>>() + s.as_ref().map_or(0, |s| s.len()))
+                .sum(),
+            Self::Bool(v, _) => mem::size_of::<Option<bool>>() * v.len(),
+            Self::Tag(v, _) => mem::size_of::<Option<u32>>() * v.len(),
+        }
+    }
+
     pub fn type_description(&self) -> &'static str {
         match self {
             Self::F64(_, _) => "f64",
@@ -232,6 +251,29 @@ impl Column {
         }
     }
 
+    /// Returns false if the statistics of this tag or string column
+    /// show that no row has the value `value`. Always returns true
+    /// for columns of other types
+    pub fn might_contain_str(&self, value: &str) -> bool {
+        match self {
+            Self::Tag(_, stats) | Self::String(_, stats) => {
+                stats.min.as_str() <= value && value <= stats.max.as_str()
+            }
+            _ => true,
+        }
+    }
+
+    /// Returns false if this is a tag column in which no row has one
+    /// of the dictionary ids `value_ids`
+    pub fn might_contain_any_id(&self, value_ids: &BTreeSet<u32>) -> bool {
+        match self {
+            Self::Tag(vals, _) => vals
+                .iter()
+                .any(|v| v.map_or(false, |id| value_ids.contains(&id))),
+            _ => true,
+        }
+    }
+
     /// Returns true if there exists at least one row idx where this
     /// self[i] is within the range [min_value, max_value). Inclusive
     /// of `start`, exclusive of `end` and where col[i] is non null
@@ -285,6 +327,43 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_might_contain_str() {
+        let mut stats = Statistics::new("MA".to_string());
+        stats.update("NY".to_string());
+        let col = Column::Tag(vec![Some(1), None, Some(2)], stats.clone());
+        assert!(!col.might_contain_str("CA"));
+        assert!(col.might_contain_str("MA"));
+        assert!(col.might_contain_str("NJ"));
+        assert!(col.might_contain_str("NY"));
+        assert!(!col.might_contain_str("TX"));
+
+        let col = Column::String(vec![Some("MA".to_string())], stats);
+        assert!(!col.might_contain_str("CA"));
+
+        let col = Column::F64(vec![Some(1.2)], Statistics::new(1.2));
+        assert!(col.might_contain_str("CA"));
+    }
+
+    #[test]
+    fn test_might_contain_any_id() {
+        let ids = |ids: &[u32]| ids.iter().cloned().collect::<BTreeSet<_>>();
+
+        let col = Column::Tag(
+            vec![Some(1), None, Some(2)],
+            Statistics::new("MA".to_string()),
+        );
+        assert!(col.might_contain_any_id(&ids(&[2, 3])));
+        assert!(!col.might_contain_any_id(&ids(&[3])));
+        assert!(!col.might_contain_any_id(&ids(&[])));
+
+        let col = Column::String(
+            vec![Some("MA".to_string())],
+            Statistics::new("MA".to_string()),
+        );
+        assert!(col.might_contain_any_id(&ids(&[3])));
+    }
+
     #[test]
     fn test_has_i64_range_does_not_panic() -> Result {
         // providing the wrong column type should get an internal error, not a panic
@@ -1,12 +1,16 @@
 use generated_types::wal as wb;
-use influxdb_line_protocol::ParsedLine;
+use influxdb_line_protocol::{FieldValue, ParsedLine};
+use object_store::ObjectStore;
 use storage::{
     exec::{
-        stringset::StringSet, FieldListPlan, GroupedSeriesSetPlan, GroupedSeriesSetPlans,
+        batches_stream, execute_stream,
+        stringset::StringSet,
+        window_aggregate::{BucketedSeries, PreAggregatedSeries, WindowAggregate},
+        ChunkStringSetPlan, FieldListPlan, GroupedSeriesSetPlan, GroupedSeriesSetPlans, Planned,
         SeriesSetPlan, SeriesSetPlans, StringSetPlan,
     },
-    predicate::Predicate,
-    Database,
+    predicate::{Predicate, TimestampRange},
+    Database, Pacer,
 };
 use wal::{
     writer::{start_wal_sync_task, Error as WalWriterError, WalDetails},
@@ -14,14 +18,24 @@ use wal::{
 };
 
 use crate::column::Column;
-use crate::partition::Partition;
-use crate::{partition::PartitionPredicate, table::Table};
+use crate::partition::{self, Partition};
+use crate::persisted::{OpenMode, PersistedChunk};
+use crate::pre_aggregate::{self, BUCKET_NANOS};
+use crate::{
+    partition::PartitionPredicate,
+    table::{self, Table},
+};
 
-use std::io::ErrorKind;
+use std::borrow::Cow;
+use std::io::{ErrorKind, Write};
 use std::path::PathBuf;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+use std::time::Duration;
 use std::{
-    collections::{BTreeSet, HashSet},
+    collections::{BTreeMap, BTreeSet, HashSet},
     path::Path,
 };
 
@@ -31,24 +45,41 @@ use arrow_deps::{
     datafusion::logical_plan::LogicalPlan,
     datafusion::prelude::ExecutionConfig,
     datafusion::{
-        datasource::MemTable, error::DataFusionError, execution::context::ExecutionContext,
+        datasource::MemTable,
+        error::DataFusionError,
+        execution::context::ExecutionContext,
+        physical_plan::{ExecutionPlan, SendableRecordBatchStream},
+    },
+};
+use data_types::{
+    data::{split_lines_into_write_entry_partitions, ReplicatedWrite},
+    database_rules::{
+        DatabaseRules, DownsamplingRule, NonFiniteFloatPolicy, TimestampBounds, TimestampPolicy,
     },
+    partition_metadata::{ChunkStorage, ChunkSummary, PartitionSummary},
 };
-use data_types::data::{split_lines_into_write_entry_partitions, ReplicatedWrite};
 
+use crate::archive;
+use crate::dedup;
 use crate::dictionary::Error as DictionaryError;
-use crate::partition::restore_partitions_from_wal;
+use crate::downsample;
+use crate::encoding;
+use crate::explain::{AnalyzedPlan, Explain, PlanDescriptions};
+use crate::histogram;
+use crate::rewrite;
+use crate::sql_range;
 
 use async_trait::async_trait;
-use chrono::{offset::TimeZone, Utc};
-use snafu::{OptionExt, ResultExt, Snafu};
+use chrono::{offset::TimeZone, DateTime, Utc};
+use serde::{de::DeserializeOwned, Serialize};
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
 use sqlparser::{
     ast::{SetExpr, Statement, TableFactor},
     dialect::GenericDialect,
     parser::Parser,
 };
-use tokio::sync::RwLock;
-use tracing::info;
+use tokio::sync::{Notify, RwLock};
+use tracing::{debug, debug_span, info, warn};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -98,6 +129,12 @@ pub enum Error {
     PartitionFull { partition: String },
 
     #[snafu(display("Error in {}: {}", source_module, source))]
+    #[snafu(display("Error waiting for blocking task of database {}: {}", database, source))]
+    BlockingTask {
+        database: String,
+        source: tokio::task::JoinError,
+    },
+
     PassThrough {
         source_module: &'static str,
         source: Box<dyn std::error::Error + Send + Sync + 'static>,
@@ -164,6 +201,15 @@ pub enum Error {
     ))]
     UnsupportedColumnTypeForListingValues { column_name: String },
 
+    #[snafu(display("Error deduplicating rows of table {}: {}", table, source))]
+    Deduplicating {
+        table: String,
+        source: crate::dedup::Error,
+    },
+
+    #[snafu(display("Table {} not found in database {}", table, database))]
+    TableNotFound { table: String, database: String },
+
     #[snafu(display("Table {} not found in partition {}", table, partition))]
     TableNotFoundInPartition { table: u32, partition: String },
 
@@ -202,6 +248,148 @@ pub enum Error {
 
     #[snafu(display("replicated write from writer {} missing payload", writer))]
     MissingPayload { writer: u32 },
+
+    #[snafu(display(
+        "Write to database {} rejected: the buffer would grow to {} bytes, over its limit of {}",
+        database,
+        size,
+        limit
+    ))]
+    BufferSizeExceeded {
+        database: String,
+        size: u64,
+        limit: u64,
+    },
+
+    #[snafu(display(
+        "Write to database {} rejected: timestamp {} is outside the accepted range {} to {}",
+        database,
+        timestamp,
+        min,
+        max
+    ))]
+    TimestampOutOfBounds {
+        database: String,
+        timestamp: i64,
+        min: i64,
+        max: i64,
+    },
+
+    #[snafu(display(
+        "Write to database {} rejected: {} rows of table {} have no timestamp, including rows {:?}",
+        database,
+        count,
+        table,
+        rows
+    ))]
+    MissingTimestamps {
+        database: String,
+        table: String,
+        count: usize,
+        rows: Vec<usize>,
+    },
+
+    #[snafu(display(
+        "Write to database {} rejected: field {} of measurement {} is {}, which is not a finite number",
+        database,
+        field,
+        measurement,
+        value
+    ))]
+    NonFiniteFloat {
+        database: String,
+        measurement: String,
+        field: String,
+        value: f64,
+    },
+
+    #[snafu(display(
+        "Write to database {} rejected: its WAL is still being replayed",
+        database
+    ))]
+    Replaying { database: String },
+
+    #[snafu(display("Error reading {:?} for database {}: {}", path, database, source))]
+    ReadingDbFile {
+        database: String,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Error writing {:?} for database {}: {}", path, database, source))]
+    WritingDbFile {
+        database: String,
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid contents of {:?} for database {}: {}", path, database, source))]
+    InvalidDbFile {
+        database: String,
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display("Partition {} not found in database {}", partition, database))]
+    PartitionNotFound { database: String, partition: String },
+
+    #[snafu(display(
+        "Chunks of partition {} of database {} changed while they were compacted",
+        partition,
+        database
+    ))]
+    CompactedChunksChanged { database: String, partition: String },
+
+    #[snafu(display(
+        "Chunk {} of partition {} not found in database {}",
+        chunk,
+        partition,
+        database
+    ))]
+    ChunkNotFound {
+        database: String,
+        partition: String,
+        chunk: u32,
+    },
+
+    #[snafu(display(
+        "Chunk {} of partition {} in database {} is already closed",
+        chunk,
+        partition,
+        database
+    ))]
+    ChunkAlreadyClosed {
+        database: String,
+        partition: String,
+        chunk: u32,
+    },
+
+    #[snafu(display(
+        "Chunk {} of partition {} in database {} must be closed before it is persisted",
+        chunk,
+        partition,
+        database
+    ))]
+    ChunkNotClosed {
+        database: String,
+        partition: String,
+        chunk: u32,
+    },
+
+    #[snafu(display("Database {} has no directory to persist chunks to", database))]
+    NoPersistenceDir { database: String },
+
+    #[snafu(display("Unsupported rules for database {}: {}", database, source))]
+    UnsupportedRules {
+        database: String,
+        source: data_types::database_rules::Error,
+    },
+
+    #[snafu(display("Error copying persisted chunks of database {}: {}", database, source))]
+    CopyingChunks {
+        database: String,
+        source: crate::archive::Error,
+    },
 }
 
 impl From<crate::table::Error> for Error {
@@ -222,14 +410,177 @@ impl From<crate::partition::Error> for Error {
     }
 }
 
+impl From<crate::downsample::Error> for Error {
+    fn from(e: crate::downsample::Error) -> Self {
+        Self::PassThrough {
+            source_module: "Downsample",
+            source: Box::new(e),
+        }
+    }
+}
+
+impl From<crate::encoding::Error> for Error {
+    fn from(e: crate::encoding::Error) -> Self {
+        Self::PassThrough {
+            source_module: "Encoding",
+            source: Box::new(e),
+        }
+    }
+}
+
+impl From<crate::persisted::Error> for Error {
+    fn from(e: crate::persisted::Error) -> Self {
+        Self::PassThrough {
+            source_module: "Persisted",
+            source: Box::new(e),
+        }
+    }
+}
+
+impl From<crate::histogram::Error> for Error {
+    fn from(e: crate::histogram::Error) -> Self {
+        Self::PassThrough {
+            source_module: "Histogram",
+            source: Box::new(e),
+        }
+    }
+}
+
+impl From<crate::rewrite::Error> for Error {
+    fn from(e: crate::rewrite::Error) -> Self {
+        Self::PassThrough {
+            source_module: "Rewrite",
+            source: Box::new(e),
+        }
+    }
+}
+
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// The name of the file in a database's WAL directory that its rules
+/// are stored in
+pub const RULES_FILE_NAME: &str = "rules.json";
+
+/// The name of the file in a database's WAL directory that records
+/// which partitions have been dropped or truncated
+pub const DROPPED_PARTITIONS_FILE_NAME: &str = "dropped_partitions.json";
+
+/// The name of the file in a database's WAL directory that records
+/// which series have been deleted from which partitions
+pub const DELETED_SERIES_FILE_NAME: &str = "deleted_series.json";
+
+/// The name of the file in a database's WAL directory that describes
+/// the chunks which have been evicted from memory after being persisted
+pub const EVICTED_CHUNKS_FILE_NAME: &str = "evicted_chunks.json";
+
+/// The name of the directory in a database's WAL directory that its
+/// persisted chunks are written to, as
+/// `<partition key>/<chunk id>/<table>.parquet`
+pub const PERSISTED_CHUNKS_DIR_NAME: &str = "chunks";
+
 #[derive(Debug, Default)]
 pub struct Db {
     pub name: String,
     // TODO: partitions need to be wrapped in an Arc if they're going to be used without this lock
     partitions: RwLock<Vec<Partition>>,
     wal_details: Option<WalDetails>,
+    /// Incremented each time data is written to the partitions
+    generation: AtomicU64,
+    /// The rules currently in effect for this database
+    rules: RwLock<DatabaseRules>,
+    /// The WAL directory of this database, if it has one, which its
+    /// rules and dropped partitions are also persisted in
+    dir: Option<PathBuf>,
+    /// The sequence number the next write to the WAL will be given
+    next_wal_sequence: AtomicU64,
+    /// The number of bytes of data in the WAL, which are read when the
+    /// database is restored from it
+    wal_bytes: AtomicU64,
+    /// Maps the key of each partition that has been dropped or
+    /// truncated to the WAL sequence number its data was dropped
+    /// before, so that the data is not restored from the WAL
+    dropped_partitions: RwLock<BTreeMap<String, u64>>,
+    /// Maps the key of each partition series have been deleted from
+    /// to the keys of those series and the WAL sequence number their
+    /// rows were deleted before, so that the rows are not restored
+    /// from the WAL
+    deleted_series: RwLock<BTreeMap<String, BTreeMap<String, u64>>>,
+    /// The chunks which have been persisted and evicted from memory,
+    /// whose data is only in their parquet files
+    evicted_chunks: std::sync::RwLock<Vec<ChunkSummary>>,
+    /// When this database was opened, in nanoseconds since the epoch,
+    /// which partitions restored from the WAL are treated as last
+    /// written at until they are written to again
+    opened_time: i64,
+    /// Counts of the chunks queries have scanned and pruned, shared
+    /// with the metrics of the server
+    chunk_pruning: Arc<ChunkPruningMetrics>,
+    /// Notified each time the rules of this database change, so that
+    /// the tasks applying them (such as dropping partitions past the
+    /// retention period) can do so without waiting for their next run
+    rules_changed: Arc<Notify>,
+    /// How far through replaying its WAL this database is, while it
+    /// is being replayed
+    replay: std::sync::Mutex<Option<ReplayProgress>>,
+    /// How the files of evicted chunks are opened when queries read
+    /// them back
+    persisted_open_mode: OpenMode,
+    /// The object store the files of persisted chunks are copied to, if
+    /// any
+    object_store: Option<Arc<ObjectStore>>,
+}
+
+/// How the WAL of a database is replayed
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayOptions {
+    /// Apply at most this many WAL entries per second (unlimited if
+    /// not set), so that replaying does not starve the queries of the
+    /// partitions already replayed
+    pub max_entries_per_second: Option<u64>,
+}
+
+/// How far through replaying its WAL a database is
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ReplayProgress {
+    pub entries_applied: u64,
+    pub entries_total: u64,
+    /// The number of partitions whose data has all been replayed, and
+    /// which queries can see
+    pub partitions_replayed: u64,
+}
+
+impl ReplayProgress {
+    pub fn entries_remaining(&self) -> u64 {
+        self.entries_total.saturating_sub(self.entries_applied)
+    }
+}
+
+/// The number of chunks the queries of a database have scanned, and
+/// the number they have pruned because the statistics of the chunk
+/// show none of its rows can match the query
+#[derive(Debug, Default)]
+pub struct ChunkPruningMetrics {
+    chunks_scanned: AtomicU64,
+    chunks_pruned: AtomicU64,
+}
+
+impl ChunkPruningMetrics {
+    fn record(&self, scanned: usize, pruned: usize) {
+        self.chunks_scanned
+            .fetch_add(scanned as u64, Ordering::Relaxed);
+        self.chunks_pruned
+            .fetch_add(pruned as u64, Ordering::Relaxed);
+    }
+
+    /// The total number of chunks scanned by queries
+    pub fn chunks_scanned(&self) -> u64 {
+        self.chunks_scanned.load(Ordering::Relaxed)
+    }
+
+    /// The total number of chunks pruned by queries
+    pub fn chunks_pruned(&self) -> u64 {
+        self.chunks_pruned.load(Ordering::Relaxed)
+    }
 }
 
 impl Db {
@@ -270,6 +621,7 @@ impl Db {
         Ok(Self {
             name,
             wal_details: Some(wal_details),
+            dir: Some(wal_dir.clone()),
             ..Default::default()
         })
     }
@@ -277,7 +629,15 @@ impl Db {
     /// Create a new DB and initially restore pre-existing data in the
     /// Write Ahead Log (WAL) directory `wal_dir`
     pub async fn restore_from_wal(wal_dir: &Path) -> Result<Self> {
-        let now = std::time::Instant::now();
+        let db = Self::open_from_wal(wal_dir).await?;
+        db.replay_wal(ReplayOptions::default(), |_| {}).await?;
+        Ok(db)
+    }
+
+    /// Opens the DB in the Write Ahead Log (WAL) directory `wal_dir`
+    /// with its rules but none of its data, which is restored by
+    /// `replay_wal`. Writes are rejected until the WAL is replayed.
+    pub async fn open_from_wal(wal_dir: &Path) -> Result<Self> {
         let name = wal_dir
             .iter()
             .last()
@@ -286,162 +646,618 @@ impl Db {
             .with_context(|| OpenDb { dir: &wal_dir })?
             .to_string();
 
-        let wal_builder = WalBuilder::new(wal_dir);
-        let wal_details = start_wal_sync_task(wal_builder.clone())
+        let rules: DatabaseRules = read_json_file(&name, &wal_dir.join(RULES_FILE_NAME))?;
+        rules
+            .validate()
+            .context(UnsupportedRules { database: &name })?;
+        let dropped_partitions: BTreeMap<String, u64> =
+            read_json_file(&name, &wal_dir.join(DROPPED_PARTITIONS_FILE_NAME))?;
+        let deleted_series: BTreeMap<String, BTreeMap<String, u64>> =
+            read_json_file(&name, &wal_dir.join(DELETED_SERIES_FILE_NAME))?;
+        let evicted_chunks: Vec<ChunkSummary> =
+            read_json_file(&name, &wal_dir.join(EVICTED_CHUNKS_FILE_NAME))?;
+
+        let wal_details = start_wal_sync_task(WalBuilder::new(wal_dir))
             .await
             .context(OpeningWal { database: &name })?;
 
-        // TODO: check wal metadata format
-        let entries = wal_builder
-            .entries()
-            .context(LoadingWal { database: &name })?;
-
-        let (partitions, stats) =
-            restore_partitions_from_wal(entries).context(WalRecoverError { database: &name })?;
-
-        let elapsed = now.elapsed();
-        info!(
-            "{} database loaded {} rows in {:?} in {} tables",
-            &name,
-            stats.row_count,
-            elapsed,
-            stats.tables.len(),
-        );
-
-        info!("{} database partition count: {}", &name, partitions.len(),);
-
         Ok(Self {
             name,
-            partitions: RwLock::new(partitions),
             wal_details: Some(wal_details),
+            rules: RwLock::new(rules),
+            dir: Some(wal_dir.to_path_buf()),
+            dropped_partitions: RwLock::new(dropped_partitions),
+            deleted_series: RwLock::new(deleted_series),
+            evicted_chunks: std::sync::RwLock::new(evicted_chunks),
+            opened_time: Utc::now().timestamp_nanos(),
+            replay: std::sync::Mutex::new(Some(ReplayProgress::default())),
+            ..Default::default()
         })
     }
 
-    async fn write_entries_to_partitions(&self, batch: &wb::WriteBufferBatch<'_>) -> Result<()> {
-        if let Some(entries) = batch.entries() {
-            let mut partitions = self.partitions.write().await;
+    /// Restores the data in the WAL of a DB opened with
+    /// `open_from_wal`, calling `on_progress` after each WAL entry is
+    /// applied.
+    ///
+    /// Each partition becomes visible to queries as soon as the last
+    /// WAL entry with data for it is applied, so queries of the
+    /// partitions already replayed can be answered while the rest of
+    /// the WAL is replayed.
+    pub async fn replay_wal(
+        &self,
+        options: ReplayOptions,
+        on_progress: impl Fn(ReplayProgress),
+    ) -> Result<()> {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => {
+                self.set_replay_progress(None);
+                return Ok(());
+            }
+        };
+        let now = std::time::Instant::now();
 
-            for entry in entries {
-                let key = entry
-                    .partition_key()
-                    .expect("partition key should have been inserted");
+        // The WAL is read once up front to find the last entry with
+        // data for each partition, so that each partition can be
+        // queried once that entry is applied
+        let scan = scan_wal(&self.name, dir)?;
+        self.next_wal_sequence
+            .fetch_max(scan.next_sequence_number, Ordering::SeqCst);
+        self.wal_bytes.fetch_add(scan.bytes, Ordering::SeqCst);
+
+        let mut progress = ReplayProgress {
+            entries_total: scan.entries,
+            ..Default::default()
+        };
+        self.set_replay_progress(Some(progress));
+        on_progress(progress);
+
+        // TODO: check wal metadata format
+        let entries = WalBuilder::new(dir).entries().context(LoadingWal {
+            database: &self.name,
+        })?;
+        let dropped_partitions = self.dropped_partitions.read().await.clone();
+        let deleted_series = self.deleted_series.read().await.clone();
+        let mut replaying: BTreeMap<String, Partition> = BTreeMap::new();
+        let mut size = 0;
+
+        for (index, wal_entry) in entries.enumerate() {
+            let wal_entry = wal_entry.context(LoadingWal {
+                database: &self.name,
+            })?;
+            let sequence_number = wal_entry.sequence_number();
+            let bytes = wal_entry.as_data();
+            let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&bytes);
+
+            let mut replayed = BTreeSet::new();
+            if let Some(entries) = batch.entries() {
+                for entry in entries {
+                    let key = entry
+                        .partition_key()
+                        .expect("partition key should have been inserted");
+                    if scan.last_entries.get(key) == Some(&index) {
+                        replayed.insert(key);
+                    }
 
-                match partitions.iter_mut().find(|p| p.should_write(key)) {
-                    Some(p) => p.write_entry(&entry)?,
-                    None => {
-                        let mut p = Partition::new(key);
-                        p.write_entry(&entry)?;
-                        partitions.push(p)
+                    let dropped = dropped_partitions
+                        .get(key)
+                        .map_or(false, |dropped_before| sequence_number < *dropped_before);
+                    if !dropped {
+                        let chunk = replaying
+                            .entry(key.to_string())
+                            .or_insert_with(|| Partition::new(key));
+                        match deleted_series.get(key) {
+                            Some(deleted) => chunk.write_entry_rows(&entry, |table, row| {
+                                deleted
+                                    .get(&partition::row_series_key(table, row))
+                                    .map_or(true, |deleted_before| {
+                                        sequence_number >= *deleted_before
+                                    })
+                            })?,
+                            None => chunk.write_entry(&entry)?,
+                        }
                     }
                 }
             }
+
+            for key in replayed {
+                if let Some(partition) = replaying.remove(key) {
+                    size += partition.size();
+                    self.add_replayed_partition(partition).await;
+                    progress.partitions_replayed += 1;
+                }
+            }
+
+            progress.entries_applied += 1;
+            self.set_replay_progress(Some(progress));
+            on_progress(progress);
+
+            // Sleep until the average rate falls to the limit
+            if let Some(max_entries_per_second) = options.max_entries_per_second {
+                let target = Duration::from_secs_f64(
+                    progress.entries_applied as f64 / max_entries_per_second.max(1) as f64,
+                );
+                let elapsed = now.elapsed();
+                if target > elapsed {
+                    tokio::time::delay_for(target - elapsed).await;
+                }
+            }
+        }
+
+        // the WAL may have been appended to since it was scanned, if
+        // it was opened by another process
+        for (_, partition) in replaying {
+            size += partition.size();
+            self.add_replayed_partition(partition).await;
+            progress.partitions_replayed += 1;
         }
+        self.set_replay_progress(None);
+        on_progress(progress);
+
+        info!(
+            "{} database replayed {} WAL entries into {} partitions of {} bytes in {:?}",
+            &self.name,
+            progress.entries_applied,
+            progress.partitions_replayed,
+            size,
+            now.elapsed(),
+        );
 
         Ok(())
     }
-}
 
-#[async_trait]
-impl Database for Db {
-    type Error = Error;
+    /// Returns how far through replaying its WAL this database is, or
+    /// `None` if it is not replaying its WAL
+    pub fn replay_progress(&self) -> Option<ReplayProgress> {
+        *self.replay.lock().expect("mutex poisoned")
+    }
 
-    // TODO: writes lines creates a column named "time" for the timestmap data. If
-    //       we keep this we need to validate that no tag or field has the same name.
-    async fn write_lines(&self, lines: &[ParsedLine<'_>]) -> Result<(), Self::Error> {
-        let data = split_lines_into_write_entry_partitions(partition_key, lines);
-        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+    fn set_replay_progress(&self, progress: Option<ReplayProgress>) {
+        *self.replay.lock().expect("mutex poisoned") = progress;
+    }
 
-        self.write_entries_to_partitions(&batch).await?;
+    /// Makes `partition`, which has been replayed from the WAL,
+    /// visible to queries. Replayed partitions are kept in order of
+    /// their keys, whichever order they finish replaying in.
+    async fn add_replayed_partition(&self, mut partition: Partition) {
+        let mut partitions = self.partitions.write().await;
+        partition.id = self.next_chunk_id(&partitions, &partition.key);
+        let index = partitions
+            .iter()
+            .position(|p| p.key > partition.key)
+            .unwrap_or_else(|| partitions.len());
+        partitions.insert(index, partition);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
 
-        if let Some(wal) = &self.wal_details {
-            wal.write_and_sync(data).await.context(WritingWal {
-                database: &self.name,
-            })?;
-        }
+    /// Notifies `rules_changed` each time the rules of this database
+    /// change, rather than a notifier of its own, so one task can wait
+    /// for the rules of any of several databases to change
+    pub(crate) fn with_rules_notify(mut self, rules_changed: Arc<Notify>) -> Self {
+        self.rules_changed = rules_changed;
+        self
+    }
 
-        Ok(())
+    /// Opens the files of evicted chunks as `mode` when they are read
+    /// back, rather than reading each file into memory first
+    pub fn with_persisted_open_mode(mut self, mode: OpenMode) -> Self {
+        self.persisted_open_mode = mode;
+        self
     }
 
-    async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
-        match write.write_buffer_batch() {
-            Some(b) => self.write_entries_to_partitions(&b).await?,
-            None => {
-                return MissingPayload {
-                    writer: write.to_fb().writer(),
-                }
-                .fail()
-            }
+    /// Returns how the files of evicted chunks are opened when they are
+    /// read back
+    pub fn persisted_open_mode(&self) -> OpenMode {
+        self.persisted_open_mode
+    }
+
+    /// Copies the files of each chunk to `store` once it is persisted,
+    /// and removes them from it along with the chunk. A chunk is only
+    /// evicted from memory once its files have been copied.
+    pub fn with_object_store(mut self, store: Arc<ObjectStore>) -> Self {
+        self.object_store = Some(store);
+        self
+    }
+
+    /// Returns the prefix of the names the files of chunk `chunk_id` of
+    /// partition `partition_key` are copied to the object store with,
+    /// which follow the directory they are persisted to
+    fn chunk_object_prefix(&self, partition_key: &str, chunk_id: u32) -> String {
+        format!(
+            "{}/{}/{}/{}/",
+            self.name,
+            PERSISTED_CHUNKS_DIR_NAME,
+            path_safe(partition_key),
+            chunk_id
+        )
+    }
+
+    /// Copies the files of the persisted chunks `chunks`, given by their
+    /// partition keys and ids, to the object store, if there is one
+    async fn copy_persisted_chunks<'a>(
+        &self,
+        chunks: impl IntoIterator<Item = (&'a str, u32)>,
+    ) -> Result<()> {
+        let (store, dir) = match (&self.object_store, &self.dir) {
+            (Some(store), Some(dir)) => (store, dir),
+            _ => return Ok(()),
         };
 
-        if let Some(wal) = &self.wal_details {
-            // TODO(paul): refactor this so we're not cloning. Although replicated writes shouldn't
-            //  be using a WAL and how the WAL is used at all is likely to have a larger refactor soon.
-            wal.write_and_sync(write.data.clone())
+        for (partition_key, chunk_id) in chunks {
+            let chunk_dir = persisted_chunk_dir(dir, partition_key, chunk_id);
+            let prefix = self.chunk_object_prefix(partition_key, chunk_id);
+            let copied = archive::copy_chunk(store, &chunk_dir, &prefix)
                 .await
-                .context(WritingWal {
+                .context(CopyingChunks {
                     database: &self.name,
                 })?;
+            debug!(
+                "{} database copied {} files of chunk {} of partition {} to the object store",
+                &self.name, copied, chunk_id, partition_key
+            );
         }
-
         Ok(())
     }
 
-    async fn table_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
-        // TODO: Cache this information to avoid creating this each time
-        let partitions = self.partitions.read().await;
+    /// Returns the counts of the chunks queries of this database have
+    /// scanned and pruned
+    pub fn chunk_pruning_metrics(&self) -> Arc<ChunkPruningMetrics> {
+        Arc::clone(&self.chunk_pruning)
+    }
 
-        let mut table_names: BTreeSet<String> = BTreeSet::new();
-        for partition in partitions.iter() {
-            let partition_predicate = partition.compile_predicate(&predicate)?;
-            // this doesn't seem to make any sense
-            assert!(
-                partition_predicate.field_restriction.is_none(),
-                "Column selection for table names not supported"
-            );
+    /// Returns the number of bytes of data in the WAL of this database,
+    /// which must all be read to restore it
+    pub fn wal_bytes(&self) -> u64 {
+        self.wal_bytes.load(Ordering::SeqCst)
+    }
 
-            // It might make sense to ask for all table names that
-            // have rows that pass a general purpose predicate. I am
-            // not sure if it is needed now, so panic
-            assert!(
-                partition_predicate.partition_exprs.is_empty(),
-                "General partition exprs on table name list are not supported"
-            );
+    /// Returns the number of distinct values of the tag of this
+    /// database with the most values, across all of its partitions
+    pub async fn max_tag_cardinality(&self) -> usize {
+        let partitions = self.partitions.read().await;
 
-            for (table_name_symbol, table) in &partition.tables {
-                if table.could_match_predicate(&partition_predicate)? {
-                    let table_name = partition.dictionary.lookup_id(*table_name_symbol).unwrap();
+        let mut tag_values: BTreeMap<(&str, &str), HashSet<&str>> = BTreeMap::new();
+        for partition in partitions.iter() {
+            let lookup = |id| {
+                partition
+                    .dictionary
+                    .lookup_id(id)
+                    .expect("ids of the partition are in its dictionary")
+            };
 
-                    if !table_names.contains(table_name) {
-                        table_names.insert(table_name.to_string());
-                    }
+            for table in partition.tables.values() {
+                let table_name = lookup(table.id);
+                for (tag_id, summary) in &table.tag_summaries {
+                    tag_values
+                        .entry((table_name, lookup(*tag_id)))
+                        .or_default()
+                        .extend(summary.value_times.keys().map(|id| lookup(*id)));
                 }
             }
         }
-        Ok(table_names.into())
-    }
-
-    // return all column names in this database, while applying optional predicates
-    async fn tag_column_names(&self, predicate: Predicate) -> Result<StringSetPlan, Self::Error> {
-        let has_exprs = predicate.has_exprs();
-        let mut filter = PartitionTableFilter::new(predicate);
 
-        if has_exprs {
-            let mut visitor = NamePredVisitor::new();
-            self.visit_tables(&mut filter, &mut visitor).await?;
-            Ok(visitor.plans.into())
-        } else {
-            let mut visitor = NameVisitor::new();
-            self.visit_tables(&mut filter, &mut visitor).await?;
-            Ok(visitor.column_names.into())
-        }
+        tag_values.values().map(HashSet::len).max().unwrap_or(0)
     }
 
-    /// return all field names in this database, while applying optional predicates
-    async fn field_columns(&self, predicate: Predicate) -> Result<FieldListPlan, Self::Error> {
-        let mut filter = PartitionTableFilter::new(predicate);
-        let mut visitor = TableFieldPredVisitor::new();
-        self.visit_tables(&mut filter, &mut visitor).await?;
-        Ok(visitor.into_fieldlist_plan())
+    /// Returns an error if the WAL of this database is being replayed,
+    /// since writes made while replaying would be ordered before the
+    /// writes still to be replayed
+    fn check_not_replaying(&self) -> Result<()> {
+        ensure!(
+            self.replay_progress().is_none(),
+            Replaying {
+                database: &self.name
+            }
+        );
+        Ok(())
+    }
+
+    /// Returns an error if writing `incoming` more bytes would take
+    /// this database over the `mutable_buffer_size` of its rules
+    async fn check_buffer_size(&self, incoming: usize) -> Result<()> {
+        let limit = match self.rules.read().await.mutable_buffer_size {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+
+        let size = (self.size().await + incoming) as u64;
+        ensure!(
+            size <= limit,
+            BufferSizeExceeded {
+                database: &self.name,
+                size,
+                limit,
+            }
+        );
+
+        Ok(())
+    }
+
+    async fn write_entries_to_partitions(&self, batch: &wb::WriteBufferBatch<'_>) -> Result<()> {
+        check_timestamps(&self.name, batch)?;
+
+        if let Some(entries) = batch.entries() {
+            // pre-aggregates are only set up when a table is created,
+            // so changing the rules only affects tables created later
+            let (pre_aggregated_tables, late_write_nanos) = {
+                let rules = self.rules.read().await;
+                let pre_aggregated_tables: BTreeSet<String> =
+                    rules.pre_aggregated_tables.iter().cloned().collect();
+                (
+                    pre_aggregated_tables,
+                    rules.late_write_nanos().filter(|&late| late > 0),
+                )
+            };
+
+            let mut partitions = self.partitions.write().await;
+            let now = Utc::now().timestamp_nanos();
+
+            for entry in entries {
+                let key = entry
+                    .partition_key()
+                    .expect("partition key should have been inserted");
+
+                let index = self.open_chunk(&mut partitions, key);
+
+                // rows further behind the newest rows of the open chunk
+                // than the late write window go to a late chunk for their
+                // window, so the open chunk keeps a narrow time range
+                let late = late_write_nanos.and_then(|late| {
+                    let (_, max) = partitions[index].time_range()?;
+                    Some((late, max.saturating_sub(late)))
+                });
+                let windows = match late {
+                    Some((late, cutoff)) => late_windows(&entry, late, cutoff),
+                    None => BTreeSet::new(),
+                };
+
+                let p = &mut partitions[index];
+                if p.pre_aggregated_tables != pre_aggregated_tables {
+                    p.pre_aggregated_tables = pre_aggregated_tables.clone();
+                }
+                match late {
+                    Some((_, cutoff)) if !windows.is_empty() => {
+                        p.write_entry_rows(&entry, |_, row| {
+                            partition::row_time(row).map_or(true, |time| time >= cutoff)
+                        })?;
+                    }
+                    _ => p.write_entry(&entry)?,
+                }
+                p.last_write_time = Some(now);
+
+                if let Some((late, cutoff)) = late {
+                    for start in windows {
+                        let window = (start, start.saturating_add(late));
+                        let index = self.late_chunk(&mut partitions, key, window);
+
+                        let p = &mut partitions[index];
+                        if p.pre_aggregated_tables != pre_aggregated_tables {
+                            p.pre_aggregated_tables = pre_aggregated_tables.clone();
+                        }
+                        p.write_entry_rows(&entry, |_, row| match partition::row_time(row) {
+                            Some(time) => time < cutoff && time >= window.0 && time < window.1,
+                            None => false,
+                        })?;
+                        p.last_write_time = Some(now);
+                    }
+                }
+            }
+
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the index in `partitions` of the open chunk of the
+    /// partition with key `key`, creating it if there is none
+    fn open_chunk(&self, partitions: &mut Vec<Partition>, key: &str) -> usize {
+        match partitions.iter().position(|p| p.should_write(key)) {
+            Some(index) => index,
+            None => {
+                let mut p = Partition::new(key);
+                p.id = self.next_chunk_id(partitions, key);
+                partitions.push(p);
+                partitions.len() - 1
+            }
+        }
+    }
+
+    /// Returns the index in `partitions` of the late chunk of the
+    /// partition with key `key` for the time window `window`, reopening
+    /// it if it has been closed, or creating it if there is none
+    fn late_chunk(&self, partitions: &mut Vec<Partition>, key: &str, window: (i64, i64)) -> usize {
+        let existing = partitions
+            .iter()
+            .position(|p| p.key == key && p.late_window == Some(window) && !p.persisted);
+        match existing {
+            Some(index) => {
+                let p = &mut partitions[index];
+                if !p.is_open {
+                    info!(
+                        "{} database reopening late chunk {} of partition {} for writes to [{}, {})",
+                        &self.name, p.id, key, window.0, window.1
+                    );
+                    p.is_open = true;
+                }
+                index
+            }
+            None => {
+                let mut p = Partition::new(key);
+                p.id = self.next_chunk_id(partitions, key);
+                p.late_window = Some(window);
+                partitions.push(p);
+                partitions.len() - 1
+            }
+        }
+    }
+}
+
+/// Returns the start of the late write window of each row of `entry`
+/// older than `cutoff`, for windows `late` nanoseconds long
+fn late_windows(entry: &wb::WriteBufferEntry<'_>, late: i64, cutoff: i64) -> BTreeSet<i64> {
+    let mut windows = BTreeSet::new();
+    if let Some(batches) = entry.table_batches() {
+        for batch in batches {
+            if let Some(rows) = batch.rows() {
+                for row in rows {
+                    if let Some(time) = partition::row_time(&row) {
+                        if time < cutoff {
+                            windows.insert(time - time.rem_euclid(late));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    windows
+}
+
+#[async_trait]
+impl Database for Db {
+    type Error = Error;
+
+    // TODO: writes lines creates a column named "time" for the timestmap data. If
+    //       we keep this we need to validate that no tag or field has the same name.
+    async fn write_lines(&self, lines: &[ParsedLine<'_>]) -> Result<(), Self::Error> {
+        self.check_not_replaying()?;
+        let data = {
+            let rules = self.rules.read().await;
+            let default_time = Utc::now();
+            let lines = rules
+                .receive_time
+                .apply(lines, default_time.timestamp_nanos());
+            let lines = bound_timestamps(
+                &self.name,
+                &lines,
+                &rules.timestamp_bounds,
+                default_time.timestamp_nanos(),
+            )?;
+            check_non_finite_floats(&self.name, &lines, rules.non_finite_floats)?;
+            split_lines_into_write_entry_partitions(
+                |line| partition_key(line, &rules, &default_time),
+                &lines,
+            )
+        };
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+
+        self.check_buffer_size(data.len()).await?;
+        self.write_entries_to_partitions(&batch).await?;
+
+        if let Some(wal) = &self.wal_details {
+            let bytes = data.len() as u64;
+            let sequence = wal.write_and_sync(data).await.context(WritingWal {
+                database: &self.name,
+            })?;
+            self.next_wal_sequence
+                .fetch_max(sequence + 1, Ordering::SeqCst);
+            self.wal_bytes.fetch_add(bytes, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    async fn store_replicated_write(&self, write: &ReplicatedWrite) -> Result<(), Self::Error> {
+        self.check_not_replaying()?;
+        self.check_buffer_size(write.data.len()).await?;
+
+        let fb = write.to_fb();
+        let payload = fb.payload().context(MissingPayload {
+            writer: fb.writer(),
+        })?;
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(payload);
+        self.write_entries_to_partitions(&batch).await?;
+
+        if let Some(wal) = &self.wal_details {
+            // The WAL stores the batch itself, as for local writes, so
+            // it can be restored in the same way
+            //
+            // TODO(paul): refactor this so we're not cloning. Although replicated writes shouldn't
+            //  be using a WAL and how the WAL is used at all is likely to have a larger refactor soon.
+            let sequence = wal
+                .write_and_sync(payload.to_vec())
+                .await
+                .context(WritingWal {
+                    database: &self.name,
+                })?;
+            self.next_wal_sequence
+                .fetch_max(sequence + 1, Ordering::SeqCst);
+            self.wal_bytes
+                .fetch_add(payload.len() as u64, Ordering::SeqCst);
+        }
+
+        Ok(())
+    }
+
+    async fn table_names(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<StringSetPlan>, Self::Error> {
+        // TODO: Cache this information to avoid creating this each time
+        let partitions = self
+            .query_chunks(|chunk| chunk_could_match(&predicate, chunk))
+            .await?;
+
+        let mut table_names: BTreeSet<String> = BTreeSet::new();
+        for partition in partitions.iter() {
+            let partition_predicate = partition.compile_predicate(&predicate)?;
+            // this doesn't seem to make any sense
+            assert!(
+                partition_predicate.field_restriction.is_none(),
+                "Column selection for table names not supported"
+            );
+
+            // It might make sense to ask for all table names that
+            // have rows that pass a general purpose predicate. I am
+            // not sure if it is needed now, so panic
+            assert!(
+                partition_predicate.partition_exprs.is_empty(),
+                "General partition exprs on table name list are not supported"
+            );
+
+            for (table_name_symbol, table) in &partition.tables {
+                if table.could_match_predicate(&partition_predicate)?
+                    && table.has_rows_in_range(&partition_predicate)?
+                {
+                    let table_name = partition.dictionary.lookup_id(*table_name_symbol).unwrap();
+
+                    if !table_names.contains(table_name) {
+                        table_names.insert(table_name.to_string());
+                    }
+                }
+            }
+        }
+        Ok(Planned::new(table_names.into(), partitions.generation))
+    }
+
+    // return all column names in this database, while applying optional predicates
+    async fn tag_column_names(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<StringSetPlan>, Self::Error> {
+        let has_exprs = predicate.has_exprs();
+        let mut filter = PartitionTableFilter::new(predicate);
+
+        if has_exprs {
+            let mut visitor = NamePredVisitor::new();
+            let walk = self.visit_tables(&mut filter, &mut visitor).await?;
+            Ok(Planned::new(visitor.plans.into(), walk.generation))
+        } else {
+            let mut visitor = NameVisitor::new();
+            let walk = self.visit_tables(&mut filter, &mut visitor).await?;
+            Ok(Planned::new(visitor.column_names.into(), walk.generation))
+        }
+    }
+
+    /// return all field names in this database, while applying optional predicates
+    async fn field_columns(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<FieldListPlan>, Self::Error> {
+        let mut filter = PartitionTableFilter::new(predicate);
+        let mut visitor = TableFieldPredVisitor::new();
+        let walk = self.visit_tables(&mut filter, &mut visitor).await?;
+        Ok(Planned::new(visitor.into_fieldlist_plan(), walk.generation))
     }
 
     /// return all column values in this database, while applying optional predicates
@@ -449,40 +1265,87 @@ impl Database for Db {
         &self,
         column_name: &str,
         predicate: Predicate,
-    ) -> Result<StringSetPlan, Self::Error> {
+    ) -> Result<Planned<StringSetPlan>, Self::Error> {
         let has_exprs = predicate.has_exprs();
         let mut filter = PartitionTableFilter::new(predicate);
 
         if has_exprs {
             let mut visitor = ValuePredVisitor::new(column_name);
-            self.visit_tables(&mut filter, &mut visitor).await?;
-            Ok(visitor.plans.into())
+            let walk = self.visit_tables(&mut filter, &mut visitor).await?;
+            Ok(Planned::new(visitor.plans.into(), walk.generation))
         } else {
             let mut visitor = ValueVisitor::new(column_name);
-            self.visit_tables(&mut filter, &mut visitor).await?;
-            Ok(visitor.column_values.into())
+            let walk = self.visit_tables(&mut filter, &mut visitor).await?;
+            Ok(Planned::new(visitor.column_values.into(), walk.generation))
         }
     }
 
-    async fn query_series(&self, predicate: Predicate) -> Result<SeriesSetPlans, Self::Error> {
+    async fn query_series(
+        &self,
+        predicate: Predicate,
+    ) -> Result<Planned<SeriesSetPlans>, Self::Error> {
         let mut filter = PartitionTableFilter::new(predicate);
         let mut visitor = SeriesVisitor::new();
-        self.visit_tables(&mut filter, &mut visitor).await?;
-        Ok(visitor.plans.into())
+        let walk = self.visit_tables(&mut filter, &mut visitor).await?;
+        let plans = SeriesSetPlans {
+            plans: visitor.plans(&filter, None)?,
+            chunks_scanned: walk.chunks_scanned,
+            chunks_pruned: walk.chunks_pruned,
+        };
+        Ok(Planned::new(plans, walk.generation))
+    }
+
+    async fn query_pre_aggregated_series(
+        &self,
+        predicate: Predicate,
+        window_aggregate: &WindowAggregate,
+    ) -> Result<Option<Planned<PreAggregatedSeries>>, Self::Error> {
+        if !window_aggregate.can_apply_to_buckets(BUCKET_NANOS) {
+            return Ok(None);
+        }
+
+        let mut filter = PartitionTableFilter::new(predicate);
+        let mut visitor = PreAggregateVisitor::new();
+        let walk = self.walk_tables(&mut filter, &mut visitor).await?;
+
+        // only record the pruning if the query is answered here, as
+        // otherwise it is planned again over the rows
+        Ok(visitor.into_series().map(|series| {
+            self.record_chunk_pruning(walk);
+            let series = PreAggregatedSeries {
+                series,
+                chunks_scanned: walk.chunks_scanned,
+                chunks_pruned: walk.chunks_pruned,
+            };
+            Planned::new(series, walk.generation)
+        }))
     }
 
     async fn query_groups(
         &self,
         predicate: Predicate,
         group_columns: Vec<String>,
-    ) -> Result<GroupedSeriesSetPlans, Self::Error> {
+    ) -> Result<Planned<GroupedSeriesSetPlans>, Self::Error> {
         let mut filter = PartitionTableFilter::new(predicate)
             // Add any specified groups as predicate columns (so we can skip tables without those tags)
             .add_required_columns(&group_columns);
 
-        let mut visitor = GroupsVisitor::new(group_columns);
-        self.visit_tables(&mut filter, &mut visitor).await?;
-        Ok(visitor.plans.into())
+        let mut visitor = SeriesVisitor::new();
+        let walk = self.visit_tables(&mut filter, &mut visitor).await?;
+        let grouped_plans = visitor
+            .plans(&filter, Some(&group_columns))?
+            .into_iter()
+            .map(|series_set_plan| GroupedSeriesSetPlan {
+                series_set_plan,
+                num_prefix_tag_group_columns: group_columns.len(),
+            })
+            .collect();
+        let plans = GroupedSeriesSetPlans {
+            grouped_plans,
+            chunks_scanned: walk.chunks_scanned,
+            chunks_pruned: walk.chunks_pruned,
+        };
+        Ok(Planned::new(plans, walk.generation))
     }
 
     async fn table_to_arrow(
@@ -490,1710 +1353,5290 @@ impl Database for Db {
         table_name: &str,
         columns: &[&str],
     ) -> Result<Vec<RecordBatch>, Self::Error> {
-        let partitions = self.partitions.read().await;
+        let partitions = self
+            .query_chunks(|chunk| chunk.table_names.iter().any(|t| t == table_name))
+            .await?;
 
         let batches = partitions
             .iter()
-            .map(|p| p.table_to_arrow(table_name, columns))
+            .map(|p| {
+                let span =
+                    debug_span!("chunk_scan", table = table_name, partition = %p.key, chunk = p.id);
+                let _enter = span.enter();
+                p.table_to_arrow(table_name, columns)
+            })
             .collect::<Result<Vec<_>, crate::partition::Error>>()?;
 
         Ok(batches)
     }
 
-    async fn query(&self, query: &str) -> Result<Vec<RecordBatch>, Self::Error> {
-        let mut tables = vec![];
+    async fn query_table(
+        &self,
+        table_name: &str,
+        range: Option<TimestampRange>,
+    ) -> Result<(Arc<ArrowSchema>, Vec<RecordBatch>), Self::Error> {
+        let (schema, data, _scan) = self.scan_table(table_name, range).await?;
+        Ok((schema, data))
+    }
 
-        let dialect = GenericDialect {};
-        let ast = Parser::parse_sql(&dialect, query).context(InvalidSqlQuery { query })?;
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
 
-        for statement in ast {
-            match statement {
-                Statement::Query(q) => {
-                    if let SetExpr::Select(q) = q.body {
-                        for item in q.from {
-                            if let TableFactor::Table { name, .. } = item.relation {
-                                let name = name.to_string();
-                                let data = self.table_to_arrow(&name, &[]).await?;
-                                tables.push(ArrowTable {
-                                    name,
-                                    schema: data[0].schema().clone(),
-                                    data,
-                                });
-                            }
-                        }
-                    }
-                }
-                _ => {
-                    return UnsupportedStatement {
-                        query: query.to_string(),
-                        statement,
-                    }
-                    .fail()
-                }
-            }
-        }
+    async fn rules(&self) -> DatabaseRules {
+        self.rules.read().await.clone()
+    }
 
-        let config = ExecutionConfig::new().with_batch_size(1024 * 1024);
-        let mut ctx = ExecutionContext::with_config(config);
+    /// Replaces the rules of this database, persisting them alongside
+    /// the WAL (if there is one) so they are restored with it
+    async fn set_rules(&self, rules: DatabaseRules) -> Result<(), Self::Error> {
+        rules.validate().context(UnsupportedRules {
+            database: &self.name,
+        })?;
 
-        for table in tables {
-            let provider =
-                MemTable::new(table.schema, vec![table.data]).context(QueryError { query })?;
-            ctx.register_table(&table.name, Box::new(provider));
-        }
+        let mut current = self.rules.write().await;
 
-        let plan = ctx
-            .create_logical_plan(&query)
-            .context(QueryError { query })?;
-        let plan = ctx.optimize(&plan).context(QueryError { query })?;
-        let plan = ctx
-            .create_physical_plan(&plan)
-            .context(QueryError { query })?;
+        if let Some(dir) = &self.dir {
+            write_json_file(&self.name, &dir.join(RULES_FILE_NAME), &rules)?;
+        }
 
-        ctx.collect(plan).await.context(QueryError { query })
-    }
-}
+        *current = rules;
+        {
+            // the generation only changes while the partitions are
+            // locked, so plans are made from the data of one generation
+            let _partitions = self.partitions.write().await;
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
+        self.rules_changed.notify();
 
-/// This trait is used to implement a "Visitor" pattern for Database
-/// which can be used to define logic that shares a common Depth First
-/// Search (DFS) traversal of the Database --> Partition --> Table -->
-/// Column datastructure heirarchy.
-///
-/// Specifically, if we had a database like the following:
-///
-/// YesterdayPartition
-///   CPU Table1
-///    Col1
-/// TodayPartition
-///   CPU Table2
-///    Col2
-///
-/// Then the methods would be invoked in the following order
-///
-///  visitor.pre_visit_partition(YesterdayPartition)
-///  visitor.pre_visit_table(CPU Table1)
-///  visitor.visit_column(Col1)
-///  visitor.post_visit_table(CPU Table1)
-///  visitor.post_visit_partition(YesterdayPartition)
-///  visitor.pre_visit_partition(TodayPartition)
-///  visitor.pre_visit_table(CPU Table2)
-///  visitor.visit_column(Col2)
-///  visitor.post_visit_table(CPU Table2)
-///  visitor.post_visit_partition(TodayPartition)
-trait Visitor {
-    // called once before any column in a partition is visisted
-    fn pre_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
         Ok(())
     }
 
-    // called once before any column in a Table is visited
-    fn pre_visit_table(
-        &mut self,
-        _table: &Table,
-        _partition: &Partition,
-        _filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        Ok(())
+    /// returns the keys of the partitions in this database, in the
+    /// order the partitions were created, followed by those of
+    /// partitions whose chunks have all been evicted
+    async fn partition_keys(&self) -> Vec<String> {
+        let partitions = self.partitions.read().await;
+        let evicted = self.evicted_chunks();
+        let mut seen = HashSet::new();
+        partitions
+            .iter()
+            .map(|p| &p.key)
+            .chain(evicted.iter().map(|c| &c.partition_key))
+            .filter(|key| seen.insert(key.as_str()))
+            .cloned()
+            .collect()
     }
 
-    // called every time a column is visited
-    fn visit_column(
-        &mut self,
-        _table: &Table,
-        _column_id: u32,
-        _column: &Column,
-        _filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        Ok(())
+    async fn chunks(&self) -> Vec<ChunkSummary> {
+        let mut chunks: Vec<_> = self
+            .partitions
+            .read()
+            .await
+            .iter()
+            .map(|p| p.chunk_summary())
+            .chain(self.evicted_chunks())
+            .collect();
+        chunks.sort_by(|a, b| (&a.partition_key, a.id).cmp(&(&b.partition_key, b.id)));
+        chunks
     }
 
-    // called once after all columns in a Table are visited
-    fn post_visit_table(&mut self, _table: &Table, _partition: &Partition) -> Result<()> {
-        Ok(())
+    /// Drops the partition and all of its data. Note that data
+    /// written to the partition key after it is dropped creates a new
+    /// partition with that key.
+    async fn drop_partition(
+        &self,
+        partition_key: &str,
+        dry_run: bool,
+    ) -> Result<PartitionSummary, Self::Error> {
+        self.remove_partition_data(partition_key, dry_run, false)
+            .await
     }
 
-    // called once after all columns in a partition is visited
-    fn post_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
-        Ok(())
+    /// Removes all data from the partition. Note a truncated
+    /// partition is empty until written to again, so it is not
+    /// restored from the WAL if the database restarts before then.
+    async fn truncate_partition(
+        &self,
+        partition_key: &str,
+        dry_run: bool,
+    ) -> Result<PartitionSummary, Self::Error> {
+        self.remove_partition_data(partition_key, dry_run, true)
+            .await
     }
-}
 
-impl Db {
-    /// returns the number of partitions in this database
-    pub async fn len(&self) -> usize {
-        self.partitions.read().await.len()
-    }
+    /// Closes the chunk. Note chunks are not closed durably: a
+    /// database restored from its WAL has a single open chunk for each
+    /// partition.
+    async fn close_chunk(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+    ) -> Result<ChunkSummary, Self::Error> {
+        let mut partitions = self.partitions.write().await;
+        let partition = self.find_chunk(&mut partitions, partition_key, chunk_id)?;
+        ensure!(
+            partition.is_open,
+            ChunkAlreadyClosed {
+                database: &self.name,
+                partition: partition_key,
+                chunk: chunk_id,
+            }
+        );
 
-    /// returns true if the database has no partititons
-    pub async fn is_empty(&self) -> bool {
-        self.partitions.read().await.is_empty()
+        partition.is_open = false;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        info!(
+            "{} database closed chunk {} of partition {}",
+            &self.name, chunk_id, partition_key
+        );
+
+        Ok(partition.chunk_summary())
     }
 
-    /// Traverse this database's tables, calling the relevant
-    /// functions, in order, of `visitor`, as described on the Visitor
-    /// trait.
+    /// Compacts the chunks of the partition. As with downsampling, the
+    /// compacted data is not written to the WAL. The encodings of the
+    /// columns of the compacted chunk, when it is persisted, are chosen
+    /// from their data and how the columns of the chunks were read and
+    /// persisted.
     ///
-    /// Skips visiting any table or columns of `filter.should_visit_table` returns false
-    async fn visit_tables<V: Visitor>(
+    /// The chunks are copied under a read lock and rewritten on a
+    /// blocking thread, paced by `pace`, so writes and queries are not
+    /// held up while they are. The compacted chunk then replaces them
+    /// under a short write lock; chunks added to the partition in the
+    /// meantime are kept. If any of the compacted chunks was changed or
+    /// removed in the meantime, nothing is replaced and an error is
+    /// returned.
+    async fn compact_partition(
         &self,
-        filter: &mut PartitionTableFilter,
-        visitor: &mut V,
-    ) -> Result<()> {
-        let partitions = self.partitions.read().await;
-
-        for partition in partitions.iter() {
-            visitor.pre_visit_partition(partition)?;
-            filter.pre_visit_partition(partition)?;
+        partition_key: &str,
+        pace: Option<Pacer>,
+    ) -> Result<ChunkSummary, Self::Error> {
+        let (snapshot, compacted_chunks) = {
+            let partitions = self.partitions.read().await;
+
+            let chunks: Vec<_> = partitions
+                .iter()
+                .filter(|p| p.key == partition_key)
+                .collect();
+            ensure!(
+                !chunks.is_empty(),
+                PartitionNotFound {
+                    database: &self.name,
+                    partition: partition_key,
+                }
+            );
 
-            for table in partition.tables.values() {
-                if filter.should_visit_table(table)? {
-                    visitor.pre_visit_table(table, partition, filter)?;
+            // chunks whose files can't be read are compacted just the
+            // same, without what their files tell about their columns
+            let persisted: Vec<_> = match &self.dir {
+                Some(dir) => chunks
+                    .iter()
+                    .filter(|p| p.persisted)
+                    .filter_map(|p| {
+                        let chunk_dir = persisted_chunk_dir(dir, partition_key, p.id);
+                        PersistedChunk::open(&chunk_dir, OpenMode::Mmap)
+                            .map_err(|e| {
+                                debug!(
+                                    "{} database could not read persisted chunk {} of partition {}: {}",
+                                    &self.name, p.id, partition_key, e
+                                )
+                            })
+                            .ok()
+                    })
+                    .collect(),
+                None => vec![],
+            };
+            let usage = encoding::column_usage(&chunks, &persisted)?;
 
-                    for (column_id, column_index) in &table.column_id_to_index {
-                        visitor.visit_column(
-                            table,
-                            *column_id,
-                            &table.columns[*column_index],
-                            filter,
-                        )?
-                    }
+            let snapshot = rewrite::snapshot_for_compaction(partition_key, &chunks, usage)?;
+            let compacted_chunks: Vec<_> = chunks.iter().map(|p| p.chunk_summary()).collect();
+            (snapshot, compacted_chunks)
+        };
 
-                    visitor.post_visit_table(table, partition)?;
+        let mut compacted = tokio::task::spawn_blocking(move || {
+            snapshot.compact(0, |bytes| {
+                if let Some(pace) = &pace {
+                    pace(bytes)
                 }
+            })
+        })
+        .await
+        .context(BlockingTask {
+            database: &self.name,
+        })??;
+
+        let mut partitions = self.partitions.write().await;
+        let unchanged = compacted_chunks.iter().all(|chunk| {
+            partitions
+                .iter()
+                .find(|p| p.key == partition_key && p.id == chunk.id)
+                .map(|p| p.chunk_summary() == *chunk)
+                .unwrap_or(false)
+        });
+        ensure!(
+            unchanged,
+            CompactedChunksChanged {
+                database: &self.name,
+                partition: partition_key,
             }
-            visitor.post_visit_partition(partition)?;
-        } // next partition
+        );
 
-        Ok(())
+        compacted.id = self.next_chunk_id(&partitions, partition_key);
+        let summary = compacted.chunk_summary();
+
+        info!(
+            "{} database compacted {} chunks of partition {} into chunk {}",
+            &self.name,
+            compacted_chunks.len(),
+            partition_key,
+            compacted.id
+        );
+
+        partitions.retain(|p| {
+            p.key != partition_key || !compacted_chunks.iter().any(|chunk| chunk.id == p.id)
+        });
+        partitions.push(compacted);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        Ok(summary)
     }
-}
 
-/// Common logic for processing and filtering tables in the write buffer
-///
-/// Note that since each partition has its own dictionary, mappings
-/// between Strings --> we cache the String->id mappings per partition
-///
-/// b) the table doesn't have a column range that overlaps the
-/// predicate values, e.g., if you have env = "us-west" and a
-/// table's env column has the range ["eu-south", "us-north"].
-#[derive(Debug)]
-struct PartitionTableFilter {
-    predicate: Predicate,
+    /// Writes the chunk to parquet files in the
+    /// `PERSISTED_CHUNKS_DIR_NAME` directory of the database. The chunk
+    /// remains in memory, to be queried as before.
+    async fn persist_chunk(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+    ) -> Result<ChunkSummary, Self::Error> {
+        let dir = self.dir.as_ref().context(NoPersistenceDir {
+            database: &self.name,
+        })?;
+
+        let mut partitions = self.partitions.write().await;
+        let partition = self.find_chunk(&mut partitions, partition_key, chunk_id)?;
+        ensure!(
+            !partition.is_open,
+            ChunkNotClosed {
+                database: &self.name,
+                partition: partition_key,
+                chunk: chunk_id,
+            }
+        );
 
-    /// If specififed, only tables with all specified columns will be
-    /// visited. Note that just becuase a table has all these columns,
-    /// it might not be visited for other reasons (e.g. it is filted
-    /// out by a table_name predicate)
-    additional_required_columns: Option<HashSet<String>>,
+        let chunk_dir = persisted_chunk_dir(dir, partition_key, chunk_id);
+        rewrite::persist_partition(partition, &chunk_dir)?;
+        partition.persisted = true;
+        let summary = partition.chunk_summary();
+        drop(partitions);
 
-    /// A 'compiled' version of the predicate to evaluate on tables /
-    /// columns in a particular partition during the walk
-    partition_predicate: Option<PartitionPredicate>,
-}
+        info!(
+            "{} database persisted chunk {} of partition {} to {:?}",
+            &self.name, chunk_id, partition_key, chunk_dir
+        );
 
-impl PartitionTableFilter {
-    fn new(predicate: Predicate) -> Self {
-        Self {
-            predicate,
-            additional_required_columns: None,
-            partition_predicate: None,
-        }
+        // persisting the chunk again copies its files again, if they
+        // could not be copied
+        self.copy_persisted_chunks(vec![(partition_key, chunk_id)])
+            .await?;
+
+        Ok(summary)
     }
 
-    /// adds the specified columns to a list of columns that must be
-    /// present in a table.
-    fn add_required_columns(mut self, column_names: &[String]) -> Self {
-        let mut required_columns = self
-            .additional_required_columns
-            .take()
-            .unwrap_or_else(HashSet::new);
+    async fn partition_line_protocol(&self, partition_key: &str) -> Result<String, Self::Error> {
+        let partitions = self
+            .query_chunks(|chunk| chunk.partition_key == partition_key)
+            .await?;
+        let mut lp = String::new();
+        for partition in partitions.iter().filter(|p| p.key == partition_key) {
+            rewrite::write_partition_lines(&mut lp, partition)?;
+        }
+        Ok(lp)
+    }
 
-        for c in column_names {
-            if !required_columns.contains(c) {
-                required_columns.insert(c.clone());
+    /// Deletes the rows of the series by rewriting the chunks of the
+    /// partition which have any. Chunks in memory are replaced by their
+    /// rewritten data, which is persisted again if they were persisted.
+    /// Evicted chunks are persisted again as new chunks, which replace
+    /// them once their files are written, so their rows are never lost.
+    async fn delete_series(
+        &self,
+        partition_key: &str,
+        series_keys: &BTreeSet<String>,
+    ) -> Result<u64, Self::Error> {
+        let mut partitions = self.partitions.write().await;
+
+        // the deletion is recorded before the rows are removed from
+        // memory, so that they are never restored from the WAL
+        self.record_deleted_series(partition_key, series_keys)
+            .await?;
+
+        let mut deleted = 0;
+        for partition in partitions.iter_mut().filter(|p| p.key == partition_key) {
+            if let Some((rewritten, rows)) = rewrite::delete_series(partition, series_keys)? {
+                *partition = rewritten;
+                deleted += rows;
             }
         }
 
-        self.additional_required_columns = Some(required_columns);
-        self
-    }
+        let evicted: Vec<_> = self
+            .evicted_chunks()
+            .into_iter()
+            .filter(|c| c.partition_key == partition_key)
+            .collect();
+        if let (Some(dir), false) = (&self.dir, evicted.is_empty()) {
+            let read_dir = dir.clone();
+            let mode = self.persisted_open_mode;
+            let evicted =
+                tokio::task::spawn_blocking(move || read_evicted_chunks(&read_dir, &evicted, mode))
+                    .await
+                    .context(BlockingTask {
+                        database: &self.name,
+                    })??;
+
+            let mut next_id = self.next_chunk_id(&partitions, partition_key);
+            let mut replaced = BTreeMap::new();
+            let mut snapshots = vec![];
+            for chunk in &evicted {
+                if let Some((mut rewritten, rows)) = rewrite::delete_series(chunk, series_keys)? {
+                    rewritten.id = next_id;
+                    rewritten.is_open = false;
+                    rewritten.persisted = true;
+                    next_id += 1;
+
+                    let chunk_dir = persisted_chunk_dir(dir, partition_key, rewritten.id);
+                    snapshots.push((chunk_dir, rewrite::snapshot_partition(&rewritten)?));
+                    replaced.insert(
+                        chunk.id,
+                        ChunkSummary {
+                            storage: ChunkStorage::ObjectStore,
+                            ..rewritten.chunk_summary()
+                        },
+                    );
+                    deleted += rows;
+                }
+            }
 
-    /// Called when each partition gets visited. Since ids are
-    /// specific to each partitition, the predicates much get
-    /// translated each time.
-    fn pre_visit_partition(&mut self, partition: &Partition) -> Result<()> {
-        let mut partition_predicate = partition.compile_predicate(&self.predicate)?;
+            if !replaced.is_empty() {
+                tokio::task::spawn_blocking(move || {
+                    snapshots
+                        .iter()
+                        .try_for_each(|(chunk_dir, snapshot)| snapshot.persist(chunk_dir))
+                })
+                .await
+                .context(BlockingTask {
+                    database: &self.name,
+                })??;
+                self.copy_persisted_chunks(
+                    replaced
+                        .values()
+                        .map(|rewritten| (partition_key, rewritten.id)),
+                )
+                .await?;
+
+                let replaced_ids: Vec<_> = replaced.keys().copied().collect();
+                let replaced_dirs: Vec<_> = replaced_ids
+                    .iter()
+                    .map(|&id| persisted_chunk_dir(dir, partition_key, id))
+                    .collect();
+                {
+                    let mut evicted_chunks = self.evicted_chunks.write().expect("lock poisoned");
+                    for chunk in evicted_chunks.iter_mut() {
+                        if chunk.partition_key == partition_key {
+                            if let Some(rewritten) = replaced.remove(&chunk.id) {
+                                *chunk = rewritten;
+                            }
+                        }
+                    }
+                    write_json_file(
+                        &self.name,
+                        &dir.join(EVICTED_CHUNKS_FILE_NAME),
+                        &*evicted_chunks,
+                    )?;
+                }
 
-        // add any additional column needs
-        if let Some(additional_required_columns) = &self.additional_required_columns {
-            partition.add_required_columns_to_predicate(
-                additional_required_columns,
-                &mut partition_predicate,
-            );
+                for chunk_dir in replaced_dirs {
+                    if let Err(e) = std::fs::remove_dir_all(&chunk_dir) {
+                        warn!(
+                            "{} database could not remove replaced chunk {:?}: {}",
+                            &self.name, chunk_dir, e
+                        );
+                    }
+                }
+                self.remove_copied_chunks(replaced_ids.iter().map(|&id| (partition_key, id)));
+            }
         }
 
-        self.partition_predicate = Some(partition_predicate);
+        if deleted > 0 {
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
 
-        Ok(())
+        info!(
+            "{} database deleted {} rows of {} series from partition {}",
+            &self.name,
+            deleted,
+            series_keys.len(),
+            partition_key
+        );
+
+        Ok(deleted)
     }
 
-    /// If returns false, skips visiting _table and all its columns
-    fn should_visit_table(&mut self, table: &Table) -> Result<bool> {
-        Ok(table.could_match_predicate(self.partition_predicate())?)
+    async fn query(&self, query: &str) -> Result<Vec<RecordBatch>, Self::Error> {
+        if let Some(explain) = Explain::parse(query) {
+            return self.explain(explain).await;
+        }
+
+        let planned = self.plan_query(query).await?;
+
+        planned
+            .ctx
+            .collect(planned.physical_plan)
+            .await
+            .context(QueryError { query })
     }
 
-    pub fn partition_predicate(&self) -> &PartitionPredicate {
-        self.partition_predicate
-            .as_ref()
-            .expect("Visited partition to compile predicate")
+    async fn query_stream(&self, query: &str) -> Result<SendableRecordBatchStream, Self::Error> {
+        if let Some(explain) = Explain::parse(query) {
+            let batches = self.explain(explain).await?;
+            let schema = batches[0].schema();
+            return Ok(batches_stream(schema, batches));
+        }
+
+        // the tables the query reads are loaded as it is planned, and
+        // its results are produced as the stream is read
+        let planned = self.plan_query(query).await?;
+        execute_stream(planned.physical_plan)
+            .await
+            .context(QueryError { query })
     }
 }
 
-/// return all column names in this database, while applying only the
-/// timestamp range (has no general purpose predicates)
-struct NameVisitor {
-    column_names: StringSet,
-    partition_column_ids: BTreeSet<u32>,
+/// Returns the directory chunk `chunk_id` of partition `partition_key`
+/// is persisted to, within the directory `dir` of a database
+fn persisted_chunk_dir(dir: &Path, partition_key: &str, chunk_id: u32) -> PathBuf {
+    dir.join(PERSISTED_CHUNKS_DIR_NAME)
+        .join(path_safe(partition_key))
+        .join(chunk_id.to_string())
 }
 
-impl NameVisitor {
-    fn new() -> Self {
-        Self {
-            column_names: StringSet::new(),
-            partition_column_ids: BTreeSet::new(),
-        }
-    }
+/// The chunks read by a query, as returned by `Db::query_chunks`
+struct QueryChunks<'a> {
+    in_memory: tokio::sync::RwLockReadGuard<'a, Vec<Partition>>,
+    evicted: Vec<Partition>,
+    /// The generation of the database, which can't change while the
+    /// chunks in memory are locked
+    generation: u64,
 }
 
-impl Visitor for NameVisitor {
-    fn visit_column(
-        &mut self,
-        table: &Table,
-        column_id: u32,
-        column: &Column,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        if let Column::Tag(column, _) = column {
-            if table.column_matches_predicate(column, filter.partition_predicate())? {
-                self.partition_column_ids.insert(column_id);
-            }
-        }
-        Ok(())
+impl QueryChunks<'_> {
+    /// The chunks of each partition, in the order they were written
+    fn iter(&self) -> impl Iterator<Item = &Partition> {
+        let mut chunks: Vec<_> = self.in_memory.iter().chain(&self.evicted).collect();
+        chunks.sort_by(|a, b| (&a.key, a.id).cmp(&(&b.key, b.id)));
+        chunks.into_iter()
     }
 
-    fn pre_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
-        self.partition_column_ids.clear();
-        Ok(())
+    fn len(&self) -> usize {
+        self.in_memory.len() + self.evicted.len()
     }
+}
 
-    fn post_visit_partition(&mut self, partition: &Partition) -> Result<()> {
-        // convert all the partition's column_ids to Strings
-        for &column_id in &self.partition_column_ids {
-            let column_name = partition.dictionary.lookup_id(column_id).context(
-                ColumnIdNotFoundInDictionary {
-                    column_id,
-                    partition: &partition.key,
-                },
-            )?;
+/// Reads each of the evicted `chunks` persisted in `dir` back into a
+/// partition, opening their files as `mode`
+fn read_evicted_chunks(
+    dir: &Path,
+    chunks: &[ChunkSummary],
+    mode: OpenMode,
+) -> Result<Vec<Partition>> {
+    chunks
+        .iter()
+        .map(|chunk| {
+            let chunk_dir = persisted_chunk_dir(dir, &chunk.partition_key, chunk.id);
+            let persisted = PersistedChunk::open(&chunk_dir, mode)?;
+            Ok(persisted.read_partition(&chunk.partition_key, chunk.id)?)
+        })
+        .collect()
+}
 
-            if !self.column_names.contains(column_name) {
-                self.column_names.insert(column_name.to_string());
-            }
+/// Returns false if no rows of the chunk `chunk` can match
+/// `predicate`, judging by the names of its tables and its time range
+fn chunk_could_match(predicate: &Predicate, chunk: &ChunkSummary) -> bool {
+    let has_table = match &predicate.table_names {
+        Some(table_names) => chunk.table_names.iter().any(|t| table_names.contains(t)),
+        None => true,
+    };
+    let in_range = match (&predicate.range, chunk.min_time, chunk.max_time) {
+        (Some(range), Some(min_time), Some(max_time)) => {
+            min_time < range.end && range.start <= max_time
         }
-        Ok(())
-    }
+        _ => true,
+    };
+    has_table && in_range
 }
 
-/// Return all column names in this database, while applying a
-/// general purpose predicates
-struct NamePredVisitor {
-    plans: Vec<LogicalPlan>,
+/// What a database's WAL holds, found by scanning it before it is
+/// replayed
+#[derive(Debug, Default)]
+struct WalScan {
+    entries: u64,
+    bytes: u64,
+    /// The sequence number that follows the last WAL entry
+    next_sequence_number: u64,
+    /// The index of the last WAL entry with data for each partition
+    last_entries: BTreeMap<String, usize>,
 }
 
-impl NamePredVisitor {
-    fn new() -> Self {
-        Self { plans: Vec::new() }
+fn scan_wal(database: &str, dir: &Path) -> Result<WalScan> {
+    let mut scan = WalScan::default();
+    let entries = WalBuilder::new(dir)
+        .entries()
+        .context(LoadingWal { database })?;
+
+    for (index, wal_entry) in entries.enumerate() {
+        let wal_entry = wal_entry.context(LoadingWal { database })?;
+        scan.entries += 1;
+        scan.bytes += wal_entry.as_data().len() as u64;
+        scan.next_sequence_number = scan
+            .next_sequence_number
+            .max(wal_entry.sequence_number() + 1);
+
+        let bytes = wal_entry.as_data();
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&bytes);
+        if let Some(entries) = batch.entries() {
+            for entry in entries {
+                let key = entry
+                    .partition_key()
+                    .expect("partition key should have been inserted");
+                scan.last_entries.insert(key.to_string(), index);
+            }
+        }
     }
-}
 
-impl Visitor for NamePredVisitor {
-    fn pre_visit_table(
-        &mut self,
-        table: &Table,
-        partition: &Partition,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        self.plans
-            .push(table.tag_column_names_plan(filter.partition_predicate(), partition)?);
-        Ok(())
-    }
+    Ok(scan)
 }
 
-/// return a plan that selects all values from field columns after
-/// applying timestamp and other predicates
-#[derive(Debug)]
-struct TableFieldPredVisitor {
-    // As Each table can be spread across multiple Partitions, we
-    // collect all the relevant plans and Union them together.
-    plans: Vec<LogicalPlan>,
+/// Returns `name` with any characters that are not safe to use in a
+/// file name replaced by underscores
+fn path_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
 }
 
-impl Visitor for TableFieldPredVisitor {
-    fn pre_visit_table(
-        &mut self,
-        table: &Table,
-        partition: &Partition,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        self.plans
-            .push(table.field_names_plan(filter.partition_predicate(), partition)?);
-        Ok(())
+/// Reads the JSON stored in the file at `path`, returning the default
+/// value if there is no such file
+fn read_json_file<T: DeserializeOwned + Default>(database: &str, path: &Path) -> Result<T> {
+    match std::fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data).context(InvalidDbFile { database, path }),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e).context(ReadingDbFile { database, path }),
     }
 }
 
-impl TableFieldPredVisitor {
-    fn new() -> Self {
-        let plans = Vec::new();
-        Self { plans }
-    }
-
-    fn into_fieldlist_plan(self) -> FieldListPlan {
-        FieldListPlan::Plans(self.plans)
-    }
+/// Writes `value` as JSON to the file at `path`. The JSON is written to
+/// a temporary file which then replaces `path`, so a crash while writing
+/// leaves either the old or the new file, never a partial one.
+fn write_json_file(database: &str, path: &Path, value: &impl Serialize) -> Result<()> {
+    let data = serde_json::to_vec_pretty(value).context(InvalidDbFile { database, path })?;
+
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+
+    let write = || -> std::io::Result<()> {
+        let mut file = std::fs::File::create(&tmp_path)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, path)
+    };
+    write().context(WritingDbFile { database, path })
 }
 
-/// return all values in the `column_name` column
-/// in this database, while applying the timestamp range
+/// This trait is used to implement a "Visitor" pattern for Database
+/// which can be used to define logic that shares a common Depth First
+/// Search (DFS) traversal of the Database --> Partition --> Table -->
+/// Column datastructure heirarchy.
 ///
-/// Potential optimizations: Run this in parallel (in different
-/// futures) for each partition / table, rather than a single one
-/// -- but that will require building up parallel hash tables.
-struct ValueVisitor<'a> {
-    column_name: &'a str,
-    // what column id we are looking for
-    column_id: Option<u32>,
-    partition_value_ids: BTreeSet<u32>,
-    column_values: StringSet,
-}
-
-impl<'a> ValueVisitor<'a> {
-    fn new(column_name: &'a str) -> Self {
-        Self {
-            column_name,
-            column_id: None,
-            column_values: StringSet::new(),
-            partition_value_ids: BTreeSet::new(),
-        }
+/// Specifically, if we had a database like the following:
+///
+/// YesterdayPartition
+///   CPU Table1
+///    Col1
+/// TodayPartition
+///   CPU Table2
+///    Col2
+///
+/// Then the methods would be invoked in the following order
+///
+///  visitor.pre_visit_partition(YesterdayPartition)
+///  visitor.pre_visit_table(CPU Table1)
+///  visitor.visit_column(Col1)
+///  visitor.post_visit_table(CPU Table1)
+///  visitor.post_visit_partition(YesterdayPartition)
+///  visitor.pre_visit_partition(TodayPartition)
+///  visitor.pre_visit_table(CPU Table2)
+///  visitor.visit_column(Col2)
+///  visitor.post_visit_table(CPU Table2)
+///  visitor.post_visit_partition(TodayPartition)
+trait Visitor {
+    // called once before any column in a partition is visisted
+    fn pre_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
+        Ok(())
     }
-}
-
-impl<'a> Visitor for ValueVisitor<'a> {
-    fn pre_visit_partition(&mut self, partition: &Partition) -> Result<()> {
-        self.partition_value_ids.clear();
-
-        self.column_id = Some(
-            partition
-                .dictionary
-                .lookup_value(self.column_name)
-                .context(ColumnNameNotFoundInDictionary {
-                    column_name: self.column_name,
-                    partition: &partition.key,
-                })?,
-        );
 
+    // called once before any column in a Table is visited
+    fn pre_visit_table(
+        &mut self,
+        _table: &Table,
+        _partition: &Partition,
+        _filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
         Ok(())
     }
 
+    // called every time a column is visited
     fn visit_column(
         &mut self,
-        table: &Table,
-        column_id: u32,
-        column: &Column,
-        filter: &mut PartitionTableFilter,
+        _table: &Table,
+        _column_id: u32,
+        _column: &Column,
+        _filter: &mut PartitionTableFilter,
     ) -> Result<()> {
-        if Some(column_id) != self.column_id {
-            return Ok(());
-        }
+        Ok(())
+    }
 
-        match column {
-            Column::Tag(column, _) => {
-                // if we have a timestamp prediate, find all values
-                // where the timestamp is within range. Otherwise take
-                // all values.
-                let partition_predicate = filter.partition_predicate();
-                match partition_predicate.range {
-                    None => {
-                        // take all non-null values
-                        column.iter().filter_map(|&s| s).for_each(|value_id| {
-                            self.partition_value_ids.insert(value_id);
-                        });
+    // called once after all columns in a Table are visited
+    fn post_visit_table(&mut self, _table: &Table, _partition: &Partition) -> Result<()> {
+        Ok(())
+    }
+
+    // called once after all columns in a partition is visited
+    fn post_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl Db {
+    /// Plans the SQL `query` with DataFusion, after loading the tables
+    /// it refers to from the chunks with rows in the range of times it
+    /// selects, or from all chunks if it does not bound the time
+    #[tracing::instrument(level = "debug", skip(self))]
+    async fn plan_query(&self, query: &str) -> Result<PlannedQuery> {
+        let mut tables = vec![];
+        let mut table_scans = vec![];
+
+        let dialect = GenericDialect {};
+        let mut ast = Parser::parse_sql(&dialect, query).context(InvalidSqlQuery { query })?;
+
+        for statement in &ast {
+            match statement {
+                Statement::Query(q) => {
+                    if let SetExpr::Select(q) = &q.body {
+                        let range = sql_range::select_time_range(q);
+                        for item in &q.from {
+                            if let TableFactor::Table { name, .. } = &item.relation {
+                                let name = name.to_string();
+                                let (schema, data, scan) = self.scan_table(&name, range).await?;
+                                table_scans.push(scan);
+                                tables.push(ArrowTable { name, schema, data });
+                            }
+                        }
                     }
-                    Some(range) => {
-                        // filter out all values that don't match the timestmap
-                        let time_column = table.column_i64(partition_predicate.time_column_id)?;
-
-                        column
-                            .iter()
-                            .zip(time_column.iter())
-                            .filter_map(|(&column_value_id, &timestamp_value)| {
-                                if range.contains_opt(timestamp_value) {
-                                    column_value_id
-                                } else {
-                                    None
-                                }
-                            })
-                            .for_each(|value_id| {
-                                self.partition_value_ids.insert(value_id);
-                            });
+                }
+                _ => {
+                    return UnsupportedStatement {
+                        query: query.to_string(),
+                        statement: statement.clone(),
                     }
+                    .fail()
                 }
-                Ok(())
-            }
-            _ => UnsupportedColumnTypeForListingValues {
-                column_name: self.column_name,
             }
-            .fail(),
         }
+
+        // histogram_quantile() is replaced by the expression computing
+        // it from the bucket columns of the table being queried
+        let table_schemas: Vec<_> = tables
+            .iter()
+            .map(|table| (table.name.as_str(), table.schema.as_ref()))
+            .collect();
+        let mut rewritten = false;
+        for statement in &mut ast {
+            rewritten |= histogram::rewrite_quantiles(statement, &table_schemas)?;
+        }
+        let query = if rewritten {
+            Cow::Owned(
+                ast.iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join("; "),
+            )
+        } else {
+            Cow::Borrowed(query)
+        };
+        let query = query.as_ref();
+
+        let config = ExecutionConfig::new().with_batch_size(1024 * 1024);
+        let mut ctx = ExecutionContext::with_config(config);
+
+        for table in tables {
+            let provider =
+                MemTable::new(table.schema, vec![table.data]).context(QueryError { query })?;
+            ctx.register_table(&table.name, Box::new(provider));
+        }
+
+        let initial_logical_plan = ctx
+            .create_logical_plan(&query)
+            .context(QueryError { query })?;
+        let logical_plan = ctx
+            .optimize(&initial_logical_plan)
+            .context(QueryError { query })?;
+        let physical_plan = ctx
+            .create_physical_plan(&logical_plan)
+            .context(QueryError { query })?;
+
+        Ok(PlannedQuery {
+            ctx,
+            table_scans,
+            initial_logical_plan,
+            logical_plan,
+            physical_plan,
+        })
     }
 
-    fn post_visit_partition(&mut self, partition: &Partition) -> Result<()> {
-        // convert all the partition's column_ids to Strings
-        for &value_id in &self.partition_value_ids {
-            let value = partition.dictionary.lookup_id(value_id).context(
-                ColumnValueIdNotFoundInDictionary {
-                    value_id,
-                    partition: &partition.key,
-                },
-            )?;
+    /// Converts the data of the table `table_name` in each chunk to
+    /// arrow, pruning chunks without the table, and those without rows
+    /// in `range` if it is given. Rows of chunks with overlapping time
+    /// ranges are deduplicated, so each field of a series and
+    /// timestamp has the last value written for it. Returns the schema
+    /// of the table, the batches and a description of the scan for
+    /// `EXPLAIN`
+    async fn scan_table(
+        &self,
+        table_name: &str,
+        range: Option<TimestampRange>,
+    ) -> Result<(Arc<ArrowSchema>, Vec<RecordBatch>, String)> {
+        let in_range = |time_range: Option<(i64, i64)>| match (range, time_range) {
+            (Some(range), Some((min_time, max_time))) => {
+                min_time < range.end && range.start <= max_time
+            }
+            _ => true,
+        };
 
-            if !self.column_values.contains(value) {
-                self.column_values.insert(value.to_string());
+        let (data, tag_columns, pruned) = self.table_chunks(table_name, &in_range).await?;
+        let schema = match data.first() {
+            Some(batch) => batch.schema(),
+            None => {
+                // no chunk has rows of the table in the range, but its
+                // schema is still needed to plan the query
+                let (all_data, _, _) = self.table_chunks(table_name, &|_| true).await?;
+                all_data
+                    .first()
+                    .context(TableNotFound {
+                        table: table_name,
+                        database: &self.name,
+                    })?
+                    .schema()
             }
-        }
-        Ok(())
+        };
+        self.chunk_pruning.record(data.len(), pruned);
+
+        let scanned = data.len();
+        let data =
+            dedup::deduplicate(data, &tag_columns).context(Deduplicating { table: table_name })?;
+
+        let rows: usize = data.iter().map(|batch| batch.num_rows()).sum();
+        let scan = format!(
+            "{}: {} chunks scanned, {} chunks pruned, {} rows",
+            table_name, scanned, pruned, rows
+        );
+        Ok((schema, data, scan))
     }
-}
 
-/// return all column values for the specified column in this
-/// database, while applying the timestamp range and predicate
-struct ValuePredVisitor<'a> {
-    column_name: &'a str,
-    plans: Vec<LogicalPlan>,
-}
+    /// Converts the data of the table `table_name` in each chunk whose
+    /// time range `in_range` returns true for to arrow. Returns the
+    /// batches, the tag columns of the table in those chunks and the
+    /// number of chunks pruned.
+    async fn table_chunks(
+        &self,
+        table_name: &str,
+        in_range: &(dyn Fn(Option<(i64, i64)>) -> bool + Sync),
+    ) -> Result<(Vec<RecordBatch>, BTreeSet<String>, usize)> {
+        // evicted chunks out of the range are not even read back
+        let partitions = self
+            .query_chunks(|chunk| {
+                chunk.table_names.iter().any(|t| t == table_name)
+                    && in_range(chunk.min_time.zip(chunk.max_time))
+            })
+            .await?;
+
+        let mut data = vec![];
+        let mut tag_columns = BTreeSet::new();
+        let mut pruned = 0;
+        for partition in partitions.iter() {
+            if !partition.has_table(table_name) || !in_range(partition.time_range()) {
+                pruned += 1;
+                continue;
+            }
+            tag_columns.extend(
+                partition
+                    .tag_column_names(table_name)
+                    .into_iter()
+                    .map(ToString::to_string),
+            );
 
-impl<'a> ValuePredVisitor<'a> {
-    fn new(column_name: &'a str) -> Self {
-        Self {
-            column_name,
-            plans: Vec::new(),
+            let span = debug_span!(
+                "chunk_scan",
+                table = table_name,
+                partition = %partition.key,
+                chunk = partition.id
+            );
+            let _enter = span.enter();
+            data.push(partition.table_to_arrow(table_name, &[])?);
         }
+
+        Ok((data, tag_columns, pruned))
     }
-}
 
-impl<'a> Visitor for ValuePredVisitor<'a> {
-    // TODO try and rule out entire tables based on the same critera
-    // as explained on NamePredVisitor
-    fn pre_visit_table(
-        &mut self,
-        table: &Table,
-        partition: &Partition,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        // skip table entirely if there are no rows that fall in the timestamp
-        if table.could_match_predicate(filter.partition_predicate())? {
-            self.plans.push(table.tag_values_plan(
-                self.column_name,
-                filter.partition_predicate(),
-                partition,
-            )?);
+    /// Returns the descriptions of the plans for an `EXPLAIN` query,
+    /// running the query first for `EXPLAIN ANALYZE`
+    async fn explain(&self, explain: Explain<'_>) -> Result<Vec<RecordBatch>> {
+        let query = explain.query;
+        let planned = self.plan_query(query).await?;
+
+        let mut descriptions = PlanDescriptions::new();
+        descriptions.push("tables", planned.table_scans.join("\n"));
+        if explain.verbose {
+            descriptions.push(
+                "initial_logical_plan",
+                planned.initial_logical_plan.display_indent().to_string(),
+            );
         }
-        Ok(())
-    }
-}
+        descriptions.push(
+            "logical_plan",
+            planned.logical_plan.display_indent().to_string(),
+        );
+        descriptions.push("physical_plan", format!("{:#?}", planned.physical_plan));
 
-/// Return DataFusion plans to calculate which series pass the
-/// specified predicate.
-struct SeriesVisitor {
-    plans: Vec<SeriesSetPlan>,
-}
+        if explain.analyze {
+            let (physical_plan, analyzed) =
+                AnalyzedPlan::instrument(planned.physical_plan).context(QueryError { query })?;
 
-impl SeriesVisitor {
-    fn new() -> Self {
-        Self { plans: Vec::new() }
+            let start = std::time::Instant::now();
+            let batches = planned
+                .ctx
+                .collect(physical_plan)
+                .await
+                .context(QueryError { query })?;
+            let elapsed = start.elapsed();
+
+            let rows: usize = batches.iter().map(|batch| batch.num_rows()).sum();
+            descriptions.push(
+                "execution",
+                format!(
+                    "output_rows={}, output_batches={}, elapsed={:?}\n{}",
+                    rows,
+                    batches.len(),
+                    elapsed,
+                    analyzed.describe()
+                ),
+            );
+        }
+
+        Ok(vec![descriptions.into_batch().context(ArrowError)?])
     }
-}
 
-impl Visitor for SeriesVisitor {
-    fn pre_visit_table(
-        &mut self,
-        table: &Table,
-        partition: &Partition,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        self.plans
-            .push(table.series_set_plan(filter.partition_predicate(), partition)?);
+    /// Downsamples each partition whose newest data is older than
+    /// the `after` of any of `rules`, as of `now` (in nanoseconds),
+    /// replacing its data with the aggregates of the rule with the
+    /// largest such `after`. Partitions that have already been
+    /// downsampled to the interval of that rule (or a wider one) are
+    /// left as they are.
+    ///
+    /// Returns the number of partitions that were downsampled.
+    ///
+    /// Note the downsampled data is not written to the WAL, so a
+    /// database restored from its WAL contains the raw data again
+    /// until it is next downsampled.
+    pub async fn downsample(&self, rules: &[DownsamplingRule], now: i64) -> Result<usize> {
+        let mut partitions = self.partitions.write().await;
+
+        let mut downsampled = 0;
+        for partition in partitions.iter_mut() {
+            let max_time = match downsample::max_time(partition) {
+                Some(max_time) => max_time,
+                None => continue,
+            };
 
-        Ok(())
-    }
-}
+            let rule = match downsample::applicable_rule(rules, now.saturating_sub(max_time)) {
+                Some(rule) => rule,
+                None => continue,
+            };
 
-/// Return DataFusion plans to calculate series that pass the
-/// specified predicate, grouped according to grouped_columns
-struct GroupsVisitor {
-    group_columns: Vec<String>,
-    plans: Vec<GroupedSeriesSetPlan>,
-}
+            if partition
+                .downsampled_interval
+                .map_or(false, |interval| interval >= rule.interval_nanos())
+            {
+                continue;
+            }
 
-impl GroupsVisitor {
-    fn new(group_columns: Vec<String>) -> Self {
-        Self {
-            group_columns,
-            plans: Vec::new(),
+            *partition = downsample::downsample_partition(partition, rule)?;
+            downsampled += 1;
         }
-    }
-}
 
-impl Visitor for GroupsVisitor {
-    fn pre_visit_table(
-        &mut self,
-        table: &Table,
-        partition: &Partition,
-        filter: &mut PartitionTableFilter,
-    ) -> Result<()> {
-        self.plans.push(table.grouped_series_set_plan(
-            filter.partition_predicate(),
-            &self.group_columns,
-            partition,
-        )?);
+        if downsampled > 0 {
+            info!(
+                "{} database downsampled {} partitions",
+                &self.name, downsampled
+            );
+            self.generation.fetch_add(1, Ordering::SeqCst);
+        }
 
-        Ok(())
+        Ok(downsampled)
     }
-}
 
-// partition_key returns the partition key for the given line. The key will be the prefix of a
-// partition name (multiple partitions can exist for each key). It uses the user defined
-// partitioning rules to construct this key
-pub fn partition_key(line: &ParsedLine<'_>) -> String {
-    // TODO - wire this up to use partitioning rules, for now just partition by day
-    let ts = line.timestamp.unwrap();
-    let dt = Utc.timestamp_nanos(ts);
-    dt.format("%Y-%m-%dT%H").to_string()
-}
+    /// Drops each partition whose newest data is older than the
+    /// retention period of this database's rules, as of `now` (in
+    /// nanoseconds), along with the files of its persisted chunks. Does
+    /// nothing if the rules have no retention period.
+    ///
+    /// Returns the number of partitions that were dropped.
+    pub async fn apply_retention(&self, now: i64) -> Result<usize> {
+        let retention = match self.rules.read().await.retention_period_nanos() {
+            Some(retention) => retention,
+            None => return Ok(0),
+        };
+        let cutoff = now.saturating_sub(retention);
 
-struct ArrowTable {
-    name: String,
-    schema: Arc<ArrowSchema>,
-    data: Vec<RecordBatch>,
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use arrow_deps::datafusion::{
-        logical_plan::{self, Literal},
-        scalar::ScalarValue,
-    };
-    use logical_plan::{Expr, Operator};
-    use storage::{
-        exec::fieldlist::{Field, FieldList},
-        exec::{
-            seriesset::{Error as SeriesSetError, SeriesSet},
-            Executor,
-        },
-        predicate::PredicateBuilder,
-        Database,
-    };
+        let mut partitions = self.partitions.write().await;
+        let expired: BTreeSet<String> = partitions
+            .iter()
+            .filter(|p| downsample::max_time(p).map_or(false, |max_time| max_time < cutoff))
+            .map(|p| p.key.clone())
+            .chain(
+                self.evicted_chunks()
+                    .into_iter()
+                    .filter(|c| c.max_time.map_or(false, |max_time| max_time < cutoff))
+                    .map(|c| c.partition_key),
+            )
+            .collect();
+        if expired.is_empty() {
+            return Ok(0);
+        }
 
-    use arrow::{
-        array::{Array, StringArray},
-        datatypes::DataType,
-        util::pretty::pretty_format_batches,
-    };
-    use influxdb_line_protocol::parse_lines;
-    use test_helpers::str_pair_vec_to_vec;
-    use tokio::sync::mpsc;
+        // the drop is recorded before the data is removed from memory, so
+        // that the expired data is never restored from the WAL
+        self.record_dropped_partitions(&expired).await?;
+
+        let (expired_chunks, kept): (Vec<_>, Vec<_>) =
+            partitions.drain(..).partition(|p| expired.contains(&p.key));
+        *partitions = kept;
+        self.remove_persisted_chunks(
+            expired_chunks
+                .iter()
+                .filter(|p| p.persisted)
+                .map(|p| (p.key.as_str(), p.id)),
+        );
+        let dropped = expired_chunks.len()
+            + self.remove_evicted_chunks(|c| expired.contains(&c.partition_key))?;
 
-    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
-    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+        info!(
+            "{} database dropped {} partitions past its retention period",
+            &self.name, dropped
+        );
+        self.generation.fetch_add(1, Ordering::SeqCst);
 
-    fn to_set(v: &[&str]) -> BTreeSet<String> {
-        v.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>()
+        Ok(dropped)
     }
 
-    // Abstract a bit of boilerplate around table assertions and improve the failure output.
-    // The default failure message uses Debug formatting, which prints newlines as `\n`.
-    // This prints the pretty_format_batches using Display so it's easier to read the tables.
-    fn assert_table_eq(table: &str, partitions: &[arrow::record_batch::RecordBatch]) {
-        let res = pretty_format_batches(partitions).unwrap();
-        assert_eq!(table, res, "\n\nleft:\n\n{}\nright:\n\n{}", table, res);
+    /// Records that the data of the partitions with keys `keys` written
+    /// to the WAL so far has been dropped, alongside the WAL, so that it
+    /// is not restored from it
+    async fn record_dropped_partitions(&self, keys: &BTreeSet<String>) -> Result<()> {
+        let sequence = self.next_wal_sequence.load(Ordering::SeqCst);
+        self.record_dropped_partitions_before(keys, sequence).await
     }
 
-    // query the table names, with optional range predicate
-    async fn table_names(db: &Db, predicate: Predicate) -> Result<StringSet> {
-        let plan = db.table_names(predicate).await?;
-        let executor = Executor::default();
-        let s = executor.to_string_set(plan).await?;
+    /// Records that the data of the partitions with keys `keys` written
+    /// to the WAL before `sequence` has been dropped, as
+    /// `record_dropped_partitions` does
+    async fn record_dropped_partitions_before(
+        &self,
+        keys: &BTreeSet<String>,
+        sequence: u64,
+    ) -> Result<()> {
+        let mut dropped_partitions = self.dropped_partitions.write().await;
+        for key in keys {
+            let dropped_before = dropped_partitions.entry(key.clone()).or_insert(sequence);
+            *dropped_before = (*dropped_before).max(sequence);
+        }
 
-        // unwrap it (for easy test comparisons)
-        let s = Arc::try_unwrap(s).expect("only one reference to the set in the test");
-        Ok(s)
+        if let Some(dir) = &self.dir {
+            write_json_file(
+                &self.name,
+                &dir.join(DROPPED_PARTITIONS_FILE_NAME),
+                &*dropped_partitions,
+            )?;
+        }
+        Ok(())
     }
 
-    #[tokio::test]
-    async fn list_table_names() -> Result {
-        let mut dir = test_helpers::tmp_dir()?.into_path();
+    /// Records that the rows of the series with keys `series_keys`
+    /// written to the partition with key `partition_key` so far have
+    /// been deleted, alongside the WAL, so that they are not restored
+    /// from it
+    async fn record_deleted_series(
+        &self,
+        partition_key: &str,
+        series_keys: &BTreeSet<String>,
+    ) -> Result<()> {
+        let sequence = self.next_wal_sequence.load(Ordering::SeqCst);
 
-        let db = Db::try_with_wal("mydb", &mut dir).await?;
+        let mut deleted_series = self.deleted_series.write().await;
+        let deleted = deleted_series.entry(partition_key.to_string()).or_default();
+        for series_key in series_keys {
+            deleted.insert(series_key.clone(), sequence);
+        }
 
-        // no tables initially
-        assert_eq!(
-            table_names(&db, Predicate::default()).await?,
-            BTreeSet::new()
-        );
+        if let Some(dir) = &self.dir {
+            write_json_file(
+                &self.name,
+                &dir.join(DELETED_SERIES_FILE_NAME),
+                &*deleted_series,
+            )?;
+        }
+        Ok(())
+    }
 
-        // write two different tables
-        let lines: Vec<_> =
-            parse_lines("cpu,region=west user=23.2 10\ndisk,region=east bytes=99i 11")
-                .map(|l| l.unwrap())
+    /// Persists and evicts each partition which has not been written to
+    /// for the idle period of this database's rules, as of `now` (in
+    /// nanoseconds), however small it is. Its open chunk is closed, any
+    /// of its chunks not yet persisted are written to parquet files, and
+    /// its chunks are then removed from memory. Does nothing if the
+    /// rules have no idle period or the database has no directory to
+    /// persist to.
+    ///
+    /// The files are written without holding the lock on the chunks of
+    /// this database, so they are written to and queried meanwhile. A
+    /// partition whose chunks are compacted or dropped meanwhile is not
+    /// evicted, and one written to meanwhile keeps the chunk it was
+    /// written to.
+    ///
+    /// Evicted chunks are still listed by `chunks`, as stored in object
+    /// storage, and are read back from their files by queries. Their
+    /// eviction is recorded alongside the WAL, as dropping a partition
+    /// is, so their data is not restored from it.
+    ///
+    /// Returns the number of partitions that were evicted.
+    pub async fn persist_idle(&self, now: i64) -> Result<usize> {
+        let idle = match self.rules.read().await.persist_idle_nanos() {
+            Some(idle) => idle,
+            None => return Ok(0),
+        };
+        let dir = match &self.dir {
+            Some(dir) => dir.clone(),
+            None => return Ok(0),
+        };
+        let cutoff = now.saturating_sub(idle);
+
+        // the chunks of each idle partition, and the sequence number
+        // of the first write to the WAL after they were closed
+        let (idle_chunks, snapshots, sequence) = {
+            let mut partitions = self.partitions.write().await;
+
+            // a partition is idle if none of its chunks has been
+            // written to since the cutoff
+            let mut last_writes: BTreeMap<&str, i64> = BTreeMap::new();
+            for partition in partitions.iter() {
+                let last_write = partition.last_write_time.unwrap_or(self.opened_time);
+                let latest = last_writes.entry(&partition.key).or_insert(last_write);
+                *latest = (*latest).max(last_write);
+            }
+            let idle_keys: BTreeSet<String> = last_writes
+                .into_iter()
+                .filter(|(_, last_write)| *last_write < cutoff)
+                .map(|(key, _)| key.to_string())
                 .collect();
-        db.write_lines(&lines).await?;
+            if idle_keys.is_empty() {
+                return Ok(0);
+            }
 
-        // Now, we should see the two tables
-        assert_eq!(
-            table_names(&db, Predicate::default()).await?,
-            to_set(&["cpu", "disk"])
+            let mut idle_chunks: BTreeMap<String, BTreeSet<u32>> = BTreeMap::new();
+            let mut snapshots = vec![];
+            for partition in partitions.iter_mut().filter(|p| idle_keys.contains(&p.key)) {
+                partition.is_open = false;
+                idle_chunks
+                    .entry(partition.key.clone())
+                    .or_default()
+                    .insert(partition.id);
+                if !partition.persisted {
+                    let chunk_dir = persisted_chunk_dir(&dir, &partition.key, partition.id);
+                    snapshots.push((chunk_dir, rewrite::snapshot_partition(partition)?));
+                }
+            }
+
+            let sequence = self.next_wal_sequence.load(Ordering::SeqCst);
+            (idle_chunks, snapshots, sequence)
+        };
+
+        tokio::task::spawn_blocking(move || {
+            snapshots
+                .iter()
+                .try_for_each(|(chunk_dir, snapshot)| snapshot.persist(chunk_dir))
+        })
+        .await
+        .context(BlockingTask {
+            database: &self.name,
+        })??;
+
+        // the files of chunks which were persisted before are copied
+        // again, in case they could not be copied then
+        self.copy_persisted_chunks(
+            idle_chunks
+                .iter()
+                .flat_map(|(key, ids)| ids.iter().map(move |&id| (key.as_str(), id))),
+        )
+        .await?;
+
+        let mut partitions = self.partitions.write().await;
+
+        // only partitions whose idle chunks are all still as they were
+        // persisted are evicted
+        let evicted_keys: BTreeSet<String> = idle_chunks
+            .iter()
+            .filter(|(key, ids)| {
+                ids.iter().all(|&id| {
+                    partitions
+                        .iter()
+                        .any(|p| &&p.key == key && p.id == id && !p.is_open)
+                })
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        if evicted_keys.is_empty() {
+            return Ok(0);
+        }
+        let is_evicted =
+            |p: &Partition| evicted_keys.contains(&p.key) && idle_chunks[&p.key].contains(&p.id);
+
+        let mut evicted = vec![];
+        for partition in partitions.iter_mut().filter(|p| is_evicted(&**p)) {
+            partition.persisted = true;
+            evicted.push(ChunkSummary {
+                storage: ChunkStorage::ObjectStore,
+                ..partition.chunk_summary()
+            });
+        }
+
+        self.record_dropped_partitions_before(&evicted_keys, sequence)
+            .await?;
+        {
+            let mut evicted_chunks = self.evicted_chunks.write().expect("lock poisoned");
+            evicted_chunks.extend(evicted);
+            write_json_file(
+                &self.name,
+                &dir.join(EVICTED_CHUNKS_FILE_NAME),
+                &*evicted_chunks,
+            )?;
+        }
+
+        partitions.retain(|p| !is_evicted(p));
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        info!(
+            "{} database persisted and evicted {} idle partitions",
+            &self.name,
+            evicted_keys.len()
         );
 
-        Ok(())
+        Ok(evicted_keys.len())
     }
 
-    #[tokio::test]
-    async fn list_table_names_timestamps() -> Result {
-        let mut dir = test_helpers::tmp_dir()?.into_path();
-
-        let db = Db::try_with_wal("mydb", &mut dir).await?;
+    /// Forgets the evicted chunks `remove` returns true for, and
+    /// removes their files, returning how many there were
+    fn remove_evicted_chunks(&self, remove: impl Fn(&ChunkSummary) -> bool) -> Result<usize> {
+        let removed: Vec<_> = {
+            let mut evicted_chunks = self.evicted_chunks.write().expect("lock poisoned");
+            let (removed, kept): (Vec<_>, Vec<_>) = evicted_chunks.drain(..).partition(&remove);
+            *evicted_chunks = kept;
+
+            if let (Some(dir), false) = (&self.dir, removed.is_empty()) {
+                write_json_file(
+                    &self.name,
+                    &dir.join(EVICTED_CHUNKS_FILE_NAME),
+                    &*evicted_chunks,
+                )?;
+            }
+            removed
+        };
 
-        // write two different tables at the following times:
-        // cpu: 100 and 150
-        // disk: 200
-        let lines: Vec<_> =
-            parse_lines("cpu,region=west user=23.2 100\ncpu,region=west user=21.0 150\ndisk,region=east bytes=99i 200")
-                .map(|l| l.unwrap())
-                .collect();
-        db.write_lines(&lines).await?;
+        self.remove_persisted_chunks(removed.iter().map(|c| (c.partition_key.as_str(), c.id)));
+        Ok(removed.len())
+    }
 
-        // Cover all times
-        let predicate = PredicateBuilder::default().timestamp_range(0, 201).build();
-        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu", "disk"]));
+    /// Removes the files of the persisted chunks `chunks`, given by
+    /// their partition keys and ids, once they are no longer part of
+    /// this database. Files which can't be removed are left in place.
+    fn remove_persisted_chunks<'a>(&self, chunks: impl IntoIterator<Item = (&'a str, u32)>) {
+        let dir = match &self.dir {
+            Some(dir) => dir,
+            None => return,
+        };
 
-        // Right before disk
-        let predicate = PredicateBuilder::default().timestamp_range(0, 200).build();
-        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu"]));
+        let chunks: Vec<_> = chunks.into_iter().collect();
+        for &(partition_key, chunk_id) in &chunks {
+            let chunk_dir = persisted_chunk_dir(dir, partition_key, chunk_id);
+            match std::fs::remove_dir_all(&chunk_dir) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!(
+                    "{} database could not remove persisted chunk {:?}: {}",
+                    &self.name, chunk_dir, e
+                ),
+            }
+        }
+        self.remove_copied_chunks(chunks);
+    }
 
-        // only one point of cpu
-        let predicate = PredicateBuilder::default().timestamp_range(50, 101).build();
-        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu"]));
+    /// Removes the copies of the files of the persisted chunks `chunks`
+    /// from the object store, if there is one, in the background.
+    /// Copies which can't be removed are left in place.
+    fn remove_copied_chunks<'a>(&self, chunks: impl IntoIterator<Item = (&'a str, u32)>) {
+        let store = match &self.object_store {
+            Some(store) => Arc::clone(store),
+            None => return,
+        };
 
-        // no ranges
-        let predicate = PredicateBuilder::default()
-            .timestamp_range(250, 350)
-            .build();
-        assert_eq!(table_names(&db, predicate).await?, to_set(&[]));
+        let prefixes: Vec<_> = chunks
+            .into_iter()
+            .map(|(partition_key, chunk_id)| self.chunk_object_prefix(partition_key, chunk_id))
+            .collect();
+        let name = self.name.clone();
+        tokio::spawn(async move {
+            for prefix in prefixes {
+                if let Err(e) = archive::remove_chunk(&store, &prefix).await {
+                    warn!(
+                        "{} database could not remove the copies of chunk {}: {}",
+                        name, prefix, e
+                    );
+                }
+            }
+        });
+    }
 
-        Ok(())
+    /// The chunks which have been persisted and evicted from memory
+    fn evicted_chunks(&self) -> Vec<ChunkSummary> {
+        self.evicted_chunks.read().expect("lock poisoned").clone()
     }
 
-    #[tokio::test]
-    async fn missing_tags_are_null() -> Result {
-        let mut dir = test_helpers::tmp_dir()?.into_path();
+    /// Returns the chunks a query reads: those in memory, and those
+    /// evicted chunks `select` returns true for, which are read back
+    /// from their parquet files. The chunks in memory stay locked until
+    /// the returned value is dropped, so no chunk is evicted, and so
+    /// missed, while they are read.
+    async fn query_chunks(
+        &self,
+        select: impl Fn(&ChunkSummary) -> bool,
+    ) -> Result<QueryChunks<'_>> {
+        let in_memory = self.partitions.read().await;
+        let generation = self.generation.load(Ordering::SeqCst);
+
+        let evicted: Vec<_> = self
+            .evicted_chunks()
+            .into_iter()
+            .filter(|chunk| select(chunk))
+            .collect();
+        let evicted = match (&self.dir, evicted.is_empty()) {
+            (Some(dir), false) => {
+                let dir = dir.clone();
+                let mode = self.persisted_open_mode;
+                tokio::task::spawn_blocking(move || read_evicted_chunks(&dir, &evicted, mode))
+                    .await
+                    .context(BlockingTask {
+                        database: &self.name,
+                    })??
+            }
+            _ => vec![],
+        };
 
-        let db = Db::try_with_wal("mydb", &mut dir).await?;
+        Ok(QueryChunks {
+            in_memory,
+            evicted,
+            generation,
+        })
+    }
 
-        // Note the `region` tag is introduced in the second line, so
-        // the values in prior rows for the region column are
-        // null. Likewise the `core` tag is introduced in the third
-        // line so the prior columns are null
-        let lines: Vec<_> = parse_lines(
-            "cpu,region=west user=23.2 10\n\
-                         cpu, user=10.0 11\n\
-                         cpu,core=one user=10.0 11\n",
-        )
-        .map(|l| l.unwrap())
-        .collect();
-        db.write_lines(&lines).await?;
+    /// Returns the id for a new chunk of the partition with key `key`:
+    /// one more than the largest id of its existing chunks in
+    /// `partitions`, or of those evicted
+    fn next_chunk_id(&self, partitions: &[Partition], key: &str) -> u32 {
+        let evicted = self.evicted_chunks.read().expect("lock poisoned");
+        partitions
+            .iter()
+            .filter(|p| p.key == key)
+            .map(|p| p.id)
+            .chain(
+                evicted
+                    .iter()
+                    .filter(|c| c.partition_key == key)
+                    .map(|c| c.id),
+            )
+            .max()
+            .map_or(0, |id| id + 1)
+    }
 
-        let partitions = db.table_to_arrow("cpu", &["region", "core"]).await?;
-        let columns = partitions[0].columns();
+    /// returns the approximate number of bytes used to store the data
+    /// in this database
+    pub async fn size(&self) -> usize {
+        self.partitions.read().await.iter().map(|p| p.size()).sum()
+    }
 
-        assert_eq!(
-            2,
-            columns.len(),
-            "Got only two columns in partiton: {:#?}",
-            columns
+    /// Removes the data of the partition with key `partition_key`,
+    /// either removing the partition itself or, if `keep_partition` is
+    /// true, leaving it empty. The removal is recorded alongside the
+    /// WAL so the removed data is not restored from it, and the files
+    /// of its persisted chunks, whether evicted or not, are removed.
+    async fn remove_partition_data(
+        &self,
+        partition_key: &str,
+        dry_run: bool,
+        keep_partition: bool,
+    ) -> Result<PartitionSummary> {
+        let mut partitions = self.partitions.write().await;
+
+        let evicted: Vec<_> = self
+            .evicted_chunks()
+            .into_iter()
+            .filter(|c| c.partition_key == partition_key)
+            .collect();
+        let chunks: Vec<_> = partitions
+            .iter()
+            .filter(|p| p.key == partition_key)
+            .map(|p| p.summary())
+            .collect();
+        ensure!(
+            !chunks.is_empty() || !evicted.is_empty(),
+            PartitionNotFound {
+                database: &self.name,
+                partition: partition_key,
+            }
         );
 
-        let region_col = columns[0]
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .expect("Get region column as a string");
+        let mut summary = PartitionSummary {
+            key: partition_key.to_string(),
+            ..Default::default()
+        };
+        for chunk in chunks {
+            summary.tables.extend(chunk.tables);
+            summary.row_count += chunk.row_count;
+            summary.size += chunk.size;
+        }
+        for chunk in evicted {
+            summary.tables.extend(chunk.table_names);
+            summary.row_count += chunk.row_count;
+            summary.size += chunk.size;
+        }
+        summary.tables.sort();
+        summary.tables.dedup();
 
-        assert_eq!(region_col.len(), 3);
-        assert_eq!(region_col.value(0), "west", "region_col: {:?}", region_col);
-        assert!(!region_col.is_null(0), "is_null(0): {:?}", region_col);
-        assert!(region_col.is_null(1), "is_null(1): {:?}", region_col);
-        assert!(region_col.is_null(2), "is_null(1): {:?}", region_col);
+        if dry_run {
+            return Ok(summary);
+        }
 
-        let host_col = columns[1]
-            .as_any()
-            .downcast_ref::<StringArray>()
-            .expect("Get host column as a string");
+        let mut keys = BTreeSet::new();
+        keys.insert(partition_key.to_string());
+        self.record_dropped_partitions(&keys).await?;
+        self.remove_evicted_chunks(|c| c.partition_key == partition_key)?;
 
-        assert_eq!(host_col.len(), 3);
-        assert!(host_col.is_null(0), "is_null(0): {:?}", host_col);
-        assert!(host_col.is_null(1), "is_null(1): {:?}", host_col);
-        assert!(!host_col.is_null(2), "is_null(2): {:?}", host_col);
-        assert_eq!(host_col.value(2), "one", "host_col: {:?}", host_col);
+        let index = partitions
+            .iter()
+            .position(|p| p.key == partition_key)
+            .unwrap_or_else(|| partitions.len());
+        let persisted: Vec<_> = partitions
+            .iter()
+            .filter(|p| p.key == partition_key && p.persisted)
+            .map(|p| p.id)
+            .collect();
+        partitions.retain(|p| p.key != partition_key);
+        self.remove_persisted_chunks(persisted.into_iter().map(|id| (partition_key, id)));
+        if keep_partition {
+            partitions.insert(index, Partition::new(partition_key));
+        }
+        self.generation.fetch_add(1, Ordering::SeqCst);
 
-        Ok(())
+        info!(
+            "{} database removed {} rows from partition {}",
+            &self.name, summary.row_count, partition_key
+        );
+
+        Ok(summary)
     }
 
-    #[tokio::test]
-    async fn write_data_and_recover() -> Result {
-        let mut dir = test_helpers::tmp_dir()?.into_path();
+    /// Returns the chunk with id `chunk_id` of the partition with key
+    /// `partition_key` from `partitions`
+    fn find_chunk<'a>(
+        &self,
+        partitions: &'a mut [Partition],
+        partition_key: &str,
+        chunk_id: u32,
+    ) -> Result<&'a mut Partition> {
+        partitions
+            .iter_mut()
+            .find(|p| p.key == partition_key && p.id == chunk_id)
+            .context(ChunkNotFound {
+                database: &self.name,
+                partition: partition_key,
+                chunk: chunk_id,
+            })
+    }
 
-        let expected_cpu_table = r#"+--------+------+------+-------+-------------+------+------+---------+-----------+
-| region | host | user | other | str         | b    | time | new_tag | new_field |
-+--------+------+------+-------+-------------+------+------+---------+-----------+
-| west   | A    | 23.2 | 1     | some string | true | 10   |         |           |
-| west   | B    | 23.1 |       |             |      | 15   |         |           |
-|        | A    |      |       |             |      | 20   | foo     | 15.1      |
-+--------+------+------+-------+-------------+------+------+---------+-----------+
-"#;
-        let expected_mem_table = r#"+--------+------+-------+------+
-| region | host | val   | time |
-+--------+------+-------+------+
-| east   | C    | 23432 | 10   |
-+--------+------+-------+------+
-"#;
-        let expected_disk_table = r#"+--------+------+----------+--------------+------+
-| region | host | bytes    | used_percent | time |
-+--------+------+----------+--------------+------+
-| west   | A    | 23432323 | 76.2         | 10   |
-+--------+------+----------+--------------+------+
-"#;
+    /// Opens chunk `chunk_id` of partition `partition_key`, which was
+    /// persisted with `persist_chunk`. Only the metadata of its files is
+    /// read; the values of each column are decoded when it is read.
+    pub fn open_persisted_chunk(
+        &self,
+        partition_key: &str,
+        chunk_id: u32,
+        mode: OpenMode,
+    ) -> Result<PersistedChunk> {
+        let dir = self.dir.as_ref().context(NoPersistenceDir {
+            database: &self.name,
+        })?;
+
+        let chunk_dir = persisted_chunk_dir(dir, partition_key, chunk_id);
+        Ok(PersistedChunk::open(&chunk_dir, mode)?)
+    }
 
-        let cpu_columns = &[
-            "region",
-            "host",
-            "user",
-            "other",
-            "str",
+    /// returns the number of partitions in this database
+    pub async fn len(&self) -> usize {
+        self.partitions.read().await.len()
+    }
+
+    /// returns true if the database has no partititons
+    pub async fn is_empty(&self) -> bool {
+        self.partitions.read().await.is_empty()
+    }
+
+    /// Traverse this database's tables, calling the relevant
+    /// functions, in order, of `visitor`, as described on the Visitor
+    /// trait.
+    ///
+    /// Skips visiting any table or columns of `filter.should_visit_table` returns false,
+    /// and any partition none of whose tables it returns true for
+    ///
+    /// Returns the number of chunks (partitions) visited and skipped,
+    /// and the generation of the data visited
+    async fn visit_tables<V: Visitor>(
+        &self,
+        filter: &mut PartitionTableFilter,
+        visitor: &mut V,
+    ) -> Result<TableWalk> {
+        let walk = self.walk_tables(filter, visitor).await?;
+        self.record_chunk_pruning(walk);
+
+        Ok(walk)
+    }
+
+    /// Traverse this database's tables as `visit_tables` does,
+    /// without recording how many chunks were pruned
+    async fn walk_tables<V: Visitor>(
+        &self,
+        filter: &mut PartitionTableFilter,
+        visitor: &mut V,
+    ) -> Result<TableWalk> {
+        let partitions = self
+            .query_chunks(|chunk| chunk_could_match(&filter.predicate, chunk))
+            .await?;
+
+        let mut pruned = 0;
+        for partition in partitions.iter() {
+            filter.pre_visit_partition(partition)?;
+            if !filter.should_visit_partition(partition)? {
+                pruned += 1;
+                continue;
+            }
+            visitor.pre_visit_partition(partition)?;
+
+            for table in partition.tables.values() {
+                if filter.should_visit_table(table)? {
+                    visitor.pre_visit_table(table, partition, filter)?;
+
+                    for (column_id, column_index) in &table.column_id_to_index {
+                        visitor.visit_column(
+                            table,
+                            *column_id,
+                            &table.columns[*column_index],
+                            filter,
+                        )?
+                    }
+
+                    visitor.post_visit_table(table, partition)?;
+                }
+            }
+            visitor.post_visit_partition(partition)?;
+        } // next partition
+
+        Ok(TableWalk {
+            chunks_scanned: (partitions.len() - pruned) as u64,
+            chunks_pruned: pruned as u64,
+            generation: partitions.generation,
+        })
+    }
+
+    fn record_chunk_pruning(&self, walk: TableWalk) {
+        debug!(
+            "{} database scanned {} chunks and pruned {} chunks",
+            &self.name, walk.chunks_scanned, walk.chunks_pruned
+        );
+        self.chunk_pruning
+            .record(walk.chunks_scanned as usize, walk.chunks_pruned as usize);
+    }
+}
+
+/// What a walk of the tables of a database visited
+#[derive(Debug, Clone, Copy)]
+struct TableWalk {
+    /// The number of chunks visited
+    chunks_scanned: u64,
+    /// The number of chunks skipped, because they could not contain
+    /// data matching the predicate
+    chunks_pruned: u64,
+    /// The generation of the database when its tables were walked
+    generation: u64,
+}
+
+/// Common logic for processing and filtering tables in the write buffer
+///
+/// Note that since each partition has its own dictionary, mappings
+/// between Strings --> we cache the String->id mappings per partition
+///
+/// b) the table doesn't have a column range that overlaps the
+/// predicate values, e.g., if you have env = "us-west" and a
+/// table's env column has the range ["eu-south", "us-north"].
+#[derive(Debug)]
+struct PartitionTableFilter {
+    predicate: Predicate,
+
+    /// If specififed, only tables with all specified columns will be
+    /// visited. Note that just becuase a table has all these columns,
+    /// it might not be visited for other reasons (e.g. it is filted
+    /// out by a table_name predicate)
+    additional_required_columns: Option<HashSet<String>>,
+
+    /// A 'compiled' version of the predicate to evaluate on tables /
+    /// columns in a particular partition during the walk
+    partition_predicate: Option<PartitionPredicate>,
+}
+
+impl PartitionTableFilter {
+    fn new(predicate: Predicate) -> Self {
+        Self {
+            predicate,
+            additional_required_columns: None,
+            partition_predicate: None,
+        }
+    }
+
+    /// adds the specified columns to a list of columns that must be
+    /// present in a table.
+    fn add_required_columns(mut self, column_names: &[String]) -> Self {
+        let mut required_columns = self
+            .additional_required_columns
+            .take()
+            .unwrap_or_else(HashSet::new);
+
+        for c in column_names {
+            if !required_columns.contains(c) {
+                required_columns.insert(c.clone());
+            }
+        }
+
+        self.additional_required_columns = Some(required_columns);
+        self
+    }
+
+    /// Called when each partition gets visited. Since ids are
+    /// specific to each partitition, the predicates much get
+    /// translated each time.
+    fn pre_visit_partition(&mut self, partition: &Partition) -> Result<()> {
+        let mut partition_predicate = partition.compile_predicate(&self.predicate)?;
+
+        // add any additional column needs
+        if let Some(additional_required_columns) = &self.additional_required_columns {
+            partition.add_required_columns_to_predicate(
+                additional_required_columns,
+                &mut partition_predicate,
+            );
+        }
+
+        self.partition_predicate = Some(partition_predicate);
+
+        Ok(())
+    }
+
+    /// If returns false, skips visiting partition: the statistics of
+    /// its tables show none of its rows can pass the predicate
+    fn should_visit_partition(&mut self, partition: &Partition) -> Result<bool> {
+        for table in partition.tables.values() {
+            if self.should_visit_table(table)? {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// If returns false, skips visiting _table and all its columns
+    fn should_visit_table(&mut self, table: &Table) -> Result<bool> {
+        Ok(table.could_match_predicate(self.partition_predicate())?)
+    }
+
+    pub fn partition_predicate(&self) -> &PartitionPredicate {
+        self.partition_predicate
+            .as_ref()
+            .expect("Visited partition to compile predicate")
+    }
+}
+
+/// return all column names in this database, while applying only the
+/// timestamp range (has no general purpose predicates). The summaries
+/// of the tags of each table usually answer this without reading rows.
+struct NameVisitor {
+    column_names: StringSet,
+    partition_column_ids: BTreeSet<u32>,
+}
+
+impl NameVisitor {
+    fn new() -> Self {
+        Self {
+            column_names: StringSet::new(),
+            partition_column_ids: BTreeSet::new(),
+        }
+    }
+}
+
+impl Visitor for NameVisitor {
+    fn visit_column(
+        &mut self,
+        table: &Table,
+        column_id: u32,
+        column: &Column,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        if let Column::Tag(column, _) = column {
+            if table.tag_matches_predicate(column_id, column, filter.partition_predicate())? {
+                self.partition_column_ids.insert(column_id);
+            }
+        }
+        Ok(())
+    }
+
+    fn pre_visit_partition(&mut self, _partition: &Partition) -> Result<()> {
+        self.partition_column_ids.clear();
+        Ok(())
+    }
+
+    fn post_visit_partition(&mut self, partition: &Partition) -> Result<()> {
+        // convert all the partition's column_ids to Strings
+        for &column_id in &self.partition_column_ids {
+            let column_name = partition.dictionary.lookup_id(column_id).context(
+                ColumnIdNotFoundInDictionary {
+                    column_id,
+                    partition: &partition.key,
+                },
+            )?;
+
+            if !self.column_names.contains(column_name) {
+                self.column_names.insert(column_name.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Return all column names in this database, while applying a
+/// general purpose predicates
+struct NamePredVisitor {
+    plans: Vec<LogicalPlan>,
+}
+
+impl NamePredVisitor {
+    fn new() -> Self {
+        Self { plans: Vec::new() }
+    }
+}
+
+impl Visitor for NamePredVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        self.plans
+            .push(table.tag_column_names_plan(filter.partition_predicate(), partition)?);
+        Ok(())
+    }
+}
+
+/// return a plan that selects all values from field columns after
+/// applying timestamp and other predicates
+#[derive(Debug)]
+struct TableFieldPredVisitor {
+    // As Each table can be spread across multiple Partitions, we
+    // collect all the relevant plans and Union them together.
+    plans: Vec<LogicalPlan>,
+}
+
+impl Visitor for TableFieldPredVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        self.plans
+            .push(table.field_names_plan(filter.partition_predicate(), partition)?);
+        Ok(())
+    }
+}
+
+impl TableFieldPredVisitor {
+    fn new() -> Self {
+        let plans = Vec::new();
+        Self { plans }
+    }
+
+    fn into_fieldlist_plan(self) -> FieldListPlan {
+        FieldListPlan::Plans(self.plans)
+    }
+}
+
+/// return all values in the `column_name` column
+/// in this database, while applying the timestamp range
+///
+/// Potential optimizations: Run this in parallel (in different
+/// futures) for each partition / table, rather than a single one
+/// -- but that will require building up parallel hash tables.
+struct ValueVisitor<'a> {
+    column_name: &'a str,
+    // what column id we are looking for
+    column_id: Option<u32>,
+    partition_value_ids: BTreeSet<u32>,
+    column_values: StringSet,
+}
+
+impl<'a> ValueVisitor<'a> {
+    fn new(column_name: &'a str) -> Self {
+        Self {
+            column_name,
+            column_id: None,
+            column_values: StringSet::new(),
+            partition_value_ids: BTreeSet::new(),
+        }
+    }
+}
+
+impl<'a> Visitor for ValueVisitor<'a> {
+    fn pre_visit_partition(&mut self, partition: &Partition) -> Result<()> {
+        self.partition_value_ids.clear();
+
+        self.column_id = Some(
+            partition
+                .dictionary
+                .lookup_value(self.column_name)
+                .context(ColumnNameNotFoundInDictionary {
+                    column_name: self.column_name,
+                    partition: &partition.key,
+                })?,
+        );
+
+        Ok(())
+    }
+
+    fn visit_column(
+        &mut self,
+        table: &Table,
+        column_id: u32,
+        column: &Column,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        if Some(column_id) != self.column_id {
+            return Ok(());
+        }
+
+        match column {
+            Column::Tag(column, _) => {
+                // the values with rows within the timestamp range, if
+                // any, which the summary of the tag usually tells
+                // without reading its rows
+                table.tag_values_matching_predicate(
+                    column_id,
+                    column,
+                    filter.partition_predicate(),
+                    &mut self.partition_value_ids,
+                )?;
+                Ok(())
+            }
+            _ => UnsupportedColumnTypeForListingValues {
+                column_name: self.column_name,
+            }
+            .fail(),
+        }
+    }
+
+    fn post_visit_partition(&mut self, partition: &Partition) -> Result<()> {
+        // convert all the partition's column_ids to Strings
+        for &value_id in &self.partition_value_ids {
+            let value = partition.dictionary.lookup_id(value_id).context(
+                ColumnValueIdNotFoundInDictionary {
+                    value_id,
+                    partition: &partition.key,
+                },
+            )?;
+
+            if !self.column_values.contains(value) {
+                self.column_values.insert(value.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// return all column values for the specified column in this
+/// database, while applying the timestamp range and predicate
+///
+/// Each table's plan is paired with all the values the column has in
+/// the table, so that the plans of tables whose values have all been
+/// found in earlier tables are not run.
+struct ValuePredVisitor<'a> {
+    column_name: &'a str,
+    plans: Vec<ChunkStringSetPlan>,
+}
+
+impl<'a> ValuePredVisitor<'a> {
+    fn new(column_name: &'a str) -> Self {
+        Self {
+            column_name,
+            plans: Vec::new(),
+        }
+    }
+}
+
+impl<'a> Visitor for ValuePredVisitor<'a> {
+    // TODO try and rule out entire tables based on the same critera
+    // as explained on NamePredVisitor
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        // skip table entirely if there are no rows that fall in the timestamp
+        if !table.could_match_predicate(filter.partition_predicate())? {
+            return Ok(());
+        }
+
+        // and if it has no values for the column
+        let summary = partition
+            .dictionary
+            .id(self.column_name)
+            .and_then(|column_id| table.tag_summaries.get(&column_id));
+        let summary = match summary {
+            Some(summary) => summary,
+            None => return Ok(()),
+        };
+
+        let possible_values = summary
+            .value_times
+            .keys()
+            .map(|&value_id| {
+                partition
+                    .dictionary
+                    .lookup_id(value_id)
+                    .map(ToString::to_string)
+                    .context(ColumnValueIdNotFoundInDictionary {
+                        value_id,
+                        partition: &partition.key,
+                    })
+            })
+            .collect::<Result<StringSet>>()?;
+
+        self.plans.push(ChunkStringSetPlan {
+            plan: table.tag_values_plan(
+                self.column_name,
+                filter.partition_predicate(),
+                partition,
+            )?,
+            possible_values,
+        });
+        Ok(())
+    }
+}
+
+/// Gathers the rows of the tables that pass the specified predicate
+/// from every chunk, to return DataFusion plans to calculate which
+/// series pass it from the deduplicated rows of each table.
+#[derive(Debug, Default)]
+struct SeriesVisitor {
+    tables: BTreeMap<String, TableChunks>,
+}
+
+/// The rows of a table in each chunk the visitor visited it in
+#[derive(Debug, Default)]
+struct TableChunks {
+    /// The rows of the table in each chunk, in the order they were
+    /// written
+    batches: Vec<RecordBatch>,
+
+    /// The names of the tag columns of the table
+    tag_columns: BTreeSet<Arc<String>>,
+
+    /// The names of the field columns of the table the predicate
+    /// selects
+    field_columns: BTreeSet<Arc<String>>,
+}
+
+impl SeriesVisitor {
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the plans for each table visited: one for each of its
+    /// chunks that does not overlap another, and one for each group
+    /// of overlapping chunks, over their rows deduplicated so that the
+    /// last value written of each field of a series and timestamp
+    /// wins. Each plan has the columns in `prefix_columns`, if any,
+    /// first of its tag columns.
+    fn plans(
+        self,
+        filter: &PartitionTableFilter,
+        prefix_columns: Option<&[String]>,
+    ) -> Result<Vec<SeriesSetPlan>> {
+        let mut plans = vec![];
+        for (table_name, chunks) in self.tables {
+            let tag_names: BTreeSet<_> = chunks.tag_columns.iter().map(|c| c.to_string()).collect();
+            let groups = dedup::deduplicate(chunks.batches, &tag_names)
+                .context(Deduplicating { table: &table_name })?;
+            let table_name = Arc::new(table_name);
+
+            for data in groups {
+                let schema = data.schema();
+                let has_column = |column_name: &str| schema.index_of(column_name).is_ok();
+
+                // the expression of the predicate only depends on the
+                // names of the columns, so the predicate compiled for
+                // any partition will do
+                let filter = filter.partition_predicate().filter_expr(has_column);
+
+                let mut tag_columns: Vec<_> = chunks
+                    .tag_columns
+                    .iter()
+                    .filter(|c| has_column(c))
+                    .cloned()
+                    .collect();
+                if let Some(prefix_columns) = prefix_columns {
+                    tag_columns = table::reorder_prefix(prefix_columns, tag_columns)?;
+                }
+                let field_columns = chunks
+                    .field_columns
+                    .iter()
+                    .filter(|c| has_column(c))
+                    .cloned()
+                    .collect();
+
+                plans.push(table::series_set_plan_for_data(
+                    Arc::clone(&table_name),
+                    data,
+                    filter,
+                    tag_columns,
+                    field_columns,
+                )?);
+            }
+        }
+        Ok(plans)
+    }
+}
+
+impl Visitor for SeriesVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        let table_name =
+            partition
+                .dictionary
+                .lookup_id(table.id)
+                .context(TableIdNotFoundInDictionary {
+                    table: table.id,
+                    partition: &partition.key,
+                })?;
+        let (tag_columns, field_columns) =
+            table.tag_and_field_column_names(filter.partition_predicate(), partition)?;
+        let data = table.all_to_arrow(partition)?;
+
+        let chunks = self.tables.entry(table_name.to_string()).or_default();
+        chunks.tag_columns.extend(tag_columns);
+        chunks.field_columns.extend(field_columns);
+        chunks.batches.push(data);
+
+        Ok(())
+    }
+}
+
+/// Collect the pre-aggregated series that pass the specified
+/// predicate. `series` is `None` if any visited table can't answer
+/// the predicate from its pre-aggregates.
+struct PreAggregateVisitor {
+    series: Option<Vec<BucketedSeries>>,
+
+    /// The time range of each table visited in each chunk
+    time_ranges: BTreeMap<String, Vec<Option<(i64, i64)>>>,
+}
+
+impl PreAggregateVisitor {
+    fn new() -> Self {
+        Self {
+            series: Some(Vec::new()),
+            time_ranges: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the series visited, or `None` if they can't answer the
+    /// predicate, or the chunks of a table overlap, so they may have
+    /// duplicate rows
+    fn into_series(self) -> Option<Vec<BucketedSeries>> {
+        let chunks_overlap = self
+            .time_ranges
+            .values()
+            .any(|ranges| dedup::overlapping_groups(ranges).len() < ranges.len());
+        if chunks_overlap {
+            return None;
+        }
+        self.series
+    }
+}
+
+impl Visitor for PreAggregateVisitor {
+    fn pre_visit_table(
+        &mut self,
+        table: &Table,
+        partition: &Partition,
+        filter: &mut PartitionTableFilter,
+    ) -> Result<()> {
+        if self.series.is_none() {
+            return Ok(());
+        }
+
+        let table_name =
+            partition
+                .dictionary
+                .lookup_id(table.id)
+                .context(TableIdNotFoundInDictionary {
+                    table: table.id,
+                    partition: &partition.key,
+                })?;
+        self.time_ranges
+            .entry(table_name.to_string())
+            .or_default()
+            .push(partition.table_time_range(table));
+
+        let table_series =
+            pre_aggregate::bucketed_series(table, partition, filter.partition_predicate());
+        self.series = match (self.series.take(), table_series) {
+            (Some(mut series), Some(table_series)) => {
+                series.extend(table_series);
+                Some(series)
+            }
+            _ => None,
+        };
+
+        Ok(())
+    }
+}
+
+/// The strftime format of the partition keys of databases whose rules
+/// have no partition template, which partitions their data by hour
+pub const DEFAULT_PARTITION_FORMAT: &str = "%Y-%m-%dT%H";
+
+/// Applies the timestamp `bounds` of a database to `lines` written at
+/// `now`: depending on the policy of the bounds, lines outside them
+/// are either rejected, clamped to the bounds or written as they are
+fn bound_timestamps<'a, 'b>(
+    database: &str,
+    lines: &'a [ParsedLine<'b>],
+    bounds: &TimestampBounds,
+    now: i64,
+) -> Result<Cow<'a, [ParsedLine<'b>]>> {
+    let (min, max) = bounds.range(now);
+    let out_of_bounds = |timestamp: &i64| *timestamp < min || *timestamp > max;
+
+    let timestamp = match lines
+        .iter()
+        .filter_map(|line| line.timestamp)
+        .find(out_of_bounds)
+    {
+        Some(timestamp) => timestamp,
+        None => return Ok(Cow::Borrowed(lines)),
+    };
+
+    match bounds.policy {
+        TimestampPolicy::Accept => Ok(Cow::Borrowed(lines)),
+        TimestampPolicy::Reject => TimestampOutOfBounds {
+            database,
+            timestamp,
+            min,
+            max,
+        }
+        .fail(),
+        TimestampPolicy::Clamp => Ok(Cow::Owned(
+            lines
+                .iter()
+                .cloned()
+                .map(|mut line| {
+                    line.timestamp = line.timestamp.map(|t| t.max(min).min(max));
+                    line
+                })
+                .collect(),
+        )),
+    }
+}
+
+/// Rejects `lines` if any of them has a float field that is NaN or
+/// infinite, unless the `policy` of the database is to store them
+fn check_non_finite_floats(
+    database: &str,
+    lines: &[ParsedLine<'_>],
+    policy: NonFiniteFloatPolicy,
+) -> Result<()> {
+    if policy == NonFiniteFloatPolicy::Store {
+        return Ok(());
+    }
+
+    for line in lines {
+        for (field, value) in &line.field_set {
+            if let FieldValue::F64(value) = value {
+                ensure!(
+                    value.is_finite(),
+                    NonFiniteFloat {
+                        database,
+                        measurement: line.series.measurement.as_str(),
+                        field: field.as_str(),
+                        value: *value,
+                    }
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The most rows without a timestamp a `MissingTimestamps` error lists
+const MAX_REPORTED_ROWS: usize = 10;
+
+/// Rejects a batch with rows that have no timestamp, since the time
+/// column of a chunk can't have NULLs. The error names the first table
+/// with such rows and their positions in its batch.
+fn check_timestamps(database: &str, batch: &wb::WriteBufferBatch<'_>) -> Result<()> {
+    let table_batches = batch
+        .entries()
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.table_batches())
+        .flatten();
+
+    for table_batch in table_batches {
+        let missing: Vec<_> = table_batch
+            .rows()
+            .into_iter()
+            .flatten()
+            .enumerate()
+            .filter(|(_, row)| partition::row_time(row).is_none())
+            .map(|(index, _)| index)
+            .collect();
+
+        ensure!(
+            missing.is_empty(),
+            MissingTimestamps {
+                database,
+                table: table_batch.name().unwrap_or_default(),
+                count: missing.len(),
+                rows: missing
+                    .into_iter()
+                    .take(MAX_REPORTED_ROWS)
+                    .collect::<Vec<_>>(),
+            }
+        );
+    }
+
+    Ok(())
+}
+
+// partition_key returns the partition key for the given line. The key will be the prefix of a
+// partition name (multiple partitions can exist for each key). It uses the partition template
+// of the database rules to construct this key, falling back to partitioning by hour if the
+// rules have no template. Lines without a timestamp are partitioned as if written at `default_time`.
+pub fn partition_key(
+    line: &ParsedLine<'_>,
+    rules: &DatabaseRules,
+    default_time: &DateTime<Utc>,
+) -> String {
+    if rules.partition_template.is_empty() {
+        let dt = line
+            .timestamp
+            .map_or(*default_time, |ts| Utc.timestamp_nanos(ts));
+        return dt.format(DEFAULT_PARTITION_FORMAT).to_string();
+    }
+
+    rules
+        .partition_key(line, default_time)
+        .expect("partition template was validated when the rules were set")
+}
+
+struct ArrowTable {
+    name: String,
+    schema: Arc<ArrowSchema>,
+    data: Vec<RecordBatch>,
+}
+
+/// A SQL query planned for execution against a `Db`
+struct PlannedQuery {
+    /// The context in which the tables of the query are registered
+    ctx: ExecutionContext,
+    /// Descriptions of the table data loaded for the query
+    table_scans: Vec<String>,
+    initial_logical_plan: LogicalPlan,
+    logical_plan: LogicalPlan,
+    physical_plan: Arc<dyn ExecutionPlan>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::datafusion::{
+        logical_plan::{self, Literal},
+        physical_plan::functions::BuiltinScalarFunction,
+        scalar::ScalarValue,
+    };
+    use logical_plan::{Expr, Operator};
+    use storage::{
+        exec::fieldlist::{Field, FieldList},
+        exec::{
+            seriesset::{Error as SeriesSetError, SeriesSet},
+            stats::QueryStats,
+            window_aggregate::{Aggregate, Fill},
+            Executor,
+        },
+        predicate::PredicateBuilder,
+        window, Database,
+    };
+
+    use arrow::{
+        array::{Array, StringArray},
+        datatypes::DataType,
+        util::pretty::pretty_format_batches,
+    };
+    use data_types::{
+        database_rules::{DownsamplingAggregate, PartitionTemplate, TemplatePart},
+        partition_metadata::{ChunkState, ChunkStorage},
+    };
+    use futures::TryStreamExt;
+    use influxdb_line_protocol::parse_lines;
+    use test_helpers::{str_pair_vec_to_vec, str_vec_to_arc_vec};
+    use tokio::sync::mpsc;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    fn to_set(v: &[&str]) -> BTreeSet<String> {
+        v.iter().map(|s| s.to_string()).collect::<BTreeSet<_>>()
+    }
+
+    // Abstract a bit of boilerplate around table assertions and improve the failure output.
+    // The default failure message uses Debug formatting, which prints newlines as `\n`.
+    // This prints the pretty_format_batches using Display so it's easier to read the tables.
+    fn assert_table_eq(table: &str, partitions: &[arrow::record_batch::RecordBatch]) {
+        let res = pretty_format_batches(partitions).unwrap();
+        assert_eq!(table, res, "\n\nleft:\n\n{}\nright:\n\n{}", table, res);
+    }
+
+    // query the table names, with optional range predicate
+    async fn table_names(db: &Db, predicate: Predicate) -> Result<StringSet> {
+        let plan = db.table_names(predicate).await?.plan;
+        let executor = Executor::default();
+        let s = executor.to_string_set(plan).await?;
+
+        // unwrap it (for easy test comparisons)
+        let s = Arc::try_unwrap(s).expect("only one reference to the set in the test");
+        Ok(s)
+    }
+
+    #[tokio::test]
+    async fn partition_line_protocol() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let db = Db::try_with_wal("mydb", &mut dir).await?;
+        assert_eq!(db.partition_line_protocol("1970-01-01T00").await?, "");
+
+        let lines: Vec<_> = parse_lines(
+            "cpu,region=west user=23.2 10\ndisk,region=east bytes=99i 11\ncpu user=1 3600000000000",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let lp = db.partition_line_protocol("1970-01-01T00").await?;
+        let mut written: Vec<_> = lp.lines().collect();
+        written.sort_unstable();
+        assert_eq!(
+            written,
+            vec![
+                "cpu,region=west user=23.2 10",
+                "disk,region=east bytes=99i 11"
+            ]
+        );
+        assert_eq!(
+            db.partition_line_protocol("1970-01-01T01").await?,
+            "cpu user=1 3600000000000\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_series() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("delete_db", &mut dir).await?;
+
+        let write = |lp: &'static str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let db = &db;
+            async move { db.write_lines(&lines).await }
+        };
+        write("cpu,host=a usage=1 10\ncpu,host=b usage=2 20\nmem,host=a free=3i 30").await?;
+
+        // the closed chunk is persisted, and loses its persisted state
+        // when rewritten
+        db.close_chunk("1970-01-01T00", 0).await?;
+        db.persist_chunk("1970-01-01T00", 0).await?;
+        write("cpu,host=a usage=4 40").await?;
+
+        let series_keys: BTreeSet<_> = vec!["cpu,host=a".to_string()].into_iter().collect();
+        assert_eq!(db.delete_series("1970-01-01T00", &series_keys).await?, 2);
+        assert_eq!(db.delete_series("1970-01-01T00", &series_keys).await?, 0);
+        assert!(db.partitions.read().await.iter().all(|p| !p.persisted));
+
+        let expected = r#"+------+-------+------+
+| host | usage | time |
++------+-------+------+
+| b    | 2     | 20   |
++------+-------+------+
+"#;
+        let results = db.query("SELECT host, usage, time FROM cpu").await?;
+        assert_table_eq(expected, &results);
+
+        // later writes of the series are kept, and the deleted rows are
+        // not restored from the WAL
+        write("cpu,host=a usage=5 50").await?;
+        drop(db);
+        let db = Db::restore_from_wal(&dir).await?;
+        let expected = r#"+------+-------+------+
+| host | usage | time |
++------+-------+------+
+| b    | 2     | 20   |
+| a    | 5     | 50   |
++------+-------+------+
+"#;
+        let results = db
+            .query("SELECT host, usage, time FROM cpu ORDER BY time")
+            .await?;
+        assert_table_eq(expected, &results);
+        let results = db.query("SELECT host, free, time FROM mem").await?;
+        assert_eq!(results.iter().map(|b| b.num_rows()).sum::<usize>(), 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn delete_series_from_evicted_chunks() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("delete_evicted_db", &mut dir).await?;
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10\ncpu,host=b usage=2 20")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
+
+        db.set_rules(DatabaseRules {
+            persist_idle_seconds: Some(1),
+            ..Default::default()
+        })
+        .await?;
+        let now = Utc::now().timestamp_nanos() + 2_000_000_000;
+        assert_eq!(db.persist_idle(now).await?, 1);
+
+        let series_keys: BTreeSet<_> = vec!["cpu,host=b".to_string()].into_iter().collect();
+        assert_eq!(db.delete_series("1970-01-01T00", &series_keys).await?, 1);
+
+        // the evicted chunk is replaced by a new one without the rows,
+        // whose files replace its own
+        let chunks = db.chunks().await;
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.id, c.storage, c.row_count))
+                .collect::<Vec<_>>(),
+            vec![(1, ChunkStorage::ObjectStore, 1)]
+        );
+        let chunks_dir = dir.join(PERSISTED_CHUNKS_DIR_NAME).join("1970-01-01T00");
+        assert!(!chunks_dir.join("0").exists());
+        assert!(chunks_dir.join("1").join("cpu.parquet").exists());
+
+        drop(db);
+        let db = Db::restore_from_wal(&dir).await?;
+        assert_eq!(
+            db.partition_line_protocol("1970-01-01T00").await?,
+            "cpu,host=a usage=1 10\n"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_table_names() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let db = Db::try_with_wal("mydb", &mut dir).await?;
+
+        // no tables initially
+        assert_eq!(
+            table_names(&db, Predicate::default()).await?,
+            BTreeSet::new()
+        );
+
+        // write two different tables
+        let lines: Vec<_> =
+            parse_lines("cpu,region=west user=23.2 10\ndisk,region=east bytes=99i 11")
+                .map(|l| l.unwrap())
+                .collect();
+        db.write_lines(&lines).await?;
+
+        // Now, we should see the two tables
+        assert_eq!(
+            table_names(&db, Predicate::default()).await?,
+            to_set(&["cpu", "disk"])
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_table_names_timestamps() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let db = Db::try_with_wal("mydb", &mut dir).await?;
+
+        // write two different tables at the following times:
+        // cpu: 100 and 150
+        // disk: 200
+        let lines: Vec<_> =
+            parse_lines("cpu,region=west user=23.2 100\ncpu,region=west user=21.0 150\ndisk,region=east bytes=99i 200")
+                .map(|l| l.unwrap())
+                .collect();
+        db.write_lines(&lines).await?;
+
+        // Cover all times
+        let predicate = PredicateBuilder::default().timestamp_range(0, 201).build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu", "disk"]));
+
+        // Right before disk
+        let predicate = PredicateBuilder::default().timestamp_range(0, 200).build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu"]));
+
+        // only one point of cpu
+        let predicate = PredicateBuilder::default().timestamp_range(50, 101).build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&["cpu"]));
+
+        // between the points of cpu
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(101, 150)
+            .build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&[]));
+
+        // no ranges
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(250, 350)
+            .build();
+        assert_eq!(table_names(&db, predicate).await?, to_set(&[]));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn missing_tags_are_null() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let db = Db::try_with_wal("mydb", &mut dir).await?;
+
+        // Note the `region` tag is introduced in the second line, so
+        // the values in prior rows for the region column are
+        // null. Likewise the `core` tag is introduced in the third
+        // line so the prior columns are null
+        let lines: Vec<_> = parse_lines(
+            "cpu,region=west user=23.2 10\n\
+                         cpu, user=10.0 11\n\
+                         cpu,core=one user=10.0 11\n",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let partitions = db.table_to_arrow("cpu", &["region", "core"]).await?;
+        let columns = partitions[0].columns();
+
+        assert_eq!(
+            2,
+            columns.len(),
+            "Got only two columns in partiton: {:#?}",
+            columns
+        );
+
+        let region_col = columns[0]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .expect("Get region column as a string");
+
+        assert_eq!(region_col.len(), 3);
+        assert_eq!(region_col.value(0), "west", "region_col: {:?}", region_col);
+        assert!(!region_col.is_null(0), "is_null(0): {:?}", region_col);
+        assert!(region_col.is_null(1), "is_null(1): {:?}", region_col);
+        assert!(region_col.is_null(2), "is_null(1): {:?}", region_col);
+
+        let host_col = columns[1]
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .expect("Get host column as a string");
+
+        assert_eq!(host_col.len(), 3);
+        assert!(host_col.is_null(0), "is_null(0): {:?}", host_col);
+        assert!(host_col.is_null(1), "is_null(1): {:?}", host_col);
+        assert!(!host_col.is_null(2), "is_null(2): {:?}", host_col);
+        assert_eq!(host_col.value(2), "one", "host_col: {:?}", host_col);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_data_and_recover() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let expected_cpu_table = r#"+--------+------+------+-------+-------------+------+------+---------+-----------+
+| region | host | user | other | str         | b    | time | new_tag | new_field |
++--------+------+------+-------+-------------+------+------+---------+-----------+
+| west   | A    | 23.2 | 1     | some string | true | 10   |         |           |
+| west   | B    | 23.1 |       |             |      | 15   |         |           |
+|        | A    |      |       |             |      | 20   | foo     | 15.1      |
++--------+------+------+-------+-------------+------+------+---------+-----------+
+"#;
+        let expected_mem_table = r#"+--------+------+-------+------+
+| region | host | val   | time |
++--------+------+-------+------+
+| east   | C    | 23432 | 10   |
++--------+------+-------+------+
+"#;
+        let expected_disk_table = r#"+--------+------+----------+--------------+------+
+| region | host | bytes    | used_percent | time |
++--------+------+----------+--------------+------+
+| west   | A    | 23432323 | 76.2         | 10   |
++--------+------+----------+--------------+------+
+"#;
+
+        let cpu_columns = &[
+            "region",
+            "host",
+            "user",
+            "other",
+            "str",
+            "b",
+            "time",
+            "new_tag",
+            "new_field",
+        ];
+        let mem_columns = &["region", "host", "val", "time"];
+        let disk_columns = &["region", "host", "bytes", "used_percent", "time"];
+
+        {
+            let db = Db::try_with_wal("mydb", &mut dir).await?;
+            let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i,str=\"some string\",b=true 10\ndisk,region=west,host=A bytes=23432323i,used_percent=76.2 10").map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("cpu,region=west,host=B user=23.1 15")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("cpu,host=A,new_tag=foo new_field=15.1 20")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("mem,region=east,host=C val=23432 10")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+
+            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+
+            let partitions = db.table_to_arrow("mem", mem_columns).await?;
+            assert_table_eq(expected_mem_table, &partitions);
+
+            let partitions = db.table_to_arrow("disk", disk_columns).await?;
+            assert_table_eq(expected_disk_table, &partitions);
+        }
+
+        // check that it recovers from the wal
+        {
+            let db = Db::restore_from_wal(&dir).await?;
+
+            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+
+            let partitions = db.table_to_arrow("mem", mem_columns).await?;
+            assert_table_eq(expected_mem_table, &partitions);
+
+            let partitions = db.table_to_arrow("disk", disk_columns).await?;
+            assert_table_eq(expected_disk_table, &partitions);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn write_and_query() -> Result {
+        let db = Db::new("foo");
+
+        let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i 10")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
+
+        let results = db.query("select * from cpu").await?;
+
+        let expected_cpu_table = r#"+------+-------+--------+------+------+
+| host | other | region | time | user |
++------+-------+--------+------+------+
+| A    | 1     | west   | 10   | 23.2 |
++------+-------+--------+------+------+
+"#;
+
+        assert_table_eq(expected_cpu_table, &results);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_field_math() -> Result {
+        let db = Db::new("foo");
+
+        let lines: Vec<_> = parse_lines(
+            "cpu,host=A usage_user=20.5,usage_system=10i 10\n\
+             cpu,host=B usage_user=3.5 20",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let results = db
+            .query(
+                "select host, usage_user + usage_system as total, usage_user * 2 as doubled \
+                 from cpu order by host",
+            )
+            .await?;
+
+        let expected_cpu_table = r#"+------+-------+---------+
+| host | total | doubled |
++------+-------+---------+
+| A    | 30.5  | 41      |
+| B    |       | 7       |
++------+-------+---------+
+"#;
+
+        assert_table_eq(expected_cpu_table, &results);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_histogram_quantile() -> Result {
+        let db = Db::new("foo");
+
+        let lines: Vec<_> = parse_lines(
+            "http,path=/a le_0.5=4,le_1=8,le_+Inf=8,sum=4.1,count=8 10\n\
+             http,path=/b le_0.5=0,le_1=2,le_+Inf=4,sum=9.9,count=4 10",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let results = db
+            .query(
+                "select path, histogram_quantile(0.5) as p50, histogram_quantile(0.75) as p75 \
+                 from http group by path order by path",
+            )
+            .await?;
+
+        let expected = r#"+------+-----+------+
+| path | p50 | p75  |
++------+-----+------+
+| /a   | 0.5 | 0.75 |
+| /b   | 1   | 1    |
++------+-----+------+
+"#;
+
+        assert_table_eq(expected, &results);
+
+        let err = db
+            .query("select histogram_quantile(2) from http")
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error in Histogram: histogram_quantile(2) expects a quantile between 0 and 1"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn query_deduplicates_overlapping_chunks() -> Result {
+        let db = Db::new("foo");
+
+        let write = |lp: &'static str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let db = &db;
+            async move { db.write_lines(&lines).await }
+        };
+
+        write("cpu,host=A user=1 10\ncpu,host=B user=2 10").await?;
+        db.close_chunk("1970-01-01T00", 0).await?;
+        write("cpu,host=A user=3 10\ncpu,host=A user=4 20").await?;
+
+        let results = db.query("select * from cpu order by host, time").await?;
+
+        let expected_cpu_table = r#"+------+------+------+
+| host | time | user |
++------+------+------+
+| A    | 10   | 3    |
+| A    | 20   | 4    |
+| B    | 10   | 2    |
++------+------+------+
+"#;
+
+        assert_table_eq(expected_cpu_table, &results);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn explain_query() -> Result {
+        let db = Db::new("foo");
+
+        let lines: Vec<_> = parse_lines("cpu,host=A user=23.2 10\ncpu,host=B user=10.1 20")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
+
+        let query = "select user from cpu where host = 'A'";
+
+        // returns the (plan_type, plan) rows of the explain output
+        async fn explain(db: &Db, query: &str) -> Result<Vec<(String, String)>> {
+            let results = db.query(query).await?;
+            assert_eq!(results.len(), 1);
+
+            let column = |index| {
+                results[0]
+                    .column(index)
+                    .as_any()
+                    .downcast_ref::<StringArray>()
+                    .unwrap()
+            };
+            Ok((0..results[0].num_rows())
+                .map(|row| (column(0).value(row).into(), column(1).value(row).into()))
+                .collect())
+        }
+
+        let plans = explain(&db, &format!("EXPLAIN {}", query)).await?;
+        let plan_types: Vec<_> = plans.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(plan_types, vec!["tables", "logical_plan", "physical_plan"]);
+        assert_eq!(plans[0].1, "cpu: 1 chunks scanned, 0 chunks pruned, 2 rows");
+        assert!(plans[1].1.contains("Filter"), "{}", plans[1].1);
+        assert!(plans[1].1.contains("TableScan: cpu"), "{}", plans[1].1);
+
+        let plans = explain(&db, &format!("explain analyze verbose {}", query)).await?;
+        let plan_types: Vec<_> = plans.iter().map(|(t, _)| t.as_str()).collect();
+        assert_eq!(
+            plan_types,
+            vec![
+                "tables",
+                "initial_logical_plan",
+                "logical_plan",
+                "physical_plan",
+                "execution"
+            ]
+        );
+        assert!(
+            plans[4]
+                .1
+                .starts_with("output_rows=1, output_batches=1, elapsed="),
+            "{}",
+            plans[4].1
+        );
+
+        // followed by what each operator produced, the scan of both
+        // rows below the filter which produced one of them
+        let operators: Vec<_> = plans[4].1.lines().skip(1).collect();
+        let operator = |name: &str| {
+            operators
+                .iter()
+                .find(|line| line.trim_start().starts_with(name))
+                .unwrap_or_else(|| panic!("no {} in {}", name, plans[4].1))
+        };
+        assert!(operator("FilterExec:").contains("output_rows=1,"));
+        assert!(operator("MemoryExec:").contains("output_rows=2,"));
+        let indent = |line: &str| line.len() - line.trim_start().len();
+        assert!(indent(operator("MemoryExec:")) > indent(operator("FilterExec:")));
+        assert_eq!(indent(operators[0]), 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn recover_partial_entries() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let expected_cpu_table = r#"+--------+------+------+-------+-------------+------+------+---------+-----------+
+| region | host | user | other | str         | b    | time | new_tag | new_field |
++--------+------+------+-------+-------------+------+------+---------+-----------+
+| west   | A    | 23.2 | 1     | some string | true | 10   |         |           |
+| west   | B    | 23.1 |       |             |      | 15   |         |           |
+|        | A    |      |       |             |      | 20   | foo     | 15.1      |
++--------+------+------+-------+-------------+------+------+---------+-----------+
+"#;
+
+        let expected_mem_table = r#"+--------+------+-------+------+
+| region | host | val   | time |
++--------+------+-------+------+
+| east   | C    | 23432 | 10   |
++--------+------+-------+------+
+"#;
+        let expected_disk_table = r#"+--------+------+----------+--------------+------+
+| region | host | bytes    | used_percent | time |
++--------+------+----------+--------------+------+
+| west   | A    | 23432323 | 76.2         | 10   |
++--------+------+----------+--------------+------+
+"#;
+
+        let cpu_columns = &[
+            "region",
+            "host",
+            "user",
+            "other",
+            "str",
             "b",
             "time",
             "new_tag",
             "new_field",
         ];
-        let mem_columns = &["region", "host", "val", "time"];
-        let disk_columns = &["region", "host", "bytes", "used_percent", "time"];
+        let mem_columns = &["region", "host", "val", "time"];
+        let disk_columns = &["region", "host", "bytes", "used_percent", "time"];
+        {
+            let db = Db::try_with_wal("mydb", &mut dir).await?;
+            let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i,str=\"some string\",b=true 10\ndisk,region=west,host=A bytes=23432323i,used_percent=76.2 10").map(|l| l.unwrap()).collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("cpu,region=west,host=B user=23.1 15")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("cpu,host=A,new_tag=foo new_field=15.1 20")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+            let lines: Vec<_> = parse_lines("mem,region=east,host=C val=23432 10")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+
+            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
+            assert_table_eq(expected_cpu_table, &partitions);
+
+            let partitions = db.table_to_arrow("mem", mem_columns).await?;
+            assert_table_eq(expected_mem_table, &partitions);
+
+            let partitions = db.table_to_arrow("disk", disk_columns).await?;
+            assert_table_eq(expected_disk_table, &partitions);
+        }
+
+        // check that it can recover from the last 2 self-describing entries of the wal
+        {
+            let name = dir.iter().last().unwrap().to_str().unwrap().to_string();
+
+            let wal_builder = WalBuilder::new(&dir);
+
+            let wal_entries = wal_builder
+                .entries()
+                .context(LoadingWal { database: &name })?;
+
+            // Skip the first 2 entries in the wal; only restore from the last 2
+            let wal_entries = wal_entries.skip(2);
+
+            let (partitions, _stats) = crate::partition::restore_partitions_from_wal(wal_entries)?;
+
+            let db = Db {
+                name,
+                partitions: RwLock::new(partitions),
+                wal_details: None,
+            };
+
+            // some cpu
+            let smaller_cpu_table = r#"+------+---------+-----------+------+
+| host | new_tag | new_field | time |
++------+---------+-----------+------+
+| A    | foo     | 15.1      | 20   |
++------+---------+-----------+------+
+"#;
+            let smaller_cpu_columns = &["host", "new_tag", "new_field", "time"];
+            let partitions = db.table_to_arrow("cpu", smaller_cpu_columns).await?;
+            assert_table_eq(smaller_cpu_table, &partitions);
+
+            // all of mem
+            let partitions = db.table_to_arrow("mem", mem_columns).await?;
+            assert_table_eq(expected_mem_table, &partitions);
+
+            // no disk
+            let nonexistent_table = db.table_to_arrow("disk", disk_columns).await;
+            assert!(nonexistent_table.is_err());
+            let actual_message = format!("{:?}", nonexistent_table);
+            let expected_message = "TableNameNotFoundInDictionary";
+            assert!(
+                actual_message.contains(expected_message),
+                "Did not find '{}' in '{}'",
+                expected_message,
+                actual_message
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn db_partition_key() -> Result {
+        let partition_keys: Vec<_> = parse_lines(
+            "\
+cpu user=23.2 1600107710000000000
+disk bytes=23432323i 1600136510000000000",
+        )
+        .map(|line| partition_key(&line.unwrap(), &DatabaseRules::default(), &Utc::now()))
+        .collect();
+
+        assert_eq!(partition_keys, vec!["2020-09-14T18", "2020-09-15T02"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn db_partition_template() -> Result {
+        let db = Db::new("mydb");
+        db.set_rules(DatabaseRules {
+            partition_template: PartitionTemplate::new(vec![
+                TemplatePart::TimeFormat("%Y-%m-%d".to_string()),
+                TemplatePart::Column("region".to_string()),
+            ]),
+            ..Default::default()
+        })
+        .await?;
+
+        let lines: Vec<_> = parse_lines(
+            "\
+cpu,region=west user=23.2 1600107710000000000
+cpu,region=east user=21.0 1600107710000000000
+disk,region=west bytes=23432323i 1600136510000000000
+disk bytes=1i 1600136510000000000",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let mut partition_keys = db.partition_keys().await;
+        partition_keys.sort();
+        assert_eq!(
+            partition_keys,
+            vec![
+                "2020-09-14-region_east",
+                "2020-09-14-region_west",
+                "2020-09-15-",
+                "2020-09-15-region_west",
+            ]
+        );
+
+        // unsupported templates are rejected
+        let err = db
+            .set_rules(DatabaseRules {
+                partition_template: PartitionTemplate::new(vec![TemplatePart::RegexCapture(
+                    serde_json::from_str(r#"{"column": "region", "regex": "(.*)"}"#)?,
+                )]),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::UnsupportedRules { .. }));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_column_names() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
+
+        let lp_data = "h2o,state=CA,city=LA,county=LA temp=70.4 100\n\
+                       h2o,state=MA,city=Boston,county=Suffolk temp=72.4 250\n\
+                       o2,state=MA,city=Boston temp=50.4 200\n\
+                       o2,state=CA temp=79.0 300\n\
+                       o2,state=NY temp=60.8 400\n\
+                       o2,state=NY,city=NYC temp=61.0 500\n\
+                       o2,state=NY,city=NYC,borough=Brooklyn temp=61.0 600\n";
+
+        let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        #[derive(Debug)]
+        struct TestCase<'a> {
+            description: &'a str,
+            predicate: Predicate,
+            expected_tag_keys: Result<Vec<&'a str>>,
+        };
+
+        let test_cases = vec![
+            TestCase {
+                description: "No predicates",
+                predicate: PredicateBuilder::default().build(),
+                expected_tag_keys: Ok(vec!["borough", "city", "county", "state"]),
+            },
+            TestCase {
+                description: "Restrictions: timestamp",
+                predicate: PredicateBuilder::default()
+                    .timestamp_range(150, 201)
+                    .build(),
+                expected_tag_keys: Ok(vec!["city", "state"]),
+            },
+            TestCase {
+                description: "Restrictions: predicate",
+                predicate: PredicateBuilder::default()
+                    .add_expr(make_column_eq_expr("state", "MA")) // state=MA
+                    .build(),
+                expected_tag_keys: Ok(vec!["city", "county", "state"]),
+            },
+            TestCase {
+                description: "Restrictions: timestamp and predicate",
+                predicate: PredicateBuilder::default()
+                    .timestamp_range(150, 201)
+                    .add_expr(make_column_eq_expr("state", "MA")) // state=MA
+                    .build(),
+                expected_tag_keys: Ok(vec!["city", "state"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name",
+                predicate: PredicateBuilder::default().table("o2").build(),
+                expected_tag_keys: Ok(vec!["borough", "city", "state"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name and timestamp",
+                predicate: PredicateBuilder::default()
+                    .table("o2")
+                    .timestamp_range(150, 201)
+                    .build(),
+                expected_tag_keys: Ok(vec!["city", "state"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name and predicate",
+                predicate: PredicateBuilder::default()
+                    .table("o2")
+                    .add_expr(make_column_eq_expr("state", "NY")) // state=NY
+                    .build(),
+                expected_tag_keys: Ok(vec!["borough", "city", "state"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name, timestamp and predicate",
+                predicate: PredicateBuilder::default()
+                    .table("o2")
+                    .timestamp_range(1, 550)
+                    .add_expr(make_column_eq_expr("state", "NY")) // state=NY
+                    .build(),
+                expected_tag_keys: Ok(vec!["city", "state"]),
+            },
+        ];
+
+        for test_case in test_cases.into_iter() {
+            let test_case_str = format!("{:#?}", test_case);
+            println!("Running test case: {:?}", test_case);
+
+            let tag_keys_plan = db
+                .tag_column_names(test_case.predicate)
+                .await
+                .expect("Created tag_keys plan successfully")
+                .plan;
+
+            // run the execution plan (
+            let executor = Executor::default();
+            let actual_tag_keys = executor.to_string_set(tag_keys_plan).await;
+
+            let is_match = if let Ok(expected_tag_keys) = &test_case.expected_tag_keys {
+                let expected_tag_keys = to_set(expected_tag_keys);
+                if let Ok(actual_tag_keys) = &actual_tag_keys {
+                    **actual_tag_keys == expected_tag_keys
+                } else {
+                    false
+                }
+            } else if let Err(e) = &actual_tag_keys {
+                // use string compare to compare errors to avoid having to build exact errors
+                format!("{:?}", e) == format!("{:?}", test_case.expected_tag_keys)
+            } else {
+                false
+            };
+
+            assert!(
+                is_match,
+                "Mismatch\n\
+                     actual_tag_keys: \n\
+                     {:?}\n\
+                     expected_tag_keys: \n\
+                     {:?}\n\
+                     Test_case: \n\
+                     {}",
+                actual_tag_keys, test_case.expected_tag_keys, test_case_str
+            );
+        }
+
+        Ok(())
+    }
+
+    // Create a predicate of the form column_name = val
+    fn make_column_eq_expr(column_name: &str, val: &str) -> Expr {
+        make_column_expr(column_name, Operator::Eq, val)
+    }
+
+    // Create a predicate of the form column_name != val
+    fn make_column_neq_expr(column_name: &str, val: &str) -> Expr {
+        make_column_expr(column_name, Operator::NotEq, val)
+    }
+
+    // Create a predicate of the form column_name <op> val
+    fn make_column_expr(column_name: &str, op: Operator, val: &str) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(Expr::Column(column_name.into())),
+            op,
+            right: Box::new(Expr::Literal(ScalarValue::Utf8(Some(val.into())))),
+        }
+    }
+
+    // Create a predicate of the form lower(column_name) = val
+    fn make_lowered_column_eq_expr(column_name: &str, val: &str) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(Expr::ScalarFunction {
+                fun: BuiltinScalarFunction::Lower,
+                args: vec![Expr::Column(column_name.into())],
+            }),
+            op: Operator::Eq,
+            right: Box::new(Expr::Literal(ScalarValue::Utf8(Some(val.into())))),
+        }
+    }
+
+    // Create a predicate of the form "foo = "foo" (no column references, but is true)
+    fn make_no_column_expr() -> Expr {
+        let foo_literal = ScalarValue::Utf8(Some(String::from("foo")));
+
+        Expr::BinaryExpr {
+            left: Box::new(Expr::Literal(foo_literal.clone())),
+            op: Operator::Eq,
+            right: Box::new(Expr::Literal(foo_literal)),
+        }
+    }
+
+    #[tokio::test]
+    async fn list_column_names_predicate() -> Result {
+        // Demonstration test to show column names with predicate working
+
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
+
+        let lp_data = "h2o,state=CA,city=LA,county=LA temp=70.4 100\n\
+                       h2o,state=MA,city=Boston,county=Suffolk temp=72.4 250\n\
+                       o2,state=MA,city=Boston temp=50.4 200\n\
+                       o2,state=CA temp=79.0 300\n\
+                       o2,state=NY,city=NYC,borough=Brooklyn temp=60.8 400\n";
+
+        let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        // Predicate: state=MA
+        let expr = logical_plan::col("state").eq("MA".lit());
+        let predicate = PredicateBuilder::default().add_expr(expr).build();
+
+        let tag_keys_plan = db
+            .tag_column_names(predicate)
+            .await
+            .expect("Created plan successfully")
+            .plan;
+
+        // run the execution plan (
+        let executor = Executor::default();
+        let actual_tag_keys = executor
+            .to_string_set(tag_keys_plan)
+            .await
+            .expect("Execution of predicate plan");
+
+        assert_eq!(to_set(&["state", "city", "county"]), *actual_tag_keys);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_column_values() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
+
+        let lp_data = "h2o,state=CA,city=LA temp=70.4 100\n\
+                       h2o,state=MA,city=Boston temp=72.4 250\n\
+                       o2,state=MA,city=Boston temp=50.4 200\n\
+                       o2,state=CA temp=79.0 300\n\
+                       o2,state=NY temp=60.8 400\n";
+
+        let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        #[derive(Debug)]
+        struct TestCase<'a> {
+            description: &'a str,
+            column_name: &'a str,
+            predicate: Predicate,
+            expected_column_values: Result<Vec<&'a str>>,
+        };
+
+        let test_cases = vec![
+            TestCase {
+                description: "No predicates, 'state' col",
+                column_name: "state",
+                predicate: PredicateBuilder::default().build(),
+                expected_column_values: Ok(vec!["CA", "MA", "NY"]),
+            },
+            TestCase {
+                description: "No predicates, 'city' col",
+                column_name: "city",
+                predicate: PredicateBuilder::default().build(),
+                expected_column_values: Ok(vec!["Boston", "LA"]),
+            },
+            TestCase {
+                description: "Restrictions: timestamp",
+                column_name: "state",
+                predicate: PredicateBuilder::default().timestamp_range(50, 201).build(),
+                expected_column_values: Ok(vec!["CA", "MA"]),
+            },
+            TestCase {
+                description: "Restrictions: timestamp between rows",
+                column_name: "state",
+                predicate: PredicateBuilder::default()
+                    .timestamp_range(101, 200)
+                    .build(),
+                expected_column_values: Ok(vec![]),
+            },
+            TestCase {
+                description: "Restrictions: predicate",
+                column_name: "city",
+                predicate: PredicateBuilder::default()
+                    .add_expr(make_column_eq_expr("state", "MA")) // state=MA
+                    .build(),
+                expected_column_values: Ok(vec!["Boston"]),
+            },
+            TestCase {
+                description: "Restrictions: timestamp and predicate",
+                column_name: "state",
+                predicate: PredicateBuilder::default()
+                    .timestamp_range(150, 301)
+                    .add_expr(make_column_eq_expr("state", "MA")) // state=MA
+                    .build(),
+                expected_column_values: Ok(vec!["MA"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name",
+                column_name: "state",
+                predicate: PredicateBuilder::default().table("h2o").build(),
+                expected_column_values: Ok(vec!["CA", "MA"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name, with nulls",
+                column_name: "city",
+                predicate: PredicateBuilder::default().table("o2").build(),
+                expected_column_values: Ok(vec!["Boston"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name and timestamp",
+                column_name: "state",
+                predicate: PredicateBuilder::default()
+                    .table("o2")
+                    .timestamp_range(50, 201)
+                    .build(),
+                expected_column_values: Ok(vec!["MA"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name and predicate",
+                column_name: "state",
+                predicate: PredicateBuilder::default()
+                    .table("o2")
+                    .add_expr(make_column_eq_expr("state", "NY")) // state=NY
+                    .build(),
+                expected_column_values: Ok(vec!["NY"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name, timestamp and predicate",
+                column_name: "state",
+                predicate: PredicateBuilder::default()
+                    .table("o2")
+                    .timestamp_range(1, 550)
+                    .add_expr(make_column_eq_expr("state", "NY")) // state=NY
+                    .build(),
+                expected_column_values: Ok(vec!["NY"]),
+            },
+            TestCase {
+                description: "Restrictions: tag is null",
+                column_name: "state",
+                predicate: PredicateBuilder::default()
+                    .add_expr(Expr::IsNull(Box::new(Expr::Column("city".into())))) // city IS NULL
+                    .build(),
+                expected_column_values: Ok(vec!["CA", "NY"]),
+            },
+            TestCase {
+                description: "Restrictions: tag is not null",
+                column_name: "state",
+                predicate: PredicateBuilder::default()
+                    .add_expr(Expr::IsNotNull(Box::new(Expr::Column("city".into())))) // city IS NOT NULL
+                    .build(),
+                expected_column_values: Ok(vec!["CA", "MA"]),
+            },
+            TestCase {
+                description: "Restrictions: tag is null, no table has the tag",
+                column_name: "state",
+                predicate: PredicateBuilder::default()
+                    .add_expr(Expr::IsNull(Box::new(Expr::Column("zone".into())))) // zone IS NULL
+                    .build(),
+                expected_column_values: Ok(vec!["CA", "MA", "NY"]),
+            },
+            TestCase {
+                description: "Restrictions: measurement name, timestamp and predicate: no match",
+                column_name: "state",
+                predicate: PredicateBuilder::default()
+                    .table("o2")
+                    .timestamp_range(1, 300) // filters out the NY row
+                    .add_expr(make_column_eq_expr("state", "NY")) // state=NY
+                    .build(),
+                expected_column_values: Ok(vec![]),
+            },
+        ];
+
+        for test_case in test_cases.into_iter() {
+            let test_case_str = format!("{:#?}", test_case);
+            println!("Running test case: {:?}", test_case);
+
+            let column_values_plan = db
+                .column_values(test_case.column_name, test_case.predicate)
+                .await
+                .expect("Created tag_values plan successfully")
+                .plan;
+
+            // run the execution plan (
+            let executor = Executor::default();
+            let actual_column_values = executor.to_string_set(column_values_plan).await;
+
+            let is_match = if let Ok(expected_column_values) = &test_case.expected_column_values {
+                let expected_column_values = to_set(expected_column_values);
+                if let Ok(actual_column_values) = &actual_column_values {
+                    **actual_column_values == expected_column_values
+                } else {
+                    false
+                }
+            } else if let Err(e) = &actual_column_values {
+                // use string compare to compare errors to avoid having to build exact errors
+                format!("{:?}", e) == format!("{:?}", test_case.expected_column_values)
+            } else {
+                false
+            };
+
+            assert!(
+                is_match,
+                "Mismatch\n\
+                     actual_column_values: \n\
+                     {:?}\n\
+                     expected_column_values: \n\
+                     {:?}\n\
+                     Test_case: \n\
+                     {}",
+                actual_column_values, test_case.expected_column_values, test_case_str
+            );
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_series() -> Result {
+        // This test checks that everything is wired together
+        // correctly.  There are more detailed tests in table.rs that
+        // test the generated queries.
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
+
+        let mut lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100", // to row 2
+            "h2o,state=MA,city=Boston temp=72.4 250", // to row 1
+            "h2o,state=CA,city=LA temp=90.0 200",     // to row 0
+            "h2o,state=CA,city=LA temp=90.0 350",     // to row 3
+            "o2,state=MA,city=Boston temp=50.4,reading=50 100", // to row 5
+            "o2,state=MA,city=Boston temp=53.4,reading=51 250", // to row 4
+        ];
 
-        {
-            let db = Db::try_with_wal("mydb", &mut dir).await?;
-            let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i,str=\"some string\",b=true 10\ndisk,region=west,host=A bytes=23432323i,used_percent=76.2 10").map(|l| l.unwrap()).collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("cpu,region=west,host=B user=23.1 15")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("cpu,host=A,new_tag=foo new_field=15.1 20")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("mem,region=east,host=C val=23432 10")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
+        // Swap around  data is not inserted in series order
+        lp_lines.swap(0, 2);
+        lp_lines.swap(4, 5);
 
-            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
-            assert_table_eq(expected_cpu_table, &partitions);
+        let lp_data = lp_lines.join("\n");
 
-            let partitions = db.table_to_arrow("mem", mem_columns).await?;
-            assert_table_eq(expected_mem_table, &partitions);
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
 
-            let partitions = db.table_to_arrow("disk", disk_columns).await?;
-            assert_table_eq(expected_disk_table, &partitions);
-        }
+        let predicate = Predicate::default();
 
-        // check that it recovers from the wal
-        {
-            let db = Db::restore_from_wal(&dir).await?;
+        let plans = db
+            .query_series(predicate)
+            .await
+            .expect("Created tag_values plan successfully")
+            .plan;
 
-            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
-            assert_table_eq(expected_cpu_table, &partitions);
+        let results = run_and_gather_results(plans).await;
 
-            let partitions = db.table_to_arrow("mem", mem_columns).await?;
-            assert_table_eq(expected_mem_table, &partitions);
+        assert_eq!(results.len(), 3);
 
-            let partitions = db.table_to_arrow("disk", disk_columns).await?;
-            assert_table_eq(expected_disk_table, &partitions);
+        let series_set0 = results[0].as_ref().expect("Correctly converted");
+        assert_eq!(*series_set0.table_name, "h2o");
+        assert_eq!(
+            series_set0.tags,
+            str_pair_vec_to_vec(&[("city", "Boston"), ("state", "MA")])
+        );
+        assert_eq!(series_set0.timestamp_index, 3);
+        assert_eq!(series_set0.field_indices, Arc::new(vec![2]));
+        assert_eq!(series_set0.start_row, 0);
+        assert_eq!(series_set0.num_rows, 2);
+
+        let series_set1 = results[1].as_ref().expect("Correctly converted");
+        assert_eq!(*series_set1.table_name, "h2o");
+        assert_eq!(
+            series_set1.tags,
+            str_pair_vec_to_vec(&[("city", "LA"), ("state", "CA")])
+        );
+        assert_eq!(series_set1.timestamp_index, 3);
+        assert_eq!(series_set1.field_indices, Arc::new(vec![2]));
+        assert_eq!(series_set1.start_row, 2);
+        assert_eq!(series_set1.num_rows, 2);
+
+        let series_set2 = results[2].as_ref().expect("Correctly converted");
+        assert_eq!(*series_set2.table_name, "o2");
+        assert_eq!(
+            series_set2.tags,
+            str_pair_vec_to_vec(&[("city", "Boston"), ("state", "MA")])
+        );
+        assert_eq!(series_set2.timestamp_index, 4);
+        assert_eq!(series_set2.field_indices, Arc::new(vec![2, 3]));
+        assert_eq!(series_set2.start_row, 0);
+        assert_eq!(series_set2.num_rows, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_pre_aggregated_series() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("pre_aggregated_db", &mut dir).await?;
+        db.set_rules(DatabaseRules {
+            pre_aggregated_tables: vec!["cpu".into()],
+            ..Default::default()
+        })
+        .await?;
+
+        let minute = 60_000_000_000;
+        let lp_lines = vec![
+            format!("cpu,host=a usage=1.5,count=1i {}", 10),
+            format!("cpu,host=a usage=2.5,count=2i {}", 20),
+            format!("cpu,host=a usage=4.0,count=3i {}", minute + 5),
+            format!("cpu,host=b usage=9.0,count=4i {}", 3 * minute),
+            format!("mem,host=a used=3.0 {}", 10),
+        ];
+        let lp_data = lp_lines.join("\n");
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        let window_aggregate = |every, aggregate| {
+            WindowAggregate::try_new(
+                window::Duration::from_nsecs(every),
+                window::Duration::from_nsecs(0),
+                aggregate,
+                Fill::None,
+                None,
+            )
+            .unwrap()
+        };
+
+        // the same windows are computed as from the rows
+        for (every, aggregate) in &[
+            (minute, Aggregate::Sum),
+            (2 * minute, Aggregate::Sum),
+            (2 * minute, Aggregate::Count),
+            (minute, Aggregate::Max),
+            (4 * minute, Aggregate::Mean),
+        ] {
+            let window_aggregate = window_aggregate(*every, *aggregate);
+            let predicate = PredicateBuilder::default()
+                .table("cpu")
+                .add_expr(make_column_eq_expr("host", "a"))
+                .build();
+
+            let pre_aggregated = db
+                .query_pre_aggregated_series(predicate.clone(), &window_aggregate)
+                .await?
+                .expect("answered from pre-aggregates")
+                .plan;
+            assert_eq!(pre_aggregated.series.len(), 1);
+            let windowed = window_aggregate.apply_to_buckets(&pre_aggregated.series[0])?;
+
+            let results = run_and_gather_results(db.query_series(predicate).await?.plan).await;
+            assert_eq!(results.len(), 1);
+            let series_set = results[0].as_ref().expect("Correctly converted");
+            let expected = window_aggregate.apply(series_set)?;
+
+            assert_eq!(windowed.table_name, expected.table_name);
+            assert_eq!(windowed.tags, expected.tags);
+            assert_eq!(windowed.timestamps, expected.timestamps);
+            assert_eq!(windowed.fields, expected.fields);
         }
 
+        let sum = window_aggregate(minute, Aggregate::Sum);
+
+        // mem has no pre-aggregates
+        let predicate = PredicateBuilder::default().build();
+        assert!(db
+            .query_pre_aggregated_series(predicate, &sum)
+            .await?
+            .is_none());
+
+        // the predicate is on a field
+        let predicate = PredicateBuilder::default()
+            .table("cpu")
+            .add_expr(make_column_eq_expr("usage", "1.5"))
+            .build();
+        assert!(db
+            .query_pre_aggregated_series(predicate, &sum)
+            .await?
+            .is_none());
+
+        // the window doesn't line up with the buckets
+        let predicate = PredicateBuilder::default().table("cpu").build();
+        let window_aggregate = window_aggregate(90_000_000_000, Aggregate::Sum);
+        assert!(db
+            .query_pre_aggregated_series(predicate, &window_aggregate)
+            .await?
+            .is_none());
+
+        // the pruning is only recorded for answered queries
+        let predicate = PredicateBuilder::default().table("cpu").build();
+        let pre_aggregated = db
+            .query_pre_aggregated_series(predicate, &sum)
+            .await?
+            .expect("answered from pre-aggregates")
+            .plan;
+        assert_eq!(pre_aggregated.series.len(), 2);
+        assert_eq!(pre_aggregated.chunks_scanned, 1);
+
+        // rows written out of time order may be duplicates, which are
+        // only removed when reading the rows
+        let lp_data = format!("cpu,host=b usage=1.0,count=5i {}", 3 * minute);
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+        let predicate = PredicateBuilder::default().table("cpu").build();
+        assert!(db
+            .query_pre_aggregated_series(predicate, &sum)
+            .await?
+            .is_none());
+
         Ok(())
     }
 
     #[tokio::test]
-    async fn write_and_query() -> Result {
-        let db = Db::new("foo");
+    async fn test_query_series_filter() -> Result {
+        // check the appropriate filters are applied in the datafusion plans
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
 
-        let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i 10")
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,state=MA,city=Boston temp=72.4 250",
+            "h2o,state=CA,city=LA temp=90.0 200",
+            "h2o,state=CA,city=LA temp=90.0 350",
+            "o2,state=MA,city=Boston temp=50.4,reading=50 100",
+            "o2,state=MA,city=Boston temp=53.4,reading=51 250",
+        ];
+
+        let lp_data = lp_lines.join("\n");
+
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        // filter out one row in h20
+        let predicate = PredicateBuilder::default()
+            .timestamp_range(200, 300)
+            .add_expr(make_column_eq_expr("state", "CA")) // state=CA
+            .build();
+
+        let plans = db
+            .query_series(predicate)
+            .await
+            .expect("Created tag_values plan successfully")
+            .plan;
+
+        let results = run_and_gather_results(plans).await;
+
+        assert_eq!(results.len(), 1);
+
+        let series_set0 = results[0].as_ref().expect("Correctly converted");
+        assert_eq!(*series_set0.table_name, "h2o");
+        assert_eq!(
+            series_set0.tags,
+            str_pair_vec_to_vec(&[("city", "LA"), ("state", "CA")])
+        );
+        assert_eq!(series_set0.timestamp_index, 3);
+        assert_eq!(series_set0.field_indices, Arc::new(vec![2]));
+        assert_eq!(series_set0.start_row, 0);
+        assert_eq!(series_set0.num_rows, 1); // only has one row!
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_plans_record_generation() -> Result {
+        let db = Db::new("generation_db");
+
+        let lines: Vec<_> = parse_lines("h2o,state=MA temp=70.4 100")
             .map(|l| l.unwrap())
             .collect();
         db.write_lines(&lines).await?;
+        let generation = db.generation();
 
-        let results = db.query("select * from cpu").await?;
+        let planned = db.query_series(Predicate::default()).await?;
+        assert_eq!(planned.generation, generation);
+        let planned = db.tag_column_names(Predicate::default()).await?;
+        assert_eq!(planned.generation, generation);
+        let planned = db.table_names(Predicate::default()).await?;
+        assert_eq!(planned.generation, generation);
 
-        let expected_cpu_table = r#"+------+-------+--------+------+------+
-| host | other | region | time | user |
-+------+-------+--------+------+------+
-| A    | 1     | west   | 10   | 23.2 |
-+------+-------+--------+------+------+
-"#;
+        // plans made after a write read the data of the new generation,
+        // while those made before keep the generation they read
+        db.write_lines(&lines).await?;
+        let later = db.field_columns(Predicate::default()).await?;
+        assert!(later.generation > generation);
+        assert_eq!(later.generation, db.generation());
+        assert_eq!(planned.generation, generation);
+
+        // changing the rules can change the results of queries too
+        db.set_rules(db.rules().await).await?;
+        let latest = db
+            .query_groups(Predicate::default(), vec!["state".into()])
+            .await?;
+        assert!(latest.generation > later.generation);
 
-        assert_table_eq(expected_cpu_table, &results);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_series_prunes_chunks() -> Result {
+        let db = Db::new("prune_db");
+
+        let write = |lp: &'static str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let db = &db;
+            async move { db.write_lines(&lines).await }
+        };
+
+        // two chunks of the same partition, with different times and states
+        write("h2o,state=MA,city=Boston temp=70.4 100\nh2o,state=MA,city=Boston temp=72.4 250")
+            .await?;
+        db.close_chunk("1970-01-01T00", 0).await?;
+        write("h2o,state=CA,city=LA temp=90.0 1000\nh2o,state=TX,city=Austin temp=91.0 1100")
+            .await?;
+
+        // returns the number of series the query finds
+        let query = |predicate: Predicate| {
+            let db = &db;
+            async move {
+                let plans = db.query_series(predicate).await?.plan;
+                Ok::<_, Error>(run_and_gather_results(plans).await.len())
+            }
+        };
+
+        // the time range rules out the second chunk
+        let predicate = PredicateBuilder::default().timestamp_range(0, 500).build();
+        assert_eq!(query(predicate).await?, 1);
+        let metrics = db.chunk_pruning_metrics();
+        assert_eq!((metrics.chunks_scanned(), metrics.chunks_pruned()), (1, 1));
+
+        // the tag's min and max rule out the first chunk
+        let predicate = PredicateBuilder::default()
+            .add_expr(make_column_eq_expr("state", "NY"))
+            .build();
+        assert_eq!(query(predicate).await?, 1);
+        let metrics = db.chunk_pruning_metrics();
+        assert_eq!((metrics.chunks_scanned(), metrics.chunks_pruned()), (2, 2));
+
+        // neither chunk can be pruned for a value between its min and max
+        let predicate = PredicateBuilder::default()
+            .add_expr(make_column_eq_expr("state", "MA"))
+            .build();
+        assert_eq!(query(predicate).await?, 1);
+        let metrics = db.chunk_pruning_metrics();
+        assert_eq!((metrics.chunks_scanned(), metrics.chunks_pruned()), (4, 2));
+
+        // the plans of each query record the chunks it scanned and pruned
+        let predicate = PredicateBuilder::default().timestamp_range(0, 500).build();
+        let plans = db.query_series(predicate).await?.plan;
+        assert_eq!((plans.chunks_scanned, plans.chunks_pruned), (1, 1));
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn recover_partial_entries() -> Result {
-        let mut dir = test_helpers::tmp_dir()?.into_path();
+    async fn test_query_series_prunes_chunks_ignoring_case() -> Result {
+        let db = Db::new("prune_db");
 
-        let expected_cpu_table = r#"+--------+------+------+-------+-------------+------+------+---------+-----------+
-| region | host | user | other | str         | b    | time | new_tag | new_field |
-+--------+------+------+-------+-------------+------+------+---------+-----------+
-| west   | A    | 23.2 | 1     | some string | true | 10   |         |           |
-| west   | B    | 23.1 |       |             |      | 15   |         |           |
-|        | A    |      |       |             |      | 20   | foo     | 15.1      |
-+--------+------+------+-------+-------------+------+------+---------+-----------+
-"#;
+        let write = |lp: &'static str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let db = &db;
+            async move { db.write_lines(&lines).await }
+        };
 
-        let expected_mem_table = r#"+--------+------+-------+------+
-| region | host | val   | time |
-+--------+------+-------+------+
-| east   | C    | 23432 | 10   |
-+--------+------+-------+------+
-"#;
-        let expected_disk_table = r#"+--------+------+----------+--------------+------+
-| region | host | bytes    | used_percent | time |
-+--------+------+----------+--------------+------+
-| west   | A    | 23432323 | 76.2         | 10   |
-+--------+------+----------+--------------+------+
-"#;
+        // two chunks of the same partition, which share its dictionary
+        write("h2o,state=MA,city=Boston temp=70.4 100\nh2o,state=MA,city=BOSTON temp=72.4 250")
+            .await?;
+        db.close_chunk("1970-01-01T00", 0).await?;
+        write("h2o,state=CA,city=LA temp=90.0 1000\nh2o,state=TX,city=Austin temp=91.0 1100")
+            .await?;
+
+        // returns the tags of the series the query finds
+        let query = |predicate: Predicate| {
+            let db = &db;
+            async move {
+                let plans = db.query_series(predicate).await?.plan;
+                let results = run_and_gather_results(plans).await;
+                Ok::<_, Error>(
+                    results
+                        .into_iter()
+                        .map(|series_set| series_set.expect("Correctly converted").tags)
+                        .collect::<Vec<_>>(),
+                )
+            }
+        };
 
-        let cpu_columns = &[
-            "region",
-            "host",
-            "user",
-            "other",
-            "str",
-            "b",
-            "time",
-            "new_tag",
-            "new_field",
-        ];
-        let mem_columns = &["region", "host", "val", "time"];
-        let disk_columns = &["region", "host", "bytes", "used_percent", "time"];
-        {
-            let db = Db::try_with_wal("mydb", &mut dir).await?;
-            let lines: Vec<_> = parse_lines("cpu,region=west,host=A user=23.2,other=1i,str=\"some string\",b=true 10\ndisk,region=west,host=A bytes=23432323i,used_percent=76.2 10").map(|l| l.unwrap()).collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("cpu,region=west,host=B user=23.1 15")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("cpu,host=A,new_tag=foo new_field=15.1 20")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
-            let lines: Vec<_> = parse_lines("mem,region=east,host=C val=23432 10")
-                .map(|l| l.unwrap())
-                .collect();
-            db.write_lines(&lines).await?;
+        // only the first chunk has a city that lower cases to boston
+        let predicate = PredicateBuilder::default()
+            .add_expr(make_lowered_column_eq_expr("city", "boston"))
+            .build();
+        assert_eq!(
+            query(predicate).await?,
+            vec![
+                str_pair_vec_to_vec(&[("city", "BOSTON"), ("state", "MA")]),
+                str_pair_vec_to_vec(&[("city", "Boston"), ("state", "MA")]),
+            ]
+        );
+        let metrics = db.chunk_pruning_metrics();
+        assert_eq!((metrics.chunks_scanned(), metrics.chunks_pruned()), (1, 1));
+
+        // the dictionary has Austin, but not in the first chunk
+        let predicate = PredicateBuilder::default()
+            .add_expr(make_lowered_column_eq_expr("city", "austin"))
+            .build();
+        assert_eq!(
+            query(predicate).await?,
+            vec![str_pair_vec_to_vec(&[("city", "Austin"), ("state", "TX")])]
+        );
+        let metrics = db.chunk_pruning_metrics();
+        assert_eq!((metrics.chunks_scanned(), metrics.chunks_pruned()), (2, 2));
+
+        // no value in the dictionary lower cases to these
+        for value in &["paris", "Boston"] {
+            let predicate = PredicateBuilder::default()
+                .add_expr(make_lowered_column_eq_expr("city", value))
+                .build();
+            assert!(query(predicate).await?.is_empty());
+        }
+        let metrics = db.chunk_pruning_metrics();
+        assert_eq!((metrics.chunks_scanned(), metrics.chunks_pruned()), (2, 6));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sql_query_prunes_chunks_by_time() -> Result {
+        let db = Db::new("prune_db");
+
+        // two chunks of the same partition, with different times
+        let lines: Vec<_> = parse_lines("h2o,city=Boston temp=70.4 100\nh2o,city=LA temp=90.0 250")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
+        db.close_chunk("1970-01-01T00", 0).await?;
+        let lines: Vec<_> = parse_lines("h2o,city=Boston temp=72.4 1000")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
+
+        // returns the number of rows the query returns
+        let rows = |query: &'static str| {
+            let db = &db;
+            async move {
+                let batches = db.query(query).await?;
+                Ok::<_, Error>(batches.iter().map(|batch| batch.num_rows()).sum::<usize>())
+            }
+        };
 
-            let partitions = db.table_to_arrow("cpu", cpu_columns).await?;
-            assert_table_eq(expected_cpu_table, &partitions);
+        assert_eq!(rows("select * from h2o where time < 500").await?, 2);
+        let metrics = db.chunk_pruning_metrics();
+        assert_eq!((metrics.chunks_scanned(), metrics.chunks_pruned()), (1, 1));
 
-            let partitions = db.table_to_arrow("mem", mem_columns).await?;
-            assert_table_eq(expected_mem_table, &partitions);
+        assert_eq!(
+            rows("select * from h2o where time between 900 and 1000 and city = 'Boston'").await?,
+            1
+        );
+        let metrics = db.chunk_pruning_metrics();
+        assert_eq!((metrics.chunks_scanned(), metrics.chunks_pruned()), (2, 2));
 
-            let partitions = db.table_to_arrow("disk", disk_columns).await?;
-            assert_table_eq(expected_disk_table, &partitions);
-        }
+        // a range without any chunks still has the columns of the table
+        assert_eq!(
+            rows("select city, temp from h2o where time > 5000").await?,
+            0
+        );
 
-        // check that it can recover from the last 2 self-describing entries of the wal
-        {
-            let name = dir.iter().last().unwrap().to_str().unwrap().to_string();
+        // conditions which do not bound the time prune nothing
+        assert_eq!(
+            rows("select * from h2o where time < 500 or temp > 80").await?,
+            2
+        );
+        let metrics = db.chunk_pruning_metrics();
+        assert_eq!((metrics.chunks_scanned(), metrics.chunks_pruned()), (4, 4));
 
-            let wal_builder = WalBuilder::new(&dir);
+        Ok(())
+    }
 
-            let wal_entries = wal_builder
-                .entries()
-                .context(LoadingWal { database: &name })?;
+    #[tokio::test]
+    async fn test_query_series_deduplicates_rows() -> Result {
+        let db = Db::new("dedup_db");
 
-            // Skip the first 2 entries in the wal; only restore from the last 2
-            let wal_entries = wal_entries.skip(2);
+        let write = |lp: &'static str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let db = &db;
+            async move { db.write_lines(&lines).await }
+        };
 
-            let (partitions, _stats) = restore_partitions_from_wal(wal_entries)?;
+        // duplicates within a chunk, and across overlapping chunks
+        write("h2o,city=Boston temp=70.4 100\nh2o,city=Boston temp=71.4 100").await?;
+        write("h2o,city=LA temp=90.0 200").await?;
+        db.close_chunk("1970-01-01T00", 0).await?;
+        write("h2o,city=LA temp=91.0 200\nh2o,city=LA temp=92.0 300").await?;
+        write("h2o,city=LA humidity=50 300").await?;
+        db.close_chunk("1970-01-01T00", 1).await?;
 
-            let db = Db {
-                name,
-                partitions: RwLock::new(partitions),
-                wal_details: None,
-            };
+        // a chunk that overlaps no other is planned on its own
+        write("h2o,city=LA temp=93.0 1000").await?;
 
-            // some cpu
-            let smaller_cpu_table = r#"+------+---------+-----------+------+
-| host | new_tag | new_field | time |
-+------+---------+-----------+------+
-| A    | foo     | 15.1      | 20   |
-+------+---------+-----------+------+
-"#;
-            let smaller_cpu_columns = &["host", "new_tag", "new_field", "time"];
-            let partitions = db.table_to_arrow("cpu", smaller_cpu_columns).await?;
-            assert_table_eq(smaller_cpu_table, &partitions);
+        let plans = db.query_series(Predicate::default()).await?.plan;
+        assert_eq!(plans.plans.len(), 2);
+        assert_eq!((plans.chunks_scanned, plans.chunks_pruned), (3, 0));
+        assert_eq!(
+            plans.plans[0].field_columns,
+            *str_vec_to_arc_vec(&["humidity", "temp"])
+        );
+        assert_eq!(plans.plans[1].field_columns, *str_vec_to_arc_vec(&["temp"]));
+        let results = run_and_gather_results(plans).await;
+        assert_eq!(results.len(), 3);
 
-            // all of mem
-            let partitions = db.table_to_arrow("mem", mem_columns).await?;
-            assert_table_eq(expected_mem_table, &partitions);
+        let series_set = results[0].as_ref().expect("Correctly converted");
+        assert_eq!(series_set.tags, str_pair_vec_to_vec(&[("city", "Boston")]));
+        assert_eq!(series_set.num_rows, 1);
+        let series_set = results[1].as_ref().expect("Correctly converted");
+        assert_eq!(series_set.tags, str_pair_vec_to_vec(&[("city", "LA")]));
+        assert_eq!(series_set.num_rows, 2);
+        let series_set = results[2].as_ref().expect("Correctly converted");
+        assert_eq!(series_set.tags, str_pair_vec_to_vec(&[("city", "LA")]));
+        assert_eq!(series_set.num_rows, 1);
+
+        // the fields of a series and timestamp are merged, so the row
+        // at 300 has both the temperature and the humidity
+        let predicate = PredicateBuilder::default()
+            .add_expr(
+                logical_plan::col("humidity")
+                    .gt(Expr::Literal(ScalarValue::Float64(Some(10.0))))
+                    .and(
+                        logical_plan::col("temp")
+                            .gt(Expr::Literal(ScalarValue::Float64(Some(91.5)))),
+                    ),
+            )
+            .build();
+        let results = run_and_gather_results(db.query_series(predicate).await?.plan).await;
+        assert_eq!(results.len(), 1);
+        let series_set = results[0].as_ref().expect("Correctly converted");
+        assert_eq!(series_set.tags, str_pair_vec_to_vec(&[("city", "LA")]));
+        assert_eq!(series_set.num_rows, 1);
 
-            // no disk
-            let nonexistent_table = db.table_to_arrow("disk", disk_columns).await;
-            assert!(nonexistent_table.is_err());
-            let actual_message = format!("{:?}", nonexistent_table);
-            let expected_message = "TableNameNotFoundInDictionary";
-            assert!(
-                actual_message.contains(expected_message),
-                "Did not find '{}' in '{}'",
-                expected_message,
-                actual_message
-            );
-        }
+        // the predicate is applied to the deduplicated rows, so a
+        // replaced row doesn't pass it
+        let predicate = PredicateBuilder::default()
+            .add_expr(logical_plan::col("temp").lt(Expr::Literal(ScalarValue::Float64(Some(90.5)))))
+            .build();
+        let results = run_and_gather_results(db.query_series(predicate).await?.plan).await;
+        assert_eq!(results.len(), 1);
+        let series_set = results[0].as_ref().expect("Correctly converted");
+        assert_eq!(series_set.tags, str_pair_vec_to_vec(&[("city", "Boston")]));
+
+        // grouped plans are deduplicated too
+        let plans = db
+            .query_groups(Predicate::default(), vec!["city".into()])
+            .await?
+            .plan;
+        assert_eq!(plans.grouped_plans.len(), 2);
+        assert_eq!(plans.grouped_plans[0].num_prefix_tag_group_columns, 1);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn db_partition_key() -> Result {
-        let partition_keys: Vec<_> = parse_lines(
-            "\
-cpu user=23.2 1600107710000000000
-disk bytes=23432323i 1600136510000000000",
-        )
-        .map(|line| partition_key(&line.unwrap()))
-        .collect();
+    async fn test_query_series_column_comparison() -> Result {
+        // fields of different types can be compared with each other
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
 
-        assert_eq!(partition_keys, vec!["2020-09-14T18", "2020-09-15T02"]);
+        let lp_lines = vec![
+            "cpu,host=a usage_user=10.5,usage_system=3i 100",
+            "cpu,host=a usage_user=1.0,usage_system=5i 200",
+            "cpu,host=b usage_user=20.0,usage_system=30i 100",
+        ];
+
+        let lp_data = lp_lines.join("\n");
+
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+
+        // usage_user > usage_system
+        let predicate = PredicateBuilder::default()
+            .add_expr(logical_plan::col("usage_user").gt(logical_plan::col("usage_system")))
+            .build();
+
+        let plans = db
+            .query_series(predicate)
+            .await
+            .expect("Created query_series plan successfully")
+            .plan;
+
+        let results = run_and_gather_results(plans).await;
+
+        assert_eq!(results.len(), 1);
+
+        let series_set0 = results[0].as_ref().expect("Correctly converted");
+        assert_eq!(*series_set0.table_name, "cpu");
+        assert_eq!(series_set0.tags, str_pair_vec_to_vec(&[("host", "a")]));
+        assert_eq!(series_set0.num_rows, 1);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn list_column_names() -> Result {
+    async fn test_query_series_pred_refers_to_column_not_in_table() -> Result {
         let mut dir = test_helpers::tmp_dir()?.into_path();
         let db = Db::try_with_wal("column_namedb", &mut dir).await?;
 
-        let lp_data = "h2o,state=CA,city=LA,county=LA temp=70.4 100\n\
-                       h2o,state=MA,city=Boston,county=Suffolk temp=72.4 250\n\
-                       o2,state=MA,city=Boston temp=50.4 200\n\
-                       o2,state=CA temp=79.0 300\n\
-                       o2,state=NY temp=60.8 400\n\
-                       o2,state=NY,city=NYC temp=61.0 500\n\
-                       o2,state=NY,city=NYC,borough=Brooklyn temp=61.0 600\n";
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,state=MA,city=Boston temp=72.4 250",
+        ];
 
-        let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+        let lp_data = lp_lines.join("\n");
+
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
         db.write_lines(&lines).await?;
 
-        #[derive(Debug)]
-        struct TestCase<'a> {
-            description: &'a str,
-            predicate: Predicate,
-            expected_tag_keys: Result<Vec<&'a str>>,
-        };
+        let predicate = PredicateBuilder::default()
+            .add_expr(make_column_eq_expr("tag_not_in_h20", "foo"))
+            .build();
 
-        let test_cases = vec![
-            TestCase {
-                description: "No predicates",
-                predicate: PredicateBuilder::default().build(),
-                expected_tag_keys: Ok(vec!["borough", "city", "county", "state"]),
-            },
-            TestCase {
-                description: "Restrictions: timestamp",
-                predicate: PredicateBuilder::default()
-                    .timestamp_range(150, 201)
-                    .build(),
-                expected_tag_keys: Ok(vec!["city", "state"]),
-            },
-            TestCase {
-                description: "Restrictions: predicate",
-                predicate: PredicateBuilder::default()
-                    .add_expr(make_column_eq_expr("state", "MA")) // state=MA
-                    .build(),
-                expected_tag_keys: Ok(vec!["city", "county", "state"]),
-            },
-            TestCase {
-                description: "Restrictions: timestamp and predicate",
-                predicate: PredicateBuilder::default()
-                    .timestamp_range(150, 201)
-                    .add_expr(make_column_eq_expr("state", "MA")) // state=MA
-                    .build(),
-                expected_tag_keys: Ok(vec!["city", "state"]),
-            },
-            TestCase {
-                description: "Restrictions: measurement name",
-                predicate: PredicateBuilder::default().table("o2").build(),
-                expected_tag_keys: Ok(vec!["borough", "city", "state"]),
-            },
-            TestCase {
-                description: "Restrictions: measurement name and timestamp",
-                predicate: PredicateBuilder::default()
-                    .table("o2")
-                    .timestamp_range(150, 201)
-                    .build(),
-                expected_tag_keys: Ok(vec!["city", "state"]),
-            },
-            TestCase {
-                description: "Restrictions: measurement name and predicate",
-                predicate: PredicateBuilder::default()
-                    .table("o2")
-                    .add_expr(make_column_eq_expr("state", "NY")) // state=NY
-                    .build(),
-                expected_tag_keys: Ok(vec!["borough", "city", "state"]),
-            },
-            TestCase {
-                description: "Restrictions: measurement name, timestamp and predicate",
-                predicate: PredicateBuilder::default()
-                    .table("o2")
-                    .timestamp_range(1, 550)
-                    .add_expr(make_column_eq_expr("state", "NY")) // state=NY
-                    .build(),
-                expected_tag_keys: Ok(vec!["city", "state"]),
-            },
-        ];
+        let plans = db
+            .query_series(predicate)
+            .await
+            .expect("Created tag_values plan successfully")
+            .plan;
 
-        for test_case in test_cases.into_iter() {
-            let test_case_str = format!("{:#?}", test_case);
-            println!("Running test case: {:?}", test_case);
+        let results = run_and_gather_results(plans).await;
+        assert!(results.is_empty());
 
-            let tag_keys_plan = db
-                .tag_column_names(test_case.predicate)
-                .await
-                .expect("Created tag_keys plan successfully");
+        // predicate with no columns,
+        let predicate = PredicateBuilder::default()
+            .add_expr(make_no_column_expr())
+            .build();
 
-            // run the execution plan (
-            let executor = Executor::default();
-            let actual_tag_keys = executor.to_string_set(tag_keys_plan).await;
+        let plans = db
+            .query_series(predicate)
+            .await
+            .expect("Created tag_values plan successfully")
+            .plan;
 
-            let is_match = if let Ok(expected_tag_keys) = &test_case.expected_tag_keys {
-                let expected_tag_keys = to_set(expected_tag_keys);
-                if let Ok(actual_tag_keys) = &actual_tag_keys {
-                    **actual_tag_keys == expected_tag_keys
-                } else {
-                    false
-                }
-            } else if let Err(e) = &actual_tag_keys {
-                // use string compare to compare errors to avoid having to build exact errors
-                format!("{:?}", e) == format!("{:?}", test_case.expected_tag_keys)
-            } else {
-                false
-            };
+        let results = run_and_gather_results(plans).await;
+        assert_eq!(results.len(), 1);
 
-            assert!(
-                is_match,
-                "Mismatch\n\
-                     actual_tag_keys: \n\
-                     {:?}\n\
-                     expected_tag_keys: \n\
-                     {:?}\n\
-                     Test_case: \n\
-                     {}",
-                actual_tag_keys, test_case.expected_tag_keys, test_case_str
-            );
-        }
+        // predicate with both a column that does and does not appear
+        let predicate = PredicateBuilder::default()
+            .add_expr(make_column_eq_expr("state", "MA"))
+            .add_expr(make_column_eq_expr("tag_not_in_h20", "foo"))
+            .build();
+
+        let plans = db
+            .query_series(predicate)
+            .await
+            .expect("Created tag_values plan successfully")
+            .plan;
+
+        let results = run_and_gather_results(plans).await;
+        assert!(results.is_empty());
 
         Ok(())
     }
 
-    // Create a predicate of the form column_name = val
-    fn make_column_eq_expr(column_name: &str, val: &str) -> Expr {
-        make_column_expr(column_name, Operator::Eq, val)
-    }
+    #[tokio::test]
+    #[should_panic(
+        expected = "Unsupported binary operator in expression: #state NotEq Utf8(\"MA\")"
+    )]
+    async fn test_query_series_pred_neq() {
+        let mut dir = test_helpers::tmp_dir().unwrap().into_path();
+        let db = Db::try_with_wal("column_namedb", &mut dir).await.unwrap();
 
-    // Create a predicate of the form column_name != val
-    fn make_column_neq_expr(column_name: &str, val: &str) -> Expr {
-        make_column_expr(column_name, Operator::NotEq, val)
-    }
+        let lp_lines = vec![
+            "h2o,state=MA,city=Boston temp=70.4 100",
+            "h2o,state=MA,city=Boston temp=72.4 250",
+        ];
 
-    // Create a predicate of the form column_name <op> val
-    fn make_column_expr(column_name: &str, op: Operator, val: &str) -> Expr {
-        Expr::BinaryExpr {
-            left: Box::new(Expr::Column(column_name.into())),
-            op,
-            right: Box::new(Expr::Literal(ScalarValue::Utf8(Some(val.into())))),
-        }
-    }
+        let lp_data = lp_lines.join("\n");
 
-    // Create a predicate of the form "foo = "foo" (no column references, but is true)
-    fn make_no_column_expr() -> Expr {
-        let foo_literal = ScalarValue::Utf8(Some(String::from("foo")));
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await.unwrap();
 
-        Expr::BinaryExpr {
-            left: Box::new(Expr::Literal(foo_literal.clone())),
-            op: Operator::Eq,
-            right: Box::new(Expr::Literal(foo_literal)),
-        }
+        let predicate = PredicateBuilder::default()
+            .add_expr(make_column_neq_expr("state", "MA"))
+            .build();
+
+        // Should panic as the neq path isn't implemented yet
+        db.query_series(predicate).await.unwrap();
     }
 
     #[tokio::test]
-    async fn list_column_names_predicate() -> Result {
-        // Demonstration test to show column names with predicate working
+    async fn test_field_columns() -> Result {
+        // Ensure that the database queries are hooked up correctly
 
         let mut dir = test_helpers::tmp_dir()?.into_path();
         let db = Db::try_with_wal("column_namedb", &mut dir).await?;
 
-        let lp_data = "h2o,state=CA,city=LA,county=LA temp=70.4 100\n\
-                       h2o,state=MA,city=Boston,county=Suffolk temp=72.4 250\n\
-                       o2,state=MA,city=Boston temp=50.4 200\n\
-                       o2,state=CA temp=79.0 300\n\
-                       o2,state=NY,city=NYC,borough=Brooklyn temp=60.8 400\n";
+        let lp_data = vec![
+            "h2o,state=MA,city=Boston temp=70.4 50",
+            "h2o,state=MA,city=Boston other_temp=70.4 250",
+            "h2o,state=CA,city=Boston other_temp=72.4 350",
+            "o2,state=MA,city=Boston temp=53.4,reading=51 50",
+        ]
+        .join("\n");
 
-        let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
         db.write_lines(&lines).await?;
 
-        // Predicate: state=MA
-        let expr = logical_plan::col("state").eq("MA".lit());
-        let predicate = PredicateBuilder::default().add_expr(expr).build();
+        // write a new lp_line that is in a new day and thus a new partition
+        let nanoseconds_per_day: i64 = 1_000_000_000 * 60 * 60 * 24;
 
-        let tag_keys_plan = db
-            .tag_column_names(predicate)
-            .await
-            .expect("Created plan successfully");
+        let lp_data = vec![format!(
+            "h2o,state=MA,city=Boston temp=70.4,moisture=43.0 {}",
+            nanoseconds_per_day * 10
+        )]
+        .join("\n");
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
 
-        // run the execution plan (
+        // ensure there are 2 partitions
+        assert_eq!(db.len().await, 2);
+
+        // setup to run the execution plan (
         let executor = Executor::default();
-        let actual_tag_keys = executor
-            .to_string_set(tag_keys_plan)
+
+        let predicate = PredicateBuilder::default()
+            .table("NoSuchTable")
+            .add_expr(make_column_eq_expr("state", "MA")) // state=MA
+            .build();
+
+        // make sure table filtering works (no tables match)
+        let plan = db
+            .field_columns(predicate)
             .await
-            .expect("Execution of predicate plan");
+            .expect("Created field_columns plan successfully")
+            .plan;
+
+        let fieldlists = executor
+            .to_fieldlist(plan)
+            .await
+            .expect("Running fieldlist plan");
+        assert!(fieldlists.fields.is_empty());
+
+        // get only fields from h20 (but both partitions)
+        let predicate = PredicateBuilder::default()
+            .table("h2o")
+            .add_expr(make_column_eq_expr("state", "MA")) // state=MA
+            .build();
+
+        let plan = db
+            .field_columns(predicate)
+            .await
+            .expect("Created field_columns plan successfully")
+            .plan;
+
+        let actual = executor
+            .to_fieldlist(plan)
+            .await
+            .expect("Running fieldlist plan");
+
+        let expected = FieldList {
+            fields: vec![
+                Field {
+                    name: "moisture".into(),
+                    data_type: DataType::Float64,
+                    last_timestamp: nanoseconds_per_day * 10,
+                },
+                Field {
+                    name: "other_temp".into(),
+                    data_type: DataType::Float64,
+                    last_timestamp: 250,
+                },
+                Field {
+                    name: "temp".into(),
+                    data_type: DataType::Float64,
+                    last_timestamp: nanoseconds_per_day * 10,
+                },
+            ],
+        };
+
+        assert_eq!(
+            expected, actual,
+            "Expected:\n{:#?}\nActual:\n{:#?}",
+            expected, actual
+        );
 
-        assert_eq!(to_set(&["state", "city", "county"]), *actual_tag_keys);
         Ok(())
     }
 
     #[tokio::test]
-    async fn list_column_values() -> Result {
+    async fn test_field_columns_timestamp_predicate() -> Result {
+        // check the appropriate filters are applied in the datafusion plans
         let mut dir = test_helpers::tmp_dir()?.into_path();
         let db = Db::try_with_wal("column_namedb", &mut dir).await?;
 
-        let lp_data = "h2o,state=CA,city=LA temp=70.4 100\n\
-                       h2o,state=MA,city=Boston temp=72.4 250\n\
-                       o2,state=MA,city=Boston temp=50.4 200\n\
-                       o2,state=CA temp=79.0 300\n\
-                       o2,state=NY temp=60.8 400\n";
+        let lp_data = vec![
+            "h2o,state=MA,city=Boston temp=70.4 50",
+            "h2o,state=MA,city=Boston other_temp=70.4 250",
+            "h2o,state=CA,city=Boston other_temp=72.4 350",
+            "o2,state=MA,city=Boston temp=53.4,reading=51 50",
+        ]
+        .join("\n");
 
-        let lines: Vec<_> = parse_lines(lp_data).map(|l| l.unwrap()).collect();
+        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
         db.write_lines(&lines).await?;
 
-        #[derive(Debug)]
-        struct TestCase<'a> {
-            description: &'a str,
-            column_name: &'a str,
-            predicate: Predicate,
-            expected_column_values: Result<Vec<&'a str>>,
+        // setup to run the execution plan (
+        let executor = Executor::default();
+
+        let predicate = PredicateBuilder::default()
+            .table("h2o")
+            .timestamp_range(200, 300)
+            .add_expr(make_column_eq_expr("state", "MA")) // state=MA
+            .build();
+
+        let plan = db
+            .field_columns(predicate)
+            .await
+            .expect("Created field_columns plan successfully")
+            .plan;
+
+        let actual = executor
+            .to_fieldlist(plan)
+            .await
+            .expect("Running fieldlist plan");
+
+        // Should only have other_temp as a field
+        let expected = FieldList {
+            fields: vec![Field {
+                name: "other_temp".into(),
+                data_type: DataType::Float64,
+                last_timestamp: 250,
+            }],
         };
 
-        let test_cases = vec![
-            TestCase {
-                description: "No predicates, 'state' col",
-                column_name: "state",
-                predicate: PredicateBuilder::default().build(),
-                expected_column_values: Ok(vec!["CA", "MA", "NY"]),
-            },
-            TestCase {
-                description: "No predicates, 'city' col",
-                column_name: "city",
-                predicate: PredicateBuilder::default().build(),
-                expected_column_values: Ok(vec!["Boston", "LA"]),
-            },
-            TestCase {
-                description: "Restrictions: timestamp",
-                column_name: "state",
-                predicate: PredicateBuilder::default().timestamp_range(50, 201).build(),
-                expected_column_values: Ok(vec!["CA", "MA"]),
-            },
-            TestCase {
-                description: "Restrictions: predicate",
-                column_name: "city",
-                predicate: PredicateBuilder::default()
-                    .add_expr(make_column_eq_expr("state", "MA")) // state=MA
-                    .build(),
-                expected_column_values: Ok(vec!["Boston"]),
-            },
-            TestCase {
-                description: "Restrictions: timestamp and predicate",
-                column_name: "state",
-                predicate: PredicateBuilder::default()
-                    .timestamp_range(150, 301)
-                    .add_expr(make_column_eq_expr("state", "MA")) // state=MA
-                    .build(),
-                expected_column_values: Ok(vec!["MA"]),
-            },
-            TestCase {
-                description: "Restrictions: measurement name",
-                column_name: "state",
-                predicate: PredicateBuilder::default().table("h2o").build(),
-                expected_column_values: Ok(vec!["CA", "MA"]),
-            },
-            TestCase {
-                description: "Restrictions: measurement name, with nulls",
-                column_name: "city",
-                predicate: PredicateBuilder::default().table("o2").build(),
-                expected_column_values: Ok(vec!["Boston"]),
-            },
-            TestCase {
-                description: "Restrictions: measurement name and timestamp",
-                column_name: "state",
-                predicate: PredicateBuilder::default()
-                    .table("o2")
-                    .timestamp_range(50, 201)
-                    .build(),
-                expected_column_values: Ok(vec!["MA"]),
-            },
-            TestCase {
-                description: "Restrictions: measurement name and predicate",
-                column_name: "state",
-                predicate: PredicateBuilder::default()
-                    .table("o2")
-                    .add_expr(make_column_eq_expr("state", "NY")) // state=NY
-                    .build(),
-                expected_column_values: Ok(vec!["NY"]),
+        assert_eq!(
+            expected, actual,
+            "Expected:\n{:#?}\nActual:\n{:#?}",
+            expected, actual
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn downsample_partitions() -> Result {
+        let db = Db::new("mydb");
+
+        // the first three rows are in one partition, the last two in another
+        let lines: Vec<_> = parse_lines(
+            "cpu,host=a usage=1 0\n\
+             cpu,host=a usage=3 30000000000\n\
+             cpu,host=a usage=5 60000000000\n\
+             cpu,host=a usage=7 34200000000000\n\
+             cpu,host=a usage=9 34200000000001",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let rules = vec![DownsamplingRule {
+            after_seconds: 3600,
+            interval_seconds: 60,
+            aggregate: DownsamplingAggregate::Mean,
+        }];
+        // only the first partition is more than an hour old
+        let now = 36_000_000_000_000;
+
+        let generation = db.generation();
+        assert_eq!(db.downsample(&rules, now).await?, 1);
+        assert!(db.generation() > generation);
+
+        // partitions are only downsampled once
+        assert_eq!(db.downsample(&rules, now).await?, 0);
+
+        let expected = r#"+------+-------+----------------+
+| host | usage | time           |
++------+-------+----------------+
+| a    | 2     | 0              |
+| a    | 5     | 60000000000    |
+| a    | 7     | 34200000000000 |
+| a    | 9     | 34200000000001 |
++------+-------+----------------+
+"#;
+        let partitions = db.table_to_arrow("cpu", &["host", "usage", "time"]).await?;
+        assert_table_eq(expected, &partitions);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn writes_without_timestamps_are_rejected() -> Result {
+        // a batch for table cpu whose second and third rows have no time
+        let mut fbb = flatbuffers::FlatBufferBuilder::new();
+        let rows = (0..3)
+            .map(|i| {
+                let mut values = vec![];
+                let column = fbb.create_string("usage");
+                let value = wb::F64Value::create(&mut fbb, &wb::F64ValueArgs { value: 1.0 });
+                values.push(wb::Value::create(
+                    &mut fbb,
+                    &wb::ValueArgs {
+                        column: Some(column),
+                        value_type: wb::ColumnValue::F64Value,
+                        value: Some(value.as_union_value()),
+                    },
+                ));
+                if i == 0 {
+                    let column = fbb.create_string(data_types::TIME_COLUMN_NAME);
+                    let value = wb::I64Value::create(&mut fbb, &wb::I64ValueArgs { value: 10 });
+                    values.push(wb::Value::create(
+                        &mut fbb,
+                        &wb::ValueArgs {
+                            column: Some(column),
+                            value_type: wb::ColumnValue::I64Value,
+                            value: Some(value.as_union_value()),
+                        },
+                    ));
+                }
+                let values = fbb.create_vector(&values);
+                wb::Row::create(
+                    &mut fbb,
+                    &wb::RowArgs {
+                        values: Some(values),
+                    },
+                )
+            })
+            .collect::<Vec<_>>();
+        let rows = fbb.create_vector(&rows);
+        let name = fbb.create_string("cpu");
+        let table_batch = wb::TableWriteBatch::create(
+            &mut fbb,
+            &wb::TableWriteBatchArgs {
+                name: Some(name),
+                rows: Some(rows),
             },
-            TestCase {
-                description: "Restrictions: measurement name, timestamp and predicate",
-                column_name: "state",
-                predicate: PredicateBuilder::default()
-                    .table("o2")
-                    .timestamp_range(1, 550)
-                    .add_expr(make_column_eq_expr("state", "NY")) // state=NY
-                    .build(),
-                expected_column_values: Ok(vec!["NY"]),
+        );
+        let table_batches = fbb.create_vector(&[table_batch]);
+        let key = fbb.create_string("1970-01-01T00");
+        let entry = wb::WriteBufferEntry::create(
+            &mut fbb,
+            &wb::WriteBufferEntryArgs {
+                partition_key: Some(key),
+                table_batches: Some(table_batches),
+                ..Default::default()
             },
-            TestCase {
-                description: "Restrictions: measurement name, timestamp and predicate: no match",
-                column_name: "state",
-                predicate: PredicateBuilder::default()
-                    .table("o2")
-                    .timestamp_range(1, 300) // filters out the NY row
-                    .add_expr(make_column_eq_expr("state", "NY")) // state=NY
-                    .build(),
-                expected_column_values: Ok(vec![]),
+        );
+        let entries = fbb.create_vector(&[entry]);
+        let batch = wb::WriteBufferBatch::create(
+            &mut fbb,
+            &wb::WriteBufferBatchArgs {
+                entries: Some(entries),
             },
-        ];
+        );
+        fbb.finish(batch, None);
+        let write = data_types::data::batch_to_replicated_write(1, 1, fbb.finished_data());
+
+        let db = Db::new("mydb");
+        let err = db.store_replicated_write(&write).await.unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                Error::MissingTimestamps { table, count: 2, rows, .. }
+                    if table == "cpu" && rows == &[1, 2]
+            ),
+            "unexpected error: {}",
+            err
+        );
 
-        for test_case in test_cases.into_iter() {
-            let test_case_str = format!("{:#?}", test_case);
-            println!("Running test case: {:?}", test_case);
+        // nothing of the batch is written
+        assert_eq!(db.size().await, 0);
+        assert!(db.partition_keys().await.is_empty());
 
-            let column_values_plan = db
-                .column_values(test_case.column_name, test_case.predicate)
-                .await
-                .expect("Created tag_values plan successfully");
+        Ok(())
+    }
 
-            // run the execution plan (
-            let executor = Executor::default();
-            let actual_column_values = executor.to_string_set(column_values_plan).await;
+    #[tokio::test]
+    async fn replicated_writes_are_restored_from_wal() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
 
-            let is_match = if let Ok(expected_column_values) = &test_case.expected_column_values {
-                let expected_column_values = to_set(expected_column_values);
-                if let Ok(actual_column_values) = &actual_column_values {
-                    **actual_column_values == expected_column_values
-                } else {
-                    false
-                }
-            } else if let Err(e) = &actual_column_values {
-                // use string compare to compare errors to avoid having to build exact errors
-                format!("{:?}", e) == format!("{:?}", test_case.expected_column_values)
-            } else {
-                false
-            };
+        {
+            let db = Db::try_with_wal("replica_db", &mut dir).await?;
+            let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10\ncpu,host=b usage=2 20")
+                .map(|l| l.unwrap())
+                .collect();
+            let batch = split_lines_into_write_entry_partitions(|_| "p1".to_string(), &lines);
+            let write = data_types::data::batch_to_replicated_write(1, 1, &batch);
+            db.store_replicated_write(&write).await?;
+            assert_eq!(db.partition_keys().await, vec!["p1"]);
+        }
 
-            assert!(
-                is_match,
-                "Mismatch\n\
-                     actual_column_values: \n\
-                     {:?}\n\
-                     expected_column_values: \n\
-                     {:?}\n\
-                     Test_case: \n\
-                     {}",
-                actual_column_values, test_case.expected_column_values, test_case_str
-            );
+        let db = Db::restore_from_wal(&dir).await?;
+        assert_eq!(db.partition_keys().await, vec!["p1"]);
+
+        let expected = r#"+------+-------+------+
+| host | usage | time |
++------+-------+------+
+| a    | 1     | 10   |
+| b    | 2     | 20   |
++------+-------+------+
+"#;
+        let partitions = db.table_to_arrow("cpu", &["host", "usage", "time"]).await?;
+        assert_table_eq(expected, &partitions);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rules_are_restored_from_wal() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        let rules = DatabaseRules {
+            mutable_buffer_size: Some(1024 * 1024),
+            retention_period_seconds: Some(3600),
+            ..Default::default()
+        };
+
+        {
+            let db = Db::try_with_wal("rules_db", &mut dir).await?;
+            assert_eq!(db.rules().await, DatabaseRules::default());
+
+            db.set_rules(rules.clone()).await?;
+            assert_eq!(db.rules().await, rules);
         }
 
+        let db = Db::restore_from_wal(&dir).await?;
+        assert_eq!(db.rules().await, rules);
+
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_query_series() -> Result {
-        // This test checks that everything is wired together
-        // correctly.  There are more detailed tests in table.rs that
-        // test the generated queries.
+    async fn replay_wal_in_stages() -> Result {
         let mut dir = test_helpers::tmp_dir()?.into_path();
-        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
 
-        let mut lp_lines = vec![
-            "h2o,state=MA,city=Boston temp=70.4 100", // to row 2
-            "h2o,state=MA,city=Boston temp=72.4 250", // to row 1
-            "h2o,state=CA,city=LA temp=90.0 200",     // to row 0
-            "h2o,state=CA,city=LA temp=90.0 350",     // to row 3
-            "o2,state=MA,city=Boston temp=50.4,reading=50 100", // to row 5
-            "o2,state=MA,city=Boston temp=53.4,reading=51 250", // to row 4
-        ];
+        {
+            let db = Db::try_with_wal("replay_db", &mut dir).await?;
+            for lp in &[
+                "cpu,host=a usage=1 0",
+                "cpu,host=a usage=2 3600000000000",
+                "cpu,host=a usage=3 10",
+            ] {
+                let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+                db.write_lines(&lines).await?;
+            }
+        }
 
-        // Swap around  data is not inserted in series order
-        lp_lines.swap(0, 2);
-        lp_lines.swap(4, 5);
+        let db = Db::open_from_wal(&dir).await?;
+        assert!(db.is_empty().await);
+        assert_eq!(db.replay_progress(), Some(ReplayProgress::default()));
 
-        let lp_data = lp_lines.join("\n");
+        let lines: Vec<_> = parse_lines("cpu,host=b usage=4 20")
+            .map(|l| l.unwrap())
+            .collect();
+        let err = db.write_lines(&lines).await.unwrap_err();
+        assert!(matches!(err, Error::Replaying { .. }));
+
+        let start = std::time::Instant::now();
+        let reported = std::sync::Mutex::new(vec![]);
+        let options = ReplayOptions {
+            max_entries_per_second: Some(100),
+        };
+        db.replay_wal(options, |progress| {
+            reported.lock().unwrap().push((
+                progress.entries_applied,
+                progress.entries_remaining(),
+                progress.partitions_replayed,
+            ))
+        })
+        .await?;
+        assert!(start.elapsed() >= Duration::from_millis(30));
+
+        // the second partition is replayed once the second entry is
+        // applied, before the first, which is written to again by the
+        // third entry
+        assert_eq!(
+            reported.into_inner().unwrap(),
+            vec![(0, 3, 0), (1, 2, 0), (2, 1, 1), (3, 0, 2), (3, 0, 2)]
+        );
+        assert_eq!(db.replay_progress(), None);
+        assert_eq!(
+            db.partition_keys().await,
+            vec!["1970-01-01T00", "1970-01-01T01"]
+        );
 
-        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
         db.write_lines(&lines).await?;
+        let expected = r#"+------+-------+---------------+
+| host | usage | time          |
++------+-------+---------------+
+| a    | 1     | 0             |
+| a    | 3     | 10            |
+| b    | 4     | 20            |
+| a    | 2     | 3600000000000 |
++------+-------+---------------+
+"#;
+        let partitions = db.table_to_arrow("cpu", &["host", "usage", "time"]).await?;
+        assert_table_eq(expected, &partitions);
 
-        let predicate = Predicate::default();
+        Ok(())
+    }
 
-        let plans = db
-            .query_series(predicate)
-            .await
-            .expect("Created tag_values plan successfully");
+    #[tokio::test]
+    async fn resource_usage() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
 
-        let results = run_and_gather_results(plans).await;
+        let wal_bytes = {
+            let db = Db::try_with_wal("usage_db", &mut dir).await?;
+            assert_eq!(db.wal_bytes(), 0);
+            assert_eq!(db.max_tag_cardinality().await, 0);
+
+            let lines: Vec<_> = parse_lines(
+                "cpu,host=a,region=west usage=1 0\n\
+                 cpu,host=b,region=west usage=2 0\n\
+                 cpu,host=c,region=east usage=3 3600000000000\n\
+                 mem,host=a free=4 0",
+            )
+            .map(|l| l.unwrap())
+            .collect();
+            db.write_lines(&lines).await?;
 
-        assert_eq!(results.len(), 3);
+            // the values of a tag are counted once across partitions
+            assert_eq!(db.max_tag_cardinality().await, 3);
+            assert!(db.wal_bytes() > 0);
+            db.wal_bytes()
+        };
 
-        let series_set0 = results[0].as_ref().expect("Correctly converted");
-        assert_eq!(*series_set0.table_name, "h2o");
+        let db = Db::restore_from_wal(&dir).await?;
+        assert_eq!(db.wal_bytes(), wal_bytes);
+        assert_eq!(db.max_tag_cardinality().await, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn drop_and_truncate_partitions() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+
+        {
+            let db = Db::try_with_wal("partition_db", &mut dir).await?;
+            let lines: Vec<_> = parse_lines(
+                "cpu,host=a usage=1 0\n\
+                 mem,host=a free=2 0\n\
+                 cpu,host=a usage=3 3600000000000\n\
+                 cpu,host=a usage=5 7200000000000",
+            )
+            .map(|l| l.unwrap())
+            .collect();
+            db.write_lines(&lines).await?;
+            assert_eq!(
+                db.partition_keys().await,
+                vec!["1970-01-01T00", "1970-01-01T01", "1970-01-01T02"]
+            );
+
+            // a dry run reports what would be removed without removing it
+            let summary = db.drop_partition("1970-01-01T00", true).await?;
+            assert_eq!(summary.key, "1970-01-01T00");
+            assert_eq!(summary.tables, vec!["cpu", "mem"]);
+            assert_eq!(summary.row_count, 2);
+            assert!(summary.size > 0);
+            assert_eq!(db.len().await, 3);
+
+            db.close_chunk("1970-01-01T00", 0).await?;
+            db.persist_chunk("1970-01-01T00", 0).await?;
+            let chunk_dir = persisted_chunk_dir(&dir, "1970-01-01T00", 0);
+            assert!(chunk_dir.exists());
+
+            // the files of the dropped partition are removed with it
+            let generation = db.generation();
+            assert_eq!(db.drop_partition("1970-01-01T00", false).await?, summary);
+            assert!(db.generation() > generation);
+            assert!(!chunk_dir.exists());
+
+            let summary = db.truncate_partition("1970-01-01T01", false).await?;
+            assert_eq!(summary.row_count, 1);
+            assert_eq!(
+                db.partition_keys().await,
+                vec!["1970-01-01T01", "1970-01-01T02"]
+            );
+            assert_eq!(
+                db.truncate_partition("1970-01-01T01", true)
+                    .await?
+                    .row_count,
+                0
+            );
+
+            let err = db.drop_partition("1970-01-01T00", true).await.unwrap_err();
+            assert!(matches!(err, Error::PartitionNotFound { .. }));
+
+            // data written to a dropped partition key after the drop is kept
+            let lines: Vec<_> = parse_lines("cpu,host=a usage=7 10")
+                .map(|l| l.unwrap())
+                .collect();
+            db.write_lines(&lines).await?;
+        }
+
+        let db = Db::restore_from_wal(&dir).await?;
         assert_eq!(
-            series_set0.tags,
-            str_pair_vec_to_vec(&[("city", "Boston"), ("state", "MA")])
+            db.partition_keys().await,
+            vec!["1970-01-01T00", "1970-01-01T02"]
         );
-        assert_eq!(series_set0.timestamp_index, 3);
-        assert_eq!(series_set0.field_indices, Arc::new(vec![2]));
-        assert_eq!(series_set0.start_row, 0);
-        assert_eq!(series_set0.num_rows, 2);
 
-        let series_set1 = results[1].as_ref().expect("Correctly converted");
-        assert_eq!(*series_set1.table_name, "h2o");
+        let expected = r#"+------+-------+---------------+
+| host | usage | time          |
++------+-------+---------------+
+| a    | 7     | 10            |
+| a    | 5     | 7200000000000 |
++------+-------+---------------+
+"#;
+        let partitions = db.table_to_arrow("cpu", &["host", "usage", "time"]).await?;
+        assert_table_eq(expected, &partitions);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn list_chunks() -> Result {
+        let db = Db::new("mydb");
+        let lines: Vec<_> = parse_lines(
+            "cpu,host=a usage=1 3600000000010\n\
+             mem,host=a free=2i 3600000000020\n\
+             cpu,host=b usage=3,str=\"foo\" 30",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+
+        let chunks = db.chunks().await;
+        assert_eq!(chunks.len(), 2);
+
+        let chunk = &chunks[0];
+        assert_eq!(chunk.partition_key, "1970-01-01T00");
+        assert_eq!(chunk.id, 0);
+        assert_eq!(chunk.storage, ChunkStorage::MutableBuffer);
+        assert_eq!(chunk.state, ChunkState::Open);
+        assert_eq!(chunk.row_count, 1);
+        assert_eq!((chunk.min_time, chunk.max_time), (Some(30), Some(30)));
         assert_eq!(
-            series_set1.tags,
-            str_pair_vec_to_vec(&[("city", "LA"), ("state", "CA")])
+            chunk.column_sizes.keys().collect::<Vec<_>>(),
+            vec!["host", "str", "time", "usage"]
         );
-        assert_eq!(series_set1.timestamp_index, 3);
-        assert_eq!(series_set1.field_indices, Arc::new(vec![2]));
-        assert_eq!(series_set1.start_row, 2);
-        assert_eq!(series_set1.num_rows, 2);
+        assert!(chunk.column_sizes["str"] > chunk.column_sizes["usage"]);
+        assert_eq!(chunk.table_names, vec!["cpu"]);
 
-        let series_set2 = results[2].as_ref().expect("Correctly converted");
-        assert_eq!(*series_set2.table_name, "o2");
+        let chunk = &chunks[1];
+        assert_eq!(chunk.partition_key, "1970-01-01T01");
+        assert_eq!(chunk.row_count, 2);
         assert_eq!(
-            series_set2.tags,
-            str_pair_vec_to_vec(&[("city", "Boston"), ("state", "MA")])
+            (chunk.min_time, chunk.max_time),
+            (Some(3600000000010), Some(3600000000020))
         );
-        assert_eq!(series_set2.timestamp_index, 4);
-        assert_eq!(series_set2.field_indices, Arc::new(vec![2, 3]));
-        assert_eq!(series_set2.start_row, 0);
-        assert_eq!(series_set2.num_rows, 2);
+        assert_eq!(
+            chunk.column_sizes.keys().collect::<Vec<_>>(),
+            vec!["free", "host", "time", "usage"]
+        );
+        assert!(chunk.size >= chunk.column_sizes.values().sum::<u64>());
+        assert_eq!(chunk.table_names, vec!["cpu", "mem"]);
+        assert!(chunk.last_write_time.is_some());
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_query_series_filter() -> Result {
-        // check the appropriate filters are applied in the datafusion plans
+    async fn force_chunk_operations() -> Result {
         let mut dir = test_helpers::tmp_dir()?.into_path();
-        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
+        let db = Db::try_with_wal("chunk_db", &mut dir).await?;
 
-        let lp_lines = vec![
-            "h2o,state=MA,city=Boston temp=70.4 100",
-            "h2o,state=MA,city=Boston temp=72.4 250",
-            "h2o,state=CA,city=LA temp=90.0 200",
-            "h2o,state=CA,city=LA temp=90.0 350",
-            "o2,state=MA,city=Boston temp=50.4,reading=50 100",
-            "o2,state=MA,city=Boston temp=53.4,reading=51 250",
-        ];
+        let write = |lp: &'static str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let db = &db;
+            async move { db.write_lines(&lines).await }
+        };
 
-        let lp_data = lp_lines.join("\n");
+        write(
+            "cpu,host=a usage=1 10
+mem,host=a free=2i 20",
+        )
+        .await?;
 
-        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
-        db.write_lines(&lines).await?;
+        let err = db.persist_chunk("1970-01-01T00", 0).await.unwrap_err();
+        assert!(matches!(err, Error::ChunkNotClosed { .. }));
 
-        // filter out one row in h20
-        let predicate = PredicateBuilder::default()
-            .timestamp_range(200, 300)
-            .add_expr(make_column_eq_expr("state", "CA")) // state=CA
-            .build();
+        let chunk = db.close_chunk("1970-01-01T00", 0).await?;
+        assert_eq!(chunk.state, ChunkState::Closed);
+        assert_eq!(chunk.row_count, 2);
 
-        let plans = db
-            .query_series(predicate)
+        let err = db.close_chunk("1970-01-01T00", 0).await.unwrap_err();
+        assert!(matches!(err, Error::ChunkAlreadyClosed { .. }));
+        let err = db.close_chunk("1970-01-01T00", 5).await.unwrap_err();
+        assert!(matches!(err, Error::ChunkNotFound { .. }));
+
+        // writes after the chunk is closed go to a new chunk
+        write("cpu,host=b usage=3 30").await?;
+        let chunks = db.chunks().await;
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.id, c.state, c.row_count))
+                .collect::<Vec<_>>(),
+            vec![(0, ChunkState::Closed, 2), (1, ChunkState::Open, 1)]
+        );
+        assert_eq!(db.partition_keys().await, vec!["1970-01-01T00"]);
+
+        let chunk = db.persist_chunk("1970-01-01T00", 0).await?;
+        assert_eq!(chunk.state, ChunkState::Persisted);
+        let chunk_dir = dir
+            .join(PERSISTED_CHUNKS_DIR_NAME)
+            .join("1970-01-01T00")
+            .join("0");
+        assert!(chunk_dir.join("cpu.parquet").exists());
+        assert!(chunk_dir.join("mem.parquet").exists());
+
+        let chunk = db.compact_partition("1970-01-01T00", None).await?;
+        assert_eq!(chunk.id, 2);
+        assert_eq!(chunk.state, ChunkState::Closed);
+        assert_eq!(chunk.row_count, 3);
+        assert_eq!((chunk.min_time, chunk.max_time), (Some(10), Some(30)));
+        assert_eq!(db.chunks().await, vec![chunk]);
+
+        let err = db
+            .compact_partition("1970-01-01T05", None)
             .await
-            .expect("Created tag_values plan successfully");
+            .unwrap_err();
+        assert!(matches!(err, Error::PartitionNotFound { .. }));
+
+        let expected = r#"+------+-------+------+
+| host | usage | time |
++------+-------+------+
+| a    | 1     | 10   |
+| b    | 3     | 30   |
++------+-------+------+
+"#;
+        let partitions = db.table_to_arrow("cpu", &["host", "usage", "time"]).await?;
+        assert_table_eq(expected, &partitions);
+
+        // the compacted chunk is persisted with the encodings chosen
+        // for its columns
+        db.persist_chunk("1970-01-01T00", 2).await?;
+        let encodings = db.partitions.read().await[0].encodings["cpu"].clone();
+        let persisted = db.open_persisted_chunk("1970-01-01T00", 2, OpenMode::Read)?;
+        let columns = persisted.table("cpu").unwrap().columns();
+        assert_eq!(columns.len(), 3);
+        for column in columns {
+            assert_eq!(column.encoding, Some(encodings[&column.name]));
+            assert!(column.uncompressed_bytes > 0);
+        }
 
-        let results = run_and_gather_results(plans).await;
+        Ok(())
+    }
 
-        assert_eq!(results.len(), 1);
+    #[tokio::test]
+    async fn persisted_chunks_are_copied_to_the_object_store() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let store = Arc::new(ObjectStore::new_in_memory(object_store::InMemory::new()));
+        let db = Db::try_with_wal("chunk_db", &mut dir)
+            .await?
+            .with_object_store(Arc::clone(&store));
 
-        let series_set0 = results[0].as_ref().expect("Correctly converted");
-        assert_eq!(*series_set0.table_name, "h2o");
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10\nmem,host=a free=2i 20")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
+        db.close_chunk("1970-01-01T00", 0).await?;
+        db.persist_chunk("1970-01-01T00", 0).await?;
+
+        let list = || async {
+            let mut locations: Vec<String> = store.list(None).await?.try_concat().await?;
+            locations.sort();
+            Ok::<_, TestError>(locations)
+        };
+        let copied = list().await?;
+        assert!(copied.contains(&"chunk_db/chunks/1970-01-01T00/0/cpu.parquet".to_string()));
+        assert!(copied.contains(&"chunk_db/chunks/1970-01-01T00/0/mem.parquet".to_string()));
+        assert!(copied
+            .iter()
+            .all(|location| location.starts_with("chunk_db/chunks/1970-01-01T00/0/")));
+
+        // the copies are removed in the background once the chunk is
+        // dropped
+        db.drop_partition("1970-01-01T00", false).await?;
+        for _ in 0..100 {
+            if list().await?.is_empty() {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        assert_eq!(list().await?, Vec::<String>::new());
+
+        Ok(())
+    }
+
+    /// Returns a pacer which, the first time it is called, signals the
+    /// returned receiver and blocks until the returned sender is sent to
+    fn paused_pacer() -> (
+        Pacer,
+        tokio::sync::oneshot::Receiver<()>,
+        std::sync::mpsc::Sender<()>,
+    ) {
+        let (started_tx, started_rx) = tokio::sync::oneshot::channel();
+        let (resume_tx, resume_rx) = std::sync::mpsc::channel();
+        let started_tx = std::sync::Mutex::new(Some(started_tx));
+        let resume_rx = std::sync::Mutex::new(resume_rx);
+
+        let pace: Pacer = Arc::new(move |_| {
+            if let Some(started_tx) = started_tx.lock().expect("mutex poisoned").take() {
+                started_tx.send(()).ok();
+                resume_rx.lock().expect("mutex poisoned").recv().ok();
+            }
+        });
+        (pace, started_rx, resume_tx)
+    }
+
+    #[tokio::test]
+    async fn compaction_does_not_block_writes() -> Result {
+        let db = Db::new("foo");
+        let write = |lp: &'static str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let db = &db;
+            async move { db.write_lines(&lines).await }
+        };
+
+        write("cpu,host=a usage=1 10").await?;
+        db.close_chunk("1970-01-01T00", 0).await?;
+
+        // chunks created while the partition is compacted are kept
+        let (pace, started, resume) = paused_pacer();
+        let (chunk, written) =
+            tokio::join!(db.compact_partition("1970-01-01T00", Some(pace)), async {
+                started.await.ok();
+                let written = write("cpu,host=b usage=2 20").await;
+                resume.send(()).ok();
+                written
+            });
+        written?;
+        let chunk = chunk?;
+        assert_eq!(chunk.id, 2);
+        assert_eq!(chunk.row_count, 1);
+        let chunks = db.chunks().await;
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.id, c.state, c.row_count))
+                .collect::<Vec<_>>(),
+            vec![(1, ChunkState::Open, 1), (2, ChunkState::Closed, 1)]
+        );
+
+        // but if a compacted chunk is written to, nothing is replaced
+        let (pace, started, resume) = paused_pacer();
+        let (compacted, written) =
+            tokio::join!(db.compact_partition("1970-01-01T00", Some(pace)), async {
+                started.await.ok();
+                let written = write("cpu,host=c usage=3 30").await;
+                resume.send(()).ok();
+                written
+            });
+        written?;
+        assert!(matches!(
+            compacted.unwrap_err(),
+            Error::CompactedChunksChanged { .. }
+        ));
+        let chunks = db.chunks().await;
         assert_eq!(
-            series_set0.tags,
-            str_pair_vec_to_vec(&[("city", "LA"), ("state", "CA")])
+            chunks
+                .iter()
+                .map(|c| (c.id, c.row_count))
+                .collect::<Vec<_>>(),
+            vec![(1, 2), (2, 1)]
         );
-        assert_eq!(series_set0.timestamp_index, 3);
-        assert_eq!(series_set0.field_indices, Arc::new(vec![2]));
-        assert_eq!(series_set0.start_row, 0);
-        assert_eq!(series_set0.num_rows, 1); // only has one row!
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_query_series_pred_refers_to_column_not_in_table() -> Result {
+    async fn persist_idle_partitions() -> Result {
         let mut dir = test_helpers::tmp_dir()?.into_path();
-        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
+        let db = Db::try_with_wal("idle_db", &mut dir).await?;
 
-        let lp_lines = vec![
-            "h2o,state=MA,city=Boston temp=70.4 100",
-            "h2o,state=MA,city=Boston temp=72.4 250",
-        ];
+        let write = |lp: &'static str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let db = &db;
+            async move { db.write_lines(&lines).await }
+        };
+        write("cpu,host=a usage=1 10\nmem,host=a free=2i 3600000000010").await?;
 
-        let lp_data = lp_lines.join("\n");
+        let hour = 3_600_000_000_000;
+        let now = Utc::now().timestamp_nanos();
+        // nothing is evicted without an idle period
+        assert_eq!(db.persist_idle(now + 2 * hour).await?, 0);
 
-        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
-        db.write_lines(&lines).await?;
+        db.set_rules(DatabaseRules {
+            persist_idle_seconds: Some(3600),
+            ..Default::default()
+        })
+        .await?;
+        assert_eq!(db.persist_idle(now).await?, 0);
 
-        let predicate = PredicateBuilder::default()
-            .add_expr(make_column_eq_expr("tag_not_in_h20", "foo"))
-            .build();
+        // one partition is written to again, so only the other is idle
+        // an hour later
+        db.partitions
+            .write()
+            .await
+            .iter_mut()
+            .find(|p| p.key == "1970-01-01T01")
+            .unwrap()
+            .last_write_time = Some(now + hour);
+        assert_eq!(db.persist_idle(now + hour + 1).await?, 1);
+        assert_eq!(
+            db.partition_keys().await,
+            vec!["1970-01-01T01", "1970-01-01T00"]
+        );
+        assert!(dir
+            .join(PERSISTED_CHUNKS_DIR_NAME)
+            .join("1970-01-01T00")
+            .join("0")
+            .join("cpu.parquet")
+            .exists());
+
+        let chunks = db.chunks().await;
+        assert_eq!(
+            chunks
+                .iter()
+                .map(|c| (c.partition_key.as_str(), c.id, c.storage, c.state))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "1970-01-01T00",
+                    0,
+                    ChunkStorage::ObjectStore,
+                    ChunkState::Persisted
+                ),
+                (
+                    "1970-01-01T01",
+                    0,
+                    ChunkStorage::MutableBuffer,
+                    ChunkState::Open
+                ),
+            ]
+        );
 
-        let plans = db
-            .query_series(predicate)
+        // evicted chunks are read back from their files by queries
+        let expected = r#"+------+-------+------+
+| host | usage | time |
++------+-------+------+
+| a    | 1     | 10   |
++------+-------+------+
+"#;
+        let results = db.query("SELECT host, usage, time FROM cpu").await?;
+        assert_table_eq(expected, &results);
+        assert_eq!(
+            db.partition_line_protocol("1970-01-01T00").await?,
+            "cpu,host=a usage=1 10\n"
+        );
+
+        // later writes to the partition go to a new chunk
+        write("cpu,host=b usage=3 30").await?;
+        let chunk_ids: Vec<_> = db
+            .chunks()
             .await
-            .expect("Created tag_values plan successfully");
+            .into_iter()
+            .filter(|c| c.partition_key == "1970-01-01T00")
+            .map(|c| (c.id, c.storage))
+            .collect();
+        assert_eq!(
+            chunk_ids,
+            vec![
+                (0, ChunkStorage::ObjectStore),
+                (1, ChunkStorage::MutableBuffer)
+            ]
+        );
 
-        let results = run_and_gather_results(plans).await;
-        assert!(results.is_empty());
+        // evicted data is not restored from the WAL, but is still read
+        // from its files, here memory mapped
+        drop(db);
+        let db = Db::open_from_wal(&dir)
+            .await?
+            .with_persisted_open_mode(OpenMode::Mmap);
+        db.replay_wal(ReplayOptions::default(), |_| {}).await?;
+        assert_eq!(db.len().await, 2);
+        let expected = r#"+------+-------+------+
+| host | usage | time |
++------+-------+------+
+| a    | 1     | 10   |
+| b    | 3     | 30   |
++------+-------+------+
+"#;
+        let results = db
+            .query("SELECT host, usage, time FROM cpu ORDER BY time")
+            .await?;
+        assert_table_eq(expected, &results);
+        assert_eq!(db.chunks().await.len(), 3);
+
+        // the files of evicted chunks are removed when their partition
+        // is dropped
+        let chunk_dir = persisted_chunk_dir(&dir, "1970-01-01T00", 0);
+        assert!(chunk_dir.exists());
+        db.drop_partition("1970-01-01T00", false).await?;
+        assert!(!chunk_dir.exists());
 
-        // predicate with no columns,
-        let predicate = PredicateBuilder::default()
-            .add_expr(make_no_column_expr())
-            .build();
+        Ok(())
+    }
 
-        let plans = db
-            .query_series(predicate)
-            .await
-            .expect("Created tag_values plan successfully");
+    #[tokio::test]
+    async fn writes_limited_to_buffer_size() -> Result {
+        let db = Db::new("mydb");
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10")
+            .map(|l| l.unwrap())
+            .collect();
+        db.write_lines(&lines).await?;
 
-        let results = run_and_gather_results(plans).await;
-        assert_eq!(results.len(), 1);
+        let size = db.size().await as u64;
+        assert!(size > 0);
 
-        // predicate with both a column that does and does not appear
-        let predicate = PredicateBuilder::default()
-            .add_expr(make_column_eq_expr("state", "MA"))
-            .add_expr(make_column_eq_expr("tag_not_in_h20", "foo"))
-            .build();
+        db.set_rules(DatabaseRules {
+            mutable_buffer_size: Some(size),
+            ..Default::default()
+        })
+        .await?;
 
-        let plans = db
-            .query_series(predicate)
-            .await
-            .expect("Created tag_values plan successfully");
+        let err = db.write_lines(&lines).await.unwrap_err();
+        assert!(
+            matches!(err, Error::BufferSizeExceeded { limit, .. } if limit == size),
+            "unexpected error: {}",
+            err
+        );
+        assert_eq!(db.size().await as u64, size);
 
-        let results = run_and_gather_results(plans).await;
-        assert!(results.is_empty());
+        // raising the limit allows writes again
+        db.set_rules(DatabaseRules::default()).await?;
+        db.write_lines(&lines).await?;
 
         Ok(())
     }
 
     #[tokio::test]
-    #[should_panic(
-        expected = "Unsupported binary operator in expression: #state NotEq Utf8(\"MA\")"
-    )]
-    async fn test_query_series_pred_neq() {
-        let mut dir = test_helpers::tmp_dir().unwrap().into_path();
-        let db = Db::try_with_wal("column_namedb", &mut dir).await.unwrap();
-
-        let lp_lines = vec![
-            "h2o,state=MA,city=Boston temp=70.4 100",
-            "h2o,state=MA,city=Boston temp=72.4 250",
-        ];
-
-        let lp_data = lp_lines.join("\n");
+    async fn writes_outside_timestamp_bounds() -> Result {
+        let db = Db::new("mydb");
+        let future = Utc::now().timestamp_nanos() + 2 * 86_400_000_000_000;
+        let lp = format!("cpu,host=a usage=1 10\ncpu,host=b usage=2 {}", future);
+        let lines: Vec<_> = parse_lines(&lp).map(|l| l.unwrap()).collect();
+
+        // by default timestamps more than a day ahead are rejected
+        let err = db.write_lines(&lines).await.unwrap_err();
+        assert!(
+            matches!(err, Error::TimestampOutOfBounds { timestamp, .. } if timestamp == future),
+            "unexpected error: {}",
+            err
+        );
+        assert_eq!(db.size().await, 0);
 
-        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
-        db.write_lines(&lines).await.unwrap();
+        db.set_rules(DatabaseRules {
+            timestamp_bounds: TimestampBounds {
+                min_nanos: Some(20),
+                max_future_seconds: None,
+                policy: TimestampPolicy::Clamp,
+            },
+            ..Default::default()
+        })
+        .await?;
+        db.write_lines(&lines).await?;
 
-        let predicate = PredicateBuilder::default()
-            .add_expr(make_column_neq_expr("state", "MA"))
-            .build();
+        let times: Vec<_> = db
+            .chunks()
+            .await
+            .iter()
+            .map(|chunk| (chunk.min_time, chunk.max_time))
+            .collect();
+        assert_eq!(
+            times,
+            vec![(Some(20), Some(20)), (Some(future), Some(future))]
+        );
 
-        // Should panic as the neq path isn't implemented yet
-        db.query_series(predicate).await.unwrap();
+        Ok(())
     }
 
     #[tokio::test]
-    async fn test_field_columns() -> Result {
-        // Ensure that the database queries are hooked up correctly
-
-        let mut dir = test_helpers::tmp_dir()?.into_path();
-        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
+    async fn writes_of_non_finite_floats() -> Result {
+        let db = Db::new("mydb");
+        let lines: Vec<_> = parse_lines("cpu,host=a usage=1 10\ncpu,host=b usage=NaN,temp=-inf 20")
+            .map(|l| l.unwrap())
+            .collect();
 
-        let lp_data = vec![
-            "h2o,state=MA,city=Boston temp=70.4 50",
-            "h2o,state=MA,city=Boston other_temp=70.4 250",
-            "h2o,state=CA,city=Boston other_temp=72.4 350",
-            "o2,state=MA,city=Boston temp=53.4,reading=51 50",
-        ]
-        .join("\n");
+        // by default the whole write is rejected
+        let err = db.write_lines(&lines).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Write to database mydb rejected: field usage of measurement cpu is NaN, \
+             which is not a finite number"
+        );
+        assert_eq!(db.size().await, 0);
 
-        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
+        db.set_rules(DatabaseRules {
+            non_finite_floats: NonFiniteFloatPolicy::Store,
+            ..Default::default()
+        })
+        .await?;
         db.write_lines(&lines).await?;
 
-        // write a new lp_line that is in a new day and thus a new partition
-        let nanoseconds_per_day: i64 = 1_000_000_000 * 60 * 60 * 24;
+        let results = db
+            .query("select host, usage, temp from cpu order by host")
+            .await?;
+        let expected_cpu_table = r#"+------+-------+------+
+| host | usage | temp |
++------+-------+------+
+| a    | 1     |      |
+| b    | NaN   | -inf |
++------+-------+------+
+"#;
+        assert_table_eq(expected_cpu_table, &results);
 
-        let lp_data = vec![format!(
-            "h2o,state=MA,city=Boston temp=70.4,moisture=43.0 {}",
-            nanoseconds_per_day * 10
-        )]
-        .join("\n");
-        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
-        db.write_lines(&lines).await?;
+        Ok(())
+    }
 
-        // ensure there are 2 partitions
-        assert_eq!(db.len().await, 2);
+    #[tokio::test]
+    async fn retention_drops_old_partitions() -> Result {
+        let db = Db::new("mydb");
 
-        // setup to run the execution plan (
-        let executor = Executor::default();
+        // the first two rows are in one partition, the last in another
+        let lines: Vec<_> = parse_lines(
+            "cpu,host=a usage=1 0\n\
+             cpu,host=a usage=3 30000000000\n\
+             cpu,host=a usage=7 34200000000000",
+        )
+        .map(|l| l.unwrap())
+        .collect();
+        db.write_lines(&lines).await?;
+        assert_eq!(db.len().await, 2);
 
-        let predicate = PredicateBuilder::default()
-            .table("NoSuchTable")
-            .add_expr(make_column_eq_expr("state", "MA")) // state=MA
-            .build();
+        let now = 36_000_000_000_000;
 
-        // make sure table filtering works (no tables match)
-        let plan = db
-            .field_columns(predicate)
-            .await
-            .expect("Created field_columns plan successfully");
+        // no retention period means nothing is dropped
+        assert_eq!(db.apply_retention(now).await?, 0);
 
-        let fieldlists = executor
-            .to_fieldlist(plan)
-            .await
-            .expect("Running fieldlist plan");
-        assert!(fieldlists.fields.is_empty());
+        db.set_rules(DatabaseRules {
+            retention_period_seconds: Some(3600),
+            ..Default::default()
+        })
+        .await?;
+
+        let generation = db.generation();
+        assert_eq!(db.apply_retention(now).await?, 1);
+        assert!(db.generation() > generation);
+        assert_eq!(db.len().await, 1);
+
+        let expected = r#"+------+-------+----------------+
+| host | usage | time           |
++------+-------+----------------+
+| a    | 7     | 34200000000000 |
++------+-------+----------------+
+"#;
+        let partitions = db.table_to_arrow("cpu", &["host", "usage", "time"]).await?;
+        assert_table_eq(expected, &partitions);
 
-        // get only fields from h20 (but both partitions)
-        let predicate = PredicateBuilder::default()
-            .table("h2o")
-            .add_expr(make_column_eq_expr("state", "MA")) // state=MA
-            .build();
+        Ok(())
+    }
 
-        let plan = db
-            .field_columns(predicate)
-            .await
-            .expect("Created field_columns plan successfully");
+    #[tokio::test]
+    async fn retention_drops_are_not_restored_from_wal() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
 
-        let actual = executor
-            .to_fieldlist(plan)
-            .await
-            .expect("Running fieldlist plan");
+        {
+            let db = Db::try_with_wal("retention_db", &mut dir).await?;
+            db.set_rules(DatabaseRules {
+                retention_period_seconds: Some(3600),
+                ..Default::default()
+            })
+            .await?;
+
+            let lines: Vec<_> = parse_lines(
+                "cpu,host=a usage=1 0\n\
+                 cpu,host=a usage=7 34200000000000",
+            )
+            .map(|l| l.unwrap())
+            .collect();
+            db.write_lines(&lines).await?;
+            assert_eq!(db.apply_retention(36_000_000_000_000).await?, 1);
+        }
 
-        let expected = FieldList {
-            fields: vec![
-                Field {
-                    name: "moisture".into(),
-                    data_type: DataType::Float64,
-                    last_timestamp: nanoseconds_per_day * 10,
-                },
-                Field {
-                    name: "other_temp".into(),
-                    data_type: DataType::Float64,
-                    last_timestamp: 250,
-                },
-                Field {
-                    name: "temp".into(),
-                    data_type: DataType::Float64,
-                    last_timestamp: nanoseconds_per_day * 10,
-                },
-            ],
-        };
+        let db = Db::restore_from_wal(&dir).await?;
+        assert_eq!(db.len().await, 1);
 
-        assert_eq!(
-            expected, actual,
-            "Expected:\n{:#?}\nActual:\n{:#?}",
-            expected, actual
-        );
+        let expected = r#"+------+-------+----------------+
+| host | usage | time           |
++------+-------+----------------+
+| a    | 7     | 34200000000000 |
++------+-------+----------------+
+"#;
+        let partitions = db.table_to_arrow("cpu", &["host", "usage", "time"]).await?;
+        assert_table_eq(expected, &partitions);
 
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_field_columns_timestamp_predicate() -> Result {
-        // check the appropriate filters are applied in the datafusion plans
+    async fn late_writes_go_to_late_chunks() -> Result {
         let mut dir = test_helpers::tmp_dir()?.into_path();
-        let db = Db::try_with_wal("column_namedb", &mut dir).await?;
-
-        let lp_data = vec![
-            "h2o,state=MA,city=Boston temp=70.4 50",
-            "h2o,state=MA,city=Boston other_temp=70.4 250",
-            "h2o,state=CA,city=Boston other_temp=72.4 350",
-            "o2,state=MA,city=Boston temp=53.4,reading=51 50",
-        ]
-        .join("\n");
-
-        let lines: Vec<_> = parse_lines(&lp_data).map(|l| l.unwrap()).collect();
-        db.write_lines(&lines).await?;
+        let db = Db::try_with_wal("late_db", &mut dir).await?;
+        db.set_rules(DatabaseRules {
+            late_write_seconds: Some(60),
+            ..Default::default()
+        })
+        .await?;
 
-        // setup to run the execution plan (
-        let executor = Executor::default();
+        let write = |lp: &'static str| {
+            let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+            let db = &db;
+            async move { db.write_lines(&lines).await }
+        };
+        let chunks = || {
+            let db = &db;
+            async move {
+                db.chunks()
+                    .await
+                    .iter()
+                    .map(|c| (c.id, c.state, c.row_count, c.min_time))
+                    .collect::<Vec<_>>()
+            }
+        };
 
-        let predicate = PredicateBuilder::default()
-            .table("h2o")
-            .timestamp_range(200, 300)
-            .add_expr(make_column_eq_expr("state", "MA")) // state=MA
-            .build();
+        write("cpu,host=a usage=1 3000000000000").await?;
+        write(
+            "cpu,host=a usage=2 10000000000
+cpu,host=a usage=3 20000000000
+cpu,host=a usage=4 130000000000
+cpu,host=a usage=5 2990000000000",
+        )
+        .await?;
 
-        let plan = db
-            .field_columns(predicate)
-            .await
-            .expect("Created field_columns plan successfully");
+        // rows more than a minute behind the open chunk go to a late
+        // chunk for their minute
+        assert_eq!(
+            chunks().await,
+            vec![
+                (0, ChunkState::Open, 2, Some(2_990_000_000_000)),
+                (1, ChunkState::Open, 2, Some(10_000_000_000)),
+                (2, ChunkState::Open, 1, Some(130_000_000_000)),
+            ]
+        );
 
-        let actual = executor
-            .to_fieldlist(plan)
-            .await
-            .expect("Running fieldlist plan");
+        write("cpu,host=a usage=6 30000000000").await?;
+        db.close_chunk("1970-01-01T00", 2).await?;
+        write("cpu,host=a usage=7 150000000000").await?;
 
-        // Should only have other_temp as a field
-        let expected = FieldList {
-            fields: vec![Field {
-                name: "other_temp".into(),
-                data_type: DataType::Float64,
-                last_timestamp: 250,
-            }],
-        };
+        // a closed late chunk is reopened for writes to its window
+        assert_eq!(
+            chunks().await,
+            vec![
+                (0, ChunkState::Open, 2, Some(2_990_000_000_000)),
+                (1, ChunkState::Open, 3, Some(10_000_000_000)),
+                (2, ChunkState::Open, 2, Some(130_000_000_000)),
+            ]
+        );
 
+        let chunk = db.compact_partition("1970-01-01T00", None).await?;
+        assert_eq!(chunk.id, 3);
+        assert_eq!(chunk.row_count, 7);
         assert_eq!(
-            expected, actual,
-            "Expected:\n{:#?}\nActual:\n{:#?}",
-            expected, actual
+            (chunk.min_time, chunk.max_time),
+            (Some(10_000_000_000), Some(3_000_000_000_000))
         );
 
+        let expected = r#"+-------+---------------+
+| usage | time          |
++-------+---------------+
+| 2     | 10000000000   |
+| 3     | 20000000000   |
+| 6     | 30000000000   |
+| 4     | 130000000000  |
+| 7     | 150000000000  |
+| 5     | 2990000000000 |
+| 1     | 3000000000000 |
++-------+---------------+
+"#;
+        let partitions = db.table_to_arrow("cpu", &["usage", "time"]).await?;
+        assert_table_eq(expected, &partitions);
+
         Ok(())
     }
 
@@ -2207,7 +6650,7 @@ disk bytes=23432323i 1600136510000000000",
         // setup to run the execution plan (
         let executor = Executor::default();
         executor
-            .to_series_set(plans, tx)
+            .to_series_set(plans, tx, &QueryStats::default())
             .await
             .expect("Running series set plan");
 
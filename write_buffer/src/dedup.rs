@@ -0,0 +1,415 @@
+//! This module removes duplicate rows from the data of a table that
+//! is spread over several chunks. A row is identified by its series
+//! (the values of its tag columns) and its timestamp. Rows with the
+//! same identity can be in the same chunk, or in chunks whose time
+//! ranges overlap.
+//!
+//! The rows of each chunk, or group of overlapping chunks, are merged
+//! in the order of their identity. As in InfluxDB, the rows with the
+//! same identity are merged field by field: each field has the last
+//! value written for it, from the chunk that comes later, or the later
+//! row of the same chunk, as if all the writes had gone to a single
+//! chunk. A row that doesn't have a field leaves the value written
+//! before it in place.
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use arrow_deps::arrow::{
+    array::{
+        Array, ArrayRef, BooleanArray, BooleanBuilder, Float64Array, Float64Builder, Int64Array,
+        Int64Builder, StringArray, StringBuilder, UInt64Array, UInt64Builder,
+    },
+    datatypes::{DataType, Field, Schema},
+    error::ArrowError as ArrowErrorType,
+    record_batch::RecordBatch,
+};
+use data_types::TIME_COLUMN_NAME;
+use snafu::{OptionExt, ResultExt, Snafu};
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Column {} has type {:?} in one chunk and {:?} in another",
+        column,
+        first,
+        second
+    ))]
+    ColumnTypeMismatch {
+        column: String,
+        first: DataType,
+        second: DataType,
+    },
+
+    #[snafu(display("Column {} has unsupported type {:?}", column, data_type))]
+    UnsupportedColumnType { column: String, data_type: DataType },
+
+    #[snafu(display("Column {} is not a {:?} column", column, data_type))]
+    UnexpectedColumnType { column: String, data_type: DataType },
+
+    #[snafu(display("Error building merged chunk: {}", source))]
+    ArrowError { source: ArrowErrorType },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns the data of `chunks`, which hold the data of one table in
+/// the order it was written, with the rows that have the same tag
+/// values (of the columns named in `tag_columns`) and timestamp merged
+/// into one, which has the last value written of each field.
+///
+/// Each chunk that does not overlap any other chunk, and each group
+/// of overlapping chunks, is merged into a single record batch,
+/// sorted by tag values and timestamp.
+pub fn deduplicate(
+    chunks: Vec<RecordBatch>,
+    tag_columns: &BTreeSet<String>,
+) -> Result<Vec<RecordBatch>> {
+    let ranges: Vec<_> = chunks.iter().map(time_range).collect();
+
+    overlapping_groups(&ranges)
+        .into_iter()
+        .map(|group| {
+            let group: Vec<_> = group.iter().map(|&index| &chunks[index]).collect();
+            merge(&group, tag_columns)
+        })
+        .collect()
+}
+
+/// Returns the smallest and largest timestamps in `chunk`, or None if
+/// it has no timestamps
+fn time_range(chunk: &RecordBatch) -> Option<(i64, i64)> {
+    let times = column_by_name(chunk, TIME_COLUMN_NAME)?
+        .as_any()
+        .downcast_ref::<Int64Array>()?;
+
+    (0..times.len())
+        .filter(|&row| !times.is_null(row))
+        .map(|row| times.value(row))
+        .fold(None, |range, time| match range {
+            None => Some((time, time)),
+            Some((min, max)) => Some((min.min(time), max.max(time))),
+        })
+}
+
+/// Groups the indexes of the time `ranges` of chunks so that the
+/// ranges in each group overlap each other, directly or by way of
+/// other ranges in the group, and not those of any other group.
+/// Chunks without a time range are in groups of their own.
+///
+/// The indexes in each group are in ascending order, as are the first
+/// indexes of the groups.
+pub fn overlapping_groups(ranges: &[Option<(i64, i64)>]) -> Vec<Vec<usize>> {
+    let mut by_start: Vec<_> = ranges
+        .iter()
+        .enumerate()
+        .filter_map(|(index, range)| range.map(|range| (range, index)))
+        .collect();
+    by_start.sort_unstable();
+
+    let mut groups: Vec<Vec<usize>> = vec![];
+    let mut group_end = None;
+    for ((start, end), index) in by_start {
+        match (groups.last_mut(), group_end) {
+            (Some(group), Some(max)) if start <= max => {
+                group.push(index);
+                group_end = Some(end.max(max));
+            }
+            _ => {
+                groups.push(vec![index]);
+                group_end = Some(end);
+            }
+        }
+    }
+
+    groups.extend(
+        ranges
+            .iter()
+            .enumerate()
+            .filter(|(_, range)| range.is_none())
+            .map(|(index, _)| vec![index]),
+    );
+
+    for group in &mut groups {
+        group.sort_unstable();
+    }
+    groups.sort_unstable_by_key(|group| group[0]);
+    groups
+}
+
+/// Merges the rows of `chunks` into one record batch with the columns
+/// of all of them, with one row for each identity
+fn merge(chunks: &[&RecordBatch], tag_columns: &BTreeSet<String>) -> Result<RecordBatch> {
+    let schema = merged_schema(chunks)?;
+
+    // the identity of each row, along with where it is. The rows are
+    // visited in the order they were written, so after a stable sort
+    // the rows of each identity are in the order they were written
+    let mut rows = vec![];
+    for (chunk_index, chunk) in chunks.iter().enumerate() {
+        let tags = tag_columns
+            .iter()
+            .map(|name| string_column(chunk, name))
+            .collect::<Result<Vec<_>>>()?;
+        let times = typed_column::<Int64Array>(chunk, TIME_COLUMN_NAME, &DataType::Int64)?;
+
+        for row in 0..chunk.num_rows() {
+            let tag_values: Vec<_> = tags
+                .iter()
+                .map(|tag| non_null(*tag, row).map(|tag| tag.value(row)))
+                .collect();
+            let time = non_null(times, row).map(|times| times.value(row));
+            rows.push(((tag_values, time), (chunk_index, row)));
+        }
+    }
+    rows.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    let mut merged: Vec<Vec<(usize, usize)>> = Vec::with_capacity(rows.len());
+    let mut last_identity = None;
+    for (identity, location) in rows {
+        match merged.last_mut() {
+            Some(locations) if last_identity.as_ref() == Some(&identity) => {
+                locations.push(location)
+            }
+            _ => {
+                merged.push(vec![location]);
+                last_identity = Some(identity);
+            }
+        }
+    }
+
+    let columns = schema
+        .fields()
+        .iter()
+        .map(|field| merge_column(chunks, field, &merged))
+        .collect::<Result<Vec<_>>>()?;
+
+    RecordBatch::try_new(Arc::new(schema), columns).context(ArrowError)
+}
+
+/// Returns the schema with the columns of all of `chunks`, sorted by
+/// name as the columns of each chunk are
+fn merged_schema(chunks: &[&RecordBatch]) -> Result<Schema> {
+    let mut fields: Vec<Field> = vec![];
+    for chunk in chunks {
+        for field in chunk.schema().fields() {
+            match fields.iter().find(|f| f.name() == field.name()) {
+                Some(existing) if existing.data_type() != field.data_type() => {
+                    return ColumnTypeMismatch {
+                        column: field.name(),
+                        first: existing.data_type().clone(),
+                        second: field.data_type().clone(),
+                    }
+                    .fail()
+                }
+                Some(_) => {}
+                None => fields.push(Field::new(field.name(), field.data_type().clone(), true)),
+            }
+        }
+    }
+    fields.sort_by(|a, b| a.name().cmp(b.name()));
+    Ok(Schema::new(fields))
+}
+
+/// Builds the column `field` of the merged record batch from the
+/// `rows` of `chunks` merged into each of its rows: the value of each
+/// row is the last non-null value of the rows merged into it. Rows of
+/// chunks without the column are null.
+fn merge_column(
+    chunks: &[&RecordBatch],
+    field: &Field,
+    rows: &[Vec<(usize, usize)>],
+) -> Result<ArrayRef> {
+    let name = field.name();
+    let data_type = field.data_type();
+
+    macro_rules! merge_typed {
+        ($array:ty, $builder:expr) => {{
+            let columns = chunks
+                .iter()
+                .map(|chunk| typed_column::<$array>(chunk, name, data_type))
+                .collect::<Result<Vec<_>>>()?;
+            let mut builder = $builder;
+            for locations in rows {
+                let value = locations.iter().rev().find_map(|&(chunk, row)| {
+                    non_null(columns[chunk], row).map(|column| column.value(row))
+                });
+                match value {
+                    Some(value) => builder.append_value(value),
+                    None => builder.append_null(),
+                }
+                .context(ArrowError)?;
+            }
+            Arc::new(builder.finish()) as ArrayRef
+        }};
+    }
+
+    Ok(match data_type {
+        DataType::Utf8 => merge_typed!(StringArray, StringBuilder::new(rows.len())),
+        DataType::Int64 => merge_typed!(Int64Array, Int64Builder::new(rows.len())),
+        DataType::UInt64 => merge_typed!(UInt64Array, UInt64Builder::new(rows.len())),
+        DataType::Float64 => merge_typed!(Float64Array, Float64Builder::new(rows.len())),
+        DataType::Boolean => merge_typed!(BooleanArray, BooleanBuilder::new(rows.len())),
+        _ => {
+            return UnsupportedColumnType {
+                column: name,
+                data_type: data_type.clone(),
+            }
+            .fail()
+        }
+    })
+}
+
+fn column_by_name<'a>(chunk: &'a RecordBatch, name: &str) -> Option<&'a ArrayRef> {
+    let index = chunk.schema().index_of(name).ok()?;
+    Some(chunk.column(index))
+}
+
+/// Returns the column `name` of `chunk` as an array of type `T`, or
+/// None if the chunk has no such column
+fn typed_column<'a, T: 'static>(
+    chunk: &'a RecordBatch,
+    name: &str,
+    data_type: &DataType,
+) -> Result<Option<&'a T>> {
+    column_by_name(chunk, name)
+        .map(|column| {
+            column
+                .as_any()
+                .downcast_ref::<T>()
+                .context(UnexpectedColumnType {
+                    column: name,
+                    data_type: data_type.clone(),
+                })
+        })
+        .transpose()
+}
+
+fn string_column<'a>(chunk: &'a RecordBatch, name: &str) -> Result<Option<&'a StringArray>> {
+    typed_column(chunk, name, &DataType::Utf8)
+}
+
+/// Returns `array` if it has a value at `row`
+fn non_null<A: Array>(array: Option<&A>, row: usize) -> Option<&A> {
+    array.filter(|array| !array.is_null(row))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::rewrite::lines_to_arrow;
+    use arrow_deps::arrow::util::pretty::pretty_format_batches;
+    use influxdb_line_protocol::parse_lines;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    fn chunk(lp: &str) -> RecordBatch {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        let mut tables = lines_to_arrow(&lines).unwrap();
+        assert_eq!(tables.len(), 1);
+        tables.remove(0).1
+    }
+
+    fn tags(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_overlapping_groups() {
+        assert_eq!(
+            overlapping_groups(&[Some((10, 20)), Some((30, 40)), None, Some((15, 30))]),
+            vec![vec![0, 1, 3], vec![2]]
+        );
+        assert_eq!(
+            overlapping_groups(&[Some((30, 40)), Some((10, 20)), Some((21, 29))]),
+            vec![vec![0], vec![1], vec![2]]
+        );
+        assert_eq!(
+            overlapping_groups(&[Some((10, 40)), Some((5, 5)), Some((20, 30))]),
+            vec![vec![0, 2], vec![1]]
+        );
+    }
+
+    #[test]
+    fn test_deduplicate_without_overlap() -> Result {
+        let chunks = vec![
+            chunk("cpu,host=a usage=1 10\ncpu,host=a usage=2 10\ncpu,host=b usage=3 10"),
+            chunk("cpu,host=a usage=4 20"),
+        ];
+        let deduplicated = deduplicate(chunks, &tags(&["host"]))?;
+
+        // chunks that don't overlap are deduplicated on their own
+        assert_eq!(deduplicated.len(), 2);
+        let expected = r#"+------+------+-------+
+| host | time | usage |
++------+------+-------+
+| a    | 10   | 2     |
+| b    | 10   | 3     |
++------+------+-------+
+"#;
+        assert_eq!(pretty_format_batches(&deduplicated[..1])?, expected);
+        assert_eq!(deduplicated[1].num_rows(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deduplicate_last_write_wins() -> Result {
+        let chunks = vec![
+            chunk("cpu,host=b usage=1 10\ncpu,host=a usage=2 10\ncpu,host=a usage=3 20"),
+            chunk("cpu,host=a usage=4,idle=5 20\ncpu,host=c usage=6 15"),
+            chunk("cpu,host=a usage=7 20\ncpu,host=a usage=8 30"),
+            chunk("cpu,host=a usage=9 100"),
+        ];
+        let deduplicated = deduplicate(chunks, &tags(&["host"]))?;
+        assert_eq!(deduplicated.len(), 2);
+
+        // the last value written of each field wins, so the row at 20
+        // keeps the idle of the second chunk
+        let expected = r#"+------+------+------+-------+
+| host | idle | time | usage |
++------+------+------+-------+
+| a    |      | 10   | 2     |
+| a    | 5    | 20   | 7     |
+| a    |      | 30   | 8     |
+| b    |      | 10   | 1     |
+| c    |      | 15   | 6     |
++------+------+------+-------+
+"#;
+        assert_eq!(pretty_format_batches(&deduplicated[..1])?, expected);
+        assert_eq!(deduplicated[1].num_rows(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deduplicate_merges_fields() -> Result {
+        let chunks = vec![
+            chunk("cpu,host=a usage=1 10\ncpu,host=a idle=2 10"),
+            chunk("cpu,host=a system=3 10\ncpu,host=a usage=4 10"),
+        ];
+        let deduplicated = deduplicate(chunks, &tags(&["host"]))?;
+
+        let expected = r#"+------+------+--------+------+-------+
+| host | idle | system | time | usage |
++------+------+--------+------+-------+
+| a    | 2    | 3      | 10   | 4     |
++------+------+--------+------+-------+
+"#;
+        assert_eq!(pretty_format_batches(&deduplicated)?, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deduplicate_type_mismatch() {
+        let chunks = vec![
+            chunk("cpu,host=a usage=1 10"),
+            chunk("cpu,host=a usage=2i 10"),
+        ];
+        let err = deduplicate(chunks, &tags(&["host"])).unwrap_err();
+        assert!(matches!(err, Error::ColumnTypeMismatch { .. }), "{}", err);
+    }
+}
@@ -1,6 +1,7 @@
 //! Contains a structure to map from strings to u32 symbols based on
 //! string interning.
 use snafu::{OptionExt, Snafu};
+use std::collections::BTreeSet;
 use string_interner::{
     backend::StringBackend, DefaultHashBuilder, DefaultSymbol, StringInterner, Symbol,
 };
@@ -59,6 +60,28 @@ impl Dictionary {
             .resolve(symbol)
             .context(DictionaryIdLookupError { id })
     }
+
+    /// The approximate number of bytes used to store the strings in
+    /// this dictionary
+    pub fn size(&self) -> usize {
+        let mut size = 0;
+        for (_, value) in &self.0 {
+            size += value.len() + std::mem::size_of::<u32>();
+        }
+        size
+    }
+
+    /// Returns the ids of the values in this dictionary that are equal
+    /// to `value` once lower cased
+    pub fn ids_lowercasing_to(&self, value: &str) -> BTreeSet<u32> {
+        let mut ids = BTreeSet::new();
+        for (symbol, entry) in &self.0 {
+            if entry.to_lowercase() == value {
+                ids.insert(symbol_to_u32(symbol));
+            }
+        }
+        ids
+    }
 }
 
 fn symbol_to_u32(sym: DefaultSymbol) -> u32 {
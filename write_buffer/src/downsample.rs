@@ -0,0 +1,492 @@
+//! This module contains the downsampling of partitions, which
+//! replaces the rows of each series in a partition with one row per
+//! time window, holding the aggregate of each field over the window.
+//!
+//! The downsampled data is converted back to line protocol and
+//! written into a new partition through the same path as any other
+//! write, so it is stored (and queried) just like the raw data was.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use data_types::{
+    data::split_lines_into_write_entry_partitions,
+    database_rules::{DownsamplingAggregate, DownsamplingRule},
+    TIME_COLUMN_NAME,
+};
+use generated_types::wal as wb;
+use influxdb_line_protocol::{
+    parse_lines, write_escaped, FIELD_KEY_DELIMITERS, MEASUREMENT_DELIMITERS, TAG_KEY_DELIMITERS,
+    TAG_VALUE_DELIMITERS,
+};
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::column::Column;
+use crate::partition::Partition;
+use crate::table::Table;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Downsampling interval must be greater than zero"))]
+    ZeroInterval,
+
+    #[snafu(display(
+        "Error looking up id in dictionary of partition {}: {}",
+        partition,
+        source
+    ))]
+    DictionaryLookup {
+        partition: String,
+        source: crate::dictionary::Error,
+    },
+
+    #[snafu(display("Table {} in partition {} has no time column", table, partition))]
+    MissingTimeColumn { table: String, partition: String },
+
+    #[snafu(display(
+        "Error parsing downsampled data of partition {}: {}",
+        partition,
+        source
+    ))]
+    ParsingDownsampled {
+        partition: String,
+        source: influxdb_line_protocol::Error,
+    },
+
+    #[snafu(display(
+        "Error writing downsampled data of partition {}: {}",
+        partition,
+        source
+    ))]
+    WritingDownsampled {
+        partition: String,
+        source: crate::partition::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Characters escaped in string field values, which unlike
+/// `influxdb_line_protocol` this also escapes backslashes in, so a
+/// value ending with one is read back as it was written
+pub(crate) const STRING_SPECIAL_CHARS: &[char] = &['"', '\\'];
+
+/// Returns the newest timestamp in `partition`, if it has any rows
+pub fn max_time(partition: &Partition) -> Option<i64> {
+    let time_column_id = partition.dictionary.id(TIME_COLUMN_NAME)?;
+
+    partition
+        .tables
+        .values()
+        .filter_map(|table| {
+            let index = table.column_id_to_index.get(&time_column_id)?;
+            match &table.columns[*index] {
+                Column::I64(_, stats) => Some(stats.max),
+                _ => None,
+            }
+        })
+        .max()
+}
+
+/// Returns the rule of `rules` that applies to data which is `age`
+/// nanoseconds old: the rule with the largest `after` less than `age`
+pub fn applicable_rule(rules: &[DownsamplingRule], age: i64) -> Option<&DownsamplingRule> {
+    rules
+        .iter()
+        .filter(|rule| rule.after_nanos() < age)
+        .max_by_key(|rule| rule.after_nanos())
+}
+
+/// Returns a new partition, with the same key as `partition`,
+/// containing the data of `partition` downsampled according to `rule`
+pub fn downsample_partition(partition: &Partition, rule: &DownsamplingRule) -> Result<Partition> {
+    let interval = rule.interval_nanos();
+    if interval <= 0 {
+        return ZeroInterval.fail();
+    }
+
+    let mut lp = String::new();
+    for table in partition.tables.values() {
+        write_downsampled_table(&mut lp, partition, table, interval, rule.aggregate)?;
+    }
+
+    let lines = parse_lines(&lp)
+        .collect::<Result<Vec<_>, _>>()
+        .context(ParsingDownsampled {
+            partition: &partition.key,
+        })?;
+    let data = split_lines_into_write_entry_partitions(|_| partition.key.clone(), &lines);
+    let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+
+    let mut downsampled = Partition::new(&partition.key);
+    downsampled.id = partition.id;
+    downsampled.is_open = partition.is_open;
+    downsampled.downsampled_interval = Some(interval);
+    downsampled.late_window = partition.late_window;
+    downsampled.last_write_time = partition.last_write_time;
+
+    if let Some(entries) = batch.entries() {
+        for entry in entries {
+            downsampled
+                .write_entry(&entry)
+                .context(WritingDownsampled {
+                    partition: &partition.key,
+                })?;
+        }
+    }
+
+    Ok(downsampled)
+}
+
+/// Appends the downsampled rows of `table` to `lp` as line protocol
+fn write_downsampled_table(
+    lp: &mut String,
+    partition: &Partition,
+    table: &Table,
+    interval: i64,
+    aggregate: DownsamplingAggregate,
+) -> Result<()> {
+    let lookup = move |id| {
+        partition
+            .dictionary
+            .lookup_id(id)
+            .context(DictionaryLookup {
+                partition: &partition.key,
+            })
+    };
+
+    let table_name = lookup(table.id)?;
+
+    let mut times = None;
+    let mut tags = vec![];
+    let mut fields = vec![];
+    for (&column_id, &index) in &table.column_id_to_index {
+        let column_name = lookup(column_id)?;
+        match &table.columns[index] {
+            Column::I64(values, _) if column_name == TIME_COLUMN_NAME => times = Some(values),
+            Column::Tag(values, _) => tags.push((column_name, values)),
+            column => fields.push((column_name, column)),
+        }
+    }
+    tags.sort_by_key(|(name, _)| *name);
+    fields.sort_by_key(|(name, _)| *name);
+
+    let times = times.context(MissingTimeColumn {
+        table: table_name,
+        partition: &partition.key,
+    })?;
+
+    // the rows of each window of each series, keyed on the tag value
+    // ids of the series and the start of the window
+    let mut windows: BTreeMap<(Vec<Option<u32>>, i64), Vec<usize>> = BTreeMap::new();
+    for (row, time) in times.iter().enumerate() {
+        if let Some(time) = time {
+            let series = tags.iter().map(|(_, values)| values[row]).collect();
+            let start = time - time.rem_euclid(interval);
+            windows.entry((series, start)).or_default().push(row);
+        }
+    }
+
+    for ((series, start), mut rows) in windows {
+        rows.sort_by_key(|&row| times[row]);
+
+        let mut line = String::new();
+        write_escaped(&mut line, table_name, MEASUREMENT_DELIMITERS);
+        for ((tag_name, _), value_id) in tags.iter().zip(series) {
+            let value = match value_id {
+                Some(value_id) => lookup(value_id)?,
+                None => continue,
+            };
+            if value.is_empty() {
+                continue;
+            }
+
+            line.push(',');
+            write_escaped(&mut line, tag_name, TAG_KEY_DELIMITERS);
+            line.push('=');
+            write_escaped(&mut line, value, TAG_VALUE_DELIMITERS);
+        }
+
+        let mut separator = ' ';
+        for (field_name, column) in &fields {
+            if let Some(value) = aggregate_field(column, &rows, aggregate) {
+                line.push(separator);
+                write_escaped(&mut line, field_name, FIELD_KEY_DELIMITERS);
+                line.push('=');
+                line.push_str(&value);
+                separator = ',';
+            }
+        }
+
+        // only write windows in which at least one field has a value
+        if separator == ',' {
+            writeln!(lp, "{} {}", line, start).expect("writing to a String can not fail");
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the aggregate of the non null values of `column` in `rows`
+/// (in time order) as a line protocol field value, or `None` if there
+/// is no such value
+fn aggregate_field(
+    column: &Column,
+    rows: &[usize],
+    aggregate: DownsamplingAggregate,
+) -> Option<String> {
+    use DownsamplingAggregate::*;
+
+    match column {
+        Column::F64(values, _) => {
+            let values = non_null_values(values, rows);
+            let first = *values.first()?;
+            // NaN values are counted and can be the first or last value,
+            // but are excluded from the other aggregates, which are only
+            // NaN if every value is
+            let numbers: Vec<_> = values.iter().copied().filter(|v| !v.is_nan()).collect();
+            let value = match aggregate {
+                Count => return Some(format!("{}i", values.len())),
+                First => first,
+                Last => *values.last()?,
+                _ if numbers.is_empty() => std::f64::NAN,
+                Mean => numbers.iter().sum::<f64>() / numbers.len() as f64,
+                Sum => numbers.iter().sum(),
+                Min => numbers.iter().copied().fold(std::f64::INFINITY, f64::min),
+                Max => numbers
+                    .iter()
+                    .copied()
+                    .fold(std::f64::NEG_INFINITY, f64::max),
+            };
+            Some(value.to_string())
+        }
+        Column::I64(values, _) => {
+            let values = non_null_values(values, rows);
+            let first = *values.first()?;
+            let sum = || values.iter().map(|&v| i128::from(v)).sum::<i128>();
+            let value = match aggregate {
+                Mean => sum().div_euclid(values.len() as i128) as i64,
+                Sum => sum().max(i64::MIN.into()).min(i64::MAX.into()) as i64,
+                Min => *values.iter().min()?,
+                Max => *values.iter().max()?,
+                Count => values.len() as i64,
+                First => first,
+                Last => *values.last()?,
+            };
+            Some(format!("{}i", value))
+        }
+        Column::String(values, _) => {
+            let values = non_null_values(values, rows);
+            let value = match aggregate {
+                Count => return Some(format!("{}i", values.len())),
+                First => values.first()?,
+                Last => values.last()?,
+                _ => return None,
+            };
+
+            let mut quoted = String::from("\"");
+            write_escaped(&mut quoted, value, STRING_SPECIAL_CHARS);
+            quoted.push('"');
+            Some(quoted)
+        }
+        Column::Bool(values, _) => {
+            let values = non_null_values(values, rows);
+            match aggregate {
+                Count => Some(format!("{}i", values.len())),
+                First => values.first().map(ToString::to_string),
+                Last => values.last().map(ToString::to_string),
+                _ => None,
+            }
+        }
+        Column::Tag(_, _) => None,
+    }
+}
+
+fn non_null_values<T: Clone>(values: &[Option<T>], rows: &[usize]) -> Vec<T> {
+    rows.iter().filter_map(|&row| values[row].clone()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    const MINUTE: i64 = 60_000_000_000;
+
+    fn rule(after_seconds: u64, interval_seconds: u64) -> DownsamplingRule {
+        DownsamplingRule {
+            after_seconds,
+            interval_seconds,
+            aggregate: DownsamplingAggregate::Mean,
+        }
+    }
+
+    fn partition(lp: &str) -> Result<Partition> {
+        let lines = parse_lines(lp).collect::<Result<Vec<_>, _>>()?;
+        let data = split_lines_into_write_entry_partitions(|_| "key".to_string(), &lines);
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+
+        let mut partition = Partition::new("key");
+        for entry in batch.entries().unwrap() {
+            partition.write_entry(&entry)?;
+        }
+        Ok(partition)
+    }
+
+    /// Returns the data of `table_name` in `partition` as line protocol
+    fn to_lp(partition: &Partition, table_name: &str, aggregate: DownsamplingAggregate) -> String {
+        let table_id = partition.dictionary.id(table_name).unwrap();
+        let mut lp = String::new();
+        write_downsampled_table(
+            &mut lp,
+            partition,
+            &partition.tables[&table_id],
+            1,
+            aggregate,
+        )
+        .unwrap();
+        lp
+    }
+
+    #[test]
+    fn test_applicable_rule() {
+        let rules = vec![rule(60, 1), rule(3600, 60)];
+
+        assert_eq!(applicable_rule(&rules, 10 * 1_000_000_000), None);
+        assert_eq!(applicable_rule(&rules, 61 * 1_000_000_000), Some(&rules[0]));
+        assert_eq!(
+            applicable_rule(&rules, 3601 * 1_000_000_000),
+            Some(&rules[1])
+        );
+        assert_eq!(applicable_rule(&[], i64::MAX), None);
+    }
+
+    #[test]
+    fn test_max_time() -> Result {
+        assert_eq!(max_time(&Partition::new("empty")), None);
+
+        let partition = partition("cpu,host=a usage=1 10\ncpu,host=b usage=2 30\nmem free=1i 20")?;
+        assert_eq!(max_time(&partition), Some(30));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_downsample_partition() -> Result {
+        let raw = partition(&format!(
+            "cpu,host=a usage=1,count=1i 0\n\
+             cpu,host=a usage=2,count=4i {}\n\
+             cpu,host=b usage=5 {}\n\
+             cpu,host=a usage=10,count=10i {}\n",
+            MINUTE / 2,
+            MINUTE / 3,
+            MINUTE + 1
+        ))?;
+
+        let downsampled = downsample_partition(&raw, &rule(0, 60))?;
+        assert_eq!(downsampled.key, "key");
+        assert_eq!(downsampled.downsampled_interval, Some(MINUTE));
+
+        let expected = format!(
+            "cpu,host=a count=2i,usage=1.5 0\n\
+             cpu,host=a count=10i,usage=10 {}\n\
+             cpu,host=b usage=5 0\n",
+            MINUTE
+        );
+        assert_eq!(
+            to_lp(&downsampled, "cpu", DownsamplingAggregate::Last),
+            expected
+        );
+
+        let error = downsample_partition(&raw, &rule(0, 0)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Downsampling interval must be greater than zero"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregates() -> Result {
+        let raw = partition(
+            "cpu,host=a f=1,i=3i,s=\"x\",b=true 1\n\
+             cpu,host=a f=4,i=-4i,s=\"y \\\"z\\\"\",b=false 2\n\
+             cpu,host=a f=2,i=2i 3\n",
+        )?;
+        let table_id = raw.dictionary.id("cpu").unwrap();
+        let table = &raw.tables[&table_id];
+
+        let expected = vec![
+            (DownsamplingAggregate::Mean, "f=2.3333333333333335,i=0i"),
+            (DownsamplingAggregate::Sum, "f=7,i=1i"),
+            (DownsamplingAggregate::Min, "f=1,i=-4i"),
+            (DownsamplingAggregate::Max, "f=4,i=3i"),
+            (DownsamplingAggregate::Count, "b=2i,f=3i,i=3i,s=2i"),
+            (DownsamplingAggregate::First, "b=true,f=1,i=3i,s=\"x\""),
+            (
+                DownsamplingAggregate::Last,
+                "b=false,f=2,i=2i,s=\"y \\\"z\\\"\"",
+            ),
+        ];
+
+        for (aggregate, fields) in expected {
+            let mut lp = String::new();
+            write_downsampled_table(&mut lp, &raw, table, 10, aggregate)?;
+            assert_eq!(
+                lp,
+                format!("cpu,host=a {} 0\n", fields),
+                "aggregate: {:?}",
+                aggregate
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_aggregates_exclude_nan() -> Result {
+        let raw = partition("cpu f=NaN 1\ncpu f=3 2\ncpu f=-inf 3\ncpu f=NaN 4\nmem f=NaN 1\n")?;
+
+        let expected = vec![
+            (
+                DownsamplingAggregate::Mean,
+                "cpu f=-inf 0\n",
+                "mem f=NaN 0\n",
+            ),
+            (
+                DownsamplingAggregate::Sum,
+                "cpu f=-inf 0\n",
+                "mem f=NaN 0\n",
+            ),
+            (
+                DownsamplingAggregate::Min,
+                "cpu f=-inf 0\n",
+                "mem f=NaN 0\n",
+            ),
+            (DownsamplingAggregate::Max, "cpu f=3 0\n", "mem f=NaN 0\n"),
+            (DownsamplingAggregate::Count, "cpu f=4i 0\n", "mem f=1i 0\n"),
+            (
+                DownsamplingAggregate::First,
+                "cpu f=NaN 0\n",
+                "mem f=NaN 0\n",
+            ),
+        ];
+
+        for (aggregate, cpu, mem) in expected {
+            for (table_name, expected) in &[("cpu", cpu), ("mem", mem)] {
+                let table_id = raw.dictionary.id(table_name).unwrap();
+                let mut lp = String::new();
+                write_downsampled_table(&mut lp, &raw, &raw.tables[&table_id], 10, aggregate)?;
+                assert_eq!(&lp, expected, "aggregate: {:?}", aggregate);
+
+                // the aggregates can be parsed back
+                parse_lines(&lp).collect::<Result<Vec<_>, _>>()?;
+            }
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,400 @@
+//! This module contains the choice of the encoding each column of a
+//! compacted partition is persisted with.
+//!
+//! The encoding of a column is first chosen from its values: columns
+//! whose values repeat are dictionary encoded, and integer columns
+//! whose values change by small steps are bit packed. If every time
+//! the column was persisted before it was stored in the same encoding,
+//! and that encoding barely compressed the column, the column has
+//! data that doesn't suit the encoding chosen from its values in
+//! memory and it keeps the encoding it had. Finally, columns that are
+//! read often are not delta encoded, as delta encoded values are the
+//! slowest to decode.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use data_types::partition_metadata::ColumnEncoding;
+use snafu::{ResultExt, Snafu};
+
+use crate::column::Column;
+use crate::partition::Partition;
+use crate::persisted::PersistedChunk;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Error looking up id in dictionary of partition {}: {}",
+        partition,
+        source
+    ))]
+    DictionaryLookup {
+        partition: String,
+        source: crate::dictionary::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The number of times a column must have been read before it is
+/// encoded so that it is fast to decode, rather than small
+pub const HOT_COLUMN_READS: u64 = 100;
+
+/// The ratio of compressed to uncompressed bytes above which a
+/// persisted column is considered to be as small as it gets in its
+/// encoding, which is then kept
+const WELL_ENCODED_RATIO: f64 = 0.8;
+
+/// The largest number of bits of the differences between consecutive
+/// values of an integer column for it to be bit packed
+const MAX_PACKED_DELTA_BITS: u32 = 32;
+
+/// How a column of the partitions being compacted has been used
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ColumnUsage {
+    /// The number of times the column has been read
+    pub reads: u64,
+
+    /// How the column was stored each time it was persisted
+    pub persisted: Vec<PersistedEncoding>,
+}
+
+/// How a column was stored when it was persisted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PersistedEncoding {
+    pub encoding: ColumnEncoding,
+    pub compressed_bytes: u64,
+    pub uncompressed_bytes: u64,
+}
+
+impl ColumnUsage {
+    /// Returns the encoding the column was persisted in, if it was
+    /// always persisted in the same one and compression couldn't make
+    /// it much smaller
+    fn well_encoded(&self) -> Option<ColumnEncoding> {
+        let first = self.persisted.first()?;
+        if self.persisted.iter().any(|p| p.encoding != first.encoding) {
+            return None;
+        }
+
+        let compressed: u64 = self.persisted.iter().map(|p| p.compressed_bytes).sum();
+        let uncompressed: u64 = self.persisted.iter().map(|p| p.uncompressed_bytes).sum();
+        if uncompressed == 0 {
+            return None;
+        }
+
+        if compressed as f64 / uncompressed as f64 > WELL_ENCODED_RATIO {
+            Some(first.encoding)
+        } else {
+            None
+        }
+    }
+}
+
+/// Returns the usage of each column of `partitions` and of the
+/// `persisted` chunks of them, keyed by table and column name
+pub fn column_usage(
+    partitions: &[&Partition],
+    persisted: &[PersistedChunk],
+) -> Result<BTreeMap<(String, String), ColumnUsage>> {
+    let mut usage: BTreeMap<(String, String), ColumnUsage> = BTreeMap::new();
+
+    for partition in partitions {
+        let lookup = |id| {
+            partition
+                .dictionary
+                .lookup_id(id)
+                .context(DictionaryLookup {
+                    partition: &partition.key,
+                })
+        };
+
+        for table in partition.tables.values() {
+            let table_name = lookup(table.id)?;
+            for (&column_id, &index) in &table.column_id_to_index {
+                let column_name = lookup(column_id)?;
+                usage
+                    .entry((table_name.to_string(), column_name.to_string()))
+                    .or_default()
+                    .reads += table.column_reads(index);
+            }
+        }
+    }
+
+    for chunk in persisted {
+        for table_name in chunk.table_names() {
+            let table = match chunk.table(table_name) {
+                Some(table) => table,
+                None => continue,
+            };
+
+            for column in table.columns() {
+                // tables persisted before encodings were recorded
+                // can't tell which encoding was used
+                let encoding = match column.encoding {
+                    Some(encoding) => encoding,
+                    None => continue,
+                };
+
+                usage
+                    .entry((table_name.to_string(), column.name.clone()))
+                    .or_default()
+                    .persisted
+                    .push(PersistedEncoding {
+                        encoding,
+                        compressed_bytes: column.compressed_bytes,
+                        uncompressed_bytes: column.uncompressed_bytes,
+                    });
+            }
+        }
+    }
+
+    Ok(usage)
+}
+
+/// Returns the encoding of each column of each table of `partition`,
+/// keyed by table and then column name, given the `usage` of the
+/// columns of the partitions it was compacted from
+pub fn choose_encodings(
+    partition: &Partition,
+    usage: &BTreeMap<(String, String), ColumnUsage>,
+) -> Result<BTreeMap<String, BTreeMap<String, ColumnEncoding>>> {
+    let lookup = |id| {
+        partition
+            .dictionary
+            .lookup_id(id)
+            .context(DictionaryLookup {
+                partition: &partition.key,
+            })
+    };
+
+    let mut encodings = BTreeMap::new();
+    for table in partition.tables.values() {
+        let table_name = lookup(table.id)?;
+
+        let mut columns = BTreeMap::new();
+        for (&column_id, &index) in &table.column_id_to_index {
+            let column_name = lookup(column_id)?;
+            let column_usage = usage.get(&(table_name.to_string(), column_name.to_string()));
+
+            let mut encoding = column_usage
+                .and_then(|usage| usage.well_encoded())
+                .unwrap_or_else(|| encoding_for_values(&table.columns[index]));
+
+            let reads = column_usage.map(|usage| usage.reads).unwrap_or(0);
+            if reads >= HOT_COLUMN_READS
+                && matches!(
+                    encoding,
+                    ColumnEncoding::DeltaBinaryPacked | ColumnEncoding::DeltaLengthByteArray
+                )
+            {
+                encoding = ColumnEncoding::Plain;
+            }
+
+            columns.insert(column_name.to_string(), encoding);
+        }
+
+        encodings.insert(table_name.to_string(), columns);
+    }
+
+    Ok(encodings)
+}
+
+/// Returns the encoding best suited to the values of `column`
+fn encoding_for_values(column: &Column) -> ColumnEncoding {
+    match column {
+        Column::Bool(_, _) => ColumnEncoding::Rle,
+        Column::F64(_, _) => ColumnEncoding::Plain,
+        Column::Tag(values, _) => {
+            if repeats(values.iter().flatten()) {
+                ColumnEncoding::Dictionary
+            } else {
+                ColumnEncoding::Plain
+            }
+        }
+        Column::String(values, _) => {
+            if repeats(values.iter().flatten()) {
+                ColumnEncoding::Dictionary
+            } else {
+                ColumnEncoding::DeltaLengthByteArray
+            }
+        }
+        Column::I64(values, _) => {
+            if repeats(values.iter().flatten()) {
+                ColumnEncoding::Dictionary
+            } else if delta_bits(values.iter().flatten().copied()) <= MAX_PACKED_DELTA_BITS {
+                ColumnEncoding::DeltaBinaryPacked
+            } else {
+                ColumnEncoding::Plain
+            }
+        }
+    }
+}
+
+/// Whether each distinct value of `values` appears at least twice on
+/// average
+fn repeats<T: Ord>(values: impl Iterator<Item = T>) -> bool {
+    let mut count = 0;
+    let mut distinct = BTreeSet::new();
+    for value in values {
+        count += 1;
+        distinct.insert(value);
+    }
+    count > 0 && distinct.len() * 2 <= count
+}
+
+/// Returns the number of bits needed to store the largest difference
+/// between consecutive `values`
+fn delta_bits(values: impl Iterator<Item = i64>) -> u32 {
+    let mut bits = 0;
+    let mut previous = None;
+    for value in values {
+        if let Some(previous) = previous {
+            let delta = i64::wrapping_sub(value, previous);
+            // zigzag encode, so small negative deltas need few bits too
+            let zigzag = ((delta << 1) ^ (delta >> 63)) as u64;
+            bits = bits.max(64 - zigzag.leading_zeros());
+        }
+        previous = Some(value);
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_types::data::split_lines_into_write_entry_partitions;
+    use generated_types::wal as wb;
+    use influxdb_line_protocol::parse_lines;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    fn partition(lp: &str) -> Result<Partition> {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        let data = split_lines_into_write_entry_partitions(|_| "p1".to_string(), &lines);
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+
+        let mut partition = Partition::new("p1");
+        for entry in batch.entries().unwrap() {
+            partition.write_entry(&entry)?;
+        }
+        Ok(partition)
+    }
+
+    fn key(table: &str, column: &str) -> (String, String) {
+        (table.to_string(), column.to_string())
+    }
+
+    #[test]
+    fn test_choose_encodings_from_values() -> Result {
+        let partition = partition(
+            "cpu,host=a,id=1 usage=0.5,count=1i,big=0i,state=\"ok\",msg=\"x\",up=true 10\n\
+             cpu,host=a,id=2 usage=1.5,count=2i,big=9000000000000i,state=\"ok\",msg=\"y\",up=true 20\n\
+             cpu,host=a,id=3 usage=2.5,count=3i,big=1i,state=\"ok\",msg=\"z\",up=false 30\n\
+             cpu,host=a,id=4 usage=3.5,count=4i,big=-9000000000000i,state=\"ok\",msg=\"w\",up=true 40\n",
+        )?;
+
+        let encodings = choose_encodings(&partition, &BTreeMap::new())?;
+
+        let expected: BTreeMap<_, _> = vec![
+            ("host", ColumnEncoding::Dictionary),
+            ("id", ColumnEncoding::Plain),
+            ("usage", ColumnEncoding::Plain),
+            ("count", ColumnEncoding::DeltaBinaryPacked),
+            ("big", ColumnEncoding::Plain),
+            ("state", ColumnEncoding::Dictionary),
+            ("msg", ColumnEncoding::DeltaLengthByteArray),
+            ("up", ColumnEncoding::Rle),
+            ("time", ColumnEncoding::DeltaBinaryPacked),
+        ]
+        .into_iter()
+        .map(|(name, encoding)| (name.to_string(), encoding))
+        .collect();
+        assert_eq!(encodings.len(), 1);
+        assert_eq!(encodings["cpu"], expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_choose_encodings_from_usage() -> Result {
+        let partition = partition(
+            "cpu,host=a count=1i,msg=\"x\" 10\n\
+             cpu,host=a count=2i,msg=\"y\" 20\n",
+        )?;
+
+        let persisted = |encoding, compressed_bytes| PersistedEncoding {
+            encoding,
+            compressed_bytes,
+            uncompressed_bytes: 100,
+        };
+
+        let mut usage = BTreeMap::new();
+        // compressed well, so the encoding is chosen from the values
+        usage.insert(
+            key("cpu", "host"),
+            ColumnUsage {
+                reads: 0,
+                persisted: vec![persisted(ColumnEncoding::Plain, 10)],
+            },
+        );
+        // the encoding barely compressed, so it is kept
+        usage.insert(
+            key("cpu", "count"),
+            ColumnUsage {
+                reads: 0,
+                persisted: vec![
+                    persisted(ColumnEncoding::Plain, 90),
+                    persisted(ColumnEncoding::Plain, 95),
+                ],
+            },
+        );
+        // read often, so not delta encoded
+        usage.insert(
+            key("cpu", "msg"),
+            ColumnUsage {
+                reads: HOT_COLUMN_READS,
+                persisted: vec![],
+            },
+        );
+        // persisted in different encodings
+        usage.insert(
+            key("cpu", "time"),
+            ColumnUsage {
+                reads: 0,
+                persisted: vec![
+                    persisted(ColumnEncoding::Plain, 90),
+                    persisted(ColumnEncoding::Dictionary, 90),
+                ],
+            },
+        );
+
+        let encodings = choose_encodings(&partition, &usage)?;
+
+        let cpu = &encodings["cpu"];
+        assert_eq!(cpu["host"], ColumnEncoding::Dictionary);
+        assert_eq!(cpu["count"], ColumnEncoding::Plain);
+        assert_eq!(cpu["msg"], ColumnEncoding::Plain);
+        assert_eq!(cpu["time"], ColumnEncoding::DeltaBinaryPacked);
+        Ok(())
+    }
+
+    #[test]
+    fn test_column_usage() -> Result {
+        let first = partition("cpu,host=a usage=0.5 10\n")?;
+        let second = partition("cpu,host=b usage=1.5 20\n")?;
+
+        let table = &first.tables[&first.dictionary.lookup_value("cpu")?];
+        table.to_arrow(&first, &["usage"])?;
+        table.to_arrow(&first, &["usage"])?;
+        let table = &second.tables[&second.dictionary.lookup_value("cpu")?];
+        table.to_arrow(&second, &["usage", "host"])?;
+
+        let usage = column_usage(&[&first, &second], &[])?;
+
+        assert_eq!(usage[&key("cpu", "usage")].reads, 3);
+        assert_eq!(usage[&key("cpu", "host")].reads, 1);
+        assert_eq!(usage[&key("cpu", "time")].reads, 0);
+        assert!(usage.values().all(|usage| usage.persisted.is_empty()));
+        Ok(())
+    }
+}
@@ -0,0 +1,398 @@
+//! This module contains support for `EXPLAIN` queries, which return
+//! how a query would be run rather than its results.
+//!
+//! `EXPLAIN [ANALYZE] [VERBOSE] <query>` returns a table with one row
+//! per plan, each with a `plan_type` and the text of the `plan`:
+//!
+//! * `tables`: the partitions and rows scanned for each table
+//! * `initial_logical_plan` (VERBOSE only): the plan before optimization
+//! * `logical_plan`: the optimized plan, including pushed down filters
+//! * `physical_plan`: the DataFusion plan that is executed
+//! * `execution` (ANALYZE only): the rows and batches produced by
+//!   actually running the query, and how long that took, followed by
+//!   the rows and batches each operator of the physical plan produced
+//!   and the time spent polling it (which includes the time its inputs
+//!   took, unless they ran in tasks of their own)
+
+use std::{
+    any::Any,
+    fmt::{self, Write},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use arrow_deps::{
+    arrow::{
+        array::{ArrayRef, StringArray},
+        datatypes::{DataType, Field, Schema, SchemaRef},
+        error::Result as ArrowResult,
+        record_batch::RecordBatch,
+    },
+    datafusion::{
+        error::{DataFusionError, Result as DataFusionResult},
+        physical_plan::{
+            Distribution, ExecutionPlan, Partitioning, RecordBatchStream, SendableRecordBatchStream,
+        },
+    },
+};
+use async_trait::async_trait;
+use futures::Stream;
+
+/// A parsed `EXPLAIN` query
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Explain<'a> {
+    /// Run the query and report on its execution
+    pub analyze: bool,
+    /// Include intermediate plans
+    pub verbose: bool,
+    /// The query to explain
+    pub query: &'a str,
+}
+
+impl<'a> Explain<'a> {
+    /// Returns the `EXPLAIN` query in `sql`, or `None` if `sql` is
+    /// not an `EXPLAIN` query
+    pub fn parse(sql: &'a str) -> Option<Self> {
+        let query = strip_keyword(sql, "EXPLAIN")?;
+
+        let (analyze, query) = match strip_keyword(query, "ANALYZE") {
+            Some(query) => (true, query),
+            None => (false, query),
+        };
+        let (verbose, query) = match strip_keyword(query, "VERBOSE") {
+            Some(query) => (true, query),
+            None => (false, query),
+        };
+
+        Some(Self {
+            analyze,
+            verbose,
+            query: query.trim(),
+        })
+    }
+}
+
+/// Returns the rest of `sql` if it starts with `keyword`, ignoring
+/// case and leading whitespace
+fn strip_keyword<'a>(sql: &'a str, keyword: &str) -> Option<&'a str> {
+    let sql = sql.trim_start();
+    let end = sql
+        .find(|c: char| c.is_whitespace())
+        .unwrap_or_else(|| sql.len());
+
+    if sql[..end].eq_ignore_ascii_case(keyword) {
+        Some(&sql[end..])
+    } else {
+        None
+    }
+}
+
+/// The plans describing a query, in the order they are returned
+#[derive(Debug, Default)]
+pub struct PlanDescriptions {
+    descriptions: Vec<(&'static str, String)>,
+}
+
+impl PlanDescriptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the description of a plan of type `plan_type`
+    pub fn push(&mut self, plan_type: &'static str, plan: impl Into<String>) {
+        self.descriptions.push((plan_type, plan.into()));
+    }
+
+    /// Converts the descriptions into a RecordBatch with
+    /// `plan_type` and `plan` columns
+    pub fn into_batch(self) -> ArrowResult<RecordBatch> {
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("plan_type", DataType::Utf8, false),
+            Field::new("plan", DataType::Utf8, false),
+        ]));
+
+        let (plan_types, plans): (Vec<_>, Vec<_>) = self
+            .descriptions
+            .iter()
+            .map(|(plan_type, plan)| (*plan_type, plan.as_str()))
+            .unzip();
+
+        RecordBatch::try_new(
+            schema,
+            vec![
+                Arc::new(StringArray::from(plan_types)) as ArrayRef,
+                Arc::new(StringArray::from(plans)),
+            ],
+        )
+    }
+}
+
+/// The operators of a physical plan instrumented by
+/// `AnalyzedPlan::instrument`, and what each produced when it ran
+#[derive(Debug, Default)]
+pub struct AnalyzedPlan {
+    /// The operators in the order they are described, each before its
+    /// inputs
+    operators: Vec<Arc<OperatorStats>>,
+}
+
+impl AnalyzedPlan {
+    /// Returns `plan` with each of its operators wrapped so that the
+    /// rows, batches and time they produce are recorded, and the
+    /// `AnalyzedPlan` they are recorded in
+    pub fn instrument(
+        plan: Arc<dyn ExecutionPlan>,
+    ) -> DataFusionResult<(Arc<dyn ExecutionPlan>, Self)> {
+        let mut analyzed = Self::default();
+        let plan = analyzed.instrument_operator(plan, 0)?;
+        Ok((plan, analyzed))
+    }
+
+    fn instrument_operator(
+        &mut self,
+        plan: Arc<dyn ExecutionPlan>,
+        depth: usize,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        let stats = Arc::new(OperatorStats::new(operator_name(plan.as_ref()), depth));
+        self.operators.push(Arc::clone(&stats));
+
+        let children = plan.children();
+        let plan = if children.is_empty() {
+            plan
+        } else {
+            let children = children
+                .into_iter()
+                .map(|child| self.instrument_operator(child, depth + 1))
+                .collect::<DataFusionResult<Vec<_>>>()?;
+            plan.with_new_children(children)?
+        };
+
+        Ok(Arc::new(InstrumentedExec { input: plan, stats }))
+    }
+
+    /// Describes what each operator produced, one per line, indented
+    /// below the operator it is the input of
+    pub fn describe(&self) -> String {
+        self.operators
+            .iter()
+            .map(|stats| {
+                format!(
+                    "{:indent$}{}: output_rows={}, output_batches={}, elapsed={:?}",
+                    "",
+                    stats.name,
+                    stats.rows.load(Ordering::Relaxed),
+                    stats.batches.load(Ordering::Relaxed),
+                    Duration::from_nanos(stats.elapsed_nanos.load(Ordering::Relaxed)),
+                    indent = stats.depth * 2
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+/// What an operator produced, across all its partitions
+#[derive(Debug)]
+struct OperatorStats {
+    name: String,
+    /// The number of operators it is below
+    depth: usize,
+    rows: AtomicUsize,
+    batches: AtomicUsize,
+    elapsed_nanos: AtomicU64,
+}
+
+impl OperatorStats {
+    fn new(name: String, depth: usize) -> Self {
+        Self {
+            name,
+            depth,
+            rows: AtomicUsize::new(0),
+            batches: AtomicUsize::new(0),
+            elapsed_nanos: AtomicU64::new(0),
+        }
+    }
+
+    fn add_elapsed(&self, elapsed: Duration) {
+        self.elapsed_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Returns the name of the type of `plan`, which starts its `Debug`
+/// output
+fn operator_name(plan: &dyn ExecutionPlan) -> String {
+    /// Keeps what is written up to the end of the first identifier,
+    /// then fails the write so that the rest of the plan (which may
+    /// include all of its data) is not formatted
+    struct Name(String);
+
+    impl Write for Name {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            match s.find(|c: char| !(c.is_alphanumeric() || c == '_')) {
+                Some(end) => {
+                    self.0.push_str(&s[..end]);
+                    Err(fmt::Error)
+                }
+                None => {
+                    self.0.push_str(s);
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    let mut name = Name(String::new());
+    // the write fails as soon as the name is complete
+    let _ = write!(name, "{:?}", plan);
+    name.0
+}
+
+/// Physical operator that records the rows and batches its input
+/// produces, and the time spent polling it
+#[derive(Debug)]
+struct InstrumentedExec {
+    input: Arc<dyn ExecutionPlan>,
+    stats: Arc<OperatorStats>,
+}
+
+#[async_trait]
+impl ExecutionPlan for InstrumentedExec {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+
+    fn output_partitioning(&self) -> Partitioning {
+        self.input.output_partitioning()
+    }
+
+    fn required_child_distribution(&self) -> Distribution {
+        Distribution::UnspecifiedDistribution
+    }
+
+    fn children(&self) -> Vec<Arc<dyn ExecutionPlan>> {
+        vec![Arc::clone(&self.input)]
+    }
+
+    fn with_new_children(
+        &self,
+        children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> DataFusionResult<Arc<dyn ExecutionPlan>> {
+        match children.len() {
+            1 => Ok(Arc::new(Self {
+                input: Arc::clone(&children[0]),
+                stats: Arc::clone(&self.stats),
+            })),
+            _ => Err(DataFusionError::Internal(
+                "InstrumentedExec wrong number of children".to_string(),
+            )),
+        }
+    }
+
+    async fn execute(&self, partition: usize) -> DataFusionResult<SendableRecordBatchStream> {
+        let start = Instant::now();
+        let input = self.input.execute(partition).await;
+        self.stats.add_elapsed(start.elapsed());
+
+        Ok(Box::pin(InstrumentedStream {
+            input: input?,
+            stats: Arc::clone(&self.stats),
+        }))
+    }
+}
+
+struct InstrumentedStream {
+    input: SendableRecordBatchStream,
+    stats: Arc<OperatorStats>,
+}
+
+impl Stream for InstrumentedStream {
+    type Item = ArrowResult<RecordBatch>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let start = Instant::now();
+        let poll = self.input.as_mut().poll_next(cx);
+        self.stats.add_elapsed(start.elapsed());
+
+        if let Poll::Ready(Some(Ok(batch))) = &poll {
+            self.stats
+                .rows
+                .fetch_add(batch.num_rows(), Ordering::Relaxed);
+            self.stats.batches.fetch_add(1, Ordering::Relaxed);
+        }
+        poll
+    }
+}
+
+impl RecordBatchStream for InstrumentedStream {
+    fn schema(&self) -> SchemaRef {
+        self.input.schema()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::array::Array;
+
+    #[test]
+    fn parse_explain() {
+        assert_eq!(Explain::parse("select * from cpu"), None);
+        assert_eq!(Explain::parse("explained"), None);
+
+        assert_eq!(
+            Explain::parse("EXPLAIN select * from cpu"),
+            Some(Explain {
+                analyze: false,
+                verbose: false,
+                query: "select * from cpu"
+            })
+        );
+
+        assert_eq!(
+            Explain::parse("  explain\n analyze  verbose select * from cpu "),
+            Some(Explain {
+                analyze: true,
+                verbose: true,
+                query: "select * from cpu"
+            })
+        );
+
+        assert_eq!(
+            Explain::parse("explain verbose select 1"),
+            Some(Explain {
+                analyze: false,
+                verbose: true,
+                query: "select 1"
+            })
+        );
+    }
+
+    #[test]
+    fn descriptions_into_batch() {
+        let mut descriptions = PlanDescriptions::new();
+        descriptions.push("logical_plan", "TableScan: cpu");
+        descriptions.push("physical_plan", String::from("MemoryExec"));
+
+        let batch = descriptions.into_batch().unwrap();
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.schema().field(0).name(), "plan_type");
+
+        let plans = batch
+            .column(1)
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(plans.value(0), "TableScan: cpu");
+        assert_eq!(plans.value(1), "MemoryExec");
+    }
+}
@@ -0,0 +1,355 @@
+//! This module contains the rewriting of `histogram_quantile(q)` in
+//! SQL queries into an expression DataFusion can evaluate, which
+//! estimates the `q` quantile of the histogram stored in the bucket
+//! columns of the table being queried.
+//!
+//! `histogram_quantile` is an aggregate: the counts of each bucket are
+//! summed over the rows of each group, and the quantile is estimated
+//! from the summed buckets by linear interpolation within the bucket
+//! it falls in, as Prometheus does. Quantiles that fall in the `+Inf`
+//! bucket are the upper bound of the highest finite bucket, and a
+//! histogram without observations has a quantile of `NaN`.
+//!
+//! Prometheus histograms count every observation since the process
+//! started, so a quantile over one row per series (such as within
+//! `GROUP BY time()` windows of the scrape interval) is the quantile
+//! of all the observations made so far.
+
+use arrow_deps::arrow::datatypes::Schema as ArrowSchema;
+use data_types::histogram::bucket_columns;
+use snafu::{OptionExt, ResultExt, Snafu};
+use sqlparser::{
+    ast::{Expr, SelectItem, SetExpr, Statement, Value},
+    dialect::GenericDialect,
+    parser::Parser,
+};
+
+/// The name of the function, and of the column it produces when it is
+/// selected without an alias
+pub const HISTOGRAM_QUANTILE: &str = "histogram_quantile";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("histogram_quantile({}) expects a quantile between 0 and 1", args))]
+    InvalidQuantile { args: String },
+
+    #[snafu(display("histogram_quantile() can only be used in queries of a single table"))]
+    SingleTableRequired,
+
+    #[snafu(display(
+        "histogram_quantile() requires table {} to have bucket columns, such as le_0.5 and le_+Inf",
+        table
+    ))]
+    NoBuckets { table: String },
+
+    #[snafu(display("Error parsing histogram_quantile() expression {}: {}", sql, source))]
+    ParsingQuantile {
+        sql: String,
+        source: sqlparser::parser::ParserError,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Replaces each call of `histogram_quantile(q)` in the select list of
+/// `statement` with the expression computing it over `tables`, the
+/// names and schemas of the tables the statement reads. Returns
+/// whether any calls were replaced.
+pub fn rewrite_quantiles(
+    statement: &mut Statement,
+    tables: &[(&str, &ArrowSchema)],
+) -> Result<bool> {
+    let select = match statement {
+        Statement::Query(query) => match &mut query.body {
+            SetExpr::Select(select) => select,
+            _ => return Ok(false),
+        },
+        _ => return Ok(false),
+    };
+
+    let mut rewritten = false;
+    for item in &mut select.projection {
+        match item {
+            SelectItem::UnnamedExpr(expr) => {
+                if is_quantile_call(expr) {
+                    // name the column after the function, rather than
+                    // the expression it is replaced with
+                    let quantile = quantile_expr(expr, tables)?;
+                    *item = parse_select_item(&format!("{} AS {}", quantile, HISTOGRAM_QUANTILE))?;
+                    rewritten = true;
+                } else {
+                    rewritten |= rewrite_expr(expr, tables)?;
+                }
+            }
+            SelectItem::ExprWithAlias { expr, .. } => {
+                rewritten |= rewrite_expr(expr, tables)?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(rewritten)
+}
+
+/// Replaces calls of `histogram_quantile` within `expr`
+fn rewrite_expr(expr: &mut Expr, tables: &[(&str, &ArrowSchema)]) -> Result<bool> {
+    if is_quantile_call(expr) {
+        let quantile = quantile_expr(expr, tables)?;
+        *expr = match parse_select_item(&quantile)? {
+            SelectItem::UnnamedExpr(expr) => expr,
+            _ => unreachable!("an expression without an alias is unnamed"),
+        };
+        return Ok(true);
+    }
+
+    match expr {
+        Expr::BinaryOp { left, right, .. } => {
+            let left = rewrite_expr(left, tables)?;
+            let right = rewrite_expr(right, tables)?;
+            Ok(left || right)
+        }
+        Expr::UnaryOp { expr, .. } | Expr::Nested(expr) | Expr::Cast { expr, .. } => {
+            rewrite_expr(expr, tables)
+        }
+        Expr::Function(function) => {
+            let mut rewritten = false;
+            for arg in &mut function.args {
+                rewritten |= rewrite_expr(arg, tables)?;
+            }
+            Ok(rewritten)
+        }
+        _ => Ok(false),
+    }
+}
+
+fn is_quantile_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Function(function) => function
+            .name
+            .to_string()
+            .eq_ignore_ascii_case(HISTOGRAM_QUANTILE),
+        _ => false,
+    }
+}
+
+/// Returns the SQL computing the quantile of the call `expr` over the
+/// bucket columns of the only table in `tables`
+fn quantile_expr(expr: &Expr, tables: &[(&str, &ArrowSchema)]) -> Result<String> {
+    let args = match expr {
+        Expr::Function(function) => &function.args,
+        _ => unreachable!("only calls are rewritten"),
+    };
+
+    let describe_args = || {
+        args.iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let q = match args.as_slice() {
+        [Expr::Value(Value::Number(q))] => q.to_string().parse::<f64>().ok(),
+        _ => None,
+    }
+    .filter(|q| (0.0..=1.0).contains(q))
+    .with_context(|| InvalidQuantile {
+        args: describe_args(),
+    })?;
+
+    let (table, schema) = match tables {
+        [table] => table,
+        _ => return SingleTableRequired.fail(),
+    };
+
+    let buckets = bucket_columns(schema.fields().iter().map(|field| field.name().as_str()));
+    let (finite, total) = match buckets.split_last() {
+        Some(((bound, total), finite))
+            if bound.is_infinite() && *bound > 0.0 && !finite.is_empty() =>
+        {
+            (finite, total)
+        }
+        _ => return NoBuckets { table: *table }.fail(),
+    };
+
+    Ok(quantile_sql(q, finite, total))
+}
+
+/// Returns the SQL estimating the quantile `q` from the summed counts
+/// of the buckets `finite`, in increasing order of upper bound, and of
+/// the `+Inf` bucket `total`.
+///
+/// Every branch of the `CASE` is evaluated for every group, so a
+/// divisor that is zero for some group (such as the count of an empty
+/// bucket) is replaced with one, which only happens in groups where
+/// the quantile falls in another bucket.
+fn quantile_sql(q: f64, finite: &[(f64, &str)], total: &str) -> String {
+    let count = |column: &str| format!("CAST(SUM({}) AS DOUBLE)", quote_ident(column));
+    let total = count(total);
+    let rank = format!("({} * {})", float_literal(q), total);
+
+    let mut sql = format!("CASE WHEN {} = 0.0 THEN CAST('NaN' AS DOUBLE)", total);
+
+    let mut lower: Option<(f64, String)> = None;
+    for &(bound, column) in finite {
+        let upper = count(column);
+        let estimate = match &lower {
+            // the lowest bucket starts at zero, unless it has a
+            // negative bound
+            None if bound <= 0.0 => float_literal(bound),
+            None => format!(
+                "({} * ({} / CASE WHEN {} > 0.0 THEN {} ELSE 1.0 END))",
+                float_literal(bound),
+                rank,
+                upper,
+                upper
+            ),
+            Some((lower_bound, lower_count)) => format!(
+                "({} + ({} * (({} - {}) / CASE WHEN {} > {} THEN ({} - {}) ELSE 1.0 END)))",
+                float_literal(*lower_bound),
+                float_literal(bound - lower_bound),
+                rank,
+                lower_count,
+                upper,
+                lower_count,
+                upper,
+                lower_count
+            ),
+        };
+        sql.push_str(&format!(" WHEN {} >= {} THEN {}", upper, rank, estimate));
+        lower = Some((bound, upper));
+    }
+
+    let (highest_bound, _) = finite.last().expect("at least one finite bucket");
+    sql.push_str(&format!(" ELSE {} END", float_literal(*highest_bound)));
+    sql
+}
+
+/// Parses the SQL of a single select item
+fn parse_select_item(sql: &str) -> Result<SelectItem> {
+    let select = format!("SELECT {}", sql);
+    let mut statements =
+        Parser::parse_sql(&GenericDialect {}, &select).context(ParsingQuantile { sql })?;
+
+    match statements.pop() {
+        Some(Statement::Query(query)) => match query.body {
+            SetExpr::Select(mut select) if select.projection.len() == 1 => {
+                Ok(select.projection.remove(0))
+            }
+            _ => unreachable!("a select of one item"),
+        },
+        _ => unreachable!("a single query"),
+    }
+}
+
+/// Returns `value` as a SQL float literal, without an exponent
+fn float_literal(value: f64) -> String {
+    let literal = value.to_string();
+    let literal = if literal.contains('.') {
+        literal
+    } else {
+        format!("{}.0", literal)
+    };
+
+    if value < 0.0 {
+        format!("({})", literal)
+    } else {
+        literal
+    }
+}
+
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_deps::arrow::datatypes::{DataType, Field};
+
+    fn rewrite(sql: &str, columns: &[&str]) -> Result<String> {
+        let schema = ArrowSchema::new(
+            columns
+                .iter()
+                .map(|column| Field::new(column, DataType::Float64, true))
+                .collect(),
+        );
+        let mut statement = Parser::parse_sql(&GenericDialect {}, sql)
+            .unwrap()
+            .remove(0);
+        rewrite_quantiles(&mut statement, &[("http", &schema)])?;
+        Ok(statement.to_string())
+    }
+
+    #[test]
+    fn quantiles_are_rewritten() {
+        let columns = ["path", "le_0.5", "le_+Inf", "le_1", "sum", "count"];
+
+        let sum = |column: &str| format!("CAST(SUM(\"{}\") AS DOUBLE)", column);
+        let rank = format!("(0.9 * {})", sum("le_+Inf"));
+        let quantile = format!(
+            "CASE WHEN {total} = 0.0 THEN CAST('NaN' AS DOUBLE) \
+             WHEN {b0} >= {rank} THEN (0.5 * ({rank} / CASE WHEN {b0} > 0.0 THEN {b0} ELSE 1.0 END)) \
+             WHEN {b1} >= {rank} THEN (0.5 + (0.5 * (({rank} - {b0}) / CASE WHEN {b1} > {b0} THEN ({b1} - {b0}) ELSE 1.0 END))) \
+             ELSE 1.0 END",
+            total = sum("le_+Inf"),
+            b0 = sum("le_0.5"),
+            b1 = sum("le_1"),
+            rank = rank,
+        );
+        let expected_select = parse_select_item(&quantile).unwrap().to_string();
+
+        assert_eq!(
+            rewrite(
+                "SELECT path, histogram_quantile(0.9) FROM http GROUP BY path",
+                &columns
+            )
+            .unwrap(),
+            format!(
+                "SELECT path, {} AS histogram_quantile FROM http GROUP BY path",
+                expected_select
+            )
+        );
+
+        assert_eq!(
+            rewrite(
+                "SELECT histogram_quantile(0.9) * 1000 AS p90_ms FROM http",
+                &columns
+            )
+            .unwrap(),
+            format!("SELECT {} * 1000 AS p90_ms FROM http", expected_select)
+        );
+
+        // queries without quantiles are unchanged
+        assert_eq!(
+            rewrite("SELECT SUM(\"count\") FROM http", &columns).unwrap(),
+            "SELECT SUM(\"count\") FROM http"
+        );
+    }
+
+    #[test]
+    fn invalid_quantiles() {
+        let columns = ["le_0.5", "le_+Inf"];
+
+        assert_eq!(
+            rewrite("SELECT histogram_quantile(1.5) FROM http", &columns)
+                .unwrap_err()
+                .to_string(),
+            "histogram_quantile(1.5) expects a quantile between 0 and 1"
+        );
+        assert_eq!(
+            rewrite("SELECT histogram_quantile() FROM http", &columns)
+                .unwrap_err()
+                .to_string(),
+            "histogram_quantile() expects a quantile between 0 and 1"
+        );
+        assert_eq!(
+            rewrite(
+                "SELECT histogram_quantile(0.5) FROM http",
+                &["le_0.5", "sum"]
+            )
+            .unwrap_err()
+            .to_string(),
+            "histogram_quantile() requires table http to have bucket columns, \
+             such as le_0.5 and le_+Inf"
+        );
+    }
+}
@@ -8,15 +8,29 @@
     clippy::use_self
 )]
 
+mod archive;
 mod column;
 mod database;
+mod dedup;
 mod dictionary;
+mod downsample;
+mod encoding;
+mod explain;
+mod histogram;
 mod partition;
+pub mod persisted;
+mod pre_aggregate;
+mod rewrite;
+mod sql_range;
 mod store;
 mod table;
 
 // Allow restore partitions to be used outside of this crate (for
 // benchmarking)
-pub use crate::database::Db;
+pub use crate::database::{
+    partition_key, Db, ReplayOptions, ReplayProgress, PERSISTED_CHUNKS_DIR_NAME,
+};
 pub use crate::partition::restore_partitions_from_wal;
+pub use crate::rewrite::lines_to_arrow;
+pub use crate::sql_range::select_time_range;
 pub use crate::store::WriteBufferDatabases;
@@ -1,18 +1,24 @@
 use arrow_deps::{
     arrow::record_batch::RecordBatch, datafusion::logical_plan::Expr,
     datafusion::logical_plan::Operator, datafusion::optimizer::utils::expr_to_column_names,
-    datafusion::scalar::ScalarValue,
+    datafusion::physical_plan::functions::BuiltinScalarFunction, datafusion::scalar::ScalarValue,
 };
 use generated_types::wal as wb;
 use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use wal::{Entry as WalEntry, Result as WalResult};
 
-use data_types::TIME_COLUMN_NAME;
+use data_types::{
+    partition_metadata::{
+        ChunkState, ChunkStorage, ChunkSummary, ColumnEncoding, PartitionSummary,
+    },
+    TIME_COLUMN_NAME,
+};
 use storage::{
     predicate::{Predicate, TimestampRange},
     util::{visit_expression, AndExprBuilder, ExpressionVisitor},
 };
 
+use crate::column::Column;
 use crate::dictionary::Dictionary;
 use crate::table::Table;
 
@@ -76,6 +82,10 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub struct Partition {
     pub key: String,
 
+    /// Identifies this partition among those with the same key, which
+    /// are the chunks of that partition
+    pub id: u32,
+
     /// `dictionary` maps &str -> u32. The u32s are used in place of String or str to avoid slow
     /// string operations. The same dictionary is used for table names, tag names, tag values, and
     /// column names.
@@ -86,6 +96,33 @@ pub struct Partition {
     pub tables: HashMap<u32, Table>,
 
     pub is_open: bool,
+
+    /// Whether the data of this partition has been written to parquet
+    /// files
+    pub persisted: bool,
+
+    /// The width, in nanoseconds, of the windows the data of this
+    /// partition has been downsampled to, if it has been downsampled
+    pub downsampled_interval: Option<i64>,
+
+    /// When data was last written to this partition, in nanoseconds
+    /// since the epoch. This is not known for partitions restored from
+    /// the WAL until they are written to again.
+    pub last_write_time: Option<i64>,
+
+    /// The names of the tables that keep pre-aggregates of the rows
+    /// written to them, if they are created in this partition
+    pub pre_aggregated_tables: BTreeSet<String>,
+
+    /// The encoding of each column of each table when this partition
+    /// is persisted, keyed by table and then column name. Columns not
+    /// in here are encoded as the parquet writer defaults to.
+    pub encodings: BTreeMap<String, BTreeMap<String, ColumnEncoding>>,
+
+    /// If this is a late chunk, the time window `[start, end)` of the
+    /// rows it holds, which were written too far behind the open chunk
+    /// of the partition to be written to it
+    pub late_window: Option<(i64, i64)>,
 }
 
 /// Describes the result of translating a set of strings into
@@ -128,6 +165,17 @@ pub struct PartitionPredicate {
     /// to pass the predicate
     pub required_columns: Option<PartitionIdSet>,
 
+    /// The ids of columns that one of `partition_exprs` compares for
+    /// equality with a string literal, and the literals. Tables whose
+    /// column statistics rule out the value cannot pass
+    pub column_equalities: Vec<(u32, String)>,
+
+    /// The ids of columns that one of `partition_exprs` compares for
+    /// equality with a string literal once lower cased, and the ids of
+    /// the values in the dictionary that lower case to the literal.
+    /// Tables with none of these values in the column cannot pass
+    pub column_lowered_equalities: Vec<(u32, BTreeSet<u32>)>,
+
     /// The id of the "time" column in this partition
     pub time_column_id: u32,
 
@@ -137,13 +185,22 @@ pub struct PartitionPredicate {
 
 impl PartitionPredicate {
     /// Creates and adds a datafuson predicate representing the
-    /// combination of predicate and timestamp.
-    pub fn filter_expr(&self) -> Option<Expr> {
+    /// combination of predicate and timestamp, for a table that has
+    /// the columns for which `has_column` returns true.
+    ///
+    /// `column IS NULL` expressions on columns the table does not have
+    /// are true for every row, so they are left out.
+    pub fn filter_expr(&self, has_column: impl Fn(&str) -> bool) -> Option<Expr> {
         // build up a list of expressions
         let mut builder =
             AndExprBuilder::default().append_opt(self.make_timestamp_predicate_expr());
 
         for expr in &self.partition_exprs {
+            if let Some(column) = is_null_column(expr) {
+                if !has_column(column) {
+                    continue;
+                }
+            }
             builder = builder.append_expr(expr.clone());
         }
 
@@ -201,38 +258,194 @@ fn make_range_expr(range: &TimestampRange) -> Expr {
         .unwrap()
 }
 
+/// Returns the key of the series of `row` of table `table_name` (see
+/// `influxdb_line_protocol::series_key`)
+pub fn row_series_key(table_name: &str, row: &wb::Row<'_>) -> String {
+    let values = row.values();
+    let tags = values
+        .iter()
+        .flat_map(|values| values.iter())
+        .filter_map(|value| {
+            let tag_value = value.value_as_tag_value()?.value()?;
+            Some((value.column()?, tag_value))
+        });
+    influxdb_line_protocol::series_key(table_name, tags)
+}
+
+/// Returns the timestamp of `row`, if it has one
+pub fn row_time(row: &wb::Row<'_>) -> Option<i64> {
+    row.values()?
+        .iter()
+        .find(|value| value.column() == Some(TIME_COLUMN_NAME))?
+        .value_as_i64value()
+        .map(|value| value.value())
+}
+
 impl Partition {
     pub fn new(key: impl Into<String>) -> Self {
         Self {
             key: key.into(),
+            id: 0,
             dictionary: Dictionary::new(),
             tables: HashMap::new(),
             is_open: true,
+            persisted: false,
+            downsampled_interval: None,
+            last_write_time: None,
+            pre_aggregated_tables: BTreeSet::new(),
+            encodings: BTreeMap::new(),
+            late_window: None,
+        }
+    }
+
+    /// The approximate number of bytes used to store the data of this
+    /// partition, including its dictionary
+    pub fn size(&self) -> usize {
+        self.dictionary.size() + self.tables.values().map(|t| t.size()).sum::<usize>()
+    }
+
+    /// Summarizes the data held in this partition
+    pub fn summary(&self) -> PartitionSummary {
+        let mut tables: Vec<_> = self
+            .tables
+            .keys()
+            .map(|id| {
+                self.dictionary
+                    .lookup_id(*id)
+                    .expect("table name is in the partition dictionary")
+                    .to_string()
+            })
+            .collect();
+        tables.sort();
+
+        PartitionSummary {
+            key: self.key.clone(),
+            tables,
+            row_count: self.tables.values().map(|t| t.row_count() as u64).sum(),
+            size: self.size() as u64,
+        }
+    }
+
+    /// Describes the data of this partition as a chunk of the
+    /// partition with its key
+    pub fn chunk_summary(&self) -> ChunkSummary {
+        let time_column_id = self.dictionary.id(TIME_COLUMN_NAME);
+
+        let mut column_sizes = BTreeMap::new();
+        let mut time_range: Option<(i64, i64)> = None;
+        for table in self.tables.values() {
+            for (column_id, index) in &table.column_id_to_index {
+                let column = &table.columns[*index];
+                let name = self
+                    .dictionary
+                    .lookup_id(*column_id)
+                    .expect("column name is in the partition dictionary");
+                *column_sizes.entry(name.to_string()).or_insert(0) += column.size() as u64;
+
+                if let (Some(time_column_id), Column::I64(_, stats)) = (time_column_id, column) {
+                    if *column_id == time_column_id {
+                        time_range = Some(match time_range {
+                            Some((min, max)) => (min.min(stats.min), max.max(stats.max)),
+                            None => (stats.min, stats.max),
+                        });
+                    }
+                }
+            }
+        }
+
+        ChunkSummary {
+            partition_key: self.key.clone(),
+            id: self.id,
+            storage: ChunkStorage::MutableBuffer,
+            state: if self.is_open {
+                ChunkState::Open
+            } else if self.persisted {
+                ChunkState::Persisted
+            } else {
+                ChunkState::Closed
+            },
+            row_count: self.tables.values().map(|t| t.row_count() as u64).sum(),
+            size: self.size() as u64,
+            column_sizes,
+            min_time: time_range.map(|(min, _)| min),
+            max_time: time_range.map(|(_, max)| max),
+            table_names: self.summary().tables,
+            last_write_time: self.last_write_time,
+        }
+    }
+
+    /// Returns the range of the timestamps of the rows of this
+    /// partition, if it has any
+    pub fn time_range(&self) -> Option<(i64, i64)> {
+        self.tables
+            .values()
+            .filter_map(|table| self.table_time_range(table))
+            .fold(None, |range, (min, max)| match range {
+                Some((range_min, range_max)) => Some((min.min(range_min), max.max(range_max))),
+                None => Some((min, max)),
+            })
+    }
+
+    /// Returns the range of the timestamps of the rows of `table` of
+    /// this partition, if it has any
+    pub fn table_time_range(&self, table: &Table) -> Option<(i64, i64)> {
+        let time_column_id = self.dictionary.id(TIME_COLUMN_NAME)?;
+        let &index = table.column_id_to_index.get(&time_column_id)?;
+        match &table.columns[index] {
+            Column::I64(_, stats) => Some((stats.min, stats.max)),
+            _ => None,
         }
     }
 
     pub fn write_entry(&mut self, entry: &wb::WriteBufferEntry<'_>) -> Result<()> {
+        self.write_entry_rows(entry, |_, _| true)
+    }
+
+    /// Writes the rows of `entry` that `include` accepts, given the
+    /// name of their table. Tables none of whose rows are accepted are
+    /// not created.
+    pub fn write_entry_rows(
+        &mut self,
+        entry: &wb::WriteBufferEntry<'_>,
+        include: impl Fn(&str, &wb::Row<'_>) -> bool,
+    ) -> Result<()> {
         if let Some(table_batches) = entry.table_batches() {
             for batch in table_batches {
-                self.write_table_batch(&batch)?;
+                self.write_table_batch(&batch, &include)?;
             }
         }
 
         Ok(())
     }
 
-    fn write_table_batch(&mut self, batch: &wb::TableWriteBatch<'_>) -> Result<()> {
+    fn write_table_batch(
+        &mut self,
+        batch: &wb::TableWriteBatch<'_>,
+        include: &dyn Fn(&str, &wb::Row<'_>) -> bool,
+    ) -> Result<()> {
         let table_name = batch.name().context(TableWriteWithoutName)?;
+        let include = |row: &wb::Row<'_>| include(table_name, row);
+        if let Some(rows) = batch.rows() {
+            if !rows.iter().any(|row| include(&row)) {
+                return Ok(());
+            }
+        }
+
         let table_id = self.dictionary.lookup_value_or_insert(table_name);
 
-        let table = self
-            .tables
-            .entry(table_id)
-            .or_insert_with(|| Table::new(table_id));
+        let pre_aggregated = self.pre_aggregated_tables.contains(table_name);
+        let table = self.tables.entry(table_id).or_insert_with(|| {
+            let table = Table::new(table_id);
+            if pre_aggregated {
+                table.with_pre_aggregates()
+            } else {
+                table
+            }
+        });
 
         if let Some(rows) = batch.rows() {
             table
-                .append_rows(&mut self.dictionary, &rows)
+                .append_rows_where(&mut self.dictionary, &rows, &include)
                 .context(TableWrite { table_name })?;
         }
 
@@ -258,12 +471,16 @@ impl Partition {
 
         // In order to evaluate expressions in the table, all columns
         // referenced in the expression must appear (I think, not sure
-        // about NOT, etc so panic if we see one of those);
+        // about NOT, etc so panic if we see one of those), except for
+        // `column IS NULL`, which every row of a table without the
+        // column matches
         let mut visitor = SupportVisitor {};
         let mut predicate_columns: HashSet<String> = HashSet::new();
         for expr in &partition_exprs {
             visit_expression(expr, &mut visitor);
-            expr_to_column_names(&expr, &mut predicate_columns).unwrap();
+            if is_null_column(expr).is_none() {
+                expr_to_column_names(&expr, &mut predicate_columns).unwrap();
+            }
         }
 
         // if there are any column references in the expression, ensure they appear in any table
@@ -273,11 +490,37 @@ impl Partition {
             Some(self.make_partition_ids(predicate_columns.iter()))
         };
 
+        // columns missing from the partition are covered by
+        // `required_columns`
+        let column_equalities = partition_exprs
+            .iter()
+            .filter_map(column_equality)
+            .filter_map(|(column, value)| {
+                self.dictionary
+                    .id(column)
+                    .map(|column_id| (column_id, value.to_string()))
+            })
+            .collect();
+
+        // the values are found in the dictionary once, rather than
+        // lower casing the column of every table
+        let column_lowered_equalities = partition_exprs
+            .iter()
+            .filter_map(lowered_column_equality)
+            .filter_map(|(column, value)| {
+                self.dictionary
+                    .id(column)
+                    .map(|column_id| (column_id, self.dictionary.ids_lowercasing_to(value)))
+            })
+            .collect();
+
         Ok(PartitionPredicate {
             table_name_predicate,
             field_restriction,
             partition_exprs,
             required_columns,
+            column_equalities,
+            column_lowered_equalities,
             time_column_id,
             range,
         })
@@ -340,7 +583,34 @@ impl Partition {
     /// returns true if data with partition key `key` should be
     /// written to this partition,
     pub fn should_write(&self, key: &str) -> bool {
-        self.key.starts_with(key) && self.is_open
+        self.key.starts_with(key) && self.is_open && self.late_window.is_none()
+    }
+
+    /// Returns true if this partition has data for the table `table_name`
+    pub fn has_table(&self, table_name: &str) -> bool {
+        self.dictionary
+            .id(table_name)
+            .map_or(false, |table_id| self.tables.contains_key(&table_id))
+    }
+
+    /// Returns the names of the tag columns of the table `table_name`
+    /// in this partition
+    pub fn tag_column_names(&self, table_name: &str) -> Vec<&str> {
+        let table = match self
+            .dictionary
+            .id(table_name)
+            .and_then(|table_id| self.tables.get(&table_id))
+        {
+            Some(table) => table,
+            None => return vec![],
+        };
+
+        table
+            .column_id_to_index
+            .iter()
+            .filter(|(_, &column_index)| matches!(table.columns[column_index], Column::Tag(..)))
+            .filter_map(|(column_id, _)| self.dictionary.lookup_id(*column_id).ok())
+            .collect()
     }
 
     /// Convert the table specified in this partition into an arrow record batch
@@ -383,6 +653,69 @@ impl Partition {
     }
 }
 
+/// Returns the name of the column and the value if `expr` is
+/// `column = 'value'` (or `'value' = column`)
+fn column_equality(expr: &Expr) -> Option<(&str, &str)> {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::Eq,
+            right,
+        } => match (left.as_ref(), right.as_ref()) {
+            (Expr::Column(column), Expr::Literal(ScalarValue::Utf8(Some(value))))
+            | (Expr::Literal(ScalarValue::Utf8(Some(value))), Expr::Column(column)) => {
+                Some((column.as_str(), value.as_str()))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the name of the column and the value if `expr` is
+/// `lower(column) = 'value'` (or `'value' = lower(column)`)
+fn lowered_column_equality(expr: &Expr) -> Option<(&str, &str)> {
+    match expr {
+        Expr::BinaryExpr {
+            left,
+            op: Operator::Eq,
+            right,
+        } => match (left.as_ref(), right.as_ref()) {
+            (lowered, Expr::Literal(ScalarValue::Utf8(Some(value))))
+            | (Expr::Literal(ScalarValue::Utf8(Some(value))), lowered) => {
+                Some((lowered_column(lowered)?, value.as_str()))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the name of the column if `expr` is `lower(column)`
+fn lowered_column(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::ScalarFunction {
+            fun: BuiltinScalarFunction::Lower,
+            args,
+        } => match args.as_slice() {
+            [Expr::Column(column)] => Some(column),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Returns the name of the column if `expr` is `column IS NULL`
+fn is_null_column(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::IsNull(expr) => match expr.as_ref() {
+            Expr::Column(name) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
 /// Used to figure out if we know how to deal with this kind of
 /// predicate in the write buffer
 struct SupportVisitor {}
@@ -392,6 +725,8 @@ impl ExpressionVisitor for SupportVisitor {
         match expr {
             Expr::Literal(..) => {}
             Expr::Column(..) => {}
+            Expr::IsNull(..) | Expr::IsNotNull(..) => {}
+            Expr::ScalarFunction { .. } if lowered_column(expr).is_some() => {}
             Expr::BinaryExpr { op, .. } => {
                 match op {
                     Operator::Eq
@@ -423,11 +758,23 @@ impl ExpressionVisitor for SupportVisitor {
 pub struct RestorationStats {
     pub row_count: usize,
     pub tables: BTreeSet<String>,
+    /// The sequence number that follows the last WAL entry restored
+    pub next_sequence_number: u64,
 }
 
 /// Given a set of WAL entries, restore them into a set of Partitions.
 pub fn restore_partitions_from_wal(
     wal_entries: impl Iterator<Item = WalResult<WalEntry>>,
+) -> Result<(Vec<Partition>, RestorationStats)> {
+    restore_partitions_from_wal_filtered(wal_entries, |_, _| true)
+}
+
+/// Given a set of WAL entries, restore them into a set of Partitions,
+/// skipping the data for a partition key in an entry with a given
+/// sequence number when `keep(partition_key, sequence_number)` is false.
+pub fn restore_partitions_from_wal_filtered(
+    wal_entries: impl Iterator<Item = WalResult<WalEntry>>,
+    keep: impl Fn(&str, u64) -> bool,
 ) -> Result<(Vec<Partition>, RestorationStats)> {
     let mut stats = RestorationStats::default();
 
@@ -435,6 +782,8 @@ pub fn restore_partitions_from_wal(
 
     for wal_entry in wal_entries {
         let wal_entry = wal_entry.context(WalEntryRead)?;
+        let sequence_number = wal_entry.sequence_number();
+        stats.next_sequence_number = stats.next_sequence_number.max(sequence_number + 1);
         let bytes = wal_entry.as_data();
 
         let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&bytes);
@@ -442,6 +791,9 @@ pub fn restore_partitions_from_wal(
         if let Some(entries) = batch.entries() {
             for entry in entries {
                 let partition_key = entry.partition_key().context(MissingPartitionKey)?;
+                if !keep(partition_key, sequence_number) {
+                    continue;
+                }
 
                 if !partitions.contains_key(partition_key) {
                     partitions.insert(
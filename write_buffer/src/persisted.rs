@@ -0,0 +1,607 @@
+//! This module contains the reading of persisted chunks, which
+//! `persist_chunk` writes as a parquet file per table.
+//!
+//! Opening a chunk only reads the metadata (footer) of each file. The
+//! values of a column are decoded when they are read, and only the
+//! pages of the columns being read are decoded. When the files are
+//! memory mapped, only those pages are read from disk, so a chunk can
+//! serve queries without first loading all of its data onto the heap.
+//!
+//! Alongside the parquet file of each table is a JSON file recording
+//! the encoding of each of its columns, which is read with the
+//! metadata.
+
+use std::any::Any;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use arrow_deps::{
+    arrow::{
+        array::{
+            Array, BooleanArray, Float64Array, Int64Array, StringArray, TimestampMicrosecondArray,
+        },
+        datatypes::{DataType, SchemaRef, TimeUnit},
+        error::ArrowError,
+        record_batch::RecordBatch,
+    },
+    parquet::{
+        self,
+        arrow::{ArrowReader, ParquetFileArrowReader},
+        errors::ParquetError,
+        file::reader::{ChunkReader, FileReader, Length, SerializedFileReader},
+    },
+};
+use data_types::{
+    data::{rows_to_write_entry_partition, RowValue, TableRows},
+    partition_metadata::ColumnEncoding,
+};
+use generated_types::wal as wb;
+use memmap::Mmap;
+use snafu::{OptionExt, ResultExt, Snafu};
+
+use crate::partition::Partition;
+
+/// The number of rows in each record batch read from a persisted table
+const BATCH_SIZE: usize = 1024;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Error listing persisted chunk directory {:?}: {}", path, source))]
+    ListingDirectory { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error opening persisted file {:?}: {}", path, source))]
+    OpeningFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error memory mapping persisted file {:?}: {}", path, source))]
+    MappingFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error reading persisted file {:?}: {}", path, source))]
+    ReadingFile { path: PathBuf, source: io::Error },
+
+    #[snafu(display("Error reading parquet data of persisted file {:?}: {}", path, source))]
+    ReadingParquet { path: PathBuf, source: ParquetError },
+
+    #[snafu(display("Error decoding columns of persisted file {:?}: {}", path, source))]
+    DecodingColumns { path: PathBuf, source: ArrowError },
+
+    #[snafu(display("Persisted table {} has no column {}", table, column))]
+    ColumnNotFound { table: String, column: String },
+
+    #[snafu(display("Error reading column metadata {:?}: {}", path, source))]
+    ReadingMetadata {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+
+    #[snafu(display(
+        "Persisted table {} has column {} of unsupported type {:?}",
+        table,
+        column,
+        data_type
+    ))]
+    UnsupportedColumnType {
+        table: String,
+        column: String,
+        data_type: DataType,
+    },
+
+    #[snafu(display("Error restoring data of persisted table {}: {}", table, source))]
+    RestoringTable {
+        table: String,
+        source: crate::partition::Error,
+    },
+
+    #[snafu(display("Invalid open mode {:?}: expected read or mmap", mode))]
+    InvalidOpenMode { mode: String },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// How the files of a persisted chunk are read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    /// Read each file into memory when the chunk is opened
+    Read,
+
+    /// Memory map each file, so that its pages are only read from disk
+    /// when the columns stored in them are first read
+    Mmap,
+}
+
+impl Default for OpenMode {
+    fn default() -> Self {
+        Self::Read
+    }
+}
+
+impl FromStr for OpenMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "read" => Ok(Self::Read),
+            "mmap" => Ok(Self::Mmap),
+            _ => InvalidOpenMode { mode: s }.fail(),
+        }
+    }
+}
+
+impl fmt::Display for OpenMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => write!(f, "read"),
+            Self::Mmap => write!(f, "mmap"),
+        }
+    }
+}
+
+/// A chunk that has been persisted to a directory of parquet files,
+/// one per table
+#[derive(Debug)]
+pub struct PersistedChunk {
+    tables: BTreeMap<String, PersistedTable>,
+}
+
+impl PersistedChunk {
+    /// Opens the persisted chunk in `dir`, reading its files as `mode`
+    /// describes
+    pub fn open(dir: &Path, mode: OpenMode) -> Result<Self> {
+        let entries = fs::read_dir(dir).context(ListingDirectory { path: dir })?;
+
+        let mut tables = BTreeMap::new();
+        for entry in entries {
+            let path = entry.context(ListingDirectory { path: dir })?.path();
+            if path.extension() != Some(std::ffi::OsStr::new("parquet")) {
+                continue;
+            }
+
+            let table_name = match path.file_stem() {
+                Some(stem) => stem.to_string_lossy().to_string(),
+                None => continue,
+            };
+            let table = PersistedTable::open(table_name.clone(), path, mode)?;
+            tables.insert(table_name, table);
+        }
+
+        Ok(Self { tables })
+    }
+
+    /// Returns the names of the tables in this chunk
+    pub fn table_names(&self) -> impl Iterator<Item = &str> {
+        self.tables.keys().map(|name| name.as_str())
+    }
+
+    /// Returns the table named `table_name`, if this chunk has it
+    pub fn table(&self, table_name: &str) -> Option<&PersistedTable> {
+        self.tables.get(table_name)
+    }
+
+    /// Reads all of the data of this chunk back into a closed and
+    /// persisted partition with key `key` and id `id`, so that it can be
+    /// queried as the chunk was before it was evicted from memory
+    pub fn read_partition(&self, key: &str, id: u32) -> Result<Partition> {
+        let mut partition = Partition::new(key);
+        partition.id = id;
+        partition.is_open = false;
+        partition.persisted = true;
+
+        for table in self.tables.values() {
+            let columns: Vec<_> = table.columns.iter().map(|c| c.name.as_str()).collect();
+            for batch in table.read_columns(&columns)? {
+                let rows = table.batch_rows(&batch)?;
+                let tables = [TableRows {
+                    name: &table.name,
+                    rows,
+                }];
+                let data = rows_to_write_entry_partition(key, &tables);
+                let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+                if let Some(entries) = batch.entries() {
+                    for entry in entries {
+                        partition
+                            .write_entry(&entry)
+                            .context(RestoringTable { table: &table.name })?;
+                    }
+                }
+            }
+
+            let encodings = table
+                .columns
+                .iter()
+                .filter_map(|c| Some((c.name.clone(), c.encoding?)))
+                .collect();
+            partition.encodings.insert(table.name.clone(), encodings);
+        }
+
+        Ok(partition)
+    }
+}
+
+/// Returns the path of the file recording the encodings of the
+/// columns of the parquet file at `path`
+pub(crate) fn encodings_path(path: &Path) -> PathBuf {
+    path.with_extension("encodings.json")
+}
+
+/// Returns the path of the file recording the names of the tag
+/// columns of the parquet file at `path`
+pub(crate) fn tags_path(path: &Path) -> PathBuf {
+    path.with_extension("tags.json")
+}
+
+/// Reads the JSON file at `path`, or returns the default value if
+/// there is no such file
+fn read_optional_json<T>(path: &Path) -> Result<T>
+where
+    T: serde::de::DeserializeOwned + Default,
+{
+    match fs::read(path) {
+        Ok(bytes) => serde_json::from_slice(&bytes).context(ReadingMetadata { path }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(T::default()),
+        Err(e) => Err(e).context(ReadingFile { path }),
+    }
+}
+
+/// A table of a persisted chunk, whose columns are decoded when they
+/// are read
+#[derive(Debug)]
+pub struct PersistedTable {
+    name: String,
+    path: PathBuf,
+    data: FileData,
+    schema: SchemaRef,
+    num_rows: usize,
+    columns: Vec<PersistedColumn>,
+    tag_columns: BTreeSet<String>,
+}
+
+/// How a column of a persisted table is stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PersistedColumn {
+    pub name: String,
+    /// The encoding of the column, if it was recorded when the table
+    /// was persisted
+    pub encoding: Option<ColumnEncoding>,
+    /// The bytes of the encoded values, after they are compressed
+    pub compressed_bytes: u64,
+    /// The bytes of the encoded values, before they are compressed
+    pub uncompressed_bytes: u64,
+}
+
+impl PersistedTable {
+    fn open(name: String, path: PathBuf, mode: OpenMode) -> Result<Self> {
+        let data = match mode {
+            OpenMode::Read => {
+                let bytes = fs::read(&path).context(ReadingFile { path: &path })?;
+                FileData::Heap(Arc::new(bytes))
+            }
+            OpenMode::Mmap => {
+                let file = fs::File::open(&path).context(OpeningFile { path: &path })?;
+                // Persisted files are written once and never modified,
+                // so the mapped memory does not change under the reader
+                let mmap = unsafe { Mmap::map(&file) }.context(MappingFile { path: &path })?;
+                FileData::Mapped(Arc::new(mmap))
+            }
+        };
+
+        // Only the metadata at the end of the file is read here
+        let file_reader =
+            SerializedFileReader::new(data.clone()).context(ReadingParquet { path: &path })?;
+        let metadata = file_reader.metadata();
+        let num_rows = metadata.file_metadata().num_rows() as usize;
+
+        // tables persisted before encodings or tags were recorded have
+        // no file of them
+        let encodings: BTreeMap<String, ColumnEncoding> =
+            read_optional_json(&encodings_path(&path))?;
+        let tag_columns: BTreeSet<String> = read_optional_json(&tags_path(&path))?;
+
+        let mut columns: Vec<_> = metadata
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|column| PersistedColumn {
+                name: column.name().to_string(),
+                encoding: encodings.get(column.name()).copied(),
+                compressed_bytes: 0,
+                uncompressed_bytes: 0,
+            })
+            .collect();
+        for row_group in metadata.row_groups() {
+            for (column, chunk) in columns.iter_mut().zip(row_group.columns()) {
+                column.compressed_bytes += chunk.compressed_size() as u64;
+                column.uncompressed_bytes += chunk.uncompressed_size() as u64;
+            }
+        }
+
+        let schema = ParquetFileArrowReader::new(Rc::new(file_reader))
+            .get_schema()
+            .context(ReadingParquet { path: &path })?;
+
+        Ok(Self {
+            name,
+            path,
+            data,
+            schema: Arc::new(schema),
+            num_rows,
+            columns,
+            tag_columns,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The schema of all the columns of the table
+    pub fn schema(&self) -> SchemaRef {
+        Arc::clone(&self.schema)
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.num_rows
+    }
+
+    /// How each column of the table is stored, in the order of the
+    /// columns of the file
+    pub fn columns(&self) -> &[PersistedColumn] {
+        &self.columns
+    }
+
+    /// The names of the tag columns of the table, if they were
+    /// recorded when the table was persisted
+    pub fn tag_columns(&self) -> &BTreeSet<String> {
+        &self.tag_columns
+    }
+
+    /// Decodes the values of `columns`, leaving the pages of any other
+    /// columns untouched. The columns of the record batches are in the
+    /// order of the table's schema.
+    pub fn read_columns(&self, columns: &[&str]) -> Result<Vec<RecordBatch>> {
+        let indices = columns
+            .iter()
+            .map(|column| {
+                self.schema
+                    .fields()
+                    .iter()
+                    .position(|field| field.name() == column)
+                    .context(ColumnNotFound {
+                        table: &self.name,
+                        column: *column,
+                    })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let file_reader = SerializedFileReader::new(self.data.clone())
+            .context(ReadingParquet { path: &self.path })?;
+        let batches = ParquetFileArrowReader::new(Rc::new(file_reader))
+            .get_record_reader_by_columns(indices, BATCH_SIZE)
+            .context(ReadingParquet { path: &self.path })?;
+
+        batches
+            .map(|batch| batch.context(DecodingColumns { path: &self.path }))
+            .collect()
+    }
+}
+
+impl PersistedTable {
+    /// Returns the values of each row of `batch`, which has all of the
+    /// columns of this table, to be written to a partition
+    fn batch_rows<'a>(
+        &'a self,
+        batch: &'a RecordBatch,
+    ) -> Result<Vec<Vec<(&'a str, RowValue<'a>)>>> {
+        let mut rows = vec![vec![]; batch.num_rows()];
+        for (field, column) in self.schema.fields().iter().zip(batch.columns()) {
+            let name = field.name().as_str();
+            let mut push = |value: &dyn Fn(usize) -> RowValue<'a>| {
+                for (row, values) in rows.iter_mut().enumerate() {
+                    if column.is_valid(row) {
+                        values.push((name, value(row)));
+                    }
+                }
+            };
+
+            let column = column.as_any();
+            match field.data_type() {
+                DataType::Utf8 => {
+                    let array = downcast::<StringArray>(column);
+                    if self.tag_columns.contains(name) {
+                        push(&move |row| RowValue::Tag(array.value(row)))
+                    } else {
+                        push(&move |row| RowValue::String(array.value(row)))
+                    }
+                }
+                DataType::Float64 => {
+                    let array = downcast::<Float64Array>(column);
+                    push(&move |row| RowValue::F64(array.value(row)))
+                }
+                DataType::Int64 => {
+                    let array = downcast::<Int64Array>(column);
+                    push(&move |row| RowValue::I64(array.value(row)))
+                }
+                DataType::Boolean => {
+                    let array = downcast::<BooleanArray>(column);
+                    push(&move |row| RowValue::Bool(array.value(row)))
+                }
+                // times are persisted as nanoseconds, although the
+                // files describe them as microseconds
+                DataType::Timestamp(TimeUnit::Microsecond, _) => {
+                    let array = downcast::<TimestampMicrosecondArray>(column);
+                    push(&move |row| RowValue::I64(array.value(row)))
+                }
+                data_type => {
+                    return UnsupportedColumnType {
+                        table: &self.name,
+                        column: name,
+                        data_type: data_type.clone(),
+                    }
+                    .fail()
+                }
+            }
+        }
+
+        Ok(rows)
+    }
+}
+
+/// Returns `array` as the concrete array type of its data type
+fn downcast<T: 'static>(array: &dyn Any) -> &T {
+    array
+        .downcast_ref::<T>()
+        .expect("array is of the type of its data type")
+}
+
+/// The contents of a persisted file, either read into memory or
+/// memory mapped
+#[derive(Debug, Clone)]
+enum FileData {
+    Heap(Arc<Vec<u8>>),
+    Mapped(Arc<Mmap>),
+}
+
+impl FileData {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Self::Heap(bytes) => bytes,
+            Self::Mapped(mmap) => mmap,
+        }
+    }
+}
+
+impl Length for FileData {
+    fn len(&self) -> u64 {
+        self.bytes().len() as u64
+    }
+}
+
+impl ChunkReader for FileData {
+    type T = FileSlice;
+
+    fn get_read(&self, start: u64, length: usize) -> parquet::errors::Result<Self::T> {
+        let start = start as usize;
+        let end = start
+            .checked_add(length)
+            .filter(|&end| end <= self.bytes().len())
+            .ok_or_else(|| {
+                ParquetError::EOF(format!(
+                    "read of {} bytes at {} is past the end of the file",
+                    length, start
+                ))
+            })?;
+
+        Ok(FileSlice {
+            data: self.clone(),
+            position: start,
+            end,
+        })
+    }
+}
+
+/// Reads a range of the bytes of a `FileData`
+#[derive(Debug)]
+struct FileSlice {
+    data: FileData,
+    position: usize,
+    end: usize,
+}
+
+impl Read for FileSlice {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = &self.data.bytes()[self.position..self.end];
+        let len = remaining.len().min(buf.len());
+        buf[..len].copy_from_slice(&remaining[..len]);
+        self.position += len;
+        Ok(len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Db;
+    use arrow_deps::arrow::util::pretty::pretty_format_batches;
+    use influxdb_line_protocol::parse_lines;
+    use storage::Database;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn read_persisted_chunk() -> Result {
+        let mut dir = test_helpers::tmp_dir()?.into_path();
+        let db = Db::try_with_wal("persisted_db", &mut dir).await?;
+
+        let lp = "cpu,host=a usage=1 10\ncpu,host=b usage=3 30\nmem,host=a free=2i 20";
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+        db.close_chunk("1970-01-01T00", 0).await?;
+        db.persist_chunk("1970-01-01T00", 0).await?;
+
+        let expected = "+------+-------+\n\
+                        | host | usage |\n\
+                        +------+-------+\n\
+                        | a    | 1     |\n\
+                        | b    | 3     |\n\
+                        +------+-------+\n";
+
+        for &mode in &[OpenMode::Read, OpenMode::Mmap] {
+            let chunk = db.open_persisted_chunk("1970-01-01T00", 0, mode)?;
+            assert_eq!(chunk.table_names().collect::<Vec<_>>(), vec!["cpu", "mem"]);
+
+            let table = chunk.table("cpu").expect("cpu table");
+            assert_eq!(table.num_rows(), 2);
+            assert_eq!(table.tag_columns().iter().collect::<Vec<_>>(), vec!["host"]);
+
+            let batches = table.read_columns(&["host", "usage"])?;
+            assert_eq!(pretty_format_batches(&batches)?, expected);
+
+            let err = table.read_columns(&["free"]).unwrap_err();
+            assert_eq!(err.to_string(), "Persisted table cpu has no column free");
+
+            let encodings: Vec<_> = table
+                .columns()
+                .iter()
+                .map(|c| (c.name.as_str(), c.encoding))
+                .collect();
+            assert_eq!(
+                encodings,
+                vec![
+                    ("host", Some(ColumnEncoding::Dictionary)),
+                    ("usage", Some(ColumnEncoding::Plain)),
+                    ("time", Some(ColumnEncoding::Dictionary)),
+                ]
+            );
+        }
+
+        // chunks persisted without the encodings of their columns can
+        // still be read
+        let chunk_dir = dir
+            .join(crate::PERSISTED_CHUNKS_DIR_NAME)
+            .join("1970-01-01T00")
+            .join("0");
+        fs::remove_file(chunk_dir.join("cpu.encodings.json"))?;
+        let chunk = db.open_persisted_chunk("1970-01-01T00", 0, OpenMode::Read)?;
+        let table = chunk.table("cpu").expect("cpu table");
+        assert!(table.columns().iter().all(|c| c.encoding.is_none()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_open_mode() {
+        for &mode in &[OpenMode::Read, OpenMode::Mmap] {
+            assert_eq!(mode.to_string().parse::<OpenMode>().unwrap(), mode);
+        }
+        assert_eq!(
+            "lazy".parse::<OpenMode>().unwrap_err().to_string(),
+            r#"Invalid open mode "lazy": expected read or mmap"#
+        );
+    }
+}
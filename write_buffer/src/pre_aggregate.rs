@@ -0,0 +1,569 @@
+//! This module contains the pre-aggregates of a table: the count,
+//! sum, minimum and maximum of each numeric field of each series in
+//! each minute, kept up to date as rows are written to the tables
+//! named in the `pre_aggregated_tables` of the database rules.
+//!
+//! Window aggregates whose windows are made up of whole minutes are
+//! computed from the pre-aggregates instead of the rows, when every
+//! table a query reads has them and its predicate can be evaluated on
+//! the tags of each series. A table only has pre-aggregates if it has
+//! had them since its first row, so tables restored from the WAL or
+//! rewritten by compaction or downsampling are always read by row.
+//!
+//! Float values that are NaN or infinite are not added to the
+//! aggregates, as they are excluded from every aggregate computed from
+//! the rows.
+//!
+//! Duplicate rows, with the same series and timestamp, would be
+//! counted more than once, so tables whose rows of a series were not
+//! written in time order, or whose chunks overlap, are read by row,
+//! where the duplicates are removed.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+use std::sync::Arc;
+
+use arrow_deps::datafusion::{
+    logical_plan::{Expr, Operator},
+    scalar::ScalarValue,
+};
+use data_types::TIME_COLUMN_NAME;
+use storage::{
+    exec::window_aggregate::{Bucket, BucketedField, BucketedSeries, BucketedValues},
+    predicate::TimestampRange,
+};
+
+use crate::column::Column;
+use crate::partition::{Partition, PartitionPredicate};
+use crate::table::Table;
+
+/// The width of the buckets the values are aggregated in: a minute
+pub const BUCKET_NANOS: i64 = 60 * 1_000_000_000;
+
+/// The value of a numeric field of a row
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldValue {
+    F64(f64),
+    I64(i64),
+}
+
+/// The pre-aggregates of the series of a table
+#[derive(Debug, Default)]
+pub struct PreAggregates {
+    /// The aggregates of each series, keyed on the (column id, value
+    /// id) of each of its tags, in column id order
+    series: BTreeMap<Vec<(u32, u32)>, SeriesAggregates>,
+
+    /// True if a row of a series was written with a timestamp no
+    /// later than that of the previous row of the series, so there
+    /// may be duplicate rows in the aggregates
+    out_of_order: bool,
+}
+
+#[derive(Debug, Default)]
+struct SeriesAggregates {
+    /// The timestamp of the last row of the series written
+    last_time: Option<i64>,
+
+    /// The start of each bucket the series has rows in
+    buckets: BTreeSet<i64>,
+
+    /// The aggregates of each numeric field, by the id of its column
+    fields: BTreeMap<u32, BucketedValues>,
+}
+
+impl PreAggregates {
+    /// Adds a row of the series with `tags` at `time`, with the
+    /// (column id, value) of each of its numeric fields with a value
+    pub fn add(&mut self, tags: Vec<(u32, u32)>, time: i64, fields: Vec<(u32, FieldValue)>) {
+        let start = time - time.rem_euclid(BUCKET_NANOS);
+        let series = self.series.entry(tags).or_default();
+        if series
+            .last_time
+            .map_or(false, |last_time| time <= last_time)
+        {
+            self.out_of_order = true;
+        }
+        series.last_time = Some(time);
+        series.buckets.insert(start);
+
+        for (column_id, value) in fields {
+            let values = series
+                .fields
+                .entry(column_id)
+                .or_insert_with(|| match value {
+                    FieldValue::F64(_) => BucketedValues::F64(BTreeMap::new()),
+                    FieldValue::I64(_) => BucketedValues::I64(BTreeMap::new()),
+                });
+
+            match (values, value) {
+                (BucketedValues::F64(buckets), FieldValue::F64(value)) => {
+                    if !value.is_finite() {
+                        continue;
+                    }
+                    let bucket = buckets.entry(start).or_insert(Bucket {
+                        count: 0,
+                        sum: 0.0,
+                        min: f64::INFINITY,
+                        max: f64::NEG_INFINITY,
+                    });
+                    bucket.count += 1;
+                    bucket.sum += value;
+                    bucket.min = bucket.min.min(value);
+                    bucket.max = bucket.max.max(value);
+                }
+                (BucketedValues::I64(buckets), FieldValue::I64(value)) => {
+                    let bucket = buckets.entry(start).or_insert(Bucket {
+                        count: 0,
+                        sum: 0,
+                        min: i64::MAX,
+                        max: i64::MIN,
+                    });
+                    bucket.count += 1;
+                    bucket.sum = bucket.sum.saturating_add(value);
+                    bucket.min = bucket.min.min(value);
+                    bucket.max = bucket.max.max(value);
+                }
+                // the type of a column never changes
+                _ => {}
+            }
+        }
+    }
+
+    /// The approximate number of bytes used by the pre-aggregates
+    pub fn size(&self) -> usize {
+        self.series
+            .iter()
+            .map(|(tags, series)| {
+                let buckets = series
+                    .fields
+                    .values()
+                    .map(|values| match values {
+                        BucketedValues::F64(buckets) => buckets.len(),
+                        BucketedValues::I64(buckets) => buckets.len(),
+                    })
+                    .sum::<usize>();
+                mem::size_of_val(tags.as_slice())
+                    + series.buckets.len() * mem::size_of::<i64>()
+                    + buckets * mem::size_of::<(i64, Bucket<i64>)>()
+            })
+            .sum()
+    }
+}
+
+/// Returns the series of `table` that pass `predicate`, from the
+/// pre-aggregates of the table, in the order the rows of the series
+/// would be read in.
+///
+/// Returns `None` if the table has no pre-aggregates, or they can not
+/// answer the predicate: when it has a time range that splits buckets
+/// of the table, selects fields that are not numeric, or has
+/// expressions on anything but tags. Also returns `None` if the rows
+/// of a series were written out of time order.
+pub fn bucketed_series(
+    table: &Table,
+    partition: &Partition,
+    predicate: &PartitionPredicate,
+) -> Option<Vec<BucketedSeries>> {
+    let pre_aggregates = table.pre_aggregates.as_ref()?;
+    if pre_aggregates.out_of_order {
+        return None;
+    }
+    let name = |id: u32| {
+        partition
+            .dictionary
+            .lookup_id(id)
+            .expect("name is in the partition dictionary")
+    };
+
+    let mut tag_columns = BTreeMap::new();
+    let mut field_columns = BTreeMap::new();
+    let mut time_range = None;
+    for (&column_id, &index) in &table.column_id_to_index {
+        match &table.columns[index] {
+            Column::Tag(_, _) => {
+                tag_columns.insert(name(column_id), column_id);
+            }
+            Column::I64(_, stats) if predicate.is_time_column(column_id) => {
+                time_range = Some((stats.min, stats.max));
+            }
+            column => {
+                if predicate.should_include_field(column_id) {
+                    match column {
+                        Column::F64(_, _) | Column::I64(_, _) => {
+                            field_columns.insert(name(column_id), column_id);
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+        }
+    }
+
+    // the buckets of rows within the range must be within it too
+    let range = match (predicate.range, time_range) {
+        (Some(range), Some((min, max))) if range.start <= min && max < range.end => None,
+        (Some(range), _) => {
+            let aligned = |t: i64| t.rem_euclid(BUCKET_NANOS) == 0;
+            if !aligned(range.start) || !aligned(range.end) {
+                return None;
+            }
+            Some(range)
+        }
+        (None, _) => None,
+    };
+    let in_range = |start: &i64| range.map_or(true, |range: TimestampRange| range.contains(*start));
+
+    let table_name = Arc::new(name(table.id).to_string());
+    let mut series = Vec::with_capacity(pre_aggregates.series.len());
+    for (tags, aggregates) in &pre_aggregates.series {
+        let tag_value = |column: &str| {
+            match tag_columns.get(column) {
+                Some(column_id) => Some(
+                    tags.iter()
+                        .find(|(id, _)| id == column_id)
+                        .map(|&(_, value_id)| name(value_id)),
+                ),
+                // a column the table does not have is null
+                None if table_has_column(table, partition, column) => None,
+                None => Some(None),
+            }
+        };
+
+        let mut passes = true;
+        for expr in &predicate.partition_exprs {
+            passes &= evaluate(expr, &tag_value)? == Value::Bool(Some(true));
+        }
+        if !passes {
+            continue;
+        }
+
+        let buckets = aggregates
+            .buckets
+            .iter()
+            .cloned()
+            .filter(in_range)
+            .collect::<Vec<_>>();
+        if buckets.is_empty() {
+            continue;
+        }
+
+        let fields = field_columns
+            .iter()
+            .map(|(&field_name, &column_id)| {
+                let values = match aggregates.fields.get(&column_id) {
+                    Some(BucketedValues::F64(values)) => BucketedValues::F64(
+                        values
+                            .iter()
+                            .filter(|(start, _)| in_range(*start))
+                            .map(|(&start, &bucket)| (start, bucket))
+                            .collect(),
+                    ),
+                    Some(BucketedValues::I64(values)) => BucketedValues::I64(
+                        values
+                            .iter()
+                            .filter(|(start, _)| in_range(*start))
+                            .map(|(&start, &bucket)| (start, bucket))
+                            .collect(),
+                    ),
+                    // the series has no values of the field
+                    None => match &table.columns[table.column_id_to_index[&column_id]] {
+                        Column::F64(_, _) => BucketedValues::F64(BTreeMap::new()),
+                        _ => BucketedValues::I64(BTreeMap::new()),
+                    },
+                };
+                BucketedField {
+                    name: field_name.to_string(),
+                    values,
+                }
+            })
+            .collect();
+
+        // every tag column of the table, with an empty value for the
+        // tags the series does not have, as when reading the rows
+        let tags = tag_columns
+            .iter()
+            .map(|(&column, _)| {
+                let value = tag_value(column).flatten().unwrap_or("");
+                (Arc::new(column.to_string()), Arc::new(value.to_string()))
+            })
+            .collect();
+
+        series.push(BucketedSeries {
+            table_name: Arc::clone(&table_name),
+            tags,
+            buckets,
+            fields,
+        });
+    }
+
+    // rows are read sorted on their tags
+    series.sort_by(|a, b| {
+        let values = |series: &BucketedSeries| {
+            series
+                .tags
+                .iter()
+                .map(|(_, value)| Arc::clone(value))
+                .collect::<Vec<_>>()
+        };
+        values(a).cmp(&values(b))
+    });
+    Some(series)
+}
+
+/// Returns true if `table` has a column named `column`
+fn table_has_column(table: &Table, partition: &Partition, column: &str) -> bool {
+    column == TIME_COLUMN_NAME
+        || partition
+            .dictionary
+            .id(column)
+            .map_or(false, |id| table.column_id_to_index.contains_key(&id))
+}
+
+/// The value of an expression evaluated on the tags of a series
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Value<'a> {
+    Bool(Option<bool>),
+    String(Option<&'a str>),
+}
+
+/// Evaluates `expr` on the tags of a series, where `tag_value` returns
+/// the value of a tag column (`None` for columns that are not tags).
+/// Returns `None` if `expr` is not made up of comparisons of tags
+/// with strings.
+fn evaluate<'a>(
+    expr: &'a Expr,
+    tag_value: &impl Fn(&str) -> Option<Option<&'a str>>,
+) -> Option<Value<'a>> {
+    let boolean = |value| match value {
+        Value::Bool(value) => Some(value),
+        Value::String(_) => None,
+    };
+    let string = |value| match value {
+        Value::String(value) => Some(value),
+        Value::Bool(_) => None,
+    };
+
+    Some(match expr {
+        Expr::Column(column) => Value::String(tag_value(column)?),
+        Expr::Literal(ScalarValue::Utf8(value)) => Value::String(value.as_deref()),
+        Expr::Literal(ScalarValue::Boolean(value)) => Value::Bool(*value),
+        Expr::Nested(expr) => evaluate(expr, tag_value)?,
+        Expr::Not(expr) => Value::Bool(boolean(evaluate(expr, tag_value)?)?.map(|value| !value)),
+        Expr::IsNull(expr) => Value::Bool(Some(is_null(evaluate(expr, tag_value)?))),
+        Expr::IsNotNull(expr) => Value::Bool(Some(!is_null(evaluate(expr, tag_value)?))),
+        Expr::BinaryExpr { left, op, right } => {
+            // both sides are always evaluated, so that unsupported
+            // expressions are found whatever the values of the tags
+            let left = evaluate(left, tag_value)?;
+            let right = evaluate(right, tag_value)?;
+            match op {
+                Operator::And => Value::Bool(match (boolean(left)?, boolean(right)?) {
+                    (Some(false), _) | (_, Some(false)) => Some(false),
+                    (Some(true), Some(true)) => Some(true),
+                    _ => None,
+                }),
+                Operator::Or => Value::Bool(match (boolean(left)?, boolean(right)?) {
+                    (Some(true), _) | (_, Some(true)) => Some(true),
+                    (Some(false), Some(false)) => Some(false),
+                    _ => None,
+                }),
+                Operator::Eq
+                | Operator::NotEq
+                | Operator::Lt
+                | Operator::LtEq
+                | Operator::Gt
+                | Operator::GtEq => Value::Bool(match (string(left)?, string(right)?) {
+                    (Some(left), Some(right)) => Some(match op {
+                        Operator::Eq => left == right,
+                        Operator::NotEq => left != right,
+                        Operator::Lt => left < right,
+                        Operator::LtEq => left <= right,
+                        Operator::Gt => left > right,
+                        _ => left >= right,
+                    }),
+                    _ => None,
+                }),
+                _ => return None,
+            }
+        }
+        _ => return None,
+    })
+}
+
+fn is_null(value: Value<'_>) -> bool {
+    matches!(value, Value::Bool(None) | Value::String(None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn col(name: &str) -> Expr {
+        Expr::Column(name.into())
+    }
+
+    fn lit(value: &str) -> Expr {
+        Expr::Literal(ScalarValue::Utf8(Some(value.into())))
+    }
+
+    fn binary(left: Expr, op: Operator, right: Expr) -> Expr {
+        Expr::BinaryExpr {
+            left: Box::new(left),
+            op,
+            right: Box::new(right),
+        }
+    }
+
+    #[test]
+    fn test_add_non_finite_floats() {
+        let mut pre_aggregates = PreAggregates::default();
+        let tags = vec![(1, 2)];
+        let values = vec![1.0, f64::NAN, f64::INFINITY, 2.0, f64::NEG_INFINITY];
+        for (time, value) in values.into_iter().enumerate() {
+            pre_aggregates.add(tags.clone(), time as i64, vec![(3, FieldValue::F64(value))]);
+        }
+        pre_aggregates.add(
+            tags.clone(),
+            BUCKET_NANOS,
+            vec![(3, FieldValue::F64(f64::NAN))],
+        );
+
+        // the bucket of the minute with only a NaN value has no
+        // aggregates, although the series has a row in it
+        let series = &pre_aggregates.series[&tags];
+        assert_eq!(
+            series.buckets.iter().cloned().collect::<Vec<_>>(),
+            vec![0, BUCKET_NANOS]
+        );
+        let expected = vec![(
+            0,
+            Bucket {
+                count: 2,
+                sum: 3.0,
+                min: 1.0,
+                max: 2.0,
+            },
+        )];
+        assert_eq!(
+            series.fields[&3],
+            BucketedValues::F64(expected.into_iter().collect())
+        );
+    }
+
+    #[test]
+    fn test_add() {
+        let mut pre_aggregates = PreAggregates::default();
+        let tags = vec![(1, 2)];
+        pre_aggregates.add(tags.clone(), 10, vec![(3, FieldValue::F64(1.5))]);
+        pre_aggregates.add(
+            tags.clone(),
+            BUCKET_NANOS - 1,
+            vec![(3, FieldValue::F64(-2.0)), (4, FieldValue::I64(7))],
+        );
+        pre_aggregates.add(tags.clone(), BUCKET_NANOS, vec![]);
+        pre_aggregates.add(tags.clone(), -1, vec![(4, FieldValue::I64(i64::MAX))]);
+
+        let series = &pre_aggregates.series[&tags];
+        assert_eq!(
+            series.buckets.iter().cloned().collect::<Vec<_>>(),
+            vec![-BUCKET_NANOS, 0, BUCKET_NANOS]
+        );
+
+        let expected = vec![(
+            0,
+            Bucket {
+                count: 2,
+                sum: -0.5,
+                min: -2.0,
+                max: 1.5,
+            },
+        )];
+        assert_eq!(
+            series.fields[&3],
+            BucketedValues::F64(expected.into_iter().collect())
+        );
+
+        let expected = vec![
+            (
+                -BUCKET_NANOS,
+                Bucket {
+                    count: 1,
+                    sum: i64::MAX,
+                    min: i64::MAX,
+                    max: i64::MAX,
+                },
+            ),
+            (
+                0,
+                Bucket {
+                    count: 1,
+                    sum: 7,
+                    min: 7,
+                    max: 7,
+                },
+            ),
+        ];
+        assert_eq!(
+            series.fields[&4],
+            BucketedValues::I64(expected.into_iter().collect())
+        );
+        assert!(pre_aggregates.size() > 0);
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let tag_value = |column: &str| match column {
+            "host" => Some(Some("a")),
+            "region" => Some(None),
+            _ => None,
+        };
+        let eval = |expr: &Expr| evaluate(expr, &tag_value);
+
+        assert_eq!(
+            eval(&binary(col("host"), Operator::Eq, lit("a"))),
+            Some(Value::Bool(Some(true)))
+        );
+        assert_eq!(
+            eval(&binary(lit("b"), Operator::Gt, col("host"))),
+            Some(Value::Bool(Some(true)))
+        );
+        // comparisons with a missing tag are null, as in SQL
+        let region = binary(col("region"), Operator::NotEq, lit("west"));
+        assert_eq!(eval(&region), Some(Value::Bool(None)));
+        assert_eq!(
+            eval(&binary(
+                region.clone(),
+                Operator::Or,
+                binary(col("host"), Operator::Eq, lit("a"))
+            )),
+            Some(Value::Bool(Some(true)))
+        );
+        assert_eq!(eval(&Expr::Not(Box::new(region))), Some(Value::Bool(None)));
+        assert_eq!(
+            eval(&Expr::IsNull(Box::new(col("region")))),
+            Some(Value::Bool(Some(true)))
+        );
+
+        // anything but tags can not be evaluated, even when the other
+        // side of the expression decides the result
+        let field = binary(col("usage"), Operator::Gt, lit("1"));
+        assert_eq!(eval(&field), None);
+        assert_eq!(
+            eval(&binary(
+                binary(col("host"), Operator::Eq, lit("b")),
+                Operator::And,
+                field
+            )),
+            None
+        );
+        assert_eq!(
+            eval(&binary(
+                col("host"),
+                Operator::Eq,
+                Expr::Literal(ScalarValue::Int64(Some(1)))
+            )),
+            None
+        );
+    }
+}
@@ -0,0 +1,775 @@
+//! This module contains the operations that rewrite the data of
+//! partitions in another form: merging several partitions with the
+//! same key into one (compaction), leaving series out of a partition
+//! (deletion), and writing a partition out as parquet files
+//! (persistence). It also converts line protocol to Arrow record
+//! batches, by way of a partition.
+//!
+//! Like downsampling, compaction converts the data to line protocol
+//! first, so it is written through the same path as any other write.
+//! Persistence copies the values of each column out of the partition,
+//! and compaction its rows as line protocol, so the files can be
+//! written and the rows compacted without holding a lock on it.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use arrow_deps::arrow::record_batch::RecordBatch;
+use data_types::{
+    data::{
+        rows_to_write_entry_partition, split_lines_into_write_entry_partitions, RowValue, TableRows,
+    },
+    partition_metadata::ColumnEncoding,
+    table_schema::{DataType, SchemaBuilder},
+    TIME_COLUMN_NAME,
+};
+use generated_types::wal as wb;
+use influxdb_line_protocol::{
+    parse_lines, series_key, write_escaped, ParsedLine, FIELD_KEY_DELIMITERS,
+    MEASUREMENT_DELIMITERS, TAG_KEY_DELIMITERS, TAG_VALUE_DELIMITERS,
+};
+use ingest::parquet::writer::{column_encodings, CompressionLevel, IOxParquetTableWriter};
+use packers::{ByteArray, Error as TableError, IOxTableWriter, Packer, Packers};
+use snafu::{ResultExt, Snafu};
+
+use crate::column::Column;
+use crate::downsample::STRING_SPECIAL_CHARS;
+use crate::encoding::{self, ColumnUsage};
+use crate::partition::Partition;
+use crate::persisted::{encodings_path, tags_path};
+use crate::table::Table;
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Error looking up id in dictionary of partition {}: {}",
+        partition,
+        source
+    ))]
+    DictionaryLookup {
+        partition: String,
+        source: crate::dictionary::Error,
+    },
+
+    #[snafu(display("Error parsing rewritten data of partition {}: {}", partition, source))]
+    ParsingRewritten {
+        partition: String,
+        source: influxdb_line_protocol::Error,
+    },
+
+    #[snafu(display("Error writing rewritten data of partition {}: {}", partition, source))]
+    WritingRewritten {
+        partition: String,
+        source: crate::partition::Error,
+    },
+
+    #[snafu(display("Error converting table {} to Arrow: {}", table, source))]
+    ConvertingToArrow {
+        table: String,
+        source: crate::table::Error,
+    },
+
+    #[snafu(display("Error removing directory {:?}: {}", path, source))]
+    RemovingDirectory {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Error creating directory {:?}: {}", path, source))]
+    CreatingDirectory {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display(
+        "Error writing parquet file of table {} of partition {}: {}",
+        table,
+        partition,
+        source
+    ))]
+    WritingTable {
+        partition: String,
+        table: String,
+        source: TableError,
+    },
+
+    #[snafu(display(
+        "Error choosing column encodings of partition {}: {}",
+        partition,
+        source
+    ))]
+    ChoosingEncodings {
+        partition: String,
+        source: crate::encoding::Error,
+    },
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Returns a new, closed, partition with id `id` containing the data
+/// of all of `partitions`, which must all have the same key. The
+/// encodings its columns are persisted with are chosen from their
+/// values and the `usage` of the columns of `partitions`.
+pub fn compact_partitions(
+    key: &str,
+    id: u32,
+    partitions: &[&Partition],
+    usage: &BTreeMap<(String, String), ColumnUsage>,
+) -> Result<Partition> {
+    snapshot_for_compaction(key, partitions, usage.clone())?.compact(id, |_| {})
+}
+
+/// The data of partitions with the same key, copied out of them as
+/// line protocol so that they can be compacted without holding a lock
+/// on them
+#[derive(Debug)]
+pub struct CompactionSnapshot {
+    key: String,
+    /// The rows of each partition
+    lines: Vec<String>,
+    downsampled_interval: Option<i64>,
+    last_write_time: Option<i64>,
+    usage: BTreeMap<(String, String), ColumnUsage>,
+}
+
+/// Copies the data of `partitions`, which must all have the same key,
+/// to be compacted. The encodings the columns of the compacted
+/// partition are persisted with are chosen from their values and the
+/// `usage` of the columns of `partitions`.
+pub fn snapshot_for_compaction(
+    key: &str,
+    partitions: &[&Partition],
+    usage: BTreeMap<(String, String), ColumnUsage>,
+) -> Result<CompactionSnapshot> {
+    let lines = partitions
+        .iter()
+        .map(|partition| {
+            let mut lp = String::new();
+            write_partition_lines(&mut lp, partition)?;
+            Ok(lp)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(CompactionSnapshot {
+        key: key.to_string(),
+        lines,
+        // the compacted data is only as fine grained as the coarsest
+        // of the partitions it came from
+        downsampled_interval: partitions
+            .iter()
+            .filter_map(|p| p.downsampled_interval)
+            .max(),
+        last_write_time: partitions.iter().filter_map(|p| p.last_write_time).max(),
+        usage,
+    })
+}
+
+impl CompactionSnapshot {
+    /// Returns a new, closed, partition with id `id` containing the
+    /// data of the snapshot. `pace` is called with the size in bytes of
+    /// the data of each of the snapshotted partitions before it is
+    /// rewritten, and may block to limit the rate they are rewritten
+    /// at.
+    pub fn compact(&self, id: u32, pace: impl Fn(u64)) -> Result<Partition> {
+        let key = self.key.as_str();
+
+        let mut lines = vec![];
+        for lp in &self.lines {
+            pace(lp.len() as u64);
+            for line in parse_lines(lp) {
+                lines.push(line.context(ParsingRewritten { partition: key })?);
+            }
+        }
+        // rows of late chunks are merged into the time order of the rest
+        lines.sort_by_key(|line| line.timestamp);
+        let data = split_lines_into_write_entry_partitions(|_| key.to_string(), &lines);
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+
+        let mut compacted = Partition::new(key);
+        compacted.id = id;
+        compacted.is_open = false;
+        compacted.downsampled_interval = self.downsampled_interval;
+        compacted.last_write_time = self.last_write_time;
+
+        if let Some(entries) = batch.entries() {
+            for entry in entries {
+                compacted
+                    .write_entry(&entry)
+                    .context(WritingRewritten { partition: key })?;
+            }
+        }
+
+        compacted.encodings = encoding::choose_encodings(&compacted, &self.usage)
+            .context(ChoosingEncodings { partition: key })?;
+
+        Ok(compacted)
+    }
+}
+
+/// Returns a copy of `partition` without the rows of the series with
+/// keys `series_keys` (see `influxdb_line_protocol::series_key`) and
+/// the number of rows left out, or `None` if it has no such rows. The
+/// copy has the id and state of `partition`, except that it is not
+/// persisted, as the files of `partition` still have the rows.
+pub fn delete_series(
+    partition: &Partition,
+    series_keys: &BTreeSet<String>,
+) -> Result<Option<(Partition, u64)>> {
+    let lookup = |id| {
+        partition
+            .dictionary
+            .lookup_id(id)
+            .context(DictionaryLookup {
+                partition: &partition.key,
+            })
+    };
+
+    let mut deleted = 0;
+    let mut tables = vec![];
+    for table in partition.tables.values() {
+        let name = lookup(table.id)?;
+        let columns = table
+            .column_id_to_index
+            .iter()
+            .map(|(&column_id, &index)| Ok((lookup(column_id)?, &table.columns[index])))
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut rows = vec![];
+        for row in 0..table.row_count() {
+            let mut values = Vec::with_capacity(columns.len());
+            for &(column_name, column) in &columns {
+                let value = match column {
+                    Column::Tag(values, _) => match values[row] {
+                        Some(value_id) => Some(RowValue::Tag(lookup(value_id)?)),
+                        None => None,
+                    },
+                    Column::F64(values, _) => values[row].map(RowValue::F64),
+                    Column::I64(values, _) => values[row].map(RowValue::I64),
+                    Column::String(values, _) => values[row].as_deref().map(RowValue::String),
+                    Column::Bool(values, _) => values[row].map(RowValue::Bool),
+                };
+                if let Some(value) = value {
+                    values.push((column_name, value));
+                }
+            }
+
+            let tags = values
+                .iter()
+                .filter_map(|&(column_name, value)| match value {
+                    RowValue::Tag(tag_value) => Some((column_name, tag_value)),
+                    _ => None,
+                });
+            if series_keys.contains(&series_key(name, tags)) {
+                deleted += 1;
+            } else {
+                rows.push(values);
+            }
+        }
+        tables.push(TableRows { name, rows });
+    }
+
+    if deleted == 0 {
+        return Ok(None);
+    }
+
+    let mut rewritten = Partition::new(&partition.key);
+    rewritten.id = partition.id;
+    rewritten.is_open = partition.is_open;
+    rewritten.downsampled_interval = partition.downsampled_interval;
+    rewritten.last_write_time = partition.last_write_time;
+    rewritten.pre_aggregated_tables = partition.pre_aggregated_tables.clone();
+    rewritten.encodings = partition.encodings.clone();
+    rewritten.late_window = partition.late_window;
+
+    let data = rows_to_write_entry_partition(&partition.key, &tables);
+    let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+    if let Some(entries) = batch.entries() {
+        for entry in entries {
+            rewritten.write_entry(&entry).context(WritingRewritten {
+                partition: &partition.key,
+            })?;
+        }
+    }
+
+    Ok(Some((rewritten, deleted)))
+}
+
+/// Converts `lines` to an Arrow record batch per measurement, returned
+/// with the name of the measurement, in measurement name order
+pub fn lines_to_arrow(lines: &[ParsedLine<'_>]) -> Result<Vec<(String, RecordBatch)>> {
+    const KEY: &str = "arrow";
+
+    let data = split_lines_into_write_entry_partitions(|_| KEY.to_string(), lines);
+    let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+
+    let mut partition = Partition::new(KEY);
+    if let Some(entries) = batch.entries() {
+        for entry in entries {
+            partition
+                .write_entry(&entry)
+                .context(WritingRewritten { partition: KEY })?;
+        }
+    }
+
+    let mut batches = partition
+        .tables
+        .values()
+        .map(|table| -> Result<_> {
+            let table_name = partition
+                .dictionary
+                .lookup_id(table.id)
+                .context(DictionaryLookup { partition: KEY })?;
+            let batch = table
+                .all_to_arrow(&partition)
+                .context(ConvertingToArrow { table: table_name })?;
+            Ok((table_name.to_string(), batch))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    batches.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(batches)
+}
+
+/// Writes the data of `partition` as parquet files in `dir`, one per
+/// table, named `<table>.parquet`, each with the encodings of its
+/// columns in `<table>.encodings.json` and the names of its tag
+/// columns in `<table>.tags.json`
+pub fn persist_partition(partition: &Partition, dir: &Path) -> Result<()> {
+    snapshot_partition(partition)?.persist(dir)
+}
+
+/// The data of a partition, copied out of it so that it can be
+/// written to parquet files without holding a lock on the partition
+#[derive(Debug)]
+pub struct PartitionSnapshot {
+    key: String,
+    tables: Vec<TableSnapshot>,
+    encodings: BTreeMap<String, BTreeMap<String, ColumnEncoding>>,
+}
+
+/// The data of a table of a `PartitionSnapshot`
+#[derive(Debug)]
+struct TableSnapshot {
+    name: String,
+    num_rows: usize,
+    /// The tag columns, then the field columns, each in name order
+    columns: Vec<(String, ColumnSnapshot)>,
+    times: Option<Vec<Option<i64>>>,
+}
+
+/// The values of a column of a `TableSnapshot`, with the ids of tag
+/// values looked up
+#[derive(Debug)]
+enum ColumnSnapshot {
+    Tag(Vec<Option<String>>),
+    F64(Vec<Option<f64>>),
+    I64(Vec<Option<i64>>),
+    String(Vec<Option<String>>),
+    Bool(Vec<Option<bool>>),
+}
+
+/// Copies the data of `partition` to be persisted
+pub fn snapshot_partition(partition: &Partition) -> Result<PartitionSnapshot> {
+    let lookup = |id| {
+        partition
+            .dictionary
+            .lookup_id(id)
+            .context(DictionaryLookup {
+                partition: &partition.key,
+            })
+    };
+
+    let mut tables = vec![];
+    for table in partition.tables.values() {
+        let num_rows = table.row_count();
+        if num_rows == 0 {
+            continue;
+        }
+
+        let mut times = None;
+        let mut tags = vec![];
+        let mut fields = vec![];
+        for (&column_id, &index) in &table.column_id_to_index {
+            let column_name = lookup(column_id)?.to_string();
+            match &table.columns[index] {
+                Column::I64(values, _) if column_name == TIME_COLUMN_NAME => {
+                    times = Some(values.clone())
+                }
+                Column::Tag(values, _) => {
+                    let values = values
+                        .iter()
+                        .map(|id| id.map(|id| lookup(id).map(ToString::to_string)).transpose())
+                        .collect::<Result<_>>()?;
+                    tags.push((column_name, ColumnSnapshot::Tag(values)));
+                }
+                Column::F64(values, _) => {
+                    fields.push((column_name, ColumnSnapshot::F64(values.clone())))
+                }
+                Column::I64(values, _) => {
+                    fields.push((column_name, ColumnSnapshot::I64(values.clone())))
+                }
+                Column::String(values, _) => {
+                    fields.push((column_name, ColumnSnapshot::String(values.clone())))
+                }
+                Column::Bool(values, _) => {
+                    fields.push((column_name, ColumnSnapshot::Bool(values.clone())))
+                }
+            }
+        }
+        tags.sort_by(|(a, _), (b, _)| a.cmp(b));
+        fields.sort_by(|(a, _), (b, _)| a.cmp(b));
+        tags.extend(fields);
+
+        tables.push(TableSnapshot {
+            name: lookup(table.id)?.to_string(),
+            num_rows,
+            columns: tags,
+            times,
+        });
+    }
+
+    Ok(PartitionSnapshot {
+        key: partition.key.clone(),
+        tables,
+        encodings: partition.encodings.clone(),
+    })
+}
+
+impl PartitionSnapshot {
+    /// Writes the data as `persist_partition` does
+    pub fn persist(&self, dir: &Path) -> Result<()> {
+        // the files of a chunk persisted before, which has had series
+        // deleted since, may be of tables it no longer has
+        if dir.exists() {
+            fs::remove_dir_all(dir).context(RemovingDirectory { path: dir })?;
+        }
+        fs::create_dir_all(dir).context(CreatingDirectory { path: dir })?;
+
+        let no_encodings = BTreeMap::new();
+        for table in &self.tables {
+            let encodings = self.encodings.get(&table.name).unwrap_or(&no_encodings);
+            persist_table(dir, table, encodings).context(WritingTable {
+                partition: &self.key,
+                table: &table.name,
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes the data of `table` to its files in `dir`, with the
+/// `encodings` of its columns
+fn persist_table(
+    dir: &Path,
+    table: &TableSnapshot,
+    encodings: &BTreeMap<String, ColumnEncoding>,
+) -> Result<(), TableError> {
+    let mut builder = SchemaBuilder::new(table.name.as_str());
+    let mut packers = Vec::with_capacity(table.columns.len() + 1);
+    let mut tag_names = vec![];
+    for (name, column) in &table.columns {
+        let packer = match column {
+            ColumnSnapshot::Tag(values) => {
+                builder = builder.tag(name);
+                tag_names.push(name.as_str());
+                bytes_packer(values)
+            }
+            ColumnSnapshot::F64(values) => {
+                builder = builder.field(name, DataType::Float);
+                Packers::Float(Packer::from(values.clone()))
+            }
+            ColumnSnapshot::I64(values) => {
+                builder = builder.field(name, DataType::Integer);
+                Packers::Integer(Packer::from(values.clone()))
+            }
+            ColumnSnapshot::String(values) => {
+                builder = builder.field(name, DataType::String);
+                bytes_packer(values)
+            }
+            ColumnSnapshot::Bool(values) => {
+                builder = builder.field(name, DataType::Boolean);
+                Packers::Boolean(Packer::from(values.clone()))
+            }
+        };
+        packers.push(packer);
+    }
+    let times = match &table.times {
+        Some(times) => times.clone(),
+        None => vec![None; table.num_rows],
+    };
+    packers.push(Packers::Integer(Packer::from(times)));
+    let schema = builder.build();
+
+    let path = dir.join(format!("{}.parquet", table.name));
+
+    let encodings_path = encodings_path(&path);
+    let json = serde_json::to_vec(&column_encodings(
+        &schema,
+        CompressionLevel::Compatibility,
+        encodings,
+    ))
+    .map_err(TableError::from_other)?;
+    fs::write(&encodings_path, json).map_err(|e| {
+        TableError::from_io(e, format!("Error writing output file {:?}", encodings_path))
+    })?;
+
+    let tags_path = tags_path(&path);
+    let json = serde_json::to_vec(&tag_names).map_err(TableError::from_other)?;
+    fs::write(&tags_path, json).map_err(|e| {
+        TableError::from_io(e, format!("Error writing output file {:?}", tags_path))
+    })?;
+
+    let file = fs::File::create(&path)
+        .map_err(|e| TableError::from_io(e, format!("Error creating output file {:?}", path)))?;
+    let mut writer = IOxParquetTableWriter::with_encodings(
+        &schema,
+        CompressionLevel::Compatibility,
+        encodings,
+        file,
+    )
+    .map_err(TableError::from_other)?;
+    writer.write_batch(&packers)?;
+    writer.close()
+}
+
+/// Returns a packer of the bytes of `values`
+fn bytes_packer(values: &[Option<String>]) -> Packers {
+    let values: Vec<_> = values
+        .iter()
+        .map(|v| v.as_ref().map(|v| ByteArray::from(v.as_str())))
+        .collect();
+    Packers::Bytes(Packer::from(values))
+}
+
+/// Appends every row of `partition` to `lp` as line protocol
+pub(crate) fn write_partition_lines(lp: &mut String, partition: &Partition) -> Result<()> {
+    for table in partition.tables.values() {
+        write_table_lines(lp, partition, table)?;
+    }
+    Ok(())
+}
+
+/// Appends every row of `table` to `lp` as line protocol, skipping
+/// rows which have no field values
+fn write_table_lines(lp: &mut String, partition: &Partition, table: &Table) -> Result<()> {
+    let lookup = move |id| {
+        partition
+            .dictionary
+            .lookup_id(id)
+            .context(DictionaryLookup {
+                partition: &partition.key,
+            })
+    };
+
+    let table_name = lookup(table.id)?;
+
+    let mut times = None;
+    let mut tags = vec![];
+    let mut fields = vec![];
+    for (&column_id, &index) in &table.column_id_to_index {
+        let column_name = lookup(column_id)?;
+        match &table.columns[index] {
+            Column::I64(values, _) if column_name == TIME_COLUMN_NAME => times = Some(values),
+            Column::Tag(values, _) => tags.push((column_name, values)),
+            column => fields.push((column_name, column)),
+        }
+    }
+    tags.sort_by_key(|(name, _)| *name);
+    fields.sort_by_key(|(name, _)| *name);
+
+    for row in 0..table.row_count() {
+        let mut line = String::new();
+        write_escaped(&mut line, table_name, MEASUREMENT_DELIMITERS);
+        for (tag_name, values) in &tags {
+            let value = match values[row] {
+                Some(value_id) => lookup(value_id)?,
+                None => continue,
+            };
+            if value.is_empty() {
+                continue;
+            }
+
+            line.push(',');
+            write_escaped(&mut line, tag_name, TAG_KEY_DELIMITERS);
+            line.push('=');
+            write_escaped(&mut line, value, TAG_VALUE_DELIMITERS);
+        }
+
+        let mut separator = ' ';
+        for (field_name, column) in &fields {
+            if let Some(value) = field_value(column, row) {
+                line.push(separator);
+                write_escaped(&mut line, field_name, FIELD_KEY_DELIMITERS);
+                line.push('=');
+                line.push_str(&value);
+                separator = ',';
+            }
+        }
+
+        // line protocol requires at least one field per line
+        if separator == ',' {
+            match times.and_then(|times| times[row]) {
+                Some(time) => writeln!(lp, "{} {}", line, time),
+                None => writeln!(lp, "{}", line),
+            }
+            .expect("writing to a String can not fail");
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the value of `column` in `row` as a line protocol field
+/// value, or `None` if it is null
+fn field_value(column: &Column, row: usize) -> Option<String> {
+    match column {
+        Column::F64(values, _) => values[row].map(|v| v.to_string()),
+        Column::I64(values, _) => values[row].map(|v| format!("{}i", v)),
+        Column::String(values, _) => values[row].as_ref().map(|v| {
+            let mut quoted = String::from("\"");
+            write_escaped(&mut quoted, v, STRING_SPECIAL_CHARS);
+            quoted.push('"');
+            quoted
+        }),
+        Column::Bool(values, _) => values[row].map(|v| v.to_string()),
+        Column::Tag(_, _) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    fn partition(lp: &str) -> Result<Partition> {
+        let lines: Vec<_> = parse_lines(lp).map(|l| l.unwrap()).collect();
+        let data = split_lines_into_write_entry_partitions(|_| "p1".to_string(), &lines);
+        let batch = flatbuffers::get_root::<wb::WriteBufferBatch<'_>>(&data);
+
+        let mut partition = Partition::new("p1");
+        for entry in batch.entries().unwrap() {
+            partition.write_entry(&entry)?;
+        }
+        Ok(partition)
+    }
+
+    fn to_lp(partition: &Partition) -> Result<Vec<String>> {
+        let mut lp = String::new();
+        write_partition_lines(&mut lp, partition)?;
+        let mut lines: Vec<_> = lp.lines().map(ToString::to_string).collect();
+        lines.sort();
+        Ok(lines)
+    }
+
+    #[test]
+    fn test_write_partition_lines() -> Result {
+        let partition = partition(
+            "cpu,host=a\\ b,region=west usage=0.5,count=2i 10\n\
+             cpu,region=east usage=1.5 20\n\
+             disk,host=a msg=\"hi\",ok=true 30\n",
+        )?;
+
+        assert_eq!(
+            to_lp(&partition)?,
+            vec![
+                "cpu,host=a\\ b,region=west count=2i,usage=0.5 10",
+                "cpu,region=east usage=1.5 20",
+                "disk,host=a msg=\"hi\",ok=true 30",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_partitions() -> Result {
+        let first = partition("cpu,host=a usage=0.5 10\ncpu,host=b usage=1.5 20\n")?;
+        let second = partition("cpu,host=a usage=2.5,count=1i 30\nmem free=3i 40\n")?;
+
+        let compacted = compact_partitions("p1", 2, &[&first, &second], &BTreeMap::new())?;
+
+        assert_eq!(compacted.key, "p1");
+        assert_eq!(compacted.id, 2);
+        assert!(!compacted.is_open);
+        assert_eq!(
+            to_lp(&compacted)?,
+            vec![
+                "cpu,host=a count=1i,usage=2.5 30",
+                "cpu,host=a usage=0.5 10",
+                "cpu,host=b usage=1.5 20",
+                "mem free=3i 40",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_snapshot_paced() -> Result {
+        let first = partition("cpu,host=a usage=0.5 10\n")?;
+        let second = partition("mem free=3i 40\n")?;
+        let snapshot = snapshot_for_compaction("p1", &[&first, &second], BTreeMap::new())?;
+
+        let paced = std::cell::RefCell::new(vec![]);
+        let compacted = snapshot.compact(3, |bytes| paced.borrow_mut().push(bytes))?;
+
+        assert_eq!(compacted.id, 3);
+        assert_eq!(
+            paced.into_inner(),
+            vec![
+                "cpu,host=a usage=0.5 10\n".len() as u64,
+                "mem free=3i 40\n".len() as u64
+            ]
+        );
+        assert_eq!(
+            to_lp(&compacted)?,
+            vec!["cpu,host=a usage=0.5 10", "mem free=3i 40"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lines_to_arrow() -> Result {
+        let lines: Vec<_> = parse_lines("mem free=3i 40\ncpu,host=a usage=0.5 10\n")
+            .map(|l| l.unwrap())
+            .collect();
+
+        let batches = lines_to_arrow(&lines)?;
+
+        let names: Vec<_> = batches.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["cpu", "mem"]);
+        let (_, cpu) = &batches[0];
+        assert_eq!(cpu.num_rows(), 1);
+        assert_eq!(cpu.num_columns(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_persist_partition() -> Result {
+        let partition = partition("cpu,host=a usage=0.5 10\nmem free=3i 40\n")?;
+        let dir = test_helpers::tmp_dir()?;
+        let chunk_dir = dir.path().join("p1").join("0");
+
+        persist_partition(&partition, &chunk_dir)?;
+
+        let mut files: Vec<_> = fs::read_dir(&chunk_dir)?
+            .map(|entry| entry.map(|e| e.file_name().to_string_lossy().to_string()))
+            .collect::<Result<_, _>>()?;
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                "cpu.encodings.json",
+                "cpu.parquet",
+                "cpu.tags.json",
+                "mem.encodings.json",
+                "mem.parquet",
+                "mem.tags.json"
+            ]
+        );
+        Ok(())
+    }
+}
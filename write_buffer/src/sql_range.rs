@@ -0,0 +1,180 @@
+//! This module finds the range of times a SQL select reads from the
+//! conditions of its `WHERE` clause, so that the chunks of the table
+//! it queries without rows in that range are not read.
+//!
+//! Only comparisons of the time column with integer literals (of
+//! nanoseconds since the epoch) which every row must satisfy, those
+//! joined by `AND`, narrow the range, including `BETWEEN`. Any other
+//! condition is ignored, as is the `WHERE` clause of a select of more
+//! than one table, whose time column may be ambiguous. The range only
+//! selects chunks: the query itself still applies every condition to
+//! the rows of the chunks it reads.
+
+use data_types::TIME_COLUMN_NAME;
+use sqlparser::ast::{BinaryOperator, Expr, Select, UnaryOperator, Value};
+use storage::predicate::TimestampRange;
+
+/// Returns the range of times `select` reads, if its `WHERE` clause
+/// bounds the time column of the single table it reads
+pub fn select_time_range(select: &Select) -> Option<TimestampRange> {
+    let single_table = select.from.len() == 1 && select.from[0].joins.is_empty();
+    if !single_table {
+        return None;
+    }
+
+    let mut range = TimestampRange::new(i64::MIN, i64::MAX);
+    narrow(select.selection.as_ref()?, &mut range);
+
+    let unbounded = range.start == i64::MIN && range.end == i64::MAX;
+    if unbounded {
+        None
+    } else {
+        Some(range)
+    }
+}
+
+/// Narrows `range` to the times the rows matching `expr` must have
+fn narrow(expr: &Expr, range: &mut TimestampRange) {
+    match expr {
+        Expr::BinaryOp {
+            left,
+            op: BinaryOperator::And,
+            right,
+        } => {
+            narrow(left, range);
+            narrow(right, range);
+        }
+        Expr::Nested(expr) => narrow(expr, range),
+        Expr::BinaryOp { left, op, right } => {
+            let comparison = match (is_time(left), is_time(right)) {
+                (true, false) => integer(right).map(|value| (op.clone(), value)),
+                (false, true) => integer(left).and_then(|value| Some((reversed(op)?, value))),
+                _ => None,
+            };
+
+            match comparison {
+                Some((BinaryOperator::Gt, value)) => {
+                    range.start = range.start.max(value.saturating_add(1))
+                }
+                Some((BinaryOperator::GtEq, value)) => range.start = range.start.max(value),
+                Some((BinaryOperator::Lt, value)) => range.end = range.end.min(value),
+                Some((BinaryOperator::LtEq, value)) => {
+                    range.end = range.end.min(value.saturating_add(1))
+                }
+                Some((BinaryOperator::Eq, value)) => {
+                    range.start = range.start.max(value);
+                    range.end = range.end.min(value.saturating_add(1));
+                }
+                _ => {}
+            }
+        }
+        Expr::Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        } if is_time(expr) => {
+            if let (Some(low), Some(high)) = (integer(low), integer(high)) {
+                range.start = range.start.max(low);
+                range.end = range.end.min(high.saturating_add(1));
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_time(expr: &Expr) -> bool {
+    match expr {
+        Expr::Identifier(ident) => ident.value == TIME_COLUMN_NAME,
+        Expr::Nested(expr) => is_time(expr),
+        _ => false,
+    }
+}
+
+/// Returns the value of `expr` if it is an integer literal
+fn integer(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::Value(Value::Number(n)) => n.to_string().parse().ok(),
+        Expr::UnaryOp {
+            op: UnaryOperator::Minus,
+            expr,
+        } => integer(expr)?.checked_neg(),
+        Expr::Nested(expr) => integer(expr),
+        _ => None,
+    }
+}
+
+/// Returns the comparison which holds of `b` and `a` when `op` holds
+/// of `a` and `b`
+fn reversed(op: &BinaryOperator) -> Option<BinaryOperator> {
+    Some(match op {
+        BinaryOperator::Gt => BinaryOperator::Lt,
+        BinaryOperator::GtEq => BinaryOperator::LtEq,
+        BinaryOperator::Lt => BinaryOperator::Gt,
+        BinaryOperator::LtEq => BinaryOperator::GtEq,
+        BinaryOperator::Eq => BinaryOperator::Eq,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlparser::{
+        ast::{SetExpr, Statement},
+        dialect::GenericDialect,
+        parser::Parser,
+    };
+
+    fn range(sql: &str) -> Option<(i64, i64)> {
+        let statement = Parser::parse_sql(&GenericDialect {}, sql)
+            .unwrap()
+            .remove(0);
+        let select = match statement {
+            Statement::Query(query) => match query.body {
+                SetExpr::Select(select) => select,
+                _ => unreachable!("a select"),
+            },
+            _ => unreachable!("a query"),
+        };
+        select_time_range(&select).map(|range| (range.start, range.end))
+    }
+
+    #[test]
+    fn comparisons_bound_the_range() {
+        assert_eq!(
+            range("select * from cpu where time >= 10 and time < 20"),
+            Some((10, 20))
+        );
+        assert_eq!(
+            range("select * from cpu where 10 < time and (time <= 20 and host = 'a')"),
+            Some((11, 21))
+        );
+        assert_eq!(
+            range("select * from cpu where time between -5 and 5"),
+            Some((-5, 6))
+        );
+        assert_eq!(range("select * from cpu where time = 7"), Some((7, 8)));
+        assert_eq!(
+            range("select * from cpu where time > 10 and time > 15 and time < 30"),
+            Some((16, 30))
+        );
+        assert_eq!(
+            range("select * from cpu where time >= 10"),
+            Some((10, i64::MAX))
+        );
+    }
+
+    #[test]
+    fn other_conditions_leave_the_range_unbounded() {
+        assert_eq!(range("select * from cpu"), None);
+        assert_eq!(range("select * from cpu where host = 'a'"), None);
+        assert_eq!(range("select * from cpu where time > 10 or time < 5"), None);
+        assert_eq!(range("select * from cpu where usage > 10"), None);
+        assert_eq!(
+            range("select * from cpu where time not between 1 and 5"),
+            None
+        );
+        assert_eq!(range("select * from cpu, mem where time > 10"), None);
+    }
+}
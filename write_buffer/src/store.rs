@@ -1,13 +1,22 @@
 use async_trait::async_trait;
-use snafu::{ResultExt, Snafu};
-use storage::DatabaseStore;
-use tokio::sync::RwLock;
+use data_types::database_rules::DatabaseRules;
+use object_store::ObjectStore;
+use snafu::{ensure, OptionExt, ResultExt, Snafu};
+use storage::{Database, DatabaseStore};
+use tokio::sync::{Notify, RwLock};
 
-use std::{fs, sync::Arc};
+use std::{
+    fs,
+    sync::{Arc, Mutex},
+};
 
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    path::{Component, Path, PathBuf},
+};
 
-use crate::database::Db;
+use crate::database::{ChunkPruningMetrics, Db};
+use crate::persisted::OpenMode;
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -22,6 +31,21 @@ pub enum Error {
 
     #[snafu(display("Error reading metadata: {}", source))]
     ReadMetadataError { source: std::io::Error },
+
+    #[snafu(display("Database {} already exists", name))]
+    DatabaseAlreadyExists { name: String },
+
+    #[snafu(display("Database {} not found", name))]
+    DatabaseNotFound { name: String },
+
+    #[snafu(display("Error removing dir {:?}: {}", dir, source))]
+    RemoveError {
+        dir: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[snafu(display("Invalid database name {:?}: {}", name, reason))]
+    InvalidDatabaseName { name: String, reason: &'static str },
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -30,6 +54,16 @@ pub type Result<T, E = Error> = std::result::Result<T, E>;
 pub struct WriteBufferDatabases {
     databases: RwLock<BTreeMap<String, Arc<Db>>>,
     base_dir: PathBuf,
+    /// Notified each time the rules of any of the databases change
+    rules_changed: Arc<Notify>,
+    /// The chunk pruning counts of each database, which can be read
+    /// without waiting for the lock on the databases
+    chunk_pruning: Mutex<BTreeMap<String, Arc<ChunkPruningMetrics>>>,
+    /// How the databases open the files of evicted chunks
+    persisted_open_mode: OpenMode,
+    /// The object store the databases copy the files of persisted
+    /// chunks to, if any
+    object_store: Option<Arc<ObjectStore>>,
 }
 
 impl WriteBufferDatabases {
@@ -37,9 +71,62 @@ impl WriteBufferDatabases {
         Self {
             databases: RwLock::new(BTreeMap::new()),
             base_dir: base_dir.into(),
+            rules_changed: Default::default(),
+            chunk_pruning: Default::default(),
+            persisted_open_mode: Default::default(),
+            object_store: None,
         }
     }
 
+    /// Has each database open the files of its evicted chunks as `mode`
+    pub fn with_persisted_open_mode(mut self, mode: OpenMode) -> Self {
+        self.persisted_open_mode = mode;
+        self
+    }
+
+    /// Has each database copy the files of its persisted chunks to
+    /// `store`
+    pub fn with_object_store(mut self, store: Arc<ObjectStore>) -> Self {
+        self.object_store = Some(store);
+        self
+    }
+
+    /// Configures `db` as every database of this store is
+    fn configure(&self, db: Db) -> Db {
+        let db = db
+            .with_rules_notify(Arc::clone(&self.rules_changed))
+            .with_persisted_open_mode(self.persisted_open_mode);
+        match &self.object_store {
+            Some(store) => db.with_object_store(Arc::clone(store)),
+            None => db,
+        }
+    }
+
+    /// Returns the number of chunks the queries of each database have
+    /// scanned and pruned, as `(name, scanned, pruned)`, ordered by name
+    pub fn chunk_pruning(&self) -> Vec<(String, u64, u64)> {
+        self.chunk_pruning
+            .lock()
+            .expect("mutex poisoned")
+            .iter()
+            .map(|(name, metrics)| {
+                (
+                    name.clone(),
+                    metrics.chunks_scanned(),
+                    metrics.chunks_pruned(),
+                )
+            })
+            .collect()
+    }
+
+    fn insert_db(&self, databases: &mut BTreeMap<String, Arc<Db>>, db: Arc<Db>) {
+        self.chunk_pruning
+            .lock()
+            .expect("mutex poisoned")
+            .insert(db.name.clone(), db.chunk_pruning_metrics());
+        databases.insert(db.name.clone(), db);
+    }
+
     /// wal_dirs will traverse the directories from the service base directory and return
     /// the directories that contain WALs for databases, which can be used to restore those DBs.
     pub fn wal_dirs(&self) -> Result<Vec<PathBuf>> {
@@ -69,9 +156,100 @@ impl WriteBufferDatabases {
         Ok(dirs)
     }
 
-    pub async fn add_db(&self, db: Db) {
+    /// Returns the directory the database `name` is stored in, which is
+    /// always directly under the base directory. Names that are empty,
+    /// hidden or that would resolve to another directory, such as `..`
+    /// or those with path separators, are rejected.
+    fn db_dir(&self, name: &str) -> Result<PathBuf> {
+        let invalid = |reason| InvalidDatabaseName { name, reason }.fail();
+
+        if name.is_empty() {
+            return invalid("name is empty");
+        }
+        // hidden directories are skipped by `wal_dirs`, so such a
+        // database would not be restored
+        if name.starts_with('.') {
+            return invalid("name starts with '.'");
+        }
+        if name.contains(|c| c == '/' || c == '\\' || c == '\0') {
+            return invalid("name contains a path separator");
+        }
+
+        let mut components = Path::new(name).components();
+        match (components.next(), components.next()) {
+            (Some(Component::Normal(_)), None) => {}
+            _ => return invalid("name is not a single path component"),
+        }
+
+        let dir = self.base_dir.join(name);
+        ensure!(
+            dir.parent() == Some(self.base_dir.as_path()),
+            InvalidDatabaseName {
+                name,
+                reason: "name resolves outside of the database directory",
+            }
+        );
+        Ok(dir)
+    }
+
+    /// Adds `db`, returning it as it is shared with queries and writes
+    pub async fn add_db(&self, db: Db) -> Arc<Db> {
+        let db = Arc::new(self.configure(db));
         let mut databases = self.databases.write().await;
-        databases.insert(db.name.clone(), Arc::new(db));
+        self.insert_db(&mut databases, Arc::clone(&db));
+        db
+    }
+
+    /// Waits until the rules of any of the databases change (or have
+    /// changed since this was last waited for), so that tasks applying
+    /// the rules, such as `apply_retention`, can apply the new rules
+    /// right away
+    pub async fn rules_changed(&self) {
+        self.rules_changed.notified().await
+    }
+
+    /// Drops the partitions of each database that are past the
+    /// retention period of its rules, as of `now` (in nanoseconds).
+    /// Returns the total number of partitions dropped.
+    pub async fn apply_retention(&self, now: i64) -> Result<usize> {
+        let databases: Vec<_> = self.databases.read().await.values().cloned().collect();
+
+        let mut dropped = 0;
+        for db in databases {
+            dropped += db.apply_retention(now).await.context(DatabaseError)?;
+        }
+        Ok(dropped)
+    }
+
+    /// Persists and evicts the partitions of each database which have
+    /// not been written to for the idle period of its rules, as of
+    /// `now` (in nanoseconds). Returns the total number of partitions
+    /// evicted.
+    pub async fn persist_idle(&self, now: i64) -> Result<usize> {
+        let databases: Vec<_> = self.databases.read().await.values().cloned().collect();
+
+        let mut evicted = 0;
+        for db in databases {
+            evicted += db.persist_idle(now).await.context(DatabaseError)?;
+        }
+        Ok(evicted)
+    }
+
+    /// Downsamples the partitions of each database according to the
+    /// downsampling rules of its rules, as of `now` (in nanoseconds).
+    /// Returns the total number of partitions downsampled.
+    pub async fn downsample(&self, now: i64) -> Result<usize> {
+        let databases: Vec<_> = self.databases.read().await.values().cloned().collect();
+
+        let mut downsampled = 0;
+        for db in databases {
+            let rules = db.rules().await;
+            downsampled += db
+                .downsample(&rules.downsampling_rules, now)
+                .await
+                .context(DatabaseError)?;
+        }
+        Ok(downsampled)
     }
 }
 
@@ -80,6 +258,12 @@ impl DatabaseStore for WriteBufferDatabases {
     type Database = Db;
     type Error = Error;
 
+    async fn db_names_sorted(&self) -> Vec<String> {
+        let databases = self.databases.read().await;
+
+        databases.keys().cloned().collect()
+    }
+
     async fn db(&self, name: &str) -> Option<Arc<Self::Database>> {
         let databases = self.databases.read().await;
 
@@ -104,12 +288,167 @@ impl DatabaseStore for WriteBufferDatabases {
             return Ok(db.clone());
         }
 
+        self.db_dir(name)?;
         let db = Db::try_with_wal(name, &mut self.base_dir.clone())
             .await
             .context(DatabaseError)?;
+        let db = self.configure(db);
         let db = Arc::new(db);
-        databases.insert(name.to_string(), db.clone());
+        self.insert_db(&mut databases, db.clone());
 
         Ok(db)
     }
+
+    async fn create_db(
+        &self,
+        name: &str,
+        rules: DatabaseRules,
+    ) -> Result<Arc<Self::Database>, Self::Error> {
+        let mut databases = self.databases.write().await;
+
+        ensure!(
+            !databases.contains_key(name),
+            DatabaseAlreadyExists { name }
+        );
+
+        self.db_dir(name)?;
+        let db = Db::try_with_wal(name, &mut self.base_dir.clone())
+            .await
+            .context(DatabaseError)?;
+        let db = self.configure(db);
+        db.set_rules(rules).await.context(DatabaseError)?;
+
+        let db = Arc::new(db);
+        self.insert_db(&mut databases, db.clone());
+
+        Ok(db)
+    }
+
+    async fn delete_db(&self, name: &str) -> Result<(), Self::Error> {
+        let dir = self.db_dir(name)?;
+        let mut databases = self.databases.write().await;
+
+        databases.remove(name).context(DatabaseNotFound { name })?;
+        self.chunk_pruning
+            .lock()
+            .expect("mutex poisoned")
+            .remove(name);
+
+        if dir.exists() {
+            fs::remove_dir_all(&dir).context(RemoveError { dir })?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use influxdb_line_protocol::parse_lines;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    type TestError = Box<dyn std::error::Error + Send + Sync + 'static>;
+    type Result<T = (), E = TestError> = std::result::Result<T, E>;
+
+    #[tokio::test]
+    async fn rules_changes_are_notified() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let store = WriteBufferDatabases::new(dir.path());
+        let wait = Duration::from_secs(5);
+
+        let db = store.db_or_create("mydb").await?;
+        assert!(timeout(Duration::from_millis(10), store.rules_changed())
+            .await
+            .is_err());
+
+        db.set_rules(DatabaseRules {
+            retention_period_seconds: Some(3600),
+            ..Default::default()
+        })
+        .await?;
+        timeout(wait, store.rules_changed()).await?;
+
+        store.create_db("otherdb", DatabaseRules::default()).await?;
+        timeout(wait, store.rules_changed()).await?;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn chunk_pruning_of_each_database() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let store = WriteBufferDatabases::new(dir.path());
+
+        let db = store.db_or_create("mydb").await?;
+        let lines: Vec<_> = parse_lines("cpu usage=1 10").map(|l| l.unwrap()).collect();
+        db.write_lines(&lines).await?;
+        store.create_db("otherdb", DatabaseRules::default()).await?;
+
+        db.query("select * from cpu").await?;
+        assert_eq!(
+            store.chunk_pruning(),
+            vec![("mydb".to_string(), 1, 0), ("otherdb".to_string(), 0, 0)]
+        );
+
+        store.delete_db("mydb").await?;
+        assert_eq!(store.chunk_pruning(), vec![("otherdb".to_string(), 0, 0)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn database_names_stay_in_the_base_dir() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let base_dir = dir.path().join("dbs");
+        fs::create_dir(&base_dir)?;
+        let store = WriteBufferDatabases::new(&base_dir);
+
+        for name in &["", ".", "..", ".hidden", "a/b", "../other", "a\\b", "/tmp"] {
+            let err = store
+                .create_db(name, DatabaseRules::default())
+                .await
+                .unwrap_err();
+            assert!(
+                matches!(err, Error::InvalidDatabaseName { .. }),
+                "{}: {}",
+                name,
+                err
+            );
+            assert!(store.db_or_create(name).await.is_err());
+            let err = store.delete_db(name).await.unwrap_err();
+            assert!(matches!(err, Error::InvalidDatabaseName { .. }));
+        }
+        assert!(base_dir.exists());
+        assert_eq!(fs::read_dir(&base_dir)?.count(), 0);
+
+        store.create_db("mydb", DatabaseRules::default()).await?;
+        store.delete_db("mydb").await?;
+        assert!(!base_dir.join("mydb").exists());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn databases_open_persisted_chunks_as_configured() -> Result {
+        let dir = test_helpers::tmp_dir()?;
+        let store = WriteBufferDatabases::new(dir.path()).with_persisted_open_mode(OpenMode::Mmap);
+
+        let db = store.db_or_create("mydb").await?;
+        assert_eq!(db.persisted_open_mode(), OpenMode::Mmap);
+        let db = store.create_db("otherdb", DatabaseRules::default()).await?;
+        assert_eq!(db.persisted_open_mode(), OpenMode::Mmap);
+
+        // as are those restored when the server restarts
+        drop(db);
+        drop(store);
+        let restored = Db::open_from_wal(&dir.path().join("mydb")).await?;
+        assert_eq!(restored.persisted_open_mode(), OpenMode::Read);
+        let store = WriteBufferDatabases::new(dir.path()).with_persisted_open_mode(OpenMode::Mmap);
+        let db = store.add_db(restored).await;
+        assert_eq!(db.persisted_open_mode(), OpenMode::Mmap);
+
+        Ok(())
+    }
 }
@@ -1,8 +1,18 @@
 use generated_types::wal as wb;
-use storage::exec::{make_schema_pivot, GroupedSeriesSetPlan, SeriesSetPlan};
+use storage::{
+    exec::{make_schema_pivot, SeriesSetPlan},
+    predicate::TimestampRange,
+    util::coerce_numeric_comparisons,
+};
 use tracing::debug;
 
-use std::{collections::BTreeSet, collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
 use crate::{
     column,
@@ -10,6 +20,7 @@ use crate::{
     dictionary::{Dictionary, Error as DictionaryError},
     partition::PartitionIdSet,
     partition::{Partition, PartitionPredicate},
+    pre_aggregate::{FieldValue, PreAggregates},
 };
 use data_types::TIME_COLUMN_NAME;
 use snafu::{OptionExt, ResultExt, Snafu};
@@ -175,6 +186,77 @@ pub struct Table {
 
     /// Actual column storage
     pub columns: Vec<Column>,
+
+    /// Maps the id of each tag column to the times of its rows
+    pub tag_summaries: HashMap<u32, TagSummary>,
+
+    /// The aggregates of the numeric fields of each series in buckets
+    /// of time, if they are kept for this table
+    pub pre_aggregates: Option<PreAggregates>,
+
+    /// The number of times each column (by index in self.columns) has
+    /// been converted to arrow to be read
+    column_reads: Vec<AtomicU64>,
+}
+
+/// The times of the rows of a tag column, kept up to date as rows are
+/// written, so which tags and tag values a time range has is usually
+/// answered without reading the rows
+#[derive(Debug, Default)]
+pub struct TagSummary {
+    /// The times of the rows with a value for the tag
+    pub times: Option<TimeSpan>,
+
+    /// The times of the rows with each value of the tag, by the id of
+    /// the value in the partition dictionary
+    pub value_times: BTreeMap<u32, TimeSpan>,
+}
+
+impl TagSummary {
+    fn update(&mut self, value_id: u32, time: i64) {
+        match &mut self.times {
+            Some(times) => times.update(time),
+            None => self.times = Some(TimeSpan::new(time)),
+        }
+        self.value_times
+            .entry(value_id)
+            .and_modify(|times| times.update(time))
+            .or_insert_with(|| TimeSpan::new(time));
+    }
+}
+
+/// The earliest and latest times of a set of rows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSpan {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl TimeSpan {
+    fn new(time: i64) -> Self {
+        Self {
+            min: time,
+            max: time,
+        }
+    }
+
+    fn update(&mut self, time: i64) {
+        self.min = self.min.min(time);
+        self.max = self.max.max(time);
+    }
+
+    /// Returns whether any of the rows are within `range`, or `None` if
+    /// only reading the rows can tell: when `range` is between the
+    /// earliest and latest rows without including either of them.
+    pub fn has_rows_in(&self, range: &TimestampRange) -> Option<bool> {
+        if self.max < range.start || self.min >= range.end {
+            Some(false)
+        } else if range.contains(self.min) || range.contains(self.max) {
+            Some(true)
+        } else {
+            None
+        }
+    }
 }
 
 type ArcStringVec = Vec<Arc<String>>;
@@ -185,6 +267,19 @@ impl Table {
             id,
             column_id_to_index: HashMap::new(),
             columns: Vec::new(),
+            tag_summaries: HashMap::new(),
+            pre_aggregates: None,
+            column_reads: Vec::new(),
+        }
+    }
+
+    /// Keeps the pre-aggregates of the rows written to this table,
+    /// which must not have any rows yet
+    pub fn with_pre_aggregates(self) -> Self {
+        debug_assert_eq!(self.row_count(), 0);
+        Self {
+            pre_aggregates: Some(PreAggregates::default()),
+            ..self
         }
     }
 
@@ -212,6 +307,7 @@ impl Table {
                         Column::with_value(dictionary, row_count, value)
                             .context(CreatingFromWal { column: column_id })?,
                     );
+                    self.column_reads.push(AtomicU64::new(0));
 
                     continue;
                 }
@@ -227,14 +323,94 @@ impl Table {
             col.push_none_if_len_equal(row_count);
         }
 
+        self.update_tag_summaries(dictionary, row_count);
+        self.update_pre_aggregates(dictionary, row_count);
+
         Ok(())
     }
 
+    /// Adds the tags of the row `row` to the summaries of their columns
+    fn update_tag_summaries(&mut self, dictionary: &Dictionary, row: usize) {
+        let time = dictionary
+            .id(TIME_COLUMN_NAME)
+            .and_then(|time_column_id| self.column_id_to_index.get(&time_column_id))
+            .and_then(|&index| match &self.columns[index] {
+                Column::I64(times, _) => times[row],
+                _ => None,
+            });
+        let time = match time {
+            Some(time) => time,
+            None => return,
+        };
+
+        for (&column_id, &index) in &self.column_id_to_index {
+            if let Column::Tag(values, _) = &self.columns[index] {
+                if let Some(value_id) = values[row] {
+                    self.tag_summaries
+                        .entry(column_id)
+                        .or_default()
+                        .update(value_id, time);
+                }
+            }
+        }
+    }
+
+    /// Adds the row `row` to the pre-aggregates of the table, if it
+    /// has them
+    fn update_pre_aggregates(&mut self, dictionary: &Dictionary, row: usize) {
+        let pre_aggregates = match &mut self.pre_aggregates {
+            Some(pre_aggregates) => pre_aggregates,
+            None => return,
+        };
+        let time_column_id = dictionary.id(TIME_COLUMN_NAME);
+
+        let mut time = None;
+        let mut tags = vec![];
+        let mut fields = vec![];
+        for (&column_id, &index) in &self.column_id_to_index {
+            match &self.columns[index] {
+                Column::I64(values, _) if Some(column_id) == time_column_id => time = values[row],
+                Column::Tag(values, _) => {
+                    if let Some(value_id) = values[row] {
+                        tags.push((column_id, value_id));
+                    }
+                }
+                Column::F64(values, _) => {
+                    if let Some(value) = values[row] {
+                        fields.push((column_id, FieldValue::F64(value)));
+                    }
+                }
+                Column::I64(values, _) => {
+                    if let Some(value) = values[row] {
+                        fields.push((column_id, FieldValue::I64(value)));
+                    }
+                }
+                Column::String(_, _) | Column::Bool(_, _) => {}
+            }
+        }
+        tags.sort_unstable();
+
+        if let Some(time) = time {
+            pre_aggregates.add(tags, time, fields);
+        }
+    }
+
     pub fn row_count(&self) -> usize {
         self.columns.first().map_or(0, |v| v.len())
     }
 
+    /// The approximate number of bytes used to store the data of this table
+    pub fn size(&self) -> usize {
+        let pre_aggregates = self.pre_aggregates.as_ref().map_or(0, |p| p.size());
+        self.columns.iter().map(|c| c.size()).sum::<usize>() + pre_aggregates
+    }
+
     /// Returns a reference to the specified column
+    /// The number of times the column at `column_index` has been read
+    pub fn column_reads(&self, column_index: usize) -> u64 {
+        self.column_reads[column_index].load(Ordering::Relaxed)
+    }
+
     fn column(&self, column_id: u32) -> Result<&Column> {
         Ok(self
             .column_id_to_index
@@ -262,8 +438,21 @@ impl Table {
         &mut self,
         dictionary: &mut Dictionary,
         rows: &flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<wb::Row<'_>>>,
+    ) -> Result<()> {
+        self.append_rows_where(dictionary, rows, &|_| true)
+    }
+
+    /// Appends the rows of `rows` that `include` accepts
+    pub fn append_rows_where(
+        &mut self,
+        dictionary: &mut Dictionary,
+        rows: &flatbuffers::Vector<'_, flatbuffers::ForwardsUOffset<wb::Row<'_>>>,
+        include: &dyn Fn(&wb::Row<'_>) -> bool,
     ) -> Result<()> {
         for row in rows {
+            if !include(&row) {
+                continue;
+            }
             if let Some(values) = row.values() {
                 self.append_row(dictionary, &values)?;
             }
@@ -272,14 +461,34 @@ impl Table {
         Ok(())
     }
 
+    /// Returns the DataFusion predicate of `partition_predicate` for
+    /// the rows of this table
+    fn filter_expr(
+        &self,
+        partition_predicate: &PartitionPredicate,
+        partition: &Partition,
+    ) -> Option<Expr> {
+        partition_predicate.filter_expr(|column_name| {
+            partition
+                .dictionary
+                .lookup_value(column_name)
+                .map_or(false, |id| self.column_id_to_index.contains_key(&id))
+        })
+    }
+
     /// Creates and adds a datafuson filtering expression, if any out of the
     /// combination of predicate and timestamp. Returns the builder
     fn add_datafusion_predicate(
+        &self,
         plan_builder: LogicalPlanBuilder,
         partition_predicate: &PartitionPredicate,
+        partition: &Partition,
     ) -> Result<LogicalPlanBuilder> {
-        match partition_predicate.filter_expr() {
-            Some(df_predicate) => plan_builder.filter(df_predicate).context(BuildingPlan),
+        match self.filter_expr(partition_predicate, partition) {
+            Some(df_predicate) => {
+                let df_predicate = coerce_numeric_comparisons(df_predicate, plan_builder.schema());
+                plan_builder.filter(df_predicate).context(BuildingPlan)
+            }
             None => Ok(plan_builder),
         }
     }
@@ -342,7 +551,8 @@ impl Table {
         // Shouldn't have field selections here (as we are getting the tags...)
         assert!(!partition_predicate.has_field_restriction());
 
-        let plan_builder = Self::add_datafusion_predicate(plan_builder, partition_predicate)?;
+        let plan_builder =
+            self.add_datafusion_predicate(plan_builder, partition_predicate, partition)?;
 
         // add optional selection to remove time column
         let plan_builder = if !need_time_column {
@@ -412,7 +622,8 @@ impl Table {
         // shouldn't have columns selection (as this is getting tag values...)
         assert!(!partition_predicate.has_field_restriction());
 
-        let plan_builder = Self::add_datafusion_predicate(plan_builder, partition_predicate)?;
+        let plan_builder =
+            self.add_datafusion_predicate(plan_builder, partition_predicate, partition)?;
 
         plan_builder
             .project(select_exprs)
@@ -421,132 +632,6 @@ impl Table {
             .context(BuildingPlan)
     }
 
-    /// Creates a SeriesSet plan that produces an output table with rows that match the predicate
-    ///
-    /// The output looks like:
-    /// (tag_col1, tag_col2, ... field1, field2, ... timestamp)
-    ///
-    /// The order of the tag_columns is orderd by name.
-    ///
-    /// The data is sorted on tag_col1, tag_col2, ...) so that all
-    /// rows for a particular series (groups where all tags are the
-    /// same) occur together in the plan
-    pub fn series_set_plan(
-        &self,
-        partition_predicate: &PartitionPredicate,
-        partition: &Partition,
-    ) -> Result<SeriesSetPlan> {
-        self.series_set_plan_impl(partition_predicate, None, partition)
-    }
-
-    /// Creates the plans for computing series set, pulling prefix_columns, if any, as a prefix of the ordering
-    /// The created plan looks like:
-    ///
-    ///    Projection (select the columns columns needed)
-    ///      Order by (tag_columns, timestamp_column)
-    ///        Filter(predicate)
-    ///          InMemoryScan
-    pub fn series_set_plan_impl(
-        &self,
-        partition_predicate: &PartitionPredicate,
-        prefix_columns: Option<&[String]>,
-        partition: &Partition,
-    ) -> Result<SeriesSetPlan> {
-        // I wonder if all this string creation will be too slow?
-        let table_name = partition
-            .dictionary
-            .lookup_id(self.id)
-            .expect("looking up table name in dictionary")
-            .to_string();
-
-        let table_name = Arc::new(table_name);
-        let (mut tag_columns, field_columns) =
-            self.tag_and_field_column_names(partition_predicate, partition)?;
-
-        // reorder tag_columns to have the prefix columns, if requested
-        if let Some(prefix_columns) = prefix_columns {
-            tag_columns = reorder_prefix(prefix_columns, tag_columns)?;
-        }
-
-        // TODO avoid materializing all the columns here (ideally
-        // DataFusion can prune them out)
-        let data = self.all_to_arrow(partition)?;
-
-        let schema = data.schema();
-
-        let projection = None;
-        let projected_schema = schema.clone();
-
-        // And build the plan from the bottom up
-        let plan_builder = LogicalPlanBuilder::from(&LogicalPlan::InMemoryScan {
-            data: vec![vec![data]],
-            schema,
-            projection,
-            projected_schema,
-        });
-
-        // Filtering
-        let plan_builder = Self::add_datafusion_predicate(plan_builder, partition_predicate)?;
-
-        let mut sort_exprs = Vec::new();
-        sort_exprs.extend(tag_columns.iter().map(|c| c.into_sort_expr()));
-        sort_exprs.push(TIME_COLUMN_NAME.into_sort_expr());
-
-        // Order by
-        let plan_builder = plan_builder.sort(sort_exprs).context(BuildingPlan)?;
-
-        // Selection
-        let mut select_exprs = Vec::new();
-        select_exprs.extend(tag_columns.iter().map(|c| c.into_expr()));
-        select_exprs.extend(field_columns.iter().map(|c| c.into_expr()));
-        select_exprs.push(TIME_COLUMN_NAME.into_expr());
-
-        let plan_builder = plan_builder.project(select_exprs).context(BuildingPlan)?;
-
-        // and finally create the plan
-        let plan = plan_builder.build().context(BuildingPlan)?;
-
-        Ok(SeriesSetPlan {
-            table_name,
-            plan,
-            tag_columns,
-            field_columns,
-        })
-    }
-
-    /// Creates a GroupedSeriesSet plan that produces an output table with rows that match the predicate
-    ///
-    /// The output looks like:
-    /// (group_tag_column1, group_tag_column2, ... tag_col1, tag_col2, ... field1, field2, ... timestamp)
-    ///
-    /// The order of the tag_columns is ordered by name.
-    ///
-    /// The data is sorted on tag_col1, tag_col2, ...) so that all
-    /// rows for a particular series (groups where all tags are the
-    /// same) occur together in the plan
-    ///
-    /// The created plan looks like:
-    ///
-    ///    Projection (select the columns columns needed)
-    ///      Order by (tag_columns, timestamp_column)
-    ///        Filter(predicate)
-    ///          InMemoryScan
-    pub fn grouped_series_set_plan(
-        &self,
-        partition_predicate: &PartitionPredicate,
-        group_columns: &[String],
-        partition: &Partition,
-    ) -> Result<GroupedSeriesSetPlan> {
-        let series_set_plan =
-            self.series_set_plan_impl(partition_predicate, Some(&group_columns), partition)?;
-        let num_prefix_tag_group_columns = group_columns.len();
-
-        Ok(GroupedSeriesSetPlan {
-            series_set_plan,
-            num_prefix_tag_group_columns,
-        })
-    }
-
     /// Creates a plan that produces an output table with rows that
     /// match the predicate for all fields in the table.
     ///
@@ -582,7 +667,8 @@ impl Table {
         });
 
         // Filtering
-        let plan_builder = Self::add_datafusion_predicate(plan_builder, partition_predicate)?;
+        let plan_builder =
+            self.add_datafusion_predicate(plan_builder, partition_predicate, partition)?;
 
         // Selection
         let select_exprs = self
@@ -600,7 +686,7 @@ impl Table {
     // Returns (tag_columns, field_columns) vectors with the names of
     // all tag and field columns, respectively. The vectors are sorted
     // by name.
-    fn tag_and_field_column_names(
+    pub fn tag_and_field_column_names(
         &self,
         partition_predicate: &PartitionPredicate,
         partition: &Partition,
@@ -754,6 +840,8 @@ impl Table {
         let mut columns: Vec<ArrayRef> = Vec::with_capacity(requested_columns_with_index.len());
 
         for &(column_name, column_index) in requested_columns_with_index.iter() {
+            self.column_reads[column_index].fetch_add(1, Ordering::Relaxed);
+
             let arrow_col: ArrayRef = match &self.columns[column_index] {
                 Column::String(vals, _) => {
                     fields.push(ArrowField::new(column_name, ArrowDataType::Utf8, true));
@@ -843,7 +931,11 @@ impl Table {
                     partition_predicate.table_name_predicate.as_ref(),
                 )
                 && self.matches_timestamp_predicate(partition_predicate)?
-                && self.has_columns(partition_predicate.required_columns.as_ref()),
+                && self.has_columns(partition_predicate.required_columns.as_ref())
+                && self.matches_column_equalities(&partition_predicate.column_equalities)
+                && self.matches_column_lowered_equalities(
+                    &partition_predicate.column_lowered_equalities,
+                ),
         )
     }
 
@@ -888,6 +980,33 @@ impl Table {
         }
     }
 
+    /// returns false if the statistics of any of the columns show it
+    /// never has the value it must equal
+    fn matches_column_equalities(&self, column_equalities: &[(u32, String)]) -> bool {
+        column_equalities.iter().all(|(column_id, value)| {
+            self.column_id_to_index
+                .get(column_id)
+                .map_or(true, |&index| self.columns[index].might_contain_str(value))
+        })
+    }
+
+    /// returns false if any of the columns has none of the values that
+    /// lower case to the value it must equal
+    fn matches_column_lowered_equalities(
+        &self,
+        column_lowered_equalities: &[(u32, BTreeSet<u32>)],
+    ) -> bool {
+        column_lowered_equalities
+            .iter()
+            .all(|(column_id, value_ids)| {
+                self.column_id_to_index
+                    .get(column_id)
+                    .map_or(true, |&index| {
+                        self.columns[index].might_contain_any_id(value_ids)
+                    })
+            })
+    }
+
     /// returns true if no columns are specified, or the table has all
     /// columns specified
     fn has_columns(&self, columns: Option<&PartitionIdSet>) -> bool {
@@ -906,6 +1025,115 @@ impl Table {
         true
     }
 
+    /// returns true if there are any rows in this table within the
+    /// timestamp range of the predicate. Unlike the check done by
+    /// `could_match_predicate`, this is exact, but the times of the
+    /// rows are only read when the statistics of the time column can't
+    /// tell.
+    pub fn has_rows_in_range(&self, partition_predicate: &PartitionPredicate) -> Result<bool> {
+        let range = match partition_predicate.range {
+            Some(range) => range,
+            None => return Ok(true),
+        };
+
+        let time_column_id = partition_predicate.time_column_id;
+        match self.column(time_column_id)? {
+            Column::I64(times, stats) => {
+                let span = TimeSpan {
+                    min: stats.min,
+                    max: stats.max,
+                };
+                Ok(span
+                    .has_rows_in(&range)
+                    .unwrap_or_else(|| times.iter().any(|&time| range.contains_opt(time))))
+            }
+            column => InternalColumnTypeMismatch {
+                column_id: time_column_id,
+                expected_column_type: "i64",
+                actual_column_type: column.type_description(),
+            }
+            .fail(),
+        }
+    }
+
+    /// returns true if there are any rows of the tag column
+    /// `column_id` (whose values are `column`) that are non-null and
+    /// within the timestamp range of the predicate. The rows are only
+    /// read when the summary of the tag can't tell.
+    pub fn tag_matches_predicate(
+        &self,
+        column_id: u32,
+        column: &[Option<u32>],
+        partition_predicate: &PartitionPredicate,
+    ) -> Result<bool> {
+        let range = match partition_predicate.range {
+            Some(range) => range,
+            None => return Ok(true),
+        };
+
+        let known = self
+            .tag_summaries
+            .get(&column_id)
+            .and_then(|summary| summary.times)
+            .and_then(|times| times.has_rows_in(&range));
+
+        match known {
+            Some(matches) => Ok(matches),
+            None => self.column_matches_predicate(column, partition_predicate),
+        }
+    }
+
+    /// Adds the ids of the values of the tag column `column_id` (whose
+    /// values are `column`) with rows within the timestamp range of the
+    /// predicate to `value_ids`. The rows are only read for the values
+    /// whose summaries can't tell.
+    pub fn tag_values_matching_predicate(
+        &self,
+        column_id: u32,
+        column: &[Option<u32>],
+        partition_predicate: &PartitionPredicate,
+        value_ids: &mut BTreeSet<u32>,
+    ) -> Result<()> {
+        let summary = match self.tag_summaries.get(&column_id) {
+            Some(summary) => summary,
+            None => return Ok(()),
+        };
+
+        let range = match partition_predicate.range {
+            Some(range) => range,
+            None => {
+                value_ids.extend(summary.value_times.keys());
+                return Ok(());
+            }
+        };
+
+        let mut unknown = BTreeSet::new();
+        for (&value_id, times) in &summary.value_times {
+            match times.has_rows_in(&range) {
+                Some(true) => {
+                    value_ids.insert(value_id);
+                }
+                Some(false) => {}
+                None => {
+                    unknown.insert(value_id);
+                }
+            }
+        }
+
+        if !unknown.is_empty() {
+            let time_column = self.column_i64(partition_predicate.time_column_id)?;
+            for (&value_id, &time) in column.iter().zip(time_column) {
+                if let Some(value_id) = value_id {
+                    if unknown.contains(&value_id) && range.contains_opt(time) {
+                        value_ids.insert(value_id);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     /// returns true if there are any rows in column that are non-null
     /// and within the timestamp range specified by pred
     pub fn column_matches_predicate<T>(
@@ -928,10 +1156,75 @@ impl Table {
     }
 }
 
+/// Creates a SeriesSet plan over `data`, the rows of the table
+/// `table_name`, which produces the rows that pass `filter` sorted by
+/// `tag_columns` and then timestamp.
+///
+/// The created plan looks like:
+///
+///    Projection (select the columns columns needed)
+///      Order by (tag_columns, timestamp_column)
+///        Filter(predicate)
+///          InMemoryScan
+pub fn series_set_plan_for_data(
+    table_name: Arc<String>,
+    data: RecordBatch,
+    filter: Option<Expr>,
+    tag_columns: ArcStringVec,
+    field_columns: ArcStringVec,
+) -> Result<SeriesSetPlan> {
+    let schema = data.schema();
+
+    let projection = None;
+    let projected_schema = schema.clone();
+
+    // And build the plan from the bottom up
+    let plan_builder = LogicalPlanBuilder::from(&LogicalPlan::InMemoryScan {
+        data: vec![vec![data]],
+        schema,
+        projection,
+        projected_schema,
+    });
+
+    // Filtering
+    let plan_builder = match filter {
+        Some(filter) => {
+            let filter = coerce_numeric_comparisons(filter, plan_builder.schema());
+            plan_builder.filter(filter).context(BuildingPlan)?
+        }
+        None => plan_builder,
+    };
+
+    let mut sort_exprs = Vec::new();
+    sort_exprs.extend(tag_columns.iter().map(|c| c.into_sort_expr()));
+    sort_exprs.push(TIME_COLUMN_NAME.into_sort_expr());
+
+    // Order by
+    let plan_builder = plan_builder.sort(sort_exprs).context(BuildingPlan)?;
+
+    // Selection
+    let mut select_exprs = Vec::new();
+    select_exprs.extend(tag_columns.iter().map(|c| c.into_expr()));
+    select_exprs.extend(field_columns.iter().map(|c| c.into_expr()));
+    select_exprs.push(TIME_COLUMN_NAME.into_expr());
+
+    let plan_builder = plan_builder.project(select_exprs).context(BuildingPlan)?;
+
+    // and finally create the plan
+    let plan = plan_builder.build().context(BuildingPlan)?;
+
+    Ok(SeriesSetPlan {
+        table_name,
+        plan,
+        tag_columns,
+        field_columns,
+    })
+}
+
 /// Reorders tag_columns so that its prefix matches exactly
 /// prefix_columns. Returns an error if there are duplicates, or other
 /// untoward inputs
-fn reorder_prefix(
+pub fn reorder_prefix(
     prefix_columns: &[String],
     tag_columns: Vec<Arc<String>>,
 ) -> Result<Vec<Arc<String>>> {
@@ -1119,9 +1412,7 @@ mod tests {
 
         let predicate = PredicateBuilder::default().build();
         let partition_predicate = partition.compile_predicate(&predicate).unwrap();
-        let series_set_plan = table
-            .series_set_plan(&partition_predicate, &partition)
-            .expect("creating the series set plan");
+        let series_set_plan = series_set_plan(&table, &partition_predicate, None, &partition);
 
         assert_eq!(series_set_plan.table_name.as_ref(), "table_name");
         assert_eq!(
@@ -1170,9 +1461,7 @@ mod tests {
 
         let predicate = PredicateBuilder::default().build();
         let partition_predicate = partition.compile_predicate(&predicate).unwrap();
-        let series_set_plan = table
-            .series_set_plan(&partition_predicate, &partition)
-            .expect("creating the series set plan");
+        let series_set_plan = series_set_plan(&table, &partition_predicate, None, &partition);
 
         assert_eq!(series_set_plan.table_name.as_ref(), "table_name");
         assert_eq!(
@@ -1231,9 +1520,7 @@ mod tests {
 
         let partition_predicate = partition.compile_predicate(&predicate).unwrap();
 
-        let series_set_plan = table
-            .series_set_plan(&partition_predicate, &partition)
-            .expect("creating the series set plan");
+        let series_set_plan = series_set_plan(&table, &partition_predicate, None, &partition);
 
         assert_eq!(series_set_plan.table_name.as_ref(), "table_name");
         assert_eq!(
@@ -1289,14 +1576,15 @@ mod tests {
 
         let group_columns = vec![String::from("state")];
 
-        let grouped_series_set_plan = table
-            .grouped_series_set_plan(&partition_predicate, &group_columns, &partition)
-            .expect("creating the grouped_series set plan");
-
-        assert_eq!(grouped_series_set_plan.num_prefix_tag_group_columns, 1);
+        let series_set_plan = series_set_plan(
+            &table,
+            &partition_predicate,
+            Some(&group_columns),
+            &partition,
+        );
 
         // run the created plan, ensuring the output is as expected
-        let results = run_plan(grouped_series_set_plan.series_set_plan.plan).await;
+        let results = run_plan(series_set_plan.plan).await;
 
         let expected = vec![
             "+-------+------+------+------+",
@@ -1351,6 +1639,90 @@ mod tests {
         assert_eq!(expected, results, "expected output");
     }
 
+    #[test]
+    fn test_tag_summaries() {
+        let mut partition = Partition::new("dummy_partition_key");
+        let dictionary = &mut partition.dictionary;
+        let mut table = Table::new(dictionary.lookup_value_or_insert("h2o"));
+
+        let lp_lines = vec![
+            "h2o,host=a temp=70.1 10",
+            "h2o,host=b,region=west temp=70.2 20",
+            "h2o,host=a temp=70.3 30",
+        ];
+        write_lines_to_table(&mut table, dictionary, lp_lines);
+
+        let host_id = dictionary.lookup_value("host").unwrap();
+        let region_id = dictionary.lookup_value("region").unwrap();
+        let a = dictionary.lookup_value("a").unwrap();
+        let b = dictionary.lookup_value("b").unwrap();
+
+        let host = &table.tag_summaries[&host_id];
+        assert_eq!(host.times, Some(TimeSpan { min: 10, max: 30 }));
+        assert_eq!(host.value_times[&a], TimeSpan { min: 10, max: 30 });
+        assert_eq!(host.value_times[&b], TimeSpan { min: 20, max: 20 });
+        assert_eq!(
+            table.tag_summaries[&region_id].times,
+            Some(TimeSpan { min: 20, max: 20 })
+        );
+
+        let host_column = match table.column(host_id).unwrap() {
+            Column::Tag(values, _) => values.clone(),
+            _ => panic!("host is a tag"),
+        };
+
+        // (range, whether the table has rows in it, whether host does,
+        // the values of host)
+        let cases = vec![
+            ((0, 100), true, true, vec![a, b]),
+            ((10, 11), true, true, vec![a]),
+            ((15, 25), true, true, vec![b]),
+            ((11, 19), false, false, vec![]),
+            ((31, 40), false, false, vec![]),
+        ];
+
+        for ((start, end), table_matches, host_matches, values) in cases {
+            let predicate = PredicateBuilder::default()
+                .timestamp_range(start, end)
+                .build();
+            let partition_predicate = partition.compile_predicate(&predicate).unwrap();
+
+            assert_eq!(
+                table.has_rows_in_range(&partition_predicate).unwrap(),
+                table_matches,
+                "table in range [{}, {})",
+                start,
+                end
+            );
+            assert_eq!(
+                table
+                    .tag_matches_predicate(host_id, &host_column, &partition_predicate)
+                    .unwrap(),
+                host_matches,
+                "host in range [{}, {})",
+                start,
+                end
+            );
+
+            let mut value_ids = BTreeSet::new();
+            table
+                .tag_values_matching_predicate(
+                    host_id,
+                    &host_column,
+                    &partition_predicate,
+                    &mut value_ids,
+                )
+                .unwrap();
+            assert_eq!(
+                value_ids.into_iter().collect::<Vec<_>>(),
+                values,
+                "host values in range [{}, {})",
+                start,
+                end
+            );
+        }
+    }
+
     #[test]
     fn test_reorder_prefix() {
         assert_eq!(reorder_prefix_ok(&[], &[]), &[] as &[&str]);
@@ -1437,6 +1809,41 @@ mod tests {
         }
     }
 
+    /// Creates a SeriesSet plan over the rows of `table` that match
+    /// `partition_predicate`, pulling `prefix_columns`, if any, as a
+    /// prefix of the ordering
+    fn series_set_plan(
+        table: &Table,
+        partition_predicate: &PartitionPredicate,
+        prefix_columns: Option<&[String]>,
+        partition: &Partition,
+    ) -> SeriesSetPlan {
+        let table_name = partition
+            .dictionary
+            .lookup_id(table.id)
+            .expect("looking up table name in dictionary")
+            .to_string();
+
+        let (mut tag_columns, field_columns) = table
+            .tag_and_field_column_names(partition_predicate, partition)
+            .expect("getting the tag and field columns");
+        if let Some(prefix_columns) = prefix_columns {
+            tag_columns = reorder_prefix(prefix_columns, tag_columns).expect("reordering tags");
+        }
+
+        let data = table.all_to_arrow(partition).expect("converting to arrow");
+        let filter = table.filter_expr(partition_predicate, partition);
+
+        series_set_plan_for_data(
+            Arc::new(table_name),
+            data,
+            filter,
+            tag_columns,
+            field_columns,
+        )
+        .expect("creating the series set plan")
+    }
+
     /// Runs `plan` and returns the output as petty-formatted array of strings
     async fn run_plan(plan: LogicalPlan) -> Vec<String> {
         // run the created plan, ensuring the output is as expected